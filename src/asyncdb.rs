@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::Duration;
+
+use crate::connection::{Database, Error, FromRow, Row, Rows};
+use crate::types::Value;
+
+// 非同期の実行環境から使う接続
+//
+// データベースは Rc で分け合うので、専用のスレッドがデータベースとすべての接続を持ち、
+// 文はチャネルでそのスレッドに送る。返す Future は結果が届いたときに起こすだけなので、
+// どの実行環境の上でも待てて、待っている間に実行環境のスレッドを止めない。
+// ひとつのスレッドで順に実行するので、ロックは待たずにすぐ失敗させる
+
+enum Request {
+    Connect {
+        id: u64,
+        reply: ReplySender<()>,
+    },
+    Execute {
+        id: u64,
+        sql: String,
+        params: Vec<Value>,
+        reply: ReplySender<usize>,
+    },
+    ExecuteBatch {
+        id: u64,
+        sql: String,
+        reply: ReplySender<()>,
+    },
+    Query {
+        id: u64,
+        sql: String,
+        params: Vec<Value>,
+        reply: ReplySender<Rows>,
+    },
+    // 実行中のトランザクションは接続を閉じたときに取り消す
+    Disconnect {
+        id: u64,
+    },
+}
+
+fn run_engine(db: Database, requests: Receiver<Request>) {
+    let mut conns = HashMap::new();
+    for request in requests {
+        match request {
+            Request::Connect { id, reply } => {
+                let mut conn = db.connect();
+                conn.set_lock_timeout(Some(Duration::ZERO));
+                conns.insert(id, conn);
+                reply.send(Ok(()));
+            }
+            Request::Execute {
+                id,
+                sql,
+                params,
+                reply,
+            } => {
+                if let Some(conn) = conns.get_mut(&id) {
+                    reply.send(conn.execute(&sql, &params));
+                }
+            }
+            Request::ExecuteBatch { id, sql, reply } => {
+                if let Some(conn) = conns.get_mut(&id) {
+                    reply.send(conn.execute_batch(&sql));
+                }
+            }
+            Request::Query {
+                id,
+                sql,
+                params,
+                reply,
+            } => {
+                if let Some(conn) = conns.get_mut(&id) {
+                    reply.send(conn.query(&sql, &params));
+                }
+            }
+            Request::Disconnect { id } => {
+                conns.remove(&id);
+            }
+        }
+    }
+}
+
+// データベースのスレッドへの入り口。複製してスレッドの間で分け合える
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    requests: Sender<Request>,
+    next_id: Arc<Mutex<u64>>,
+}
+
+impl AsyncDatabase {
+    // path のファイルを開く。なければ作る。開き終わるまでは呼んだスレッドで待つ
+    pub fn open(path: impl Into<PathBuf>) -> Result<AsyncDatabase, Error> {
+        let path = path.into();
+        AsyncDatabase::start(move || Database::open(path))
+    }
+
+    pub fn open_temporary() -> Result<AsyncDatabase, Error> {
+        AsyncDatabase::start(Database::open_temporary)
+    }
+
+    fn start(
+        open: impl FnOnce() -> Result<Database, Error> + Send + 'static,
+    ) -> Result<AsyncDatabase, Error> {
+        let (requests, receiver) = mpsc::channel();
+        let (opened, open_result) = mpsc::channel();
+        thread::spawn(move || match open() {
+            Ok(db) => {
+                let _ = opened.send(Ok(()));
+                run_engine(db, receiver);
+            }
+            Err(e) => {
+                let _ = opened.send(Err(e));
+            }
+        });
+        open_result.recv().map_err(|_| Error::EngineStopped)??;
+        Ok(AsyncDatabase {
+            requests,
+            next_id: Arc::default(),
+        })
+    }
+
+    pub async fn connect(&self) -> Result<AsyncConnection, Error> {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        let conn = AsyncConnection {
+            id,
+            requests: self.requests.clone(),
+        };
+        conn.call(|reply| Request::Connect { id, reply }).await?;
+        Ok(conn)
+    }
+}
+
+// Connection と同じように文を実行する。落とせばデータベースのスレッドの接続も閉じる
+pub struct AsyncConnection {
+    id: u64,
+    requests: Sender<Request>,
+}
+
+impl AsyncConnection {
+    pub async fn execute(&self, sql: &str, params: &[Value]) -> Result<usize, Error> {
+        let (id, sql, params) = (self.id, sql.to_string(), params.to_vec());
+        self.call(|reply| Request::Execute {
+            id,
+            sql,
+            params,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn execute_batch(&self, sql: &str) -> Result<(), Error> {
+        let (id, sql) = (self.id, sql.to_string());
+        self.call(|reply| Request::ExecuteBatch { id, sql, reply })
+            .await
+    }
+
+    pub async fn query(&self, sql: &str, params: &[Value]) -> Result<Rows, Error> {
+        let (id, sql, params) = (self.id, sql.to_string(), params.to_vec());
+        self.call(|reply| Request::Query {
+            id,
+            sql,
+            params,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn query_row(&self, sql: &str, params: &[Value]) -> Result<Row, Error> {
+        self.query(sql, params).await?.next().ok_or(Error::NoRows)
+    }
+
+    pub async fn query_as<T: FromRow>(&self, sql: &str, params: &[Value]) -> Result<Vec<T>, Error> {
+        self.query(sql, params)
+            .await?
+            .map(|row| T::from_row(&row))
+            .collect()
+    }
+
+    fn call<T>(&self, request: impl FnOnce(ReplySender<T>) -> Request) -> Reply<T> {
+        let shared = Arc::new(Mutex::new(Shared {
+            value: None,
+            waker: None,
+        }));
+        let reply = ReplySender(Some(shared.clone()));
+        // 送れなければ reply を落としたところで EngineStopped が入る
+        let _ = self.requests.send(request(reply));
+        Reply(shared)
+    }
+}
+
+impl Drop for AsyncConnection {
+    fn drop(&mut self) {
+        let _ = self.requests.send(Request::Disconnect { id: self.id });
+    }
+}
+
+struct Shared<T> {
+    value: Option<Result<T, Error>>,
+    waker: Option<Waker>,
+}
+
+// データベースのスレッドが結果を入れる側。入れずに落とせば EngineStopped を入れる
+struct ReplySender<T>(Option<Arc<Mutex<Shared<T>>>>);
+
+impl<T> ReplySender<T> {
+    fn send(mut self, value: Result<T, Error>) {
+        let shared = self.0.take().unwrap();
+        let mut shared = shared.lock().unwrap();
+        shared.value = Some(value);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for ReplySender<T> {
+    fn drop(&mut self) {
+        if let Some(shared) = self.0.take() {
+            ReplySender(Some(shared)).send(Err(Error::EngineStopped));
+        }
+    }
+}
+
+// 結果が届けば終わる Future
+pub struct Reply<T>(Arc<Mutex<Shared<T>>>);
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.0.lock().unwrap();
+        match shared.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// 起こされるまでスレッドを止めて待つだけの実行環境
+struct Unpark(thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// 実行環境を使わないスレッドで future を終わるまで待つ
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::SyncSender;
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn test_async_connection() {
+        let db = AsyncDatabase::open_temporary().unwrap();
+        let conn = block_on(db.connect()).unwrap();
+        let create = conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)", &[]);
+        assert_send(&create);
+        block_on(create).unwrap();
+        let n = block_on(conn.execute(
+            "INSERT INTO t VALUES ($1, $2), (2, 'b')",
+            &[Value::from(1), Value::from("a")],
+        ))
+        .unwrap();
+        assert_eq!(n, 2);
+
+        // ほかのスレッドの接続からも使える
+        let (done, wait): (SyncSender<Vec<(i64, String)>>, _) = mpsc::sync_channel(1);
+        let other = db.clone();
+        thread::spawn(move || {
+            let conn = block_on(other.connect()).unwrap();
+            let rows = block_on(conn.query_as("SELECT id, name FROM t ORDER BY id", &[]));
+            done.send(rows.unwrap()).unwrap();
+        });
+        assert_eq!(
+            wait.recv().unwrap(),
+            vec![(1, "a".to_string()), (2, "b".to_string())]
+        );
+
+        // トランザクションは接続ごと。ロックは待たずに失敗する
+        block_on(conn.execute_batch("BEGIN; UPDATE t SET name = 'x' WHERE id = 1")).unwrap();
+        let other = block_on(db.connect()).unwrap();
+        let err = block_on(other.execute("UPDATE t SET name = 'y' WHERE id = 1", &[])).unwrap_err();
+        assert_eq!(err.sqlstate(), "55P03");
+        let row = block_on(other.query_row("SELECT name FROM t WHERE id = 1", &[])).unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "a");
+        // 落とした接続のトランザクションは取り消される
+        drop(conn);
+        block_on(other.execute("UPDATE t SET name = 'y' WHERE id = 1", &[])).unwrap();
+        assert!(matches!(
+            block_on(other.execute("SELECT * FROM missing", &[])),
+            Err(Error::Planner(_))
+        ));
+    }
+}
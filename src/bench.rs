@@ -0,0 +1,363 @@
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::asyncdb::{block_on, AsyncConnection, AsyncDatabase};
+use crate::connection::Error;
+use crate::types::Value;
+
+// 性能を測るための負荷
+//
+// TPC-B (pgbench と同じ表と更新) か YCSB (キーで読むか書き換えるだけ) の表を規模に応じて作り、
+// clients 個のスレッドがそれぞれの接続で文を送り続ける。
+// 接続はすべて AsyncDatabase のひとつのスレッドで順に実行するので、ロックが取れなかったトランザクションは
+// 待たずに取り消され、数え直さずに abort として数える。
+// 乱数は seed から決めるので、同じ設定なら同じ順に同じキーを選ぶ
+
+// 規模 1 あたりの行数。pgbench の 1/10
+const ACCOUNTS_PER_SCALE: usize = 10_000;
+const TELLERS_PER_SCALE: usize = 10;
+const RECORDS_PER_SCALE: usize = 10_000;
+// 読み込みの INSERT 1 文に入れる行の数
+const LOAD_BATCH: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Workload {
+    TpcB,
+    // read_ratio の割合で読み、残りで書き換える
+    Ycsb { read_ratio: f64 },
+}
+
+impl FromStr for Workload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Workload, String> {
+        match s {
+            "tpcb" => Ok(Workload::TpcB),
+            "ycsb" => Ok(Workload::Ycsb { read_ratio: 0.95 }),
+            _ => Err(format!("unknown workload {} (expected tpcb or ycsb)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub workload: Workload,
+    pub scale: usize,
+    pub clients: usize,
+    // どちらかに達したら止める
+    pub duration: Duration,
+    pub transactions: Option<usize>,
+    pub seed: u64,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            workload: Workload::TpcB,
+            scale: 1,
+            clients: 1,
+            duration: Duration::from_secs(10),
+            transactions: None,
+            seed: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BenchReport {
+    pub transactions: usize,
+    pub aborts: usize,
+    pub elapsed: Duration,
+    // コミットしたトランザクションの時間を短い順に
+    latencies: Vec<Duration>,
+}
+
+impl BenchReport {
+    // 1 秒あたりのコミットしたトランザクション
+    pub fn throughput(&self) -> f64 {
+        self.transactions as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    // p は 0 から 100。最も近い順位の値
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        writeln!(
+            f,
+            "transactions: {} ({} aborted) in {:.3} s",
+            self.transactions,
+            self.aborts,
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(f, "throughput: {:.1} tps", self.throughput())?;
+        write!(
+            f,
+            "latency: p50 {:.3} ms, p95 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+            ms(self.percentile(50.0)),
+            ms(self.percentile(95.0)),
+            ms(self.percentile(99.0)),
+            ms(self.percentile(100.0))
+        )
+    }
+}
+
+// 表を作り直して規模の分の行を入れる
+pub fn load(db: &AsyncDatabase, config: &BenchConfig) -> Result<(), Error> {
+    let conn = block_on(db.connect())?;
+    let scale = config.scale.max(1);
+    match config.workload {
+        Workload::TpcB => {
+            block_on(conn.execute_batch(
+                "DROP TABLE IF EXISTS pgbench_history;
+                 DROP TABLE IF EXISTS pgbench_accounts;
+                 DROP TABLE IF EXISTS pgbench_tellers;
+                 DROP TABLE IF EXISTS pgbench_branches;
+                 CREATE TABLE pgbench_branches (bid INTEGER PRIMARY KEY, bbalance INTEGER);
+                 CREATE TABLE pgbench_tellers (tid INTEGER PRIMARY KEY, bid INTEGER, tbalance INTEGER);
+                 CREATE TABLE pgbench_accounts (aid INTEGER PRIMARY KEY, bid INTEGER, abalance INTEGER, filler TEXT);
+                 CREATE TABLE pgbench_history (tid INTEGER, bid INTEGER, aid INTEGER, delta INTEGER, mtime TIMESTAMP)",
+            ))?;
+            insert_rows(&conn, "pgbench_branches", scale, |i| {
+                format!("({}, 0)", i + 1)
+            })?;
+            insert_rows(&conn, "pgbench_tellers", scale * TELLERS_PER_SCALE, |i| {
+                format!("({}, {}, 0)", i + 1, i / TELLERS_PER_SCALE + 1)
+            })?;
+            insert_rows(&conn, "pgbench_accounts", scale * ACCOUNTS_PER_SCALE, |i| {
+                format!("({}, {}, 0, '')", i + 1, i / ACCOUNTS_PER_SCALE + 1)
+            })?;
+        }
+        Workload::Ycsb { .. } => {
+            block_on(conn.execute_batch(
+                "DROP TABLE IF EXISTS usertable;
+                 CREATE TABLE usertable (ycsb_key INTEGER PRIMARY KEY, field0 TEXT, field1 TEXT)",
+            ))?;
+            insert_rows(&conn, "usertable", scale * RECORDS_PER_SCALE, |i| {
+                format!("({}, '{:0>20}', '{:0>20}')", i + 1, i, i)
+            })?;
+        }
+    }
+    block_on(conn.execute("ANALYZE", &[]))?;
+    Ok(())
+}
+
+fn insert_rows(
+    conn: &AsyncConnection,
+    table: &str,
+    count: usize,
+    row: impl Fn(usize) -> String,
+) -> Result<(), Error> {
+    for start in (0..count).step_by(LOAD_BATCH) {
+        let rows: Vec<String> = (start..(start + LOAD_BATCH).min(count)).map(&row).collect();
+        let sql = format!("INSERT INTO {} VALUES {}", table, rows.join(", "));
+        block_on(conn.execute(&sql, &[]))?;
+    }
+    Ok(())
+}
+
+// load した表に負荷をかける
+pub fn run(db: &AsyncDatabase, config: &BenchConfig) -> Result<BenchReport, Error> {
+    let clients = config.clients.max(1);
+    let start = Instant::now();
+    let deadline = start + config.duration;
+    let handles: Vec<_> = (0..clients)
+        .map(|client| {
+            let db = db.clone();
+            let config = config.clone();
+            // 全体の数をクライアントで分ける
+            let limit = config
+                .transactions
+                .map(|n| n / clients + usize::from(client < n % clients));
+            thread::spawn(move || -> Result<Client, Error> {
+                let mut state = Client {
+                    conn: block_on(db.connect())?,
+                    rng: Rng::new(config.seed.wrapping_add(client as u64)),
+                    latencies: vec![],
+                    aborts: 0,
+                };
+                while limit.is_none_or(|n| state.latencies.len() < n) && Instant::now() < deadline {
+                    state.transaction(&config)?;
+                }
+                Ok(state)
+            })
+        })
+        .collect();
+    let mut report = BenchReport {
+        transactions: 0,
+        aborts: 0,
+        elapsed: Duration::ZERO,
+        latencies: vec![],
+    };
+    for handle in handles {
+        let client = handle.join().expect("benchmark client panicked")?;
+        report.aborts += client.aborts;
+        report.latencies.extend(client.latencies);
+    }
+    report.elapsed = start.elapsed();
+    report.transactions = report.latencies.len();
+    report.latencies.sort();
+    Ok(report)
+}
+
+struct Client {
+    conn: AsyncConnection,
+    rng: Rng,
+    latencies: Vec<Duration>,
+    aborts: usize,
+}
+
+impl Client {
+    fn transaction(&mut self, config: &BenchConfig) -> Result<(), Error> {
+        let scale = config.scale.max(1);
+        let start = Instant::now();
+        let result = match config.workload {
+            Workload::TpcB => {
+                let aid = self.rng.below(scale * ACCOUNTS_PER_SCALE) + 1;
+                let tid = self.rng.below(scale * TELLERS_PER_SCALE) + 1;
+                let bid = self.rng.below(scale) + 1;
+                let delta = self.rng.below(10_001) as i64 - 5000;
+                self.tpcb(aid, tid, bid, delta)
+            }
+            Workload::Ycsb { read_ratio } => {
+                let key = Value::from((self.rng.below(scale * RECORDS_PER_SCALE) + 1) as i64);
+                if self.rng.unit() < read_ratio {
+                    block_on(
+                        self.conn
+                            .query("SELECT * FROM usertable WHERE ycsb_key = $1", &[key]),
+                    )
+                    .map(|_| ())
+                } else {
+                    let value = Value::from(format!("{:0>20}", self.rng.next()));
+                    block_on(self.conn.execute(
+                        "UPDATE usertable SET field0 = $2 WHERE ycsb_key = $1",
+                        &[key, value],
+                    ))
+                    .map(|_| ())
+                }
+            }
+        };
+        match result {
+            Ok(()) => self.latencies.push(start.elapsed()),
+            // ロックが取れないか直列化に失敗したトランザクションは、取り消して次に進む
+            Err(e) if matches!(e.sqlstate(), "55P03" | "40001" | "40P01") => {
+                let _ = block_on(self.conn.execute("ROLLBACK", &[]));
+                self.aborts += 1;
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn tpcb(&self, aid: usize, tid: usize, bid: usize, delta: i64) -> Result<(), Error> {
+        let conn = &self.conn;
+        let (aid, tid, bid, delta) = (
+            Value::from(aid as i64),
+            Value::from(tid as i64),
+            Value::from(bid as i64),
+            Value::from(delta),
+        );
+        block_on(conn.execute("BEGIN", &[]))?;
+        block_on(conn.execute(
+            "UPDATE pgbench_accounts SET abalance = abalance + $1 WHERE aid = $2",
+            &[delta.clone(), aid.clone()],
+        ))?;
+        block_on(conn.query(
+            "SELECT abalance FROM pgbench_accounts WHERE aid = $1",
+            std::slice::from_ref(&aid),
+        ))?;
+        block_on(conn.execute(
+            "UPDATE pgbench_tellers SET tbalance = tbalance + $1 WHERE tid = $2",
+            &[delta.clone(), tid.clone()],
+        ))?;
+        block_on(conn.execute(
+            "UPDATE pgbench_branches SET bbalance = bbalance + $1 WHERE bid = $2",
+            &[delta.clone(), bid.clone()],
+        ))?;
+        block_on(conn.execute(
+            "INSERT INTO pgbench_history VALUES ($1, $2, $3, $4, now())",
+            &[tid, bid, aid, delta],
+        ))?;
+        block_on(conn.execute("COMMIT", &[]))?;
+        Ok(())
+    }
+}
+
+// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // 0 の状態からは抜け出せない
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    // [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench() {
+        let db = AsyncDatabase::open_temporary().unwrap();
+        let config = BenchConfig {
+            clients: 3,
+            transactions: Some(40),
+            duration: Duration::from_secs(60),
+            ..BenchConfig::default()
+        };
+        load(&db, &config).unwrap();
+        let report = run(&db, &config).unwrap();
+        assert_eq!(report.transactions, 40);
+        assert!(report.percentile(50.0) <= report.percentile(99.0));
+        assert!(report.to_string().contains("transactions: 40 ("));
+
+        // コミットした分だけ残高と履歴が揃う
+        let conn = block_on(db.connect()).unwrap();
+        let sum = |sql: &str| -> Value {
+            block_on(conn.query_row(sql, &[])).unwrap().values()[0].clone()
+        };
+        let history = sum("SELECT sum(delta) FROM pgbench_history");
+        assert_eq!(sum("SELECT sum(abalance) FROM pgbench_accounts"), history);
+        assert_eq!(sum("SELECT sum(bbalance) FROM pgbench_branches"), history);
+        assert_eq!(
+            sum("SELECT count(*) FROM pgbench_history"),
+            Value::BigInt(40)
+        );
+
+        let config = BenchConfig {
+            workload: Workload::Ycsb { read_ratio: 0.5 },
+            clients: 2,
+            transactions: Some(20),
+            ..config
+        };
+        load(&db, &config).unwrap();
+        assert_eq!(run(&db, &config).unwrap().transactions, 20);
+    }
+}
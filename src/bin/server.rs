@@ -0,0 +1,602 @@
+// PostgreSQL のプロトコルで話すサーバー
+//
+// psql などのクライアントから接続できる。LOGIN のロールがあれば平文のパスワードで認証する。
+// HTTP の問い合わせは認証せず、postgres のデータベースで持ち主の権限で実行する。
+// クライアントごとのスレッドがメッセージを読み書きし、文は 1 つのエンジンのスレッドが受け取った順に実行する。
+// 接続ごとにトランザクションと設定、名前をつけた文とポータルを分ける。
+// ロックをすぐに取れない文は、エンジンのスレッドを止めずに接続ごと寝かせ、ほかの接続の依頼を続けて実行する。
+// 寝かせた文は依頼を 1 つ実行するごとと LOCK_RETRY_INTERVAL ごとに実行し直し、ロックが取れたら応答する。
+// HTTP の問い合わせは接続を持ち続けないので、ロックをすぐに取れなければ失敗させる。
+// CancelRequest が来れば、鍵の合う接続で実行中の文を止める。
+// --http を指定すれば、その番地で JSON の問い合わせ (POST /query) も受け付ける。要求ごとに新しい接続で実行する。
+// 同じ番地の GET /metrics で、計器の値を Prometheus のテキスト形式で返す。
+// --config で設定ファイルを指定すれば、その設定で開く。
+// --log-min-duration を指定すれば、その時間 (ミリ秒) より長くかかった文を標準エラーに書く。
+// 起動パケットの database でつなぐデータベースを選ぶ。指定がなければ postgres につなぐ。
+// CREATE DATABASE で作ったデータベースは --data のファイルと同じディレクトリに置く。
+// --data でファイルを指定しなければ、データベースは一時ファイルに作り、終了すると消える。
+// コミットはグループコミットにする。エンジンのスレッドはコミットを記してから commit_delay の間ほかの依頼を実行し、
+// そのあとログを 1 回の fsync でディスクに届けてから、その間の応答をまとめて送る。
+// autovacuum が on なら、別のスレッドが autovacuum_naptime ごとにエンジンのスレッドへ自動の VACUUM と ANALYZE を頼む。
+// 1 度に 1 つのテーブルだけを片づけ、片づけたものがあれば autovacuum_delay だけ待って次を頼む。
+// --replication を指定すれば、その番地でつないだスタンバイに postgres のデータベースのログを送る。
+// --standby でオンラインバックアップのディレクトリを、--primary でプライマリの --replication の番地を指定すれば、
+// スタンバイとして開いて読むだけの文に答える。別のスレッドがプライマリからログを受け取ってエンジンのスレッドに当てさせ、
+// 切れたらつなぎ直す。--promote-file のファイルができたら昇格させ、書き込みも受け付ける
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rdbms_training::cluster::{Cluster, CommitGroup};
+use rdbms_training::executor::CancelToken;
+use rdbms_training::http;
+use rdbms_training::pgwire::{self, Session, Startup, StartupRequest};
+use rdbms_training::replication;
+use rdbms_training::settings::Settings;
+use rdbms_training::slowlog::SlowQueryLog;
+use rdbms_training::wal::{LogRecord, Lsn};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:5432";
+// プライマリとの接続が切れてからつなぎ直すまでの間
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// ロックを待つ文を、依頼が来なくても実行し直す間隔。止められたか lock_timeout を過ぎたかもこのときに確かめる
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Options {
+    listen: String,
+    http: Option<String>,
+    data: Option<String>,
+    config: Option<String>,
+    log_min_duration: Option<Duration>,
+    replication: Option<String>,
+    standby: Option<String>,
+    primary: Option<String>,
+    promote_file: Option<String>,
+}
+
+// クライアントのスレッドからエンジンのスレッドへの依頼
+enum Request {
+    Connect {
+        id: i32,
+        secret_key: i32,
+        startup: Startup,
+        reply: Sender<Reply>,
+    },
+    Message {
+        id: i32,
+        tag: u8,
+        body: Vec<u8>,
+    },
+    Disconnect {
+        id: i32,
+    },
+    Http {
+        request: http::Request,
+        reply: Sender<http::Response>,
+    },
+    // 片づけたテーブルがあれば true を返す
+    Autovacuum {
+        reply: Sender<bool>,
+    },
+    // スタンバイで、プライマリから受け取ったもの
+    Replicate {
+        received: Option<(Lsn, LogRecord)>,
+    },
+    // スタンバイが当て終えたログの位置を返す。つなぎ直すときはそこから受け取る
+    StandbyLsn {
+        reply: Sender<Option<Lsn>>,
+    },
+    Promote,
+}
+
+// 1 つの依頼への応答。close が立っていれば接続を閉じる
+struct Reply {
+    output: Vec<u8>,
+    close: bool,
+}
+
+// グループコミットでログをディスクに届けるまでためておく、応答を送ること
+type Pending = Box<dyn FnOnce()>;
+
+// 接続ごとの、BackendKeyData で渡した鍵と実行中の文を止める印
+type Cancels = Arc<Mutex<HashMap<i32, (i32, CancelToken)>>>;
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        listen: DEFAULT_LISTEN.to_string(),
+        http: None,
+        data: None,
+        config: None,
+        log_min_duration: None,
+        replication: None,
+        standby: None,
+        primary: None,
+        promote_file: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{}: missing required argument", arg))?;
+                (arg, value)
+            }
+        };
+        match name.as_str() {
+            "--listen" | "-l" => options.listen = value,
+            "--http" => options.http = Some(value),
+            "--data" | "-D" => options.data = Some(value),
+            "--config" | "-c" => options.config = Some(value),
+            "--log-min-duration" => {
+                let ms = value
+                    .parse()
+                    .map_err(|_| format!("invalid --log-min-duration: {}", value))?;
+                options.log_min_duration = Some(Duration::from_millis(ms));
+            }
+            "--replication" => options.replication = Some(value),
+            "--standby" => options.standby = Some(value),
+            "--primary" => options.primary = Some(value),
+            "--promote-file" => options.promote_file = Some(value),
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+    if options.standby.is_some() != options.primary.is_some() {
+        return Err("--standby and --primary must be given together".to_string());
+    }
+    if options.standby.is_some() && options.data.is_some() {
+        return Err("--standby cannot be used with --data".to_string());
+    }
+    Ok(options)
+}
+
+// データベースは Rc で分け合うので、すべての接続をこのスレッドで持つ。
+// コミットはグループコミットにし、ログをディスクに届けるまで応答を送らない
+fn run_engine(
+    cluster: Cluster,
+    requests: Receiver<Request>,
+    cancels: Cancels,
+    commit_delay: Duration,
+) {
+    let mut sessions: HashMap<i32, (Session, Sender<Reply>)> = HashMap::new();
+    // ロックを待っている接続。来た順
+    let mut parked = vec![];
+    let mut group = CommitGroup::new(commit_delay);
+    cluster.set_group_commit(true);
+    loop {
+        retry_parked(&cluster, &mut sessions, &mut parked, &mut group);
+        let timeout = match (group.timeout(), parked.is_empty()) {
+            (timeout, true) => timeout,
+            (timeout, false) => {
+                Some(timeout.map_or(LOCK_RETRY_INTERVAL, |t| t.min(LOCK_RETRY_INTERVAL)))
+            }
+        };
+        let request = match timeout {
+            None => requests.recv().ok(),
+            Some(timeout) => match requests.recv_timeout(timeout) {
+                Ok(request) => Some(request),
+                Err(RecvTimeoutError::Timeout) => {
+                    if group.timeout().is_some_and(|t| t.is_zero()) {
+                        flush_commits(&cluster, &mut group);
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+        };
+        let Some(request) = request else {
+            flush_commits(&cluster, &mut group);
+            return;
+        };
+        let reply: Pending = match request {
+            Request::Connect {
+                id,
+                secret_key,
+                startup,
+                reply,
+            } => {
+                let mut conn = match cluster.connect(startup.get("database")) {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        let _ = reply.send(Reply {
+                            output: pgwire::startup_error(&e),
+                            close: true,
+                        });
+                        continue;
+                    }
+                };
+                conn.set_park_lock_waits(true);
+                let cancel = conn.cancel_token();
+                cancels.lock().unwrap().insert(id, (secret_key, cancel));
+                let mut session = Session::login(conn, &startup, id, secret_key);
+                let output = session.take_output();
+                sessions.insert(id, (session, reply.clone()));
+                Box::new(move || {
+                    let _ = reply.send(Reply {
+                        output,
+                        close: false,
+                    });
+                })
+            }
+            Request::Message { id, tag, body } => {
+                let Some((session, reply)) = sessions.get_mut(&id) else {
+                    continue;
+                };
+                let close = session.handle(tag, body).is_err();
+                if session.waiting_for_lock() {
+                    parked.push(id);
+                    continue;
+                }
+                let output = session.take_output();
+                let reply = reply.clone();
+                Box::new(move || {
+                    let _ = reply.send(Reply { output, close });
+                })
+            }
+            // 実行中のトランザクションは接続を閉じたときに取り消す
+            Request::Disconnect { id } => {
+                sessions.remove(&id);
+                cancels.lock().unwrap().remove(&id);
+                continue;
+            }
+            Request::Http { request, reply } => {
+                let mut conn = cluster.connect(None).unwrap();
+                conn.set_lock_timeout(Some(Duration::ZERO));
+                conn.fix_setting("lock_timeout");
+                let response = http::handle(&mut conn, &request);
+                Box::new(move || {
+                    let _ = reply.send(response);
+                })
+            }
+            Request::Autovacuum { reply } => {
+                let done = match cluster.autovacuum() {
+                    Ok(done) => done.is_some(),
+                    Err(e) => {
+                        eprintln!("autovacuum: {}", e);
+                        false
+                    }
+                };
+                Box::new(move || {
+                    let _ = reply.send(done);
+                })
+            }
+            // 当てられなかったスタンバイはプライマリと食い違うので止まる
+            Request::Replicate { received } => {
+                if let Err(e) = cluster.replay(received) {
+                    eprintln!("replication: {}", e);
+                    std::process::exit(1);
+                }
+                continue;
+            }
+            Request::StandbyLsn { reply } => {
+                let _ = reply.send(cluster.standby_lsn());
+                continue;
+            }
+            Request::Promote => {
+                match cluster.promote() {
+                    Ok(()) => eprintln!("promoted to primary"),
+                    Err(e) => eprintln!("promote: {}", e),
+                }
+                continue;
+            }
+        };
+        if let Some(reply) = group.hold(&cluster, reply) {
+            reply();
+        }
+    }
+}
+
+// ロックを待っている接続の文を来た順に実行し直し、待ち終えたものに応答する。
+// 待ち終えた文がロックを外したかもしれないので、待ち終えるものがなくなるまで繰り返す
+fn retry_parked(
+    cluster: &Cluster,
+    sessions: &mut HashMap<i32, (Session, Sender<Reply>)>,
+    parked: &mut Vec<i32>,
+    group: &mut CommitGroup<Pending>,
+) {
+    loop {
+        let mut done = false;
+        parked.retain(|id| {
+            // 閉じた接続の文はもう待たない
+            let Some((session, reply)) = sessions.get_mut(id) else {
+                return false;
+            };
+            session.retry();
+            if session.waiting_for_lock() {
+                return true;
+            }
+            done = true;
+            let output = session.take_output();
+            let reply = reply.clone();
+            let reply: Pending = Box::new(move || {
+                let _ = reply.send(Reply {
+                    output,
+                    close: false,
+                });
+            });
+            if let Some(reply) = group.hold(cluster, reply) {
+                reply();
+            }
+            false
+        });
+        if !done {
+            return;
+        }
+    }
+}
+
+// ログをディスクに届けてから、ためた応答を送る。届けられなければ、コミットしたと答えられないので止まる
+fn flush_commits(cluster: &Cluster, group: &mut CommitGroup<Pending>) {
+    match group.flush(cluster) {
+        Ok(replies) => replies.into_iter().for_each(|reply| reply()),
+        Err(e) => {
+            eprintln!("wal flush: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// エンジンのスレッドが止まるまで、自動の VACUUM と ANALYZE を頼み続ける
+fn run_autovacuum(requests: Sender<Request>, naptime: Duration, delay: Duration) {
+    loop {
+        let (reply, done) = mpsc::channel();
+        if requests.send(Request::Autovacuum { reply }).is_err() {
+            return;
+        }
+        let Ok(done) = done.recv() else {
+            return;
+        };
+        thread::sleep(if done { delay } else { naptime });
+    }
+}
+
+// スタンバイで、プライマリから受け取ったログをエンジンのスレッドに当てさせる。
+// 切れたら当て終えたところからつなぎ直し、promote_file ができたら昇格を頼んで終わる
+fn run_receiver(primary: String, requests: Sender<Request>, promote_file: Option<PathBuf>) {
+    let promoted = || promote_file.as_ref().is_some_and(|path| path.exists());
+    loop {
+        if promoted() {
+            let _ = requests.send(Request::Promote);
+            return;
+        }
+        let (reply, lsn) = mpsc::channel();
+        if requests.send(Request::StandbyLsn { reply }).is_err() {
+            return;
+        }
+        let Ok(Some(start)) = lsn.recv() else {
+            return;
+        };
+        let result = (|| -> Result<(), replication::Error> {
+            let (mut receiver, _) = replication::Receiver::connect(primary.as_str(), start)?;
+            loop {
+                let received = receiver.receive()?;
+                // 送るものがないときの KEEPALIVE ごとに昇格を確かめる
+                if received.is_none() && promoted() {
+                    return Ok(());
+                }
+                if requests.send(Request::Replicate { received }).is_err() {
+                    return Ok(());
+                }
+            }
+        })();
+        if let Err(e) = result {
+            eprintln!("replication: {}", e);
+            thread::sleep(RECONNECT_INTERVAL);
+        }
+    }
+}
+
+fn engine_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "engine thread stopped")
+}
+
+fn secret_key() -> i32 {
+    RandomState::new().build_hasher().finish() as i32
+}
+
+fn handle_client(
+    stream: TcpStream,
+    id: i32,
+    requests: Sender<Request>,
+    cancels: Cancels,
+) -> io::Result<()> {
+    let mut reader = stream.try_clone()?;
+    let mut writer = BufWriter::new(stream);
+    let startup = match pgwire::read_startup(&mut reader, &mut writer)? {
+        StartupRequest::Startup(startup) => startup,
+        StartupRequest::Cancel {
+            process_id,
+            secret_key,
+        } => {
+            if let Some((key, cancel)) = cancels.lock().unwrap().get(&process_id) {
+                if *key == secret_key {
+                    cancel.cancel();
+                }
+            }
+            return Ok(());
+        }
+    };
+    let (reply, replies) = mpsc::channel();
+    requests
+        .send(Request::Connect {
+            id,
+            secret_key: secret_key(),
+            startup,
+            reply,
+        })
+        .map_err(|_| engine_stopped())?;
+    let result = (|| loop {
+        let Reply { output, close } = replies.recv().map_err(|_| engine_stopped())?;
+        writer.write_all(&output)?;
+        writer.flush()?;
+        if close {
+            return Ok(());
+        }
+        let Some((tag, body)) = pgwire::read_message(&mut reader)? else {
+            return Ok(());
+        };
+        if tag == b'X' {
+            return Ok(());
+        }
+        requests
+            .send(Request::Message { id, tag, body })
+            .map_err(|_| engine_stopped())?;
+    })();
+    let _ = requests.send(Request::Disconnect { id });
+    result
+}
+
+fn handle_http(stream: TcpStream, requests: Sender<Request>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let response = match http::read_request(&mut reader) {
+        // 計器はエンジンのスレッドを待たずに読める
+        Ok(request) if request.method == "GET" && request.path == "/metrics" => {
+            http::Response::metrics()
+        }
+        Ok(request) => {
+            let (reply, response) = mpsc::channel();
+            requests
+                .send(Request::Http { request, reply })
+                .map_err(|_| engine_stopped())?;
+            response.recv().map_err(|_| engine_stopped())?
+        }
+        Err(response) => response,
+    };
+    response.write_to(&mut writer)
+}
+
+fn serve_http(listener: TcpListener, requests: Sender<Request>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept: {}", e);
+                continue;
+            }
+        };
+        let requests = requests.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_http(stream, requests) {
+                eprintln!("http: {}", e);
+            }
+        });
+    }
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!(
+                "usage: server [--listen ADDR] [--http ADDR] [--data PATH] [--config PATH] [--log-min-duration MS] \
+                 [--replication ADDR] [--standby DIR --primary ADDR [--promote-file PATH]]"
+            );
+            std::process::exit(2);
+        }
+    };
+    let settings = match &options.config {
+        Some(path) => match Settings::load(path) {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => Settings::default(),
+    };
+    let bind = |addr: &str| match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("{}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    let listener = bind(&options.listen);
+    let http_listener = options.http.as_deref().map(bind);
+    let replication_listener = options.replication.as_deref().map(bind);
+    let (requests, receiver) = mpsc::channel();
+    let (opened, open_result) = mpsc::channel();
+    let cancels = Cancels::default();
+    let engine_cancels = cancels.clone();
+    let data = options.data.clone();
+    let standby = options.standby.clone();
+    let log_min_duration = options.log_min_duration;
+    let commit_delay = settings.commit_delay;
+    let autovacuum = settings
+        .autovacuum
+        .then_some((settings.autovacuum_naptime, settings.autovacuum_delay));
+    thread::spawn(move || {
+        let cluster = match &standby {
+            Some(backup) => Cluster::open_standby(Path::new(backup), settings),
+            None => Cluster::open(data.as_deref().map(Path::new), settings),
+        };
+        let cluster = cluster.and_then(|cluster| {
+            if let Some(listener) = replication_listener {
+                cluster.serve_replication(listener)?;
+            }
+            Ok(cluster)
+        });
+        match cluster {
+            Ok(cluster) => {
+                if let Some(min_duration) = log_min_duration {
+                    cluster.set_slow_query_log(move || {
+                        SlowQueryLog::to_writer(io::stderr(), min_duration)
+                    });
+                }
+                let _ = opened.send(Ok(()));
+                run_engine(cluster, receiver, engine_cancels, commit_delay);
+            }
+            Err(e) => {
+                let _ = opened.send(Err(e.to_string()));
+            }
+        }
+    });
+    if let Ok(Err(message)) = open_result.recv() {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+    if let Some((naptime, delay)) = autovacuum {
+        let requests = requests.clone();
+        thread::spawn(move || run_autovacuum(requests, naptime, delay));
+    }
+    if let Some(primary) = options.primary.clone() {
+        let requests = requests.clone();
+        let promote_file = options.promote_file.clone().map(PathBuf::from);
+        thread::spawn(move || run_receiver(primary, requests, promote_file));
+    }
+    if let Some(addr) = &options.replication {
+        eprintln!("sending WAL to standbys on {}", addr);
+    }
+    eprintln!("listening on {}", options.listen);
+    if let Some(listener) = http_listener {
+        eprintln!("listening for HTTP on {}", options.http.as_deref().unwrap());
+        let requests = requests.clone();
+        thread::spawn(move || serve_http(listener, requests));
+    }
+    for (id, stream) in (1..).zip(listener.incoming()) {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept: {}", e);
+                continue;
+            }
+        };
+        let requests = requests.clone();
+        let cancels = cancels.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_client(stream, id, requests, cancels) {
+                eprintln!("connection {}: {}", id, e);
+            }
+        });
+    }
+}
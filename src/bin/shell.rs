@@ -0,0 +1,513 @@
+// 対話的に SQL を実行するシェル
+//
+// 文は ; で終わるまで何行にわたって書いてもよい。実行した文は 1 行にして ~/.rdbms_history に書き足していき、
+// 起動すると読み込んで ↑ ↓ で呼び出せる。
+// 問い合わせの実行中に Ctrl-C を押すとその問い合わせだけを止め、入力の途中で押すと書きかけの文を捨てる。
+// \dt、\d table、\timing、\format、\i file、\dump [file]、\q のメタコマンドも使える。\dump で書いたファイルは \i で読み戻せる。
+// 結果の書き方は --format でも選べる。
+// shell bench と始めれば、シェルの代わりに負荷をかけて性能を測る (bench_args を参照)。
+// 行の編集は lineedit を参照。引数にファイルを渡せばそのデータベースを開き、なければ一時ファイルに作って終了すると消える
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use rdbms_training::asyncdb::AsyncDatabase;
+use rdbms_training::bench::{self, BenchConfig, Workload};
+use rdbms_training::collation::Collation;
+use rdbms_training::connection::{Connection, Database, Row, StatementResult};
+use rdbms_training::dump;
+use rdbms_training::executor::{self, CancelToken};
+use rdbms_training::format::{self, OutputFormat};
+use rdbms_training::lineedit::{Input, LineEditor};
+use rdbms_training::planner;
+use rdbms_training::sql;
+use rdbms_training::sql::lexer::{Lexer, TokenKind};
+use rdbms_training::types::Value;
+
+const HISTORY_FILE: &str = ".rdbms_history";
+// \i で入れ子に読めるファイルの深さ
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Ctrl-C のシグナルハンドラから立てる印
+static CANCEL: OnceLock<CancelToken> = OnceLock::new();
+
+// ; で終わっていれば文が揃っている。閉じていない文字列やコメントの途中なら続きを読む
+fn is_complete(buffer: &str) -> bool {
+    match Lexer::new(buffer).tokenize() {
+        Ok(tokens) => tokens
+            .iter()
+            .rev()
+            .find(|token| token.kind != TokenKind::Eof)
+            .is_some_and(|token| token.kind == TokenKind::Semicolon),
+        Err(e) => !e.message.starts_with("unterminated"),
+    }
+}
+
+fn print_result(result: StatementResult, format: OutputFormat) {
+    match result {
+        StatementResult::Rows(rows) => {
+            let columns = rows.columns().to_vec();
+            let rows: Vec<_> = rows.map(Row::into_values).collect();
+            let _ = format.write(&mut io::stdout().lock(), &columns, &rows);
+        }
+        result => println!("{}", result.tag()),
+    }
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+struct Shell {
+    conn: Connection,
+    editor: LineEditor,
+    history: Option<File>,
+    // ; が来るまでためている文
+    buffer: String,
+    // 結果の書き方
+    format: OutputFormat,
+    // 立っていれば文ごとに実行にかかった時間を書く
+    timing: bool,
+    // \i で読んでいるファイルの深さ。ファイルから読んだ行は履歴に残さない
+    depth: usize,
+}
+
+impl Shell {
+    // 1 行を読む。文の始めの \ はメタコマンド
+    fn feed(&mut self, line: &str) -> Flow {
+        if self.buffer.is_empty() {
+            if line.trim().is_empty() {
+                return Flow::Continue;
+            }
+            if let Some(command) = line.trim().strip_prefix('\\') {
+                self.record(line);
+                return self.meta(command);
+            }
+        }
+        self.buffer.push_str(line);
+        if !is_complete(&self.buffer) {
+            return Flow::Continue;
+        }
+        let source = std::mem::take(&mut self.buffer);
+        self.record(&source);
+        let statements = match sql::parse(&source) {
+            Ok(statements) => statements,
+            Err(e) => {
+                eprintln!("ERROR:  {}", e);
+                return Flow::Continue;
+            }
+        };
+        for stmt in &statements {
+            let start = Instant::now();
+            match self.conn.execute_statement(stmt, &[]) {
+                Ok(result) => print_result(result, self.format),
+                Err(e) => eprintln!("ERROR:  {}", e),
+            }
+            if self.timing {
+                println!("Time: {:.3} ms", start.elapsed().as_secs_f64() * 1000.0);
+            }
+            if self.conn.cancel_token().is_canceled() {
+                break;
+            }
+        }
+        Flow::Continue
+    }
+
+    // 何行にわたる文も、行を空白でつないだ 1 行にして残す
+    fn record(&mut self, source: &str) {
+        if self.depth > 0 {
+            return;
+        }
+        let line = source
+            .split('\n')
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let line = line.trim();
+        if self
+            .editor
+            .history()
+            .last()
+            .is_some_and(|last| last == line)
+        {
+            return;
+        }
+        self.editor.add_history(line);
+        if let Some(file) = &mut self.history {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    fn meta(&mut self, command: &str) -> Flow {
+        let (name, arg) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, arg)| (name, arg.trim()));
+        match (name, arg) {
+            ("q", _) => return Flow::Quit,
+            ("dt", _) | ("d", "") => self.list_tables(),
+            ("d", table) => self.describe(table),
+            ("timing", arg) => {
+                self.timing = match arg {
+                    "on" => true,
+                    "off" => false,
+                    _ => !self.timing,
+                };
+                println!("Timing is {}.", if self.timing { "on" } else { "off" });
+            }
+            ("format", "") => println!("Output format is {}.", self.format.name()),
+            ("format", name) => match name.parse() {
+                Ok(format) => {
+                    self.format = format;
+                    println!("Output format is {}.", self.format.name());
+                }
+                Err(e) => eprintln!("\\format: {}", e),
+            },
+            ("i", "") => eprintln!("\\i: missing required argument"),
+            ("i", path) => return self.include(path),
+            ("dump", path) => self.dump(path),
+            ("check", _) => self.check(),
+            _ => eprintln!("invalid command \\{}", name),
+        }
+        Flow::Continue
+    }
+
+    fn list_tables(&self) {
+        let catalog = self.conn.catalog();
+        let tables = catalog.tables();
+        if tables.is_empty() {
+            println!("Did not find any relations.");
+            return;
+        }
+        let rows: Vec<executor::Row> = tables
+            .iter()
+            .map(|t| {
+                vec![
+                    Value::Text(t.name.clone()),
+                    Value::Text("table".to_string()),
+                    Value::Integer(t.columns.len() as i64),
+                ]
+            })
+            .collect();
+        println!("List of relations");
+        let columns = ["Name", "Type", "Columns"].map(String::from);
+        let _ = OutputFormat::Aligned.write(&mut io::stdout().lock(), &columns, &rows);
+    }
+
+    // 列と、インデックスと一意性の制約を書く
+    fn describe(&self, name: &str) {
+        let catalog = self.conn.catalog();
+        let Some(table) = catalog.table(name) else {
+            eprintln!("Did not find any relation named \"{}\".", name);
+            return;
+        };
+        let rows: Vec<executor::Row> = table
+            .columns
+            .iter()
+            .map(|c| {
+                let collation = match c.collation {
+                    Collation::Binary => String::new(),
+                    collation => collation.name().to_string(),
+                };
+                let nullable = if c.not_null { "not null" } else { "" };
+                vec![
+                    Value::Text(c.name.clone()),
+                    Value::Text(c.data_type.to_string()),
+                    Value::Text(collation),
+                    Value::Text(nullable.to_string()),
+                ]
+            })
+            .collect();
+        println!("Table \"{}\"", table.name);
+        let columns = ["Column", "Type", "Collation", "Nullable"].map(String::from);
+        let _ = format::write_table(&mut io::stdout().lock(), &columns, &rows);
+        if !table.indexes.is_empty() {
+            println!("Indexes:");
+        }
+        for index in &table.indexes {
+            let keys = planner::index_columns(&catalog, &table.name, &index.name);
+            let unique = if index.unique { " UNIQUE," } else { "" };
+            let method = match (index.fulltext, index.rtree) {
+                (true, _) => "fulltext",
+                (_, Some(_)) => "rtree",
+                _ => "btree",
+            };
+            println!(
+                "    \"{}\"{} {} ({})",
+                index.name,
+                unique,
+                method,
+                keys.join(", ")
+            );
+        }
+    }
+
+    // データベースを SQL の文にして path に書く。path を省けば標準出力
+    fn dump(&mut self, path: &str) {
+        let result = if path.is_empty() {
+            dump::dump(&mut self.conn, &mut io::stdout().lock())
+        } else {
+            File::create(path)
+                .map_err(Into::into)
+                .and_then(|file| dump::dump(&mut self.conn, &mut io::BufWriter::new(file)))
+        };
+        if let Err(e) = result {
+            eprintln!("\\dump: {}", e);
+        }
+    }
+
+    // データベースの整合性を確かめ、見つけた誤りをすべて書く
+    fn check(&mut self) {
+        match self.conn.check() {
+            Ok(problems) if problems.is_empty() => println!("No problems found."),
+            Ok(problems) => {
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                println!("{} problems found.", problems.len());
+            }
+            Err(e) => eprintln!("\\check: {}", e),
+        }
+    }
+
+    // ファイルの行を入力と同じように読む
+    fn include(&mut self, path: &str) -> Flow {
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            eprintln!("{}: too many nested \\i", path);
+            return Flow::Continue;
+        }
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return Flow::Continue;
+            }
+        };
+        let pending = std::mem::take(&mut self.buffer);
+        self.depth += 1;
+        let mut flow = Flow::Continue;
+        for line in source.lines() {
+            flow = self.feed(&format!("{}\n", line));
+            if let Flow::Quit = flow {
+                break;
+            }
+        }
+        self.depth -= 1;
+        self.buffer = pending;
+        flow
+    }
+}
+
+// 結果の書き方と、開くデータベースのファイル
+fn parse_args() -> std::result::Result<(OutputFormat, Option<String>), String> {
+    let mut format = OutputFormat::default();
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.split_once('=') {
+            Some(("--format", value)) => value.to_string(),
+            _ if arg == "--format" || arg == "-F" => args
+                .next()
+                .ok_or_else(|| format!("{}: missing required argument", arg))?,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if path.is_some() => return Err(format!("extra argument {}", arg)),
+            _ => {
+                path = Some(arg);
+                continue;
+            }
+        };
+        format = value.parse().map_err(|e| format!("{}: {}", arg, e))?;
+    }
+    Ok((format, path))
+}
+
+const BENCH_USAGE: &str = "usage: shell bench [--workload tpcb|ycsb] [--scale N] [--clients N] \
+[--duration SECONDS] [--transactions N] [--read-ratio R] [--seed N] [--data PATH]";
+
+// bench の引数。--data がなければ一時ファイルに作る。--transactions は全クライアントの合計
+fn bench_args(
+    mut args: impl Iterator<Item = String>,
+) -> std::result::Result<(BenchConfig, Option<String>), String> {
+    let mut config = BenchConfig::default();
+    let mut data = None;
+    let mut read_ratio = None;
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{}: missing required argument", arg))?;
+                (arg, value)
+            }
+        };
+        let number = |value: &str| {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("invalid {}: {}", name, value))
+        };
+        match name.as_str() {
+            "--workload" | "-w" => config.workload = value.parse()?,
+            "--scale" | "-s" => config.scale = number(&value)?,
+            "--clients" | "-c" => config.clients = number(&value)?,
+            "--duration" | "-T" => config.duration = Duration::from_secs(number(&value)? as u64),
+            "--transactions" | "-t" => config.transactions = Some(number(&value)?),
+            "--seed" => config.seed = number(&value)? as u64,
+            "--data" | "-D" => data = Some(value),
+            "--read-ratio" => {
+                let ratio = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|r| (0.0..=1.0).contains(r))
+                    .ok_or_else(|| format!("invalid {}: {}", name, value))?;
+                read_ratio = Some(ratio);
+            }
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+    if let Some(ratio) = read_ratio {
+        let Workload::Ycsb { read_ratio } = &mut config.workload else {
+            return Err("--read-ratio is only for the ycsb workload".to_string());
+        };
+        *read_ratio = ratio;
+    }
+    Ok((config, data))
+}
+
+fn run_bench(args: impl Iterator<Item = String>) -> i32 {
+    let (config, data) = match bench_args(args) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", BENCH_USAGE);
+            return 2;
+        }
+    };
+    let result = match &data {
+        Some(path) => AsyncDatabase::open(path),
+        None => AsyncDatabase::open_temporary(),
+    }
+    .and_then(|db| {
+        let start = Instant::now();
+        bench::load(&db, &config)?;
+        eprintln!(
+            "loaded scale {} in {:.3} s",
+            config.scale,
+            start.elapsed().as_secs_f64()
+        );
+        bench::run(&db, &config)
+    });
+    match result {
+        Ok(report) => {
+            println!("{}", report);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    Some(PathBuf::from(std::env::var_os("HOME")?).join(HISTORY_FILE))
+}
+
+// これまでの履歴と、書き足していくファイル
+fn open_history() -> (Vec<String>, Option<File>) {
+    let Some(path) = history_path() else {
+        return (vec![], None);
+    };
+    let lines = std::fs::read_to_string(&path)
+        .map(|s| s.lines().map(String::from).collect())
+        .unwrap_or_default();
+    let file = OpenOptions::new().create(true).append(true).open(path).ok();
+    (lines, file)
+}
+
+#[cfg(unix)]
+fn install_interrupt_handler() {
+    extern "C" fn on_interrupt(_: i32) {
+        if let Some(cancel) = CANCEL.get() {
+            cancel.cancel();
+        }
+    }
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    const SIGINT: i32 = 2;
+    // SAFETY: ハンドラは原子的な書き込みしかしない
+    unsafe {
+        signal(SIGINT, on_interrupt);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_interrupt_handler() {}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "bench").is_some() {
+        std::process::exit(run_bench(args));
+    }
+    let (format, path) = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("usage: shell [--format aligned|csv|tsv|json] [DATABASE]");
+            std::process::exit(2);
+        }
+    };
+    let db = match &path {
+        Some(path) => Database::open(path),
+        None => Database::open_temporary(),
+    };
+    let conn = match db {
+        Ok(db) => db.connect(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let cancel = CANCEL.get_or_init(|| conn.cancel_token()).clone();
+    install_interrupt_handler();
+    let (lines, history) = open_history();
+    let mut shell = Shell {
+        conn,
+        editor: LineEditor::new(lines),
+        history,
+        buffer: String::new(),
+        format,
+        timing: false,
+        depth: 0,
+    };
+    loop {
+        let prompt = if shell.buffer.is_empty() {
+            "rdbms=> "
+        } else {
+            "rdbms-> "
+        };
+        let line = match shell.editor.read_line(prompt)? {
+            Input::Line(line) => line,
+            Input::Interrupted => {
+                shell.buffer.clear();
+                continue;
+            }
+            Input::Eof => {
+                println!();
+                return Ok(());
+            }
+        };
+        // 端末でなければ Ctrl-C はシグナルで来る
+        if cancel.is_canceled() {
+            cancel.reset();
+            shell.buffer.clear();
+            continue;
+        }
+        if let Flow::Quit = shell.feed(&format!("{}\n", line)) {
+            return Ok(());
+        }
+    }
+}
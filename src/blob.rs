@@ -0,0 +1,237 @@
+use crate::buffer::{self, Buffer, BufferPoolManager};
+use crate::disk::{PageId, PAGE_SIZE};
+
+// 1 ページに収まらない大きな値
+//
+// | magic (4) | next_page_id (8) | last_page_id (8) | size (8) | データ |
+//
+// ページは単方向リストでつながっており、先頭ページの id が値の id になる。last_page_id と size は
+// 先頭ページでだけ使い、ほかのページの last_page_id は INVALID_PAGE_ID。size は値全体のバイト数。末尾のページのほかはデータで埋まっているので、
+// 位置からそれが何枚目のページにあるかがわかる。
+// 書き足すたびにページをそのまま書き換えるので、トランザクションには入らず取り消せない
+const MAGIC: u32 = 0x424c_4f42;
+const NEXT_PAGE_ID: usize = 4;
+const LAST_PAGE_ID: usize = 12;
+const SIZE: usize = 20;
+const HEADER_SIZE: usize = 28;
+
+pub const DATA_SIZE: usize = PAGE_SIZE - HEADER_SIZE;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Buffer(#[from] buffer::Error),
+    #[error("page {0} is not a large object")]
+    NotABlob(u64),
+}
+
+fn read_u64(page: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(page[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u64(page: &mut [u8], offset: usize, n: u64) {
+    page[offset..offset + 8].copy_from_slice(&n.to_be_bytes());
+}
+
+fn initialize_page(buffer: &Buffer, last: PageId) {
+    let mut page = buffer.page.borrow_mut();
+    page[..4].copy_from_slice(&MAGIC.to_be_bytes());
+    write_u64(
+        &mut page[..],
+        NEXT_PAGE_ID,
+        PageId::INVALID_PAGE_ID.to_u64(),
+    );
+    write_u64(&mut page[..], LAST_PAGE_ID, last.to_u64());
+    write_u64(&mut page[..], SIZE, 0);
+    buffer.is_dirty.set(true);
+}
+
+// 読み進めた位置のページ。次に読むページを先頭からたどり直さないように使う
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cursor {
+    // 何枚目のページか
+    index: u64,
+    page_id: Option<PageId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blob {
+    pub first_page_id: PageId,
+}
+
+impl Blob {
+    pub fn create(bufmgr: &mut BufferPoolManager) -> Result<Self, Error> {
+        let buffer = bufmgr.create_page()?;
+        initialize_page(&buffer, buffer.page_id);
+        Ok(Self {
+            first_page_id: buffer.page_id,
+        })
+    }
+
+    // 先頭ページが大きな値のページか確かめる
+    pub fn open(bufmgr: &mut BufferPoolManager, page_id: PageId) -> Result<Self, Error> {
+        let not_blob = || Error::NotABlob(page_id.to_u64());
+        if page_id.valid().is_none() || page_id.to_u64() >= bufmgr.page_count() {
+            return Err(not_blob());
+        }
+        let buffer = bufmgr.fetch_page(page_id)?;
+        let page = buffer.page.borrow();
+        if page[..4] != MAGIC.to_be_bytes()
+            || PageId(read_u64(&page[..], LAST_PAGE_ID)) == PageId::INVALID_PAGE_ID
+        {
+            return Err(not_blob());
+        }
+        Ok(Self {
+            first_page_id: page_id,
+        })
+    }
+
+    pub fn size(&self, bufmgr: &mut BufferPoolManager) -> Result<u64, Error> {
+        let buffer = bufmgr.fetch_page(self.first_page_id)?;
+        let size = read_u64(&buffer.page.borrow()[..], SIZE);
+        Ok(size)
+    }
+
+    // 末尾に data を書き足す。末尾のページが埋まったら新しいページをつなげる
+    pub fn append(&self, bufmgr: &mut BufferPoolManager, mut data: &[u8]) -> Result<(), Error> {
+        let first = bufmgr.fetch_page(self.first_page_id)?;
+        let (mut size, last_page_id) = {
+            let page = first.page.borrow();
+            (
+                read_u64(&page[..], SIZE),
+                PageId(read_u64(&page[..], LAST_PAGE_ID)),
+            )
+        };
+        let mut last = bufmgr.fetch_page(last_page_id)?;
+        // 末尾のページに入っているバイト数
+        let mut used = match size as usize % DATA_SIZE {
+            0 if size > 0 => DATA_SIZE,
+            used => used,
+        };
+        while !data.is_empty() {
+            if used == DATA_SIZE {
+                let new_page = bufmgr.create_page()?;
+                initialize_page(&new_page, PageId::INVALID_PAGE_ID);
+                write_u64(
+                    &mut last.page.borrow_mut()[..],
+                    NEXT_PAGE_ID,
+                    new_page.page_id.to_u64(),
+                );
+                last.is_dirty.set(true);
+                last = new_page;
+                used = 0;
+                continue;
+            }
+            let n = data.len().min(DATA_SIZE - used);
+            let start = HEADER_SIZE + used;
+            last.page.borrow_mut()[start..start + n].copy_from_slice(&data[..n]);
+            last.is_dirty.set(true);
+            data = &data[n..];
+            used += n;
+            size += n as u64;
+        }
+        let mut page = first.page.borrow_mut();
+        write_u64(&mut page[..], SIZE, size);
+        write_u64(&mut page[..], LAST_PAGE_ID, last.page_id.to_u64());
+        first.is_dirty.set(true);
+        Ok(())
+    }
+
+    // offset から buf に読み、読んだバイト数を返す。末尾を超えた分は読まない。
+    // cursor が読もうとするページより前を指していれば、そこからたどる
+    pub fn read_at(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        offset: u64,
+        buf: &mut [u8],
+        cursor: &mut Cursor,
+    ) -> Result<usize, Error> {
+        let size = self.size(bufmgr)?;
+        let end = size.min(offset.saturating_add(buf.len() as u64));
+        let mut pos = offset;
+        while pos < end {
+            let index = pos / DATA_SIZE as u64;
+            let page_id = self.page_at(bufmgr, index, cursor)?;
+            let buffer = bufmgr.fetch_page(page_id)?;
+            let page = buffer.page.borrow();
+            let start = (pos % DATA_SIZE as u64) as usize;
+            let n = (DATA_SIZE - start).min((end - pos) as usize);
+            let done = (pos - offset) as usize;
+            buf[done..done + n]
+                .copy_from_slice(&page[HEADER_SIZE + start..HEADER_SIZE + start + n]);
+            pos += n as u64;
+        }
+        Ok((pos - offset) as usize)
+    }
+
+    fn page_at(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        index: u64,
+        cursor: &mut Cursor,
+    ) -> Result<PageId, Error> {
+        if cursor.page_id.is_none() || cursor.index > index {
+            *cursor = Cursor {
+                index: 0,
+                page_id: Some(self.first_page_id),
+            };
+        }
+        let mut page_id = cursor.page_id.unwrap();
+        while cursor.index < index {
+            let buffer = bufmgr.fetch_page(page_id)?;
+            page_id = PageId(read_u64(&buffer.page.borrow()[..], NEXT_PAGE_ID));
+            cursor.index += 1;
+        }
+        cursor.page_id = Some(page_id);
+        Ok(page_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_bufmgr;
+
+    #[test]
+    fn test_blob() {
+        let mut bufmgr = temp_bufmgr(4);
+        let blob = Blob::create(&mut bufmgr).unwrap();
+        let data: Vec<u8> = (0..3 * DATA_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        for chunk in data.chunks(1000) {
+            blob.append(&mut bufmgr, chunk).unwrap();
+        }
+        assert_eq!(blob.size(&mut bufmgr).unwrap(), data.len() as u64);
+
+        let mut cursor = Cursor::default();
+        let mut out = vec![0; data.len() + 10];
+        let n = blob.read_at(&mut bufmgr, 0, &mut out, &mut cursor).unwrap();
+        assert_eq!(&out[..n], &data[..]);
+        let mut out = vec![0; 50];
+        let offset = 2 * DATA_SIZE as u64 - 25;
+        let n = blob
+            .read_at(&mut bufmgr, offset, &mut out, &mut cursor)
+            .unwrap();
+        assert_eq!(&out[..n], &data[offset as usize..offset as usize + 50]);
+        let n = blob
+            .read_at(&mut bufmgr, data.len() as u64 + 1, &mut out, &mut cursor)
+            .unwrap();
+        assert_eq!(n, 0);
+
+        let id = blob.first_page_id;
+        assert_eq!(Blob::open(&mut bufmgr, id).unwrap(), blob);
+        let second = PageId(id.to_u64() + 1);
+        assert!(matches!(
+            Blob::open(&mut bufmgr, second),
+            Err(Error::NotABlob(_))
+        ));
+        let other = bufmgr.create_page().unwrap().page_id;
+        assert!(matches!(
+            Blob::open(&mut bufmgr, other),
+            Err(Error::NotABlob(_))
+        ));
+        assert!(matches!(
+            Blob::open(&mut bufmgr, PageId(1000)),
+            Err(Error::NotABlob(1000))
+        ));
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// 入れた値なら必ず true を返し、入れていない値はたいてい false を返す集合
+//
+// 値のハッシュを 2 つに分け、h1 + i * h2 の位置のビットを hashes 個立てる。
+// 値 1 つあたり 10 ビットなら、入れていない値を入れたと答えるのはおよそ 1%
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+const BITS_PER_VALUE: usize = 10;
+const HASHES: u32 = 7;
+
+impl BloomFilter {
+    // values 個の値を入れる大きさで作る
+    pub fn new(values: usize) -> Self {
+        let words = (values * BITS_PER_VALUE).div_ceil(64).max(1);
+        Self {
+            bits: vec![0; words],
+            hashes: HASHES,
+        }
+    }
+
+    pub fn insert(&mut self, value: &impl Hash) {
+        let len = self.bits.len() * 64;
+        for bit in positions(value, self.hashes, len) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, value: &impl Hash) -> bool {
+        let len = self.bits.len() * 64;
+        positions(value, self.hashes, len).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+fn positions(value: &impl Hash, hashes: u32, len: usize) -> impl Iterator<Item = usize> {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    let hash = hasher.finish();
+    let (h1, h2) = (hash as u32 as u64, (hash >> 32) | 1);
+    (0..hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len as u64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(1000);
+        for i in 0..1000u64 {
+            filter.insert(&i);
+        }
+        assert!((0..1000u64).all(|i| filter.contains(&i)));
+        let false_positives = (1000..11000u64).filter(|i| filter.contains(i)).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+}
@@ -0,0 +1,575 @@
+use std::sync::Arc;
+
+use crate::datetime::{Interval, Time, Timestamp};
+use crate::decimal::{decompose, Decimal};
+use crate::enum_type::{self, EnumType};
+use crate::geometry::{Point, Rect};
+use crate::types::Value;
+use crate::uuid::Uuid;
+
+// バイト列の辞書順が Value::sort_cmp の順と一致するように値を並べる
+//
+// 各値は型のタグのあとに続く。数値は型によらず正確な値で並べ、等しい数値は同じキーになる。
+// 数値は符号などの種類の 1 バイトのあとに、100 進の指数 2 バイトと、100 進の各桁に 1 を足したバイトを上の桁から続け、0 で終える。
+// 先頭と末尾の桁は 0 にしない。負の数は絶対値のバイトをすべて反転する。
+// 日時はマイクロ秒の i64 の符号のビットを反転して 8 バイトで書く。DATE は 0 時の TIMESTAMP として書き、TIMESTAMP に戻す。
+// 文字列とバイト列と JSON は 0x00 を 0x00 0xff に置き換えて 0x00 0x00 で終える。UUID は 16 バイトをそのまま書く。
+// INTERVAL は 1 か月を 30 日としたマイクロ秒の i128 の符号のビットを反転して書き、30 日ごとに月にまとめて戻す。
+// POINT と BOX は座標の f64 を、正なら符号のビットを立て、負ならすべてのビットを反転して 8 バイトずつ書く。
+// 配列は要素ごとに 0x01 とその要素のキーを書き、0x00 で終える。列挙型は型の番号の 4 バイトのあとにラベルの順位を 4 バイトで書く。
+// どの値も途中で終わらないので、複数列のキーの前方一致がそのまま先頭の列の一致になる。
+// 数値はキーから型がわからないので、i64 に収まる整数は Integer、DECIMAL に収まるものは Decimal、それ以外は Real に戻す
+const TAG_BOOLEAN: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_TIMESTAMP: u8 = 3;
+const TAG_TIME: u8 = 4;
+const TAG_TEXT: u8 = 5;
+const TAG_BLOB: u8 = 6;
+const TAG_JSON: u8 = 7;
+const TAG_UUID: u8 = 8;
+const TAG_INTERVAL: u8 = 9;
+const TAG_POINT: u8 = 10;
+const TAG_BOX: u8 = 11;
+const TAG_ARRAY: u8 = 12;
+const TAG_ENUM: u8 = 13;
+const TAG_NULL: u8 = 14;
+// encode_ordered で NULL を先にするときのタグ
+const TAG_NULL_FIRST: u8 = 0;
+
+const NUMBER_NEG_INF: u8 = 1;
+const NUMBER_NEG: u8 = 2;
+const NUMBER_ZERO: u8 = 3;
+const NUMBER_POS: u8 = 4;
+const NUMBER_POS_INF: u8 = 5;
+const NUMBER_NAN: u8 = 6;
+
+pub fn encode(values: &[Value], bytes: &mut Vec<u8>) {
+    for value in values {
+        match value {
+            Value::Boolean(b) => {
+                bytes.push(TAG_BOOLEAN);
+                bytes.push(*b as u8);
+            }
+            Value::Integer(_) | Value::BigInt(_) | Value::Real(_) | Value::Decimal(_) => {
+                bytes.push(TAG_NUMBER);
+                encode_number(value, bytes);
+            }
+            // DATE は TIMESTAMP と同じキーにする
+            Value::Date(_) | Value::Timestamp(_) => {
+                bytes.push(TAG_TIMESTAMP);
+                let micros = value.timestamp().unwrap().micros();
+                bytes.extend_from_slice(&(micros ^ i64::MIN).to_be_bytes());
+            }
+            Value::Time(t) => {
+                bytes.push(TAG_TIME);
+                bytes.extend_from_slice(&t.micros().to_be_bytes());
+            }
+            Value::Text(s) => {
+                bytes.push(TAG_TEXT);
+                escape(s.as_bytes(), bytes);
+            }
+            Value::Blob(b) => {
+                bytes.push(TAG_BLOB);
+                escape(b, bytes);
+            }
+            Value::Json(s) => {
+                bytes.push(TAG_JSON);
+                escape(s.as_bytes(), bytes);
+            }
+            Value::Uuid(u) => {
+                bytes.push(TAG_UUID);
+                bytes.extend_from_slice(u.as_bytes());
+            }
+            Value::Interval(i) => {
+                bytes.push(TAG_INTERVAL);
+                bytes.extend_from_slice(&(i.total_micros() ^ i128::MIN).to_be_bytes());
+            }
+            Value::Point(p) => {
+                bytes.push(TAG_POINT);
+                encode_point(p, bytes);
+            }
+            Value::Box(b) => {
+                bytes.push(TAG_BOX);
+                encode_point(&b.low, bytes);
+                encode_point(&b.high, bytes);
+            }
+            Value::Array(items) => {
+                bytes.push(TAG_ARRAY);
+                for item in items.iter() {
+                    bytes.push(1);
+                    encode(std::slice::from_ref(item), bytes);
+                }
+                bytes.push(0);
+            }
+            Value::Enum(e) => {
+                bytes.push(TAG_ENUM);
+                bytes.extend_from_slice(&e.ty().id.to_be_bytes());
+                bytes.extend_from_slice(&e.sort().to_be_bytes());
+            }
+            Value::Null => bytes.push(TAG_NULL),
+        }
+    }
+}
+
+// 並べ替えのキーの 1 つの向き
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyOrder {
+    pub asc: bool,
+    pub nulls_first: bool,
+}
+
+// バイト列の辞書順が、値を orders の向きで前から比べた順と一致するように並べる。
+// 降順の値はバイトをすべて反転する。どの値も途中で終わらないので、反転すればその値どうしの順が逆になる。
+// NULL を先にするなら、反転したあとにほかのタグより前に来るタグにする。decode では戻せない
+pub fn encode_ordered(values: &[Value], orders: &[KeyOrder], bytes: &mut Vec<u8>) {
+    for (value, order) in values.iter().zip(orders) {
+        let start = bytes.len();
+        match value {
+            Value::Null if order.nulls_first == order.asc => bytes.push(TAG_NULL_FIRST),
+            _ => encode(std::slice::from_ref(value), bytes),
+        }
+        if !order.asc {
+            for b in &mut bytes[start..] {
+                *b = !*b;
+            }
+        }
+    }
+}
+
+fn encode_point(p: &Point, bytes: &mut Vec<u8>) {
+    encode_f64(p.x, bytes);
+    encode_f64(p.y, bytes);
+}
+
+fn encode_f64(c: f64, bytes: &mut Vec<u8>) {
+    // -0.0 と 0.0、NaN どうしを同じキーにする
+    let c = if c.is_nan() { f64::NAN } else { c + 0.0 };
+    let bits = c.to_bits();
+    let bits = if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | 1 << 63
+    };
+    bytes.extend_from_slice(&bits.to_be_bytes());
+}
+
+fn decode_point(bytes: &mut &[u8]) -> Option<Point> {
+    let x = decode_f64(bytes)?;
+    let y = decode_f64(bytes)?;
+    Some(Point::new(x, y))
+}
+
+fn decode_f64(bytes: &mut &[u8]) -> Option<f64> {
+    let (n, rest) = bytes.split_first_chunk::<8>()?;
+    *bytes = rest;
+    let bits = u64::from_be_bytes(*n);
+    Some(f64::from_bits(if bits >> 63 == 1 {
+        bits & !(1 << 63)
+    } else {
+        !bits
+    }))
+}
+
+fn encode_number(value: &Value, bytes: &mut Vec<u8>) {
+    let class = match value {
+        Value::Real(f) if f.is_nan() => NUMBER_NAN,
+        Value::Real(f) if *f == f64::INFINITY => NUMBER_POS_INF,
+        Value::Real(f) if *f == f64::NEG_INFINITY => NUMBER_NEG_INF,
+        _ => match digits(value) {
+            None => NUMBER_ZERO,
+            Some((negative, digits, exponent)) => {
+                bytes.push(if negative { NUMBER_NEG } else { NUMBER_POS });
+                let start = bytes.len();
+                // 10 進の指数が奇数なら先頭に 0 を足して 100 進にそろえる
+                let (digits, exponent) = if exponent % 2 != 0 {
+                    ([&[0][..], &digits].concat(), exponent + 1)
+                } else {
+                    (digits, exponent)
+                };
+                bytes.extend_from_slice(&(((exponent / 2) as i16 as u16) ^ 0x8000).to_be_bytes());
+                for pair in digits.chunks(2) {
+                    bytes.push(pair[0] * 10 + pair.get(1).copied().unwrap_or(0) + 1);
+                }
+                bytes.push(0);
+                if negative {
+                    for b in &mut bytes[start..] {
+                        *b = !*b;
+                    }
+                }
+                return;
+            }
+        },
+    };
+    bytes.push(class);
+}
+
+// 0 でない有限の数の符号、先頭と末尾が 0 でない 10 進の数字の並び、指数。値は 0.数字 * 10^指数。0 なら None
+fn digits(value: &Value) -> Option<(bool, Vec<u8>, i32)> {
+    let (negative, text, exponent) = match value {
+        Value::Integer(n) | Value::BigInt(n) => {
+            let text = n.unsigned_abs().to_string();
+            let exponent = text.len() as i32;
+            (*n < 0, text, exponent)
+        }
+        Value::Decimal(d) => {
+            let text = d.mantissa().unsigned_abs().to_string();
+            let exponent = text.len() as i32 - d.scale() as i32;
+            (d.mantissa() < 0, text, exponent)
+        }
+        // m * 2^e は、e が負なら m * 5^-e / 10^-e
+        Value::Real(f) => {
+            let (m, e) = decompose(f.abs());
+            let mut n = BigUint::from(m);
+            let text = if e >= 0 {
+                n.mul_pow(2, e as u32);
+                n.to_string()
+            } else {
+                n.mul_pow(5, (-e) as u32);
+                n.to_string()
+            };
+            let exponent = text.len() as i32 + e.min(0);
+            (*f < 0.0, text, exponent)
+        }
+        _ => unreachable!(),
+    };
+    let trimmed = text.trim_start_matches('0');
+    let exponent = exponent - (text.len() - trimmed.len()) as i32;
+    let trimmed = trimmed.trim_end_matches('0');
+    if trimmed.is_empty() {
+        return None;
+    }
+    Some((
+        negative,
+        trimmed.bytes().map(|b| b - b'0').collect(),
+        exponent,
+    ))
+}
+
+fn decode_number(rest: &mut &[u8]) -> Option<Value> {
+    let (&class, tail) = rest.split_first()?;
+    *rest = tail;
+    let negative = match class {
+        NUMBER_NEG_INF => return Some(Value::Real(f64::NEG_INFINITY)),
+        NUMBER_ZERO => return Some(Value::Integer(0)),
+        NUMBER_POS_INF => return Some(Value::Real(f64::INFINITY)),
+        NUMBER_NAN => return Some(Value::Real(f64::NAN)),
+        NUMBER_NEG => true,
+        NUMBER_POS => false,
+        _ => return None,
+    };
+    let flip = |b: u8| if negative { !b } else { b };
+    let (exponent, tail) = rest.split_first_chunk::<2>()?;
+    *rest = tail;
+    let exponent = (u16::from_be_bytes([flip(exponent[0]), flip(exponent[1])]) ^ 0x8000) as i16;
+    let mut text = String::new();
+    loop {
+        let (&b, tail) = rest.split_first()?;
+        *rest = tail;
+        match flip(b) {
+            0 => break,
+            c @ 1..=100 => text.push_str(&format!("{:02}", c - 1)),
+            _ => return None,
+        }
+    }
+    let exponent = exponent as i32 * 2 - (text.len() - text.trim_start_matches('0').len()) as i32;
+    let text = text.trim_matches('0');
+    let sign = if negative { "-" } else { "" };
+    let scale = text.len() as i32 - exponent;
+    if scale <= 0 {
+        if let Ok(n) = format!("{}{}{}", sign, text, "0".repeat(-scale as usize)).parse() {
+            return Some(Value::Integer(n));
+        }
+    } else if let Some(d) = format!("{}{}", sign, text)
+        .parse::<i128>()
+        .ok()
+        .and_then(|m| Decimal::new(m, u8::try_from(scale).ok()?))
+    {
+        return Some(Value::Decimal(d));
+    }
+    format!("{}0.{}e{}", sign, text, exponent)
+        .parse()
+        .ok()
+        .map(Value::Real)
+}
+
+// 10 進の数字に直すための多倍長の自然数。10^9 ごとに下の桁から並べる
+struct BigUint(Vec<u32>);
+
+const BIG_BASE: u64 = 1_000_000_000;
+
+impl BigUint {
+    fn from(n: u64) -> BigUint {
+        BigUint(vec![
+            (n % BIG_BASE) as u32,
+            (n / BIG_BASE % BIG_BASE) as u32,
+            (n / BIG_BASE / BIG_BASE) as u32,
+        ])
+    }
+
+    // base^exp を掛ける。base は 2 か 5
+    fn mul_pow(&mut self, base: u64, mut exp: u32) {
+        // 一度に掛ける数が 2^31 を超えないようにする
+        let step = if base == 2 { 31 } else { 13 };
+        while exp > 0 {
+            let k = exp.min(step);
+            exp -= k;
+            let factor = base.pow(k);
+            let mut carry = 0;
+            for limb in &mut self.0 {
+                let n = *limb as u64 * factor + carry;
+                *limb = (n % BIG_BASE) as u32;
+                carry = n / BIG_BASE;
+            }
+            while carry > 0 {
+                self.0.push((carry % BIG_BASE) as u32);
+                carry /= BIG_BASE;
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for BigUint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut limbs = self.0.iter().rev().skip_while(|&&limb| limb == 0);
+        match limbs.next() {
+            None => return f.write_str("0"),
+            Some(first) => write!(f, "{}", first)?,
+        }
+        limbs.try_for_each(|limb| write!(f, "{:09}", limb))
+    }
+}
+
+fn escape(data: &[u8], bytes: &mut Vec<u8>) {
+    for &b in data {
+        bytes.push(b);
+        if b == 0 {
+            bytes.push(0xff);
+        }
+    }
+    bytes.extend_from_slice(&[0, 0]);
+}
+
+fn unescape(rest: &mut &[u8]) -> Option<Vec<u8>> {
+    let mut data = vec![];
+    loop {
+        match *rest {
+            [0, 0, tail @ ..] => {
+                *rest = tail;
+                return Some(data);
+            }
+            [0, 0xff, tail @ ..] => {
+                data.push(0);
+                *rest = tail;
+            }
+            [b, tail @ ..] => {
+                data.push(*b);
+                *rest = tail;
+            }
+            [] => return None,
+        }
+    }
+}
+
+// 先頭から n 個の値を取り出す。壊れていれば None。列挙型の値は enums の中の型で読む
+pub fn decode(bytes: &[u8], n: usize, enums: &[Arc<EnumType>]) -> Option<Vec<Value>> {
+    let mut rest = bytes;
+    (0..n).map(|_| decode_value(&mut rest, enums)).collect()
+}
+
+fn decode_value(rest: &mut &[u8], enums: &[Arc<EnumType>]) -> Option<Value> {
+    let (&tag, tail) = rest.split_first()?;
+    *rest = tail;
+    Some(match tag {
+        TAG_BOOLEAN => {
+            let (&b, tail) = rest.split_first()?;
+            *rest = tail;
+            Value::Boolean(b != 0)
+        }
+        TAG_NUMBER => decode_number(rest)?,
+        TAG_TIMESTAMP | TAG_TIME => {
+            let (n, tail) = rest.split_first_chunk::<8>()?;
+            *rest = tail;
+            let n = i64::from_be_bytes(*n);
+            if tag == TAG_TIME {
+                Value::Time(Time::from_micros(n)?)
+            } else {
+                Value::Timestamp(Timestamp::from_micros(n ^ i64::MIN)?)
+            }
+        }
+        TAG_TEXT => Value::Text(String::from_utf8(unescape(rest)?).ok()?),
+        TAG_BLOB => Value::Blob(unescape(rest)?.into()),
+        TAG_JSON => Value::Json(String::from_utf8(unescape(rest)?).ok()?.into()),
+        TAG_UUID => {
+            let (u, tail) = rest.split_first_chunk::<16>()?;
+            *rest = tail;
+            Value::Uuid(Uuid::from_bytes(*u))
+        }
+        TAG_INTERVAL => {
+            let (n, tail) = rest.split_first_chunk::<16>()?;
+            *rest = tail;
+            Value::Interval(Interval::from_total_micros(
+                i128::from_be_bytes(*n) ^ i128::MIN,
+            )?)
+        }
+        TAG_POINT => Value::Point(decode_point(rest)?),
+        TAG_BOX => {
+            let low = decode_point(rest)?;
+            let high = decode_point(rest)?;
+            Value::Box(Box::new(Rect { low, high }))
+        }
+        TAG_NULL => Value::Null,
+        TAG_ARRAY => {
+            let mut items = vec![];
+            loop {
+                let (&more, tail) = rest.split_first()?;
+                *rest = tail;
+                match more {
+                    0 => break,
+                    1 => items.push(decode_value(rest, enums)?),
+                    _ => return None,
+                }
+            }
+            Value::Array(items.into())
+        }
+        TAG_ENUM => {
+            let (id, tail) = rest.split_first_chunk::<4>()?;
+            let (sort, tail) = tail.split_first_chunk::<4>()?;
+            *rest = tail;
+            let ty = enum_type::find(enums, u32::from_be_bytes(*id))?;
+            Value::Enum(ty.from_sort(u32::from_be_bytes(*sort))?)
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let values = [
+            vec![Value::Boolean(false)],
+            vec![Value::Boolean(true)],
+            vec![Value::Real(f64::NEG_INFINITY)],
+            vec![Value::Integer(i64::MIN)],
+            vec![Value::Integer(-1)],
+            vec![Value::Real(-0.5)],
+            vec![Value::Decimal("-0.000000000000000001".parse().unwrap())],
+            vec![Value::Integer(0)],
+            vec![Value::Decimal("0.1".parse().unwrap())],
+            vec![Value::Real(0.1)],
+            vec![Value::Real(0.5)],
+            vec![Value::Decimal("123.45".parse().unwrap())],
+            vec![Value::Integer(256)],
+            vec![Value::Integer(i64::MAX - 1)],
+            vec![Value::Integer(i64::MAX)],
+            vec![Value::Real(1e300)],
+            vec![Value::Real(f64::NAN)],
+            vec![Value::Timestamp("1969-12-31 23:59:59".parse().unwrap())],
+            vec![Value::Date("1970-01-01".parse().unwrap())],
+            vec![Value::Timestamp("2024-01-15 13:45".parse().unwrap())],
+            vec![Value::Time("00:00:01".parse().unwrap())],
+            vec![Value::Text("".to_string())],
+            vec![Value::Text("a".to_string()), Value::Integer(1)],
+            vec![Value::Text("a\0".to_string())],
+            vec![Value::Text("ab".to_string())],
+            vec![Value::Blob(vec![0, 1].into())],
+            vec![Value::Json("[1]".into())],
+            vec![Value::Uuid(Uuid::from_bytes([0; 16]))],
+            vec![Value::Uuid(Uuid::from_bytes([
+                0x01, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ]))],
+            vec![Value::Uuid(Uuid::from_bytes([0x02; 16]))],
+            vec![Value::Interval("-1 day".parse().unwrap())],
+            vec![Value::Interval("1 day".parse().unwrap())],
+            vec![Value::Interval("1 mon 1 s".parse().unwrap())],
+            vec![Value::Point(Point::new(-1.0, 5.0))],
+            vec![Value::Point(Point::new(0.0, -2.0))],
+            vec![Value::Point(Point::new(0.0, 0.5))],
+            vec![Value::Box(Box::new("(0,0),(1,1)".parse().unwrap()))],
+            vec![Value::Box(Box::new("(0,0),(2,1)".parse().unwrap()))],
+            vec![Value::Array(vec![].into())],
+            vec![Value::Array(vec![Value::Integer(1)].into())],
+            vec![Value::Array(vec![Value::Integer(1), Value::Null].into())],
+            vec![Value::Array(vec![Value::Integer(2)].into())],
+            vec![Value::Null],
+        ];
+        let keys = values
+            .iter()
+            .map(|v| {
+                let mut bytes = vec![];
+                encode(v, &mut bytes);
+                bytes
+            })
+            .collect::<Vec<_>>();
+        for (pair, v) in keys.windows(2).zip(values.windows(2)) {
+            assert!(pair[0] < pair[1], "{:?}", v);
+            assert!(v[0][0].sort_cmp(&v[1][0]).is_lt());
+        }
+        for (v, key) in values.iter().zip(&keys) {
+            assert_eq!(decode(key, v.len(), &[]).as_ref(), Some(v));
+        }
+        assert_eq!(
+            decode(&keys[22], 1, &[]),
+            Some(vec![Value::Text("a".to_string())])
+        );
+        assert_eq!(decode(&keys[22][..3], 1, &[]), None);
+
+        // 等しい数値は型が違っても同じキーになる
+        let key = |value: Value| {
+            let mut bytes = vec![];
+            encode(&[value], &mut bytes);
+            bytes
+        };
+        assert_eq!(key(Value::BigInt(3)), key(Value::Real(3.0)));
+        assert_eq!(key(Value::Real(-0.0)), key(Value::Integer(0)));
+        assert_eq!(
+            key("3.00".parse().map(Value::Decimal).unwrap()),
+            key(Value::Integer(3))
+        );
+        assert_eq!(
+            key("-2.50".parse().map(Value::Decimal).unwrap()),
+            key(Value::Real(-2.5))
+        );
+        assert_eq!(
+            key(Value::Date("2024-01-15".parse().unwrap())),
+            key(Value::Timestamp("2024-01-15 00:00".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_encode_ordered() {
+        let orders = [
+            KeyOrder {
+                asc: false,
+                nulls_first: false,
+            },
+            KeyOrder {
+                asc: true,
+                nulls_first: true,
+            },
+        ];
+        let text = |s: &str| Value::Text(s.to_string());
+        let rows = [
+            vec![Value::Integer(2), Value::Null],
+            vec![Value::Integer(2), text("")],
+            vec![Value::Integer(2), text("a")],
+            vec![Value::Real(1.5), Value::Null],
+            vec![Value::Integer(1), text("a\0")],
+            vec![Value::Integer(1), text("ab")],
+            vec![Value::Null, text("a")],
+        ];
+        let keys = rows
+            .iter()
+            .map(|row| {
+                let mut bytes = vec![];
+                encode_ordered(row, &orders, &mut bytes);
+                bytes
+            })
+            .collect::<Vec<_>>();
+        for (pair, rows) in keys.windows(2).zip(rows.windows(2)) {
+            assert!(pair[0] < pair[1], "{:?}", rows);
+        }
+    }
+}
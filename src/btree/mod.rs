@@ -0,0 +1,631 @@
+pub mod key;
+mod node;
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::buffer::{self, Buffer, BufferPoolManager};
+use crate::disk::{PageId, PAGE_SIZE};
+
+use node::{encode_entry, entry_key, entry_value, max_entry_size, Node};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Buffer(#[from] buffer::Error),
+    #[error("duplicate key")]
+    DuplicateKey,
+    #[error("index entry of {0} bytes exceeds the maximum size")]
+    EntryTooLarge(usize),
+}
+
+// メタページには根のページ ID だけを置く
+fn read_root(meta: &Buffer) -> PageId {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&meta.page.borrow()[..8]);
+    PageId(u64::from_be_bytes(bytes))
+}
+
+fn write_root(meta: &Buffer, root: PageId) {
+    meta.page.borrow_mut()[..8].copy_from_slice(&root.to_u64().to_be_bytes());
+    meta.is_dirty.set(true);
+}
+
+// (キー, 値)
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+// ノードが分割されたとき親に伝える (区切りのキー, 右側の新しいノード)
+type Split = (Vec<u8>, PageId);
+
+// キーの重複を許さない B+Tree
+//
+// 削除してもノードの併合はしない。空になった葉も走査では読み飛ばすだけにしている。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BTree {
+    pub meta_page_id: PageId,
+}
+
+impl BTree {
+    pub fn create(bufmgr: &mut BufferPoolManager) -> Result<Self, Error> {
+        let meta = bufmgr.create_page()?;
+        let root = bufmgr.create_page()?;
+        Node::new(&mut root.page.borrow_mut()[..]).initialize_leaf(None, None);
+        root.is_dirty.set(true);
+        write_root(&meta, root.page_id);
+        Ok(Self {
+            meta_page_id: meta.page_id,
+        })
+    }
+
+    fn root(&self, bufmgr: &mut BufferPoolManager) -> Result<PageId, Error> {
+        let meta = bufmgr.fetch_page(self.meta_page_id)?;
+        Ok(read_root(&meta))
+    }
+
+    // key を含みうる葉までたどる
+    fn find_leaf(&self, bufmgr: &mut BufferPoolManager, key: &[u8]) -> Result<Rc<Buffer>, Error> {
+        let root = self.root(bufmgr)?;
+        let mut buffer = bufmgr.fetch_page(root)?;
+        loop {
+            let child = {
+                let page = buffer.page.borrow();
+                let node = Node::new(&page[..]);
+                if node.is_leaf() {
+                    break;
+                }
+                node.child_at(node.child_index(key))
+            };
+            buffer = bufmgr.fetch_page(child)?;
+        }
+        Ok(buffer)
+    }
+
+    pub fn get(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let leaf = self.find_leaf(bufmgr, key)?;
+        let page = leaf.page.borrow();
+        let node = Node::new(&page[..]);
+        Ok(node.search(key).ok().map(|i| node.entry(i).1.to_vec()))
+    }
+
+    pub fn insert(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error> {
+        let entry = encode_entry(key, value);
+        if entry.len() > max_entry_size(PAGE_SIZE) {
+            return Err(Error::EntryTooLarge(entry.len()));
+        }
+        let root = self.root(bufmgr)?;
+        let Some((separator, right)) = insert_into(bufmgr, root, key, &entry)? else {
+            return Ok(());
+        };
+        // 根が分割されたので 1 段高くする
+        let new_root = bufmgr.create_page()?;
+        {
+            let mut page = new_root.page.borrow_mut();
+            let mut node = Node::new(&mut page[..]);
+            node.initialize_branch(right);
+            let inserted = node.insert(0, &encode_entry(&separator, &root.to_u64().to_be_bytes()));
+            assert!(inserted);
+        }
+        new_root.is_dirty.set(true);
+        let meta = bufmgr.fetch_page(self.meta_page_id)?;
+        write_root(&meta, new_root.page_id);
+        Ok(())
+    }
+
+    pub fn delete(&self, bufmgr: &mut BufferPoolManager, key: &[u8]) -> Result<bool, Error> {
+        let leaf = self.find_leaf(bufmgr, key)?;
+        let mut page = leaf.page.borrow_mut();
+        let mut node = Node::new(&mut page[..]);
+        let Ok(i) = node.search(key) else {
+            return Ok(false);
+        };
+        node.remove(i);
+        leaf.is_dirty.set(true);
+        Ok(true)
+    }
+
+    // 根からすべてのノードをたどって確かめ、見つけた誤りをページごとに返す。
+    // キーがノードの中で昇順か、親の区切りの範囲に入っているか、葉がすべて同じ深さにあるか、
+    // 葉のリストが木の順につながっているかを見る
+    pub fn verify(&self, bufmgr: &mut BufferPoolManager) -> Result<Vec<(PageId, String)>, Error> {
+        let mut verifier = Verifier::default();
+        let root = self.root(bufmgr)?;
+        verifier.visit(bufmgr, root, 0, None, None)?;
+        let Verifier {
+            mut problems,
+            leaves,
+            ..
+        } = verifier;
+        let show =
+            |page_id: Option<PageId>| page_id.map_or("none".to_string(), |id| id.0.to_string());
+        for (i, &leaf) in leaves.iter().enumerate() {
+            let buffer = bufmgr.fetch_page(leaf)?;
+            let page = buffer.page.borrow();
+            let node = Node::new(&page[..]);
+            let prev = i.checked_sub(1).map(|i| leaves[i]);
+            let next = leaves.get(i + 1).copied();
+            if node.prev() != prev {
+                problems.push((
+                    leaf,
+                    format!(
+                        "previous leaf is {} instead of {}",
+                        show(node.prev()),
+                        show(prev)
+                    ),
+                ));
+            }
+            if node.next() != next {
+                problems.push((
+                    leaf,
+                    format!(
+                        "next leaf is {} instead of {}",
+                        show(node.next()),
+                        show(next)
+                    ),
+                ));
+            }
+        }
+        Ok(problems)
+    }
+
+    // key 以上の最初のエントリから昇順に走査する。None なら先頭から
+    pub fn scan(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        key: Option<&[u8]>,
+    ) -> Result<BTreeScan, Error> {
+        let leaf = self.find_leaf(bufmgr, key.unwrap_or(&[]))?;
+        let page = leaf.page.borrow();
+        let node = Node::new(&page[..]);
+        let slot = key.map_or(0, |key| match node.search(key) {
+            Ok(i) | Err(i) => i,
+        });
+        Ok(BTreeScan {
+            page_id: Some(leaf.page_id),
+            slot,
+        })
+    }
+}
+
+fn insert_into(
+    bufmgr: &mut BufferPoolManager,
+    page_id: PageId,
+    key: &[u8],
+    entry: &[u8],
+) -> Result<Option<Split>, Error> {
+    let buffer = bufmgr.fetch_page(page_id)?;
+    let (is_leaf, index, child) = {
+        let page = buffer.page.borrow();
+        let node = Node::new(&page[..]);
+        if node.is_leaf() {
+            let index = match node.search(key) {
+                Ok(_) => return Err(Error::DuplicateKey),
+                Err(i) => i,
+            };
+            (true, index, None)
+        } else {
+            let index = node.child_index(key);
+            (false, index, Some(node.child_at(index)))
+        }
+    };
+    if is_leaf {
+        return insert_entry(bufmgr, &buffer, index, entry);
+    }
+    let Some((separator, right)) = insert_into(bufmgr, child.unwrap(), key, entry)? else {
+        return Ok(None);
+    };
+    // 子が (separator より小さい側, right) に分かれたので、
+    // 元の子の位置を right に差し替えて、その前に (separator, 元の子) を入れる
+    {
+        let mut page = buffer.page.borrow_mut();
+        let mut node = Node::new(&mut page[..]);
+        if index == node.len() {
+            node.set_right_child(right);
+        } else {
+            node.set_child(index, right);
+        }
+    }
+    buffer.is_dirty.set(true);
+    let entry = encode_entry(&separator, &child.unwrap().to_u64().to_be_bytes());
+    insert_entry(bufmgr, &buffer, index, &entry)
+}
+
+// ノードの index の位置にエントリを入れる。収まらなければ半分に分ける
+fn insert_entry(
+    bufmgr: &mut BufferPoolManager,
+    buffer: &Rc<Buffer>,
+    index: usize,
+    entry: &[u8],
+) -> Result<Option<Split>, Error> {
+    let (is_leaf, mut entries, next) = {
+        let mut page = buffer.page.borrow_mut();
+        let mut node = Node::new(&mut page[..]);
+        if node.insert(index, entry) {
+            buffer.is_dirty.set(true);
+            return Ok(None);
+        }
+        (node.is_leaf(), node.entries(), node.next())
+    };
+    entries.insert(index, entry.to_vec());
+    // バイト数でおおよそ半分になる位置で分ける
+    let total: usize = entries.iter().map(|e| e.len()).sum();
+    let mut size = 0;
+    let mid = entries
+        .iter()
+        .position(|e| {
+            size += e.len();
+            size > total / 2
+        })
+        .unwrap()
+        .clamp(1, entries.len() - 1);
+    let mut right_entries = entries.split_off(mid);
+    let new_buffer = bufmgr.create_page()?;
+    let separator;
+    {
+        let mut page = buffer.page.borrow_mut();
+        let mut left = Node::new(&mut page[..]);
+        let mut new_page = new_buffer.page.borrow_mut();
+        let mut right = Node::new(&mut new_page[..]);
+        if is_leaf {
+            separator = entry_key(&right_entries[0]).to_vec();
+            let prev = left.prev();
+            left.initialize_leaf(prev, Some(new_buffer.page_id));
+            right.initialize_leaf(Some(buffer.page_id), next);
+        } else {
+            // 内部ノードでは右側の先頭のキーを親に上げ、その子を左側の最も右の子にする
+            let first = right_entries.remove(0);
+            separator = entry_key(&first).to_vec();
+            let mut child = [0; 8];
+            child.copy_from_slice(entry_value(&first));
+            let old_right_child = left.right_child();
+            left.initialize_branch(PageId(u64::from_be_bytes(child)));
+            right.initialize_branch(old_right_child);
+        }
+        for (i, e) in entries.iter().enumerate() {
+            assert!(left.insert(i, e));
+        }
+        for (i, e) in right_entries.iter().enumerate() {
+            assert!(right.insert(i, e));
+        }
+    }
+    buffer.is_dirty.set(true);
+    new_buffer.is_dirty.set(true);
+    if is_leaf {
+        if let Some(next) = next {
+            let next = bufmgr.fetch_page(next)?;
+            Node::new(&mut next.page.borrow_mut()[..]).set_prev(Some(new_buffer.page_id));
+            next.is_dirty.set(true);
+        }
+    }
+    Ok(Some((separator, new_buffer.page_id)))
+}
+
+// 昇順に並んだエントリから木を作る
+//
+// 葉を左から順に埋め、収まらなくなったら新しい葉を右に足して区切りのキーを親に入れる。
+// 各段では最も右のノードだけに書き足すので、親も同じように右へ伸びていく。
+pub struct BulkLoader {
+    meta_page_id: PageId,
+    // 各段の最も右のノード。0 番目が葉
+    levels: Vec<PageId>,
+    last_key: Option<Vec<u8>>,
+}
+
+impl BulkLoader {
+    pub fn new(bufmgr: &mut BufferPoolManager) -> Result<Self, Error> {
+        let meta = bufmgr.create_page()?;
+        let leaf = bufmgr.create_page()?;
+        Node::new(&mut leaf.page.borrow_mut()[..]).initialize_leaf(None, None);
+        leaf.is_dirty.set(true);
+        Ok(Self {
+            meta_page_id: meta.page_id,
+            levels: vec![leaf.page_id],
+            last_key: None,
+        })
+    }
+
+    pub fn push(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error> {
+        if let Some(last_key) = &self.last_key {
+            assert!(
+                last_key.as_slice() <= key,
+                "keys must be in ascending order"
+            );
+            if last_key == key {
+                return Err(Error::DuplicateKey);
+            }
+        }
+        let entry = encode_entry(key, value);
+        if entry.len() > max_entry_size(PAGE_SIZE) {
+            return Err(Error::EntryTooLarge(entry.len()));
+        }
+        self.last_key = Some(key.to_vec());
+        let leaf = bufmgr.fetch_page(self.levels[0])?;
+        {
+            let mut page = leaf.page.borrow_mut();
+            let mut node = Node::new(&mut page[..]);
+            if node.insert(node.len(), &entry) {
+                leaf.is_dirty.set(true);
+                return Ok(());
+            }
+        }
+        let new_leaf = bufmgr.create_page()?;
+        {
+            let mut page = new_leaf.page.borrow_mut();
+            let mut node = Node::new(&mut page[..]);
+            node.initialize_leaf(Some(leaf.page_id), None);
+            assert!(node.insert(0, &entry));
+        }
+        new_leaf.is_dirty.set(true);
+        Node::new(&mut leaf.page.borrow_mut()[..]).set_next(Some(new_leaf.page_id));
+        leaf.is_dirty.set(true);
+        self.levels[0] = new_leaf.page_id;
+        self.add_separator(bufmgr, 1, key, leaf.page_id, new_leaf.page_id)
+    }
+
+    // level 段目の最も右のノードの右端の子 left を (key, left) にして、right を右端の子にする
+    fn add_separator(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        level: usize,
+        key: &[u8],
+        left: PageId,
+        right: PageId,
+    ) -> Result<(), Error> {
+        let entry = encode_entry(key, &left.to_u64().to_be_bytes());
+        if level == self.levels.len() {
+            let buffer = bufmgr.create_page()?;
+            {
+                let mut page = buffer.page.borrow_mut();
+                let mut node = Node::new(&mut page[..]);
+                node.initialize_branch(right);
+                assert!(node.insert(0, &entry));
+            }
+            buffer.is_dirty.set(true);
+            self.levels.push(buffer.page_id);
+            return Ok(());
+        }
+        let buffer = bufmgr.fetch_page(self.levels[level])?;
+        {
+            let mut page = buffer.page.borrow_mut();
+            let mut node = Node::new(&mut page[..]);
+            if node.insert(node.len(), &entry) {
+                node.set_right_child(right);
+                buffer.is_dirty.set(true);
+                return Ok(());
+            }
+        }
+        // 収まらなければ、right だけを子に持つノードを右に作って key を上の段に送る
+        let new_buffer = bufmgr.create_page()?;
+        Node::new(&mut new_buffer.page.borrow_mut()[..]).initialize_branch(right);
+        new_buffer.is_dirty.set(true);
+        self.levels[level] = new_buffer.page_id;
+        self.add_separator(bufmgr, level + 1, key, buffer.page_id, new_buffer.page_id)
+    }
+
+    pub fn finish(self, bufmgr: &mut BufferPoolManager) -> Result<BTree, Error> {
+        let meta = bufmgr.fetch_page(self.meta_page_id)?;
+        write_root(&meta, *self.levels.last().unwrap());
+        Ok(BTree {
+            meta_page_id: self.meta_page_id,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Verifier {
+    problems: Vec<(PageId, String)>,
+    visited: HashSet<PageId>,
+    // 木の順に並べた葉
+    leaves: Vec<PageId>,
+    leaf_depth: Option<usize>,
+}
+
+impl Verifier {
+    // page_id のノードのキーが lower 以上 upper 未満にあるか確かめて、子をたどる
+    fn visit(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        page_id: PageId,
+        depth: usize,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        if page_id.to_u64() >= bufmgr.page_count() {
+            self.problems
+                .push((page_id, "node is past the end of the file".to_string()));
+            return Ok(());
+        }
+        if !self.visited.insert(page_id) {
+            self.problems
+                .push((page_id, "node is reached more than once".to_string()));
+            return Ok(());
+        }
+        let buffer = bufmgr.fetch_page(page_id)?;
+        let (keys, children) = {
+            let page = buffer.page.borrow();
+            let node = Node::new(&page[..]);
+            if let Err(message) = node.verify() {
+                self.problems.push((page_id, message));
+                return Ok(());
+            }
+            let keys: Vec<Vec<u8>> = (0..node.len()).map(|i| node.key(i).to_vec()).collect();
+            let children: Option<Vec<PageId>> =
+                (!node.is_leaf()).then(|| (0..=node.len()).map(|i| node.child_at(i)).collect());
+            (keys, children)
+        };
+        drop(buffer);
+        if let Some(i) = (1..keys.len()).find(|&i| keys[i - 1] >= keys[i]) {
+            self.problems.push((
+                page_id,
+                format!("key {} is not greater than key {}", i, i - 1),
+            ));
+        }
+        let out_of_range = keys.iter().position(|key| {
+            lower.is_some_and(|lower| key.as_slice() < lower)
+                || upper.is_some_and(|upper| key.as_slice() >= upper)
+        });
+        if let Some(i) = out_of_range {
+            self.problems.push((
+                page_id,
+                format!("key {} is outside the range of its parent", i),
+            ));
+        }
+        let Some(children) = children else {
+            match self.leaf_depth {
+                Some(leaf_depth) if leaf_depth != depth => self.problems.push((
+                    page_id,
+                    format!(
+                        "leaf is at depth {} but others are at depth {}",
+                        depth, leaf_depth
+                    ),
+                )),
+                _ => self.leaf_depth = Some(depth),
+            }
+            self.leaves.push(page_id);
+            return Ok(());
+        };
+        // i 番目の子には i - 1 番目のキー以上、i 番目のキー未満のキーが入る
+        for (i, &child) in children.iter().enumerate() {
+            let lower = if i == 0 {
+                lower
+            } else {
+                Some(keys[i - 1].as_slice())
+            };
+            let upper = keys.get(i).map_or(upper, |key| Some(key.as_slice()));
+            self.visit(bufmgr, child, depth + 1, lower, upper)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct BTreeScan {
+    page_id: Option<PageId>,
+    slot: usize,
+}
+
+impl BTreeScan {
+    pub fn next(&mut self, bufmgr: &mut BufferPoolManager) -> Result<Option<Entry>, Error> {
+        while let Some(page_id) = self.page_id {
+            let buffer = bufmgr.fetch_page(page_id)?;
+            let page = buffer.page.borrow();
+            let node = Node::new(&page[..]);
+            if self.slot < node.len() {
+                let (key, value) = node.entry(self.slot);
+                self.slot += 1;
+                return Ok(Some((key.to_vec(), value.to_vec())));
+            }
+            self.page_id = node.next();
+            self.slot = 0;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_bufmgr;
+
+    fn collect(tree: &BTree, bufmgr: &mut BufferPoolManager, key: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let mut scan = tree.scan(bufmgr, key).unwrap();
+        let mut keys = vec![];
+        while let Some((key, _)) = scan.next(bufmgr).unwrap() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn test_insert_get_delete() {
+        let mut bufmgr = temp_bufmgr(16);
+        let tree = BTree::create(&mut bufmgr).unwrap();
+        // 分割が何段か起きるだけの量を順不同で入れる
+        let n = 3000u32;
+        for i in 0..n {
+            let k = (i * 7919) % n;
+            let key = format!("key{:06}", k).into_bytes();
+            tree.insert(&mut bufmgr, &key, &k.to_be_bytes()).unwrap();
+        }
+        assert_eq!(
+            tree.get(&mut bufmgr, b"key001234").unwrap(),
+            Some(1234u32.to_be_bytes().to_vec())
+        );
+        assert_eq!(tree.get(&mut bufmgr, b"nokey").unwrap(), None);
+        assert!(matches!(
+            tree.insert(&mut bufmgr, b"key000001", b""),
+            Err(Error::DuplicateKey)
+        ));
+
+        let keys = collect(&tree, &mut bufmgr, None);
+        assert_eq!(keys.len(), n as usize);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(tree.verify(&mut bufmgr).unwrap(), []);
+        let keys = collect(&tree, &mut bufmgr, Some(b"key002990x"));
+        assert_eq!(keys.len(), 9);
+        assert_eq!(keys[0], b"key002991");
+
+        for i in 0..n / 2 {
+            assert!(tree
+                .delete(&mut bufmgr, format!("key{:06}", i).as_bytes())
+                .unwrap());
+        }
+        assert!(!tree.delete(&mut bufmgr, b"key000000").unwrap());
+        let keys = collect(&tree, &mut bufmgr, None);
+        assert_eq!(keys.len(), (n / 2) as usize);
+        assert_eq!(keys[0], format!("key{:06}", n / 2).into_bytes());
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        let mut bufmgr = temp_bufmgr(16);
+        let mut loader = BulkLoader::new(&mut bufmgr).unwrap();
+        let n = 3000u32;
+        for k in (0..n).map(|i| i * 2) {
+            let key = format!("key{:06}", k).into_bytes();
+            loader.push(&mut bufmgr, &key, &k.to_be_bytes()).unwrap();
+        }
+        assert!(matches!(
+            loader.push(
+                &mut bufmgr,
+                format!("key{:06}", (n - 1) * 2).as_bytes(),
+                b""
+            ),
+            Err(Error::DuplicateKey)
+        ));
+        let tree = loader.finish(&mut bufmgr).unwrap();
+        assert_eq!(
+            tree.get(&mut bufmgr, b"key001234").unwrap(),
+            Some(1234u32.to_be_bytes().to_vec())
+        );
+        assert_eq!(tree.get(&mut bufmgr, b"key001235").unwrap(), None);
+        // 作ったあとも普通に挿入できる
+        tree.insert(&mut bufmgr, b"key001235", b"").unwrap();
+        let keys = collect(&tree, &mut bufmgr, None);
+        assert_eq!(keys.len(), n as usize + 1);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(tree.verify(&mut bufmgr).unwrap(), []);
+    }
+
+    #[test]
+    fn test_entry_too_large() {
+        let mut bufmgr = temp_bufmgr(4);
+        let tree = BTree::create(&mut bufmgr).unwrap();
+        assert!(matches!(
+            tree.insert(&mut bufmgr, &[0; PAGE_SIZE / 2], b""),
+            Err(Error::EntryTooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::disk::PageId;
+use crate::slotted::Slotted;
+
+// B+Tree のノード
+//
+// | node_type (1) | 予約 (7) | link1 (8) | link2 (8) | スロット付きページ |
+//
+// 葉では link1 が前の葉、link2 が次の葉。
+// 内部ノードでは link1 が最も右の子で、link2 は使わない。
+//
+// 各スロットは | key_len (2) | key | value | で、キーの昇順に並べる。
+// 内部ノードの value は子のページ ID で、その子にはキーより小さいキーが入る。
+const NODE_TYPE: usize = 0;
+const LINK1: usize = 8;
+const LINK2: usize = 16;
+const HEADER_SIZE: usize = 24;
+
+const LEAF: u8 = 1;
+const BRANCH: u8 = 2;
+
+pub fn encode_entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(2 + key.len() + value.len());
+    entry.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    entry.extend_from_slice(key);
+    entry.extend_from_slice(value);
+    entry
+}
+
+fn split_entry(entry: &[u8]) -> (&[u8], &[u8]) {
+    let len = u16::from_be_bytes([entry[0], entry[1]]) as usize;
+    entry[2..].split_at(len)
+}
+
+pub struct Node<B> {
+    bytes: B,
+}
+
+impl<B: AsRef<[u8]>> Node<B> {
+    pub fn new(bytes: B) -> Self {
+        Self { bytes }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.bytes.as_ref()[NODE_TYPE] == LEAF
+    }
+
+    fn read_link(&self, offset: usize) -> PageId {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.bytes.as_ref()[offset..offset + 8]);
+        PageId(u64::from_be_bytes(bytes))
+    }
+
+    pub fn prev(&self) -> Option<PageId> {
+        self.read_link(LINK1).valid()
+    }
+
+    pub fn next(&self) -> Option<PageId> {
+        self.read_link(LINK2).valid()
+    }
+
+    pub fn right_child(&self) -> PageId {
+        self.read_link(LINK1)
+    }
+
+    fn slotted(&self) -> Slotted<&[u8]> {
+        Slotted::new(&self.bytes.as_ref()[HEADER_SIZE..])
+    }
+
+    pub fn len(&self) -> usize {
+        self.slotted().num_slots()
+    }
+
+    pub fn entry(&self, i: usize) -> (&[u8], &[u8]) {
+        let range = self.slotted().data_range(i).expect("slot in range");
+        split_entry(&self.bytes.as_ref()[HEADER_SIZE..][range])
+    }
+
+    pub fn key(&self, i: usize) -> &[u8] {
+        self.entry(i).0
+    }
+
+    pub fn child(&self, i: usize) -> PageId {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.entry(i).1);
+        PageId(u64::from_be_bytes(bytes))
+    }
+
+    // key を探す。見つからなければ挿入すべき位置を Err で返す
+    pub fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.key(mid).cmp(key) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(low)
+    }
+
+    // 内部ノードで key を含む子の位置。len() なら最も右の子
+    pub fn child_index(&self, key: &[u8]) -> usize {
+        match self.search(key) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+
+    pub fn child_at(&self, index: usize) -> PageId {
+        if index == self.len() {
+            self.right_child()
+        } else {
+            self.child(index)
+        }
+    }
+
+    // ノードの種類、スロット、エントリの形が壊れていないか確かめる。壊れていればその説明を返す
+    pub fn verify(&self) -> Result<(), String> {
+        let node_type = self.bytes.as_ref()[NODE_TYPE];
+        if node_type != LEAF && node_type != BRANCH {
+            return Err(format!("unknown node type {}", node_type));
+        }
+        let slotted = self.slotted();
+        slotted.verify()?;
+        for i in 0..slotted.num_slots() {
+            let Some(entry) = slotted.get(i) else {
+                return Err(format!("entry {} is missing", i));
+            };
+            if entry.len() < 2
+                || entry.len() < 2 + u16::from_be_bytes([entry[0], entry[1]]) as usize
+            {
+                return Err(format!("entry {} is shorter than its key", i));
+            }
+            if node_type == BRANCH && split_entry(entry).1.len() != 8 {
+                return Err(format!("entry {} does not hold a child page id", i));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Vec<Vec<u8>> {
+        let slotted = self.slotted();
+        (0..slotted.num_slots())
+            .map(|i| slotted.get(i).unwrap().to_vec())
+            .collect()
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Node<B> {
+    fn write_link(&mut self, offset: usize, page_id: PageId) {
+        self.bytes.as_mut()[offset..offset + 8].copy_from_slice(&page_id.to_u64().to_be_bytes());
+    }
+
+    fn slotted_mut(&mut self) -> Slotted<&mut [u8]> {
+        Slotted::new(&mut self.bytes.as_mut()[HEADER_SIZE..])
+    }
+
+    pub fn initialize_leaf(&mut self, prev: Option<PageId>, next: Option<PageId>) {
+        self.bytes.as_mut()[NODE_TYPE] = LEAF;
+        self.write_link(LINK1, prev.unwrap_or(PageId::INVALID_PAGE_ID));
+        self.write_link(LINK2, next.unwrap_or(PageId::INVALID_PAGE_ID));
+        self.slotted_mut().initialize();
+    }
+
+    pub fn initialize_branch(&mut self, right_child: PageId) {
+        self.bytes.as_mut()[NODE_TYPE] = BRANCH;
+        self.write_link(LINK1, right_child);
+        self.write_link(LINK2, PageId::INVALID_PAGE_ID);
+        self.slotted_mut().initialize();
+    }
+
+    pub fn set_prev(&mut self, prev: Option<PageId>) {
+        self.write_link(LINK1, prev.unwrap_or(PageId::INVALID_PAGE_ID));
+    }
+
+    pub fn set_next(&mut self, next: Option<PageId>) {
+        self.write_link(LINK2, next.unwrap_or(PageId::INVALID_PAGE_ID));
+    }
+
+    pub fn set_right_child(&mut self, child: PageId) {
+        self.write_link(LINK1, child);
+    }
+
+    // 収まらなければ false
+    pub fn insert(&mut self, i: usize, entry: &[u8]) -> bool {
+        self.slotted_mut().insert_at(i, entry)
+    }
+
+    pub fn remove(&mut self, i: usize) {
+        self.slotted_mut().remove(i);
+    }
+
+    // 内部ノードの i 番目のエントリの子を付け替える
+    pub fn set_child(&mut self, i: usize, child: PageId) {
+        let key = self.key(i).to_vec();
+        self.remove(i);
+        let inserted = self.insert(i, &encode_entry(&key, &child.to_u64().to_be_bytes()));
+        // 同じ長さのエントリを入れ直すだけなので必ず収まる
+        assert!(inserted);
+    }
+}
+
+// ノードに入れられる 1 エントリの最大長。分割した両側に必ず収まるようにする
+pub fn max_entry_size(page_size: usize) -> usize {
+    (Slotted::<&[u8]>::capacity(page_size - HEADER_SIZE) - 4) / 4
+}
+
+pub fn entry_key(entry: &[u8]) -> &[u8] {
+    split_entry(entry).0
+}
+
+pub fn entry_value(entry: &[u8]) -> &[u8] {
+    split_entry(entry).1
+}
@@ -1,10 +1,13 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::collections::HashMap;
 use std::ops::Index;
 use std::ops::IndexMut;
 
 use crate::disk::{PageId, PAGE_SIZE, DiskManager};
+use crate::metrics::METRICS;
+use crate::trace;
+use crate::wal::{self, LogRecord, Lsn, Wal};
 
 #[derive(Debug,thiserror::Error)]
 pub enum Error{
@@ -12,6 +15,8 @@ pub enum Error{
     Io(#[from] std::io::Error),
     #[error("no free buffer available in the pool")]
     NoFreeBuffer,
+    #[error(transparent)]
+    Wal(#[from] wal::Error),
 }
 
 pub type Page = [u8; PAGE_SIZE];
@@ -22,8 +27,22 @@ pub struct BufferId(usize);
 
 pub struct Buffer{
     pub page_id: PageId,
-    pub page: Page,
+    pub page: RefCell<Page>,
     pub is_dirty: Cell<bool>,
+    // ページを最後に変えたログのレコードと、書き出してから最初に変えたレコード
+    pub lsn: Cell<Lsn>,
+    pub rec_lsn: Cell<Lsn>,
+}
+impl Default for Buffer{
+    fn default() -> Self{
+        Self{
+            page_id: PageId::INVALID_PAGE_ID,
+            page: RefCell::new([0; PAGE_SIZE]),
+            is_dirty: Cell::new(false),
+            lsn: Cell::new(Lsn::INVALID_LSN),
+            rec_lsn: Cell::new(Lsn::INVALID_LSN),
+        }
+    }
 }
 
 pub struct Frame{
@@ -36,6 +55,15 @@ pub struct BufferPool{
     next_victim_id: BufferId,
 }
 impl BufferPool{
+    pub fn new(pool_size: usize) -> Self{
+        let mut buffers = vec![];
+        buffers.resize_with(pool_size, || Frame{usage_count: 0, buffer: Rc::default()});
+        Self{
+            buffers,
+            next_victim_id: BufferId::default(),
+        }
+    }
+
     fn size(& self) -> usize{
         self.buffers.len()
     }
@@ -86,14 +114,200 @@ pub struct BufferPoolManager{
     disk: DiskManager,
     pool: BufferPool,
     page_table: HashMap<PageId, BufferId>,
+    hits: u64,
+    reads: u64,
+    // 汚れたページを書き出して、きれいにした回数
+    cleaned: u64,
+    // あればページを書き出す前に、そのページの LSN までログを書き出す
+    wal: Option<Wal>,
+    // log_page_images してあれば、ログに書いた像
+    images: Option<Images>,
+}
+
+// 隣り合う変わったところの間がこれより短ければ、差分では 1 つにまとめる
+const DELTA_GAP: usize = 8;
+
+#[derive(Default)]
+struct Images{
+    // 最後にログに像を書いてからディスクに書いたかもしれないページと、その最後のレコード
+    written: HashMap<PageId, Lsn>,
+    // チェックポイントのあとに像を書いたページの、最後に書いた内容
+    last: HashMap<PageId, LastImage>,
+}
+
+struct LastImage{
+    page: Box<Page>,
+    // 当て直しを始める、全体の像のレコード
+    start: Lsn,
+    // チェックポイントより前に書いた像で、次は全体の像を書く
+    stale: bool,
+}
+
+impl Images{
+    // ページの内容をログに書く。チェックポイントのあとに書いたことがあれば、前に書いた内容からの差分だけを書く
+    fn log(&mut self, wal: &mut Wal, page_id: PageId, page: &Page) -> Result<Lsn, Error>{
+        let lsn = match self.last.get_mut(&page_id){
+            Some(last) if !last.stale => {
+                let ranges = delta(&last.page, page);
+                // 前に書いてから変わっていなければ、前に書いたレコードでよい
+                if let (true, Some(&lsn)) = (ranges.is_empty(), self.written.get(&page_id)){
+                    return Ok(lsn);
+                }
+                let lsn = wal.append(&LogRecord::PageDelta{page_id, ranges})?;
+                last.page.copy_from_slice(page);
+                lsn
+            }
+            _ => {
+                let lsn = wal.append(&LogRecord::PageImage{page_id, image: page.to_vec()})?;
+                self.last.insert(page_id, LastImage{page: Box::new(*page), start: lsn, stale: false});
+                lsn
+            }
+        };
+        self.written.insert(page_id, lsn);
+        Ok(lsn)
+    }
+}
+
+// old から new に変わったところ
+fn delta(old: &Page, new: &Page) -> Vec<(u16, Vec<u8>)>{
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for i in (0..PAGE_SIZE).filter(|&i| old[i] != new[i]){
+        match ranges.last_mut(){
+            Some((_, end)) if i - *end < DELTA_GAP => *end = i + 1,
+            _ => ranges.push((i, i + 1)),
+        }
+    }
+    ranges.into_iter().map(|(start, end)| (start as u16, new[start..end].to_vec())).collect()
 }
 impl BufferPoolManager{
-    fn fetch_page(&mut self, page_id: PageId) -> Result<Rc<Buffer>, Error>{
+    pub fn new(disk: DiskManager, pool: BufferPool) -> Self{
+        Self{
+            disk,
+            pool,
+            page_table: HashMap::new(),
+            hits: 0,
+            reads: 0,
+            cleaned: 0,
+            wal: None,
+            images: None,
+        }
+    }
+
+    pub fn set_wal(&mut self, wal: Wal){
+        self.wal = Some(wal);
+    }
+
+    pub fn wal_mut(&mut self) -> Option<&mut Wal>{
+        self.wal.as_mut()
+    }
+
+    pub fn wal(&self) -> Option<&Wal>{
+        self.wal.as_ref()
+    }
+
+    // ページの変更をログに書かずに書き換える使い方で、クラッシュしても log_images したときのページに戻せるようにする。
+    // ディスクのページを初めて書き換える前に元の像をログに書き、回復ではいつも像を当てるようページ LSN は残さない
+    pub fn log_page_images(&mut self){
+        self.images = Some(Images::default());
+    }
+
+    // チェックポイントに dirty_pages を書いたあとに呼ぶ。どのページも、次は全体の像を書く。
+    // 当て直しを始める像は、チェックポイントに載ったページの分だけ覚えておく
+    pub fn mark_checkpoint(&mut self){
+        if let Some(images) = self.images.as_mut(){
+            let written = &images.written;
+            images.last.retain(|page_id, _| written.contains_key(page_id));
+            for last in images.last.values_mut(){
+                last.stale = true;
+            }
+        }
+    }
+
+    // 汚れたページと、最後に像を書いてから書き出したページの像をログに書き、最後に書いたレコードを返す。
+    // 書いたものがなければ None。ディスクに届くのはログを flush したとき
+    pub fn log_images(&mut self) -> Result<Option<Lsn>, Error>{
+        let (Some(wal), Some(images)) = (self.wal.as_mut(), self.images.as_mut()) else{
+            return Ok(None);
+        };
+        let mut last = None;
+        for (&page_id, &buffer_id) in self.page_table.iter(){
+            let buffer = &self.pool[buffer_id].buffer;
+            if !buffer.is_dirty.get() && !images.written.contains_key(&page_id){
+                continue;
+            }
+            let lsn = images.log(wal, page_id, &buffer.page.borrow())?;
+            #[cfg(test)]
+            crate::crash::hit(crate::crash::Point::AfterWalAppend)?;
+            buffer.lsn.set(lsn);
+            last = Some(lsn);
+        }
+        // 追い出したページは、ディスクにあるものが最後の像より新しい
+        let evicted: Vec<PageId> = images.written.keys().filter(|id| !self.page_table.contains_key(id)).copied().collect();
+        for page_id in evicted{
+            let mut image = [0; PAGE_SIZE];
+            self.disk.read_page_data(page_id, &mut image)?;
+            last = Some(images.log(wal, page_id, &image)?);
+        }
+        Ok(last)
+    }
+
+    // log_images のあとに汚れたページを書き出す。ディスクのページがどれも最後の像と同じになる
+    pub fn write_logged(&mut self) -> Result<(), Error>{
+        let Some(images) = self.images.as_mut() else{
+            return Ok(());
+        };
+        let mut written = !images.written.is_empty();
+        for (&page_id, &buffer_id) in self.page_table.iter(){
+            let buffer = &self.pool[buffer_id].buffer;
+            if buffer.is_dirty.get(){
+                write_page(&mut self.disk, self.wal.as_mut(), Some(&mut *images), page_id, buffer)?;
+                buffer.is_dirty.set(false);
+                self.cleaned += 1;
+                written = true;
+            }
+        }
+        if written{
+            self.disk.sync()?;
+            images.written.clear();
+        }
+        Ok(())
+    }
+
+    // フレームごとの、入っているページ、変更の有無、ピンの数、使用回数。空のフレームのページは INVALID_PAGE_ID
+    pub fn frames(&self) -> Vec<(PageId, bool, usize, u64)>{
+        self.pool.buffers.iter().map(|frame| {
+            // プールの持つ分を除いた参照の数がピンの数
+            let pins = Rc::strong_count(&frame.buffer) - 1;
+            (frame.buffer.page_id, frame.buffer.is_dirty.get(), pins, frame.usage_count)
+        }).collect()
+    }
+
+    // fetch_page でプールにあったページの数と、ディスクから読んだページの数
+    pub fn hits(&self) -> u64{
+        self.hits
+    }
+
+    pub fn reads(&self) -> u64{
+        self.reads
+    }
+
+    // これまでにきれいなページを汚した回数。書き出したものと、いま汚れているものを足す
+    pub fn dirtied(&self) -> u64{
+        let dirty = self.pool.buffers.iter().filter(|frame| frame.buffer.is_dirty.get()).count();
+        self.cleaned + dirty as u64
+    }
+
+    pub fn fetch_page(&mut self, page_id: PageId) -> Result<Rc<Buffer>, Error>{
+        let mut span = trace::span("fetch_page", &[("page", &page_id.0)]);
         if let Some(&buffer_id) = self.page_table.get(&page_id){
             let frame = &mut self.pool[buffer_id];
             frame.usage_count += 1;
+            self.hits += 1;
+            METRICS.buffer_hits.inc();
+            span.record("hit", true);
             return Ok(frame.buffer.clone());
         }
+        span.record("hit", false);
 
         let buffer_id = self.pool.evict().ok_or(Error::NoFreeBuffer)?;
         let frame = &mut self.pool[buffer_id];
@@ -101,18 +315,148 @@ impl BufferPoolManager{
         {
             let buffer = Rc::get_mut(&mut frame.buffer).unwrap();
             if buffer.is_dirty.get(){
-                self.disk.write_page_data(evict_page_id, &buffer.page)?;
+                let _span = trace::span("evict", &[("page", &evict_page_id.0)]);
+                write_page(&mut self.disk, self.wal.as_mut(), self.images.as_mut(), evict_page_id, buffer)?;
+                buffer.is_dirty.set(false);
+                self.cleaned += 1;
             }
+            // 読めなかったときは、フレームに追い出したページが書き戻した状態で残る
+            let lsn = self.disk.read_page_data(page_id, buffer.page.get_mut())?;
             buffer.page_id = page_id;
-            buffer.is_dirty.set(false);
-            self.disk.read_page_data(page_id, &mut buffer.page)?;
+            buffer.rec_lsn.set(Lsn::INVALID_LSN);
+            buffer.lsn.set(Lsn(lsn));
             frame.usage_count = 1;
         }
         let page = Rc::clone(&frame.buffer);
         self.page_table.remove(&evict_page_id);
         self.page_table.insert(page_id, buffer_id);
+        self.reads += 1;
+        METRICS.buffer_reads.inc();
         Ok(page)
     }
+
+    pub fn create_page(&mut self) -> Result<Rc<Buffer>, Error>{
+        let buffer_id = self.pool.evict().ok_or(Error::NoFreeBuffer)?;
+        let frame = &mut self.pool[buffer_id];
+        let evict_page_id = frame.buffer.page_id;
+        let page_id = {
+            let buffer = Rc::get_mut(&mut frame.buffer).unwrap();
+            if buffer.is_dirty.get(){
+                let _span = trace::span("evict", &[("page", &evict_page_id.0)]);
+                write_page(&mut self.disk, self.wal.as_mut(), self.images.as_mut(), evict_page_id, buffer)?;
+                self.cleaned += 1;
+            }
+            let page_id = self.disk.allocate_page();
+            *buffer = Buffer::default();
+            buffer.page_id = page_id;
+            buffer.is_dirty.set(true);
+            frame.usage_count = 1;
+            page_id
+        };
+        let page = Rc::clone(&frame.buffer);
+        self.page_table.remove(&evict_page_id);
+        self.page_table.insert(page_id, buffer_id);
+        Ok(page)
+    }
+
+    // ログに残した変更で汚れているページと、そのページを最初に汚したレコード。
+    // log_page_images していれば、最後に像を書いてからまだ書き出し終えていないページと、その像のレコードも入る
+    pub fn dirty_pages(&self) -> Vec<(PageId, Lsn)>{
+        let mut pages = vec![];
+        for (&page_id, &buffer_id) in self.page_table.iter(){
+            let buffer = &self.pool[buffer_id].buffer;
+            if let (true, Some(rec_lsn)) = (buffer.is_dirty.get(), buffer.rec_lsn.get().valid()){
+                pages.push((page_id, rec_lsn));
+            }
+        }
+        if let Some(images) = self.images.as_ref(){
+            for &page_id in images.written.keys(){
+                let lsn = images.last.get(&page_id).map_or(images.written[&page_id], |last| last.start);
+                match pages.iter_mut().find(|(p, _)| *p == page_id){
+                    Some((_, rec_lsn)) => *rec_lsn = (*rec_lsn).min(lsn),
+                    None => pages.push((page_id, lsn)),
+                }
+            }
+        }
+        pages
+    }
+
+    // プールにある汚れたページを 1 枚書き出す。ディスクに届くのは sync したとき
+    pub fn flush_page(&mut self, page_id: PageId) -> Result<bool, Error>{
+        let Some(&buffer_id) = self.page_table.get(&page_id) else{
+            return Ok(false);
+        };
+        let buffer = &self.pool[buffer_id].buffer;
+        if !buffer.is_dirty.get(){
+            return Ok(false);
+        }
+        write_page(&mut self.disk, self.wal.as_mut(), self.images.as_mut(), page_id, buffer)?;
+        buffer.is_dirty.set(false);
+        self.cleaned += 1;
+        Ok(true)
+    }
+
+    // ディスクにあるページのチェックサムを確かめる。プールで書き換えたものはまだ反映されていない
+    pub fn verify_page(&mut self, page_id: PageId) -> Result<bool, Error>{
+        Ok(self.disk.verify_page(page_id)?)
+    }
+
+    pub fn page_count(&self) -> u64{
+        self.disk.page_count()
+    }
+
+    pub fn sync(&mut self) -> Result<(), Error>{
+        self.disk.sync()?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error>{
+        for (&page_id, &buffer_id) in self.page_table.iter(){
+            let frame = &self.pool[buffer_id];
+            write_page(&mut self.disk, self.wal.as_mut(), self.images.as_mut(), page_id, &frame.buffer)?;
+            if frame.buffer.is_dirty.replace(false){
+                self.cleaned += 1;
+            }
+        }
+        self.disk.sync()?;
+        // どのページもディスクにあるものがいまのページなので、元の像はいらない
+        if let Some(images) = self.images.as_mut(){
+            *images = Images::default();
+        }
+        Ok(())
+    }
+}
+
+// ページを書き出す。ページを変えたログが先にディスクに届いていなければならない
+fn write_page(disk: &mut DiskManager, wal: Option<&mut Wal>, images: Option<&mut Images>, page_id: PageId, buffer: &Buffer) -> Result<(), Error>{
+    let mut lsn = buffer.lsn.get();
+    match (wal, images){
+        (Some(wal), Some(images)) => {
+            // 像をとってから初めて書き換えるなら、ディスクにある元の像をログに書いておく
+            let base = match images.written.get(&page_id){
+                Some(&base) => base,
+                None if buffer.is_dirty.get() => {
+                    let mut image = [0; PAGE_SIZE];
+                    disk.read_page_data(page_id, &mut image)?;
+                    images.log(wal, page_id, &image)?
+                }
+                None => Lsn::INVALID_LSN,
+            };
+            if let Some(lsn) = lsn.max(base).valid(){
+                wal.flush(lsn)?;
+            }
+            lsn = Lsn::INVALID_LSN;
+        }
+        (Some(wal), None) => {
+            if let Some(lsn) = lsn.valid(){
+                wal.flush(lsn)?;
+            }
+        }
+        _ => {}
+    }
+    disk.write_page_data(page_id, lsn.to_u64(), buffer.page.borrow().as_ref())?;
+    buffer.rec_lsn.set(Lsn::INVALID_LSN);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -122,8 +466,8 @@ mod test{
     fn create_buffer_pool() -> BufferPool{
         BufferPool{
             buffers: vec![
-                Frame{usage_count: 0, buffer: Rc::new(Buffer{page_id: PageId(0), page: [0; PAGE_SIZE], is_dirty: Cell::new(false)})},
-                Frame{usage_count: 0, buffer: Rc::new(Buffer{page_id: PageId(1), page: [0; PAGE_SIZE], is_dirty: Cell::new(false)})},
+                Frame{usage_count: 0, buffer: Rc::new(Buffer{page_id: PageId(0), page: RefCell::new([0; PAGE_SIZE]), is_dirty: Cell::new(false), lsn: Cell::new(Lsn::INVALID_LSN), rec_lsn: Cell::new(Lsn::INVALID_LSN)})},
+                Frame{usage_count: 0, buffer: Rc::new(Buffer{page_id: PageId(1), page: RefCell::new([0; PAGE_SIZE]), is_dirty: Cell::new(false), lsn: Cell::new(Lsn::INVALID_LSN), rec_lsn: Cell::new(Lsn::INVALID_LSN)})},
             ],
             next_victim_id: BufferId(0),
         }
@@ -134,16 +478,32 @@ mod test{
         let mut pool = create_buffer_pool();
         assert_eq!(pool.evict(), Some(BufferId(0)));
         {
-            let a = Rc::clone(&mut pool[BufferId(0)].buffer);
+            let a = Rc::clone(&pool[BufferId(0)].buffer);
             pool[BufferId(0)].usage_count = 1;
             assert_eq!(pool.evict(), Some(BufferId(1)));
-            let b = Rc::clone(&mut pool[BufferId(1)].buffer);
+            let b = Rc::clone(&pool[BufferId(1)].buffer);
             pool[BufferId(1)].usage_count = 1;
             assert_eq!(pool.evict(), None);
             drop(a);
             drop(b);
         }
-        let _ = Rc::clone(&mut pool[BufferId(1)].buffer);
+        let _ = Rc::clone(&pool[BufferId(1)].buffer);
         assert_eq!(pool.evict(), Some(BufferId(0)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_buffer_pool_manager(){
+        let mut bufmgr = crate::testutil::temp_bufmgr(1);
+        let page_id = {
+            let buffer = bufmgr.create_page().unwrap();
+            buffer.page.borrow_mut()[0] = 42;
+            buffer.page_id
+        };
+        // プールが 1 枚なので次のページを作ると書き戻される
+        let other = bufmgr.create_page().unwrap();
+        assert!(matches!(bufmgr.fetch_page(page_id), Err(Error::NoFreeBuffer)));
+        drop(other);
+        let buffer = bufmgr.fetch_page(page_id).unwrap();
+        assert_eq!(buffer.page.borrow()[0], 42);
+    }
+}
@@ -0,0 +1,2040 @@
+use std::cell::Cell;
+use std::sync::Arc;
+
+use crate::btree::{self, BTree, BulkLoader};
+use crate::buffer::BufferPoolManager;
+use crate::collation::Collation;
+use crate::datetime::{Interval, Timestamp};
+use crate::disk::PageId;
+use crate::enum_type::EnumType;
+use crate::executor::expr::{Expr, Function, ScalarFunction};
+use crate::executor::{
+    self, compare_values, AggregateFunction, MemoryBudget, Sorter, DEFAULT_WORK_MEM,
+};
+use crate::fulltext;
+use crate::geometry::Rect;
+use crate::heap::{self, RecordId};
+use crate::rtree::{self, RTree};
+use crate::sql::ast::{PartitionStrategy, Privilege};
+use crate::storage::{self, StorageEngine, TableAccess};
+use crate::transaction::TxnId;
+use crate::types::{CoerceError, DataType, Value};
+
+mod dictionary;
+mod generated;
+mod partition;
+mod role;
+mod stats;
+mod trigger;
+
+pub use dictionary::{decode_row, Dictionary, MAX_DICTIONARY_SIZE};
+pub use generated::{fill_virtual, GeneratedColumn};
+pub use partition::{partition_hash, PartitionBound, Partitioning};
+pub use role::{Role, TablePrivilege};
+pub use stats::{ColumnStats, TableStats, HISTOGRAM_BUCKETS, SAMPLE_ROWS};
+pub use trigger::{Trigger, TriggerBody, TriggerFunction};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Heap(#[from] heap::Error),
+    #[error("table \"{0}\" already exists")]
+    TableExists(String),
+    #[error("table \"{0}\" does not exist")]
+    TableNotFound(String),
+    #[error(transparent)]
+    BTree(#[from] btree::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("column \"{0}\" specified more than once")]
+    DuplicateColumn(String),
+    #[error("column \"{0}\" does not exist")]
+    ColumnNotFound(String),
+    #[error("relation \"{0}\" already exists")]
+    IndexExists(String),
+    #[error("function \"{0}\" already exists")]
+    FunctionExists(String),
+    #[error("access method \"{0}\" does not exist")]
+    StorageEngineNotFound(String),
+    #[error("access method \"{0}\" already exists")]
+    StorageEngineExists(String),
+    #[error("duplicate key value violates unique constraint \"{0}\"")]
+    UniqueViolation(String),
+    #[error("corrupted tuple at page {}, slot {}", .0.page_id.to_u64(), .0.slot)]
+    CorruptedTuple(RecordId),
+    #[error(
+        "null value in column \"{column}\" of relation \"{table}\" violates not-null constraint"
+    )]
+    NotNullViolation { column: String, table: String },
+    #[error("column \"{column}\" is of type {expected} but expression is of type {actual}")]
+    DatatypeMismatch {
+        column: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("numeric field overflow in column \"{0}\"")]
+    NumericFieldOverflow(String),
+    #[error("integer out of range for column \"{0}\"")]
+    IntegerOutOfRange(String),
+    #[error("invalid input syntax for type {expected}: \"{value}\"")]
+    InvalidSyntax { expected: String, value: String },
+    // 式のインデックスのキーを計算できなかった
+    #[error(transparent)]
+    Expression(Box<executor::Error>),
+    #[error("column \"{0}\" of a full-text index must be of type text")]
+    NotTextColumn(String),
+    #[error(transparent)]
+    RTree(#[from] rtree::Error),
+    #[error("column \"{0}\" of an R-tree index must be of type point or box")]
+    NotGeometricColumn(String),
+    #[error("an R-tree index must have exactly one column")]
+    RTreeColumnCount,
+    #[error("table \"{0}\" is not partitioned")]
+    NotPartitioned(String),
+    #[error("invalid bound specification for a {0} partition")]
+    PartitionStrategyMismatch(PartitionStrategy),
+    #[error("empty range bound specified for partition \"{0}\"")]
+    EmptyRange(String),
+    #[error("modulus for hash partition must be an integer value greater than zero")]
+    InvalidModulus,
+    #[error("remainder for hash partition must be less than modulus")]
+    InvalidRemainder,
+    #[error("partition \"{0}\" would overlap partition \"{1}\"")]
+    PartitionOverlap(String, String),
+    #[error("no partition of relation \"{0}\" found for row")]
+    NoPartition(String),
+    #[error("new row for relation \"{0}\" violates partition constraint")]
+    PartitionConstraint(String),
+    #[error("cannot create index on partitioned table \"{0}\"")]
+    PartitionedIndex(String),
+    #[error("column \"{0}\" of a TTL must be of type timestamp or date")]
+    NotTimestampColumn(String),
+    #[error("TTL must be a positive interval")]
+    InvalidTtl,
+    #[error("\"{0}\" is not a materialized view")]
+    NotMaterializedView(String),
+    #[error("query of materialized view \"{0}\" no longer returns the same columns")]
+    ViewColumnsChanged(String),
+    #[error("trigger \"{0}\" for relation \"{1}\" already exists")]
+    TriggerExists(String, String),
+    #[error("trigger \"{0}\" for table \"{1}\" does not exist")]
+    TriggerNotFound(String, String),
+    #[error("function {0}() does not exist")]
+    TriggerFunctionNotFound(String),
+    #[error("role \"{0}\" already exists")]
+    RoleExists(String),
+    #[error("role \"{0}\" does not exist")]
+    RoleNotFound(String),
+    #[error("role \"{0}\" cannot be dropped because some objects depend on it")]
+    RoleHasPrivileges(String),
+    #[error("cannot use generated column \"{0}\" in column generation expression")]
+    GeneratedColumnReference(String),
+    #[error("cannot create index on virtual generated column \"{0}\"")]
+    VirtualColumnIndex(String),
+    #[error("cannot drop column {column} because generated column {generated} depends on it")]
+    GeneratedColumnDependency { column: String, generated: String },
+    #[error("column \"{0}\" must be of type text to use dictionary compression")]
+    DictionaryType(String),
+    #[error("type \"{0}\" already exists")]
+    TypeExists(String),
+    #[error("type \"{0}\" does not exist")]
+    TypeNotFound(String),
+    #[error("enum label \"{0}\" already exists")]
+    EnumLabelExists(String),
+    #[error("\"{0}\" is not an existing enum label")]
+    EnumLabelNotFound(String),
+    #[error("cannot drop type {0} because other objects depend on it")]
+    TypeInUse(String),
+    #[error("unrecognized parameter \"{0}\"")]
+    UnknownTableOption(String),
+    #[error("invalid value for boolean option \"{option}\": {value}")]
+    InvalidTableOption { option: String, value: String },
+    #[error("cannot add column to a partition")]
+    PartitionColumn,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub data_type: DataType,
+    pub not_null: bool,
+    // TEXT の列の比べ方
+    pub collation: Collation,
+}
+
+impl Column {
+    // 値を列の型に揃える
+    pub fn coerce(&self, value: Value) -> Result<Value, Error> {
+        let actual = value.type_name();
+        self.data_type.coerce(value).map_err(|err| match err {
+            CoerceError::Mismatch => Error::DatatypeMismatch {
+                column: self.name.clone(),
+                expected: self.data_type.name().to_string(),
+                actual,
+            },
+            CoerceError::Overflow if self.data_type.is_integer() => {
+                Error::IntegerOutOfRange(self.name.clone())
+            }
+            CoerceError::Overflow => Error::NumericFieldOverflow(self.name.clone()),
+            CoerceError::InvalidSyntax(value) => Error::InvalidSyntax {
+                expected: self.data_type.name().to_string(),
+                value,
+            },
+        })
+    }
+}
+
+// B+Tree のキーは列の値を btree::key::encode したものに RecordId を続けたもの。
+// BINARY でない照合順の列は、値を照合順のキーにしてから encode する。
+// 式のインデックスは、行で計算した式の値をひとつだけキーにする。
+// RecordId を含めることで同じ値の行があってもキーが重複しない。
+// 全文インデックスは行の列の語ごとに、その語をひとつだけ値にしたキーを持つ。
+// R-tree のインデックスは POINT か BOX の列を 1 つだけ持ち、NULL でない値を囲む矩形と RecordId を R-tree に入れる。
+// btree は使わない空の B+Tree にしておく。
+// 行を書き換えたり消したりしても古い版を指すキーは残るので、引いた版が消されていないかはヒープで確かめる。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub name: String,
+    // テーブルの列の位置
+    pub columns: Vec<usize>,
+    // 各列の照合順
+    pub collations: Vec<Collation>,
+    // 式のインデックスの式。テーブルの行に対する式で、columns は空
+    pub expression: Option<Expr>,
+    pub unique: bool,
+    pub fulltext: bool,
+    pub btree: BTree,
+    pub rtree: Option<RTree>,
+}
+
+// ADD COLUMN より前に書いた版の行の後ろを、足した列の NULL で埋めて width 列にする
+pub fn pad_row(row: &mut Vec<Value>, width: usize) {
+    if row.len() < width {
+        row.resize(width, Value::Null);
+    }
+}
+
+pub fn encode_record_id(rid: RecordId, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&rid.page_id.to_u64().to_be_bytes());
+    bytes.extend_from_slice(&rid.slot.to_be_bytes());
+}
+
+// R-tree のエントリを B+Tree のキーと同じ形にしたもの。突き合わせに使う
+pub fn rtree_key(rect: Rect, rid: RecordId) -> Vec<u8> {
+    let mut key = vec![];
+    btree::key::encode(&[Value::Box(Box::new(rect))], &mut key);
+    encode_record_id(rid, &mut key);
+    key
+}
+
+// B+Tree のキーの末尾から RecordId を取り出す
+pub fn decode_record_id(key: &[u8]) -> RecordId {
+    let (_, tail) = key.split_at(key.len() - 10);
+    let mut page_id = [0; 8];
+    page_id.copy_from_slice(&tail[..8]);
+    RecordId {
+        page_id: PageId(u64::from_be_bytes(page_id)),
+        slot: u16::from_be_bytes([tail[8], tail[9]]),
+    }
+}
+
+// 行のキーの値。全文インデックスでは語ごとに 1 つ、ほかはいつも 1 つ
+fn entry_values(
+    columns: &[usize],
+    collations: &[Collation],
+    expression: Option<&Expr>,
+    fulltext: bool,
+    row: &[Value],
+) -> Result<Vec<Vec<Value>>, Error> {
+    if !fulltext {
+        return Ok(vec![key_values(columns, collations, expression, row)?]);
+    }
+    let texts = columns.iter().filter_map(|&i| match &row[i] {
+        Value::Text(s) => Some(s.as_str()),
+        _ => None,
+    });
+    Ok(fulltext::term_frequencies(texts)
+        .into_keys()
+        .map(|term| vec![Value::Text(term)])
+        .collect())
+}
+
+fn key_values(
+    columns: &[usize],
+    collations: &[Collation],
+    expression: Option<&Expr>,
+    row: &[Value],
+) -> Result<Vec<Value>, Error> {
+    if let Some(expr) = expression {
+        let value = expr
+            .eval(&row.to_vec())
+            .map_err(|err| Error::Expression(Box::new(err)))?;
+        return Ok(vec![value]);
+    }
+    Ok(columns
+        .iter()
+        .zip(collations)
+        .map(|(&i, c)| c.key(row[i].clone()))
+        .collect())
+}
+
+impl Index {
+    // 行からこのインデックスのキーの値を取り出す
+    pub fn key_values(&self, row: &[Value]) -> Result<Vec<Value>, Error> {
+        key_values(
+            &self.columns,
+            &self.collations,
+            self.expression.as_ref(),
+            row,
+        )
+    }
+
+    // 行からキーの前半を作る
+    pub fn key_prefix(&self, row: &[Value]) -> Result<Vec<u8>, Error> {
+        let mut key = vec![];
+        btree::key::encode(&self.key_values(row)?, &mut key);
+        Ok(key)
+    }
+
+    // キーの順に行を引ける B+Tree のインデックスか
+    pub fn is_ordered(&self) -> bool {
+        !self.fulltext && self.rtree.is_none()
+    }
+
+    // rid の行がこのインデックスに持つキー。R-tree のインデックスでは rtree_key
+    pub fn entries(&self, row: &[Value], rid: RecordId) -> Result<Vec<Vec<u8>>, Error> {
+        if self.rtree.is_some() {
+            let rect = row[self.columns[0]].rect();
+            return Ok(rect.map(|rect| rtree_key(rect, rid)).into_iter().collect());
+        }
+        let values = entry_values(
+            &self.columns,
+            &self.collations,
+            self.expression.as_ref(),
+            self.fulltext,
+            row,
+        )?;
+        Ok(values
+            .iter()
+            .map(|values| {
+                let mut key = vec![];
+                btree::key::encode(values, &mut key);
+                encode_record_id(rid, &mut key);
+                key
+            })
+            .collect())
+    }
+
+    fn insert(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        row: &[Value],
+        rid: RecordId,
+    ) -> Result<(), Error> {
+        if let Some(rtree) = self.rtree {
+            if let Some(rect) = row[self.columns[0]].rect() {
+                rtree.insert(bufmgr, rect, rid)?;
+            }
+            return Ok(());
+        }
+        for key in self.entries(row, rid)? {
+            self.btree.insert(bufmgr, &key, &[])?;
+        }
+        Ok(())
+    }
+
+    fn delete(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        row: &[Value],
+        rid: RecordId,
+    ) -> Result<(), Error> {
+        if let Some(rtree) = self.rtree {
+            if let Some(rect) = row[self.columns[0]].rect() {
+                rtree.delete(bufmgr, rect, rid)?;
+            }
+            return Ok(());
+        }
+        for key in self.entries(row, rid)? {
+            self.btree.delete(bufmgr, &key)?;
+        }
+        Ok(())
+    }
+
+    // 同じ値の消されていない行があればその RecordId。NULL を含む値はどの行とも重複しない
+    pub fn find(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        heap: &dyn TableAccess,
+        row: &[Value],
+    ) -> Result<Option<RecordId>, Error> {
+        let values = self.key_values(row)?;
+        if values.iter().any(Value::is_null) {
+            return Ok(None);
+        }
+        let mut prefix = vec![];
+        btree::key::encode(&values, &mut prefix);
+        let mut scan = self.btree.scan(bufmgr, Some(&prefix))?;
+        while let Some((key, _)) = scan.next(bufmgr)? {
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let rid = decode_record_id(&key);
+            if heap
+                .get(bufmgr, rid)?
+                .is_some_and(|(header, _)| !header.is_deleted())
+            {
+                return Ok(Some(rid));
+            }
+        }
+        Ok(None)
+    }
+
+    fn contains(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        heap: &dyn TableAccess,
+        row: &[Value],
+    ) -> Result<bool, Error> {
+        Ok(self.find(bufmgr, heap, row)?.is_some())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+    // 行の版を持つところと、それを作った StorageEngine の名前
+    pub storage: Arc<dyn TableAccess>,
+    pub engine: String,
+    pub indexes: Vec<Index>,
+    // 入っている行の数。計画で行数を見積もるのに使う
+    rows: Cell<usize>,
+    // 最後に ANALYZE したときの統計
+    pub stats: Option<TableStats>,
+    // PARTITION BY で作った親のテーブルなら、その分け方
+    pub partitioning: Option<Partitioning>,
+    // PARTITION OF で作った子のテーブルなら、親と値の範囲
+    pub partition: Option<Partition>,
+    pub ttl: Option<Ttl>,
+    // CREATE MATERIALIZED VIEW で作ったテーブルなら、その問い合わせ
+    pub view: Option<MaterializedView>,
+    // 行を入れたり消したりした回数。実体化したビューが古くなったかを調べるのに使う
+    changes: Cell<u64>,
+    // 最後に VACUUM してから消した版の数と、最後に ANALYZE したときの changes。
+    // 自動の VACUUM と ANALYZE をするかどうかを決めるのに使い、ファイルには残さない
+    dead: Cell<u64>,
+    analyzed_changes: Cell<u64>,
+    // WITH (autovacuum_enabled = false) なら、自動の VACUUM と ANALYZE をしない
+    pub autovacuum: bool,
+    pub last_autovacuum: Cell<Option<Timestamp>>,
+    pub last_autoanalyze: Cell<Option<Timestamp>>,
+    // 作った順
+    pub triggers: Vec<Trigger>,
+    // 列の順
+    pub generated: Vec<GeneratedColumn>,
+    // COMPRESSION dictionary の列の辞書。列の順
+    pub dictionaries: Vec<Arc<Dictionary>>,
+}
+
+// 問い合わせの結果を行として持つビュー
+//
+// sources は問い合わせが読んだテーブルと、最後に作り直したときのその changes。
+// どれかが変わったか消えていれば、ビューの行は古いかもしれない
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterializedView {
+    pub query: String,
+    pub sources: Vec<(String, u64)>,
+    pub refreshed_at: Timestamp,
+}
+
+// 列の時刻から interval が過ぎた行は期限切れで、VACUUM で取り除く
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ttl {
+    pub column: usize,
+    pub interval: Interval,
+}
+
+impl Ttl {
+    // この時刻より前の行が期限切れ
+    pub fn cutoff(&self, now: Timestamp) -> Option<Timestamp> {
+        now.checked_add_interval(self.interval.checked_neg()?)
+    }
+
+    // NULL の行は期限が来ない
+    pub fn expired(&self, row: &[Value], cutoff: Timestamp) -> bool {
+        row[self.column].timestamp().is_some_and(|t| t <= cutoff)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partition {
+    pub parent: String,
+    // 親の分け方の列の位置。子の列は親と同じ
+    pub column: usize,
+    pub bound: PartitionBound,
+}
+
+impl Table {
+    pub fn row_count(&self) -> usize {
+        self.rows.get()
+    }
+
+    // ファイルから読み戻したテーブルの行を数え直したとき
+    pub fn set_row_count(&self, rows: usize) {
+        self.rows.set(rows);
+    }
+
+    pub fn changes(&self) -> u64 {
+        self.changes.get()
+    }
+
+    // 最後に VACUUM してから消した版の数
+    pub fn dead_versions(&self) -> u64 {
+        self.dead.get()
+    }
+
+    // 最後に ANALYZE してから行を入れたり消したりした回数
+    pub fn changes_since_analyze(&self) -> u64 {
+        self.changes.get().saturating_sub(self.analyzed_changes.get())
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    pub fn index(&self, name: &str) -> Option<&Index> {
+        self.indexes.iter().find(|i| i.name == name)
+    }
+
+    pub fn generated_column(&self, column: usize) -> Option<&GeneratedColumn> {
+        self.generated.iter().find(|g| g.column == column)
+    }
+
+    pub fn virtual_columns(&self) -> impl Iterator<Item = &GeneratedColumn> {
+        self.generated.iter().filter(|g| !g.stored)
+    }
+
+    // 列の型に使う列挙型。同じ型は 1 度だけ
+    pub fn enum_types(&self) -> Vec<Arc<EnumType>> {
+        let mut types: Vec<Arc<EnumType>> = vec![];
+        for column in &self.columns {
+            if let DataType::Enum(ty) = &column.data_type {
+                if !types.contains(ty) {
+                    types.push(ty.clone());
+                }
+            }
+        }
+        types
+    }
+
+    // 版のバイト列から行を復元する。VIRTUAL の生成列と、ADD COLUMN より前に書いた版にない列は NULL
+    pub fn decode(&self, bytes: &[u8]) -> Option<Vec<Value>> {
+        let mut row = dictionary::decode_row(&self.dictionaries, &self.enum_types(), bytes, None)?;
+        pad_row(&mut row, self.columns.len());
+        Some(row)
+    }
+
+    // other はカタログを写す前に同じテーブルだったもの。写してから両方で数えた行数や書き換えた回数の、大きいほうにする。
+    // どれも見積もりと、変わったかどうかを調べるのにしか使わない
+    pub fn merge_counters(&self, other: &Table) {
+        let max = |cell: &Cell<u64>, other: &Cell<u64>| cell.set(cell.get().max(other.get()));
+        self.rows.set(self.rows.get().max(other.rows.get()));
+        max(&self.changes, &other.changes);
+        max(&self.dead, &other.dead);
+        max(&self.analyzed_changes, &other.analyzed_changes);
+        self.last_autovacuum
+            .set(self.last_autovacuum.get().max(other.last_autovacuum.get()));
+        self.last_autoanalyze
+            .set(self.last_autoanalyze.get().max(other.last_autoanalyze.get()));
+    }
+
+    // 行の値を列の型に揃え、NOT NULL 制約を確かめる。入れる前の行はこれを通しておく。
+    // 生成列は与えた値を捨て、ほかの列を揃えてから計算する
+    pub fn coerce(&self, row: Vec<Value>) -> Result<Vec<Value>, Error> {
+        let mut row = self
+            .columns
+            .iter()
+            .zip(row)
+            .enumerate()
+            .map(|(i, (column, value))| match self.generated_column(i) {
+                Some(_) => Ok(Value::Null),
+                None => self.coerce_value(column, value),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for generated in &self.generated {
+            let value = generated
+                .expr
+                .eval(&row)
+                .map_err(|err| Error::Expression(Box::new(err)))?;
+            row[generated.column] = self.coerce_value(&self.columns[generated.column], value)?;
+        }
+        Ok(row)
+    }
+
+    fn coerce_value(&self, column: &Column, value: Value) -> Result<Value, Error> {
+        if value.is_null() && column.not_null {
+            return Err(Error::NotNullViolation {
+                column: column.name.clone(),
+                table: self.name.clone(),
+            });
+        }
+        column.coerce(value)
+    }
+
+    // column を消してよいか確かめる。生成列の式が参照している列は消せない
+    pub fn check_drop_column(&self, column: usize) -> Result<(), Error> {
+        match self
+            .generated
+            .iter()
+            .find(|g| g.dependencies().contains(&column))
+        {
+            Some(g) => Err(Error::GeneratedColumnDependency {
+                column: self.columns[column].name.clone(),
+                generated: self.columns[g.column].name.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // 列の型と NOT NULL 制約を確かめる。値は列の型に揃っていなければならない
+    fn check(&self, row: &[Value]) -> Result<(), Error> {
+        for (column, value) in self.columns.iter().zip(row) {
+            if value.is_null() {
+                if column.not_null {
+                    return Err(Error::NotNullViolation {
+                        column: column.name.clone(),
+                        table: self.name.clone(),
+                    });
+                }
+                continue;
+            }
+            if !column.data_type.holds(value) {
+                return Err(Error::DatatypeMismatch {
+                    column: column.name.clone(),
+                    expected: column.data_type.name().to_string(),
+                    actual: value.type_name(),
+                });
+            }
+        }
+        if let Some(partition) = &self.partition {
+            if !partition.bound.contains(&row[partition.column]) {
+                return Err(Error::PartitionConstraint(self.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    // xid が入れた版としてヒープに行を入れ、すべてのインデックスにも登録する
+    pub fn insert(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        xid: TxnId,
+        row: &[Value],
+    ) -> Result<RecordId, Error> {
+        self.check(row)?;
+        for index in self.indexes.iter().filter(|i| i.unique) {
+            if index.contains(bufmgr, &*self.storage, row)? {
+                return Err(Error::UniqueViolation(index.name.clone()));
+            }
+        }
+        let rid = self.insert_version(bufmgr, xid, row)?;
+        self.rows.set(self.rows.get() + 1);
+        Ok(rid)
+    }
+
+    fn insert_version(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        xid: TxnId,
+        row: &[Value],
+    ) -> Result<RecordId, Error> {
+        let mut bytes = vec![];
+        // VIRTUAL の列は読むときに計算するので持たない
+        if self.virtual_columns().next().is_some() {
+            let mut stored = row.to_vec();
+            for generated in self.virtual_columns() {
+                stored[generated.column] = Value::Null;
+            }
+            dictionary::encode_row(&self.dictionaries, &stored, &mut bytes);
+        } else {
+            dictionary::encode_row(&self.dictionaries, row, &mut bytes);
+        }
+        let rid = self.storage.insert(bufmgr, xid, &bytes)?;
+        self.changes.set(self.changes.get() + 1);
+        for (i, index) in self.indexes.iter().enumerate() {
+            if let Err(err) = index.insert(bufmgr, row, rid) {
+                // 途中まで入れたものを残すと、取り消しの記録にない版が残ってしまう
+                for index in &self.indexes[..i] {
+                    index.delete(bufmgr, row, rid)?;
+                }
+                self.storage.remove(bufmgr, rid)?;
+                return Err(err);
+            }
+        }
+        Ok(rid)
+    }
+
+    // rid の版 old を消したことにして、new を新しい版として入れる。
+    // 新しい版の RecordId を返し、rid の版がすでに消されていれば None
+    pub fn update(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        xid: TxnId,
+        rid: RecordId,
+        old: &[Value],
+        new: &[Value],
+    ) -> Result<Option<RecordId>, Error> {
+        self.check(new)?;
+        for index in self.indexes.iter().filter(|i| i.unique) {
+            if index.key_prefix(old)? != index.key_prefix(new)?
+                && index.contains(bufmgr, &*self.storage, new)?
+            {
+                return Err(Error::UniqueViolation(index.name.clone()));
+            }
+        }
+        if !self.storage.delete(bufmgr, rid, xid)? {
+            return Ok(None);
+        }
+        match self.insert_version(bufmgr, xid, new) {
+            Ok(new_rid) => {
+                self.dead.set(self.dead.get() + 1);
+                Ok(Some(new_rid))
+            }
+            Err(err) => {
+                self.storage.undelete(bufmgr, rid)?;
+                Err(err)
+            }
+        }
+    }
+
+    // 版に xid が消したしるしをつける。インデックスのキーは残しておく
+    pub fn delete(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        xid: TxnId,
+        rid: RecordId,
+    ) -> Result<bool, Error> {
+        if !self.storage.delete(bufmgr, rid, xid)? {
+            return Ok(false);
+        }
+        self.rows.set(self.rows.get().saturating_sub(1));
+        self.changes.set(self.changes.get() + 1);
+        self.dead.set(self.dead.get() + 1);
+        Ok(true)
+    }
+
+    // 取り消した版をヒープとインデックスから取り除く。row はその版の値
+    pub fn remove(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+        row: &[Value],
+    ) -> Result<(), Error> {
+        let live = self
+            .storage
+            .get(bufmgr, rid)?
+            .is_some_and(|(header, _)| !header.is_deleted());
+        if !self.storage.remove(bufmgr, rid)? {
+            return Ok(());
+        }
+        for index in &self.indexes {
+            index.delete(bufmgr, row, rid)?;
+        }
+        if live {
+            self.rows.set(self.rows.get().saturating_sub(1));
+        }
+        Ok(())
+    }
+
+    // 消したしるしを外して版を元に戻す
+    pub fn restore(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<(), Error> {
+        if self.storage.undelete(bufmgr, rid)? {
+            self.rows.set(self.rows.get() + 1);
+            self.dead.set(self.dead.get().saturating_sub(1));
+        }
+        Ok(())
+    }
+
+    // horizon より前のトランザクションが消した版は、どのスナップショットからも見えないので
+    // ヒープとインデックスから取り除く。取り除いた版の数を返す
+    //
+    // expire が (ttl, 時刻) なら、その時刻より前に期限が切れた行も取り除く。
+    // 書いている途中のトランザクションが作ったり消したりした版は残す
+    pub fn vacuum(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        horizon: TxnId,
+        expire: Option<(Ttl, Timestamp)>,
+    ) -> Result<usize, Error> {
+        let mut dead = vec![];
+        let mut deleted_versions = 0;
+        let mut scan = self.storage.scan();
+        while let Some((rid, header, bytes)) = scan.next(bufmgr)? {
+            let deleted = header.xmax.valid().is_some_and(|xmax| xmax < horizon);
+            let settled = header.xmax.valid().is_none() && header.xmin < horizon;
+            if !(deleted || settled && expire.is_some()) {
+                continue;
+            }
+            let row = self.decode(&bytes).ok_or(Error::CorruptedTuple(rid))?;
+            if deleted || expire.is_some_and(|(ttl, cutoff)| ttl.expired(&row, cutoff)) {
+                deleted_versions += deleted as u64;
+                dead.push((rid, row));
+            }
+        }
+        for (rid, row) in &dead {
+            self.remove(bufmgr, *rid, row)?;
+        }
+        self.dead
+            .set(self.dead.get().saturating_sub(deleted_versions));
+        Ok(dead.len())
+    }
+
+    // すべての版をヒープとインデックスから取り除く。COPY (BULK) に失敗したときに空に戻すのに使う
+    pub fn truncate(&self, bufmgr: &mut BufferPoolManager) -> Result<(), Error> {
+        let mut versions = vec![];
+        let mut scan = self.storage.scan();
+        while let Some((rid, _, bytes)) = scan.next(bufmgr)? {
+            versions.push((rid, self.decode(&bytes).ok_or(Error::CorruptedTuple(rid))?));
+        }
+        for (rid, row) in &versions {
+            self.remove(bufmgr, *rid, row)?;
+        }
+        Ok(())
+    }
+}
+
+// トランザクションの中の DDL は、接続が写したカタログを変えてコミットで入れ替える。
+// 写しもテーブルの行の版やインデックスのページは同じものを指す
+#[derive(Debug, Default, Clone)]
+pub struct Catalog {
+    tables: Vec<Table>,
+    functions: Vec<Arc<ScalarFunction>>,
+    trigger_functions: Vec<Arc<TriggerFunction>>,
+    roles: Vec<Role>,
+    grants: Vec<TablePrivilege>,
+    // CREATE TYPE ... AS ENUM で作った型
+    types: Vec<Arc<EnumType>>,
+    // 次に作る型の番号。行には型の番号を書くので、消した型の番号も使い回さない
+    next_type_id: u32,
+    // 組み込みの heap と lsm のほかに登録したもの
+    storage_engines: Vec<Arc<dyn StorageEngine>>,
+    // テーブルやインデックスを作ったり消したり、統計を集め直したりするたびに増える
+    version: u64,
+    // version のうち、ANALYZE で増やした分
+    analyzed: u64,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn create_table(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        columns: Vec<Column>,
+    ) -> Result<&Table, Error> {
+        self.create_table_using(bufmgr, name, columns, storage::DEFAULT_STORAGE_ENGINE)
+    }
+
+    // engine の名前の StorageEngine に行を持たせるテーブルを作る
+    pub fn create_table_using(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        columns: Vec<Column>,
+        engine: &str,
+    ) -> Result<&Table, Error> {
+        if self.table(name).is_some() {
+            return Err(Error::TableExists(name.to_string()));
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|c| c.name == column.name) {
+                return Err(Error::DuplicateColumn(column.name.clone()));
+            }
+        }
+        let storage = self
+            .storage_engine(engine)
+            .ok_or_else(|| Error::StorageEngineNotFound(engine.to_string()))?
+            .create(bufmgr)?;
+        self.tables.push(Table {
+            name: name.to_string(),
+            columns,
+            storage,
+            engine: engine.to_string(),
+            indexes: vec![],
+            rows: Cell::new(0),
+            stats: None,
+            partitioning: None,
+            partition: None,
+            ttl: None,
+            view: None,
+            changes: Cell::new(0),
+            dead: Cell::new(0),
+            analyzed_changes: Cell::new(0),
+            autovacuum: true,
+            last_autovacuum: Cell::new(None),
+            last_autoanalyze: Cell::new(None),
+            triggers: vec![],
+            generated: vec![],
+            dictionaries: vec![],
+        });
+        self.version += 1;
+        Ok(self.tables.last().unwrap())
+    }
+
+    // 作ったばかりの空のテーブルを、行を持たずに column の値で子のテーブルへ行を分ける親にする
+    pub fn partition_table(
+        &mut self,
+        name: &str,
+        strategy: PartitionStrategy,
+        column: &str,
+    ) -> Result<(), Error> {
+        let table = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        if !table.indexes.is_empty() {
+            return Err(Error::PartitionedIndex(name.to_string()));
+        }
+        let column = table
+            .column_index(column)
+            .ok_or_else(|| Error::ColumnNotFound(column.to_string()))?;
+        table.partitioning = Some(Partitioning {
+            strategy,
+            column,
+            partitions: vec![],
+        });
+        self.version += 1;
+        Ok(())
+    }
+
+    // 作ったばかりのテーブルの生成列を決める
+    pub fn set_generated(
+        &mut self,
+        name: &str,
+        generated: Vec<GeneratedColumn>,
+    ) -> Result<(), Error> {
+        let table = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        for g in &generated {
+            if let Some(&i) = g
+                .dependencies()
+                .iter()
+                .find(|&&i| generated.iter().any(|other| other.column == i))
+            {
+                return Err(Error::GeneratedColumnReference(
+                    table.columns[i].name.clone(),
+                ));
+            }
+        }
+        table.generated = generated;
+        self.version += 1;
+        Ok(())
+    }
+
+    // 作ったばかりのテーブルの TEXT 列を、値を辞書の番号にして持つようにする
+    pub fn set_dictionary(&mut self, name: &str, column: &str) -> Result<(), Error> {
+        let table = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        let i = table
+            .column_index(column)
+            .ok_or_else(|| Error::ColumnNotFound(column.to_string()))?;
+        if table.columns[i].data_type != DataType::Text {
+            return Err(Error::DictionaryType(column.to_string()));
+        }
+        if table.dictionaries.iter().all(|d| d.column != i) {
+            table.dictionaries.push(Arc::new(Dictionary::new(i)));
+            table.dictionaries.sort_by_key(|d| d.column);
+        }
+        self.version += 1;
+        Ok(())
+    }
+
+    // 作ったばかりのテーブルに TTL を決める
+    pub fn set_ttl(&mut self, name: &str, column: &str, interval: Interval) -> Result<(), Error> {
+        let table = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        let i = table
+            .column_index(column)
+            .ok_or_else(|| Error::ColumnNotFound(column.to_string()))?;
+        if !matches!(
+            table.columns[i].data_type,
+            DataType::Timestamp | DataType::Date
+        ) {
+            return Err(Error::NotTimestampColumn(column.to_string()));
+        }
+        if interval.total_micros() <= 0 {
+            return Err(Error::InvalidTtl);
+        }
+        table.ttl = Some(Ttl {
+            column: i,
+            interval,
+        });
+        self.version += 1;
+        Ok(())
+    }
+
+    // WITH (...) や ALTER TABLE ... SET (...) のテーブルの設定を変える。値がなければ true
+    pub fn set_table_options(
+        &mut self,
+        name: &str,
+        options: &[(String, Option<String>)],
+    ) -> Result<(), Error> {
+        let table = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        let mut autovacuum = table.autovacuum;
+        for (option, value) in options {
+            if option != "autovacuum_enabled" {
+                return Err(Error::UnknownTableOption(option.clone()));
+            }
+            autovacuum = match value.as_deref().map(str::to_ascii_lowercase).as_deref() {
+                None | Some("true" | "on" | "1") => true,
+                Some("false" | "off" | "0") => false,
+                Some(_) => {
+                    return Err(Error::InvalidTableOption {
+                        option: option.clone(),
+                        value: value.clone().unwrap(),
+                    })
+                }
+            };
+        }
+        table.autovacuum = autovacuum;
+        self.version += 1;
+        Ok(())
+    }
+
+    // テーブルの最後に列を足す。パーティションに分けたテーブルなら子にも足す。
+    // 版は書き直さないので、足す前に書いた版ではその列は NULL になる
+    pub fn add_column(&mut self, name: &str, column: Column) -> Result<(), Error> {
+        let table = self
+            .table(name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        if table.partition.is_some() {
+            return Err(Error::PartitionColumn);
+        }
+        if table.column_index(&column.name).is_some() {
+            return Err(Error::DuplicateColumn(column.name));
+        }
+        let mut names: Vec<String> = self
+            .descendants(table)
+            .iter()
+            .map(|t| t.name.clone())
+            .collect();
+        names.push(name.to_string());
+        for t in self.tables.iter_mut().filter(|t| names.contains(&t.name)) {
+            t.columns.push(column.clone());
+        }
+        self.version += 1;
+        Ok(())
+    }
+
+    // 実体化したビューの問い合わせと、それが読んだテーブルを覚える
+    pub fn set_view(&mut self, name: &str, view: MaterializedView) -> Result<(), Error> {
+        let table = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        table.view = Some(view);
+        self.version += 1;
+        Ok(())
+    }
+
+    // 実体化したビューの行が、読んだテーブルの今の行と食い違っているかもしれないか
+    pub fn is_stale(&self, view: &MaterializedView) -> bool {
+        view.sources.iter().any(|(name, changes)| {
+            self.table(name)
+                .is_none_or(|table| table.changes() != *changes)
+        })
+    }
+
+    // table の TTL。パーティションの子は親の TTL に従う
+    pub fn ttl(&self, table: &Table) -> Option<Ttl> {
+        table.ttl.or_else(|| {
+            let parent = self.table(&table.partition.as_ref()?.parent)?;
+            self.ttl(parent)
+        })
+    }
+
+    // parent の子のテーブルを作る。範囲の端の値は分け方の列の型に揃える
+    pub fn create_partition(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        parent: &str,
+        bound: PartitionBound,
+    ) -> Result<&Table, Error> {
+        let p = self
+            .table(parent)
+            .ok_or_else(|| Error::TableNotFound(parent.to_string()))?;
+        let partitioning = p
+            .partitioning
+            .as_ref()
+            .ok_or_else(|| Error::NotPartitioned(parent.to_string()))?;
+        if bound.strategy() != partitioning.strategy {
+            return Err(Error::PartitionStrategyMismatch(partitioning.strategy));
+        }
+        let key = &p.columns[partitioning.column];
+        let coerce = |value: Option<Value>| value.map(|v| key.coerce(v)).transpose();
+        let bound = match bound {
+            PartitionBound::Range { from, to } => {
+                let (from, to) = (coerce(from)?, coerce(to)?);
+                if let (Some(from), Some(to)) = (&from, &to) {
+                    if from.sort_cmp(to).is_ge() {
+                        return Err(Error::EmptyRange(name.to_string()));
+                    }
+                }
+                PartitionBound::Range { from, to }
+            }
+            PartitionBound::Hash { modulus: 0, .. } => return Err(Error::InvalidModulus),
+            PartitionBound::Hash { modulus, remainder } if remainder >= modulus => {
+                return Err(Error::InvalidRemainder)
+            }
+            bound => bound,
+        };
+        let overlapping = partitioning.partitions.iter().find(|other| {
+            self.table(other)
+                .and_then(|t| t.partition.as_ref())
+                .is_some_and(|other| other.bound.overlaps(&bound))
+        });
+        if let Some(other) = overlapping {
+            return Err(Error::PartitionOverlap(name.to_string(), other.clone()));
+        }
+        let column = partitioning.column;
+        let columns = p.columns.clone();
+        let generated = p.generated.clone();
+        let dictionaries = p
+            .dictionaries
+            .iter()
+            .map(|d| Arc::new(Dictionary::new(d.column)))
+            .collect();
+        let engine = p.engine.clone();
+        self.create_table_using(bufmgr, name, columns, &engine)?;
+        let child = self.tables.last_mut().unwrap();
+        child.partition = Some(Partition {
+            parent: parent.to_string(),
+            column,
+            bound,
+        });
+        child.generated = generated;
+        child.dictionaries = dictionaries;
+        let p = self.tables.iter_mut().find(|t| t.name == parent).unwrap();
+        p.partitioning
+            .as_mut()
+            .unwrap()
+            .partitions
+            .push(name.to_string());
+        Ok(self.tables.last().unwrap())
+    }
+
+    // 行を入れるテーブル。パーティションに分けたテーブルなら、行の値が入る子を孫までたどる
+    pub fn route<'c>(&'c self, table: &'c Table, row: &[Value]) -> Result<&'c Table, Error> {
+        let Some(partitioning) = &table.partitioning else {
+            return Ok(table);
+        };
+        let value = &row[partitioning.column];
+        let child = self
+            .partitions(table)
+            .find(|child| {
+                child
+                    .partition
+                    .as_ref()
+                    .is_some_and(|p| p.bound.contains(value))
+            })
+            .ok_or_else(|| Error::NoPartition(table.name.clone()))?;
+        self.route(child, row)
+    }
+
+    // パーティションに分けたテーブルの子。分けていなければ空
+    pub fn partitions<'c>(&'c self, table: &'c Table) -> impl Iterator<Item = &'c Table> + 'c {
+        table
+            .partitioning
+            .iter()
+            .flat_map(|p| &p.partitions)
+            .filter_map(|name| self.table(name))
+    }
+
+    // 子と孫のテーブルをすべて。親より先に子が来る
+    pub fn descendants<'c>(&'c self, table: &'c Table) -> Vec<&'c Table> {
+        let mut tables = vec![];
+        for child in self.partitions(table) {
+            tables.extend(self.descendants(child));
+            tables.push(child);
+        }
+        tables
+    }
+
+    // テーブルのインデックスを、いまある版から作り直す。回復で取り除いた版を指すものを残さないのに使う
+    pub fn rebuild_indexes(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        table: &str,
+    ) -> Result<(), Error> {
+        let t = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        let indexes = std::mem::take(&mut t.indexes);
+        let columns: Vec<String> = t.columns.iter().map(|c| c.name.clone()).collect();
+        // 作り直しても定義は変わらないので、ANALYZE と同じく schema_version は変えない
+        let version = self.version;
+        for index in indexes {
+            match index.rtree {
+                Some(_) => self.create_rtree_index(
+                    bufmgr,
+                    &index.name,
+                    table,
+                    &[columns[index.columns[0]].clone()],
+                )?,
+                None => self.build_index(
+                    bufmgr,
+                    &index.name,
+                    table,
+                    index.columns,
+                    index.collations,
+                    index.expression,
+                    index.unique,
+                    index.fulltext,
+                )?,
+            };
+        }
+        self.analyzed += self.version - version;
+        Ok(())
+    }
+
+    // 既存の行からインデックスを作る
+    pub fn create_index(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        table: &str,
+        columns: &[String],
+        unique: bool,
+    ) -> Result<&Index, Error> {
+        let t = self
+            .table(table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        let columns = columns
+            .iter()
+            .map(|c| {
+                t.column_index(c)
+                    .ok_or_else(|| Error::ColumnNotFound(c.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let collations = columns.iter().map(|&i| t.columns[i].collation).collect();
+        self.build_index(bufmgr, name, table, columns, collations, None, unique, false)
+    }
+
+    // TEXT の列の語を引く全文インデックスを作る
+    pub fn create_fulltext_index(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        table: &str,
+        columns: &[String],
+    ) -> Result<&Index, Error> {
+        let t = self
+            .table(table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        let columns = columns
+            .iter()
+            .map(|c| {
+                let i = t
+                    .column_index(c)
+                    .ok_or_else(|| Error::ColumnNotFound(c.clone()))?;
+                if t.columns[i].data_type != DataType::Text {
+                    return Err(Error::NotTextColumn(c.clone()));
+                }
+                Ok(i)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let collations = vec![Collation::Binary; columns.len()];
+        self.build_index(bufmgr, name, table, columns, collations, None, false, true)
+    }
+
+    // POINT か BOX の列の R-tree のインデックスを作る。消された版は入れない
+    pub fn create_rtree_index(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        table: &str,
+        columns: &[String],
+    ) -> Result<&Index, Error> {
+        if self.tables.iter().any(|t| t.index(name).is_some()) {
+            return Err(Error::IndexExists(name.to_string()));
+        }
+        let t = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        if t.partitioning.is_some() {
+            return Err(Error::PartitionedIndex(table.to_string()));
+        }
+        let [column] = columns else {
+            return Err(Error::RTreeColumnCount);
+        };
+        let i = t
+            .column_index(column)
+            .ok_or_else(|| Error::ColumnNotFound(column.clone()))?;
+        if !matches!(t.columns[i].data_type, DataType::Point | DataType::Box) {
+            return Err(Error::NotGeometricColumn(column.clone()));
+        }
+        if t.virtual_columns().any(|g| g.column == i) {
+            return Err(Error::VirtualColumnIndex(column.clone()));
+        }
+        let rtree = RTree::create(bufmgr)?;
+        let mut scan = t.storage.scan();
+        while let Some((rid, header, bytes)) = scan.next(bufmgr)? {
+            if header.is_deleted() {
+                continue;
+            }
+            let row = t.decode(&bytes).ok_or(Error::CorruptedTuple(rid))?;
+            if let Some(rect) = row[i].rect() {
+                rtree.insert(bufmgr, rect, rid)?;
+            }
+        }
+        t.indexes.push(Index {
+            name: name.to_string(),
+            columns: vec![i],
+            collations: vec![Collation::Binary],
+            expression: None,
+            unique: false,
+            fulltext: false,
+            btree: BTree::create(bufmgr)?,
+            rtree: Some(rtree),
+        });
+        self.version += 1;
+        Ok(t.indexes.last().unwrap())
+    }
+
+    // 行で計算した expr の値をキーにするインデックスを作る。expr はテーブルの行に対する式
+    pub fn create_expression_index(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        table: &str,
+        expr: Expr,
+        unique: bool,
+    ) -> Result<&Index, Error> {
+        self.build_index(bufmgr, name, table, vec![], vec![], Some(expr), unique, false)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_index(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        table: &str,
+        columns: Vec<usize>,
+        collations: Vec<Collation>,
+        expression: Option<Expr>,
+        unique: bool,
+        fulltext: bool,
+    ) -> Result<&Index, Error> {
+        if self.tables.iter().any(|t| t.index(name).is_some()) {
+            return Err(Error::IndexExists(name.to_string()));
+        }
+        let table = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        // 親のテーブルは行を持たないので、インデックスは子のテーブルごとに作る
+        if table.partitioning.is_some() {
+            return Err(Error::PartitionedIndex(table.name.clone()));
+        }
+        // VIRTUAL の列の値はヒープにないので、キーにできない
+        let mut referenced = columns.clone();
+        if let Some(expr) = &expression {
+            expr.collect_columns(&mut referenced);
+        }
+        if let Some(g) = table
+            .virtual_columns()
+            .find(|g| referenced.contains(&g.column))
+        {
+            return Err(Error::VirtualColumnIndex(
+                table.columns[g.column].name.clone(),
+            ));
+        }
+        // キーの値と RecordId の順に並べ替えてから、葉を左から順に埋める
+        let mut sorter = Sorter::new(
+            compare_values,
+            DEFAULT_WORK_MEM,
+            MemoryBudget::unlimited().reservation(),
+        );
+        // 消された版は一意かどうかを調べるときに邪魔になるので入れない
+        let mut scan = table.storage.scan();
+        let mut width = 0;
+        while let Some((rid, header, bytes)) = scan.next(bufmgr)? {
+            if header.is_deleted() {
+                continue;
+            }
+            let row = table.decode(&bytes).ok_or(Error::CorruptedTuple(rid))?;
+            let entries = entry_values(&columns, &collations, expression.as_ref(), fulltext, &row)?;
+            for mut key in entries {
+                width = key.len();
+                key.push(Value::Integer(rid.page_id.to_u64() as i64));
+                key.push(Value::Integer(rid.slot as i64));
+                sorter.push(key, vec![])?;
+            }
+        }
+        let mut sorted = sorter.finish()?;
+        let mut loader = BulkLoader::new(bufmgr)?;
+        let mut last_prefix: Option<Vec<u8>> = None;
+        while let Some((key, _)) = sorted.next()? {
+            let (values, rid) = key.split_at(width);
+            let [Value::Integer(page_id), Value::Integer(slot)] = rid else {
+                unreachable!();
+            };
+            let mut key = vec![];
+            btree::key::encode(values, &mut key);
+            // 並べ替えたので同じ値は隣り合う
+            if unique && !values.iter().any(Value::is_null) && last_prefix.as_ref() == Some(&key) {
+                return Err(Error::UniqueViolation(name.to_string()));
+            }
+            let prefix_len = key.len();
+            let rid = RecordId {
+                page_id: PageId(*page_id as u64),
+                slot: *slot as u16,
+            };
+            encode_record_id(rid, &mut key);
+            loader.push(bufmgr, &key, &[])?;
+            key.truncate(prefix_len);
+            last_prefix = Some(key);
+        }
+        let index = Index {
+            name: name.to_string(),
+            columns,
+            collations,
+            expression,
+            unique,
+            fulltext,
+            btree: loader.finish(bufmgr)?,
+            rtree: None,
+        };
+        table.indexes.push(index);
+        self.version += 1;
+        Ok(table.indexes.last().unwrap())
+    }
+
+    // ページは解放せずにカタログから外すだけ。パーティションに分けたテーブルは子も外し、
+    // 子を外すときは親の子の一覧からも除く
+    pub fn drop_table(&mut self, name: &str) -> Result<Table, Error> {
+        let table = self
+            .table(name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        let children: Vec<String> = self
+            .descendants(table)
+            .iter()
+            .map(|t| t.name.clone())
+            .collect();
+        self.tables.retain(|t| !children.contains(&t.name));
+        self.grants
+            .retain(|g| g.table != name && !children.contains(&g.table));
+        let pos = self.tables.iter().position(|t| t.name == name).unwrap();
+        let table = self.tables.remove(pos);
+        if let Some(partition) = &table.partition {
+            let parent = self.tables.iter_mut().find(|t| t.name == partition.parent);
+            if let Some(partitioning) = parent.and_then(|t| t.partitioning.as_mut()) {
+                partitioning.partitions.retain(|child| child != name);
+            }
+        }
+        self.version += 1;
+        Ok(table)
+    }
+
+    // テーブルの統計を集め直す。table が None ならすべてのテーブル
+    pub fn analyze(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        table: Option<&str>,
+    ) -> Result<(), Error> {
+        if let Some(name) = table {
+            if self.table(name).is_none() {
+                return Err(Error::TableNotFound(name.to_string()));
+            }
+        }
+        for t in &mut self.tables {
+            if table.is_some_and(|name| name != t.name) {
+                continue;
+            }
+            let stats = TableStats::collect(t, bufmgr)?;
+            t.rows.set(stats.rows);
+            t.stats = Some(stats);
+            t.analyzed_changes.set(t.changes.get());
+        }
+        self.version += 1;
+        self.analyzed += 1;
+        Ok(())
+    }
+
+    // 誰からも見えなくなった版と、TTL の期限が切れた行を取り除く。table が None ならすべてのテーブル。
+    // パーティションに分けたテーブルなら子のテーブルも。skip が真になるテーブルは飛ばす
+    pub fn vacuum(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        table: Option<&str>,
+        horizon: TxnId,
+        skip: impl Fn(&str) -> bool,
+    ) -> Result<usize, Error> {
+        let tables = match table {
+            Some(name) => {
+                let t = self
+                    .table(name)
+                    .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+                let mut tables = self.descendants(t);
+                tables.push(t);
+                tables
+            }
+            None => self.tables.iter().collect(),
+        };
+        let now = Timestamp::now();
+        let mut removed = 0;
+        for t in tables.into_iter().filter(|t| !skip(&t.name)) {
+            let expire = self
+                .ttl(t)
+                .and_then(|ttl| Some((ttl, ttl.cutoff(now)?)));
+            removed += t.vacuum(bufmgr, horizon, expire)?;
+        }
+        Ok(removed)
+    }
+
+    // SQL から呼べる関数を登録する。組み込みの関数や集約関数と同じ名前は使えない
+    pub fn create_function(&mut self, func: ScalarFunction) -> Result<(), Error> {
+        let name = func.name();
+        if Function::lookup(name).is_some()
+            || AggregateFunction::lookup(name).is_some()
+            || self.function(name).is_some()
+        {
+            return Err(Error::FunctionExists(name.to_string()));
+        }
+        self.functions.push(Arc::new(func));
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Arc<ScalarFunction>> {
+        self.functions.iter().find(|f| f.name() == name)
+    }
+
+    // CREATE TABLE ... USING で選べるようにする
+    pub fn register_storage_engine(
+        &mut self,
+        engine: Arc<dyn StorageEngine>,
+    ) -> Result<(), Error> {
+        if self.storage_engine(engine.name()).is_some() {
+            return Err(Error::StorageEngineExists(engine.name().to_string()));
+        }
+        self.storage_engines.push(engine);
+        Ok(())
+    }
+
+    pub fn storage_engine(&self, name: &str) -> Option<Arc<dyn StorageEngine>> {
+        match name {
+            storage::DEFAULT_STORAGE_ENGINE => return Some(Arc::new(storage::Heap)),
+            storage::LSM_STORAGE_ENGINE => return Some(Arc::new(storage::Lsm)),
+            _ => {}
+        }
+        self.storage_engines.iter().find(|e| e.name() == name).cloned()
+    }
+
+    // EXECUTE FUNCTION で呼べる関数を登録する
+    pub fn create_trigger_function(&mut self, func: TriggerFunction) -> Result<(), Error> {
+        if self.trigger_function(func.name()).is_some() {
+            return Err(Error::FunctionExists(func.name().to_string()));
+        }
+        self.trigger_functions.push(Arc::new(func));
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn trigger_function(&self, name: &str) -> Option<&Arc<TriggerFunction>> {
+        self.trigger_functions.iter().find(|f| f.name() == name)
+    }
+
+    pub fn create_trigger(&mut self, table: &str, trigger: Trigger) -> Result<(), Error> {
+        let t = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        if t.triggers.iter().any(|tr| tr.name == trigger.name) {
+            return Err(Error::TriggerExists(trigger.name, table.to_string()));
+        }
+        t.triggers.push(trigger);
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn drop_trigger(&mut self, table: &str, name: &str) -> Result<(), Error> {
+        let t = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        let pos = t
+            .triggers
+            .iter()
+            .position(|tr| tr.name == name)
+            .ok_or_else(|| Error::TriggerNotFound(name.to_string(), table.to_string()))?;
+        t.triggers.remove(pos);
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn create_role(&mut self, role: Role) -> Result<(), Error> {
+        if self.role(&role.name).is_some() {
+            return Err(Error::RoleExists(role.name));
+        }
+        self.roles.push(role);
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn alter_role(&mut self, name: &str, alter: impl FnOnce(&mut Role)) -> Result<(), Error> {
+        let role = self
+            .roles
+            .iter_mut()
+            .find(|r| r.name == name)
+            .ok_or_else(|| Error::RoleNotFound(name.to_string()))?;
+        alter(role);
+        self.version += 1;
+        Ok(())
+    }
+
+    // 権限を与えられたままのロールは消せない
+    pub fn drop_role(&mut self, name: &str) -> Result<(), Error> {
+        let pos = self
+            .roles
+            .iter()
+            .position(|r| r.name == name)
+            .ok_or_else(|| Error::RoleNotFound(name.to_string()))?;
+        if self.grants.iter().any(|g| g.role == name) {
+            return Err(Error::RoleHasPrivileges(name.to_string()));
+        }
+        self.roles.remove(pos);
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn create_enum_type(&mut self, name: &str, labels: &[String]) -> Result<(), Error> {
+        if self.enum_type(name).is_some() || DataType::parse(name).is_some() {
+            return Err(Error::TypeExists(name.to_string()));
+        }
+        let ty = EnumType::new(self.next_type_id, name, labels).map_err(Error::EnumLabelExists)?;
+        self.next_type_id += 1;
+        self.types.push(ty);
+        self.version += 1;
+        Ok(())
+    }
+
+    // 型に label を足す。position は EnumType::add_label と同じ。if_not_exists なら、既にあるラベルは何もしない。
+    // 順位を振り直したら、その型の列を持つテーブルのインデックスを作り直す
+    pub fn add_enum_label(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        name: &str,
+        label: &str,
+        position: Option<(bool, &str)>,
+        if_not_exists: bool,
+    ) -> Result<(), Error> {
+        let ty = self
+            .enum_type(name)
+            .ok_or_else(|| Error::TypeNotFound(name.to_string()))?;
+        if ty.value(label).is_some() {
+            if if_not_exists {
+                return Ok(());
+            }
+            return Err(Error::EnumLabelExists(label.to_string()));
+        }
+        if let Some((_, other)) = position {
+            if ty.value(other).is_none() {
+                return Err(Error::EnumLabelNotFound(other.to_string()));
+            }
+        }
+        if ty.add_label(label, position) {
+            let ty = DataType::Enum(ty);
+            let tables = self
+                .tables
+                .iter()
+                .filter(|t| t.columns.iter().any(|c| c.data_type == ty))
+                .map(|t| t.name.clone())
+                .collect::<Vec<_>>();
+            for table in tables {
+                self.rebuild_indexes(bufmgr, &table)?;
+            }
+        }
+        self.version += 1;
+        Ok(())
+    }
+
+    // 列の型に使っている型は消せない。消した型の番号は振り直さない
+    pub fn drop_type(&mut self, name: &str) -> Result<(), Error> {
+        let pos = self
+            .types
+            .iter()
+            .position(|t| t.name == name)
+            .ok_or_else(|| Error::TypeNotFound(name.to_string()))?;
+        let ty = DataType::Enum(self.types[pos].clone());
+        if self
+            .tables
+            .iter()
+            .any(|t| t.columns.iter().any(|c| c.data_type == ty))
+        {
+            return Err(Error::TypeInUse(name.to_string()));
+        }
+        self.types.remove(pos);
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn enum_type(&self, name: &str) -> Option<Arc<EnumType>> {
+        self.types.iter().find(|t| t.name == name).cloned()
+    }
+
+    pub fn enum_types(&self) -> &[Arc<EnumType>] {
+        &self.types
+    }
+
+    pub fn next_type_id(&self) -> u32 {
+        self.next_type_id
+    }
+
+    // ファイルに残した型を、番号を変えずに戻す。テーブルを作る前に使う
+    pub fn restore_enum_types(&mut self, types: Vec<Arc<EnumType>>, next_type_id: u32) {
+        self.types = types;
+        self.next_type_id = next_type_id;
+        self.version += 1;
+    }
+
+    pub fn role(&self, name: &str) -> Option<&Role> {
+        self.roles.iter().find(|r| r.name == name)
+    }
+
+    pub fn roles(&self) -> &[Role] {
+        &self.roles
+    }
+
+    // すでに持っている権限を与えても何もしない
+    pub fn grant(&mut self, table: &str, role: &str, privilege: Privilege) -> Result<(), Error> {
+        if self.table(table).is_none() {
+            return Err(Error::TableNotFound(table.to_string()));
+        }
+        if self.role(role).is_none() {
+            return Err(Error::RoleNotFound(role.to_string()));
+        }
+        if !self.has_grant(table, role, privilege) {
+            self.grants.push(TablePrivilege {
+                table: table.to_string(),
+                role: role.to_string(),
+                privilege,
+            });
+        }
+        self.version += 1;
+        Ok(())
+    }
+
+    // 持っていない権限を取り上げても何もしない
+    pub fn revoke(&mut self, table: &str, role: &str, privilege: Privilege) -> Result<(), Error> {
+        if self.table(table).is_none() {
+            return Err(Error::TableNotFound(table.to_string()));
+        }
+        if self.role(role).is_none() {
+            return Err(Error::RoleNotFound(role.to_string()));
+        }
+        self.grants
+            .retain(|g| !(g.table == table && g.role == role && g.privilege == privilege));
+        self.version += 1;
+        Ok(())
+    }
+
+    fn has_grant(&self, table: &str, role: &str, privilege: Privilege) -> bool {
+        self.grants
+            .iter()
+            .any(|g| g.table == table && g.role == role && g.privilege == privilege)
+    }
+
+    // スーパーユーザーはどのテーブルにもすべての権限を持つ
+    pub fn has_privilege(&self, role: &str, table: &str, privilege: Privilege) -> bool {
+        self.role(role).is_some_and(|r| r.superuser) || self.has_grant(table, role, privilege)
+    }
+
+    pub fn grants(&self) -> &[TablePrivilege] {
+        &self.grants
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // version から ANALYZE の分を除いたもの。テーブルの定義や権限が変われば変わる
+    pub fn schema_version(&self) -> u64 {
+        self.version - self.analyzed
+    }
+
+    // トランザクションの中で変えた写しを、写したときに committed だったカタログの代わりにする。
+    // committed は写してからほかの接続がテーブルの定義を変えていないもの。版は committed から進める
+    pub fn replace(&mut self, committed: &Catalog, base: u64) {
+        self.merge_counters(committed);
+        let changes = (self.schema_version() - base).max(1);
+        self.version = committed.version + changes;
+        self.analyzed = committed.analyzed;
+    }
+
+    // other で数えた行の数などを、同じ行の版を持つテーブルに移す
+    pub fn merge_counters(&self, other: &Catalog) {
+        for table in &self.tables {
+            if let Some(old) = other.table(&table.name) {
+                if Arc::ptr_eq(&old.storage, &table.storage) {
+                    table.merge_counters(old);
+                }
+            }
+        }
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    // ファイルから読み戻したテーブルを、ファイルにある行の版とインデックスのページにつなぎ直す。
+    // indexes はインデックスの名前と、そのページ。ないインデックスはそのままにする
+    pub fn attach_storage(
+        &mut self,
+        name: &str,
+        storage: Arc<dyn TableAccess>,
+        indexes: &[(String, BTree, Option<RTree>)],
+    ) -> Result<(), Error> {
+        let table = self
+            .tables
+            .iter_mut()
+            .find(|t| t.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        table.storage = storage;
+        for (name, btree, rtree) in indexes {
+            if let Some(index) = table.indexes.iter_mut().find(|i| &i.name == name) {
+                index.btree = *btree;
+                index.rtree = *rtree;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_bufmgr;
+
+    fn column(name: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type: DataType::Integer,
+            not_null: false,
+            collation: Collation::Binary,
+        }
+    }
+
+    #[test]
+    fn test_create_drop_table() {
+        let mut bufmgr = temp_bufmgr(4);
+        let mut catalog = Catalog::new();
+        let table = catalog
+            .create_table(&mut bufmgr, "t", vec![column("a"), column("b")])
+            .unwrap();
+        assert_eq!(table.column_index("b"), Some(1));
+        assert!(matches!(
+            catalog.create_table(&mut bufmgr, "t", vec![]),
+            Err(Error::TableExists(_))
+        ));
+        assert!(matches!(
+            catalog.create_table(&mut bufmgr, "u", vec![column("a"), column("a")]),
+            Err(Error::DuplicateColumn(_))
+        ));
+        catalog.drop_table("t").unwrap();
+        assert!(catalog.table("t").is_none());
+        assert!(matches!(
+            catalog.drop_table("t"),
+            Err(Error::TableNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_index() {
+        let mut bufmgr = temp_bufmgr(8);
+        let mut catalog = Catalog::new();
+        catalog
+            .create_table(&mut bufmgr, "t", vec![column("a"), column("b")])
+            .unwrap();
+        let row = |a: i64, b: i64| vec![Value::Integer(a), Value::Integer(b)];
+        let table = catalog.table("t").unwrap();
+        table
+            .insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, &row(1, 10))
+            .unwrap();
+        table
+            .insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, &row(2, 10))
+            .unwrap();
+        assert!(matches!(
+            catalog.create_index(&mut bufmgr, "t_b", "t", &["b".to_string()], true),
+            Err(Error::UniqueViolation(_))
+        ));
+        assert!(matches!(
+            catalog.create_index(&mut bufmgr, "t_c", "t", &["c".to_string()], false),
+            Err(Error::ColumnNotFound(_))
+        ));
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], true)
+            .unwrap();
+        let table = catalog.table("t").unwrap();
+        assert!(matches!(
+            table.insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, &row(1, 20)),
+            Err(Error::UniqueViolation(_))
+        ));
+        // NULL は何度でも入れられる
+        let null = vec![Value::Null, Value::Integer(0)];
+        table
+            .insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, &null)
+            .unwrap();
+        table
+            .insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, &null)
+            .unwrap();
+        let rid = table
+            .insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, &row(3, 30))
+            .unwrap();
+        assert_eq!(table.row_count(), 5);
+
+        let index = table.index("t_a").unwrap();
+        let prefix = index.key_prefix(&row(3, 0)).unwrap();
+        let mut scan = index.btree.scan(&mut bufmgr, Some(&prefix)).unwrap();
+        let (key, _) = scan.next(&mut bufmgr).unwrap().unwrap();
+        assert!(key.starts_with(&prefix));
+        assert_eq!(decode_record_id(&key), rid);
+    }
+
+    #[test]
+    fn test_update_delete_versions() {
+        let mut bufmgr = temp_bufmgr(8);
+        let mut catalog = Catalog::new();
+        catalog
+            .create_table(&mut bufmgr, "t", vec![column("a"), column("b")])
+            .unwrap();
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], true)
+            .unwrap();
+        let row = |a: i64, b: i64| vec![Value::Integer(a), Value::Integer(b)];
+        let table = catalog.table("t").unwrap();
+        let index = table.index("t_a").unwrap();
+        let rid = table.insert(&mut bufmgr, TxnId(2), &row(1, 10)).unwrap();
+
+        // 書き換えると古い版に xmax がつき、新しい版が入る
+        let new_rid = table
+            .update(&mut bufmgr, TxnId(3), rid, &row(1, 10), &row(1, 20))
+            .unwrap()
+            .unwrap();
+        assert_ne!(new_rid, rid);
+        let (header, _) = table.storage.get(&mut bufmgr, rid).unwrap().unwrap();
+        assert_eq!((header.xmin, header.xmax), (TxnId(2), TxnId(3)));
+        let (header, _) = table.storage.get(&mut bufmgr, new_rid).unwrap().unwrap();
+        assert_eq!(
+            (header.xmin, header.xmax),
+            (TxnId(3), TxnId::INVALID_TXN_ID)
+        );
+        assert_eq!(
+            table
+                .update(&mut bufmgr, TxnId(4), rid, &row(1, 10), &row(1, 30))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            index.find(&mut bufmgr, &*table.storage, &row(1, 0)).unwrap(),
+            Some(new_rid)
+        );
+        assert_eq!(table.row_count(), 1);
+
+        // 消しても版は残り、一意インデックスは同じ値を入れられるようになる
+        assert!(table.delete(&mut bufmgr, TxnId(4), new_rid).unwrap());
+        assert!(!table.delete(&mut bufmgr, TxnId(5), new_rid).unwrap());
+        assert!(table.storage.get(&mut bufmgr, new_rid).unwrap().is_some());
+        assert_eq!(
+            index.find(&mut bufmgr, &*table.storage, &row(1, 0)).unwrap(),
+            None
+        );
+        assert_eq!(table.row_count(), 0);
+        let rid = table.insert(&mut bufmgr, TxnId(5), &row(1, 40)).unwrap();
+
+        // 取り消すときは入れた版を取り除き、消した版を戻す
+        table.remove(&mut bufmgr, rid, &row(1, 40)).unwrap();
+        table.restore(&mut bufmgr, new_rid).unwrap();
+        assert_eq!(table.storage.get(&mut bufmgr, rid).unwrap(), None);
+        assert_eq!(
+            index.find(&mut bufmgr, &*table.storage, &row(1, 0)).unwrap(),
+            Some(new_rid)
+        );
+        assert_eq!(table.row_count(), 1);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut bufmgr = temp_bufmgr(16);
+        let mut catalog = Catalog::new();
+        catalog
+            .create_table(&mut bufmgr, "t", vec![column("a"), column("b")])
+            .unwrap();
+        catalog.create_table(&mut bufmgr, "u", vec![]).unwrap();
+        let table = catalog.table("t").unwrap();
+        for i in 0..10000 {
+            let b = if i % 4 == 0 {
+                Value::Null
+            } else {
+                Value::Integer(i % 50)
+            };
+            table
+                .insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, &[Value::Integer(i), b])
+                .unwrap();
+        }
+        assert!(matches!(
+            catalog.analyze(&mut bufmgr, Some("v")),
+            Err(Error::TableNotFound(_))
+        ));
+        catalog.analyze(&mut bufmgr, Some("t")).unwrap();
+        assert!(catalog.table("u").unwrap().stats.is_none());
+        let stats = catalog.table("t").unwrap().stats.as_ref().unwrap();
+        assert_eq!(stats.rows, 10000);
+
+        let a = &stats.columns[0];
+        assert_eq!(a.null_fraction, 0.0);
+        assert!(a.distinct > 5000.0, "{}", a.distinct);
+        assert_eq!(a.histogram.len(), HISTOGRAM_BUCKETS + 1);
+        let below = a.below_fraction(&Value::Integer(2500)).unwrap();
+        assert!((below - 0.25).abs() < 0.05, "{}", below);
+
+        let b = &stats.columns[1];
+        assert!((b.null_fraction - 0.25).abs() < 0.05);
+        assert!((b.distinct - 50.0).abs() < 5.0, "{}", b.distinct);
+        assert_eq!(b.min, Some(Value::Integer(0)));
+        assert_eq!(b.max, Some(Value::Integer(49)));
+        assert_eq!(b.eq_fraction(&Value::Integer(60)), 0.0);
+        assert!((b.eq_fraction(&Value::Integer(7)) - 0.015).abs() < 0.005);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::enum_type::EnumType;
+use crate::executor::Row;
+use crate::tuple;
+use crate::types::Value;
+
+// 辞書に入れる値の数の上限。これより後に現れた値は文字列のまま行に入れる
+pub const MAX_DICTIONARY_SIZE: usize = 1 << 16;
+
+// COMPRESSION dictionary の TEXT 列の辞書
+//
+// 新しい値に現れた順に番号を振り、行には値の代わりにその番号を入れる。番号は振り直さず、
+// 値を持つ行がなくなっても辞書からは消さない。同じ値はいつも同じ番号なので、列と文字列の等号は
+// 文字列に戻さずに番号で比べられる。ただし辞書が一杯になってから現れた値は番号を持たない
+#[derive(Debug)]
+pub struct Dictionary {
+    pub column: usize,
+    entries: RwLock<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    values: Vec<String>,
+    codes: HashMap<String, u32>,
+}
+
+impl Dictionary {
+    pub fn new(column: usize) -> Self {
+        Self {
+            column,
+            entries: RwLock::default(),
+        }
+    }
+
+    pub fn code(&self, value: &str) -> Option<u32> {
+        self.entries.read().unwrap().codes.get(value).copied()
+    }
+
+    // 値の番号。辞書になければ番号を振る。辞書が一杯なら None
+    pub fn intern(&self, value: &str) -> Option<u32> {
+        if let Some(code) = self.code(value) {
+            return Some(code);
+        }
+        let entries = &mut *self.entries.write().unwrap();
+        if let Some(&code) = entries.codes.get(value) {
+            return Some(code);
+        }
+        if entries.values.len() >= MAX_DICTIONARY_SIZE {
+            return None;
+        }
+        let code = entries.values.len() as u32;
+        entries.values.push(value.to_string());
+        entries.codes.insert(value.to_string(), code);
+        Some(code)
+    }
+
+    pub fn value(&self, code: u32) -> Option<String> {
+        self.entries
+            .read()
+            .unwrap()
+            .values
+            .get(code as usize)
+            .cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= MAX_DICTIONARY_SIZE
+    }
+}
+
+fn find(dictionaries: &[Arc<Dictionary>], column: usize) -> Option<&Dictionary> {
+    dictionaries
+        .iter()
+        .find(|d| d.column == column)
+        .map(|d| &**d)
+}
+
+// 辞書のある列の値を番号にして行をエンコードする
+pub fn encode_row(dictionaries: &[Arc<Dictionary>], row: &[Value], bytes: &mut Vec<u8>) {
+    tuple::encode_with(row, bytes, |i, s| find(dictionaries, i)?.intern(s));
+}
+
+// 行を復元する。needed は tuple::decode_columns と同じで、None ならすべての列。enums は列の列挙型
+pub fn decode_row(
+    dictionaries: &[Arc<Dictionary>],
+    enums: &[Arc<EnumType>],
+    bytes: &[u8],
+    needed: Option<&[bool]>,
+) -> Option<Row> {
+    tuple::decode_dictionary(bytes, needed, enums, |i, code| {
+        find(dictionaries, i)?.value(code)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dictionary() {
+        let dictionaries = vec![Arc::new(Dictionary::new(1))];
+        let text = |s: &str| Value::Text(s.to_string());
+        let mut encoded = vec![];
+        for row in [
+            vec![text("a"), text("red")],
+            vec![text("b"), text("blue")],
+            vec![text("c"), text("red")],
+        ] {
+            let mut bytes = vec![];
+            encode_row(&dictionaries, &row, &mut bytes);
+            assert_eq!(decode_row(&dictionaries, &[], &bytes, None), Some(row));
+            encoded.push(bytes);
+        }
+        let dictionary = &dictionaries[0];
+        assert_eq!(dictionary.len(), 2);
+        assert_eq!(
+            encoded
+                .iter()
+                .map(|bytes| tuple::column_code(bytes, 1))
+                .collect::<Vec<_>>(),
+            [Some(0), Some(1), Some(0)]
+        );
+        // 辞書のない列は文字列のまま入れる
+        assert_eq!(tuple::column_code(&encoded[0], 0), None);
+        assert_eq!(dictionary.code("green"), None);
+        assert_eq!(decode_row(&[], &[], &encoded[0], None), None);
+    }
+}
@@ -0,0 +1,37 @@
+use crate::executor::expr::Expr;
+use crate::executor::{self, Row};
+
+// GENERATED ALWAYS AS (expr) の列
+//
+// 式はテーブルの行に対するもので、列の型への CAST まで含む。ほかの生成列は参照しない。
+// 行を書くたびに coerce で計算し直すので、INSERT や UPDATE で値を与えることはできない。
+// STORED の列は計算した値をヒープに持ち、インデックスも作れる。
+// VIRTUAL の列はヒープには NULL を持ち、テーブルを読む演算子が行を復元するときに計算する
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedColumn {
+    pub column: usize,
+    pub expr: Expr,
+    pub stored: bool,
+}
+
+impl GeneratedColumn {
+    // 式が参照する列の位置
+    pub fn dependencies(&self) -> Vec<usize> {
+        let mut columns = vec![];
+        self.expr.collect_columns(&mut columns);
+        columns.sort_unstable();
+        columns.dedup();
+        columns
+    }
+}
+
+// ヒープから復元した行に VIRTUAL の列の値を入れる
+pub fn fill_virtual<'a>(
+    columns: impl IntoIterator<Item = &'a GeneratedColumn>,
+    row: &mut Row,
+) -> Result<(), executor::Error> {
+    for generated in columns {
+        row[generated.column] = generated.expr.eval(row)?;
+    }
+    Ok(())
+}
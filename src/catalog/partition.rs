@@ -0,0 +1,136 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::sql::ast::PartitionStrategy;
+use crate::types::Value;
+
+// パーティションに分けたテーブル
+//
+// 親のテーブルは行を持たず、行は column の値で partitions の子のテーブルのどれかに入る。
+// 子はそれぞれ自分のヒープとインデックスを持つふつうのテーブルで、値の範囲を partition_of に持つ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partitioning {
+    pub strategy: PartitionStrategy,
+    pub column: usize,
+    // 子のテーブルの名前。作った順
+    pub partitions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartitionBound {
+    // from 以上 to 未満。None ならその側に端がない。NULL はどの範囲にも入らない
+    Range {
+        from: Option<Value>,
+        to: Option<Value>,
+    },
+    // 値のハッシュを modulus で割った余りが remainder
+    Hash {
+        modulus: u64,
+        remainder: u64,
+    },
+}
+
+impl PartitionBound {
+    pub fn strategy(&self) -> PartitionStrategy {
+        match self {
+            PartitionBound::Range { .. } => PartitionStrategy::Range,
+            PartitionBound::Hash { .. } => PartitionStrategy::Hash,
+        }
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        match self {
+            PartitionBound::Range { from, to } => {
+                !value.is_null()
+                    && from
+                        .as_ref()
+                        .is_none_or(|from| value.sort_cmp(from).is_ge())
+                    && to.as_ref().is_none_or(|to| value.sort_cmp(to).is_lt())
+            }
+            PartitionBound::Hash { modulus, remainder } => {
+                partition_hash(value) % modulus == *remainder
+            }
+        }
+    }
+
+    // どちらにも入る値がありうるか
+    pub fn overlaps(&self, other: &PartitionBound) -> bool {
+        match (self, other) {
+            (
+                PartitionBound::Range { from: a, to: b },
+                PartitionBound::Range { from: c, to: d },
+            ) => below(a.as_ref(), d.as_ref()) && below(c.as_ref(), b.as_ref()),
+            // 余りが最大公約数を法として等しければ、両方の余りになる値がある
+            (
+                PartitionBound::Hash {
+                    modulus: m,
+                    remainder: r,
+                },
+                PartitionBound::Hash {
+                    modulus: n,
+                    remainder: s,
+                },
+            ) => {
+                let g = gcd(*m, *n);
+                r % g == s % g
+            }
+            _ => false,
+        }
+    }
+}
+
+// 下限 from が上限 to より下にあるか
+fn below(from: Option<&Value>, to: Option<&Value>) -> bool {
+    match (from, to) {
+        (Some(from), Some(to)) => from.sort_cmp(to).is_lt(),
+        _ => true,
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// ハッシュのパーティションを決めるハッシュ。値が同じとみなす数値の型どうしは同じになる
+pub fn partition_hash(value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_bound() {
+        let range = |from: Option<i64>, to: Option<i64>| PartitionBound::Range {
+            from: from.map(Value::Integer),
+            to: to.map(Value::Integer),
+        };
+        let low = range(None, Some(10));
+        assert!(low.contains(&Value::Integer(-100)) && low.contains(&Value::Integer(9)));
+        assert!(!low.contains(&Value::Integer(10)) && !low.contains(&Value::Null));
+        assert!(low.overlaps(&range(Some(9), Some(20))));
+        assert!(!low.overlaps(&range(Some(10), None)));
+        assert!(range(Some(0), None).overlaps(&range(None, Some(1))));
+
+        let hash = |modulus, remainder| PartitionBound::Hash { modulus, remainder };
+        let hits = (0..100)
+            .filter(|&i| hash(4, 1).contains(&Value::Integer(i)))
+            .count();
+        assert!(hits > 10 && hits < 40, "{}", hits);
+        assert_eq!(
+            partition_hash(&Value::Integer(3)),
+            partition_hash(&Value::BigInt(3))
+        );
+        // 4 で割って 1 余るものは 2 で割っても 1 余る
+        assert!(hash(4, 1).overlaps(&hash(2, 1)));
+        assert!(!hash(4, 1).overlaps(&hash(2, 0)));
+        assert!(!hash(4, 1).overlaps(&range(None, None)));
+    }
+}
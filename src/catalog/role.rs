@@ -0,0 +1,110 @@
+use crate::sha256::sha256;
+use crate::sql::ast::Privilege;
+use crate::uuid::Uuid;
+
+// データベースを使う人
+//
+// superuser はどの権限も持ち、DDL や GRANT を実行できる。login のないロールではつながれない。
+// パスワードはそのまま覚えず、ロールごとの塩をつけた SHA-256 のハッシュにして持つ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Role {
+    pub name: String,
+    pub superuser: bool,
+    pub login: bool,
+    password: Option<Password>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Password {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+impl Password {
+    fn hash(salt: &[u8; 16], password: &str) -> [u8; 32] {
+        let mut data = salt.to_vec();
+        data.extend_from_slice(password.as_bytes());
+        sha256(&data)
+    }
+}
+
+impl Role {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            superuser: false,
+            login: false,
+            password: None,
+        }
+    }
+
+    pub fn set_password(&mut self, password: Option<&str>) {
+        self.password = password.map(|password| {
+            let salt = *Uuid::new_v4().as_bytes();
+            Password {
+                hash: Password::hash(&salt, password),
+                salt,
+            }
+        });
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password.is_some()
+    }
+
+    // ファイルに残すための塩とハッシュ
+    pub fn password_hash(&self) -> Option<([u8; 16], [u8; 32])> {
+        self.password.as_ref().map(|p| (p.salt, p.hash))
+    }
+
+    // ファイルに残した塩とハッシュを戻す
+    pub fn restore_password(&mut self, salt: [u8; 16], hash: [u8; 32]) {
+        self.password = Some(Password { salt, hash });
+    }
+
+    // パスワードのないロールはどのパスワードでも通らない
+    pub fn check_password(&self, password: &str) -> bool {
+        let Some(p) = &self.password else {
+            return false;
+        };
+        // どこで食い違ったかが時間でわからないよう、すべてのバイトを比べる
+        Password::hash(&p.salt, password)
+            .iter()
+            .zip(&p.hash)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+    }
+}
+
+// GRANT で role に与えた table への権限
+#[derive(Debug, Clone, PartialEq)]
+pub struct TablePrivilege {
+    pub table: String,
+    pub role: String,
+    pub privilege: Privilege,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password() {
+        let mut role = Role::new("alice");
+        assert!(!role.check_password(""));
+        role.set_password(Some("secret"));
+        assert!(role.check_password("secret"));
+        assert!(!role.check_password("Secret") && !role.check_password(""));
+        // 同じパスワードでも塩が違うのでハッシュは変わる
+        let mut other = Role::new("alice");
+        other.set_password(Some("secret"));
+        assert_ne!(role, other);
+        let (salt, hash) = other.password_hash().unwrap();
+        let mut restored = Role::new("alice");
+        restored.restore_password(salt, hash);
+        assert_eq!(restored, other);
+        assert!(restored.check_password("secret"));
+        role.set_password(None);
+        assert!(!role.check_password("secret"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use crate::buffer::BufferPoolManager;
+use crate::types::Value;
+
+use super::{Error, Table};
+
+// ANALYZE で読む行の数の上限と、ヒストグラムの区間の数
+pub const SAMPLE_ROWS: usize = 3000;
+pub const HISTOGRAM_BUCKETS: usize = 100;
+
+// ANALYZE で集めたテーブルの統計
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    pub rows: usize,
+    // テーブルの列の順
+    pub columns: Vec<ColumnStats>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    pub null_fraction: f64,
+    // NULL でない値の種類の数の見積もり
+    pub distinct: f64,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    // NULL でない値を同じ行数ずつの区間に分けたときの境目。先頭は最小値、末尾は最大値
+    pub histogram: Vec<Value>,
+}
+
+impl TableStats {
+    // テーブルを最後まで読み、一様に選んだ SAMPLE_ROWS 行から列の統計を作る
+    pub fn collect(table: &Table, bufmgr: &mut BufferPoolManager) -> Result<Self, Error> {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        let mut sample = vec![];
+        let mut rows = 0;
+        let mut scan = table.storage.scan();
+        while let Some((rid, header, bytes)) = scan.next(bufmgr)? {
+            if header.is_deleted() {
+                continue;
+            }
+            let row = table.decode(&bytes).ok_or(Error::CorruptedTuple(rid))?;
+            rows += 1;
+            if sample.len() < SAMPLE_ROWS {
+                sample.push(row);
+            } else {
+                let i = (rng.next() % rows as u64) as usize;
+                if i < SAMPLE_ROWS {
+                    sample[i] = row;
+                }
+            }
+        }
+        let columns = (0..table.columns.len())
+            .map(|i| {
+                let values = sample.iter().map(|row| row[i].clone()).collect::<Vec<_>>();
+                ColumnStats::collect(values, rows)
+            })
+            .collect();
+        Ok(Self { rows, columns })
+    }
+}
+
+impl ColumnStats {
+    // rows 行のテーブルから選んだ values の統計
+    fn collect(values: Vec<Value>, rows: usize) -> Self {
+        let sampled = values.len();
+        let mut values = values
+            .into_iter()
+            .filter(|v| !v.is_null())
+            .collect::<Vec<_>>();
+        let null_fraction = if sampled == 0 {
+            0.0
+        } else {
+            1.0 - values.len() as f64 / sampled as f64
+        };
+        values.sort_by(|a, b| a.sort_cmp(b));
+        let mut counts = HashMap::new();
+        for value in &values {
+            *counts.entry(value).or_insert(0) += 1;
+        }
+        let d = counts.len() as f64;
+        let n = values.len() as f64;
+        let total = rows as f64 * (1.0 - null_fraction);
+        // 全体を読めたならそのまま、そうでなければ一度しか現れない値の数から見積もる (Haas and Stokes)
+        let distinct = if sampled >= rows || n == 0.0 {
+            d
+        } else {
+            let once = counts.values().filter(|&&c| c == 1).count() as f64;
+            (n * d / (n - once + once * n / total)).clamp(d, total)
+        };
+        let buckets = HISTOGRAM_BUCKETS.min(values.len().saturating_sub(1));
+        let histogram = if buckets == 0 {
+            values.first().cloned().into_iter().collect()
+        } else {
+            (0..=buckets)
+                .map(|i| values[i * (values.len() - 1) / buckets].clone())
+                .collect()
+        };
+        Self {
+            null_fraction,
+            distinct,
+            min: values.first().cloned(),
+            max: values.last().cloned(),
+            histogram,
+        }
+    }
+
+    // 列が value と等しい行の割合
+    pub fn eq_fraction(&self, value: &Value) -> f64 {
+        if value.is_null() || self.distinct < 1.0 {
+            return 0.0;
+        }
+        let outside = |bound: &Option<Value>, ordering| {
+            bound
+                .as_ref()
+                .is_some_and(|b| value.sort_cmp(b) == ordering)
+        };
+        if outside(&self.min, std::cmp::Ordering::Less)
+            || outside(&self.max, std::cmp::Ordering::Greater)
+        {
+            return 0.0;
+        }
+        (1.0 - self.null_fraction) / self.distinct
+    }
+
+    // 列が NULL でなく value より小さい行の割合。区間の中は数値なら線形に補う
+    pub fn below_fraction(&self, value: &Value) -> Option<f64> {
+        let bounds = &self.histogram;
+        let first = bounds.first()?;
+        let fraction = if value.sort_cmp(first).is_le() {
+            0.0
+        } else if value.sort_cmp(bounds.last()?).is_gt() {
+            1.0
+        } else {
+            let i = bounds.partition_point(|b| b.sort_cmp(value).is_lt()) - 1;
+            let numbers = (bounds[i].as_f64(), bounds[i + 1].as_f64(), value.as_f64());
+            let within = match numbers {
+                (Some(lo), Some(hi), Some(v)) if hi > lo => ((v - lo) / (hi - lo)).clamp(0.0, 1.0),
+                _ => 0.5,
+            };
+            (i as f64 + within) / (bounds.len() - 1) as f64
+        };
+        Some(fraction * (1.0 - self.null_fraction))
+    }
+}
+
+// 標本を選ぶための乱数。同じテーブルなら毎回同じ統計になるように種を固定する
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
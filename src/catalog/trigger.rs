@@ -0,0 +1,80 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::sql::ast::{Statement, TriggerEvent, TriggerTiming};
+use crate::types::Value;
+
+// 行を入れたり書き換えたり消したりするたびに呼ぶ処理
+//
+// BEFORE のトリガーは行を書く前に、AFTER のトリガーは書いたあとに、文を実行している演算子が同じトランザクションの中で呼ぶ。
+// 本体は NEW と OLD で行を参照する SQL の文か、登録した Rust の関数。同じテーブルの同じ時機のトリガーは作った順に呼ぶ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    pub name: String,
+    pub timing: TriggerTiming,
+    pub event: TriggerEvent,
+    pub body: TriggerBody,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerBody {
+    // sql は文の元の文字列
+    Statement {
+        statement: Box<Statement>,
+        sql: String,
+    },
+    Function(Arc<TriggerFunction>),
+}
+
+type Body = dyn Fn(Option<&[Value]>, Option<&mut Vec<Value>>) -> Result<bool, String> + Send + Sync;
+
+// トリガーから呼ぶ Rust の関数
+//
+// old は UPDATE と DELETE で元の行、new は INSERT と UPDATE で新しい行。
+// BEFORE のトリガーでは、new を書き換えるとその行を書き、false を返すとその行を飛ばす。
+// AFTER のトリガーでは戻り値も new の変更も使わない。Err を返すと文が失敗する
+pub struct TriggerFunction {
+    name: String,
+    body: Box<Body>,
+}
+
+impl TriggerFunction {
+    pub fn new(
+        name: &str,
+        body: impl Fn(Option<&[Value]>, Option<&mut Vec<Value>>) -> Result<bool, String>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_lowercase(),
+            body: Box::new(body),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn call(
+        &self,
+        old: Option<&[Value]>,
+        new: Option<&mut Vec<Value>>,
+    ) -> Result<bool, String> {
+        (self.body)(old, new)
+    }
+}
+
+impl fmt::Debug for TriggerFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TriggerFunction")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for TriggerFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::btree;
+use crate::buffer::{self, BufferPoolManager};
+use crate::catalog::{decode_record_id, rtree_key, Catalog, Index};
+use crate::disk::PageId;
+use crate::executor::Row;
+use crate::heap::{self, RecordId};
+use crate::rtree;
+
+// データベースの整合性の検査
+//
+// プールの汚れたページをすべて書き出してから、ファイルのすべてのページのチェックサムを確かめる。
+// 続けてテーブルごとにヒープのページのリスト、スロット、版のヘッダと、版の行が列の数どおりに読めるかを確かめ、
+// インデックスごとに B+Tree をたどる。木が壊れていなければ、インデックスのキーとヒープの版を突き合わせる。
+// R-tree のインデックスは木の形は確かめず、葉のエントリをヒープの版と突き合わせるだけにする。
+// 誤りを見つけても止まらず、すべて集めて返す
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Buffer(#[from] buffer::Error),
+    #[error(transparent)]
+    Heap(#[from] heap::Error),
+    #[error(transparent)]
+    BTree(#[from] btree::Error),
+    #[error(transparent)]
+    RTree(#[from] rtree::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub page_id: PageId,
+    // 誤りのあったテーブルかインデックス。チェックサムの誤りでは None
+    pub relation: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {}: ", self.page_id.to_u64())?;
+        if let Some(relation) = &self.relation {
+            write!(f, "relation \"{}\": ", relation)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+pub fn check(bufmgr: &mut BufferPoolManager, catalog: &Catalog) -> Result<Vec<Problem>, Error> {
+    bufmgr.flush()?;
+    let mut problems = vec![];
+    for page_id in (0..bufmgr.page_count()).map(PageId) {
+        if !bufmgr.verify_page(page_id)? {
+            problems.push(Problem {
+                page_id,
+                relation: None,
+                message: "page checksum mismatch".to_string(),
+            });
+        }
+    }
+    for table in catalog.tables() {
+        let mut found = vec![];
+        let tuples = table.storage.verify(bufmgr, &mut found)?;
+        let mut rows = vec![];
+        for (rid, _, bytes) in tuples {
+            match table.decode(&bytes) {
+                Some(row) if row.len() == table.columns.len() => rows.push((rid, row)),
+                Some(row) => found.push((
+                    rid.page_id,
+                    format!(
+                        "slot {} has {} columns instead of {}",
+                        rid.slot,
+                        row.len(),
+                        table.columns.len()
+                    ),
+                )),
+                None => found.push((
+                    rid.page_id,
+                    format!("slot {} does not hold a valid row", rid.slot),
+                )),
+            }
+        }
+        // 壊れたページの版は読めていないので、それを指すキーは突き合わせない
+        let broken: HashSet<PageId> = found.iter().map(|&(page_id, _)| page_id).collect();
+        extend(&mut problems, &table.name, found);
+        for index in &table.indexes {
+            let mut found = index.btree.verify(bufmgr)?;
+            if found.is_empty() {
+                cross_check(bufmgr, index, &rows, &broken, &mut found)?;
+            }
+            extend(&mut problems, &index.name, found);
+        }
+    }
+    Ok(problems)
+}
+
+fn extend(problems: &mut Vec<Problem>, relation: &str, found: Vec<(PageId, String)>) {
+    problems.extend(found.into_iter().map(|(page_id, message)| Problem {
+        page_id,
+        relation: Some(relation.to_string()),
+        message,
+    }));
+}
+
+// 消したものも含めてどの版もキーを持ち、どのキーもその値の版を指していなければならない
+fn cross_check(
+    bufmgr: &mut BufferPoolManager,
+    index: &Index,
+    rows: &[(RecordId, Row)],
+    broken: &HashSet<PageId>,
+    found: &mut Vec<(PageId, String)>,
+) -> Result<(), Error> {
+    let mut keys = HashSet::new();
+    if let Some(rtree) = index.rtree {
+        for (rect, rid) in rtree.entries(bufmgr)? {
+            keys.insert(rtree_key(rect, rid));
+        }
+    } else {
+        let mut scan = index.btree.scan(bufmgr, None)?;
+        while let Some((key, _)) = scan.next(bufmgr)? {
+            keys.insert(key);
+        }
+    }
+    for (rid, row) in rows {
+        let entries = match index.entries(row, *rid) {
+            Ok(entries) => entries,
+            Err(err) => {
+                found.push((
+                    rid.page_id,
+                    format!("cannot compute the key of slot {}: {}", rid.slot, err),
+                ));
+                continue;
+            }
+        };
+        // 全文インデックスでは、行は語ごとにキーを持つ
+        let missing = entries.iter().filter(|key| !keys.remove(*key)).count();
+        if missing > 0 {
+            found.push((rid.page_id, format!("slot {} has no index entry", rid.slot)));
+        }
+    }
+    let meta_page_id = index.btree.meta_page_id;
+    for key in keys {
+        if key.len() < 10 {
+            found.push((
+                meta_page_id,
+                "index entry is too short to hold a record id".to_string(),
+            ));
+            continue;
+        }
+        let rid = decode_record_id(&key);
+        if !broken.contains(&rid.page_id) {
+            found.push((
+                rid.page_id,
+                format!(
+                    "index entry points to slot {}, which does not hold a matching row",
+                    rid.slot
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{encode_record_id, Column};
+    use crate::collation::Collation;
+    use crate::testutil::temp_bufmgr;
+    use crate::transaction::TxnId;
+    use crate::types::{DataType, Value};
+
+    #[test]
+    fn test_check() {
+        let mut bufmgr = temp_bufmgr(16);
+        let mut catalog = Catalog::new();
+        let column = |name: &str| Column {
+            name: name.to_string(),
+            data_type: DataType::Integer,
+            not_null: false,
+            collation: Collation::Binary,
+        };
+        catalog
+            .create_table(&mut bufmgr, "t", vec![column("a"), column("b")])
+            .unwrap();
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], true)
+            .unwrap();
+        let table = catalog.table("t").unwrap();
+        let row = |a: i64| vec![Value::Integer(a), Value::Integer(a * 10)];
+        let rids: Vec<_> = (0..2000)
+            .map(|a| {
+                table
+                    .insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, &row(a))
+                    .unwrap()
+            })
+            .collect();
+        let new = vec![Value::Integer(5), Value::Integer(0)];
+        table
+            .update(&mut bufmgr, TxnId(2), rids[5], &row(5), &new)
+            .unwrap();
+        table.delete(&mut bufmgr, TxnId(2), rids[6]).unwrap();
+        assert_eq!(check(&mut bufmgr, &catalog).unwrap(), []);
+
+        // インデックスのキーを 1 つ消し、最後のページのスロットの数を壊す
+        let index = table.index("t_a").unwrap();
+        let mut key = index.key_prefix(&row(3)).unwrap();
+        encode_record_id(rids[3], &mut key);
+        assert!(index.btree.delete(&mut bufmgr, &key).unwrap());
+        let last = *table.storage.page_ids(&mut bufmgr).unwrap().last().unwrap();
+        assert_ne!(last, rids[3].page_id);
+        let buffer = bufmgr.fetch_page(last).unwrap();
+        // ヒープのページの見出しの後ろがスロット付きページ
+        buffer.page.borrow_mut()[16..18].copy_from_slice(&u16::MAX.to_be_bytes());
+        buffer.is_dirty.set(true);
+        drop(buffer);
+        let problems: Vec<String> = check(&mut bufmgr, &catalog)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                format!(
+                    "page {}: relation \"t\": 65535 slots do not fit in the page",
+                    last.0
+                ),
+                format!(
+                    "page {}: relation \"t_a\": slot {} has no index entry",
+                    rids[3].page_id.0, rids[3].slot
+                ),
+            ]
+        );
+    }
+}
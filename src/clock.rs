@@ -0,0 +1,168 @@
+use std::cell::Cell;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub use wasm::{set_time_source, Instant};
+
+// 時刻の出どころ
+//
+// ふだんは OS の時計を読む。仮想の時計にしたスレッドでは、時刻は advance したときだけ進み、
+// 眠る代わりにその分だけ時刻を進める。文の時間切れ、ロックを待つ時間、now() とコミットの時刻がこの時計を読む
+
+thread_local! {
+    // 仮想の時計なら、0 からの経過時間
+    static VIRTUAL: Cell<Option<Duration>> = const { Cell::new(None) };
+}
+
+// 仮想の時計の 0 に当たる Instant。Instant は好きな値で作れないので、最初に読んだ OS の時刻を使う
+static BASE: OnceLock<Instant> = OnceLock::new();
+// 仮想の時計の 0 に当たる時刻 (2000-01-01 00:00:00 UTC)
+const VIRTUAL_EPOCH: Duration = Duration::from_secs(946_684_800);
+
+pub fn now() -> Instant {
+    match VIRTUAL.get() {
+        Some(elapsed) => *BASE.get_or_init(Instant::now) + elapsed,
+        None => Instant::now(),
+    }
+}
+
+pub fn system_now() -> SystemTime {
+    match VIRTUAL.get() {
+        Some(elapsed) => UNIX_EPOCH + VIRTUAL_EPOCH + elapsed,
+        #[cfg(not(target_arch = "wasm32"))]
+        None => SystemTime::now(),
+        #[cfg(target_arch = "wasm32")]
+        None => UNIX_EPOCH + wasm::since_epoch(),
+    }
+}
+
+pub fn is_virtual() -> bool {
+    VIRTUAL.get().is_some()
+}
+
+// 仮想の時計の、0 からの経過時間
+pub fn elapsed() -> Option<Duration> {
+    VIRTUAL.get()
+}
+
+// このスレッドの時計を仮想の時計にして 0 から始めるか、OS の時計に戻す
+pub fn set_virtual(enabled: bool) {
+    BASE.get_or_init(Instant::now);
+    VIRTUAL.set(enabled.then_some(Duration::ZERO));
+}
+
+// 仮想の時計を進める。OS の時計なら何もしない
+pub fn advance(by: Duration) {
+    if let Some(elapsed) = VIRTUAL.get() {
+        VIRTUAL.set(Some(elapsed + by));
+    }
+}
+
+pub fn sleep(duration: Duration) {
+    if is_virtual() {
+        advance(duration);
+    } else {
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::sleep(duration);
+        #[cfg(target_arch = "wasm32")]
+        wasm::sleep(duration);
+    }
+}
+
+// wasm32-unknown-unknown の std には時計がなく、Instant::now と SystemTime::now は panic し、眠ることもできない。
+// 代わりに埋め込む側が set_time_source で渡した、1970-01-01 からの時間を返す関数 (ブラウザなら Date.now) を読む。
+// 渡さなければ時刻は 0 のままで、sleep した分だけ進む
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use std::ops::{Add, AddAssign, Sub};
+    use std::sync::{Mutex, OnceLock};
+    use std::time::Duration;
+
+    static SOURCE: OnceLock<fn() -> Duration> = OnceLock::new();
+    // sleep で進めた時間
+    static SLEPT: Mutex<Duration> = Mutex::new(Duration::ZERO);
+
+    // 最初に渡した関数だけを使う
+    pub fn set_time_source(source: fn() -> Duration) {
+        let _ = SOURCE.set(source);
+    }
+
+    pub(super) fn since_epoch() -> Duration {
+        let now = SOURCE.get().map_or(Duration::ZERO, |source| source());
+        now + *SLEPT.lock().unwrap()
+    }
+
+    pub(super) fn sleep(duration: Duration) {
+        *SLEPT.lock().unwrap() += duration;
+    }
+
+    // std::time::Instant の代わり。1970-01-01 からの時間で、戻らないように前に読んだ値より小さくしない
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Instant(Duration);
+
+    impl Instant {
+        pub fn now() -> Instant {
+            static LAST: Mutex<Duration> = Mutex::new(Duration::ZERO);
+            let mut last = LAST.lock().unwrap();
+            *last = (*last).max(since_epoch());
+            Instant(*last)
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Instant::now().duration_since(*self)
+        }
+
+        pub fn duration_since(&self, earlier: Instant) -> Duration {
+            self.0.saturating_sub(earlier.0)
+        }
+
+        pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+            self.duration_since(earlier)
+        }
+
+        pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+            self.0.checked_sub(earlier.0)
+        }
+
+        pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_add(duration).map(Instant)
+        }
+
+        pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_sub(duration).map(Instant)
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Instant;
+
+        fn add(self, duration: Duration) -> Instant {
+            Instant(self.0 + duration)
+        }
+    }
+
+    impl AddAssign<Duration> for Instant {
+        fn add_assign(&mut self, duration: Duration) {
+            self.0 += duration;
+        }
+    }
+
+    impl Sub<Duration> for Instant {
+        type Output = Instant;
+
+        fn sub(self, duration: Duration) -> Instant {
+            Instant(self.0 - duration)
+        }
+    }
+
+    impl Sub<Instant> for Instant {
+        type Output = Duration;
+
+        fn sub(self, earlier: Instant) -> Duration {
+            self.duration_since(earlier)
+        }
+    }
+}
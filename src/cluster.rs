@@ -0,0 +1,395 @@
+use std::cell::RefCell;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::connection::{Connection, Database, Error, Maintenance};
+use crate::settings::Settings;
+use crate::slowlog::SlowQueryLog;
+use crate::wal::{LogRecord, Lsn};
+
+// つながる先を指定しなかった接続が使うデータベースの名前
+pub const DEFAULT_DATABASE: &str = "postgres";
+
+// ひとつのサーバーが受け持つデータベースの集まり
+//
+// データベースはそれぞれ自分のファイル、カタログ、バッファプール、トランザクションを持ち、
+// 接続はつないだデータベースのテーブルとロールだけを見る。バッファプールの読み書きの数もデータベースごとに分かれる。
+// 最初に開くのが DEFAULT_DATABASE で、CREATE DATABASE で作ったものはそのファイルと同じディレクトリの name.db に置く。
+// ファイルを指定しなければ、どのデータベースも一時ファイルに作る
+pub struct Cluster {
+    databases: Rc<RefCell<Databases>>,
+}
+
+pub(crate) struct Databases {
+    // None なら一時ファイルに作る
+    dir: Option<PathBuf>,
+    settings: Settings,
+    databases: Vec<(String, Database)>,
+    // 開いたデータベースごとに遅い文のログを作る
+    slow_log: Option<Box<dyn Fn() -> SlowQueryLog>>,
+    group_commit: bool,
+    this: Weak<RefCell<Databases>>,
+}
+
+// グループコミットで、ログをディスクに届けるまで送らずにためておく応答
+//
+// サーバーのエンジンのスレッドは、接続のコミットを記してもすぐには fsync しない。
+// 最初に応答をためてから commit_delay が過ぎるまでほかの接続の依頼を続けて実行し、そのあとすべてのデータベースのログを
+// 1 回ずつ fsync してから、ためた応答をまとめて送る。ディスクに届く前のコミットを見せないよう、その間の応答はどれもためる。
+// 接続は応答を受け取るまで次の依頼を送らないので、待つ間に来る依頼は接続の数より多くならない
+pub struct CommitGroup<T> {
+    delay: Duration,
+    pending: Vec<T>,
+    deadline: Option<Instant>,
+}
+
+impl Cluster {
+    // path を既定のデータベースのファイルとして開く。None なら一時ファイル
+    pub fn open(path: Option<&Path>, settings: Settings) -> Result<Cluster, Error> {
+        let db = match path {
+            Some(path) => Database::open_with(path, settings.clone())?,
+            None => Database::open_temporary_with(settings.clone())?,
+        };
+        let dir = path.map(|path| path.parent().unwrap_or(Path::new("")).to_path_buf());
+        Ok(Cluster::with_default(dir, db, settings))
+    }
+
+    // オンラインバックアップの backup を、既定のデータベースのスタンバイとして開く。
+    // 昇格したあとに作るデータベースは backup のディレクトリに置く
+    pub fn open_standby(backup: &Path, settings: Settings) -> Result<Cluster, Error> {
+        let db = Database::open_standby(backup, settings.clone())?;
+        Ok(Cluster::with_default(
+            Some(backup.to_path_buf()),
+            db,
+            settings,
+        ))
+    }
+
+    fn with_default(dir: Option<PathBuf>, db: Database, settings: Settings) -> Cluster {
+        let databases = Rc::new_cyclic(|this| {
+            RefCell::new(Databases {
+                dir,
+                settings,
+                databases: vec![],
+                slow_log: None,
+                group_commit: false,
+                this: this.clone(),
+            })
+        });
+        databases.borrow_mut().add(DEFAULT_DATABASE, db);
+        Cluster { databases }
+    }
+
+    // name のデータベースにつなぐ。None なら DEFAULT_DATABASE
+    pub fn connect(&self, name: Option<&str>) -> Result<Connection, Error> {
+        let name = name.unwrap_or(DEFAULT_DATABASE);
+        let databases = self.databases.borrow();
+        let (_, db) = databases
+            .databases
+            .iter()
+            .find(|(n, _)| n == name)
+            .ok_or_else(|| Error::DatabaseNotFound(name.to_string()))?;
+        Ok(db.connect())
+    }
+
+    pub fn create_database(&self, name: &str) -> Result<(), Error> {
+        self.databases.borrow_mut().create(name)
+    }
+
+    pub fn database_names(&self) -> Vec<String> {
+        let databases = self.databases.borrow();
+        databases.databases.iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    // データベースを順に見て、最初に自動の VACUUM か ANALYZE をしたデータベースの名前としたこと
+    pub fn autovacuum(&self) -> Result<Option<(String, Maintenance)>, Error> {
+        let databases = self.databases.borrow();
+        for (name, db) in &databases.databases {
+            if let Some(maintenance) = db.autovacuum()? {
+                return Ok(Some((name.clone(), maintenance)));
+            }
+        }
+        Ok(None)
+    }
+
+    // すべてのデータベースでグループコミットにする。これから作るデータベースもそうする
+    pub fn set_group_commit(&self, on: bool) {
+        let databases = &mut *self.databases.borrow_mut();
+        for (_, db) in &databases.databases {
+            db.set_group_commit(on);
+        }
+        databases.group_commit = on;
+    }
+
+    // ディスクに届けていないコミットのあるデータベースがある
+    pub fn unflushed_commits(&self) -> bool {
+        let databases = self.databases.borrow();
+        databases
+            .databases
+            .iter()
+            .any(|(_, db)| db.unflushed_commits())
+    }
+
+    // グループコミットでためたログを、データベースごとにディスクに届ける
+    pub fn flush_commits(&self) -> Result<(), Error> {
+        let databases = self.databases.borrow();
+        for (_, db) in &databases.databases {
+            if db.unflushed_commits() {
+                db.flush_commits()?;
+            }
+        }
+        Ok(())
+    }
+
+    // 既定のデータベースのログを listener につないだスタンバイに送る。ほかのデータベースのログは送らない
+    pub fn serve_replication(&self, listener: TcpListener) -> Result<JoinHandle<()>, Error> {
+        self.default_database(|db| db.serve_replication(listener))
+    }
+
+    // スタンバイの既定のデータベースに、受け取ったものを当てる
+    pub fn replay(&self, received: Option<(Lsn, LogRecord)>) -> Result<(), Error> {
+        self.default_database(|db| db.replay(received))
+    }
+
+    // 既定のデータベースが当て終えたログの位置。スタンバイでなければ None
+    pub fn standby_lsn(&self) -> Option<Lsn> {
+        self.default_database(Database::standby_lsn)
+    }
+
+    pub fn promote(&self) -> Result<(), Error> {
+        self.default_database(Database::promote)
+    }
+
+    fn default_database<T>(&self, f: impl FnOnce(&Database) -> T) -> T {
+        let databases = self.databases.borrow();
+        f(&databases.databases[0].1)
+    }
+
+    // すべてのデータベースの遅い文を、make で作ったログに書く。これから作るデータベースにも使う
+    pub fn set_slow_query_log(&self, make: impl Fn() -> SlowQueryLog + 'static) {
+        let databases = &mut *self.databases.borrow_mut();
+        for (_, db) in &databases.databases {
+            db.set_slow_query_log(Some(make()));
+        }
+        databases.slow_log = Some(Box::new(make));
+    }
+}
+
+impl Databases {
+    fn add(&mut self, name: &str, db: Database) {
+        if let Some(make) = &self.slow_log {
+            db.set_slow_query_log(Some(make()));
+        }
+        db.set_cluster(self.this.clone());
+        db.set_group_commit(self.group_commit);
+        self.databases.push((name.to_string(), db));
+    }
+
+    pub(crate) fn create(&mut self, name: &str) -> Result<(), Error> {
+        // 名前はそのままファイルの名前になる
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(Error::InvalidDatabaseName(name.to_string()));
+        }
+        if self.databases.iter().any(|(n, _)| n == name) {
+            return Err(Error::DatabaseExists(name.to_string()));
+        }
+        let settings = self.settings.clone();
+        let db = match &self.dir {
+            Some(dir) => Database::open_with(dir.join(format!("{}.db", name)), settings)?,
+            None => Database::open_temporary_with(settings)?,
+        };
+        self.add(name, db);
+        Ok(())
+    }
+}
+
+impl<T> CommitGroup<T> {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            pending: vec![],
+            deadline: None,
+        }
+    }
+
+    // cluster にディスクに届けていないコミットがあれば reply をためて None を返し、なければそのまま返す
+    pub fn hold(&mut self, cluster: &Cluster, reply: T) -> Option<T> {
+        if self.pending.is_empty() {
+            if !cluster.unflushed_commits() {
+                return Some(reply);
+            }
+            self.deadline = Some(Instant::now() + self.delay);
+        }
+        self.pending.push(reply);
+        None
+    }
+
+    // 次の依頼を待ってよい時間。ためた応答がなければ None で、いつまでも待てる
+    pub fn timeout(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    // ログをディスクに届け、ためた応答を返す
+    pub fn flush(&mut self, cluster: &Cluster) -> Result<Vec<T>, Error> {
+        cluster.flush_commits()?;
+        self.deadline = None;
+        Ok(std::mem::take(&mut self.pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Sender};
+    use std::thread;
+
+    use super::*;
+    use crate::testutil::temp_path;
+    use crate::types::Value;
+
+    #[test]
+    fn test_cluster() {
+        let cluster = Cluster::open(None, Settings::default()).unwrap();
+        let mut conn = cluster.connect(None).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER); INSERT INTO t VALUES (1); CREATE DATABASE other",
+        )
+        .unwrap();
+        assert_eq!(cluster.database_names(), ["postgres", "other"]);
+        let err = conn.execute("CREATE DATABASE other", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42P04");
+        let err = conn.execute("CREATE DATABASE \"../x\"", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42602");
+
+        // 別のデータベースには別のカタログとバッファプールがある
+        let mut other = cluster.connect(Some("other")).unwrap();
+        let err = other.execute("SELECT a FROM t", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42P01");
+        other
+            .execute_batch("CREATE TABLE t (b TEXT); INSERT INTO t VALUES ('x'), ('y')")
+            .unwrap();
+        assert_eq!(conn.query("SELECT * FROM t", &[]).unwrap().len(), 1);
+        let hit_ratio = |conn: &mut Connection| -> f64 {
+            conn.query_row("SELECT max(hit_ratio) FROM stats.buffer_pool", &[])
+                .unwrap()
+                .get(0)
+                .unwrap()
+        };
+        let before = hit_ratio(&mut conn);
+        for _ in 0..10 {
+            other.execute("SELECT * FROM t", &[]).unwrap();
+        }
+        assert_eq!(hit_ratio(&mut conn), before);
+
+        // 列挙型もデータベースごとで、番号が同じでも別の型になる
+        conn.execute_batch(
+            "CREATE TYPE color AS ENUM ('red', 'blue'); CREATE TABLE c (x color);
+             INSERT INTO c VALUES ('blue')",
+        )
+        .unwrap();
+        other
+            .execute_batch(
+                "CREATE TYPE size AS ENUM ('s', 'm', 'l'); CREATE TABLE c (x size);
+                 INSERT INTO c VALUES ('m')",
+            )
+            .unwrap();
+        let label = |conn: &mut Connection| -> String {
+            conn.query_row("SELECT CAST(x AS TEXT) FROM c", &[])
+                .unwrap()
+                .get(0)
+                .unwrap()
+        };
+        assert_eq!(
+            (label(&mut conn), label(&mut other)),
+            ("blue".into(), "m".into())
+        );
+        let err = other.execute("CREATE TABLE d (x color)", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42704");
+
+        let Err(err) = cluster.connect(Some("nope")) else {
+            panic!("connected to a missing database");
+        };
+        assert_eq!(err.sqlstate(), "3D000");
+        let mut embedded = Database::open_temporary().unwrap().connect();
+        let err = embedded.execute("CREATE DATABASE x", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "0A000");
+    }
+
+    // clients の数のスレッドがそれぞれ commits 回ずつコミットを頼み、応答を受け取ってから次を頼む。
+    // 依頼はエンジンのスレッドのように 1 つのスレッドが順に実行する。ログを fsync した回数を返す
+    fn group_commit(clients: usize, commits: usize, delay: Duration) -> u64 {
+        let path = temp_path("group.db");
+        let cluster = Cluster::open(Some(&path), Settings::default()).unwrap();
+        cluster.set_group_commit(true);
+        let mut conns = (0..clients)
+            .map(|_| cluster.connect(None).unwrap())
+            .collect::<Vec<_>>();
+        conns[0].execute("CREATE TABLE t (a INTEGER)", &[]).unwrap();
+        cluster.flush_commits().unwrap();
+        let syncs = || cluster.databases.borrow().databases[0].1.wal_syncs();
+        let before = syncs();
+        let (requests, receiver) = mpsc::channel::<(usize, Sender<()>)>();
+        thread::scope(|s| {
+            for client in 0..clients {
+                let requests = requests.clone();
+                s.spawn(move || {
+                    for i in 0..commits {
+                        let (reply, done) = mpsc::channel();
+                        requests.send((client * commits + i, reply)).unwrap();
+                        done.recv().unwrap();
+                    }
+                });
+            }
+            drop(requests);
+            let mut group = CommitGroup::<Sender<()>>::new(delay);
+            loop {
+                let request = match group.timeout() {
+                    None => receiver.recv().ok(),
+                    Some(timeout) => match receiver.recv_timeout(timeout) {
+                        Ok(request) => Some(request),
+                        Err(_) => {
+                            for reply in group.flush(&cluster).unwrap() {
+                                reply.send(()).unwrap();
+                            }
+                            continue;
+                        }
+                    },
+                };
+                let Some((key, reply)) = request else {
+                    break;
+                };
+                conns[key / commits]
+                    .execute("INSERT INTO t VALUES ($1)", &[Value::Integer(key as i64)])
+                    .unwrap();
+                if let Some(reply) = group.hold(&cluster, reply) {
+                    reply.send(()).unwrap();
+                }
+            }
+        });
+        assert!(!cluster.unflushed_commits());
+        let count: i64 = conns[0]
+            .query_row("SELECT count(*) FROM t", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, (clients * commits) as i64);
+        let syncs = syncs() - before;
+        drop(conns);
+        drop(cluster);
+        let _ = std::fs::remove_dir_all(format!("{}-wal", path.display()));
+        let _ = std::fs::remove_file(&path);
+        syncs
+    }
+
+    #[test]
+    fn test_group_commit() {
+        // 1 つの接続ならコミットごとに fsync する
+        assert_eq!(group_commit(1, 20, Duration::ZERO), 20);
+        // 待つ間にほかの接続のコミットが集まり、fsync はコミットよりずっと少ない
+        let syncs = group_commit(8, 20, Duration::from_millis(2));
+        assert!(syncs < 160 / 2, "{} fsyncs", syncs);
+    }
+}
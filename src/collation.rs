@@ -0,0 +1,142 @@
+use std::cmp::Ordering;
+
+use crate::types::Value;
+
+// 文字列の比べ方
+//
+// 比べるときは、値を照合順のキーにしてから比べる。キーは Value で、キーどうしの sort_cmp が照合順になる。
+// インデックスにもキーを入れる。キーから元の文字列には戻せない
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Collation {
+    // バイト列の順
+    #[default]
+    Binary,
+    // 小文字にしてから比べる。大文字と小文字だけが違う文字列は等しい
+    NoCase,
+    // 辞書の順。アクセントと大文字小文字を無視して比べ、同じなら順にアクセント、大文字小文字、バイト列で比べる。
+    // 等しいのは同じ文字列だけ
+    Unicode,
+}
+
+// アクセントの付いたラテン文字を、基底の文字ごとに並べたもの。アクセントの番号は並びの位置 + 1
+const ACCENTED: &[(char, &str)] = &[
+    ('a', "àáâãäåāăą"),
+    ('c', "çćĉċč"),
+    ('d', "ďđ"),
+    ('e', "èéêëēĕėęě"),
+    ('g', "ĝğġģ"),
+    ('h', "ĥħ"),
+    ('i', "ìíîïĩīĭįı"),
+    ('j', "ĵ"),
+    ('k', "ķ"),
+    ('l', "ĺļľŀł"),
+    ('n', "ñńņňŉ"),
+    ('o', "òóôõöøōŏő"),
+    ('r', "ŕŗř"),
+    ('s', "śŝşš"),
+    ('t', "ţťŧ"),
+    ('u', "ùúûüũūŭůűų"),
+    ('w', "ŵ"),
+    ('y', "ýÿŷ"),
+    ('z', "źżž"),
+];
+
+impl Collation {
+    // COLLATE に書く名前から。わからない名前なら None
+    pub fn parse(name: &str) -> Option<Collation> {
+        match name.to_lowercase().as_str() {
+            "binary" | "c" | "posix" => Some(Collation::Binary),
+            "nocase" | "case_insensitive" => Some(Collation::NoCase),
+            "unicode" | "und" => Some(Collation::Unicode),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Collation::Binary => "binary",
+            Collation::NoCase => "nocase",
+            Collation::Unicode => "unicode",
+        }
+    }
+
+    // 等しい文字列が同じ文字列だけか。そうでなければグループ分けにもキーを使う
+    pub fn is_deterministic(self) -> bool {
+        self != Collation::NoCase
+    }
+
+    // 照合順のキー。文字列でない値はそのまま
+    pub fn key(self, value: Value) -> Value {
+        let Value::Text(s) = value else {
+            return value;
+        };
+        match self {
+            Collation::Binary => Value::Text(s),
+            Collation::NoCase => Value::Text(s.to_lowercase()),
+            Collation::Unicode => Value::Blob(unicode_key(&s).into()),
+        }
+    }
+
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        let key = |s: &str| self.key(Value::Text(s.to_string()));
+        key(a).sort_cmp(&key(b))
+    }
+}
+
+// 基底の小文字と、アクセントの番号と、大文字なら true
+fn decompose(c: char) -> (char, u8, bool) {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    let upper = lower != c;
+    for (base, accented) in ACCENTED {
+        if let Some(i) = accented.chars().position(|a| a == lower) {
+            return (*base, i as u8 + 1, upper);
+        }
+    }
+    (lower, 0, upper)
+}
+
+// 基底の文字の UTF-8、アクセントの番号 + 1、大文字なら 2 で小文字なら 1 を、それぞれ 0 で区切って並べ、最後に元のバイト列を続ける。
+// どのレベルも 0 を含まないので、短い文字列が先に終わって前に並ぶ
+fn unicode_key(s: &str) -> Vec<u8> {
+    let chars = s.chars().map(decompose).collect::<Vec<_>>();
+    let mut key = Vec::with_capacity(s.len() * 3 + 3);
+    let mut buf = [0; 4];
+    for &(base, _, _) in &chars {
+        let base = if base == '\0' { '\u{1}' } else { base };
+        key.extend_from_slice(base.encode_utf8(&mut buf).as_bytes());
+    }
+    key.push(0);
+    key.extend(chars.iter().map(|&(_, accent, _)| accent + 1));
+    key.push(0);
+    key.extend(chars.iter().map(|&(_, _, upper)| upper as u8 + 1));
+    key.push(0);
+    key.extend_from_slice(s.as_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collation() {
+        assert_eq!(Collation::Binary.compare("B", "a"), Ordering::Less);
+        assert_eq!(Collation::NoCase.compare("B", "a"), Ordering::Greater);
+        assert_eq!(Collation::NoCase.compare("ABC", "abc"), Ordering::Equal);
+        assert_eq!(Collation::parse("NOCASE"), Some(Collation::NoCase));
+        assert_eq!(Collation::parse("fr_FR"), None);
+
+        // アクセントと大文字小文字は、基底の文字が同じときだけ効く
+        let mut words = vec![
+            "peach", "Péché", "péché", "pêche", "PECHE", "pèche", "pechf", "pec",
+        ];
+        words.sort_by(|a, b| Collation::Unicode.compare(a, b));
+        assert_eq!(
+            words,
+            vec!["peach", "pec", "PECHE", "pèche", "péché", "Péché", "pêche", "pechf"]
+        );
+        assert_eq!(Collation::Unicode.compare("é", "é"), Ordering::Equal);
+        assert_eq!(Collation::Unicode.compare("a", "A"), Ordering::Less);
+        assert_eq!(Collation::Unicode.key(Value::Integer(1)), Value::Integer(1));
+    }
+}
@@ -0,0 +1,6652 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::vec;
+
+use thiserror::Error;
+
+use crate::blob::{self, Blob};
+use crate::buffer::{BufferPool, BufferPoolManager};
+use crate::catalog::{self, Catalog, Column, Role, Trigger, TriggerBody, TriggerFunction};
+use crate::check;
+use crate::clock::{self, Instant};
+use crate::cluster::Databases;
+use crate::collation::Collation;
+use crate::copy::{RecordWriter, Records};
+use crate::datetime::{Date, Interval, Time, Timestamp};
+use crate::decimal::Decimal;
+use crate::disk::{DiskBackend, DiskManager, MemoryDisk, PageId};
+use crate::executor::expr::ScalarFunction;
+use crate::executor::{self, CancelToken, ExecContext, PlanNode, Profile};
+use crate::lock::{self, LockMode};
+use crate::metrics::METRICS;
+use crate::parquet::{self, ParquetWriter};
+use crate::persist::{self, DatabaseFile, StandbyFile};
+use crate::planner::{self, PlanCache, Planner};
+use crate::recovery::{self, BackupLabel};
+use crate::replication::{self, Receiver};
+use crate::result_cache::{ResultCache, TableVersion};
+use crate::session::{PendingCatalog, PreparedStatement, Session};
+use crate::settings::{self, Settings};
+use crate::slowlog::{self, SlowQueryLog};
+use crate::sql::ast::{
+    self as ast, AlterTableAction, ColumnDef, ConflictAction, CopyDirection, CopyFormat,
+    CopyOptions, CopyRelation, CreateIndex, CreateMaterializedView, CreateTable, CreateTrigger,
+    Insert, InsertSource, IsolationLevel, OnConflict, PartitionBoundSpec, Privilege, Query,
+    RoleOptions, Statement, TransactionStatement, TriggerBodySpec,
+};
+use crate::sql::{self, ParseError};
+use crate::sqlite;
+use crate::stats::{Activity, IoStats};
+use crate::storage::{self, StorageEngine};
+use crate::trace;
+use crate::transaction::{self, TransactionManager};
+use crate::types::{CoerceError, DataType, Value};
+use crate::uuid::Uuid;
+use crate::wal::{LogRecord, Lsn, RowChange, Wal};
+
+// COPY で一度に入れる行の数
+const COPY_BATCH: usize = 1000;
+// トランザクションの中の COPY が失敗したときに戻るセーブポイント
+const COPY_SAVEPOINT: &str = "copy";
+// execute_many で一度に入れる値の組の数
+const MANY_BATCH: usize = 1000;
+// トランザクションの中の execute_many が失敗したときに戻るセーブポイント
+const MANY_SAVEPOINT: &str = "execute_many";
+// トランザクションの中で実体化したビューを作り直すのに失敗したときに戻るセーブポイント
+const VIEW_SAVEPOINT: &str = "materialized_view";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Planner(#[from] planner::Error),
+    #[error(transparent)]
+    Executor(#[from] executor::Error),
+    #[error(transparent)]
+    Catalog(#[from] catalog::Error),
+    #[error(transparent)]
+    Transaction(#[from] transaction::Error),
+    #[error("type \"{0}\" does not exist")]
+    UnknownType(String),
+    #[error("collation \"{0}\" does not exist")]
+    UnknownCollation(String),
+    #[error("compression method \"{0}\" does not exist")]
+    UnknownCompression(String),
+    #[error("cannot insert multiple commands into a single statement")]
+    MultipleStatements,
+    #[error("prepared statement \"{0}\" does not exist")]
+    PreparedStatementNotFound(String),
+    #[error("statement does not return rows")]
+    NoResultSet,
+    #[error("query returned no rows")]
+    NoRows,
+    #[error("column {0} does not exist in the result")]
+    ColumnNotFound(String),
+    #[error("cannot read column {index} (\"{column}\") of type {found} as {expected}")]
+    InvalidColumnType {
+        index: usize,
+        column: String,
+        expected: &'static str,
+        found: String,
+    },
+    // COPY で読んだデータの形の誤り
+    #[error("{0}")]
+    CopyData(String),
+    #[error("{source} (COPY {table}, line {line})")]
+    Copy {
+        table: String,
+        line: usize,
+        source: Box<Error>,
+    },
+    // 非同期の接続で、データベースのスレッドが止まっている
+    #[error("database engine thread stopped")]
+    EngineStopped,
+    #[error(transparent)]
+    Parquet(#[from] parquet::Error),
+    #[error(transparent)]
+    Sqlite(#[from] sqlite::Error),
+    #[error(transparent)]
+    Settings(#[from] settings::Error),
+    #[error(transparent)]
+    Check(#[from] check::Error),
+    #[error(transparent)]
+    Blob(#[from] blob::Error),
+    #[error("{source} (parameter set {index})")]
+    Many { index: usize, source: Box<Error> },
+    #[error("{source} (importing {table}, rowid {rowid})")]
+    Import {
+        table: String,
+        rowid: i64,
+        source: Box<Error>,
+    },
+    #[error("permission denied to run {0}")]
+    InsufficientPrivilege(&'static str),
+    #[error("password authentication failed for user \"{0}\"")]
+    AuthenticationFailed(String),
+    #[error("role \"{0}\" is not permitted to log in")]
+    LoginNotAllowed(String),
+    #[error("database \"{0}\" already exists")]
+    DatabaseExists(String),
+    #[error("database \"{0}\" does not exist")]
+    DatabaseNotFound(String),
+    #[error("invalid database name \"{0}\"")]
+    InvalidDatabaseName(String),
+    // Cluster から開いていないデータベース
+    #[error("CREATE DATABASE is only supported by a server")]
+    NoCluster,
+    #[error("database \"{0}\" is already attached")]
+    AttachmentExists(String),
+    #[error("no attached database \"{0}\"")]
+    AttachmentNotFound(String),
+    #[error("cannot attach a database to itself")]
+    AttachSelf,
+    #[error("attached database \"{0}\" is read-only")]
+    ReadOnlyAttachment(String),
+    #[error("COPY (BULK) requires table \"{0}\" to be empty")]
+    BulkLoadNotEmpty(String),
+    #[error("COPY (BULK) is only supported with COPY FROM a table")]
+    BulkCopyTo,
+    #[error("column \"{0}\" cannot be added with constraints, generation or compression")]
+    AddColumnOptions(String),
+    #[error("could not serialize access due to concurrent schema change")]
+    SchemaConflict,
+    #[error("cannot PREPARE a transaction that has changed the catalog")]
+    PrepareSchemaChange,
+    #[error(transparent)]
+    Recovery(#[from] recovery::Error),
+    // ページ 0 にカタログがないか、カタログが読めない
+    #[error("database file is broken or is not a database")]
+    BrokenDatabaseFile,
+    #[error("database is being accessed by other connections")]
+    DatabaseInUse,
+    // ファイルから開いていないデータベース
+    #[error("backups are only supported by a database opened from a file")]
+    NoDatabaseFile,
+    #[error(transparent)]
+    Replication(#[from] replication::Error),
+    #[error("cannot execute this statement on a standby")]
+    ReadOnlyStandby,
+    #[error("database is not a standby")]
+    NotStandby,
+}
+
+impl Error {
+    // PostgreSQL の SQLSTATE。細かく分けていないものは XX000
+    // set_park_lock_waits で、ロックを待つところで戻ったか
+    pub fn is_lock_wait(&self) -> bool {
+        match self {
+            Error::Executor(executor::Error::Lock(lock::Error::WouldBlock))
+            | Error::Executor(executor::Error::Transaction(transaction::Error::Lock(
+                lock::Error::WouldBlock,
+            )))
+            | Error::Transaction(transaction::Error::Lock(lock::Error::WouldBlock)) => true,
+            Error::Copy { source, .. } | Error::Many { source, .. } => source.is_lock_wait(),
+            _ => false,
+        }
+    }
+
+    pub fn sqlstate(&self) -> &'static str {
+        match self {
+            Error::Parse(_) | Error::MultipleStatements => "42601",
+            Error::UnknownType(_) => "42704",
+            Error::UnknownCollation(_) => "42704",
+            Error::UnknownCompression(_) => "42704",
+            Error::Planner(e) => planner_sqlstate(e),
+            Error::Executor(e) => executor_sqlstate(e),
+            Error::Catalog(e) => catalog_sqlstate(e),
+            Error::Transaction(e) => transaction_sqlstate(e),
+            Error::Io(_) => "58030",
+            Error::NoResultSet => "42809",
+            Error::NoRows => "P0002",
+            Error::ColumnNotFound(_) => "42703",
+            Error::InvalidColumnType { .. } => "42804",
+            Error::CopyData(_) => "22P04",
+            Error::Copy { source, .. } | Error::Many { source, .. } => source.sqlstate(),
+            Error::Settings(e) => e.sqlstate(),
+            Error::Parquet(parquet::Error::Io(_)) | Error::Sqlite(sqlite::Error::Io(_)) => "58030",
+            Error::Parquet(_) => "42804",
+            Error::EngineStopped => "08006",
+            Error::PreparedStatementNotFound(_) => "26000",
+            Error::Sqlite(_) => "XX001",
+            Error::Check(_) => "XX000",
+            Error::Blob(blob::Error::NotABlob(_)) => "42704",
+            Error::Blob(_) => "XX000",
+            Error::Import { source, .. } => source.sqlstate(),
+            Error::InsufficientPrivilege(_) => "42501",
+            Error::AuthenticationFailed(_) => "28P01",
+            Error::LoginNotAllowed(_) => "28000",
+            Error::DatabaseExists(_) => "42P04",
+            Error::DatabaseNotFound(_) => "3D000",
+            Error::InvalidDatabaseName(_) => "42602",
+            Error::NoCluster => "0A000",
+            Error::AttachmentExists(_) => "42710",
+            Error::AttachmentNotFound(_) => "3D000",
+            Error::AttachSelf => "55000",
+            Error::ReadOnlyAttachment(_) => "25006",
+            Error::BulkLoadNotEmpty(_) => "55000",
+            Error::BulkCopyTo => "0A000",
+            Error::AddColumnOptions(_) | Error::PrepareSchemaChange => "0A000",
+            Error::SchemaConflict => "40001",
+            Error::Recovery(_) => "XX000",
+            Error::BrokenDatabaseFile => "XX001",
+            Error::DatabaseInUse => "55006",
+            Error::NoDatabaseFile => "0A000",
+            Error::Replication(replication::Error::Io(_)) => "08006",
+            Error::Replication(_) => "XX000",
+            Error::ReadOnlyStandby => "25006",
+            Error::NotStandby => "55000",
+        }
+    }
+}
+
+fn planner_sqlstate(e: &planner::Error) -> &'static str {
+    match e {
+        planner::Error::Executor(e) => executor_sqlstate(e),
+        planner::Error::Parse(_) => "42601",
+        planner::Error::TableNotFound(_) => "42P01",
+        planner::Error::ColumnNotFound(_) => "42703",
+        planner::Error::AmbiguousColumn(_) => "42702",
+        planner::Error::FunctionNotFound(_) => "42883",
+        planner::Error::ParameterNotFound(_) => "42P02",
+        planner::Error::AggregateNotAllowed(_)
+        | planner::Error::NestedAggregate
+        | planner::Error::WindowNotAllowed(_)
+        | planner::Error::NestedWindow
+        | planner::Error::NotGrouped(_)
+        | planner::Error::GroupingArgument => "42803",
+        planner::Error::SetReturningNotAllowed(_) | planner::Error::NestedSetReturning => "0A000",
+        planner::Error::MaterializedView(_)
+        | planner::Error::SampleNotTable
+        | planner::Error::AsOfNotTable => "42809",
+        planner::Error::SampleNull(_) => "22023",
+        planner::Error::SampleNotNumber(..) => "42804",
+        planner::Error::SamplePercent => "2202H",
+        planner::Error::Percentile(_) => "22003",
+        planner::Error::PermissionDenied(_) => "42501",
+        planner::Error::GeneratedColumn(_) => "428C9",
+        planner::Error::VolatileGenerationExpression => "42P17",
+        planner::Error::Attached { sqlstate, .. } => sqlstate,
+        _ => "42000",
+    }
+}
+
+fn executor_sqlstate(e: &executor::Error) -> &'static str {
+    match e {
+        executor::Error::Catalog(e) => catalog_sqlstate(e),
+        executor::Error::TableNotFound(_) => "42P01",
+        executor::Error::ReadOnly(_) => "25006",
+        executor::Error::DivisionByZero => "22012",
+        executor::Error::IntegerOutOfRange
+        | executor::Error::NumericOutOfRange
+        | executor::Error::FloatOutOfRange => "22003",
+        executor::Error::DateOutOfRange => "22008",
+        executor::Error::IntervalOutOfRange => "22015",
+        executor::Error::InvalidSyntax { .. } => "22P02",
+        executor::Error::UndefinedType(_) | executor::Error::UndefinedCollation(_) => "42704",
+        executor::Error::UndefinedOperator { .. }
+        | executor::Error::UndefinedUnaryOperator { .. }
+        | executor::Error::UndefinedFunction { .. } => "42883",
+        executor::Error::NotBoolean(_) => "42804",
+        executor::Error::SerializationFailure => "40001",
+        executor::Error::Lock(e) => lock_sqlstate(e),
+        executor::Error::QueryCanceled => "57014",
+        executor::Error::StatementTimeout => "57014",
+        executor::Error::ResultRowLimit(_) | executor::Error::RecursionDepthLimit(_) => "54000",
+        executor::Error::TempFileLimit(_) => "53400",
+        executor::Error::QueryMemoryLimit(_) => "53200",
+        executor::Error::SubqueryRows => "21000",
+        executor::Error::TriggerFailed { .. } => "P0001",
+        executor::Error::Trigger { source, .. } => planner_sqlstate(source),
+        executor::Error::TriggerDepth(_) => "54001",
+        executor::Error::Transaction(e) => transaction_sqlstate(e),
+        executor::Error::AsOfNull => "22004",
+        executor::Error::DuplicateCursor(_) => "42P03",
+        executor::Error::CursorNotFound(_) => "34000",
+        _ => "XX000",
+    }
+}
+
+fn transaction_sqlstate(e: &transaction::Error) -> &'static str {
+    match e {
+        transaction::Error::AlreadyInTransaction => "25001",
+        transaction::Error::NoTransaction => "25P01",
+        transaction::Error::SerializationFailure => "40001",
+        transaction::Error::InTransactionBlock(_) => "25001",
+        transaction::Error::NoVersionRetention | transaction::Error::NoWal => "55000",
+        transaction::Error::SnapshotTooOld(_) => "72000",
+        transaction::Error::FutureSnapshot(_) => "22023",
+        transaction::Error::Catalog(e) => catalog_sqlstate(e),
+        transaction::Error::Lock(e) => lock_sqlstate(e),
+        _ => "XX000",
+    }
+}
+
+fn lock_sqlstate(e: &lock::Error) -> &'static str {
+    match e {
+        lock::Error::DeadlockDetected => "40P01",
+        lock::Error::LockNotAvailable | lock::Error::LockTimeout | lock::Error::WouldBlock => {
+            "55P03"
+        }
+    }
+}
+
+fn catalog_sqlstate(e: &catalog::Error) -> &'static str {
+    match e {
+        catalog::Error::TableExists(_) | catalog::Error::IndexExists(_) => "42P07",
+        catalog::Error::TableNotFound(_) => "42P01",
+        catalog::Error::ColumnNotFound(_) => "42703",
+        catalog::Error::DuplicateColumn(_) => "42701",
+        catalog::Error::FunctionExists(_) => "42723",
+        catalog::Error::StorageEngineNotFound(_) => "42704",
+        catalog::Error::StorageEngineExists(_) => "42710",
+        catalog::Error::UniqueViolation(_) => "23505",
+        catalog::Error::NotNullViolation { .. } => "23502",
+        catalog::Error::DatatypeMismatch { .. } => "42804",
+        catalog::Error::NumericFieldOverflow(_) | catalog::Error::IntegerOutOfRange(_) => "22003",
+        catalog::Error::InvalidSyntax { .. } => "22P02",
+        catalog::Error::NotTextColumn(_)
+        | catalog::Error::NotGeometricColumn(_)
+        | catalog::Error::DictionaryType(_) => "42804",
+        catalog::Error::RTreeColumnCount | catalog::Error::PartitionedIndex(_) => "0A000",
+        catalog::Error::NotPartitioned(_) => "42809",
+        catalog::Error::PartitionStrategyMismatch(_)
+        | catalog::Error::EmptyRange(_)
+        | catalog::Error::PartitionOverlap(..) => "42P17",
+        catalog::Error::InvalidModulus | catalog::Error::InvalidRemainder => "22023",
+        catalog::Error::NoPartition(_) | catalog::Error::PartitionConstraint(_) => "23514",
+        catalog::Error::NotTimestampColumn(_) => "42804",
+        catalog::Error::InvalidTtl => "22023",
+        catalog::Error::NotMaterializedView(_) => "42809",
+        catalog::Error::ViewColumnsChanged(_) => "42804",
+        catalog::Error::TriggerExists(..) => "42710",
+        catalog::Error::TriggerNotFound(..) => "42704",
+        catalog::Error::TriggerFunctionNotFound(_) => "42883",
+        catalog::Error::RoleExists(_) => "42710",
+        catalog::Error::RoleNotFound(_) => "42704",
+        catalog::Error::RoleHasPrivileges(_) => "2BP01",
+        catalog::Error::GeneratedColumnReference(_) => "42P17",
+        catalog::Error::VirtualColumnIndex(_) => "0A000",
+        catalog::Error::GeneratedColumnDependency { .. } => "2BP01",
+        catalog::Error::TypeExists(_) | catalog::Error::EnumLabelExists(_) => "42710",
+        catalog::Error::TypeNotFound(_) => "42704",
+        catalog::Error::EnumLabelNotFound(_) => "22023",
+        catalog::Error::TypeInUse(_) => "2BP01",
+        catalog::Error::UnknownTableOption(_) | catalog::Error::InvalidTableOption { .. } => {
+            "22023"
+        }
+        catalog::Error::PartitionColumn => "42P16",
+        catalog::Error::Expression(e) => executor_sqlstate(e),
+        _ => "XX000",
+    }
+}
+
+// 埋め込んで使うデータベース
+//
+// connect で作った接続はバッファプールとカタログを分け合い、それぞれが自分のトランザクションを持つ。
+// ファイルを開いたときは、カタログもファイルに書き、ログを横のディレクトリに書く。
+// 何を残すかは persist に書いた
+pub struct Database {
+    engine: Rc<RefCell<Engine>>,
+    txns: TransactionManager,
+    // 接続の設定の既定の値
+    settings: Settings,
+}
+
+struct Engine {
+    bufmgr: BufferPoolManager,
+    catalog: Catalog,
+    slow_log: Option<SlowQueryLog>,
+    activity: Activity,
+    io: IoStats,
+    // 次に接続につける番号
+    next_session: u64,
+    // CREATE DATABASE で新しいデータベースを加える集まり
+    cluster: Option<Weak<RefCell<Databases>>>,
+    result_cache: ResultCache,
+    // ファイルを開いたときの、カタログを書くところ
+    file: Option<DatabaseFile>,
+    // ファイルから開いたときの heap のファイル。オンラインバックアップで写す
+    path: Option<PathBuf>,
+    // スタンバイなら、プライマリから受け取ったログを当てるところ
+    standby: Option<StandbyFile>,
+    // コミットしてもログを flush せず、flush_commits でまとめてディスクに届ける
+    group_commit: bool,
+    // グループコミットで、まだディスクに届けていないログがある
+    deferred: bool,
+}
+
+impl Engine {
+    // ファイルのデータベースなら、カタログが変わっていればページに書く
+    fn save_catalog(&mut self) -> Result<(), Error> {
+        match &mut self.file {
+            Some(file) => file.save(&mut self.bufmgr, &self.catalog),
+            None => Ok(()),
+        }
+    }
+
+    // ファイルのデータベースなら、カタログとページの像をログに書いてディスクに届け、ページを書き出す。
+    // グループコミットなら、ログに書くだけにして、ディスクに届けるのは flush_commits まで延ばす
+    fn persist(&mut self) -> Result<(), Error> {
+        if !self.group_commit {
+            return self.flush_commits();
+        }
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.save(&mut self.bufmgr, &self.catalog)?;
+        self.bufmgr.log_images().map_err(executor::Error::from)?;
+        if let Some(wal) = self.bufmgr.wal() {
+            self.deferred |= wal.flushed_lsn() < wal.next_lsn();
+        }
+        Ok(())
+    }
+
+    // 書いたログをディスクに届けてからページを書き出す。ログが進んでいればチェックポイントも書く
+    fn flush_commits(&mut self) -> Result<(), Error> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.save(&mut self.bufmgr, &self.catalog)?;
+        let bufmgr = &mut self.bufmgr;
+        bufmgr.log_images().map_err(executor::Error::from)?;
+        if let Some(wal) = bufmgr.wal_mut() {
+            let end = wal.next_lsn();
+            if wal.flushed_lsn() < end {
+                wal.flush(end).map_err(executor::Error::from)?;
+            }
+        }
+        bufmgr.write_logged().map_err(executor::Error::from)?;
+        self.deferred = false;
+        file.checkpoint(bufmgr)
+    }
+
+    // ファイルのデータベースを dest に写す。previous があれば、そのバックアップのあとのログだけを写す
+    fn backup(&mut self, previous: Option<&Path>, dest: &Path) -> Result<BackupLabel, Error> {
+        let Some(heap) = self.path.clone() else {
+            return Err(Error::NoDatabaseFile);
+        };
+        // 応答を待たせているコミットも写す
+        self.flush_commits()?;
+        match (previous, &mut self.file) {
+            (_, None) => Err(Error::NoDatabaseFile),
+            (Some(previous), Some(_)) => Ok(recovery::incremental_backup(
+                &mut self.bufmgr,
+                previous,
+                dest,
+            )?),
+            (None, Some(file)) => file.backup(&mut self.bufmgr, &heap, dest),
+        }
+    }
+
+    // スタンバイで、プライマリから受け取ったレコードを当てる。None なら送るものがなくなったところ。
+    // ページの像を当て終えたところでカタログが変わっていれば読み直し、ためた結果はどれも捨てる
+    fn replay(
+        &mut self,
+        txns: &TransactionManager,
+        received: Option<(Lsn, LogRecord)>,
+    ) -> Result<(), Error> {
+        let Some(standby) = &mut self.standby else {
+            return Err(Error::NotStandby);
+        };
+        if let Some((lsn, record)) = &received {
+            if standby.apply(&mut self.bufmgr, *lsn, record)? {
+                txns.set_standby(Some(standby.view().clone()));
+            }
+            self.result_cache.clear();
+            if let LogRecord::PageImage { .. } | LogRecord::PageDelta { .. } = record {
+                return Ok(());
+            }
+        }
+        if let Some(catalog) = standby.reload(&mut self.bufmgr) {
+            self.install_catalog(catalog);
+        }
+        Ok(())
+    }
+
+    // ページから読み直したカタログに替える。版は進め、前のカタログで立てた計画は使わせない
+    fn install_catalog(&mut self, mut catalog: Catalog) {
+        let base = catalog.schema_version();
+        catalog.replace(&self.catalog, base);
+        self.catalog = catalog;
+    }
+
+    fn exec_context<'a>(&'a mut self, session: &'a mut Session) -> ExecContext<'a> {
+        let mut ctx = session.exec_context(&mut self.bufmgr, &self.catalog);
+        ctx.activity = Some(&self.activity);
+        ctx.io = Some(&self.io);
+        ctx.read_only = self.standby.is_some();
+        ctx
+    }
+
+    fn cursor_context<'a>(&'a mut self, session: &'a mut Session) -> ExecContext<'a> {
+        let mut ctx = session.cursor_context(&mut self.bufmgr, &self.catalog);
+        ctx.activity = Some(&self.activity);
+        ctx.io = Some(&self.io);
+        ctx.read_only = self.standby.is_some();
+        ctx
+    }
+}
+
+impl Database {
+    // path のファイルを開く。なければ作る
+    pub fn open(path: impl AsRef<Path>) -> Result<Database, Error> {
+        Database::open_with(path, Settings::default())
+    }
+
+    // 開いてすぐ消した一時ファイルを使う。閉じれば何も残らない
+    pub fn open_temporary() -> Result<Database, Error> {
+        Database::open_temporary_with(Settings::default())
+    }
+
+    // settings で開く。Session の設定は接続ごとの既定の値になる
+    pub fn open_with(path: impl AsRef<Path>, settings: Settings) -> Result<Database, Error> {
+        Database::open_file(path.as_ref(), None, settings)
+    }
+
+    // Connection::backup で写したディレクトリを開き、写し終えた時点でコミットしていた状態に戻す。
+    // 開いたあとはそのディレクトリのファイルに書く
+    pub fn open_backup(backup: impl AsRef<Path>, settings: Settings) -> Result<Database, Error> {
+        let (heap, wal_dir) = recovery::backup_files(backup.as_ref())?;
+        Database::open_file(&heap, Some(&wal_dir), settings)
+    }
+
+    // base のバックアップに increments の増分バックアップを古い順につなげて dest に戻し、開く
+    pub fn restore_incremental(
+        base: impl AsRef<Path>,
+        increments: &[&Path],
+        dest: impl AsRef<Path>,
+        settings: Settings,
+    ) -> Result<Database, Error> {
+        recovery::restore_incremental(base.as_ref(), increments, dest.as_ref())?;
+        Database::open_backup(dest, settings)
+    }
+
+    // Connection::backup で写したディレクトリをスタンバイとして開く。終わっていないトランザクションは取り消さず、
+    // replay でプライマリから受け取ったログを当て続ける。接続は読むだけの文しか実行できない
+    pub fn open_standby(backup: impl AsRef<Path>, settings: Settings) -> Result<Database, Error> {
+        let (heap, wal_dir) = recovery::backup_files(backup.as_ref())?;
+        let file = OpenOptions::new().read(true).write(true).open(&heap)?;
+        let db = Database::from_disk(DiskManager::new(file)?, settings)?;
+        let wal = Wal::open(&wal_dir).map_err(recovery::Error::from)?;
+        {
+            let engine = &mut *db.engine.borrow_mut();
+            let (catalog, standby) = persist::open_standby(&mut engine.bufmgr, wal)?;
+            db.txns.set_standby(Some(standby.view().clone()));
+            engine.catalog = catalog;
+            engine.standby = Some(standby);
+            engine.path = Some(heap.canonicalize()?);
+        }
+        Ok(db)
+    }
+
+    // path のファイルを wal_dir のログで開く。None ならログは path-wal のディレクトリに書く
+    fn open_file(
+        path: &Path,
+        wal_dir: Option<&Path>,
+        settings: Settings,
+    ) -> Result<Database, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let path = path.canonicalize()?;
+        let wal_dir = match wal_dir {
+            Some(wal_dir) => wal_dir.to_path_buf(),
+            None => {
+                let mut wal_dir = path.clone().into_os_string();
+                wal_dir.push("-wal");
+                PathBuf::from(wal_dir)
+            }
+        };
+        let db = Database::open_disk(DiskManager::new(file)?, &wal_dir, settings)?;
+        db.engine.borrow_mut().path = Some(path.clone());
+        OPEN.with_borrow_mut(|open| {
+            open.retain(|o| o.engine.strong_count() > 0);
+            open.push(OpenDatabase {
+                path,
+                engine: Rc::downgrade(&db.engine),
+                txns: db.txns.session(),
+                settings: db.settings.clone(),
+            });
+        });
+        Ok(db)
+    }
+
+    // disk のページと wal_dir のログでファイルのデータベースを開く
+    pub(crate) fn open_disk(
+        disk: DiskManager,
+        wal_dir: &Path,
+        settings: Settings,
+    ) -> Result<Database, Error> {
+        let mut db = Database::from_disk(disk, settings)?;
+        let wal = Wal::open(wal_dir).map_err(recovery::Error::from)?;
+        {
+            let engine = &mut *db.engine.borrow_mut();
+            let (catalog, file) = persist::open(
+                &mut engine.bufmgr,
+                wal,
+                &mut db.txns,
+                db.settings.checkpoint_wal_size,
+            )?;
+            engine.catalog = catalog;
+            engine.file = Some(file);
+        }
+        Ok(db)
+    }
+
+    // ページをすべて書き出してチェックポイントを書き、次に開いたときに回復しなくてよいと記す。
+    // drop しても同じことをするが、失敗したかどうかはこちらでしかわからない。接続が残っていれば閉じない
+    pub fn close(self) -> Result<(), Error> {
+        if Rc::strong_count(&self.engine) > 1 {
+            return Err(Error::DatabaseInUse);
+        }
+        let engine = &mut *self.engine.borrow_mut();
+        match engine.file.take() {
+            Some(mut file) => file.close(&mut engine.bufmgr, &engine.catalog),
+            None => Ok(()),
+        }
+    }
+
+    // 閉じずに落ちたことにする。ページもカタログも書き出さず、ログのディスクに届いた位置を返す
+    #[cfg(test)]
+    pub(crate) fn crash(self) -> Option<crate::wal::Lsn> {
+        let flushed = self
+            .engine
+            .borrow()
+            .bufmgr
+            .wal()
+            .map(|wal| wal.flushed_lsn());
+        std::mem::forget(self);
+        flushed
+    }
+
+    // このスレッドで path のファイルを開いていれば、そのデータベース
+    fn find_open(path: &Path) -> Option<Database> {
+        let path = path.canonicalize().ok()?;
+        OPEN.with_borrow(|open| {
+            let o = open.iter().find(|o| o.path == path)?;
+            Some(Database {
+                engine: o.engine.upgrade()?,
+                txns: o.txns.session(),
+                settings: o.settings.clone(),
+            })
+        })
+    }
+
+    // ファイルを使わず、ページをメモリに置く。wasm32 でも動く
+    pub fn open_in_memory() -> Result<Database, Error> {
+        Database::open_backend_with(Box::new(MemoryDisk::new()), Settings::default())
+    }
+
+    // backend にページを置く。IndexedDB などに置くときに使う
+    pub fn open_backend_with(
+        backend: Box<dyn DiskBackend>,
+        settings: Settings,
+    ) -> Result<Database, Error> {
+        Database::from_disk(DiskManager::with_backend(backend)?, settings)
+    }
+
+    // wasm32 には一時ファイルを置くところがないので、メモリに置く
+    #[cfg(target_arch = "wasm32")]
+    pub fn open_temporary_with(settings: Settings) -> Result<Database, Error> {
+        Database::open_backend_with(Box::new(MemoryDisk::new()), settings)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open_temporary_with(settings: Settings) -> Result<Database, Error> {
+        let path = temporary_path();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        std::fs::remove_file(&path)?;
+        Database::from_disk(DiskManager::new(file)?, settings)
+    }
+
+    pub(crate) fn from_disk(disk: DiskManager, settings: Settings) -> Result<Database, Error> {
+        let pool = BufferPool::new(settings.buffer_pool_size);
+        let mut txns = TransactionManager::new();
+        txns.set_version_retention(settings.version_retention);
+        Ok(Database {
+            engine: Rc::new(RefCell::new(Engine {
+                bufmgr: BufferPoolManager::new(disk, pool),
+                catalog: Catalog::new(),
+                slow_log: None,
+                activity: Activity::default(),
+                io: IoStats::default(),
+                next_session: 1,
+                cluster: None,
+                result_cache: ResultCache::new(),
+                file: None,
+                path: None,
+                standby: None,
+                group_commit: false,
+                deferred: false,
+            })),
+            txns,
+            settings,
+        })
+    }
+
+    // コミットを記してもログを flush せず、flush_commits を呼ぶまで応答を待たせる。
+    // サーバーはこうして、同じころに来たほかの接続のコミットと 1 回の fsync でディスクに届ける
+    pub fn set_group_commit(&self, on: bool) {
+        let engine = &mut *self.engine.borrow_mut();
+        engine.group_commit = on;
+        if let Some(wal) = engine.bufmgr.wal_mut() {
+            wal.set_group_commit(on);
+        }
+    }
+
+    // グループコミットで、まだディスクに届けていないコミットがある
+    pub fn unflushed_commits(&self) -> bool {
+        self.engine.borrow().deferred
+    }
+
+    // グループコミットでためたログをディスクに届け、ページを書き出す
+    pub fn flush_commits(&self) -> Result<(), Error> {
+        self.engine.borrow_mut().flush_commits()
+    }
+
+    // ファイルのデータベースのログを、listener につないできたスタンバイに送る。送るのはディスクに届いたレコードだけ
+    pub fn serve_replication(&self, listener: TcpListener) -> Result<JoinHandle<()>, Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        let (Some(_), Some(wal)) = (&engine.file, engine.bufmgr.wal_mut()) else {
+            return Err(Error::NoDatabaseFile);
+        };
+        let dir = wal.dir().to_path_buf();
+        Ok(replication::serve(listener, dir, wal.flushed_watch()))
+    }
+
+    // スタンバイで、Receiver が受け取ったものを当てる。None は送るものがなくなったところで、
+    // そこで受け取ったカタログを読み直す
+    pub fn replay(&self, received: Option<(Lsn, LogRecord)>) -> Result<(), Error> {
+        self.engine.borrow_mut().replay(&self.txns, received)
+    }
+
+    // スタンバイで、プライマリのログの lsn までを当て終わるまで受け取る
+    pub fn catch_up(&self, receiver: &mut Receiver, lsn: Lsn) -> Result<(), Error> {
+        while self.standby_lsn().ok_or(Error::NotStandby)? < lsn {
+            self.replay(receiver.receive()?)?;
+        }
+        self.replay(None)
+    }
+
+    // スタンバイが当て終えたログの位置。スタンバイでなければ None
+    pub fn standby_lsn(&self) -> Option<Lsn> {
+        let engine = self.engine.borrow();
+        engine.standby.as_ref().map(StandbyFile::applied_lsn)
+    }
+
+    // ディスクに届いたログの位置。スタンバイの standby_lsn と比べれば遅れがわかる
+    pub fn flushed_lsn(&self) -> Option<Lsn> {
+        let engine = self.engine.borrow();
+        engine.bufmgr.wal().map(|wal| wal.flushed_lsn())
+    }
+
+    // スタンバイを昇格させる。コミットを受け取っていないトランザクションを取り消し、書き込みを受け付ける
+    pub fn promote(&self) -> Result<(), Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        if engine.standby.take().is_none() {
+            return Err(Error::NotStandby);
+        }
+        self.txns.set_standby(None);
+        let (catalog, file) = persist::promote(
+            &mut engine.bufmgr,
+            &mut self.txns.session(),
+            self.settings.checkpoint_wal_size,
+        )?;
+        engine.install_catalog(catalog);
+        engine.file = Some(file);
+        Ok(())
+    }
+
+    // ログを fsync した回数
+    #[cfg(test)]
+    pub(crate) fn wal_syncs(&self) -> u64 {
+        self.engine
+            .borrow()
+            .bufmgr
+            .wal()
+            .map_or(0, |wal| wal.syncs())
+    }
+
+    pub(crate) fn set_cluster(&self, cluster: Weak<RefCell<Databases>>) {
+        self.engine.borrow_mut().cluster = Some(cluster);
+    }
+
+    // すべての接続の遅い文を log に書く。None なら書かない
+    pub fn set_slow_query_log(&self, log: Option<SlowQueryLog>) {
+        self.engine.borrow_mut().slow_log = log;
+    }
+
+    pub fn connect(&self) -> Connection {
+        let id = {
+            let mut engine = self.engine.borrow_mut();
+            let id = engine.next_session;
+            engine.next_session += 1;
+            engine.activity.connect(id);
+            id
+        };
+        Connection {
+            engine: self.engine.clone(),
+            session: Session::new(self.txns.session(), self.settings.clone()),
+            id,
+            attachments: vec![],
+        }
+    }
+}
+
+// 自動の VACUUM と ANALYZE で 1 つのテーブルにしたこと
+#[derive(Debug, Clone, PartialEq)]
+pub struct Maintenance {
+    pub table: String,
+    // VACUUM したなら、取り除いた版の数
+    pub vacuumed: Option<usize>,
+    pub analyzed: bool,
+}
+
+impl Database {
+    // 最後に VACUUM してから消した版か、最後に ANALYZE してから書き換えた行が閾値を超えたテーブルのうち、
+    // いちばん超えたもの 1 つを VACUUM か ANALYZE する。することがなければ None
+    //
+    // 閾値は autovacuum_*_threshold に行数と autovacuum_*_scale_factor の積を足したもの。
+    // autovacuum_enabled = false のテーブルと、パーティションに分けたテーブルの親は飛ばす。
+    // 古いトランザクションが残っていると VACUUM しても版が減らないので、
+    // 同じテーブルは autovacuum_naptime が過ぎるまでもう一度はしない。スタンバイでは何もしない
+    pub fn autovacuum(&self) -> Result<Option<Maintenance>, Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        if engine.standby.is_some() {
+            return Ok(None);
+        }
+        let settings = &self.settings;
+        let now = Timestamp::now();
+        let naptime = settings.autovacuum_naptime.as_micros() as i128;
+        let due =
+            |last: Option<Timestamp>| last.is_none_or(|t| now.since(t).total_micros() >= naptime);
+        let excess = |count: u64, threshold: u64, scale_factor: f64, rows: usize| {
+            count as f64 - (threshold as f64 + scale_factor * rows as f64)
+        };
+        let mut target: Option<(&str, f64, bool, bool)> = None;
+        for table in engine.catalog.tables() {
+            if !table.autovacuum
+                || table.partitioning.is_some()
+                || self.txns.table_locked(&table.name)
+            {
+                continue;
+            }
+            let rows = table.row_count();
+            let vacuum = excess(
+                table.dead_versions(),
+                settings.autovacuum_vacuum_threshold,
+                settings.autovacuum_vacuum_scale_factor,
+                rows,
+            );
+            let analyze = excess(
+                table.changes_since_analyze(),
+                settings.autovacuum_analyze_threshold,
+                settings.autovacuum_analyze_scale_factor,
+                rows,
+            );
+            let vacuum = (vacuum > 0.0 && due(table.last_autovacuum.get())).then_some(vacuum);
+            let analyze = (analyze > 0.0 && due(table.last_autoanalyze.get())).then_some(analyze);
+            let Some(score) = vacuum.into_iter().chain(analyze).reduce(f64::max) else {
+                continue;
+            };
+            if target.is_none_or(|(_, best, _, _)| score > best) {
+                target = Some((&table.name, score, vacuum.is_some(), analyze.is_some()));
+            }
+        }
+        let Some((name, _, vacuum, analyze)) = target else {
+            return Ok(None);
+        };
+        let name = name.to_string();
+        let mut maintenance = Maintenance {
+            table: name.clone(),
+            vacuumed: None,
+            analyzed: false,
+        };
+        if vacuum {
+            let horizon = self.txns.horizon();
+            let locked = |table: &str| self.txns.table_locked(table);
+            let catalog = &engine.catalog;
+            let removed = catalog.vacuum(&mut engine.bufmgr, Some(&name), horizon, locked)?;
+            maintenance.vacuumed = Some(removed);
+            METRICS.autovacuums.inc();
+        }
+        if analyze {
+            engine.catalog.analyze(&mut engine.bufmgr, Some(&name))?;
+            maintenance.analyzed = true;
+            METRICS.autoanalyzes.inc();
+        }
+        let table = engine.catalog.table(&name).unwrap();
+        if vacuum {
+            table.last_autovacuum.set(Some(now));
+        }
+        if analyze {
+            table.last_autoanalyze.set(Some(now));
+        }
+        Ok(Some(maintenance))
+    }
+}
+
+// ファイルから開いたデータベース。ATTACH は開いているものにはそのままつなぐ
+struct OpenDatabase {
+    path: PathBuf,
+    engine: Weak<RefCell<Engine>>,
+    txns: TransactionManager,
+    settings: Settings,
+}
+
+thread_local! {
+    static OPEN: RefCell<Vec<OpenDatabase>> = const { RefCell::new(vec![]) };
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn temporary_path() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "rdbms-training-{}-{}.db",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+// データベースへの接続
+//
+// 明示的なトランザクションの外で実行した文は、文ごとにトランザクションを始めて終える。
+// CREATE TABLE などの DDL はトランザクションに関係なくすぐにカタログを変える。
+// トランザクションや準備した文など接続ごとの状態は Session に持つ
+//
+// ATTACH 'path' AS alias でつないだデータベースのテーブルは alias.table で読み書きでき、自分のテーブルは main.table でも指せる。
+// つないだデータベースには専用の接続をひとつ作り、その superuser として読み書きする。
+// つないだテーブルは文ごとにその時点でコミットしている行をすべて読み、自分のトランザクションのスナップショットとは関係しない。
+// 書き込みはつないだ先で文ごとにコミットするので、明示的なトランザクションの中ではできない。READ_ONLY でつなぐと書き込めない。
+// ATTACH と DETACH はこの接続だけに効き、トランザクションを取り消しても戻らない
+pub struct Connection {
+    engine: Rc<RefCell<Engine>>,
+    session: Session,
+    // stats.activity に出す接続の番号
+    id: u64,
+    attachments: Vec<Attachment>,
+}
+
+// ATTACH でつないだデータベース
+struct Attachment {
+    alias: String,
+    conn: RefCell<Connection>,
+    read_only: bool,
+}
+
+// 文を実行した結果
+#[derive(Debug)]
+pub enum StatementResult {
+    Rows(Rows),
+    // INSERT、UPDATE、DELETE で書き換えた行の数
+    Modified { command: &'static str, rows: usize },
+    // DDL やトランザクションの文。PostgreSQL のコマンドタグ
+    Done(&'static str),
+}
+
+impl StatementResult {
+    // PostgreSQL の CommandComplete と同じ形のタグ
+    pub fn tag(&self) -> String {
+        match self {
+            StatementResult::Rows(rows) => format!("SELECT {}", rows.len()),
+            StatementResult::Modified {
+                command: "INSERT",
+                rows,
+            } => format!("INSERT 0 {}", rows),
+            StatementResult::Modified { command, rows } => format!("{} {}", command, rows),
+            StatementResult::Done(tag) => tag.to_string(),
+        }
+    }
+}
+
+// 実行する前にわかる、文の引数と結果の列
+#[derive(Debug, Clone, PartialEq)]
+pub struct Description {
+    // $n の型。n - 1 番目が $n のもので、文脈から決まらないものは None
+    pub params: Vec<Option<DataType>>,
+    // 結果の列の名前と型。行を返さない文なら None
+    pub columns: Option<Vec<(String, Option<DataType>)>>,
+}
+
+impl Connection {
+    // 1 つの文を実行し、書き換えた行の数を返す。問い合わせなら返した行の数
+    pub fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize, Error> {
+        let mut span = trace::span("statement", &[("sql", &sql)]);
+        let count = match self.execute_parsed(sql, &parse_one(sql)?, params)? {
+            StatementResult::Rows(rows) => rows.len(),
+            StatementResult::Modified { rows, .. } => rows,
+            StatementResult::Done(_) => 0,
+        };
+        span.record("rows", count);
+        Ok(count)
+    }
+
+    // ; で区切った文を順に実行する。エラーになればそこで止める
+    pub fn execute_batch(&mut self, sql: &str) -> Result<(), Error> {
+        for stmt in sql::parse(sql)? {
+            self.execute_parsed(sql, &stmt, &[])?;
+        }
+        Ok(())
+    }
+
+    // sql を param_sets の値の組ごとに実行し、書き換えた行の数の合計を返す。
+    // 文は一度だけ解析し、どれかの組で失敗すれば 1 つも反映しない。
+    // RETURNING と DO UPDATE のない INSERT ... VALUES は一度だけ計画し、MANY_BATCH 組ずつ
+    // ひとつの VALUES にまとめて入れる
+    pub fn execute_many<P: ToParams>(
+        &mut self,
+        sql: &str,
+        param_sets: impl IntoIterator<Item = P>,
+    ) -> Result<usize, Error> {
+        let mut span = trace::span("statement", &[("sql", &sql)]);
+        let stmt = parse_one(sql)?;
+        let mut param_sets = param_sets.into_iter().map(|params| params.to_params());
+        let count = self.atomic(MANY_SAVEPOINT, |conn| {
+            conn.execute_sets(sql, &stmt, &mut param_sets)
+        })?;
+        span.record("rows", count);
+        Ok(count)
+    }
+
+    fn execute_sets(
+        &mut self,
+        sql: &str,
+        stmt: &Statement,
+        param_sets: &mut dyn Iterator<Item = Vec<Value>>,
+    ) -> Result<usize, Error> {
+        let context = |index, source: Error| Error::Many {
+            index,
+            source: Box::new(source),
+        };
+        let values = match stmt {
+            Statement::Insert(Insert {
+                source: InsertSource::Values(values),
+                on_conflict:
+                    None
+                    | Some(OnConflict {
+                        action: ConflictAction::Nothing,
+                        ..
+                    }),
+                returning,
+                ..
+            }) if returning.is_empty() => values,
+            _ => {
+                let mut count = 0;
+                for (index, params) in param_sets.enumerate() {
+                    count += match self.execute_parsed(sql, stmt, &params) {
+                        Ok(StatementResult::Rows(rows)) => rows.len(),
+                        Ok(StatementResult::Modified { rows, .. }) => rows,
+                        Ok(StatementResult::Done(_)) => 0,
+                        Err(e) => return Err(context(index, e)),
+                    };
+                }
+                return Ok(count);
+            }
+        };
+        let mut plan = None;
+        let mut batch = vec![];
+        let mut count = 0;
+        for (index, params) in param_sets.enumerate() {
+            let rows = {
+                let engine = self.engine.borrow();
+                let planner = self.session.planner(&engine.catalog).with_params(&params);
+                if plan.is_none() {
+                    plan = Some(
+                        planner
+                            .plan_statement(stmt)
+                            .map_err(|e| context(index, e.into()))?,
+                    );
+                }
+                planner
+                    .plan_values(values)
+                    .map_err(|e| context(index, e.into()))?
+            };
+            batch.push((index, rows));
+            if batch.len() == MANY_BATCH {
+                let plan = plan.as_mut().unwrap();
+                count += self
+                    .insert_values(plan, std::mem::take(&mut batch))
+                    .map_err(|(index, e)| context(index, e))?;
+            }
+        }
+        if let Some(plan) = &mut plan {
+            count += self
+                .insert_values(plan, batch)
+                .map_err(|(index, e)| context(index, e))?;
+        }
+        Ok(count)
+    }
+
+    // 計画の VALUES を batch の行に入れ替えて実行し、入れた行の数を返す。
+    // 失敗したら 1 組ずつ実行し直して、失敗した組の番号を返す
+    fn insert_values(
+        &mut self,
+        plan: &mut PlanNode,
+        batch: Vec<(usize, Vec<executor::Row>)>,
+    ) -> Result<usize, (usize, Error)> {
+        let Some(&(first, _)) = batch.first() else {
+            return Ok(0);
+        };
+        let sets: Vec<(usize, usize)> = batch
+            .iter()
+            .map(|(index, rows)| (*index, rows.len()))
+            .collect();
+        *values_mut(plan) = batch.into_iter().flat_map(|(_, rows)| rows).collect();
+        let e = match self.execute_plan(plan) {
+            Ok(rows) => return Ok(modified_count(&rows)),
+            Err(e) if sets.len() == 1 => return Err((first, e)),
+            Err(e) => e,
+        };
+        let mut rows = std::mem::take(values_mut(plan)).into_iter();
+        for (index, len) in sets {
+            *values_mut(plan) = rows.by_ref().take(len).collect();
+            if let Err(e) = self.execute_plan(plan) {
+                return Err((index, e));
+            }
+        }
+        Err((first, e))
+    }
+
+    fn execute_plan(&mut self, plan: &PlanNode) -> Result<Vec<executor::Row>, Error> {
+        let _span = trace::span("execute", &[]);
+        let engine = &mut *self.engine.borrow_mut();
+        let mut ctx = engine.exec_context(&mut self.session);
+        Ok(executor::execute(plan, &mut ctx)?)
+    }
+
+    // 行を返さない文は実行せずにエラーにする
+    pub fn query(&mut self, sql: &str, params: &[Value]) -> Result<Rows, Error> {
+        let mut span = trace::span("statement", &[("sql", &sql)]);
+        let stmt = parse_one(sql)?;
+        if !returns_rows(&stmt) {
+            return Err(Error::NoResultSet);
+        }
+        match self.execute_parsed(sql, &stmt, params)? {
+            StatementResult::Rows(rows) => {
+                span.record("rows", rows.len());
+                Ok(rows)
+            }
+            _ => Err(Error::NoResultSet),
+        }
+    }
+
+    // 問い合わせの最初の行
+    pub fn query_row(&mut self, sql: &str, params: &[Value]) -> Result<Row, Error> {
+        self.query(sql, params)?.next().ok_or(Error::NoRows)
+    }
+
+    // 結果の行をそれぞれ T にする。params は値の並びのほか、row_struct! の構造体やタプルでもよい
+    pub fn query_as<T: FromRow>(
+        &mut self,
+        sql: &str,
+        params: &(impl ToParams + ?Sized),
+    ) -> Result<Vec<T>, Error> {
+        self.query(sql, &params.to_params())?
+            .map(|row| T::from_row(&row))
+            .collect()
+    }
+
+    // 問い合わせの結果を少しずつ読むカーソルを name で開く。
+    // query と違って結果をためずに、fetch_cursor で読むたびに演算子を進める。
+    // トランザクションの中でしか開けず、コミットか取り消しで閉じる
+    pub fn declare_cursor(&mut self, name: &str, sql: &str, params: &[Value]) -> Result<(), Error> {
+        let stmt = parse_one(sql)?;
+        self.declare_parsed_cursor(name, &stmt, params)
+    }
+
+    pub fn declare_parsed_cursor(
+        &mut self,
+        name: &str,
+        stmt: &Statement,
+        params: &[Value],
+    ) -> Result<(), Error> {
+        if !matches!(stmt, Statement::Query(_)) {
+            return Err(Error::NoResultSet);
+        }
+        if !self.in_transaction() {
+            return Err(transaction::Error::NoTransaction.into());
+        }
+        self.with_lock_wait(|conn| {
+            conn.with_catalog(|conn| {
+                conn.session.cancel.reset();
+                let engine = &mut *conn.engine.borrow_mut();
+                let plan = conn
+                    .session
+                    .planner(&engine.catalog)
+                    .with_params(params)
+                    .plan_statement(stmt)?;
+                let mut cursors = std::mem::take(&mut conn.session.cursors);
+                let declared = {
+                    let mut ctx = engine.exec_context(&mut conn.session);
+                    cursors.declare(name, Rc::new(plan), &mut ctx)
+                };
+                conn.session.cursors = cursors;
+                Ok(declared?)
+            })
+        })
+    }
+
+    // カーソルから最大 n 行を読む。n 行より少なければ結果はもう残っていない
+    pub fn fetch_cursor(&mut self, name: &str, n: usize) -> Result<Rows, Error> {
+        self.with_lock_wait(|conn| {
+            conn.with_catalog(|conn| {
+                conn.session.cancel.reset();
+                let engine = &mut *conn.engine.borrow_mut();
+                let mut cursors = std::mem::take(&mut conn.session.cursors);
+                let fetched = match cursors.get(name) {
+                    Some(cursor) => match cursor.plan().columns(&engine.catalog) {
+                        Ok(columns) => {
+                            let mut ctx = engine.cursor_context(&mut conn.session);
+                            cursors
+                                .fetch(name, &mut ctx, n)
+                                .map(|rows| Rows::new(columns, rows))
+                                .map_err(Error::from)
+                        }
+                        Err(err) => Err(err.into()),
+                    },
+                    None => Err(executor::Error::CursorNotFound(name.to_string()).into()),
+                };
+                conn.session.cursors = cursors;
+                fetched
+            })
+        })
+    }
+
+    pub fn close_cursor(&mut self, name: &str) -> Result<(), Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        let mut cursors = std::mem::take(&mut self.session.cursors);
+        let closed = {
+            let mut ctx = engine.cursor_context(&mut self.session);
+            cursors.close(name, &mut ctx)
+        };
+        self.session.cursors = cursors;
+        Ok(closed?)
+    }
+
+    // トランザクションが終わったら、開いているカーソルをすべて閉じる
+    fn close_cursors(&mut self) -> Result<(), Error> {
+        if self.session.cursors.is_empty() {
+            return Ok(());
+        }
+        let engine = &mut *self.engine.borrow_mut();
+        let mut cursors = std::mem::take(&mut self.session.cursors);
+        let mut ctx = engine.cursor_context(&mut self.session);
+        Ok(cursors.close_all(&mut ctx)?)
+    }
+
+    pub fn execute_statement(
+        &mut self,
+        stmt: &Statement,
+        params: &[Value],
+    ) -> Result<StatementResult, Error> {
+        let start = Instant::now();
+        let mut result =
+            self.with_lock_wait(|conn| conn.with_catalog(|conn| conn.dispatch(stmt, params)));
+        if !self.in_transaction() {
+            if let Err(err) = self.close_cursors() {
+                result = result.and(Err(err));
+            }
+            self.session.end_transaction();
+            self.finish_catalog(false);
+            // トランザクションの外で変えたものは、文が終わったところでディスクに届ける
+            if let Err(err) = self.engine.borrow_mut().persist() {
+                result = result.and(Err(err));
+            }
+        }
+        // ロックを待つ文は、待ち終えて実行したときに数える
+        if result.as_ref().is_err_and(Error::is_lock_wait) {
+            return result;
+        }
+        METRICS.queries.inc();
+        if result.is_err() {
+            METRICS.query_errors.inc();
+        }
+        METRICS.query_duration.observe(start.elapsed());
+        result
+    }
+
+    // sql から解析した stmt を実行する。遅い文のログと stats.activity には sql を書く
+    pub fn execute_parsed(
+        &mut self,
+        sql: &str,
+        stmt: &Statement,
+        params: &[Value],
+    ) -> Result<StatementResult, Error> {
+        self.engine.borrow_mut().activity.start(self.id, sql);
+        let result = self.execute_logged(sql, stmt, params);
+        let in_transaction = self.in_transaction();
+        self.engine
+            .borrow_mut()
+            .activity
+            .finish(self.id, in_transaction);
+        result
+    }
+
+    // 遅い文のログがあれば、閾値より長くかかった文を書く
+    fn execute_logged(
+        &mut self,
+        sql: &str,
+        stmt: &Statement,
+        params: &[Value],
+    ) -> Result<StatementResult, Error> {
+        let min_duration = self
+            .engine
+            .borrow()
+            .slow_log
+            .as_ref()
+            .map(|log| log.min_duration);
+        let Some(min_duration) = min_duration else {
+            return self.execute_statement(stmt, params);
+        };
+        let io = |engine: &Engine| (engine.bufmgr.hits(), engine.bufmgr.reads());
+        let (hits, reads) = io(&self.engine.borrow());
+        let start = Instant::now();
+        let result = self.execute_statement(stmt, params);
+        let duration = start.elapsed();
+        let rows = match &result {
+            Ok(StatementResult::Rows(rows)) => rows.len(),
+            Ok(StatementResult::Modified { rows, .. }) => *rows,
+            Ok(StatementResult::Done(_)) => 0,
+            Err(_) => return result,
+        };
+        if duration >= min_duration {
+            let engine = &mut *self.engine.borrow_mut();
+            let (hits_after, reads_after) = io(engine);
+            if let Some(log) = &mut engine.slow_log {
+                log.log(&slowlog::Entry {
+                    sql,
+                    params,
+                    duration,
+                    rows,
+                    hits: hits_after - hits,
+                    reads: reads_after - reads,
+                });
+            }
+        }
+        result
+    }
+
+    fn dispatch(&mut self, stmt: &Statement, params: &[Value]) -> Result<StatementResult, Error> {
+        self.session.cancel.reset();
+        if let Some(command) = superuser_command(stmt) {
+            self.require_superuser(command)?;
+        }
+        if self.engine.borrow().standby.is_some() && !reads_only(stmt) {
+            return Err(Error::ReadOnlyStandby);
+        }
+        // main.table と alias.table は、名前から前を除いた文を自分かつないだ先で実行する
+        if let Some((schema, table)) = target_table(stmt).and_then(|name| name.split_once('.')) {
+            let attachment = self.attachments.iter().position(|a| a.alias == schema);
+            if schema == "main" || attachment.is_some() {
+                let stmt = rename_target(stmt, table);
+                return match attachment {
+                    Some(i) => self.write_attached(i, &stmt, params),
+                    None => self.dispatch(&stmt, params),
+                };
+            }
+        }
+        let mut held = vec![];
+        if self.in_transaction() {
+            if let Some(lock) = ddl_lock(stmt) {
+                held = self.stage_catalog(lock)?;
+            }
+        }
+        match stmt {
+            Statement::CreateTable(create) => self.create_table(create),
+            Statement::CreateIndex(create) => {
+                let result = self.create_index(create);
+                // 作り終えたら書き換えを待たせない。その間にほかの接続が入れた行は、コミットする前に作り直して入れる
+                if let Some(txn) = self.session.txns.current() {
+                    for (table, mode) in held {
+                        txn.restore_table_lock(&table, mode);
+                    }
+                }
+                result
+            }
+            Statement::CreateMaterializedView(create) => self.create_materialized_view(create),
+            Statement::RefreshMaterializedView(name) => self.refresh_materialized_view(name),
+            Statement::DropTable(drop) => {
+                let mut engine = self.engine.borrow_mut();
+                if !drop.if_exists || engine.catalog.table(&drop.name).is_some() {
+                    // パーティションに分けたテーブルは子も消える
+                    let names: Vec<String> = engine
+                        .catalog
+                        .table(&drop.name)
+                        .map(|table| {
+                            let mut tables = engine.catalog.descendants(table);
+                            tables.push(table);
+                            tables
+                                .iter()
+                                .flat_map(|t| {
+                                    std::iter::once(t.name.clone())
+                                        .chain(t.indexes.iter().map(|i| i.name.clone()))
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    engine.catalog.drop_table(&drop.name)?;
+                    for name in names {
+                        engine.io.forget(&name);
+                        engine.result_cache.invalidate(&name);
+                    }
+                }
+                Ok(StatementResult::Done("DROP TABLE"))
+            }
+            Statement::CreateTrigger(create) => self.create_trigger(create),
+            Statement::DropTrigger {
+                name,
+                table,
+                if_exists,
+            } => {
+                let catalog = &mut self.engine.borrow_mut().catalog;
+                match catalog.drop_trigger(table, name) {
+                    Err(catalog::Error::TriggerNotFound(..)) if *if_exists => {}
+                    result => result?,
+                }
+                Ok(StatementResult::Done("DROP TRIGGER"))
+            }
+            Statement::CreateDatabase(name) => {
+                if self.in_transaction() {
+                    return Err(transaction::Error::InTransactionBlock("CREATE DATABASE").into());
+                }
+                let cluster = self.engine.borrow().cluster.clone();
+                let cluster = cluster.and_then(|c| c.upgrade()).ok_or(Error::NoCluster)?;
+                cluster.borrow_mut().create(name)?;
+                Ok(StatementResult::Done("CREATE DATABASE"))
+            }
+            Statement::Attach {
+                path,
+                alias,
+                read_only,
+            } => {
+                self.attach(path, alias, *read_only)?;
+                Ok(StatementResult::Done("ATTACH"))
+            }
+            Statement::Detach(alias) => {
+                let i = self
+                    .attachments
+                    .iter()
+                    .position(|a| a.alias == *alias)
+                    .ok_or_else(|| Error::AttachmentNotFound(alias.clone()))?;
+                self.attachments.remove(i);
+                Ok(StatementResult::Done("DETACH"))
+            }
+            Statement::CreateRole { name, options } => {
+                let mut role = Role::new(name);
+                apply_role_options(&mut role, options);
+                self.engine.borrow_mut().catalog.create_role(role)?;
+                Ok(StatementResult::Done("CREATE ROLE"))
+            }
+            Statement::AlterRole { name, options } => {
+                let catalog = &mut self.engine.borrow_mut().catalog;
+                catalog.alter_role(name, |role| apply_role_options(role, options))?;
+                Ok(StatementResult::Done("ALTER ROLE"))
+            }
+            Statement::DropRole { name, if_exists } => {
+                let catalog = &mut self.engine.borrow_mut().catalog;
+                match catalog.drop_role(name) {
+                    Err(catalog::Error::RoleNotFound(_)) if *if_exists => {}
+                    result => result?,
+                }
+                Ok(StatementResult::Done("DROP ROLE"))
+            }
+            Statement::CreateType { name, labels } => {
+                let catalog = &mut self.engine.borrow_mut().catalog;
+                catalog.create_enum_type(name, labels)?;
+                Ok(StatementResult::Done("CREATE TYPE"))
+            }
+            Statement::AlterType {
+                name,
+                label,
+                position,
+                if_not_exists,
+            } => {
+                let engine = &mut *self.engine.borrow_mut();
+                let position = position
+                    .as_ref()
+                    .map(|(before, other)| (*before, &other[..]));
+                engine.catalog.add_enum_label(
+                    &mut engine.bufmgr,
+                    name,
+                    label,
+                    position,
+                    *if_not_exists,
+                )?;
+                Ok(StatementResult::Done("ALTER TYPE"))
+            }
+            Statement::AlterTable { name, action } => {
+                let engine = &mut *self.engine.borrow_mut();
+                match action {
+                    AlterTableAction::SetOptions(options) => {
+                        engine.catalog.set_table_options(name, options)?
+                    }
+                    AlterTableAction::AddColumn(def) => {
+                        add_column(&mut engine.catalog, name, def)?;
+                        engine.result_cache.invalidate(name);
+                    }
+                }
+                Ok(StatementResult::Done("ALTER TABLE"))
+            }
+            Statement::DropType { name, if_exists } => {
+                let catalog = &mut self.engine.borrow_mut().catalog;
+                match catalog.drop_type(name) {
+                    Err(catalog::Error::TypeNotFound(_)) if *if_exists => {}
+                    result => result?,
+                }
+                Ok(StatementResult::Done("DROP TYPE"))
+            }
+            Statement::Grant(grant) | Statement::Revoke(grant) => {
+                let catalog = &mut self.engine.borrow_mut().catalog;
+                // 途中で失敗して一部だけ変わらないよう、先にすべての名前を確かめる
+                for table in &grant.tables {
+                    catalog
+                        .table(table)
+                        .ok_or_else(|| catalog::Error::TableNotFound(table.clone()))?;
+                }
+                for role in &grant.roles {
+                    catalog
+                        .role(role)
+                        .ok_or_else(|| catalog::Error::RoleNotFound(role.clone()))?;
+                }
+                let revoke = matches!(stmt, Statement::Revoke(_));
+                for table in &grant.tables {
+                    for role in &grant.roles {
+                        for &privilege in &grant.privileges {
+                            match revoke {
+                                true => catalog.revoke(table, role, privilege)?,
+                                false => catalog.grant(table, role, privilege)?,
+                            }
+                        }
+                    }
+                }
+                Ok(StatementResult::Done(if revoke {
+                    "REVOKE"
+                } else {
+                    "GRANT"
+                }))
+            }
+            Statement::Transaction(txn) => {
+                if self.session.pending.as_ref().is_some_and(|p| p.changed) {
+                    match txn {
+                        TransactionStatement::Commit => self.check_catalog()?,
+                        TransactionStatement::Prepare(_) => return Err(Error::PrepareSchemaChange),
+                        _ => {}
+                    }
+                }
+                if matches!(
+                    txn,
+                    TransactionStatement::Commit | TransactionStatement::Prepare(_)
+                ) && self.in_transaction()
+                {
+                    self.settle_catalog()?;
+                }
+                let result = {
+                    let engine = &mut *self.engine.borrow_mut();
+                    self.session
+                        .txns
+                        .execute(txn, &mut engine.bufmgr, &engine.catalog)
+                };
+                if result.is_ok() {
+                    self.savepoint_catalog(txn);
+                }
+                self.finish_catalog(result.is_ok() && *txn == TransactionStatement::Commit);
+                result?;
+                if matches!(
+                    txn,
+                    TransactionStatement::Commit | TransactionStatement::CommitPrepared(_)
+                ) {
+                    self.sync_commit()?;
+                }
+                Ok(StatementResult::Done(transaction_tag(txn)))
+            }
+            Statement::Set { name, value, local } => {
+                self.session.set(name, value.as_deref(), *local)?;
+                Ok(StatementResult::Done("SET"))
+            }
+            Statement::Reset(name) => {
+                match name {
+                    Some(name) => self.session.set(name, None, false)?,
+                    None => self.session.reset_all(),
+                }
+                Ok(StatementResult::Done("RESET"))
+            }
+            Statement::Show(name) => {
+                let settings = self.session.settings();
+                let rows = match name {
+                    Some(name) => Rows::new(
+                        vec![name.clone()],
+                        vec![vec![Value::Text(settings.get(name)?)]],
+                    ),
+                    None => Rows::new(
+                        vec!["name".to_string(), "setting".to_string()],
+                        settings::PARAMETERS
+                            .iter()
+                            .map(|(name, _, _)| {
+                                let value = settings.get(name).unwrap();
+                                vec![Value::Text(name.to_string()), Value::Text(value)]
+                            })
+                            .collect(),
+                    ),
+                };
+                Ok(StatementResult::Rows(rows))
+            }
+            Statement::Analyze { table } => {
+                let engine = &mut *self.engine.borrow_mut();
+                engine
+                    .catalog
+                    .analyze(&mut engine.bufmgr, table.as_deref())?;
+                Ok(StatementResult::Done("ANALYZE"))
+            }
+            Statement::Vacuum { table } => {
+                let engine = &mut *self.engine.borrow_mut();
+                let txns = &self.session.txns;
+                engine.catalog.vacuum(
+                    &mut engine.bufmgr,
+                    table.as_deref(),
+                    txns.horizon(),
+                    |table| txns.table_locked(table),
+                )?;
+                Ok(StatementResult::Done("VACUUM"))
+            }
+            Statement::Copy(copy) => {
+                let rows = match (&copy.relation, copy.direction) {
+                    (CopyRelation::Table { name, columns }, CopyDirection::From) => {
+                        let file = File::open(&copy.file)?;
+                        self.copy_from(name, columns, BufReader::new(file), &copy.options)?
+                    }
+                    _ if copy.options.bulk => return Err(Error::BulkCopyTo),
+                    (relation, _) => {
+                        let mut file = BufWriter::new(File::create(&copy.file)?);
+                        let rows =
+                            self.export_query(&relation.query(), params, &mut file, &copy.options)?;
+                        file.flush()?;
+                        rows
+                    }
+                };
+                Ok(StatementResult::Modified {
+                    command: "COPY",
+                    rows,
+                })
+            }
+            _ if self.session.txns.in_transaction() => self.run(stmt, params, false),
+            _ => {
+                self.begin()?;
+                match self.run(stmt, params, true) {
+                    Ok(result) => {
+                        self.commit()?;
+                        Ok(result)
+                    }
+                    Err(e) => {
+                        self.rollback()?;
+                        Err(e)
+                    }
+                }
+            }
+        }
+    }
+
+    // main と stats は名前に使えない。同じファイルを開いていればそのデータベースにつなぐ
+    fn attach(&mut self, path: &str, alias: &str, read_only: bool) -> Result<(), Error> {
+        if alias == "main" || alias == "stats" {
+            return Err(Error::InvalidDatabaseName(alias.to_string()));
+        }
+        if self.attachments.iter().any(|a| a.alias == alias) {
+            return Err(Error::AttachmentExists(alias.to_string()));
+        }
+        let db = match Database::find_open(Path::new(path)) {
+            Some(db) => db,
+            None => Database::open(path)?,
+        };
+        if Rc::ptr_eq(&db.engine, &self.engine) {
+            return Err(Error::AttachSelf);
+        }
+        self.attachments.push(Attachment {
+            alias: alias.to_string(),
+            conn: RefCell::new(db.connect()),
+            read_only,
+        });
+        Ok(())
+    }
+
+    // alias.table を書き換える文を、名前から alias を除いてつないだ先で実行する
+    fn write_attached(
+        &mut self,
+        i: usize,
+        stmt: &Statement,
+        params: &[Value],
+    ) -> Result<StatementResult, Error> {
+        let attachment = &self.attachments[i];
+        if attachment.read_only {
+            return Err(Error::ReadOnlyAttachment(attachment.alias.clone()));
+        }
+        if self.in_transaction() {
+            return Err(
+                transaction::Error::InTransactionBlock("writing to an attached database").into(),
+            );
+        }
+        attachment.conn.borrow_mut().execute_statement(stmt, params)
+    }
+
+    // cache が立っていれば、文ごとのトランザクションで実行する問い合わせの結果を結果のキャッシュから返したりためたりする
+    fn run(
+        &mut self,
+        stmt: &Statement,
+        params: &[Value],
+        cache: bool,
+    ) -> Result<StatementResult, Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        let attachments = &self.attachments;
+        let attached = |name: &str| {
+            let (alias, table) = name.split_once('.')?;
+            let attachment = attachments.iter().find(|a| a.alias == alias)?;
+            let sql = format!("SELECT * FROM \"{}\"", table);
+            let rows = attachment.conn.borrow_mut().query(&sql, &[]);
+            Some(
+                rows.map(|rows| {
+                    (
+                        rows.columns().to_vec(),
+                        rows.map(Row::into_values).collect(),
+                    )
+                })
+                .map_err(|e| planner::Error::Attached {
+                    message: e.to_string(),
+                    sqlstate: e.sqlstate(),
+                }),
+            )
+        };
+        // 準備した文は、つないだデータベースを読まず、この接続が DDL で変えたカタログでもなければ計画を使い回す
+        let plan_key = self.session.plan_key.take().filter(|_| {
+            self.attachments.is_empty() && self.session.pending.as_ref().is_none_or(|p| !p.changed)
+        });
+        let (plan, volatile) = {
+            let _span = trace::span("plan", &[]);
+            let planner = self
+                .session
+                .planner(&engine.catalog)
+                .with_params(params)
+                .with_attached(&attached);
+            let plan = match &plan_key {
+                Some(sql) => self.session.plans.plan_statement(&planner, sql, stmt)?,
+                None => Rc::new(planner.plan_statement(stmt)?),
+            };
+            (plan, planner.uses_volatile())
+        };
+        // 権限は計画するときに確かめるので、ためた結果を返すときにも確かめる
+        let cached = cache
+            && !volatile
+            && !self.session.profiling
+            && self.session.settings().result_cache
+            && self.attachments.is_empty()
+            && self.session.pending.as_ref().is_none_or(|p| {
+                // 写したカタログで読んだ結果は、ほかの接続から見えるものと違うかもしれない
+                !p.changed && p.catalog.schema_version() == p.base
+            })
+            && matches!(stmt, Statement::Query(_));
+        let cache_key = match cached {
+            true => table_versions(&plan, &engine.catalog, &self.session.txns).map(|versions| {
+                let key = format!("{:?} {:?} {:?}", stmt, params, self.session.user);
+                (key, versions)
+            }),
+            false => None,
+        };
+        if let Some((key, versions)) = &cache_key {
+            let max_rows = self.session.settings().max_result_rows;
+            if let Some((columns, rows)) = engine.result_cache.get(key, versions) {
+                if max_rows.is_none_or(|max| rows.len() as u64 <= max) {
+                    return Ok(StatementResult::Rows(Rows::new(columns, rows)));
+                }
+            }
+        }
+        let nodes = match self.session.profiling {
+            true => Some(self.session.planner(&engine.catalog).explain(&plan)?),
+            false => None,
+        };
+        let mut ctx = engine.exec_context(&mut self.session);
+        let (rows, profile) = {
+            let _span = trace::span("execute", &[]);
+            match &nodes {
+                Some(nodes) => {
+                    let (rows, profile) = executor::execute_profiled(&plan, nodes, &mut ctx)?;
+                    (rows, Some(profile))
+                }
+                None => (executor::execute(&plan, &mut ctx)?, None),
+            }
+        };
+        if profile.is_some() {
+            self.session.profile = profile;
+        }
+        let command = match stmt {
+            Statement::Insert(insert) if insert.returning.is_empty() => "INSERT",
+            Statement::Update(update) if update.returning.is_empty() => "UPDATE",
+            Statement::Delete(delete) if delete.returning.is_empty() => "DELETE",
+            _ => {
+                let columns = plan.columns(&engine.catalog)?;
+                if let Some((key, versions)) = cache_key {
+                    engine.result_cache.insert(key, versions, &columns, &rows);
+                }
+                return Ok(StatementResult::Rows(Rows::new(columns, rows)));
+            }
+        };
+        Ok(StatementResult::Modified {
+            command,
+            rows: modified_count(&rows),
+        })
+    }
+
+    // 主キーと UNIQUE の列には一意インデックスを作る
+    fn create_table(&mut self, create: &CreateTable) -> Result<StatementResult, Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        if create.if_not_exists && engine.catalog.table(&create.name).is_some() {
+            return Ok(StatementResult::Done("CREATE TABLE"));
+        }
+        if let Some(partition_of) = &create.partition_of {
+            let bound = partition_bound(&engine.catalog, &partition_of.bound)?;
+            engine.catalog.create_partition(
+                &mut engine.bufmgr,
+                &create.name,
+                &partition_of.parent,
+                bound,
+            )?;
+            return partition_table(engine, create);
+        }
+        let mut primary_key = create.primary_key.clone();
+        let mut columns = vec![];
+        for def in &create.columns {
+            let (data_type, collation) = column_type(&engine.catalog, def)?;
+            if let Some(name) = def
+                .compression
+                .as_ref()
+                .filter(|name| *name != "dictionary")
+            {
+                return Err(Error::UnknownCompression(name.clone()));
+            }
+            if def.primary_key {
+                primary_key.push(def.name.clone());
+            }
+            let virtual_column = def.generated.as_ref().is_some_and(|g| !g.stored);
+            if virtual_column
+                && (def.primary_key || def.unique || create.primary_key.contains(&def.name))
+            {
+                return Err(catalog::Error::VirtualColumnIndex(def.name.clone()).into());
+            }
+            columns.push(Column {
+                name: def.name.clone(),
+                data_type,
+                not_null: def.not_null || def.primary_key || create.primary_key.contains(&def.name),
+                collation,
+            });
+        }
+        // パーティションに分けたテーブルの親はインデックスを持たない
+        if create.partition_by.is_some()
+            && (!primary_key.is_empty() || create.columns.iter().any(|def| def.unique))
+        {
+            return Err(catalog::Error::PartitionedIndex(create.name.clone()).into());
+        }
+        let ttl = match &create.ttl {
+            Some(ttl) => Some((&ttl.column, ttl_interval(&engine.catalog, &ttl.interval)?)),
+            None => None,
+        };
+        let using = create
+            .using
+            .as_deref()
+            .unwrap_or(storage::DEFAULT_STORAGE_ENGINE);
+        engine
+            .catalog
+            .create_table_using(&mut engine.bufmgr, &create.name, columns, using)?;
+        if let Some((column, interval)) = ttl {
+            if let Err(err) = engine.catalog.set_ttl(&create.name, column, interval) {
+                engine.catalog.drop_table(&create.name)?;
+                return Err(err.into());
+            }
+        }
+        if let Err(err) = engine
+            .catalog
+            .set_table_options(&create.name, &create.options)
+        {
+            engine.catalog.drop_table(&create.name)?;
+            return Err(err.into());
+        }
+        if let Err(err) = set_generated(&mut engine.catalog, create) {
+            engine.catalog.drop_table(&create.name)?;
+            return Err(err);
+        }
+        for def in create
+            .columns
+            .iter()
+            .filter(|def| def.compression.is_some())
+        {
+            if let Err(err) = engine.catalog.set_dictionary(&create.name, &def.name) {
+                engine.catalog.drop_table(&create.name)?;
+                return Err(err.into());
+            }
+        }
+        if create.partition_by.is_some() {
+            return partition_table(engine, create);
+        }
+        if !primary_key.is_empty() {
+            let name = format!("{}_pkey", create.name);
+            engine.catalog.create_index(
+                &mut engine.bufmgr,
+                &name,
+                &create.name,
+                &primary_key,
+                true,
+            )?;
+        }
+        for def in create.columns.iter().filter(|def| def.unique) {
+            let name = format!("{}_{}_key", create.name, def.name);
+            engine.catalog.create_index(
+                &mut engine.bufmgr,
+                &name,
+                &create.name,
+                std::slice::from_ref(&def.name),
+                true,
+            )?;
+        }
+        Ok(StatementResult::Done("CREATE TABLE"))
+    }
+
+    // 問い合わせの結果を行として持つテーブルを作る。
+    // 列の型は列ごとの最初の NULL でない値の型で、すべて NULL なら text。NUMERIC の桁は値のうちいちばん多いものにする
+    fn create_materialized_view(
+        &mut self,
+        create: &CreateMaterializedView,
+    ) -> Result<StatementResult, Error> {
+        if self.catalog().table(&create.name).is_some() {
+            if create.if_not_exists {
+                return Ok(StatementResult::Done("CREATE MATERIALIZED VIEW"));
+            }
+            return Err(catalog::Error::TableExists(create.name.clone()).into());
+        }
+        self.atomic(VIEW_SAVEPOINT, |conn| {
+            let (columns, rows, view) = conn.materialize(&create.query, &create.sql)?;
+            {
+                let engine = &mut *conn.engine.borrow_mut();
+                let columns = view_columns(columns, &rows);
+                engine
+                    .catalog
+                    .create_table(&mut engine.bufmgr, &create.name, columns)?;
+                engine.catalog.set_view(&create.name, view)?;
+            }
+            if let (_, Err(err)) = conn.insert_rows(&create.name, rows) {
+                conn.engine.borrow_mut().catalog.drop_table(&create.name)?;
+                return Err(err);
+            }
+            Ok(StatementResult::Done("CREATE MATERIALIZED VIEW"))
+        })
+    }
+
+    fn create_trigger(&mut self, create: &CreateTrigger) -> Result<StatementResult, Error> {
+        let catalog = &mut self.engine.borrow_mut().catalog;
+        let table = catalog
+            .table(&create.table)
+            .ok_or_else(|| catalog::Error::TableNotFound(create.table.clone()))?;
+        if table.view.is_some() {
+            return Err(planner::Error::MaterializedView(create.table.clone()).into());
+        }
+        let body = match &create.body {
+            TriggerBodySpec::Statement { statement, sql } => TriggerBody::Statement {
+                statement: statement.clone(),
+                sql: sql.clone(),
+            },
+            TriggerBodySpec::Function(name) => TriggerBody::Function(
+                catalog
+                    .trigger_function(name)
+                    .ok_or_else(|| catalog::Error::TriggerFunctionNotFound(name.clone()))?
+                    .clone(),
+            ),
+        };
+        catalog.create_trigger(
+            &create.table,
+            Trigger {
+                name: create.name.clone(),
+                timing: create.timing,
+                event: create.event,
+                body,
+            },
+        )?;
+        Ok(StatementResult::Done("CREATE TRIGGER"))
+    }
+
+    // 問い合わせを実行し直して行を入れ替える。
+    // 消す行と入れる行はひとつのトランザクションで書くので、ほかの接続はコミットするまで前の行を読める
+    fn refresh_materialized_view(&mut self, name: &str) -> Result<StatementResult, Error> {
+        let (sql, names) = {
+            let catalog = self.catalog();
+            let table = catalog
+                .table(name)
+                .ok_or_else(|| catalog::Error::TableNotFound(name.to_string()))?;
+            let view = table
+                .view
+                .as_ref()
+                .ok_or_else(|| catalog::Error::NotMaterializedView(name.to_string()))?;
+            let names: Vec<String> = table.columns.iter().map(|c| c.name.clone()).collect();
+            (view.query.clone(), names)
+        };
+        let Statement::Query(query) = parse_one(&sql)? else {
+            unreachable!("materialized view holds a query");
+        };
+        self.atomic(VIEW_SAVEPOINT, |conn| {
+            let (columns, rows, view) = conn.materialize(&query, &sql)?;
+            if columns != names {
+                return Err(catalog::Error::ViewColumnsChanged(name.to_string()).into());
+            }
+            conn.execute_plan(&PlanNode::Delete {
+                table: name.to_string(),
+                input: Box::new(PlanNode::SeqScan {
+                    table: name.to_string(),
+                    needed: None,
+                }),
+                returning: false,
+            })?;
+            conn.insert_rows(name, rows).1?;
+            conn.engine.borrow_mut().catalog.set_view(name, view)?;
+            Ok(StatementResult::Done("REFRESH MATERIALIZED VIEW"))
+        })
+    }
+
+    // 実体化したビューの問い合わせを実行し、列の名前と行と、ビューとして覚えるものを返す。
+    // 読むテーブルの changes は実行する前に覚える
+    fn materialize(
+        &mut self,
+        query: &Query,
+        sql: &str,
+    ) -> Result<(Vec<String>, Vec<executor::Row>, catalog::MaterializedView), Error> {
+        let (plan, columns, sources) = {
+            let catalog = self.catalog();
+            let plan = self
+                .session
+                .planner(&catalog)
+                .plan_statement(&Statement::Query(Box::new(query.clone())))?;
+            let mut names = vec![];
+            plan_tables(&plan, &mut names);
+            let sources = names
+                .into_iter()
+                .filter_map(|name| {
+                    let changes = catalog.table(&name)?.changes();
+                    Some((name, changes))
+                })
+                .collect();
+            let columns = plan.columns(&catalog)?;
+            (plan, columns, sources)
+        };
+        let rows = self.execute_plan(&plan)?;
+        let view = catalog::MaterializedView {
+            query: sql.to_string(),
+            sources,
+            refreshed_at: Timestamp::now(),
+        };
+        Ok((columns, rows, view))
+    }
+
+    fn create_index(&mut self, create: &CreateIndex) -> Result<StatementResult, Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        match &create.expression {
+            None if create.rtree => {
+                engine.catalog.create_rtree_index(
+                    &mut engine.bufmgr,
+                    &create.name,
+                    &create.table,
+                    &create.columns,
+                )?;
+            }
+            None if create.fulltext => {
+                engine.catalog.create_fulltext_index(
+                    &mut engine.bufmgr,
+                    &create.name,
+                    &create.table,
+                    &create.columns,
+                )?;
+            }
+            Some(expr) => {
+                let expr =
+                    Planner::new(&engine.catalog).plan_index_expression(&create.table, expr)?;
+                engine.catalog.create_expression_index(
+                    &mut engine.bufmgr,
+                    &create.name,
+                    &create.table,
+                    expr,
+                    create.unique,
+                )?;
+            }
+            None => {
+                engine.catalog.create_index(
+                    &mut engine.bufmgr,
+                    &create.name,
+                    &create.table,
+                    &create.columns,
+                    create.unique,
+                )?;
+            }
+        }
+        Ok(StatementResult::Done("CREATE INDEX"))
+    }
+
+    // reader のデータを読んで table の columns に入れ、入れた行数を返す。columns が空ならすべての列。
+    // 値は列の型に変換し、COPY_BATCH 行ずつまとめて入れる。どこかの行で失敗すれば 1 行も入れない
+    pub fn copy_from(
+        &mut self,
+        table: &str,
+        columns: &[String],
+        reader: impl BufRead,
+        options: &CopyOptions,
+    ) -> Result<usize, Error> {
+        let (table, columns, targets) = {
+            let catalog = self.catalog();
+            let table = catalog
+                .table(table)
+                .ok_or_else(|| catalog::Error::TableNotFound(table.to_string()))?;
+            if table.view.is_some() {
+                return Err(planner::Error::MaterializedView(table.name.clone()).into());
+            }
+            if let Some(user) = &self.session.user {
+                if !catalog.has_privilege(user, &table.name, Privilege::Insert) {
+                    return Err(planner::Error::PermissionDenied(table.name.clone()).into());
+                }
+            }
+            let mut targets = vec![];
+            for name in columns {
+                let i = table
+                    .column_index(name)
+                    .ok_or_else(|| catalog::Error::ColumnNotFound(name.clone()))?;
+                if targets.contains(&i) {
+                    return Err(catalog::Error::DuplicateColumn(name.clone()).into());
+                }
+                if table.generated_column(i).is_some() {
+                    return Err(planner::Error::GeneratedColumn(name.clone()).into());
+                }
+                targets.push(i);
+            }
+            // INSERT と同じく、列を省けば生成列には読まない
+            if columns.is_empty() {
+                targets = (0..table.columns.len())
+                    .filter(|&i| table.generated_column(i).is_none())
+                    .collect();
+            }
+            (table.name.clone(), table.columns.clone(), targets)
+        };
+        if options.bulk {
+            return self.bulk_copy(&table, &columns, &targets, reader, options);
+        }
+        self.atomic(COPY_SAVEPOINT, |conn| {
+            conn.copy_rows(&table, &columns, &targets, reader, options)
+        })
+    }
+
+    // COPY (BULK)。空のテーブルに、トランザクションの外で行ごとのログを書かずに入れる
+    //
+    // 行は誰からも見える版として COPY_BATCH 行ずつ書くので、実行中のトランザクションのスナップショットからも見える。
+    // 入れ終わったらページをすべて書き出して fsync し、テーブルを作り直したことだけをログに残す。
+    // 途中で失敗すれば入れた行をすべて取り除いて空に戻す。ログから行は戻せないので、
+    // 途中で落ちたテーブルは空として扱わなければならない
+    fn bulk_copy(
+        &mut self,
+        table: &str,
+        columns: &[Column],
+        targets: &[usize],
+        reader: impl BufRead,
+        options: &CopyOptions,
+    ) -> Result<usize, Error> {
+        if self.in_transaction() {
+            return Err(transaction::Error::InTransactionBlock("COPY (BULK)").into());
+        }
+        self.session
+            .txns
+            .lock_table(table, LockMode::IntentionExclusive)?;
+        {
+            let engine = &mut *self.engine.borrow_mut();
+            let storage = &engine.catalog.table(table).unwrap().storage;
+            let first = storage
+                .scan()
+                .next(&mut engine.bufmgr)
+                .map_err(executor::Error::from)?;
+            if first.is_some() {
+                return Err(Error::BulkLoadNotEmpty(table.to_string()));
+            }
+        }
+        self.session.unlogged = true;
+        let result = self.copy_rows(table, columns, targets, reader, options);
+        self.session.unlogged = false;
+        let engine = &mut *self.engine.borrow_mut();
+        let count = match result {
+            Ok(count) => count,
+            Err(err) => {
+                let table = engine.catalog.table(table).unwrap();
+                table.truncate(&mut engine.bufmgr)?;
+                return Err(err);
+            }
+        };
+        engine.bufmgr.flush().map_err(executor::Error::from)?;
+        if let Some(wal) = engine.bufmgr.wal_mut() {
+            let lsn = wal
+                .append(&LogRecord::Changes {
+                    txn: None,
+                    prev: Lsn::INVALID_LSN,
+                    changes: vec![RowChange::Rebuilt {
+                        table: table.to_string(),
+                    }],
+                })
+                .map_err(executor::Error::from)?;
+            wal.flush(lsn).map_err(executor::Error::from)?;
+        }
+        Ok(count)
+    }
+
+    // f を、失敗すれば何も反映しないように実行する。
+    // トランザクションの外ならひとつのトランザクションにし、中ならセーブポイントまで戻して f の前の状態にする
+    fn atomic<T>(
+        &mut self,
+        savepoint: &str,
+        f: impl FnOnce(&mut Connection) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let autocommit = !self.in_transaction();
+        if autocommit {
+            self.begin()?;
+        } else {
+            self.session.txns.savepoint(savepoint)?;
+        }
+        let result = f(self);
+        match (&result, autocommit) {
+            (Ok(_), true) => self.commit()?,
+            (Err(_), true) => self.rollback()?,
+            (Ok(_), false) => self.session.txns.release(savepoint)?,
+            (Err(_), false) => {
+                let engine = &mut *self.engine.borrow_mut();
+                self.session
+                    .txns
+                    .rollback_to(savepoint, &mut engine.bufmgr, &engine.catalog)?;
+                self.session.txns.release(savepoint)?;
+            }
+        }
+        result
+    }
+
+    fn copy_rows(
+        &mut self,
+        table: &str,
+        columns: &[Column],
+        targets: &[usize],
+        reader: impl BufRead,
+        options: &CopyOptions,
+    ) -> Result<usize, Error> {
+        let context = |line, source: Error| Error::Copy {
+            table: table.to_string(),
+            line,
+            source: Box::new(source),
+        };
+        let mut batch = vec![];
+        let mut count = 0;
+        for record in Records::new(reader, options) {
+            let record = record.map_err(|e| context(e.line, Error::CopyData(e.message)))?;
+            if let Some(&missing) = targets.get(record.fields.len()) {
+                let message = format!("missing data for column \"{}\"", columns[missing].name);
+                return Err(context(record.line, Error::CopyData(message)));
+            }
+            if record.fields.len() > targets.len() {
+                let message = "extra data after last expected column".to_string();
+                return Err(context(record.line, Error::CopyData(message)));
+            }
+            let mut row = vec![Value::Null; columns.len()];
+            for (&i, field) in targets.iter().zip(record.fields) {
+                if let Some(text) = field {
+                    row[i] = input_value(&columns[i], Value::Text(text))
+                        .map_err(|e| context(record.line, e.into()))?;
+                }
+            }
+            batch.push((record.line, row));
+            if batch.len() == COPY_BATCH {
+                count += self
+                    .insert_batch(table, std::mem::take(&mut batch))
+                    .map_err(|(line, e)| context(line, e))?;
+            }
+        }
+        count += self
+            .insert_batch(table, batch)
+            .map_err(|(line, e)| context(line, e))?;
+        Ok(count)
+    }
+
+    // まとめて入れる。失敗したら 1 行ずつ入れ直して、失敗した行の番号を返す
+    pub(crate) fn insert_batch(
+        &mut self,
+        table: &str,
+        batch: Vec<(usize, executor::Row)>,
+    ) -> Result<usize, (usize, Error)> {
+        let Some(&(first, _)) = batch.first() else {
+            return Ok(0);
+        };
+        let lines: Vec<usize> = batch.iter().map(|(line, _)| *line).collect();
+        let rows = batch.into_iter().map(|(_, row)| row).collect();
+        let (rows, result) = self.insert_rows(table, rows);
+        let Err(e) = result else {
+            return Ok(lines.len());
+        };
+        if lines.len() == 1 {
+            return Err((first, e));
+        }
+        for (line, row) in lines.into_iter().zip(rows) {
+            if let (_, Err(e)) = self.insert_rows(table, vec![row]) {
+                return Err((line, e));
+            }
+        }
+        Err((first, e))
+    }
+
+    // 入れようとした行も返す
+    fn insert_rows(
+        &mut self,
+        table: &str,
+        rows: Vec<executor::Row>,
+    ) -> (Vec<executor::Row>, Result<(), Error>) {
+        let plan = PlanNode::Insert {
+            table: table.to_string(),
+            input: Box::new(PlanNode::Values { rows }),
+            on_conflict: None,
+            returning: false,
+        };
+        let result = self.execute_plan(&plan).map(|_| ());
+        let PlanNode::Insert { input, .. } = plan else {
+            unreachable!()
+        };
+        let PlanNode::Values { rows } = *input else {
+            unreachable!()
+        };
+        (rows, result)
+    }
+
+    // 問い合わせの結果を options の形式で writer に書き、書いた行数を返す。
+    // 行はバッチごとに書くので、結果をすべてメモリに持つことはない
+    pub fn export(
+        &mut self,
+        sql: &str,
+        params: &[Value],
+        writer: &mut dyn Write,
+        options: &CopyOptions,
+    ) -> Result<usize, Error> {
+        match parse_one(sql)? {
+            Statement::Query(query) => self.export_query(&query, params, writer, options),
+            _ => Err(Error::NoResultSet),
+        }
+    }
+
+    fn export_query(
+        &mut self,
+        query: &Query,
+        params: &[Value],
+        writer: &mut dyn Write,
+        options: &CopyOptions,
+    ) -> Result<usize, Error> {
+        if options.format == CopyFormat::Parquet {
+            let (writer, count) = self.stream_query(
+                query,
+                params,
+                |columns| Ok(ParquetWriter::new(writer, columns)?),
+                |writer, row| Ok(writer.write(&row)?),
+            )?;
+            writer.finish()?;
+            return Ok(count);
+        }
+        let (writer, count) = self.stream_query(
+            query,
+            params,
+            |columns| Ok(RecordWriter::new(writer, columns, options)?),
+            |writer, row| Ok(writer.write(&row)?),
+        )?;
+        writer.into_inner()?;
+        Ok(count)
+    }
+
+    // query を実行し、列の名前から start で作った値と 1 行ずつを each に渡して、渡した行数を返す。
+    // 行はバッチごとに取り出す。トランザクションの外なら、このためだけのトランザクションで実行する
+    pub(crate) fn stream_query<T>(
+        &mut self,
+        query: &Query,
+        params: &[Value],
+        start: impl FnOnce(&[String]) -> Result<T, Error>,
+        mut each: impl FnMut(&mut T, executor::Row) -> Result<(), Error>,
+    ) -> Result<(T, usize), Error> {
+        self.session.cancel.reset();
+        if !self.in_transaction() {
+            return self.transaction(|conn| conn.stream_query(query, params, start, each));
+        }
+        let engine = &mut *self.engine.borrow_mut();
+        let plan = {
+            let _span = trace::span("plan", &[]);
+            self.session
+                .planner(&engine.catalog)
+                .with_params(params)
+                .plan_query(query)?
+        };
+        let _span = trace::span("execute", &[]);
+        let mut state = start(&plan.columns(&engine.catalog)?)?;
+        let mut ctx = engine.exec_context(&mut self.session);
+        let mut exec = plan.start(&mut ctx)?;
+        let mut count = 0;
+        let result = (|| {
+            while let Some(batch) = exec.next_batch(&mut ctx)? {
+                for row in batch.into_rows() {
+                    each(&mut state, row)?;
+                    count += 1;
+                }
+            }
+            Ok::<_, Error>(())
+        })();
+        let closed = exec.close(&mut ctx);
+        result?;
+        closed?;
+        Ok((state, count))
+    }
+
+    // stmt の $n の型と結果の列。実行はせずに計画だけ作る。params は $n の数だけの値で、
+    // 型を決めるのには使わない。列の型は式と列の型から決め、関数の結果などで決まらないものは None
+    pub fn describe_statement(
+        &self,
+        stmt: &Statement,
+        params: &[Value],
+    ) -> Result<Description, Error> {
+        let returns_rows = returns_rows(stmt) && !matches!(stmt, Statement::Show(_));
+        let planned = matches!(
+            stmt,
+            Statement::Insert(_) | Statement::Update(_) | Statement::Delete(_)
+        );
+        if !returns_rows && !planned {
+            return Ok(Description {
+                params: vec![None; params.len()],
+                columns: None,
+            });
+        }
+        let catalog = self.catalog();
+        let planner = self.session.planner(&catalog).with_params(params);
+        let plan = planner.plan_statement(stmt)?;
+        let columns = if returns_rows {
+            let types = plan.column_types(&catalog)?;
+            Some(plan.columns(&catalog)?.into_iter().zip(types).collect())
+        } else {
+            None
+        };
+        Ok(Description {
+            params: planner.param_types(),
+            columns,
+        })
+    }
+
+    pub fn begin(&mut self) -> Result<(), Error> {
+        self.session.txns.begin()?;
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.with_catalog(|conn| {
+            conn.check_catalog()?;
+            conn.settle_catalog()?;
+            let result = {
+                let engine = &mut *conn.engine.borrow_mut();
+                // カタログは、コミットのレコードより前にページに書いておく
+                match engine.save_catalog() {
+                    Ok(()) => conn
+                        .session
+                        .txns
+                        .commit(&mut engine.bufmgr, &engine.catalog)
+                        .map_err(Error::from),
+                    Err(err) => {
+                        let _ = conn
+                            .session
+                            .txns
+                            .rollback(&mut engine.bufmgr, &engine.catalog);
+                        Err(err)
+                    }
+                }
+            };
+            conn.session.end_transaction();
+            conn.finish_catalog(result.is_ok());
+            let closed = conn.close_cursors();
+            result?;
+            closed?;
+            conn.sync_commit()
+        })
+    }
+
+    pub fn rollback(&mut self) -> Result<(), Error> {
+        self.with_catalog(|conn| {
+            let result = {
+                let engine = &mut *conn.engine.borrow_mut();
+                conn.session
+                    .txns
+                    .rollback(&mut engine.bufmgr, &engine.catalog)
+            };
+            conn.session.end_transaction();
+            conn.finish_catalog(false);
+            let closed = conn.close_cursors();
+            result?;
+            closed
+        })
+    }
+
+    // f を、この接続から見えるカタログで実行する。
+    // トランザクションの中で DDL を実行していれば、その間だけ Engine のカタログを写して変えたものと入れ替える
+    fn with_catalog<T>(&mut self, f: impl FnOnce(&mut Connection) -> T) -> T {
+        let outermost = self.session.pending.as_ref().is_none_or(|p| !p.active);
+        if let Some(pending) = self.session.pending.as_mut().filter(|p| !p.active) {
+            std::mem::swap(&mut self.engine.borrow_mut().catalog, &mut pending.catalog);
+            pending.active = true;
+        }
+        let result = f(self);
+        if outermost {
+            self.pin_catalog();
+        }
+        if let Some(pending) = self
+            .session
+            .pending
+            .as_mut()
+            .filter(|p| p.active && outermost)
+        {
+            std::mem::swap(&mut self.engine.borrow_mut().catalog, &mut pending.catalog);
+            pending.active = false;
+        }
+        result
+    }
+
+    // REPEATABLE READ と SERIALIZABLE のトランザクションは、最初の文を実行したときのカタログを使い続ける
+    fn pin_catalog(&mut self) {
+        let snapshot = self.session.txns.current().is_some_and(|txn| {
+            txn.started() && txn.isolation_level() != IsolationLevel::ReadCommitted
+        });
+        if !snapshot || self.session.pending.is_some() {
+            return;
+        }
+        let catalog = self.engine.borrow().catalog.clone();
+        self.session.pending = Some(PendingCatalog {
+            base: catalog.schema_version(),
+            catalog,
+            active: false,
+            changed: false,
+        });
+    }
+
+    // トランザクションの中で DDL を実行する前に呼ぶ。まだ写していなければカタログを写し、
+    // lock のテーブルと、パーティションに分けたテーブルならその子をトランザクションが終わるまでロックする。
+    // ロックしたテーブルと、その前に持っていたロックを返す
+    fn stage_catalog(
+        &mut self,
+        lock: Option<(&str, LockMode)>,
+    ) -> Result<Vec<(String, Option<LockMode>)>, Error> {
+        let mut held = vec![];
+        if let Some((table, mode)) = lock {
+            let mut names = vec![table.to_string()];
+            {
+                let catalog = self.catalog();
+                if let Some(table) = catalog.table(table) {
+                    names.extend(catalog.descendants(table).iter().map(|t| t.name.clone()));
+                }
+            }
+            for name in names {
+                let before = self
+                    .session
+                    .txns
+                    .current()
+                    .and_then(|t| t.table_lock(&name));
+                self.session.txns.lock_table(&name, mode)?;
+                held.push((name, before));
+            }
+        }
+        match &mut self.session.pending {
+            Some(pending) => pending.changed = true,
+            None => {
+                let engine = &mut *self.engine.borrow_mut();
+                let copy = engine.catalog.clone();
+                let committed = std::mem::replace(&mut engine.catalog, copy);
+                self.session.pending = Some(PendingCatalog {
+                    base: committed.schema_version(),
+                    catalog: committed,
+                    active: true,
+                    changed: true,
+                });
+            }
+        }
+        Ok(held)
+    }
+
+    // SAVEPOINT でそのときのカタログを覚え、ROLLBACK TO でセーブポイントより後の DDL を取り消す
+    fn savepoint_catalog(&mut self, txn: &TransactionStatement) {
+        let savepoints = &mut self.session.savepoint_catalogs;
+        match txn {
+            TransactionStatement::Savepoint(name) => {
+                let engine = self.engine.borrow();
+                let copy = self
+                    .session
+                    .pending
+                    .as_ref()
+                    .map(|p| (engine.catalog.clone(), p.changed));
+                savepoints.push((name.clone(), copy));
+            }
+            TransactionStatement::RollbackTo(name) => {
+                let Some(pos) = savepoints.iter().rposition(|(n, _)| n == name) else {
+                    return;
+                };
+                savepoints.truncate(pos + 1);
+                let engine = &mut *self.engine.borrow_mut();
+                match (savepoints[pos].1.clone(), &mut self.session.pending) {
+                    (Some((catalog, changed)), Some(pending)) => {
+                        engine.catalog = catalog;
+                        pending.changed = changed;
+                    }
+                    // セーブポイントより後に写したなら、コミットしたカタログに戻す
+                    (None, pending) => {
+                        if let Some(pending) = pending.take() {
+                            engine.catalog = pending.catalog;
+                        }
+                    }
+                    (Some(_), None) => {}
+                }
+            }
+            TransactionStatement::Release(name) => {
+                if let Some(pos) = savepoints.iter().rposition(|(n, _)| n == name) {
+                    savepoints.truncate(pos);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // コミットする前に呼ぶ。写したあとに作られたインデックスに、それを知らずに書き換えた行を入れ、
+    // 読むのに使っただけの写しは、数えたものをコミットしたカタログに移して捨てる
+    fn settle_catalog(&mut self) -> Result<(), Error> {
+        let Some(pending) = &mut self.session.pending else {
+            return Ok(());
+        };
+        let engine = &mut *self.engine.borrow_mut();
+        let (copy, committed) = match pending.active {
+            true => (&mut engine.catalog, &mut pending.catalog),
+            false => (&mut pending.catalog, &mut engine.catalog),
+        };
+        if pending.changed {
+            return rebuild_stale_indexes(copy, &mut engine.bufmgr, committed);
+        }
+        committed.merge_counters(copy);
+        rebuild_stale_indexes(committed, &mut engine.bufmgr, copy)?;
+        let pending = self.session.pending.take().unwrap();
+        if pending.active {
+            engine.catalog = pending.catalog;
+        }
+        Ok(())
+    }
+
+    // 写してからほかの接続がテーブルの定義や権限を変えていれば、トランザクションを取り消して失敗する
+    fn check_catalog(&mut self) -> Result<(), Error> {
+        let Some(pending) = self.session.pending.as_ref().filter(|p| p.changed) else {
+            return Ok(());
+        };
+        let committed = match pending.active {
+            true => pending.catalog.schema_version(),
+            false => self.catalog().schema_version(),
+        };
+        if committed == pending.base {
+            return Ok(());
+        }
+        self.rollback()?;
+        Err(Error::SchemaConflict)
+    }
+
+    // トランザクションが終わっていれば、写したカタログを committed ならコミットしたものにし、そうでなければ捨てる
+    fn finish_catalog(&mut self, committed: bool) {
+        if self.in_transaction() {
+            return;
+        }
+        self.session.savepoint_catalogs.clear();
+        let Some(mut pending) = self.session.pending.take() else {
+            return;
+        };
+        let engine = &mut *self.engine.borrow_mut();
+        if !pending.active {
+            std::mem::swap(&mut engine.catalog, &mut pending.catalog);
+        }
+        if !pending.changed {
+            pending.catalog.merge_counters(&engine.catalog);
+            engine.catalog = pending.catalog;
+            return;
+        }
+        if !committed {
+            engine.catalog = pending.catalog;
+            return;
+        }
+        engine.catalog.replace(&pending.catalog, pending.base);
+        // 消したり作り直したり列を足したりしたテーブルの結果は、ほかの接続がためたものも使えない
+        let old = &pending.catalog;
+        let new = &engine.catalog;
+        for table in old.tables().iter().chain(new.tables()) {
+            let same = match (old.table(&table.name), new.table(&table.name)) {
+                (Some(a), Some(b)) => Arc::ptr_eq(&a.storage, &b.storage) && a.columns == b.columns,
+                _ => false,
+            };
+            if !same {
+                engine.result_cache.invalidate(&table.name);
+            }
+        }
+    }
+
+    // synchronous_commit なら、コミットした変更をディスクに届ける
+    fn sync_commit(&mut self) -> Result<(), Error> {
+        if self.session.settings().synchronous_commit {
+            let engine = &mut *self.engine.borrow_mut();
+            engine.bufmgr.flush().map_err(executor::Error::from)?;
+        }
+        Ok(())
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.session.in_transaction()
+    }
+
+    // ロックを待つ時間の上限。None ならいつまでも待つ
+    pub fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        self.session.set_lock_timeout(timeout);
+    }
+
+    // on なら、ロックをすぐに取れない文は待たずに、何も変えずに is_lock_wait のエラーを返す。
+    // ロックの列には残るので、同じ文をもう一度実行すれば続きから待つ。1 つのスレッドで多くの接続を進めるときに使う。
+    // 待ち始めてから止められるか lock_timeout か statement_timeout を過ぎたら、次に実行したときにそのエラーを返す
+    pub fn set_park_lock_waits(&mut self, on: bool) {
+        self.session.txns.set_park_lock_waits(on);
+    }
+
+    // set_park_lock_waits のとき、f がロックを待って戻ったら待ち始めた時刻を覚える。
+    // 待っている文が止められたか時間を過ぎていれば、f を実行せずに待つのをやめる
+    fn with_lock_wait<T>(
+        &mut self,
+        f: impl FnOnce(&mut Connection) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if let Some(since) = self.session.lock_wait_since {
+            let waited = clock::now().saturating_duration_since(since);
+            let settings = self.session.settings();
+            let over = |timeout: Option<Duration>| timeout.is_some_and(|t| waited >= t);
+            let error = if self.session.cancel.is_canceled() {
+                Some(executor::Error::QueryCanceled)
+            } else if over(settings.lock_timeout) {
+                Some(executor::Error::Lock(lock::Error::LockTimeout))
+            } else if over(settings.statement_timeout) {
+                Some(executor::Error::StatementTimeout)
+            } else {
+                None
+            };
+            if let Some(error) = error {
+                self.stop_lock_wait();
+                return Err(error.into());
+            }
+        }
+        let result = f(self);
+        match &result {
+            Err(err) if err.is_lock_wait() => {
+                self.session.lock_wait_since.get_or_insert_with(clock::now);
+            }
+            _ if self.session.lock_wait_since.is_some() => self.stop_lock_wait(),
+            _ => {}
+        }
+        result
+    }
+
+    fn stop_lock_wait(&mut self) {
+        self.session.lock_wait_since = None;
+        if let Some(txn) = self.session.txns.current() {
+            self.session.txns.lock_manager().stop_waiting(txn.id());
+        }
+    }
+
+    // SET で変えさせない設定。set_lock_timeout のように呼ぶ側が決めた値を守る
+    pub fn fix_setting(&mut self, name: &'static str) {
+        self.session.fix_setting(name);
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    // sql をひとつの文として解析し、name で準備しておく。param_types は引数の型の OID で、省いたものは 0
+    pub fn prepare(&mut self, name: &str, sql: &str, param_types: &[i32]) -> Result<(), Error> {
+        let mut statements = sql::parse(sql)?;
+        if statements.len() > 1 {
+            return Err(Error::MultipleStatements);
+        }
+        self.session
+            .prepare(name, sql, statements.pop(), param_types);
+        Ok(())
+    }
+
+    pub fn prepared(&self, name: &str) -> Result<&PreparedStatement, Error> {
+        self.session
+            .prepared(name)
+            .ok_or_else(|| Error::PreparedStatementNotFound(name.to_string()))
+    }
+
+    // 準備した文の $n の型と結果の列。準備したときに伝えられた引数の型 (OID) は見ない
+    pub fn describe_prepared(&self, name: &str) -> Result<Description, Error> {
+        let prepared = self.prepared(name)?;
+        let Some(stmt) = &prepared.statement else {
+            return Ok(Description {
+                params: vec![],
+                columns: None,
+            });
+        };
+        let nulls = vec![Value::Null; prepared.param_types.len()];
+        self.describe_statement(stmt, &nulls)
+    }
+
+    // 準備した文を実行する。空の文なら何もせずに None
+    pub fn execute_prepared(
+        &mut self,
+        name: &str,
+        params: &[Value],
+    ) -> Result<Option<StatementResult>, Error> {
+        let prepared = self.prepared(name)?.clone();
+        match &prepared.statement {
+            Some(stmt) => self.execute_cached(&prepared.sql, stmt, params).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // sql を解析した stmt を実行する。計画は sql ごとにためて、使えるうちは使い回す
+    pub fn execute_cached(
+        &mut self,
+        sql: &str,
+        stmt: &Statement,
+        params: &[Value],
+    ) -> Result<StatementResult, Error> {
+        self.session.plan_key = Some(sql.to_string());
+        let result = self.execute_parsed(sql, stmt, params);
+        self.session.plan_key = None;
+        result
+    }
+
+    // 準備した文を捨てる。なければ false
+    pub fn deallocate(&mut self, name: &str) -> bool {
+        self.session.deallocate(name)
+    }
+
+    // f をひとつのトランザクションで実行する。Ok ならコミットし、Err ならロールバックする
+    pub fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Connection) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.begin()?;
+        match f(self) {
+            Ok(value) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                if self.in_transaction() {
+                    self.rollback()?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // SQL から呼べる関数を登録する。同じデータベースのすべての接続から見える
+    pub fn register_function(&mut self, func: ScalarFunction) -> Result<(), Error> {
+        self.engine.borrow_mut().catalog.create_function(func)?;
+        Ok(())
+    }
+
+    // CREATE TABLE ... USING で選べるようにする。同じデータベースのすべての接続から使える
+    pub fn register_storage_engine(&mut self, engine: Arc<dyn StorageEngine>) -> Result<(), Error> {
+        self.engine
+            .borrow_mut()
+            .catalog
+            .register_storage_engine(engine)?;
+        Ok(())
+    }
+
+    // CREATE TRIGGER ... EXECUTE FUNCTION で呼べる関数を登録する
+    pub fn register_trigger_function(&mut self, func: TriggerFunction) -> Result<(), Error> {
+        self.engine
+            .borrow_mut()
+            .catalog
+            .create_trigger_function(func)?;
+        Ok(())
+    }
+
+    // user のロールとして password で認証し、この接続の文をそのロールの権限で実行する
+    pub fn authenticate(&mut self, user: &str, password: &str) -> Result<(), Error> {
+        let catalog = self.catalog();
+        match catalog.role(user) {
+            Some(role) if role.check_password(password) => {
+                if !role.login {
+                    return Err(Error::LoginNotAllowed(user.to_string()));
+                }
+            }
+            _ => return Err(Error::AuthenticationFailed(user.to_string())),
+        }
+        drop(catalog);
+        self.session.user = Some(user.to_string());
+        Ok(())
+    }
+
+    // 認証せずに、この接続の文を user のロールの権限で実行する。None なら持ち主に戻す。
+    // 相手を信頼できる入り口だけが使う
+    pub fn set_user(&mut self, user: Option<&str>) {
+        self.session.user = user.map(str::to_string);
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        self.session.user.as_deref()
+    }
+
+    // LOGIN のロールがあれば、サーバーはつなぐ前にパスワードを求める
+    pub fn requires_password(&self) -> bool {
+        self.catalog().roles().iter().any(|role| role.login)
+    }
+
+    // 持ち主かスーパーユーザーでなければ command を実行させない
+    fn require_superuser(&self, command: &'static str) -> Result<(), Error> {
+        match &self.session.user {
+            Some(user) if !self.catalog().role(user).is_some_and(|role| role.superuser) => {
+                Err(Error::InsufficientPrivilege(command))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // 実行中の文を止めるための印。別のスレッドやシグナルハンドラから cancel を呼ぶ
+    pub fn cancel_token(&self) -> CancelToken {
+        self.session.cancel.clone()
+    }
+
+    // 立てると、これから実行する計画ごとに演算子ごとの時間や行数などを測る。測るぶん少し遅くなる
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.session.profiling = enabled;
+    }
+
+    // 測っていれば、最後に実行した計画の演算子ごとの値
+    pub fn last_profile(&self) -> Option<&Profile> {
+        self.session.profile.as_ref()
+    }
+
+    // データベースの整合性を確かめ、見つけた誤りを返す。プールの汚れたページはすべて書き出す
+    pub fn check(&mut self) -> Result<Vec<check::Problem>, Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        Ok(check::check(&mut engine.bufmgr, &engine.catalog)?)
+    }
+
+    // 動いているファイルのデータベースを dest のディレクトリに写す。ほかの接続のトランザクションは止めない。
+    // Database::open_backup で開くと、写し終えた時点でコミットしていた行だけが残る
+    pub fn backup(&mut self, dest: impl AsRef<Path>) -> Result<BackupLabel, Error> {
+        self.require_superuser("BACKUP")?;
+        self.engine.borrow_mut().backup(None, dest.as_ref())
+    }
+
+    // previous のバックアップのあとのログだけを dest に写す。Database::restore_incremental で previous につなげて戻す。
+    // previous のあとのログをチェックポイントで消していれば作れない
+    pub fn incremental_backup(
+        &mut self,
+        previous: impl AsRef<Path>,
+        dest: impl AsRef<Path>,
+    ) -> Result<BackupLabel, Error> {
+        self.require_superuser("BACKUP")?;
+        self.engine
+            .borrow_mut()
+            .backup(Some(previous.as_ref()), dest.as_ref())
+    }
+
+    // 空の大きな値を作り、その id を返す。大きな値はトランザクションに入らず、書いたものは取り消せない
+    pub fn create_blob(&mut self) -> Result<u64, Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        Ok(Blob::create(&mut engine.bufmgr)?.first_page_id.to_u64())
+    }
+
+    // 大きな値の末尾に書き足す。書いたものはすぐにバッファプールのページに入る
+    pub fn blob_writer(&self, id: u64) -> Result<BlobWriter, Error> {
+        Ok(BlobWriter {
+            blob: self.open_blob(id)?,
+            engine: Rc::clone(&self.engine),
+        })
+    }
+
+    // 大きな値を先頭から読む。読むたびにページを 1 枚ずつ読むので、値全体はメモリに置かない
+    pub fn blob_reader(&self, id: u64) -> Result<BlobReader, Error> {
+        Ok(BlobReader {
+            blob: self.open_blob(id)?,
+            engine: Rc::clone(&self.engine),
+            pos: 0,
+            cursor: blob::Cursor::default(),
+        })
+    }
+
+    // offset から len バイトを読む。substr と同じく、末尾を超えた分は返さない
+    pub fn read_blob(&self, id: u64, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        let blob = self.open_blob(id)?;
+        let engine = &mut *self.engine.borrow_mut();
+        let len = blob
+            .size(&mut engine.bufmgr)?
+            .saturating_sub(offset)
+            .min(len as u64);
+        let mut buf = vec![0; len as usize];
+        let mut cursor = blob::Cursor::default();
+        blob.read_at(&mut engine.bufmgr, offset, &mut buf, &mut cursor)?;
+        Ok(buf)
+    }
+
+    pub fn blob_size(&self, id: u64) -> Result<u64, Error> {
+        let blob = self.open_blob(id)?;
+        Ok(blob.size(&mut self.engine.borrow_mut().bufmgr)?)
+    }
+
+    fn open_blob(&self, id: u64) -> Result<Blob, Error> {
+        let engine = &mut *self.engine.borrow_mut();
+        Ok(Blob::open(&mut engine.bufmgr, PageId(id))?)
+    }
+
+    // テーブルやインデックスの定義を見る。返した値を持っている間は文を実行できない
+    // 開いたファイルのカタログを作り直すのに使う
+    pub(crate) fn catalog_mut(&self) -> RefMut<'_, Catalog> {
+        RefMut::map(self.engine.borrow_mut(), |engine| &mut engine.catalog)
+    }
+
+    pub fn catalog(&self) -> Ref<'_, Catalog> {
+        Ref::map(self.engine.borrow(), |engine| &engine.catalog)
+    }
+
+    // この接続が準備した文の計画
+    pub fn plan_cache(&self) -> &PlanCache {
+        &self.session.plans
+    }
+
+    // 接続が分け合う結果のキャッシュ。SET result_cache = on の接続だけが使う
+    pub fn result_cache(&self) -> Ref<'_, ResultCache> {
+        Ref::map(self.engine.borrow(), |engine| &engine.result_cache)
+    }
+}
+
+// 閉じた接続で実行中のトランザクションは取り消す
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.in_transaction() {
+            let _ = self.rollback();
+        }
+        self.engine.borrow_mut().activity.disconnect(self.id);
+    }
+}
+
+// 最後の接続がなくなれば、ファイルを次に開いたときに回復のいらない状態にする。
+// 閉じるのに失敗すれば、次に開いたときにログから回復する
+impl Drop for Engine {
+    fn drop(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            let _ = file.close(&mut self.bufmgr, &self.catalog);
+        }
+    }
+}
+
+// INSERT、UPDATE、DELETE の計画が返す、書き換えた行の数
+fn modified_count(rows: &[executor::Row]) -> usize {
+    match rows.first().and_then(|row| row.first()) {
+        Some(Value::Integer(n) | Value::BigInt(n)) => *n as usize,
+        _ => 0,
+    }
+}
+
+// INSERT の計画の VALUES の行
+fn values_mut(plan: &mut PlanNode) -> &mut Vec<executor::Row> {
+    let PlanNode::Insert { input, .. } = plan else {
+        unreachable!()
+    };
+    let input = match &mut **input {
+        PlanNode::Projection { input, .. } => &mut **input,
+        input => input,
+    };
+    let PlanNode::Values { rows } = input else {
+        unreachable!()
+    };
+    rows
+}
+
+// 列の定義の型と照合順。型は組み込みのものか CREATE TYPE で作ったもの
+fn column_type(catalog: &Catalog, def: &ColumnDef) -> Result<(DataType, Collation), Error> {
+    let data_type = DataType::parse(&def.data_type)
+        .or_else(|| {
+            let name = def.data_type.to_lowercase();
+            catalog.enum_type(&name).map(DataType::Enum)
+        })
+        .ok_or_else(|| Error::UnknownType(def.data_type.clone()))?;
+    let collation = match &def.collation {
+        Some(name) => {
+            Collation::parse(name).ok_or_else(|| Error::UnknownCollation(name.clone()))?
+        }
+        None => Collation::default(),
+    };
+    Ok((data_type, collation))
+}
+
+// ALTER TABLE ... ADD COLUMN。行は書き直さないので、前からある行の値が NULL にならない制約や式は付けられない
+fn add_column(catalog: &mut Catalog, table: &str, def: &ColumnDef) -> Result<(), Error> {
+    if def.not_null
+        || def.primary_key
+        || def.unique
+        || def.generated.is_some()
+        || def.compression.is_some()
+    {
+        return Err(Error::AddColumnOptions(def.name.clone()));
+    }
+    if catalog.table(table).is_some_and(|t| t.view.is_some()) {
+        return Err(planner::Error::MaterializedView(table.to_string()).into());
+    }
+    let (data_type, collation) = column_type(catalog, def)?;
+    let column = Column {
+        name: def.name.clone(),
+        data_type,
+        not_null: false,
+        collation,
+    };
+    Ok(catalog.add_column(table, column)?)
+}
+
+// 生成列の式を、列の型への CAST をつけて作ったばかりのテーブルの行に対する式にする
+fn set_generated(catalog: &mut Catalog, create: &CreateTable) -> Result<(), Error> {
+    let table = catalog.table(&create.name).unwrap();
+    let mut generated = vec![];
+    for (column, def) in create.columns.iter().enumerate() {
+        let Some(spec) = &def.generated else {
+            continue;
+        };
+        let ty = table.columns[column].data_type.to_string();
+        let expr = ast::Expr::Function {
+            name: "cast".to_string(),
+            args: vec![
+                spec.expr.clone(),
+                ast::Expr::Literal(ast::Literal::String(ty)),
+            ],
+            distinct: false,
+        };
+        generated.push(catalog::GeneratedColumn {
+            column,
+            expr: Planner::new(catalog).plan_generated_expression(&create.name, &expr)?,
+            stored: spec.stored,
+        });
+    }
+    if !generated.is_empty() {
+        catalog.set_generated(&create.name, generated)?;
+    }
+    Ok(())
+}
+
+// FOR VALUES の範囲の端の式を計算する。列の型に揃えるのはカタログ
+fn partition_bound(
+    catalog: &Catalog,
+    bound: &PartitionBoundSpec,
+) -> Result<catalog::PartitionBound, Error> {
+    let planner = Planner::new(catalog);
+    let value = |expr: &Option<ast::Expr>| -> Result<Option<Value>, Error> {
+        let Some(expr) = expr else {
+            return Ok(None);
+        };
+        let mut rows = planner.plan_values(&[vec![expr.clone()]])?;
+        Ok(rows.pop().and_then(|mut row| row.pop()))
+    };
+    Ok(match bound {
+        PartitionBoundSpec::Range { from, to } => catalog::PartitionBound::Range {
+            from: value(from)?,
+            to: value(to)?,
+        },
+        PartitionBoundSpec::Hash { modulus, remainder } => catalog::PartitionBound::Hash {
+            modulus: *modulus,
+            remainder: *remainder,
+        },
+    })
+}
+
+// 計画が読むテーブルの名前。重なりは除く
+// plan が読むテーブルの今の版。stats の表を読むか行をロックする計画と、TTL のあるテーブルを読む計画は、
+// テーブルが変わらなくても結果が変わりうるので None
+fn table_versions(
+    plan: &PlanNode,
+    catalog: &Catalog,
+    txns: &TransactionManager,
+) -> Option<Vec<TableVersion>> {
+    fn cacheable(plan: &PlanNode) -> bool {
+        !matches!(plan, PlanNode::StatsScan { .. } | PlanNode::LockRows { .. })
+            && plan.children().into_iter().all(cacheable)
+    }
+    if !cacheable(plan) {
+        return None;
+    }
+    let mut names = vec![];
+    plan_tables(plan, &mut names);
+    names
+        .into_iter()
+        .map(|name| {
+            let table = catalog.table(&name)?;
+            if catalog.ttl(table).is_some() {
+                return None;
+            }
+            Some(TableVersion {
+                changes: table.changes(),
+                commits: txns.table_commits(&name),
+                table: name,
+            })
+        })
+        .collect()
+}
+
+fn plan_tables(plan: &PlanNode, names: &mut Vec<String>) {
+    match plan {
+        PlanNode::SeqScan { table, .. }
+        | PlanNode::Gather { table, .. }
+        | PlanNode::IndexScan { table, .. }
+        | PlanNode::FulltextScan { table, .. }
+        | PlanNode::RTreeScan { table, .. }
+        | PlanNode::IndexJoin { table, .. }
+            if !names.contains(table) =>
+        {
+            names.push(table.clone())
+        }
+        _ => {}
+    }
+    for child in plan.children() {
+        plan_tables(child, names);
+    }
+}
+
+fn view_columns(names: Vec<String>, rows: &[executor::Row]) -> Vec<Column> {
+    names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let mut data_type = rows
+                .iter()
+                .find_map(|row| row[i].data_type())
+                .unwrap_or(DataType::Text);
+            if let DataType::Decimal { scale, .. } = &mut data_type {
+                for row in rows {
+                    if let Value::Decimal(d) = &row[i] {
+                        *scale = (*scale).max(d.scale());
+                    }
+                }
+            }
+            Column {
+                name,
+                data_type,
+                not_null: false,
+                collation: Collation::default(),
+            }
+        })
+        .collect()
+}
+
+fn ttl_interval(catalog: &Catalog, expr: &ast::Expr) -> Result<Interval, Error> {
+    let planner = Planner::new(catalog);
+    let mut rows = planner.plan_values(&[vec![expr.clone()]])?;
+    match rows.pop().and_then(|mut row| row.pop()) {
+        Some(Value::Interval(interval)) => Ok(interval),
+        _ => Err(catalog::Error::InvalidTtl.into()),
+    }
+}
+
+// PARTITION BY があれば、作ったテーブルを親にする。できなければ作ったテーブルを消す
+fn partition_table(engine: &mut Engine, create: &CreateTable) -> Result<StatementResult, Error> {
+    if let Some(partition_by) = &create.partition_by {
+        let result = engine.catalog.partition_table(
+            &create.name,
+            partition_by.strategy,
+            &partition_by.column,
+        );
+        if let Err(err) = result {
+            engine.catalog.drop_table(&create.name)?;
+            return Err(err.into());
+        }
+    }
+    Ok(StatementResult::Done("CREATE TABLE"))
+}
+
+// COPY や取り込みで読んだ値を列の型の値にする
+pub(crate) fn input_value(column: &Column, value: Value) -> Result<Value, catalog::Error> {
+    let actual = value.type_name();
+    column.data_type.cast(value).map_err(|err| match err {
+        CoerceError::Mismatch => catalog::Error::DatatypeMismatch {
+            column: column.name.clone(),
+            expected: column.data_type.name().to_string(),
+            actual,
+        },
+        CoerceError::Overflow if column.data_type.is_integer() => {
+            catalog::Error::IntegerOutOfRange(column.name.clone())
+        }
+        CoerceError::Overflow => catalog::Error::NumericFieldOverflow(column.name.clone()),
+        CoerceError::InvalidSyntax(value) => catalog::Error::InvalidSyntax {
+            expected: column.data_type.name().to_string(),
+            value,
+        },
+    })
+}
+
+fn parse_one(sql: &str) -> Result<Statement, Error> {
+    let mut statements = sql::parse(sql)?;
+    if statements.len() != 1 {
+        return Err(Error::MultipleStatements);
+    }
+    Ok(statements.remove(0))
+}
+
+// 実行すると結果の行を返す文
+fn returns_rows(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Query(_) | Statement::Explain { .. } | Statement::Show(_) => true,
+        Statement::Insert(insert) => !insert.returning.is_empty(),
+        Statement::Update(update) => !update.returning.is_empty(),
+        Statement::Delete(delete) => !delete.returning.is_empty(),
+        _ => false,
+    }
+}
+
+// スタンバイでも実行できる、ページもカタログも書き換えない文
+fn reads_only(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Query(_) | Statement::Set { .. } | Statement::Show(_) | Statement::Reset(_) => {
+            true
+        }
+        Statement::Explain { statement, .. } => reads_only(statement),
+        Statement::Copy(copy) => copy.direction == CopyDirection::To,
+        Statement::Transaction(stmt) => !matches!(
+            stmt,
+            TransactionStatement::Prepare(_)
+                | TransactionStatement::CommitPrepared(_)
+                | TransactionStatement::RollbackPrepared(_)
+        ),
+        _ => false,
+    }
+}
+
+// スーパーユーザーだけが実行できる文の名前。テーブルの行の読み書きは計画するときに権限を確かめる
+fn superuser_command(stmt: &Statement) -> Option<&'static str> {
+    Some(match stmt {
+        Statement::CreateTable(_) => "CREATE TABLE",
+        Statement::CreateIndex(_) => "CREATE INDEX",
+        Statement::CreateMaterializedView(_) => "CREATE MATERIALIZED VIEW",
+        Statement::RefreshMaterializedView(_) => "REFRESH MATERIALIZED VIEW",
+        Statement::DropTable(_) => "DROP TABLE",
+        Statement::CreateTrigger(_) => "CREATE TRIGGER",
+        Statement::DropTrigger { .. } => "DROP TRIGGER",
+        Statement::CreateDatabase(_) => "CREATE DATABASE",
+        Statement::Attach { .. } => "ATTACH",
+        Statement::Detach(_) => "DETACH",
+        Statement::CreateRole { .. } => "CREATE ROLE",
+        Statement::AlterRole { .. } => "ALTER ROLE",
+        Statement::DropRole { .. } => "DROP ROLE",
+        Statement::CreateType { .. } => "CREATE TYPE",
+        Statement::AlterType { .. } => "ALTER TYPE",
+        Statement::AlterTable { .. } => "ALTER TABLE",
+        Statement::DropType { .. } => "DROP TYPE",
+        Statement::Grant(_) => "GRANT",
+        Statement::Revoke(_) => "REVOKE",
+        Statement::Analyze { .. } => "ANALYZE",
+        Statement::Vacuum { .. } => "VACUUM",
+        // サーバーのファイルを読み書きする
+        Statement::Copy(_) => "COPY",
+        _ => return None,
+    })
+}
+
+// catalog のテーブルのうち、used の同じテーブルにはないインデックスを持つもののインデックスを作り直す。
+// used を使って書き換えた行は、そのインデックスに入っていない
+fn rebuild_stale_indexes(
+    catalog: &mut Catalog,
+    bufmgr: &mut BufferPoolManager,
+    used: &Catalog,
+) -> Result<(), Error> {
+    let stale: Vec<String> = catalog
+        .tables()
+        .iter()
+        .filter(|table| {
+            used.table(&table.name).is_some_and(|t| {
+                Arc::ptr_eq(&t.storage, &table.storage)
+                    && table
+                        .indexes
+                        .iter()
+                        .any(|i| t.indexes.iter().all(|j| j.name != i.name))
+            })
+        })
+        .map(|table| table.name.clone())
+        .collect();
+    if stale.is_empty() {
+        return Ok(());
+    }
+    // 一意性に反する行があれば失敗するので、写しで作り直してから入れ替える
+    let mut rebuilt = catalog.clone();
+    for name in stale {
+        rebuilt.rebuild_indexes(bufmgr, &name)?;
+    }
+    *catalog = rebuilt;
+    Ok(())
+}
+
+// カタログを変える文なら Some で、中身はトランザクションの中で実行するときにテーブルにかけるロック。
+// インデックスやトリガーを作るものは作っている間だけ書き換えを、テーブルを消すものは読むのも待たせる。
+// 列を足すものは読み書きを待たせず、作り変えている間にテーブルを消させないだけにする
+fn ddl_lock(stmt: &Statement) -> Option<Option<(&str, LockMode)>> {
+    Some(match stmt {
+        Statement::CreateIndex(create) => Some((&create.table, LockMode::Shared)),
+        Statement::CreateTrigger(create) => Some((&create.table, LockMode::Shared)),
+        Statement::DropTrigger { table, .. } => Some((table, LockMode::Shared)),
+        Statement::CreateTable(create) => create
+            .partition_of
+            .as_ref()
+            .map(|partition_of| (&partition_of.parent[..], LockMode::Shared)),
+        Statement::DropTable(drop) => Some((&drop.name, LockMode::Exclusive)),
+        Statement::AlterTable { name, .. } => Some((name, LockMode::IntentionShared)),
+        Statement::CreateMaterializedView(_)
+        | Statement::RefreshMaterializedView(_)
+        | Statement::CreateRole { .. }
+        | Statement::AlterRole { .. }
+        | Statement::DropRole { .. }
+        | Statement::CreateType { .. }
+        | Statement::AlterType { .. }
+        | Statement::DropType { .. }
+        | Statement::Grant(_)
+        | Statement::Revoke(_) => None,
+        _ => return None,
+    })
+}
+
+// INSERT、UPDATE、DELETE、CREATE TABLE、DROP TABLE が書き換えるテーブルの名前
+fn target_table(stmt: &Statement) -> Option<&str> {
+    Some(match stmt {
+        Statement::Insert(insert) => &insert.table,
+        Statement::Update(update) => &update.table,
+        Statement::Delete(delete) => &delete.table,
+        Statement::CreateTable(create) => &create.name,
+        Statement::DropTable(drop) => &drop.name,
+        _ => return None,
+    })
+}
+
+// 書き換えるテーブルの名前を name にした文
+fn rename_target(stmt: &Statement, name: &str) -> Statement {
+    let mut stmt = stmt.clone();
+    match &mut stmt {
+        Statement::Insert(insert) => insert.table = name.to_string(),
+        Statement::Update(update) => update.table = name.to_string(),
+        Statement::Delete(delete) => delete.table = name.to_string(),
+        Statement::CreateTable(create) => create.name = name.to_string(),
+        Statement::DropTable(drop) => drop.name = name.to_string(),
+        _ => unreachable!(),
+    }
+    stmt
+}
+
+fn apply_role_options(role: &mut Role, options: &RoleOptions) {
+    if let Some(superuser) = options.superuser {
+        role.superuser = superuser;
+    }
+    if let Some(login) = options.login {
+        role.login = login;
+    }
+    if let Some(password) = &options.password {
+        role.set_password(password.as_deref());
+    }
+}
+
+fn transaction_tag(stmt: &TransactionStatement) -> &'static str {
+    match stmt {
+        TransactionStatement::Begin => "BEGIN",
+        TransactionStatement::Commit => "COMMIT",
+        TransactionStatement::Rollback => "ROLLBACK",
+        TransactionStatement::Savepoint(_) => "SAVEPOINT",
+        TransactionStatement::RollbackTo(_) => "ROLLBACK",
+        TransactionStatement::Release(_) => "RELEASE",
+        TransactionStatement::SetIsolationLevel(_) => "SET",
+        TransactionStatement::Prepare(_) => "PREPARE TRANSACTION",
+        TransactionStatement::CommitPrepared(_) => "COMMIT PREPARED",
+        TransactionStatement::RollbackPrepared(_) => "ROLLBACK PREPARED",
+    }
+}
+
+// Connection::blob_writer が返す、大きな値の末尾に書き足す Write
+pub struct BlobWriter {
+    blob: Blob,
+    engine: Rc<RefCell<Engine>>,
+}
+
+impl Write for BlobWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let engine = &mut *self.engine.borrow_mut();
+        self.blob
+            .append(&mut engine.bufmgr, buf)
+            .map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Connection::blob_reader が返す、大きな値を読む Read と Seek
+pub struct BlobReader {
+    blob: Blob,
+    engine: Rc<RefCell<Engine>>,
+    pos: u64,
+    cursor: blob::Cursor,
+}
+
+impl Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let engine = &mut *self.engine.borrow_mut();
+        let n = self
+            .blob
+            .read_at(&mut engine.bufmgr, self.pos, buf, &mut self.cursor)
+            .map_err(io::Error::other)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for BlobReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::Current(n) => (self.pos, n),
+            SeekFrom::End(n) => {
+                let engine = &mut *self.engine.borrow_mut();
+                let size = self
+                    .blob
+                    .size(&mut engine.bufmgr)
+                    .map_err(io::Error::other)?;
+                (size, n)
+            }
+        };
+        self.pos = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+// 問い合わせが返した行。実行し終えてからひとつずつ渡す
+#[derive(Debug)]
+pub struct Rows {
+    columns: Arc<[String]>,
+    rows: vec::IntoIter<executor::Row>,
+}
+
+impl Rows {
+    fn new(columns: Vec<String>, rows: Vec<executor::Row>) -> Rows {
+        Rows {
+            columns: columns.into(),
+            rows: rows.into_iter(),
+        }
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    // まだ渡していない行の数
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.len() == 0
+    }
+}
+
+impl Iterator for Rows {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        let values = self.rows.next()?;
+        Some(Row {
+            columns: self.columns.clone(),
+            values,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    columns: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    // index の列を T で読む。index は位置か列名
+    pub fn get<T: FromValue>(&self, index: impl ColumnIndex) -> Result<T, Error> {
+        let i = index.position(&self.columns)?;
+        T::from_value(&self.values[i]).ok_or_else(|| Error::InvalidColumnType {
+            index: i,
+            column: self.columns[i].clone(),
+            expected: T::NAME,
+            found: self.values[i].type_name(),
+        })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+}
+
+pub trait ColumnIndex {
+    fn position(&self, columns: &[String]) -> Result<usize, Error>;
+}
+
+impl ColumnIndex for usize {
+    fn position(&self, columns: &[String]) -> Result<usize, Error> {
+        if *self < columns.len() {
+            Ok(*self)
+        } else {
+            Err(Error::ColumnNotFound(self.to_string()))
+        }
+    }
+}
+
+impl ColumnIndex for &str {
+    fn position(&self, columns: &[String]) -> Result<usize, Error> {
+        columns
+            .iter()
+            .position(|c| c == self)
+            .ok_or_else(|| Error::ColumnNotFound(format!("\"{}\"", self)))
+    }
+}
+
+// 結果の値から読める Rust の型。読めなければ None
+pub trait FromValue: Sized {
+    const NAME: &'static str;
+
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FromValue for Value {
+    const NAME: &'static str = "value";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    const NAME: &'static str = T::NAME;
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    const NAME: &'static str = "i64";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Integer(n) | Value::BigInt(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for i32 {
+    const NAME: &'static str = "i32";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        i64::from_value(value).and_then(|n| n.try_into().ok())
+    }
+}
+
+// 整数も実数として読む
+impl FromValue for f64 {
+    const NAME: &'static str = "f64";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Real(x) => Some(*x),
+            Value::Integer(n) | Value::BigInt(n) => Some(*n as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    const NAME: &'static str = "bool";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    const NAME: &'static str = "String";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Text(s) => Some(s.clone()),
+            Value::Json(s) => Some(s.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    const NAME: &'static str = "Vec<u8>";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Blob(b) => Some(b.to_vec()),
+            _ => None,
+        }
+    }
+}
+
+// 配列は要素ごとに読む
+impl<T: FromValue> FromValue for Vec<T> {
+    const NAME: &'static str = "Vec";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Array(items) => items.iter().map(T::from_value).collect(),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_from_value {
+    ($ty:ty, $variant:ident) => {
+        impl FromValue for $ty {
+            const NAME: &'static str = stringify!($ty);
+
+            fn from_value(value: &Value) -> Option<Self> {
+                match value {
+                    Value::$variant(v) => Some(*v),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_from_value!(Decimal, Decimal);
+impl_from_value!(Date, Date);
+impl_from_value!(Time, Time);
+impl_from_value!(Timestamp, Timestamp);
+impl_from_value!(Uuid, Uuid);
+impl_from_value!(Interval, Interval);
+
+// 結果の 1 行から作れる Rust の型。タプルは位置で、row_struct! の構造体は列名で読む
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, Error>;
+}
+
+// 文の引数にできる Rust の値。タプルと row_struct! の構造体は並びの順に $1, $2, ... になる
+pub trait ToParams {
+    fn to_params(&self) -> Vec<Value>;
+}
+
+impl ToParams for [Value] {
+    fn to_params(&self) -> Vec<Value> {
+        self.to_vec()
+    }
+}
+
+impl<T: ToParams + ?Sized> ToParams for &T {
+    fn to_params(&self) -> Vec<Value> {
+        (**self).to_params()
+    }
+}
+
+impl<const N: usize> ToParams for [Value; N] {
+    fn to_params(&self) -> Vec<Value> {
+        self.to_vec()
+    }
+}
+
+impl ToParams for Vec<Value> {
+    fn to_params(&self) -> Vec<Value> {
+        self.clone()
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($t:ident $i:tt),+) => {
+        impl<$($t: FromValue),+> FromRow for ($($t,)+) {
+            fn from_row(row: &Row) -> Result<Self, Error> {
+                Ok(($(row.get::<$t>($i)?,)+))
+            }
+        }
+
+        impl<$($t: Clone + Into<Value>),+> ToParams for ($($t,)+) {
+            fn to_params(&self) -> Vec<Value> {
+                vec![$(self.$i.clone().into()),+]
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+// 構造体を定義し、フィールドと同じ名前の列から読む FromRow と、フィールドの順に引数にする ToParams を実装する。
+// フィールドの型は FromValue と Into<Value> と Clone を実装していること
+//
+//     row_struct! {
+//         #[derive(Debug)]
+//         pub struct User { pub id: i64, pub name: String }
+//     }
+//     let users: Vec<User> = conn.query_as("SELECT id, name FROM users", &[])?;
+#[macro_export]
+macro_rules! row_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::connection::FromRow for $name {
+            fn from_row(
+                row: &$crate::connection::Row,
+            ) -> ::std::result::Result<Self, $crate::connection::Error> {
+                Ok($name {
+                    $($field: row.get(stringify!($field))?),*
+                })
+            }
+        }
+
+        impl $crate::connection::ToParams for $name {
+            fn to_params(&self) -> ::std::vec::Vec<$crate::types::Value> {
+                vec![$(::std::clone::Clone::clone(&self.$field).into()),*]
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::ast::CopyFormat;
+
+    #[test]
+    fn test_connection() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL, score REAL);
+             CREATE INDEX users_name ON users (name);",
+        )
+        .unwrap();
+        for (id, name, score) in [(1, "alice", Some(1.5)), (2, "bob", None)] {
+            let params = [Value::from(id), Value::from(name), Value::from(score)];
+            let n = conn
+                .execute("INSERT INTO users VALUES ($1, $2, $3)", &params)
+                .unwrap();
+            assert_eq!(n, 1);
+        }
+        // INTEGER は 32 bit に収まらなければあふれ、収まらない整数のリテラルは BIGINT
+        let err = conn
+            .execute("INSERT INTO users VALUES (2147483648, 'x', NULL)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "22003");
+        let err = conn.query_row("SELECT 2147483647 + 1", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "22003");
+        let row = conn
+            .query_row("SELECT 2147483648, -9223372036854775808", &[])
+            .unwrap();
+        assert_eq!(
+            row.into_values(),
+            vec![Value::BigInt(1 << 31), Value::BigInt(i64::MIN)]
+        );
+
+        let rows: Vec<(i64, String, Option<f64>)> = conn
+            .query(
+                "SELECT id, name, score FROM users WHERE id >= ? ORDER BY id",
+                &[Value::from(1)],
+            )
+            .unwrap()
+            .map(|row| {
+                (
+                    row.get(0).unwrap(),
+                    row.get("name").unwrap(),
+                    row.get("score").unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (1, "alice".to_string(), Some(1.5)),
+                (2, "bob".to_string(), None)
+            ]
+        );
+        let row = conn
+            .query_row("SELECT name FROM users WHERE id = $1", &[Value::from(2)])
+            .unwrap();
+        assert!(matches!(
+            row.get::<i64>(0),
+            Err(Error::InvalidColumnType { index: 0, .. })
+        ));
+        assert!(matches!(
+            row.get::<String>("x"),
+            Err(Error::ColumnNotFound(_))
+        ));
+        assert!(matches!(
+            conn.query_row("SELECT name FROM users WHERE id = $2", &[Value::from(2)]),
+            Err(Error::Planner(planner::Error::ParameterNotFound(2)))
+        ));
+        assert!(matches!(
+            conn.query_row("SELECT name FROM users WHERE id = 9", &[]),
+            Err(Error::NoRows)
+        ));
+
+        // Err を返せばロールバックする
+        let result = conn.transaction(|conn| {
+            conn.execute("DELETE FROM users WHERE id = 1", &[])?;
+            conn.execute("INSERT INTO users VALUES (2, 'carol', NULL)", &[])
+        });
+        assert!(matches!(result, Err(Error::Executor(_))));
+        assert!(!conn.in_transaction());
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM users", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, 2);
+
+        conn.transaction(|conn| conn.execute("UPDATE users SET score = 2 WHERE id = 2", &[]))
+            .unwrap();
+        // 別の接続からもコミットした変更と登録した関数が見える
+        conn.register_function(ScalarFunction::new(
+            "double",
+            vec![DataType::Real],
+            DataType::Real,
+            |args| Ok(Value::Real(args[0].as_f64().unwrap() * 2.0)),
+        ))
+        .unwrap();
+        let mut other = db.connect();
+        let score: f64 = other
+            .query_row("SELECT double(score) FROM users WHERE id = 2", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(score, 4.0);
+        // 行を返さない文は実行しない
+        let err = other.query("DELETE FROM users", &[]).unwrap_err();
+        assert!(matches!(err, Error::NoResultSet));
+        assert_eq!(err.sqlstate(), "42809");
+        let count: i64 = other
+            .query_row("SELECT count(*) FROM users", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert!(count > 0);
+        assert!(matches!(
+            other.execute("SELECT 1; SELECT 2", &[]),
+            Err(Error::MultipleStatements)
+        ));
+
+        // 閉じた接続のトランザクションは取り消す
+        let mut third = db.connect();
+        third.begin().unwrap();
+        third
+            .execute("INSERT INTO users VALUES (5, 'dave', NULL)", &[])
+            .unwrap();
+        drop(third);
+        other.set_lock_timeout(Some(Duration::ZERO));
+        let inserted = other
+            .execute("INSERT INTO users VALUES (5, 'erin', NULL)", &[])
+            .unwrap();
+        assert_eq!(inserted, 1);
+    }
+
+    crate::row_struct! {
+        #[derive(Debug, Clone, PartialEq)]
+        struct Item {
+            id: i64,
+            name: String,
+            price: Option<Decimal>,
+        }
+    }
+
+    #[test]
+    fn test_query_as() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute(
+            "CREATE TABLE items (id INTEGER, name TEXT, price NUMERIC(6,2))",
+            &[],
+        )
+        .unwrap();
+        let items = [
+            Item {
+                id: 1,
+                name: "pen".to_string(),
+                price: Decimal::new(150, 2),
+            },
+            Item {
+                id: 2,
+                name: "ink".to_string(),
+                price: None,
+            },
+        ];
+        for item in &items {
+            conn.execute("INSERT INTO items VALUES ($1, $2, $3)", &item.to_params())
+                .unwrap();
+        }
+
+        // 列の順が違っても名前で読む
+        let rows: Vec<Item> = conn
+            .query_as("SELECT price, name, id FROM items ORDER BY id", &[])
+            .unwrap();
+        assert_eq!(rows, items);
+        let rows: Vec<(String, Option<Decimal>)> = conn
+            .query_as("SELECT name, price FROM items WHERE id = $1", &(2,))
+            .unwrap();
+        assert_eq!(rows, vec![("ink".to_string(), None)]);
+
+        let err = conn
+            .query_as::<Item>("SELECT id, name, id AS price FROM items", &[])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot read column 2 (\"price\") of type integer as Decimal"
+        );
+        assert!(matches!(
+            conn.query_as::<Item>("SELECT id, name FROM items", &[]),
+            Err(Error::ColumnNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_execute_many() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, n INTEGER)")
+            .unwrap();
+        // 列の順を入れ替え、1 組で 2 行を入れる
+        let sets = (0..1500).map(|i| (format!("x{}", i), i * 2, i * 2 + 1));
+        let count = conn
+            .execute_many(
+                "INSERT INTO t (name, id) VALUES ($1, $2), ($1 || '!', $3)",
+                sets,
+            )
+            .unwrap();
+        assert_eq!(count, 3000);
+        let row = conn
+            .query_row("SELECT count(*), sum(id), max(n) FROM t", &[])
+            .unwrap();
+        assert_eq!(
+            row.values(),
+            [Value::BigInt(3000), Value::BigInt(2999 * 1500), Value::Null]
+        );
+        let name: String = conn
+            .query_row("SELECT name FROM t WHERE id = 1001", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(name, "x500!");
+
+        // 計画し直す文は 1 組ずつ実行する
+        let sets: Vec<[Value; 2]> = vec![
+            [Value::Integer(10), Value::Integer(0)],
+            [Value::Integer(20), Value::Integer(1)],
+        ];
+        assert_eq!(
+            conn.execute_many("UPDATE t SET n = $1 WHERE id = $2", &sets)
+                .unwrap(),
+            2
+        );
+
+        // 失敗した組の番号を返し、トランザクションの中でもその前の状態に戻す
+        conn.begin().unwrap();
+        conn.execute("DELETE FROM t WHERE id = 0", &[]).unwrap();
+        let sets = [(5000,), (5001,), (3,), (5002,)];
+        let err = conn
+            .execute_many("INSERT INTO t (id) VALUES ($1)", sets)
+            .unwrap_err();
+        assert!(matches!(err, Error::Many { index: 2, .. }), "{}", err);
+        assert_eq!(err.sqlstate(), "23505");
+        conn.commit().unwrap();
+        let row = conn
+            .query_row("SELECT count(*), sum(n) FROM t", &[])
+            .unwrap();
+        assert_eq!(row.values(), [Value::BigInt(2999), Value::BigInt(20)]);
+    }
+
+    #[test]
+    fn test_profile() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch("CREATE TABLE t (a INTEGER, b TEXT); SET work_mem = '64kB'")
+            .unwrap();
+        let rows: Vec<(i64, String)> = (1..=3000).map(|i| (i, format!("{:0>100}", i))).collect();
+        conn.execute_many("INSERT INTO t VALUES ($1, $2)", rows)
+            .unwrap();
+        conn.query("SELECT * FROM t", &[]).unwrap();
+        assert!(conn.last_profile().is_none());
+
+        conn.set_profiling(true);
+        conn.query("SELECT a FROM t WHERE a > 1000 ORDER BY b DESC", &[])
+            .unwrap();
+        let profile = conn.last_profile().unwrap();
+        let names: Vec<_> = profile
+            .operators
+            .iter()
+            .map(|op| op.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Projection",
+                "Sort",
+                "Projection",
+                "Filter",
+                "Seq Scan on t"
+            ]
+        );
+        let [projection, sort, _, filter, scan] = &profile.operators[..] else {
+            unreachable!()
+        };
+        assert_eq!(projection.metrics.rows, 2000);
+        assert_eq!(filter.metrics.rows, 2000);
+        assert_eq!(scan.metrics.rows, 3000);
+        assert!(scan.metrics.hits + scan.metrics.reads > 0);
+        // 並べ替えは work_mem に収まらず書き出す
+        assert!(sort.metrics.spill_files > 0 && sort.metrics.spill_bytes > 0);
+        assert_eq!(scan.metrics.spill_files, 0);
+        assert!(sort.metrics.memory_peak > 0);
+        assert!(sort.self_time <= sort.metrics.elapsed);
+        let folded = profile.folded();
+        assert!(folded[4].starts_with("Projection;Sort;Projection;Filter;Seq Scan on t "));
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE t (a INTEGER, b TEXT)", &[])
+            .unwrap();
+        let rows: Vec<(i64, String)> = (1..=3000).map(|i| (i, format!("{:0>100}", i))).collect();
+        conn.execute_many("INSERT INTO t VALUES ($1, $2)", rows)
+            .unwrap();
+        let sql = "SELECT a FROM t ORDER BY b DESC";
+
+        conn.execute("SET max_result_rows = 100", &[]).unwrap();
+        let err = conn.query(sql, &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "54000");
+        assert_eq!(
+            conn.query(&format!("{} LIMIT 100", sql), &[])
+                .unwrap()
+                .len(),
+            100
+        );
+        assert!(conn.query(&format!("EXPLAIN ANALYZE {}", sql), &[]).is_ok());
+        conn.execute("RESET max_result_rows", &[]).unwrap();
+
+        conn.execute_batch("SET work_mem = '64kB'; SET temp_file_limit = '64kB'")
+            .unwrap();
+        let err = conn.query(sql, &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "53400");
+        conn.execute_batch("RESET temp_file_limit; RESET work_mem; SET max_query_memory = '128kB'")
+            .unwrap();
+        let err = conn.query(sql, &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "53200");
+
+        // 上限を超えた問い合わせのあとも、接続は使える
+        conn.execute("RESET max_query_memory", &[]).unwrap();
+        assert_eq!(conn.query(sql, &[]).unwrap().len(), 3000);
+    }
+
+    #[test]
+    fn test_blob_stream() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let id = conn.create_blob().unwrap();
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let mut writer = conn.blob_writer(id).unwrap();
+        io::copy(&mut &data[..], &mut writer).unwrap();
+        // 書いている間も文を実行できる
+        conn.execute("CREATE TABLE t (a INTEGER)", &[]).unwrap();
+        writer.write_all(b"end").unwrap();
+        assert_eq!(conn.blob_size(id).unwrap(), 100_003);
+
+        let mut reader = conn.blob_reader(id).unwrap();
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(&read[..100_000], &data[..]);
+        assert_eq!(&read[100_000..], b"end");
+        reader.seek(SeekFrom::Start(50_000)).unwrap();
+        let mut buf = [0; 10];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[50_000..50_010]);
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 100_000);
+        assert!(reader.seek(SeekFrom::Current(-200_000)).is_err());
+
+        assert_eq!(conn.read_blob(id, 4090, 20).unwrap(), data[4090..4110]);
+        assert_eq!(
+            conn.read_blob(id, 99_999, 20).unwrap(),
+            [data[99_999], b'e', b'n', b'd']
+        );
+        assert!(conn.read_blob(id, 200_000, 20).unwrap().is_empty());
+        let err = conn.blob_reader(id + 1).err().unwrap();
+        assert_eq!(err.sqlstate(), "42704");
+    }
+
+    #[test]
+    fn test_fulltext() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE doc (id INTEGER, body TEXT)", &[])
+            .unwrap();
+        let rows: Vec<(i64, String)> = (0..2000).map(|i| (i, format!("note {}", i))).collect();
+        conn.execute_many("INSERT INTO doc VALUES ($1, $2)", rows)
+            .unwrap();
+        conn.execute_batch(
+            "CREATE FULLTEXT INDEX doc_body ON doc (body);
+             INSERT INTO doc VALUES (1, 'The quick brown fox'), (2, 'A fox, a fox and a dog');
+             INSERT INTO doc VALUES (3, 'lazy DOG'), (4, NULL);
+             ANALYZE doc",
+        )
+        .unwrap();
+        let sql = "SELECT id, body FROM doc WHERE MATCH(body) AGAINST('fox dog')
+                   ORDER BY MATCH(body) AGAINST('fox dog') DESC, id";
+        let ids = |conn: &mut Connection| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.values()[0].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&mut conn), [2, 1, 3].map(Value::Integer));
+        let mut plan = conn.query(&format!("EXPLAIN {}", sql), &[]).unwrap();
+        assert!(plan.any(|row| matches!(
+            &row.values()[0],
+            Value::Text(line) if line.contains("Fulltext Scan using doc_body on doc")
+        )));
+
+        // 書き換えた行と消した行の語は引けなくなり、新しい語で引ける
+        conn.execute_batch(
+            "UPDATE doc SET body = 'a cat' WHERE id = 1;
+             DELETE FROM doc WHERE id = 3;
+             UPDATE doc SET body = 'dog days' WHERE id = 10",
+        )
+        .unwrap();
+        assert_eq!(ids(&mut conn), [2, 10].map(Value::Integer));
+        let err = conn
+            .execute("CREATE FULLTEXT INDEX doc_id ON doc (id)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42804");
+        assert!(conn.check().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rtree() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE place (id INTEGER, loc POINT)", &[])
+            .unwrap();
+        let rows: Vec<(i64, String)> = (0..2500)
+            .map(|i| (i, format!("({},{})", i % 50, i / 50)))
+            .collect();
+        conn.execute_many("INSERT INTO place VALUES ($1, $2)", rows)
+            .unwrap();
+        conn.execute_batch(
+            "CREATE INDEX place_loc ON place USING gist (loc);
+             INSERT INTO place VALUES (2500, NULL), (2501, POINT '(0.5,0.5)');
+             DELETE FROM place WHERE id = 51;
+             ANALYZE place",
+        )
+        .unwrap();
+        let ids = |conn: &mut Connection, sql: &str| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.values()[0].clone())
+                .collect::<Vec<_>>()
+        };
+        let sql = "SELECT id FROM place WHERE loc <@ BOX '(0,0),(1,1)' ORDER BY id";
+        assert_eq!(ids(&mut conn, sql), [0, 1, 50, 2501].map(Value::Integer));
+        let sql = "SELECT id FROM place WHERE BOX '(48.5,48.5),(60,60)' && loc AND id > 0";
+        assert_eq!(ids(&mut conn, sql), [2499].map(Value::Integer));
+        let mut plan = conn.query(&format!("EXPLAIN {}", sql), &[]).unwrap();
+        assert!(plan.any(|row| matches!(
+            &row.values()[0],
+            Value::Text(line) if line.contains("R-tree Scan using place_loc on place")
+        )));
+
+        // 近い順の走査は並べ替えずに LIMIT の分だけ読む。NULL は最後に来る
+        let sql = "SELECT id, loc <-> POINT '(1.1,1.2)' AS d FROM place ORDER BY d LIMIT 3";
+        assert_eq!(ids(&mut conn, sql), [101, 52, 2501].map(Value::Integer));
+        let mut plan = conn.query(&format!("EXPLAIN {}", sql), &[]).unwrap();
+        assert!(
+            !plan.any(|row| matches!(&row.values()[0], Value::Text(line) if line.contains("Sort")))
+        );
+        let sql = "SELECT id FROM place WHERE id >= 2499 ORDER BY loc <-> POINT '(0,0)'";
+        assert_eq!(ids(&mut conn, sql), [2501, 2499, 2500].map(Value::Integer));
+        assert!(conn.check().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_partitioning() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE m (k INTEGER, v TEXT) PARTITION BY RANGE (k);
+             CREATE TABLE m1 PARTITION OF m FOR VALUES FROM (MINVALUE) TO (10);
+             CREATE TABLE m2 PARTITION OF m FOR VALUES FROM (10) TO (20);
+             CREATE TABLE h (k INTEGER) PARTITION BY HASH (k);
+             CREATE TABLE h0 PARTITION OF h FOR VALUES WITH (MODULUS 2, REMAINDER 0);
+             CREATE TABLE h1 PARTITION OF h FOR VALUES WITH (MODULUS 2, REMAINDER 1)",
+        )
+        .unwrap();
+        let rows: Vec<(i64, String)> = (0..20).map(|k| (k, format!("v{}", k))).collect();
+        conn.execute_many("INSERT INTO m VALUES ($1, $2)", rows)
+            .unwrap();
+        conn.execute_many("INSERT INTO h VALUES ($1)", (0..100).map(|k| (k,)))
+            .unwrap();
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM m1"), 10);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM m WHERE k >= 5"), 15);
+        assert_eq!(
+            count(&mut conn, "SELECT count(*) FROM h0")
+                + count(&mut conn, "SELECT count(*) FROM h1"),
+            100
+        );
+        let err = conn
+            .execute("INSERT INTO m VALUES (20, 'x')", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "23514");
+        let err = conn
+            .execute("INSERT INTO m2 VALUES (1, 'x')", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "23514");
+
+        // 範囲に入らない子は読まない
+        let plan: Vec<_> = conn
+            .query("EXPLAIN SELECT v FROM m WHERE k = 15", &[])
+            .unwrap()
+            .map(|row| row.values()[0].to_string())
+            .collect();
+        assert!(
+            plan.iter().any(|line| line.contains("Seq Scan on m2")),
+            "{:?}",
+            plan
+        );
+        assert!(!plan.iter().any(|line| line.contains("m1")), "{:?}", plan);
+        let plan: Vec<_> = conn
+            .query("EXPLAIN SELECT * FROM h WHERE k = 3", &[])
+            .unwrap()
+            .map(|row| row.values()[0].to_string())
+            .collect();
+        assert_eq!(
+            plan.iter().filter(|line| line.contains("Seq Scan")).count(),
+            1
+        );
+
+        // 分け方の列を変えた行は別の子に移る
+        let updated = conn
+            .execute("UPDATE m SET k = k + 10 WHERE k < 3", &[])
+            .unwrap();
+        assert_eq!(updated, 3);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM m2"), 13);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM m WHERE k = 12"), 2);
+        conn.execute("DELETE FROM m WHERE k >= 10", &[]).unwrap();
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM m"), 7);
+        assert!(conn.check().unwrap().is_empty());
+
+        conn.execute("DROP TABLE m", &[]).unwrap();
+        let err = conn.execute("SELECT * FROM m1", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42P01");
+    }
+
+    #[test]
+    fn test_runtime_partition_pruning() {
+        let mut conn = Database::open_temporary().unwrap().connect();
+        conn.execute_batch(
+            "CREATE TABLE m (k INTEGER, v INTEGER) PARTITION BY RANGE (k);
+             CREATE TABLE m1 PARTITION OF m FOR VALUES FROM (MINVALUE) TO (1000);
+             CREATE TABLE m2 PARTITION OF m FOR VALUES FROM (1000) TO (MAXVALUE);
+             CREATE TABLE dim (id INTEGER);
+             INSERT INTO dim VALUES (1500), (1501), (1502);
+             INSERT INTO m WITH RECURSIVE g (n) AS (SELECT 0 UNION ALL SELECT n + 1 FROM g WHERE n < 1999)
+                 SELECT n, n FROM g;
+             ANALYZE m1;
+             ANALYZE m2;
+             ANALYZE dim",
+        )
+        .unwrap();
+        conn.set_profiling(true);
+        let scans = |conn: &Connection| -> Vec<(String, u64, u64)> {
+            conn.last_profile()
+                .unwrap()
+                .operators
+                .iter()
+                .filter(|op| op.name.starts_with("Seq Scan on m"))
+                .map(|op| (op.name.clone(), op.metrics.loops, op.metrics.rows))
+                .collect()
+        };
+
+        // $n の値で範囲に入らないとわかった子は開かない。二度目は同じ計画を使う
+        conn.prepare("q", "SELECT v FROM m WHERE k = $1", &[])
+            .unwrap();
+        for (k, loops) in [(1500, [0, 1]), (20, [1, 0])] {
+            let Some(StatementResult::Rows(rows)) =
+                conn.execute_prepared("q", &[Value::from(k)]).unwrap()
+            else {
+                panic!("not rows");
+            };
+            let rows: Vec<_> = rows.map(|row| row.into_values()).collect();
+            assert_eq!(rows, [vec![Value::Integer(k)]]);
+            let expected = [
+                ("Seq Scan on m1".to_string(), loops[0], loops[0] * 1000),
+                ("Seq Scan on m2".to_string(), loops[1], loops[1] * 1000),
+            ];
+            assert_eq!(scans(&conn), expected);
+        }
+        assert_eq!(conn.plan_cache().hits(), 1);
+
+        // ハッシュ表のキーの範囲に入らない子は、ハッシュ表を作る間に読んだ行のあとは読まない
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM m JOIN dim ON m.k = dim.id", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, 3);
+        let scans = scans(&conn);
+        assert!(
+            scans.contains(&("Seq Scan on m1".to_string(), 1, 4)),
+            "{:?}",
+            scans
+        );
+        assert!(
+            scans.contains(&("Seq Scan on m2".to_string(), 1, 1000)),
+            "{:?}",
+            scans
+        );
+    }
+
+    #[test]
+    fn test_generated_columns() {
+        let mut conn = Database::open_temporary().unwrap().connect();
+        conn.execute_batch(
+            "CREATE TABLE t (
+                 a INTEGER,
+                 b INTEGER,
+                 total BIGINT GENERATED ALWAYS AS (a + b) STORED,
+                 twice INTEGER GENERATED ALWAYS AS (a * 2)
+             );
+             INSERT INTO t VALUES (1, 2), (3, 4);
+             INSERT INTO t (b, a) VALUES (10, 20);
+             CREATE INDEX t_total ON t (total)",
+        )
+        .unwrap();
+        let rows = |conn: &mut Connection, sql: &str| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.into_values())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            rows(&mut conn, "SELECT a, total, twice FROM t WHERE total = 7"),
+            [vec![Value::Integer(3), Value::BigInt(7), Value::Integer(6)]]
+        );
+        conn.execute("UPDATE t SET a = 5 WHERE a = 1", &[]).unwrap();
+        assert_eq!(
+            rows(&mut conn, "SELECT total, twice FROM t WHERE a = 5"),
+            [vec![Value::BigInt(7), Value::Integer(10)]]
+        );
+        assert_eq!(
+            rows(&mut conn, "SELECT sum(twice) FROM t WHERE b > 2"),
+            [vec![Value::BigInt(46)]]
+        );
+
+        for sql in [
+            "INSERT INTO t (a, total) VALUES (1, 1)",
+            "UPDATE t SET twice = 0",
+        ] {
+            let err = conn.execute(sql, &[]).unwrap_err();
+            assert_eq!(err.sqlstate(), "428C9", "{}", sql);
+        }
+        let err = conn
+            .execute("CREATE INDEX t_twice ON t (twice)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "0A000");
+        let err = conn
+            .execute(
+                "CREATE TABLE u (a INTEGER, b INTEGER GENERATED ALWAYS AS (a), \
+                 c INTEGER GENERATED ALWAYS AS (b + 1))",
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42P17");
+        assert!(conn.catalog().table("u").is_none());
+        let err = conn
+            .execute(
+                "CREATE TABLE u (a TIMESTAMP GENERATED ALWAYS AS (now()) STORED)",
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42P17");
+        let catalog = conn.catalog();
+        let err = catalog
+            .table("t")
+            .unwrap()
+            .check_drop_column(1)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot drop column b because generated column total depends on it"
+        );
+    }
+
+    #[test]
+    fn test_ttl() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE sessions (id INTEGER, seen TIMESTAMP) TTL seen + INTERVAL '1 day';
+             INSERT INTO sessions VALUES
+                 (1, now()), (2, now() - INTERVAL '2 days'), (3, NULL),
+                 (4, now() - INTERVAL '25 hours')",
+        )
+        .unwrap();
+        let ids = |conn: &mut Connection| {
+            conn.query("SELECT id FROM sessions ORDER BY id", &[])
+                .unwrap()
+                .map(|row| row.values()[0].clone())
+                .collect::<Vec<_>>()
+        };
+        // 期限の切れた行は取り除く前から見えない。ttl_filter を切れば見える
+        assert_eq!(ids(&mut conn), [1, 3].map(Value::Integer));
+        conn.execute("SET ttl_filter = off", &[]).unwrap();
+        assert_eq!(ids(&mut conn), [1, 2, 3, 4].map(Value::Integer));
+        conn.execute("VACUUM sessions", &[]).unwrap();
+        assert_eq!(ids(&mut conn), [1, 3].map(Value::Integer));
+        assert!(conn.check().unwrap().is_empty());
+
+        let err = conn
+            .execute("CREATE TABLE t (id INTEGER) TTL id + INTERVAL '1 day'", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42804");
+        let err = conn
+            .execute("CREATE TABLE t (at DATE) TTL at + INTERVAL '-1 day'", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "22023");
+        assert!(conn.execute("SELECT * FROM t", &[]).is_err());
+    }
+
+    #[test]
+    fn test_materialized_view() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (k INTEGER, v NUMERIC(10,2));
+             INSERT INTO t VALUES (1, 1.50), (1, 2), (2, 3.25);
+             CREATE MATERIALIZED VIEW totals AS SELECT k, sum(v) AS total FROM t GROUP BY k",
+        )
+        .unwrap();
+        let rows = |conn: &mut Connection, sql: &str| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.into_values())
+                .collect::<Vec<_>>()
+        };
+        let totals = "SELECT k, CAST(total AS TEXT) FROM totals ORDER BY k";
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(
+            rows(&mut conn, totals),
+            [
+                vec![Value::Integer(1), text("3.50")],
+                vec![Value::Integer(2), text("3.25")]
+            ]
+        );
+        let stale = "SELECT stale FROM stats.materialized_views WHERE name = 'totals'";
+        assert_eq!(rows(&mut conn, stale), [[Value::Boolean(false)]]);
+
+        // 元のテーブルを変えてもビューの行は REFRESH するまで変わらない
+        conn.execute("INSERT INTO t VALUES (3, 1)", &[]).unwrap();
+        assert_eq!(rows(&mut conn, stale), [[Value::Boolean(true)]]);
+        assert_eq!(rows(&mut conn, totals).len(), 2);
+        conn.execute("REFRESH MATERIALIZED VIEW totals", &[])
+            .unwrap();
+        assert_eq!(rows(&mut conn, totals).len(), 3);
+        assert_eq!(rows(&mut conn, stale), [[Value::Boolean(false)]]);
+
+        let err = conn
+            .execute("UPDATE totals SET total = 0", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42809");
+        let err = conn
+            .execute("REFRESH MATERIALIZED VIEW t", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42809");
+        assert!(conn.check().unwrap().is_empty());
+        conn.execute("DROP MATERIALIZED VIEW totals", &[]).unwrap();
+        assert!(rows(&mut conn, "SELECT * FROM stats.materialized_views").is_empty());
+    }
+
+    #[test]
+    fn test_triggers() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        // 負の残高は 0 にし、id が 99 の行は入れず、13 なら失敗する
+        conn.register_trigger_function(TriggerFunction::new("clamp", |_, new| {
+            let new = new.unwrap();
+            match new[0] {
+                Value::Integer(99) => return Ok(false),
+                Value::Integer(13) => return Err("unlucky".to_string()),
+                _ => {}
+            }
+            if matches!(new[1], Value::Integer(n) if n < 0) {
+                new[1] = Value::Integer(0);
+            }
+            Ok(true)
+        }))
+        .unwrap();
+        conn.execute_batch(
+            "CREATE TABLE accounts (id INTEGER, balance INTEGER);
+             CREATE TABLE audit (id INTEGER, old INTEGER, new INTEGER, op TEXT);
+             CREATE TRIGGER accounts_clamp BEFORE INSERT ON accounts FOR EACH ROW
+                 EXECUTE FUNCTION clamp();
+             CREATE TRIGGER accounts_update AFTER UPDATE ON accounts FOR EACH ROW
+                 INSERT INTO audit VALUES (old.id, old.balance, new.balance, 'update');
+             CREATE TRIGGER accounts_delete AFTER DELETE ON accounts FOR EACH ROW
+                 INSERT INTO audit VALUES (old.id, old.balance, NULL, 'delete')",
+        )
+        .unwrap();
+        let rows = |conn: &mut Connection, sql: &str| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.into_values())
+                .collect::<Vec<_>>()
+        };
+        let int = Value::Integer;
+        let inserted = conn
+            .execute("INSERT INTO accounts VALUES (1, -5), (2, 10), (99, 1)", &[])
+            .unwrap();
+        assert_eq!(inserted, 2);
+        let accounts = "SELECT id, balance FROM accounts ORDER BY id";
+        assert_eq!(
+            rows(&mut conn, accounts),
+            [[int(1), int(0)], [int(2), int(10)]]
+        );
+        assert_eq!(
+            conn.execute("UPDATE accounts SET balance = balance + 1", &[])
+                .unwrap(),
+            2
+        );
+        let audit = "SELECT id, old, new, op FROM audit ORDER BY id, op";
+        assert_eq!(
+            rows(&mut conn, audit),
+            [
+                [int(1), int(0), int(1), Value::Text("update".to_string())],
+                [int(2), int(10), int(11), Value::Text("update".to_string())]
+            ]
+        );
+
+        // 文が失敗すれば、トリガーが書いた行もいっしょに取り消される
+        let err = conn
+            .execute("INSERT INTO accounts VALUES (3, 1), (13, 1)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "P0001");
+        conn.begin().unwrap();
+        let err = conn
+            .execute("UPDATE accounts SET balance = 10 / (2 - id)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "22012");
+        assert_eq!(rows(&mut conn, audit).len(), 2);
+        let deleted = rows(&mut conn, "DELETE FROM accounts WHERE id = 2 RETURNING id");
+        assert_eq!(deleted, [[int(2)]]);
+        conn.commit().unwrap();
+        assert_eq!(rows(&mut conn, audit).len(), 3);
+        assert_eq!(rows(&mut conn, accounts).len(), 1);
+
+        conn.execute("DROP TRIGGER accounts_delete ON accounts", &[])
+            .unwrap();
+        conn.execute("DROP TRIGGER IF EXISTS accounts_delete ON accounts", &[])
+            .unwrap();
+        let err = conn
+            .execute("DROP TRIGGER accounts_delete ON accounts", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42704");
+        let err = conn
+            .execute(
+                "CREATE TRIGGER x BEFORE DELETE ON accounts FOR EACH ROW EXECUTE FUNCTION nope()",
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42883");
+
+        // 自分のテーブルに書くトリガーは深さの上限で止まる
+        conn.execute(
+            "CREATE TRIGGER audit_loop AFTER INSERT ON audit FOR EACH ROW
+                 INSERT INTO audit VALUES (new.id + 1, NULL, NULL, 'loop')",
+            &[],
+        )
+        .unwrap();
+        let err = conn
+            .execute("INSERT INTO audit VALUES (0, NULL, NULL, 'x')", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "54001");
+        assert_eq!(rows(&mut conn, audit).len(), 3);
+        assert!(conn.check().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_attach() {
+        let path = temporary_path();
+        let mut conn = Database::open_temporary().unwrap().connect();
+        conn.execute_batch(
+            "CREATE TABLE s (id INTEGER, name TEXT); INSERT INTO s VALUES (1, 'a'), (2, 'b')",
+        )
+        .unwrap();
+        let attach = |alias: &str, options: &str| {
+            format!("ATTACH '{}' AS {} {}", path.display(), alias, options)
+        };
+        conn.execute(&attach("other", ""), &[]).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE other.t (id INTEGER, score INTEGER);
+             INSERT INTO other.t VALUES (1, 10), (2, 20), (3, 30)",
+        )
+        .unwrap();
+        let rows = conn
+            .query(
+                "SELECT s.name, t.score FROM other.t JOIN main.s ON t.id = s.id ORDER BY t.score",
+                &[],
+            )
+            .unwrap()
+            .map(|row| row.into_values())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                vec![Value::Text("a".into()), Value::Integer(10)],
+                vec![Value::Text("b".into()), Value::Integer(20)],
+            ]
+        );
+        let err = conn.execute(&attach("other", ""), &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42710");
+        conn.execute_batch("BEGIN").unwrap();
+        let err = conn.execute("DELETE FROM other.t", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "25001");
+        conn.execute_batch("ROLLBACK").unwrap();
+
+        // 同じファイルを開いていれば、そのテーブルが見える
+        let mut second = Database::open_temporary().unwrap().connect();
+        second.execute(&attach("o", "(READ_ONLY)"), &[]).unwrap();
+        let count: i64 = second
+            .query_row("SELECT count(*) FROM o.t", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, 3);
+        let err = second.execute("UPDATE o.t SET score = 0", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "25006");
+
+        conn.execute("DETACH other", &[]).unwrap();
+        let err = conn.execute("SELECT * FROM other.t", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42P01");
+        let err = conn.execute("DETACH other", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "3D000");
+        drop(second);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_roles() {
+        let db = Database::open_temporary().unwrap();
+        let mut admin = db.connect();
+        admin
+            .execute_batch(
+                "CREATE TABLE t (id INTEGER, v TEXT);
+                 INSERT INTO t VALUES (1, 'a'), (2, 'b');
+                 CREATE USER alice PASSWORD 'secret';
+                 CREATE ROLE bob NOLOGIN PASSWORD 'pw';
+                 GRANT SELECT, INSERT ON t TO alice",
+            )
+            .unwrap();
+        let mut conn = db.connect();
+        let err = conn.authenticate("alice", "wrong").unwrap_err();
+        assert_eq!(err.sqlstate(), "28P01");
+        assert_eq!(
+            conn.authenticate("bob", "pw").unwrap_err().sqlstate(),
+            "28000"
+        );
+        conn.authenticate("alice", "secret").unwrap();
+        assert_eq!(conn.user(), Some("alice"));
+
+        assert_eq!(conn.query("SELECT id FROM t", &[]).unwrap().len(), 2);
+        conn.execute("INSERT INTO t VALUES (3, 'c')", &[]).unwrap();
+        for sql in [
+            "UPDATE t SET v = 'x'",
+            "DELETE FROM t WHERE id = 1",
+            "INSERT INTO t VALUES (4, 'd') RETURNING id",
+        ] {
+            admin.execute("REVOKE SELECT ON t FROM alice", &[]).unwrap();
+            let err = conn.execute(sql, &[]).unwrap_err();
+            assert_eq!(err.sqlstate(), "42501", "{}", sql);
+            assert_eq!(err.to_string(), "permission denied for table t");
+        }
+        // UPDATE の権限だけでも、WHERE のない UPDATE はできる
+        admin.execute("GRANT UPDATE ON t TO alice", &[]).unwrap();
+        assert_eq!(conn.execute("UPDATE t SET v = 'x'", &[]).unwrap(), 3);
+        let err = conn
+            .execute("UPDATE t SET v = 'y' WHERE id = 1", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42501");
+        let err = conn.execute("CREATE TABLE u (a INTEGER)", &[]).unwrap_err();
+        assert_eq!(err.to_string(), "permission denied to run CREATE TABLE");
+        assert_eq!(
+            conn.execute("GRANT DELETE ON t TO alice", &[])
+                .unwrap_err()
+                .sqlstate(),
+            "42501"
+        );
+
+        let rows = admin
+            .query("SELECT grantee, privilege FROM stats.table_privileges", &[])
+            .unwrap()
+            .map(|row| row.into_values())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                vec![Value::Text("alice".into()), Value::Text("INSERT".into())],
+                vec![Value::Text("alice".into()), Value::Text("UPDATE".into())],
+            ]
+        );
+        let err = admin.execute("DROP ROLE alice", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "2BP01");
+        admin.execute("ALTER ROLE alice SUPERUSER", &[]).unwrap();
+        conn.execute("CREATE TABLE u (a INTEGER)", &[]).unwrap();
+        admin
+            .execute_batch("REVOKE ALL ON t FROM alice; DROP ROLE alice; DROP ROLE IF EXISTS alice")
+            .unwrap();
+        assert_eq!(
+            admin
+                .query_row("SELECT count(*) FROM stats.roles", &[])
+                .unwrap()
+                .get::<i64>(0)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_set_show() {
+        let settings = Settings {
+            work_mem: 8 << 20,
+            ..Settings::default()
+        };
+        let db = Database::open_temporary_with(settings).unwrap();
+        let mut conn = db.connect();
+        let show = |conn: &mut Connection, name: &str| -> String {
+            conn.query_row(&format!("SHOW {}", name), &[])
+                .unwrap()
+                .get(0)
+                .unwrap()
+        };
+        assert_eq!(show(&mut conn, "work_mem"), "8MB");
+        conn.execute("SET work_mem = '64MB'", &[]).unwrap();
+        conn.execute("SET statement_timeout TO 1500", &[]).unwrap();
+        assert_eq!(show(&mut conn, "work_mem"), "64MB");
+        assert_eq!(show(&mut conn, "statement_timeout"), "1500ms");
+
+        // SET LOCAL はトランザクションが終われば戻る。SET は取り消しても戻らない
+        conn.execute_batch("BEGIN; SET LOCAL work_mem = 1024; SET parallel_workers = 4")
+            .unwrap();
+        assert_eq!(show(&mut conn, "work_mem"), "1MB");
+        conn.rollback().unwrap();
+        assert_eq!(show(&mut conn, "work_mem"), "64MB");
+        assert_eq!(show(&mut conn, "parallel_workers"), "4");
+
+        let err = conn.execute("SET buffer_pool_size = 10", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "55P02");
+        let err = conn.execute("SET work_mem = 'lots'", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "22023");
+        let err = conn.execute("SHOW nope", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42704");
+        conn.fix_setting("lock_timeout");
+        assert!(conn.execute("SET lock_timeout = 0", &[]).is_err());
+
+        conn.execute("RESET work_mem", &[]).unwrap();
+        assert_eq!(show(&mut conn, "work_mem"), "8MB");
+        conn.execute("RESET ALL", &[]).unwrap();
+        assert_eq!(show(&mut conn, "statement_timeout"), "0");
+        let rows = conn.query("SHOW ALL", &[]).unwrap();
+        assert_eq!(rows.len(), settings::PARAMETERS.len());
+    }
+
+    #[test]
+    fn test_copy() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL, price DECIMAL(6, 2), day DATE)",
+        )
+        .unwrap();
+        let path = crate::testutil::temp_path("copy.csv");
+        std::fs::write(
+            &path,
+            "id,name,price,day\n1,apple,1.5,2024-01-02\n2,\"pear, green\",,\n",
+        )
+        .unwrap();
+        let sql = format!(
+            "COPY items FROM '{}' WITH (FORMAT csv, HEADER, DELIMITER ',')",
+            path.display()
+        );
+        assert_eq!(conn.execute(&sql, &[]).unwrap(), 2);
+        std::fs::remove_file(&path).unwrap();
+        let row = conn
+            .query_row("SELECT name, price, day FROM items WHERE id = 2", &[])
+            .unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "pear, green");
+        assert_eq!(row.get::<Option<Decimal>>(1).unwrap(), None);
+
+        // 列を選び、text 形式で読む
+        let options = CopyOptions {
+            format: CopyFormat::Text,
+            ..CopyOptions::default()
+        };
+        let columns = ["name".to_string(), "id".to_string()];
+        let data = "melon\t3\nfig\t4\n";
+        let n = conn
+            .copy_from("items", &columns, data.as_bytes(), &options)
+            .unwrap();
+        assert_eq!(n, 2);
+
+        // 失敗した行の番号を返し、1 行も入れない
+        let err = conn
+            .copy_from(
+                "items",
+                &[],
+                "5,kiwi,,\n6,x,abc,\n".as_bytes(),
+                &CopyOptions::default(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid input syntax for type numeric: \"abc\" (COPY items, line 2)"
+        );
+        let err = conn
+            .copy_from(
+                "items",
+                &[],
+                "7,a,,\n1,b,,\n".as_bytes(),
+                &CopyOptions::default(),
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::Copy { line: 2, .. }), "{}", err);
+        assert_eq!(err.sqlstate(), "23505");
+        let err = conn
+            .copy_from("items", &[], "8,a\n".as_bytes(), &CopyOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "missing data for column \"price\" (COPY items, line 1)"
+        );
+        // トランザクションの中で失敗しても、それまでの変更は残る
+        conn.begin().unwrap();
+        conn.execute("INSERT INTO items VALUES (9, 'plum', NULL, NULL)", &[])
+            .unwrap();
+        assert!(conn
+            .copy_from(
+                "items",
+                &[],
+                "10,a,,\n10,b,,\n".as_bytes(),
+                &CopyOptions::default()
+            )
+            .is_err());
+        conn.commit().unwrap();
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM items", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, 5);
+    }
+
+    #[test]
+    fn test_export() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (id INTEGER, name TEXT);
+             INSERT INTO t VALUES (1, 'a,b'), (2, NULL), (3, 'c')",
+        )
+        .unwrap();
+        let mut out = vec![];
+        let options = CopyOptions {
+            header: true,
+            ..CopyOptions::default()
+        };
+        let rows = conn
+            .export(
+                "SELECT id, name FROM t WHERE id < $1 ORDER BY id",
+                &[Value::Integer(3)],
+                &mut out,
+                &options,
+            )
+            .unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(String::from_utf8(out).unwrap(), "id,name\n1,\"a,b\"\n2,\n");
+        assert!(matches!(
+            conn.export("DELETE FROM t", &[], &mut vec![], &options),
+            Err(Error::NoResultSet)
+        ));
+
+        // 書き出したファイルを別のテーブルに読み戻す
+        let path = crate::testutil::temp_path("export.csv");
+        let sql = format!(
+            "COPY (SELECT * FROM t ORDER BY id) TO '{}' (FORMAT csv, HEADER)",
+            path.display()
+        );
+        assert_eq!(conn.execute(&sql, &[]).unwrap(), 3);
+        conn.execute_batch("CREATE TABLE u (id INTEGER, name TEXT)")
+            .unwrap();
+        let sql = format!("COPY u FROM '{}' (HEADER)", path.display());
+        assert_eq!(conn.execute(&sql, &[]).unwrap(), 3);
+        let names: Vec<Option<String>> = conn
+            .query("SELECT name FROM u ORDER BY id", &[])
+            .unwrap()
+            .map(|row| row.get(0).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![Some("a,b".to_string()), None, Some("c".to_string())]
+        );
+
+        let sql = format!("COPY t (name) TO '{}' (FORMAT json)", path.display());
+        conn.execute(&sql, &[]).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(json.lines().next(), Some("{\"name\":\"a,b\"}"));
+        std::fs::remove_file(&path).unwrap();
+
+        let sql = format!("COPY t FROM '{}' (FORMAT json)", path.display());
+        assert!(matches!(conn.execute(&sql, &[]), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_dictionary_compression() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute(
+            "CREATE TABLE t (id INTEGER, status TEXT COMPRESSION dictionary)",
+            &[],
+        )
+        .unwrap();
+        let values: Vec<String> = (1..=300)
+            .map(|i| match i % 3 {
+                0 => format!("({}, 'open')", i),
+                1 => format!("({}, 'closed')", i),
+                _ => format!("({}, NULL)", i),
+            })
+            .collect();
+        conn.execute(&format!("INSERT INTO t VALUES {}", values.join(", ")), &[])
+            .unwrap();
+        conn.execute("UPDATE t SET status = 'done' WHERE id <= 30", &[])
+            .unwrap();
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        assert_eq!(
+            count(&mut conn, "SELECT count(*) FROM t WHERE status = 'open'"),
+            90
+        );
+        assert_eq!(
+            count(
+                &mut conn,
+                "SELECT count(*) FROM t WHERE 'done' = status AND id > 5"
+            ),
+            25
+        );
+        assert_eq!(
+            count(&mut conn, "SELECT count(*) FROM t WHERE status = 'missing'"),
+            0
+        );
+        assert_eq!(
+            count(&mut conn, "SELECT count(*) FROM t WHERE status IS NULL"),
+            90
+        );
+        assert_eq!(conn.catalog().table("t").unwrap().dictionaries[0].len(), 3);
+        assert!(conn.check().unwrap().is_empty());
+
+        let err = conn
+            .execute("CREATE TABLE u (n INTEGER COMPRESSION dictionary)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42804");
+        let err = conn
+            .execute("CREATE TABLE u (s TEXT COMPRESSION lz4)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42704");
+        assert!(conn.catalog().table("u").is_none());
+    }
+
+    #[test]
+    fn test_result_cache() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let mut other = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (id INTEGER, v TEXT);
+             INSERT INTO t VALUES (1, 'a'), (2, 'b');
+             SET result_cache = on",
+        )
+        .unwrap();
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 2);
+        // 空白や大文字と小文字が違っても同じ文
+        assert_eq!(count(&mut conn, "select  COUNT(*) from t"), 2);
+        assert_eq!(conn.result_cache().hits(), 1);
+        let v = |conn: &mut Connection, id: i64| -> String {
+            let row = conn.query_row("SELECT v FROM t WHERE id = $1", &[Value::Integer(id)]);
+            row.unwrap().get(0).unwrap()
+        };
+        assert_eq!(v(&mut conn, 1), "a");
+        assert_eq!(v(&mut conn, 2), "b");
+        assert_eq!(conn.result_cache().hits(), 1);
+
+        // コミットしていない行は見えず、コミットすれば見える
+        other
+            .execute_batch("BEGIN; INSERT INTO t VALUES (3, 'c')")
+            .unwrap();
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 2);
+        other.commit().unwrap();
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 3);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 3);
+        assert_eq!(conn.result_cache().hits(), 2);
+
+        // 呼ぶたびに変わる関数や、トランザクションの中の問い合わせはためない
+        let entries = conn.result_cache().len();
+        conn.query("SELECT now()", &[]).unwrap();
+        conn.execute_batch("BEGIN; SELECT count(*) FROM t; COMMIT")
+            .unwrap();
+        assert_eq!(conn.result_cache().len(), entries);
+        assert_eq!(conn.result_cache().hits(), 2);
+
+        conn.execute_batch("DROP TABLE t; CREATE TABLE t (id INTEGER, v TEXT)")
+            .unwrap();
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 0);
+        // 設定していない接続は使わない
+        assert_eq!(count(&mut other, "SELECT count(*) FROM t"), 0);
+        assert_eq!(conn.result_cache().hits(), 2);
+    }
+
+    #[test]
+    fn test_reopen_file() {
+        let path = crate::testutil::temp_path("reopen.db");
+        {
+            let mut conn = Database::open(&path).unwrap().connect();
+            conn.execute_batch(
+                "CREATE TABLE t (
+                     a INTEGER PRIMARY KEY,
+                     b TEXT COMPRESSION dictionary,
+                     c INTEGER GENERATED ALWAYS AS (a * 2) STORED
+                 );
+                 CREATE INDEX t_b ON t (b);
+                 INSERT INTO t (a, b) VALUES (1, 'x'), (2, 'y'), (3, 'x');
+                 DELETE FROM t WHERE a = 2;
+                 CREATE TABLE l (a INTEGER) USING lsm;
+                 INSERT INTO l WITH RECURSIVE g (n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM g WHERE n < 3000)
+                     SELECT n FROM g;
+                 DELETE FROM l WHERE a % 3 = 0;
+                 CREATE TYPE gone AS ENUM ('a');
+                 CREATE TYPE mood AS ENUM ('sad', 'happy');
+                 DROP TYPE gone;
+                 ALTER TYPE mood ADD VALUE 'ok' BEFORE 'happy';
+                 CREATE TABLE e (id INTEGER, m mood);
+                 CREATE INDEX e_m ON e (m);
+                 INSERT INTO e VALUES (1, 'happy'), (2, 'ok'), (3, 'sad');
+                 CREATE USER alice PASSWORD 'secret';
+                 CREATE ROLE bob SUPERUSER;
+                 GRANT SELECT ON t TO alice",
+            )
+            .unwrap();
+            conn.begin().unwrap();
+            conn.execute("INSERT INTO t (a, b) VALUES (4, 'z')", &[])
+                .unwrap();
+            conn.execute("DELETE FROM l WHERE a < 100", &[]).unwrap();
+        }
+        let db = Database::open(&path).unwrap();
+        let mut conn = db.connect();
+        let rows = |conn: &mut Connection, sql: &str| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.into_values())
+                .collect::<Vec<_>>()
+        };
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(
+            rows(&mut conn, "SELECT a, b, c FROM t ORDER BY a"),
+            [
+                vec![Value::Integer(1), text("x"), Value::Integer(2)],
+                vec![Value::Integer(3), text("x"), Value::Integer(6)],
+            ]
+        );
+        assert_eq!(conn.catalog().table("t").unwrap().row_count(), 2);
+        // lsm のテーブルも、run とログのページからメモテーブルまで読み直す
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM l"), 2000);
+        assert_eq!(conn.catalog().table("l").unwrap().row_count(), 2000);
+        assert_eq!(count(&mut conn, "SELECT max(a) FROM l"), 2999);
+        conn.execute_batch("INSERT INTO l VALUES (3001); DELETE FROM l WHERE a < 1000")
+            .unwrap();
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM l"), 1335);
+        // インデックスも開き直す前のページを読む
+        assert_eq!(
+            rows(&mut conn, "SELECT a FROM t WHERE b = 'x' ORDER BY a"),
+            [vec![Value::Integer(1)], vec![Value::Integer(3)]]
+        );
+        let err = conn
+            .execute("INSERT INTO t (a, b) VALUES (3, 'w')", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "23505");
+        conn.execute("INSERT INTO t (a, b) VALUES (5, 'y')", &[])
+            .unwrap();
+        assert_eq!(
+            rows(&mut conn, "SELECT c FROM t WHERE b = 'y'"),
+            [vec![Value::Integer(10)]]
+        );
+
+        // ENUM の型は番号とラベルの順位を変えずに戻り、消した型の番号も使わない
+        let ids = [Value::Integer(3), Value::Integer(2), Value::Integer(1)].map(|v| vec![v]);
+        assert_eq!(rows(&mut conn, "SELECT id FROM e ORDER BY m"), ids);
+        assert_eq!(
+            rows(
+                &mut conn,
+                "SELECT /*+ IndexScan(e e_m) */ id FROM e WHERE m >= 'sad' ORDER BY m"
+            ),
+            ids
+        );
+        conn.execute_batch(
+            "ALTER TYPE mood ADD VALUE 'meh' AFTER 'sad';
+             INSERT INTO e VALUES (4, 'meh');
+             CREATE TYPE size AS ENUM ('s')",
+        )
+        .unwrap();
+        assert_eq!(
+            rows(&mut conn, "SELECT CAST(m AS TEXT) FROM e ORDER BY m"),
+            ["sad", "meh", "ok", "happy"].map(|s| vec![text(s)])
+        );
+        let type_ids = ["mood", "size"].map(|t| conn.catalog().enum_type(t).unwrap().id);
+        assert_eq!(type_ids, [1, 2]);
+
+        // ロールとパスワードと権限も戻るので、開き直しても認証なしではつながない
+        assert!(conn.requires_password());
+        assert!(conn.catalog().role("bob").is_some_and(|r| r.superuser));
+        let mut alice = db.connect();
+        let err = alice.authenticate("alice", "wrong").unwrap_err();
+        assert_eq!(err.sqlstate(), "28P01");
+        alice.authenticate("alice", "secret").unwrap();
+        assert_eq!(rows(&mut alice, "SELECT a FROM t WHERE a = 1").len(), 1);
+        let err = alice.execute("DELETE FROM t", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42501");
+    }
+
+    #[test]
+    fn test_recover_file_after_crash() {
+        let path = crate::testutil::temp_path("crash.db");
+        let settings = Settings {
+            buffer_pool_size: 16,
+            ..Settings::default()
+        };
+        let db = Database::open_with(&path, settings.clone()).unwrap();
+        let mut conn = db.connect();
+        let mut other = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT);
+             CREATE INDEX t_b ON t (b);
+             CREATE TABLE l (a INTEGER) USING lsm",
+        )
+        .unwrap();
+        for i in 0..300 {
+            conn.execute(
+                "INSERT INTO t VALUES ($1, $2)",
+                &[Value::Integer(i), Value::Text(format!("v{}", i % 7))],
+            )
+            .unwrap();
+        }
+        // コミットしていない変更も、ほかの接続のコミットでページの像に入る
+        other.begin().unwrap();
+        other
+            .execute("INSERT INTO t VALUES (1000, 'new')", &[])
+            .unwrap();
+        other.execute("DELETE FROM t WHERE a < 10", &[]).unwrap();
+        other.execute("INSERT INTO l SELECT a FROM t", &[]).unwrap();
+        conn.execute("INSERT INTO l SELECT a FROM t WHERE a < 50", &[])
+            .unwrap();
+        conn.execute("INSERT INTO t VALUES (300, 'v0')", &[])
+            .unwrap();
+        conn.begin().unwrap();
+        conn.execute("UPDATE t SET b = 'w' WHERE a = 20", &[])
+            .unwrap();
+        // 閉じずに落ちたことにする
+        std::mem::forget(other);
+        std::mem::forget(conn);
+        std::mem::forget(db);
+
+        let mut conn = Database::open_with(&path, settings).unwrap().connect();
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 301);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t WHERE a < 10"), 10);
+        assert_eq!(
+            count(&mut conn, "SELECT count(*) FROM t WHERE b = 'new'"),
+            0
+        );
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t WHERE b = 'w'"), 0);
+        assert_eq!(
+            count(&mut conn, "SELECT count(*) FROM t WHERE b = 'v0'"),
+            44
+        );
+        assert_eq!(conn.catalog().table("t").unwrap().row_count(), 301);
+        // lsm のテーブルも、コミットした行だけが残る
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM l"), 50);
+        assert_eq!(conn.catalog().table("l").unwrap().row_count(), 50);
+        conn.execute("INSERT INTO t VALUES (1000, 'new')", &[])
+            .unwrap();
+        let err = conn
+            .execute("INSERT INTO t VALUES (5, 'x')", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "23505");
+    }
+
+    #[test]
+    fn test_close_file() {
+        let path = crate::testutil::temp_path("close.db");
+        let clean = |db: &Database| db.engine.borrow().bufmgr.wal().unwrap().is_clean();
+        let db = Database::open(&path).unwrap();
+        assert!(!clean(&db));
+        let mut conn = db.connect();
+        conn.execute_batch("CREATE TABLE t (a INTEGER); INSERT INTO t VALUES (1)")
+            .unwrap();
+        drop(conn);
+        let other = db.connect();
+        assert_eq!(db.close().unwrap_err().sqlstate(), "55006");
+        // 最後の接続がなくなったところで閉じる
+        drop(other);
+
+        // 閉じてあれば回復しない。開いているうちに落ちれば、次は回復する
+        let db = Database::open(&path).unwrap();
+        assert!(clean(&db));
+        std::mem::forget(db);
+        let db = Database::open(&path).unwrap();
+        assert!(!clean(&db));
+        let count: i64 = db
+            .connect()
+            .query_row("SELECT count(*) FROM t", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, 1);
+        db.close().unwrap();
+        let db = Database::open(&path).unwrap();
+        assert!(clean(&db));
+    }
+
+    #[test]
+    fn test_checkpoint_while_open() {
+        let path = crate::testutil::temp_path("checkpoint.db");
+        let settings = Settings {
+            checkpoint_wal_size: Some(4 << 10),
+            ..Settings::default()
+        };
+        let db = Database::open_with(&path, settings.clone()).unwrap();
+        let checkpoint = |db: &Database| {
+            let engine = db.engine.borrow();
+            engine
+                .bufmgr
+                .wal()
+                .unwrap()
+                .checkpoint_lsn()
+                .unwrap()
+                .unwrap()
+        };
+        let opened = checkpoint(&db);
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE t (a INTEGER, b TEXT)", &[])
+            .unwrap();
+        let insert = |conn: &mut Connection, i: i64| {
+            conn.execute(
+                "INSERT INTO t VALUES ($1, $2)",
+                &[Value::Integer(i), Value::Text("x".repeat(1000))],
+            )
+            .unwrap();
+        };
+        for i in 0..100 {
+            insert(&mut conn, i);
+        }
+        // ログが進むたびにチェックポイントを書く
+        let last = checkpoint(&db);
+        assert!(last > opened);
+
+        // 実行中のトランザクションがあるうちは書かない
+        let mut other = db.connect();
+        other.begin().unwrap();
+        other.execute("DELETE FROM t WHERE a < 50", &[]).unwrap();
+        for i in 100..200 {
+            insert(&mut conn, i);
+        }
+        assert_eq!(checkpoint(&db), last);
+        std::mem::forget(other);
+        std::mem::forget(conn);
+        std::mem::forget(db);
+
+        // 落ちても、最後のチェックポイントからコミットした行だけに戻る
+        let db = Database::open_with(&path, settings).unwrap();
+        let mut conn = db.connect();
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM t", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, 200);
+    }
+
+    #[test]
+    fn test_online_backup() {
+        let path = crate::testutil::temp_path("online.db");
+        let backup = crate::testutil::temp_path("online-backup");
+        let settings = Settings {
+            buffer_pool_size: 8,
+            ..Settings::default()
+        };
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        let db = Database::open_with(&path, settings.clone()).unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT)", &[])
+            .unwrap();
+        let insert = |conn: &mut Connection, i: i64| {
+            conn.execute(
+                "INSERT INTO t VALUES ($1, $2)",
+                &[Value::Integer(i), Value::Text("x".repeat(1000))],
+            )
+            .unwrap();
+        };
+        for i in 0..50 {
+            insert(&mut conn, i);
+        }
+        // 写している間も終わっていないトランザクションの変更は、バッファから追い出されてファイルにある
+        let mut other = db.connect();
+        other.begin().unwrap();
+        other.execute("DELETE FROM t WHERE a < 40", &[]).unwrap();
+        insert(&mut other, 1000);
+        let label = conn.backup(&backup).unwrap();
+        assert!(label.end >= label.start);
+        other.commit().unwrap();
+        insert(&mut conn, 50);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 12);
+
+        // 写し終えた時点でコミットしていた行だけがある
+        let restored = Database::open_backup(&backup, settings).unwrap();
+        let mut conn = restored.connect();
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 50);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t WHERE a = 5"), 1);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t WHERE a >= 50"), 0);
+        // 開いたバックアップにはそのまま書ける
+        insert(&mut conn, 60);
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 51);
+
+        let err = Database::open_in_memory()
+            .unwrap()
+            .connect()
+            .backup(crate::testutil::temp_path("memory-backup"))
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "0A000");
+    }
+
+    #[test]
+    fn test_incremental_backup() {
+        let path = crate::testutil::temp_path("incremental.db");
+        let [base, first, second, dest, skipped] =
+            ["base", "first", "second", "dest", "skipped"].map(crate::testutil::temp_path);
+        let settings = Settings {
+            buffer_pool_size: 8,
+            ..Settings::default()
+        };
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        let insert = |conn: &mut Connection, range: std::ops::Range<i64>| {
+            for i in range {
+                conn.execute(
+                    "INSERT INTO t VALUES ($1, $2)",
+                    &[Value::Integer(i), Value::Text("x".repeat(1000))],
+                )
+                .unwrap();
+            }
+        };
+        let db = Database::open_with(&path, settings.clone()).unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT)", &[])
+            .unwrap();
+        insert(&mut conn, 0..10);
+        conn.backup(&base).unwrap();
+        insert(&mut conn, 10..20);
+        let label = conn.incremental_backup(&base, &first).unwrap();
+        // 増分のあいだにコミットしたページの像には、終わっていないトランザクションの変更も入る
+        let mut other = db.connect();
+        other.begin().unwrap();
+        other.execute("DELETE FROM t WHERE a < 5", &[]).unwrap();
+        insert(&mut conn, 20..30);
+        assert_eq!(
+            conn.incremental_backup(&first, &second).unwrap().start,
+            label.end
+        );
+        other.commit().unwrap();
+
+        let restored =
+            Database::restore_incremental(&base, &[&first, &second], &dest, settings.clone())
+                .unwrap();
+        let mut restored = restored.connect();
+        assert_eq!(count(&mut restored, "SELECT count(*) FROM t"), 30);
+        assert_eq!(
+            count(&mut restored, "SELECT count(*) FROM t WHERE a < 5"),
+            5
+        );
+        drop(restored);
+        std::fs::remove_dir_all(&dest).unwrap();
+        let restored =
+            Database::restore_incremental(&base, &[&first], &dest, settings.clone()).unwrap();
+        assert_eq!(count(&mut restored.connect(), "SELECT count(*) FROM t"), 20);
+
+        // 間の増分を飛ばしてはつなげない
+        let result = Database::restore_incremental(&base, &[&second], &skipped, settings);
+        assert!(matches!(
+            result,
+            Err(Error::Recovery(recovery::Error::BrokenBackupChain { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_bulk_copy() {
+        use crate::logical::Slot;
+        use crate::wal::Wal;
+
+        let db = Database::open_temporary().unwrap();
+        let dir = crate::testutil::temp_path("bulk-wal");
+        let start = {
+            let bufmgr = &mut db.engine.borrow_mut().bufmgr;
+            bufmgr.set_wal(Wal::open(&dir).unwrap());
+            bufmgr.wal_mut().unwrap().next_lsn()
+        };
+        let slot = Slot::create(&dir, "bulk", start).unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT);
+             CREATE TABLE u (a INTEGER PRIMARY KEY, b TEXT)",
+        )
+        .unwrap();
+        let options = CopyOptions {
+            bulk: true,
+            ..CopyOptions::default()
+        };
+        let data: String = (0..2500).map(|i| format!("{},v{}\n", i, i)).collect();
+        assert_eq!(
+            conn.copy_from("t", &[], data.as_bytes(), &options).unwrap(),
+            2500
+        );
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM t WHERE a >= 1000", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, 1500);
+        // 行ごとのレコードはなく、作り直したことだけが残る
+        let end = db.engine.borrow().bufmgr.wal().unwrap().next_lsn();
+        let events = slot.poll(end).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].change,
+            RowChange::Rebuilt {
+                table: "t".to_string()
+            }
+        );
+
+        let err = conn.copy_from("t", &[], "9000,x\n".as_bytes(), &options);
+        assert_eq!(err.unwrap_err().sqlstate(), "55000");
+        conn.begin().unwrap();
+        let err = conn.copy_from("u", &[], "1,x\n".as_bytes(), &options);
+        assert_eq!(err.unwrap_err().sqlstate(), "25001");
+        conn.rollback().unwrap();
+        let err = conn
+            .execute("COPY t TO '/dev/null' (BULK)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "0A000");
+
+        // 途中で失敗すれば、入れた行をすべて取り除く
+        let data: String = (0..1500).chain([7]).map(|i| format!("{},v\n", i)).collect();
+        let err = conn.copy_from("u", &[], data.as_bytes(), &options);
+        assert_eq!(err.unwrap_err().sqlstate(), "23505");
+        assert!(conn.query("SELECT * FROM u", &[]).unwrap().is_empty());
+        conn.execute("INSERT INTO u VALUES (7, 'x')", &[]).unwrap();
+        assert!(conn.check().unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_order_by_nulls() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER, b INTEGER);
+             INSERT INTO t VALUES (1, 2), (NULL, 1), (2, NULL), (1, NULL), (NULL, NULL), (2, 1)",
+        )
+        .unwrap();
+        let mut query = |sql: &str| -> Vec<(Option<i64>, Option<i64>)> {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| (row.get(0).unwrap(), row.get(1).unwrap()))
+                .collect()
+        };
+        // 指定がなければ昇順で NULL が後、降順で先
+        assert_eq!(
+            query("SELECT a, b FROM t ORDER BY a, b DESC"),
+            [
+                (Some(1), None),
+                (Some(1), Some(2)),
+                (Some(2), None),
+                (Some(2), Some(1)),
+                (None, None),
+                (None, Some(1)),
+            ]
+        );
+        assert_eq!(
+            query("SELECT a, b FROM t ORDER BY a DESC NULLS LAST, b NULLS FIRST LIMIT 4"),
+            [
+                (Some(2), None),
+                (Some(2), Some(1)),
+                (Some(1), None),
+                (Some(1), Some(2)),
+            ]
+        );
+        assert_eq!(
+            query(
+                "SELECT a, row_number() OVER (ORDER BY a NULLS FIRST, b) FROM t \
+                 ORDER BY 2 LIMIT 3"
+            ),
+            [(None, Some(1)), (None, Some(2)), (Some(1), Some(3))]
+        );
+        let err = conn
+            .query("SELECT a FROM t ORDER BY a NULLS", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42601");
+    }
+
+    #[test]
+    fn test_arrays() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (id INTEGER, tags TEXT[], scores INTEGER[]);
+             INSERT INTO t VALUES (1, ARRAY['a', 'b c'], ARRAY[3, 1]),
+                                  (2, '{b,NULL}', '{}'),
+                                  (3, NULL, ARRAY[2, NULL, 5])",
+        )
+        .unwrap();
+        let mut query = |sql: &str| -> Vec<Vec<Value>> {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.values.clone())
+                .collect()
+        };
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(
+            query("SELECT CAST(tags AS TEXT), tags[2], scores[0], cardinality(scores) FROM t"),
+            [
+                vec![
+                    text("{a,\"b c\"}"),
+                    text("b c"),
+                    Value::Null,
+                    Value::Integer(2)
+                ],
+                vec![
+                    text("{b,NULL}"),
+                    Value::Null,
+                    Value::Null,
+                    Value::Integer(0)
+                ],
+                vec![Value::Null, Value::Null, Value::Null, Value::Integer(3)],
+            ]
+        );
+        assert_eq!(
+            query(
+                "SELECT id FROM t WHERE 'b' = ANY (tags) OR 5 <= ALL (scores) \
+                 OR scores @> ARRAY[1] ORDER BY id"
+            ),
+            [vec![Value::Integer(1)], vec![Value::Integer(2)]]
+        );
+        // ALL は要素に NULL があれば結果が決まらない
+        assert_eq!(
+            query("SELECT 1 > ALL (scores), scores && '{5,6}' FROM t WHERE id = 3"),
+            [vec![Value::Boolean(false), Value::Boolean(true)]]
+        );
+        // 配列の要素を行に展開する。並べた unnest は長いほうに合わせて NULL で埋める
+        assert_eq!(
+            query("SELECT id, unnest(scores) * 10, unnest(tags) FROM t WHERE id <> 2 ORDER BY id"),
+            [
+                vec![Value::Integer(1), Value::Integer(30), text("a")],
+                vec![Value::Integer(1), Value::Integer(10), text("b c")],
+                vec![Value::Integer(3), Value::Integer(20), Value::Null],
+                vec![Value::Integer(3), Value::Null, Value::Null],
+                vec![Value::Integer(3), Value::Integer(50), Value::Null],
+            ]
+        );
+        let scores: Vec<Option<i64>> = conn
+            .query_row("SELECT scores || 7 FROM t WHERE id = 3", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(scores, [Some(2), None, Some(5), Some(7)]);
+        let err = conn
+            .query("SELECT id FROM t WHERE unnest(scores) = 1", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "0A000");
+        assert!(conn
+            .execute("INSERT INTO t VALUES (4, NULL, ARRAY['x'])", &[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_enum_types() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TYPE mood AS ENUM ('sad', 'ok', 'happy');
+             CREATE TABLE t (id INTEGER, m mood);
+             CREATE INDEX t_m ON t (m);
+             INSERT INTO t VALUES (1, 'happy'), (2, 'sad'), (3, 'ok'), (4, NULL)",
+        )
+        .unwrap();
+        let err = conn
+            .execute("INSERT INTO t VALUES (5, 'angry')", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "22P02");
+        let ids = |conn: &mut Connection, sql: &str| -> Vec<i64> {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.get::<i64>(0).unwrap())
+                .collect()
+        };
+        // 文字列の順ではなく、ラベルを宣言した順に並ぶ
+        assert_eq!(
+            ids(&mut conn, "SELECT id FROM t ORDER BY m, id"),
+            [2, 3, 1, 4]
+        );
+        assert_eq!(
+            ids(&mut conn, "SELECT id FROM t WHERE m > 'sad' ORDER BY m"),
+            [3, 1]
+        );
+        assert_eq!(ids(&mut conn, "SELECT id FROM t WHERE m = 'ok'"), [3]);
+
+        conn.execute_batch(
+            "ALTER TYPE mood ADD VALUE 'meh' BEFORE 'ok';
+             ALTER TYPE mood ADD VALUE IF NOT EXISTS 'ok';
+             INSERT INTO t VALUES (6, 'meh')",
+        )
+        .unwrap();
+        assert_eq!(
+            ids(
+                &mut conn,
+                "SELECT id FROM t WHERE m BETWEEN 'sad' AND 'ok' ORDER BY m"
+            ),
+            [2, 6, 3]
+        );
+        let label: String = conn
+            .query_row("SELECT CAST(m AS TEXT) FROM t WHERE id = 6", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(label, "meh");
+        let err = conn.execute("DROP TYPE mood", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "2BP01");
+
+        // 同じところに足し続けると順位を振り直す。インデックスも作り直すので、並びと検索は変わらない
+        for i in 0..20 {
+            let before = if i == 0 {
+                "meh".to_string()
+            } else {
+                format!("l{}", i - 1)
+            };
+            conn.execute(
+                &format!("ALTER TYPE mood ADD VALUE 'l{}' BEFORE '{}'", i, before),
+                &[],
+            )
+            .unwrap();
+            conn.execute(&format!("INSERT INTO t VALUES ({}, 'l{}')", 10 + i, i), &[])
+                .unwrap();
+        }
+        let mut expected = vec![2];
+        expected.extend((10..30).rev());
+        expected.extend([6, 3]);
+        assert_eq!(
+            ids(
+                &mut conn,
+                "SELECT /*+ IndexScan(t t_m) */ id FROM t WHERE m BETWEEN 'sad' AND 'ok' ORDER BY m"
+            ),
+            expected
+        );
+        assert_eq!(
+            ids(
+                &mut conn,
+                "SELECT /*+ IndexScan(t t_m) */ id FROM t WHERE m = 'l0'"
+            ),
+            [10]
+        );
+        assert_eq!(
+            ids(
+                &mut conn,
+                "SELECT /*+ IndexScan(t t_m) */ id FROM t WHERE m > 'meh' ORDER BY m"
+            ),
+            [3, 1]
+        );
+        let err = conn
+            .execute("ALTER TYPE mood ADD VALUE 'sad'", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42710");
+    }
+
+    #[test]
+    fn test_recursive_cte() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE emp (id INT, boss INT);
+             INSERT INTO emp VALUES (1, NULL), (2, 1), (3, 1), (4, 2), (5, 4)",
+        )
+        .unwrap();
+        let pairs = |conn: &mut Connection, sql| -> Vec<(i64, i64)> {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| (row.get::<i64>(0).unwrap(), row.get::<i64>(1).unwrap()))
+                .collect()
+        };
+        assert_eq!(
+            pairs(
+                &mut conn,
+                "WITH RECURSIVE sub (id, depth) AS (
+                     SELECT id, 0 FROM emp WHERE id = 2
+                     UNION ALL
+                     SELECT e.id, s.depth + 1 FROM emp e JOIN sub s ON e.boss = s.id)
+                 SELECT id, depth FROM sub ORDER BY id"
+            ),
+            [(2, 0), (4, 1), (5, 2)]
+        );
+        // UNION は返した行を繰り返さないので、閉路をたどっても止まる
+        assert_eq!(
+            pairs(
+                &mut conn,
+                "WITH RECURSIVE c (n, m) AS (
+                     SELECT 1, 0 UNION SELECT n % 3 + 1, 0 FROM c)
+                 SELECT n, m FROM c ORDER BY n"
+            ),
+            [(1, 0), (2, 0), (3, 0)]
+        );
+        // LIMIT で読むのをやめれば終わらない再帰も止まる
+        assert_eq!(
+            pairs(
+                &mut conn,
+                "WITH RECURSIVE c (n, m) AS (SELECT 1, 0 UNION ALL SELECT n + 1, 0 FROM c)
+                 SELECT n, m FROM c LIMIT 3"
+            ),
+            [(1, 0), (2, 0), (3, 0)]
+        );
+        conn.execute("SET max_recursion_depth = 10", &[]).unwrap();
+        let err = conn
+            .query(
+                "WITH RECURSIVE c (n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM c) \
+                 SELECT count(*) FROM c",
+                &[],
+            )
+            .err()
+            .unwrap();
+        assert_eq!(err.sqlstate(), "54000");
+    }
+
+    #[test]
+    fn test_lateral_join() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INT);
+             INSERT INTO t VALUES (1), (2), (3);
+             CREATE TABLE u (a INT, c INT);
+             INSERT INTO u VALUES (1, 10), (1, 11), (1, 12), (2, 20)",
+        )
+        .unwrap();
+        let pairs = |conn: &mut Connection, sql| -> Vec<(i64, Option<i64>)> {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| (row.get::<i64>(0).unwrap(), row.get(1).unwrap()))
+                .collect()
+        };
+        // 外側の行ごとに、その行の値で絞り込んだ上位 2 行
+        assert_eq!(
+            pairs(
+                &mut conn,
+                "SELECT t.a, s.c FROM t JOIN LATERAL (
+                     SELECT c FROM u WHERE u.a = t.a ORDER BY c DESC LIMIT 2) s ON true
+                 ORDER BY t.a, s.c"
+            ),
+            [(1, Some(11)), (1, Some(12)), (2, Some(20))]
+        );
+        assert_eq!(
+            pairs(
+                &mut conn,
+                "SELECT t.a, s.c FROM t LEFT JOIN LATERAL (
+                     SELECT c FROM u WHERE u.a = t.a ORDER BY c LIMIT 1) s ON true
+                 ORDER BY t.a"
+            ),
+            [(1, Some(10)), (2, Some(20)), (3, None)]
+        );
+        assert_eq!(
+            pairs(
+                &mut conn,
+                "SELECT t.a, s.n FROM t, LATERAL (SELECT count(*) AS n FROM u WHERE u.a = t.a) s
+                 ORDER BY t.a"
+            ),
+            [(1, Some(3)), (2, Some(1)), (3, Some(0))]
+        );
+        let err = conn
+            .query("SELECT a, (SELECT c FROM u WHERE u.a = t.a) FROM t", &[])
+            .err()
+            .unwrap();
+        assert_eq!(err.sqlstate(), "21000");
+    }
+
+    #[test]
+    fn test_grouping_sets() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE s (region TEXT, item TEXT, n INT);
+             INSERT INTO s VALUES ('east', 'a', 1), ('east', 'b', 2), ('west', 'a', 4), (NULL, 'a', 8)",
+        )
+        .unwrap();
+        let rows = |conn: &mut Connection, sql| -> Vec<(Option<String>, i64, i64)> {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| {
+                    (
+                        row.get(0).unwrap(),
+                        row.get::<i64>(1).unwrap(),
+                        row.get::<i64>(2).unwrap(),
+                    )
+                })
+                .collect()
+        };
+        let text = |s: &str| Some(s.to_string());
+        // GROUPING は総計の NULL とデータの NULL を区別する
+        assert_eq!(
+            rows(
+                &mut conn,
+                "SELECT region, grouping(region), sum(n) FROM s GROUP BY ROLLUP (region)
+                 ORDER BY grouping(region), region NULLS LAST"
+            ),
+            [
+                (text("east"), 0, 3),
+                (text("west"), 0, 4),
+                (None, 0, 8),
+                (None, 1, 15)
+            ]
+        );
+        assert_eq!(
+            rows(
+                &mut conn,
+                "SELECT item, grouping(region, item), count(*) FROM s GROUP BY CUBE (region, item)
+                 HAVING grouping(region) = 1 ORDER BY 2, 1"
+            ),
+            [(text("a"), 2, 3), (text("b"), 2, 1), (None, 3, 4)]
+        );
+        // 空の集合は入力が空でも 1 行を返す
+        assert_eq!(
+            rows(
+                &mut conn,
+                "SELECT item, grouping(item), count(*) FROM s WHERE n > 100
+                 GROUP BY GROUPING SETS ((item), ())"
+            ),
+            [(None, 1, 0)]
+        );
+        let err = conn
+            .query("SELECT grouping(n) FROM s GROUP BY ROLLUP (region)", &[])
+            .err()
+            .unwrap();
+        assert_eq!(err.sqlstate(), "42803");
+    }
+
+    #[test]
+    fn test_tablesample() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INT, b TEXT);
+             INSERT INTO t WITH RECURSIVE g (n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM g WHERE n < 5000)
+                 SELECT n, 'row ' || n FROM g",
+        )
+        .unwrap();
+        let count = |conn: &mut Connection, sql| -> i64 {
+            conn.query(sql, &[])
+                .unwrap()
+                .next()
+                .unwrap()
+                .get(0)
+                .unwrap()
+        };
+        assert_eq!(
+            count(&mut conn, "SELECT count(*) FROM t TABLESAMPLE SYSTEM (100)"),
+            5000
+        );
+        assert_eq!(
+            count(
+                &mut conn,
+                "SELECT count(*) FROM t TABLESAMPLE BERNOULLI (0)"
+            ),
+            0
+        );
+        // 同じ種なら同じページを選ぶ
+        let sql = "SELECT count(*) FROM t TABLESAMPLE SYSTEM (20) REPEATABLE (7)";
+        let system = count(&mut conn, sql);
+        assert!(0 < system && system < 5000);
+        assert_eq!(count(&mut conn, sql), system);
+        let bernoulli = count(
+            &mut conn,
+            "SELECT count(*) FROM t s TABLESAMPLE BERNOULLI (20) WHERE s.a > 0",
+        );
+        assert!((700..1300).contains(&bernoulli));
+
+        let sqlstate = |conn: &mut Connection, sql| conn.query(sql, &[]).err().unwrap().sqlstate();
+        assert_eq!(
+            sqlstate(&mut conn, "SELECT * FROM t TABLESAMPLE SYSTEM (101)"),
+            "2202H"
+        );
+        assert_eq!(
+            sqlstate(
+                &mut conn,
+                "WITH c AS (SELECT 1) SELECT * FROM c TABLESAMPLE SYSTEM (1)"
+            ),
+            "42809"
+        );
+    }
+
+    #[test]
+    fn test_runtime_filter() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE dim (id INT, name TEXT);
+             CREATE TABLE fact (k INT, v INT);
+             INSERT INTO dim WITH RECURSIVE g (n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM g WHERE n < 100)
+                 SELECT n, 'dim ' || n FROM g;
+             INSERT INTO fact WITH RECURSIVE g (n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM g WHERE n < 5000)
+                 SELECT n % 100, n FROM g;
+             ANALYZE dim;
+             ANALYZE fact",
+        )
+        .unwrap();
+        let count = |conn: &mut Connection, sql| -> i64 {
+            conn.query(sql, &[])
+                .unwrap()
+                .next()
+                .unwrap()
+                .get(0)
+                .unwrap()
+        };
+        conn.set_profiling(true);
+        assert_eq!(
+            count(
+                &mut conn,
+                "SELECT count(*) FROM fact JOIN dim ON fact.k = dim.id WHERE dim.id < 5 AND fact.v > 10"
+            ),
+            196
+        );
+        // 探索側の走査のすぐ上で、ハッシュ表にないキーの行を捨てる
+        let profile = conn.last_profile().unwrap();
+        let names: Vec<_> = profile
+            .operators
+            .iter()
+            .map(|op| op.name.as_str())
+            .collect();
+        let i = names
+            .iter()
+            .position(|name| name.starts_with("Bloom Filter"))
+            .unwrap();
+        assert_eq!(names[i + 1], "Seq Scan on fact");
+        let filter = &profile.operators[i];
+        assert!(filter.metrics.removed > 4000);
+        assert_eq!(
+            filter.metrics.rows + filter.metrics.removed,
+            profile.operators[i + 1].metrics.rows
+        );
+        assert_eq!(
+            count(
+                &mut conn,
+                "SELECT count(*) FROM fact WHERE k IN (SELECT id FROM dim WHERE id < 5)"
+            ),
+            200
+        );
+        assert_eq!(
+            count(
+                &mut conn,
+                "SELECT count(fact.v) FROM dim LEFT JOIN fact ON fact.k = dim.id WHERE dim.id < 5"
+            ),
+            200
+        );
+        // 相手のない探索側の行を返す結合では捨てない
+        assert_eq!(
+            count(
+                &mut conn,
+                "SELECT count(*) FROM fact LEFT JOIN dim ON fact.k = dim.id AND dim.id < 5"
+            ),
+            5000
+        );
+        let lines: Vec<String> = conn
+            .query("EXPLAIN ANALYZE SELECT count(*) FROM fact JOIN dim ON fact.k = dim.id WHERE dim.id < 5", &[])
+            .unwrap()
+            .map(|row| row.get(0).unwrap())
+            .collect();
+        assert!(lines
+            .iter()
+            .any(|line| line.trim() == "Runtime Filter: bf0"));
+        assert!(lines
+            .iter()
+            .any(|line| line.trim().starts_with("Rows Removed by Bloom Filter: ")));
+    }
+
+    #[test]
+    fn test_autovacuum() {
+        let settings = Settings {
+            autovacuum_vacuum_threshold: 5,
+            autovacuum_vacuum_scale_factor: 0.0,
+            autovacuum_analyze_threshold: 5,
+            autovacuum_analyze_scale_factor: 0.0,
+            ..Settings::default()
+        };
+        let db = Database::open_temporary_with(settings).unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER);
+             CREATE TABLE off (a INTEGER) WITH (autovacuum_enabled = false);
+             INSERT INTO t VALUES (1), (2), (3), (4), (5), (6), (7), (8), (9), (10);
+             INSERT INTO off SELECT a FROM t;
+             DELETE FROM t WHERE a > 2;
+             DELETE FROM off",
+        )
+        .unwrap();
+        assert_eq!(
+            db.autovacuum().unwrap(),
+            Some(Maintenance {
+                table: "t".to_string(),
+                vacuumed: Some(8),
+                analyzed: true,
+            })
+        );
+        let row = conn
+            .query_row(
+                "SELECT dead_tuples, modifications, last_autovacuum IS NOT NULL
+                 FROM stats.tables WHERE name = 't'",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(
+            (
+                row.get::<i64>(0).unwrap(),
+                row.get::<i64>(1).unwrap(),
+                row.get::<bool>(2).unwrap()
+            ),
+            (0, 0, true)
+        );
+        // 設定を変えるまで off は飛ばす
+        assert_eq!(db.autovacuum().unwrap(), None);
+        conn.execute("ALTER TABLE off SET (autovacuum_enabled)", &[])
+            .unwrap();
+        let maintenance = db.autovacuum().unwrap().unwrap();
+        assert_eq!(
+            (maintenance.table.as_str(), maintenance.vacuumed),
+            ("off", Some(10))
+        );
+
+        let err = conn
+            .execute("ALTER TABLE t SET (fillfactor = 50)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "22023");
+        let err = conn
+            .execute(
+                "CREATE TABLE u (a INTEGER) WITH (autovacuum_enabled = maybe)",
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "22023");
+        assert!(conn.execute("SELECT * FROM u", &[]).is_err());
+    }
+
+    #[test]
+    fn test_describe_prepared() {
+        use DataType::*;
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch("CREATE TABLE t (a INTEGER, b TEXT, c REAL)")
+            .unwrap();
+        let types = |description: &Description| {
+            let columns = description.columns.as_ref().unwrap();
+            columns.iter().map(|(_, ty)| ty.clone()).collect::<Vec<_>>()
+        };
+        conn.prepare(
+            "q",
+            "SELECT a, b || 'x' AS bx, count(*), c * 2 FROM t \
+             WHERE b = $1 AND a > $2 GROUP BY a, b, c LIMIT $3",
+            &[],
+        )
+        .unwrap();
+        let description = conn.describe_prepared("q").unwrap();
+        assert_eq!(
+            description.params,
+            [Some(Text), Some(Integer), Some(Integer)]
+        );
+        assert_eq!(description.columns.as_ref().unwrap()[1].0, "bx");
+        assert_eq!(
+            types(&description),
+            [Some(Integer), Some(Text), Some(Integer), Some(Real)]
+        );
+        // 行を返さない文も計画して $n の型を決める。型の決まらない $n は None
+        conn.prepare("ins", "INSERT INTO t (c, a) VALUES ($1, $2)", &[])
+            .unwrap();
+        let description = conn.describe_prepared("ins").unwrap();
+        assert_eq!(description.params, [Some(Real), Some(Integer)]);
+        assert_eq!(description.columns, None);
+        conn.prepare(
+            "upd",
+            "UPDATE t SET b = $2 WHERE a IN ($1, 3) RETURNING a, $3",
+            &[],
+        )
+        .unwrap();
+        let description = conn.describe_prepared("upd").unwrap();
+        assert_eq!(description.params, [Some(Integer), Some(Text), None]);
+        assert_eq!(types(&description), [Some(Integer), None]);
+        // 実行しても同じ型の値が返る
+        conn.execute("INSERT INTO t VALUES (1, 'y', 1.5)", &[])
+            .unwrap();
+        let row = conn.execute_prepared("q", &["y".into(), 0.into(), 10.into()]);
+        let Some(StatementResult::Rows(mut rows)) = row.unwrap() else {
+            panic!();
+        };
+        let values = rows.next().unwrap().into_values();
+        let actual = values.iter().map(Value::data_type).collect::<Vec<_>>();
+        assert_eq!(actual, types(&conn.describe_prepared("q").unwrap()));
+    }
+
+    #[test]
+    fn test_as_of() {
+        use crate::clock;
+        use crate::wal::Wal;
+
+        // 仮想の時計は 2000-01-01 00:00:00 から始まり、進めたときだけ進む
+        clock::set_virtual(true);
+        let settings = Settings {
+            version_retention: Some(Duration::from_secs(3600)),
+            ..Settings::default()
+        };
+        let db = Database::open_temporary_with(settings).unwrap();
+        let mut conn = db.connect();
+        let rows = |conn: &mut Connection, sql: &str| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.into_values())
+                .collect::<Vec<_>>()
+        };
+        let at = |time: &str| {
+            format!(
+                "SELECT a, b FROM t AS OF TIMESTAMP '2000-01-01 {}' ORDER BY a",
+                time
+            )
+        };
+        let row = |a: i64, b: &str| vec![Value::Integer(a), Value::Text(b.to_string())];
+        conn.execute_batch("CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT)")
+            .unwrap();
+        for sql in [
+            "INSERT INTO t VALUES (1, 'x'), (2, 'y')",
+            "UPDATE t SET b = 'z' WHERE a = 1",
+            "DELETE FROM t WHERE a = 2",
+            "VACUUM t",
+        ] {
+            clock::advance(Duration::from_secs(1));
+            conn.execute_batch(sql).unwrap();
+        }
+        assert_eq!(rows(&mut conn, &at("00:00:00.5")), Vec::<Vec<Value>>::new());
+        assert_eq!(
+            rows(&mut conn, &at("00:00:01.5")),
+            [row(1, "x"), row(2, "y")]
+        );
+        assert_eq!(
+            rows(&mut conn, &at("00:00:02.5")),
+            [row(1, "z"), row(2, "y")]
+        );
+        assert_eq!(rows(&mut conn, "SELECT a, b FROM t"), [row(1, "z")]);
+        // 昔の版を読むのは AS OF をつけたテーブルだけ
+        assert_eq!(
+            rows(
+                &mut conn,
+                "SELECT o.b, n.b FROM t AS OF TIMESTAMP '2000-01-01 00:00:01.5' o JOIN t n ON o.a = n.a"
+            ),
+            [vec![Value::Text("x".to_string()), Value::Text("z".to_string())]]
+        );
+        let err = conn.query(&at("00:10:00"), &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "22023");
+        let err = conn.query("SELECT * FROM t AS OF LSN 0", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "55000");
+
+        let dir = crate::testutil::temp_path("as-of-wal");
+        db.engine
+            .borrow_mut()
+            .bufmgr
+            .set_wal(Wal::open(&dir).unwrap());
+        conn.execute_batch("UPDATE t SET b = 'w'").unwrap();
+        let lsn = db.engine.borrow().bufmgr.wal().unwrap().next_lsn();
+        conn.execute_batch("UPDATE t SET b = 'v'").unwrap();
+        let sql = format!("SELECT b FROM t AS OF LSN {}", lsn.to_u64());
+        assert_eq!(rows(&mut conn, &sql), [vec![Value::Text("w".to_string())]]);
+
+        // retention より古い版は VACUUM で消えてよく、読めない
+        clock::advance(Duration::from_secs(7200));
+        let err = conn.query(&at("00:00:02.5"), &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "72000");
+        clock::set_virtual(false);
+    }
+
+    #[test]
+    fn test_transactional_ddl() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let mut other = db.connect();
+        other.set_lock_timeout(Some(Duration::ZERO));
+        let rows = |conn: &mut Connection, sql: &str| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.into_values())
+                .collect::<Vec<_>>()
+        };
+        conn.execute_batch("CREATE TABLE t (a INTEGER); INSERT INTO t VALUES (1), (2)")
+            .unwrap();
+        conn.execute_batch(
+            "BEGIN; CREATE TABLE u (x INTEGER); INSERT INTO u VALUES (1); \
+             CREATE INDEX t_a ON t (a); ALTER TABLE t ADD COLUMN b TEXT; \
+             INSERT INTO t VALUES (3, 'c')",
+        )
+        .unwrap();
+        assert_eq!(
+            rows(&mut conn, "SELECT a, b FROM t ORDER BY a"),
+            [
+                vec![Value::Integer(1), Value::Null],
+                vec![Value::Integer(2), Value::Null],
+                vec![Value::Integer(3), Value::Text("c".to_string())],
+            ]
+        );
+        // ほかの接続にはコミットするまで見えない。作り終えたインデックスは書き換えを待たせず、
+        // その間に入れた行はコミットしたときにインデックスに入る
+        let err = other.query("SELECT * FROM u", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42P01");
+        assert!(other.catalog().table("t").unwrap().indexes.is_empty());
+        assert_eq!(
+            rows(&mut other, "SELECT * FROM t ORDER BY a"),
+            [vec![Value::Integer(1)], vec![Value::Integer(2)]]
+        );
+        other.execute("INSERT INTO t VALUES (4)", &[]).unwrap();
+        conn.execute_batch("COMMIT").unwrap();
+        assert_eq!(
+            rows(&mut other, "SELECT x FROM u"),
+            [vec![Value::Integer(1)]]
+        );
+        assert_eq!(other.catalog().table("t").unwrap().indexes.len(), 1);
+        let sql = "SELECT /*+ IndexScan(t t_a) */ a FROM t WHERE a = 4";
+        let plan = rows(&mut other, &format!("EXPLAIN {}", sql));
+        assert!(
+            plan.iter()
+                .any(|line| line[0].to_string().contains("Index Only Scan")),
+            "{:?}",
+            plan
+        );
+        assert_eq!(rows(&mut other, sql), [vec![Value::Integer(4)]]);
+        assert_eq!(
+            rows(&mut other, "SELECT a FROM t WHERE b IS NULL ORDER BY a"),
+            [
+                vec![Value::Integer(1)],
+                vec![Value::Integer(2)],
+                vec![Value::Integer(4)]
+            ]
+        );
+
+        // 取り消せば DDL も戻る
+        conn.execute_batch("BEGIN; CREATE TABLE v (x INTEGER); DROP TABLE u; ROLLBACK")
+            .unwrap();
+        assert!(conn.catalog().table("v").is_none());
+        assert_eq!(
+            rows(&mut conn, "SELECT x FROM u"),
+            [vec![Value::Integer(1)]]
+        );
+
+        // 写してからほかの接続が定義を変えていれば、コミットできない
+        conn.execute_batch("BEGIN; CREATE TABLE w (x INTEGER)")
+            .unwrap();
+        other.execute("CREATE TABLE z (x INTEGER)", &[]).unwrap();
+        let err = conn.execute("COMMIT", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "40001");
+        assert!(!conn.in_transaction());
+        assert!(conn.catalog().table("w").is_none());
+        assert!(conn.catalog().table("z").is_some());
+        // ANALYZE は定義を変えない
+        conn.execute_batch("BEGIN; CREATE TABLE w (x INTEGER)")
+            .unwrap();
+        other.execute("ANALYZE", &[]).unwrap();
+        conn.execute("COMMIT", &[]).unwrap();
+        assert!(other.catalog().table("w").is_some());
+
+        let err = conn
+            .execute("ALTER TABLE t ADD COLUMN c INTEGER NOT NULL", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "0A000");
+        let err = conn.execute("ALTER TABLE t ADD b TEXT", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42701");
+    }
+
+    #[test]
+    fn test_declare_cursor() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let mut other = db.connect();
+        conn.execute_batch("CREATE TABLE t (a INTEGER); INSERT INTO t VALUES (1), (2), (3)")
+            .unwrap();
+        let err = conn
+            .declare_cursor("c", "SELECT a FROM t", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "25P01");
+
+        conn.execute_batch("BEGIN; SET TRANSACTION ISOLATION LEVEL READ COMMITTED")
+            .unwrap();
+        conn.declare_cursor(
+            "c",
+            "SELECT a FROM t WHERE a >= $1 ORDER BY a",
+            &[Value::Integer(2)],
+        )
+        .unwrap();
+        let rows = conn.fetch_cursor("c", 1).unwrap();
+        assert_eq!(rows.columns(), ["a"]);
+        let values: Vec<_> = rows.map(|row| row.values()[0].clone()).collect();
+        assert_eq!(values, [Value::Integer(2)]);
+        assert_eq!(
+            conn.declare_cursor("c", "SELECT 1", &[])
+                .unwrap_err()
+                .sqlstate(),
+            "42P03"
+        );
+        // 開いたあとのコミットは、READ COMMITTED でもカーソルには見えない
+        conn.declare_cursor("d", "SELECT a FROM t", &[]).unwrap();
+        other.execute("INSERT INTO t VALUES (4)", &[]).unwrap();
+        assert_eq!(conn.fetch_cursor("d", 10).unwrap().len(), 3);
+        assert_eq!(conn.query("SELECT a FROM t", &[]).unwrap().len(), 4);
+        conn.close_cursor("d").unwrap();
+        assert_eq!(conn.fetch_cursor("d", 1).unwrap_err().sqlstate(), "34000");
+        assert_eq!(conn.fetch_cursor("c", 10).unwrap().len(), 1);
+
+        // コミットすれば開いているカーソルは閉じる
+        conn.execute("COMMIT", &[]).unwrap();
+        assert_eq!(conn.fetch_cursor("c", 1).unwrap_err().sqlstate(), "34000");
+        conn.execute("BEGIN", &[]).unwrap();
+        conn.declare_cursor("c", "SELECT a FROM t", &[]).unwrap();
+        conn.rollback().unwrap();
+        assert_eq!(conn.fetch_cursor("c", 1).unwrap_err().sqlstate(), "34000");
+    }
+
+    #[test]
+    fn test_savepoint_ddl() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let columns = |conn: &mut Connection, name: &str| {
+            let rows = conn.query(&format!("SELECT * FROM {}", name), &[]);
+            rows.map(|rows| rows.columns().to_vec())
+        };
+        conn.execute_batch("CREATE TABLE t (a INTEGER); INSERT INTO t VALUES (1)")
+            .unwrap();
+        // 写す前のセーブポイントに戻れば、コミットしたカタログに戻る
+        conn.execute_batch("BEGIN; SAVEPOINT s; DROP TABLE t; ROLLBACK TO SAVEPOINT s; COMMIT")
+            .unwrap();
+        let count = conn.query("SELECT count(*) FROM t", &[]).unwrap().next();
+        assert_eq!(count.unwrap().values()[0], Value::Integer(1));
+
+        // セーブポイントより前の DDL は残り、後のものだけが戻る。戻ったセーブポイントは何度でも使える
+        conn.execute_batch(
+            "BEGIN; CREATE TABLE u (x INTEGER); SAVEPOINT s; CREATE TABLE v (x INTEGER); \
+             ALTER TABLE u ADD COLUMN y TEXT; ROLLBACK TO s",
+        )
+        .unwrap();
+        assert_eq!(columns(&mut conn, "v").unwrap_err().sqlstate(), "42P01");
+        assert_eq!(columns(&mut conn, "u").unwrap(), ["x"]);
+        conn.execute_batch("DROP TABLE u; ROLLBACK TO s; INSERT INTO u VALUES (1); RELEASE s")
+            .unwrap();
+        assert!(conn.execute("ROLLBACK TO s", &[]).is_err());
+        conn.execute_batch("ROLLBACK; BEGIN; CREATE TABLE u (x INTEGER); SAVEPOINT s")
+            .unwrap();
+        conn.execute_batch("DROP TABLE u; RELEASE s; COMMIT")
+            .unwrap();
+        assert_eq!(columns(&mut conn, "u").unwrap_err().sqlstate(), "42P01");
+        assert_eq!(columns(&mut conn, "t").unwrap(), ["a"]);
+    }
+
+    #[test]
+    fn test_snapshot_schema() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let mut other = db.connect();
+        let rows = |conn: &mut Connection, sql: &str| {
+            conn.query(sql, &[])
+                .unwrap()
+                .map(|row| row.into_values())
+                .collect::<Vec<_>>()
+        };
+        conn.execute_batch("CREATE TABLE t (a INTEGER); INSERT INTO t VALUES (1)")
+            .unwrap();
+        // REPEATABLE READ では、最初の文のあとにほかの接続がコミットした DDL は見えない
+        conn.execute_batch("BEGIN; SELECT * FROM t").unwrap();
+        other
+            .execute_batch(
+                "ALTER TABLE t ADD COLUMN b TEXT; CREATE TABLE u (x INTEGER); \
+                 CREATE INDEX t_a ON t (a)",
+            )
+            .unwrap();
+        assert_eq!(
+            rows(&mut conn, "SELECT * FROM t"),
+            [vec![Value::Integer(1)]]
+        );
+        let err = conn.query("SELECT * FROM u", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "42P01");
+        // 見えないインデックスにも、書き換えた行はコミットしたときに入る
+        conn.execute("INSERT INTO t VALUES (2)", &[]).unwrap();
+        conn.execute("COMMIT", &[]).unwrap();
+        assert_eq!(
+            rows(&mut conn, "SELECT * FROM t ORDER BY a"),
+            [
+                vec![Value::Integer(1), Value::Null],
+                vec![Value::Integer(2), Value::Null]
+            ]
+        );
+        let sql = "SELECT /*+ IndexScan(t t_a) */ a FROM t WHERE a = 2";
+        assert_eq!(rows(&mut conn, sql), [vec![Value::Integer(2)]]);
+        assert_eq!(conn.catalog().table("t").unwrap().row_count(), 2);
+
+        // 写したあとにほかの接続が定義を変えていれば、DDL のあるトランザクションはコミットできない
+        conn.execute_batch("BEGIN; SELECT * FROM u").unwrap();
+        other.execute("CREATE TABLE v (x INTEGER)", &[]).unwrap();
+        conn.execute("CREATE TABLE w (x INTEGER)", &[]).unwrap();
+        let err = conn.execute("COMMIT", &[]).unwrap_err();
+        assert_eq!(err.sqlstate(), "40001");
+
+        // READ COMMITTED では文ごとに見える
+        conn.execute_batch(
+            "BEGIN; SET TRANSACTION ISOLATION LEVEL READ COMMITTED; SELECT * FROM t",
+        )
+        .unwrap();
+        other.execute("CREATE TABLE w (x INTEGER)", &[]).unwrap();
+        assert!(rows(&mut conn, "SELECT * FROM w").is_empty());
+        conn.execute("COMMIT", &[]).unwrap();
+    }
+
+    #[test]
+    fn test_open_in_memory() {
+        let db = Database::open_in_memory().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (id INTEGER, name TEXT); INSERT INTO t VALUES (1, 'a'), (2, 'b');",
+        )
+        .unwrap();
+        let rows = conn
+            .query("SELECT name FROM t ORDER BY id", &[])
+            .unwrap()
+            .map(|row| row.into_values())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![vec![Value::Text("a".into())], vec![Value::Text("b".into())]]
+        );
+    }
+}
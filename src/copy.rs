@@ -0,0 +1,378 @@
+use std::io::{self, BufRead, Write};
+
+use crate::format::json_value;
+use crate::json::Json;
+use crate::sql::ast::{CopyFormat, CopyOptions};
+use crate::types::Value;
+
+// COPY で読むデータを 1 件ずつの値の並びに分け、書き出す行を 1 件ずつ書く
+//
+// CSV では引用符で囲んだ値は改行を含んでよく、中の引用符は 2 つ重ねる。
+// 引用符で囲まずに NULL の字面と同じ値だけを NULL とし、囲んだものは文字列として読む。
+// text 形式では 1 行が 1 件で、\ の後の t、n、r などを制御文字に戻す。逃がす前の値が NULL の字面なら NULL。
+// json 形式は書き出すときだけ使え、1 行に列名をキーにしたオブジェクトを 1 つ書く
+
+// 1 件の値。None は NULL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    // 件の始まりの行の番号。1 始まり
+    pub line: usize,
+    pub fields: Vec<Option<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordError {
+    pub line: usize,
+    pub message: String,
+}
+
+pub struct Records<R> {
+    reader: R,
+    options: CopyOptions,
+    // 次に読む行の番号
+    line: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Records<R> {
+    pub fn new(reader: R, options: &CopyOptions) -> Records<R> {
+        Records {
+            reader,
+            options: options.clone(),
+            line: 1,
+            done: false,
+        }
+    }
+
+    // 行末の改行を除いた 1 行。ファイルの終わりなら None
+    fn read_line(&mut self) -> Result<Option<String>, RecordError> {
+        let mut buf = vec![];
+        let error = |message: String| RecordError {
+            line: self.line,
+            message,
+        };
+        match self.reader.read_until(b'\n', &mut buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(e) => return Err(error(e.to_string())),
+        }
+        let line = String::from_utf8(buf)
+            .map_err(|_| error("invalid byte sequence for encoding \"UTF8\"".to_string()))?;
+        self.line += 1;
+        Ok(Some(line))
+    }
+
+    fn read_csv(&mut self, first: String) -> Result<Vec<Option<String>>, RecordError> {
+        let start = self.line - 1;
+        let delimiter = self.options.delimiter();
+        let quote = self.options.quote();
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        let mut line = first;
+        loop {
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c != quote {
+                        field.push(c);
+                    } else if chars.peek() == Some(&quote) {
+                        chars.next();
+                        field.push(quote);
+                    } else {
+                        in_quotes = false;
+                    }
+                } else if c == quote {
+                    in_quotes = true;
+                    quoted = true;
+                } else if c == delimiter {
+                    fields.push(self.csv_field(std::mem::take(&mut field), quoted));
+                    quoted = false;
+                } else if c == '\n' || c == '\r' && chars.peek() == Some(&'\n') {
+                    // 引用符の外の改行で件が終わる
+                } else {
+                    field.push(c);
+                }
+            }
+            if !in_quotes {
+                fields.push(self.csv_field(field, quoted));
+                return Ok(fields);
+            }
+            line = self.read_line()?.ok_or_else(|| RecordError {
+                line: start,
+                message: "unterminated CSV quoted field".to_string(),
+            })?;
+        }
+    }
+
+    fn csv_field(&self, field: String, quoted: bool) -> Option<String> {
+        (quoted || field != self.options.null()).then_some(field)
+    }
+
+    fn read_text(&self, line: &str) -> Vec<Option<String>> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        line.split(self.options.delimiter())
+            .map(|field| (field != self.options.null()).then(|| unescape(field)))
+            .collect()
+    }
+}
+
+fn unescape(field: &str) -> String {
+    let mut value = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => value.push('\u{8}'),
+            Some('f') => value.push('\u{c}'),
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some('t') => value.push('\t'),
+            Some('v') => value.push('\u{b}'),
+            Some(c) => value.push(c),
+            None => value.push('\\'),
+        }
+    }
+    value
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = Result<Record, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                return None;
+            }
+            let line = self.line;
+            let first = match self.read_line() {
+                Ok(Some(first)) => first,
+                Ok(None) => return None,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            let fields = match self.options.format {
+                CopyFormat::Csv => self.read_csv(first),
+                CopyFormat::Text => Ok(self.read_text(&first)),
+                CopyFormat::Json | CopyFormat::Parquet => Err(RecordError {
+                    line,
+                    message: "COPY FROM supports only the csv and text formats".to_string(),
+                }),
+            };
+            let fields = match fields {
+                Ok(fields) => fields,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            if self.options.header && line == 1 {
+                continue;
+            }
+            return Some(Ok(Record { line, fields }));
+        }
+    }
+}
+
+// 行を options の形式で書く。HEADER なら最初に列名を書く (json では書かない)
+pub struct RecordWriter<W> {
+    writer: W,
+    options: CopyOptions,
+    // json のキー
+    columns: Vec<Json>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(
+        mut writer: W,
+        columns: &[String],
+        options: &CopyOptions,
+    ) -> io::Result<RecordWriter<W>> {
+        if options.header && options.format != CopyFormat::Json {
+            let header: Vec<String> = columns
+                .iter()
+                .map(|column| escape(column, options))
+                .collect();
+            writeln!(writer, "{}", header.join(&options.delimiter().to_string()))?;
+        }
+        Ok(RecordWriter {
+            writer,
+            options: options.clone(),
+            columns: columns.iter().cloned().map(Json::String).collect(),
+        })
+    }
+
+    pub fn write(&mut self, row: &[Value]) -> io::Result<()> {
+        if self.options.format == CopyFormat::Json {
+            let members: Vec<String> = self
+                .columns
+                .iter()
+                .zip(row)
+                .map(|(column, value)| format!("{}:{}", column, json_value(value)))
+                .collect();
+            return writeln!(self.writer, "{{{}}}", members.join(","));
+        }
+        let fields: Vec<String> = row
+            .iter()
+            .map(|value| match value {
+                Value::Null => self.options.null().to_string(),
+                value => escape(&value.to_string(), &self.options),
+            })
+            .collect();
+        writeln!(
+            self.writer,
+            "{}",
+            fields.join(&self.options.delimiter().to_string())
+        )
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// CSV では区切りや引用符や改行を含むか NULL の字面と同じなら引用符で囲む。text では \ で逃がす
+fn escape(field: &str, options: &CopyOptions) -> String {
+    let delimiter = options.delimiter();
+    match options.format {
+        CopyFormat::Csv | CopyFormat::Json | CopyFormat::Parquet => {
+            let quote = options.quote();
+            if field == options.null() || field.contains([delimiter, quote, '\n', '\r']) {
+                let doubled = format!("{}{}", quote, quote);
+                format!("{}{}{}", quote, field.replace(quote, &doubled), quote)
+            } else {
+                field.to_string()
+            }
+        }
+        CopyFormat::Text => {
+            let mut escaped = String::with_capacity(field.len());
+            for c in field.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    '\t' => escaped.push_str("\\t"),
+                    c if c == delimiter => {
+                        escaped.push('\\');
+                        escaped.push(c);
+                    }
+                    c => escaped.push(c),
+                }
+            }
+            escaped
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str, options: &CopyOptions) -> Vec<Result<Record, RecordError>> {
+        Records::new(input.as_bytes(), options).collect()
+    }
+
+    #[test]
+    fn test_records() {
+        let options = CopyOptions {
+            header: true,
+            ..CopyOptions::default()
+        };
+        let input = "a,b,c\r\n1,,\"\"\n2,\"x,\"\"y\"\"\nz\",w\n3";
+        let s = |s: &str| Some(s.to_string());
+        assert_eq!(
+            read(input, &options),
+            vec![
+                Ok(Record {
+                    line: 2,
+                    fields: vec![s("1"), None, s("")],
+                }),
+                Ok(Record {
+                    line: 3,
+                    fields: vec![s("2"), s("x,\"y\"\nz"), s("w")],
+                }),
+                Ok(Record {
+                    line: 5,
+                    fields: vec![s("3")],
+                }),
+            ]
+        );
+        assert_eq!(
+            read("1\n\"open\n", &CopyOptions::default())[1],
+            Err(RecordError {
+                line: 2,
+                message: "unterminated CSV quoted field".to_string(),
+            })
+        );
+
+        let options = CopyOptions {
+            format: CopyFormat::Text,
+            ..CopyOptions::default()
+        };
+        assert_eq!(
+            read("1\t\\N\ta\\tb\\\\\n", &options),
+            vec![Ok(Record {
+                line: 1,
+                fields: vec![s("1"), None, s("a\tb\\")],
+            })]
+        );
+    }
+
+    fn write(rows: &[Vec<Value>], options: &CopyOptions) -> String {
+        let columns = vec!["a".to_string(), "b".to_string()];
+        let mut writer = RecordWriter::new(vec![], &columns, options).unwrap();
+        for row in rows {
+            writer.write(row).unwrap();
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_record_writer() {
+        let text = |s: &str| Value::Text(s.to_string());
+        let rows = vec![
+            vec![Value::Integer(1), text("x,\"y\"\nz")],
+            vec![Value::Null, text("")],
+            vec![Value::Integer(3), text("a\tb\\")],
+        ];
+        let options = CopyOptions {
+            header: true,
+            ..CopyOptions::default()
+        };
+        let csv = write(&rows, &options);
+        assert_eq!(csv, "a,b\n1,\"x,\"\"y\"\"\nz\"\n,\"\"\n3,a\tb\\\n");
+        // 書いたものを読めば同じ値に戻る
+        let records: Vec<Vec<Option<String>>> = Records::new(csv.as_bytes(), &options)
+            .map(|record| record.unwrap().fields)
+            .collect();
+        assert_eq!(records[1], vec![None, Some(String::new())]);
+        assert_eq!(records[0][1].as_deref(), Some("x,\"y\"\nz"));
+
+        let options = CopyOptions {
+            format: CopyFormat::Text,
+            ..CopyOptions::default()
+        };
+        assert_eq!(
+            write(&rows, &options),
+            "1\tx,\"y\"\\nz\n\\N\t\n3\ta\\tb\\\\\n"
+        );
+
+        let options = CopyOptions {
+            format: CopyFormat::Json,
+            header: true,
+            ..CopyOptions::default()
+        };
+        assert_eq!(
+            write(&rows[..2], &options),
+            "{\"a\":1,\"b\":\"x,\\\"y\\\"\\nz\"}\n{\"a\":null,\"b\":\"\"}\n"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::cell::Cell;
+use std::io;
+use std::path::PathBuf;
+
+use crate::connection::{Database, Error};
+use crate::settings::Settings;
+use crate::testutil::temp_path;
+use crate::wal;
+
+// 落ちたあとに回復できるかを確かめるテストの道具
+//
+// 本体のコードの落ちうる場所で hit を呼んでおき、arm した場所を決めた回数だけ通ったところで io::Error を返す。
+// 落ちたらデータベースを閉じずに捨て、fsync したと分かっていないログを失ったことにしてから開き直して回復する。
+// 書き出しの途中で落ちたページは前半だけが新しくなり、ページ LSN は古いまま残る。
+// 書き出したページの fsync は確かめないので、ページは書いた内容がそのまま残る。
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Point {
+    // ログを fsync する直前
+    WalSync,
+    // ページの書き出しの途中
+    PageWrite,
+    // ページの像をログに追記したあと、コミットを記してページを書き出す前
+    AfterWalAppend,
+    // カタログのページを書き換える途中
+    CatalogWrite,
+    // 開くときに、コミットしていなかった版を片づける途中
+    CleanUp,
+}
+
+pub const POINTS: [Point; 5] = [
+    Point::WalSync,
+    Point::PageWrite,
+    Point::AfterWalAppend,
+    Point::CatalogWrite,
+    Point::CleanUp,
+];
+
+thread_local! {
+    // 落ちる場所と、それまでに通る残りの回数。落ちたら None にして fired を立てる
+    static ARMED: Cell<Option<(Point, usize)>> = const { Cell::new(None) };
+    static FIRED: Cell<bool> = const { Cell::new(false) };
+}
+
+// このスレッドで point を n 回通ったあと、次に通ったところで落ちるようにする
+pub fn arm(point: Point, n: usize) {
+    ARMED.set(Some((point, n)));
+    FIRED.set(false);
+}
+
+// 落ちる場所を外し、落ちていたら true を返す
+pub fn disarm() -> bool {
+    ARMED.set(None);
+    FIRED.replace(false)
+}
+
+pub fn hit(point: Point) -> io::Result<()> {
+    match ARMED.get() {
+        Some((armed, 0)) if armed == point => {
+            ARMED.set(None);
+            FIRED.set(true);
+            Err(io::Error::other(format!("crashed at {:?}", point)))
+        }
+        Some((armed, n)) if armed == point => {
+            ARMED.set(Some((armed, n - 1)));
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+// 一時ファイルのデータベースを落としては開き直す
+pub struct Harness {
+    path: PathBuf,
+    settings: Settings,
+}
+
+impl Harness {
+    pub fn new(pool_size: usize) -> Self {
+        Self {
+            path: temp_path("crash.db"),
+            settings: Settings {
+                buffer_pool_size: pool_size,
+                ..Settings::default()
+            },
+        }
+    }
+
+    pub fn open(&self) -> Result<Database, Error> {
+        Database::open_with(&self.path, self.settings.clone())
+    }
+
+    // 閉じずに落ちたことにして、fsync したと分かっていないログを失う
+    pub fn crash(&self, db: Database) {
+        if let Some(flushed) = db.crash() {
+            wal::lose_unflushed(&self.wal_dir(), flushed).unwrap();
+        }
+    }
+
+    // workload を point の n 回目で落とし、開き直して回復する。落ちる前に終わった workload もそこで落とす。
+    // 開き直すところの point で落ちれば、もう一度開き直す。
+    // 落ちた以外の理由で workload が失敗したら panic する。回復したデータベースと、落ちたかどうかを返す
+    pub fn run(
+        &self,
+        point: Point,
+        n: usize,
+        workload: impl FnOnce(&Database) -> Result<(), Error>,
+    ) -> (Database, bool) {
+        let db = self.open().unwrap();
+        arm(point, n);
+        let result = workload(&db);
+        self.crash(db);
+        let reopened = self.open();
+        let crashed = disarm();
+        if let Err(err) = result {
+            assert!(crashed, "workload failed without crashing: {}", err);
+        }
+        let db = match reopened {
+            Ok(db) => db,
+            Err(err) => {
+                assert!(crashed, "recovery failed without crashing: {}", err);
+                self.open().unwrap()
+            }
+        };
+        (db, crashed)
+    }
+
+    fn wal_dir(&self) -> PathBuf {
+        let mut dir = self.path.canonicalize().unwrap().into_os_string();
+        dir.push("-wal");
+        PathBuf::from(dir)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(self.wal_dir());
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use crate::types::Value;
+
+    const TXNS: i64 = 12;
+
+    fn row(i: i64) -> [Value; 2] {
+        [
+            Value::Integer(i),
+            Value::Text(format!("v{}", i % 3).repeat(150)),
+        ]
+    }
+
+    // 行の大きいテーブルにバッファより多くのページを書き、4 つに 1 つは取り消す。途中で別のテーブルを作る。
+    // 最後まで終わらないトランザクションが、前からある行を消して新しい行も入れる。
+    // acked にはコミットが返った行を足し、pending には実行中の行を置く。created はテーブルを作り終えたら立てる
+    fn workload(
+        db: &Database,
+        acked: &mut Vec<i64>,
+        pending: &mut Option<i64>,
+        created: &mut bool,
+    ) -> Result<(), Error> {
+        let mut long = db.connect();
+        let mut conn = db.connect();
+        let result = (|| {
+            long.begin()?;
+            long.execute("DELETE FROM t WHERE a = 0", &[])?;
+            long.execute("INSERT INTO t VALUES ($1, $2)", &row(1000))?;
+            for i in 1..=TXNS {
+                if i % 4 == 3 {
+                    conn.begin()?;
+                    conn.execute("INSERT INTO t VALUES ($1, $2)", &row(i))?;
+                    conn.rollback()?;
+                } else {
+                    *pending = Some(i);
+                    conn.execute("INSERT INTO t VALUES ($1, $2)", &row(i))?;
+                    acked.push(i);
+                    *pending = None;
+                }
+                if i == TXNS / 2 {
+                    conn.execute_batch("CREATE TABLE u (a INTEGER); INSERT INTO u VALUES (1)")?;
+                    *created = true;
+                }
+            }
+            Ok(())
+        })();
+        // 落ちたのでトランザクションは取り消さない
+        std::mem::forget(long);
+        std::mem::forget(conn);
+        result
+    }
+
+    fn setup(harness: &Harness) {
+        let mut conn = harness.open().unwrap().connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT);
+             CREATE INDEX t_b ON t (b)",
+        )
+        .unwrap();
+        conn.execute("INSERT INTO t VALUES ($1, $2)", &row(0))
+            .unwrap();
+    }
+
+    fn keys(conn: &mut Connection, sql: &str) -> Vec<i64> {
+        conn.query(sql, &[])
+            .unwrap()
+            .map(|row| row.get::<i64>(0).unwrap())
+            .collect()
+    }
+
+    // コミットが返ったものは残り、取り消したものと終わらなかったものは残らない。
+    // 実行中だった行は、コミットのレコードがディスクに届いていれば残る
+    fn check(db: &Database, acked: &[i64], pending: Option<i64>, created: bool, what: &str) {
+        let mut conn = db.connect();
+        let rows = keys(&mut conn, "SELECT a FROM t ORDER BY a");
+        let mut expected = [&[0], acked].concat();
+        if let Some(i) = pending.filter(|i| rows.contains(i)) {
+            expected.push(i);
+        }
+        assert_eq!(rows, expected, "{}", what);
+        // インデックスも同じ行を指す
+        let indexed = keys(
+            &mut conn,
+            "SELECT /*+ IndexScan(t t_b) */ a FROM t WHERE b >= '' ORDER BY a",
+        );
+        assert_eq!(indexed, rows, "{}", what);
+        assert_eq!(
+            db.connect().catalog().table("t").unwrap().row_count(),
+            rows.len(),
+            "{}",
+            what
+        );
+        if created {
+            assert_eq!(keys(&mut conn, "SELECT a FROM u"), [1], "{}", what);
+        }
+    }
+
+    #[test]
+    fn test_crash_recovery() {
+        for point in POINTS {
+            for n in 0.. {
+                let harness = Harness::new(4);
+                setup(&harness);
+                let (mut acked, mut pending, mut created) = (vec![], None, false);
+                let (db, crashed) = harness.run(point, n, |db| {
+                    workload(db, &mut acked, &mut pending, &mut created)
+                });
+                let what = format!("{:?} #{}", point, n);
+
+                check(&db, &acked, pending, created, &what);
+
+                // 回復のあとにもう一度落ちても同じ状態に戻る
+                let rows = keys(&mut db.connect(), "SELECT a FROM t ORDER BY a");
+                harness.crash(db);
+                let db = harness.open().unwrap();
+                let again = keys(&mut db.connect(), "SELECT a FROM t ORDER BY a");
+                assert_eq!(again, rows, "{}", what);
+                drop(db);
+                if !crashed {
+                    assert_eq!(acked.len() as i64, TXNS - TXNS / 4);
+                    assert!(n > 0, "{:?} is never reached", point);
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_group_commit_crash() {
+        let harness = Harness::new(16);
+        setup(&harness);
+        let db = harness.open().unwrap();
+        db.set_group_commit(true);
+        let mut conn = db.connect();
+        conn.execute("INSERT INTO t VALUES ($1, $2)", &row(1))
+            .unwrap();
+        assert!(db.unflushed_commits());
+        db.flush_commits().unwrap();
+        assert!(!db.unflushed_commits());
+        // flush する前に落ちれば、まだ応答していないコミットは失われる
+        conn.execute("INSERT INTO t VALUES ($1, $2)", &row(2))
+            .unwrap();
+        std::mem::forget(conn);
+        harness.crash(db);
+        let db = harness.open().unwrap();
+        let mut conn = db.connect();
+        assert_eq!(keys(&mut conn, "SELECT a FROM t ORDER BY a"), [0, 1]);
+    }
+}
@@ -0,0 +1,813 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use crate::clock;
+use crate::decimal::Decimal;
+
+// 日付と時刻。タイムゾーンは持たない
+//
+// Date は 1970-01-01 からの日数、Time は 0 時からのマイクロ秒、Timestamp は 1970-01-01 0 時からのマイクロ秒で持つ。
+// 年は 1 から 9999 まで。文字列は '2024-01-15'、'13:45:00.5'、'2024-01-15 13:45:00' の形で読み書きする。
+// Interval は月と日とマイクロ秒に分けて持ち、月と日の長さは足す相手の日付で決まる。
+// 比べるときは 1 か月を 30 日、1 日を 24 時間として比べるので、'1 mon' と '30 days' は等しい
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+const MIN_YEAR: i32 = 1;
+const MAX_YEAR: i32 = 9999;
+// 0001-01-01 と 9999-12-31 の日数
+const MIN_DAYS: i64 = -719_162;
+const MAX_DAYS: i64 = 2_932_896;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date(i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time(i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Interval {
+    months: i32,
+    days: i32,
+    micros: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDatetimeError;
+
+impl fmt::Display for ParseDatetimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid date or time")
+    }
+}
+
+// EXTRACT と DATE_TRUNC に渡す単位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Year,
+    Quarter,
+    Month,
+    // ISO 8601 の週。月曜から始まる
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    // 日曜を 0 とする曜日
+    Dow,
+    // 年の初めからの日数。1 月 1 日は 1
+    Doy,
+    // 1970-01-01 0 時からの秒数
+    Epoch,
+}
+
+impl Field {
+    pub fn parse(name: &str) -> Option<Field> {
+        let field = match name.to_lowercase().as_str() {
+            "year" | "years" => Field::Year,
+            "quarter" => Field::Quarter,
+            "month" | "months" => Field::Month,
+            "week" | "weeks" => Field::Week,
+            "day" | "days" => Field::Day,
+            "hour" | "hours" => Field::Hour,
+            "minute" | "minutes" => Field::Minute,
+            "second" | "seconds" => Field::Second,
+            "dow" => Field::Dow,
+            "doy" => Field::Doy,
+            "epoch" => Field::Epoch,
+            _ => return None,
+        };
+        Some(field)
+    }
+}
+
+fn is_leap(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// 暦の日付から 1970-01-01 からの日数へ。400 年の周期に分けて数える
+fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+    let y = year as i64 - (month <= 2) as i64;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = (yoe + era * 400 + (month <= 2) as i64) as i32;
+    (year, month, day)
+}
+
+impl Date {
+    pub fn from_ymd(year: i32, month: u32, day: u32) -> Option<Date> {
+        let valid = (MIN_YEAR..=MAX_YEAR).contains(&year)
+            && (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day);
+        valid.then(|| Date(days_from_civil(year, month, day) as i32))
+    }
+
+    // 1970-01-01 からの日数から。年の範囲を外れたら None
+    pub fn from_days(days: i64) -> Option<Date> {
+        (MIN_DAYS..=MAX_DAYS)
+            .contains(&days)
+            .then_some(Date(days as i32))
+    }
+
+    pub fn days(self) -> i32 {
+        self.0
+    }
+
+    pub fn ymd(self) -> (i32, u32, u32) {
+        civil_from_days(self.0 as i64)
+    }
+
+    // 日曜を 0 とする曜日
+    pub fn weekday(self) -> u32 {
+        // 1970-01-01 は木曜
+        (self.0 as i64 + 4).rem_euclid(7) as u32
+    }
+
+    pub fn ordinal(self) -> u32 {
+        let (year, ..) = self.ymd();
+        (self.0 as i64 - days_from_civil(year, 1, 1) + 1) as u32
+    }
+
+    // ISO 8601 の週番号。その週の木曜が入る年の週として数える
+    fn iso_week(self) -> u32 {
+        let monday = self.0 as i64 - (self.weekday() as i64 + 6) % 7;
+        let thursday = Date((monday + 3) as i32);
+        (thursday.ordinal() - 1) / 7 + 1
+    }
+
+    pub fn checked_add_days(self, days: i64) -> Option<Date> {
+        Date::from_days((self.0 as i64).checked_add(days)?)
+    }
+
+    pub fn and_time(self, time: Time) -> Timestamp {
+        Timestamp(self.0 as i64 * MICROS_PER_DAY + time.0)
+    }
+}
+
+impl Time {
+    pub fn from_hms_micro(hour: u32, minute: u32, second: u32, micro: u32) -> Option<Time> {
+        let valid = hour < 24 && minute < 60 && second < 60 && (micro as i64) < MICROS_PER_SECOND;
+        valid.then(|| {
+            Time(
+                ((hour as i64 * 60 + minute as i64) * 60 + second as i64) * MICROS_PER_SECOND
+                    + micro as i64,
+            )
+        })
+    }
+
+    pub fn from_micros(micros: i64) -> Option<Time> {
+        (0..MICROS_PER_DAY)
+            .contains(&micros)
+            .then_some(Time(micros))
+    }
+
+    // 0 時からのマイクロ秒
+    pub fn micros(self) -> i64 {
+        self.0
+    }
+
+    pub fn hms_micro(self) -> (u32, u32, u32, u32) {
+        let seconds = self.0 / MICROS_PER_SECOND;
+        (
+            (seconds / 3600) as u32,
+            (seconds / 60 % 60) as u32,
+            (seconds % 60) as u32,
+            (self.0 % MICROS_PER_SECOND) as u32,
+        )
+    }
+
+    // 日付のない単位なら None
+    pub fn extract(self, field: Field) -> Option<Decimal> {
+        let (hour, minute, ..) = self.hms_micro();
+        match field {
+            Field::Hour => Decimal::from_i64(hour as i64),
+            Field::Minute => Decimal::from_i64(minute as i64),
+            Field::Second => Decimal::new((self.0 % (60 * MICROS_PER_SECOND)) as i128, 6),
+            Field::Epoch => Decimal::new(self.0 as i128, 6),
+            _ => None,
+        }
+    }
+}
+
+impl Timestamp {
+    // 年の範囲を外れたら None
+    pub fn from_micros(micros: i64) -> Option<Timestamp> {
+        Date::from_days(micros.div_euclid(MICROS_PER_DAY))?;
+        Some(Timestamp(micros))
+    }
+
+    pub fn now() -> Timestamp {
+        let elapsed = clock::system_now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock is before 1970");
+        Timestamp(elapsed.as_micros() as i64)
+    }
+
+    // 1970-01-01 0 時からのマイクロ秒
+    pub fn micros(self) -> i64 {
+        self.0
+    }
+
+    pub fn date(self) -> Date {
+        Date(self.0.div_euclid(MICROS_PER_DAY) as i32)
+    }
+
+    pub fn time(self) -> Time {
+        Time(self.0.rem_euclid(MICROS_PER_DAY))
+    }
+
+    pub fn extract(self, field: Field) -> Option<Decimal> {
+        let date = self.date();
+        let (year, month, day) = date.ymd();
+        let n = match field {
+            Field::Year => year as i64,
+            Field::Quarter => ((month - 1) / 3 + 1) as i64,
+            Field::Month => month as i64,
+            Field::Week => date.iso_week() as i64,
+            Field::Day => day as i64,
+            Field::Dow => date.weekday() as i64,
+            Field::Doy => date.ordinal() as i64,
+            Field::Epoch => return Decimal::new(self.0 as i128, 6),
+            Field::Hour | Field::Minute | Field::Second => return self.time().extract(field),
+        };
+        Decimal::from_i64(n)
+    }
+
+    // field より細かい単位を切り捨てる。週は月曜に、四半期はその最初の月に揃える
+    pub fn trunc(self, field: Field) -> Option<Timestamp> {
+        let date = self.date();
+        let (year, month, _) = date.ymd();
+        let date = match field {
+            Field::Year => Date::from_ymd(year, 1, 1)?,
+            Field::Quarter => Date::from_ymd(year, (month - 1) / 3 * 3 + 1, 1)?,
+            Field::Month => Date::from_ymd(year, month, 1)?,
+            Field::Week => date.checked_add_days(-(((date.weekday() + 6) % 7) as i64))?,
+            Field::Day => date,
+            Field::Hour | Field::Minute | Field::Second => {
+                let unit = match field {
+                    Field::Hour => 3600 * MICROS_PER_SECOND,
+                    Field::Minute => 60 * MICROS_PER_SECOND,
+                    _ => MICROS_PER_SECOND,
+                };
+                return Some(Timestamp(self.0 - self.0.rem_euclid(unit)));
+            }
+            Field::Dow | Field::Doy | Field::Epoch => return None,
+        };
+        Some(Timestamp::from(date))
+    }
+}
+
+impl Timestamp {
+    // 月を足して日が月末を越えたら月末にし、そのあと日とマイクロ秒を足す
+    pub fn checked_add_interval(self, interval: Interval) -> Option<Timestamp> {
+        let (year, month, day) = self.date().ymd();
+        let months = (year as i64 * 12 + month as i64 - 1).checked_add(interval.months as i64)?;
+        let year = i32::try_from(months.div_euclid(12)).ok()?;
+        let month = months.rem_euclid(12) as u32 + 1;
+        if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+            return None;
+        }
+        let date = Date::from_ymd(year, month, day.min(days_in_month(year, month)))?
+            .checked_add_days(interval.days as i64)?;
+        Timestamp::from_micros(date.and_time(self.time()).0.checked_add(interval.micros)?)
+    }
+
+    // start からの経過。日とマイクロ秒に分け、月は使わない
+    pub fn since(self, start: Timestamp) -> Interval {
+        let micros = self.0 - start.0;
+        Interval {
+            months: 0,
+            days: (micros / MICROS_PER_DAY) as i32,
+            micros: micros % MICROS_PER_DAY,
+        }
+    }
+
+    // start からこの時刻までに越えた field の区切りの数
+    pub fn boundaries_since(self, start: Timestamp, field: Field) -> Option<i64> {
+        let ((y1, m1, _), (y2, m2, _)) = (start.date().ymd(), self.date().ymd());
+        let months = (y2 as i64 * 12 + m2 as i64) - (y1 as i64 * 12 + m1 as i64);
+        let n = match field {
+            Field::Year => (y2 - y1) as i64,
+            Field::Quarter => {
+                (y2 as i64 * 4 + (m2 as i64 - 1) / 3) - (y1 as i64 * 4 + (m1 as i64 - 1) / 3)
+            }
+            Field::Month => months,
+            Field::Week | Field::Day | Field::Hour | Field::Minute | Field::Second => {
+                let unit = match field {
+                    Field::Week => 7 * MICROS_PER_DAY,
+                    Field::Day => MICROS_PER_DAY,
+                    Field::Hour => 3600 * MICROS_PER_SECOND,
+                    Field::Minute => 60 * MICROS_PER_SECOND,
+                    _ => MICROS_PER_SECOND,
+                };
+                (self.trunc(field)?.0 - start.trunc(field)?.0) / unit
+            }
+            Field::Dow | Field::Doy | Field::Epoch => return None,
+        };
+        Some(n)
+    }
+}
+
+impl Time {
+    // 間隔の日と月は無視し、24 時を越えたら 0 時に戻る
+    pub fn wrapping_add_interval(self, interval: Interval) -> Time {
+        Time((self.0 + interval.micros.rem_euclid(MICROS_PER_DAY)).rem_euclid(MICROS_PER_DAY))
+    }
+}
+
+impl Interval {
+    pub fn new(months: i32, days: i32, micros: i64) -> Interval {
+        Interval {
+            months,
+            days,
+            micros,
+        }
+    }
+
+    pub fn months(self) -> i32 {
+        self.months
+    }
+
+    pub fn days(self) -> i32 {
+        self.days
+    }
+
+    pub fn micros(self) -> i64 {
+        self.micros
+    }
+
+    // 1 か月を 30 日、1 日を 24 時間としたマイクロ秒。比べるときに使う
+    pub fn total_micros(self) -> i128 {
+        (self.months as i128 * 30 + self.days as i128) * MICROS_PER_DAY as i128
+            + self.micros as i128
+    }
+
+    // total_micros から戻す。30 日ごとに 1 か月、24 時間ごとに 1 日にまとめる
+    pub fn from_total_micros(total: i128) -> Option<Interval> {
+        let day = MICROS_PER_DAY as i128;
+        Some(Interval {
+            months: i32::try_from(total / (30 * day)).ok()?,
+            days: (total % (30 * day) / day) as i32,
+            micros: (total % day) as i64,
+        })
+    }
+
+    pub fn checked_add(self, other: Interval) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_add(other.months)?,
+            days: self.days.checked_add(other.days)?,
+            micros: self.micros.checked_add(other.micros)?,
+        })
+    }
+
+    pub fn checked_neg(self) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_neg()?,
+            days: self.days.checked_neg()?,
+            micros: self.micros.checked_neg()?,
+        })
+    }
+
+    // 各部分を factor 倍する。月と日の端数は 1 か月 30 日、1 日 24 時間で下の単位に回す
+    pub fn checked_mul(self, factor: f64) -> Option<Interval> {
+        from_parts(
+            self.months as f64 * factor,
+            self.days as f64 * factor,
+            self.micros as f64 * factor,
+        )
+    }
+}
+
+// 端数のある月と日とマイクロ秒から。範囲を外れたら None
+fn from_parts(months: f64, days: f64, micros: f64) -> Option<Interval> {
+    let whole = |f: f64, max: f64| (f.is_finite() && f.trunc().abs() <= max).then(|| f.trunc());
+    let m = whole(months, i32::MAX as f64)?;
+    let days = days + (months - m) * 30.0;
+    let d = whole(days, i32::MAX as f64)?;
+    let micros = (micros + (days - d) * MICROS_PER_DAY as f64).round();
+    Some(Interval {
+        months: m as i32,
+        days: d as i32,
+        micros: whole(micros, i64::MAX as f64)? as i64,
+    })
+}
+
+impl From<Date> for Timestamp {
+    fn from(date: Date) -> Self {
+        date.and_time(Time(0))
+    }
+}
+
+// 数字だけの並びを読む。min 桁から max 桁まで
+fn number(s: &mut &str, min: usize, max: usize) -> Result<u32, ParseDatetimeError> {
+    let len = s.bytes().take_while(u8::is_ascii_digit).count();
+    if len < min || len > max {
+        return Err(ParseDatetimeError);
+    }
+    let (digits, rest) = s.split_at(len);
+    *s = rest;
+    digits.parse().map_err(|_| ParseDatetimeError)
+}
+
+fn expect(s: &mut &str, c: char) -> Result<(), ParseDatetimeError> {
+    *s = s.strip_prefix(c).ok_or(ParseDatetimeError)?;
+    Ok(())
+}
+
+fn parse_date(s: &mut &str) -> Result<Date, ParseDatetimeError> {
+    let year = number(s, 4, 4)?;
+    expect(s, '-')?;
+    let month = number(s, 1, 2)?;
+    expect(s, '-')?;
+    let day = number(s, 1, 2)?;
+    Date::from_ymd(year as i32, month, day).ok_or(ParseDatetimeError)
+}
+
+// HH:MM[:SS[.ffffff]]
+fn parse_time(s: &mut &str) -> Result<Time, ParseDatetimeError> {
+    let hour = number(s, 1, 2)?;
+    expect(s, ':')?;
+    let minute = number(s, 2, 2)?;
+    let (mut second, mut micro) = (0, 0);
+    if expect(s, ':').is_ok() {
+        second = number(s, 2, 2)?;
+        if expect(s, '.').is_ok() {
+            let len = s.bytes().take_while(u8::is_ascii_digit).count();
+            micro = number(s, 1, 6)? * 10u32.pow(6 - len as u32);
+        }
+    }
+    Time::from_hms_micro(hour, minute, second, micro).ok_or(ParseDatetimeError)
+}
+
+fn parse_all<T>(
+    s: &str,
+    parse: impl FnOnce(&mut &str) -> Result<T, ParseDatetimeError>,
+) -> Result<T, ParseDatetimeError> {
+    let mut rest = s.trim();
+    let value = parse(&mut rest)?;
+    if !rest.is_empty() {
+        return Err(ParseDatetimeError);
+    }
+    Ok(value)
+}
+
+impl FromStr for Date {
+    type Err = ParseDatetimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_all(s, parse_date)
+    }
+}
+
+impl FromStr for Time {
+    type Err = ParseDatetimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_all(s, parse_time)
+    }
+}
+
+// 時刻を省くと 0 時。日付と時刻のあいだは空白か T
+impl FromStr for Timestamp {
+    type Err = ParseDatetimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_all(s, |s| {
+            let date = parse_date(s)?;
+            let Some(rest) = s.strip_prefix([' ', 'T']) else {
+                return Ok(Timestamp::from(date));
+            };
+            *s = rest.trim_start();
+            Ok(date.and_time(parse_time(s)?))
+        })
+    }
+}
+
+// '1 year 2 months 3 days 04:05:06' のように数と単位を並べたもの。単位は複数形と略記も読み、
+// 数には符号と小数を書ける。時刻の形の部分は時と分と秒で、末尾の ago は全体の符号を反転する
+impl FromStr for Interval {
+    type Err = ParseDatetimeError;
+
+    fn from_str(s: &str) -> Result<Interval, ParseDatetimeError> {
+        let s = s.trim().to_lowercase();
+        let (s, ago) = match s.strip_suffix("ago") {
+            Some(rest) => (rest.trim_end(), true),
+            None => (s.as_str(), false),
+        };
+        let mut tokens = s.split_whitespace().peekable();
+        if tokens.peek().is_none() {
+            return Err(ParseDatetimeError);
+        }
+        let (mut months, mut days, mut micros) = (0.0, 0.0, 0.0);
+        while let Some(token) = tokens.next() {
+            if token.contains(':') {
+                micros += parse_interval_time(token)? as f64;
+                continue;
+            }
+            let split = token
+                .find(|c: char| c.is_ascii_alphabetic())
+                .unwrap_or(token.len());
+            let n: f64 = token[..split].parse().map_err(|_| ParseDatetimeError)?;
+            let unit = match &token[split..] {
+                "" => tokens.next().ok_or(ParseDatetimeError)?,
+                unit => unit,
+            };
+            match unit {
+                "year" | "years" | "y" | "yr" | "yrs" => months += n * 12.0,
+                "month" | "months" | "mon" | "mons" => months += n,
+                "week" | "weeks" | "w" => days += n * 7.0,
+                "day" | "days" | "d" => days += n,
+                "hour" | "hours" | "h" | "hr" | "hrs" => micros += n * 3600e6,
+                "minute" | "minutes" | "min" | "mins" | "m" => micros += n * 60e6,
+                "second" | "seconds" | "sec" | "secs" | "s" => micros += n * 1e6,
+                "millisecond" | "milliseconds" | "ms" | "msec" => micros += n * 1e3,
+                "microsecond" | "microseconds" | "us" | "usec" => micros += n,
+                _ => return Err(ParseDatetimeError),
+            }
+        }
+        let interval = from_parts(months, days, micros).ok_or(ParseDatetimeError)?;
+        if ago {
+            interval.checked_neg().ok_or(ParseDatetimeError)
+        } else {
+            Ok(interval)
+        }
+    }
+}
+
+// [-]h:mm[:ss[.ffffff]] のマイクロ秒。時は 24 を越えてもよい
+fn parse_interval_time(token: &str) -> Result<i64, ParseDatetimeError> {
+    let (negative, mut s) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token.strip_prefix('+').unwrap_or(token)),
+    };
+    let hour = number(&mut s, 1, 9)? as i64;
+    expect(&mut s, ':')?;
+    let minute = number(&mut s, 2, 2)? as i64;
+    let mut micros = (hour * 60 + minute) * 60 * MICROS_PER_SECOND;
+    if expect(&mut s, ':').is_ok() {
+        micros += number(&mut s, 2, 2)? as i64 * MICROS_PER_SECOND;
+        if expect(&mut s, '.').is_ok() {
+            let len = s.bytes().take_while(u8::is_ascii_digit).count();
+            micros += number(&mut s, 1, 6)? as i64 * 10i64.pow(6 - len as u32);
+        }
+    }
+    if minute >= 60 || !s.is_empty() {
+        return Err(ParseDatetimeError);
+    }
+    Ok(if negative { -micros } else { micros })
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+// 秒の端数は末尾の 0 を除いて書く
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (hour, minute, second, micro) = self.hms_micro();
+        write!(f, "{:02}:{:02}:{:02}", hour, minute, second)?;
+        if micro > 0 {
+            let fraction = format!("{:06}", micro);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.date(), self.time())
+    }
+}
+
+// '1 year 2 mons 3 days 04:05:06.5' の形。0 の部分は書かず、すべて 0 なら 00:00:00
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        let mut part = |n: i64, unit: &str, plural: &str| {
+            if n != 0 {
+                parts.push(format!("{} {}", n, if n == 1 { unit } else { plural }));
+            }
+        };
+        part(self.months as i64 / 12, "year", "years");
+        part(self.months as i64 % 12, "mon", "mons");
+        part(self.days as i64, "day", "days");
+        if self.micros != 0 || parts.is_empty() {
+            let sign = if self.micros < 0 { "-" } else { "" };
+            let micros = self.micros.unsigned_abs();
+            let seconds = micros / MICROS_PER_SECOND as u64;
+            let mut time = format!(
+                "{}{:02}:{:02}:{:02}",
+                sign,
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            );
+            let fraction = micros % MICROS_PER_SECOND as u64;
+            if fraction > 0 {
+                time.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+            }
+            parts.push(time);
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> Timestamp {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_display() {
+        for s in [
+            "2024-01-15",
+            "1970-01-01",
+            "0001-01-01",
+            "9999-12-31",
+            "2000-02-29",
+        ] {
+            assert_eq!(s.parse::<Date>().unwrap().to_string(), s);
+        }
+        assert_eq!("1970-01-02".parse::<Date>().unwrap().days(), 1);
+        assert_eq!("1969-12-31".parse::<Date>().unwrap().days(), -1);
+        assert_eq!(days_from_civil(1, 1, 1), MIN_DAYS);
+        assert_eq!(days_from_civil(9999, 12, 31), MAX_DAYS);
+        for s in [
+            "2023-02-29",
+            "2024-13-01",
+            "24-01-01",
+            "2024-01-15x",
+            "0000-01-01",
+        ] {
+            assert_eq!(s.parse::<Date>(), Err(ParseDatetimeError), "{}", s);
+        }
+        assert_eq!("13:45".parse::<Time>().unwrap().to_string(), "13:45:00");
+        assert_eq!(
+            "7:05:09.25".parse::<Time>().unwrap().to_string(),
+            "07:05:09.25"
+        );
+        assert_eq!("24:00".parse::<Time>(), Err(ParseDatetimeError));
+        assert_eq!(ts("2024-01-15").to_string(), "2024-01-15 00:00:00");
+        assert_eq!(
+            ts(" 2024-01-15T13:45:00.000001 ").to_string(),
+            "2024-01-15 13:45:00.000001"
+        );
+        assert_eq!(ts("1969-12-31 23:59:59").micros(), -MICROS_PER_SECOND);
+    }
+
+    #[test]
+    fn test_calendar() {
+        let date = "2024-03-01".parse::<Date>().unwrap();
+        assert_eq!(date.checked_add_days(-1).unwrap().to_string(), "2024-02-29");
+        assert_eq!(date.weekday(), 5);
+        assert_eq!(date.ordinal(), 61);
+        assert_eq!(
+            "9999-12-31".parse::<Date>().unwrap().checked_add_days(1),
+            None
+        );
+        // 2021-01-03 は 2020 年の第 53 週
+        let sunday = "2021-01-03".parse::<Date>().unwrap();
+        assert_eq!(sunday.iso_week(), 53);
+        assert_eq!(sunday.checked_add_days(1).unwrap().iso_week(), 1);
+    }
+
+    #[test]
+    fn test_extract_trunc() {
+        let t = ts("2024-08-17 13:45:30.5");
+        let extract = |field| t.extract(field).unwrap().to_string();
+        assert_eq!(extract(Field::Year), "2024");
+        assert_eq!(extract(Field::Quarter), "3");
+        assert_eq!(extract(Field::Week), "33");
+        assert_eq!(extract(Field::Dow), "6");
+        assert_eq!(extract(Field::Doy), "230");
+        assert_eq!(extract(Field::Second), "30.500000");
+        assert_eq!(extract(Field::Epoch), "1723902330.500000");
+        assert_eq!(t.time().extract(Field::Year), None);
+
+        let trunc = |field| t.trunc(field).unwrap().to_string();
+        assert_eq!(trunc(Field::Year), "2024-01-01 00:00:00");
+        assert_eq!(trunc(Field::Quarter), "2024-07-01 00:00:00");
+        assert_eq!(trunc(Field::Week), "2024-08-12 00:00:00");
+        assert_eq!(trunc(Field::Hour), "2024-08-17 13:00:00");
+        assert_eq!(trunc(Field::Second), "2024-08-17 13:45:30");
+        assert_eq!(t.trunc(Field::Dow), None);
+        assert_eq!(
+            ts("1969-12-31 23:59:59.5")
+                .trunc(Field::Minute)
+                .unwrap()
+                .to_string(),
+            "1969-12-31 23:59:00"
+        );
+    }
+
+    #[test]
+    fn test_interval() {
+        let iv = |s: &str| s.parse::<Interval>().unwrap();
+        for (s, expected) in [
+            ("3 days", "3 days"),
+            (
+                "1 year 2 months 3 days 04:05:06.5",
+                "1 year 2 mons 3 days 04:05:06.5",
+            ),
+            ("90 minutes", "01:30:00"),
+            ("1.5 days", "1 day 12:00:00"),
+            ("-1 week 2H", "-7 days 02:00:00"),
+            ("1 mon ago", "-1 mons"),
+            ("-25:00", "-25:00:00"),
+            ("0 s", "00:00:00"),
+            ("250 ms", "00:00:00.25"),
+        ] {
+            assert_eq!(iv(s).to_string(), expected, "{}", s);
+            assert_eq!(iv(expected), iv(s), "{}", s);
+        }
+        for s in ["", "3", "3 fortnights", "1:5", "days 3", "1:00:00.5x"] {
+            assert_eq!(s.parse::<Interval>(), Err(ParseDatetimeError), "{}", s);
+        }
+        assert_eq!(iv("1 mon").total_micros(), iv("30 days").total_micros());
+        let total = iv("1 year 40 days 25:00:00").total_micros();
+        assert_eq!(
+            Interval::from_total_micros(total).unwrap().to_string(),
+            "1 year 1 mon 11 days 01:00:00"
+        );
+        assert_eq!(
+            iv("1 mon 1 day").checked_mul(0.5).unwrap().to_string(),
+            "15 days 12:00:00"
+        );
+
+        // 月末を越える日は月末にする
+        let add = |t: &str, i: &str| ts(t).checked_add_interval(iv(i)).unwrap().to_string();
+        assert_eq!(add("2024-01-31 10:00", "1 mon"), "2024-02-29 10:00:00");
+        assert_eq!(add("2024-03-31", "-1 mon 1 day"), "2024-03-01 00:00:00");
+        assert_eq!(add("2024-12-31 23:00", "2 hours"), "2025-01-01 01:00:00");
+        assert_eq!(ts("9999-12-31").checked_add_interval(iv("1 day")), None);
+        assert_eq!(
+            ts("2024-03-02 06:00")
+                .since(ts("2024-02-28 12:00"))
+                .to_string(),
+            "2 days 18:00:00"
+        );
+        assert_eq!(
+            "23:30"
+                .parse::<Time>()
+                .unwrap()
+                .wrapping_add_interval(iv("1 day 01:00"))
+                .to_string(),
+            "00:30:00"
+        );
+
+        let end = ts("2025-01-01 00:00");
+        let start = ts("2024-12-31 23:59:59");
+        for (field, expected) in [
+            (Field::Year, 1),
+            (Field::Quarter, 1),
+            (Field::Month, 1),
+            (Field::Day, 1),
+            (Field::Hour, 1),
+            (Field::Second, 1),
+        ] {
+            assert_eq!(
+                end.boundaries_since(start, field),
+                Some(expected),
+                "{:?}",
+                field
+            );
+        }
+        assert_eq!(start.boundaries_since(end, Field::Month), Some(-1));
+        assert_eq!(end.boundaries_since(start, Field::Dow), None);
+    }
+}
@@ -0,0 +1,422 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+// 固定小数点の 10 進数。値は mantissa / 10^scale で、精度は MAX_PRECISION 桁まで
+//
+// 桁を落とすときは 0 から遠いほうに丸める (四捨五入)。
+// 同じ値でも scale が違えば別の表し方になり、比べるときは値で比べる。
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i64,
+    scale: u8,
+}
+
+pub const MAX_PRECISION: u8 = 18;
+// 割り算の結果の scale の下限
+pub const DIVISION_SCALE: u8 = 6;
+const LIMIT: i128 = 10i128.pow(MAX_PRECISION as u32);
+
+fn pow10(n: u8) -> i128 {
+    10i128.pow(n as u32)
+}
+
+// n / d を 0 から遠いほうに丸める
+fn div_round(n: i128, d: i128) -> i128 {
+    let (q, r) = (n / d, n % d);
+    if r.abs() * 2 >= d.abs() {
+        q + if (n < 0) == (d < 0) { 1 } else { -1 }
+    } else {
+        q
+    }
+}
+
+impl Decimal {
+    // 精度に収まらなければ None
+    pub fn new(mantissa: i128, scale: u8) -> Option<Decimal> {
+        (mantissa.abs() < LIMIT && scale <= MAX_PRECISION).then_some(Decimal {
+            mantissa: mantissa as i64,
+            scale,
+        })
+    }
+
+    pub fn from_i64(n: i64) -> Option<Decimal> {
+        Decimal::new(n as i128, 0)
+    }
+
+    // f64 の正確な値を小数点以下 scale 桁に丸める
+    pub fn from_f64(f: f64, scale: u8) -> Option<Decimal> {
+        if !f.is_finite() || f.abs() >= 1e18 || scale > MAX_PRECISION {
+            return None;
+        }
+        let (q, _, half) = scale_f64(f.abs(), scale);
+        let m = (q + half as u128) as i128;
+        Decimal::new(if f < 0.0 { -m } else { m }, scale)
+    }
+
+    pub fn mantissa(self) -> i64 {
+        self.mantissa
+    }
+
+    pub fn scale(self) -> u8 {
+        self.scale
+    }
+
+    // 整数部の桁数
+    pub fn integer_digits(self) -> u8 {
+        let digits = self
+            .mantissa
+            .unsigned_abs()
+            .checked_ilog10()
+            .map_or(0, |d| d + 1) as u8;
+        digits.saturating_sub(self.scale)
+    }
+
+    // 小数点以下を scale 桁にする。精度に収まらなければ None
+    pub fn rescale(self, scale: u8) -> Option<Decimal> {
+        if scale > MAX_PRECISION {
+            return None;
+        }
+        let m = self.mantissa as i128;
+        let m = if scale >= self.scale {
+            m * pow10(scale - self.scale)
+        } else {
+            div_round(m, pow10(self.scale - scale))
+        };
+        Decimal::new(m, scale)
+    }
+
+    // 小数点以下の末尾の 0 を除く
+    pub fn normalize(self) -> Decimal {
+        let mut d = self;
+        while d.scale > 0 && d.mantissa % 10 == 0 {
+            d.mantissa /= 10;
+            d.scale -= 1;
+        }
+        d
+    }
+
+    // 0 から遠いほうに丸めた整数
+    pub fn round(self) -> i64 {
+        div_round(self.mantissa as i128, pow10(self.scale)) as i64
+    }
+
+    // 小数点以下 digits 桁に丸める。digits が負なら整数部の下の桁を丸める
+    pub fn round_to(self, digits: i64) -> Option<Decimal> {
+        if digits >= 0 {
+            return self.rescale(u8::try_from(digits).ok()?);
+        }
+        // 精度より多い桁を丸めると 0 になる
+        let k = (-digits).min(MAX_PRECISION as i64 + 1) as u8;
+        let m = div_round(self.mantissa as i128, pow10(self.scale + k));
+        Decimal::new(m * pow10(k), 0)
+    }
+
+    pub fn floor(self) -> Decimal {
+        let m = (self.mantissa as i128).div_euclid(pow10(self.scale));
+        Decimal {
+            mantissa: m as i64,
+            scale: 0,
+        }
+    }
+
+    pub fn ceil(self) -> Decimal {
+        let m = -(-(self.mantissa as i128)).div_euclid(pow10(self.scale));
+        Decimal {
+            mantissa: m as i64,
+            scale: 0,
+        }
+    }
+
+    pub fn is_integer(self) -> bool {
+        self.mantissa as i128 % pow10(self.scale) == 0
+    }
+
+    pub fn to_f64(self) -> f64 {
+        // 文字列から読むと正しく丸められる
+        self.to_string().parse().unwrap()
+    }
+
+    pub fn checked_neg(self) -> Option<Decimal> {
+        Decimal::new(-(self.mantissa as i128), self.scale)
+    }
+
+    // 足し算と引き算の scale は大きいほう
+    pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.aligned(scale), other.aligned(scale));
+        Decimal::new(a + b, scale)
+    }
+
+    pub fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+        self.checked_add(other.checked_neg()?)
+    }
+
+    // 掛け算の scale は両方の和。精度に収まるまで小数点以下を丸める
+    pub fn checked_mul(self, other: Decimal) -> Option<Decimal> {
+        let m = self.mantissa as i128 * other.mantissa as i128;
+        let scale = self.scale + other.scale;
+        let reduce = (scale.saturating_sub(MAX_PRECISION)..=scale)
+            .find(|&k| div_round(m, pow10(k)).abs() < LIMIT)?;
+        Decimal::new(div_round(m, pow10(reduce)), scale - reduce)
+    }
+
+    // 割り算の scale は両方の大きいほうで、DIVISION_SCALE 以上。精度に収まらなければ小数点以下を減らす。
+    // 0 で割ったら None
+    pub fn checked_div(self, other: Decimal) -> Option<Decimal> {
+        if other.mantissa == 0 {
+            return None;
+        }
+        let preferred = self.scale.max(other.scale).max(DIVISION_SCALE);
+        (0..=preferred).rev().find_map(|scale| {
+            // self * 10^(scale - self.scale + other.scale) / other
+            let shift = scale as i32 - self.scale as i32 + other.scale as i32;
+            let (n, d) = if shift >= 0 {
+                let n = (self.mantissa as i128).checked_mul(10i128.checked_pow(shift as u32)?)?;
+                (n, other.mantissa as i128)
+            } else {
+                let d = (other.mantissa as i128).checked_mul(pow10((-shift) as u8))?;
+                (self.mantissa as i128, d)
+            };
+            Decimal::new(div_round(n, d), scale)
+        })
+    }
+
+    // 剰余の符号は割られる数と同じ。0 で割ったら None
+    pub fn checked_rem(self, other: Decimal) -> Option<Decimal> {
+        if other.mantissa == 0 {
+            return None;
+        }
+        let scale = self.scale.max(other.scale);
+        Decimal::new(self.aligned(scale) % other.aligned(scale), scale)
+    }
+
+    // (mantissa / 10^scale) / count。scale は checked_div と同じように決める。平均を求めるのに使う
+    pub fn ratio(mantissa: i128, scale: u8, count: i64) -> Option<Decimal> {
+        if count == 0 {
+            return None;
+        }
+        let preferred = scale.clamp(DIVISION_SCALE, MAX_PRECISION);
+        (0..=preferred).rev().find_map(|s| {
+            let n = if s >= scale {
+                mantissa.checked_mul(pow10(s - scale))?
+            } else {
+                div_round(mantissa, pow10(scale - s))
+            };
+            Decimal::new(div_round(n, count as i128), s)
+        })
+    }
+
+    fn aligned(self, scale: u8) -> i128 {
+        self.mantissa as i128 * pow10(scale - self.scale)
+    }
+
+    pub fn cmp_i64(self, n: i64) -> Ordering {
+        (self.mantissa as i128).cmp(&(n as i128 * pow10(self.scale)))
+    }
+
+    // f64 の値と正確に比べる。NaN はどの数よりも大きい
+    pub fn cmp_f64(self, f: f64) -> Ordering {
+        if f.is_nan() || f >= 1e18 {
+            return Ordering::Less;
+        }
+        if f <= -1e18 {
+            return Ordering::Greater;
+        }
+        let (q, fraction, _) = scale_f64(f.abs(), self.scale);
+        let negative = f < 0.0;
+        let target = if negative { -(q as i128) } else { q as i128 };
+        match (self.mantissa as i128).cmp(&target) {
+            Ordering::Equal if fraction && negative => Ordering::Greater,
+            Ordering::Equal if fraction => Ordering::Less,
+            ordering => ordering,
+        }
+    }
+}
+
+// 1e18 より小さい 0 以上の f * 10^scale の整数部と、小数部が 0 でないか、0.5 以上か
+fn scale_f64(f: f64, scale: u8) -> (u128, bool, bool) {
+    let (m, e) = decompose(f);
+    // f * 10^scale = m * 5^scale * 2^(e + scale) で、1e36 より小さい
+    let p = m as u128 * 5u128.pow(scale as u32);
+    let k = e + scale as i32;
+    if k >= 0 {
+        return (p << k, false, false);
+    }
+    let shift = -k as u32;
+    if shift >= 128 {
+        return (0, p != 0, false);
+    }
+    let rest = p & ((1 << shift) - 1);
+    (p >> shift, rest != 0, rest >= 1 << (shift - 1))
+}
+
+// 有限の f64 を m * 2^e に分ける
+pub(crate) fn decompose(f: f64) -> (u64, i32) {
+    let bits = f.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1 << 52) - 1);
+    if exponent == 0 {
+        (fraction, -1074)
+    } else {
+        (fraction | (1 << 52), exponent - 1075)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        self.aligned(scale).cmp(&other.aligned(scale))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDecimalError;
+
+// [+-]数字[.数字] の形。精度に収まらなければエラー
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(s: &str) -> Result<Decimal, ParseDecimalError> {
+        let (negative, s) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let digits = [integer, fraction].concat();
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseDecimalError);
+        }
+        let significant = digits.trim_start_matches('0');
+        if significant.len() > MAX_PRECISION as usize || fraction.len() > MAX_PRECISION as usize {
+            return Err(ParseDecimalError);
+        }
+        let m = significant.parse::<i128>().unwrap_or(0);
+        Decimal::new(if negative { -m } else { m }, fraction.len() as u8).ok_or(ParseDecimalError)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let digits = format!("{:0>1$}", digits, scale + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        if self.mantissa < 0 {
+            f.write_str("-")?;
+        }
+        f.write_str(integer)?;
+        if scale > 0 {
+            write!(f, ".{}", fraction)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_display() {
+        for s in [
+            "0",
+            "1.50",
+            "-0.05",
+            "123456789012345678",
+            "0.000000000000000001",
+        ] {
+            assert_eq!(d(s).to_string(), s);
+        }
+        assert_eq!(d("+007.10").to_string(), "7.10");
+        assert_eq!(d(".5").to_string(), "0.5");
+        for s in [
+            "",
+            ".",
+            "1e5",
+            "1.2.3",
+            "1234567890123456789",
+            "0.1234567890123456789",
+        ] {
+            assert_eq!(s.parse::<Decimal>(), Err(ParseDecimalError), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(d("0.1").checked_add(d("0.2")).unwrap().to_string(), "0.3");
+        assert_eq!(
+            d("1.5").checked_sub(d("2.25")).unwrap().to_string(),
+            "-0.75"
+        );
+        assert_eq!(
+            d("1.5").checked_mul(d("-0.25")).unwrap().to_string(),
+            "-0.375"
+        );
+        assert_eq!(d("10").checked_div(d("4")).unwrap().to_string(), "2.500000");
+        assert_eq!(d("2").checked_div(d("3")).unwrap().to_string(), "0.666667");
+        assert_eq!(d("-7.5").checked_rem(d("2")).unwrap().to_string(), "-1.5");
+        assert_eq!(d("1").checked_div(d("0.0")), None);
+        assert_eq!(Decimal::ratio(1001, 2, 3).unwrap().to_string(), "3.336667");
+        assert_eq!(Decimal::ratio(-5, 0, 2).unwrap().to_string(), "-2.500000");
+        // 精度に収まるように小数点以下を丸め、整数部が収まらなければ None
+        let big = d("123456789.123456789");
+        assert_eq!(
+            big.checked_mul(d("1.5")).unwrap().to_string(),
+            "185185183.685185184"
+        );
+        assert_eq!(d("1234567890123.5").checked_mul(big), None);
+        assert_eq!(d("999999999999999999").checked_add(d("1")), None);
+    }
+
+    #[test]
+    fn test_rescale() {
+        assert_eq!(d("2.345").rescale(2).unwrap().to_string(), "2.35");
+        assert_eq!(d("-2.345").rescale(2).unwrap().to_string(), "-2.35");
+        assert_eq!(d("2.344").rescale(0).unwrap().to_string(), "2");
+        assert_eq!(d("2.5").rescale(3).unwrap().to_string(), "2.500");
+        assert_eq!(d("12345678901234567").rescale(2), None);
+        assert_eq!(d("-2.5").round(), -3);
+        assert_eq!(d("1.200").normalize().to_string(), "1.2");
+        assert_eq!(d("12.50").integer_digits(), 2);
+        assert_eq!(d("0.5").integer_digits(), 0);
+        assert_eq!(Decimal::from_f64(0.125, 2).unwrap().to_string(), "0.13");
+        assert_eq!(Decimal::from_f64(f64::NAN, 2), None);
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(d("1.50"), d("1.5"));
+        assert!(d("-1.5") < d("-1.49"));
+        assert_eq!(d("3.00").cmp_i64(3), Ordering::Equal);
+        assert_eq!(d("2.99").cmp_i64(3), Ordering::Less);
+        assert_eq!(d("0.5").cmp_f64(0.5), Ordering::Equal);
+        // 0.1 の f64 は 0.1 よりわずかに大きい
+        assert_eq!(d("0.1").cmp_f64(0.1), Ordering::Less);
+        assert_eq!(d("-0.1").cmp_f64(-0.1), Ordering::Greater);
+        assert_eq!(d("0").cmp_f64(-0.0), Ordering::Equal);
+        assert_eq!(d("0.000000000000000001").cmp_f64(1e-300), Ordering::Greater);
+        assert_eq!(d("5").cmp_f64(f64::NAN), Ordering::Less);
+        assert_eq!(d("-5").cmp_f64(-1e300), Ordering::Greater);
+        assert_eq!(d("0.1").to_f64(), 0.1);
+    }
+}
@@ -2,26 +2,195 @@ use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::trace;
+use crate::wal::crc32;
 
 pub const PAGE_SIZE: usize = 4096;
+// ファイル上のページは | ページ LSN (8) | チェックサム (4) | ページ (PAGE_SIZE) | で、
+// LSN はページを最後に変えたログの位置、チェックサムは LSN とページの CRC-32。
+// 読むときはチェックサムを確かめない。書き出しの途中で落ちたページも、回復で当て直せば直るため
+const PAGE_LSN_SIZE: usize = 8;
+const PAGE_HEADER_SIZE: usize = PAGE_LSN_SIZE + 4;
+const DISK_PAGE_SIZE: u64 = (PAGE_HEADER_SIZE + PAGE_SIZE) as u64;
 
-#[derive(Copy, Clone, Eq, Hash, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
 pub struct PageId(pub u64);
 impl PageId {
+    pub const INVALID_PAGE_ID: PageId = PageId(u64::MAX);
+
+    pub fn valid(self) -> Option<PageId> {
+        if self == Self::INVALID_PAGE_ID {
+            None
+        } else {
+            Some(self)
+        }
+    }
+
     pub fn to_u64(self) -> u64 {
         self.0
     }
 }
 
 pub struct DiskManager {
-    heap_file: File,
+    heap_file: Box<dyn DiskBackend>,
     next_page_id: u64,
 }
 
+// ページを置くバイト列の置き場所。ふだんはファイルで、ファイルのない wasm32 ではメモリに置く。
+// ブラウザの IndexedDB のような非同期の置き場所は、中身をメモリに読み込んで開き、sync で書き戻す実装にする
+pub trait DiskBackend: Send {
+    // 書いたバイト数
+    fn size(&self) -> io::Result<u64>;
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    // 末尾を越えて書けば、そこまでを 0 で埋めて伸ばす
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    // それまでに書いたものを落ちても残るようにする
+    fn sync(&mut self) -> io::Result<()>;
+
+    // 次の書き込みに仕掛けた障害
+    fn write_fault(&mut self) -> Option<Fault> {
+        None
+    }
+}
+
+// メモリに置いたディスク。複製は同じ中身を分け合うので、DiskManager を捨ててから同じ中身で開き直せる。
+// inject した障害は、次に当てはまる読み書きで一度だけ起こる
+#[derive(Clone, Default)]
+pub struct MemoryDisk {
+    inner: Arc<Mutex<MemoryFile>>,
+}
+
+#[derive(Default)]
+struct MemoryFile {
+    bytes: Vec<u8>,
+    fault: Option<Fault>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // ページを読めない
+    ReadError,
+    // ページを書けず、何も変わらない
+    WriteError,
+    // ページの前半だけを書いて失敗する。ページ LSN は古いまま残る
+    TornWrite,
+}
+
+impl MemoryDisk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inject(&self, fault: Fault) {
+        self.inner.lock().unwrap().fault = Some(fault);
+    }
+
+    // 起こっていない障害を取り除き、それを返す
+    pub fn clear_fault(&self) -> Option<Fault> {
+        self.inner.lock().unwrap().fault.take()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.inner.lock().unwrap().bytes.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 書いたバイト列から始める。IndexedDB などに保存しておいた中身を開き直すのに使う
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let disk = Self::new();
+        disk.inner.lock().unwrap().bytes = bytes;
+        disk
+    }
+
+    // 今の中身の複製
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.inner.lock().unwrap().bytes.clone()
+    }
+
+    fn take_fault(&self, matches: impl Fn(Fault) -> bool) -> Option<Fault> {
+        let mut file = self.inner.lock().unwrap();
+        file.fault.take_if(|fault| matches(*fault))
+    }
+}
+
+fn fault_error(fault: Fault) -> io::Error {
+    io::Error::other(format!("injected fault: {:?}", fault))
+}
+
+impl DiskBackend for File {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(buf)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(data)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.sync_all()
+    }
+}
+
+impl DiskBackend for MemoryDisk {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if let Some(fault) = self.take_fault(|fault| fault == Fault::ReadError) {
+            return Err(fault_error(fault));
+        }
+        let file = self.inner.lock().unwrap();
+        let start = offset as usize;
+        buf.copy_from_slice(&file.bytes[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut file = self.inner.lock().unwrap();
+        let start = offset as usize;
+        if file.bytes.len() < start + data.len() {
+            file.bytes.resize(start + data.len(), 0);
+        }
+        file.bytes[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_fault(&mut self) -> Option<Fault> {
+        self.take_fault(|fault| fault != Fault::ReadError)
+    }
+}
+
 impl DiskManager {
     pub fn new(heap_file: File) -> io::Result<Self> {
-        let heap_file_size = heap_file.metadata()?.len();
-        let next_page_id = heap_file_size / PAGE_SIZE as u64;
+        Self::with_backend(Box::new(heap_file))
+    }
+
+    pub fn memory(disk: MemoryDisk) -> Self {
+        Self::with_backend(Box::new(disk)).unwrap()
+    }
+
+    pub fn with_backend(heap_file: Box<dyn DiskBackend>) -> io::Result<Self> {
+        let next_page_id = heap_file.size()?.div_ceil(DISK_PAGE_SIZE);
         Ok(Self { heap_file, next_page_id })
     }
 
@@ -30,6 +199,7 @@ impl DiskManager {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(heap_file_path)?;
         Self::new(heap_file)
     }
@@ -40,16 +210,73 @@ impl DiskManager {
         PageId(page_id)
     }
 
-    pub fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> io::Result<()> {
-        let offset = PAGE_SIZE as u64 * page_id.to_u64();
-        self.heap_file.seek(SeekFrom::Start(offset))?;
-        self.heap_file.read_exact(data)
+    // ページを読み、ページ LSN を返す。
+    // 割り当てたあと一度も書き出さないうちに落ちたページは、LSN が 0 の空のページとして読む。
+    // ファイルの末尾で書き出しの途中に落ちたページは、足りない分を 0 として読む
+    pub fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> io::Result<u64> {
+        let _span = trace::span("read_page", &[("page", &page_id.0)]);
+        let offset = DISK_PAGE_SIZE * page_id.to_u64();
+        let len = self.heap_file.size()?;
+        if offset >= len {
+            data.fill(0);
+            self.next_page_id = self.next_page_id.max(page_id.to_u64() + 1);
+            return Ok(0);
+        }
+        let mut buf = vec![0; DISK_PAGE_SIZE as usize];
+        let available = (len - offset).min(DISK_PAGE_SIZE) as usize;
+        self.heap_file.read_at(offset, &mut buf[..available])?;
+        data.copy_from_slice(&buf[PAGE_HEADER_SIZE..]);
+        Ok(u64::from_be_bytes(buf[..PAGE_LSN_SIZE].try_into().unwrap()))
+    }
+
+    // ディスクにあるページのチェックサムが合っているか。一度も書き出していないページは合っているとみなす
+    pub fn verify_page(&mut self, page_id: PageId) -> io::Result<bool> {
+        let offset = DISK_PAGE_SIZE * page_id.to_u64();
+        let len = self.heap_file.size()?;
+        if offset >= len {
+            return Ok(true);
+        }
+        let mut buf = vec![0; DISK_PAGE_SIZE as usize];
+        let available = (len - offset).min(DISK_PAGE_SIZE) as usize;
+        self.heap_file.read_at(offset, &mut buf[..available])?;
+        if buf.iter().all(|&b| b == 0) {
+            return Ok(true);
+        }
+        let checksum = u32::from_be_bytes(buf[PAGE_LSN_SIZE..PAGE_HEADER_SIZE].try_into().unwrap());
+        Ok(crc32(&[&buf[..PAGE_LSN_SIZE], &buf[PAGE_HEADER_SIZE..]]) == checksum)
+    }
+
+    // ファイルにあるページの数
+    pub fn page_count(&self) -> u64 {
+        self.next_page_id
+    }
+
+    // 書き出しの途中で落ちても古いページ LSN が残り、回復で当て直されるように、ページ LSN は後に書く
+    pub fn write_page_data(&mut self, page_id: PageId, lsn: u64, data: &[u8]) -> io::Result<()> {
+        let _span = trace::span("write_page", &[("page", &page_id.0)]);
+        let offset = DISK_PAGE_SIZE * page_id.to_u64();
+        let data_offset = offset + PAGE_HEADER_SIZE as u64;
+        #[cfg(test)]
+        if let Err(e) = crate::crash::hit(crate::crash::Point::PageWrite) {
+            self.heap_file.write_at(data_offset, &data[..data.len() / 2])?;
+            return Err(e);
+        }
+        match self.heap_file.write_fault() {
+            Some(Fault::TornWrite) => {
+                self.heap_file.write_at(data_offset, &data[..data.len() / 2])?;
+                return Err(fault_error(Fault::TornWrite));
+            }
+            Some(fault) => return Err(fault_error(fault)),
+            None => {}
+        }
+        self.heap_file.write_at(data_offset, data)?;
+        let mut header = lsn.to_be_bytes().to_vec();
+        header.extend_from_slice(&crc32(&[&lsn.to_be_bytes(), data]).to_be_bytes());
+        self.heap_file.write_at(offset, &header)
     }
 
-    pub fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> io::Result<()> {
-        let offset = PAGE_SIZE as u64 * page_id.to_u64();
-        self.heap_file.seek(SeekFrom::Start(offset))?;
-        self.heap_file.write_all(data)
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.heap_file.sync()
     }
 }
 
@@ -60,14 +287,52 @@ mod tests {
 
     #[test]
     fn test_disk_manager() {
-        let heap_file_path = "test.db";
-        let _ = std::fs::remove_file(heap_file_path);
-        let mut disk = DiskManager::open(heap_file_path).unwrap();
+        let heap_file_path = crate::testutil::temp_path("test.db");
+        let mut disk = DiskManager::open(&heap_file_path).unwrap();
         let page_id = disk.allocate_page();
-        let data = vec![1; PAGE_SIZE as usize];
-        disk.write_page_data(page_id, &data).unwrap();
-        let mut buf = vec![0; PAGE_SIZE as usize];
-        disk.read_page_data(page_id, &mut buf).unwrap();
+        let data = vec![1; PAGE_SIZE];
+        disk.write_page_data(page_id, 7, &data).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        assert_eq!(disk.read_page_data(page_id, &mut buf).unwrap(), 7);
         assert_eq!(data, buf);
+        // まだ書いていないページは空
+        let page_id = disk.allocate_page();
+        assert_eq!(disk.read_page_data(page_id, &mut buf).unwrap(), 0);
+        assert_eq!(buf, vec![0; PAGE_SIZE]);
+        assert!(disk.verify_page(PageId(0)).unwrap());
+        assert!(disk.verify_page(page_id).unwrap());
+        std::fs::remove_file(&heap_file_path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_write_checksum() {
+        let memory = MemoryDisk::new();
+        let mut disk = DiskManager::memory(memory.clone());
+        let page_id = disk.allocate_page();
+        disk.write_page_data(page_id, 1, &[1; PAGE_SIZE]).unwrap();
+        assert!(disk.verify_page(page_id).unwrap());
+        // 半分だけ書けたページはチェックサムが合わない
+        memory.inject(Fault::TornWrite);
+        assert!(disk.write_page_data(page_id, 2, &[2; PAGE_SIZE]).is_err());
+        assert!(!disk.verify_page(page_id).unwrap());
+        disk.write_page_data(page_id, 2, &[2; PAGE_SIZE]).unwrap();
+        assert!(disk.verify_page(page_id).unwrap());
+    }
+
+    #[test]
+    fn test_memory_backend_bytes() {
+        let memory = MemoryDisk::new();
+        let mut disk = DiskManager::memory(memory.clone());
+        let page_id = disk.allocate_page();
+        disk.write_page_data(page_id, 3, &[5; PAGE_SIZE]).unwrap();
+        disk.sync().unwrap();
+        drop(disk);
+        // 保存しておいた中身から開き直せる
+        let saved = MemoryDisk::from_bytes(memory.to_bytes());
+        let mut disk = DiskManager::with_backend(Box::new(saved)).unwrap();
+        assert_eq!(disk.page_count(), 1);
+        let mut buf = vec![0; PAGE_SIZE];
+        assert_eq!(disk.read_page_data(page_id, &mut buf).unwrap(), 3);
+        assert_eq!(buf, vec![5; PAGE_SIZE]);
     }
 }
\ No newline at end of file
@@ -0,0 +1,520 @@
+use std::io::{Read, Write};
+
+use crate::catalog::{Catalog, Index, PartitionBound, Table, Trigger, TriggerBody};
+use crate::collation::Collation;
+use crate::connection::{Connection, Error};
+use crate::planner;
+use crate::sql::ast::CopyRelation;
+use crate::sql::lexer::Keyword;
+use crate::storage;
+use crate::types::Value;
+
+// データベースの中身を SQL の文として書き出す論理ダンプ
+//
+// CREATE TYPE で作った列挙型を先に書き、テーブルごとの CREATE TABLE、行を入れる INSERT、インデックスの CREATE INDEX の順に書く。
+// 列挙型のラベルは並び順に書くので、流し直した型ではラベルの番号が変わることがあるが、並びは同じになる。
+// 主キーと 1 列の UNIQUE は CREATE TABLE の中に書き、ほかのインデックスは行を入れてから作る。
+// 書いた文を ; で区切った文として順に実行すれば、同じテーブルと行とインデックスができる。
+// 行はひとつのスナップショットから読むので、書き出している間にほかの接続が変えても食い違わない。
+// パーティションに分けたテーブルは親の後ろに子の CREATE TABLE ... PARTITION OF を書き、行は子ごとに書く。
+// 実体化したビューは行を書かず、テーブルの行を入れたあとに CREATE MATERIALIZED VIEW で作り直す。
+// トリガーは最後に作るので、流し直すときの INSERT では呼ばれない。
+// 登録した関数は SQL で書けないので含めない。関数を呼ぶトリガーは、流し直す前に同じ名前の関数を登録しておく
+
+// ひとつの INSERT に入れる行の数
+const INSERT_BATCH: usize = 1000;
+
+pub fn dump(conn: &mut Connection, writer: &mut dyn Write) -> Result<(), Error> {
+    if !conn.in_transaction() {
+        return conn.transaction(|conn| {
+            conn.execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ", &[])?;
+            dump(conn, writer)
+        });
+    }
+    let (types, tables, views, indexes, triggers) = {
+        let catalog = conn.catalog();
+        let types: Vec<String> = catalog
+            .enum_types()
+            .iter()
+            .map(|ty| {
+                let labels: Vec<String> = ty
+                    .labels()
+                    .into_iter()
+                    .map(|l| literal(&Value::Text(l)))
+                    .collect();
+                format!(
+                    "CREATE TYPE {} AS ENUM ({});",
+                    ident(&ty.name),
+                    labels.join(", ")
+                )
+            })
+            .collect();
+        // 親のテーブルの行は子にあるので、列の名前を None にして行を書かない
+        let tables: Vec<(String, Option<Vec<String>>, String)> = catalog
+            .tables()
+            .iter()
+            .filter(|table| table.view.is_none())
+            .map(|table| {
+                let columns = table.columns.iter().map(|c| ident(&c.name)).collect();
+                let columns = table.partitioning.is_none().then_some(columns);
+                (table.name.clone(), columns, create_table(table, false))
+            })
+            .collect();
+        let views: Vec<String> = catalog
+            .tables()
+            .iter()
+            .filter_map(|table| {
+                let view = table.view.as_ref()?;
+                Some(format!(
+                    "CREATE MATERIALIZED VIEW {} AS {};",
+                    ident(&table.name),
+                    view.query
+                ))
+            })
+            .collect();
+        let indexes: Vec<String> = catalog
+            .tables()
+            .iter()
+            .flat_map(|table| {
+                table
+                    .indexes
+                    .iter()
+                    .filter(|index| !is_constraint(table, index))
+                    .map(|index| create_index(&catalog, table, index))
+            })
+            .collect();
+        let triggers: Vec<String> = catalog
+            .tables()
+            .iter()
+            .flat_map(|table| {
+                table
+                    .triggers
+                    .iter()
+                    .map(|trigger| create_trigger(table, trigger))
+            })
+            .collect();
+        (types, tables, views, indexes, triggers)
+    };
+    writeln!(writer, "-- rdbms-training database dump")?;
+    if !types.is_empty() {
+        writeln!(writer)?;
+    }
+    for create in &types {
+        writeln!(writer, "{}", create)?;
+    }
+    for (_, _, create) in &tables {
+        writeln!(writer, "\n{}", create)?;
+    }
+    for (name, columns) in tables.iter().filter_map(|(n, c, _)| Some((n, c.as_ref()?))) {
+        let query = CopyRelation::Table {
+            name: name.clone(),
+            columns: vec![],
+        }
+        .query();
+        let insert = format!(
+            "INSERT INTO {} ({}) VALUES",
+            ident(name),
+            columns.join(", ")
+        );
+        let (rows, _) = conn.stream_query(
+            &query,
+            &[],
+            |_| Ok(0),
+            |rows, row| {
+                if *rows % INSERT_BATCH == 0 {
+                    let end = if *rows == 0 { "" } else { ";" };
+                    write!(writer, "{}\n{}\n    (", end, insert)?;
+                } else {
+                    write!(writer, ",\n    (")?;
+                }
+                let values: Vec<String> = row.iter().map(literal).collect();
+                write!(writer, "{})", values.join(", "))?;
+                *rows += 1;
+                Ok(())
+            },
+        )?;
+        if rows > 0 {
+            writeln!(writer, ";")?;
+        }
+    }
+    for create in &views {
+        writeln!(writer, "\n{}", create)?;
+    }
+    if !indexes.is_empty() {
+        writeln!(writer)?;
+    }
+    for create in &indexes {
+        writeln!(writer, "{}", create)?;
+    }
+    if !triggers.is_empty() {
+        writeln!(writer)?;
+    }
+    for create in &triggers {
+        writeln!(writer, "{}", create)?;
+    }
+    Ok(())
+}
+
+// ファイルに残すカタログの CREATE の文。dump と違って行は書かず、生成列の式も書く。
+// keep が false のテーブルはインデックスとトリガーも書かない。登録した関数を呼ぶトリガーも書かない
+pub(crate) fn schema(catalog: &Catalog, keep: impl Fn(&Table) -> bool) -> String {
+    let tables: Vec<&Table> = catalog.tables().iter().filter(|t| keep(t)).collect();
+    let mut sql: Vec<String> = tables
+        .iter()
+        .filter(|table| table.view.is_none())
+        .map(|table| create_table(table, true))
+        .collect();
+    for table in &tables {
+        if let Some(view) = &table.view {
+            sql.push(format!(
+                "CREATE MATERIALIZED VIEW {} AS {};",
+                ident(&table.name),
+                view.query
+            ));
+        }
+    }
+    for table in &tables {
+        for index in table.indexes.iter().filter(|i| !is_constraint(table, i)) {
+            sql.push(create_index(catalog, table, index));
+        }
+    }
+    for table in &tables {
+        for trigger in &table.triggers {
+            if let TriggerBody::Statement { .. } = trigger.body {
+                sql.push(create_trigger(table, trigger));
+            }
+        }
+    }
+    sql.join("\n")
+}
+
+// dump で書いた SQL を実行する。エラーになればそこで止める
+pub fn restore(conn: &mut Connection, mut reader: impl Read) -> Result<(), Error> {
+    let mut sql = String::new();
+    reader.read_to_string(&mut sql)?;
+    conn.execute_batch(&sql)
+}
+
+// CREATE TABLE が作った主キーと UNIQUE のインデックス
+fn is_constraint(table: &Table, index: &Index) -> bool {
+    match index.columns[..] {
+        _ if !index.unique || index.expression.is_some() => false,
+        _ if index.name == format!("{}_pkey", table.name) => true,
+        [i] => index.name == format!("{}_{}_key", table.name, table.columns[i].name),
+        _ => false,
+    }
+}
+
+// generated なら生成列の式も書く
+fn create_table(table: &Table, generated: bool) -> String {
+    let partition_by = match &table.partitioning {
+        Some(p) => format!(
+            " PARTITION BY {} ({})",
+            p.strategy.to_string().to_uppercase(),
+            ident(&table.columns[p.column].name)
+        ),
+        None => String::new(),
+    };
+    if let Some(partition) = &table.partition {
+        let bound = match &partition.bound {
+            PartitionBound::Range { from, to } => format!(
+                "FROM ({}) TO ({})",
+                from.as_ref().map_or("MINVALUE".to_string(), literal),
+                to.as_ref().map_or("MAXVALUE".to_string(), literal)
+            ),
+            PartitionBound::Hash { modulus, remainder } => {
+                format!("WITH (MODULUS {}, REMAINDER {})", modulus, remainder)
+            }
+        };
+        return format!(
+            "CREATE TABLE {} PARTITION OF {} FOR VALUES {}{};",
+            ident(&table.name),
+            ident(&partition.parent),
+            bound,
+            partition_by
+        );
+    }
+    let mut lines: Vec<String> = table
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let mut line = format!("    {} {}", ident(&column.name), column.data_type);
+            if column.collation != Collation::default() {
+                line += &format!(" COLLATE {}", column.collation.name());
+            }
+            if let (true, Some(expr)) = (generated, planner::generated_expr(table, i)) {
+                let stored = table.generated_column(i).is_some_and(|g| g.stored);
+                let kind = if stored { "STORED" } else { "VIRTUAL" };
+                line += &format!(" GENERATED ALWAYS AS ({}) {}", expr, kind);
+            }
+            if table.dictionaries.iter().any(|d| d.column == i) {
+                line += " COMPRESSION dictionary";
+            }
+            if column.not_null {
+                line += " NOT NULL";
+            }
+            let unique = table.indexes.iter().any(|index| {
+                index.columns == [i]
+                    && is_constraint(table, index)
+                    && !index.name.ends_with("_pkey")
+            });
+            if unique {
+                line += " UNIQUE";
+            }
+            line
+        })
+        .collect();
+    let pkey = format!("{}_pkey", table.name);
+    if let Some(index) = table.indexes.iter().find(|index| index.name == pkey) {
+        let columns: Vec<String> = index
+            .columns
+            .iter()
+            .map(|&i| ident(&table.columns[i].name))
+            .collect();
+        lines.push(format!("    PRIMARY KEY ({})", columns.join(", ")));
+    }
+    let ttl = match &table.ttl {
+        Some(ttl) => format!(
+            " TTL {} + {}",
+            ident(&table.columns[ttl.column].name),
+            literal(&Value::Interval(ttl.interval))
+        ),
+        None => String::new(),
+    };
+    let using = match table.engine.as_str() {
+        storage::DEFAULT_STORAGE_ENGINE => String::new(),
+        engine => format!(" USING {}", ident(engine)),
+    };
+    let options = if table.autovacuum {
+        ""
+    } else {
+        " WITH (autovacuum_enabled = false)"
+    };
+    format!(
+        "CREATE TABLE {} (\n{}\n){}{}{}{};",
+        ident(&table.name),
+        lines.join(",\n"),
+        partition_by,
+        using,
+        options,
+        ttl
+    )
+}
+
+fn create_trigger(table: &Table, trigger: &Trigger) -> String {
+    let body = match &trigger.body {
+        TriggerBody::Statement { sql, .. } => sql.clone(),
+        TriggerBody::Function(func) => format!("EXECUTE FUNCTION {}()", ident(func.name())),
+    };
+    format!(
+        "CREATE TRIGGER {} {} {} ON {} FOR EACH ROW {};",
+        ident(&trigger.name),
+        trigger.timing,
+        trigger.event,
+        ident(&table.name),
+        body
+    )
+}
+
+fn create_index(catalog: &Catalog, table: &Table, index: &Index) -> String {
+    let keys = match index.expression {
+        // 式のインデックスはキーをもう一重のかっこで囲む
+        Some(_) => format!(
+            "({})",
+            planner::index_columns(catalog, &table.name, &index.name).join(", ")
+        ),
+        None => index
+            .columns
+            .iter()
+            .map(|&i| ident(&table.columns[i].name))
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    format!(
+        "CREATE {}INDEX {} ON {}{} ({});",
+        match (index.unique, index.fulltext) {
+            (true, _) => "UNIQUE ",
+            (_, true) => "FULLTEXT ",
+            _ => "",
+        },
+        ident(&index.name),
+        ident(&table.name),
+        if index.rtree.is_some() {
+            " USING rtree"
+        } else {
+            ""
+        },
+        keys
+    )
+}
+
+// 小文字と数字と _ だけでできた、予約語でない名前はそのまま。ほかは " で囲む
+fn ident(name: &str) -> String {
+    let plain = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && Keyword::lookup(name).is_none();
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+// 列に入れれば同じ値に戻るリテラル
+fn literal(value: &Value) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    match value {
+        // 符号を外した値が i64 に収まらない
+        Value::Integer(i64::MIN) | Value::BigInt(i64::MIN) => {
+            format!("CAST('{}' AS bigint)", value)
+        }
+        Value::Real(x) if x.is_finite() => format!("{:?}", x),
+        Value::Real(_) => format!("CAST('{}' AS real)", value),
+        Value::Boolean(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+        Value::Text(s) => quote(s),
+        Value::Json(s) => quote(s),
+        // 文字列のまま列に入れればラベルとして読む
+        Value::Enum(_) => quote(&value.to_string()),
+        Value::Blob(_) => format!("CAST({} AS blob)", quote(&value.to_string())),
+        Value::Array(_) => format!(
+            "CAST({} AS {})",
+            quote(&value.to_string()),
+            value.data_type().unwrap()
+        ),
+        Value::Date(_)
+        | Value::Time(_)
+        | Value::Timestamp(_)
+        | Value::Uuid(_)
+        | Value::Interval(_)
+        | Value::Point(_)
+        | Value::Box(_) => {
+            format!(
+                "{} {}",
+                value.type_name().to_uppercase(),
+                quote(&value.to_string())
+            )
+        }
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Database;
+
+    #[test]
+    fn test_dump() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE items (
+                 id INTEGER PRIMARY KEY,
+                 name TEXT COLLATE nocase NOT NULL,
+                 code TEXT UNIQUE,
+                 price NUMERIC(10,2),
+                 weight REAL,
+                 data BLOB,
+                 added TIMESTAMP,
+                 attrs JSON
+             );
+             CREATE TABLE \"Order\" (\"select\" BIGINT, n INTEGER, d DATE, i INTERVAL, u UUID);
+             CREATE UNIQUE INDEX items_name_code ON items (name, code);
+             CREATE INDEX items_lower ON items ((lower(code) || 'x'));
+             INSERT INTO items VALUES
+                 (1, 'it''s', 'a', 1.50, -0.0, CAST('\\x00ff' AS BLOB),
+                  TIMESTAMP '2024-01-15 10:30:00', '{\"k\": [1, 2]}'),
+                 (2, 'b', NULL, NULL, CAST('NaN' AS REAL), NULL, NULL, NULL);
+             INSERT INTO \"Order\" VALUES
+                 (CAST('-9223372036854775808' AS BIGINT), 3, DATE '2024-02-29',
+                  INTERVAL '1 day 02:00:00', UUID '0190b3a4-7c2e-7d10-8f00-0123456789ab');
+             CREATE TABLE log (at DATE, n INTEGER) PARTITION BY RANGE (at);
+             CREATE TABLE log_old PARTITION OF log FOR VALUES FROM (MINVALUE) TO (DATE '2024-01-01');
+             CREATE TABLE log_new PARTITION OF log FOR VALUES FROM (DATE '2024-01-01') TO (MAXVALUE)
+                 PARTITION BY HASH (n);
+             CREATE TABLE log_new0 PARTITION OF log_new FOR VALUES WITH (MODULUS 1, REMAINDER 0);
+             INSERT INTO log VALUES (DATE '2023-05-01', 1), (DATE '2024-05-01', 2);
+             CREATE TABLE seen (at DATE) TTL at + INTERVAL '7 days';
+             CREATE MATERIALIZED VIEW log_counts AS SELECT n, count(*) AS c FROM log GROUP BY n;
+             CREATE INDEX log_counts_n ON log_counts (n);
+             CREATE TRIGGER items_seen AFTER INSERT ON items FOR EACH ROW
+                 INSERT INTO seen VALUES (CAST(new.added AS DATE))",
+        )
+        .unwrap();
+        let mut out = vec![];
+        dump(&mut conn, &mut out).unwrap();
+        let sql = String::from_utf8(out).unwrap();
+        assert!(
+            sql.contains("CREATE TABLE \"Order\" (\n    \"select\" bigint,"),
+            "{}",
+            sql
+        );
+        assert!(
+            sql.contains("    code text UNIQUE,\n") && sql.contains("    PRIMARY KEY (id)\n"),
+            "{}",
+            sql
+        );
+        assert!(
+            sql.contains(
+                "CREATE TABLE log_new PARTITION OF log FOR VALUES \
+                 FROM (DATE '2024-01-01') TO (MAXVALUE) PARTITION BY HASH (n);"
+            ) && !sql.contains("INSERT INTO log (")
+                && sql.contains(") TTL at + INTERVAL '7 days';")
+                && sql.contains(
+                    "CREATE MATERIALIZED VIEW log_counts AS \
+                     SELECT n, count(*) AS c FROM log GROUP BY n;"
+                )
+                && sql.ends_with(
+                    "\nCREATE TRIGGER items_seen AFTER INSERT ON items FOR EACH ROW \
+                     INSERT INTO seen VALUES (CAST(new.added AS DATE));\n"
+                ),
+            "{}",
+            sql
+        );
+
+        // 流し直したデータベースからもう一度書き出すと同じものになる
+        let restored = Database::open_temporary().unwrap();
+        let mut copy = restored.connect();
+        restore(&mut copy, sql.as_bytes()).unwrap();
+        let mut out = vec![];
+        dump(&mut copy, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), sql);
+        let query = "SELECT id, name, price, weight, data, added, attrs FROM items ORDER BY id";
+        let rows = |conn: &mut Connection| -> Vec<Vec<Value>> {
+            conn.query(query, &[])
+                .unwrap()
+                .map(|row| row.into_values())
+                .collect()
+        };
+        assert_eq!(rows(&mut copy), rows(&mut conn));
+        let count: i64 = copy
+            .query_row("SELECT count(*) FROM log", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(count, 2);
+        let err = copy
+            .execute("INSERT INTO items (id, name) VALUES (3, 'B')", &[])
+            .err();
+        assert!(err.is_none());
+        let err = copy
+            .execute(
+                "INSERT INTO items (id, name, code) VALUES (4, 'x', 'a')",
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "23505");
+
+        // 空のデータベースは見出しだけ
+        let empty = Database::open_temporary().unwrap();
+        let mut out = vec![];
+        dump(&mut empty.connect(), &mut out).unwrap();
+        assert_eq!(out, b"-- rdbms-training database dump\n");
+    }
+}
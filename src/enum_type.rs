@@ -0,0 +1,276 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+
+// CREATE TYPE ... AS ENUM で作る列挙型
+//
+// 型はデータベースのカタログが持ち、番号はカタログが作った順に振る。消した型の番号は使い回さない。
+// ラベルには作った順に番号を振り、行には型とラベルの番号を入れる。番号は振り直さないので、ラベルを足しても入れた行はそのまま読める。
+// 並びはラベルごとの順位で決め、順位は SORT_GAP ずつ空けて振る。BEFORE や AFTER で足したラベルには隣どうしの間の順位を与え、
+// 間が空いていなければすべての順位を振り直す。インデックスのキーは順位なので、振り直したら作り直す
+pub struct EnumType {
+    pub id: u32,
+    pub name: String,
+    // 同じデータベースのカタログの写しどうしで共有する
+    labels: RwLock<Vec<Label>>,
+}
+
+// 隣り合うラベルの順位の間隔
+const SORT_GAP: u32 = 1 << 16;
+
+#[derive(Clone)]
+struct Label {
+    name: String,
+    sort: u32,
+}
+
+#[derive(Clone)]
+pub struct EnumValue {
+    ty: Arc<EnumType>,
+    code: u32,
+}
+
+impl EnumType {
+    // 同じラベルが 2 度あれば Err でそのラベル
+    pub fn new(id: u32, name: &str, labels: &[String]) -> Result<Arc<EnumType>, String> {
+        for (i, label) in labels.iter().enumerate() {
+            if labels[..i].contains(label) {
+                return Err(label.clone());
+            }
+        }
+        let mut labels = labels
+            .iter()
+            .map(|name| Label {
+                name: name.clone(),
+                sort: 0,
+            })
+            .collect::<Vec<_>>();
+        let order = (0..labels.len()).collect::<Vec<_>>();
+        renumber(&mut labels, &order);
+        Ok(Arc::new(EnumType {
+            id,
+            name: name.to_string(),
+            labels: RwLock::new(labels),
+        }))
+    }
+
+    // ファイルに残した型を作り直す。labels はラベルの番号の順に、ラベルと順位
+    pub fn restore(id: u32, name: &str, labels: Vec<(String, u32)>) -> Arc<EnumType> {
+        let labels = labels
+            .into_iter()
+            .map(|(name, sort)| Label { name, sort })
+            .collect();
+        Arc::new(EnumType {
+            id,
+            name: name.to_string(),
+            labels: RwLock::new(labels),
+        })
+    }
+
+    // ラベルの番号の順に、ラベルと順位
+    pub fn label_sorts(&self) -> Vec<(String, u32)> {
+        self.with_labels(|labels| labels.iter().map(|l| (l.name.clone(), l.sort)).collect())
+    }
+
+    fn with_labels<T>(&self, f: impl FnOnce(&[Label]) -> T) -> T {
+        f(&self.labels.read().unwrap())
+    }
+
+    pub fn value(self: &Arc<Self>, label: &str) -> Option<EnumValue> {
+        let code = self.with_labels(|labels| labels.iter().position(|l| l.name == label))?;
+        Some(EnumValue {
+            ty: self.clone(),
+            code: code as u32,
+        })
+    }
+
+    pub fn from_code(self: &Arc<Self>, code: u32) -> Option<EnumValue> {
+        let len = self.with_labels(|labels| labels.len());
+        ((code as usize) < len).then(|| EnumValue {
+            ty: self.clone(),
+            code,
+        })
+    }
+
+    pub fn from_sort(self: &Arc<Self>, sort: u32) -> Option<EnumValue> {
+        let code = self.with_labels(|labels| labels.iter().position(|l| l.sort == sort))?;
+        Some(EnumValue {
+            ty: self.clone(),
+            code: code as u32,
+        })
+    }
+
+    // 並び順のラベル
+    pub fn labels(&self) -> Vec<String> {
+        self.with_labels(|labels| {
+            let mut sorted = labels.iter().collect::<Vec<_>>();
+            sorted.sort_by_key(|l| l.sort);
+            sorted.into_iter().map(|l| l.name.clone()).collect()
+        })
+    }
+
+    // ラベルを足す。position が Some((before, other)) なら other の前か後ろ、None なら最後。
+    // 足すラベルがないことと other があることは呼ぶ側で確かめる。順位を振り直せば true
+    pub fn add_label(&self, label: &str, position: Option<(bool, &str)>) -> bool {
+        let mut labels = self.labels.write().unwrap();
+        let mut order = (0..labels.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| labels[i].sort);
+        let at = match position {
+            None => order.len(),
+            Some((before, other)) => {
+                let i = order
+                    .iter()
+                    .position(|&i| labels[i].name == other)
+                    .unwrap_or(order.len());
+                if before {
+                    i
+                } else {
+                    (i + 1).min(order.len())
+                }
+            }
+        };
+        // 前のラベルと後ろのラベルの順位の間。先頭の前は 0 から、最後の後ろは SORT_GAP だけ空ける
+        let low = at.checked_sub(1).map_or(0, |i| labels[order[i]].sort);
+        let sort = match order.get(at) {
+            Some(&next) => {
+                let high = labels[next].sort;
+                (high - low > 1).then(|| low + (high - low) / 2)
+            }
+            None => low.checked_add(SORT_GAP),
+        };
+        labels.push(Label {
+            name: label.to_string(),
+            sort: sort.unwrap_or(0),
+        });
+        if sort.is_some() {
+            return false;
+        }
+        order.insert(at, labels.len() - 1);
+        renumber(&mut labels, &order);
+        true
+    }
+}
+
+// order の順に、間を空けて順位を振り直す。ラベルが多ければ間を狭める
+fn renumber(labels: &mut [Label], order: &[usize]) {
+    let gap = (u32::MAX / (order.len() as u32 + 1)).clamp(1, SORT_GAP);
+    for (rank, &i) in order.iter().enumerate() {
+        labels[i].sort = (rank as u32 + 1) * gap;
+    }
+}
+
+// types から番号が id の型を探す
+pub fn find(types: &[Arc<EnumType>], id: u32) -> Option<&Arc<EnumType>> {
+    types.iter().find(|t| t.id == id)
+}
+
+impl PartialEq for EnumType {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for EnumType {}
+
+impl Hash for EnumType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl fmt::Debug for EnumType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl EnumValue {
+    pub fn ty(&self) -> &Arc<EnumType> {
+        &self.ty
+    }
+
+    pub fn code(&self) -> u32 {
+        self.code
+    }
+
+    pub fn label(&self) -> String {
+        self.ty
+            .with_labels(|labels| labels[self.code as usize].name.clone())
+    }
+
+    pub fn sort(&self) -> u32 {
+        self.ty
+            .with_labels(|labels| labels[self.code as usize].sort)
+    }
+}
+
+impl PartialEq for EnumValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.ty == other.ty && self.code == other.code
+    }
+}
+
+impl fmt::Debug for EnumValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}::{}", self.ty.name, self.label())
+    }
+}
+
+impl fmt::Display for EnumValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.label())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enum_type() {
+        let labels = ["sad", "ok", "happy"].map(String::from);
+        let ty = EnumType::new(7, "mood", &labels).unwrap();
+        assert!(!ty.add_label("great", None));
+        assert!(!ty.add_label("meh", Some((true, "ok"))));
+        assert!(!ty.add_label("glad", Some((false, "ok"))));
+        assert_eq!(ty.labels(), ["sad", "meh", "ok", "glad", "happy", "great"]);
+        // 足したラベルの番号は後ろに並び、前からある番号は変わらない
+        let ok = ty.value("ok").unwrap();
+        assert_eq!((ok.code(), ty.value("meh").unwrap().code()), (1, 4));
+        assert_eq!(ty.from_sort(ok.sort()), Some(ok.clone()));
+        assert_eq!(find(std::slice::from_ref(&ty), 7).map(|t| t.id), Some(7));
+        let restored = EnumType::restore(7, "mood", ty.label_sorts());
+        assert_eq!(restored.labels(), ty.labels());
+        assert_eq!(restored.value("meh").unwrap().code(), 4);
+        assert!(ty.value("angry").is_none());
+        let twice = ["a", "a"].map(String::from);
+        assert_eq!(EnumType::new(8, "dup", &twice).err(), Some("a".to_string()));
+
+        // 同じところに足し続けると間がなくなり、順位を振り直す
+        let sad = ty.value("sad").unwrap();
+        let mut renumbered = 0;
+        for i in 0..40 {
+            let label = format!("l{}", i);
+            let before = if i == 0 {
+                "sad".to_string()
+            } else {
+                format!("l{}", i - 1)
+            };
+            renumbered += ty.add_label(&label, Some((false, &before))) as usize;
+        }
+        assert!(renumbered > 0);
+        let labels = ty.labels();
+        assert_eq!(labels[..3], ["sad", "l0", "l1"]);
+        assert_eq!(labels[40..], ["l39", "meh", "ok", "glad", "happy", "great"]);
+        // 振り直しても番号は変わらず、並びは順位で決まる
+        assert_eq!(ty.value("sad"), Some(sad.clone()));
+        assert_eq!(ty.value("ok").unwrap().code(), 1);
+        let mut values = labels
+            .iter()
+            .rev()
+            .map(|l| ty.value(l).unwrap())
+            .collect::<Vec<_>>();
+        values.sort_by_key(EnumValue::sort);
+        assert_eq!(values.iter().map(|v| v.label()).collect::<Vec<_>>(), labels);
+    }
+}
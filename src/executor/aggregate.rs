@@ -0,0 +1,1046 @@
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::vec;
+
+use crate::decimal::Decimal;
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::memory::MemoryReservation;
+use super::spill::SpillFile;
+use super::{row_size, BoxExecutor, Error, ExecContext, Executor, Row};
+
+// グループがメモリに収まらないときに分割するパーティション数
+const NUM_PARTITIONS: usize = 8;
+// パーティションがまだ大きすぎるときに分割し直す回数の上限
+const MAX_DEPTH: u32 = 3;
+
+type Key = Vec<Value>;
+// 書き出したグループの行と、それを分けたときの深さ
+type Partition = (SpillFile, u32);
+// 並列のワーカーが途中まで集約したグループ
+pub(crate) type PartialGroups = HashMap<Key, Vec<Box<dyn Accumulator>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    ApproxCountDistinct,
+    ApproxPercentile,
+}
+
+impl AggregateFunction {
+    pub fn lookup(name: &str) -> Option<Self> {
+        let func = match name {
+            "count" => AggregateFunction::Count,
+            "sum" => AggregateFunction::Sum,
+            "avg" => AggregateFunction::Avg,
+            "min" => AggregateFunction::Min,
+            "max" => AggregateFunction::Max,
+            "approx_count_distinct" => AggregateFunction::ApproxCountDistinct,
+            "approx_percentile" => AggregateFunction::ApproxPercentile,
+            _ => return None,
+        };
+        Some(func)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+            AggregateFunction::ApproxCountDistinct => "approx_count_distinct",
+            AggregateFunction::ApproxPercentile => "approx_percentile",
+        }
+    }
+}
+
+// 集約関数の呼び出し。arg が None なのは COUNT(*)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateCall {
+    pub func: AggregateFunction,
+    pub arg: Option<Expr>,
+    // 同じ値は一度だけ集約する
+    pub distinct: bool,
+    // APPROX_PERCENTILE で求める分位。0 から 1 まで
+    pub percentile: Option<f64>,
+}
+
+impl AggregateCall {
+    // 途中の状態を merge でまとめられる。並列の集約はこの集約関数だけを使う
+    pub fn mergeable(&self) -> bool {
+        !self.distinct && self.func != AggregateFunction::ApproxPercentile
+    }
+
+    pub(crate) fn accumulator(&self) -> Box<dyn Accumulator> {
+        if self.distinct {
+            return Box::new(Distinct::new(AggregateCall {
+                distinct: false,
+                ..self.clone()
+            }));
+        }
+        match (self.func, &self.arg) {
+            (AggregateFunction::Count, None) => Box::new(CountStar(0)),
+            (AggregateFunction::Count, Some(_)) => Box::new(Count(0)),
+            (AggregateFunction::Sum, _) => Box::new(Sum(NumericSum::new("sum"))),
+            (AggregateFunction::Avg, _) => Box::new(Avg(NumericSum::new("avg"))),
+            (AggregateFunction::Min, _) => Box::new(Extreme {
+                value: Value::Null,
+                keep: Ordering::Less,
+            }),
+            (AggregateFunction::Max, _) => Box::new(Extreme {
+                value: Value::Null,
+                keep: Ordering::Greater,
+            }),
+            (AggregateFunction::ApproxCountDistinct, _) => Box::new(HyperLogLog::new()),
+            (AggregateFunction::ApproxPercentile, _) => {
+                Box::new(TDigest::new(self.percentile.unwrap_or(0.5)))
+            }
+        }
+    }
+}
+
+// グループごとの途中の状態。ハッシュ集約、並んだ入力の集約、ウィンドウ関数で同じものを使う
+//
+// COUNT(*) 以外は NULL を無視し、行がなければ COUNT は 0、ほかは NULL を返す。
+pub(crate) trait Accumulator: AsAny + Send {
+    fn update(&mut self, value: Value) -> Result<(), Error>;
+
+    // 別のワーカーが同じ集約関数で求めた状態を足し込む。mergeable な集約関数だけが呼ばれる
+    fn merge(&mut self, _other: Box<dyn Accumulator>) -> Result<(), Error> {
+        unreachable!("aggregate state cannot be merged")
+    }
+
+    // 途中でも呼べる。ウィンドウ関数はフレームを広げながら何度も呼ぶ
+    fn finish(&mut self) -> Result<Value, Error>;
+
+    // グループの大きさの見積もりに使う
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
+    // メモリが足りないときに、覚えている値を一時ファイルに書き出して size を減らす
+    fn spill(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// merge で相手の状態を元の型に戻すのに使う
+pub(crate) trait AsAny {
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any> AsAny for T {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+fn downcast<T: Accumulator + 'static>(other: Box<dyn Accumulator>) -> Box<T> {
+    other
+        .into_any()
+        .downcast()
+        .expect("aggregate states of different functions")
+}
+
+struct CountStar(i64);
+
+impl Accumulator for CountStar {
+    fn update(&mut self, _: Value) -> Result<(), Error> {
+        self.0 += 1;
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Accumulator>) -> Result<(), Error> {
+        self.0 += downcast::<Self>(other).0;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<Value, Error> {
+        Ok(Value::Integer(self.0))
+    }
+}
+
+struct Count(i64);
+
+impl Accumulator for Count {
+    fn update(&mut self, value: Value) -> Result<(), Error> {
+        if !value.is_null() {
+            self.0 += 1;
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Accumulator>) -> Result<(), Error> {
+        self.0 += downcast::<Self>(other).0;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<Value, Error> {
+        Ok(Value::Integer(self.0))
+    }
+}
+
+// SUM と AVG の途中の合計
+//
+// 整数と DECIMAL は 10^scale 倍した i128 で足すので、途中で i64 を超えても最後に収まればよい。
+// REAL を足したら REAL で返す。
+struct NumericSum {
+    name: &'static str,
+    exact: i128,
+    // DECIMAL を足したら、それまでの最大の scale
+    scale: Option<u8>,
+    real: Option<f64>,
+    count: i64,
+    bigint: bool,
+}
+
+impl NumericSum {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            exact: 0,
+            scale: None,
+            real: None,
+            count: 0,
+            bigint: false,
+        }
+    }
+
+    fn add(&mut self, value: Value) -> Result<(), Error> {
+        let overflow = || Error::NumericOutOfRange;
+        match value {
+            Value::Integer(n) | Value::BigInt(n) => {
+                self.bigint |= matches!(value, Value::BigInt(_));
+                let n = n as i128 * 10i128.pow(self.scale.unwrap_or(0) as u32);
+                self.exact = self.exact.checked_add(n).ok_or_else(overflow)?;
+            }
+            Value::Decimal(d) => {
+                let s = self.scale.get_or_insert(0);
+                if d.scale() > *s {
+                    let shift = 10i128.pow((d.scale() - *s) as u32);
+                    self.exact = self.exact.checked_mul(shift).ok_or_else(overflow)?;
+                    *s = d.scale();
+                }
+                let n = d.mantissa() as i128 * 10i128.pow((*s - d.scale()) as u32);
+                self.exact = self.exact.checked_add(n).ok_or_else(overflow)?;
+            }
+            Value::Real(f) => *self.real.get_or_insert(0.0) += f,
+            value => {
+                return Err(Error::UndefinedFunction {
+                    name: self.name,
+                    arg: value.type_name(),
+                })
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    // 別のワーカーの合計を足す。scale の小さい方をそろえてから足す
+    fn merge(&mut self, other: &NumericSum) -> Result<(), Error> {
+        let overflow = || Error::NumericOutOfRange;
+        let (s, o) = (self.scale.unwrap_or(0), other.scale.unwrap_or(0));
+        let mut exact = other.exact;
+        if o > s {
+            let shift = 10i128.pow((o - s) as u32);
+            self.exact = self.exact.checked_mul(shift).ok_or_else(overflow)?;
+        } else if s > o {
+            let shift = 10i128.pow((s - o) as u32);
+            exact = exact.checked_mul(shift).ok_or_else(overflow)?;
+        }
+        self.exact = self.exact.checked_add(exact).ok_or_else(overflow)?;
+        self.scale = self.scale.max(other.scale);
+        if let Some(real) = other.real {
+            *self.real.get_or_insert(0.0) += real;
+        }
+        self.count += other.count;
+        self.bigint |= other.bigint;
+        Ok(())
+    }
+
+    fn integer(&self, n: i128) -> Result<Value, Error> {
+        let n = i64::try_from(n).map_err(|_| Error::IntegerOutOfRange)?;
+        Ok(if self.bigint {
+            Value::BigInt(n)
+        } else {
+            Value::Integer(n)
+        })
+    }
+
+    fn exact_f64(&self) -> f64 {
+        self.exact as f64 / 10f64.powi(self.scale.unwrap_or(0) as i32)
+    }
+}
+
+struct Sum(NumericSum);
+
+impl Accumulator for Sum {
+    fn update(&mut self, value: Value) -> Result<(), Error> {
+        if value.is_null() {
+            return Ok(());
+        }
+        self.0.add(value)
+    }
+
+    fn merge(&mut self, other: Box<dyn Accumulator>) -> Result<(), Error> {
+        self.0.merge(&downcast::<Self>(other).0)
+    }
+
+    fn finish(&mut self) -> Result<Value, Error> {
+        let sum = &self.0;
+        if sum.count == 0 {
+            return Ok(Value::Null);
+        }
+        if let Some(real) = sum.real {
+            return Ok(Value::Real(real + sum.exact_f64()));
+        }
+        match sum.scale {
+            Some(scale) => Decimal::new(sum.exact, scale)
+                .map(Value::Decimal)
+                .ok_or(Error::NumericOutOfRange),
+            None => sum.integer(sum.exact),
+        }
+    }
+}
+
+struct Avg(NumericSum);
+
+impl Accumulator for Avg {
+    fn update(&mut self, value: Value) -> Result<(), Error> {
+        if value.is_null() {
+            return Ok(());
+        }
+        self.0.add(value)
+    }
+
+    fn merge(&mut self, other: Box<dyn Accumulator>) -> Result<(), Error> {
+        self.0.merge(&downcast::<Self>(other).0)
+    }
+
+    fn finish(&mut self) -> Result<Value, Error> {
+        let sum = &self.0;
+        if sum.count == 0 {
+            return Ok(Value::Null);
+        }
+        if let Some(real) = sum.real {
+            return Ok(Value::Real((real + sum.exact_f64()) / sum.count as f64));
+        }
+        match sum.scale {
+            // 平均は足した値のどれかと同じ範囲に収まる
+            Some(scale) => Decimal::ratio(sum.exact, scale, sum.count)
+                .map(Value::Decimal)
+                .ok_or(Error::NumericOutOfRange),
+            // 整数だけなら、商を切り捨てずに浮動小数点数で返す。Decimal には収まらないことがある
+            None => Ok(Value::Real(sum.exact as f64 / sum.count as f64)),
+        }
+    }
+}
+
+// MIN と MAX。keep は今の値と比べて置き換える向き
+struct Extreme {
+    value: Value,
+    keep: Ordering,
+}
+
+impl Accumulator for Extreme {
+    fn update(&mut self, value: Value) -> Result<(), Error> {
+        if !value.is_null() && (self.value.is_null() || value.sort_cmp(&self.value) == self.keep) {
+            self.value = value;
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Accumulator>) -> Result<(), Error> {
+        self.update(downcast::<Self>(other).value)
+    }
+
+    fn finish(&mut self) -> Result<Value, Error> {
+        Ok(self.value.clone())
+    }
+}
+
+fn value_size(value: &Value) -> usize {
+    std::mem::size_of::<Value>()
+        + match value {
+            Value::Text(s) => s.len(),
+            Value::Json(s) => s.len(),
+            Value::Blob(b) => b.len(),
+            _ => 0,
+        }
+}
+
+// DISTINCT を付けた集約関数。同じ値は一度だけ call の集約関数に渡す
+//
+// 覚えた値は spill で 1 つの一時ファイルに書き出す。最後にファイルの値をハッシュ値で
+// NUM_PARTITIONS 個に分け直し、同じ値が同じパーティションに入るようにしてから、パーティションごとに重複を除く。
+struct Distinct {
+    call: AggregateCall,
+    seen: HashSet<Value>,
+    // seen の値の大きさ
+    size: usize,
+    spilled: Option<SpillFile>,
+}
+
+impl Distinct {
+    fn new(call: AggregateCall) -> Self {
+        Self {
+            call,
+            seen: HashSet::new(),
+            size: 0,
+            spilled: None,
+        }
+    }
+}
+
+fn partition_of(value: &Value) -> usize {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    (hasher.finish() % NUM_PARTITIONS as u64) as usize
+}
+
+impl Accumulator for Distinct {
+    fn update(&mut self, value: Value) -> Result<(), Error> {
+        if value.is_null() || self.seen.contains(&value) {
+            return Ok(());
+        }
+        self.size += value_size(&value);
+        self.seen.insert(value);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<Value, Error> {
+        let mut accumulator = self.call.accumulator();
+        let Some(file) = &mut self.spilled else {
+            for value in &self.seen {
+                accumulator.update(value.clone())?;
+            }
+            return accumulator.finish();
+        };
+        let mut partitions = (0..NUM_PARTITIONS)
+            .map(|_| SpillFile::new())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut reader = file.reader()?;
+        while let Some(row) = reader.next()? {
+            partitions[partition_of(&row[0])].write(&row)?;
+        }
+        for (i, partition) in partitions.into_iter().enumerate() {
+            let mut values = self
+                .seen
+                .iter()
+                .filter(|value| partition_of(value) == i)
+                .cloned()
+                .collect::<HashSet<_>>();
+            let mut reader = partition.into_reader()?;
+            while let Some(mut row) = reader.next()? {
+                values.insert(row.pop().unwrap());
+            }
+            for value in values {
+                accumulator.update(value)?;
+            }
+        }
+        accumulator.finish()
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + self.size
+    }
+
+    fn spill(&mut self) -> Result<(), Error> {
+        if self.seen.is_empty() {
+            return Ok(());
+        }
+        if self.spilled.is_none() {
+            self.spilled = Some(SpillFile::new()?);
+        }
+        let file = self.spilled.as_mut().unwrap();
+        for value in self.seen.drain() {
+            file.write(&vec![value])?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+// レジスタの番号に使うハッシュ値のビット数
+const HLL_BITS: u32 = 12;
+
+// APPROX_COUNT_DISTINCT。HyperLogLog で異なる値の数を見積もる
+//
+// 値のハッシュ値の上位 HLL_BITS ビットでレジスタを選び、残りのビットの先頭に続く 0 の数 + 1 の最大を覚える。
+// レジスタは 2^HLL_BITS バイトで、見積もりの誤差はおよそ 1.04 / sqrt(2^HLL_BITS) (1.6%)。
+struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; 1 << HLL_BITS],
+        }
+    }
+}
+
+impl Accumulator for HyperLogLog {
+    fn update(&mut self, value: Value) -> Result<(), Error> {
+        if value.is_null() {
+            return Ok(());
+        }
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = &mut self.registers[(hash >> (64 - HLL_BITS)) as usize];
+        let rank = ((hash << HLL_BITS).leading_zeros().min(64 - HLL_BITS) + 1) as u8;
+        *register = (*register).max(rank);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Accumulator>) -> Result<(), Error> {
+        let other = downcast::<Self>(other);
+        for (register, rank) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*rank);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<Value, Error> {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-(r as i32)))
+            .sum::<f64>();
+        let mut estimate = alpha * m * m / sum;
+        // 少ないうちは空のレジスタの割合から数える
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            estimate = m * (m / zeros as f64).ln();
+        }
+        Ok(Value::Integer(estimate.round() as i64))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + self.registers.len()
+    }
+}
+
+// t-digest の圧縮の度合い。重心の数はおよそこの値まで
+const TDIGEST_COMPRESSION: f64 = 100.0;
+// 重心にまとめる前にためておく値の数
+const TDIGEST_BUFFER: usize = 500;
+
+// APPROX_PERCENTILE。t-digest で分位を見積もる
+//
+// 値を重み付きの重心の列にまとめる。分位の両端ほど 1 つの重心にまとめる重みを小さくするので、
+// 0.99 のような端の分位もよく見積もれる。ためた値が TDIGEST_BUFFER 個になるたびに重心と合わせて並べ直し、
+// 隣り合うものをまとめ直すので、グループの状態はグループの行の数によらず一定の大きさに収まる。
+// 分位は重心の中心どうしを、最小値と最大値を両端として直線で補う
+struct TDigest {
+    percentile: f64,
+    // 平均と重み。平均の順に並ぶ
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    fn new(percentile: f64) -> Self {
+        Self {
+            percentile,
+            centroids: vec![],
+            buffer: vec![],
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    // 分位 q を δ/2π·asin(2q - 1) に写す尺度。1 つの重心は尺度で幅 1 までの値をまとめる
+    fn scale(q: f64) -> f64 {
+        TDIGEST_COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).asin()
+    }
+
+    fn unscale(k: f64) -> f64 {
+        let k = k.min(TDIGEST_COMPRESSION / 4.0);
+        ((k * 2.0 * std::f64::consts::PI / TDIGEST_COMPRESSION).sin() + 1.0) / 2.0
+    }
+
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut points = std::mem::take(&mut self.centroids);
+        points.extend(self.buffer.drain(..).map(|x| (x, 1.0)));
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total = points.iter().map(|p| p.1).sum::<f64>();
+        let mut before = 0.0;
+        let mut limit = total * Self::unscale(Self::scale(0.0) + 1.0);
+        let mut current = points[0];
+        for &(mean, weight) in &points[1..] {
+            if before + current.1 + weight <= limit {
+                let merged = current.1 + weight;
+                current = (current.0 + (mean - current.0) * weight / merged, merged);
+                continue;
+            }
+            before += current.1;
+            self.centroids.push(current);
+            limit = total * Self::unscale(Self::scale(before / total) + 1.0);
+            current = (mean, weight);
+        }
+        self.centroids.push(current);
+    }
+}
+
+impl Accumulator for TDigest {
+    fn update(&mut self, value: Value) -> Result<(), Error> {
+        if value.is_null() {
+            return Ok(());
+        }
+        let Some(x) = value.as_f64() else {
+            return Err(Error::UndefinedFunction {
+                name: "approx_percentile",
+                arg: value.type_name(),
+            });
+        };
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.buffer.push(x);
+        if self.buffer.len() >= TDIGEST_BUFFER {
+            self.compress();
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<Value, Error> {
+        self.compress();
+        if self.centroids.is_empty() {
+            return Ok(Value::Null);
+        }
+        // 重心の中心の位置と値に、両端の最小値と最大値を足した折れ線
+        let total = self.centroids.iter().map(|c| c.1).sum::<f64>();
+        let mut points = vec![(0.0, self.min)];
+        let mut before = 0.0;
+        for &(mean, weight) in &self.centroids {
+            points.push((before + weight / 2.0, mean));
+            before += weight;
+        }
+        points.push((total, self.max));
+        let target = self.percentile * total;
+        let i = points
+            .iter()
+            .position(|&(at, _)| at >= target)
+            .unwrap_or(points.len() - 1)
+            .max(1);
+        let ((x0, y0), (x1, y1)) = (points[i - 1], points[i]);
+        let y = if x1 > x0 {
+            y0 + (y1 - y0) * (target - x0) / (x1 - x0)
+        } else {
+            y1
+        };
+        Ok(Value::Real(y.clamp(self.min, self.max)))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.centroids.capacity() * std::mem::size_of::<(f64, f64)>()
+            + self.buffer.capacity() * std::mem::size_of::<f64>()
+    }
+}
+
+fn accumulators(aggregates: &[AggregateCall]) -> Vec<Box<dyn Accumulator>> {
+    aggregates.iter().map(AggregateCall::accumulator).collect()
+}
+
+fn update(accumulators: &mut [Box<dyn Accumulator>], args: Vec<Value>) -> Result<(), Error> {
+    for (accumulator, arg) in accumulators.iter_mut().zip(args) {
+        accumulator.update(arg)?;
+    }
+    Ok(())
+}
+
+fn state_size(accumulators: &[Box<dyn Accumulator>]) -> usize {
+    accumulators.iter().map(|a| a.size()).sum()
+}
+
+// グループのキーのあとに各集約関数の結果を並べる
+fn finish(mut key: Key, accumulators: &mut [Box<dyn Accumulator>]) -> Result<Row, Error> {
+    for accumulator in accumulators {
+        key.push(accumulator.finish()?);
+    }
+    Ok(key)
+}
+
+// 入力の行からグループのキーと集約関数の引数を求める
+fn eval_row(
+    group_by: &[Expr],
+    aggregates: &[AggregateCall],
+    row: &Row,
+) -> Result<(Key, Vec<Value>), Error> {
+    let key = group_by
+        .iter()
+        .map(|expr| expr.eval(row))
+        .collect::<Result<_, _>>()?;
+    let args = aggregates
+        .iter()
+        .map(|call| call.arg.as_ref().map_or(Ok(Value::Null), |e| e.eval(row)))
+        .collect::<Result<_, _>>()?;
+    Ok((key, args))
+}
+
+// 並列のワーカーで、行をグループごとに途中まで集約する
+pub(crate) fn partial_update(
+    groups: &mut PartialGroups,
+    group_by: &[Expr],
+    aggregates: &[AggregateCall],
+    row: &Row,
+) -> Result<(), Error> {
+    let (key, args) = eval_row(group_by, aggregates, row)?;
+    let accumulators = groups
+        .entry(key)
+        .or_insert_with(|| accumulators(aggregates));
+    update(accumulators, args)
+}
+
+// ワーカーの途中の状態 other を groups に足し込み、新しいグループの分だけ増えた大きさを返す
+pub(crate) fn merge_groups(
+    groups: &mut PartialGroups,
+    other: PartialGroups,
+) -> Result<usize, Error> {
+    let mut grown = 0;
+    for (key, states) in other {
+        match groups.get_mut(&key) {
+            Some(accumulators) => {
+                for (accumulator, state) in accumulators.iter_mut().zip(states) {
+                    accumulator.merge(state)?;
+                }
+            }
+            None => {
+                grown += row_size(&key) + state_size(&states);
+                groups.insert(key, states);
+            }
+        }
+    }
+    Ok(grown)
+}
+
+// まとめたグループの集約を終える。出力は HashAggregate と同じ
+pub(crate) fn finish_groups(
+    mut groups: PartialGroups,
+    group_by: &[Expr],
+    aggregates: &[AggregateCall],
+) -> Result<Vec<Row>, Error> {
+    // GROUP BY がなければ入力が空でも 1 行返す
+    if group_by.is_empty() && groups.is_empty() {
+        groups.insert(vec![], accumulators(aggregates));
+    }
+    groups
+        .into_iter()
+        .map(|(key, mut accumulators)| finish(key, &mut accumulators))
+        .collect()
+}
+
+// グループのハッシュ表
+//
+// work_mem か問い合わせの予算を超えたあとに現れた新しいグループの行は、キーのハッシュ値で分けて
+// 一時ファイルに書き出す。書き出す行はグループのキーのあとに集約関数の引数を並べたもの。
+struct Groups<'a> {
+    aggregates: &'a [AggregateCall],
+    table: HashMap<Key, Vec<Box<dyn Accumulator>>>,
+    size: usize,
+    reservation: MemoryReservation,
+    depth: u32,
+    partitions: Vec<SpillFile>,
+}
+
+impl<'a> Groups<'a> {
+    fn new(aggregates: &'a [AggregateCall], depth: u32, reservation: MemoryReservation) -> Self {
+        Self {
+            aggregates,
+            table: HashMap::new(),
+            size: 0,
+            reservation,
+            depth,
+            partitions: vec![],
+        }
+    }
+
+    fn add(&mut self, key: Key, args: Vec<Value>, work_mem: usize) -> Result<(), Error> {
+        if let Some(accumulators) = self.table.get_mut(&key) {
+            let before = state_size(accumulators);
+            update(accumulators, args)?;
+            let grown = state_size(accumulators).saturating_sub(before);
+            if grown == 0 {
+                return Ok(());
+            }
+            // DISTINCT で覚えた値が収まらなくなったら、このグループの値を書き出す
+            if self.size + grown > work_mem || !self.reservation.can_grow(grown) {
+                for accumulator in accumulators.iter_mut() {
+                    accumulator.spill()?;
+                }
+            }
+            let after = state_size(accumulators);
+            if after >= before {
+                self.size += after - before;
+                self.reservation.grow(after - before);
+            } else {
+                self.size -= before - after;
+                self.reservation.shrink(before - after);
+            }
+            return Ok(());
+        }
+        let mut accumulators = accumulators(self.aggregates);
+        // 状態そのものに加えて、状態を指す Box と、それを並べる Vec の分も数える
+        let size = row_size(&key)
+            + std::mem::size_of_val(&accumulators)
+            + accumulators
+                .iter()
+                .map(|a| std::mem::size_of_val(a) + a.size())
+                .sum::<usize>();
+        if (self.size > work_mem || !self.reservation.can_grow(size)) && self.depth < MAX_DEPTH {
+            if self.partitions.is_empty() {
+                self.partitions = (0..NUM_PARTITIONS)
+                    .map(|_| SpillFile::new())
+                    .collect::<Result<_, _>>()?;
+            }
+            let mut hasher = DefaultHasher::new();
+            self.depth.hash(&mut hasher);
+            key.hash(&mut hasher);
+            let i = (hasher.finish() % NUM_PARTITIONS as u64) as usize;
+            let mut row = key;
+            row.extend(args);
+            self.partitions[i].write(&row)?;
+            return Ok(());
+        }
+        update(&mut accumulators, args)?;
+        self.size += size;
+        self.reservation.grow(size);
+        self.table.insert(key, accumulators);
+        Ok(())
+    }
+
+    // 集約の終わったグループの行と、書き出したパーティションを返す
+    fn finish(self) -> Result<(Vec<Row>, Vec<Partition>), Error> {
+        let rows = self
+            .table
+            .into_iter()
+            .map(|(key, mut accumulators)| finish(key, &mut accumulators))
+            .collect::<Result<_, _>>()?;
+        let depth = self.depth + 1;
+        let partitions = self.partitions.into_iter().map(|f| (f, depth)).collect();
+        Ok((rows, partitions))
+    }
+}
+
+// 集合 set のグループのキー。group_by の値のうち集合にないものを NULL にし、
+// 集合にない式のビットを立てた番号を後ろにつける。先頭の式が上位のビット
+fn set_key(key: &[Value], set: &[usize]) -> Key {
+    let n = key.len();
+    let mut id = 0;
+    let mut masked = Vec::with_capacity(n + 1);
+    for (i, value) in key.iter().enumerate() {
+        if set.contains(&i) {
+            masked.push(value.clone());
+        } else {
+            masked.push(Value::Null);
+            id |= 1 << (n - 1 - i);
+        }
+    }
+    masked.push(Value::Integer(id));
+    masked
+}
+
+// ハッシュ集約
+//
+// 出力は group_by の値のあとに各集約関数の結果を並べたもの。行の順序は決まっていない。
+// sets があれば、入力を 1 度読むあいだに集合ごとのグループを同じハッシュ表で集約する。
+// 出力は group_by の値のあとに set_key の番号、各集約関数の結果を並べたもの
+pub struct HashAggregate<'a> {
+    input: BoxExecutor<'a>,
+    group_by: &'a [Expr],
+    sets: Option<&'a [Vec<usize>]>,
+    aggregates: &'a [AggregateCall],
+    started: bool,
+    output: vec::IntoIter<Row>,
+    partitions: Vec<Partition>,
+}
+
+impl<'a> HashAggregate<'a> {
+    pub fn new(
+        input: BoxExecutor<'a>,
+        group_by: &'a [Expr],
+        aggregates: &'a [AggregateCall],
+    ) -> Self {
+        Self {
+            input,
+            group_by,
+            sets: None,
+            aggregates,
+            started: false,
+            output: vec![].into_iter(),
+            partitions: vec![],
+        }
+    }
+
+    pub fn grouping_sets(
+        input: BoxExecutor<'a>,
+        group_by: &'a [Expr],
+        sets: &'a [Vec<usize>],
+        aggregates: &'a [AggregateCall],
+    ) -> Self {
+        Self {
+            sets: Some(sets),
+            ..Self::new(input, group_by, aggregates)
+        }
+    }
+
+    fn start(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        let mut groups = Groups::new(self.aggregates, 0, ctx.memory.reservation());
+        let mut empty = true;
+        while let Some(row) = self.input.next(ctx)? {
+            empty = false;
+            let (key, args) = eval_row(self.group_by, self.aggregates, &row)?;
+            match self.sets {
+                None => groups.add(key, args, ctx.work_mem)?,
+                Some(sets) => {
+                    for set in sets {
+                        groups.add(set_key(&key, set), args.clone(), ctx.work_mem)?;
+                    }
+                }
+            }
+        }
+        // GROUP BY がなければ入力が空でも 1 行返す。空の集合も同じ
+        match self.sets {
+            None if self.group_by.is_empty() && groups.table.is_empty() => {
+                groups.table.insert(vec![], accumulators(self.aggregates));
+            }
+            Some(sets) if empty => {
+                let nulls = vec![Value::Null; self.group_by.len()];
+                for set in sets.iter().filter(|set| set.is_empty()) {
+                    groups
+                        .table
+                        .insert(set_key(&nulls, set), accumulators(self.aggregates));
+                }
+            }
+            _ => {}
+        }
+        self.finish(groups)
+    }
+
+    // 次のパーティションを集約する。パーティションが残っていなければ false
+    fn load_partition(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        let Some((file, depth)) = self.partitions.pop() else {
+            return Ok(false);
+        };
+        let mut groups = Groups::new(self.aggregates, depth, ctx.memory.reservation());
+        let mut reader = file.into_reader()?;
+        while let Some(mut row) = reader.next()? {
+            let args = row.split_off(self.group_by.len() + self.sets.is_some() as usize);
+            groups.add(row, args, ctx.work_mem)?;
+        }
+        self.finish(groups)?;
+        Ok(true)
+    }
+
+    fn finish(&mut self, groups: Groups) -> Result<(), Error> {
+        let (rows, partitions) = groups.finish()?;
+        self.output = rows.into_iter();
+        self.partitions.extend(partitions);
+        Ok(())
+    }
+}
+
+impl Executor for HashAggregate<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if !self.started {
+            self.started = true;
+            self.start(ctx)?;
+        }
+        loop {
+            ctx.check_interrupt()?;
+            if let Some(row) = self.output.next() {
+                return Ok(Some(row));
+            }
+            if !self.load_partition(ctx)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
+
+// 並んだ入力の集約
+//
+// 入力は group_by の値が同じ行が隣り合うように並べておく。キーが変わるたびにそれまでのグループの行を返すので、
+// グループを覚えておく必要がない。出力は HashAggregate と同じで、行はキーの順に並ぶ。
+pub struct GroupAggregate<'a> {
+    input: BoxExecutor<'a>,
+    group_by: &'a [Expr],
+    aggregates: &'a [AggregateCall],
+    current: Option<(Key, Vec<Box<dyn Accumulator>>)>,
+    done: bool,
+}
+
+impl<'a> GroupAggregate<'a> {
+    pub fn new(
+        input: BoxExecutor<'a>,
+        group_by: &'a [Expr],
+        aggregates: &'a [AggregateCall],
+    ) -> Self {
+        Self {
+            input,
+            group_by,
+            aggregates,
+            current: None,
+            done: false,
+        }
+    }
+}
+
+impl Executor for GroupAggregate<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+        loop {
+            ctx.check_interrupt()?;
+            let Some(row) = self.input.next(ctx)? else {
+                self.done = true;
+                let mut last = match self.current.take() {
+                    Some(group) => group,
+                    // GROUP BY がなければ入力が空でも 1 行返す
+                    None if self.group_by.is_empty() => (vec![], accumulators(self.aggregates)),
+                    None => return Ok(None),
+                };
+                return finish(last.0, &mut last.1).map(Some);
+            };
+            let (key, args) = eval_row(self.group_by, self.aggregates, &row)?;
+            match &mut self.current {
+                Some((current, accumulators)) if *current == key => update(accumulators, args)?,
+                _ => {
+                    let mut accumulators = accumulators(self.aggregates);
+                    update(&mut accumulators, args)?;
+                    if let Some((key, mut accumulators)) = self.current.replace((key, accumulators))
+                    {
+                        return finish(key, &mut accumulators).map(Some);
+                    }
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
@@ -0,0 +1,99 @@
+use crate::heap::RecordId;
+use crate::transaction::{self, AsOfPoint, Snapshot};
+use crate::types::{DataType, Value};
+use crate::wal::Lsn;
+
+use super::batch::Batch;
+use super::expr::{cast_error, Expr};
+use super::{BoxExecutor, Error, ExecContext, Executor, Row};
+
+// AS OF のテーブルの走査。入力を動かすあいだだけ、その時点のスナップショットで版を見せる
+//
+// 時点は走査を始めるときに評価し、コミットの記録からその時点までにコミットしたものだけが見えるスナップショットを作る。
+// 上の演算子と、外の AS OF の走査には入れ替える前のスナップショットを戻す
+pub struct AsOf<'a> {
+    input: BoxExecutor<'a>,
+    // 入力を動かしていないあいだ預かる
+    snapshot: Option<Snapshot>,
+}
+
+impl<'a> AsOf<'a> {
+    pub fn new(input: BoxExecutor<'a>, snapshot: Snapshot) -> Self {
+        Self {
+            input,
+            snapshot: Some(snapshot),
+        }
+    }
+
+    fn with<T>(
+        &mut self,
+        ctx: &mut ExecContext,
+        f: impl FnOnce(&mut BoxExecutor<'a>, &mut ExecContext) -> T,
+    ) -> T {
+        let outer = std::mem::replace(&mut ctx.as_of, self.snapshot.take());
+        let result = f(&mut self.input, ctx);
+        self.snapshot = std::mem::replace(&mut ctx.as_of, outer);
+        result
+    }
+}
+
+// point を評価してスナップショットを作る。lsn でなければ時刻
+pub fn snapshot(ctx: &ExecContext, point: &Expr, lsn: bool) -> Result<Snapshot, Error> {
+    let value = point.eval(&vec![])?;
+    if value.is_null() {
+        return Err(Error::AsOfNull);
+    }
+    let point = if lsn {
+        if ctx.bufmgr.wal().is_none() {
+            return Err(transaction::Error::NoWal.into());
+        }
+        match DataType::BigInt
+            .cast(value.clone())
+            .map_err(|err| cast_error(err, &value, &DataType::BigInt))?
+        {
+            Value::BigInt(n) if n >= 0 => AsOfPoint::Lsn(Lsn(n as u64)),
+            _ => {
+                return Err(Error::InvalidSyntax {
+                    expected: "lsn".to_string(),
+                    value: value.to_string(),
+                })
+            }
+        }
+    } else {
+        match DataType::Timestamp
+            .cast(value.clone())
+            .map_err(|err| cast_error(err, &value, &DataType::Timestamp))?
+        {
+            Value::Timestamp(t) => AsOfPoint::Timestamp(t),
+            _ => unreachable!(),
+        }
+    };
+    let txn = ctx.txn.as_ref().ok_or(transaction::Error::NoTransaction)?;
+    Ok(txn.snapshot_as_of(point)?)
+}
+
+impl Executor for AsOf<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        self.with(ctx, |input, ctx| input.next(ctx))
+    }
+
+    fn next_batch(&mut self, ctx: &mut ExecContext) -> Result<Option<Batch>, Error> {
+        self.with(ctx, |input, ctx| input.next_batch(ctx))
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.input.record_id()
+    }
+
+    fn relation(&self) -> Option<&str> {
+        self.input.relation()
+    }
+
+    fn rescan(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        self.with(ctx, |input, ctx| input.rescan(ctx))
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.with(ctx, |input, ctx| input.close(ctx))
+    }
+}
@@ -0,0 +1,79 @@
+use crate::types::Value;
+
+use super::Row;
+
+// 演算子の間で一度に受け渡す行数の上限
+pub const BATCH_SIZE: usize = 1024;
+
+// 1 つの列の値を行の順に並べたもの
+pub type ValueVector = Vec<Value>;
+
+// 列ごとに値を並べた行の束
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    columns: Vec<ValueVector>,
+    // 列のない行もあるので行数は別に持つ
+    len: usize,
+}
+
+impl Batch {
+    pub fn new(columns: Vec<ValueVector>, len: usize) -> Self {
+        debug_assert!(columns.iter().all(|c| c.len() == len));
+        Self { columns, len }
+    }
+
+    // rows は空でなく、すべて同じ列数でなければならない
+    pub fn from_rows(rows: Vec<Row>) -> Self {
+        let len = rows.len();
+        let mut columns = vec![Vec::with_capacity(len); rows[0].len()];
+        for row in rows {
+            for (column, value) in columns.iter_mut().zip(row) {
+                column.push(value);
+            }
+        }
+        Self { columns, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn column(&self, index: usize) -> &ValueVector {
+        &self.columns[index]
+    }
+
+    // mask が真の行だけを残す
+    pub fn select(self, mask: &[bool]) -> Self {
+        let len = mask.iter().filter(|&&m| m).count();
+        if len == self.len {
+            return self;
+        }
+        let columns = self
+            .columns
+            .into_iter()
+            .map(|column| {
+                column
+                    .into_iter()
+                    .zip(mask)
+                    .filter(|(_, &m)| m)
+                    .map(|(value, _)| value)
+                    .collect()
+            })
+            .collect();
+        Self { columns, len }
+    }
+
+    pub fn into_rows(self) -> Vec<Row> {
+        let mut rows = vec![Vec::with_capacity(self.columns.len()); self.len];
+        for column in self.columns {
+            for (row, value) in rows.iter_mut().zip(column) {
+                row.push(value);
+            }
+        }
+        rows
+    }
+}
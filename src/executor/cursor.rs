@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::lock;
+use crate::transaction::Snapshot;
+
+use super::{BoxExecutor, Error, ExecContext, PlanNode, Row};
+
+// 結果を少しずつ取り出すためのカーソル
+//
+// 実行中の演算子をそのまま持っておき、fetch のたびに必要な行数だけ進める。
+// 最後まで読むか close すると演算子を close する。
+// 計画は演算子が借りるので、カーソルが持つ。fetch ごとに ExecContext を作り直してよく、
+// 行は開いたときのスナップショットで選ぶので、READ COMMITTED であとの文が新しいコミットを見ても変わらない
+pub struct Cursor {
+    // plan を借りている演算子。plan より先に落とす
+    exec: Option<BoxExecutor<'static>>,
+    plan: Rc<PlanNode>,
+    snapshot: Option<Snapshot>,
+    // ロックを待って戻った fetch が読んでいた行。次の fetch はその続きから読む
+    fetched: Vec<Row>,
+}
+
+impl Cursor {
+    pub fn declare(plan: Rc<PlanNode>, ctx: &mut ExecContext) -> Result<Self, Error> {
+        let snapshot = match &ctx.as_of {
+            Some(snapshot) => Some(snapshot.clone()),
+            None => ctx.txn.as_ref().map(|txn| txn.snapshot().clone()),
+        };
+        let exec = plan.start(ctx)?;
+        // SAFETY: exec が借りるのは plan の指す PlanNode だけで、それは Rc の中にあって動かない。
+        // plan はカーソルが持ち続け、exec は close するか、フィールドの順でそれより先に落とす
+        let exec = unsafe { std::mem::transmute::<BoxExecutor<'_>, BoxExecutor<'static>>(exec) };
+        Ok(Self {
+            exec: Some(exec),
+            plan,
+            snapshot,
+            fetched: vec![],
+        })
+    }
+
+    pub fn plan(&self) -> &PlanNode {
+        &self.plan
+    }
+
+    // 最大 n 行を返す。n 行より少なければ結果はもう残っていない
+    pub fn fetch(&mut self, ctx: &mut ExecContext, n: usize) -> Result<Vec<Row>, Error> {
+        let outer = std::mem::replace(&mut ctx.as_of, self.snapshot.take());
+        let result = self.fetch_rows(ctx, n);
+        self.snapshot = std::mem::replace(&mut ctx.as_of, outer);
+        result
+    }
+
+    fn fetch_rows(&mut self, ctx: &mut ExecContext, n: usize) -> Result<Vec<Row>, Error> {
+        let mut rows = std::mem::take(&mut self.fetched);
+        let Some(exec) = &mut self.exec else {
+            return Ok(rows);
+        };
+        while rows.len() < n {
+            match exec.next(ctx) {
+                Ok(Some(row)) => rows.push(row),
+                Ok(None) => {
+                    self.close(ctx)?;
+                    break;
+                }
+                Err(err @ Error::Lock(lock::Error::WouldBlock)) => {
+                    self.fetched = rows;
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(rows)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.exec.is_none()
+    }
+
+    pub fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        match self.exec.take() {
+            Some(mut exec) => exec.close(ctx),
+            None => Ok(()),
+        }
+    }
+}
+
+// 名前を付けたカーソルの集まり。トランザクションが終わったら close_all で閉じる
+#[derive(Default)]
+pub struct Cursors {
+    cursors: HashMap<String, Cursor>,
+}
+
+impl Cursors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn declare(
+        &mut self,
+        name: &str,
+        plan: Rc<PlanNode>,
+        ctx: &mut ExecContext,
+    ) -> Result<(), Error> {
+        if self.cursors.contains_key(name) {
+            return Err(Error::DuplicateCursor(name.to_string()));
+        }
+        let cursor = Cursor::declare(plan, ctx)?;
+        self.cursors.insert(name.to_string(), cursor);
+        Ok(())
+    }
+
+    pub fn fetch(
+        &mut self,
+        name: &str,
+        ctx: &mut ExecContext,
+        n: usize,
+    ) -> Result<Vec<Row>, Error> {
+        self.cursors
+            .get_mut(name)
+            .ok_or_else(|| Error::CursorNotFound(name.to_string()))?
+            .fetch(ctx, n)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Cursor> {
+        self.cursors.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cursors.is_empty()
+    }
+
+    pub fn close(&mut self, name: &str, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.cursors
+            .remove(name)
+            .ok_or_else(|| Error::CursorNotFound(name.to_string()))?
+            .close(ctx)
+    }
+
+    // すべてのカーソルを閉じる。失敗しても残りは閉じ、最初のエラーを返す
+    pub fn close_all(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        let mut result = Ok(());
+        for (_, mut cursor) in self.cursors.drain() {
+            let closed = cursor.close(ctx);
+            if result.is_ok() {
+                result = closed;
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::clock::Instant;
+use crate::heap::RecordId;
+use crate::json::Json;
+use crate::sql::ast::ExplainFormat;
+use crate::types::Value;
+
+use super::spill;
+use super::{execute, Batch, BoxExecutor, Error, ExecContext, Executor, PlanNode, Row};
+
+// EXPLAIN で書く演算子 1 つ分。計画の木を children の順に深さ優先でたどった順に並べる
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainNode {
+    pub depth: usize,
+    pub name: String,
+    // 見積もった費用と行数
+    pub cost: f64,
+    pub rows: f64,
+    // "名前: 値" の形の条件やキー
+    pub details: Vec<String>,
+}
+
+impl ExplainNode {
+    // 演算子の名前と見積もり
+    pub fn title(&self) -> String {
+        format!(
+            "{}  (cost={:.2} rows={:.0})",
+            self.name, self.cost, self.rows
+        )
+    }
+}
+
+// EXPLAIN ANALYZE で演算子ごとに測った値。時間とバッファは子の分も含む
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub rows: u64,
+    // 始めた回数と読み直した回数の和
+    pub loops: u64,
+    pub elapsed: Duration,
+    // プールにあったページと、ディスクから読んだページの数
+    pub hits: u64,
+    pub reads: u64,
+    // 演算子を実行している間の、問い合わせ全体で使ったメモリの最大のバイト数
+    pub memory_peak: usize,
+    // 書き出しに作った一時ファイルの数と、書いたバイト数
+    pub spill_files: u64,
+    pub spill_bytes: u64,
+    // RuntimeFilter が Bloom フィルタで捨てた行の数
+    pub removed: u64,
+}
+
+// 問い合わせを実行して演算子ごとに測った値。演算子は EXPLAIN と同じ順に並べる
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub operators: Vec<OperatorProfile>,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatorProfile {
+    pub depth: usize,
+    // 見積もりを除いた演算子の名前
+    pub name: String,
+    pub details: Vec<String>,
+    // 実行しなかった演算子は loops が 0
+    pub metrics: Metrics,
+    // 子の演算子の分を除いた時間
+    pub self_time: Duration,
+}
+
+impl Profile {
+    fn new(nodes: &[ExplainNode], metrics: Vec<Metrics>, elapsed: Duration) -> Self {
+        let mut operators: Vec<OperatorProfile> = nodes
+            .iter()
+            .zip(metrics)
+            .map(|(node, metrics)| OperatorProfile {
+                depth: node.depth,
+                name: node.name.clone(),
+                details: node.details.clone(),
+                self_time: metrics.elapsed,
+                metrics,
+            })
+            .collect();
+        for i in 0..operators.len() {
+            let depth = operators[i].depth;
+            let children: Duration = operators[i + 1..]
+                .iter()
+                .take_while(|op| op.depth > depth)
+                .filter(|op| op.depth == depth + 1)
+                .map(|op| op.metrics.elapsed)
+                .sum();
+            operators[i].self_time = operators[i].self_time.saturating_sub(children);
+        }
+        Profile { operators, elapsed }
+    }
+
+    // 根から演算子までの名前を ; でつなぎ、子の分を除いた時間をマイクロ秒で添えた行。
+    // flamegraph.pl などが読む折りたたんだスタックの形
+    pub fn folded(&self) -> Vec<String> {
+        let mut stack: Vec<&str> = vec![];
+        self.operators
+            .iter()
+            .map(|op| {
+                stack.truncate(op.depth);
+                stack.push(&op.name);
+                format!("{} {}", stack.join(";"), op.self_time.as_micros())
+            })
+            .collect()
+    }
+}
+
+// plan を実行して行を返し、演算子ごとに測った値も返す。nodes は plan の EXPLAIN
+pub fn execute_profiled(
+    plan: &PlanNode,
+    nodes: &[ExplainNode],
+    ctx: &mut ExecContext,
+) -> Result<(Vec<Row>, Profile), Error> {
+    let outer = ctx.metrics.replace(MetricsMap::new());
+    let started = Instant::now();
+    let result = execute(plan, ctx);
+    let elapsed = started.elapsed();
+    let mut metrics = std::mem::replace(&mut ctx.metrics, outer).unwrap_or_default();
+    let rows = result?;
+    let mut plans = vec![];
+    preorder(plan, &mut plans);
+    let metrics = plans
+        .iter()
+        .map(|&plan| metrics.remove(&node_key(plan)).unwrap_or_default())
+        .collect();
+    Ok((rows, Profile::new(nodes, metrics, elapsed)))
+}
+
+// 計画の演算子ごとの Metrics。演算子は PlanNode のアドレスで区別する
+pub(super) type MetricsMap = HashMap<usize, Metrics>;
+
+pub(super) fn node_key(plan: &PlanNode) -> usize {
+    plan as *const PlanNode as usize
+}
+
+// f の間に進んだ時間とバッファの読み込みを plan の値に足す
+pub(super) fn measure<T>(
+    ctx: &mut ExecContext,
+    plan: usize,
+    f: impl FnOnce(&mut ExecContext) -> T,
+) -> T {
+    let (hits, reads) = (ctx.bufmgr.hits(), ctx.bufmgr.reads());
+    let (files, bytes) = spill::spilled();
+    let outer_peak = ctx.memory.reset_peak();
+    let started = Instant::now();
+    let result = f(ctx);
+    let elapsed = started.elapsed();
+    let peak = ctx.memory.peak();
+    ctx.memory.raise_peak(outer_peak);
+    let (hits, reads) = (ctx.bufmgr.hits() - hits, ctx.bufmgr.reads() - reads);
+    let (files_after, bytes_after) = spill::spilled();
+    if let Some(metrics) = ctx.metrics.as_mut() {
+        let metrics = metrics.entry(plan).or_default();
+        metrics.elapsed += elapsed;
+        metrics.hits += hits;
+        metrics.reads += reads;
+        metrics.memory_peak = metrics.memory_peak.max(peak);
+        metrics.spill_files += files_after - files;
+        metrics.spill_bytes += bytes_after - bytes;
+    }
+    result
+}
+
+fn add_rows(ctx: &mut ExecContext, plan: usize, rows: usize) {
+    if let Some(metrics) = ctx.metrics.as_mut() {
+        metrics.entry(plan).or_default().rows += rows as u64;
+    }
+}
+
+pub(super) fn add_removed(ctx: &mut ExecContext, plan: usize) {
+    if let Some(metrics) = ctx.metrics.as_mut() {
+        metrics.entry(plan).or_default().removed += 1;
+    }
+}
+
+pub(super) fn add_loop(ctx: &mut ExecContext, plan: usize) {
+    if let Some(metrics) = ctx.metrics.as_mut() {
+        metrics.entry(plan).or_default().loops += 1;
+    }
+}
+
+// ExecContext が値を集めているときに PlanNode::start が演算子にかぶせる
+pub(super) struct Instrumented<'a> {
+    plan: usize,
+    input: BoxExecutor<'a>,
+}
+
+impl<'a> Instrumented<'a> {
+    pub fn new(plan: &PlanNode, input: BoxExecutor<'a>) -> Self {
+        Self {
+            plan: node_key(plan),
+            input,
+        }
+    }
+}
+
+impl Executor for Instrumented<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        let row = measure(ctx, self.plan, |ctx| self.input.next(ctx))?;
+        if row.is_some() {
+            add_rows(ctx, self.plan, 1);
+        }
+        Ok(row)
+    }
+
+    fn next_batch(&mut self, ctx: &mut ExecContext) -> Result<Option<Batch>, Error> {
+        let batch = measure(ctx, self.plan, |ctx| self.input.next_batch(ctx))?;
+        if let Some(batch) = &batch {
+            add_rows(ctx, self.plan, batch.len());
+        }
+        Ok(batch)
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.input.record_id()
+    }
+
+    fn relation(&self) -> Option<&str> {
+        self.input.relation()
+    }
+
+    fn rescan(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        let rescanned = measure(ctx, self.plan, |ctx| self.input.rescan(ctx))?;
+        if rescanned {
+            add_loop(ctx, self.plan);
+        }
+        Ok(rescanned)
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        measure(ctx, self.plan, |ctx| self.input.close(ctx))
+    }
+}
+
+// 入力を実行して結果を捨て、演算子ごとに測った値を見積もりと並べて 1 行ずつ返す
+pub struct ExplainAnalyze<'a> {
+    input: &'a PlanNode,
+    nodes: &'a [ExplainNode],
+    format: ExplainFormat,
+    lines: Option<std::vec::IntoIter<String>>,
+}
+
+impl<'a> ExplainAnalyze<'a> {
+    pub fn new(input: &'a PlanNode, nodes: &'a [ExplainNode], format: ExplainFormat) -> Self {
+        Self {
+            input,
+            nodes,
+            format,
+            lines: None,
+        }
+    }
+
+    fn run(&self, ctx: &mut ExecContext) -> Result<Vec<String>, Error> {
+        // 結果の行は返さないので、行の数の上限は当てない
+        let max_rows = ctx.max_rows.take();
+        let result = execute_profiled(self.input, self.nodes, ctx);
+        ctx.max_rows = max_rows;
+        let (_, profile) = result?;
+        let actual = profile
+            .operators
+            .iter()
+            .map(|op| Some(&op.metrics))
+            .collect::<Vec<_>>();
+        Ok(format_plan(
+            self.format,
+            self.nodes,
+            Some((&actual, profile.elapsed)),
+        ))
+    }
+}
+
+impl Executor for ExplainAnalyze<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if self.lines.is_none() {
+            self.lines = Some(self.run(ctx)?.into_iter());
+        }
+        let line = self.lines.as_mut().and_then(|lines| lines.next());
+        Ok(line.map(|line| vec![Value::Text(line)]))
+    }
+}
+
+fn preorder<'a>(plan: &'a PlanNode, plans: &mut Vec<&'a PlanNode>) {
+    plans.push(plan);
+    for child in plan.children() {
+        preorder(child, plans);
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// EXPLAIN の結果の行。actual があれば、演算子ごとに測った値と全体の時間も書く。
+// JSON は全体を 1 行に、テキストと DOT は 1 行ずつ返す
+pub fn format_plan(
+    format: ExplainFormat,
+    nodes: &[ExplainNode],
+    actual: Option<(&[Option<&Metrics>], Duration)>,
+) -> Vec<String> {
+    let metrics = actual.map(|(metrics, _)| metrics);
+    let elapsed = actual.map(|(_, elapsed)| elapsed);
+    match format {
+        ExplainFormat::Text => {
+            let mut lines = format_explain(nodes, metrics);
+            if let Some(elapsed) = elapsed {
+                lines.push(format!("Execution Time: {:.3} ms", millis(elapsed)));
+            }
+            lines
+        }
+        ExplainFormat::Json => {
+            let mut i = 0;
+            let mut members = vec![("Plan".to_string(), json_node(nodes, metrics, &mut i))];
+            if let Some(elapsed) = elapsed {
+                members.push((
+                    "Execution Time".to_string(),
+                    json_number(millis(elapsed), 3),
+                ));
+            }
+            vec![Json::Array(vec![Json::Object(members)]).to_string()]
+        }
+        ExplainFormat::Dot => format_dot(nodes, metrics),
+    }
+}
+
+fn json_number(n: f64, precision: usize) -> Json {
+    Json::Number(format!("{:.*}", precision, n))
+}
+
+// nodes[*i] を根とする部分木。子は PostgreSQL と同じく "Plans" に並べる
+fn json_node(nodes: &[ExplainNode], actual: Option<&[Option<&Metrics>]>, i: &mut usize) -> Json {
+    let node = &nodes[*i];
+    let index = *i;
+    *i += 1;
+    let mut members = vec![
+        ("Node Type".to_string(), Json::String(node.name.clone())),
+        ("Total Cost".to_string(), json_number(node.cost, 2)),
+        ("Plan Rows".to_string(), json_number(node.rows, 0)),
+    ];
+    for detail in &node.details {
+        let (key, value) = detail.split_once(": ").unwrap_or((detail, ""));
+        members.push((key.to_string(), Json::String(value.to_string())));
+    }
+    if let Some(actual) = actual {
+        match actual[index].filter(|m| m.loops > 0) {
+            None => members.push(("Actual Loops".to_string(), json_number(0.0, 0))),
+            Some(m) => {
+                let loops = m.loops as f64;
+                members.extend([
+                    (
+                        "Actual Total Time".to_string(),
+                        json_number(millis(m.elapsed) / loops, 3),
+                    ),
+                    (
+                        "Actual Rows".to_string(),
+                        json_number(m.rows as f64 / loops, 0),
+                    ),
+                    ("Actual Loops".to_string(), json_number(loops, 0)),
+                    (
+                        "Shared Hit Blocks".to_string(),
+                        json_number(m.hits as f64, 0),
+                    ),
+                    (
+                        "Shared Read Blocks".to_string(),
+                        json_number(m.reads as f64, 0),
+                    ),
+                ]);
+                if m.removed > 0 {
+                    members.push((
+                        "Rows Removed by Bloom Filter".to_string(),
+                        json_number(m.removed as f64, 0),
+                    ));
+                }
+            }
+        }
+    }
+    let mut children = vec![];
+    while nodes.get(*i).is_some_and(|child| child.depth > node.depth) {
+        children.push(json_node(nodes, actual, i));
+    }
+    if !children.is_empty() {
+        members.push(("Plans".to_string(), Json::Array(children)));
+    }
+    Json::Object(members)
+}
+
+// Graphviz の有向グラフ。演算子ごとに箱を 1 つ作り、親から子へ矢印を引く
+fn format_dot(nodes: &[ExplainNode], actual: Option<&[Option<&Metrics>]>) -> Vec<String> {
+    let mut lines = vec![
+        "digraph plan {".to_string(),
+        "  node [shape=box];".to_string(),
+    ];
+    // 深さごとの、いま開いている演算子の番号
+    let mut parents: Vec<usize> = vec![];
+    for (i, node) in nodes.iter().enumerate() {
+        let mut label = vec![
+            node.name.clone(),
+            format!("cost={:.2} rows={:.0}", node.cost, node.rows),
+        ];
+        label.extend(node.details.iter().cloned());
+        if let Some(actual) = actual {
+            label.push(match actual[i].filter(|m| m.loops > 0) {
+                None => "never executed".to_string(),
+                Some(m) => format!(
+                    "actual time={:.3} rows={:.0} loops={}",
+                    millis(m.elapsed) / m.loops as f64,
+                    m.rows as f64 / m.loops as f64,
+                    m.loops
+                ),
+            });
+        }
+        let label: Vec<String> = label.iter().map(|line| dot_escape(line)).collect();
+        lines.push(format!("  n{} [label=\"{}\\l\"];", i, label.join("\\l")));
+        parents.truncate(node.depth);
+        if let Some(parent) = parents.last() {
+            lines.push(format!("  n{} -> n{};", parent, i));
+        }
+        parents.push(i);
+    }
+    lines.push("}".to_string());
+    lines
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// 演算子を字下げして書く。子の演算子は "->" をつけて一段下げる。
+// actual があれば、見積もりのあとに 1 回あたりの時間と行数、回数と、Bloom フィルタで捨てた行と読んだバッファを書く
+pub fn format_explain(nodes: &[ExplainNode], actual: Option<&[Option<&Metrics>]>) -> Vec<String> {
+    let mut lines = vec![];
+    for (i, node) in nodes.iter().enumerate() {
+        let indent = if node.depth == 0 {
+            String::new()
+        } else {
+            format!("{}->  ", " ".repeat(node.depth * 6 - 4))
+        };
+        let detail = " ".repeat(node.depth * 6 + 2);
+        let metrics = actual.map(|actual| actual[i].filter(|m| m.loops > 0));
+        let title = match metrics {
+            None => node.title(),
+            Some(None) => format!("{} (never executed)", node.title()),
+            Some(Some(m)) => format!(
+                "{} (actual time={:.3} rows={:.0} loops={})",
+                node.title(),
+                millis(m.elapsed) / m.loops as f64,
+                m.rows as f64 / m.loops as f64,
+                m.loops
+            ),
+        };
+        lines.push(format!("{}{}", indent, title));
+        for text in &node.details {
+            lines.push(format!("{}{}", detail, text));
+        }
+        if let Some(Some(m)) = metrics {
+            if m.removed > 0 {
+                lines.push(format!(
+                    "{}Rows Removed by Bloom Filter: {}",
+                    detail, m.removed
+                ));
+            }
+            if m.hits + m.reads > 0 {
+                lines.push(format!(
+                    "{}Buffers: shared hit={} read={}",
+                    detail, m.hits, m.reads
+                ));
+            }
+        }
+    }
+    lines
+}
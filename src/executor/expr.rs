@@ -0,0 +1,947 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::datetime::Interval;
+use crate::decimal::Decimal;
+use crate::geometry::Rect;
+use crate::json::{self, Json, PathStep};
+use crate::sql::ast::{BinaryOp, UnaryOp};
+use crate::types::{CoerceError, Coercion, DataType, Value};
+
+use super::batch::{Batch, ValueVector};
+use super::function::{self, read_json};
+pub use super::function::{like_escape, parse_like, Function, LikeToken, ScalarFunction};
+use super::{Error, Row};
+
+// 列を位置で参照するように解決済みの式
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column(usize),
+    Literal(Value),
+    // 使い回す計画の $index。実行する前に bind_params で値に置き換える。ty は計画したときの値の型
+    Parameter {
+        index: usize,
+        ty: Option<DataType>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    Function {
+        func: Function,
+        args: Vec<Expr>,
+    },
+    // カタログに登録した関数の呼び出し
+    UserFunction {
+        func: Arc<ScalarFunction>,
+        args: Vec<Expr>,
+    },
+}
+
+impl Expr {
+    pub fn column(index: usize) -> Self {
+        Expr::Column(index)
+    }
+
+    pub fn binary(op: BinaryOp, left: Expr, right: Expr) -> Self {
+        Expr::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    pub fn eval(&self, row: &Row) -> Result<Value, Error> {
+        match self {
+            Expr::Column(index) => Ok(row[*index].clone()),
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Parameter { index, .. } => Err(Error::UnboundParameter(*index)),
+            Expr::Binary { op, left, right } => {
+                let left = left.eval(row)?;
+                let right = right.eval(row)?;
+                eval_binary(*op, left, right)
+            }
+            Expr::Unary { op, expr } => eval_unary(*op, expr.eval(row)?),
+            Expr::IsNull { expr, negated } => {
+                Ok(Value::Boolean(expr.eval(row)?.is_null() != *negated))
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => in_list(
+                expr.eval(row)?,
+                list.iter().map(|item| item.eval(row)),
+                *negated,
+            ),
+            Expr::Function { func, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<Vec<_>, _>>()?;
+                func.call(args)
+            }
+            Expr::UserFunction { func, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<Vec<_>, _>>()?;
+                func.call(args)
+            }
+        }
+    }
+
+    // 束のすべての行に対して評価し、結果を行の順に並べて返す
+    pub fn eval_batch(&self, batch: &Batch) -> Result<ValueVector, Error> {
+        match self {
+            Expr::Column(index) => Ok(batch.column(*index).clone()),
+            Expr::Literal(value) => Ok(vec![value.clone(); batch.len()]),
+            Expr::Parameter { index, .. } => Err(Error::UnboundParameter(*index)),
+            Expr::Binary { op, left, right } => {
+                let left = left.eval_batch(batch)?;
+                let right = right.eval_batch(batch)?;
+                left.into_iter()
+                    .zip(right)
+                    .map(|(l, r)| eval_binary(*op, l, r))
+                    .collect()
+            }
+            Expr::Unary { op, expr } => expr
+                .eval_batch(batch)?
+                .into_iter()
+                .map(|value| eval_unary(*op, value))
+                .collect(),
+            Expr::IsNull { expr, negated } => Ok(expr
+                .eval_batch(batch)?
+                .into_iter()
+                .map(|value| Value::Boolean(value.is_null() != *negated))
+                .collect()),
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let values = expr.eval_batch(batch)?;
+                let list = list
+                    .iter()
+                    .map(|item| item.eval_batch(batch))
+                    .collect::<Result<Vec<_>, _>>()?;
+                values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        in_list(value, list.iter().map(|item| Ok(item[i].clone())), *negated)
+                    })
+                    .collect()
+            }
+            Expr::Function { func, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval_batch(batch))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (0..batch.len())
+                    .map(|i| func.call(args.iter().map(|arg| arg[i].clone()).collect()))
+                    .collect()
+            }
+            Expr::UserFunction { func, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval_batch(batch))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (0..batch.len())
+                    .map(|i| func.call(args.iter().map(|arg| arg[i].clone()).collect()))
+                    .collect()
+            }
+        }
+    }
+
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Parameter { .. } => vec![],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } => vec![expr],
+            Expr::InList { expr, list, .. } => std::iter::once(&**expr).chain(list).collect(),
+            Expr::Function { args, .. } | Expr::UserFunction { args, .. } => args.iter().collect(),
+        }
+    }
+
+    fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Parameter { .. } => vec![],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } => vec![expr],
+            Expr::InList { expr, list, .. } => std::iter::once(&mut **expr).chain(list).collect(),
+            Expr::Function { args, .. } | Expr::UserFunction { args, .. } => {
+                args.iter_mut().collect()
+            }
+        }
+    }
+
+    pub fn has_params(&self) -> bool {
+        matches!(self, Expr::Parameter { .. }) || self.children().iter().any(|e| e.has_params())
+    }
+
+    // $n を params[n - 1] の値に置き換える
+    pub fn bind_params(&mut self, params: &[Value]) -> Result<(), Error> {
+        if let Expr::Parameter { index, .. } = *self {
+            let value = params
+                .get(index - 1)
+                .ok_or(Error::UnboundParameter(index))?;
+            *self = Expr::Literal(value.clone());
+            return Ok(());
+        }
+        for child in self.children_mut() {
+            child.bind_params(params)?;
+        }
+        Ok(())
+    }
+
+    // 式が参照する列の位置を集める
+    pub fn collect_columns(&self, columns: &mut Vec<usize>) {
+        if let Expr::Column(index) = self {
+            columns.push(*index);
+        }
+        for child in self.children() {
+            child.collect_columns(columns);
+        }
+    }
+
+    // 列の位置を f で付け替えた式
+    pub fn map_columns(&self, f: &impl Fn(usize) -> usize) -> Expr {
+        self.replace_columns(&|i| Expr::Column(f(i)))
+    }
+
+    // 列をそれぞれ f の返す式で置き換えた式
+    pub fn replace_columns(&self, f: &impl Fn(usize) -> Expr) -> Expr {
+        let map = |expr: &Expr| Box::new(expr.replace_columns(f));
+        match self {
+            Expr::Column(index) => f(*index),
+            Expr::Literal(_) | Expr::Parameter { .. } => self.clone(),
+            Expr::Binary { op, left, right } => Expr::Binary {
+                op: *op,
+                left: map(left),
+                right: map(right),
+            },
+            Expr::Unary { op, expr } => Expr::Unary {
+                op: *op,
+                expr: map(expr),
+            },
+            Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: map(expr),
+                negated: *negated,
+            },
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: map(expr),
+                list: list.iter().map(|item| item.replace_columns(f)).collect(),
+                negated: *negated,
+            },
+            Expr::Function { func, args } => Expr::Function {
+                func: *func,
+                args: args.iter().map(|arg| arg.replace_columns(f)).collect(),
+            },
+            Expr::UserFunction { func, args } => Expr::UserFunction {
+                func: func.clone(),
+                args: args.iter().map(|arg| arg.replace_columns(f)).collect(),
+            },
+        }
+    }
+
+    // AND でつながった条件を分解する
+    pub fn split_conjunction(self) -> Vec<Expr> {
+        match self {
+            Expr::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => {
+                let mut conjuncts = left.split_conjunction();
+                conjuncts.extend(right.split_conjunction());
+                conjuncts
+            }
+            expr => vec![expr],
+        }
+    }
+
+    pub fn conjunction(conjuncts: Vec<Expr>) -> Option<Expr> {
+        conjuncts
+            .into_iter()
+            .reduce(|left, right| Expr::binary(BinaryOp::And, left, right))
+    }
+
+    // WHERE 句などの条件として評価する。NULL は偽とみなす
+    pub fn eval_predicate(&self, row: &Row) -> Result<bool, Error> {
+        predicate_value(self.eval(row)?)
+    }
+
+    pub fn eval_predicate_batch(&self, batch: &Batch) -> Result<Vec<bool>, Error> {
+        self.eval_batch(batch)?
+            .into_iter()
+            .map(predicate_value)
+            .collect()
+    }
+}
+
+// どの項目とも等しくなく、NULL の項目があれば、等しいかどうかわからないので NULL
+fn in_list(
+    value: Value,
+    list: impl Iterator<Item = Result<Value, Error>>,
+    negated: bool,
+) -> Result<Value, Error> {
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+    let mut null = false;
+    for item in list {
+        let item = item?;
+        if item.is_null() {
+            null = true;
+        } else if compare(BinaryOp::Eq, &value, &item)? == Ordering::Equal {
+            return Ok(Value::Boolean(!negated));
+        }
+    }
+    if null {
+        return Ok(Value::Null);
+    }
+    Ok(Value::Boolean(negated))
+}
+
+fn predicate_value(value: Value) -> Result<bool, Error> {
+    match value {
+        Value::Boolean(b) => Ok(b),
+        Value::Null => Ok(false),
+        value => Err(Error::NotBoolean(value.type_name())),
+    }
+}
+
+fn undefined(op: BinaryOp, left: &Value, right: &Value) -> Error {
+    Error::UndefinedOperator {
+        op: op_symbol(op),
+        left: left.type_name(),
+        right: right.type_name(),
+    }
+}
+
+pub(crate) fn op_symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Or => "OR",
+        BinaryOp::And => "AND",
+        BinaryOp::Eq => "=",
+        BinaryOp::NotEq => "<>",
+        BinaryOp::Lt => "<",
+        BinaryOp::LtEq => "<=",
+        BinaryOp::Gt => ">",
+        BinaryOp::GtEq => ">=",
+        BinaryOp::Plus => "+",
+        BinaryOp::Minus => "-",
+        BinaryOp::Multiply => "*",
+        BinaryOp::Divide => "/",
+        BinaryOp::Modulo => "%",
+        BinaryOp::Concat => "||",
+        BinaryOp::JsonGet => "->",
+        BinaryOp::JsonGetText => "->>",
+        BinaryOp::IsDistinctFrom => "IS DISTINCT FROM",
+        BinaryOp::IsNotDistinctFrom => "IS NOT DISTINCT FROM",
+        BinaryOp::Overlaps => "&&",
+        BinaryOp::Contains => "@>",
+        BinaryOp::ContainedBy => "<@",
+        BinaryOp::Distance => "<->",
+    }
+}
+
+// 型の違う値は、どちらかの型に黙って変換できれば比べる。文字列ともう一方の型の値は、文字列をその型として読んで比べる
+fn compare(op: BinaryOp, left: &Value, right: &Value) -> Result<Ordering, Error> {
+    let (Some(a), Some(b)) = (left.data_type(), right.data_type()) else {
+        return Err(undefined(op, left, right));
+    };
+    let implicit =
+        |from: &DataType, to: &DataType| to.coercion_from(from) == Some(Coercion::Implicit);
+    match (a, b) {
+        (a, b) if implicit(&a, &b) || implicit(&b, &a) => Ok(left.sort_cmp(right)),
+        (DataType::Text, ty) => compare(op, &read_as(ty, left)?, right),
+        (ty, DataType::Text) => compare(op, left, &read_as(ty, right)?),
+        _ => Err(undefined(op, left, right)),
+    }
+}
+
+// DECIMAL は桁を丸めずに読む
+fn read_as(ty: DataType, value: &Value) -> Result<Value, Error> {
+    if let (DataType::Decimal { .. }, Value::Text(s)) = (&ty, value) {
+        return s
+            .trim()
+            .parse()
+            .map(Value::Decimal)
+            .map_err(|_| Error::InvalidSyntax {
+                expected: ty.name().to_string(),
+                value: s.clone(),
+            });
+    }
+    ty.cast(value.clone())
+        .map_err(|err| cast_error(err, value, &ty))
+}
+
+// CAST と比較での型の変換の失敗
+pub(super) fn cast_error(err: CoerceError, value: &Value, ty: &DataType) -> Error {
+    match err {
+        CoerceError::Mismatch => Error::CannotCast {
+            from: value.type_name(),
+            to: ty.name().to_string(),
+        },
+        CoerceError::Overflow if matches!(ty, DataType::Integer | DataType::BigInt) => {
+            Error::IntegerOutOfRange
+        }
+        CoerceError::Overflow => Error::NumericOutOfRange,
+        CoerceError::InvalidSyntax(value) => Error::InvalidSyntax {
+            expected: ty.name().to_string(),
+            value,
+        },
+    }
+}
+
+// AND / OR は三値論理。片方が NULL でももう片方で結果が決まることがある
+fn eval_logical(op: BinaryOp, left: Value, right: Value) -> Result<Value, Error> {
+    let as_bool = |value: &Value| match value {
+        Value::Boolean(b) => Ok(Some(*b)),
+        Value::Null => Ok(None),
+        _ => Err(undefined(op, &left, &right)),
+    };
+    let (a, b) = (as_bool(&left)?, as_bool(&right)?);
+    // AND は偽、OR は真があれば確定する
+    let dominant = op == BinaryOp::Or;
+    let result = match (a, b) {
+        (Some(a), _) if a == dominant => Some(dominant),
+        (_, Some(b)) if b == dominant => Some(dominant),
+        (Some(_), Some(_)) => Some(!dominant),
+        _ => None,
+    };
+    Ok(result.map_or(Value::Null, Value::Boolean))
+}
+
+pub(super) fn eval_binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, Error> {
+    if matches!(op, BinaryOp::And | BinaryOp::Or) {
+        return eval_logical(op, left, right);
+    }
+    if matches!(op, BinaryOp::IsDistinctFrom | BinaryOp::IsNotDistinctFrom) {
+        let distinct = match (left.is_null(), right.is_null()) {
+            (true, true) => false,
+            (false, false) => compare(op, &left, &right)?.is_ne(),
+            _ => true,
+        };
+        return Ok(Value::Boolean(distinct == (op == BinaryOp::IsDistinctFrom)));
+    }
+    // それ以外はどちらかが NULL なら結果も NULL
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null);
+    }
+    let result = match op {
+        BinaryOp::And | BinaryOp::Or | BinaryOp::IsDistinctFrom | BinaryOp::IsNotDistinctFrom => {
+            unreachable!()
+        }
+        BinaryOp::Eq => Value::Boolean(compare(op, &left, &right)?.is_eq()),
+        BinaryOp::NotEq => Value::Boolean(compare(op, &left, &right)?.is_ne()),
+        BinaryOp::Lt => Value::Boolean(compare(op, &left, &right)?.is_lt()),
+        BinaryOp::LtEq => Value::Boolean(compare(op, &left, &right)?.is_le()),
+        BinaryOp::Gt => Value::Boolean(compare(op, &left, &right)?.is_gt()),
+        BinaryOp::GtEq => Value::Boolean(compare(op, &left, &right)?.is_ge()),
+        BinaryOp::Plus
+        | BinaryOp::Minus
+        | BinaryOp::Multiply
+        | BinaryOp::Divide
+        | BinaryOp::Modulo => eval_arithmetic(op, &left, &right)?,
+        BinaryOp::Concat => match (left, right) {
+            (Value::Array(a), Value::Array(b)) => Value::Array([a, b].concat().into()),
+            (Value::Array(a), item) => Value::Array([&a[..], &[item]].concat().into()),
+            (item, Value::Array(b)) => Value::Array([&[item], &b[..]].concat().into()),
+            (left, right) => Value::Text(format!("{}{}", left, right)),
+        },
+        BinaryOp::JsonGet | BinaryOp::JsonGetText => json_get(op, &left, &right)?,
+        BinaryOp::Overlaps | BinaryOp::Contains | BinaryOp::ContainedBy
+            if matches!(left, Value::Array(_)) || matches!(right, Value::Array(_)) =>
+        {
+            eval_containment(op, left, right)?
+        }
+        BinaryOp::Overlaps | BinaryOp::Contains | BinaryOp::ContainedBy | BinaryOp::Distance => {
+            eval_geometric(op, &left, &right)?
+        }
+    };
+    Ok(result)
+}
+
+// 配列の @> は右の要素がすべて左にあるか、&& は共通の要素があるか。NULL の要素はどの要素とも等しくない。
+// 配列でない側の文字列は、もう一方と同じ型の配列として読む
+fn eval_containment(op: BinaryOp, left: Value, right: Value) -> Result<Value, Error> {
+    let (left, right) = match op {
+        BinaryOp::ContainedBy => (right, left),
+        _ => (left, right),
+    };
+    let symbol = op_symbol(op);
+    let (left_type, right_type) = (left.data_type(), right.data_type());
+    let a = function::array_arg(symbol, left, right_type.and_then(DataType::element))?;
+    let b = function::array_arg(symbol, right, left_type.and_then(DataType::element))?;
+    let contains = |item: &Value| -> Result<bool, Error> {
+        for other in &a {
+            if !item.is_null() && !other.is_null() && compare(op, item, other)?.is_eq() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    };
+    let any = op == BinaryOp::Overlaps;
+    for item in &b {
+        if contains(item)? == any {
+            return Ok(Value::Boolean(any));
+        }
+    }
+    Ok(Value::Boolean(!any))
+}
+
+// 点は幅のない矩形として比べる。文字列は BOX か POINT として読めるほうで読む
+fn eval_geometric(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, Error> {
+    let (Some(a), Some(b)) = (shape(left), shape(right)) else {
+        return Err(undefined(op, left, right));
+    };
+    Ok(match op {
+        BinaryOp::Overlaps => Value::Boolean(a.overlaps(&b)),
+        BinaryOp::Contains => Value::Boolean(a.contains(&b)),
+        BinaryOp::ContainedBy => Value::Boolean(b.contains(&a)),
+        _ => Value::Real(a.distance(&b)),
+    })
+}
+
+pub(crate) fn shape(value: &Value) -> Option<Rect> {
+    match value {
+        Value::Text(s) => s.parse().or_else(|_| s.parse().map(Rect::point)).ok(),
+        value => value.rect(),
+    }
+}
+
+// j -> key は JSON のまま、j ->> key は文字列にして返す。JSON の null は ->> では NULL になる。
+// key は文字列ならオブジェクトのキーで、$ で始まればパス。整数なら配列の位置
+fn json_get(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, Error> {
+    let json = read_json(left).unwrap_or_else(|| Err(undefined(op, left, right)))?;
+    let path = match right {
+        Value::Text(s) if s.starts_with('$') => {
+            json::parse_path(s).ok_or_else(|| Error::InvalidJsonPath(s.clone()))?
+        }
+        Value::Text(s) => vec![PathStep::Key(s.clone())],
+        Value::Integer(n) | Value::BigInt(n) => vec![PathStep::Index(*n)],
+        _ => return Err(undefined(op, left, right)),
+    };
+    let value = match json.get_path(&path) {
+        None => Value::Null,
+        Some(found) if op == BinaryOp::JsonGet => Value::Json(found.to_string().into()),
+        Some(Json::Null) => Value::Null,
+        Some(Json::String(s)) => Value::Text(s.clone()),
+        Some(found) => Value::Text(found.to_string()),
+    };
+    Ok(value)
+}
+
+// 片方が REAL なら REAL、そうでなく片方が DECIMAL なら DECIMAL、片方が BIGINT なら BIGINT で計算する
+pub(super) fn eval_arithmetic(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, Error> {
+    if matches!(left, Value::Interval(_)) || matches!(right, Value::Interval(_)) {
+        return eval_interval(op, left, right);
+    }
+    match (left, right) {
+        (Value::Real(_), _) | (_, Value::Real(_)) => {
+            let (Some(a), Some(b)) = (left.as_f64(), right.as_f64()) else {
+                return Err(undefined(op, left, right));
+            };
+            let result = match op {
+                BinaryOp::Plus => a + b,
+                BinaryOp::Minus => a - b,
+                BinaryOp::Multiply => a * b,
+                _ if b == 0.0 => return Err(Error::DivisionByZero),
+                BinaryOp::Divide => a / b,
+                _ => a % b,
+            };
+            if result.is_infinite() && a.is_finite() && b.is_finite() {
+                return Err(Error::FloatOutOfRange);
+            }
+            Ok(Value::Real(result))
+        }
+        (Value::Decimal(_), _) | (_, Value::Decimal(_)) => {
+            let (Some(a), Some(b)) = (to_decimal(left)?, to_decimal(right)?) else {
+                return Err(undefined(op, left, right));
+            };
+            let result = match op {
+                BinaryOp::Plus => a.checked_add(b),
+                BinaryOp::Minus => a.checked_sub(b),
+                BinaryOp::Multiply => a.checked_mul(b),
+                _ if b.mantissa() == 0 => return Err(Error::DivisionByZero),
+                BinaryOp::Divide => a.checked_div(b),
+                _ => a.checked_rem(b),
+            };
+            Ok(Value::Decimal(result.ok_or(Error::NumericOutOfRange)?))
+        }
+        (Value::Integer(a) | Value::BigInt(a), Value::Integer(b) | Value::BigInt(b)) => {
+            let result = match op {
+                BinaryOp::Plus => a.checked_add(*b),
+                BinaryOp::Minus => a.checked_sub(*b),
+                BinaryOp::Multiply => a.checked_mul(*b),
+                _ if *b == 0 => return Err(Error::DivisionByZero),
+                // 整数の割り算は 0 の方向に切り捨てる
+                BinaryOp::Divide => a.checked_div(*b),
+                // i64::MIN % -1 もあふれずに 0
+                _ => Some(a.checked_rem(*b).unwrap_or(0)),
+            };
+            let result = result.ok_or(Error::IntegerOutOfRange)?;
+            if matches!(left, Value::BigInt(_)) || matches!(right, Value::BigInt(_)) {
+                Ok(Value::BigInt(result))
+            } else {
+                Ok(Value::Integer(int32(result)?))
+            }
+        }
+        // 日付に整数を足し引きすると日をずらし、日付どうしの差は日数
+        (Value::Date(d), Value::Integer(n) | Value::BigInt(n))
+            if matches!(op, BinaryOp::Plus | BinaryOp::Minus) =>
+        {
+            let days = if op == BinaryOp::Plus {
+                Some(*n)
+            } else {
+                n.checked_neg()
+            };
+            days.and_then(|days| d.checked_add_days(days))
+                .map(Value::Date)
+                .ok_or(Error::DateOutOfRange)
+        }
+        (Value::Integer(n) | Value::BigInt(n), Value::Date(d)) if op == BinaryOp::Plus => d
+            .checked_add_days(*n)
+            .map(Value::Date)
+            .ok_or(Error::DateOutOfRange),
+        (Value::Date(a), Value::Date(b)) if op == BinaryOp::Minus => {
+            Ok(Value::Integer(a.days() as i64 - b.days() as i64))
+        }
+        // TIMESTAMP どうしの差は、日と時刻に分けた間隔
+        (Value::Date(_) | Value::Timestamp(_), Value::Date(_) | Value::Timestamp(_))
+            if op == BinaryOp::Minus =>
+        {
+            Ok(Value::Interval(
+                left.timestamp().unwrap().since(right.timestamp().unwrap()),
+            ))
+        }
+        _ => Err(undefined(op, left, right)),
+    }
+}
+
+// 間隔どうしの足し引きと、日時に間隔を足し引きしたものと、間隔を数で掛けたり割ったりしたもの。
+// DATE に足すと TIMESTAMP になり、TIME に足すと日を無視して 0 時で折り返す
+fn eval_interval(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, Error> {
+    let signed = |i: &Interval| match op {
+        BinaryOp::Minus => i.checked_neg().ok_or(Error::IntervalOutOfRange),
+        _ => Ok(*i),
+    };
+    let additive = matches!(op, BinaryOp::Plus | BinaryOp::Minus);
+    match (left, right) {
+        (Value::Interval(a), Value::Interval(b)) if additive => a
+            .checked_add(signed(b)?)
+            .map(Value::Interval)
+            .ok_or(Error::IntervalOutOfRange),
+        (Value::Date(_) | Value::Timestamp(_), Value::Interval(i)) if additive => left
+            .timestamp()
+            .unwrap()
+            .checked_add_interval(signed(i)?)
+            .map(Value::Timestamp)
+            .ok_or(Error::DateOutOfRange),
+        (Value::Interval(i), Value::Date(_) | Value::Timestamp(_)) if op == BinaryOp::Plus => {
+            eval_interval(op, right, left)
+        }
+        (Value::Time(t), Value::Interval(i)) if additive => {
+            Ok(Value::Time(t.wrapping_add_interval(signed(i)?)))
+        }
+        (Value::Interval(_), Value::Time(_)) if op == BinaryOp::Plus => {
+            eval_interval(op, right, left)
+        }
+        (Value::Interval(i), n) if op == BinaryOp::Multiply && n.is_numeric() => i
+            .checked_mul(n.as_f64().unwrap())
+            .map(Value::Interval)
+            .ok_or(Error::IntervalOutOfRange),
+        (n, Value::Interval(_)) if op == BinaryOp::Multiply && n.is_numeric() => {
+            eval_interval(op, right, left)
+        }
+        (Value::Interval(i), n) if op == BinaryOp::Divide && n.is_numeric() => {
+            let n = n.as_f64().unwrap();
+            if n == 0.0 {
+                return Err(Error::DivisionByZero);
+            }
+            i.checked_mul(1.0 / n)
+                .map(Value::Interval)
+                .ok_or(Error::IntervalOutOfRange)
+        }
+        _ => Err(undefined(op, left, right)),
+    }
+}
+
+// 整数は DECIMAL に直す。数値でなければ None
+fn to_decimal(value: &Value) -> Result<Option<Decimal>, Error> {
+    match value {
+        Value::Decimal(d) => Ok(Some(*d)),
+        Value::Integer(n) | Value::BigInt(n) => Decimal::from_i64(*n)
+            .ok_or(Error::NumericOutOfRange)
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+// INTEGER は 32 bit に収まらなければならない
+fn int32(n: i64) -> Result<i64, Error> {
+    match i32::try_from(n) {
+        Ok(_) => Ok(n),
+        Err(_) => Err(Error::IntegerOutOfRange),
+    }
+}
+
+fn eval_unary(op: UnaryOp, value: Value) -> Result<Value, Error> {
+    match (op, value) {
+        (_, Value::Null) => Ok(Value::Null),
+        (UnaryOp::Not, Value::Boolean(b)) => Ok(Value::Boolean(!b)),
+        (UnaryOp::Minus, Value::Integer(n)) => Ok(Value::Integer(int32(
+            n.checked_neg().ok_or(Error::IntegerOutOfRange)?,
+        )?)),
+        (UnaryOp::Minus, Value::BigInt(n)) => Ok(Value::BigInt(
+            n.checked_neg().ok_or(Error::IntegerOutOfRange)?,
+        )),
+        (UnaryOp::Minus, Value::Real(f)) => Ok(Value::Real(-f)),
+        (UnaryOp::Minus, Value::Interval(i)) => Ok(Value::Interval(
+            i.checked_neg().ok_or(Error::IntervalOutOfRange)?,
+        )),
+        (UnaryOp::Minus, Value::Decimal(d)) => Ok(Value::Decimal(
+            d.checked_neg().ok_or(Error::NumericOutOfRange)?,
+        )),
+        (UnaryOp::Plus, value) if value.is_numeric() => Ok(value),
+        (op, value) => Err(Error::UndefinedUnaryOperator {
+            op: match op {
+                UnaryOp::Not => "NOT",
+                UnaryOp::Minus => "-",
+                UnaryOp::Plus => "+",
+            },
+            operand: value.type_name(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::tests::{int, text};
+
+    fn lit(value: Value) -> Expr {
+        Expr::Literal(value)
+    }
+
+    #[test]
+    fn test_eval() {
+        let row = vec![int(3), text("ab"), Value::Null];
+        let expr = Expr::binary(
+            BinaryOp::Multiply,
+            Expr::binary(BinaryOp::Plus, Expr::column(0), lit(int(1))),
+            lit(int(2)),
+        );
+        assert_eq!(expr.eval(&row).unwrap(), int(8));
+        let expr = Expr::binary(BinaryOp::Concat, Expr::column(1), Expr::column(0));
+        assert_eq!(expr.eval(&row).unwrap(), text("ab3"));
+        let expr = Expr::binary(BinaryOp::Plus, Expr::column(0), Expr::column(2));
+        assert_eq!(expr.eval(&row).unwrap(), Value::Null);
+        let expr = Expr::IsNull {
+            expr: Box::new(Expr::column(2)),
+            negated: false,
+        };
+        assert_eq!(expr.eval(&row).unwrap(), Value::Boolean(true));
+        let expr = Expr::InList {
+            expr: Box::new(Expr::column(0)),
+            list: vec![lit(int(1)), lit(int(3))],
+            negated: true,
+        };
+        assert_eq!(expr.eval(&row).unwrap(), Value::Boolean(false));
+        // NULL の項目があれば、一致しない限り NULL
+        for (list, negated, expected) in [
+            (vec![lit(int(1)), lit(Value::Null)], false, Value::Null),
+            (vec![lit(int(1)), lit(Value::Null)], true, Value::Null),
+            (
+                vec![lit(Value::Null), lit(int(3))],
+                false,
+                Value::Boolean(true),
+            ),
+            (
+                vec![lit(Value::Null), lit(int(3))],
+                true,
+                Value::Boolean(false),
+            ),
+        ] {
+            let expr = Expr::InList {
+                expr: Box::new(Expr::column(0)),
+                list,
+                negated,
+            };
+            assert_eq!(expr.eval(&row).unwrap(), expected);
+        }
+        let expr = Expr::Function {
+            func: Function::Upper,
+            args: vec![Expr::Function {
+                func: Function::Coalesce,
+                args: vec![Expr::column(2), Expr::column(1)],
+            }],
+        };
+        assert_eq!(expr.eval(&row).unwrap(), text("AB"));
+
+        let null = || lit(Value::Null);
+        let t = || lit(Value::Boolean(true));
+        let f = || lit(Value::Boolean(false));
+        for (op, left, right, expected) in [
+            (BinaryOp::And, f(), null(), Value::Boolean(false)),
+            (BinaryOp::And, t(), null(), Value::Null),
+            (BinaryOp::Or, null(), t(), Value::Boolean(true)),
+            (BinaryOp::Or, f(), null(), Value::Null),
+            (BinaryOp::Or, f(), f(), Value::Boolean(false)),
+        ] {
+            assert_eq!(Expr::binary(op, left, right).eval(&row).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let (t, f, n) = (Value::Boolean(true), Value::Boolean(false), Value::Null);
+        // 左と右の組み合わせごとの AND と OR
+        for (left, right, and, or) in [
+            (&t, &t, &t, &t),
+            (&t, &f, &f, &t),
+            (&t, &n, &n, &t),
+            (&f, &f, &f, &f),
+            (&f, &n, &f, &n),
+            (&n, &n, &n, &n),
+        ] {
+            for (l, r) in [(left, right), (right, left)] {
+                let eval = |op| Expr::binary(op, lit(l.clone()), lit(r.clone())).eval(&vec![]);
+                assert_eq!(eval(BinaryOp::And).unwrap(), *and, "{} AND {}", l, r);
+                assert_eq!(eval(BinaryOp::Or).unwrap(), *or, "{} OR {}", l, r);
+            }
+        }
+        let not = Expr::Unary {
+            op: UnaryOp::Not,
+            expr: Box::new(lit(n.clone())),
+        };
+        assert_eq!(not.eval(&vec![]).unwrap(), n);
+
+        // 比較は NULL があれば UNKNOWN、IS DISTINCT FROM は NULL どうしを等しいとみなす
+        for (left, right, eq, distinct) in [
+            (int(1), int(1), t.clone(), f.clone()),
+            (int(1), int(2), f.clone(), t.clone()),
+            (int(1), n.clone(), n.clone(), t.clone()),
+            (n.clone(), n.clone(), n.clone(), f.clone()),
+        ] {
+            let eval = |op| Expr::binary(op, lit(left.clone()), lit(right.clone())).eval(&vec![]);
+            assert_eq!(eval(BinaryOp::Eq).unwrap(), eq);
+            assert_eq!(eval(BinaryOp::IsDistinctFrom).unwrap(), distinct);
+            let not_distinct = Value::Boolean(distinct == f);
+            assert_eq!(eval(BinaryOp::IsNotDistinctFrom).unwrap(), not_distinct);
+        }
+        let expr = Expr::binary(
+            BinaryOp::IsDistinctFrom,
+            lit(int(1)),
+            lit(Value::Boolean(true)),
+        );
+        assert_eq!(
+            expr.eval(&vec![]).unwrap_err().to_string(),
+            "operator does not exist: integer IS DISTINCT FROM boolean"
+        );
+
+        // 文字列はもう一方の型として読んで比べる
+        let eq = |left, right| Expr::binary(BinaryOp::Eq, lit(left), lit(right)).eval(&vec![]);
+        assert_eq!(eq(text(" 12"), int(12)).unwrap(), t);
+        assert_eq!(eq(Value::Real(0.5), text("5e-1")).unwrap(), t);
+        assert_eq!(eq(Value::Boolean(true), text("yes")).unwrap(), t);
+        assert_eq!(
+            eq(int(1), text("a")).unwrap_err().to_string(),
+            "invalid input syntax for type integer: \"a\""
+        );
+
+        // 絞り込みでは UNKNOWN は偽
+        let expr = Expr::binary(BinaryOp::Eq, lit(int(1)), lit(n));
+        assert!(!expr.eval_predicate(&vec![]).unwrap());
+    }
+
+    #[test]
+    fn test_eval_batch() {
+        let rows = vec![
+            vec![int(3), text("ab")],
+            vec![Value::Null, text("c")],
+            vec![int(-1), Value::Null],
+        ];
+        let batch = Batch::from_rows(rows.clone());
+        let exprs = [
+            Expr::binary(BinaryOp::Plus, Expr::column(0), lit(int(1))),
+            Expr::Unary {
+                op: UnaryOp::Minus,
+                expr: Box::new(Expr::column(0)),
+            },
+            Expr::IsNull {
+                expr: Box::new(Expr::column(1)),
+                negated: true,
+            },
+            Expr::InList {
+                expr: Box::new(Expr::column(0)),
+                list: vec![lit(int(-1)), Expr::column(0)],
+                negated: false,
+            },
+            Expr::Function {
+                func: Function::Coalesce,
+                args: vec![Expr::column(1), lit(text("z"))],
+            },
+        ];
+        // 行ごとに評価したときと同じ結果になる
+        for expr in &exprs {
+            let expected = rows
+                .iter()
+                .map(|row| expr.eval(row))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(expr.eval_batch(&batch).unwrap(), expected);
+        }
+        let predicate = Expr::binary(BinaryOp::Gt, Expr::column(0), lit(int(0)));
+        let mask = predicate.eval_predicate_batch(&batch).unwrap();
+        assert_eq!(mask, vec![true, false, false]);
+        assert_eq!(batch.select(&mask).into_rows(), vec![rows[0].clone()]);
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let row = vec![int(1), text("a")];
+        let expr = Expr::binary(BinaryOp::Plus, Expr::column(0), Expr::column(1));
+        assert_eq!(
+            expr.eval(&row).unwrap_err().to_string(),
+            "operator does not exist: integer + text"
+        );
+        let expr = Expr::binary(BinaryOp::Divide, Expr::column(0), lit(int(0)));
+        assert!(matches!(expr.eval(&row), Err(Error::DivisionByZero)));
+        let expr = Expr::binary(BinaryOp::Plus, lit(int(i64::MAX)), Expr::column(0));
+        assert!(matches!(expr.eval(&row), Err(Error::IntegerOutOfRange)));
+        let expr = Expr::Function {
+            func: Function::Length,
+            args: vec![Expr::column(0)],
+        };
+        assert_eq!(
+            expr.eval(&row).unwrap_err().to_string(),
+            "function length(integer) does not exist"
+        );
+        assert!(matches!(
+            Expr::column(0).eval_predicate(&row),
+            Err(Error::NotBoolean(ty)) if ty == "integer"
+        ));
+    }
+}
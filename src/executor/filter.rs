@@ -0,0 +1,50 @@
+use crate::heap::RecordId;
+
+use super::expr::Expr;
+use super::{Batch, BoxExecutor, Error, ExecContext, Executor, Row};
+
+// 条件が真になる行だけを通す
+pub struct Filter<'a> {
+    input: BoxExecutor<'a>,
+    predicate: &'a Expr,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(input: BoxExecutor<'a>, predicate: &'a Expr) -> Self {
+        Self { input, predicate }
+    }
+}
+
+impl Executor for Filter<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        while let Some(row) = self.input.next(ctx)? {
+            if self.predicate.eval_predicate(&row)? {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_batch(&mut self, ctx: &mut ExecContext) -> Result<Option<Batch>, Error> {
+        while let Some(batch) = self.input.next_batch(ctx)? {
+            let mask = self.predicate.eval_predicate_batch(&batch)?;
+            let batch = batch.select(&mask);
+            if !batch.is_empty() {
+                return Ok(Some(batch));
+            }
+        }
+        Ok(None)
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.input.record_id()
+    }
+
+    fn relation(&self) -> Option<&str> {
+        self.input.relation()
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use crate::btree;
+use crate::catalog::{self, Dictionary, GeneratedColumn};
+use crate::enum_type::EnumType;
+use crate::heap::RecordId;
+use crate::storage::TableAccess;
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::scan::decode_row;
+use super::{Error, ExecContext, Executor, Row};
+
+// 全文インデックスの走査
+//
+// terms のどれかを含む行を返す。語ごとにキーを引いて RecordId を集め、重なりを除いてページの順に読む。
+// インデックスには消した版を指すキーも残っているので、版が見えるかはヒープで確かめる
+pub struct FulltextScan<'a> {
+    table: &'a str,
+    storage: Arc<dyn TableAccess>,
+    // 行を読むときに計算する生成列
+    // テーブルの列の数
+    width: usize,
+    virtual_columns: Vec<GeneratedColumn>,
+    dictionaries: Vec<Arc<Dictionary>>,
+    enums: Vec<Arc<EnumType>>,
+    rids: std::vec::IntoIter<RecordId>,
+    predicate: Option<&'a Expr>,
+    rid: Option<RecordId>,
+}
+
+impl<'a> FulltextScan<'a> {
+    pub fn new(
+        ctx: &mut ExecContext,
+        table: &'a str,
+        index: &'a str,
+        terms: &[String],
+        predicate: Option<&'a Expr>,
+    ) -> Result<Self, Error> {
+        let (table_name, index_name) = (table, index);
+        let table = ctx
+            .catalog
+            .table(table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        let index = table
+            .index(index)
+            .ok_or_else(|| Error::IndexNotFound(index.to_string()))?;
+        if let Some(io) = ctx.io {
+            io.update(table_name, |io| io.index_scans += 1);
+            io.update(index_name, |io| io.index_scans += 1);
+        }
+        let btree = index.btree;
+        let mut rids = vec![];
+        for term in terms {
+            let mut prefix = vec![];
+            btree::key::encode(&[Value::Text(term.clone())], &mut prefix);
+            let mut scan =
+                ctx.count_reads(index_name, |ctx| btree.scan(ctx.bufmgr, Some(&prefix)))?;
+            loop {
+                ctx.check_interrupt()?;
+                let Some((key, _)) = ctx.count_reads(index_name, |ctx| scan.next(ctx.bufmgr))?
+                else {
+                    break;
+                };
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+                rids.push(catalog::decode_record_id(&key));
+            }
+        }
+        rids.sort_by_key(|rid| (rid.page_id.to_u64(), rid.slot));
+        rids.dedup();
+        Ok(Self {
+            table: table_name,
+            storage: table.storage.clone(),
+            width: table.columns.len(),
+            virtual_columns: table.virtual_columns().cloned().collect(),
+            dictionaries: table.dictionaries.clone(),
+            enums: table.enum_types(),
+            rids: rids.into_iter(),
+            predicate,
+            rid: None,
+        })
+    }
+}
+
+impl Executor for FulltextScan<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        for rid in self.rids.by_ref() {
+            ctx.check_interrupt()?;
+            let storage = &self.storage;
+            let Some((header, bytes)) =
+                ctx.count_reads(self.table, |ctx| storage.get(ctx.bufmgr, rid))?
+            else {
+                continue;
+            };
+            if !ctx.visible(&header) {
+                continue;
+            }
+            let row = decode_row(
+                &bytes,
+                self.width,
+                None,
+                &self.virtual_columns,
+                &self.dictionaries,
+                &self.enums,
+                rid,
+            )?;
+            match self.predicate {
+                Some(predicate) if !predicate.eval_predicate(&row)? => {}
+                _ => {
+                    self.rid = Some(rid);
+                    return Ok(Some(row));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.rid
+    }
+
+    fn relation(&self) -> Option<&str> {
+        Some(self.table)
+    }
+}
@@ -0,0 +1,1183 @@
+use std::fmt;
+
+use crate::collation::Collation;
+use crate::datetime::{Field, Timestamp};
+use crate::decimal::{Decimal, DIVISION_SCALE};
+use crate::fulltext;
+use crate::json::{self, Json};
+use crate::regex::Regex;
+use crate::types::{Coercion, DataType, Value};
+use crate::uuid::Uuid;
+
+use super::expr::{cast_error, eval_arithmetic, eval_binary};
+use super::Error;
+use crate::sql::ast::BinaryOp;
+
+// 組み込みのスカラー関数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Upper,
+    Lower,
+    Length,
+    Substr,
+    Trim,
+    Ltrim,
+    Rtrim,
+    Replace,
+    Concat,
+    Instr,
+    Abs,
+    Round,
+    Ceil,
+    Floor,
+    Mod,
+    Power,
+    Coalesce,
+    Now,
+    CurrentDate,
+    // 1 つめの引数は単位の文字列
+    Extract,
+    DateTrunc,
+    DateAdd,
+    DateDiff,
+    // LIKE、ILIKE、REGEXP は関数の呼び出しとして読む。3 つめの引数は ESCAPE の文字
+    Like,
+    ILike,
+    Regexp,
+    // CAST(expr AS type) は型の名前を 2 つめの引数の文字列にする
+    Cast,
+    // 照合順のキー。BINARY でない照合順の列を比べるときに、計画を作る側が両辺を包む
+    CollationKey,
+    JsonExtract,
+    // MATCH(col, ...) AGAINST(query)。1 つめの引数が問い合わせで、関連度を返す
+    Match,
+    UuidV4,
+    UuidV7,
+    // ARRAY[...] は関数の呼び出しとして読む
+    Array,
+    // a[i] は 2 引数の呼び出しにする。i は 1 から数える
+    Subscript,
+    // a op ANY (arr)、a op ALL (arr) は演算子を 1 つめの引数の文字列にする
+    Any,
+    All,
+    Cardinality,
+}
+
+// 関数の表の 1 行
+struct Builtin {
+    name: &'static str,
+    func: Function,
+    min_args: usize,
+    max_args: usize,
+    // 引数のどれかが NULL なら呼ばずに NULL を返す
+    strict: bool,
+    // 同じ引数でも呼ぶたびに値が変わりうる
+    volatile: bool,
+    call: fn(&'static str, Vec<Value>) -> Result<Value, Error>,
+}
+
+impl Builtin {
+    const fn new(
+        name: &'static str,
+        func: Function,
+        min_args: usize,
+        max_args: usize,
+        call: fn(&'static str, Vec<Value>) -> Result<Value, Error>,
+    ) -> Self {
+        Self {
+            name,
+            func,
+            min_args,
+            max_args,
+            strict: true,
+            volatile: false,
+            call,
+        }
+    }
+
+    const fn lenient(self) -> Self {
+        Self {
+            strict: false,
+            ..self
+        }
+    }
+
+    const fn volatile(self) -> Self {
+        Self {
+            volatile: true,
+            ..self
+        }
+    }
+}
+
+const VARIADIC: usize = usize::MAX;
+
+// 名前から関数を引く表。別名は同じ関数をもう一度載せ、最初に載せた名前を正式な名前にする
+const BUILTINS: &[Builtin] = &[
+    Builtin::new("upper", Function::Upper, 1, 1, upper),
+    Builtin::new("lower", Function::Lower, 1, 1, lower),
+    Builtin::new("length", Function::Length, 1, 1, length),
+    Builtin::new("char_length", Function::Length, 1, 1, length),
+    Builtin::new("substr", Function::Substr, 2, 3, substr),
+    Builtin::new("substring", Function::Substr, 2, 3, substr),
+    Builtin::new("trim", Function::Trim, 1, 2, trim),
+    Builtin::new("btrim", Function::Trim, 1, 2, trim),
+    Builtin::new("ltrim", Function::Ltrim, 1, 2, ltrim),
+    Builtin::new("rtrim", Function::Rtrim, 1, 2, rtrim),
+    Builtin::new("replace", Function::Replace, 3, 3, replace),
+    Builtin::new("concat", Function::Concat, 1, VARIADIC, concat).lenient(),
+    Builtin::new("instr", Function::Instr, 2, 2, instr),
+    Builtin::new("strpos", Function::Instr, 2, 2, instr),
+    Builtin::new("abs", Function::Abs, 1, 1, abs),
+    Builtin::new("round", Function::Round, 1, 2, round),
+    Builtin::new("ceil", Function::Ceil, 1, 1, ceil),
+    Builtin::new("ceiling", Function::Ceil, 1, 1, ceil),
+    Builtin::new("floor", Function::Floor, 1, 1, floor),
+    Builtin::new("mod", Function::Mod, 2, 2, modulo),
+    Builtin::new("power", Function::Power, 2, 2, power),
+    Builtin::new("pow", Function::Power, 2, 2, power),
+    Builtin::new("coalesce", Function::Coalesce, 1, VARIADIC, coalesce).lenient(),
+    Builtin::new("now", Function::Now, 0, 0, now).volatile(),
+    Builtin::new("current_timestamp", Function::Now, 0, 0, now).volatile(),
+    Builtin::new("current_date", Function::CurrentDate, 0, 0, current_date).volatile(),
+    Builtin::new("extract", Function::Extract, 2, 2, extract),
+    Builtin::new("date_trunc", Function::DateTrunc, 2, 2, date_trunc),
+    Builtin::new("date_add", Function::DateAdd, 2, 2, date_add),
+    Builtin::new("date_diff", Function::DateDiff, 3, 3, date_diff),
+    Builtin::new("datediff", Function::DateDiff, 3, 3, date_diff),
+    Builtin::new("like", Function::Like, 2, 3, like),
+    Builtin::new("ilike", Function::ILike, 2, 3, ilike),
+    Builtin::new("regexp_like", Function::Regexp, 2, 2, regexp_like),
+    Builtin::new("cast", Function::Cast, 2, 2, cast),
+    Builtin::new("collation_key", Function::CollationKey, 2, 2, collation_key),
+    Builtin::new("json_extract", Function::JsonExtract, 2, 2, json_extract),
+    Builtin::new("match", Function::Match, 2, VARIADIC, match_against).lenient(),
+    Builtin::new("uuid_v4", Function::UuidV4, 0, 0, uuid_v4).volatile(),
+    Builtin::new("gen_random_uuid", Function::UuidV4, 0, 0, uuid_v4).volatile(),
+    Builtin::new("uuid_v7", Function::UuidV7, 0, 0, uuid_v7).volatile(),
+    Builtin::new("array", Function::Array, 0, VARIADIC, array).lenient(),
+    Builtin::new("subscript", Function::Subscript, 2, 2, subscript),
+    Builtin::new("any", Function::Any, 3, 3, any).lenient(),
+    Builtin::new("all", Function::All, 3, 3, all).lenient(),
+    Builtin::new("cardinality", Function::Cardinality, 1, 1, cardinality),
+];
+
+impl Function {
+    pub fn lookup(name: &str) -> Option<Self> {
+        BUILTINS.iter().find(|b| b.name == name).map(|b| b.func)
+    }
+
+    fn builtin(self) -> &'static Builtin {
+        BUILTINS.iter().find(|b| b.func == self).unwrap()
+    }
+
+    pub fn name(self) -> &'static str {
+        self.builtin().name
+    }
+
+    // 同じ引数でも呼ぶたびに値が変わりうるか。計画を作るときに畳まない
+    pub fn is_volatile(self) -> bool {
+        self.builtin().volatile
+    }
+
+    // 引数の個数として受け付けるか
+    pub fn accepts(self, num_args: usize) -> bool {
+        let builtin = self.builtin();
+        (builtin.min_args..=builtin.max_args).contains(&num_args)
+    }
+
+    pub(super) fn call(self, args: Vec<Value>) -> Result<Value, Error> {
+        let builtin = self.builtin();
+        if builtin.strict && args.iter().any(Value::is_null) {
+            return Ok(Value::Null);
+        }
+        (builtin.call)(builtin.name, args)
+    }
+}
+
+type Body = dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync;
+
+// 利用者が Rust のクロージャで定義するスカラー関数
+//
+// 引数は宣言した型に列に入れるときと同じ規則で変換してから渡し、返した値も戻り値の型に変換する。
+// 組み込みの関数と同じく、lenient にしなければ引数のどれかが NULL のときは呼ばずに NULL を返す
+pub struct ScalarFunction {
+    name: String,
+    arg_types: Vec<DataType>,
+    return_type: DataType,
+    strict: bool,
+    volatile: bool,
+    body: Box<Body>,
+}
+
+impl ScalarFunction {
+    pub fn new(
+        name: &str,
+        arg_types: Vec<DataType>,
+        return_type: DataType,
+        body: impl Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_lowercase(),
+            arg_types,
+            return_type,
+            strict: true,
+            volatile: false,
+            body: Box::new(body),
+        }
+    }
+
+    pub fn lenient(self) -> Self {
+        Self {
+            strict: false,
+            ..self
+        }
+    }
+
+    pub fn volatile(self) -> Self {
+        Self {
+            volatile: true,
+            ..self
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arg_types(&self) -> &[DataType] {
+        &self.arg_types
+    }
+
+    pub fn return_type(&self) -> DataType {
+        self.return_type.clone()
+    }
+
+    pub fn is_volatile(&self) -> bool {
+        self.volatile
+    }
+
+    pub(super) fn call(&self, args: Vec<Value>) -> Result<Value, Error> {
+        let args = args
+            .into_iter()
+            .zip(&self.arg_types)
+            .map(|(value, ty)| {
+                ty.coerce(value.clone())
+                    .map_err(|err| cast_error(err, &value, ty))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.strict && args.iter().any(Value::is_null) {
+            return Ok(Value::Null);
+        }
+        let failed = |message| Error::UserFunctionFailed {
+            name: self.name.clone(),
+            message,
+        };
+        let value = (self.body)(&args).map_err(failed)?;
+        let actual = value.type_name();
+        self.return_type.coerce(value).map_err(|_| {
+            failed(format!(
+                "returned type {} but {} was declared",
+                actual,
+                self.return_type.name()
+            ))
+        })
+    }
+}
+
+impl fmt::Debug for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScalarFunction")
+            .field("name", &self.name)
+            .field("arg_types", &self.arg_types)
+            .field("return_type", &self.return_type)
+            .finish_non_exhaustive()
+    }
+}
+
+// 同じ名前の関数は 1 つしか登録できないので、名前で比べる
+impl PartialEq for ScalarFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+fn undefined(name: &'static str, arg: &Value) -> Error {
+    Error::UndefinedFunction {
+        name,
+        arg: arg.type_name(),
+    }
+}
+
+fn text_arg<'a>(name: &'static str, value: &'a Value) -> Result<&'a str, Error> {
+    match value {
+        Value::Text(s) => Ok(s),
+        value => Err(undefined(name, value)),
+    }
+}
+
+fn integer_arg(name: &'static str, value: &Value) -> Result<i64, Error> {
+    match value {
+        Value::Integer(n) | Value::BigInt(n) => Ok(*n),
+        value => Err(undefined(name, value)),
+    }
+}
+
+fn upper(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    Ok(Value::Text(text_arg(name, &args[0])?.to_uppercase()))
+}
+
+fn lower(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    Ok(Value::Text(text_arg(name, &args[0])?.to_lowercase()))
+}
+
+// 文字列は文字数、バイト列はバイト数
+fn length(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let n = match &args[0] {
+        Value::Text(s) => s.chars().count(),
+        Value::Blob(b) => b.len(),
+        value => return Err(undefined(name, value)),
+    };
+    Ok(Value::Integer(n as i64))
+}
+
+// start 文字目から len 文字。位置は 1 から数え、1 より前から始めた分も len に数える
+fn substr(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let s = text_arg(name, &args[0])?;
+    let start = integer_arg(name, &args[1])? as i128;
+    let end = match args.get(2) {
+        Some(len) => {
+            let len = integer_arg(name, len)?;
+            if len < 0 {
+                return Err(Error::NegativeSubstringLength);
+            }
+            start + len as i128
+        }
+        None => i128::MAX,
+    };
+    let from = start.max(1);
+    let taken = (end - from).clamp(0, usize::MAX as i128) as usize;
+    Ok(Value::Text(
+        s.chars().skip(from as usize - 1).take(taken).collect(),
+    ))
+}
+
+// 2 つめの引数の文字を取り除く。省くと空白
+fn trim_with(
+    name: &'static str,
+    args: &[Value],
+    trim: impl FnOnce(&str, &[char]) -> String,
+) -> Result<Value, Error> {
+    let s = text_arg(name, &args[0])?;
+    let chars = match args.get(1) {
+        Some(chars) => text_arg(name, chars)?.chars().collect(),
+        None => vec![' '],
+    };
+    Ok(Value::Text(trim(s, &chars)))
+}
+
+fn trim(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    trim_with(name, &args, |s, chars| s.trim_matches(chars).to_string())
+}
+
+fn ltrim(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    trim_with(name, &args, |s, chars| {
+        s.trim_start_matches(chars).to_string()
+    })
+}
+
+fn rtrim(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    trim_with(name, &args, |s, chars| {
+        s.trim_end_matches(chars).to_string()
+    })
+}
+
+// 空の文字列は置き換えない
+fn replace(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let s = text_arg(name, &args[0])?;
+    let from = text_arg(name, &args[1])?;
+    let to = text_arg(name, &args[2])?;
+    if from.is_empty() {
+        return Ok(Value::Text(s.to_string()));
+    }
+    Ok(Value::Text(s.replace(from, to)))
+}
+
+// NULL は飛ばし、文字列でない値は文字列にしてつなげる
+fn concat(_: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let mut result = String::new();
+    for arg in args.iter().filter(|arg| !arg.is_null()) {
+        result.push_str(&arg.to_string());
+    }
+    Ok(Value::Text(result))
+}
+
+// 最初に現れる文字の位置。1 から数え、なければ 0
+fn instr(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let s = text_arg(name, &args[0])?;
+    let sub = text_arg(name, &args[1])?;
+    let position = s.find(sub).map_or(0, |i| s[..i].chars().count() + 1);
+    Ok(Value::Integer(position as i64))
+}
+
+fn numeric_arg<'a>(name: &'static str, value: &'a Value) -> Result<&'a Value, Error> {
+    match value {
+        Value::Integer(_) | Value::BigInt(_) | Value::Real(_) | Value::Decimal(_) => Ok(value),
+        value => Err(undefined(name, value)),
+    }
+}
+
+// 数値の関数は引数と同じ型を返す
+fn abs(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let abs = |n: &i64| n.checked_abs().ok_or(Error::IntegerOutOfRange);
+    Ok(match &args[0] {
+        Value::Integer(n) => Value::Integer(abs(n)?),
+        Value::BigInt(n) => Value::BigInt(abs(n)?),
+        Value::Real(f) => Value::Real(f.abs()),
+        // 絶対値は精度に収まる
+        Value::Decimal(d) if d.mantissa() < 0 => Value::Decimal(d.checked_neg().unwrap()),
+        Value::Decimal(d) => Value::Decimal(*d),
+        value => return Err(undefined(name, value)),
+    })
+}
+
+// 小数点以下 2 つめの引数の桁に 0 から遠いほうへ丸める。負の桁なら整数部の下の桁を丸める
+fn round(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let digits = match args.get(1) {
+        Some(digits) => integer_arg(name, digits)?,
+        None => 0,
+    };
+    let round_integer = |n: i64| {
+        if digits >= 0 {
+            return Ok(n);
+        }
+        let unit = 10i128.pow((-digits).min(19) as u32);
+        let half = if n < 0 { -unit / 2 } else { unit / 2 };
+        i64::try_from((n as i128 + half) / unit * unit).map_err(|_| Error::IntegerOutOfRange)
+    };
+    Ok(match &args[0] {
+        Value::Integer(n) => Value::Integer(round_integer(*n)?),
+        Value::BigInt(n) => Value::BigInt(round_integer(*n)?),
+        Value::Real(f) => {
+            let unit = 10f64.powi(digits.clamp(-400, 400) as i32);
+            let rounded = (f * unit).round() / unit;
+            // 桁が多すぎて計算できなければそのまま
+            Value::Real(if rounded.is_finite() { rounded } else { *f })
+        }
+        // 今より細かい桁に丸めて精度からあふれるなら、値はそのまま
+        Value::Decimal(d) => Value::Decimal(
+            d.round_to(digits)
+                .or((digits >= d.scale() as i64).then_some(*d))
+                .ok_or(Error::NumericOutOfRange)?,
+        ),
+        value => return Err(undefined(name, value)),
+    })
+}
+
+fn ceil(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    Ok(match numeric_arg(name, &args[0])? {
+        Value::Real(f) => Value::Real(f.ceil()),
+        Value::Decimal(d) => Value::Decimal(d.ceil()),
+        value => value.clone(),
+    })
+}
+
+fn floor(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    Ok(match numeric_arg(name, &args[0])? {
+        Value::Real(f) => Value::Real(f.floor()),
+        Value::Decimal(d) => Value::Decimal(d.floor()),
+        value => value.clone(),
+    })
+}
+
+// a % b と同じ。符号は割られる数と同じ
+fn modulo(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let a = numeric_arg(name, &args[0])?;
+    let b = numeric_arg(name, &args[1])?;
+    eval_arithmetic(BinaryOp::Modulo, a, b)
+}
+
+// 引数に DECIMAL があれば DECIMAL、なければ REAL を返す。DECIMAL の整数乗は掛け算で正確に求める
+fn power(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let base = numeric_arg(name, &args[0])?;
+    let exponent = numeric_arg(name, &args[1])?;
+    let (a, b) = (base.as_f64().unwrap(), exponent.as_f64().unwrap());
+    if a == 0.0 && b < 0.0 {
+        return Err(Error::ZeroRaisedToNegativePower);
+    }
+    if a < 0.0 && b.fract() != 0.0 {
+        return Err(Error::ComplexPower);
+    }
+    let decimal = match (base, exponent) {
+        (Value::Real(_), _) | (_, Value::Real(_)) => false,
+        (Value::Decimal(_), _) | (_, Value::Decimal(_)) => true,
+        _ => false,
+    };
+    if !decimal {
+        let result = a.powf(b);
+        if result.is_infinite() {
+            return Err(Error::FloatOutOfRange);
+        }
+        return Ok(Value::Real(result));
+    }
+    let to_decimal = |value: &Value| match value {
+        Value::Decimal(d) => Ok(*d),
+        Value::Integer(n) | Value::BigInt(n) => {
+            Decimal::from_i64(*n).ok_or(Error::NumericOutOfRange)
+        }
+        _ => unreachable!(),
+    };
+    let (base, exponent) = (to_decimal(base)?, to_decimal(exponent)?);
+    let result = if exponent.is_integer() {
+        integer_power(base, exponent.round())
+    } else {
+        Decimal::from_f64(a.powf(b), DIVISION_SCALE)
+    };
+    Ok(Value::Decimal(result.ok_or(Error::NumericOutOfRange)?))
+}
+
+// 2 乗を繰り返して base^exponent を求める。負の指数なら逆数
+fn integer_power(base: Decimal, exponent: i64) -> Option<Decimal> {
+    let one = Decimal::from_i64(1).unwrap();
+    let (mut result, mut square, mut n) = (one, base, exponent.unsigned_abs());
+    while n > 0 {
+        if n & 1 == 1 {
+            result = result.checked_mul(square)?;
+        }
+        n >>= 1;
+        if n > 0 {
+            square = square.checked_mul(square)?;
+        }
+    }
+    if exponent < 0 {
+        one.checked_div(result)
+    } else {
+        Some(result)
+    }
+}
+
+fn coalesce(_: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    Ok(args
+        .into_iter()
+        .find(|v| !v.is_null())
+        .unwrap_or(Value::Null))
+}
+
+fn now(_: &'static str, _: Vec<Value>) -> Result<Value, Error> {
+    Ok(Value::Timestamp(Timestamp::now()))
+}
+
+fn current_date(_: &'static str, _: Vec<Value>) -> Result<Value, Error> {
+    Ok(Value::Date(Timestamp::now().date()))
+}
+
+fn extract(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    call_datetime(name, &args[0], &args[1], |value, field| {
+        match value {
+            Value::Time(t) => t.extract(field),
+            value => value.timestamp()?.extract(field),
+        }
+        .map(Value::Decimal)
+    })
+}
+
+fn date_trunc(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    if let Value::Time(_) = &args[1] {
+        return Err(undefined(name, &args[1]));
+    }
+    call_datetime(name, &args[0], &args[1], |value, field| {
+        value.timestamp()?.trunc(field).map(Value::Timestamp)
+    })
+}
+
+// 日時に間隔を足す。DATE に足すと TIMESTAMP になる
+fn date_add(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    if !args[0].is_temporal() {
+        return Err(undefined(name, &args[0]));
+    }
+    if !matches!(args[1], Value::Interval(_)) {
+        return Err(undefined(name, &args[1]));
+    }
+    eval_arithmetic(BinaryOp::Plus, &args[0], &args[1])
+}
+
+// 2 つめの日時から 3 つめの日時までに越えた単位の区切りの数。'2024-12-31' から '2025-01-01' は 1 年
+fn date_diff(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    for value in &args[1..] {
+        if value.timestamp().is_none() {
+            return Err(undefined(name, value));
+        }
+    }
+    let end = args[2].timestamp().unwrap();
+    call_datetime(name, &args[0], &args[1], |start, field| {
+        end.boundaries_since(start.timestamp().unwrap(), field)
+            .map(Value::Integer)
+    })
+}
+
+// EXTRACT は単位の値を DECIMAL で、DATE_TRUNC は切り捨てた TIMESTAMP を返す。単位は 1 つめの引数の文字列
+fn call_datetime(
+    name: &'static str,
+    unit: &Value,
+    value: &Value,
+    f: impl FnOnce(&Value, Field) -> Option<Value>,
+) -> Result<Value, Error> {
+    let unit = text_arg(name, unit)?;
+    if !value.is_temporal() {
+        return Err(undefined(name, value));
+    }
+    let unsupported = || Error::UnsupportedUnit {
+        unit: unit.to_string(),
+        ty: value.type_name(),
+    };
+    let field = Field::parse(unit).ok_or_else(unsupported)?;
+    f(value, field).ok_or_else(unsupported)
+}
+
+// LIKE のパターンの 1 文字
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LikeToken {
+    Char(char),
+    // _
+    Any,
+    // %
+    AnyString,
+}
+
+// ESCAPE の文字列を 1 文字にする。省くと \、空の文字列ならエスケープしない
+pub fn like_escape(escape: Option<&str>) -> Result<Option<char>, Error> {
+    let Some(escape) = escape else {
+        return Ok(Some('\\'));
+    };
+    let mut chars = escape.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Ok(None),
+        (Some(c), None) => Ok(Some(c)),
+        _ => Err(Error::InvalidEscapeString),
+    }
+}
+
+pub fn parse_like(pattern: &str, escape: Option<char>) -> Result<Vec<LikeToken>, Error> {
+    let mut tokens = vec![];
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            c if Some(c) == escape => {
+                LikeToken::Char(chars.next().ok_or(Error::LikePatternEndsWithEscape)?)
+            }
+            '%' => LikeToken::AnyString,
+            '_' => LikeToken::Any,
+            c => LikeToken::Char(c),
+        });
+    }
+    Ok(tokens)
+}
+
+// 最後に見た % から読み直すことで、後戻りを % 1 つ分に限る
+fn like_match(s: &[char], pattern: &[LikeToken]) -> bool {
+    let (mut i, mut p) = (0, 0);
+    let mut retry = None;
+    while i < s.len() {
+        match pattern.get(p) {
+            Some(LikeToken::AnyString) => {
+                p += 1;
+                retry = Some((i, p));
+            }
+            Some(LikeToken::Any) => {
+                i += 1;
+                p += 1;
+            }
+            Some(LikeToken::Char(c)) if *c == s[i] => {
+                i += 1;
+                p += 1;
+            }
+            _ => match retry {
+                Some((start, after)) => {
+                    i = start + 1;
+                    p = after;
+                    retry = Some((i, p));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|t| *t == LikeToken::AnyString)
+}
+
+fn like_with(
+    name: &'static str,
+    args: &[Value],
+    fold: impl Fn(&str) -> String,
+) -> Result<Value, Error> {
+    let s = text_arg(name, &args[0])?;
+    let pattern = text_arg(name, &args[1])?;
+    let escape = args.get(2).map(|e| text_arg(name, e)).transpose()?;
+    let pattern = parse_like(&fold(pattern), like_escape(escape)?)?;
+    let s = fold(s).chars().collect::<Vec<_>>();
+    Ok(Value::Boolean(like_match(&s, &pattern)))
+}
+
+fn like(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    like_with(name, &args, str::to_string)
+}
+
+// 大文字と小文字を区別しない
+fn ilike(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    like_with(name, &args, str::to_lowercase)
+}
+
+// 文字列のどこかがパターンに一致するか
+fn regexp_like(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let s = text_arg(name, &args[0])?;
+    let regex = Regex::new(text_arg(name, &args[1])?).map_err(Error::InvalidRegex)?;
+    Ok(Value::Boolean(regex.is_match(s)))
+}
+
+fn cast(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let [value, ty] = <[Value; 2]>::try_from(args).unwrap();
+    let ty = text_arg(name, &ty)?;
+    let ty = DataType::parse(ty).ok_or_else(|| Error::UndefinedType(ty.to_string()))?;
+    ty.cast(value.clone())
+        .map_err(|err| cast_error(err, &value, &ty))
+}
+
+fn collation_key(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let [value, collation] = <[Value; 2]>::try_from(args).unwrap();
+    let collation = text_arg(name, &collation)?;
+    let collation = Collation::parse(collation)
+        .ok_or_else(|| Error::UndefinedCollation(collation.to_string()))?;
+    Ok(collation.key(value))
+}
+
+// NULL の列は語を持たないものとして扱う
+fn match_against(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    if args[0].is_null() {
+        return Ok(Value::Null);
+    }
+    let terms = fulltext::query_terms(text_arg(name, &args[0])?);
+    let mut texts = vec![];
+    for arg in args[1..].iter().filter(|arg| !arg.is_null()) {
+        texts.push(text_arg(name, arg)?);
+    }
+    Ok(Value::Real(fulltext::relevance(&terms, texts)))
+}
+
+// JSON の値か、JSON として読む文字列。どちらでもなければ None
+pub(super) fn read_json(value: &Value) -> Option<Result<Json, Error>> {
+    let s: &str = match value {
+        Value::Json(s) => s,
+        Value::Text(s) => s,
+        _ => return None,
+    };
+    Some(s.parse().map_err(|_| Error::InvalidSyntax {
+        expected: "json".to_string(),
+        value: s.to_string(),
+    }))
+}
+
+// パスの先の値を SQL の値にする。文字列は引用符を外し、数は整数か実数にし、配列とオブジェクトは JSON のまま返す
+fn json_extract(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let json = read_json(&args[0]).unwrap_or_else(|| Err(undefined(name, &args[0])))?;
+    let path = text_arg(name, &args[1])?;
+    let path = json::parse_path(path).ok_or_else(|| Error::InvalidJsonPath(path.to_string()))?;
+    let value = match json.get_path(&path) {
+        None | Some(Json::Null) => Value::Null,
+        Some(Json::Bool(b)) => Value::Boolean(*b),
+        Some(Json::Number(n)) => match n.parse() {
+            Ok(n) => Value::Integer(n),
+            Err(_) => Value::Real(n.parse().unwrap()),
+        },
+        Some(Json::String(s)) => Value::Text(s.clone()),
+        Some(json) => Value::Json(json.to_string().into()),
+    };
+    Ok(value)
+}
+
+// 要素は最初の要素の型にそろえる。ほかの要素の型へ黙って変換できればそちらにそろえ、文字列はそろえる型として読む
+fn array(_: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let implicit =
+        |from: &DataType, to: &DataType| to.coercion_from(from) == Some(Coercion::Implicit);
+    let mut common: Option<DataType> = None;
+    for ty in args.iter().filter_map(Value::data_type) {
+        common = Some(match common {
+            None => ty,
+            Some(common) if implicit(&ty, &common) => common,
+            Some(common) if implicit(&common, &ty) || common == DataType::Text => ty,
+            Some(common) if ty == DataType::Text => common,
+            Some(common) => {
+                return Err(Error::CannotCast {
+                    from: ty.name().to_string(),
+                    to: common.name().to_string(),
+                })
+            }
+        });
+    }
+    let Some(common) = common else {
+        return Ok(Value::Array(args.into()));
+    };
+    let items = args
+        .into_iter()
+        .map(|item| match (&common, &item) {
+            // DECIMAL の桁は要素ごとに残す
+            (DataType::Decimal { .. }, Value::Decimal(_)) => Ok(item),
+            _ => common
+                .cast(item.clone())
+                .map_err(|err| cast_error(err, &item, &common)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::Array(items.into()))
+}
+
+// 配列の引数。文字列は element の配列として読む
+pub(super) fn array_arg(
+    name: &'static str,
+    value: Value,
+    element: Option<DataType>,
+) -> Result<Vec<Value>, Error> {
+    match value {
+        Value::Array(items) => Ok(items.into_vec()),
+        Value::Text(_) => {
+            let ty = DataType::array(element.unwrap_or(DataType::Text))
+                .unwrap_or(DataType::array(DataType::Text).unwrap());
+            match ty.cast(value.clone()) {
+                Ok(Value::Array(items)) => Ok(items.into_vec()),
+                Ok(_) => unreachable!(),
+                Err(err) => Err(cast_error(err, &value, &ty)),
+            }
+        }
+        value => Err(undefined(name, &value)),
+    }
+}
+
+// 範囲の外なら NULL
+fn subscript(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let [array, index] = <[Value; 2]>::try_from(args).unwrap();
+    let index = integer_arg(name, &index)?;
+    let items = array_arg(name, array, None)?;
+    let item = usize::try_from(index - 1)
+        .ok()
+        .and_then(|i| items.into_iter().nth(i));
+    Ok(item.unwrap_or(Value::Null))
+}
+
+fn any(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    quantified(name, args, true)
+}
+
+fn all(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    quantified(name, args, false)
+}
+
+// 要素との比較のどれかで結果が決まらず、NULL になった比較があれば NULL。空の配列なら ANY は偽、ALL は真
+fn quantified(name: &'static str, args: Vec<Value>, any: bool) -> Result<Value, Error> {
+    let [op, value, array] = <[Value; 3]>::try_from(args).unwrap();
+    let op = match text_arg(name, &op)? {
+        "=" => BinaryOp::Eq,
+        "<>" => BinaryOp::NotEq,
+        "<" => BinaryOp::Lt,
+        "<=" => BinaryOp::LtEq,
+        ">" => BinaryOp::Gt,
+        ">=" => BinaryOp::GtEq,
+        _ => return Err(undefined(name, &op)),
+    };
+    if array.is_null() {
+        return Ok(Value::Null);
+    }
+    let mut unknown = false;
+    for item in array_arg(name, array, value.data_type())? {
+        match eval_binary(op, value.clone(), item)? {
+            Value::Boolean(b) if b == any => return Ok(Value::Boolean(any)),
+            Value::Null => unknown = true,
+            _ => {}
+        }
+    }
+    Ok(if unknown {
+        Value::Null
+    } else {
+        Value::Boolean(!any)
+    })
+}
+
+fn cardinality(name: &'static str, args: Vec<Value>) -> Result<Value, Error> {
+    let items = array_arg(name, args.into_iter().next().unwrap(), None)?;
+    Ok(Value::Integer(items.len() as i64))
+}
+
+fn uuid_v4(_: &'static str, _: Vec<Value>) -> Result<Value, Error> {
+    Ok(Value::Uuid(Uuid::new_v4()))
+}
+
+fn uuid_v7(_: &'static str, _: Vec<Value>) -> Result<Value, Error> {
+    Ok(Value::Uuid(Uuid::new_v7()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::tests::{int, text};
+
+    fn call(name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let func = Function::lookup(name).unwrap();
+        assert!(func.accepts(args.len()), "{}", name);
+        func.call(args)
+    }
+
+    #[test]
+    fn test_string_functions() {
+        // 位置と長さはバイトではなく文字で数える
+        for (name, args, expected) in [
+            ("length", vec![text("日本語")], int(3)),
+            ("char_length", vec![text("")], int(0)),
+            ("upper", vec![text("straße")], text("STRASSE")),
+            (
+                "substr",
+                vec![text("こんにちは"), int(2), int(3)],
+                text("んにち"),
+            ),
+            ("substring", vec![text("hello"), int(0), int(2)], text("h")),
+            ("substr", vec![text("hello"), int(4)], text("lo")),
+            ("substr", vec![text("hello"), int(9)], text("")),
+            ("trim", vec![text("  a b  ")], text("a b")),
+            ("trim", vec![text("xxaxx"), text("x")], text("a")),
+            ("ltrim", vec![text("ーーあー"), text("ー")], text("あー")),
+            ("rtrim", vec![text("ab  ")], text("ab")),
+            (
+                "replace",
+                vec![text("ababa"), text("ba"), text("é")],
+                text("aéé"),
+            ),
+            (
+                "replace",
+                vec![text("abc"), text(""), text("x")],
+                text("abc"),
+            ),
+            ("instr", vec![text("ありがとう"), text("がと")], int(3)),
+            ("strpos", vec![text("abc"), text("z")], int(0)),
+            ("concat", vec![text("a"), Value::Null, int(1)], text("a1")),
+            ("concat", vec![Value::Null], text("")),
+            (
+                "replace",
+                vec![text("a"), Value::Null, text("b")],
+                Value::Null,
+            ),
+        ] {
+            assert_eq!(
+                call(name, args.clone()).unwrap(),
+                expected,
+                "{}{:?}",
+                name,
+                args
+            );
+        }
+        assert!(matches!(
+            call("substr", vec![text("abc"), int(1), int(-1)]),
+            Err(Error::NegativeSubstringLength)
+        ));
+        assert!(matches!(
+            call("instr", vec![text("abc"), int(1)]),
+            Err(Error::UndefinedFunction {
+                name: "instr",
+                arg,
+            }) if arg == "integer"
+        ));
+        assert_eq!(Function::lookup("btrim").unwrap().name(), "trim");
+        assert!(!Function::lookup("replace").unwrap().accepts(2));
+        assert!(Function::lookup("concat").unwrap().accepts(5));
+    }
+
+    #[test]
+    fn test_like() {
+        for (s, pattern, expected) in [
+            ("abc", "abc", true),
+            ("abc", "a%", true),
+            ("abc", "%c", true),
+            ("abc", "a_c", true),
+            ("ac", "a_c", false),
+            ("日本語", "_本%", true),
+            ("", "%", true),
+            ("aXbXc", "%b%c", true),
+            ("aab", "%ab", true),
+            ("abca", "%a%b%", true),
+            ("abc", "%d%", false),
+            ("50%", "50\\%", true),
+            ("500", "50\\%", false),
+            ("a\\b", "a\\\\b", true),
+        ] {
+            assert_eq!(
+                call("like", vec![text(s), text(pattern)]).unwrap(),
+                Value::Boolean(expected),
+                "{} LIKE {}",
+                s,
+                pattern
+            );
+        }
+        for (args, expected) in [
+            (
+                vec![text("a_b"), text("a!_%"), text("!")],
+                Value::Boolean(true),
+            ),
+            (
+                vec![text("axb"), text("a!_%"), text("!")],
+                Value::Boolean(false),
+            ),
+            // 空の ESCAPE ならエスケープしない
+            (
+                vec![text("a\\b"), text("a\\_"), text("")],
+                Value::Boolean(true),
+            ),
+            (vec![text("a"), Value::Null], Value::Null),
+        ] {
+            assert_eq!(call("like", args.clone()).unwrap(), expected, "{:?}", args);
+        }
+        assert_eq!(
+            call("ilike", vec![text("ÄBC"), text("äb%")]).unwrap(),
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            call("regexp_like", vec![text("abc123"), text("[0-9]+$")]).unwrap(),
+            Value::Boolean(true)
+        );
+        for (args, message) in [
+            (
+                vec![text("a"), text("a"), text("xy")],
+                "invalid escape string",
+            ),
+            (
+                vec![text("a"), text("a\\")],
+                "LIKE pattern must not end with escape character",
+            ),
+        ] {
+            assert_eq!(call("like", args).unwrap_err().to_string(), message);
+        }
+        assert_eq!(
+            call("regexp_like", vec![text("a"), text("(a")])
+                .unwrap_err()
+                .to_string(),
+            "invalid regular expression: parentheses () not balanced"
+        );
+    }
+
+    #[test]
+    fn test_numeric_functions() {
+        let dec = |s: &str| Value::Decimal(s.parse().unwrap());
+        let big = Value::BigInt;
+        for (name, args, expected) in [
+            ("abs", vec![int(-3)], int(3)),
+            ("abs", vec![big(-3)], big(3)),
+            ("abs", vec![Value::Real(-1.5)], Value::Real(1.5)),
+            ("abs", vec![dec("-1.50")], dec("1.50")),
+            ("round", vec![dec("2.5")], dec("3")),
+            ("round", vec![dec("-2.5")], dec("-3")),
+            ("round", vec![dec("1.2345"), int(2)], dec("1.23")),
+            ("round", vec![dec("1.5"), int(3)], dec("1.500")),
+            ("round", vec![dec("1250"), int(-2)], dec("1300")),
+            ("round", vec![dec("0.5"), int(40)], dec("0.5")),
+            ("round", vec![int(-1250), int(-2)], int(-1300)),
+            ("round", vec![int(7), int(2)], int(7)),
+            ("round", vec![Value::Real(2.675), int(1)], Value::Real(2.7)),
+            ("ceil", vec![dec("-1.5")], dec("-1")),
+            ("ceiling", vec![Value::Real(1.2)], Value::Real(2.0)),
+            ("floor", vec![dec("-1.5")], dec("-2")),
+            ("floor", vec![int(4)], int(4)),
+            ("mod", vec![int(-7), int(3)], int(-1)),
+            ("mod", vec![dec("5.5"), int(2)], dec("1.5")),
+            ("mod", vec![big(i64::MIN), int(-1)], big(0)),
+            ("power", vec![int(2), int(10)], Value::Real(1024.0)),
+            ("pow", vec![Value::Real(4.0), dec("0.5")], Value::Real(2.0)),
+            ("power", vec![dec("1.5"), int(3)], dec("3.375")),
+            ("power", vec![dec("2"), int(-2)], dec("0.25")),
+            ("power", vec![dec("2"), dec("0.5")], dec("1.414214")),
+            ("power", vec![int(2), Value::Null], Value::Null),
+        ] {
+            assert_eq!(
+                call(name, args.clone()).unwrap(),
+                expected,
+                "{}{:?}",
+                name,
+                args
+            );
+        }
+        for (name, args, message) in [
+            ("abs", vec![int(i64::MIN)], "integer out of range"),
+            ("mod", vec![int(1), int(0)], "division by zero"),
+            (
+                "mod",
+                vec![text("1"), int(0)],
+                "function mod(text) does not exist",
+            ),
+            (
+                "power",
+                vec![int(0), int(-1)],
+                "zero raised to a negative power is undefined",
+            ),
+            (
+                "power",
+                vec![Value::Real(-8.0), dec("0.5")],
+                "a negative number raised to a non-integer power yields a complex result",
+            ),
+            (
+                "power",
+                vec![Value::Real(10.0), int(400)],
+                "value out of range: overflow",
+            ),
+            (
+                "power",
+                vec![dec("10"), int(20)],
+                "numeric value out of range",
+            ),
+        ] {
+            assert_eq!(
+                call(name, args).unwrap_err().to_string(),
+                message,
+                "{}",
+                name
+            );
+        }
+
+        // 整数の割り算は切り捨て、あふれたらエラーにする
+        for (op, left, right, expected) in [
+            (BinaryOp::Divide, int(7), int(-2), Ok(int(-3))),
+            (
+                BinaryOp::Divide,
+                Value::Real(7.0),
+                int(2),
+                Ok(Value::Real(3.5)),
+            ),
+            (BinaryOp::Divide, dec("7"), int(2), Ok(dec("3.500000"))),
+            (BinaryOp::Divide, int(1), int(0), Err("division by zero")),
+            (
+                BinaryOp::Divide,
+                Value::Real(1.0),
+                int(0),
+                Err("division by zero"),
+            ),
+            (
+                BinaryOp::Plus,
+                big(i64::MAX),
+                int(1),
+                Err("integer out of range"),
+            ),
+            (
+                BinaryOp::Divide,
+                big(i64::MIN),
+                int(-1),
+                Err("integer out of range"),
+            ),
+            (
+                BinaryOp::Multiply,
+                Value::Real(1e300),
+                Value::Real(1e300),
+                Err("value out of range: overflow"),
+            ),
+        ] {
+            let result = eval_arithmetic(op, &left, &right).map_err(|e| e.to_string());
+            assert_eq!(
+                result,
+                expected.map_err(str::to_string),
+                "{:?} {:?}",
+                left,
+                right
+            );
+        }
+    }
+}
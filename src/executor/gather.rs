@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::catalog::{Dictionary, GeneratedColumn};
+use crate::disk::PageId;
+use crate::enum_type::EnumType;
+use crate::heap::RecordId;
+use crate::storage::TableAccess;
+
+use super::aggregate::{finish_groups, merge_groups, partial_update, AggregateCall, PartialGroups};
+use super::expr::Expr;
+use super::memory::MemoryReservation;
+use super::scan::{decode_row, needed_mask, virtual_columns};
+use super::{Error, ExecContext, Executor, Row};
+
+// ワーカーに送ったまま処理されていないページの数の上限
+const QUEUE_SIZE: usize = 4;
+
+type Tuples = Vec<(RecordId, Vec<u8>)>;
+
+// ワーカーが返すもの。集約しないなら 1 ページごとの行、集約するなら範囲を読み終わったあとのグループ
+enum Output {
+    Rows(Vec<Row>),
+    Groups(PartialGroups),
+}
+
+// テーブルの行の復元と絞り込みを並列に評価する走査
+//
+// ヒープのページを先頭から workers 個の範囲に分け、範囲ごとにワーカースレッドを 1 つ
+// 割り当てる。バッファプールはスレッド間で共有できないので、ページはすべてこのスレッドで読んで
+// 行のバイト列をワーカーに渡し、ワーカーは行の復元と絞り込みだけを並列に行う。ページの読み込みは並列にならない。
+// 出力の行の順序は決まっていない。
+//
+// aggregate があれば、ワーカーは絞り込んだ行をそのままグループごとに途中まで集約し、範囲を読み終わったら
+// その状態を返す。このスレッドは状態を merge でまとめてから集約を終えるので、出力は HashAggregate と同じになる。
+pub struct Gather<'a> {
+    table: &'a str,
+    storage: Arc<dyn TableAccess>,
+    predicate: Option<&'a Expr>,
+    workers: usize,
+    needed: Option<Vec<bool>>,
+    // テーブルの列の数
+    width: usize,
+    virtual_columns: Vec<GeneratedColumn>,
+    dictionaries: Vec<Arc<Dictionary>>,
+    enums: Vec<Arc<EnumType>>,
+    // ワーカーごとの残りのページ
+    ranges: Vec<VecDeque<PageId>>,
+    next_worker: usize,
+    senders: Vec<Option<SyncSender<Tuples>>>,
+    receiver: Option<Receiver<Result<Output, Error>>>,
+    handles: Vec<JoinHandle<()>>,
+    output: VecDeque<Row>,
+    aggregate: Option<(&'a [Expr], &'a [AggregateCall])>,
+    // ワーカーから受け取ってまとめたグループ
+    groups: PartialGroups,
+    reservation: MemoryReservation,
+}
+
+impl<'a> Gather<'a> {
+    pub fn new(
+        ctx: &mut ExecContext,
+        name: &'a str,
+        predicate: Option<&'a Expr>,
+        workers: usize,
+        needed: Option<&[usize]>,
+    ) -> Result<Self, Error> {
+        let table = ctx
+            .catalog
+            .table(name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        if let Some(io) = ctx.io {
+            io.update(name, |io| io.seq_scans += 1);
+        }
+        let mut needed = needed_mask(table.columns.len(), needed);
+        Ok(Self {
+            table: name,
+            storage: table.storage.clone(),
+            predicate,
+            workers: workers.max(1),
+            width: table.columns.len(),
+            virtual_columns: virtual_columns(table, &mut needed),
+            dictionaries: table.dictionaries.clone(),
+            enums: table.enum_types(),
+            needed,
+            ranges: vec![],
+            next_worker: 0,
+            senders: vec![],
+            receiver: None,
+            handles: vec![],
+            output: VecDeque::new(),
+            aggregate: None,
+            groups: PartialGroups::new(),
+            reservation: ctx.memory.reservation(),
+        })
+    }
+
+    pub fn partial_aggregate(
+        ctx: &mut ExecContext,
+        name: &'a str,
+        predicate: Option<&'a Expr>,
+        workers: usize,
+        needed: Option<&[usize]>,
+        group_by: &'a [Expr],
+        aggregates: &'a [AggregateCall],
+    ) -> Result<Self, Error> {
+        let mut gather = Self::new(ctx, name, predicate, workers, needed)?;
+        gather.aggregate = Some((group_by, aggregates));
+        Ok(gather)
+    }
+
+    fn start(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        let storage = &self.storage;
+        let page_ids = ctx.count_reads(self.table, |ctx| storage.page_ids(ctx.bufmgr))?;
+        let chunk = page_ids.len().div_ceil(self.workers).max(1);
+        self.ranges = page_ids
+            .chunks(chunk)
+            .map(|range| range.iter().copied().collect())
+            .collect();
+        let (result_sender, receiver) = mpsc::channel();
+        for _ in 0..self.ranges.len() {
+            let (sender, pages) = mpsc::sync_channel::<Tuples>(QUEUE_SIZE);
+            let results = result_sender.clone();
+            let predicate = self.predicate.cloned();
+            let needed = self.needed.clone();
+            let width = self.width;
+            let virtual_columns = self.virtual_columns.clone();
+            let dictionaries = self.dictionaries.clone();
+            let enums = self.enums.clone();
+            let aggregate = self
+                .aggregate
+                .map(|(group_by, aggregates)| (group_by.to_vec(), aggregates.to_vec()));
+            self.handles.push(thread::spawn(move || {
+                let mut groups = PartialGroups::new();
+                for tuples in pages {
+                    let rows = filter_tuples(
+                        tuples,
+                        predicate.as_ref(),
+                        width,
+                        needed.as_deref(),
+                        &virtual_columns,
+                        &dictionaries,
+                        &enums,
+                    );
+                    let output = match (&aggregate, rows) {
+                        (Some((group_by, aggregates)), Ok(rows)) => rows
+                            .iter()
+                            .try_for_each(|row| {
+                                partial_update(&mut groups, group_by, aggregates, row)
+                            })
+                            .map(|()| None),
+                        (_, rows) => rows.map(|rows| Some(Output::Rows(rows))),
+                    };
+                    let failed = output.is_err();
+                    let Some(output) = output.transpose() else {
+                        continue;
+                    };
+                    if results.send(output).is_err() || failed {
+                        return;
+                    }
+                }
+                if aggregate.is_some() {
+                    let _ = results.send(Ok(Output::Groups(groups)));
+                }
+            }));
+            self.senders.push(Some(sender));
+        }
+        self.receiver = Some(receiver);
+        Ok(())
+    }
+
+    // ページの残っている範囲から順に 1 ページずつワーカーに渡す。渡すページがなければ false
+    fn dispatch(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        for _ in 0..self.ranges.len() {
+            let worker = self.next_worker;
+            self.next_worker = (self.next_worker + 1) % self.ranges.len();
+            let Some(page_id) = self.ranges[worker].pop_front() else {
+                // 範囲を読み終わったワーカーには終わりを知らせる
+                self.senders[worker] = None;
+                continue;
+            };
+            let storage = &self.storage;
+            let tuples = ctx
+                .count_reads(self.table, |ctx| storage.page_tuples(ctx.bufmgr, page_id))?
+                .into_iter()
+                .filter(|(_, header, _)| ctx.visible(header))
+                .map(|(rid, _, bytes)| (rid, bytes))
+                .collect();
+            if let Some(sender) = &self.senders[worker] {
+                // ワーカーが途中で止まっていれば結果の方でエラーを受け取る
+                let _ = sender.send(tuples);
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn finish(&mut self) {
+        self.ranges.clear();
+        self.senders.clear();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn filter_tuples(
+    tuples: Tuples,
+    predicate: Option<&Expr>,
+    width: usize,
+    needed: Option<&[bool]>,
+    virtual_columns: &[GeneratedColumn],
+    dictionaries: &[Arc<Dictionary>],
+    enums: &[Arc<EnumType>],
+) -> Result<Vec<Row>, Error> {
+    let mut rows = vec![];
+    for (rid, bytes) in tuples {
+        let row = decode_row(
+            &bytes,
+            width,
+            needed,
+            virtual_columns,
+            dictionaries,
+            enums,
+            rid,
+        )?;
+        match predicate {
+            Some(predicate) if !predicate.eval_predicate(&row)? => {}
+            _ => rows.push(row),
+        }
+    }
+    Ok(rows)
+}
+
+impl Executor for Gather<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if self.receiver.is_none() {
+            self.start(ctx)?;
+        }
+        loop {
+            ctx.check_interrupt()?;
+            if let Some(row) = self.output.pop_front() {
+                return Ok(Some(row));
+            }
+            let receiver = self.receiver.as_ref().unwrap();
+            let rows = match receiver.try_recv() {
+                Ok(rows) => rows,
+                Err(_) if self.dispatch(ctx)? => continue,
+                // すべて渡し終わったので、ワーカーが終わるまで結果を待つ
+                Err(_) => {
+                    let receiver = self.receiver.as_ref().unwrap();
+                    match receiver.recv() {
+                        Ok(rows) => rows,
+                        Err(_) => {
+                            self.finish();
+                            // すべてのワーカーの状態をまとめたので集約を終える
+                            if let Some((group_by, aggregates)) = self.aggregate.take() {
+                                let groups = std::mem::take(&mut self.groups);
+                                self.output
+                                    .extend(finish_groups(groups, group_by, aggregates)?);
+                                self.reservation.shrink(self.reservation.size());
+                                continue;
+                            }
+                            return Ok(None);
+                        }
+                    }
+                }
+            };
+            match rows {
+                Ok(Output::Rows(rows)) => self.output.extend(rows),
+                Ok(Output::Groups(groups)) => match merge_groups(&mut self.groups, groups) {
+                    Ok(grown) => self.reservation.grow(grown),
+                    Err(e) => {
+                        self.finish();
+                        return Err(e);
+                    }
+                },
+                Err(e) => {
+                    self.finish();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, _ctx: &mut ExecContext) -> Result<(), Error> {
+        self.finish();
+        Ok(())
+    }
+}
+
+// エラーや取り消しで close されずに捨てられてもワーカーを止める
+impl Drop for Gather<'_> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
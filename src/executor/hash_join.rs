@@ -0,0 +1,450 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::vec;
+
+use crate::bloom::BloomFilter;
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::memory::MemoryReservation;
+use super::spill::{SpillFile, SpillReader};
+use super::{row_size, BoxExecutor, Error, ExecContext, Executor, JoinType, Row};
+
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+
+// メモリに収まらないときに分割するパーティション数
+const NUM_PARTITIONS: usize = 8;
+// パーティションがまだ大きすぎるときに分割し直す回数の上限
+const MAX_DEPTH: u32 = 3;
+
+type Key = Vec<Value>;
+
+// 両側を同じハッシュ値で分けたパーティションの組
+struct Partition {
+    files: [SpillFile; 2],
+    depth: u32,
+}
+
+impl Partition {
+    fn new(depth: u32) -> Result<Self, Error> {
+        Ok(Self {
+            files: [SpillFile::new()?, SpillFile::new()?],
+            depth,
+        })
+    }
+}
+
+enum Probe {
+    // 読み込み済みの行のあとに入力の残りを読む
+    Input {
+        buffered: vec::IntoIter<Row>,
+        done: bool,
+    },
+    File(SpillReader),
+}
+
+// ハッシュ結合 (Grace hash join)
+//
+// 両側を交互に読み、先に読み終わった (小さい) 方でハッシュ表を作り、もう一方で探索する。
+// 読み終わる前に work_mem か問い合わせの予算を超えたら、両側を結合キーのハッシュ値でパーティションに分けて
+// 一時ファイルに書き出し、パーティションごとに小さい方でハッシュ表を作る。
+// Semi と Anti では常に右でハッシュ表を作り、左の行ごとに一致する行があるかだけを調べる。
+// 外部結合で相手のない行を返す側が探索側なら探索のたびに、ハッシュ表の側なら一致した行に印を付けておいて
+// 探索が終わったときに返す。
+// runtime_filter があり、その側が探索側になれば、メモリでハッシュ表を作り終わったときにそのキーの Bloom フィルタと
+// キーの範囲を ExecContext に置き、探索側の RuntimeFilter に残りの行やパーティションの子を絞らせる。
+// 一時ファイルに書き出したときは作らない。
+pub struct HashJoin<'a> {
+    inputs: [BoxExecutor<'a>; 2],
+    keys: [&'a [Expr]; 2],
+    predicate: Option<&'a Expr>,
+    join_type: JoinType,
+    // 左右の列数。相手のない行を NULL で埋めるのに使う
+    widths: [usize; 2],
+    runtime_filter: Option<(usize, usize)>,
+    started: bool,
+    // ハッシュ表の側の行と、その位置をキーで引く表
+    rows: Vec<Row>,
+    table: HashMap<Key, Vec<usize>>,
+    // ハッシュ表の各行が一致したか
+    matched: Vec<bool>,
+    build: usize,
+    probe: Option<Probe>,
+    partitions: Vec<Partition>,
+    output: VecDeque<Row>,
+    // メモリに置いている行の分
+    reservation: MemoryReservation,
+}
+
+impl<'a> HashJoin<'a> {
+    // inputs と keys は左、右の順
+    pub fn new(
+        inputs: [BoxExecutor<'a>; 2],
+        keys: [&'a [Expr]; 2],
+        predicate: Option<&'a Expr>,
+        join_type: JoinType,
+        widths: [usize; 2],
+        runtime_filter: Option<(usize, usize)>,
+        reservation: MemoryReservation,
+    ) -> Self {
+        Self {
+            inputs,
+            keys,
+            predicate,
+            join_type,
+            widths,
+            runtime_filter,
+            started: false,
+            rows: vec![],
+            table: HashMap::new(),
+            matched: vec![],
+            build: RIGHT,
+            probe: None,
+            partitions: vec![],
+            output: VecDeque::new(),
+            reservation,
+        }
+    }
+
+    // キーに NULL を含む行はどの行とも結合しないので None
+    fn key(&self, side: usize, row: &Row) -> Result<Option<Key>, Error> {
+        let mut key = Vec::with_capacity(self.keys[side].len());
+        for expr in self.keys[side] {
+            let value = expr.eval(row)?;
+            if value.is_null() {
+                return Ok(None);
+            }
+            key.push(value);
+        }
+        Ok(Some(key))
+    }
+
+    // 相手のない行も返す側か
+    fn keeps(&self, side: usize) -> bool {
+        if side == LEFT {
+            self.join_type.keeps_left()
+        } else {
+            self.join_type.keeps_right()
+        }
+    }
+
+    // 相手のない行の反対側の列を NULL で埋める
+    fn pad(&self, side: usize, row: Row) -> Row {
+        let nulls = vec![Value::Null; self.widths[1 - side]];
+        if side == LEFT {
+            [row, nulls].concat()
+        } else {
+            [nulls, row].concat()
+        }
+    }
+
+    // キーが NULL の行は表に入れないが、相手のない行として返すために rows には残す
+    fn build_table(&mut self, rows: Vec<Row>) -> Result<(), Error> {
+        self.table.clear();
+        for (i, row) in rows.iter().enumerate() {
+            if let Some(key) = self.key(self.build, row)? {
+                self.table.entry(key).or_default().push(i);
+            }
+        }
+        self.matched = vec![false; rows.len()];
+        self.rows = rows;
+        Ok(())
+    }
+
+    // 探索が終わったハッシュ表を捨てる。相手のない行を返す側なら一致しなかった行を出力に回す
+    fn finish_table(&mut self) {
+        let rows = std::mem::take(&mut self.rows);
+        if self.keeps(self.build) {
+            for (row, matched) in rows.into_iter().zip(std::mem::take(&mut self.matched)) {
+                if !matched {
+                    self.output.push_back(self.pad(self.build, row));
+                }
+            }
+        }
+        self.table.clear();
+        self.matched.clear();
+    }
+
+    fn start(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        let mut buffered = [vec![], vec![]];
+        let mut done = [false, false];
+        let mut size = 0;
+        let mut over_budget = false;
+        // Semi と Anti では右を読み終わるまで続ける
+        while !done[RIGHT] && (!done[LEFT] || self.join_type.is_semi()) {
+            if size > ctx.work_mem || over_budget {
+                self.reservation.free();
+                return self.spill_inputs(ctx, buffered);
+            }
+            for side in [LEFT, RIGHT] {
+                if done[side] {
+                    continue;
+                }
+                match self.inputs[side].next(ctx)? {
+                    Some(row) => {
+                        let n = row_size(&row);
+                        size += n;
+                        if self.reservation.can_grow(n) {
+                            self.reservation.grow(n);
+                        } else {
+                            over_budget = true;
+                        }
+                        buffered[side].push(row);
+                    }
+                    None => done[side] = true,
+                }
+            }
+        }
+        self.build = if !self.join_type.is_semi()
+            && done[LEFT]
+            && buffered[LEFT].len() <= buffered[RIGHT].len()
+        {
+            LEFT
+        } else {
+            RIGHT
+        };
+        let probe = 1 - self.build;
+        let [left, right] = buffered;
+        let (build_rows, probe_rows) = if self.build == LEFT {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        self.build_table(build_rows)?;
+        if let Some((id, side)) = self.runtime_filter.filter(|&(_, side)| side == probe) {
+            // 相手のない探索側の行を返す結合や Anti では、一致しない行も捨てられない
+            if !self.keeps(side) && self.join_type != JoinType::Anti {
+                let mut filter = BloomFilter::new(self.table.len());
+                let mut ranges: Vec<Option<(Value, Value)>> =
+                    vec![None; self.keys[self.build].len()];
+                for key in self.table.keys() {
+                    filter.insert(key);
+                    for (range, value) in ranges.iter_mut().zip(key) {
+                        if value.is_null() {
+                            continue;
+                        }
+                        match range {
+                            Some((min, max)) => {
+                                if value.sort_cmp(min).is_lt() {
+                                    *min = value.clone();
+                                } else if value.sort_cmp(max).is_gt() {
+                                    *max = value.clone();
+                                }
+                            }
+                            None => *range = Some((value.clone(), value.clone())),
+                        }
+                    }
+                }
+                ctx.runtime_filters.insert(id, filter);
+                ctx.key_ranges.insert(id, ranges);
+            }
+        }
+        self.probe = Some(Probe::Input {
+            buffered: probe_rows.into_iter(),
+            done: done[probe],
+        });
+        Ok(())
+    }
+
+    // 読み込み済みの行と入力の残りをすべてパーティションに書き出す
+    fn spill_inputs(
+        &mut self,
+        ctx: &mut ExecContext,
+        buffered: [Vec<Row>; 2],
+    ) -> Result<(), Error> {
+        let mut partitions = (0..NUM_PARTITIONS)
+            .map(|_| Partition::new(0))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (side, rows) in buffered.into_iter().enumerate() {
+            for row in rows {
+                self.write_partitioned(&mut partitions, side, &row)?;
+            }
+            while let Some(row) = self.inputs[side].next(ctx)? {
+                self.write_partitioned(&mut partitions, side, &row)?;
+            }
+        }
+        self.partitions = partitions;
+        Ok(())
+    }
+
+    fn write_partitioned(
+        &self,
+        partitions: &mut [Partition],
+        side: usize,
+        row: &Row,
+    ) -> Result<(), Error> {
+        let Some(key) = self.key(side, row)? else {
+            // 相手のない行も返す側と Anti の左では、キーが NULL の行も出力するので残しておく
+            if self.keeps(side) || (side == LEFT && self.join_type == JoinType::Anti) {
+                partitions[0].files[side].write(row)?;
+            }
+            return Ok(());
+        };
+        let depth = partitions[0].depth;
+        let mut hasher = DefaultHasher::new();
+        depth.hash(&mut hasher);
+        key.hash(&mut hasher);
+        let i = (hasher.finish() % partitions.len() as u64) as usize;
+        partitions[i].files[side].write(row)?;
+        Ok(())
+    }
+
+    // 次のパーティションのハッシュ表を作る。パーティションが残っていなければ false
+    fn load_partition(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        let Some(partition) = self.partitions.pop() else {
+            return Ok(false);
+        };
+        let [left, right] = partition.files;
+        self.build = if !self.join_type.is_semi() && left.size() < right.size() {
+            LEFT
+        } else {
+            RIGHT
+        };
+        let (build, probe) = if self.build == LEFT {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        let size = build.size() as usize;
+        if (size > ctx.work_mem || !self.reservation.can_grow(size)) && partition.depth < MAX_DEPTH
+        {
+            // まだ大きすぎるので別のハッシュ値で分け直す
+            let mut partitions = (0..NUM_PARTITIONS)
+                .map(|_| Partition::new(partition.depth + 1))
+                .collect::<Result<Vec<_>, _>>()?;
+            for (side, file) in [(self.build, build), (1 - self.build, probe)] {
+                let mut reader = file.into_reader()?;
+                while let Some(row) = reader.next()? {
+                    self.write_partitioned(&mut partitions, side, &row)?;
+                }
+            }
+            self.partitions.extend(partitions);
+            return Ok(true);
+        }
+        self.reservation.grow(size);
+        let mut reader = build.into_reader()?;
+        let mut rows = vec![];
+        while let Some(row) = reader.next()? {
+            rows.push(row);
+        }
+        self.build_table(rows)?;
+        self.probe = Some(Probe::File(probe.into_reader()?));
+        Ok(true)
+    }
+
+    fn next_probe_row(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        let probe_side = 1 - self.build;
+        match &mut self.probe {
+            Some(Probe::Input { buffered, done }) => {
+                if let Some(row) = buffered.next() {
+                    return Ok(Some(row));
+                }
+                if *done {
+                    return Ok(None);
+                }
+                let row = self.inputs[probe_side].next(ctx)?;
+                if row.is_none() {
+                    *done = true;
+                }
+                Ok(row)
+            }
+            Some(Probe::File(reader)) => Ok(reader.next()?),
+            None => Ok(None),
+        }
+    }
+
+    fn probe_row(&mut self, row: Row) -> Result<(), Error> {
+        if self.join_type.is_semi() {
+            return self.probe_semi(row);
+        }
+        let probe = 1 - self.build;
+        let mut found = false;
+        if let Some(key) = self.key(probe, &row)? {
+            for &i in self.table.get(&key).into_iter().flatten() {
+                let other = &self.rows[i];
+                // 出力は常に左の行、右の行の順
+                let joined = if self.build == LEFT {
+                    [other.as_slice(), row.as_slice()].concat()
+                } else {
+                    [row.as_slice(), other.as_slice()].concat()
+                };
+                match self.predicate {
+                    Some(predicate) if !predicate.eval_predicate(&joined)? => {}
+                    _ => {
+                        found = true;
+                        self.matched[i] = true;
+                        self.output.push_back(joined);
+                    }
+                }
+            }
+        }
+        if !found && self.keeps(probe) {
+            self.output.push_back(self.pad(probe, row));
+        }
+        Ok(())
+    }
+
+    // 左の行に条件を満たす右の行があれば Semi では出力し、なければ Anti では出力する
+    fn probe_semi(&mut self, row: Row) -> Result<(), Error> {
+        let mut found = false;
+        if let Some(matches) = self.key(LEFT, &row)?.and_then(|key| self.table.get(&key)) {
+            for other in matches.iter().map(|&i| &self.rows[i]) {
+                found = match self.predicate {
+                    Some(predicate) => {
+                        predicate.eval_predicate(&[row.as_slice(), other.as_slice()].concat())?
+                    }
+                    None => true,
+                };
+                if found {
+                    break;
+                }
+            }
+        }
+        if found == (self.join_type == JoinType::Semi) {
+            self.output.push_back(row);
+        }
+        Ok(())
+    }
+}
+
+impl Executor for HashJoin<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            ctx.check_interrupt()?;
+            if let Some(row) = self.output.pop_front() {
+                return Ok(Some(row));
+            }
+            if !self.started {
+                self.started = true;
+                self.start(ctx)?;
+                continue;
+            }
+            if self.probe.is_some() {
+                match self.next_probe_row(ctx)? {
+                    Some(row) => self.probe_row(row)?,
+                    None => {
+                        self.probe = None;
+                        self.finish_table();
+                        self.reservation.free();
+                    }
+                }
+                continue;
+            }
+            if !self.load_partition(ctx)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.partitions.clear();
+        if let Some((id, _)) = self.runtime_filter {
+            ctx.runtime_filters.remove(&id);
+            ctx.key_ranges.remove(&id);
+        }
+        self.inputs[LEFT].close(ctx)?;
+        self.inputs[RIGHT].close(ctx)
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use crate::btree::{self, BTree, BTreeScan};
+use crate::catalog::{self, Dictionary, GeneratedColumn};
+use crate::enum_type::EnumType;
+use crate::storage::TableAccess;
+
+use super::expr::Expr;
+use super::scan::decode_row;
+use super::{BoxExecutor, Error, ExecContext, Executor, Row};
+
+// インデックス入れ子ループ結合
+//
+// 外側の行ごとに keys を評価してインデックスを引き、一致した内側の行をヒープから読む。
+// keys はインデックスの列の順に並んでいる。
+pub struct IndexJoin<'a> {
+    outer: BoxExecutor<'a>,
+    table: &'a str,
+    index: &'a str,
+    storage: Arc<dyn TableAccess>,
+    // 行を読むときに計算する生成列
+    // テーブルの列の数
+    width: usize,
+    virtual_columns: Vec<GeneratedColumn>,
+    dictionaries: Vec<Arc<Dictionary>>,
+    enums: Vec<Arc<EnumType>>,
+    btree: BTree,
+    keys: &'a [Expr],
+    predicate: Option<&'a Expr>,
+    // 探索中の外側の行とキーの前半
+    current: Option<(Row, Vec<u8>, BTreeScan)>,
+}
+
+impl<'a> IndexJoin<'a> {
+    pub fn new(
+        ctx: &mut ExecContext,
+        outer: BoxExecutor<'a>,
+        table: &'a str,
+        index: &'a str,
+        keys: &'a [Expr],
+        predicate: Option<&'a Expr>,
+    ) -> Result<Self, Error> {
+        let (table_name, index_name) = (table, index);
+        let table = ctx
+            .catalog
+            .table(table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        let index = table
+            .index(index)
+            .ok_or_else(|| Error::IndexNotFound(index.to_string()))?;
+        Ok(Self {
+            outer,
+            table: table_name,
+            index: index_name,
+            storage: table.storage.clone(),
+            width: table.columns.len(),
+            virtual_columns: table.virtual_columns().cloned().collect(),
+            dictionaries: table.dictionaries.clone(),
+            enums: table.enum_types(),
+            btree: index.btree,
+            keys,
+            predicate,
+            current: None,
+        })
+    }
+
+    // 外側の次の行でインデックスの探索を始める。外側が尽きたら false
+    fn next_outer(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        'rows: while let Some(row) = self.outer.next(ctx)? {
+            let mut values = Vec::with_capacity(self.keys.len());
+            for key in self.keys {
+                let value = key.eval(&row)?;
+                // NULL はどの行とも一致しない
+                if value.is_null() {
+                    continue 'rows;
+                }
+                values.push(value);
+            }
+            let mut prefix = vec![];
+            btree::key::encode(&values, &mut prefix);
+            // 外側の行ごとに 1 回インデックスを探したと数える
+            if let Some(io) = ctx.io {
+                io.update(self.table, |io| io.index_scans += 1);
+                io.update(self.index, |io| io.index_scans += 1);
+            }
+            let btree = self.btree;
+            let scan = ctx.count_reads(self.index, |ctx| btree.scan(ctx.bufmgr, Some(&prefix)))?;
+            self.current = Some((row, prefix, scan));
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+impl Executor for IndexJoin<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            ctx.check_interrupt()?;
+            let Some((outer, prefix, scan)) = &mut self.current else {
+                if !self.next_outer(ctx)? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            let key = match ctx.count_reads(self.index, |ctx| scan.next(ctx.bufmgr))? {
+                Some((key, _)) if key.starts_with(prefix) => key,
+                _ => {
+                    self.current = None;
+                    continue;
+                }
+            };
+            let rid = catalog::decode_record_id(&key);
+            let storage = &self.storage;
+            let Some((header, bytes)) =
+                ctx.count_reads(self.table, |ctx| storage.get(ctx.bufmgr, rid))?
+            else {
+                continue;
+            };
+            if !ctx.visible(&header) {
+                continue;
+            }
+            let inner = decode_row(
+                &bytes,
+                self.width,
+                None,
+                &self.virtual_columns,
+                &self.dictionaries,
+                &self.enums,
+                rid,
+            )?;
+            let mut row = outer.clone();
+            row.extend(inner);
+            match self.predicate {
+                Some(predicate) if !predicate.eval_predicate(&row)? => {}
+                _ => return Ok(Some(row)),
+            }
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.outer.close(ctx)
+    }
+}
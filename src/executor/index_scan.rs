@@ -0,0 +1,222 @@
+use std::sync::Arc;
+
+use crate::btree::{self, BTreeScan};
+use crate::catalog::{self, Dictionary, GeneratedColumn};
+use crate::enum_type::EnumType;
+use crate::heap::RecordId;
+use crate::storage::TableAccess;
+use crate::types::{DataType, Value};
+
+use super::expr::Expr;
+use super::scan::decode_row;
+use super::{Error, ExecContext, Executor, Row};
+
+// インデックスの走査範囲の端
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanBound {
+    pub value: Expr,
+    pub inclusive: bool,
+}
+
+// 先頭の列から順に prefix の値と等しく、その次の列が lower と upper の間にあるキーの範囲。
+// 値は定数か $n で、走査を始めるときに評価する
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IndexRange {
+    pub prefix: Vec<Expr>,
+    pub lower: Option<ScanBound>,
+    pub upper: Option<ScanBound>,
+}
+
+// インデックスの範囲走査
+//
+// range に入る行をキーの順に返す。lower か upper があれば、その列が NULL の行は返さない。
+// index_only なら版が見えるかだけをヒープで確かめ、行はキーの値で作ってインデックスにない列は NULL にする。
+pub struct IndexScan<'a> {
+    table: &'a str,
+    index: &'a str,
+    storage: Arc<dyn TableAccess>,
+    // 行を読むときに計算する生成列
+    virtual_columns: Vec<GeneratedColumn>,
+    dictionaries: Vec<Arc<Dictionary>>,
+    enums: Vec<Arc<EnumType>>,
+    scan: BTreeScan,
+    // テーブルの列の数と、キーの各値を置く列の位置とその型
+    width: usize,
+    columns: Vec<(usize, DataType)>,
+    prefix: Vec<u8>,
+    lower: Option<(Vec<u8>, bool)>,
+    upper: Option<(Vec<u8>, bool)>,
+    predicate: Option<&'a Expr>,
+    index_only: bool,
+    rid: Option<RecordId>,
+    done: bool,
+}
+
+fn encode(value: &Value) -> Vec<u8> {
+    let mut bytes = vec![];
+    btree::key::encode(std::slice::from_ref(value), &mut bytes);
+    bytes
+}
+
+impl<'a> IndexScan<'a> {
+    pub fn new(
+        ctx: &mut ExecContext,
+        table: &'a str,
+        index: &'a str,
+        range: &IndexRange,
+        predicate: Option<&'a Expr>,
+        index_only: bool,
+    ) -> Result<Self, Error> {
+        let (table_name, index_name) = (table, index);
+        let table = ctx
+            .catalog
+            .table(table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        let index = table
+            .index(index)
+            .ok_or_else(|| Error::IndexNotFound(index.to_string()))?;
+        if let Some(io) = ctx.io {
+            io.update(table_name, |io| io.index_scans += 1);
+            io.update(index_name, |io| io.index_scans += 1);
+        }
+        let prefix = range
+            .prefix
+            .iter()
+            .map(|value| value.eval(&vec![]))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut key = vec![];
+        btree::key::encode(&prefix, &mut key);
+        let bound = |bound: &ScanBound| -> Result<_, Error> {
+            Ok((encode(&bound.value.eval(&vec![])?), bound.inclusive))
+        };
+        let lower = range.lower.as_ref().map(bound).transpose()?;
+        let upper = range.upper.as_ref().map(bound).transpose()?;
+        let mut start = key.clone();
+        if let Some((value, _)) = &lower {
+            start.extend_from_slice(value);
+        }
+        let btree = index.btree;
+        Ok(Self {
+            table: table_name,
+            index: index_name,
+            storage: table.storage.clone(),
+            virtual_columns: table.virtual_columns().cloned().collect(),
+            dictionaries: table.dictionaries.clone(),
+            enums: table.enum_types(),
+            scan: ctx.count_reads(index_name, |ctx| btree.scan(ctx.bufmgr, Some(&start)))?,
+            width: table.columns.len(),
+            columns: index
+                .columns
+                .iter()
+                .map(|&i| (i, table.columns[i].data_type.clone()))
+                .collect(),
+            prefix: key,
+            lower,
+            upper,
+            predicate,
+            index_only,
+            rid: None,
+            done: false,
+        })
+    }
+
+    // キーが範囲に入っていれば Some(true)、下端と等しいので飛ばすなら Some(false)、範囲を超えたら None
+    fn in_range(&self, key: &[u8]) -> Option<bool> {
+        let rest = key.strip_prefix(&self.prefix[..])?;
+        if self.lower.is_some() || self.upper.is_some() {
+            // NULL はほかのどの値よりも後ろに並ぶ
+            if rest.starts_with(&encode(&Value::Null)) {
+                return None;
+            }
+        }
+        if let Some((upper, inclusive)) = &self.upper {
+            let beyond = if rest.starts_with(upper) {
+                !inclusive
+            } else {
+                rest > &upper[..]
+            };
+            if beyond {
+                return None;
+            }
+        }
+        match &self.lower {
+            Some((lower, false)) => Some(!rest.starts_with(lower)),
+            _ => Some(true),
+        }
+    }
+
+    fn read_row(
+        &self,
+        ctx: &mut ExecContext,
+        key: &[u8],
+        rid: RecordId,
+    ) -> Result<Option<Row>, Error> {
+        // インデックスには消した版を指すキーも残っている
+        let storage = &self.storage;
+        let Some((header, bytes)) =
+            ctx.count_reads(self.table, |ctx| storage.get(ctx.bufmgr, rid))?
+        else {
+            return Ok(None);
+        };
+        if !ctx.visible(&header) {
+            return Ok(None);
+        }
+        if self.index_only {
+            let values = btree::key::decode(key, self.columns.len(), &self.enums)
+                .ok_or(Error::CorruptedTuple(rid))?;
+            let mut row = vec![Value::Null; self.width];
+            // キーからは数値の型がわからないので列の型に戻す
+            for ((i, ty), value) in self.columns.iter().zip(values) {
+                row[*i] = ty.coerce(value).map_err(|_| Error::CorruptedTuple(rid))?;
+            }
+            return Ok(Some(row));
+        }
+        Ok(Some(decode_row(
+            &bytes,
+            self.width,
+            None,
+            &self.virtual_columns,
+            &self.dictionaries,
+            &self.enums,
+            rid,
+        )?))
+    }
+}
+
+impl Executor for IndexScan<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        while !self.done {
+            ctx.check_interrupt()?;
+            let scan = &mut self.scan;
+            let Some((key, _)) = ctx.count_reads(self.index, |ctx| scan.next(ctx.bufmgr))? else {
+                break;
+            };
+            match self.in_range(&key) {
+                None => self.done = true,
+                Some(false) => {}
+                Some(true) => {
+                    let rid = catalog::decode_record_id(&key);
+                    let Some(row) = self.read_row(ctx, &key, rid)? else {
+                        continue;
+                    };
+                    match self.predicate {
+                        Some(predicate) if !predicate.eval_predicate(&row)? => {}
+                        _ => {
+                            self.rid = Some(rid);
+                            return Ok(Some(row));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.rid
+    }
+
+    fn relation(&self) -> Option<&str> {
+        Some(self.table)
+    }
+}
@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::materialize::RowStore;
+use super::{BoxExecutor, Error, ExecContext, Executor, JoinType, PlanNode, Row};
+
+// LATERAL の副問い合わせとの結合
+//
+// 外側の行を 1 つ読むたびに、その行だけを id の作業テーブルに入れて内側を計画から実行し直す。
+// 内側はその作業テーブルを CteScan で読み、外側の列の値を使う。外側の行ごとに始め直すので、
+// 内側の Materialize などがためた行を次の外側の行で読み直すことはない。
+// single なら内側は外側の行ごとに 1 行までしか返してはならず、2 行目でエラーにする
+pub struct LateralJoin<'a> {
+    outer: BoxExecutor<'a>,
+    inner_plan: &'a PlanNode,
+    id: usize,
+    predicate: Option<&'a Expr>,
+    join_type: JoinType,
+    single: bool,
+    inner_width: usize,
+    // 今の外側の行と、それに対して実行している内側。一致した行があったか
+    current: Option<(Row, BoxExecutor<'a>, bool)>,
+}
+
+impl<'a> LateralJoin<'a> {
+    pub fn new(
+        outer: BoxExecutor<'a>,
+        inner_plan: &'a PlanNode,
+        id: usize,
+        predicate: Option<&'a Expr>,
+        join_type: JoinType,
+        single: bool,
+        inner_width: usize,
+    ) -> Self {
+        Self {
+            outer,
+            inner_plan,
+            id,
+            predicate,
+            join_type,
+            single,
+            inner_width,
+            current: None,
+        }
+    }
+
+    // 外側の次の行で内側を始める。外側が尽きたら false
+    fn next_outer(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        let Some(row) = self.outer.next(ctx)? else {
+            return Ok(false);
+        };
+        let mut store = RowStore::new(ctx.memory.reservation());
+        store.push(ctx, row.clone())?;
+        ctx.ctes.insert(self.id, Rc::new(RefCell::new(store)));
+        let inner = self.inner_plan.start(ctx)?;
+        self.current = Some((row, inner, false));
+        Ok(true)
+    }
+}
+
+impl Executor for LateralJoin<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            ctx.check_interrupt()?;
+            let Some((outer, inner, matched)) = &mut self.current else {
+                if !self.next_outer(ctx)? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            let Some(inner_row) = inner.next(ctx)? else {
+                inner.close(ctx)?;
+                let (mut row, _, matched) = self.current.take().unwrap();
+                match self.join_type {
+                    JoinType::Left if !matched => {
+                        row.resize(row.len() + self.inner_width, Value::Null);
+                        return Ok(Some(row));
+                    }
+                    JoinType::Anti if !matched => return Ok(Some(row)),
+                    _ => continue,
+                }
+            };
+            if self.single && *matched {
+                return Err(Error::SubqueryRows);
+            }
+            let mut row = outer.clone();
+            row.extend(inner_row);
+            match self.predicate {
+                Some(predicate) if !predicate.eval_predicate(&row)? => continue,
+                _ => {}
+            }
+            *matched = true;
+            match self.join_type {
+                // 一致した行が 1 つあれば内側の残りは読まない
+                JoinType::Semi | JoinType::Anti => {
+                    let (outer, mut inner, _) = self.current.take().unwrap();
+                    inner.close(ctx)?;
+                    if self.join_type == JoinType::Semi {
+                        return Ok(Some(outer));
+                    }
+                }
+                _ => return Ok(Some(row)),
+            }
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        if let Some((_, mut inner, _)) = self.current.take() {
+            inner.close(ctx)?;
+        }
+        ctx.ctes.remove(&self.id);
+        self.outer.close(ctx)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::heap::RecordId;
+
+use super::{BoxExecutor, Error, ExecContext, Executor, Row};
+
+// offset 行を読み飛ばしてから limit 行だけ返す
+//
+// limit 行を返し終えたらその場で子を close し、それ以上は子から読まない。
+pub struct Limit<'a> {
+    input: BoxExecutor<'a>,
+    limit: Option<usize>,
+    offset: usize,
+    returned: usize,
+    closed: bool,
+}
+
+impl<'a> Limit<'a> {
+    pub fn new(input: BoxExecutor<'a>, limit: Option<usize>, offset: usize) -> Self {
+        Self {
+            input,
+            limit,
+            offset,
+            returned: 0,
+            closed: false,
+        }
+    }
+}
+
+impl Executor for Limit<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if self.closed {
+            return Ok(None);
+        }
+        if self.limit.is_some_and(|limit| self.returned >= limit) {
+            self.close(ctx)?;
+            return Ok(None);
+        }
+        while self.offset > 0 {
+            if self.input.next(ctx)?.is_none() {
+                return Ok(None);
+            }
+            self.offset -= 1;
+        }
+        let row = self.input.next(ctx)?;
+        self.returned += 1;
+        Ok(row)
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.input.record_id()
+    }
+
+    fn relation(&self) -> Option<&str> {
+        self.input.relation()
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.input.close(ctx)
+    }
+}
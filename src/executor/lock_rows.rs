@@ -0,0 +1,102 @@
+use crate::heap::RecordId;
+use crate::lock::{self, LockMode, LockWait};
+use crate::sql::ast::WaitPolicy;
+
+use super::{BoxExecutor, Error, ExecContext, Executor, Row};
+
+// SELECT ... FOR UPDATE / FOR SHARE。入力の行を返す前にその行をロックする。
+// トランザクションの外ではロックをかけずにそのまま返す
+pub struct LockRows<'a> {
+    input: BoxExecutor<'a>,
+    table: &'a str,
+    mode: LockMode,
+    wait: WaitPolicy,
+    rid: Option<RecordId>,
+    // ロックを待って戻った行。次はこの行からロックする
+    blocked: Option<(Row, RecordId)>,
+}
+
+impl<'a> LockRows<'a> {
+    pub fn new(input: BoxExecutor<'a>, table: &'a str, mode: LockMode, wait: WaitPolicy) -> Self {
+        Self {
+            input,
+            table,
+            mode,
+            wait,
+            rid: None,
+            blocked: None,
+        }
+    }
+
+    // ロックできれば true。SKIP LOCKED でほかのトランザクションが持っていれば false
+    fn lock(&self, ctx: &mut ExecContext, rid: RecordId) -> Result<bool, Error> {
+        let Some(txn) = &ctx.txn else {
+            return Ok(true);
+        };
+        let wait = match self.wait {
+            WaitPolicy::Block => txn.lock_wait(),
+            WaitPolicy::NoWait | WaitPolicy::SkipLocked => LockWait::NoWait,
+        };
+        match txn.lock_row_with(self.table, rid, self.mode, wait) {
+            Err(lock::Error::LockNotAvailable) if self.wait == WaitPolicy::SkipLocked => {
+                return Ok(false)
+            }
+            result => result?,
+        }
+        // ロックを待つあいだに、ほかのトランザクションが書き換えてコミットしたかもしれない
+        let table = ctx
+            .catalog
+            .table(self.table)
+            .ok_or_else(|| Error::TableNotFound(self.table.to_string()))?;
+        match table.storage.get(ctx.bufmgr, rid)? {
+            Some((header, _)) if header.xmax.valid().is_some_and(|xmax| xmax != txn.id()) => {
+                Err(Error::SerializationFailure)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+impl Executor for LockRows<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            let (row, rid) = match self.blocked.take() {
+                Some(blocked) => blocked,
+                None => {
+                    let Some(row) = self.input.next(ctx)? else {
+                        return Ok(None);
+                    };
+                    let rid = self
+                        .input
+                        .record_id()
+                        .expect("input of FOR UPDATE returns table rows");
+                    (row, rid)
+                }
+            };
+            match self.lock(ctx, rid) {
+                Ok(true) => {
+                    self.rid = Some(rid);
+                    return Ok(Some(row));
+                }
+                Ok(false) => {}
+                Err(err @ Error::Lock(lock::Error::WouldBlock)) => {
+                    self.blocked = Some((row, rid));
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.rid
+    }
+
+    fn relation(&self) -> Option<&str> {
+        self.input.relation()
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
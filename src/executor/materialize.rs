@@ -0,0 +1,200 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::batch::Batch;
+use super::memory::MemoryReservation;
+use super::spill::{SpillFile, SpillReader};
+use super::{row_size, BoxExecutor, Error, ExecContext, Executor, PlanNode, Row};
+
+// 読み直せるように行をためておく場所。work_mem か問い合わせの予算を超えたら一時ファイルに移す
+pub struct RowStore {
+    rows: Vec<Row>,
+    spill: Option<SpillFile>,
+    size: usize,
+    reservation: MemoryReservation,
+}
+
+// RowStore のどこまで読んだか
+pub enum StoreCursor {
+    Memory(usize),
+    File(SpillReader),
+}
+
+impl RowStore {
+    pub fn new(reservation: MemoryReservation) -> Self {
+        Self {
+            rows: vec![],
+            spill: None,
+            size: 0,
+            reservation,
+        }
+    }
+
+    // 計画を最後まで実行した結果をためる
+    pub fn fill(plan: &PlanNode, ctx: &mut ExecContext) -> Result<Self, Error> {
+        let mut store = Self::new(ctx.memory.reservation());
+        let mut exec = plan.start(ctx)?;
+        while let Some(row) = exec.next(ctx)? {
+            ctx.check_interrupt()?;
+            store.push(ctx, row)?;
+        }
+        exec.close(ctx)?;
+        Ok(store)
+    }
+
+    pub fn push(&mut self, ctx: &ExecContext, row: Row) -> Result<(), Error> {
+        if let Some(file) = &mut self.spill {
+            file.write(&row)?;
+            return Ok(());
+        }
+        let size = row_size(&row);
+        self.size += size;
+        self.rows.push(row);
+        if self.size > ctx.work_mem || !self.reservation.can_grow(size) {
+            let mut file = SpillFile::new()?;
+            for row in self.rows.drain(..) {
+                file.write(&row)?;
+            }
+            self.spill = Some(file);
+            self.reservation.free();
+        } else {
+            self.reservation.grow(size);
+        }
+        Ok(())
+    }
+
+    // 先頭から読むカーソル。作ったあとは push しない
+    pub fn cursor(&mut self) -> Result<StoreCursor, Error> {
+        match &mut self.spill {
+            Some(file) => Ok(StoreCursor::File(file.reader()?)),
+            None => Ok(StoreCursor::Memory(0)),
+        }
+    }
+
+    pub fn read(&self, cursor: &mut StoreCursor) -> Result<Option<Row>, Error> {
+        match cursor {
+            StoreCursor::Memory(i) => {
+                let row = self.rows.get(*i).cloned();
+                *i += 1;
+                Ok(row)
+            }
+            StoreCursor::File(reader) => Ok(reader.next()?),
+        }
+    }
+}
+
+// 子の出力をすべてためてから返す。rescan では子を実行し直さずにためた行を先頭から返す
+pub struct Materialize<'a> {
+    input: BoxExecutor<'a>,
+    store: RowStore,
+    cursor: Option<StoreCursor>,
+}
+
+impl<'a> Materialize<'a> {
+    pub fn new(input: BoxExecutor<'a>, reservation: MemoryReservation) -> Self {
+        Self {
+            input,
+            store: RowStore::new(reservation),
+            cursor: None,
+        }
+    }
+}
+
+impl Executor for Materialize<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        let cursor = match &mut self.cursor {
+            Some(cursor) => cursor,
+            None => {
+                while let Some(row) = self.input.next(ctx)? {
+                    ctx.check_interrupt()?;
+                    self.store.push(ctx, row)?;
+                }
+                self.cursor.insert(self.store.cursor()?)
+            }
+        };
+        self.store.read(cursor)
+    }
+
+    fn rescan(&mut self, _ctx: &mut ExecContext) -> Result<bool, Error> {
+        if self.cursor.is_some() {
+            self.cursor = Some(self.store.cursor()?);
+        }
+        Ok(true)
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.cursor = None;
+        self.store = RowStore::new(ctx.memory.reservation());
+        self.input.close(ctx)
+    }
+}
+
+// WITH の問い合わせの結果を読む。結果は With が先に ExecContext にためておく
+pub struct CteScan {
+    store: Rc<RefCell<RowStore>>,
+    cursor: StoreCursor,
+}
+
+impl CteScan {
+    pub fn new(ctx: &ExecContext, id: usize) -> Result<Self, Error> {
+        let store = ctx
+            .ctes
+            .get(&id)
+            .expect("WITH query is materialized before it is read")
+            .clone();
+        let cursor = store.borrow_mut().cursor()?;
+        Ok(Self { store, cursor })
+    }
+}
+
+impl Executor for CteScan {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        ctx.check_interrupt()?;
+        self.store.borrow().read(&mut self.cursor)
+    }
+
+    fn rescan(&mut self, _ctx: &mut ExecContext) -> Result<bool, Error> {
+        self.cursor = self.store.borrow_mut().cursor()?;
+        Ok(true)
+    }
+}
+
+// 本体を始める前に、複数回参照される WITH の問い合わせを順に実行して結果をためる
+pub struct With<'a> {
+    input: BoxExecutor<'a>,
+    ids: Vec<usize>,
+}
+
+impl<'a> With<'a> {
+    pub fn new(
+        ctes: &[(usize, PlanNode)],
+        input: &'a PlanNode,
+        ctx: &mut ExecContext,
+    ) -> Result<Self, Error> {
+        for (id, plan) in ctes {
+            let store = RowStore::fill(plan, ctx)?;
+            ctx.ctes.insert(*id, Rc::new(RefCell::new(store)));
+        }
+        Ok(Self {
+            input: input.start(ctx)?,
+            ids: ctes.iter().map(|(id, _)| *id).collect(),
+        })
+    }
+}
+
+impl Executor for With<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        self.input.next(ctx)
+    }
+
+    fn next_batch(&mut self, ctx: &mut ExecContext) -> Result<Option<Batch>, Error> {
+        self.input.next_batch(ctx)
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        for id in &self.ids {
+            ctx.ctes.remove(id);
+        }
+        self.input.close(ctx)
+    }
+}
@@ -0,0 +1,109 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+// 問い合わせ全体で使ってよいメモリの量
+//
+// 並べ替えやハッシュ表を作る演算子はそれぞれ MemoryReservation で使う量を申告し、
+// 残りが足りなければ一時ファイルに書き出す。どこで書き出すかは入力の順序だけで決まる。
+#[derive(Debug, Clone)]
+pub struct MemoryBudget(Rc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    limit: usize,
+    used: Cell<usize>,
+    peak: Cell<usize>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self(Rc::new(Inner {
+            limit,
+            used: Cell::new(0),
+            peak: Cell::new(0),
+        }))
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.0.limit
+    }
+
+    pub fn used(&self) -> usize {
+        self.0.used.get()
+    }
+
+    // これまでに同時に使った量の最大
+    pub fn peak(&self) -> usize {
+        self.0.peak.get()
+    }
+
+    // 最大を今の量に戻し、それまでの最大を返す。区間の中の最大を測ったら raise_peak で戻す
+    pub(crate) fn reset_peak(&self) -> usize {
+        self.0.peak.replace(self.used())
+    }
+
+    pub(crate) fn raise_peak(&self, peak: usize) {
+        self.0.peak.set(self.0.peak.get().max(peak));
+    }
+
+    pub fn reservation(&self) -> MemoryReservation {
+        MemoryReservation {
+            budget: self.clone(),
+            size: 0,
+        }
+    }
+}
+
+// 1 つの演算子が使っているメモリの量。捨てると予算に返す
+#[derive(Debug)]
+pub struct MemoryReservation {
+    budget: MemoryBudget,
+    size: usize,
+}
+
+impl MemoryReservation {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // あと n バイト使っても予算に収まるか
+    pub fn can_grow(&self, n: usize) -> bool {
+        self.budget
+            .used()
+            .checked_add(n)
+            .is_some_and(|used| used <= self.budget.limit())
+    }
+
+    // 予算を超えていても申告はする。書き出せない場合に使う
+    pub fn grow(&mut self, n: usize) {
+        let inner = &self.budget.0;
+        let used = inner.used.get().saturating_add(n);
+        inner.used.set(used);
+        inner.peak.set(inner.peak.get().max(used));
+        self.size += n;
+    }
+
+    // 使わなくなった n バイトを予算に返す
+    pub fn shrink(&mut self, n: usize) {
+        let n = n.min(self.size);
+        let inner = &self.budget.0;
+        inner.used.set(inner.used.get() - n);
+        self.size -= n;
+    }
+
+    pub fn free(&mut self) {
+        let inner = &self.budget.0;
+        inner.used.set(inner.used.get() - self.size);
+        self.size = 0;
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.free();
+    }
+}
@@ -0,0 +1,128 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::sort::compare_values;
+use super::{BoxExecutor, Error, ExecContext, Executor, Row};
+
+type Key = Vec<Value>;
+
+// マージ結合。両側が結合キーの昇順に並んでいることを前提とする
+//
+// 右側は同じキーの行をまとめて読み、左側に同じキーが続く間はそのまとまりを使い回す。
+pub struct MergeJoin<'a> {
+    inputs: [BoxExecutor<'a>; 2],
+    keys: [&'a [Expr]; 2],
+    predicate: Option<&'a Expr>,
+    left: Option<(Key, Row)>,
+    // 右側の次の行 (まだまとまりに入れていない)
+    right: Option<(Key, Row)>,
+    group_key: Option<Key>,
+    group: Vec<Row>,
+    output: VecDeque<Row>,
+}
+
+impl<'a> MergeJoin<'a> {
+    pub fn new(
+        left: BoxExecutor<'a>,
+        right: BoxExecutor<'a>,
+        left_keys: &'a [Expr],
+        right_keys: &'a [Expr],
+        predicate: Option<&'a Expr>,
+    ) -> Self {
+        Self {
+            inputs: [left, right],
+            keys: [left_keys, right_keys],
+            predicate,
+            left: None,
+            right: None,
+            group_key: None,
+            group: vec![],
+            output: VecDeque::new(),
+        }
+    }
+
+    // キーに NULL を含む行は読み飛ばす
+    fn fetch(&mut self, side: usize, ctx: &mut ExecContext) -> Result<Option<(Key, Row)>, Error> {
+        'rows: while let Some(row) = self.inputs[side].next(ctx)? {
+            let mut key = Vec::with_capacity(self.keys[side].len());
+            for expr in self.keys[side] {
+                let value = expr.eval(&row)?;
+                if value.is_null() {
+                    continue 'rows;
+                }
+                key.push(value);
+            }
+            return Ok(Some((key, row)));
+        }
+        Ok(None)
+    }
+
+    fn join_group(&mut self, left: &Row) -> Result<(), Error> {
+        for right in &self.group {
+            let joined = [left.as_slice(), right.as_slice()].concat();
+            match self.predicate {
+                Some(predicate) if !predicate.eval_predicate(&joined)? => {}
+                _ => self.output.push_back(joined),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Executor for MergeJoin<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            ctx.check_interrupt()?;
+            if let Some(row) = self.output.pop_front() {
+                return Ok(Some(row));
+            }
+            if self.left.is_none() {
+                self.left = self.fetch(0, ctx)?;
+            }
+            let Some((left_key, left_row)) = self.left.take() else {
+                return Ok(None);
+            };
+            if self.group_key.as_ref() == Some(&left_key) {
+                self.join_group(&left_row)?;
+                continue;
+            }
+            if self.right.is_none() {
+                self.right = self.fetch(1, ctx)?;
+            }
+            let Some((right_key, _)) = &self.right else {
+                return Ok(None);
+            };
+            match compare_values(&left_key, right_key) {
+                Ordering::Less => {}
+                Ordering::Greater => {
+                    self.right = None;
+                    self.left = Some((left_key, left_row));
+                }
+                Ordering::Equal => {
+                    // 右側の同じキーの行をまとめる
+                    let (key, row) = self.right.take().unwrap();
+                    self.group = vec![row];
+                    loop {
+                        match self.fetch(1, ctx)? {
+                            Some((next_key, row)) if next_key == key => self.group.push(row),
+                            next => {
+                                self.right = next;
+                                break;
+                            }
+                        }
+                    }
+                    self.group_key = Some(key);
+                    self.left = Some((left_key, left_row));
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.inputs[0].close(ctx)?;
+        self.inputs[1].close(ctx)
+    }
+}
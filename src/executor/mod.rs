@@ -0,0 +1,3020 @@
+mod aggregate;
+mod as_of;
+mod batch;
+mod cancel;
+mod cursor;
+mod explain;
+pub mod expr;
+mod filter;
+mod fulltext_scan;
+mod function;
+mod gather;
+mod hash_join;
+mod index_join;
+mod index_scan;
+mod lateral_join;
+mod limit;
+mod lock_rows;
+mod materialize;
+mod memory;
+mod merge_join;
+mod modify;
+mod nested_loop;
+mod project_set;
+mod projection;
+mod recursive_union;
+mod rtree_scan;
+mod runtime_filter;
+mod sample_scan;
+mod scan;
+mod set_op;
+mod sort;
+mod spill;
+mod stats_scan;
+mod typing;
+mod unique;
+mod values;
+mod window;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::bloom::BloomFilter;
+use crate::btree;
+use crate::buffer::{self, BufferPoolManager};
+use crate::catalog::{self, Catalog};
+use crate::clock::{self, Instant};
+use crate::heap::{self, RecordId, TupleHeader};
+use crate::lock::{self, LockMode};
+use crate::planner;
+use crate::regex::RegexError;
+use crate::rtree::{self, Search};
+use crate::sql::ast::{ExplainFormat, SampleMethod, SetOperator, WaitPolicy};
+use crate::stats::{Activity, IoStats, StatsView};
+use crate::transaction::{self, Snapshot, Transaction, TxnId};
+use crate::types::Value;
+use crate::wal;
+
+use aggregate::{GroupAggregate, HashAggregate};
+use as_of::AsOf;
+use explain::{ExplainAnalyze, Instrumented, MetricsMap};
+use expr::Expr;
+use filter::Filter;
+use fulltext_scan::FulltextScan;
+use gather::Gather;
+use hash_join::HashJoin;
+use index_join::IndexJoin;
+use index_scan::IndexScan;
+use lateral_join::LateralJoin;
+use limit::Limit;
+use lock_rows::LockRows;
+use materialize::{CteScan, Materialize, RowStore, With};
+use merge_join::MergeJoin;
+use modify::{Delete, Insert, Update};
+use nested_loop::NestedLoopJoin;
+use project_set::ProjectSet;
+use projection::Projection;
+use recursive_union::RecursiveUnion;
+use rtree_scan::RTreeScan;
+use runtime_filter::RuntimeFilter;
+use sample_scan::SampleScan;
+use scan::SeqScan;
+use set_op::{Append, HashSetOp};
+use sort::Sort;
+use stats_scan::StatsScan;
+use unique::Unique;
+use values::Values;
+use window::Window;
+
+pub use aggregate::{AggregateCall, AggregateFunction};
+pub use batch::{Batch, ValueVector, BATCH_SIZE};
+pub use cancel::CancelToken;
+pub use cursor::{Cursor, Cursors};
+pub use explain::{
+    execute_profiled, format_explain, format_plan, ExplainNode, Metrics, OperatorProfile, Profile,
+};
+pub use index_scan::{IndexRange, ScanBound};
+pub use memory::{MemoryBudget, MemoryReservation};
+pub use modify::{ConflictAction, OnConflict};
+pub use sort::SortKey;
+pub(crate) use sort::{compare_values, Sorter};
+pub use window::{WindowCall, WindowFunction};
+
+pub type Row = Vec<Value>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Buffer(#[from] buffer::Error),
+    #[error(transparent)]
+    Heap(#[from] heap::Error),
+    #[error(transparent)]
+    BTree(#[from] btree::Error),
+    #[error(transparent)]
+    RTree(#[from] rtree::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Catalog(#[from] catalog::Error),
+    #[error(transparent)]
+    Lock(#[from] lock::Error),
+    #[error(transparent)]
+    Wal(#[from] wal::Error),
+    #[error(transparent)]
+    Transaction(#[from] transaction::Error),
+    #[error("table \"{0}\" does not exist")]
+    TableNotFound(String),
+    #[error("cannot modify table \"{0}\" in a read-only session")]
+    ReadOnly(String),
+    #[error("index \"{0}\" does not exist")]
+    IndexNotFound(String),
+    #[error("corrupted tuple at page {}, slot {}", .0.page_id.to_u64(), .0.slot)]
+    CorruptedTuple(RecordId),
+    #[error("operator does not exist: {left} {op} {right}")]
+    UndefinedOperator {
+        op: &'static str,
+        left: String,
+        right: String,
+    },
+    #[error("operator does not exist: {op} {operand}")]
+    UndefinedUnaryOperator { op: &'static str, operand: String },
+    #[error("argument of WHERE must be type boolean, not type {0}")]
+    NotBoolean(String),
+    #[error("function {name}({arg}) does not exist")]
+    UndefinedFunction { name: &'static str, arg: String },
+    #[error("function {name} failed: {message}")]
+    UserFunctionFailed { name: String, message: String },
+    #[error("trigger \"{name}\" failed: {message}")]
+    TriggerFailed { name: String, message: String },
+    #[error("{source} (trigger \"{name}\")")]
+    Trigger {
+        name: String,
+        source: Box<crate::planner::Error>,
+    },
+    #[error("trigger \"{0}\" exceeded the maximum nesting depth")]
+    TriggerDepth(String),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("integer out of range")]
+    IntegerOutOfRange,
+    #[error("numeric value out of range")]
+    NumericOutOfRange,
+    #[error("value out of range: overflow")]
+    FloatOutOfRange,
+    #[error("zero raised to a negative power is undefined")]
+    ZeroRaisedToNegativePower,
+    #[error("a negative number raised to a non-integer power yields a complex result")]
+    ComplexPower,
+    #[error("negative substring length not allowed")]
+    NegativeSubstringLength,
+    #[error("date out of range")]
+    DateOutOfRange,
+    #[error("interval out of range")]
+    IntervalOutOfRange,
+    #[error("invalid input syntax for type {expected}: \"{value}\"")]
+    InvalidSyntax { expected: String, value: String },
+    #[error("cannot cast type {from} to {to}")]
+    CannotCast { from: String, to: String },
+    #[error("type \"{0}\" does not exist")]
+    UndefinedType(String),
+    #[error("collation \"{0}\" does not exist")]
+    UndefinedCollation(String),
+    #[error("invalid JSON path \"{0}\"")]
+    InvalidJsonPath(String),
+    #[error("invalid escape string")]
+    InvalidEscapeString,
+    #[error("LIKE pattern must not end with escape character")]
+    LikePatternEndsWithEscape,
+    #[error("invalid regular expression: {0}")]
+    InvalidRegex(RegexError),
+    #[error("unit \"{unit}\" not supported for type {ty}")]
+    UnsupportedUnit { unit: String, ty: String },
+    #[error("{op} types {left} and {right} cannot be matched")]
+    SetOperationTypes {
+        op: SetOperator,
+        left: String,
+        right: String,
+    },
+    #[error("ON CONFLICT DO UPDATE command cannot affect row a second time")]
+    ConflictRowTwice,
+    #[error("could not serialize access due to concurrent update")]
+    SerializationFailure,
+    #[error("cursor \"{0}\" already exists")]
+    DuplicateCursor(String),
+    #[error("cursor \"{0}\" does not exist")]
+    CursorNotFound(String),
+    #[error("canceling statement due to user request")]
+    QueryCanceled,
+    #[error("canceling statement due to statement timeout")]
+    StatementTimeout,
+    #[error("query returned more than max_result_rows ({0}) rows")]
+    ResultRowLimit(u64),
+    #[error("temporary file size exceeds temp_file_limit ({0} bytes)")]
+    TempFileLimit(usize),
+    #[error("query memory exceeds max_query_memory ({0} bytes)")]
+    QueryMemoryLimit(usize),
+    #[error("recursive query exceeded max_recursion_depth ({0}) iterations")]
+    RecursionDepthLimit(u64),
+    #[error("more than one row returned by a subquery used as an expression")]
+    SubqueryRows,
+    #[error("AS OF point must not be null")]
+    AsOfNull,
+    #[error("there is no parameter ${0}")]
+    UnboundParameter(usize),
+}
+
+pub const DEFAULT_WORK_MEM: usize = 4 * 1024 * 1024;
+pub const DEFAULT_QUERY_MEM: usize = 64 * 1024 * 1024;
+
+// 実行中の演算子が共有する資源
+pub struct ExecContext<'a> {
+    pub bufmgr: &'a mut BufferPoolManager,
+    pub catalog: &'a Catalog,
+    // 実行しているトランザクション。行を書き換える演算子は変更をこのトランザクションのものとして残す
+    pub txn: Option<&'a mut Transaction>,
+    // 1 つの演算子がハッシュ表などに使ってよいメモリのバイト数。超えたら一時ファイルに書き出す
+    pub work_mem: usize,
+    // 問い合わせのすべての演算子で分け合うメモリ
+    pub memory: MemoryBudget,
+    pub cancel: CancelToken,
+    // この時刻を過ぎたら実行を止める
+    pub deadline: Option<Instant>,
+    // 問い合わせが返してよい行の数、書いてよい一時ファイルのバイト数、使ってよいメモリのバイト数。
+    // 超えたら実行を止める。一時ファイルはこのスレッドで書いたものだけを数える
+    pub max_rows: Option<u64>,
+    pub temp_file_limit: Option<usize>,
+    pub max_memory: Option<usize>,
+    // WITH RECURSIVE の再帰項を実行し直してよい回数
+    pub max_recursion_depth: Option<u64>,
+    // 実行を始めたときに、このスレッドが一時ファイルに書いていたバイト数
+    spilled_before: u64,
+    // 立っていればテーブルを書き換える演算子を開かない。ホットスタンバイで読むときに使う
+    pub read_only: bool,
+    // 立っていれば、トランザクションの外で書き換えた行を論理デコード用のレコードに書かない。COPY (BULK) で使う
+    pub unlogged: bool,
+    // With がためた WITH の問い合わせの結果
+    ctes: HashMap<usize, Rc<RefCell<RowStore>>>,
+    // HashJoin がハッシュ表のキーで作り、探索側の RuntimeFilter が読む Bloom フィルタ
+    runtime_filters: HashMap<usize, BloomFilter>,
+    // 同じ HashJoin のハッシュ表のキーの位置ごとの最小と最大。NULL でない値がなければ None
+    key_ranges: HashMap<usize, Vec<Option<(Value, Value)>>>,
+    // EXPLAIN ANALYZE の実行中なら、演算子ごとに測った値
+    metrics: Option<MetricsMap>,
+    // stats.activity で見せる接続の一覧。なければ stats.activity は行を返さない
+    pub activity: Option<&'a Activity>,
+    // テーブルとインデックスごとの読み書きを数える先。なければ数えない
+    pub io: Option<&'a IoStats>,
+    // 実行しているトリガーの文の入れ子の深さ
+    trigger_depth: usize,
+    // AS OF の走査の中なら、その時点のスナップショット
+    as_of: Option<Snapshot>,
+}
+
+impl<'a> ExecContext<'a> {
+    pub fn new(bufmgr: &'a mut BufferPoolManager, catalog: &'a Catalog) -> Self {
+        Self {
+            bufmgr,
+            catalog,
+            txn: None,
+            work_mem: DEFAULT_WORK_MEM,
+            memory: MemoryBudget::new(DEFAULT_QUERY_MEM),
+            cancel: CancelToken::new(),
+            deadline: None,
+            max_rows: None,
+            temp_file_limit: None,
+            max_memory: None,
+            max_recursion_depth: None,
+            spilled_before: spill::spilled().1,
+            read_only: false,
+            unlogged: false,
+            ctes: HashMap::new(),
+            runtime_filters: HashMap::new(),
+            key_ranges: HashMap::new(),
+            metrics: None,
+            activity: None,
+            io: None,
+            trigger_depth: 0,
+            as_of: None,
+        }
+    }
+
+    // 行の版につけるトランザクション。トランザクションの外なら誰からも見える版として書く
+    pub fn xid(&self) -> TxnId {
+        self.txn
+            .as_ref()
+            .map_or(TxnId::FROZEN_TXN_ID, |txn| txn.id())
+    }
+
+    // 読む演算子が返してよい版か。トランザクションの外では消されていない版がすべて見える
+    pub fn visible(&self, header: &TupleHeader) -> bool {
+        if let Some(snapshot) = &self.as_of {
+            return snapshot.visible(header);
+        }
+        match &self.txn {
+            Some(txn) => txn.snapshot().visible(header),
+            None => !header.is_deleted(),
+        }
+    }
+
+    // テーブルを読む演算子は IS、行を書き換える演算子は IX のロックをテーブルにかける。
+    // どちらもテーブルを作り変えるものとは両立しない
+    fn open_table(&mut self, table: &str, write: bool) -> Result<(), Error> {
+        if write && self.read_only {
+            return Err(Error::ReadOnly(table.to_string()));
+        }
+        if let Some(txn) = self.txn.as_mut() {
+            if write {
+                txn.lock_table(table, LockMode::IntentionExclusive)?;
+            } else {
+                txn.lock_table(table, LockMode::IntentionShared)?;
+                txn.record_read(table);
+            }
+        }
+        Ok(())
+    }
+
+    // 行を書き換える前にかける。トランザクションの外ではロックをかけない
+    pub fn lock_row(&mut self, table: &str, rid: RecordId) -> Result<(), Error> {
+        if let Some(txn) = &self.txn {
+            txn.lock_row(table, rid, LockMode::Exclusive)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_statement_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some(clock::now() + timeout);
+    }
+
+    // f の間に読んだページを relation の読み込みに数える
+    pub(super) fn count_reads<T>(&mut self, relation: &str, f: impl FnOnce(&mut Self) -> T) -> T {
+        let Some(io) = self.io else {
+            return f(self);
+        };
+        let (hits, reads) = (self.bufmgr.hits(), self.bufmgr.reads());
+        let result = f(self);
+        let (hits, reads) = (self.bufmgr.hits() - hits, self.bufmgr.reads() - reads);
+        io.update(relation, |io| {
+            io.hits += hits;
+            io.reads += reads;
+        });
+        result
+    }
+
+    // count_reads と同じく数え、f の間に汚したページも数える
+    pub(super) fn count_writes<T>(&mut self, relation: &str, f: impl FnOnce(&mut Self) -> T) -> T {
+        let Some(io) = self.io else {
+            return f(self);
+        };
+        let dirtied = self.bufmgr.dirtied();
+        let result = self.count_reads(relation, f);
+        let dirtied = self.bufmgr.dirtied() - dirtied;
+        io.update(relation, |io| io.dirtied += dirtied);
+        result
+    }
+
+    // 取り消されたか時間切れならエラーを返す。演算子は行を返すたびと長いループの中で呼ぶ
+    pub fn check_interrupt(&self) -> Result<(), Error> {
+        if self.cancel.is_canceled() {
+            return Err(Error::QueryCanceled);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| clock::now() >= deadline)
+        {
+            return Err(Error::StatementTimeout);
+        }
+        if let Some(limit) = self.temp_file_limit {
+            if spill::spilled().1 - self.spilled_before > limit as u64 {
+                return Err(Error::TempFileLimit(limit));
+            }
+        }
+        if let Some(limit) = self.max_memory {
+            if self.memory.used() > limit {
+                return Err(Error::QueryMemoryLimit(limit));
+            }
+        }
+        Ok(())
+    }
+}
+
+// メモリ上の行のおおよその大きさ
+pub(crate) fn row_size(row: &Row) -> usize {
+    let values = row
+        .iter()
+        .map(|value| match value {
+            Value::Text(s) => s.len(),
+            Value::Json(s) => s.len(),
+            Value::Blob(b) => b.len(),
+            _ => 0,
+        })
+        .sum::<usize>();
+    std::mem::size_of::<Row>() + row.len() * std::mem::size_of::<Value>() + values
+}
+
+// Volcano 方式の演算子。PlanNode::start が open に当たる
+pub trait Executor {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error>;
+
+    // 最大 BATCH_SIZE 行を列ごとの束で返す。束で処理できない演算子は next を繰り返す
+    fn next_batch(&mut self, ctx: &mut ExecContext) -> Result<Option<Batch>, Error> {
+        let mut rows = vec![];
+        while rows.len() < BATCH_SIZE {
+            match self.next(ctx)? {
+                Some(row) => rows.push(row),
+                None => break,
+            }
+        }
+        Ok((!rows.is_empty()).then(|| Batch::from_rows(rows)))
+    }
+
+    // 直前に返した行のヒープ上の位置。テーブルの行をそのまま返す演算子だけが返す
+    fn record_id(&self) -> Option<RecordId> {
+        None
+    }
+
+    // record_id の版を持つテーブル。パーティションに分けたテーブルの UPDATE と DELETE で、どの子の行かを知るのに使う
+    fn relation(&self) -> Option<&str> {
+        None
+    }
+
+    // 最初から読み直す。できない演算子は false を返すので、呼び出し側は計画から始め直す
+    fn rescan(&mut self, _ctx: &mut ExecContext) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    // 使い終わったときに呼ぶ。子を持つ演算子は子の close も呼ぶ
+    fn close(&mut self, _ctx: &mut ExecContext) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub type BoxExecutor<'a> = Box<dyn Executor + 'a>;
+
+// Left、Right、Full は相手のない行も反対側の列を NULL で埋めて返す。
+// Semi は右に条件を満たす行がある左の行、Anti はない左の行だけを返す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinType {
+    Inner,
+    Left,
+    Right,
+    Full,
+    Semi,
+    Anti,
+}
+
+impl JoinType {
+    pub fn keeps_left(self) -> bool {
+        matches!(self, JoinType::Left | JoinType::Full)
+    }
+
+    pub fn keeps_right(self) -> bool {
+        matches!(self, JoinType::Right | JoinType::Full)
+    }
+
+    // 左の列だけを返すか
+    pub fn is_semi(self) -> bool {
+        matches!(self, JoinType::Semi | JoinType::Anti)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanNode {
+    // needed があれば、上で使うその位置の列だけを読み、ほかの列は NULL にする
+    SeqScan {
+        table: String,
+        needed: Option<Vec<usize>>,
+    },
+    // インデックスの range の行のうち predicate を満たすものを返す。
+    // index_only ならヒープを読まず、インデックスにない列は NULL になる
+    IndexScan {
+        table: String,
+        index: String,
+        range: IndexRange,
+        predicate: Option<Expr>,
+        index_only: bool,
+    },
+    // 全文インデックスで terms のどれかを含む行を集め、predicate を満たすものを返す
+    FulltextScan {
+        table: String,
+        index: String,
+        terms: Vec<String>,
+        predicate: Option<Expr>,
+    },
+    // R-tree のインデックスで search に合う行を集め、predicate を満たすものを返す
+    RTreeScan {
+        table: String,
+        index: String,
+        search: Search,
+        predicate: Option<Expr>,
+    },
+    // テーブルの行を fraction の割合で選んで返す。seed がなければ走査を始めるたびに決める。needed は SeqScan と同じ
+    SampleScan {
+        table: String,
+        method: SampleMethod,
+        fraction: f64,
+        seed: Option<f64>,
+        needed: Option<Vec<usize>>,
+    },
+    // テーブルを読み、行の復元と predicate の評価を workers 個のスレッドに分けて、満たす行を返す。
+    // ページはこのスレッドで読む。needed は SeqScan と同じ
+    Gather {
+        table: String,
+        predicate: Option<Expr>,
+        workers: usize,
+        needed: Option<Vec<usize>>,
+    },
+    Values {
+        rows: Vec<Row>,
+    },
+    Filter {
+        input: Box<PlanNode>,
+        predicate: Expr,
+    },
+    // id の HashJoin がハッシュ表を作り終わったあと、keys がその Bloom フィルタにない行を捨てる。
+    // それまでは行をそのまま通す。partition は input が読むパーティションの子と、その分け方の列のキーの位置で、
+    // ハッシュ表のキーの範囲が子の範囲と重ならなければ残りを読まない
+    RuntimeFilter {
+        input: Box<PlanNode>,
+        id: usize,
+        keys: Vec<Expr>,
+        partition: Option<(String, usize)>,
+    },
+    // パーティションの子 table を読む input。$n を値で置き換えたあとの conditions が子の範囲と両立しなければ、
+    // 実行を始めるときに input を開かずに何も返さない
+    PartitionPrune {
+        input: Box<PlanNode>,
+        table: String,
+        conditions: Vec<Expr>,
+    },
+    // input のテーブルを point の時点の版で読む。lsn でなければ point は時刻
+    AsOf {
+        input: Box<PlanNode>,
+        point: Expr,
+        lsn: bool,
+    },
+    // 出力は左の行の後ろに右の行をつなげたもの。predicate がなければ直積
+    NestedLoopJoin {
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+        predicate: Option<Expr>,
+        join_type: JoinType,
+    },
+    // 等結合。left_keys と right_keys はそれぞれの側の行に対して評価する。
+    // runtime_filter は探索側に置いた RuntimeFilter の id と、それを置いた側 (0 が左、1 が右)
+    HashJoin {
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+        left_keys: Vec<Expr>,
+        right_keys: Vec<Expr>,
+        predicate: Option<Expr>,
+        join_type: JoinType,
+        runtime_filter: Option<(usize, usize)>,
+    },
+    // 外側の行ごとに keys でテーブルのインデックスを引く。出力は外側の行、テーブルの行の順
+    IndexJoin {
+        left: Box<PlanNode>,
+        table: String,
+        index: String,
+        keys: Vec<Expr>,
+        predicate: Option<Expr>,
+    },
+    // 左の行ごとに、その行を id の CteScan で読む right を実行し直す。出力は IndexJoin と同じ順。
+    // single なら right は左の行ごとに 1 行までしか返せない
+    LateralJoin {
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+        id: usize,
+        predicate: Option<Expr>,
+        join_type: JoinType,
+        single: bool,
+    },
+    // 両側が結合キーの昇順に並んでいる必要がある
+    MergeJoin {
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+        left_keys: Vec<Expr>,
+        right_keys: Vec<Expr>,
+        predicate: Option<Expr>,
+    },
+    // top_n があれば先頭の top_n 行だけを返す
+    Sort {
+        input: Box<PlanNode>,
+        keys: Vec<SortKey>,
+        top_n: Option<usize>,
+    },
+    // limit が None なら制限しない
+    Limit {
+        input: Box<PlanNode>,
+        limit: Option<usize>,
+        offset: usize,
+    },
+    // 直前と同じ行を除く。同じ行が隣り合うように並べた入力に使う
+    Unique {
+        input: Box<PlanNode>,
+    },
+    // 出力は group_by の値のあとに aggregates の結果を並べたもの
+    HashAggregate {
+        input: Box<PlanNode>,
+        group_by: Vec<Expr>,
+        aggregates: Vec<AggregateCall>,
+    },
+    // HashAggregate と同じものを、group_by の順に並んだ入力から求める
+    GroupAggregate {
+        input: Box<PlanNode>,
+        group_by: Vec<Expr>,
+        aggregates: Vec<AggregateCall>,
+    },
+    // HashAggregate と同じものを、input の Gather のワーカーがグループごとに途中まで集約した状態を
+    // まとめて求める。aggregates はすべて mergeable
+    ParallelAggregate {
+        input: Box<PlanNode>,
+        group_by: Vec<Expr>,
+        aggregates: Vec<AggregateCall>,
+    },
+    // GROUPING SETS の集約。sets は集合ごとの group_by の添字。出力は group_by の値 (集合にない式は NULL) のあとに、
+    // 集合にない式のビットを立てた番号と aggregates の結果を並べたもの。先頭の式が上位のビット
+    GroupingSets {
+        input: Box<PlanNode>,
+        group_by: Vec<Expr>,
+        sets: Vec<Vec<usize>>,
+        aggregates: Vec<AggregateCall>,
+    },
+    // 入力の行のあとに calls の結果を並べる。入力は partition_by、order_by の順に並べておく
+    Window {
+        input: Box<PlanNode>,
+        partition_by: Vec<Expr>,
+        order_by: Vec<SortKey>,
+        calls: Vec<WindowCall>,
+    },
+    // 入力の行のあとに exprs の配列の要素を 1 つずつ並べた行を返す。SELECT の unnest に使う
+    ProjectSet {
+        input: Box<PlanNode>,
+        exprs: Vec<Expr>,
+    },
+    // 左の行のあとに右の行を返す (UNION ALL)
+    Append {
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+    },
+    // op は INTERSECT か EXCEPT。all が false なら同じ行を一度だけ返す
+    HashSetOp {
+        op: SetOperator,
+        all: bool,
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+    },
+    // 入力の行をテーブルの列の順に並べたものとして入れる。
+    // DML の演算子はどれも処理した行数だけの 1 行を返す。returning なら処理したテーブルの行を返す
+    Insert {
+        table: String,
+        input: Box<PlanNode>,
+        on_conflict: Option<OnConflict>,
+        returning: bool,
+    },
+    // input はテーブルの行を返し、record_id を持つ必要がある。assignments は (列の位置, 新しい値)
+    Update {
+        table: String,
+        input: Box<PlanNode>,
+        assignments: Vec<(usize, Expr)>,
+        returning: bool,
+    },
+    Delete {
+        table: String,
+        input: Box<PlanNode>,
+        returning: bool,
+    },
+    // SELECT ... FOR UPDATE / FOR SHARE。input は Update と同じくテーブルの行を返す。
+    // 返す行ごとに mode の行ロックをかけ、SkipLocked ならロックできない行を飛ばす
+    LockRows {
+        table: String,
+        input: Box<PlanNode>,
+        mode: LockMode,
+        wait: WaitPolicy,
+    },
+    // 子の出力をためておき、読み直すときは子を実行し直さない
+    Materialize {
+        input: Box<PlanNode>,
+    },
+    // 複数回参照される WITH の問い合わせ。ctes を (id, 計画) の順に実行してから input を実行する
+    With {
+        ctes: Vec<(usize, PlanNode)>,
+        input: Box<PlanNode>,
+    },
+    // WITH RECURSIVE の問い合わせ。anchor の行を返したあと、前の段で返した行を id の CteScan で読む recursive を
+    // 行が出なくなるまで繰り返す。all でなければ同じ行は一度だけ返す
+    RecursiveUnion {
+        id: usize,
+        anchor: Box<PlanNode>,
+        recursive: Box<PlanNode>,
+        all: bool,
+    },
+    // With が実行した id の問い合わせの結果を読む
+    CteScan {
+        id: usize,
+        columns: Vec<String>,
+    },
+    // stats スキーマの表を読む
+    StatsScan {
+        view: StatsView,
+    },
+    // columns は結果の列名 (別名があればそれ)
+    Projection {
+        input: Box<PlanNode>,
+        exprs: Vec<Expr>,
+        columns: Vec<String>,
+    },
+    // input を実行し、nodes に測った値を添えて "QUERY PLAN" 列の行として返す
+    ExplainAnalyze {
+        input: Box<PlanNode>,
+        nodes: Vec<ExplainNode>,
+        format: ExplainFormat,
+    },
+}
+
+impl PlanNode {
+    pub fn start<'a>(&'a self, ctx: &mut ExecContext) -> Result<BoxExecutor<'a>, Error> {
+        self.start_filtered(ctx, None)
+    }
+
+    // predicate は上の Filter の条件。スキャンは行を復元する前に、それで一部の行を飛ばしてよい
+    fn start_filtered<'a>(
+        &'a self,
+        ctx: &mut ExecContext,
+        predicate: Option<&Expr>,
+    ) -> Result<BoxExecutor<'a>, Error> {
+        if ctx.metrics.is_none() {
+            return self.open(ctx, predicate);
+        }
+        let key = explain::node_key(self);
+        explain::add_loop(ctx, key);
+        let exec = explain::measure(ctx, key, |ctx| self.open(ctx, predicate))?;
+        Ok(Box::new(Instrumented::new(self, exec)))
+    }
+
+    fn open<'a>(
+        &'a self,
+        ctx: &mut ExecContext,
+        predicate: Option<&Expr>,
+    ) -> Result<BoxExecutor<'a>, Error> {
+        match self {
+            // SERIALIZABLE のトランザクションでは、同時に実行したものとの依存を調べるのに読んだテーブルを覚える
+            PlanNode::SeqScan { table, .. }
+            | PlanNode::SampleScan { table, .. }
+            | PlanNode::Gather { table, .. }
+            | PlanNode::IndexScan { table, .. }
+            | PlanNode::FulltextScan { table, .. }
+            | PlanNode::RTreeScan { table, .. }
+            | PlanNode::IndexJoin { table, .. } => ctx.open_table(table, false)?,
+            PlanNode::Insert { table, .. }
+            | PlanNode::Update { table, .. }
+            | PlanNode::Delete { table, .. }
+            | PlanNode::LockRows {
+                table,
+                mode: LockMode::Exclusive,
+                ..
+            } => {
+                ctx.open_table(table, true)?;
+                // パーティションに分けたテーブルでは、行は子のテーブルに書く
+                let catalog = ctx.catalog;
+                if let Some(parent) = catalog.table(table) {
+                    for child in catalog.descendants(parent) {
+                        ctx.open_table(&child.name, true)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        match self {
+            PlanNode::SeqScan { table, needed } => Ok(Box::new(SeqScan::new(
+                ctx,
+                table,
+                needed.as_deref(),
+                predicate,
+            )?)),
+            PlanNode::SampleScan {
+                table,
+                method,
+                fraction,
+                seed,
+                needed,
+            } => Ok(Box::new(SampleScan::new(
+                ctx,
+                table,
+                *method,
+                *fraction,
+                *seed,
+                needed.as_deref(),
+            )?)),
+            PlanNode::Gather {
+                table,
+                predicate,
+                workers,
+                needed,
+            } => Ok(Box::new(Gather::new(
+                ctx,
+                table,
+                predicate.as_ref(),
+                *workers,
+                needed.as_deref(),
+            )?)),
+            PlanNode::IndexScan {
+                table,
+                index,
+                range,
+                predicate,
+                index_only,
+            } => Ok(Box::new(IndexScan::new(
+                ctx,
+                table,
+                index,
+                range,
+                predicate.as_ref(),
+                *index_only,
+            )?)),
+            PlanNode::FulltextScan {
+                table,
+                index,
+                terms,
+                predicate,
+            } => Ok(Box::new(FulltextScan::new(
+                ctx,
+                table,
+                index,
+                terms,
+                predicate.as_ref(),
+            )?)),
+            PlanNode::RTreeScan {
+                table,
+                index,
+                search,
+                predicate,
+            } => Ok(Box::new(RTreeScan::new(
+                ctx,
+                table,
+                index,
+                *search,
+                predicate.as_ref(),
+            )?)),
+            PlanNode::Values { rows } => Ok(Box::new(Values::new(rows))),
+            PlanNode::Filter { input, predicate } => Ok(Box::new(Filter::new(
+                input.start_filtered(ctx, Some(predicate))?,
+                predicate,
+            ))),
+            PlanNode::RuntimeFilter {
+                input,
+                id,
+                keys,
+                partition,
+            } => Ok(Box::new(RuntimeFilter::new(
+                input.start_filtered(ctx, predicate)?,
+                *id,
+                keys,
+                partition.as_ref(),
+                explain::node_key(self),
+            ))),
+            PlanNode::PartitionPrune {
+                input,
+                table,
+                conditions,
+            } => {
+                let pruned = ctx
+                    .catalog
+                    .table(table)
+                    .is_some_and(|t| planner::outside_partition(t, conditions));
+                if pruned {
+                    return Ok(Box::new(Values::new(&[])));
+                }
+                input.start_filtered(ctx, predicate)
+            }
+            PlanNode::AsOf { input, point, lsn } => {
+                let snapshot = as_of::snapshot(ctx, point, *lsn)?;
+                let outer = ctx.as_of.replace(snapshot);
+                let input = input.start_filtered(ctx, predicate);
+                let snapshot = std::mem::replace(&mut ctx.as_of, outer).unwrap();
+                Ok(Box::new(AsOf::new(input?, snapshot)))
+            }
+            PlanNode::MergeJoin {
+                left,
+                right,
+                left_keys,
+                right_keys,
+                predicate,
+            } => Ok(Box::new(MergeJoin::new(
+                left.start(ctx)?,
+                right.start(ctx)?,
+                left_keys,
+                right_keys,
+                predicate.as_ref(),
+            ))),
+            PlanNode::IndexJoin {
+                left,
+                table,
+                index,
+                keys,
+                predicate,
+            } => {
+                let left = left.start(ctx)?;
+                let join = IndexJoin::new(ctx, left, table, index, keys, predicate.as_ref())?;
+                Ok(Box::new(join))
+            }
+            PlanNode::LateralJoin {
+                left,
+                right,
+                id,
+                predicate,
+                join_type,
+                single,
+            } => Ok(Box::new(LateralJoin::new(
+                left.start(ctx)?,
+                right,
+                *id,
+                predicate.as_ref(),
+                *join_type,
+                *single,
+                right.columns(ctx.catalog)?.len(),
+            ))),
+            PlanNode::Sort { input, keys, top_n } => {
+                Ok(Box::new(Sort::new(input.start(ctx)?, keys, *top_n)))
+            }
+            PlanNode::Unique { input } => Ok(Box::new(Unique::new(input.start(ctx)?))),
+            PlanNode::Insert {
+                table,
+                input,
+                on_conflict,
+                returning,
+            } => Ok(Box::new(Insert::new(
+                table,
+                input.start(ctx)?,
+                on_conflict.as_ref(),
+                *returning,
+            ))),
+            PlanNode::Update {
+                table,
+                input,
+                assignments,
+                returning,
+            } => Ok(Box::new(Update::new(
+                table,
+                input.start(ctx)?,
+                assignments,
+                *returning,
+            ))),
+            PlanNode::Delete {
+                table,
+                input,
+                returning,
+            } => Ok(Box::new(Delete::new(table, input.start(ctx)?, *returning))),
+            PlanNode::LockRows {
+                table,
+                input,
+                mode,
+                wait,
+            } => Ok(Box::new(LockRows::new(
+                input.start(ctx)?,
+                table,
+                *mode,
+                *wait,
+            ))),
+            PlanNode::Limit {
+                input,
+                limit,
+                offset,
+            } => Ok(Box::new(Limit::new(input.start(ctx)?, *limit, *offset))),
+            PlanNode::HashAggregate {
+                input,
+                group_by,
+                aggregates,
+            } => Ok(Box::new(HashAggregate::new(
+                input.start(ctx)?,
+                group_by,
+                aggregates,
+            ))),
+            PlanNode::GroupAggregate {
+                input,
+                group_by,
+                aggregates,
+            } => Ok(Box::new(GroupAggregate::new(
+                input.start(ctx)?,
+                group_by,
+                aggregates,
+            ))),
+            PlanNode::ParallelAggregate {
+                input,
+                group_by,
+                aggregates,
+            } => match &**input {
+                PlanNode::Gather {
+                    table,
+                    predicate,
+                    workers,
+                    needed,
+                } => {
+                    // Gather を start しないので、読んだテーブルと EXPLAIN ANALYZE の実行はここで数える
+                    ctx.open_table(table, false)?;
+                    explain::add_loop(ctx, explain::node_key(input));
+                    Ok(Box::new(Gather::partial_aggregate(
+                        ctx,
+                        table,
+                        predicate.as_ref(),
+                        *workers,
+                        needed.as_deref(),
+                        group_by,
+                        aggregates,
+                    )?))
+                }
+                // Gather でなければ、ワーカーに分けずにこのスレッドで集約する
+                input => Ok(Box::new(HashAggregate::new(
+                    input.start(ctx)?,
+                    group_by,
+                    aggregates,
+                ))),
+            },
+            PlanNode::GroupingSets {
+                input,
+                group_by,
+                sets,
+                aggregates,
+            } => Ok(Box::new(HashAggregate::grouping_sets(
+                input.start(ctx)?,
+                group_by,
+                sets,
+                aggregates,
+            ))),
+            PlanNode::Window {
+                input,
+                partition_by,
+                order_by,
+                calls,
+            } => Ok(Box::new(Window::new(
+                input.start(ctx)?,
+                partition_by,
+                order_by,
+                calls,
+                ctx.memory.reservation(),
+            ))),
+            PlanNode::ProjectSet { input, exprs } => {
+                Ok(Box::new(ProjectSet::new(input.start(ctx)?, exprs)))
+            }
+            PlanNode::Projection { input, exprs, .. } => {
+                Ok(Box::new(Projection::new(input.start(ctx)?, exprs)))
+            }
+            PlanNode::Materialize { input } => Ok(Box::new(Materialize::new(
+                input.start(ctx)?,
+                ctx.memory.reservation(),
+            ))),
+            PlanNode::With { ctes, input } => Ok(Box::new(With::new(ctes, input, ctx)?)),
+            PlanNode::ExplainAnalyze {
+                input,
+                nodes,
+                format,
+            } => Ok(Box::new(ExplainAnalyze::new(input, nodes, *format))),
+            PlanNode::RecursiveUnion {
+                id,
+                anchor,
+                recursive,
+                all,
+            } => Ok(Box::new(RecursiveUnion::new(
+                anchor, recursive, *id, *all, ctx,
+            )?)),
+            PlanNode::CteScan { id, .. } => Ok(Box::new(CteScan::new(ctx, *id)?)),
+            PlanNode::StatsScan { view } => Ok(Box::new(StatsScan::new(*view, ctx)?)),
+            PlanNode::Append { left, right } => {
+                Ok(Box::new(Append::new(left.start(ctx)?, right.start(ctx)?)))
+            }
+            PlanNode::HashSetOp {
+                op,
+                all,
+                left,
+                right,
+            } => Ok(Box::new(HashSetOp::new(
+                left.start(ctx)?,
+                right.start(ctx)?,
+                *op,
+                *all,
+                ctx.memory.reservation(),
+            ))),
+            PlanNode::NestedLoopJoin {
+                left,
+                right,
+                predicate,
+                join_type,
+            } => Ok(Box::new(NestedLoopJoin::new(
+                left.start(ctx)?,
+                right,
+                predicate.as_ref(),
+                *join_type,
+                [
+                    left.columns(ctx.catalog)?.len(),
+                    right.columns(ctx.catalog)?.len(),
+                ],
+            ))),
+            PlanNode::HashJoin {
+                left,
+                right,
+                left_keys,
+                right_keys,
+                predicate,
+                join_type,
+                runtime_filter,
+            } => {
+                // 前に実行したときのフィルタが残っていれば、作り直すまで使わせない
+                if let Some((id, _)) = runtime_filter {
+                    ctx.runtime_filters.remove(id);
+                    ctx.key_ranges.remove(id);
+                }
+                Ok(Box::new(HashJoin::new(
+                    [left.start(ctx)?, right.start(ctx)?],
+                    [left_keys, right_keys],
+                    predicate.as_ref(),
+                    *join_type,
+                    [
+                        left.columns(ctx.catalog)?.len(),
+                        right.columns(ctx.catalog)?.len(),
+                    ],
+                    *runtime_filter,
+                    ctx.memory.reservation(),
+                )))
+            }
+        }
+    }
+
+    // 出力する列の名前
+    pub fn columns(&self, catalog: &Catalog) -> Result<Vec<String>, Error> {
+        match self {
+            PlanNode::SeqScan { table, .. }
+            | PlanNode::SampleScan { table, .. }
+            | PlanNode::Gather { table, .. }
+            | PlanNode::IndexScan { table, .. }
+            | PlanNode::FulltextScan { table, .. }
+            | PlanNode::RTreeScan { table, .. } => {
+                let table = catalog
+                    .table(table)
+                    .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+                Ok(table.columns.iter().map(|c| c.name.clone()).collect())
+            }
+            PlanNode::Values { rows } => {
+                let width = rows.first().map_or(0, |row| row.len());
+                Ok((1..=width).map(|i| format!("column{}", i)).collect())
+            }
+            PlanNode::Filter { input, .. }
+            | PlanNode::RuntimeFilter { input, .. }
+            | PlanNode::PartitionPrune { input, .. }
+            | PlanNode::AsOf { input, .. }
+            | PlanNode::Sort { input, .. }
+            | PlanNode::Unique { input }
+            | PlanNode::Limit { input, .. }
+            | PlanNode::LockRows { input, .. }
+            | PlanNode::Materialize { input }
+            | PlanNode::With { input, .. } => input.columns(catalog),
+            PlanNode::RecursiveUnion { anchor, .. } => anchor.columns(catalog),
+            PlanNode::CteScan { columns, .. } => Ok(columns.clone()),
+            PlanNode::StatsScan { view } => {
+                Ok(view.columns().iter().map(|c| c.to_string()).collect())
+            }
+            // 列名は左の入力のもの
+            PlanNode::Append { left, .. } | PlanNode::HashSetOp { left, .. } => {
+                left.columns(catalog)
+            }
+            PlanNode::Projection { columns, .. } => Ok(columns.clone()),
+            PlanNode::ExplainAnalyze { .. } => Ok(vec!["QUERY PLAN".into()]),
+            PlanNode::Insert {
+                table,
+                returning: true,
+                ..
+            }
+            | PlanNode::Update {
+                table,
+                returning: true,
+                ..
+            }
+            | PlanNode::Delete {
+                table,
+                returning: true,
+                ..
+            } => PlanNode::SeqScan {
+                table: table.clone(),
+                needed: None,
+            }
+            .columns(catalog),
+            PlanNode::Insert { .. } | PlanNode::Update { .. } | PlanNode::Delete { .. } => {
+                Ok(vec!["count".to_string()])
+            }
+            PlanNode::HashAggregate {
+                input,
+                group_by,
+                aggregates,
+            }
+            | PlanNode::GroupAggregate {
+                input,
+                group_by,
+                aggregates,
+            }
+            | PlanNode::ParallelAggregate {
+                input,
+                group_by,
+                aggregates,
+            } => {
+                let input = input.columns(catalog)?;
+                let mut columns = group_by
+                    .iter()
+                    .map(|expr| match expr {
+                        Expr::Column(i) => input[*i].clone(),
+                        _ => "?column?".to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                columns.extend(aggregates.iter().map(|call| call.func.name().to_string()));
+                Ok(columns)
+            }
+            PlanNode::GroupingSets {
+                input,
+                group_by,
+                aggregates,
+                ..
+            } => {
+                let input = input.columns(catalog)?;
+                let mut columns = group_by
+                    .iter()
+                    .map(|expr| match expr {
+                        Expr::Column(i) => input[*i].clone(),
+                        _ => "?column?".to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                columns.push("grouping".to_string());
+                columns.extend(aggregates.iter().map(|call| call.func.name().to_string()));
+                Ok(columns)
+            }
+            PlanNode::Window { input, calls, .. } => {
+                let mut columns = input.columns(catalog)?;
+                columns.extend(calls.iter().map(|call| call.func.name().to_string()));
+                Ok(columns)
+            }
+            PlanNode::ProjectSet { input, exprs } => {
+                let mut columns = input.columns(catalog)?;
+                columns.extend(exprs.iter().map(|_| "unnest".to_string()));
+                Ok(columns)
+            }
+            PlanNode::IndexJoin { left, table, .. } => {
+                let mut columns = left.columns(catalog)?;
+                columns.extend(
+                    PlanNode::SeqScan {
+                        table: table.clone(),
+                        needed: None,
+                    }
+                    .columns(catalog)?,
+                );
+                Ok(columns)
+            }
+            PlanNode::NestedLoopJoin {
+                left, join_type, ..
+            }
+            | PlanNode::HashJoin {
+                left, join_type, ..
+            }
+            | PlanNode::LateralJoin {
+                left, join_type, ..
+            } if join_type.is_semi() => left.columns(catalog),
+            PlanNode::NestedLoopJoin { left, right, .. }
+            | PlanNode::HashJoin { left, right, .. }
+            | PlanNode::LateralJoin { left, right, .. }
+            | PlanNode::MergeJoin { left, right, .. } => {
+                let mut columns = left.columns(catalog)?;
+                columns.extend(right.columns(catalog)?);
+                Ok(columns)
+            }
+        }
+    }
+
+    pub fn children(&self) -> Vec<&PlanNode> {
+        match self {
+            PlanNode::SeqScan { .. }
+            | PlanNode::SampleScan { .. }
+            | PlanNode::Gather { .. }
+            | PlanNode::IndexScan { .. }
+            | PlanNode::FulltextScan { .. }
+            | PlanNode::RTreeScan { .. }
+            | PlanNode::Values { .. }
+            | PlanNode::CteScan { .. }
+            | PlanNode::StatsScan { .. } => vec![],
+            PlanNode::Filter { input, .. }
+            | PlanNode::RuntimeFilter { input, .. }
+            | PlanNode::PartitionPrune { input, .. }
+            | PlanNode::AsOf { input, .. }
+            | PlanNode::Sort { input, .. }
+            | PlanNode::Limit { input, .. }
+            | PlanNode::Unique { input }
+            | PlanNode::HashAggregate { input, .. }
+            | PlanNode::GroupAggregate { input, .. }
+            | PlanNode::ParallelAggregate { input, .. }
+            | PlanNode::GroupingSets { input, .. }
+            | PlanNode::Window { input, .. }
+            | PlanNode::ProjectSet { input, .. }
+            | PlanNode::Insert { input, .. }
+            | PlanNode::Update { input, .. }
+            | PlanNode::Delete { input, .. }
+            | PlanNode::LockRows { input, .. }
+            | PlanNode::Materialize { input }
+            | PlanNode::Projection { input, .. }
+            | PlanNode::ExplainAnalyze { input, .. }
+            | PlanNode::IndexJoin { left: input, .. } => vec![input],
+            PlanNode::NestedLoopJoin { left, right, .. }
+            | PlanNode::HashJoin { left, right, .. }
+            | PlanNode::LateralJoin { left, right, .. }
+            | PlanNode::MergeJoin { left, right, .. }
+            | PlanNode::Append { left, right }
+            | PlanNode::HashSetOp { left, right, .. } => vec![left, right],
+            PlanNode::RecursiveUnion {
+                anchor, recursive, ..
+            } => vec![anchor, recursive],
+            PlanNode::With { ctes, input } => ctes
+                .iter()
+                .map(|(_, plan)| plan)
+                .chain([&**input])
+                .collect(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut PlanNode> {
+        match self {
+            PlanNode::SeqScan { .. }
+            | PlanNode::SampleScan { .. }
+            | PlanNode::Gather { .. }
+            | PlanNode::IndexScan { .. }
+            | PlanNode::FulltextScan { .. }
+            | PlanNode::RTreeScan { .. }
+            | PlanNode::Values { .. }
+            | PlanNode::CteScan { .. }
+            | PlanNode::StatsScan { .. } => vec![],
+            PlanNode::Filter { input, .. }
+            | PlanNode::RuntimeFilter { input, .. }
+            | PlanNode::PartitionPrune { input, .. }
+            | PlanNode::AsOf { input, .. }
+            | PlanNode::Sort { input, .. }
+            | PlanNode::Limit { input, .. }
+            | PlanNode::Unique { input }
+            | PlanNode::HashAggregate { input, .. }
+            | PlanNode::GroupAggregate { input, .. }
+            | PlanNode::ParallelAggregate { input, .. }
+            | PlanNode::GroupingSets { input, .. }
+            | PlanNode::Window { input, .. }
+            | PlanNode::ProjectSet { input, .. }
+            | PlanNode::Insert { input, .. }
+            | PlanNode::Update { input, .. }
+            | PlanNode::Delete { input, .. }
+            | PlanNode::LockRows { input, .. }
+            | PlanNode::Materialize { input }
+            | PlanNode::Projection { input, .. }
+            | PlanNode::ExplainAnalyze { input, .. }
+            | PlanNode::IndexJoin { left: input, .. } => vec![input],
+            PlanNode::NestedLoopJoin { left, right, .. }
+            | PlanNode::HashJoin { left, right, .. }
+            | PlanNode::LateralJoin { left, right, .. }
+            | PlanNode::MergeJoin { left, right, .. }
+            | PlanNode::Append { left, right }
+            | PlanNode::HashSetOp { left, right, .. } => vec![left, right],
+            PlanNode::RecursiveUnion {
+                anchor, recursive, ..
+            } => vec![anchor, recursive],
+            PlanNode::With { ctes, input } => ctes
+                .iter_mut()
+                .map(|(_, plan)| plan)
+                .chain([&mut **input])
+                .collect(),
+        }
+    }
+
+    // この演算子が評価する式。子の演算子のものは含まない
+    fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            PlanNode::SeqScan { .. }
+            | PlanNode::SampleScan { .. }
+            | PlanNode::Values { .. }
+            | PlanNode::Limit { .. }
+            | PlanNode::Unique { .. }
+            | PlanNode::Append { .. }
+            | PlanNode::HashSetOp { .. }
+            | PlanNode::Delete { .. }
+            | PlanNode::LockRows { .. }
+            | PlanNode::Materialize { .. }
+            | PlanNode::With { .. }
+            | PlanNode::RecursiveUnion { .. }
+            | PlanNode::CteScan { .. }
+            | PlanNode::StatsScan { .. }
+            | PlanNode::ExplainAnalyze { .. } => vec![],
+            PlanNode::IndexScan {
+                range, predicate, ..
+            } => range
+                .prefix
+                .iter_mut()
+                .chain(range.lower.iter_mut().map(|b| &mut b.value))
+                .chain(range.upper.iter_mut().map(|b| &mut b.value))
+                .chain(predicate)
+                .collect(),
+            PlanNode::FulltextScan { predicate, .. }
+            | PlanNode::RTreeScan { predicate, .. }
+            | PlanNode::Gather { predicate, .. }
+            | PlanNode::NestedLoopJoin { predicate, .. }
+            | PlanNode::LateralJoin { predicate, .. } => predicate.iter_mut().collect(),
+            PlanNode::Filter { predicate, .. } => vec![predicate],
+            PlanNode::AsOf { point, .. } => vec![point],
+            PlanNode::PartitionPrune { conditions, .. } => conditions.iter_mut().collect(),
+            PlanNode::RuntimeFilter { keys, .. }
+            | PlanNode::ProjectSet { exprs: keys, .. }
+            | PlanNode::Projection { exprs: keys, .. } => keys.iter_mut().collect(),
+            PlanNode::HashJoin {
+                left_keys,
+                right_keys,
+                predicate,
+                ..
+            }
+            | PlanNode::MergeJoin {
+                left_keys,
+                right_keys,
+                predicate,
+                ..
+            } => left_keys
+                .iter_mut()
+                .chain(right_keys)
+                .chain(predicate)
+                .collect(),
+            PlanNode::IndexJoin {
+                keys, predicate, ..
+            } => keys.iter_mut().chain(predicate).collect(),
+            PlanNode::Sort { keys, .. } => keys.iter_mut().map(|k| &mut k.expr).collect(),
+            PlanNode::HashAggregate {
+                group_by,
+                aggregates,
+                ..
+            }
+            | PlanNode::GroupAggregate {
+                group_by,
+                aggregates,
+                ..
+            }
+            | PlanNode::ParallelAggregate {
+                group_by,
+                aggregates,
+                ..
+            }
+            | PlanNode::GroupingSets {
+                group_by,
+                aggregates,
+                ..
+            } => group_by
+                .iter_mut()
+                .chain(aggregates.iter_mut().filter_map(|a| a.arg.as_mut()))
+                .collect(),
+            PlanNode::Window {
+                partition_by,
+                order_by,
+                calls,
+                ..
+            } => partition_by
+                .iter_mut()
+                .chain(order_by.iter_mut().map(|k| &mut k.expr))
+                .chain(calls.iter_mut().flat_map(|c| &mut c.args))
+                .collect(),
+            PlanNode::Insert { on_conflict, .. } => match on_conflict {
+                Some(OnConflict {
+                    action:
+                        ConflictAction::Update {
+                            assignments,
+                            predicate,
+                        },
+                    ..
+                }) => assignments
+                    .iter_mut()
+                    .map(|(_, e)| e)
+                    .chain(predicate)
+                    .collect(),
+                _ => vec![],
+            },
+            PlanNode::Update { assignments, .. } => {
+                assignments.iter_mut().map(|(_, e)| e).collect()
+            }
+        }
+    }
+
+    // 計画の $n を params[n - 1] の値に置き換える
+    pub fn bind_params(&mut self, params: &[Value]) -> Result<(), Error> {
+        for expr in self.exprs_mut() {
+            expr.bind_params(params)?;
+        }
+        for child in self.children_mut() {
+            child.bind_params(params)?;
+        }
+        Ok(())
+    }
+}
+
+// 計画を最後まで実行して結果をすべて集める
+pub fn execute(plan: &PlanNode, ctx: &mut ExecContext) -> Result<Vec<Row>, Error> {
+    let mut exec = plan.start(ctx)?;
+    let mut rows = vec![];
+    while let Some(batch) = exec.next_batch(ctx)? {
+        rows.extend(batch.into_rows());
+        if let Some(limit) = ctx.max_rows {
+            if rows.len() as u64 > limit {
+                return Err(Error::ResultRowLimit(limit));
+            }
+        }
+    }
+    exec.close(ctx)?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::catalog::Column;
+    use crate::collation::Collation;
+    use crate::sql::ast::BinaryOp;
+    use crate::testutil::temp_bufmgr;
+    use crate::types::DataType;
+
+    pub fn int(n: i64) -> Value {
+        Value::Integer(n)
+    }
+
+    pub fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    // (a INTEGER, b TEXT) のテーブル t に行を入れる
+    pub fn setup(rows: &[Row]) -> (BufferPoolManager, Catalog) {
+        let mut bufmgr = temp_bufmgr(16);
+        let mut catalog = Catalog::new();
+        let columns = vec![
+            Column {
+                name: "a".to_string(),
+                data_type: DataType::Integer,
+                not_null: false,
+                collation: Collation::Binary,
+            },
+            Column {
+                name: "b".to_string(),
+                data_type: DataType::Text,
+                not_null: false,
+                collation: Collation::Binary,
+            },
+        ];
+        let table = catalog.create_table(&mut bufmgr, "t", columns).unwrap();
+        for row in rows {
+            table
+                .insert(&mut bufmgr, TxnId::FROZEN_TXN_ID, row)
+                .unwrap();
+        }
+        (bufmgr, catalog)
+    }
+
+    #[test]
+    fn test_seq_scan() {
+        let rows = vec![vec![int(1), text("x")], vec![int(2), Value::Null]];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = PlanNode::SeqScan {
+            table: "t".to_string(),
+            needed: None,
+        };
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), rows);
+
+        let plan = PlanNode::SeqScan {
+            table: "missing".to_string(),
+            needed: None,
+        };
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(Error::TableNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_values() {
+        let (mut bufmgr, catalog) = setup(&[]);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let rows = vec![vec![int(1)], vec![int(2)]];
+        let plan = PlanNode::Values { rows: rows.clone() };
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), rows);
+    }
+
+    #[test]
+    fn test_filter() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), Value::Null],
+            vec![Value::Null, text("y")],
+            vec![Value::Null, text("w")],
+            vec![int(3), text("z")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        // a >= 2 OR b = 'y'。NULL になる行は条件を満たさない
+        let predicate = Expr::binary(
+            BinaryOp::Or,
+            Expr::binary(BinaryOp::GtEq, Expr::column(0), Expr::Literal(int(2))),
+            Expr::binary(BinaryOp::Eq, Expr::column(1), Expr::Literal(text("y"))),
+        );
+        let plan = PlanNode::Filter {
+            input: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            predicate,
+        };
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![rows[1].clone(), rows[2].clone(), rows[4].clone()]
+        );
+    }
+
+    #[test]
+    fn test_next_batch() {
+        let rows = (0..3000)
+            .map(|i| vec![int(i), text(&i.to_string())])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = PlanNode::Projection {
+            input: Box::new(PlanNode::Filter {
+                input: Box::new(PlanNode::SeqScan {
+                    table: "t".to_string(),
+                    needed: None,
+                }),
+                predicate: Expr::binary(
+                    BinaryOp::Eq,
+                    Expr::binary(BinaryOp::Modulo, Expr::column(0), Expr::Literal(int(3))),
+                    Expr::Literal(int(0)),
+                ),
+            }),
+            exprs: vec![Expr::column(1)],
+            columns: vec!["b".to_string()],
+        };
+        let mut exec = plan.start(&mut ctx).unwrap();
+        let mut lens = vec![];
+        let mut output = vec![];
+        while let Some(batch) = exec.next_batch(&mut ctx).unwrap() {
+            lens.push(batch.len());
+            output.extend(batch.into_rows());
+        }
+        exec.close(&mut ctx).unwrap();
+        // 入力の束ごとに絞り込むので、出力の束は BATCH_SIZE より小さくなる
+        assert_eq!(lens, vec![342, 341, 317]);
+        let expected = (0..3000)
+            .filter(|i| i % 3 == 0)
+            .map(|i| vec![text(&i.to_string())])
+            .collect::<Vec<_>>();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_gather() {
+        let rows = (0..1000)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for workers in [1, 3, 100] {
+            let plan = PlanNode::Gather {
+                table: "t".to_string(),
+                predicate: Some(Expr::binary(
+                    BinaryOp::Lt,
+                    Expr::column(0),
+                    Expr::Literal(int(500)),
+                )),
+                workers,
+                needed: None,
+            };
+            let mut result = execute(&plan, &mut ctx).unwrap();
+            result.sort_by(|a, b| compare_values(a, b));
+            assert_eq!(result, rows[..500]);
+        }
+        // ワーカーで起きたエラーも返す
+        let plan = PlanNode::Gather {
+            table: "t".to_string(),
+            predicate: Some(Expr::column(0)),
+            workers: 2,
+            needed: None,
+        };
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(Error::NotBoolean(ty)) if ty == "integer"
+        ));
+    }
+
+    #[test]
+    fn test_projection() {
+        let rows = vec![vec![int(1), text("x")], vec![int(2), Value::Null]];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let scan = PlanNode::SeqScan {
+            table: "t".to_string(),
+            needed: None,
+        };
+        assert_eq!(scan.columns(&catalog).unwrap(), vec!["a", "b"]);
+        let plan = PlanNode::Projection {
+            input: Box::new(scan),
+            exprs: vec![
+                Expr::column(1),
+                Expr::binary(BinaryOp::Multiply, Expr::column(0), Expr::Literal(int(10))),
+                Expr::Literal(Value::Boolean(true)),
+            ],
+            columns: vec!["b".to_string(), "x".to_string(), "?column?".to_string()],
+        };
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![text("x"), int(10), Value::Boolean(true)],
+                vec![Value::Null, int(20), Value::Boolean(true)],
+            ]
+        );
+        assert_eq!(plan.columns(&catalog).unwrap(), vec!["b", "x", "?column?"]);
+    }
+
+    #[test]
+    fn test_nested_loop_join() {
+        let rows = vec![vec![int(1), text("x")], vec![int(2), text("y")]];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        // 外側が 1 ブロックに収まらないようにする
+        let outer = (0..600).map(|i| vec![int(i % 3)]).collect();
+        let plan = PlanNode::NestedLoopJoin {
+            left: Box::new(PlanNode::Values { rows: outer }),
+            right: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            predicate: Some(Expr::binary(BinaryOp::Eq, Expr::column(0), Expr::column(1))),
+            join_type: JoinType::Inner,
+        };
+        let result = execute(&plan, &mut ctx).unwrap();
+        assert_eq!(result.len(), 400);
+        assert_eq!(result.iter().filter(|row| row[2] == text("y")).count(), 200);
+        assert!(result.iter().all(|row| row[0] == row[1]));
+        assert_eq!(plan.columns(&catalog).unwrap(), vec!["column1", "a", "b"]);
+
+        let plan = PlanNode::NestedLoopJoin {
+            left: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            right: Box::new(PlanNode::Values {
+                rows: vec![vec![int(10)], vec![int(20)]],
+            }),
+            predicate: None,
+            join_type: JoinType::Inner,
+        };
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![int(1), text("x"), int(10)],
+                vec![int(2), text("y"), int(10)],
+                vec![int(1), text("x"), int(20)],
+                vec![int(2), text("y"), int(20)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_join() {
+        let rows = (0..300)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let outer = (0..1000)
+            .map(|i| vec![int(i % 500), int(i)])
+            .chain([vec![Value::Null, int(-1)]])
+            .collect();
+        let plan = PlanNode::HashJoin {
+            left: Box::new(PlanNode::Values { rows: outer }),
+            right: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            left_keys: vec![Expr::column(0)],
+            right_keys: vec![Expr::column(0)],
+            predicate: Some(Expr::binary(
+                BinaryOp::Lt,
+                Expr::column(1),
+                Expr::Literal(int(900)),
+            )),
+            join_type: JoinType::Inner,
+            runtime_filter: None,
+        };
+        let expected = (0..900)
+            .filter(|i| i % 500 < 300)
+            .map(|i| {
+                vec![
+                    int(i % 500),
+                    int(i),
+                    int(i % 500),
+                    text(&format!("row{}", i % 500)),
+                ]
+            })
+            .collect::<Vec<_>>();
+        // 出力順は決まっていないので左の 2 列目で並べて比べる
+        let sorted = |mut rows: Vec<Row>| {
+            rows.sort_by_key(|row| match row[1] {
+                Value::Integer(n) => n,
+                _ => unreachable!(),
+            });
+            rows
+        };
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(sorted(execute(&plan, &mut ctx).unwrap()), expected);
+        // work_mem を小さくしてパーティションに分けさせる
+        ctx.work_mem = 1024;
+        assert_eq!(sorted(execute(&plan, &mut ctx).unwrap()), expected);
+        ctx.work_mem = 0;
+        assert_eq!(sorted(execute(&plan, &mut ctx).unwrap()), expected);
+    }
+
+    #[test]
+    fn test_semi_anti_join() {
+        let rows = (0..300)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let outer = (0..1000)
+            .map(|i| vec![int(i % 500), int(i)])
+            .chain([vec![Value::Null, int(-1)]])
+            .collect::<Vec<_>>();
+        let predicate = Expr::binary(BinaryOp::Lt, Expr::column(1), Expr::Literal(int(900)));
+        let plan = |join_type, hash| {
+            let left = Box::new(PlanNode::Values {
+                rows: outer.clone(),
+            });
+            let right = Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            });
+            if hash {
+                PlanNode::HashJoin {
+                    left,
+                    right,
+                    left_keys: vec![Expr::column(0)],
+                    right_keys: vec![Expr::column(0)],
+                    predicate: Some(predicate.clone()),
+                    join_type,
+                    runtime_filter: None,
+                }
+            } else {
+                PlanNode::NestedLoopJoin {
+                    left,
+                    right,
+                    predicate: Some(Expr::binary(
+                        BinaryOp::And,
+                        Expr::binary(BinaryOp::Eq, Expr::column(0), Expr::column(2)),
+                        predicate.clone(),
+                    )),
+                    join_type,
+                }
+            }
+        };
+        let matched = |row: &Row| match (&row[0], &row[1]) {
+            (Value::Integer(a), Value::Integer(b)) => *a < 300 && *b < 900,
+            _ => false,
+        };
+        let semi = outer
+            .iter()
+            .filter(|r| matched(r))
+            .cloned()
+            .collect::<Vec<_>>();
+        let anti = outer
+            .iter()
+            .filter(|r| !matched(r))
+            .cloned()
+            .collect::<Vec<_>>();
+        let sorted = |mut rows: Vec<Row>| {
+            rows.sort_by_key(|row| match row[1] {
+                Value::Integer(n) => n,
+                _ => unreachable!(),
+            });
+            rows
+        };
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for hash in [false, true] {
+            for work_mem in [DEFAULT_WORK_MEM, 1024, 0] {
+                ctx.work_mem = work_mem;
+                let plan_semi = plan(JoinType::Semi, hash);
+                assert_eq!(
+                    plan_semi.columns(ctx.catalog).unwrap(),
+                    ["column1", "column2"]
+                );
+                assert_eq!(
+                    sorted(execute(&plan_semi, &mut ctx).unwrap()),
+                    sorted(semi.clone())
+                );
+                let plan_anti = plan(JoinType::Anti, hash);
+                assert_eq!(
+                    sorted(execute(&plan_anti, &mut ctx).unwrap()),
+                    sorted(anti.clone())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_outer_join() {
+        let rows = (0..300)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .chain([vec![Value::Null, text("null")]])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let outer = (0..500)
+            .map(|i| vec![int(i % 400 + 100), int(i)])
+            .chain([vec![Value::Null, int(-1)]])
+            .collect::<Vec<_>>();
+        let predicate = Expr::binary(BinaryOp::Lt, Expr::column(1), Expr::Literal(int(450)));
+        let plan = |join_type, hash| {
+            let left = Box::new(PlanNode::Values {
+                rows: outer.clone(),
+            });
+            let right = Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            });
+            if hash {
+                PlanNode::HashJoin {
+                    left,
+                    right,
+                    left_keys: vec![Expr::column(0)],
+                    right_keys: vec![Expr::column(0)],
+                    predicate: Some(predicate.clone()),
+                    join_type,
+                    runtime_filter: None,
+                }
+            } else {
+                PlanNode::NestedLoopJoin {
+                    left,
+                    right,
+                    predicate: Some(Expr::binary(
+                        BinaryOp::And,
+                        Expr::binary(BinaryOp::Eq, Expr::column(0), Expr::column(2)),
+                        predicate.clone(),
+                    )),
+                    join_type,
+                }
+            }
+        };
+        let matches = |l: &Row, r: &Row| match (&l[0], &l[1], &r[0]) {
+            (Value::Integer(a), Value::Integer(b), Value::Integer(c)) => a == c && *b < 450,
+            _ => false,
+        };
+        let expected = |join_type: JoinType| {
+            let mut result = vec![];
+            for l in &outer {
+                let mut found = false;
+                for r in &rows {
+                    if matches(l, r) {
+                        found = true;
+                        result.push([l.as_slice(), r].concat());
+                    }
+                }
+                if !found && join_type.keeps_left() {
+                    result.push([l.as_slice(), &[Value::Null, Value::Null]].concat());
+                }
+            }
+            if join_type.keeps_right() {
+                for r in &rows {
+                    if !outer.iter().any(|l| matches(l, r)) {
+                        result.push([&[Value::Null, Value::Null], r.as_slice()].concat());
+                    }
+                }
+            }
+            sorted(result)
+        };
+        fn sorted(mut rows: Vec<Row>) -> Vec<Row> {
+            rows.sort_by(|a, b| compare_values(a, b));
+            rows
+        }
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for join_type in [JoinType::Left, JoinType::Right, JoinType::Full] {
+            for hash in [false, true] {
+                for work_mem in [DEFAULT_WORK_MEM, 1024, 0] {
+                    ctx.work_mem = work_mem;
+                    let plan = plan(join_type, hash);
+                    assert_eq!(
+                        sorted(execute(&plan, &mut ctx).unwrap()),
+                        expected(join_type),
+                        "{:?} hash={} work_mem={}",
+                        join_type,
+                        hash,
+                        work_mem
+                    );
+                }
+            }
+        }
+        assert_eq!(
+            plan(JoinType::Full, true).columns(ctx.catalog).unwrap(),
+            ["column1", "column2", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let (mut bufmgr, catalog) = setup(&[]);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        // 負の数は NULL
+        let rows = |rows: &[i64]| {
+            rows.iter()
+                .map(|&n| vec![if n < 0 { Value::Null } else { int(n) }])
+                .collect::<Vec<_>>()
+        };
+        let values = |r: &[i64]| Box::new(PlanNode::Values { rows: rows(r) });
+        let left = [1, 1, 1, 2, 3, -1, -1];
+        let right = [1, 1, 3, 3, 4, -1];
+        let mut run = |op, all| {
+            let plan = PlanNode::HashSetOp {
+                op,
+                all,
+                left: values(&left),
+                right: values(&right),
+            };
+            execute(&plan, &mut ctx).unwrap()
+        };
+        assert_eq!(run(SetOperator::Intersect, true), rows(&[1, 1, 3, -1]));
+        assert_eq!(run(SetOperator::Intersect, false), rows(&[1, 3, -1]));
+        assert_eq!(run(SetOperator::Except, true), rows(&[1, 2, -1]));
+        assert_eq!(run(SetOperator::Except, false), rows(&[2]));
+
+        let plan = PlanNode::Append {
+            left: values(&[1, 2]),
+            right: values(&[2, -1]),
+        };
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), rows(&[1, 2, 2, -1]));
+
+        // NULL は型を決めないので、どちらの型の列とも組み合わせられる
+        let plan = PlanNode::Append {
+            left: Box::new(PlanNode::Values {
+                rows: vec![vec![Value::Null, int(1)]],
+            }),
+            right: Box::new(PlanNode::Values {
+                rows: vec![vec![text("x"), text("y")]],
+            }),
+        };
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(Error::SetOperationTypes {
+                op: SetOperator::Union,
+                left,
+                right,
+            }) if left == "integer" && right == "text"
+        ));
+    }
+
+    #[test]
+    fn test_cursor() {
+        let rows = (0..1500)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = PlanNode::SeqScan {
+            table: "t".to_string(),
+            needed: None,
+        };
+        let mut cursor = Cursor::declare(Rc::new(plan.clone()), &mut ctx).unwrap();
+        assert_eq!(cursor.fetch(&mut ctx, 10).unwrap(), rows[..10]);
+        assert_eq!(cursor.fetch(&mut ctx, 1000).unwrap(), rows[10..1010]);
+        assert!(!cursor.is_closed());
+        assert_eq!(cursor.fetch(&mut ctx, 1000).unwrap(), rows[1010..]);
+        assert!(cursor.is_closed());
+        assert!(cursor.fetch(&mut ctx, 10).unwrap().is_empty());
+
+        let sorted = PlanNode::Sort {
+            input: Box::new(plan.clone()),
+            keys: vec![SortKey {
+                expr: Expr::column(0),
+                asc: false,
+                nulls_first: true,
+            }],
+            top_n: None,
+        };
+        let mut cursors = Cursors::new();
+        let plan = Rc::new(plan);
+        cursors.declare("c1", plan.clone(), &mut ctx).unwrap();
+        cursors.declare("c2", Rc::new(sorted), &mut ctx).unwrap();
+        assert!(matches!(
+            cursors.declare("c1", plan, &mut ctx),
+            Err(Error::DuplicateCursor(_))
+        ));
+        assert_eq!(
+            cursors.fetch("c2", &mut ctx, 1).unwrap(),
+            [rows[1499].clone()]
+        );
+        assert_eq!(cursors.fetch("c1", &mut ctx, 1).unwrap(), [rows[0].clone()]);
+        cursors.close("c1", &mut ctx).unwrap();
+        assert!(matches!(
+            cursors.fetch("c1", &mut ctx, 1),
+            Err(Error::CursorNotFound(_))
+        ));
+        cursors.close_all(&mut ctx).unwrap();
+        assert!(matches!(
+            cursors.close("c2", &mut ctx),
+            Err(Error::CursorNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_materialize() {
+        let rows = (0..500)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let filter = PlanNode::Filter {
+            input: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            predicate: Expr::binary(BinaryOp::Lt, Expr::column(0), Expr::Literal(int(300))),
+        };
+        let plan = PlanNode::Materialize {
+            input: Box::new(filter.clone()),
+        };
+        for work_mem in [DEFAULT_WORK_MEM, 0] {
+            let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+            ctx.work_mem = work_mem;
+            let mut exec = plan.start(&mut ctx).unwrap();
+            assert_eq!(exec.next(&mut ctx).unwrap(), Some(rows[0].clone()));
+            assert_eq!(exec.next(&mut ctx).unwrap(), Some(rows[1].clone()));
+            // 途中からでも最後まで読んだあとでも先頭から返す
+            for _ in 0..2 {
+                assert!(exec.rescan(&mut ctx).unwrap());
+                let mut read = vec![];
+                while let Some(row) = exec.next(&mut ctx).unwrap() {
+                    read.push(row);
+                }
+                assert_eq!(read, rows[..300]);
+            }
+            exec.close(&mut ctx).unwrap();
+            assert_eq!(ctx.memory.used(), 0);
+        }
+
+        // 同じ WITH の問い合わせを両側で読む
+        let join = PlanNode::With {
+            ctes: vec![(0, filter)],
+            input: Box::new(PlanNode::NestedLoopJoin {
+                left: Box::new(PlanNode::CteScan {
+                    id: 0,
+                    columns: vec!["a".to_string(), "b".to_string()],
+                }),
+                right: Box::new(PlanNode::CteScan {
+                    id: 0,
+                    columns: vec!["a".to_string(), "b".to_string()],
+                }),
+                predicate: Some(Expr::binary(
+                    BinaryOp::Eq,
+                    Expr::binary(BinaryOp::Plus, Expr::column(0), Expr::Literal(int(1))),
+                    Expr::column(2),
+                )),
+                join_type: JoinType::Inner,
+            }),
+        };
+        for work_mem in [DEFAULT_WORK_MEM, 0] {
+            let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+            ctx.work_mem = work_mem;
+            let result = execute(&join, &mut ctx).unwrap();
+            assert_eq!(result.len(), 299);
+            assert_eq!(
+                result[298],
+                [int(298), text("row298"), int(299), text("row299")]
+            );
+            assert!(ctx.ctes.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let rows = (0..500)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let plan = PlanNode::Sort {
+            input: Box::new(PlanNode::HashAggregate {
+                input: Box::new(PlanNode::HashJoin {
+                    left: Box::new(PlanNode::SeqScan {
+                        table: "t".to_string(),
+                        needed: None,
+                    }),
+                    right: Box::new(PlanNode::SeqScan {
+                        table: "t".to_string(),
+                        needed: None,
+                    }),
+                    left_keys: vec![Expr::column(0)],
+                    right_keys: vec![Expr::binary(
+                        BinaryOp::Modulo,
+                        Expr::column(0),
+                        Expr::Literal(int(100)),
+                    )],
+                    predicate: None,
+                    join_type: JoinType::Inner,
+                    runtime_filter: None,
+                }),
+                group_by: vec![Expr::column(1)],
+                aggregates: vec![AggregateCall {
+                    func: AggregateFunction::Count,
+                    arg: None,
+                    distinct: false,
+                    percentile: None,
+                }],
+            }),
+            keys: vec![SortKey {
+                expr: Expr::column(0),
+                asc: true,
+                nulls_first: false,
+            }],
+            top_n: None,
+        };
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let expected = execute(&plan, &mut ctx).unwrap();
+        assert_eq!(expected.len(), 100);
+        assert_eq!(expected[0], vec![text("row0"), int(5)]);
+        // 演算子ごとの work_mem には収まっても、合わせると予算を超えるので書き出す
+        for limit in [16 * 1024, 1024, 0] {
+            let budget = MemoryBudget::new(limit);
+            ctx.memory = budget.clone();
+            assert_eq!(execute(&plan, &mut ctx).unwrap(), expected);
+            // 分け直せなくなったパーティションだけは予算を超えて読み込む
+            if limit > 0 {
+                assert!(budget.peak() <= limit, "{} {}", budget.peak(), limit);
+            }
+            assert_eq!(budget.used(), 0);
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let rows = (0..300)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let scan = || {
+            Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            })
+        };
+        // 終わるまでに時間のかかる 3 重の直積
+        let plan = PlanNode::NestedLoopJoin {
+            left: Box::new(PlanNode::NestedLoopJoin {
+                left: scan(),
+                right: scan(),
+                predicate: None,
+                join_type: JoinType::Inner,
+            }),
+            right: scan(),
+            predicate: Some(Expr::Literal(Value::Boolean(false))),
+            join_type: JoinType::Inner,
+        };
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let cancel = ctx.cancel.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(Error::QueryCanceled)
+        ));
+        handle.join().unwrap();
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        ctx.set_statement_timeout(Duration::from_millis(20));
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(Error::StatementTimeout)
+        ));
+    }
+
+    #[test]
+    fn test_read_only() {
+        let rows = vec![vec![int(1), text("x")]];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        ctx.read_only = true;
+        let scan = PlanNode::SeqScan {
+            table: "t".to_string(),
+            needed: None,
+        };
+        assert_eq!(execute(&scan, &mut ctx).unwrap(), rows);
+        let plan = PlanNode::Insert {
+            table: "t".to_string(),
+            input: Box::new(PlanNode::Values {
+                rows: vec![vec![int(2), text("y")]],
+            }),
+            on_conflict: None,
+            returning: false,
+        };
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(Error::ReadOnly(table)) if table == "t"
+        ));
+        assert_eq!(execute(&scan, &mut ctx).unwrap(), rows);
+    }
+
+    #[test]
+    fn test_sort() {
+        let rows = vec![
+            vec![int(2), text("b")],
+            vec![Value::Null, text("n")],
+            vec![int(1), text("c")],
+            vec![int(2), text("a")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = PlanNode::Sort {
+            input: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            keys: vec![
+                SortKey {
+                    expr: Expr::column(0),
+                    asc: true,
+                    nulls_first: false,
+                },
+                SortKey {
+                    expr: Expr::column(1),
+                    asc: false,
+                    nulls_first: true,
+                },
+            ],
+            top_n: None,
+        };
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                rows[2].clone(),
+                rows[0].clone(),
+                rows[3].clone(),
+                rows[1].clone(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_top_n_sort() {
+        // 同じキーの行が多いので、入れた順が保たれているかも確かめられる
+        let rows = (0..1000)
+            .map(|i| vec![int((i * 37) % 100), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let sort = |top_n| PlanNode::Sort {
+            input: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            keys: vec![SortKey {
+                expr: Expr::column(0),
+                asc: false,
+                nulls_first: true,
+            }],
+            top_n,
+        };
+        let full = execute(&sort(None), &mut ExecContext::new(&mut bufmgr, &catalog)).unwrap();
+        for (n, work_mem) in [
+            (0, DEFAULT_WORK_MEM),
+            (25, DEFAULT_WORK_MEM),
+            (25, 512),
+            (2000, 0),
+        ] {
+            let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+            ctx.work_mem = work_mem;
+            let result = execute(&sort(Some(n)), &mut ctx).unwrap();
+            // ヒープに収まらなければすべての行を並べ替えて返す
+            let expected = if work_mem == DEFAULT_WORK_MEM {
+                n
+            } else {
+                full.len()
+            };
+            assert_eq!(result, full[..expected]);
+            assert_eq!(ctx.memory.used(), 0);
+        }
+    }
+
+    #[test]
+    fn test_external_sort() {
+        let rows = (0..200)
+            .map(|i| vec![int(i % 7), text(&format!("{:03}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let plan = PlanNode::Sort {
+            input: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            keys: vec![SortKey {
+                expr: Expr::column(0),
+                asc: true,
+                nulls_first: false,
+            }],
+            top_n: None,
+        };
+        let mut expected = rows.clone();
+        expected.sort_by(|a, b| a[0].sort_cmp(&b[0]));
+        // work_mem が 0 なら 1 行ずつの run になり、マージも何段かに分かれる
+        for work_mem in [1024, 0] {
+            let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+            ctx.work_mem = work_mem;
+            assert_eq!(execute(&plan, &mut ctx).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_hash_aggregate() {
+        let mut rows = (0..100)
+            .map(|i| vec![int(i), text(&format!("g{}", i % 10))])
+            .collect::<Vec<_>>();
+        rows.push(vec![Value::Null, text("g0")]);
+        let (mut bufmgr, catalog) = setup(&rows);
+        let call = |func, arg: Option<usize>| AggregateCall {
+            func,
+            arg: arg.map(Expr::column),
+            distinct: false,
+            percentile: None,
+        };
+        let plan = PlanNode::HashAggregate {
+            input: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            group_by: vec![Expr::column(1)],
+            aggregates: vec![
+                call(AggregateFunction::Count, None),
+                call(AggregateFunction::Count, Some(0)),
+                call(AggregateFunction::Sum, Some(0)),
+                call(AggregateFunction::Avg, Some(0)),
+                call(AggregateFunction::Min, Some(0)),
+                call(AggregateFunction::Max, Some(0)),
+            ],
+        };
+        let expected = (0..10)
+            .map(|g| {
+                let count = if g == 0 { 11 } else { 10 };
+                let sum = (0..10).map(|i| i * 10 + g).sum::<i64>();
+                vec![
+                    text(&format!("g{}", g)),
+                    int(count),
+                    int(10),
+                    int(sum),
+                    Value::Real(sum as f64 / 10.0),
+                    int(g),
+                    int(90 + g),
+                ]
+            })
+            .collect::<Vec<_>>();
+        // work_mem が 0 ならほとんどのグループを一時ファイルに書き出す
+        for work_mem in [DEFAULT_WORK_MEM, 0] {
+            let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+            ctx.work_mem = work_mem;
+            let mut result = execute(&plan, &mut ctx).unwrap();
+            result.sort_by(|a, b| a[0].sort_cmp(&b[0]));
+            assert_eq!(result, expected);
+        }
+
+        // GROUP BY がなければ空の入力でも 1 行返す
+        let plan = PlanNode::HashAggregate {
+            input: Box::new(PlanNode::Filter {
+                input: Box::new(PlanNode::SeqScan {
+                    table: "t".to_string(),
+                    needed: None,
+                }),
+                predicate: Expr::Literal(Value::Boolean(false)),
+            }),
+            group_by: vec![],
+            aggregates: vec![
+                call(AggregateFunction::Count, None),
+                call(AggregateFunction::Sum, Some(0)),
+            ],
+        };
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(0), Value::Null]]
+        );
+    }
+
+    #[test]
+    fn test_distinct_aggregate() {
+        let rows = (0..1000)
+            .map(|i| vec![int(i % 300), text(&format!("g{}", i % 3))])
+            .chain([vec![Value::Null, text("g0")]])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let call = |func, distinct| AggregateCall {
+            func,
+            arg: Some(Expr::column(0)),
+            distinct,
+            percentile: None,
+        };
+        let plan = |group_by| PlanNode::HashAggregate {
+            input: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            group_by,
+            aggregates: vec![
+                call(AggregateFunction::Count, true),
+                call(AggregateFunction::Sum, true),
+                call(AggregateFunction::Count, false),
+            ],
+        };
+        // 同じグループには i % 3 の等しい a だけが入る
+        let expected = (0..3)
+            .map(|g| {
+                let sum = (0..300).filter(|a| a % 3 == g).sum::<i64>();
+                let count = (0..1000).filter(|i| i % 3 == g).count() as i64;
+                vec![text(&format!("g{}", g)), int(100), int(sum), int(count)]
+            })
+            .collect::<Vec<_>>();
+        // work_mem が 0 なら覚えた値も一時ファイルに書き出す
+        for work_mem in [DEFAULT_WORK_MEM, 0] {
+            let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+            ctx.work_mem = work_mem;
+            let mut result = execute(&plan(vec![Expr::column(1)]), &mut ctx).unwrap();
+            result.sort_by(|a, b| a[0].sort_cmp(&b[0]));
+            assert_eq!(result, expected);
+            assert_eq!(
+                execute(&plan(vec![]), &mut ctx).unwrap(),
+                vec![vec![int(300), int(299 * 300 / 2), int(1000)]]
+            );
+        }
+
+        // 見積もりの誤差はおよそ 1.6%
+        let rows = (0..10000)
+            .map(|i| vec![int(i), text("x")])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = PlanNode::HashAggregate {
+            input: Box::new(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            }),
+            group_by: vec![],
+            aggregates: vec![
+                call(AggregateFunction::ApproxCountDistinct, false),
+                AggregateCall {
+                    func: AggregateFunction::ApproxCountDistinct,
+                    arg: Some(Expr::column(1)),
+                    distinct: false,
+                    percentile: None,
+                },
+            ],
+        };
+        let result = execute(&plan, &mut ctx).unwrap();
+        let Value::Integer(estimate) = result[0][0] else {
+            panic!("{:?}", result);
+        };
+        assert!((9500..=10500).contains(&estimate), "{}", estimate);
+        assert_eq!(result[0][1], int(1));
+    }
+
+    #[test]
+    fn test_merge_aggregates() {
+        let dec = |s: &str| Value::Decimal(s.parse().unwrap());
+        let call = |func| AggregateCall {
+            func,
+            arg: Some(Expr::column(0)),
+            distinct: false,
+            percentile: None,
+        };
+        // ワーカーごとに別の値を集約してから、状態をまとめる
+        let parts = [
+            vec![int(1), dec("0.25"), Value::Null],
+            vec![dec("1.5"), int(2)],
+            vec![],
+        ];
+        for (func, expected) in [
+            (AggregateFunction::Count, int(4)),
+            (AggregateFunction::Sum, dec("4.75")),
+            (AggregateFunction::Avg, dec("1.1875")),
+            (AggregateFunction::Min, dec("0.25")),
+            (AggregateFunction::Max, int(2)),
+            (AggregateFunction::ApproxCountDistinct, int(4)),
+        ] {
+            let mut merged = call(func).accumulator();
+            for part in &parts {
+                let mut accumulator = call(func).accumulator();
+                for value in part {
+                    accumulator.update(value.clone()).unwrap();
+                }
+                merged.merge(accumulator).unwrap();
+            }
+            assert_eq!(merged.finish().unwrap(), expected, "{:?}", func);
+        }
+        assert!(call(AggregateFunction::Sum).mergeable());
+        assert!(!AggregateCall {
+            distinct: true,
+            ..call(AggregateFunction::Sum)
+        }
+        .mergeable());
+        assert!(!call(AggregateFunction::ApproxPercentile).mergeable());
+    }
+
+    #[test]
+    fn test_approx_percentile() {
+        let call = |percentile| AggregateCall {
+            func: AggregateFunction::ApproxPercentile,
+            arg: Some(Expr::column(0)),
+            distinct: false,
+            percentile: Some(percentile),
+        };
+        let mut accumulators = [0.0, 0.5, 0.99, 1.0].map(|p| call(p).accumulator());
+        // 0 から 99999 までを順序を混ぜて入れる
+        for i in 0..100_000i64 {
+            for accumulator in &mut accumulators {
+                accumulator
+                    .update(Value::Integer(i * 7919 % 100_000))
+                    .unwrap();
+            }
+        }
+        let estimates = accumulators
+            .iter_mut()
+            .map(|a| match a.finish().unwrap() {
+                Value::Real(f) => f,
+                value => panic!("{:?}", value),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!((estimates[0], estimates[3]), (0.0, 99999.0));
+        assert!((estimates[1] - 50000.0).abs() < 500.0, "{}", estimates[1]);
+        assert!((estimates[2] - 99000.0).abs() < 100.0, "{}", estimates[2]);
+        // 状態の大きさは行の数によらない
+        assert!(accumulators[1].size() < 16 * 1024);
+        assert_eq!(call(0.5).accumulator().finish().unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn test_group_aggregate() {
+        let call = |func, arg: Option<usize>| AggregateCall {
+            func,
+            arg: arg.map(Expr::column),
+            distinct: false,
+            percentile: None,
+        };
+        let aggregates = vec![
+            call(AggregateFunction::Count, None),
+            call(AggregateFunction::Count, Some(1)),
+            call(AggregateFunction::Sum, Some(1)),
+            call(AggregateFunction::Avg, Some(1)),
+            call(AggregateFunction::Min, Some(1)),
+            call(AggregateFunction::Max, Some(1)),
+        ];
+        let dec = |s: &str| Value::Decimal(s.parse().unwrap());
+        // 並んだ入力なら、キーが変わるたびにグループを返す
+        let rows = vec![
+            vec![text("a"), Value::BigInt(i64::MAX)],
+            vec![text("a"), int(1)],
+            vec![text("a"), int(-2)],
+            vec![text("b"), Value::Null],
+            vec![text("c"), dec("1.5")],
+            vec![text("c"), int(2)],
+            vec![text("d"), Value::Real(0.5)],
+            vec![text("d"), dec("1.5")],
+            vec![Value::Null, int(1)],
+        ];
+        let (mut bufmgr, catalog) = setup(&[]);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = PlanNode::GroupAggregate {
+            input: Box::new(PlanNode::Values { rows: rows.clone() }),
+            group_by: vec![Expr::column(0)],
+            aggregates: aggregates.clone(),
+        };
+        let max = Value::BigInt;
+        let expected = vec![
+            // 途中で i64 を超えても、合計が収まればよい
+            vec![
+                text("a"),
+                int(3),
+                int(3),
+                max(i64::MAX - 1),
+                Value::Real((i64::MAX - 1) as f64 / 3.0),
+                int(-2),
+                max(i64::MAX),
+            ],
+            vec![
+                text("b"),
+                int(1),
+                int(0),
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Null,
+            ],
+            vec![
+                text("c"),
+                int(2),
+                int(2),
+                dec("3.5"),
+                dec("1.750000"),
+                dec("1.5"),
+                int(2),
+            ],
+            vec![
+                text("d"),
+                int(2),
+                int(2),
+                Value::Real(2.0),
+                Value::Real(1.0),
+                Value::Real(0.5),
+                dec("1.5"),
+            ],
+            vec![
+                Value::Null,
+                int(1),
+                int(1),
+                int(1),
+                Value::Real(1.0),
+                int(1),
+                int(1),
+            ],
+        ];
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), expected);
+        // ハッシュ集約でも同じ結果になる
+        let plan = PlanNode::HashAggregate {
+            input: Box::new(PlanNode::Values { rows }),
+            group_by: vec![Expr::column(0)],
+            aggregates: aggregates.clone(),
+        };
+        let mut result = execute(&plan, &mut ctx).unwrap();
+        result.sort_by(|a, b| a[0].sort_cmp(&b[0]));
+        assert_eq!(result, expected);
+        // 整数の平均は切り捨てない
+        let plan = PlanNode::GroupAggregate {
+            input: Box::new(PlanNode::Values {
+                rows: vec![vec![int(0), int(1)], vec![int(0), int(2)]],
+            }),
+            group_by: vec![],
+            aggregates: vec![call(AggregateFunction::Avg, Some(1))],
+        };
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![Value::Real(1.5)]]
+        );
+
+        // 合計が収まらなければエラー
+        let plan = PlanNode::GroupAggregate {
+            input: Box::new(PlanNode::Values {
+                rows: vec![vec![int(0), max(i64::MAX)], vec![int(0), int(1)]],
+            }),
+            group_by: vec![Expr::column(0)],
+            aggregates: vec![call(AggregateFunction::Sum, Some(1))],
+        };
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(Error::IntegerOutOfRange)
+        ));
+        let plan = PlanNode::GroupAggregate {
+            input: Box::new(PlanNode::Values { rows: vec![] }),
+            group_by: vec![],
+            aggregates,
+        };
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![
+                int(0),
+                int(0),
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Null
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_window() {
+        use crate::sql::ast::{FrameBound, FrameUnits, WindowFrame};
+
+        let input = [(1, 10), (1, 20), (1, 20), (1, 30), (2, 5)]
+            .iter()
+            .map(|&(p, v)| vec![int(p), int(v)])
+            .chain([vec![int(2), Value::Null]])
+            .collect::<Vec<_>>();
+        let frame = |units, start, end| WindowFrame { units, start, end };
+        let running = frame(
+            FrameUnits::Range,
+            FrameBound::UnboundedPreceding,
+            FrameBound::CurrentRow,
+        );
+        let call = |func, args| WindowCall {
+            func,
+            args,
+            frame: running,
+        };
+        let sum = WindowFunction::Aggregate(AggregateFunction::Sum);
+        let plan = PlanNode::Window {
+            input: Box::new(PlanNode::Values {
+                rows: input.clone(),
+            }),
+            partition_by: vec![Expr::column(0)],
+            order_by: vec![SortKey {
+                expr: Expr::column(1),
+                asc: true,
+                nulls_first: false,
+            }],
+            calls: vec![
+                call(WindowFunction::RowNumber, vec![]),
+                call(WindowFunction::Rank, vec![]),
+                call(WindowFunction::DenseRank, vec![]),
+                call(WindowFunction::Lag, vec![Expr::column(1)]),
+                call(
+                    WindowFunction::Lead,
+                    vec![
+                        Expr::column(1),
+                        Expr::Literal(int(2)),
+                        Expr::Literal(int(-1)),
+                    ],
+                ),
+                call(sum, vec![Expr::column(1)]),
+                WindowCall {
+                    func: sum,
+                    args: vec![Expr::column(1)],
+                    frame: frame(
+                        FrameUnits::Rows,
+                        FrameBound::Preceding(1),
+                        FrameBound::Following(1),
+                    ),
+                },
+                WindowCall {
+                    func: WindowFunction::Aggregate(AggregateFunction::Count),
+                    args: vec![],
+                    frame: frame(
+                        FrameUnits::Range,
+                        FrameBound::UnboundedPreceding,
+                        FrameBound::UnboundedFollowing,
+                    ),
+                },
+            ],
+        };
+        let null = Value::Null;
+        let expected = [
+            [1, 1, 1, 0, 20, 10, 30, 4],
+            [2, 2, 2, 10, 30, 50, 50, 4],
+            [3, 2, 2, 20, -1, 50, 70, 4],
+            [4, 4, 3, 20, -1, 80, 50, 4],
+            [1, 1, 1, 0, -1, 5, 5, 2],
+            [2, 2, 2, 5, -1, 5, 5, 2],
+        ]
+        .iter()
+        .zip(&input)
+        .map(|(results, row)| {
+            let mut row = row.clone();
+            row.extend(results.iter().enumerate().map(|(i, &n)| {
+                // lag の 0 は前の行がないことを表す
+                if i == 3 && n == 0 {
+                    null.clone()
+                } else {
+                    int(n)
+                }
+            }));
+            row
+        })
+        .collect::<Vec<_>>();
+
+        let (mut bufmgr, catalog) = setup(&[]);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            plan.columns(ctx.catalog).unwrap()[2..],
+            [
+                "row_number",
+                "rank",
+                "dense_rank",
+                "lag",
+                "lead",
+                "sum",
+                "sum",
+                "count"
+            ]
+        );
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), expected);
+        // 行を一時ファイルに書き出しても同じ
+        ctx.work_mem = 0;
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_limit() {
+        let (mut bufmgr, catalog) = setup(&[]);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        // 3 行目を評価するとゼロ除算になるので、読みすぎればエラーになる
+        let plan = |limit, offset| PlanNode::Limit {
+            input: Box::new(PlanNode::Filter {
+                input: Box::new(PlanNode::Values {
+                    rows: vec![vec![int(1)], vec![int(2)], vec![int(0)]],
+                }),
+                predicate: Expr::binary(
+                    BinaryOp::Gt,
+                    Expr::binary(BinaryOp::Divide, Expr::Literal(int(10)), Expr::column(0)),
+                    Expr::Literal(int(0)),
+                ),
+            }),
+            limit,
+            offset,
+        };
+        assert_eq!(
+            execute(&plan(Some(2), 0), &mut ctx).unwrap(),
+            vec![vec![int(1)], vec![int(2)]]
+        );
+        assert_eq!(
+            execute(&plan(Some(1), 1), &mut ctx).unwrap(),
+            vec![vec![int(2)]]
+        );
+        assert_eq!(execute(&plan(Some(0), 0), &mut ctx).unwrap().len(), 0);
+        assert!(matches!(
+            execute(&plan(None, 1), &mut ctx),
+            Err(Error::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_merge_join() {
+        let rows = vec![
+            vec![int(1), text("a")],
+            vec![int(3), text("b")],
+            vec![int(3), text("c")],
+            vec![Value::Null, text("d")],
+            vec![int(4), text("e")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let sorted = |plan: PlanNode| PlanNode::Sort {
+            input: Box::new(plan),
+            keys: vec![SortKey {
+                expr: Expr::column(0),
+                asc: true,
+                nulls_first: false,
+            }],
+            top_n: None,
+        };
+        let left = vec![
+            vec![int(0)],
+            vec![int(1)],
+            vec![int(3)],
+            vec![int(3)],
+            vec![Value::Null],
+            vec![int(5)],
+        ];
+        let plan = PlanNode::MergeJoin {
+            left: Box::new(sorted(PlanNode::Values { rows: left })),
+            right: Box::new(sorted(PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: None,
+            })),
+            left_keys: vec![Expr::column(0)],
+            right_keys: vec![Expr::column(0)],
+            predicate: None,
+        };
+        // 両側に重複があれば全組み合わせを返す
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![int(1), int(1), text("a")],
+                vec![int(3), int(3), text("b")],
+                vec![int(3), int(3), text("c")],
+                vec![int(3), int(3), text("b")],
+                vec![int(3), int(3), text("c")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index_join() {
+        let rows = vec![
+            vec![int(1), text("a")],
+            vec![int(2), text("b")],
+            vec![int(2), text("c")],
+            vec![Value::Null, text("d")],
+        ];
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = PlanNode::IndexJoin {
+            left: Box::new(PlanNode::Values {
+                rows: vec![vec![int(2)], vec![Value::Null], vec![int(3)], vec![int(1)]],
+            }),
+            table: "t".to_string(),
+            index: "t_a".to_string(),
+            keys: vec![Expr::column(0)],
+            predicate: Some(Expr::binary(
+                BinaryOp::NotEq,
+                Expr::column(2),
+                Expr::Literal(text("c")),
+            )),
+        };
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![int(2), int(2), text("b")],
+                vec![int(1), int(1), text("a")],
+            ]
+        );
+        assert_eq!(plan.columns(&catalog).unwrap(), vec!["column1", "a", "b"]);
+    }
+}
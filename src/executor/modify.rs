@@ -0,0 +1,536 @@
+use std::mem;
+use std::vec;
+
+use crate::catalog::{self, Table, TriggerBody};
+use crate::heap::RecordId;
+use crate::planner::Planner;
+use crate::sql::ast::{TriggerEvent, TriggerTiming};
+use crate::transaction::{self, Undo};
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::{BoxExecutor, Error, ExecContext, Executor, PlanNode, Row};
+
+fn table<'c>(ctx: &ExecContext<'c>, name: &str) -> Result<&'c Table, Error> {
+    ctx.catalog
+        .table(name)
+        .ok_or_else(|| Error::TableNotFound(name.to_string()))
+}
+
+// 文の途中で失敗していれば、それまでの変更を取り消してエラーを返す。
+// 成功したらトランザクションの中なら変更をトランザクションに残す
+fn finish(
+    ctx: &mut ExecContext,
+    changes: Vec<Undo>,
+    result: Result<(), Error>,
+) -> Result<(), Error> {
+    if let Err(err) = result {
+        transaction::undo(ctx.bufmgr, ctx.catalog, changes.into_iter())?;
+        return Err(err);
+    }
+    match ctx.txn.as_mut() {
+        Some(txn) => txn.push_undo(changes.iter().cloned()),
+        None if ctx.unlogged => {}
+        None => transaction::log_autocommit(ctx.bufmgr, &changes)?,
+    }
+    Ok(())
+}
+
+// 書き換えようとした版がもう消されていたときに呼ぶ。
+// 同じ文の中で消したものなら飛ばし、ほかのトランザクションが先に書き換えていたら後から書いた方を失敗させる
+fn check_conflict(ctx: &mut ExecContext, table: &Table, rid: RecordId) -> Result<(), Error> {
+    match table.storage.get(ctx.bufmgr, rid)? {
+        Some((header, _)) if header.xmax != ctx.xid() => Err(Error::SerializationFailure),
+        _ => Ok(()),
+    }
+}
+
+// トリガーが実行した文がまたトリガーを呼ぶ深さの上限
+const MAX_TRIGGER_DEPTH: usize = 32;
+
+// table のトリガーのうち timing と event のものを作った順に呼ぶ。BEFORE のトリガーが行を飛ばしたら false を返す
+//
+// SQL の文の本体は NEW と OLD を行の値にして計画し、同じ文脈で実行する。
+// その文の変更は changes に移すので、呼んだ文が失敗すればいっしょに取り消される
+fn fire(
+    ctx: &mut ExecContext,
+    table: &Table,
+    timing: TriggerTiming,
+    event: TriggerEvent,
+    old: Option<&Row>,
+    mut new: Option<&mut Row>,
+    changes: &mut Vec<Undo>,
+) -> Result<bool, Error> {
+    let triggers = table
+        .triggers
+        .iter()
+        .filter(|t| t.timing == timing && t.event == event);
+    for trigger in triggers {
+        match &trigger.body {
+            TriggerBody::Function(func) => {
+                let proceed = func
+                    .call(old.map(Vec::as_slice), new.as_deref_mut())
+                    .map_err(|message| Error::TriggerFailed {
+                        name: trigger.name.clone(),
+                        message,
+                    })?;
+                if timing == TriggerTiming::Before {
+                    if !proceed {
+                        return Ok(false);
+                    }
+                    if let Some(new) = new.as_deref_mut() {
+                        *new = table.coerce(mem::take(new))?;
+                    }
+                }
+            }
+            TriggerBody::Statement { statement, .. } => {
+                let rows = [("new", new.as_deref()), ("old", old)]
+                    .into_iter()
+                    .filter_map(|(name, row)| Some((name, table, row?.as_slice())))
+                    .collect();
+                let plan = Planner::new(ctx.catalog)
+                    .with_trigger_rows(rows)
+                    .plan_statement(statement)
+                    .map_err(|source| Error::Trigger {
+                        name: trigger.name.clone(),
+                        source: Box::new(source),
+                    })?;
+                execute_trigger(ctx, &trigger.name, &plan, changes)?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+fn execute_trigger(
+    ctx: &mut ExecContext,
+    name: &str,
+    plan: &PlanNode,
+    changes: &mut Vec<Undo>,
+) -> Result<(), Error> {
+    if ctx.trigger_depth >= MAX_TRIGGER_DEPTH {
+        return Err(Error::TriggerDepth(name.to_string()));
+    }
+    // 呼んだ文の EXPLAIN ANALYZE の値と WITH の結果には混ぜない
+    let metrics = ctx.metrics.take();
+    let ctes = mem::take(&mut ctx.ctes);
+    let start = ctx.txn.as_ref().map(|txn| txn.undo_len());
+    ctx.trigger_depth += 1;
+    let result = super::execute(plan, ctx);
+    ctx.trigger_depth -= 1;
+    ctx.metrics = metrics;
+    ctx.ctes = ctes;
+    if let (Some(txn), Some(start)) = (ctx.txn.as_mut(), start) {
+        changes.extend(txn.take_undo(start));
+    }
+    result.map(|_| ())
+}
+
+// returning なら処理した行を、そうでなければ行数だけの 1 行を返す
+fn output(rows: Vec<Row>, returning: bool) -> vec::IntoIter<Row> {
+    if returning {
+        rows.into_iter()
+    } else {
+        vec![vec![Value::Integer(rows.len() as i64)]].into_iter()
+    }
+}
+
+// INSERT で、一意インデックスに同じ値の行がすでにあったときの動作
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnConflict {
+    // 重複を調べるインデックス。None ならすべての一意インデックス
+    pub index: Option<String>,
+    pub action: ConflictAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConflictAction {
+    Nothing,
+    // assignments と predicate は、すでにある行のあとに入れようとした行をつなげたものに対して評価する
+    Update {
+        assignments: Vec<(usize, Expr)>,
+        predicate: Option<Expr>,
+    },
+}
+
+// 入力の行をすべてテーブルに入れ、入れた行数を 1 行で返す。returning なら入れた行を返す
+//
+// 書き換える演算子はどれも、途中の行で失敗したらその文で書き換えた行を元に戻す。
+pub struct Insert<'a> {
+    table: &'a str,
+    input: BoxExecutor<'a>,
+    on_conflict: Option<&'a OnConflict>,
+    returning: bool,
+    output: Option<vec::IntoIter<Row>>,
+}
+
+impl<'a> Insert<'a> {
+    pub fn new(
+        table: &'a str,
+        input: BoxExecutor<'a>,
+        on_conflict: Option<&'a OnConflict>,
+        returning: bool,
+    ) -> Self {
+        Self {
+            table,
+            input,
+            on_conflict,
+            returning,
+            output: None,
+        }
+    }
+
+    fn run(&mut self, ctx: &mut ExecContext) -> Result<Vec<Row>, Error> {
+        let table = table(ctx, self.table)?;
+        // 入力が同じテーブルを読んでいると、入れた行をまた読んでしまうので先にすべて読む
+        let mut rows = vec![];
+        while let Some(row) = self.input.next(ctx)? {
+            rows.push(row);
+        }
+        ctx.count_writes(self.table, |ctx| {
+            let mut changes = vec![];
+            let mut inserted = vec![];
+            let result = rows
+                .into_iter()
+                .try_for_each(|row| self.insert(ctx, table, row, &mut changes, &mut inserted));
+            finish(ctx, changes, result).map(|_| inserted)
+        })
+    }
+
+    // 入れた行か、ON CONFLICT で書き換えたあとの行を inserted に足す
+    fn insert(
+        &self,
+        ctx: &mut ExecContext,
+        table: &Table,
+        row: Row,
+        changes: &mut Vec<Undo>,
+        inserted: &mut Vec<Row>,
+    ) -> Result<(), Error> {
+        let mut row = table.coerce(row)?;
+        let (before, after) = (TriggerTiming::Before, TriggerTiming::After);
+        // トリガーは文が書くテーブルのものを呼ぶ
+        let parent = table;
+        let event = TriggerEvent::Insert;
+        if !fire(ctx, parent, before, event, None, Some(&mut row), changes)? {
+            return Ok(());
+        }
+        // パーティションに分けたテーブルには、行の値が入る子のテーブルに入れる
+        let catalog = ctx.catalog;
+        let table = catalog.route(table, &row)?;
+        let Some((rid, old)) = self.find_conflict(ctx, table, &row)? else {
+            let rid = table.insert(ctx.bufmgr, ctx.xid(), &row)?;
+            changes.push(Undo::Insert {
+                table: table.name.clone(),
+                rid,
+                row: row.clone(),
+            });
+            inserted.push(row.clone());
+            fire(ctx, parent, after, event, None, Some(&mut row), changes)?;
+            return Ok(());
+        };
+        let Some(ConflictAction::Update {
+            assignments,
+            predicate,
+        }) = self.on_conflict.map(|c| &c.action)
+        else {
+            return Ok(());
+        };
+        // この文で入れたか書き換えた行をもう一度書き換えると、結果が入力の順に依存してしまう
+        if changes
+            .iter()
+            .any(|change| change.rid() == rid && change.table() == table.name)
+        {
+            return Err(Error::ConflictRowTwice);
+        }
+        let joined = [old.as_slice(), row.as_slice()].concat();
+        if let Some(predicate) = predicate {
+            if !predicate.eval_predicate(&joined)? {
+                return Ok(());
+            }
+        }
+        let mut new = old.clone();
+        for (i, expr) in assignments {
+            new[*i] = expr.eval(&joined)?;
+        }
+        let mut new = table.coerce(new)?;
+        let event = TriggerEvent::Update;
+        if !fire(
+            ctx,
+            parent,
+            before,
+            event,
+            Some(&old),
+            Some(&mut new),
+            changes,
+        )? {
+            return Ok(());
+        }
+        ctx.lock_row(&table.name, rid)?;
+        let Some(rid_after) = table.update(ctx.bufmgr, ctx.xid(), rid, &old, &new)? else {
+            return check_conflict(ctx, table, rid);
+        };
+        changes.push(Undo::Update {
+            table: table.name.clone(),
+            before: rid,
+            after: rid_after,
+            old: old.clone(),
+            new: new.clone(),
+        });
+        inserted.push(new.clone());
+        fire(
+            ctx,
+            parent,
+            after,
+            event,
+            Some(&old),
+            Some(&mut new),
+            changes,
+        )?;
+        Ok(())
+    }
+
+    // 対象のインデックスで row と重複する行とその RecordId
+    fn find_conflict(
+        &self,
+        ctx: &mut ExecContext,
+        table: &Table,
+        row: &Row,
+    ) -> Result<Option<(RecordId, Row)>, Error> {
+        let Some(on_conflict) = self.on_conflict else {
+            return Ok(None);
+        };
+        let indexes = table
+            .indexes
+            .iter()
+            .filter(|index| match &on_conflict.index {
+                Some(name) => index.name == *name,
+                None => index.unique,
+            });
+        for index in indexes {
+            let Some(rid) = index.find(ctx.bufmgr, &*table.storage, row)? else {
+                continue;
+            };
+            let (_, bytes) = table
+                .storage
+                .get(ctx.bufmgr, rid)?
+                .ok_or(Error::CorruptedTuple(rid))?;
+            let mut old = table.decode(&bytes).ok_or(Error::CorruptedTuple(rid))?;
+            catalog::fill_virtual(table.virtual_columns(), &mut old)?;
+            return Ok(Some((rid, old)));
+        }
+        Ok(None)
+    }
+}
+
+impl Executor for Insert<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if self.output.is_none() {
+            let rows = self.run(ctx)?;
+            self.output = Some(output(rows, self.returning));
+        }
+        Ok(self.output.as_mut().unwrap().next())
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
+
+// 入力が返したテーブルの行を書き換え、書き換えた行数を 1 行で返す。returning なら書き換えたあとの行を返す
+//
+// 書き換えた行の新しい版がヒープの後ろに入ると走査でもう一度読まれてしまうので、
+// 対象の行をすべて読んでから書き換える。
+// パーティションに分けたテーブルで、新しい行が別の子に入るなら、元の子から消して新しい子に入れる。
+pub struct Update<'a> {
+    table: &'a str,
+    input: BoxExecutor<'a>,
+    assignments: &'a [(usize, Expr)],
+    returning: bool,
+    output: Option<vec::IntoIter<Row>>,
+}
+
+impl<'a> Update<'a> {
+    pub fn new(
+        table: &'a str,
+        input: BoxExecutor<'a>,
+        assignments: &'a [(usize, Expr)],
+        returning: bool,
+    ) -> Self {
+        Self {
+            table,
+            input,
+            assignments,
+            returning,
+            output: None,
+        }
+    }
+
+    fn run(&mut self, ctx: &mut ExecContext) -> Result<Vec<Row>, Error> {
+        let table = table(ctx, self.table)?;
+        let targets = collect_targets(&mut self.input, ctx, table)?;
+        ctx.count_writes(self.table, |ctx| {
+            let mut changes = Vec::with_capacity(targets.len());
+            let mut updated = Vec::with_capacity(targets.len());
+            let result = targets.into_iter().try_for_each(|(source, rid, old)| {
+                let mut new = old.clone();
+                for (i, expr) in self.assignments {
+                    new[*i] = expr.eval(&old)?;
+                }
+                let mut new = source.coerce(new)?;
+                let (before, after) = (TriggerTiming::Before, TriggerTiming::After);
+                let event = TriggerEvent::Update;
+                if !fire(
+                    ctx,
+                    table,
+                    before,
+                    event,
+                    Some(&old),
+                    Some(&mut new),
+                    &mut changes,
+                )? {
+                    return Ok(());
+                }
+                let catalog = ctx.catalog;
+                let dest = catalog.route(table, &new)?;
+                ctx.lock_row(&source.name, rid)?;
+                if dest.name != source.name {
+                    if !source.delete(ctx.bufmgr, ctx.xid(), rid)? {
+                        return check_conflict(ctx, source, rid);
+                    }
+                    changes.push(Undo::Delete {
+                        table: source.name.clone(),
+                        rid,
+                        row: old.clone(),
+                    });
+                    let rid_after = dest.insert(ctx.bufmgr, ctx.xid(), &new)?;
+                    changes.push(Undo::Insert {
+                        table: dest.name.clone(),
+                        rid: rid_after,
+                        row: new.clone(),
+                    });
+                } else {
+                    let Some(rid_after) = source.update(ctx.bufmgr, ctx.xid(), rid, &old, &new)?
+                    else {
+                        return check_conflict(ctx, source, rid);
+                    };
+                    changes.push(Undo::Update {
+                        table: source.name.clone(),
+                        before: rid,
+                        after: rid_after,
+                        old: old.clone(),
+                        new: new.clone(),
+                    });
+                }
+                updated.push(new.clone());
+                fire(
+                    ctx,
+                    table,
+                    after,
+                    event,
+                    Some(&old),
+                    Some(&mut new),
+                    &mut changes,
+                )?;
+                Ok(())
+            });
+            finish(ctx, changes, result).map(|_| updated)
+        })
+    }
+}
+
+impl Executor for Update<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if self.output.is_none() {
+            let rows = self.run(ctx)?;
+            self.output = Some(output(rows, self.returning));
+        }
+        Ok(self.output.as_mut().unwrap().next())
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
+
+// 入力が返したテーブルの行を消し、消した行数を 1 行で返す。returning なら消した行を返す
+pub struct Delete<'a> {
+    table: &'a str,
+    input: BoxExecutor<'a>,
+    returning: bool,
+    output: Option<vec::IntoIter<Row>>,
+}
+
+impl<'a> Delete<'a> {
+    pub fn new(table: &'a str, input: BoxExecutor<'a>, returning: bool) -> Self {
+        Self {
+            table,
+            input,
+            returning,
+            output: None,
+        }
+    }
+
+    fn run(&mut self, ctx: &mut ExecContext) -> Result<Vec<Row>, Error> {
+        let table = table(ctx, self.table)?;
+        let targets = collect_targets(&mut self.input, ctx, table)?;
+        ctx.count_writes(self.table, |ctx| {
+            let mut changes = vec![];
+            let mut deleted = vec![];
+            let result = targets.into_iter().try_for_each(|(source, rid, row)| {
+                let (before, after) = (TriggerTiming::Before, TriggerTiming::After);
+                let event = TriggerEvent::Delete;
+                if !fire(ctx, table, before, event, Some(&row), None, &mut changes)? {
+                    return Ok(());
+                }
+                ctx.lock_row(&source.name, rid)?;
+                if !source.delete(ctx.bufmgr, ctx.xid(), rid)? {
+                    return check_conflict(ctx, source, rid);
+                }
+                changes.push(Undo::Delete {
+                    table: source.name.clone(),
+                    rid,
+                    row: row.clone(),
+                });
+                fire(ctx, table, after, event, Some(&row), None, &mut changes)?;
+                deleted.push(row);
+                Ok(())
+            });
+            finish(ctx, changes, result).map(|_| deleted)
+        })
+    }
+}
+
+impl Executor for Delete<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if self.output.is_none() {
+            let rows = self.run(ctx)?;
+            self.output = Some(output(rows, self.returning));
+        }
+        Ok(self.output.as_mut().unwrap().next())
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
+
+// 入力が返した行と、その版を持つテーブル。パーティションに分けたテーブルなら版は子のテーブルにある
+fn collect_targets<'c>(
+    input: &mut BoxExecutor,
+    ctx: &mut ExecContext<'c>,
+    table: &'c Table,
+) -> Result<Vec<(&'c Table, RecordId, Row)>, Error> {
+    let catalog = ctx.catalog;
+    let mut targets = vec![];
+    while let Some(row) = input.next(ctx)? {
+        let rid = input
+            .record_id()
+            .expect("input of UPDATE and DELETE returns table rows");
+        let source = input
+            .relation()
+            .and_then(|name| catalog.table(name))
+            .unwrap_or(table);
+        targets.push((source, rid, row));
+    }
+    Ok(targets)
+}
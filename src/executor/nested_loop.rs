@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::{BoxExecutor, Error, ExecContext, Executor, JoinType, PlanNode, Row};
+
+// 外側からまとめて読み込む行数
+const BLOCK_SIZE: usize = 256;
+
+// ブロック入れ子ループ結合。外側をブロック単位で読み、ブロックごとに内側を最初から走査する
+//
+// Semi と Anti では一致した外側の行をそれ以上比べず、ブロックの行がすべて一致したら
+// 内側の走査を打ち切る。Right と Full では内側の何行目が一致したかを覚えておき、外側が尽きたら
+// 内側をもう一度走査して一致しなかった行を返す。内側は走査するたびに同じ順で行を返す必要がある。
+// 内側の演算子は rescan できれば使い回し、できなければ計画から始め直す。
+pub struct NestedLoopJoin<'a> {
+    outer: BoxExecutor<'a>,
+    inner_plan: &'a PlanNode,
+    predicate: Option<&'a Expr>,
+    join_type: JoinType,
+    // 外側と内側の列数。相手のない行を NULL で埋めるのに使う
+    widths: [usize; 2],
+    block: Vec<Row>,
+    // ブロックの各行が一致したか
+    matched: Vec<bool>,
+    num_matched: usize,
+    output: VecDeque<Row>,
+    outer_done: bool,
+    inner: Option<BoxExecutor<'a>>,
+    // ブロックに対して内側を走査している途中か
+    scanning: bool,
+    inner_row: Option<Row>,
+    // 現在の内側の行と次に組み合わせるブロック中の位置
+    pos: usize,
+    // 内側の各行がいずれかの外側の行と一致したか
+    inner_matched: Vec<bool>,
+    inner_index: usize,
+    // 一致しなかった内側の行を返すための走査を始めたか
+    unmatched_started: bool,
+    unmatched_done: bool,
+}
+
+impl<'a> NestedLoopJoin<'a> {
+    pub fn new(
+        outer: BoxExecutor<'a>,
+        inner_plan: &'a PlanNode,
+        predicate: Option<&'a Expr>,
+        join_type: JoinType,
+        widths: [usize; 2],
+    ) -> Self {
+        Self {
+            outer,
+            inner_plan,
+            predicate,
+            join_type,
+            widths,
+            block: vec![],
+            matched: vec![],
+            num_matched: 0,
+            output: VecDeque::new(),
+            outer_done: false,
+            inner: None,
+            scanning: false,
+            inner_row: None,
+            pos: 0,
+            inner_matched: vec![],
+            inner_index: 0,
+            unmatched_started: false,
+            unmatched_done: !join_type.keeps_right(),
+        }
+    }
+
+    // 次のブロックを読み込み、内側の走査を始める。外側が尽きていれば false
+    fn load_block(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        self.block.clear();
+        while !self.outer_done && self.block.len() < BLOCK_SIZE {
+            match self.outer.next(ctx)? {
+                Some(row) => self.block.push(row),
+                None => self.outer_done = true,
+            }
+        }
+        if self.block.is_empty() {
+            return Ok(false);
+        }
+        self.matched = vec![false; self.block.len()];
+        self.num_matched = 0;
+        self.start_inner(ctx)?;
+        self.scanning = true;
+        Ok(true)
+    }
+
+    // 内側を先頭から走査し直す
+    fn start_inner(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        let inner = match self.inner.take() {
+            Some(mut inner) => {
+                if inner.rescan(ctx)? {
+                    inner
+                } else {
+                    inner.close(ctx)?;
+                    self.inner_plan.start(ctx)?
+                }
+            }
+            None => self.inner_plan.start(ctx)?,
+        };
+        self.inner = Some(inner);
+        self.inner_index = 0;
+        Ok(())
+    }
+
+    // 内側の走査が終わったブロックの、一致しなかった行を出力に回す
+    fn finish_block(&mut self) {
+        let block = std::mem::take(&mut self.block);
+        let unmatched = block
+            .into_iter()
+            .zip(&self.matched)
+            .filter(|(_, matched)| !**matched)
+            .map(|(row, _)| row);
+        match self.join_type {
+            JoinType::Anti => self.output.extend(unmatched),
+            JoinType::Left | JoinType::Full => {
+                let width = self.widths[1];
+                self.output.extend(unmatched.map(|mut row| {
+                    row.resize(row.len() + width, Value::Null);
+                    row
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    // どの外側の行とも一致しなかった内側の行を、外側の列を NULL で埋めて返す
+    fn next_unmatched(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if self.unmatched_done {
+            return Ok(None);
+        }
+        if !self.unmatched_started {
+            self.unmatched_started = true;
+            self.start_inner(ctx)?;
+        }
+        let inner = self.inner.as_mut().expect("inner scan is started");
+        while let Some(row) = inner.next(ctx)? {
+            ctx.check_interrupt()?;
+            let i = self.inner_index;
+            self.inner_index += 1;
+            if !self.inner_matched.get(i).copied().unwrap_or(false) {
+                let mut padded = vec![Value::Null; self.widths[0]];
+                padded.extend(row);
+                return Ok(Some(padded));
+            }
+        }
+        self.unmatched_done = true;
+        Ok(None)
+    }
+}
+
+impl Executor for NestedLoopJoin<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            ctx.check_interrupt()?;
+            if let Some(row) = self.output.pop_front() {
+                return Ok(Some(row));
+            }
+            let (true, Some(inner)) = (self.scanning, &mut self.inner) else {
+                if !self.load_block(ctx)? {
+                    return self.next_unmatched(ctx);
+                }
+                continue;
+            };
+            let all_matched = self.join_type.is_semi() && self.num_matched == self.block.len();
+            let inner_row = match &self.inner_row {
+                Some(row) if self.pos < self.block.len() => row,
+                _ => match inner.next(ctx)?.filter(|_| !all_matched) {
+                    Some(row) => {
+                        self.pos = 0;
+                        self.inner_index += 1;
+                        self.inner_row.insert(row)
+                    }
+                    None => {
+                        self.scanning = false;
+                        self.inner_row = None;
+                        self.finish_block();
+                        continue;
+                    }
+                },
+            };
+            while self.pos < self.block.len() {
+                let i = self.pos;
+                self.pos += 1;
+                if self.matched[i] && self.join_type.is_semi() {
+                    continue;
+                }
+                let mut row = self.block[i].clone();
+                row.extend_from_slice(inner_row);
+                match self.predicate {
+                    Some(predicate) if !predicate.eval_predicate(&row)? => continue,
+                    _ => {}
+                }
+                if !self.matched[i] {
+                    self.matched[i] = true;
+                    self.num_matched += 1;
+                }
+                match self.join_type {
+                    JoinType::Semi => return Ok(Some(self.block[i].clone())),
+                    JoinType::Anti => {}
+                    _ => {
+                        if self.join_type.keeps_right() {
+                            let j = self.inner_index - 1;
+                            if self.inner_matched.len() <= j {
+                                self.inner_matched.resize(j + 1, false);
+                            }
+                            self.inner_matched[j] = true;
+                        }
+                        return Ok(Some(row));
+                    }
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        if let Some(mut inner) = self.inner.take() {
+            inner.close(ctx)?;
+        }
+        self.outer.close(ctx)
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+use super::expr::Expr;
+use super::function::array_arg;
+use super::{BoxExecutor, Error, ExecContext, Executor, Row};
+use crate::types::Value;
+
+// 入力の各行のあとに、exprs の配列の要素を 1 つずつ並べた行を返す (unnest)
+//
+// 配列が複数なら最も長いものの要素の数だけ行を作り、足りない要素は NULL にする。
+// NULL と空の配列は要素のない配列として扱うので、すべてそうなら行を作らない
+pub struct ProjectSet<'a> {
+    input: BoxExecutor<'a>,
+    exprs: &'a [Expr],
+    pending: VecDeque<Row>,
+}
+
+impl<'a> ProjectSet<'a> {
+    pub fn new(input: BoxExecutor<'a>, exprs: &'a [Expr]) -> Self {
+        Self {
+            input,
+            exprs,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn expand(&mut self, row: Row) -> Result<(), Error> {
+        let mut arrays = vec![];
+        for expr in self.exprs {
+            arrays.push(match expr.eval(&row)? {
+                Value::Null => vec![],
+                value => array_arg("unnest", value, None)?,
+            });
+        }
+        let len = arrays.iter().map(Vec::len).max().unwrap_or(0);
+        let mut arrays = arrays
+            .into_iter()
+            .map(|items| items.into_iter())
+            .collect::<Vec<_>>();
+        for _ in 0..len {
+            let mut expanded = row.clone();
+            expanded.extend(
+                arrays
+                    .iter_mut()
+                    .map(|items| items.next().unwrap_or(Value::Null)),
+            );
+            self.pending.push_back(expanded);
+        }
+        Ok(())
+    }
+}
+
+impl Executor for ProjectSet<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+            let Some(row) = self.input.next(ctx)? else {
+                return Ok(None);
+            };
+            self.expand(row)?;
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
@@ -0,0 +1,44 @@
+use super::expr::Expr;
+use super::{Batch, BoxExecutor, Error, ExecContext, Executor, Row};
+
+// 入力の各行から式を評価して新しい行を作る
+pub struct Projection<'a> {
+    input: BoxExecutor<'a>,
+    exprs: &'a [Expr],
+}
+
+impl<'a> Projection<'a> {
+    pub fn new(input: BoxExecutor<'a>, exprs: &'a [Expr]) -> Self {
+        Self { input, exprs }
+    }
+}
+
+impl Executor for Projection<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        let Some(row) = self.input.next(ctx)? else {
+            return Ok(None);
+        };
+        let row = self
+            .exprs
+            .iter()
+            .map(|expr| expr.eval(&row))
+            .collect::<Result<Row, _>>()?;
+        Ok(Some(row))
+    }
+
+    fn next_batch(&mut self, ctx: &mut ExecContext) -> Result<Option<Batch>, Error> {
+        let Some(batch) = self.input.next_batch(ctx)? else {
+            return Ok(None);
+        };
+        let columns = self
+            .exprs
+            .iter()
+            .map(|expr| expr.eval_batch(&batch))
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Batch::new(columns, batch.len())))
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use super::materialize::RowStore;
+use super::memory::MemoryReservation;
+use super::{row_size, BoxExecutor, Error, ExecContext, Executor, PlanNode, Row};
+
+// WITH RECURSIVE の問い合わせ
+//
+// 非再帰項の行を返したあと、前の段で返した行を作業テーブルとして id の CteScan に読ませ、再帰項を実行し直す。
+// 再帰項が行を返さなくなれば終わる。UNION ALL でなければ、それまでに返した行と同じ行は返さず作業テーブルにも入れないので、
+// 閉路をたどる問い合わせも止まる。返した行を覚える表はメモリに置いたままにし、一時ファイルには書き出さない。
+// 段の数が max_recursion_depth を超えたらエラーで止める。行は段ごとに返すので、LIMIT で読むのをやめれば先の段は実行しない
+pub struct RecursiveUnion<'a> {
+    recursive: &'a PlanNode,
+    id: usize,
+    all: bool,
+    // 実行中の非再帰項か再帰項
+    input: Option<BoxExecutor<'a>>,
+    // 今の段で返した行。次の段の作業テーブルになる
+    work: RowStore,
+    work_rows: usize,
+    depth: u64,
+    seen: HashSet<Row>,
+    reservation: MemoryReservation,
+}
+
+impl<'a> RecursiveUnion<'a> {
+    pub fn new(
+        anchor: &'a PlanNode,
+        recursive: &'a PlanNode,
+        id: usize,
+        all: bool,
+        ctx: &mut ExecContext,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            recursive,
+            id,
+            all,
+            input: Some(anchor.start(ctx)?),
+            work: RowStore::new(ctx.memory.reservation()),
+            work_rows: 0,
+            depth: 0,
+            seen: HashSet::new(),
+            reservation: ctx.memory.reservation(),
+        })
+    }
+
+    // 次の段を始める。作業テーブルが空なら false
+    fn next_step(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        if self.work_rows == 0 {
+            return Ok(false);
+        }
+        self.depth += 1;
+        if let Some(limit) = ctx.max_recursion_depth {
+            if self.depth > limit {
+                return Err(Error::RecursionDepthLimit(limit));
+            }
+        }
+        let work = std::mem::replace(&mut self.work, RowStore::new(ctx.memory.reservation()));
+        self.work_rows = 0;
+        ctx.ctes.insert(self.id, Rc::new(RefCell::new(work)));
+        self.input = Some(self.recursive.start(ctx)?);
+        Ok(true)
+    }
+}
+
+impl Executor for RecursiveUnion<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            let Some(input) = &mut self.input else {
+                if !self.next_step(ctx)? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            let Some(row) = input.next(ctx)? else {
+                input.close(ctx)?;
+                self.input = None;
+                continue;
+            };
+            ctx.check_interrupt()?;
+            if !self.all {
+                if self.seen.contains(&row) {
+                    continue;
+                }
+                self.reservation.grow(row_size(&row));
+                self.seen.insert(row.clone());
+            }
+            self.work.push(ctx, row.clone())?;
+            self.work_rows += 1;
+            return Ok(Some(row));
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        if let Some(mut input) = self.input.take() {
+            input.close(ctx)?;
+        }
+        ctx.ctes.remove(&self.id);
+        self.seen.clear();
+        self.reservation.free();
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use crate::catalog::{Dictionary, GeneratedColumn};
+use crate::enum_type::EnumType;
+use crate::heap::RecordId;
+use crate::rtree::{self, Search};
+use crate::storage::{TableAccess, TableScan};
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::scan::decode_row;
+use super::{Error, ExecContext, Executor, Row};
+
+// R-tree のインデックスの走査
+//
+// search に合う値の行を、近い順の走査なら距離の近い順に返す。木をたどりながら 1 行ずつ返すので、
+// LIMIT で止めれば残りのノードは読まない。消した版を指すエントリも残っているので、版が見えるかはヒープで確かめる。
+// NULL の値は木にないので、近い順の走査では木を読み終えてからヒープを読み、列が NULL の行を最後に返す
+pub struct RTreeScan<'a> {
+    table: &'a str,
+    index: &'a str,
+    storage: Arc<dyn TableAccess>,
+    // 行を読むときに計算する生成列
+    // テーブルの列の数
+    width: usize,
+    virtual_columns: Vec<GeneratedColumn>,
+    dictionaries: Vec<Arc<Dictionary>>,
+    enums: Vec<Arc<EnumType>>,
+    scan: rtree::Scan,
+    // 近い順の走査ならインデックスの列の位置
+    nearest: Option<usize>,
+    nulls: Option<Box<dyn TableScan>>,
+    predicate: Option<&'a Expr>,
+    rid: Option<RecordId>,
+}
+
+impl<'a> RTreeScan<'a> {
+    pub fn new(
+        ctx: &mut ExecContext,
+        table: &'a str,
+        index: &'a str,
+        search: Search,
+        predicate: Option<&'a Expr>,
+    ) -> Result<Self, Error> {
+        let (table_name, index_name) = (table, index);
+        let table = ctx
+            .catalog
+            .table(table)
+            .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+        let (rtree, column) = table
+            .index(index)
+            .and_then(|index| Some((index.rtree?, index.columns[0])))
+            .ok_or_else(|| Error::IndexNotFound(index.to_string()))?;
+        if let Some(io) = ctx.io {
+            io.update(table_name, |io| io.index_scans += 1);
+            io.update(index_name, |io| io.index_scans += 1);
+        }
+        let scan = ctx.count_reads(index_name, |ctx| rtree.scan(ctx.bufmgr, search))?;
+        Ok(Self {
+            table: table_name,
+            index: index_name,
+            storage: table.storage.clone(),
+            width: table.columns.len(),
+            virtual_columns: table.virtual_columns().cloned().collect(),
+            dictionaries: table.dictionaries.clone(),
+            enums: table.enum_types(),
+            scan,
+            nearest: matches!(search, Search::Nearest(_)).then_some(column),
+            nulls: None,
+            predicate,
+            rid: None,
+        })
+    }
+}
+
+impl Executor for RTreeScan<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            ctx.check_interrupt()?;
+            let (rid, header, bytes) = match &mut self.nulls {
+                Some(nulls) => {
+                    let Some(tuple) = ctx.count_reads(self.table, |ctx| nulls.next(ctx.bufmgr))?
+                    else {
+                        return Ok(None);
+                    };
+                    tuple
+                }
+                None => {
+                    let scan = &mut self.scan;
+                    let Some((_, _, rid)) =
+                        ctx.count_reads(self.index, |ctx| scan.next(ctx.bufmgr))?
+                    else {
+                        if self.nearest.is_none() {
+                            return Ok(None);
+                        }
+                        self.nulls = Some(self.storage.scan());
+                        continue;
+                    };
+                    let storage = &self.storage;
+                    let Some((header, bytes)) =
+                        ctx.count_reads(self.table, |ctx| storage.get(ctx.bufmgr, rid))?
+                    else {
+                        continue;
+                    };
+                    (rid, header, bytes)
+                }
+            };
+            if !ctx.visible(&header) {
+                continue;
+            }
+            let row = decode_row(
+                &bytes,
+                self.width,
+                None,
+                &self.virtual_columns,
+                &self.dictionaries,
+                &self.enums,
+                rid,
+            )?;
+            if self.nulls.is_some() && self.nearest.is_some_and(|c| !matches!(row[c], Value::Null))
+            {
+                continue;
+            }
+            match self.predicate {
+                Some(predicate) if !predicate.eval_predicate(&row)? => {}
+                _ => {
+                    self.rid = Some(rid);
+                    return Ok(Some(row));
+                }
+            }
+        }
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.rid
+    }
+
+    fn relation(&self) -> Option<&str> {
+        Some(self.table)
+    }
+}
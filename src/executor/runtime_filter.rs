@@ -0,0 +1,104 @@
+use crate::heap::RecordId;
+use crate::planner;
+
+use super::explain;
+use super::expr::Expr;
+use super::{BoxExecutor, Error, ExecContext, Executor, Row};
+
+// ハッシュ結合の探索側の走査のすぐ上で、ハッシュ表のどの行とも一致しない行を結合より前に捨てる
+//
+// Bloom フィルタは id の HashJoin がハッシュ表を作り終わったときに ExecContext に置くので、それまでに読んだ行はそのまま通す。
+// フィルタは一致する行を必ず通すが、一致しない行も少しは通すので、結合の結果は変わらない。
+// キーに NULL を含む行はどの行とも一致しないので、フィルタがあれば捨てる。
+// partition があれば input はその子のテーブルの走査で、ハッシュ表のそのキーの範囲が子の範囲と重ならなければ
+// どの行も一致しないので、残りを読まない
+pub struct RuntimeFilter<'a> {
+    input: BoxExecutor<'a>,
+    id: usize,
+    keys: &'a [Expr],
+    partition: Option<&'a (String, usize)>,
+    // キーの範囲が置かれたあとに一度だけ決める
+    pruned: Option<bool>,
+    // EXPLAIN ANALYZE で捨てた行を数える演算子
+    plan: usize,
+}
+
+impl<'a> RuntimeFilter<'a> {
+    pub fn new(
+        input: BoxExecutor<'a>,
+        id: usize,
+        keys: &'a [Expr],
+        partition: Option<&'a (String, usize)>,
+        plan: usize,
+    ) -> Self {
+        Self {
+            input,
+            id,
+            keys,
+            partition,
+            pruned: None,
+            plan,
+        }
+    }
+
+    fn prune(&mut self, ctx: &ExecContext) -> bool {
+        if let Some(pruned) = self.pruned {
+            return pruned;
+        }
+        let (Some((table, key)), Some(ranges)) = (self.partition, ctx.key_ranges.get(&self.id))
+        else {
+            return false;
+        };
+        let pruned = match &ranges[*key] {
+            Some((min, max)) => ctx
+                .catalog
+                .table(table)
+                .is_some_and(|t| planner::outside_range(t, min, max)),
+            None => true,
+        };
+        self.pruned = Some(pruned);
+        pruned
+    }
+
+    fn passes(&self, ctx: &ExecContext, row: &Row) -> Result<bool, Error> {
+        let Some(filter) = ctx.runtime_filters.get(&self.id) else {
+            return Ok(true);
+        };
+        let mut key = Vec::with_capacity(self.keys.len());
+        for expr in self.keys {
+            let value = expr.eval(row)?;
+            if value.is_null() {
+                return Ok(false);
+            }
+            key.push(value);
+        }
+        Ok(filter.contains(&key))
+    }
+}
+
+impl Executor for RuntimeFilter<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        while !self.prune(ctx) {
+            let Some(row) = self.input.next(ctx)? else {
+                break;
+            };
+            if self.passes(ctx, &row)? {
+                return Ok(Some(row));
+            }
+            explain::add_removed(ctx, self.plan);
+        }
+        Ok(None)
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.input.record_id()
+    }
+
+    fn relation(&self) -> Option<&str> {
+        self.input.relation()
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
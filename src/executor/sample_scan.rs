@@ -0,0 +1,139 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use crate::catalog::{Dictionary, GeneratedColumn};
+use crate::disk::PageId;
+use crate::enum_type::EnumType;
+use crate::heap::RecordId;
+use crate::sql::ast::SampleMethod;
+use crate::storage::TableAccess;
+
+use super::scan::{decode_row, needed_mask, virtual_columns};
+use super::{Error, ExecContext, Executor, Row};
+
+// TABLESAMPLE のテーブルの走査
+//
+// SYSTEM はページを fraction の割合で選び、選んだページの行だけを読む。選ばなかったページの行は復元も見もしない。
+// BERNOULLI はすべてのページを読み、行を 1 つずつ fraction の割合で選ぶ。
+// 選ぶかどうかは種とページや行の位置のハッシュで決めるので、同じ種ならテーブルが変わらない限り同じ行を返す。
+// 種がなければ走査を始めるたびに決める
+pub struct SampleScan<'a> {
+    table: &'a str,
+    storage: Arc<dyn TableAccess>,
+    method: SampleMethod,
+    fraction: f64,
+    seed: u64,
+    needed: Option<Vec<bool>>,
+    // テーブルの列の数
+    width: usize,
+    virtual_columns: Vec<GeneratedColumn>,
+    dictionaries: Vec<Arc<Dictionary>>,
+    enums: Vec<Arc<EnumType>>,
+    // 読み残したページ。None ならまだ選んでいない
+    pages: Option<VecDeque<PageId>>,
+    // 今のページの、見えて選んだ行
+    tuples: VecDeque<(RecordId, Vec<u8>)>,
+    rid: Option<RecordId>,
+}
+
+impl<'a> SampleScan<'a> {
+    pub fn new(
+        ctx: &mut ExecContext,
+        name: &'a str,
+        method: SampleMethod,
+        fraction: f64,
+        seed: Option<f64>,
+        needed: Option<&[usize]>,
+    ) -> Result<Self, Error> {
+        let table = ctx
+            .catalog
+            .table(name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        if let Some(io) = ctx.io {
+            io.update(name, |io| io.seq_scans += 1);
+        }
+        let mut needed = needed_mask(table.columns.len(), needed);
+        Ok(Self {
+            table: name,
+            storage: table.storage.clone(),
+            method,
+            fraction,
+            seed: seed.map_or_else(|| RandomState::new().build_hasher().finish(), f64::to_bits),
+            width: table.columns.len(),
+            virtual_columns: virtual_columns(table, &mut needed),
+            dictionaries: table.dictionaries.clone(),
+            enums: table.enum_types(),
+            needed,
+            pages: None,
+            tuples: VecDeque::new(),
+            rid: None,
+        })
+    }
+
+    // 種と key で決まる、fraction の割合で真になる値
+    fn chosen(&self, key: impl Hash) -> bool {
+        let mut hasher = DefaultHasher::new();
+        (self.seed, key).hash(&mut hasher);
+        (hasher.finish() as f64) < self.fraction * u64::MAX as f64
+    }
+
+    // 次のページの行を読む。ページが残っていなければ false
+    fn next_page(&mut self, ctx: &mut ExecContext) -> Result<bool, Error> {
+        let storage = &self.storage;
+        if self.pages.is_none() {
+            let page_ids = ctx.count_reads(self.table, |ctx| storage.page_ids(ctx.bufmgr))?;
+            let pages = match self.method {
+                SampleMethod::System => page_ids.into_iter().filter(|id| self.chosen(id)).collect(),
+                SampleMethod::Bernoulli => page_ids.into(),
+            };
+            self.pages = Some(pages);
+        }
+        let Some(page_id) = self.pages.as_mut().unwrap().pop_front() else {
+            return Ok(false);
+        };
+        let tuples = ctx.count_reads(self.table, |ctx| storage.page_tuples(ctx.bufmgr, page_id))?;
+        self.tuples = tuples
+            .into_iter()
+            .filter(|(rid, header, _)| {
+                ctx.visible(header) && (self.method == SampleMethod::System || self.chosen(rid))
+            })
+            .map(|(rid, _, bytes)| (rid, bytes))
+            .collect();
+        Ok(true)
+    }
+}
+
+impl Executor for SampleScan<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        let (rid, bytes) = loop {
+            ctx.check_interrupt()?;
+            if let Some(tuple) = self.tuples.pop_front() {
+                break tuple;
+            }
+            if !self.next_page(ctx)? {
+                return Ok(None);
+            }
+        };
+        let row = decode_row(
+            &bytes,
+            self.width,
+            self.needed.as_deref(),
+            &self.virtual_columns,
+            &self.dictionaries,
+            &self.enums,
+            rid,
+        )?;
+        self.rid = Some(rid);
+        Ok(Some(row))
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.rid
+    }
+
+    fn relation(&self) -> Option<&str> {
+        Some(self.table)
+    }
+}
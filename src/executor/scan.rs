@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use crate::catalog::{self, Dictionary, GeneratedColumn, Table};
+use crate::collation::Collation;
+use crate::enum_type::EnumType;
+use crate::heap::RecordId;
+use crate::sql::ast::BinaryOp;
+use crate::storage::TableScan;
+use crate::tuple;
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::{Error, ExecContext, Executor, Row};
+
+pub struct SeqScan<'a> {
+    table: &'a str,
+    scan: Box<dyn TableScan>,
+    // 読む列。None ならすべての列
+    needed: Option<Vec<bool>>,
+    // テーブルの列の数
+    width: usize,
+    virtual_columns: Vec<GeneratedColumn>,
+    dictionaries: Vec<Arc<Dictionary>>,
+    enums: Vec<Arc<EnumType>>,
+    code_filters: Vec<CodeFilter>,
+    rid: Option<RecordId>,
+}
+
+// 辞書のある列と文字列の等号。番号で入っている値は、復元せずに番号で比べて合わない行を飛ばす。
+// 文字列のまま入っている値と NULL は上の Filter に任せる
+struct CodeFilter {
+    dictionary: Arc<Dictionary>,
+    value: String,
+    // value の番号。辞書にまだなければ None で、番号で入っている値はどれも等しくない
+    code: Option<u32>,
+}
+
+impl CodeFilter {
+    fn matches(&mut self, bytes: &[u8]) -> bool {
+        let Some(code) = tuple::column_code(bytes, self.dictionary.column) else {
+            return true;
+        };
+        // スキャンの途中で辞書に入ったかもしれない
+        if self.code.is_none() {
+            self.code = self.dictionary.code(&self.value);
+        }
+        self.code == Some(code)
+    }
+}
+
+fn code_filters(table: &Table, predicate: Option<&Expr>) -> Vec<CodeFilter> {
+    let Some(predicate) = predicate else {
+        return vec![];
+    };
+    if table.dictionaries.is_empty() {
+        return vec![];
+    }
+    predicate
+        .clone()
+        .split_conjunction()
+        .into_iter()
+        .filter_map(|conjunct| {
+            let Expr::Binary {
+                op: BinaryOp::Eq,
+                left,
+                right,
+            } = conjunct
+            else {
+                return None;
+            };
+            let (column, value) = match (*left, *right) {
+                (Expr::Column(i), Expr::Literal(Value::Text(s)))
+                | (Expr::Literal(Value::Text(s)), Expr::Column(i)) => (i, s),
+                _ => return None,
+            };
+            if table.columns[column].collation != Collation::Binary {
+                return None;
+            }
+            let dictionary = table.dictionaries.iter().find(|d| d.column == column)?;
+            Some(CodeFilter {
+                dictionary: dictionary.clone(),
+                code: dictionary.code(&value),
+                value,
+            })
+        })
+        .collect()
+}
+
+// 列の位置の一覧を、列ごとに読むかどうかに直す
+pub(super) fn needed_mask(width: usize, needed: Option<&[usize]>) -> Option<Vec<bool>> {
+    let needed = needed?;
+    let mut mask = vec![false; width];
+    for &i in needed {
+        mask[i] = true;
+    }
+    Some(mask)
+}
+
+// 読む列のうち VIRTUAL の生成列。needed があれば、その式が参照する列も読むようにする
+pub(super) fn virtual_columns(
+    table: &Table,
+    needed: &mut Option<Vec<bool>>,
+) -> Vec<GeneratedColumn> {
+    let columns: Vec<_> = table
+        .virtual_columns()
+        .filter(|g| needed.as_ref().is_none_or(|needed| needed[g.column]))
+        .cloned()
+        .collect();
+    if let Some(needed) = needed {
+        for i in columns.iter().flat_map(GeneratedColumn::dependencies) {
+            needed[i] = true;
+        }
+    }
+    columns
+}
+
+// 行を復元し、VIRTUAL の生成列を計算する。needed がある場合、読まない列は NULL にする。
+// width はテーブルの列の数で、ADD COLUMN より前に書いた版にない列も NULL にする
+pub(super) fn decode_row(
+    bytes: &[u8],
+    width: usize,
+    needed: Option<&[bool]>,
+    virtual_columns: &[GeneratedColumn],
+    dictionaries: &[Arc<Dictionary>],
+    enums: &[Arc<EnumType>],
+    rid: RecordId,
+) -> Result<Row, Error> {
+    let mut row = catalog::decode_row(dictionaries, enums, bytes, needed)
+        .ok_or(Error::CorruptedTuple(rid))?;
+    catalog::pad_row(&mut row, width);
+    catalog::fill_virtual(virtual_columns, &mut row)?;
+    Ok(row)
+}
+
+impl<'a> SeqScan<'a> {
+    pub fn new(
+        ctx: &mut ExecContext,
+        name: &'a str,
+        needed: Option<&[usize]>,
+        predicate: Option<&Expr>,
+    ) -> Result<Self, Error> {
+        let table = ctx
+            .catalog
+            .table(name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        if let Some(io) = ctx.io {
+            io.update(name, |io| io.seq_scans += 1);
+        }
+        let mut needed = needed_mask(table.columns.len(), needed);
+        Ok(Self {
+            table: name,
+            scan: table.storage.scan(),
+            width: table.columns.len(),
+            virtual_columns: virtual_columns(table, &mut needed),
+            dictionaries: table.dictionaries.clone(),
+            enums: table.enum_types(),
+            code_filters: code_filters(table, predicate),
+            needed,
+            rid: None,
+        })
+    }
+}
+
+impl Executor for SeqScan<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        let (rid, bytes) = loop {
+            ctx.check_interrupt()?;
+            let scan = &mut self.scan;
+            let Some((rid, header, bytes)) =
+                ctx.count_reads(self.table, |ctx| scan.next(ctx.bufmgr))?
+            else {
+                return Ok(None);
+            };
+            if ctx.visible(&header) && self.code_filters.iter_mut().all(|f| f.matches(&bytes)) {
+                break (rid, bytes);
+            }
+        };
+        let row = decode_row(
+            &bytes,
+            self.width,
+            self.needed.as_deref(),
+            &self.virtual_columns,
+            &self.dictionaries,
+            &self.enums,
+            rid,
+        )?;
+        self.rid = Some(rid);
+        Ok(Some(row))
+    }
+
+    fn record_id(&self) -> Option<RecordId> {
+        self.rid
+    }
+
+    fn relation(&self) -> Option<&str> {
+        Some(self.table)
+    }
+}
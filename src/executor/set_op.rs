@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use crate::heap::RecordId;
+use crate::sql::ast::SetOperator;
+
+use super::memory::MemoryReservation;
+use super::{row_size, BoxExecutor, Error, ExecContext, Executor, Row};
+
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+
+// 左右で同じ位置の列の型がそろっているかを調べる。型は NULL でない最初の値で決める
+struct ColumnTypes {
+    op: SetOperator,
+    types: [Vec<Option<String>>; 2],
+}
+
+impl ColumnTypes {
+    fn new(op: SetOperator) -> Self {
+        Self {
+            op,
+            types: [vec![], vec![]],
+        }
+    }
+
+    fn check(&mut self, side: usize, row: &Row) -> Result<(), Error> {
+        if self.types[side].len() < row.len() {
+            self.types[side].resize(row.len(), None);
+        }
+        for (i, value) in row.iter().enumerate() {
+            if value.is_null() || self.types[side][i].is_some() {
+                continue;
+            }
+            let ty = value.type_name();
+            if let Some(Some(other)) = self.types[1 - side].get(i) {
+                if *other != ty {
+                    let other = other.clone();
+                    let (left, right) = if side == LEFT {
+                        (ty, other)
+                    } else {
+                        (other, ty)
+                    };
+                    return Err(Error::SetOperationTypes {
+                        op: self.op,
+                        left,
+                        right,
+                    });
+                }
+            }
+            self.types[side][i] = Some(ty);
+        }
+        Ok(())
+    }
+}
+
+// 左の行をすべて返してから右の行を返す (UNION ALL)
+pub struct Append<'a> {
+    inputs: [BoxExecutor<'a>; 2],
+    side: usize,
+    types: ColumnTypes,
+}
+
+impl<'a> Append<'a> {
+    pub fn new(left: BoxExecutor<'a>, right: BoxExecutor<'a>) -> Self {
+        Self {
+            inputs: [left, right],
+            side: LEFT,
+            types: ColumnTypes::new(SetOperator::Union),
+        }
+    }
+}
+
+impl Executor for Append<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        while self.side <= RIGHT {
+            match self.inputs[self.side].next(ctx)? {
+                Some(row) => {
+                    self.types.check(self.side, &row)?;
+                    return Ok(Some(row));
+                }
+                None => self.side += 1,
+            }
+        }
+        Ok(None)
+    }
+
+    // パーティションに分けたテーブルを読むときは、直前に行を返した子の走査の位置
+    fn record_id(&self) -> Option<RecordId> {
+        self.inputs[self.side.min(RIGHT)].record_id()
+    }
+
+    fn relation(&self) -> Option<&str> {
+        self.inputs[self.side.min(RIGHT)].relation()
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.inputs[LEFT].close(ctx)?;
+        self.inputs[RIGHT].close(ctx)
+    }
+}
+
+// ハッシュによる INTERSECT と EXCEPT
+//
+// 右の行をすべて読んで同じ行の数を数えておき、左の行を順に調べる。ALL でなければ同じ行は一度だけ返す。
+// 数える表はメモリに置いたままにし、一時ファイルには書き出さない。
+pub struct HashSetOp<'a> {
+    left: BoxExecutor<'a>,
+    right: BoxExecutor<'a>,
+    op: SetOperator,
+    all: bool,
+    started: bool,
+    // 右にある行の数。EXCEPT では返した左の行も 0 で入れておく
+    counts: HashMap<Row, usize>,
+    types: ColumnTypes,
+    reservation: MemoryReservation,
+}
+
+impl<'a> HashSetOp<'a> {
+    pub fn new(
+        left: BoxExecutor<'a>,
+        right: BoxExecutor<'a>,
+        op: SetOperator,
+        all: bool,
+        reservation: MemoryReservation,
+    ) -> Self {
+        Self {
+            left,
+            right,
+            op,
+            all,
+            started: false,
+            counts: HashMap::new(),
+            types: ColumnTypes::new(op),
+            reservation,
+        }
+    }
+
+    fn start(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        while let Some(row) = self.right.next(ctx)? {
+            ctx.check_interrupt()?;
+            self.types.check(RIGHT, &row)?;
+            self.insert(row, 1);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, row: Row, n: usize) {
+        if let Some(count) = self.counts.get_mut(&row) {
+            *count += n;
+            return;
+        }
+        self.reservation.grow(row_size(&row));
+        self.counts.insert(row, n);
+    }
+
+    // 左の行を返すか
+    fn take(&mut self, row: &Row) -> bool {
+        let count = self.counts.get_mut(row);
+        match (self.op, count) {
+            (SetOperator::Intersect, Some(count)) if *count > 0 => {
+                *count = if self.all { *count - 1 } else { 0 };
+                true
+            }
+            (SetOperator::Intersect, _) => false,
+            (SetOperator::Except, Some(count)) if self.all && *count > 0 => {
+                *count -= 1;
+                false
+            }
+            (SetOperator::Except, Some(_)) => self.all,
+            (SetOperator::Except, None) => {
+                if !self.all {
+                    self.insert(row.clone(), 0);
+                }
+                true
+            }
+            (SetOperator::Union, _) => unreachable!(),
+        }
+    }
+}
+
+impl Executor for HashSetOp<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        if !self.started {
+            self.started = true;
+            self.start(ctx)?;
+        }
+        while let Some(row) = self.left.next(ctx)? {
+            ctx.check_interrupt()?;
+            self.types.check(LEFT, &row)?;
+            if self.take(&row) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.counts.clear();
+        self.reservation.free();
+        self.left.close(ctx)?;
+        self.right.close(ctx)
+    }
+}
@@ -0,0 +1,416 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::vec;
+
+use crate::btree::key::{self, KeyOrder};
+use crate::types::Value;
+
+use super::expr::Expr;
+use super::memory::MemoryReservation;
+use super::spill::{SpillFile, SpillReader};
+use super::{row_size, BoxExecutor, Error, ExecContext, Executor, Row};
+
+// 一度にマージする run の数の上限
+const MAX_FAN_IN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub expr: Expr,
+    pub asc: bool,
+    // NULL を NULL でない値より先にする。ORDER BY では指定がなければ降順のときだけ先
+    pub nulls_first: bool,
+}
+
+impl SortKey {
+    fn order(&self) -> KeyOrder {
+        KeyOrder {
+            asc: self.asc,
+            nulls_first: self.nulls_first,
+        }
+    }
+}
+
+// キーを前から順に比べる
+pub(crate) fn compare_keys(keys: &[SortKey], a: &[Value], b: &[Value]) -> Ordering {
+    for ((key, a), b) in keys.iter().zip(a).zip(b) {
+        let ord = match (a, b) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) if key.nulls_first => Ordering::Less,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) if key.nulls_first => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            _ if key.asc => a.sort_cmp(b),
+            _ => a.sort_cmp(b).reverse(),
+        };
+        if ord.is_ne() {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+// すべて昇順として前から順に比べる
+pub(crate) fn compare_values(a: &[Value], b: &[Value]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| a.sort_cmp(b))
+        .find(|ord| ord.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+type Compare<'a> = Box<dyn Fn(&[Value], &[Value]) -> Ordering + 'a>;
+
+// (キー, 行)
+type Entry = (Row, Row);
+
+// 外部マージソート
+//
+// work_mem か問い合わせの予算を超えるまでメモリに貯め、超えたら並べ替えて run として
+// 一時ファイルに書き出す。
+// run にはキーのあとに行を続けて書く。最後に run を MAX_FAN_IN 個ずつマージする。
+// 同じキーの行は入れた順を保つ。
+pub(crate) struct Sorter<'a> {
+    compare: Compare<'a>,
+    work_mem: usize,
+    key_len: usize,
+    buffer: Vec<Entry>,
+    size: usize,
+    reservation: MemoryReservation,
+    runs: Vec<SpillFile>,
+}
+
+impl<'a> Sorter<'a> {
+    pub fn new(
+        compare: impl Fn(&[Value], &[Value]) -> Ordering + 'a,
+        work_mem: usize,
+        reservation: MemoryReservation,
+    ) -> Self {
+        Self {
+            compare: Box::new(compare),
+            work_mem,
+            key_len: 0,
+            buffer: vec![],
+            size: 0,
+            reservation,
+            runs: vec![],
+        }
+    }
+
+    pub fn push(&mut self, key: Row, row: Row) -> io::Result<()> {
+        self.key_len = key.len();
+        let size = row_size(&key) + row_size(&row);
+        self.size += size;
+        self.buffer.push((key, row));
+        if self.size > self.work_mem || !self.reservation.can_grow(size) {
+            self.write_run()?;
+        } else {
+            self.reservation.grow(size);
+        }
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) {
+        // sort_by は安定なので同じキーの行は入れた順のまま
+        self.buffer.sort_by(|(a, _), (b, _)| (self.compare)(a, b));
+    }
+
+    fn write_run(&mut self) -> io::Result<()> {
+        self.sort_buffer();
+        let mut file = SpillFile::new()?;
+        for (mut key, row) in self.buffer.drain(..) {
+            key.extend(row);
+            file.write(&key)?;
+        }
+        self.runs.push(file);
+        self.size = 0;
+        self.reservation.free();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<Sorted<'a>> {
+        if self.runs.is_empty() {
+            self.sort_buffer();
+            return Ok(Sorted::Memory {
+                entries: self.buffer.into_iter(),
+                _reservation: self.reservation,
+            });
+        }
+        if !self.buffer.is_empty() {
+            self.write_run()?;
+        }
+        let mut runs = self.runs;
+        while runs.len() > MAX_FAN_IN {
+            // 隣り合う run をマージして、run の並び (入れた順) を保つ
+            let mut merged = vec![];
+            while !runs.is_empty() {
+                let n = runs.len().min(MAX_FAN_IN);
+                let mut merge = Merge::new(runs.drain(..n).collect(), self.key_len)?;
+                let mut file = SpillFile::new()?;
+                while let Some(row) = merge.next(&*self.compare)? {
+                    file.write(&row)?;
+                }
+                merged.push(file);
+            }
+            runs = merged;
+        }
+        Ok(Sorted::Merge {
+            merge: Merge::new(runs, self.key_len)?,
+            compare: self.compare,
+        })
+    }
+}
+
+// run の先頭の行を比べて最も小さいものから返す
+pub(crate) struct Merge {
+    readers: Vec<SpillReader>,
+    heads: Vec<Option<Row>>,
+    key_len: usize,
+}
+
+impl Merge {
+    fn new(runs: Vec<SpillFile>, key_len: usize) -> io::Result<Self> {
+        let mut readers = runs
+            .into_iter()
+            .map(SpillFile::into_reader)
+            .collect::<io::Result<Vec<_>>>()?;
+        let heads = readers
+            .iter_mut()
+            .map(SpillReader::next)
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            readers,
+            heads,
+            key_len,
+        })
+    }
+
+    fn next(
+        &mut self,
+        compare: &dyn Fn(&[Value], &[Value]) -> Ordering,
+    ) -> io::Result<Option<Row>> {
+        let mut min: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let Some(head) = head else {
+                continue;
+            };
+            // 同じキーなら前の run を先にする
+            let smaller = min.is_none_or(|j| {
+                let other = self.heads[j].as_ref().unwrap();
+                compare(&head[..self.key_len], &other[..self.key_len]).is_lt()
+            });
+            if smaller {
+                min = Some(i);
+            }
+        }
+        let Some(i) = min else {
+            return Ok(None);
+        };
+        let next = self.readers[i].next()?;
+        Ok(std::mem::replace(&mut self.heads[i], next))
+    }
+}
+
+pub(crate) enum Sorted<'a> {
+    Memory {
+        entries: vec::IntoIter<Entry>,
+        // 読み終わるまでメモリの予算を確保しておく
+        _reservation: MemoryReservation,
+    },
+    Merge {
+        merge: Merge,
+        compare: Compare<'a>,
+    },
+}
+
+impl Sorted<'_> {
+    pub fn next(&mut self) -> io::Result<Option<Entry>> {
+        match self {
+            Sorted::Memory { entries, .. } => Ok(entries.next()),
+            Sorted::Merge { merge, compare } => {
+                let key_len = merge.key_len;
+                Ok(merge.next(&**compare)?.map(|mut key| {
+                    let row = key.split_off(key_len);
+                    (key, row)
+                }))
+            }
+        }
+    }
+}
+
+// 上位 n 行の候補。キーが大きく後から入った行ほど大きいので、ヒープの先頭から捨てる
+struct TopEntry<'a> {
+    keys: &'a [SortKey],
+    key: Row,
+    seq: usize,
+    row: Row,
+}
+
+impl TopEntry<'_> {
+    fn size(&self) -> usize {
+        row_size(&self.key) + row_size(&self.row)
+    }
+}
+
+impl Ord for TopEntry<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_keys(self.keys, &self.key, &other.key).then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl PartialOrd for TopEntry<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TopEntry<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for TopEntry<'_> {}
+
+// 入力をすべて読み込んでから並べ替えて返す。work_mem を超えたら一時ファイルを使う
+//
+// top_n があれば先頭の top_n 行だけを返す。大きさ top_n のヒープで候補だけを残し、
+// ヒープが work_mem か問い合わせの予算に収まらなくなったら外部ソートに切り替える。
+// キーが 2 つ以上なら、値を向きと NULL の位置ごと key::encode_ordered で 1 つのバイト列にして、
+// バイト列どうしで比べる
+pub struct Sort<'a> {
+    input: BoxExecutor<'a>,
+    keys: &'a [SortKey],
+    // バイト列にするときの各キーの向き
+    orders: Option<Vec<KeyOrder>>,
+    // eval_key の返すキーの並べ方
+    compared: &'a [SortKey],
+    top_n: Option<usize>,
+    sorted: Option<Sorted<'a>>,
+}
+
+// バイト列にしたキーは 1 つの値として昇順に比べる
+const ENCODED_KEY: &[SortKey] = &[SortKey {
+    expr: Expr::Column(0),
+    asc: true,
+    nulls_first: false,
+}];
+
+impl<'a> Sort<'a> {
+    pub fn new(input: BoxExecutor<'a>, keys: &'a [SortKey], top_n: Option<usize>) -> Self {
+        Self {
+            input,
+            keys,
+            orders: (keys.len() > 1).then(|| keys.iter().map(SortKey::order).collect()),
+            compared: if keys.len() > 1 { ENCODED_KEY } else { keys },
+            top_n,
+            sorted: None,
+        }
+    }
+
+    fn eval_key(&self, row: &Row) -> Result<Row, Error> {
+        let values = self
+            .keys
+            .iter()
+            .map(|key| key.expr.eval(row))
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some(orders) = &self.orders else {
+            return Ok(values);
+        };
+        let mut bytes = vec![];
+        key::encode_ordered(&values, orders, &mut bytes);
+        Ok(vec![Value::Blob(bytes.into())])
+    }
+
+    fn sorter(&self, ctx: &ExecContext) -> Sorter<'a> {
+        let keys = self.compared;
+        Sorter::new(
+            move |a, b| compare_keys(keys, a, b),
+            ctx.work_mem,
+            ctx.memory.reservation(),
+        )
+    }
+
+    fn sort(&mut self, ctx: &mut ExecContext) -> Result<Sorted<'a>, Error> {
+        let mut sorter = match self.top_n {
+            Some(n) => match self.top_n(ctx, n)? {
+                Ok(sorted) => return Ok(sorted),
+                Err(sorter) => sorter,
+            },
+            None => self.sorter(ctx),
+        };
+        while let Some(row) = self.input.next(ctx)? {
+            let key = self.eval_key(&row)?;
+            sorter.push(key, row)?;
+        }
+        Ok(sorter.finish()?)
+    }
+
+    // ヒープで上位 n 行を求める。メモリに収まらなくなったら、それまでの行を入れた Sorter を返す
+    fn top_n(
+        &mut self,
+        ctx: &mut ExecContext,
+        n: usize,
+    ) -> Result<Result<Sorted<'a>, Sorter<'a>>, Error> {
+        let mut heap = BinaryHeap::new();
+        let mut reservation = ctx.memory.reservation();
+        let mut seq = 0;
+        while let Some(row) = self.input.next(ctx)? {
+            ctx.check_interrupt()?;
+            let entry = TopEntry {
+                keys: self.compared,
+                key: self.eval_key(&row)?,
+                seq,
+                row,
+            };
+            seq += 1;
+            let size = entry.size();
+            if heap.len() == n {
+                if let Some(mut top) = heap.peek_mut() {
+                    if entry < *top {
+                        // 小さくなった分は返さず、大きくなった分だけ申告する
+                        reservation.grow(size.saturating_sub(top.size()));
+                        *top = entry;
+                    }
+                }
+                continue;
+            }
+            if reservation.size() + size > ctx.work_mem || !reservation.can_grow(size) {
+                let mut entries = heap.into_vec();
+                entries.push(entry);
+                entries.sort_by_key(|entry| entry.seq);
+                let mut sorter = self.sorter(ctx);
+                for entry in entries {
+                    sorter.push(entry.key, entry.row)?;
+                }
+                return Ok(Err(sorter));
+            }
+            reservation.grow(size);
+            heap.push(entry);
+        }
+        let entries = heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|entry| (entry.key, entry.row))
+            .collect::<Vec<_>>();
+        Ok(Ok(Sorted::Memory {
+            entries: entries.into_iter(),
+            _reservation: reservation,
+        }))
+    }
+}
+
+impl Executor for Sort<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        ctx.check_interrupt()?;
+        if self.sorted.is_none() {
+            self.sorted = Some(self.sort(ctx)?);
+        }
+        Ok(self.sorted.as_mut().unwrap().next()?.map(|(_, row)| row))
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.sorted = None;
+        self.input.close(ctx)
+    }
+}
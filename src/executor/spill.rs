@@ -0,0 +1,204 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crate::enum_type::EnumType;
+use crate::tuple;
+use crate::types::Value;
+
+use super::Row;
+
+// 一度に読み込むバイト数
+const READ_SIZE: usize = 8192;
+
+thread_local! {
+    // このスレッドで作った一時ファイルの数と、書いたバイト数
+    static SPILLED: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+pub(super) fn spilled() -> (u64, u64) {
+    SPILLED.get()
+}
+
+// メモリに収まらない行を書き出す一時ファイル
+//
+// 行ごとに | 長さ (4) | tuple::encode した値 | を並べる。
+// ファイルは作成直後に削除するので、閉じれば領域は解放される。
+pub struct SpillFile {
+    writer: BufWriter<File>,
+    size: u64,
+    // 書いた列挙型の値の型。読むときに番号から型を探す
+    enums: Vec<Arc<EnumType>>,
+}
+
+impl SpillFile {
+    // wasm32 には一時ファイルを置くところがないので、書き出す演算子はエラーにする
+    #[cfg(target_arch = "wasm32")]
+    pub fn new() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "temporary files are not available on wasm32",
+        ))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new() -> io::Result<Self> {
+        use std::fs::OpenOptions;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("rdbms-training-spill-{}-{}", std::process::id(), n));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        std::fs::remove_file(&path)?;
+        let (files, bytes) = SPILLED.get();
+        SPILLED.set((files + 1, bytes));
+        Ok(Self {
+            writer: BufWriter::new(file),
+            size: 0,
+            enums: vec![],
+        })
+    }
+
+    pub fn write(&mut self, row: &Row) -> io::Result<()> {
+        row.iter().for_each(|value| self.add_enums(value));
+        let mut bytes = vec![];
+        tuple::encode(row, &mut bytes);
+        self.writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&bytes)?;
+        self.size += 4 + bytes.len() as u64;
+        let (files, written) = SPILLED.get();
+        SPILLED.set((files, written + 4 + bytes.len() as u64));
+        Ok(())
+    }
+
+    fn add_enums(&mut self, value: &Value) {
+        match value {
+            Value::Enum(e) if !self.enums.contains(e.ty()) => self.enums.push(e.ty().clone()),
+            Value::Array(items) => items.iter().for_each(|item| self.add_enums(item)),
+            _ => {}
+        }
+    }
+
+    // 書き込んだバイト数
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn into_reader(mut self) -> io::Result<SpillReader> {
+        self.reader()
+    }
+
+    // 先頭から読むリーダーを作る。いくつ作ってもよいが、作ったあとは書き込まない
+    pub fn reader(&mut self) -> io::Result<SpillReader> {
+        self.writer.flush()?;
+        Ok(SpillReader {
+            file: self.writer.get_ref().try_clone()?,
+            buf: vec![],
+            pos: 0,
+            offset: 0,
+            enums: self.enums.clone(),
+        })
+    }
+}
+
+// 複製したファイルはオフセットを共有するので、読み込むたびに自分の位置へ移動する
+pub struct SpillReader {
+    file: File,
+    buf: Vec<u8>,
+    pos: usize,
+    // buf の末尾のファイル上の位置
+    offset: u64,
+    enums: Vec<Arc<EnumType>>,
+}
+
+impl SpillReader {
+    pub fn next(&mut self) -> io::Result<Option<Row>> {
+        let mut len = [0; 4];
+        if !self.read_exact(&mut len)? {
+            return Ok(None);
+        }
+        let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+        if !self.read_exact(&mut bytes)? {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let row = tuple::decode(&bytes, &self.enums)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupted spill file"))?;
+        Ok(Some(row))
+    }
+
+    // ファイルの終わりに達して out を埋められなければ false
+    fn read_exact(&mut self, out: &mut [u8]) -> io::Result<bool> {
+        let mut filled = 0;
+        while filled < out.len() {
+            if self.pos == self.buf.len() {
+                self.buf.resize(READ_SIZE, 0);
+                self.file.seek(SeekFrom::Start(self.offset))?;
+                let n = self.file.read(&mut self.buf)?;
+                self.buf.truncate(n);
+                self.pos = 0;
+                self.offset += n as u64;
+                if n == 0 {
+                    return Ok(false);
+                }
+            }
+            let n = (out.len() - filled).min(self.buf.len() - self.pos);
+            out[filled..filled + n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+            filled += n;
+            self.pos += n;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Value;
+
+    #[test]
+    fn test_spill_file() {
+        let rows = vec![
+            vec![Value::Integer(1), Value::Text("abc".to_string())],
+            vec![],
+            vec![Value::Null, Value::Boolean(true)],
+        ];
+        let mut file = SpillFile::new().unwrap();
+        for row in &rows {
+            file.write(row).unwrap();
+        }
+        assert!(file.size() > 0);
+        let mut reader = file.into_reader().unwrap();
+        let mut read = vec![];
+        while let Some(row) = reader.next().unwrap() {
+            read.push(row);
+        }
+        assert_eq!(read, rows);
+    }
+
+    #[test]
+    fn test_spill_readers() {
+        let rows = (0..1000)
+            .map(|i| vec![Value::Integer(i), Value::Text("x".repeat(20))])
+            .collect::<Vec<_>>();
+        let mut file = SpillFile::new().unwrap();
+        for row in &rows {
+            file.write(row).unwrap();
+        }
+        // 2 つのリーダーを交互に進めても、それぞれ先頭から読める
+        let mut first = file.reader().unwrap();
+        let mut second = file.reader().unwrap();
+        for row in &rows {
+            assert_eq!(first.next().unwrap().as_ref(), Some(row));
+            assert_eq!(second.next().unwrap().as_ref(), Some(row));
+        }
+        assert_eq!(first.next().unwrap(), None);
+        assert_eq!(second.next().unwrap(), None);
+    }
+}
@@ -0,0 +1,31 @@
+use super::{Error, ExecContext, Executor, Row};
+use crate::stats::StatsView;
+
+// stats スキーマの表を、開いたときに集めた値で返す
+pub struct StatsScan {
+    rows: Vec<Row>,
+    next: usize,
+}
+
+impl StatsScan {
+    pub fn new(view: StatsView, ctx: &mut ExecContext) -> Result<Self, Error> {
+        Ok(Self {
+            rows: view.rows(ctx)?,
+            next: 0,
+        })
+    }
+}
+
+impl Executor for StatsScan {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        ctx.check_interrupt()?;
+        let row = self.rows.get(self.next).cloned();
+        self.next += 1;
+        Ok(row)
+    }
+
+    fn rescan(&mut self, _ctx: &mut ExecContext) -> Result<bool, Error> {
+        self.next = 0;
+        Ok(true)
+    }
+}
@@ -0,0 +1,329 @@
+use crate::catalog::Catalog;
+use crate::decimal::MAX_PRECISION;
+use crate::sql::ast::{BinaryOp, UnaryOp};
+use crate::types::{DataType, Value};
+
+use super::expr::{Expr, Function};
+use super::{AggregateCall, AggregateFunction, Error, PlanNode, WindowFunction};
+
+// 実行する前に、計画の出力の列と式の値の型を決める
+//
+// 型は式の形と入力の列の型だけから決め、値は見ない。NULL の定数や型のわからない列を使う式など、
+// 決められないものは None にする。CTE と stats スキーマの表の列の型もわからないものとする
+impl PlanNode {
+    // columns と同じ順の、出力する列の型
+    pub fn column_types(&self, catalog: &Catalog) -> Result<Vec<Option<DataType>>, Error> {
+        match self {
+            PlanNode::SeqScan { table, .. }
+            | PlanNode::SampleScan { table, .. }
+            | PlanNode::Gather { table, .. }
+            | PlanNode::IndexScan { table, .. }
+            | PlanNode::FulltextScan { table, .. }
+            | PlanNode::RTreeScan { table, .. } => table_types(catalog, table),
+            // 列ごとに、最初の NULL でない値の型
+            PlanNode::Values { rows } => {
+                let width = rows.first().map_or(0, |row| row.len());
+                Ok((0..width)
+                    .map(|i| rows.iter().find_map(|row| row[i].data_type()))
+                    .collect())
+            }
+            PlanNode::Filter { input, .. }
+            | PlanNode::RuntimeFilter { input, .. }
+            | PlanNode::PartitionPrune { input, .. }
+            | PlanNode::AsOf { input, .. }
+            | PlanNode::Sort { input, .. }
+            | PlanNode::Unique { input }
+            | PlanNode::Limit { input, .. }
+            | PlanNode::LockRows { input, .. }
+            | PlanNode::Materialize { input }
+            | PlanNode::With { input, .. } => input.column_types(catalog),
+            PlanNode::RecursiveUnion { anchor, .. } => anchor.column_types(catalog),
+            PlanNode::Append { left, right } | PlanNode::HashSetOp { left, right, .. } => {
+                let right = right.column_types(catalog)?;
+                Ok(left
+                    .column_types(catalog)?
+                    .into_iter()
+                    .zip(right)
+                    .map(|(l, r)| l.or(r))
+                    .collect())
+            }
+            PlanNode::CteScan { columns, .. } => Ok(vec![None; columns.len()]),
+            PlanNode::StatsScan { view } => Ok(vec![None; view.columns().len()]),
+            PlanNode::Projection { input, exprs, .. } => {
+                let input = input.column_types(catalog)?;
+                Ok(exprs.iter().map(|expr| expr.data_type(&input)).collect())
+            }
+            PlanNode::ExplainAnalyze { .. } => Ok(vec![Some(DataType::Text)]),
+            PlanNode::Insert {
+                table,
+                returning: true,
+                ..
+            }
+            | PlanNode::Update {
+                table,
+                returning: true,
+                ..
+            }
+            | PlanNode::Delete {
+                table,
+                returning: true,
+                ..
+            } => table_types(catalog, table),
+            PlanNode::Insert { .. } | PlanNode::Update { .. } | PlanNode::Delete { .. } => {
+                Ok(vec![Some(DataType::Integer)])
+            }
+            PlanNode::HashAggregate {
+                input,
+                group_by,
+                aggregates,
+            }
+            | PlanNode::GroupAggregate {
+                input,
+                group_by,
+                aggregates,
+            }
+            | PlanNode::ParallelAggregate {
+                input,
+                group_by,
+                aggregates,
+            } => {
+                let input = input.column_types(catalog)?;
+                let mut types = group_by
+                    .iter()
+                    .map(|expr| expr.data_type(&input))
+                    .collect::<Vec<_>>();
+                types.extend(aggregates.iter().map(|call| call.data_type(&input)));
+                Ok(types)
+            }
+            PlanNode::GroupingSets {
+                input,
+                group_by,
+                aggregates,
+                ..
+            } => {
+                let input = input.column_types(catalog)?;
+                let mut types = group_by
+                    .iter()
+                    .map(|expr| expr.data_type(&input))
+                    .collect::<Vec<_>>();
+                types.push(Some(DataType::Integer));
+                types.extend(aggregates.iter().map(|call| call.data_type(&input)));
+                Ok(types)
+            }
+            PlanNode::Window { input, calls, .. } => {
+                let mut types = input.column_types(catalog)?;
+                for call in calls {
+                    let arg = call.args.first().and_then(|arg| arg.data_type(&types));
+                    let ty = match call.func {
+                        WindowFunction::RowNumber
+                        | WindowFunction::Rank
+                        | WindowFunction::DenseRank => Some(DataType::Integer),
+                        WindowFunction::Lag | WindowFunction::Lead => arg,
+                        WindowFunction::Aggregate(func) => aggregate_type(func, arg),
+                    };
+                    types.push(ty);
+                }
+                Ok(types)
+            }
+            PlanNode::ProjectSet { input, exprs } => {
+                let mut types = input.column_types(catalog)?;
+                let elements = exprs
+                    .iter()
+                    .map(|expr| expr.data_type(&types).and_then(DataType::element))
+                    .collect::<Vec<_>>();
+                types.extend(elements);
+                Ok(types)
+            }
+            PlanNode::IndexJoin { left, table, .. } => {
+                let mut types = left.column_types(catalog)?;
+                types.extend(table_types(catalog, table)?);
+                Ok(types)
+            }
+            PlanNode::NestedLoopJoin {
+                left, join_type, ..
+            }
+            | PlanNode::HashJoin {
+                left, join_type, ..
+            }
+            | PlanNode::LateralJoin {
+                left, join_type, ..
+            } if join_type.is_semi() => left.column_types(catalog),
+            PlanNode::NestedLoopJoin { left, right, .. }
+            | PlanNode::HashJoin { left, right, .. }
+            | PlanNode::LateralJoin { left, right, .. }
+            | PlanNode::MergeJoin { left, right, .. } => {
+                let mut types = left.column_types(catalog)?;
+                types.extend(right.column_types(catalog)?);
+                Ok(types)
+            }
+        }
+    }
+}
+
+fn table_types(catalog: &Catalog, table: &str) -> Result<Vec<Option<DataType>>, Error> {
+    let table = catalog
+        .table(table)
+        .ok_or_else(|| Error::TableNotFound(table.to_string()))?;
+    Ok(table
+        .columns
+        .iter()
+        .map(|c| Some(c.data_type.clone()))
+        .collect())
+}
+
+impl Expr {
+    // input の型の列を持つ行に対して評価したときの値の型
+    pub fn data_type(&self, input: &[Option<DataType>]) -> Option<DataType> {
+        match self {
+            Expr::Column(i) => input.get(*i).cloned().flatten(),
+            Expr::Literal(value) => value.data_type(),
+            Expr::Parameter { ty, .. } => ty.clone(),
+            Expr::Binary { op, left, right } => {
+                binary_type(*op, left.data_type(input), right.data_type(input))
+            }
+            Expr::Unary {
+                op: UnaryOp::Not, ..
+            }
+            | Expr::IsNull { .. }
+            | Expr::InList { .. } => Some(DataType::Boolean),
+            Expr::Unary { expr, .. } => expr.data_type(input),
+            Expr::Function { func, args } => {
+                let arg = |i: usize| args.get(i).and_then(|arg| arg.data_type(input));
+                match func {
+                    Function::Upper
+                    | Function::Lower
+                    | Function::Substr
+                    | Function::Trim
+                    | Function::Ltrim
+                    | Function::Rtrim
+                    | Function::Replace
+                    | Function::Concat => Some(DataType::Text),
+                    Function::Length
+                    | Function::Instr
+                    | Function::DateDiff
+                    | Function::Cardinality => Some(DataType::Integer),
+                    Function::Abs
+                    | Function::Round
+                    | Function::Ceil
+                    | Function::Floor
+                    | Function::Mod => arg(0),
+                    Function::Power => match (arg(0), arg(1)) {
+                        (Some(DataType::Decimal { .. }), _) => arg(0),
+                        (_, Some(DataType::Decimal { .. })) => arg(1),
+                        _ => Some(DataType::Real),
+                    },
+                    Function::Coalesce => (0..args.len()).find_map(arg),
+                    Function::Now | Function::DateTrunc | Function::DateAdd => {
+                        Some(DataType::Timestamp)
+                    }
+                    Function::CurrentDate => Some(DataType::Date),
+                    // 秒はマイクロ秒までの小数
+                    Function::Extract => Some(DataType::Decimal {
+                        precision: MAX_PRECISION,
+                        scale: 6,
+                    }),
+                    Function::Like
+                    | Function::ILike
+                    | Function::Regexp
+                    | Function::Any
+                    | Function::All => Some(DataType::Boolean),
+                    // 型の名前が定数なら、その型
+                    Function::Cast => match args.get(1) {
+                        Some(Expr::Literal(Value::Text(name))) => DataType::parse(name),
+                        _ => None,
+                    },
+                    Function::CollationKey => None,
+                    Function::JsonExtract => Some(DataType::Json),
+                    Function::Match => Some(DataType::Real),
+                    Function::UuidV4 | Function::UuidV7 => Some(DataType::Uuid),
+                    Function::Array => {
+                        DataType::array((0..args.len()).find_map(arg).unwrap_or(DataType::Text))
+                    }
+                    Function::Subscript => arg(0).and_then(DataType::element),
+                }
+            }
+            Expr::UserFunction { func, .. } => Some(func.return_type()),
+        }
+    }
+}
+
+impl AggregateCall {
+    pub fn data_type(&self, input: &[Option<DataType>]) -> Option<DataType> {
+        aggregate_type(
+            self.func,
+            self.arg.as_ref().and_then(|arg| arg.data_type(input)),
+        )
+    }
+}
+
+// 引数の型が arg の集約関数の結果の型。整数の平均は浮動小数点数になる
+fn aggregate_type(func: AggregateFunction, arg: Option<DataType>) -> Option<DataType> {
+    match func {
+        AggregateFunction::Avg if matches!(arg, Some(DataType::Integer | DataType::BigInt)) => {
+            Some(DataType::Real)
+        }
+        AggregateFunction::Count | AggregateFunction::ApproxCountDistinct => {
+            Some(DataType::Integer)
+        }
+        AggregateFunction::ApproxPercentile => Some(DataType::Real),
+        AggregateFunction::Sum
+        | AggregateFunction::Avg
+        | AggregateFunction::Min
+        | AggregateFunction::Max => arg,
+    }
+}
+
+// 片側の型がわからなければ、両側が同じ型だとみなす
+fn binary_type(op: BinaryOp, left: Option<DataType>, right: Option<DataType>) -> Option<DataType> {
+    use DataType::*;
+    match op {
+        BinaryOp::Or
+        | BinaryOp::And
+        | BinaryOp::Eq
+        | BinaryOp::NotEq
+        | BinaryOp::Lt
+        | BinaryOp::LtEq
+        | BinaryOp::Gt
+        | BinaryOp::GtEq
+        | BinaryOp::IsDistinctFrom
+        | BinaryOp::IsNotDistinctFrom
+        | BinaryOp::Overlaps
+        | BinaryOp::Contains
+        | BinaryOp::ContainedBy => Some(Boolean),
+        BinaryOp::Distance => Some(Real),
+        BinaryOp::JsonGet => Some(Json),
+        BinaryOp::JsonGetText => Some(Text),
+        BinaryOp::Concat => match (&left, &right) {
+            (Some(Array(_)), _) => left,
+            (_, Some(Array(_))) => right,
+            _ => Some(Text),
+        },
+        BinaryOp::Plus
+        | BinaryOp::Minus
+        | BinaryOp::Multiply
+        | BinaryOp::Divide
+        | BinaryOp::Modulo => {
+            let (left, right) = match (left, right) {
+                (Some(left), Some(right)) => (left, right),
+                (Some(ty), None) | (None, Some(ty)) => (ty.clone(), ty),
+                (None, None) => return None,
+            };
+            let ty = match (&left, &right) {
+                (Real, _) | (_, Real) => Real,
+                (Decimal { .. }, _) => left,
+                (_, Decimal { .. }) => right,
+                (BigInt, Integer | BigInt) | (Integer, BigInt) => BigInt,
+                (Integer, Integer) => Integer,
+                (Interval, Interval) => Interval,
+                (Date | Timestamp, Interval) | (Interval, Date | Timestamp) => Timestamp,
+                (Time, Interval) | (Interval, Time) => Time,
+                (Interval, _) | (_, Interval) => Interval,
+                (Date, Date) => Integer,
+                (Date | Timestamp, Date | Timestamp) => Interval,
+                (Date, Integer | BigInt) | (Integer | BigInt, Date) => Date,
+                _ => return None,
+            };
+            Some(ty)
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use super::{BoxExecutor, Error, ExecContext, Executor, Row};
+
+// 直前の行と同じ行を読み飛ばす。入力は同じ行が隣り合うように並んでいる必要がある
+pub struct Unique<'a> {
+    input: BoxExecutor<'a>,
+    last: Option<Row>,
+}
+
+impl<'a> Unique<'a> {
+    pub fn new(input: BoxExecutor<'a>) -> Self {
+        Self { input, last: None }
+    }
+}
+
+impl Executor for Unique<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        while let Some(row) = self.input.next(ctx)? {
+            // NULL どうしも同じ値として扱う
+            if self.last.as_ref() != Some(&row) {
+                self.last = Some(row.clone());
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.input.close(ctx)
+    }
+}
@@ -0,0 +1,28 @@
+use super::{Error, ExecContext, Executor, Row};
+
+// 定数の行を順に返す
+pub struct Values<'a> {
+    source: &'a [Row],
+    rows: std::slice::Iter<'a, Row>,
+}
+
+impl<'a> Values<'a> {
+    pub fn new(rows: &'a [Row]) -> Self {
+        Self {
+            source: rows,
+            rows: rows.iter(),
+        }
+    }
+}
+
+impl Executor for Values<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        ctx.check_interrupt()?;
+        Ok(self.rows.next().cloned())
+    }
+
+    fn rescan(&mut self, _ctx: &mut ExecContext) -> Result<bool, Error> {
+        self.rows = self.source.iter();
+        Ok(true)
+    }
+}
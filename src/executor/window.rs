@@ -0,0 +1,384 @@
+use std::vec;
+
+use crate::sql::ast::{FrameBound, FrameUnits, WindowFrame};
+use crate::types::Value;
+
+use super::aggregate::{AggregateCall, AggregateFunction};
+use super::expr::Expr;
+use super::memory::MemoryReservation;
+use super::sort::{compare_keys, SortKey};
+use super::spill::{SpillFile, SpillReader};
+use super::{row_size, BoxExecutor, Error, ExecContext, Executor, Row};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Lag,
+    Lead,
+    Aggregate(AggregateFunction),
+}
+
+impl WindowFunction {
+    pub fn lookup(name: &str) -> Option<Self> {
+        let func = match name {
+            "row_number" => WindowFunction::RowNumber,
+            "rank" => WindowFunction::Rank,
+            "dense_rank" => WindowFunction::DenseRank,
+            "lag" => WindowFunction::Lag,
+            "lead" => WindowFunction::Lead,
+            name => WindowFunction::Aggregate(AggregateFunction::lookup(name)?),
+        };
+        Some(func)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowFunction::RowNumber => "row_number",
+            WindowFunction::Rank => "rank",
+            WindowFunction::DenseRank => "dense_rank",
+            WindowFunction::Lag => "lag",
+            WindowFunction::Lead => "lead",
+            WindowFunction::Aggregate(func) => func.name(),
+        }
+    }
+
+    pub fn accepts(self, num_args: usize) -> bool {
+        match self {
+            WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank => {
+                num_args == 0
+            }
+            WindowFunction::Lag | WindowFunction::Lead => (1..=3).contains(&num_args),
+            // 引数のない COUNT は COUNT(*)
+            WindowFunction::Aggregate(AggregateFunction::Count) => num_args <= 1,
+            // 分位を渡す引数がない
+            WindowFunction::Aggregate(AggregateFunction::ApproxPercentile) => false,
+            WindowFunction::Aggregate(_) => num_args == 1,
+        }
+    }
+}
+
+// ウィンドウ関数の呼び出し。frame は集約関数でだけ使う
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowCall {
+    pub func: WindowFunction,
+    pub args: Vec<Expr>,
+    pub frame: WindowFrame,
+}
+
+// パーティションの行。work_mem を超えたら一時ファイルに移す
+struct RowStore {
+    rows: Vec<Row>,
+    spill: Option<SpillFile>,
+}
+
+enum StoredRows {
+    Memory(vec::IntoIter<Row>),
+    File(SpillReader),
+}
+
+impl StoredRows {
+    fn next(&mut self) -> Result<Option<Row>, Error> {
+        match self {
+            StoredRows::Memory(rows) => Ok(rows.next()),
+            StoredRows::File(reader) => Ok(reader.next()?),
+        }
+    }
+}
+
+// パーティションを読み終わるまでに使う、行以外の値
+struct Partition {
+    // ORDER BY の値
+    keys: Vec<Row>,
+    // 行ごとにすべての呼び出しの引数を並べたもの
+    args: Vec<Row>,
+    store: RowStore,
+    size: usize,
+}
+
+// ウィンドウ関数の計算
+//
+// 入力は partition_by、order_by の順に並んでいなければならない。パーティションを 1 つずつ
+// 読み込み、各行のあとに calls の結果を並べて返す。行そのものは大きければ一時ファイルに
+// 書き出し、関数の計算には ORDER BY と引数の値だけを使う。
+pub struct Window<'a> {
+    input: BoxExecutor<'a>,
+    partition_by: &'a [Expr],
+    order_by: &'a [SortKey],
+    calls: &'a [WindowCall],
+    reservation: MemoryReservation,
+    // 次のパーティションの最初の行とそのパーティションキー
+    pending: Option<(Row, Row)>,
+    done: bool,
+    output: Option<(StoredRows, vec::IntoIter<Row>)>,
+}
+
+impl<'a> Window<'a> {
+    pub fn new(
+        input: BoxExecutor<'a>,
+        partition_by: &'a [Expr],
+        order_by: &'a [SortKey],
+        calls: &'a [WindowCall],
+        reservation: MemoryReservation,
+    ) -> Self {
+        Self {
+            input,
+            partition_by,
+            order_by,
+            calls,
+            reservation,
+            pending: None,
+            done: false,
+            output: None,
+        }
+    }
+
+    fn eval_all<'e>(exprs: impl IntoIterator<Item = &'e Expr>, row: &Row) -> Result<Row, Error> {
+        exprs.into_iter().map(|expr| expr.eval(row)).collect()
+    }
+
+    // 次のパーティションを読み込む。入力が尽きていれば None
+    fn load_partition(&mut self, ctx: &mut ExecContext) -> Result<Option<Partition>, Error> {
+        let mut partition = Partition {
+            keys: vec![],
+            args: vec![],
+            store: RowStore {
+                rows: vec![],
+                spill: None,
+            },
+            size: 0,
+        };
+        self.reservation.free();
+        let mut current = None;
+        loop {
+            let (row, key) = match self.pending.take() {
+                Some(pending) => pending,
+                None if self.done => break,
+                None => match self.input.next(ctx)? {
+                    Some(row) => {
+                        let key = Self::eval_all(self.partition_by, &row)?;
+                        (row, key)
+                    }
+                    None => {
+                        self.done = true;
+                        break;
+                    }
+                },
+            };
+            match &current {
+                Some(current) if *current != key => {
+                    self.pending = Some((row, key));
+                    break;
+                }
+                Some(_) => {}
+                None => current = Some(key),
+            }
+            partition
+                .keys
+                .push(Self::eval_all(self.order_by.iter().map(|k| &k.expr), &row)?);
+            partition.args.push(Self::eval_all(
+                self.calls.iter().flat_map(|call| &call.args),
+                &row,
+            )?);
+            self.push_row(ctx, &mut partition, row)?;
+        }
+        Ok((!partition.keys.is_empty()).then_some(partition))
+    }
+
+    fn push_row(
+        &mut self,
+        ctx: &mut ExecContext,
+        partition: &mut Partition,
+        row: Row,
+    ) -> Result<(), Error> {
+        let store = &mut partition.store;
+        if let Some(file) = &mut store.spill {
+            file.write(&row)?;
+            return Ok(());
+        }
+        let size = row_size(&row);
+        partition.size += size;
+        store.rows.push(row);
+        if partition.size > ctx.work_mem || !self.reservation.can_grow(size) {
+            let mut file = SpillFile::new()?;
+            for row in store.rows.drain(..) {
+                file.write(&row)?;
+            }
+            store.spill = Some(file);
+            self.reservation.free();
+        } else {
+            self.reservation.grow(size);
+        }
+        Ok(())
+    }
+
+    // パーティションの各行の関数の結果
+    fn compute(&self, partition: &Partition) -> Result<Vec<Row>, Error> {
+        let n = partition.keys.len();
+        // ORDER BY の値が等しい行 (peer) の範囲
+        let mut peer_start = vec![0; n];
+        let mut peer_end = vec![0; n];
+        let mut group = vec![0; n];
+        for i in 1..n {
+            let same =
+                compare_keys(self.order_by, &partition.keys[i - 1], &partition.keys[i]).is_eq();
+            peer_start[i] = if same { peer_start[i - 1] } else { i };
+            group[i] = if same { group[i - 1] } else { group[i - 1] + 1 };
+        }
+        for i in (0..n).rev() {
+            peer_end[i] = if i + 1 < n && peer_start[i + 1] == peer_start[i] {
+                peer_end[i + 1]
+            } else {
+                i
+            };
+        }
+        let mut results = vec![Vec::with_capacity(self.calls.len()); n];
+        let mut offset = 0;
+        for call in self.calls {
+            let arg = |i: usize, j: usize| partition.args[i][offset + j].clone();
+            match call.func {
+                WindowFunction::RowNumber => {
+                    for (i, result) in results.iter_mut().enumerate() {
+                        result.push(Value::Integer(i as i64 + 1));
+                    }
+                }
+                WindowFunction::Rank => {
+                    for (i, result) in results.iter_mut().enumerate() {
+                        result.push(Value::Integer(peer_start[i] as i64 + 1));
+                    }
+                }
+                WindowFunction::DenseRank => {
+                    for (i, result) in results.iter_mut().enumerate() {
+                        result.push(Value::Integer(group[i] as i64 + 1));
+                    }
+                }
+                WindowFunction::Lag | WindowFunction::Lead => {
+                    for (i, result) in results.iter_mut().enumerate() {
+                        let distance = match call.args.len() {
+                            1 => 1,
+                            _ => match arg(i, 1) {
+                                Value::Integer(n) => n,
+                                Value::Null => {
+                                    result.push(Value::Null);
+                                    continue;
+                                }
+                                value => {
+                                    return Err(Error::UndefinedFunction {
+                                        name: call.func.name(),
+                                        arg: value.type_name(),
+                                    })
+                                }
+                            },
+                        };
+                        let j = if call.func == WindowFunction::Lag {
+                            i as i128 - distance as i128
+                        } else {
+                            i as i128 + distance as i128
+                        };
+                        result.push(if (0..n as i128).contains(&j) {
+                            arg(j as usize, 0)
+                        } else if call.args.len() == 3 {
+                            arg(i, 2)
+                        } else {
+                            Value::Null
+                        });
+                    }
+                }
+                WindowFunction::Aggregate(func) => {
+                    let aggregate = AggregateCall {
+                        func,
+                        arg: call.args.first().cloned(),
+                        distinct: false,
+                        percentile: None,
+                    };
+                    let frame = |i: usize| frame_range(&call.frame, i, n, &peer_start, &peer_end);
+                    let value = |j: usize| {
+                        if call.args.is_empty() {
+                            Value::Null
+                        } else {
+                            arg(j, 0)
+                        }
+                    };
+                    if call.frame.start == FrameBound::UnboundedPreceding {
+                        // 始まりが動かないので、終わりまでを順に足していく
+                        let mut accumulator = aggregate.accumulator();
+                        let mut next = 0;
+                        for (i, result) in results.iter_mut().enumerate() {
+                            let (_, end) = frame(i);
+                            while next < end {
+                                accumulator.update(value(next))?;
+                                next += 1;
+                            }
+                            result.push(accumulator.finish()?);
+                        }
+                    } else {
+                        for (i, result) in results.iter_mut().enumerate() {
+                            let (start, end) = frame(i);
+                            let mut accumulator = aggregate.accumulator();
+                            for j in start..end {
+                                accumulator.update(value(j))?;
+                            }
+                            result.push(accumulator.finish()?);
+                        }
+                    }
+                }
+            }
+            offset += call.args.len();
+        }
+        Ok(results)
+    }
+}
+
+// i 行目のフレームの範囲 [start, end)
+fn frame_range(
+    frame: &WindowFrame,
+    i: usize,
+    n: usize,
+    peer_start: &[usize],
+    peer_end: &[usize],
+) -> (usize, usize) {
+    let bound = |bound: FrameBound, is_end: bool| -> usize {
+        let position = match (frame.units, bound) {
+            (_, FrameBound::UnboundedPreceding) => 0,
+            (_, FrameBound::UnboundedFollowing) => n as i128,
+            (FrameUnits::Range, FrameBound::CurrentRow) if is_end => peer_end[i] as i128 + 1,
+            (FrameUnits::Range, FrameBound::CurrentRow) => peer_start[i] as i128,
+            // RANGE の前後の件数指定は計画の時点で弾いている
+            (_, bound) => i as i128 + bound.position() + is_end as i128,
+        };
+        position.clamp(0, n as i128) as usize
+    };
+    let start = bound(frame.start, false);
+    let end = bound(frame.end, true);
+    (start, end.max(start))
+}
+
+impl Executor for Window<'_> {
+    fn next(&mut self, ctx: &mut ExecContext) -> Result<Option<Row>, Error> {
+        loop {
+            ctx.check_interrupt()?;
+            if let Some((rows, results)) = &mut self.output {
+                if let Some(mut row) = rows.next()? {
+                    row.extend(results.next().unwrap());
+                    return Ok(Some(row));
+                }
+                self.output = None;
+            }
+            let Some(partition) = self.load_partition(ctx)? else {
+                return Ok(None);
+            };
+            let results = self.compute(&partition)?;
+            let rows = match partition.store.spill {
+                Some(file) => StoredRows::File(file.into_reader()?),
+                None => StoredRows::Memory(partition.store.rows.into_iter()),
+            };
+            self.output = Some((rows, results.into_iter()));
+        }
+    }
+
+    fn close(&mut self, ctx: &mut ExecContext) -> Result<(), Error> {
+        self.output = None;
+        self.input.close(ctx)
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::executor::Row;
+use crate::json::Json;
+use crate::types::Value;
+
+// 問い合わせの結果の書き方
+//
+// Aligned は列の幅をそろえた表で NULL は空欄。Csv は RFC 4180 の形で、NULL は空欄、空文字列は "" と書き分ける。
+// Tsv は PostgreSQL の COPY の text 形式と同じく \ で逃がし、NULL は \N。
+// Json は 1 行に 1 つのオブジェクトを書く JSON Lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Aligned,
+    Csv,
+    Tsv,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFormatError;
+
+impl fmt::Display for ParseFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("allowed formats are aligned, csv, tsv, json")
+    }
+}
+
+impl OutputFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Aligned => "aligned",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    // 列名と行を書く。Aligned のときだけ最後に行数を書く
+    pub fn write(self, out: &mut dyn Write, columns: &[String], rows: &[Row]) -> io::Result<()> {
+        match self {
+            OutputFormat::Aligned => {
+                write_table(out, columns, rows)?;
+                match rows.len() {
+                    1 => writeln!(out, "(1 row)"),
+                    n => writeln!(out, "({} rows)", n),
+                }
+            }
+            OutputFormat::Csv => {
+                let header: Vec<String> = columns.iter().map(|c| csv_field(c)).collect();
+                writeln!(out, "{}", header.join(","))?;
+                for row in rows {
+                    let fields: Vec<String> = row
+                        .iter()
+                        .map(|v| match v {
+                            Value::Null => String::new(),
+                            v => csv_field(&v.to_string()),
+                        })
+                        .collect();
+                    writeln!(out, "{}", fields.join(","))?;
+                }
+                Ok(())
+            }
+            OutputFormat::Tsv => {
+                let header: Vec<String> = columns.iter().map(|c| tsv_field(c)).collect();
+                writeln!(out, "{}", header.join("\t"))?;
+                for row in rows {
+                    let fields: Vec<String> = row
+                        .iter()
+                        .map(|v| match v {
+                            Value::Null => "\\N".to_string(),
+                            v => tsv_field(&v.to_string()),
+                        })
+                        .collect();
+                    writeln!(out, "{}", fields.join("\t"))?;
+                }
+                Ok(())
+            }
+            OutputFormat::Json => {
+                for row in rows {
+                    let members: Vec<String> = columns
+                        .iter()
+                        .zip(row)
+                        .map(|(c, v)| format!("{}:{}", Json::String(c.clone()), json_value(v)))
+                        .collect();
+                    writeln!(out, "{{{}}}", members.join(","))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ParseFormatError;
+
+    fn from_str(s: &str) -> Result<OutputFormat, ParseFormatError> {
+        match s.to_lowercase().as_str() {
+            "aligned" | "table" => Ok(OutputFormat::Aligned),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "json" | "jsonl" => Ok(OutputFormat::Json),
+            _ => Err(ParseFormatError),
+        }
+    }
+}
+
+// 列の幅をそろえた表を書く。数は右に、ほかは左に寄せる
+pub fn write_table(out: &mut dyn Write, columns: &[String], rows: &[Row]) -> io::Result<()> {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|v| {
+                    if v.is_null() {
+                        String::new()
+                    } else {
+                        v.to_string()
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain([columns[i].chars().count()])
+                .max()
+                .unwrap()
+        })
+        .collect();
+    let header: Vec<String> = columns
+        .iter()
+        .zip(&widths)
+        .map(|(name, &w)| format!(" {:^w$} ", name))
+        .collect();
+    writeln!(out, "{}", header.join("|").trim_end())?;
+    let rule: Vec<String> = widths.iter().map(|&w| "-".repeat(w + 2)).collect();
+    writeln!(out, "{}", rule.join("+"))?;
+    for (row, values) in cells.iter().zip(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(values)
+            .zip(&widths)
+            .map(|((cell, value), &w)| {
+                if value.is_numeric() {
+                    format!(" {:>w$} ", cell)
+                } else {
+                    format!(" {:<w$} ", cell)
+                }
+            })
+            .collect();
+        writeln!(out, "{}", line.join("|").trim_end())?;
+    }
+    Ok(())
+}
+
+// 区切りや引用符や改行を含むか空なら " で囲み、中の " は重ねる
+fn csv_field(s: &str) -> String {
+    if s.is_empty() || s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn tsv_field(s: &str) -> String {
+    let mut field = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => field.push_str("\\\\"),
+            '\t' => field.push_str("\\t"),
+            '\n' => field.push_str("\\n"),
+            '\r' => field.push_str("\\r"),
+            c => field.push(c),
+        }
+    }
+    field
+}
+
+// 数と真偽値と JSON の値はそのまま、ほかは文字列にする
+pub fn json_value(value: &Value) -> Json {
+    match value {
+        Value::Null => Json::Null,
+        Value::Integer(n) | Value::BigInt(n) => Json::Number(n.to_string()),
+        Value::Real(x) if x.is_finite() => Json::Number(format!("{:?}", x)),
+        Value::Decimal(d) => Json::Number(d.to_string()),
+        Value::Boolean(b) => Json::Bool(*b),
+        Value::Json(s) => s.parse().unwrap_or_else(|_| Json::String(s.to_string())),
+        v => Json::String(v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(format: OutputFormat, columns: &[&str], rows: &[Row]) -> String {
+        let columns: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+        let mut out = vec![];
+        format.write(&mut out, &columns, rows).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_output_format() {
+        let rows = vec![
+            vec![
+                Value::Integer(1),
+                Value::Text("a,\"b\"".to_string()),
+                Value::Null,
+            ],
+            vec![
+                Value::Real(2.0),
+                Value::Text(String::new()),
+                Value::Text("x\ty\\".to_string()),
+            ],
+            vec![
+                Value::Boolean(true),
+                Value::Json("{\"k\":[1]}".into()),
+                Value::Text("line\nbreak".to_string()),
+            ],
+        ];
+        let columns = ["n", "s", "t"];
+        assert_eq!(
+            render(OutputFormat::Aligned, &columns, &rows[..2]),
+            concat!(
+                " n |   s   |  t\n",
+                "---+-------+------\n",
+                " 1 | a,\"b\" |\n",
+                " 2 |       | x\ty\\\n",
+                "(2 rows)\n",
+            )
+        );
+        assert_eq!(
+            render(OutputFormat::Csv, &columns, &rows),
+            concat!(
+                "n,s,t\n",
+                "1,\"a,\"\"b\"\"\",\n",
+                "2,\"\",x\ty\\\n",
+                "true,\"{\"\"k\"\":[1]}\",\"line\nbreak\"\n",
+            )
+        );
+        assert_eq!(
+            render(OutputFormat::Tsv, &columns, &rows),
+            concat!(
+                "n\ts\tt\n",
+                "1\ta,\"b\"\t\\N\n",
+                "2\t\tx\\ty\\\\\n",
+                "true\t{\"k\":[1]}\tline\\nbreak\n",
+            )
+        );
+        assert_eq!(
+            render(OutputFormat::Json, &columns, &rows),
+            concat!(
+                "{\"n\":1,\"s\":\"a,\\\"b\\\"\",\"t\":null}\n",
+                "{\"n\":2.0,\"s\":\"\",\"t\":\"x\\ty\\\\\"}\n",
+                "{\"n\":true,\"s\":{\"k\":[1]},\"t\":\"line\\nbreak\"}\n",
+            )
+        );
+        assert_eq!("CSV".parse(), Ok(OutputFormat::Csv));
+        assert_eq!("xml".parse::<OutputFormat>(), Err(ParseFormatError));
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+// 全文検索の語の切り出しと関連度
+//
+// 文字と数字の続きを 1 語とし、小文字にそろえる。全文インデックスは語と RecordId をキーにした
+// B+Tree で、行の語ごとにキーを 1 つ持つ。MATCH ... AGAINST は問い合わせのどれかの語を含む行に
+// 正の関連度を返す。関連度は問い合わせの語ごとに、行に現れた回数 n から 1 + ln(n) を足したもの
+
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+// 問い合わせの語。同じ語は 1 つにまとめる
+pub fn query_terms(query: &str) -> BTreeSet<String> {
+    tokenize(query).collect()
+}
+
+// texts に現れた語とその回数
+pub fn term_frequencies<'a>(texts: impl IntoIterator<Item = &'a str>) -> BTreeMap<String, usize> {
+    let mut terms = BTreeMap::new();
+    for text in texts {
+        for term in tokenize(text) {
+            *terms.entry(term).or_insert(0) += 1;
+        }
+    }
+    terms
+}
+
+pub fn relevance<'a>(terms: &BTreeSet<String>, texts: impl IntoIterator<Item = &'a str>) -> f64 {
+    let mut counts = BTreeMap::new();
+    for text in texts {
+        for term in tokenize(text).filter(|term| terms.contains(term)) {
+            *counts.entry(term).or_insert(0usize) += 1;
+        }
+    }
+    counts.values().map(|&n| 1.0 + (n as f64).ln()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relevance() {
+        let words: Vec<_> = tokenize("The quick-brown fox, THE end.").collect();
+        assert_eq!(words, ["the", "quick", "brown", "fox", "the", "end"]);
+        let frequencies = term_frequencies(["a b a", "b c"]);
+        assert_eq!(frequencies.get("a"), Some(&2));
+        assert_eq!(frequencies.get("b"), Some(&2));
+
+        let terms = query_terms("Fox fox dog");
+        assert_eq!(terms.len(), 2);
+        assert_eq!(relevance(&terms, ["a cat"]), 0.0);
+        assert_eq!(relevance(&terms, ["a fox"]), 1.0);
+        // 2 つの語を含む行は、1 つの語を何度も含む行より上に来る
+        let both = relevance(&terms, ["a fox", "and a dog"]);
+        let repeated = relevance(&terms, ["fox fox"]);
+        assert!(both > repeated && repeated > 1.0);
+    }
+}
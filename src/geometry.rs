@@ -0,0 +1,164 @@
+use std::fmt;
+use std::str::FromStr;
+
+// 平面の点と、辺が軸に平行な矩形
+//
+// 点は '(x,y)'、矩形は対角の 2 点 '(x1,y1),(x2,y2)' と書く。読むときは括弧を省いてもよく、
+// 矩形はどの対角の 2 点で書いても low が左下、high が右上になるように並べ直す。書くときは PostgreSQL と同じく右上の点を先に書く。
+// 座標は有限の f64 だけを受け付ける。点も幅のない矩形として重なりや包含を調べる。
+// 距離はユークリッド距離で、矩形どうしではいちばん近い点どうしの距離
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub low: Point,
+    pub high: Point,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseGeometryError;
+
+impl fmt::Display for ParseGeometryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid geometry")
+    }
+}
+
+impl Point {
+    pub fn new(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+}
+
+impl Rect {
+    // 対角の 2 点から
+    pub fn new(a: Point, b: Point) -> Rect {
+        Rect {
+            low: Point::new(a.x.min(b.x), a.y.min(b.y)),
+            high: Point::new(a.x.max(b.x), a.y.max(b.y)),
+        }
+    }
+
+    pub fn point(p: Point) -> Rect {
+        Rect { low: p, high: p }
+    }
+
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.low.x <= other.high.x
+            && other.low.x <= self.high.x
+            && self.low.y <= other.high.y
+            && other.low.y <= self.high.y
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        self.low.x <= other.low.x
+            && self.low.y <= other.low.y
+            && other.high.x <= self.high.x
+            && other.high.y <= self.high.y
+    }
+
+    // 両方を囲む最小の矩形
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            low: Point::new(self.low.x.min(other.low.x), self.low.y.min(other.low.y)),
+            high: Point::new(self.high.x.max(other.high.x), self.high.y.max(other.high.y)),
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        (self.high.x - self.low.x) * (self.high.y - self.low.y)
+    }
+
+    // いちばん近い点どうしの距離。重なっていれば 0
+    pub fn distance(&self, other: &Rect) -> f64 {
+        let dx = (self.low.x - other.high.x)
+            .max(other.low.x - self.high.x)
+            .max(0.0);
+        let dy = (self.low.y - other.high.y)
+            .max(other.low.y - self.high.y)
+            .max(0.0);
+        dx.hypot(dy)
+    }
+}
+
+// 括弧と空白を除いて , で区切った n 個の座標
+fn parse_coordinates(s: &str, n: usize) -> Result<Vec<f64>, ParseGeometryError> {
+    let s: String = s
+        .chars()
+        .filter(|c| !matches!(c, '(' | ')') && !c.is_whitespace())
+        .collect();
+    let coordinates = s
+        .split(',')
+        .map(|c| c.parse::<f64>().ok().filter(|f| f.is_finite()))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(ParseGeometryError)?;
+    if coordinates.len() != n {
+        return Err(ParseGeometryError);
+    }
+    // -0.0 は 0.0 にそろえる
+    Ok(coordinates.into_iter().map(|c| c + 0.0).collect())
+}
+
+impl FromStr for Point {
+    type Err = ParseGeometryError;
+
+    fn from_str(s: &str) -> Result<Point, ParseGeometryError> {
+        let c = parse_coordinates(s, 2)?;
+        Ok(Point::new(c[0], c[1]))
+    }
+}
+
+impl FromStr for Rect {
+    type Err = ParseGeometryError;
+
+    fn from_str(s: &str) -> Result<Rect, ParseGeometryError> {
+        let c = parse_coordinates(s, 4)?;
+        Ok(Rect::new(Point::new(c[0], c[1]), Point::new(c[2], c[3])))
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({},{})", self.x, self.y)
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.high, self.low)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geometry() {
+        let p: Point = " ( 1.5 , -2 ) ".parse().unwrap();
+        assert_eq!(p, Point::new(1.5, -2.0));
+        assert_eq!(p.to_string(), "(1.5,-2)");
+        let rect: Rect = "(3,0),(0,4)".parse().unwrap();
+        assert_eq!(rect.low, Point::new(0.0, 0.0));
+        assert_eq!(rect.high, Point::new(3.0, 4.0));
+        assert_eq!(rect.to_string(), "(3,4),(0,0)");
+        assert_eq!("0,0,3,4".parse::<Rect>(), Ok(rect));
+        for s in ["", "(1)", "(1,2,3)", "(1,x)", "(inf,0)"] {
+            assert_eq!(s.parse::<Point>(), Err(ParseGeometryError), "{}", s);
+        }
+
+        let other = Rect::new(Point::new(2.0, 3.0), Point::new(5.0, 5.0));
+        assert!(rect.overlaps(&other) && other.overlaps(&rect));
+        assert!(!rect.contains(&other));
+        assert!(rect.contains(&Rect::point(Point::new(3.0, 1.0))));
+        assert_eq!(rect.union(&other).area(), 25.0);
+        let point = |x, y| Rect::point(Point::new(x, y));
+        assert_eq!(rect.distance(&point(1.0, 1.0)), 0.0);
+        assert_eq!(rect.distance(&point(6.0, 8.0)), 5.0);
+        assert_eq!(point(0.0, 0.0).distance(&point(-3.0, 4.0)), 5.0);
+    }
+}
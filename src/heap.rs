@@ -0,0 +1,434 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::buffer::{self, Buffer, BufferPoolManager};
+use crate::disk::{PageId, PAGE_SIZE};
+use crate::slotted::Slotted;
+use crate::transaction::TxnId;
+
+// ヒープファイルのページ
+//
+// | next_page_id (8) | last_page_id (8) | スロット付きページ |
+//
+// ページは単方向リストでつながっており、last_page_id は先頭ページでのみ使う。
+//
+// スロットには | xmin (8) | xmax (8) | 行 | を入れる。xmin は行を入れたトランザクション、
+// xmax は行を消したトランザクションで、消されていなければ INVALID_TXN_ID。
+// 行を書き換えるときは新しい版を入れて古い版に xmax をつけ、古い版も残しておく。
+const NEXT_PAGE_ID: usize = 0;
+const LAST_PAGE_ID: usize = 8;
+const HEADER_SIZE: usize = 16;
+const TUPLE_HEADER_SIZE: usize = 16;
+
+pub const MAX_TUPLE_SIZE: usize = PAGE_SIZE - HEADER_SIZE - 8 - TUPLE_HEADER_SIZE;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Buffer(#[from] buffer::Error),
+    #[error("tuple of {0} bytes does not fit in a page")]
+    TupleTooLarge(usize),
+    #[error("page {} does not hold valid entries", .0.to_u64())]
+    CorruptedPage(PageId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RecordId {
+    pub page_id: PageId,
+    pub slot: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TupleHeader {
+    pub xmin: TxnId,
+    pub xmax: TxnId,
+}
+
+impl TupleHeader {
+    fn read(bytes: &[u8]) -> Self {
+        let xid = |offset: usize| {
+            TxnId(u64::from_be_bytes(
+                bytes[offset..offset + 8].try_into().unwrap(),
+            ))
+        };
+        Self {
+            xmin: xid(0),
+            xmax: xid(8),
+        }
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        bytes[..8].copy_from_slice(&self.xmin.to_u64().to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.xmax.to_u64().to_be_bytes());
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.xmax.valid().is_some()
+    }
+}
+
+// スロットの中身をヘッダと行に分ける
+fn split_tuple(bytes: &[u8]) -> (TupleHeader, Vec<u8>) {
+    (
+        TupleHeader::read(bytes),
+        bytes[TUPLE_HEADER_SIZE..].to_vec(),
+    )
+}
+
+fn read_page_id(page: &[u8], offset: usize) -> PageId {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&page[offset..offset + 8]);
+    PageId(u64::from_be_bytes(bytes))
+}
+
+fn write_page_id(page: &mut [u8], offset: usize, page_id: PageId) {
+    page[offset..offset + 8].copy_from_slice(&page_id.to_u64().to_be_bytes());
+}
+
+fn initialize_page(buffer: &Buffer) {
+    let mut page = buffer.page.borrow_mut();
+    write_page_id(&mut page[..], NEXT_PAGE_ID, PageId::INVALID_PAGE_ID);
+    write_page_id(&mut page[..], LAST_PAGE_ID, buffer.page_id);
+    Slotted::new(&mut page[HEADER_SIZE..]).initialize();
+    buffer.is_dirty.set(true);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapFile {
+    pub first_page_id: PageId,
+}
+
+impl HeapFile {
+    pub fn create(bufmgr: &mut BufferPoolManager) -> Result<Self, Error> {
+        let buffer = bufmgr.create_page()?;
+        initialize_page(&buffer);
+        Ok(Self {
+            first_page_id: buffer.page_id,
+        })
+    }
+
+    // xmin のトランザクションが入れた版として行を入れる
+    pub fn insert(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        xmin: TxnId,
+        data: &[u8],
+    ) -> Result<RecordId, Error> {
+        if data.len() > MAX_TUPLE_SIZE {
+            return Err(Error::TupleTooLarge(data.len()));
+        }
+        let header = TupleHeader {
+            xmin,
+            xmax: TxnId::INVALID_TXN_ID,
+        };
+        let mut bytes = vec![0; TUPLE_HEADER_SIZE];
+        header.write(&mut bytes);
+        bytes.extend_from_slice(data);
+        let data = &bytes[..];
+        let first = bufmgr.fetch_page(self.first_page_id)?;
+        let last_page_id = read_page_id(&first.page.borrow()[..], LAST_PAGE_ID);
+        let last = if last_page_id == self.first_page_id {
+            Rc::clone(&first)
+        } else {
+            bufmgr.fetch_page(last_page_id)?
+        };
+        if let Some(slot) = insert_into(&last, data) {
+            return Ok(RecordId {
+                page_id: last.page_id,
+                slot,
+            });
+        }
+        // 末尾のページに入らなければ新しいページをつなげる
+        let new_page = bufmgr.create_page()?;
+        initialize_page(&new_page);
+        write_page_id(
+            &mut last.page.borrow_mut()[..],
+            NEXT_PAGE_ID,
+            new_page.page_id,
+        );
+        last.is_dirty.set(true);
+        write_page_id(
+            &mut first.page.borrow_mut()[..],
+            LAST_PAGE_ID,
+            new_page.page_id,
+        );
+        first.is_dirty.set(true);
+        let slot = insert_into(&new_page, data).expect("tuple fits in an empty page");
+        Ok(RecordId {
+            page_id: new_page.page_id,
+            slot,
+        })
+    }
+
+    // 版のヘッダと行
+    pub fn get(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+    ) -> Result<Option<(TupleHeader, Vec<u8>)>, Error> {
+        let buffer = bufmgr.fetch_page(rid.page_id)?;
+        let page = buffer.page.borrow();
+        let slotted = Slotted::new(&page[HEADER_SIZE..]);
+        Ok(slotted.get(rid.slot as usize).map(split_tuple))
+    }
+
+    // 版に xmax をつけて消したことにする。版がないか、すでに消されていれば false
+    pub fn delete(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+        xmax: TxnId,
+    ) -> Result<bool, Error> {
+        self.set_xmax(bufmgr, rid, |header| (!header.is_deleted()).then_some(xmax))
+    }
+
+    // 消した版を元に戻す
+    pub fn undelete(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error> {
+        self.set_xmax(bufmgr, rid, |_| Some(TxnId::INVALID_TXN_ID))
+    }
+
+    fn set_xmax(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+        xmax: impl FnOnce(&TupleHeader) -> Option<TxnId>,
+    ) -> Result<bool, Error> {
+        let buffer = bufmgr.fetch_page(rid.page_id)?;
+        let mut page = buffer.page.borrow_mut();
+        let Some(range) = Slotted::new(&page[HEADER_SIZE..]).data_range(rid.slot as usize) else {
+            return Ok(false);
+        };
+        let bytes = &mut page[HEADER_SIZE + range.start..HEADER_SIZE + range.end];
+        let mut header = TupleHeader::read(bytes);
+        let Some(xmax) = xmax(&header) else {
+            return Ok(false);
+        };
+        header.xmax = xmax;
+        header.write(bytes);
+        buffer.is_dirty.set(true);
+        Ok(true)
+    }
+
+    // 版をページから取り除く。取り消した挿入や、誰からも見えなくなった版に使う
+    pub fn remove(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error> {
+        let buffer = bufmgr.fetch_page(rid.page_id)?;
+        let removed =
+            Slotted::new(&mut buffer.page.borrow_mut()[HEADER_SIZE..]).delete(rid.slot as usize);
+        if removed {
+            buffer.is_dirty.set(true);
+        }
+        Ok(removed)
+    }
+
+    pub fn scan(&self) -> HeapScan {
+        HeapScan {
+            page_id: Some(self.first_page_id),
+            slot: 0,
+        }
+    }
+
+    // ヒープが使っているページの一覧
+    pub fn page_ids(&self, bufmgr: &mut BufferPoolManager) -> Result<Vec<PageId>, Error> {
+        let mut page_ids = vec![];
+        let mut page_id = Some(self.first_page_id);
+        while let Some(id) = page_id {
+            page_ids.push(id);
+            let buffer = bufmgr.fetch_page(id)?;
+            page_id = read_page_id(&buffer.page.borrow()[..], NEXT_PAGE_ID).valid();
+        }
+        Ok(page_ids)
+    }
+
+    // ページのリストをたどり、各ページのスロットと版のヘッダを確かめる。
+    // 見つけた誤りを problems に足し、壊れていないページにある版を返す
+    pub fn verify(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        problems: &mut Vec<(PageId, String)>,
+    ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        let mut tuples = vec![];
+        let mut visited = HashSet::new();
+        let mut page_id = Some(self.first_page_id);
+        let mut prev = self.first_page_id;
+        while let Some(id) = page_id {
+            if id.to_u64() >= bufmgr.page_count() {
+                problems.push((
+                    prev,
+                    format!("next page {} is past the end of the file", id.0),
+                ));
+                return Ok(tuples);
+            }
+            if !visited.insert(id) {
+                problems.push((prev, format!("page list loops back to page {}", id.0)));
+                return Ok(tuples);
+            }
+            prev = id;
+            let buffer = bufmgr.fetch_page(id)?;
+            let page = buffer.page.borrow();
+            page_id = read_page_id(&page[..], NEXT_PAGE_ID).valid();
+            let slotted = Slotted::new(&page[HEADER_SIZE..]);
+            if let Err(message) = slotted.verify() {
+                problems.push((id, message));
+                continue;
+            }
+            for slot in 0..slotted.num_slots() {
+                let Some(bytes) = slotted.get(slot) else {
+                    continue;
+                };
+                if bytes.len() < TUPLE_HEADER_SIZE {
+                    problems.push((id, format!("slot {} is too short for a tuple header", slot)));
+                    continue;
+                }
+                let (header, data) = split_tuple(bytes);
+                if header.xmin.valid().is_none() {
+                    problems.push((id, format!("slot {} has no inserting transaction", slot)));
+                    continue;
+                }
+                let rid = RecordId {
+                    page_id: id,
+                    slot: slot as u16,
+                };
+                tuples.push((rid, header, data));
+            }
+        }
+        let first = bufmgr.fetch_page(self.first_page_id)?;
+        let last = read_page_id(&first.page.borrow()[..], LAST_PAGE_ID);
+        if last != prev {
+            problems.push((
+                self.first_page_id,
+                format!(
+                    "last page is {} but the list ends at page {}",
+                    last.0, prev.0
+                ),
+            ));
+        }
+        Ok(tuples)
+    }
+
+    // 1 ページにある版をすべて読む
+    pub fn page_tuples(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        page_id: PageId,
+    ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        let buffer = bufmgr.fetch_page(page_id)?;
+        let page = buffer.page.borrow();
+        let slotted = Slotted::new(&page[HEADER_SIZE..]);
+        let tuples = (0..slotted.num_slots())
+            .filter_map(|slot| {
+                let rid = RecordId {
+                    page_id,
+                    slot: slot as u16,
+                };
+                slotted.get(slot).map(|bytes| {
+                    let (header, data) = split_tuple(bytes);
+                    (rid, header, data)
+                })
+            })
+            .collect();
+        Ok(tuples)
+    }
+}
+
+fn insert_into(buffer: &Buffer, data: &[u8]) -> Option<u16> {
+    let slot = Slotted::new(&mut buffer.page.borrow_mut()[HEADER_SIZE..]).insert(data)?;
+    buffer.is_dirty.set(true);
+    Some(slot as u16)
+}
+
+pub struct HeapScan {
+    page_id: Option<PageId>,
+    slot: usize,
+}
+
+// ヒープの版を、消されたものも含めて順に読む
+impl HeapScan {
+    pub fn next(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+    ) -> Result<Option<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        while let Some(page_id) = self.page_id {
+            let buffer = bufmgr.fetch_page(page_id)?;
+            let page = buffer.page.borrow();
+            let slotted = Slotted::new(&page[HEADER_SIZE..]);
+            while self.slot < slotted.num_slots() {
+                let slot = self.slot;
+                self.slot += 1;
+                if let Some(bytes) = slotted.get(slot) {
+                    let rid = RecordId {
+                        page_id,
+                        slot: slot as u16,
+                    };
+                    let (header, data) = split_tuple(bytes);
+                    return Ok(Some((rid, header, data)));
+                }
+            }
+            self.page_id = read_page_id(&page[..], NEXT_PAGE_ID).valid();
+            self.slot = 0;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_bufmgr;
+    use crate::transaction::TxnId;
+
+    // 消されていない版
+    fn collect(heap: &HeapFile, bufmgr: &mut BufferPoolManager) -> Vec<Vec<u8>> {
+        let mut scan = heap.scan();
+        let mut rows = vec![];
+        while let Some((_, header, data)) = scan.next(bufmgr).unwrap() {
+            if !header.is_deleted() {
+                rows.push(data);
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn test_insert_scan() {
+        let mut bufmgr = temp_bufmgr(4);
+        let heap = HeapFile::create(&mut bufmgr).unwrap();
+        let mut rids = vec![];
+        // 複数ページにまたがる量を入れる
+        for i in 0..100u32 {
+            rids.push(heap.insert(&mut bufmgr, TxnId(2), &[i as u8; 100]).unwrap());
+        }
+        assert!(heap.page_ids(&mut bufmgr).unwrap().len() > 1);
+        let rows = collect(&heap, &mut bufmgr);
+        assert_eq!(rows.len(), 100);
+        assert_eq!(rows[99], vec![99; 100]);
+
+        // 消した版は xmax をつけて残る
+        assert!(heap.delete(&mut bufmgr, rids[0], TxnId(3)).unwrap());
+        assert!(!heap.delete(&mut bufmgr, rids[0], TxnId(4)).unwrap());
+        let header = TupleHeader {
+            xmin: TxnId(2),
+            xmax: TxnId(3),
+        };
+        assert_eq!(
+            heap.get(&mut bufmgr, rids[0]).unwrap(),
+            Some((header, vec![0; 100]))
+        );
+        assert_eq!(collect(&heap, &mut bufmgr).len(), 99);
+        assert!(heap.undelete(&mut bufmgr, rids[0]).unwrap());
+        assert_eq!(collect(&heap, &mut bufmgr).len(), 100);
+        assert!(heap.remove(&mut bufmgr, rids[1]).unwrap());
+        assert_eq!(heap.get(&mut bufmgr, rids[1]).unwrap(), None);
+        assert!(!heap.delete(&mut bufmgr, rids[1], TxnId(3)).unwrap());
+        assert_eq!(collect(&heap, &mut bufmgr).len(), 99);
+    }
+
+    #[test]
+    fn test_tuple_too_large() {
+        let mut bufmgr = temp_bufmgr(2);
+        let heap = HeapFile::create(&mut bufmgr).unwrap();
+        assert!(matches!(
+            heap.insert(&mut bufmgr, TxnId(2), &[0; PAGE_SIZE]),
+            Err(Error::TupleTooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,359 @@
+use std::io::{self, BufRead, Read, Write};
+
+use crate::connection::{Connection, StatementResult};
+use crate::format::json_value;
+use crate::json::Json;
+use crate::metrics::METRICS;
+use crate::sql;
+use crate::types::Value;
+
+// JSON で問い合わせる HTTP のインターフェース
+//
+// POST /query に {"sql": "...", "params": [...]} を送ると、; で区切った文を順に実行して
+// {"results": [{"command": "SELECT 1", "row_count": 1, "columns": [...], "rows": [[...]]}]} を返す。
+// params は $1 から順に入り、すべての文で同じものを使う。エラーになればそこで止め、
+// それまでの結果と {"error": {"code": SQLSTATE, "message": ...}} を 400 で返す。
+// GET /metrics は計器の値を Prometheus のテキスト形式で返す。
+// 1 つの要求を 1 つの接続で受け、答えたら閉じる。ブラウザから呼べるように CORS のヘッダーをつける
+
+// 本体の長さの上限
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+// 要求の行とヘッダーの長さの上限
+const MAX_HEADER_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // 名前の大文字と小文字は区別しない
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: Json) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    // 計器の値を Prometheus のテキスト形式で返す
+    pub fn metrics() -> Response {
+        Response {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: METRICS.render(),
+        }
+    }
+
+    // 要求の誤りを伝える
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(
+            status,
+            Json::Object(vec![(
+                "error".to_string(),
+                Json::Object(vec![(
+                    "message".to_string(),
+                    Json::String(message.to_string()),
+                )]),
+            )]),
+        )
+    }
+
+    pub fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason(self.status)
+        )?;
+        if !self.body.is_empty() {
+            write!(writer, "Content-Type: {}\r\n", self.content_type)?;
+        }
+        write!(writer, "Content-Length: {}\r\n", self.body.len())?;
+        writer.write_all(b"Access-Control-Allow-Origin: *\r\n")?;
+        writer.write_all(b"Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n")?;
+        writer.write_all(b"Access-Control-Allow-Headers: Content-Type\r\n")?;
+        writer.write_all(b"Connection: close\r\n\r\n")?;
+        writer.write_all(self.body.as_bytes())?;
+        writer.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+// 要求を読む。読めなければ返すべき応答を返す
+pub fn read_request(reader: &mut dyn BufRead) -> Result<Request, Response> {
+    let bad_request = |_| Response::error(400, "malformed request");
+    let mut read = 0;
+    let mut line = || -> Result<String, Response> {
+        let mut line = String::new();
+        let n = reader
+            .take((MAX_HEADER_LEN - read) as u64)
+            .read_line(&mut line)
+            .map_err(bad_request)?;
+        read += n;
+        if !line.ends_with('\n') {
+            return Err(Response::error(400, "malformed request"));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let request_line = line()?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(_), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Response::error(400, "malformed request line"));
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        // 問い合わせの文字列は使わない
+        path: target.split('?').next().unwrap().to_string(),
+        headers: vec![],
+        body: vec![],
+    };
+    loop {
+        let line = line()?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(Response::error(400, "malformed header"));
+        };
+        request
+            .headers
+            .push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let len = match request.header("Content-Length") {
+        Some(len) => len
+            .parse::<usize>()
+            .map_err(|_| Response::error(400, "invalid Content-Length"))?,
+        None if request.header("Transfer-Encoding").is_some() => {
+            return Err(Response::error(411, "Content-Length is required"))
+        }
+        None => 0,
+    };
+    if len > MAX_BODY_LEN {
+        return Err(Response::error(413, "request body is too large"));
+    }
+    request.body = vec![0; len];
+    reader.read_exact(&mut request.body).map_err(bad_request)?;
+    Ok(request)
+}
+
+// 要求に答える
+pub fn handle(conn: &mut Connection, request: &Request) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/query") => query(conn, &request.body),
+        ("OPTIONS", "/query") => Response {
+            status: 204,
+            content_type: "",
+            body: String::new(),
+        },
+        (_, "/query") => Response::error(405, "use POST"),
+        ("GET", "/metrics") => Response::metrics(),
+        (_, "/metrics") => Response::error(405, "use GET"),
+        _ => Response::error(404, "not found"),
+    }
+}
+
+fn query(conn: &mut Connection, body: &[u8]) -> Response {
+    let json = std::str::from_utf8(body)
+        .ok()
+        .and_then(|body| body.parse::<Json>().ok());
+    let Some(Json::Object(members)) = json else {
+        return Response::error(400, "request body must be a JSON object");
+    };
+    let member = |name: &str| members.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+    let Some(Json::String(sql)) = member("sql") else {
+        return Response::error(400, "\"sql\" must be a string");
+    };
+    let params: Vec<Value> = match member("params") {
+        None | Some(Json::Null) => vec![],
+        Some(Json::Array(params)) => params.iter().map(param_value).collect(),
+        Some(_) => return Response::error(400, "\"params\" must be an array"),
+    };
+    let mut results = vec![];
+    let error = match sql::parse(sql) {
+        Ok(statements) => {
+            statements
+                .iter()
+                .find_map(|stmt| match conn.execute_parsed(sql, stmt, &params) {
+                    Ok(result) => {
+                        results.push(result_json(result));
+                        None
+                    }
+                    Err(e) => Some(e),
+                })
+        }
+        Err(e) => Some(e.into()),
+    };
+    let mut body = vec![("results".to_string(), Json::Array(results))];
+    let Some(e) = error else {
+        return Response::json(200, Json::Object(body));
+    };
+    body.push((
+        "error".to_string(),
+        Json::Object(vec![
+            ("code".to_string(), Json::String(e.sqlstate().to_string())),
+            ("message".to_string(), Json::String(e.to_string())),
+        ]),
+    ));
+    Response::json(400, Json::Object(body))
+}
+
+// 整数は整数、小数点や指数のある数は実数、配列とオブジェクトは JSON の値にする
+fn param_value(json: &Json) -> Value {
+    match json {
+        Json::Null => Value::Null,
+        Json::Bool(b) => Value::Boolean(*b),
+        Json::Number(n) => match n.parse() {
+            Ok(n) => Value::BigInt(n),
+            Err(_) => Value::Real(n.parse().unwrap_or(f64::NAN)),
+        },
+        Json::String(s) => Value::Text(s.clone()),
+        json => Value::Json(json.to_string().into()),
+    }
+}
+
+fn result_json(result: StatementResult) -> Json {
+    let mut members = vec![("command".to_string(), Json::String(result.tag()))];
+    match result {
+        StatementResult::Rows(rows) => {
+            let columns = rows.columns().iter().cloned().map(Json::String).collect();
+            let rows: Vec<Json> = rows
+                .map(|row| Json::Array(row.values().iter().map(json_value).collect()))
+                .collect();
+            members.push((
+                "row_count".to_string(),
+                Json::Number(rows.len().to_string()),
+            ));
+            members.push(("columns".to_string(), Json::Array(columns)));
+            members.push(("rows".to_string(), Json::Array(rows)));
+        }
+        StatementResult::Modified { rows, .. } => {
+            members.push(("row_count".to_string(), Json::Number(rows.to_string())));
+        }
+        StatementResult::Done(_) => {}
+    }
+    Json::Object(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Database;
+
+    fn post(conn: &mut Connection, body: &str) -> Response {
+        let raw = format!(
+            "POST /query HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        handle(conn, &request)
+    }
+
+    #[test]
+    fn test_http() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let response = post(
+            &mut conn,
+            r#"{"sql": "CREATE TABLE t (a INTEGER, b TEXT); INSERT INTO t VALUES (1, 'x'), (2, NULL)"}"#,
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body,
+            r#"{"results":[{"command":"CREATE TABLE"},{"command":"INSERT 0 2","row_count":2}]}"#
+        );
+        let response = post(
+            &mut conn,
+            r#"{"sql": "SELECT a, b, a > $1 AS big FROM t ORDER BY a", "params": [1]}"#,
+        );
+        assert_eq!(
+            response.body,
+            concat!(
+                r#"{"results":[{"command":"SELECT 2","row_count":2,"columns":["a","b","big"],"#,
+                r#""rows":[[1,"x",false],[2,null,true]]}]}"#
+            )
+        );
+        // 失敗した文の前までの結果とエラーを返す
+        let response = post(
+            &mut conn,
+            r#"{"sql": "DELETE FROM t WHERE a = 2; SELECT * FROM nope"}"#,
+        );
+        assert_eq!(response.status, 400);
+        assert_eq!(
+            response.body,
+            concat!(
+                r#"{"results":[{"command":"DELETE 1","row_count":1}],"#,
+                r#""error":{"code":"42P01","message":"table \"nope\" does not exist"}}"#
+            )
+        );
+        assert_eq!(post(&mut conn, "[]").status, 400);
+
+        let request = read_request(&mut &b"GET /query HTTP/1.1\r\n\r\n"[..]).unwrap();
+        assert_eq!(handle(&mut conn, &request).status, 405);
+        let request = read_request(&mut &b"GET /nope?x=1 HTTP/1.1\r\n\r\n"[..]).unwrap();
+        assert_eq!(request.path, "/nope");
+        assert_eq!(handle(&mut conn, &request).status, 404);
+        assert_eq!(
+            read_request(&mut &b"GARBAGE\r\n\r\n"[..])
+                .unwrap_err()
+                .status,
+            400
+        );
+        let mut out = vec![];
+        Response {
+            status: 200,
+            content_type: "application/json",
+            body: "{}".to_string(),
+        }
+        .write_to(&mut out)
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{}", out);
+        assert!(out.ends_with("\r\n\r\n{}"), "{}", out);
+
+        let request = read_request(&mut &b"GET /metrics HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let response = handle(&mut conn, &request);
+        assert_eq!(response.content_type, "text/plain; version=0.0.4");
+        assert!(
+            response
+                .body
+                .contains("\n# TYPE rdbms_queries_total counter\n"),
+            "{}",
+            response.body
+        );
+    }
+}
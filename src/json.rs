@@ -0,0 +1,375 @@
+use std::fmt;
+use std::str::FromStr;
+
+// JSON の値
+//
+// JSON 型の列には、読んでから空白を詰めて書き直した文字列を入れる。オブジェクトのキーの順と重複はそのまま残す。
+// 数は書いてあった字面のまま持ち、SQL の値にするときに整数か実数として読む
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseJsonError;
+
+impl fmt::Display for ParseJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid json")
+    }
+}
+
+// パスの 1 段。オブジェクトのキーか配列の位置。負の位置は末尾から数える
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathStep {
+    Key(String),
+    Index(i64),
+}
+
+// 入れ子の深さの上限。深すぎる値でスタックを使い切らないようにする
+const MAX_DEPTH: usize = 256;
+
+impl Json {
+    // key のメンバーか要素。なければ None
+    pub fn get(&self, step: &PathStep) -> Option<&Json> {
+        match (self, step) {
+            (Json::Object(members), PathStep::Key(key)) => {
+                members.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            (Json::Array(items), PathStep::Index(i)) => {
+                let i = if *i < 0 { items.len() as i64 + i } else { *i };
+                items.get(usize::try_from(i).ok()?)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_path(&self, path: &[PathStep]) -> Option<&Json> {
+        path.iter().try_fold(self, |json, step| json.get(step))
+    }
+}
+
+// $ に .key、."key"、[n] を続けたパスを読む。読めなければ None
+pub fn parse_path(path: &str) -> Option<Vec<PathStep>> {
+    let mut rest = path.trim().strip_prefix('$')?;
+    let mut steps = vec![];
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix("[") {
+            let (index, r) = r.split_once(']')?;
+            steps.push(PathStep::Index(index.trim().parse().ok()?));
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix(".\"") {
+            let end = r.find('"')?;
+            steps.push(PathStep::Key(r[..end].to_string()));
+            rest = &r[end + 1..];
+        } else if let Some(r) = rest.strip_prefix('.') {
+            let end = r.find(['.', '[']).unwrap_or(r.len());
+            if end == 0 {
+                return None;
+            }
+            steps.push(PathStep::Key(r[..end].to_string()));
+            rest = &r[end..];
+        } else {
+            return None;
+        }
+    }
+    Some(steps)
+}
+
+impl FromStr for Json {
+    type Err = ParseJsonError;
+
+    fn from_str(s: &str) -> Result<Json, ParseJsonError> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            pos: 0,
+        };
+        let json = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.pos != parser.chars.len() {
+            return Err(ParseJsonError);
+        }
+        Ok(json)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseJsonError> {
+        self.skip_whitespace();
+        if self.bump() == Some(c) {
+            Ok(())
+        } else {
+            Err(ParseJsonError)
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn keyword(&mut self, word: &str, json: Json) -> Result<Json, ParseJsonError> {
+        for expected in word.chars() {
+            if self.bump() != Some(expected) {
+                return Err(ParseJsonError);
+            }
+        }
+        Ok(json)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, ParseJsonError> {
+        if depth > MAX_DEPTH {
+            return Err(ParseJsonError);
+        }
+        self.skip_whitespace();
+        match self.peek().ok_or(ParseJsonError)? {
+            'n' => self.keyword("null", Json::Null),
+            't' => self.keyword("true", Json::Bool(true)),
+            'f' => self.keyword("false", Json::Bool(false)),
+            '"' => self.string().map(Json::String),
+            '[' => {
+                self.pos += 1;
+                let mut items = vec![];
+                if !self.eat(']') {
+                    loop {
+                        items.push(self.value(depth + 1)?);
+                        if self.eat(']') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Json::Array(items))
+            }
+            '{' => {
+                self.pos += 1;
+                let mut members = vec![];
+                if !self.eat('}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.expect(':')?;
+                        members.push((key, self.value(depth + 1)?));
+                        if self.eat('}') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Json::Object(members))
+            }
+            _ => self.number(),
+        }
+    }
+
+    // -? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?
+    fn number(&mut self) -> Result<Json, ParseJsonError> {
+        let start = self.pos;
+        let digits = |p: &mut Parser| {
+            let start = p.pos;
+            while matches!(p.peek(), Some('0'..='9')) {
+                p.pos += 1;
+            }
+            p.pos - start
+        };
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        match digits(self) {
+            0 => return Err(ParseJsonError),
+            n if n > 1 && self.chars[self.pos - n] == '0' => return Err(ParseJsonError),
+            _ => {}
+        }
+        if self.peek() == Some('.') {
+            self.pos += 1;
+            if digits(self) == 0 {
+                return Err(ParseJsonError);
+            }
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.pos += 1;
+            }
+            if digits(self) == 0 {
+                return Err(ParseJsonError);
+            }
+        }
+        Ok(Json::Number(self.chars[start..self.pos].iter().collect()))
+    }
+
+    fn string(&mut self) -> Result<String, ParseJsonError> {
+        if self.bump() != Some('"') {
+            return Err(ParseJsonError);
+        }
+        let mut s = String::new();
+        loop {
+            match self.bump().ok_or(ParseJsonError)? {
+                '"' => return Ok(s),
+                '\\' => {
+                    let c = match self.bump().ok_or(ParseJsonError)? {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => self.unicode_escape()?,
+                        _ => return Err(ParseJsonError),
+                    };
+                    s.push(c);
+                }
+                c if (c as u32) < 0x20 => return Err(ParseJsonError),
+                c => s.push(c),
+            }
+        }
+    }
+
+    // \u のあとの 4 桁。サロゲートは次の \u と組にする
+    fn unicode_escape(&mut self) -> Result<char, ParseJsonError> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or(ParseJsonError);
+        }
+        if self.bump() != Some('\\') || self.bump() != Some('u') {
+            return Err(ParseJsonError);
+        }
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(ParseJsonError);
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)).ok_or(ParseJsonError)
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseJsonError> {
+        (0..4).try_fold(0, |n, _| {
+            let digit = self.bump().and_then(|c| c.to_digit(16));
+            digit.map(|d| n * 16 + d).ok_or(ParseJsonError)
+        })
+    }
+}
+
+// 空白を入れずに書く
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => f.write_str(n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let json: Json = r#" { "a" : { "b" : [1, -2.5e3, "x\ty", null] }, "c": true, "d": "\u00e9\ud83d\ude00" } "#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            json.to_string(),
+            r#"{"a":{"b":[1,-2.5e3,"x\ty",null]},"c":true,"d":"é😀"}"#
+        );
+        assert_eq!(json.to_string().parse::<Json>().unwrap(), json);
+        for invalid in [
+            "", "{", "[1,]", "01", "1.", "{'a': 1}", "[1] 2", "\"\\x\"", "tru", "-",
+        ] {
+            assert!(invalid.parse::<Json>().is_err(), "{}", invalid);
+        }
+        assert!("[".repeat(1000).parse::<Json>().is_err());
+
+        let path = parse_path("$.a.\"b\"[-3]").unwrap();
+        assert_eq!(
+            path,
+            vec![
+                PathStep::Key("a".to_string()),
+                PathStep::Key("b".to_string()),
+                PathStep::Index(-3)
+            ]
+        );
+        assert_eq!(
+            json.get_path(&path),
+            Some(&Json::Number("-2.5e3".to_string()))
+        );
+        assert_eq!(json.get_path(&parse_path("$").unwrap()), Some(&json));
+        assert_eq!(json.get_path(&parse_path("$.a.b[4]").unwrap()), None);
+        assert_eq!(json.get_path(&parse_path("$.c.d").unwrap()), None);
+        for invalid in ["a.b", "$.", "$[x]", "$a", "$.a[1"] {
+            assert_eq!(parse_path(invalid), None, "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+pub mod asyncdb;
+pub mod bench;
+pub mod blob;
+pub mod bloom;
+pub mod btree;
+pub mod buffer;
+pub mod catalog;
+pub mod check;
+pub mod clock;
+pub mod cluster;
+pub mod collation;
+pub mod connection;
+pub mod copy;
+pub mod datetime;
+pub mod decimal;
+pub mod disk;
+pub mod dump;
+pub mod enum_type;
+pub mod executor;
+pub mod format;
+pub mod fulltext;
+pub mod geometry;
+pub mod heap;
+pub mod http;
+pub mod json;
+pub mod lineedit;
+pub mod lock;
+pub mod logical;
+pub mod lsm;
+pub mod lz4;
+pub mod metrics;
+pub mod parquet;
+pub mod persist;
+pub mod pgwire;
+pub mod planner;
+pub mod recovery;
+pub mod regex;
+pub mod replication;
+pub mod result_cache;
+pub mod rtree;
+pub mod session;
+pub mod settings;
+pub mod sha256;
+pub mod slotted;
+pub mod slowlog;
+pub mod sql;
+pub mod sqlite;
+pub mod stats;
+pub mod storage;
+pub mod transaction;
+pub mod trace;
+pub mod tuple;
+pub mod types;
+pub mod uuid;
+pub mod wal;
+
+#[cfg(test)]
+mod crash;
+#[cfg(test)]
+mod sim;
+#[cfg(test)]
+mod testutil;
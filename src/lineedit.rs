@@ -0,0 +1,430 @@
+// 端末から 1 行を編集しながら読む
+//
+// 標準入力が端末なら読む間だけ端末を生の入力にし、カーソルの移動と文字の削除、履歴の呼び出しをここで行う。
+// ← → Home End (Ctrl-B Ctrl-F Ctrl-A Ctrl-E) で動き、Backspace Delete Ctrl-K Ctrl-U Ctrl-W で消し、
+// ↑ ↓ (Ctrl-P Ctrl-N) で履歴を呼ぶ。Ctrl-C は書きかけの行を捨て、空の行の Ctrl-D は入力の終わりにする。
+// 端末でなければそのまま 1 行を読む。文字の幅は 1 とみなす
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+
+// 履歴に残す行の数
+const HISTORY_SIZE: usize = 1000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Input {
+    Line(String),
+    // Ctrl-C で捨てた
+    Interrupted,
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Backspace,
+    Delete,
+    KillEnd,
+    KillStart,
+    KillWord,
+    Enter,
+    Interrupt,
+    // Ctrl-D。行が空なら入力の終わり
+    EndOfFile,
+    Ignore,
+}
+
+pub struct LineEditor {
+    history: Vec<String>,
+}
+
+impl LineEditor {
+    pub fn new(history: Vec<String>) -> Self {
+        let mut editor = Self { history: vec![] };
+        for line in &history {
+            editor.add_history(line);
+        }
+        editor
+    }
+
+    // 空の行と直前と同じ行は足さない
+    pub fn add_history(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        if self.history.len() == HISTORY_SIZE {
+            self.history.remove(0);
+        }
+        self.history.push(line.to_string());
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    // prompt を書いて 1 行を読む。行の終わりの改行は含めない。入力の終わりでは改行を書かない
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}", prompt)?;
+        stdout.flush()?;
+        let stdin = io::stdin();
+        let raw = match stdin.is_terminal() {
+            true => RawMode::enable().ok(),
+            false => None,
+        };
+        let Some(_raw) = raw else {
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(Input::Eof);
+            }
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            return Ok(Input::Line(line));
+        };
+        let mut input = stdin.lock();
+        let mut state = State::new(&self.history);
+        loop {
+            let Some(key) = read_key(&mut input)? else {
+                return Ok(Input::Eof);
+            };
+            if let Some(result) = state.apply(key) {
+                let end = match result {
+                    Input::Line(_) => "\r\n",
+                    Input::Interrupted => "^C\r\n",
+                    Input::Eof => "",
+                };
+                write!(stdout, "{}", end)?;
+                stdout.flush()?;
+                return Ok(result);
+            }
+            state.redraw(&mut stdout, prompt)?;
+        }
+    }
+}
+
+// 編集している行と、呼び出している履歴の位置
+struct State<'a> {
+    history: &'a [String],
+    line: Vec<char>,
+    cursor: usize,
+    // history.len() なら履歴でなく書いている行
+    index: usize,
+    // 履歴を呼ぶ前に書いていた行
+    draft: Vec<char>,
+}
+
+impl<'a> State<'a> {
+    fn new(history: &'a [String]) -> Self {
+        Self {
+            history,
+            line: vec![],
+            cursor: 0,
+            index: history.len(),
+            draft: vec![],
+        }
+    }
+
+    // key で行を変える。行が決まれば返す
+    fn apply(&mut self, key: Key) -> Option<Input> {
+        match key {
+            Key::Char(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::Up if self.index > 0 => self.recall(self.index - 1),
+            Key::Down if self.index < self.history.len() => self.recall(self.index + 1),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            Key::EndOfFile if self.line.is_empty() => return Some(Input::Eof),
+            Key::Delete | Key::EndOfFile if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            Key::KillEnd => self.line.truncate(self.cursor),
+            Key::KillStart => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            // カーソルの前の空白と、その前の単語を消す
+            Key::KillWord => {
+                let mut start = self.cursor;
+                while start > 0 && self.line[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.line[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.line.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Key::Enter => return Some(Input::Line(self.line.iter().collect())),
+            Key::Interrupt => return Some(Input::Interrupted),
+            _ => {}
+        }
+        None
+    }
+
+    fn recall(&mut self, index: usize) {
+        if self.index == self.history.len() {
+            self.draft = std::mem::take(&mut self.line);
+        }
+        self.line = match self.history.get(index) {
+            Some(line) => line.chars().collect(),
+            None => std::mem::take(&mut self.draft),
+        };
+        self.cursor = self.line.len();
+        self.index = index;
+    }
+
+    // 行の始めから書き直し、行の残りを消してカーソルを戻す
+    fn redraw(&self, out: &mut impl Write, prompt: &str) -> io::Result<()> {
+        let line: String = self.line.iter().collect();
+        write!(out, "\r{}{}\x1b[K", prompt, line)?;
+        let back = self.line.len() - self.cursor;
+        if back > 0 {
+            write!(out, "\x1b[{}D", back)?;
+        }
+        out.flush()
+    }
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// 1 つのキーの入力を読む。矢印などのキーはエスケープシーケンスで来る
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        1 => Key::Home,
+        2 => Key::Left,
+        3 => Key::Interrupt,
+        4 => Key::EndOfFile,
+        5 => Key::End,
+        6 => Key::Right,
+        8 | 127 => Key::Backspace,
+        11 => Key::KillEnd,
+        14 => Key::Down,
+        16 => Key::Up,
+        21 => Key::KillStart,
+        23 => Key::KillWord,
+        b'\r' | b'\n' => Key::Enter,
+        27 => read_escape(input)?,
+        byte if byte < 0x20 => Key::Ignore,
+        byte => {
+            let len = match byte {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                0xc0.. => 2,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.extend(read_byte(input)?);
+            }
+            match std::str::from_utf8(&bytes) {
+                Ok(s) => s.chars().next().map_or(Key::Ignore, Key::Char),
+                Err(_) => Key::Ignore,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+// ESC の後ろ。ESC [ の後ろは数字と ; が続き、最後の文字でキーが決まる
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let Some(kind @ (b'[' | b'O')) = read_byte(input)? else {
+        return Ok(Key::Ignore);
+    };
+    let mut param = String::new();
+    let last = loop {
+        match read_byte(input)? {
+            Some(byte) if kind == b'[' && (byte.is_ascii_digit() || byte == b';') => {
+                param.push(byte as char);
+            }
+            Some(byte) => break byte,
+            None => return Ok(Key::Ignore),
+        }
+    };
+    Ok(match (last, param.as_str()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', "1" | "7") => Key::Home,
+        (b'F', _) | (b'~', "4" | "8") => Key::End,
+        (b'~', "3") => Key::Delete,
+        _ => Key::Ignore,
+    })
+}
+
+// 生きている間、標準入力の端末を生の入力にしておく
+struct RawMode {
+    #[cfg(unix)]
+    saved: termios::Termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let saved = termios::get()?;
+        let mut raw = saved.clone();
+        termios::make_raw(&mut raw);
+        termios::set(&raw)?;
+        Ok(Self { saved })
+    }
+}
+
+#[cfg(not(unix))]
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ = termios::set(&self.saved);
+    }
+}
+
+#[cfg(unix)]
+mod termios {
+    use std::io;
+
+    // 中身は libc に任せるので、どの unix の struct termios よりも大きい領域として持つ
+    #[repr(C)]
+    #[derive(Clone)]
+    pub struct Termios([u64; 32]);
+
+    extern "C" {
+        fn tcgetattr(fd: i32, termios: *mut Termios) -> i32;
+        fn tcsetattr(fd: i32, action: i32, termios: *const Termios) -> i32;
+        fn cfmakeraw(termios: *mut Termios);
+    }
+
+    const STDIN: i32 = 0;
+    const TCSADRAIN: i32 = 1;
+
+    pub fn get() -> io::Result<Termios> {
+        let mut termios = Termios([0; 32]);
+        // SAFETY: termios は struct termios を収める大きさがある
+        match unsafe { tcgetattr(STDIN, &mut termios) } {
+            0 => Ok(termios),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub fn set(termios: &Termios) -> io::Result<()> {
+        // SAFETY: termios は tcgetattr で埋めたもの
+        match unsafe { tcsetattr(STDIN, TCSADRAIN, termios) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub fn make_raw(termios: &mut Termios) {
+        // SAFETY: termios は tcgetattr で埋めたもの
+        unsafe { cfmakeraw(termios) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut input = bytes;
+        std::iter::from_fn(|| read_key(&mut input).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_read_key() {
+        assert_eq!(
+            keys("aé\x1b[D\x1b[3~\x1bOH\x1b[1;5C\x7f\x01\x03\r".as_bytes()),
+            [
+                Key::Char('a'),
+                Key::Char('é'),
+                Key::Left,
+                Key::Delete,
+                Key::Home,
+                Key::Right,
+                Key::Backspace,
+                Key::Home,
+                Key::Interrupt,
+                Key::Enter,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edit() {
+        let history = vec!["SELECT 1;".to_string(), "SELECT 2;".to_string()];
+        let mut state = State::new(&history);
+        let type_keys =
+            |state: &mut State, keys: &[Key]| keys.iter().find_map(|&key| state.apply(key));
+        let text = |s: &str| s.chars().map(Key::Char).collect::<Vec<_>>();
+        type_keys(&mut state, &text("SELEC x"));
+        type_keys(&mut state, &[Key::Left, Key::Left, Key::Char('T')]);
+        assert_eq!(state.line.iter().collect::<String>(), "SELECT x");
+        assert_eq!(state.cursor, 6);
+
+        // 履歴を呼んでから戻ると、書いていた行に戻る
+        type_keys(&mut state, &[Key::Up, Key::Up, Key::Up]);
+        assert_eq!(state.line.iter().collect::<String>(), "SELECT 1;");
+        type_keys(&mut state, &[Key::Down, Key::Down]);
+        assert_eq!(state.line.iter().collect::<String>(), "SELECT x");
+        assert_eq!(state.cursor, 8);
+
+        type_keys(&mut state, &[Key::KillWord]);
+        assert_eq!(state.line.iter().collect::<String>(), "SELECT ");
+        type_keys(
+            &mut state,
+            &[Key::Home, Key::Delete, Key::End, Key::Backspace],
+        );
+        assert_eq!(state.line.iter().collect::<String>(), "ELECT");
+        type_keys(
+            &mut state,
+            &[Key::Left, Key::KillEnd, Key::Home, Key::Right],
+        );
+        type_keys(&mut state, &[Key::KillStart]);
+        assert_eq!(state.line.iter().collect::<String>(), "LEC");
+        assert_eq!(
+            type_keys(&mut state, &[Key::EndOfFile, Key::Enter]),
+            Some(Input::Line("EC".to_string()))
+        );
+
+        let mut state = State::new(&history);
+        assert_eq!(
+            type_keys(&mut state, &[Key::Char('x'), Key::Interrupt]),
+            Some(Input::Interrupted)
+        );
+        let mut state = State::new(&history);
+        assert_eq!(type_keys(&mut state, &[Key::EndOfFile]), Some(Input::Eof));
+
+        let mut editor = LineEditor::new(vec![" a ".into(), "a".into(), "".into(), "b".into()]);
+        editor.add_history("b");
+        assert_eq!(editor.history(), ["a", "b"]);
+    }
+}
@@ -0,0 +1,744 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::clock;
+use crate::heap::RecordId;
+use crate::transaction::TxnId;
+
+// ロックの管理
+//
+// テーブルと行に共有 (S) と排他 (X) のロックをかける。テーブルにはさらに意図ロック (IS、IX、SIX) をかけ、
+// 行をロックする前にテーブルに IS か IX をかけておく。こうするとテーブル全体へのロックは、
+// 行のロックを 1 つずつ調べなくてもテーブルのロックどうしを比べるだけで待つかどうかが決まる。
+// ロックごとに与えたものと待っているものの列を持ち、
+// 待っているものには来た順に与える。すでに持っているロックを強めるときは列の先頭で待つ。
+// ロックはトランザクションが終わるまで持ち続け、コミットか取り消しでまとめて外す。
+// インデックスを作るときのように、強めたロックは待たせなくてよくなったところで前のモードに戻せる。
+// 複数のスレッドから使えるように、状態は 1 つの Mutex で守り、ロックを外すたびに待っているものを起こす。
+//
+// deadlock_timeout より長く待ったものは、待っているものが何を待っているかのグラフを作って自分を含む循環を探す。
+// 循環があれば、その中で最も新しいトランザクションのロックを DeadlockDetected で失敗させる。
+//
+// 待つのに上限をつけるときは LockWait で指定する。上限を過ぎたか NOWAIT ですぐに与えられなければ、
+// 列から外れてエラーを返す。
+// LockWait::Park はスレッドを止めずに、列に残したまま WouldBlock を返す。同じロックをもう一度頼めば列の同じ場所から続け、
+// 違うロックを頼むかトランザクションが終われば列から外れる。待つ間に循環を探すものがいないので、列に残す前に探す。
+pub const DEFAULT_DEADLOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("deadlock detected")]
+    DeadlockDetected,
+    #[error("could not obtain lock")]
+    LockNotAvailable,
+    #[error("canceling statement due to lock timeout")]
+    LockTimeout,
+    #[error("waiting for lock")]
+    WouldBlock,
+}
+
+// ロックをすぐに与えられないときの動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockWait {
+    // 与えられるまで待つ
+    Block,
+    // この時間だけ待って LockTimeout を返す
+    Timeout(Duration),
+    // 待たずに LockNotAvailable を返す
+    NoWait,
+    // 列に残して WouldBlock を返す
+    Park,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LockTarget {
+    Table(String),
+    Row(String, RecordId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    // IS。中の行を共有ロックする
+    IntentionShared,
+    // IX。中の行を排他ロックする
+    IntentionExclusive,
+    Shared,
+    // SIX。全体を読み、中の行を排他ロックする
+    SharedIntentionExclusive,
+    Exclusive,
+}
+
+impl LockMode {
+    pub fn compatible(self, other: LockMode) -> bool {
+        use LockMode::*;
+        match (self, other) {
+            (Exclusive, _) | (_, Exclusive) => false,
+            (IntentionShared, _) | (_, IntentionShared) => true,
+            (IntentionExclusive, IntentionExclusive) | (Shared, Shared) => true,
+            _ => false,
+        }
+    }
+
+    // 両方を持つのと同じ強さのモード
+    fn combine(self, other: LockMode) -> LockMode {
+        use LockMode::*;
+        match (self, other) {
+            _ if self == other => self,
+            (IntentionShared, mode) | (mode, IntentionShared) => mode,
+            (Exclusive, _) | (_, Exclusive) => Exclusive,
+            // 残りは S、IX、SIX のうちの異なる 2 つ
+            _ => SharedIntentionExclusive,
+        }
+    }
+
+    // このモードで持っていれば other を求めなくてよいか
+    fn covers(self, other: LockMode) -> bool {
+        self.combine(other) == self
+    }
+}
+
+#[derive(Debug, Default)]
+struct LockQueue {
+    granted: Vec<(TxnId, LockMode)>,
+    waiting: VecDeque<(TxnId, LockMode)>,
+}
+
+impl LockQueue {
+    fn can_grant(&self, txn: TxnId, mode: LockMode) -> bool {
+        self.granted
+            .iter()
+            .all(|&(holder, held)| holder == txn || held.compatible(mode))
+    }
+}
+
+#[derive(Debug, Default)]
+struct LockTable {
+    queues: HashMap<LockTarget, LockQueue>,
+    // トランザクションごとの持っているロック
+    held: HashMap<TxnId, Vec<LockTarget>>,
+    // デッドロックを解くために、待つのをやめさせるトランザクション
+    victims: HashSet<TxnId>,
+    // Park で列に残して戻ったトランザクションと、その待っているロック
+    parked: HashMap<TxnId, (LockTarget, LockMode)>,
+}
+
+impl LockTable {
+    // 待っているトランザクションから、それが待っている相手への辺。
+    // 来た順に与えるので、持っているものだけでなく先に待っているものも待つ
+    fn waits_for(&self) -> HashMap<TxnId, Vec<TxnId>> {
+        let mut graph: HashMap<_, Vec<_>> = HashMap::new();
+        for queue in self.queues.values() {
+            for (i, &(waiter, mode)) in queue.waiting.iter().enumerate() {
+                let edges = graph.entry(waiter).or_default();
+                edges.extend(
+                    queue
+                        .granted
+                        .iter()
+                        .filter(|&&(holder, held)| holder != waiter && !held.compatible(mode))
+                        .map(|&(holder, _)| holder),
+                );
+                edges.extend(
+                    queue
+                        .waiting
+                        .iter()
+                        .take(i)
+                        .map(|&(ahead, _)| ahead)
+                        .filter(|&ahead| ahead != waiter),
+                );
+            }
+        }
+        graph
+    }
+
+    // txn から出て txn に戻る循環
+    fn find_cycle(&self, txn: TxnId) -> Option<Vec<TxnId>> {
+        fn visit(
+            graph: &HashMap<TxnId, Vec<TxnId>>,
+            node: TxnId,
+            path: &mut Vec<TxnId>,
+            visited: &mut HashSet<TxnId>,
+        ) -> bool {
+            for &next in graph.get(&node).into_iter().flatten() {
+                if next == path[0] {
+                    return true;
+                }
+                if visited.insert(next) {
+                    path.push(next);
+                    if visit(graph, next, path, visited) {
+                        return true;
+                    }
+                    path.pop();
+                }
+            }
+            false
+        }
+        let graph = self.waits_for();
+        let mut path = vec![txn];
+        visit(&graph, txn, &mut path, &mut HashSet::new()).then_some(path)
+    }
+
+    // 待つのをやめて列から外れる
+    fn cancel(&mut self, txn: TxnId, target: &LockTarget) {
+        let queue = self.queues.get_mut(target).unwrap();
+        queue.waiting.retain(|&(waiter, _)| waiter != txn);
+        if queue.granted.is_empty() && queue.waiting.is_empty() {
+            self.queues.remove(target);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LockManager {
+    state: Mutex<LockTable>,
+    released: Condvar,
+    deadlock_timeout: Duration,
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self::with_deadlock_timeout(DEFAULT_DEADLOCK_TIMEOUT)
+    }
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_deadlock_timeout(deadlock_timeout: Duration) -> Self {
+        Self {
+            state: Mutex::new(LockTable::default()),
+            released: Condvar::new(),
+            deadlock_timeout,
+        }
+    }
+
+    // ロックを与えられるまで待つ。デッドロックを解くために選ばれたら DeadlockDetected を返す
+    pub fn lock(&self, txn: TxnId, target: LockTarget, mode: LockMode) -> Result<(), Error> {
+        self.lock_with(txn, target, mode, LockWait::Block)
+    }
+
+    pub fn lock_with(
+        &self,
+        txn: TxnId,
+        target: LockTarget,
+        mode: LockMode,
+        wait: LockWait,
+    ) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let queue = state.queues.entry(target.clone()).or_default();
+        let held = queue
+            .granted
+            .iter()
+            .find(|(holder, _)| *holder == txn)
+            .map(|&(_, held)| held);
+        let wanted = held.map_or(mode, |held| held.combine(mode));
+        let resumed = match state.parked.remove(&txn) {
+            Some(parked) if parked == (target.clone(), wanted) => true,
+            Some((other, _)) => {
+                state.cancel(txn, &other);
+                self.released.notify_all();
+                false
+            }
+            None => false,
+        };
+        if held.is_some_and(|held| held.covers(mode)) {
+            return Ok(());
+        }
+        let mode = wanted;
+        if !resumed {
+            let queue = state.queues.entry(target.clone()).or_default();
+            // 強めるのを後ろで待たせると、持っているロックを待つ相手と互いに待ち続けてしまう
+            if held.is_some() {
+                queue.waiting.push_front((txn, mode));
+            } else {
+                queue.waiting.push_back((txn, mode));
+            }
+        }
+        let start = clock::now();
+        let mut deadline = start + self.deadlock_timeout;
+        loop {
+            if state.victims.remove(&txn) {
+                state.cancel(txn, &target);
+                self.released.notify_all();
+                return Err(Error::DeadlockDetected);
+            }
+            let table = &mut *state;
+            let queue = table.queues.get_mut(&target).unwrap();
+            if queue.waiting.front() == Some(&(txn, mode)) && queue.can_grant(txn, mode) {
+                queue.waiting.pop_front();
+                match queue.granted.iter_mut().find(|(holder, _)| *holder == txn) {
+                    Some(granted) => granted.1 = mode,
+                    None => {
+                        queue.granted.push((txn, mode));
+                        table.held.entry(txn).or_default().push(target);
+                    }
+                }
+                // 次に待っているものも与えられるかもしれない
+                self.released.notify_all();
+                return Ok(());
+            }
+            let now = clock::now();
+            let error = match wait {
+                LockWait::NoWait => Some(Error::LockNotAvailable),
+                LockWait::Timeout(timeout) if now >= start + timeout => Some(Error::LockTimeout),
+                LockWait::Park => {
+                    let victim = state
+                        .find_cycle(txn)
+                        .and_then(|cycle| cycle.into_iter().max());
+                    if victim == Some(txn) {
+                        Some(Error::DeadlockDetected)
+                    } else {
+                        if let Some(victim) = victim {
+                            state.victims.insert(victim);
+                            self.released.notify_all();
+                        }
+                        state.parked.insert(txn, (target, mode));
+                        return Err(Error::WouldBlock);
+                    }
+                }
+                _ => None,
+            };
+            if let Some(error) = error {
+                state.cancel(txn, &target);
+                self.released.notify_all();
+                return Err(error);
+            }
+            if now >= deadline {
+                if let Some(cycle) = state.find_cycle(txn) {
+                    let victim = cycle.into_iter().max().unwrap();
+                    state.victims.insert(victim);
+                    self.released.notify_all();
+                }
+                deadline = now + self.deadlock_timeout;
+                continue;
+            }
+            let until = match wait {
+                LockWait::Timeout(timeout) => deadline.min(start + timeout),
+                _ => deadline,
+            };
+            // 仮想の時計と、ほかのスレッドのない wasm32 では、外すのを待たずに時刻だけを進める
+            if clock::is_virtual() || cfg!(target_arch = "wasm32") {
+                clock::sleep(until - now);
+                continue;
+            }
+            state = self.released.wait_timeout(state, until - now).unwrap().0;
+        }
+    }
+
+    // トランザクションが終わったときに、持っているロックをすべて外す
+    pub fn release_all(&self, txn: TxnId) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((target, _)) = state.parked.remove(&txn) {
+            state.cancel(txn, &target);
+            state.victims.remove(&txn);
+        }
+        let Some(targets) = state.held.remove(&txn) else {
+            return;
+        };
+        for target in targets {
+            let queue = state.queues.get_mut(&target).unwrap();
+            queue.granted.retain(|(holder, _)| *holder != txn);
+            if queue.granted.is_empty() && queue.waiting.is_empty() {
+                state.queues.remove(&target);
+            }
+        }
+        self.released.notify_all();
+    }
+
+    // Park で列に残したロックを待つのをやめる
+    pub fn stop_waiting(&self, txn: TxnId) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((target, _)) = state.parked.remove(&txn) {
+            state.cancel(txn, &target);
+            state.victims.remove(&txn);
+            self.released.notify_all();
+        }
+    }
+
+    // target のロックを mode に戻す。None なら外す。
+    // トランザクションの途中で、強めたロックでほかのものを待たせなくてよくなったときに使う
+    pub fn restore(&self, txn: TxnId, target: &LockTarget, mode: Option<LockMode>) {
+        let mut state = self.state.lock().unwrap();
+        let Some(queue) = state.queues.get_mut(target) else {
+            return;
+        };
+        match mode {
+            Some(mode) => {
+                if let Some(granted) = queue.granted.iter_mut().find(|(holder, _)| *holder == txn) {
+                    granted.1 = mode;
+                }
+            }
+            None => {
+                queue.granted.retain(|(holder, _)| *holder != txn);
+                if queue.granted.is_empty() && queue.waiting.is_empty() {
+                    state.queues.remove(target);
+                }
+                if let Some(targets) = state.held.get_mut(&txn) {
+                    targets.retain(|t| t != target);
+                }
+            }
+        }
+        self.released.notify_all();
+    }
+
+    // トランザクションが持っているロックを、かけた順に
+    pub fn locks(&self, txn: TxnId) -> Vec<(LockTarget, LockMode)> {
+        let state = self.state.lock().unwrap();
+        let Some(targets) = state.held.get(&txn) else {
+            return vec![];
+        };
+        targets
+            .iter()
+            .map(|target| {
+                let queue = &state.queues[target];
+                let &(_, mode) = queue
+                    .granted
+                    .iter()
+                    .find(|(holder, _)| *holder == txn)
+                    .unwrap();
+                (target.clone(), mode)
+            })
+            .collect()
+    }
+
+    // target を持っているトランザクションと、そのモード
+    pub fn holders(&self, target: &LockTarget) -> Vec<(TxnId, LockMode)> {
+        let state = self.state.lock().unwrap();
+        state
+            .queues
+            .get(target)
+            .map_or_else(Vec::new, |queue| queue.granted.clone())
+    }
+
+    // ロックを待っているトランザクションの数
+    pub fn waiting(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.queues.values().map(|q| q.waiting.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::disk::PageId;
+
+    fn table(name: &str) -> LockTarget {
+        LockTarget::Table(name.to_string())
+    }
+
+    // 別のスレッドでロックをかけ、与えられたら知らせる
+    fn spawn_lock(
+        locks: &Arc<LockManager>,
+        txn: u64,
+        target: LockTarget,
+        mode: LockMode,
+        granted: &mpsc::Sender<u64>,
+    ) -> thread::JoinHandle<Result<(), Error>> {
+        let locks = locks.clone();
+        let granted = granted.clone();
+        thread::spawn(move || {
+            locks.lock(TxnId(txn), target, mode)?;
+            granted.send(txn).unwrap();
+            Ok(())
+        })
+    }
+
+    // 待っているものが count になるまで待つ
+    fn wait_for(locks: &LockManager, count: usize) {
+        while locks.waiting() != count {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_lock_wait_queue() {
+        let locks = Arc::new(LockManager::new());
+        let (sender, granted) = mpsc::channel();
+        // 共有ロックは同時に持てる
+        locks.lock(TxnId(1), table("t"), LockMode::Shared).unwrap();
+        locks.lock(TxnId(2), table("t"), LockMode::Shared).unwrap();
+        locks.lock(TxnId(2), table("t"), LockMode::Shared).unwrap();
+        assert_eq!(locks.locks(TxnId(2)), [(table("t"), LockMode::Shared)]);
+
+        // 排他ロックは共有ロックが外れるまで待ち、後から来た共有ロックはその後ろで待つ
+        let x = spawn_lock(&locks, 3, table("t"), LockMode::Exclusive, &sender);
+        wait_for(&locks, 1);
+        let s = spawn_lock(&locks, 4, table("t"), LockMode::Shared, &sender);
+        wait_for(&locks, 2);
+        locks.release_all(TxnId(1));
+        locks.release_all(TxnId(2));
+        x.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 3);
+        assert_eq!(locks.waiting(), 1);
+        locks.release_all(TxnId(3));
+        s.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 4);
+
+        // ほかのものには関係しない
+        let rid = RecordId {
+            page_id: PageId(0),
+            slot: 0,
+        };
+        locks
+            .lock(
+                TxnId(5),
+                LockTarget::Row("t".to_string(), rid),
+                LockMode::Exclusive,
+            )
+            .unwrap();
+        locks.release_all(TxnId(4));
+        locks.release_all(TxnId(5));
+        assert!(locks.locks(TxnId(4)).is_empty());
+        assert_eq!(locks.state.lock().unwrap().queues.len(), 0);
+    }
+
+    #[test]
+    fn test_lock_upgrade() {
+        let locks = Arc::new(LockManager::new());
+        let (sender, granted) = mpsc::channel();
+        // ほかに持っているものがいなければすぐに強められる
+        locks.lock(TxnId(1), table("t"), LockMode::Shared).unwrap();
+        locks
+            .lock(TxnId(1), table("t"), LockMode::Exclusive)
+            .unwrap();
+        locks.lock(TxnId(1), table("t"), LockMode::Shared).unwrap();
+        assert_eq!(locks.locks(TxnId(1)), [(table("t"), LockMode::Exclusive)]);
+        locks.release_all(TxnId(1));
+
+        // 強めるものは、先に待っていたものより前に与えられる
+        locks.lock(TxnId(1), table("t"), LockMode::Shared).unwrap();
+        locks.lock(TxnId(2), table("t"), LockMode::Shared).unwrap();
+        let waiter = spawn_lock(&locks, 3, table("t"), LockMode::Exclusive, &sender);
+        wait_for(&locks, 1);
+        let upgrade = spawn_lock(&locks, 1, table("t"), LockMode::Exclusive, &sender);
+        wait_for(&locks, 2);
+        locks.release_all(TxnId(2));
+        upgrade.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 1);
+        assert_eq!(locks.locks(TxnId(1)), [(table("t"), LockMode::Exclusive)]);
+        locks.release_all(TxnId(1));
+        waiter.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_lock_restore() {
+        let locks = Arc::new(LockManager::new());
+        let (sender, granted) = mpsc::channel();
+        // 前のモードに戻せば、それと両立するものは待たなくなる
+        locks
+            .lock(TxnId(1), table("t"), LockMode::IntentionExclusive)
+            .unwrap();
+        locks.lock(TxnId(1), table("t"), LockMode::Shared).unwrap();
+        let waiter = spawn_lock(&locks, 2, table("t"), LockMode::IntentionExclusive, &sender);
+        wait_for(&locks, 1);
+        locks.restore(TxnId(1), &table("t"), Some(LockMode::IntentionExclusive));
+        waiter.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 2);
+        assert_eq!(
+            locks.locks(TxnId(1)),
+            [(table("t"), LockMode::IntentionExclusive)]
+        );
+
+        // 外したものはトランザクションが持っているものからも消える
+        locks.lock(TxnId(1), table("u"), LockMode::Shared).unwrap();
+        locks.restore(TxnId(1), &table("u"), None);
+        assert_eq!(
+            locks.locks(TxnId(1)),
+            [(table("t"), LockMode::IntentionExclusive)]
+        );
+        locks.release_all(TxnId(1));
+        locks.release_all(TxnId(2));
+        assert_eq!(locks.state.lock().unwrap().queues.len(), 0);
+    }
+
+    #[test]
+    fn test_intention_locks() {
+        use LockMode::*;
+
+        let modes = [
+            IntentionShared,
+            IntentionExclusive,
+            Shared,
+            SharedIntentionExclusive,
+            Exclusive,
+        ];
+        let matrix = [
+            [true, true, true, true, false],
+            [true, true, false, false, false],
+            [true, false, true, false, false],
+            [true, false, false, false, false],
+            [false, false, false, false, false],
+        ];
+        for (i, a) in modes.iter().enumerate() {
+            for (j, b) in modes.iter().enumerate() {
+                assert_eq!(a.compatible(*b), matrix[i][j], "{:?} {:?}", a, b);
+            }
+        }
+
+        let locks = Arc::new(LockManager::new());
+        let (sender, granted) = mpsc::channel();
+        // S を持ったまま IX を求めると SIX になる
+        locks.lock(TxnId(1), table("t"), Shared).unwrap();
+        locks
+            .lock(TxnId(1), table("t"), IntentionExclusive)
+            .unwrap();
+        assert_eq!(
+            locks.locks(TxnId(1)),
+            [(table("t"), SharedIntentionExclusive)]
+        );
+        locks.lock(TxnId(2), table("t"), IntentionShared).unwrap();
+        locks.release_all(TxnId(1));
+        locks.release_all(TxnId(2));
+
+        // 行を読むものと書き換えるものは同時に持てる。書き換えているものがいれば、テーブル全体の共有ロックは待つ
+        locks
+            .lock(TxnId(3), table("t"), IntentionExclusive)
+            .unwrap();
+        locks
+            .lock_with(TxnId(5), table("t"), IntentionShared, LockWait::NoWait)
+            .unwrap();
+        let s = spawn_lock(&locks, 4, table("t"), Shared, &sender);
+        wait_for(&locks, 1);
+        locks.release_all(TxnId(3));
+        s.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 4);
+        let result = locks.lock_with(TxnId(6), table("t"), IntentionExclusive, LockWait::NoWait);
+        assert!(matches!(result, Err(Error::LockNotAvailable)));
+    }
+
+    #[test]
+    fn test_deadlock_detection() {
+        let locks = Arc::new(LockManager::with_deadlock_timeout(Duration::from_millis(
+            10,
+        )));
+        let (sender, granted) = mpsc::channel();
+        // 互いに相手の持っているロックを待つと、新しい方が失敗する
+        locks
+            .lock(TxnId(1), table("a"), LockMode::Exclusive)
+            .unwrap();
+        locks
+            .lock(TxnId(2), table("b"), LockMode::Exclusive)
+            .unwrap();
+        let older = spawn_lock(&locks, 1, table("b"), LockMode::Exclusive, &sender);
+        let younger = spawn_lock(&locks, 2, table("a"), LockMode::Exclusive, &sender);
+        assert!(matches!(
+            younger.join().unwrap(),
+            Err(Error::DeadlockDetected)
+        ));
+        locks.release_all(TxnId(2));
+        older.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 1);
+        locks.release_all(TxnId(1));
+
+        // 共有ロックを 2 つのトランザクションが同時に強めようとしても循環になる
+        locks.lock(TxnId(3), table("a"), LockMode::Shared).unwrap();
+        locks.lock(TxnId(4), table("a"), LockMode::Shared).unwrap();
+        let older = spawn_lock(&locks, 3, table("a"), LockMode::Exclusive, &sender);
+        wait_for(&locks, 1);
+        let younger = spawn_lock(&locks, 4, table("a"), LockMode::Exclusive, &sender);
+        assert!(matches!(
+            younger.join().unwrap(),
+            Err(Error::DeadlockDetected)
+        ));
+        locks.release_all(TxnId(4));
+        older.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 3);
+
+        // 循環がなければ待ち続ける
+        let waiter = spawn_lock(&locks, 5, table("a"), LockMode::Shared, &sender);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(locks.waiting(), 1);
+        locks.release_all(TxnId(3));
+        waiter.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 5);
+    }
+
+    #[test]
+    fn test_lock_wait_limit() {
+        let locks = Arc::new(LockManager::new());
+        locks
+            .lock(TxnId(1), table("t"), LockMode::Exclusive)
+            .unwrap();
+        // NOWAIT はすぐに失敗し、列に残らない
+        let result = locks.lock_with(TxnId(2), table("t"), LockMode::Shared, LockWait::NoWait);
+        assert!(matches!(result, Err(Error::LockNotAvailable)));
+        assert_eq!(locks.waiting(), 0);
+        // 与えられるなら NOWAIT でもかけられる
+        locks
+            .lock_with(TxnId(2), table("u"), LockMode::Shared, LockWait::NoWait)
+            .unwrap();
+
+        // 上限まで待ってから失敗する
+        let start = Instant::now();
+        let timeout = LockWait::Timeout(Duration::from_millis(20));
+        let result = locks.lock_with(TxnId(3), table("t"), LockMode::Shared, timeout);
+        assert!(matches!(result, Err(Error::LockTimeout)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(locks.waiting(), 0);
+
+        // 上限までに外れれば与えられる
+        let (sender, granted) = mpsc::channel();
+        let waiter = {
+            let locks = locks.clone();
+            thread::spawn(move || {
+                let timeout = LockWait::Timeout(Duration::from_secs(10));
+                locks.lock_with(TxnId(3), table("t"), LockMode::Shared, timeout)?;
+                sender.send(3).unwrap();
+                Ok::<_, Error>(())
+            })
+        };
+        wait_for(&locks, 1);
+        locks.release_all(TxnId(1));
+        waiter.join().unwrap().unwrap();
+        assert_eq!(granted.recv().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_park() {
+        use LockMode::*;
+        let locks = LockManager::new();
+        let park = |txn: u64, name: &str, mode: LockMode| {
+            locks.lock_with(TxnId(txn), table(name), mode, LockWait::Park)
+        };
+        locks.lock(TxnId(1), table("t"), Exclusive).unwrap();
+        // 列に残して戻り、もう一度頼んでも並び直さない
+        assert!(matches!(park(2, "t", Shared), Err(Error::WouldBlock)));
+        assert!(matches!(park(2, "t", Shared), Err(Error::WouldBlock)));
+        assert!(matches!(park(3, "t", Exclusive), Err(Error::WouldBlock)));
+        assert_eq!(locks.waiting(), 2);
+        // 外れても来た順にしか与えない
+        locks.release_all(TxnId(1));
+        assert!(matches!(park(3, "t", Exclusive), Err(Error::WouldBlock)));
+        park(2, "t", Shared).unwrap();
+        // 違うロックを頼むか待つのをやめれば列から外れる
+        assert!(matches!(park(3, "u", Shared), Ok(())));
+        assert_eq!(locks.waiting(), 0);
+        assert!(matches!(park(3, "t", Exclusive), Err(Error::WouldBlock)));
+        locks.stop_waiting(TxnId(3));
+        assert_eq!(locks.waiting(), 0);
+
+        // 循環になれば、新しい方が次に頼んだときに失敗する
+        assert!(matches!(park(3, "t", Exclusive), Err(Error::WouldBlock)));
+        assert!(matches!(park(2, "u", Exclusive), Err(Error::WouldBlock)));
+        assert!(matches!(
+            park(3, "t", Exclusive),
+            Err(Error::DeadlockDetected)
+        ));
+        locks.release_all(TxnId(3));
+        park(2, "u", Exclusive).unwrap();
+        // 自分が新しい方なら、列に残さずにすぐ失敗する
+        locks.lock(TxnId(4), table("v"), Exclusive).unwrap();
+        assert!(matches!(park(2, "v", Shared), Err(Error::WouldBlock)));
+        assert!(matches!(park(4, "u", Shared), Err(Error::DeadlockDetected)));
+        assert_eq!(locks.waiting(), 1);
+        locks.release_all(TxnId(2));
+        assert_eq!(locks.waiting(), 0);
+    }
+}
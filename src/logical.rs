@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::wal::{self, LogRecord, Lsn, RowChange, WalReader};
+
+// 論理デコード
+//
+// トランザクションはコミットするとき (2 相コミットでは準備するとき) に、取り消されずに残った行の変更を
+// Changes のレコードに書く。トランザクションの外の変更は、文ごとに txn のない Changes のレコードに書く。
+// デコードはログを読んでトランザクションごとに変更をためておき、コミットのレコードが来たらまとめて返す。
+// 取り消したトランザクションの変更は Abort か End のレコードで捨てる。
+//
+// 読む側は名前を付けたスロットを作り、受け取り終わったコミットを confirm で知らせる。
+// スロットはログのディレクトリに <名前>.slot として | 受け取り終わったコミット (8) | 読み始める位置 (8) | を残すので、
+// 読む側が落ちても続きから読める。読み始める位置は、受け取り終わった時点で終わっていなかった
+// トランザクションの最初の Changes のレコードで、チェックポイントはこれより前のセグメントしか手放さない。
+
+const SLOT_EXTENSION: &str = "slot";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Wal(#[from] wal::Error),
+    #[error("replication slot \"{0}\" already exists")]
+    SlotExists(String),
+    #[error("replication slot \"{0}\" does not exist")]
+    NoSuchSlot(String),
+    #[error("invalid replication slot name \"{0}\"")]
+    BadSlotName(String),
+    #[error("replication slot \"{0}\" is corrupted")]
+    CorruptedSlot(String),
+}
+
+// コミットしたトランザクションの 1 行の変更
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    // コミットのレコード。トランザクションの外の変更では Changes のレコード
+    pub commit_lsn: Lsn,
+    pub txn: Option<u64>,
+    pub change: RowChange,
+}
+
+#[derive(Debug)]
+pub struct Slot {
+    dir: PathBuf,
+    name: String,
+    // ここまでのコミットは受け取った
+    confirmed: Lsn,
+    restart: Lsn,
+}
+
+impl Slot {
+    // start より後ろでコミットしたものを読むスロットを作る。start はふつう Wal::next_lsn
+    pub fn create(dir: &Path, name: &str, start: Lsn) -> Result<Self, Error> {
+        if slot_path(dir, name)?.exists() {
+            return Err(Error::SlotExists(name.to_string()));
+        }
+        let slot = Self {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            confirmed: Lsn::INVALID_LSN,
+            restart: start,
+        };
+        slot.save()?;
+        Ok(slot)
+    }
+
+    pub fn open(dir: &Path, name: &str) -> Result<Self, Error> {
+        let bytes = match fs::read(slot_path(dir, name)?) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::NoSuchSlot(name.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let (confirmed, restart) =
+            parse_slot(&bytes).ok_or_else(|| Error::CorruptedSlot(name.to_string()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            confirmed,
+            restart,
+        })
+    }
+
+    pub fn drop_slot(dir: &Path, name: &str) -> Result<(), Error> {
+        match fs::remove_file(slot_path(dir, name)?) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(Error::NoSuchSlot(name.to_string()))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    pub fn confirmed_lsn(&self) -> Lsn {
+        self.confirmed
+    }
+
+    pub fn restart_lsn(&self) -> Lsn {
+        self.restart
+    }
+
+    // まだ受け取っていないコミットの変更を、コミットの順に返す。until より後ろのレコードは読まない。
+    // until はふつう Wal::flushed_lsn で、confirm するまでは同じ変更を何度でも返す
+    pub fn poll(&self, until: Lsn) -> Result<Vec<ChangeEvent>, Error> {
+        let mut events = vec![];
+        self.scan(until, |event| events.push(event))?;
+        Ok(events)
+    }
+
+    // commit_lsn のコミットまでの変更を受け取ったことを残す
+    pub fn confirm(&mut self, commit_lsn: Lsn) -> Result<(), Error> {
+        if commit_lsn <= self.confirmed {
+            return Ok(());
+        }
+        self.restart = self.scan(Lsn(commit_lsn.0 + 1), |_| {})?;
+        self.confirmed = commit_lsn;
+        self.save()
+    }
+
+    // restart から until の手前まで読んで、受け取っていないコミットの変更を emit に渡す。
+    // 読み終わった時点で終わっていないトランザクションがあればその最初のレコード、なければ読み終わった位置を返す
+    fn scan(&self, until: Lsn, mut emit: impl FnMut(ChangeEvent)) -> Result<Lsn, Error> {
+        let mut pending: HashMap<u64, (Lsn, Vec<RowChange>)> = HashMap::new();
+        let mut publish = |commit_lsn: Lsn, txn: Option<u64>, changes: Vec<RowChange>| {
+            if commit_lsn > self.confirmed {
+                for change in changes {
+                    emit(ChangeEvent {
+                        commit_lsn,
+                        txn,
+                        change,
+                    });
+                }
+            }
+        };
+        let mut reader = WalReader::new(&self.dir, self.restart);
+        let mut end = self.restart;
+        while let Some((lsn, record)) = reader.next_record()? {
+            if lsn >= until {
+                break;
+            }
+            end = reader.lsn();
+            match record {
+                LogRecord::Changes {
+                    txn: None, changes, ..
+                } => publish(lsn, None, changes),
+                LogRecord::Changes {
+                    txn: Some(txn),
+                    changes,
+                    ..
+                } => pending
+                    .entry(txn)
+                    .or_insert((lsn, vec![]))
+                    .1
+                    .extend(changes),
+                LogRecord::Commit { txn, .. } => {
+                    if let Some((_, changes)) = pending.remove(&txn) {
+                        publish(lsn, Some(txn), changes);
+                    }
+                }
+                LogRecord::Abort { txn, .. } | LogRecord::End { txn, .. } => {
+                    pending.remove(&txn);
+                }
+                _ => {}
+            }
+        }
+        Ok(pending
+            .values()
+            .map(|&(first, _)| first)
+            .min()
+            .unwrap_or(end))
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let path = slot_path(&self.dir, &self.name)?;
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&self.confirmed.0.to_be_bytes())?;
+        file.write_all(&self.restart.0.to_be_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+// dir のスロットがまだ読むかもしれない最初の位置。スロットがなければ None
+pub fn restart_lsn(dir: &Path) -> io::Result<Option<Lsn>> {
+    let mut restart = None;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == SLOT_EXTENSION) {
+            // 壊れたスロットは読めないので、ログを残す理由にしない
+            if let Some((_, lsn)) = parse_slot(&fs::read(&path)?) {
+                restart = Some(restart.map_or(lsn, |restart: Lsn| restart.min(lsn)));
+            }
+        }
+    }
+    Ok(restart)
+}
+
+fn slot_path(dir: &Path, name: &str) -> Result<PathBuf, Error> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::BadSlotName(name.to_string()));
+    }
+    Ok(dir.join(name).with_extension(SLOT_EXTENSION))
+}
+
+fn parse_slot(bytes: &[u8]) -> Option<(Lsn, Lsn)> {
+    let bytes: [u8; 16] = bytes.try_into().ok()?;
+    Some((
+        Lsn(u64::from_be_bytes(bytes[..8].try_into().unwrap())),
+        Lsn(u64::from_be_bytes(bytes[8..].try_into().unwrap())),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferPoolManager;
+    use crate::catalog::Catalog;
+    use crate::executor::tests::{int, setup, text};
+    use crate::executor::{execute, ExecContext};
+    use crate::planner::Planner;
+    use crate::sql::{self, ast::Statement};
+    use crate::testutil::temp_path;
+    use crate::transaction::TransactionManager;
+    use crate::wal::Wal;
+
+    fn run(
+        manager: &mut TransactionManager,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+        sql: &str,
+    ) {
+        for stmt in sql::parse(sql).unwrap() {
+            if let Statement::Transaction(stmt) = &stmt {
+                manager.execute(stmt, bufmgr, catalog).unwrap();
+                continue;
+            }
+            let plan = Planner::new(catalog).plan_statement(&stmt).unwrap();
+            let mut ctx = ExecContext::new(bufmgr, catalog);
+            ctx.txn = manager.start_statement();
+            execute(&plan, &mut ctx).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_logical_decoding() {
+        let dir = temp_path("wal");
+        let (mut bufmgr, catalog) = setup(&[vec![int(1), text("x")], vec![int(2), text("y")]]);
+        bufmgr.set_wal(Wal::open(&dir).unwrap());
+        let start = bufmgr.wal_mut().unwrap().next_lsn();
+        let mut slot = Slot::create(&dir, "cdc", start).unwrap();
+        assert!(matches!(
+            Slot::create(&dir, "cdc", start),
+            Err(Error::SlotExists(_))
+        ));
+        assert!(matches!(
+            Slot::create(&dir, "../x", start),
+            Err(Error::BadSlotName(_))
+        ));
+
+        let mut manager = TransactionManager::new();
+        let mut other = manager.session();
+        // 準備しただけのものはまだ返さない
+        run(
+            &mut other,
+            &mut bufmgr,
+            &catalog,
+            "BEGIN; DELETE FROM t WHERE a = 2; PREPARE TRANSACTION 'g'",
+        );
+        // 1 つめはトランザクションの外、2 つめはセーブポイントまで戻した分を除いてコミット、3 つめは取り消す
+        run(
+            &mut manager,
+            &mut bufmgr,
+            &catalog,
+            "INSERT INTO t VALUES (3, 'z')",
+        );
+        run(
+            &mut manager,
+            &mut bufmgr,
+            &catalog,
+            "BEGIN; INSERT INTO t VALUES (4, 'p'); SAVEPOINT s; INSERT INTO t VALUES (5, 'q'); \
+             ROLLBACK TO s; UPDATE t SET b = 'w' WHERE a = 1; COMMIT",
+        );
+        run(
+            &mut manager,
+            &mut bufmgr,
+            &catalog,
+            "BEGIN; DELETE FROM t WHERE a = 3; ROLLBACK",
+        );
+
+        let until = bufmgr.wal_mut().unwrap().next_lsn();
+        let events = slot.poll(until).unwrap();
+        let changes = events
+            .iter()
+            .map(|event| event.change.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                RowChange::Insert {
+                    table: "t".to_string(),
+                    new: vec![int(3), text("z")]
+                },
+                RowChange::Insert {
+                    table: "t".to_string(),
+                    new: vec![int(4), text("p")]
+                },
+                RowChange::Update {
+                    table: "t".to_string(),
+                    old: vec![int(1), text("x")],
+                    new: vec![int(1), text("w")]
+                },
+            ]
+        );
+        assert_eq!(events[0].txn, None);
+        assert!(events[1].txn.is_some());
+        assert_eq!(events[1].commit_lsn, events[2].commit_lsn);
+        assert_eq!(slot.poll(until).unwrap(), events);
+
+        // 受け取ったところはスロットに残り、開き直しても返さない。
+        // 準備したトランザクションの変更はまだ返していないので、そこから読み直す
+        slot.confirm(events[2].commit_lsn).unwrap();
+        let restart = slot.restart_lsn();
+        assert!(restart < events[2].commit_lsn);
+        assert_eq!(restart_lsn(&dir).unwrap(), Some(restart));
+        run(&mut manager, &mut bufmgr, &catalog, "COMMIT PREPARED 'g'");
+        let until = bufmgr.wal_mut().unwrap().next_lsn();
+        let slot = Slot::open(&dir, "cdc").unwrap();
+        let events = slot.poll(until).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].change,
+            RowChange::Delete {
+                table: "t".to_string(),
+                old: vec![int(2), text("y")]
+            }
+        );
+
+        Slot::drop_slot(&dir, "cdc").unwrap();
+        assert!(matches!(Slot::open(&dir, "cdc"), Err(Error::NoSuchSlot(_))));
+        assert_eq!(restart_lsn(&dir).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,812 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::mem;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::bloom::BloomFilter;
+use crate::buffer::{Buffer, BufferPoolManager};
+use crate::disk::{PageId, PAGE_SIZE};
+use crate::heap::{Error, RecordId, TupleHeader, MAX_TUPLE_SIZE};
+use crate::storage::{TableAccess, TableScan};
+use crate::transaction::TxnId;
+
+// 書き込みの多いテーブルのための LSM-tree
+//
+// 版には入れた順に番号を振り、番号を鍵にしてまずメモリのメモテーブルに入れる。xmax をつけたり版を取り除いたり
+// するときも、同じ鍵に新しい項目を書くだけで古い項目には触らない。
+// メモテーブルに書いたものが memtable_size を超えたら鍵の順にページに書き出して run にする。読むときはメモテーブル、
+// 新しい run の順に探して最初に見つけた項目を使い、run ごとの Bloom フィルタで鍵を持たない run のページは読まない。
+// run が MAX_RUNS を超えたらすべてを 1 つにまとめ、隠れた項目と取り除いた版のしるしを捨てて、空いたページを使い回す。
+//
+// メモテーブルに書いた項目は書いた順にログのページにも足すので、ほかのページと同じく変更が WAL に載る。
+// 作ったときのページには次の鍵と、run と空いたページとログのページのリストの先頭を書き、
+// 開くときは run を読んで Bloom フィルタを作り直し、ログのページを順に当ててメモテーブルを作り直す。
+// RecordId は版の番号を page_id と slot に分けて入れたもので、ページを指さない
+#[derive(Debug)]
+pub struct LsmTree {
+    // 作ったときのページ。どのテーブルのものかを見分けるのにも使う
+    first_page_id: PageId,
+    state: Arc<Mutex<State>>,
+}
+
+const MEMTABLE_SIZE: usize = 64 * 1024;
+const MAX_RUNS: usize = 4;
+
+// 並列に読むときの 1 単位の鍵の数
+const KEYS_PER_UNIT: u64 = 256;
+
+// 作ったときのページ
+//
+// | MAGIC (8) | 次の鍵 (8) | 空いたページ (8) | ログ (8) | run の数 (2) | run の最初のページ (8) ... |
+const MAGIC: &[u8; 8] = b"rdbmslsm";
+const META_HEADER_SIZE: usize = 34;
+
+// run とログのページ。run のページ、ログのページ、空いたページはそれぞれ次のページにつなぐ
+//
+// | 次のページ (8) | 項目の数 (2) | 項目 ... |
+//
+// 項目は | 鍵 (8) | 種類 (1) | で、版なら続けて | xmin (8) | xmax (8) | 長さ (2) | 行 | を置く
+const PAGE_HEADER_SIZE: usize = 10;
+const ENTRY_HEADER_SIZE: usize = 9;
+const VERSION_HEADER_SIZE: usize = 18;
+const REMOVED: u8 = 0;
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Version(TupleHeader, Vec<u8>),
+    // remove で取り除いた版のしるし。古い run にある同じ鍵の項目を隠す
+    Removed,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        ENTRY_HEADER_SIZE
+            + match self {
+                Entry::Version(_, data) => VERSION_HEADER_SIZE + data.len(),
+                Entry::Removed => 0,
+            }
+    }
+}
+
+// 鍵の順の項目
+type Entries = Vec<(u64, Entry)>;
+
+#[derive(Debug)]
+struct Run {
+    pages: Vec<PageId>,
+    // ページごとの最初の鍵
+    first_keys: Vec<u64>,
+    bloom: BloomFilter,
+}
+
+#[derive(Debug)]
+struct State {
+    meta_page_id: PageId,
+    memtable: BTreeMap<u64, Entry>,
+    // 最後に run を書き出してからログに足したバイト数
+    log_bytes: usize,
+    memtable_size: usize,
+    // 新しいものが先
+    runs: Vec<Run>,
+    next_key: u64,
+    // まとめた run やログが使っていたページのリストの先頭
+    free_page_id: PageId,
+    // ログのページと、最後のページで使った長さ
+    log_pages: Vec<PageId>,
+    log_used: usize,
+}
+
+fn record_id(key: u64) -> RecordId {
+    RecordId {
+        page_id: PageId(key >> 16),
+        slot: key as u16,
+    }
+}
+
+fn key(rid: RecordId) -> u64 {
+    (rid.page_id.to_u64() << 16) | rid.slot as u64
+}
+
+fn encode_entry(bytes: &mut Vec<u8>, key: u64, entry: &Entry) {
+    bytes.extend_from_slice(&key.to_be_bytes());
+    match entry {
+        Entry::Version(header, data) => {
+            bytes.push(VERSION);
+            bytes.extend_from_slice(&header.xmin.to_u64().to_be_bytes());
+            bytes.extend_from_slice(&header.xmax.to_u64().to_be_bytes());
+            bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(data);
+        }
+        Entry::Removed => bytes.push(REMOVED),
+    }
+}
+
+fn encode_page(buffer: &Buffer, entries: &[(u64, Entry)]) {
+    let mut bytes = Vec::with_capacity(PAGE_SIZE);
+    bytes.extend_from_slice(&PageId::INVALID_PAGE_ID.0.to_be_bytes());
+    bytes.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    for (key, entry) in entries {
+        encode_entry(&mut bytes, *key, entry);
+    }
+    buffer.page.borrow_mut()[..bytes.len()].copy_from_slice(&bytes);
+    buffer.is_dirty.set(true);
+}
+
+fn next_page(buffer: &Rc<Buffer>) -> PageId {
+    PageId(u64::from_be_bytes(
+        buffer.page.borrow()[..8].try_into().unwrap(),
+    ))
+}
+
+fn set_next(buffer: &Rc<Buffer>, next: PageId) {
+    buffer.page.borrow_mut()[..8].copy_from_slice(&next.0.to_be_bytes());
+    buffer.is_dirty.set(true);
+}
+
+// 読めなければ None
+fn decode_page(page: &[u8]) -> Option<Entries> {
+    let count = u16::from_be_bytes(page[8..PAGE_HEADER_SIZE].try_into().unwrap());
+    let mut entries = Vec::with_capacity(count as usize);
+    let mut offset = PAGE_HEADER_SIZE;
+    let u64_at = |offset: usize| -> Option<u64> {
+        Some(u64::from_be_bytes(
+            page.get(offset..offset + 8)?.try_into().unwrap(),
+        ))
+    };
+    for _ in 0..count {
+        let key = u64_at(offset)?;
+        let entry = match *page.get(offset + 8)? {
+            REMOVED => Entry::Removed,
+            VERSION => {
+                let body = offset + ENTRY_HEADER_SIZE;
+                let header = TupleHeader {
+                    xmin: TxnId(u64_at(body)?),
+                    xmax: TxnId(u64_at(body + 8)?),
+                };
+                let len = page.get(body + 16..body + VERSION_HEADER_SIZE)?;
+                let len = u16::from_be_bytes(len.try_into().unwrap()) as usize;
+                let start = body + VERSION_HEADER_SIZE;
+                Entry::Version(header, page.get(start..start + len)?.to_vec())
+            }
+            _ => return None,
+        };
+        offset += entry.size();
+        entries.push((key, entry));
+    }
+    Some(entries)
+}
+
+fn read_page(bufmgr: &mut BufferPoolManager, page_id: PageId) -> Result<Entries, Error> {
+    let buffer = bufmgr.fetch_page(page_id)?;
+    let page = buffer.page.borrow();
+    decode_page(&page[..]).ok_or(Error::CorruptedPage(page_id))
+}
+
+// first からつないだページを順に読む。輪になったリストは読まない
+fn read_chain(
+    bufmgr: &mut BufferPoolManager,
+    first: PageId,
+) -> Result<Vec<(PageId, Entries)>, Error> {
+    let mut pages = vec![];
+    let mut visited = HashSet::new();
+    let mut page_id = first;
+    while page_id.valid().is_some() {
+        if !visited.insert(page_id) {
+            return Err(Error::CorruptedPage(page_id));
+        }
+        let entries = read_page(bufmgr, page_id)?;
+        let next = next_page(&bufmgr.fetch_page(page_id)?);
+        pages.push((page_id, entries));
+        page_id = next;
+    }
+    Ok(pages)
+}
+
+fn versions(
+    entries: impl IntoIterator<Item = (u64, Entry)>,
+) -> impl Iterator<Item = (RecordId, TupleHeader, Vec<u8>)> {
+    entries.into_iter().filter_map(|(key, entry)| match entry {
+        Entry::Version(header, data) => Some((record_id(key), header, data)),
+        Entry::Removed => None,
+    })
+}
+
+impl State {
+    fn lookup(&self, bufmgr: &mut BufferPoolManager, key: u64) -> Result<Option<Entry>, Error> {
+        if let Some(entry) = self.memtable.get(&key) {
+            return Ok(Some(entry.clone()));
+        }
+        for run in &self.runs {
+            if !run.bloom.contains(&key) {
+                continue;
+            }
+            let i = run.first_keys.partition_point(|&k| k <= key);
+            if i == 0 {
+                continue;
+            }
+            let mut entries = read_page(bufmgr, run.pages[i - 1])?;
+            if let Ok(j) = entries.binary_search_by_key(&key, |&(k, _)| k) {
+                return Ok(Some(entries.swap_remove(j).1));
+            }
+        }
+        Ok(None)
+    }
+
+    fn put(&mut self, bufmgr: &mut BufferPoolManager, key: u64, entry: Entry) -> Result<(), Error> {
+        self.append_log(bufmgr, key, &entry)?;
+        self.log_bytes += entry.size();
+        self.memtable.insert(key, entry);
+        if self.log_bytes >= self.memtable_size {
+            self.flush(bufmgr)?;
+        }
+        self.save_meta(bufmgr)
+    }
+
+    // 項目をログの最後のページに足す。入らなければ新しいページをつなぐ
+    fn append_log(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        key: u64,
+        entry: &Entry,
+    ) -> Result<(), Error> {
+        let mut bytes = Vec::with_capacity(entry.size());
+        encode_entry(&mut bytes, key, entry);
+        if self.log_pages.is_empty() || self.log_used + bytes.len() > PAGE_SIZE {
+            let buffer = self.allocate_page(bufmgr)?;
+            encode_page(&buffer, &[]);
+            if let Some(&last) = self.log_pages.last() {
+                set_next(&bufmgr.fetch_page(last)?, buffer.page_id);
+            }
+            self.log_pages.push(buffer.page_id);
+            self.log_used = PAGE_HEADER_SIZE;
+        }
+        let buffer = bufmgr.fetch_page(*self.log_pages.last().unwrap())?;
+        {
+            let page = &mut *buffer.page.borrow_mut();
+            let count = u16::from_be_bytes(page[8..PAGE_HEADER_SIZE].try_into().unwrap());
+            page[8..PAGE_HEADER_SIZE].copy_from_slice(&(count + 1).to_be_bytes());
+            page[self.log_used..self.log_used + bytes.len()].copy_from_slice(&bytes);
+        }
+        buffer.is_dirty.set(true);
+        self.log_used += bytes.len();
+        Ok(())
+    }
+
+    // メモテーブルを新しい run にし、ログのページを空いたページにする
+    fn flush(&mut self, bufmgr: &mut BufferPoolManager) -> Result<(), Error> {
+        let entries = self.memtable.iter().map(|(&k, e)| (k, e.clone()));
+        let run = self.write_run(bufmgr, entries.collect())?;
+        self.runs.insert(0, run);
+        self.memtable.clear();
+        self.log_bytes = 0;
+        for page_id in mem::take(&mut self.log_pages) {
+            self.free_page(bufmgr, page_id)?;
+        }
+        if self.runs.len() > MAX_RUNS {
+            self.compact(bufmgr)?;
+        }
+        Ok(())
+    }
+
+    // すべての run を 1 つにまとめる。いちばん古い run までまとめるので、取り除いた版のしるしは残さない
+    fn compact(&mut self, bufmgr: &mut BufferPoolManager) -> Result<(), Error> {
+        let mut merged = BTreeMap::new();
+        for run in &self.runs {
+            for &page_id in &run.pages {
+                for (key, entry) in read_page(bufmgr, page_id)? {
+                    merged.entry(key).or_insert(entry);
+                }
+            }
+        }
+        let entries = merged
+            .into_iter()
+            .filter(|(_, entry)| *entry != Entry::Removed)
+            .collect();
+        let run = self.write_run(bufmgr, entries)?;
+        for old in mem::replace(&mut self.runs, vec![run]) {
+            for page_id in old.pages {
+                self.free_page(bufmgr, page_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 鍵の順の項目をページに詰めて書く
+    fn write_run(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        entries: Entries,
+    ) -> Result<Run, Error> {
+        let mut run = Run {
+            pages: vec![],
+            first_keys: vec![],
+            bloom: BloomFilter::new(entries.len()),
+        };
+        let mut page = vec![];
+        let mut size = PAGE_HEADER_SIZE;
+        for (key, entry) in entries {
+            if size + entry.size() > PAGE_SIZE {
+                self.write_page(bufmgr, &mut run, &mem::take(&mut page))?;
+                size = PAGE_HEADER_SIZE;
+            }
+            run.bloom.insert(&key);
+            size += entry.size();
+            page.push((key, entry));
+        }
+        if !page.is_empty() {
+            self.write_page(bufmgr, &mut run, &page)?;
+        }
+        Ok(run)
+    }
+
+    fn write_page(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        run: &mut Run,
+        entries: &[(u64, Entry)],
+    ) -> Result<(), Error> {
+        let buffer = self.allocate_page(bufmgr)?;
+        encode_page(&buffer, entries);
+        if let Some(&last) = run.pages.last() {
+            set_next(&bufmgr.fetch_page(last)?, buffer.page_id);
+        }
+        run.pages.push(buffer.page_id);
+        run.first_keys.push(entries[0].0);
+        Ok(())
+    }
+
+    // 空いたページがあれば使い回す
+    fn allocate_page(&mut self, bufmgr: &mut BufferPoolManager) -> Result<Rc<Buffer>, Error> {
+        match self.free_page_id.valid() {
+            Some(page_id) => {
+                let buffer = bufmgr.fetch_page(page_id)?;
+                self.free_page_id = next_page(&buffer);
+                Ok(buffer)
+            }
+            None => Ok(bufmgr.create_page()?),
+        }
+    }
+
+    fn free_page(&mut self, bufmgr: &mut BufferPoolManager, page_id: PageId) -> Result<(), Error> {
+        set_next(&bufmgr.fetch_page(page_id)?, self.free_page_id);
+        self.free_page_id = page_id;
+        Ok(())
+    }
+
+    fn save_meta(&self, bufmgr: &mut BufferPoolManager) -> Result<(), Error> {
+        let mut bytes = Vec::with_capacity(META_HEADER_SIZE + 8 * self.runs.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.next_key.to_be_bytes());
+        bytes.extend_from_slice(&self.free_page_id.0.to_be_bytes());
+        let log = self.log_pages.first().unwrap_or(&PageId::INVALID_PAGE_ID);
+        bytes.extend_from_slice(&log.0.to_be_bytes());
+        bytes.extend_from_slice(&(self.runs.len() as u16).to_be_bytes());
+        for run in &self.runs {
+            let first = run.pages.first().unwrap_or(&PageId::INVALID_PAGE_ID);
+            bytes.extend_from_slice(&first.0.to_be_bytes());
+        }
+        let buffer = bufmgr.fetch_page(self.meta_page_id)?;
+        buffer.page.borrow_mut()[..bytes.len()].copy_from_slice(&bytes);
+        buffer.is_dirty.set(true);
+        Ok(())
+    }
+
+    // from 以上 to 未満の鍵の項目を、新しいものを優先して鍵の順に返す。
+    // run ごとに 1 ページだけ読み、読んだページのどれかが終わるところまで返すので、残りがあれば続きの鍵も返す
+    fn merge(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        from: u64,
+        to: u64,
+    ) -> Result<(Entries, Option<u64>), Error> {
+        if from >= to {
+            return Ok((vec![], None));
+        }
+        let mut end = to;
+        let mut pages = vec![];
+        for run in &self.runs {
+            let mut i = run
+                .first_keys
+                .partition_point(|&k| k <= from)
+                .saturating_sub(1);
+            while i < run.pages.len() && run.first_keys[i] < to {
+                let entries = read_page(bufmgr, run.pages[i])?;
+                // from がページの間に落ちたときは次のページから読む
+                if entries.last().is_some_and(|&(k, _)| k >= from) {
+                    if let Some(&next) = run.first_keys.get(i + 1) {
+                        end = end.min(next);
+                    }
+                    pages.push(entries);
+                    break;
+                }
+                i += 1;
+            }
+        }
+        let mut merged: BTreeMap<u64, Entry> = self
+            .memtable
+            .range(from..end)
+            .map(|(&k, e)| (k, e.clone()))
+            .collect();
+        for entries in pages {
+            for (key, entry) in entries {
+                if (from..end).contains(&key) {
+                    merged.entry(key).or_insert(entry);
+                }
+            }
+        }
+        Ok((merged.into_iter().collect(), (end < to).then_some(end)))
+    }
+
+    // 版のヘッダを書き換える。版がないか、xmax が None を返せば false
+    fn set_xmax(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+        xmax: impl FnOnce(&TupleHeader) -> Option<TxnId>,
+    ) -> Result<bool, Error> {
+        let key = key(rid);
+        let Some(Entry::Version(mut header, data)) = self.lookup(bufmgr, key)? else {
+            return Ok(false);
+        };
+        let Some(xmax) = xmax(&header) else {
+            return Ok(false);
+        };
+        header.xmax = xmax;
+        self.put(bufmgr, key, Entry::Version(header, data))?;
+        Ok(true)
+    }
+}
+
+impl LsmTree {
+    pub fn create(bufmgr: &mut BufferPoolManager) -> Result<Self, Error> {
+        Self::with_memtable_size(bufmgr, MEMTABLE_SIZE)
+    }
+
+    // メモテーブルに書いたものが memtable_size バイトになったら run に書き出す
+    pub fn with_memtable_size(
+        bufmgr: &mut BufferPoolManager,
+        memtable_size: usize,
+    ) -> Result<Self, Error> {
+        let buffer = bufmgr.create_page()?;
+        let state = State {
+            meta_page_id: buffer.page_id,
+            memtable: BTreeMap::new(),
+            log_bytes: 0,
+            memtable_size,
+            runs: vec![],
+            next_key: 0,
+            free_page_id: PageId::INVALID_PAGE_ID,
+            log_pages: vec![],
+            log_used: 0,
+        };
+        state.save_meta(bufmgr)?;
+        Ok(Self {
+            first_page_id: buffer.page_id,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    // ファイルに残した木を開く。run の Bloom フィルタとメモテーブルは読み直して作る
+    pub fn open(bufmgr: &mut BufferPoolManager, first_page_id: PageId) -> Result<Self, Error> {
+        let (next_key, free_page_id, log, firsts) = {
+            let buffer = bufmgr.fetch_page(first_page_id)?;
+            let page = buffer.page.borrow();
+            if &page[..8] != MAGIC {
+                return Err(Error::CorruptedPage(first_page_id));
+            }
+            let u64_at =
+                |offset: usize| u64::from_be_bytes(page[offset..offset + 8].try_into().unwrap());
+            let count = u16::from_be_bytes(page[32..META_HEADER_SIZE].try_into().unwrap()) as usize;
+            if count > MAX_RUNS + 1 {
+                return Err(Error::CorruptedPage(first_page_id));
+            }
+            let firsts = (0..count)
+                .map(|i| PageId(u64_at(META_HEADER_SIZE + 8 * i)))
+                .collect::<Vec<_>>();
+            (u64_at(8), PageId(u64_at(16)), PageId(u64_at(24)), firsts)
+        };
+        let mut runs = vec![];
+        for first in firsts {
+            let pages = read_chain(bufmgr, first)?;
+            let mut bloom = BloomFilter::new(pages.iter().map(|(_, e)| e.len()).sum());
+            let mut run = Run {
+                pages: vec![],
+                first_keys: vec![],
+                bloom: BloomFilter::new(0),
+            };
+            for (page_id, entries) in pages {
+                let first_key = entries.first().ok_or(Error::CorruptedPage(page_id))?.0;
+                for (key, _) in &entries {
+                    bloom.insert(key);
+                }
+                run.pages.push(page_id);
+                run.first_keys.push(first_key);
+            }
+            run.bloom = bloom;
+            runs.push(run);
+        }
+        let mut state = State {
+            meta_page_id: first_page_id,
+            memtable: BTreeMap::new(),
+            log_bytes: 0,
+            memtable_size: MEMTABLE_SIZE,
+            runs,
+            next_key,
+            free_page_id,
+            log_pages: vec![],
+            log_used: 0,
+        };
+        // 後に書いた項目が前の項目を隠す
+        for (page_id, entries) in read_chain(bufmgr, log)? {
+            state.log_pages.push(page_id);
+            state.log_used = PAGE_HEADER_SIZE;
+            for (key, entry) in entries {
+                state.log_used += entry.size();
+                state.log_bytes += entry.size();
+                state.memtable.insert(key, entry);
+            }
+        }
+        Ok(Self {
+            first_page_id,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    // ページに書いた run の数
+    pub fn runs(&self) -> usize {
+        self.state().runs.len()
+    }
+}
+
+impl TableAccess for LsmTree {
+    fn insert(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        xmin: TxnId,
+        data: &[u8],
+    ) -> Result<RecordId, Error> {
+        if data.len() > MAX_TUPLE_SIZE {
+            return Err(Error::TupleTooLarge(data.len()));
+        }
+        let mut state = self.state();
+        let key = state.next_key;
+        state.next_key += 1;
+        let header = TupleHeader {
+            xmin,
+            xmax: TxnId::INVALID_TXN_ID,
+        };
+        state.put(bufmgr, key, Entry::Version(header, data.to_vec()))?;
+        Ok(record_id(key))
+    }
+
+    fn get(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+    ) -> Result<Option<(TupleHeader, Vec<u8>)>, Error> {
+        Ok(match self.state().lookup(bufmgr, key(rid))? {
+            Some(Entry::Version(header, data)) => Some((header, data)),
+            _ => None,
+        })
+    }
+
+    fn delete(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+        xmax: TxnId,
+    ) -> Result<bool, Error> {
+        self.state()
+            .set_xmax(bufmgr, rid, |header| (!header.is_deleted()).then_some(xmax))
+    }
+
+    fn undelete(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error> {
+        self.state()
+            .set_xmax(bufmgr, rid, |_| Some(TxnId::INVALID_TXN_ID))
+    }
+
+    fn remove(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error> {
+        let mut state = self.state();
+        let key = key(rid);
+        if !matches!(state.lookup(bufmgr, key)?, Some(Entry::Version(..))) {
+            return Ok(false);
+        }
+        state.put(bufmgr, key, Entry::Removed)?;
+        Ok(true)
+    }
+
+    fn scan(&self) -> Box<dyn TableScan> {
+        Box::new(LsmScan {
+            state: self.state.clone(),
+            next: Some(0),
+            tuples: VecDeque::new(),
+        })
+    }
+
+    // 並列に読む単位は KEYS_PER_UNIT 個ずつの鍵の範囲で、PageId にはその範囲の番号を入れる
+    fn page_ids(&self, _: &mut BufferPoolManager) -> Result<Vec<PageId>, Error> {
+        let units = self.state().next_key.div_ceil(KEYS_PER_UNIT);
+        Ok((0..units).map(PageId).collect())
+    }
+
+    fn page_tuples(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        page_id: PageId,
+    ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        let state = self.state();
+        let to = (page_id.to_u64() + 1) * KEYS_PER_UNIT;
+        let mut tuples = vec![];
+        let mut next = Some(page_id.to_u64() * KEYS_PER_UNIT);
+        while let Some(from) = next {
+            let (entries, rest) = state.merge(bufmgr, from, to)?;
+            tuples.extend(versions(entries));
+            next = rest;
+        }
+        Ok(tuples)
+    }
+
+    // run のページが読めて、鍵が run の中で昇順に並んでいることを確かめる
+    fn verify(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        problems: &mut Vec<(PageId, String)>,
+    ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        let state = self.state();
+        let mut merged = state.memtable.clone();
+        for run in &state.runs {
+            let mut last = None;
+            for (&page_id, &first_key) in run.pages.iter().zip(&run.first_keys) {
+                let entries = match read_page(bufmgr, page_id) {
+                    Ok(entries) => entries,
+                    Err(Error::CorruptedPage(_)) => {
+                        problems.push((page_id, "run page does not hold valid entries".into()));
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                if entries.first().map(|&(k, _)| k) != Some(first_key) {
+                    problems.push((page_id, "run page does not start with its fence key".into()));
+                }
+                for (key, entry) in entries {
+                    if last.is_some_and(|last| key <= last) {
+                        problems.push((page_id, format!("key {} is out of order", key)));
+                    }
+                    last = Some(key);
+                    merged.entry(key).or_insert(entry);
+                }
+            }
+        }
+        Ok(versions(merged).collect())
+    }
+
+    fn first_page_id(&self) -> PageId {
+        self.first_page_id
+    }
+}
+
+// 鍵の順にすべての版を読む。ページを読むたびにそのページまでの範囲をまとめて取り出す
+pub struct LsmScan {
+    state: Arc<Mutex<State>>,
+    next: Option<u64>,
+    tuples: VecDeque<(RecordId, TupleHeader, Vec<u8>)>,
+}
+
+impl TableScan for LsmScan {
+    fn next(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+    ) -> Result<Option<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        loop {
+            if let Some(tuple) = self.tuples.pop_front() {
+                return Ok(Some(tuple));
+            }
+            let Some(from) = self.next else {
+                return Ok(None);
+            };
+            let (entries, next) = self.state.lock().unwrap().merge(bufmgr, from, u64::MAX)?;
+            self.next = next;
+            self.tuples.extend(versions(entries));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_bufmgr;
+
+    fn collect(tree: &LsmTree, bufmgr: &mut BufferPoolManager) -> Vec<(RecordId, TupleHeader)> {
+        let mut scan = tree.scan();
+        let mut tuples = vec![];
+        while let Some((rid, header, _)) = scan.next(bufmgr).unwrap() {
+            tuples.push((rid, header));
+        }
+        tuples
+    }
+
+    #[test]
+    fn test_lsm_tree() {
+        let mut bufmgr = temp_bufmgr(8);
+        let tree = LsmTree::with_memtable_size(&mut bufmgr, 2048).unwrap();
+        let rids: Vec<RecordId> = (0..500u32)
+            .map(|i| tree.insert(&mut bufmgr, TxnId(2), &[i as u8; 40]).unwrap())
+            .collect();
+        // メモテーブルを何度も書き出し、run をまとめている
+        assert!((1..=MAX_RUNS).contains(&tree.runs()));
+        for rid in rids.iter().step_by(3) {
+            assert!(tree.delete(&mut bufmgr, *rid, TxnId(3)).unwrap());
+            assert!(!tree.delete(&mut bufmgr, *rid, TxnId(3)).unwrap());
+        }
+        for rid in rids.iter().step_by(5) {
+            assert!(tree.remove(&mut bufmgr, *rid).unwrap());
+        }
+        assert!(tree.undelete(&mut bufmgr, rids[3]).unwrap());
+        assert!(!tree.undelete(&mut bufmgr, rids[5]).unwrap());
+
+        let expected: Vec<(RecordId, TupleHeader)> = (0..500)
+            .filter(|i| i % 5 != 0)
+            .map(|i| {
+                let xmax = if i % 3 == 0 && i != 3 {
+                    TxnId(3)
+                } else {
+                    TxnId::INVALID_TXN_ID
+                };
+                (
+                    rids[i],
+                    TupleHeader {
+                        xmin: TxnId(2),
+                        xmax,
+                    },
+                )
+            })
+            .collect();
+        assert_eq!(collect(&tree, &mut bufmgr), expected);
+        assert_eq!(
+            tree.get(&mut bufmgr, rids[7]).unwrap(),
+            Some((expected[5].1, vec![7; 40]))
+        );
+        assert_eq!(tree.get(&mut bufmgr, rids[10]).unwrap(), None);
+
+        // 並列に読む単位を合わせるとすべての版になる
+        let mut units = vec![];
+        for unit in tree.page_ids(&mut bufmgr).unwrap() {
+            units.extend(tree.page_tuples(&mut bufmgr, unit).unwrap());
+        }
+        let units: Vec<_> = units
+            .into_iter()
+            .map(|(rid, header, _)| (rid, header))
+            .collect();
+        assert_eq!(units, expected);
+        let mut problems = vec![];
+        assert_eq!(
+            tree.verify(&mut bufmgr, &mut problems).unwrap().len(),
+            expected.len()
+        );
+        assert!(problems.is_empty());
+
+        // 作ったときのページから開き直すと、run もメモテーブルも同じ版を返す
+        let memtable = tree.state().memtable.len();
+        assert!(memtable > 0);
+        let reopened = LsmTree::open(&mut bufmgr, tree.first_page_id()).unwrap();
+        assert_eq!(reopened.runs(), tree.runs());
+        assert_eq!(reopened.state().memtable.len(), memtable);
+        assert_eq!(collect(&reopened, &mut bufmgr), expected);
+        let rid = reopened.insert(&mut bufmgr, TxnId(4), &[1; 40]).unwrap();
+        assert_eq!(rid, record_id(500));
+        // 空いたページを使い回すので、ファイルは伸び続けない
+        let pages = bufmgr.page_count();
+        for i in 0..2000u32 {
+            let rid = reopened
+                .insert(&mut bufmgr, TxnId(4), &[i as u8; 40])
+                .unwrap();
+            reopened.remove(&mut bufmgr, rid).unwrap();
+        }
+        assert!(bufmgr.page_count() < pages + 16);
+    }
+}
@@ -0,0 +1,150 @@
+// LZ4 のブロック形式の圧縮と展開
+//
+// ブロックはシーケンスの並びで、シーケンスは | トークン (1) | リテラルの長さ | リテラル | オフセット (2) | 一致の長さ |。
+// トークンの上位 4 bit がリテラルの長さ、下位 4 bit が一致の長さから MIN_MATCH を引いたもので、
+// 15 のときは続くバイトを 255 でないものが出るまで足す。オフセットはリトルエンディアン。
+// 最後のシーケンスはリテラルだけで、ブロックの最後の LAST_LITERALS バイトは必ずリテラルにする。
+const MIN_MATCH: usize = 4;
+const LAST_LITERALS: usize = 5;
+// 最後の一致はブロックの末尾からこれだけ前までに始める
+const MF_LIMIT: usize = 12;
+const MAX_OFFSET: usize = 65535;
+const HASH_BITS: u32 = 12;
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2);
+    // 4 バイトのハッシュごとに、最後に見た位置
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut i = 0;
+    while i + MF_LIMIT <= input.len() {
+        let seq = read_u32(input, i);
+        let hash = (seq.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize;
+        let candidate = table[hash];
+        table[hash] = i;
+        if candidate == usize::MAX
+            || i - candidate > MAX_OFFSET
+            || read_u32(input, candidate) != seq
+        {
+            i += 1;
+            continue;
+        }
+        let mut len = MIN_MATCH;
+        while i + len < input.len() - LAST_LITERALS && input[candidate + len] == input[i + len] {
+            len += 1;
+        }
+        put_sequence(&mut out, &input[anchor..i], Some((i - candidate, len)));
+        i += len;
+        anchor = i;
+    }
+    put_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+// 展開すると size バイトになるブロックを展開する。壊れていれば None
+pub fn decompress(input: &[u8], size: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(size);
+    let mut i = 0;
+    loop {
+        let token = *input.get(i)?;
+        i += 1;
+        let literals = read_length(input, &mut i, (token >> 4) as usize)?;
+        out.extend_from_slice(input.get(i..i.checked_add(literals)?)?);
+        i += literals;
+        if i == input.len() {
+            break;
+        }
+        let offset = u16::from_le_bytes(input.get(i..i + 2)?.try_into().unwrap()) as usize;
+        i += 2;
+        if offset == 0 || offset > out.len() {
+            return None;
+        }
+        let len = read_length(input, &mut i, (token & 0xf) as usize)? + MIN_MATCH;
+        if out.len() + len > size {
+            return None;
+        }
+        // 一致は自分自身と重なりうるので 1 バイトずつ写す
+        let start = out.len() - offset;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+    (out.len() == size).then_some(out)
+}
+
+fn read_u32(input: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(input[i..i + 4].try_into().unwrap())
+}
+
+fn put_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+    put_length(out, literals.len());
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        put_length(out, match_len);
+    }
+}
+
+// トークンに収まらない長さの残りを書く
+fn put_length(out: &mut Vec<u8>, len: usize) {
+    if len < 15 {
+        return;
+    }
+    let mut rest = len - 15;
+    while rest >= 255 {
+        out.push(255);
+        rest -= 255;
+    }
+    out.push(rest as u8);
+}
+
+fn read_length(input: &[u8], i: &mut usize, mut len: usize) -> Option<usize> {
+    if len == 15 {
+        loop {
+            let byte = *input.get(*i)?;
+            *i += 1;
+            len = len.checked_add(byte as usize)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lz4() {
+        let mut text = b"the quick brown fox jumps over the lazy dog. ".repeat(40);
+        text.extend((0..=255u8).cycle().take(1000));
+        text.extend(vec![0; 5000]);
+        for input in [
+            &text[..],
+            b"",
+            b"short",
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        ] {
+            let compressed = compress(input);
+            assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+        }
+        let compressed = compress(&text);
+        assert!(
+            compressed.len() < text.len() / 4,
+            "{} bytes",
+            compressed.len()
+        );
+
+        // 長さが合わないものや途中で切れたものは展開しない
+        assert_eq!(decompress(&compressed, text.len() - 1), None);
+        assert_eq!(
+            decompress(&compressed[..compressed.len() / 2], text.len()),
+            None
+        );
+        assert_eq!(decompress(&[0x0f, 1, 0], 4), None);
+    }
+}
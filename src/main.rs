@@ -1,6 +1,3 @@
-mod disk;
-mod buffer;
-
 fn main() {
     println!("Hello, world!");
 }
@@ -0,0 +1,247 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+// 動作を数える計器と、Prometheus のテキスト形式での書き出し
+//
+// 計器はプロセスでひとつの METRICS に集め、同じプロセスで開いたデータベースはすべて同じ計器を増やす。
+// バッファのヒット率は、ヒットと読み込みの数から書き出すときに計算する
+
+// 文の実行時間のヒストグラムの区切り (秒)
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+];
+
+pub static METRICS: Metrics = Metrics {
+    queries: Counter::new(),
+    query_errors: Counter::new(),
+    query_duration: Histogram::new(),
+    buffer_hits: Counter::new(),
+    buffer_reads: Counter::new(),
+    wal_bytes: Counter::new(),
+    active_transactions: Gauge::new(),
+    autovacuums: Counter::new(),
+    autoanalyzes: Counter::new(),
+};
+
+pub struct Metrics {
+    // 実行した文と、そのうち失敗した文
+    pub queries: Counter,
+    pub query_errors: Counter,
+    pub query_duration: Histogram,
+    // バッファプールで見つかったページと、ディスクから読んだページ
+    pub buffer_hits: Counter,
+    pub buffer_reads: Counter,
+    // WAL に書いたレコードのバイト数。圧縮したあとの大きさ
+    pub wal_bytes: Counter,
+    // 始めて、まだコミットも取り消しもしていないトランザクション。準備したものも含む
+    pub active_transactions: Gauge,
+    // 自動でしたテーブルの VACUUM と ANALYZE
+    pub autovacuums: Counter,
+    pub autoanalyzes: Counter,
+}
+
+impl Metrics {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        };
+        metric(
+            "rdbms_queries_total",
+            "counter",
+            "Statements executed.",
+            self.queries.get().to_string(),
+        );
+        metric(
+            "rdbms_query_errors_total",
+            "counter",
+            "Statements that returned an error.",
+            self.query_errors.get().to_string(),
+        );
+        let (hits, reads) = (self.buffer_hits.get(), self.buffer_reads.get());
+        metric(
+            "rdbms_buffer_hits_total",
+            "counter",
+            "Page fetches served from the buffer pool.",
+            hits.to_string(),
+        );
+        metric(
+            "rdbms_buffer_reads_total",
+            "counter",
+            "Page fetches that read from disk.",
+            reads.to_string(),
+        );
+        let ratio = if hits + reads == 0 {
+            0.0
+        } else {
+            hits as f64 / (hits + reads) as f64
+        };
+        metric(
+            "rdbms_buffer_hit_ratio",
+            "gauge",
+            "Fraction of page fetches served from the buffer pool.",
+            ratio.to_string(),
+        );
+        metric(
+            "rdbms_wal_bytes_total",
+            "counter",
+            "Bytes of WAL records appended.",
+            self.wal_bytes.get().to_string(),
+        );
+        metric(
+            "rdbms_active_transactions",
+            "gauge",
+            "Transactions that have not committed or rolled back.",
+            self.active_transactions.get().to_string(),
+        );
+        metric(
+            "rdbms_autovacuum_total",
+            "counter",
+            "Tables vacuumed by the maintenance task.",
+            self.autovacuums.get().to_string(),
+        );
+        metric(
+            "rdbms_autoanalyze_total",
+            "counter",
+            "Tables analyzed by the maintenance task.",
+            self.autoanalyzes.get().to_string(),
+        );
+        self.query_duration.render(
+            &mut out,
+            "rdbms_query_duration_seconds",
+            "Statement execution time.",
+        );
+        out
+    }
+}
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub const fn new() -> Self {
+        Gauge(AtomicI64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Gauge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 区切りごとの数は、その区切り以下の値だけを数える。書き出すときに累積する
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    // マイクロ秒
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        let mut cumulative = 0;
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative).unwrap();
+        }
+        let count = self.count();
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count).unwrap();
+        let sum = self.sum.load(Ordering::Relaxed) as f64 / 1e6;
+        writeln!(out, "{}_sum {}", name, sum).unwrap();
+        writeln!(out, "{}_count {}", name, count).unwrap();
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_micros(50));
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_secs(10));
+        let mut out = String::new();
+        histogram.render(&mut out, "t", "Test.");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[..2], ["# HELP t Test.", "# TYPE t histogram"]);
+        assert_eq!(lines[2], "t_bucket{le=\"0.0001\"} 1");
+        assert_eq!(lines[5], "t_bucket{le=\"0.005\"} 2");
+        assert_eq!(lines[13], "t_bucket{le=\"5\"} 2");
+        assert_eq!(lines[14], "t_bucket{le=\"+Inf\"} 3");
+        assert_eq!(lines[15], "t_sum 10.00305");
+        assert_eq!(lines[16], "t_count 3");
+    }
+}
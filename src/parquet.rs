@@ -0,0 +1,581 @@
+use std::io::{self, Write};
+
+use thiserror::Error;
+
+use crate::types::Value;
+
+// 行を Apache Parquet のファイルとして書く
+//
+// ROW_GROUP_ROWS 行ごとに行グループにし、列ごとに PLAIN で符号化したひとつのデータページを書く。
+// 圧縮はしない。列はすべて OPTIONAL で、NULL かどうかの定義レベルは RLE で書く。
+// 結果の列には型がないので、列の型は最初に NULL でない値が来たときに決める。
+// スキーマはファイルの末尾のメタデータに書くので、それまでの行グループはその列がすべて NULL でよい。
+// 型の対応:
+//   integer、bigint → INT64           real → DOUBLE          boolean → BOOLEAN
+//   numeric → INT64 の DECIMAL(18, 最初の値の scale)     date → INT32 の DATE
+//   time、timestamp → INT64 のマイクロ秒 (UTC に合わせていない)
+//   text、interval → STRING           json → JSON            blob → BYTE_ARRAY
+//   uuid → 16 バイトの UUID           すべて NULL の列 → INT32 の UNKNOWN
+// interval は Parquet の INTERVAL (負の値とマイクロ秒を持てない) にせず、文字列で書く
+
+// ひとつの行グループの行の数
+const ROW_GROUP_ROWS: usize = 65536;
+const MAGIC: &[u8] = b"PAR1";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("column \"{column}\" has values of both type {expected} and type {found}")]
+    MixedTypes {
+        column: String,
+        expected: &'static str,
+        found: String,
+    },
+    #[error("numeric value of column \"{0}\" does not fit in the column's scale")]
+    DecimalScale(String),
+}
+
+// Parquet の物理型
+const BOOLEAN: i32 = 0;
+const INT32: i32 = 1;
+const INT64: i32 = 2;
+const DOUBLE: i32 = 5;
+const BYTE_ARRAY: i32 = 6;
+const FIXED_LEN_BYTE_ARRAY: i32 = 7;
+
+// 符号化
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+
+// 列に決めた型
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Int64,
+    Double,
+    Boolean,
+    Decimal { scale: u8 },
+    Date,
+    Time,
+    Timestamp,
+    Text,
+    Json,
+    Blob,
+    Uuid,
+}
+
+impl Kind {
+    fn of(value: &Value) -> Option<Kind> {
+        Some(match value {
+            Value::Null => return None,
+            Value::Integer(_) | Value::BigInt(_) => Kind::Int64,
+            Value::Real(_) => Kind::Double,
+            Value::Boolean(_) => Kind::Boolean,
+            Value::Decimal(d) => Kind::Decimal { scale: d.scale() },
+            Value::Date(_) => Kind::Date,
+            Value::Time(_) => Kind::Time,
+            Value::Timestamp(_) => Kind::Timestamp,
+            Value::Text(_)
+            | Value::Interval(_)
+            | Value::Point(_)
+            | Value::Box(_)
+            | Value::Array(_)
+            | Value::Enum(_) => Kind::Text,
+            Value::Json(_) => Kind::Json,
+            Value::Blob(_) => Kind::Blob,
+            Value::Uuid(_) => Kind::Uuid,
+        })
+    }
+
+    fn physical(kind: Option<Kind>) -> i32 {
+        match kind {
+            None | Some(Kind::Date) => INT32,
+            Some(Kind::Int64 | Kind::Decimal { .. } | Kind::Time | Kind::Timestamp) => INT64,
+            Some(Kind::Double) => DOUBLE,
+            Some(Kind::Boolean) => BOOLEAN,
+            Some(Kind::Text | Kind::Json | Kind::Blob) => BYTE_ARRAY,
+            Some(Kind::Uuid) => FIXED_LEN_BYTE_ARRAY,
+        }
+    }
+}
+
+struct ColumnBuffer {
+    name: String,
+    kind: Option<Kind>,
+    // 行ごとに NULL でないか
+    defined: Vec<bool>,
+    // PLAIN で符号化した NULL でない値。boolean はビットに詰める
+    values: Vec<u8>,
+    booleans: usize,
+    // 書いた列チャンクの場所
+    chunks: Vec<Chunk>,
+}
+
+struct Chunk {
+    offset: u64,
+    size: u64,
+    rows: usize,
+}
+
+impl ColumnBuffer {
+    fn push(&mut self, value: &Value) -> Result<(), Error> {
+        let Some(found) = Kind::of(value) else {
+            self.defined.push(false);
+            return Ok(());
+        };
+        let kind = *self.kind.get_or_insert(found);
+        let mismatch = match (kind, found) {
+            (Kind::Decimal { .. }, Kind::Decimal { .. }) => false,
+            (kind, found) => kind != found,
+        };
+        if mismatch {
+            return Err(Error::MixedTypes {
+                column: self.name.clone(),
+                expected: self.first_type(),
+                found: value.type_name(),
+            });
+        }
+        self.defined.push(true);
+        match value {
+            Value::Integer(n) | Value::BigInt(n) => self.values.extend(n.to_le_bytes()),
+            Value::Real(x) => self.values.extend(x.to_le_bytes()),
+            Value::Boolean(b) => {
+                if self.booleans.is_multiple_of(8) {
+                    self.values.push(0);
+                }
+                *self.values.last_mut().unwrap() |= (*b as u8) << (self.booleans % 8);
+                self.booleans += 1;
+            }
+            Value::Decimal(d) => {
+                let Kind::Decimal { scale } = kind else {
+                    unreachable!()
+                };
+                let d = d
+                    .rescale(scale)
+                    .ok_or_else(|| Error::DecimalScale(self.name.clone()))?;
+                self.values.extend(d.mantissa().to_le_bytes());
+            }
+            Value::Date(d) => self.values.extend(d.days().to_le_bytes()),
+            Value::Time(t) => self.values.extend(t.micros().to_le_bytes()),
+            Value::Timestamp(t) => self.values.extend(t.micros().to_le_bytes()),
+            Value::Text(s) => self.push_bytes(s.as_bytes()),
+            Value::Interval(_)
+            | Value::Point(_)
+            | Value::Box(_)
+            | Value::Array(_)
+            | Value::Enum(_) => self.push_bytes(value.to_string().as_bytes()),
+            Value::Json(s) => self.push_bytes(s.as_bytes()),
+            Value::Blob(b) => self.push_bytes(b),
+            Value::Uuid(u) => self.values.extend(u.as_bytes()),
+            Value::Null => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        self.values.extend((bytes.len() as u32).to_le_bytes());
+        self.values.extend(bytes);
+    }
+
+    fn first_type(&self) -> &'static str {
+        match self.kind {
+            Some(Kind::Int64) => "bigint",
+            Some(Kind::Double) => "real",
+            Some(Kind::Boolean) => "boolean",
+            Some(Kind::Decimal { .. }) => "numeric",
+            Some(Kind::Date) => "date",
+            Some(Kind::Time) => "time",
+            Some(Kind::Timestamp) => "timestamp",
+            Some(Kind::Text) => "text",
+            Some(Kind::Json) => "json",
+            Some(Kind::Blob) => "blob",
+            Some(Kind::Uuid) => "uuid",
+            None => "unknown",
+        }
+    }
+
+    // 定義レベルを RLE の連なりで書き、長さを前に付ける
+    fn definition_levels(&self) -> Vec<u8> {
+        let mut runs = vec![];
+        let mut i = 0;
+        while i < self.defined.len() {
+            let level = self.defined[i];
+            let len = self.defined[i..]
+                .iter()
+                .take_while(|&&d| d == level)
+                .count();
+            varint(&mut runs, (len as u64) << 1);
+            runs.push(level as u8);
+            i += len;
+        }
+        let mut levels = (runs.len() as u32).to_le_bytes().to_vec();
+        levels.extend(runs);
+        levels
+    }
+}
+
+pub struct ParquetWriter<W> {
+    writer: W,
+    // 書いたバイト数。列チャンクの場所に使う
+    offset: u64,
+    columns: Vec<ColumnBuffer>,
+    rows: usize,
+    groups: Vec<usize>,
+}
+
+impl<W: Write> ParquetWriter<W> {
+    pub fn new(mut writer: W, columns: &[String]) -> Result<ParquetWriter<W>, Error> {
+        writer.write_all(MAGIC)?;
+        Ok(ParquetWriter {
+            writer,
+            offset: MAGIC.len() as u64,
+            columns: columns
+                .iter()
+                .map(|name| ColumnBuffer {
+                    name: name.clone(),
+                    kind: None,
+                    defined: vec![],
+                    values: vec![],
+                    booleans: 0,
+                    chunks: vec![],
+                })
+                .collect(),
+            rows: 0,
+            groups: vec![],
+        })
+    }
+
+    pub fn write(&mut self, row: &[Value]) -> Result<(), Error> {
+        for (column, value) in self.columns.iter_mut().zip(row) {
+            column.push(value)?;
+        }
+        self.rows += 1;
+        if self.rows == ROW_GROUP_ROWS {
+            self.flush_group()?;
+        }
+        Ok(())
+    }
+
+    fn flush_group(&mut self) -> Result<(), Error> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        for column in &mut self.columns {
+            let mut page = column.definition_levels();
+            page.append(&mut column.values);
+            let mut header = Thrift::new();
+            header.i32(1, 0);
+            header.i32(2, page.len() as i32);
+            header.i32(3, page.len() as i32);
+            header.begin(5);
+            header.i32(1, self.rows as i32);
+            header.i32(2, PLAIN);
+            header.i32(3, RLE);
+            header.i32(4, RLE);
+            header.end();
+            header.stop();
+            self.writer.write_all(&header.buf)?;
+            self.writer.write_all(&page)?;
+            let size = (header.buf.len() + page.len()) as u64;
+            column.chunks.push(Chunk {
+                offset: self.offset,
+                size,
+                rows: self.rows,
+            });
+            self.offset += size;
+            column.defined.clear();
+            column.booleans = 0;
+        }
+        self.groups.push(self.rows);
+        self.rows = 0;
+        Ok(())
+    }
+
+    // 残りの行グループとメタデータを書いて writer を返す
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.flush_group()?;
+        let mut meta = Thrift::new();
+        meta.i32(1, 1);
+        meta.list(2, STRUCT, self.columns.len() + 1);
+        meta.element();
+        meta.binary(4, b"schema");
+        meta.i32(5, self.columns.len() as i32);
+        meta.end();
+        for column in &self.columns {
+            meta.element();
+            schema_element(&mut meta, column);
+            meta.end();
+        }
+        meta.i64(3, self.groups.iter().sum::<usize>() as i64);
+        meta.list(4, STRUCT, self.groups.len());
+        for (g, &rows) in self.groups.iter().enumerate() {
+            meta.element();
+            meta.list(1, STRUCT, self.columns.len());
+            let mut total = 0;
+            for column in &self.columns {
+                let chunk = &column.chunks[g];
+                total += chunk.size;
+                meta.element();
+                meta.i64(2, chunk.offset as i64);
+                meta.begin(3);
+                meta.i32(1, Kind::physical(column.kind));
+                meta.list(2, I32, 2);
+                meta.list_i32(&[PLAIN, RLE]);
+                meta.list(3, BINARY, 1);
+                meta.list_binary(column.name.as_bytes());
+                meta.i32(4, 0);
+                meta.i64(5, chunk.rows as i64);
+                meta.i64(6, chunk.size as i64);
+                meta.i64(7, chunk.size as i64);
+                meta.i64(9, chunk.offset as i64);
+                meta.end();
+                meta.end();
+            }
+            meta.i64(2, total as i64);
+            meta.i64(3, rows as i64);
+            meta.end();
+        }
+        meta.binary(6, b"rdbms-training");
+        meta.stop();
+        self.writer.write_all(&meta.buf)?;
+        self.writer
+            .write_all(&(meta.buf.len() as u32).to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// 列の SchemaElement のフィールド。型の注釈は古い ConvertedType と LogicalType の両方を書く
+fn schema_element(meta: &mut Thrift, column: &ColumnBuffer) {
+    meta.i32(1, Kind::physical(column.kind));
+    if column.kind == Some(Kind::Uuid) {
+        meta.i32(2, 16);
+    }
+    meta.i32(3, 1);
+    meta.binary(4, column.name.as_bytes());
+    let converted = match column.kind {
+        Some(Kind::Text) => Some(0),
+        Some(Kind::Decimal { .. }) => Some(5),
+        Some(Kind::Date) => Some(6),
+        Some(Kind::Json) => Some(19),
+        _ => None,
+    };
+    if let Some(converted) = converted {
+        meta.i32(6, converted);
+    }
+    if let Some(Kind::Decimal { scale }) = column.kind {
+        meta.i32(7, scale as i32);
+        meta.i32(8, 18);
+    }
+    // LogicalType の共用体のどれか
+    let logical = match column.kind {
+        Some(Kind::Text) => 1,
+        Some(Kind::Decimal { .. }) => 5,
+        Some(Kind::Date) => 6,
+        Some(Kind::Time) => 7,
+        Some(Kind::Timestamp) => 8,
+        None => 11,
+        Some(Kind::Json) => 12,
+        Some(Kind::Uuid) => 14,
+        Some(Kind::Int64 | Kind::Double | Kind::Boolean | Kind::Blob) => return,
+    };
+    meta.begin(10);
+    meta.begin(logical);
+    match column.kind {
+        Some(Kind::Decimal { scale }) => {
+            meta.i32(1, scale as i32);
+            meta.i32(2, 18);
+        }
+        Some(Kind::Time | Kind::Timestamp) => {
+            meta.bool(1, false);
+            // TimeUnit の MICROS
+            meta.begin(2);
+            meta.begin(2);
+            meta.end();
+            meta.end();
+        }
+        _ => {}
+    }
+    meta.end();
+    meta.end();
+}
+
+// Thrift の compact protocol の型
+const BINARY: u8 = 8;
+const I32: u8 = 5;
+const STRUCT: u8 = 12;
+
+// Thrift の compact protocol で構造体を書く。フィールドの番号は前のフィールドとの差で書く
+struct Thrift {
+    buf: Vec<u8>,
+    // 入れ子の構造体ごとの、最後に書いたフィールドの番号
+    last: Vec<i16>,
+}
+
+impl Thrift {
+    fn new() -> Thrift {
+        Thrift {
+            buf: vec![],
+            last: vec![0],
+        }
+    }
+
+    fn field(&mut self, id: i16, ty: u8) {
+        let last = self.last.last_mut().unwrap();
+        match id - *last {
+            delta @ 1..=15 => self.buf.push((delta as u8) << 4 | ty),
+            _ => {
+                self.buf.push(ty);
+                varint(&mut self.buf, zigzag(id as i64));
+            }
+        }
+        *self.last.last_mut().unwrap() = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        varint(&mut self.buf, zigzag(value as i64));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, 6);
+        varint(&mut self.buf, zigzag(value));
+    }
+
+    // 真偽値は型の欄に値を書く
+    fn bool(&mut self, id: i16, value: bool) {
+        self.field(id, if value { 1 } else { 2 });
+    }
+
+    fn binary(&mut self, id: i16, bytes: &[u8]) {
+        self.field(id, BINARY);
+        self.list_binary(bytes);
+    }
+
+    fn begin(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.last.push(0);
+    }
+
+    // リストの要素の構造体を始める
+    fn element(&mut self) {
+        self.last.push(0);
+    }
+
+    fn end(&mut self) {
+        self.buf.push(0);
+        self.last.pop();
+    }
+
+    // いちばん外の構造体を閉じる
+    fn stop(&mut self) {
+        self.buf.push(0);
+    }
+
+    fn list(&mut self, id: i16, ty: u8, len: usize) {
+        self.field(id, 9);
+        if len < 15 {
+            self.buf.push((len as u8) << 4 | ty);
+        } else {
+            self.buf.push(0xf0 | ty);
+            varint(&mut self.buf, len as u64);
+        }
+    }
+
+    fn list_i32(&mut self, values: &[i32]) {
+        for &value in values {
+            varint(&mut self.buf, zigzag(value as i64));
+        }
+    }
+
+    fn list_binary(&mut self, bytes: &[u8]) {
+        varint(&mut self.buf, bytes.len() as u64);
+        self.buf.extend(bytes);
+    }
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+// 下位から 7 ビットずつの可変長整数
+fn varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Database;
+    use crate::sql::ast::{CopyFormat, CopyOptions};
+
+    #[test]
+    fn test_parquet_writer() {
+        let columns = vec!["n".to_string(), "b".to_string()];
+        let mut writer = ParquetWriter::new(vec![], &columns).unwrap();
+        for row in [
+            [Value::Integer(1), Value::Boolean(true)],
+            [Value::Null, Value::Boolean(false)],
+            [Value::BigInt(3), Value::Boolean(true)],
+        ] {
+            writer.write(&row).unwrap();
+        }
+        let file = writer.finish().unwrap();
+        assert_eq!(&file[..4], MAGIC);
+        assert_eq!(&file[file.len() - 4..], MAGIC);
+        let footer = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap());
+        let meta = &file[file.len() - 8 - footer as usize..file.len() - 8];
+        assert!(meta.windows(14).any(|w| w == b"rdbms-training"));
+
+        // 最初の列チャンク: ページの見出し、定義レベルの RLE の連なり (1, 0, 1)、2 つの値
+        let mut chunk = vec![0x15, 0x00, 0x15, 0x34, 0x15, 0x34, 0x2c, 0x15, 0x06];
+        chunk.extend([0x15, 0x00, 0x15, 0x06, 0x15, 0x06, 0x00, 0x00]);
+        chunk.extend([6, 0, 0, 0, 2, 1, 2, 0, 2, 1]);
+        chunk.extend(1i64.to_le_bytes());
+        chunk.extend(3i64.to_le_bytes());
+        assert_eq!(&file[4..4 + chunk.len()], &chunk[..]);
+        // boolean はビットに詰める
+        let page = &file[4 + chunk.len()..];
+        assert_eq!(&page[17..24], &[2, 0, 0, 0, 6, 1, 0b101][..]);
+
+        let mut writer = ParquetWriter::new(vec![], &columns[..1]).unwrap();
+        writer.write(&[Value::Integer(1)]).unwrap();
+        let err = writer.write(&[Value::Text("x".to_string())]).unwrap_err();
+        assert!(
+            matches!(&err, Error::MixedTypes { column, expected: "bigint", found } if column == "n" && found == "text"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_export_parquet() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (n NUMERIC(10,2), d DATE);
+             INSERT INTO t VALUES (NULL, NULL), (1.5, DATE '1970-01-02'), (2, NULL)",
+        )
+        .unwrap();
+        let options = CopyOptions {
+            format: CopyFormat::Parquet,
+            ..CopyOptions::default()
+        };
+        let mut out = vec![];
+        let rows = conn
+            .export("SELECT n, d FROM t", &[], &mut out, &options)
+            .unwrap();
+        assert_eq!(rows, 3);
+        // numeric は列の scale の INT64、date は 1970-01-01 からの日数
+        assert!(out
+            .windows(16)
+            .any(|w| w[..8] == 150i64.to_le_bytes() && w[8..] == 200i64.to_le_bytes()));
+        assert!(out.windows(4).any(|w| w == 1i32.to_le_bytes()));
+        assert_eq!(&out[out.len() - 4..], MAGIC);
+    }
+}
@@ -0,0 +1,657 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::btree::BTree;
+use crate::buffer::{Buffer, BufferPoolManager};
+use crate::catalog::{Catalog, Role, Table};
+use crate::connection::{Database, Error};
+use crate::disk::{PageId, PAGE_SIZE};
+use crate::dump;
+use crate::enum_type::EnumType;
+use crate::executor;
+use crate::heap::HeapFile;
+use crate::lsm::LsmTree;
+use crate::recovery::{self, BackupLabel, Checkpointer};
+use crate::replication::Standby;
+use crate::rtree::RTree;
+use crate::sql::ast::Privilege;
+use crate::storage::{TableAccess, DEFAULT_STORAGE_ENGINE, LSM_STORAGE_ENGINE};
+use crate::transaction::{StandbyView, TransactionManager, TxnId};
+use crate::wal::{ActiveTxn, LogRecord, Lsn, Wal};
+
+// ファイルに残すカタログ
+//
+// カタログは ENUM の型と、テーブルを作り直す CREATE の文と、テーブルとインデックスのページの番号と、ロールと権限で、
+// ページ 0 から始まるページのリストに書く。型は行に入れた番号が変わらないように、番号とラベルの順位をそのまま書く。
+// ロールのパスワードは塩とハッシュを書く。ロールの部分が読めなければ、認証なしで開かないよう壊れたファイルとする
+//
+// 先頭のページ   | MAGIC (8) | データの長さ (8) | 次のページ (8) | データ |
+// 続きのページ   | 次のページ (8) | データ |
+//
+// カタログが縮んでもページは手放さず、リストにつないだまま次に書くときに使う。
+// 残すのは ENUM の型、heap と lsm のテーブルと実体化したビュー、インデックス、文のトリガー、パーティション、生成列、辞書、
+// ロールと残したテーブルへの権限。登録した StorageEngine のテーブル、登録した関数、統計は残さない。行の数は開いたときに数え直す
+const MAGIC: &[u8; 8] = b"rdbmscat";
+const CATALOG_PAGE_ID: PageId = PageId(0);
+const FIRST_HEADER_SIZE: usize = 24;
+const NEXT_HEADER_SIZE: usize = 8;
+
+// ファイルのデータベースが開いている間、カタログを書き、閉じるときに回復のいらない状態にする
+pub(crate) struct DatabaseFile {
+    txns: TransactionManager,
+    // 最後にページに書いたカタログ
+    saved: Vec<u8>,
+    // None なら開くときと閉じるときにしかチェックポイントを書かない
+    checkpointer: Option<Checkpointer>,
+}
+
+impl DatabaseFile {
+    // カタログが最後に書いたものと違えばページに書く。ディスクに届くのはページを書き出したとき
+    pub(crate) fn save(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        let data = encode(catalog);
+        if data != self.saved {
+            write(bufmgr, &data)?;
+            self.saved = data;
+        }
+        Ok(())
+    }
+
+    // ページを書き出したあとに呼び、ログが決めた量だけ進んでいればチェックポイントを書いて古いログを切り詰める。
+    // 実行中のトランザクションがあれば、書き出したページに残るその版をコミットしたものと見分けられなくなるので次に延ばす
+    pub(crate) fn checkpoint(&mut self, bufmgr: &mut BufferPoolManager) -> Result<(), Error> {
+        let Some(checkpointer) = self.checkpointer.as_mut() else {
+            return Ok(());
+        };
+        if !self.txns.running().is_empty() {
+            return Ok(());
+        }
+        checkpointer.step(bufmgr, &self.txns.active(), self.txns.next_txn_id().0)?;
+        Ok(())
+    }
+
+    // heap のファイルを dest にオンラインバックアップする。ログを書いていない実行中のトランザクションもチェックポイントに載せ、
+    // 戻したときに写したページに残るその版を片づけさせる
+    pub(crate) fn backup(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        heap: &Path,
+        dest: &Path,
+    ) -> Result<BackupLabel, Error> {
+        let mut active = self.txns.active();
+        active.extend(self.txns.running().into_iter().map(|id| ActiveTxn {
+            txn: id.0,
+            first: Lsn::INVALID_LSN,
+            last: Lsn::INVALID_LSN,
+        }));
+        let next_txn = self.txns.next_txn_id().0;
+        Ok(recovery::online_backup(
+            bufmgr, heap, dest, &active, next_txn,
+        )?)
+    }
+
+    // カタログを書き、ページをすべて書き出してチェックポイントを書く
+    pub(crate) fn close(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        self.save(bufmgr, catalog)?;
+        recovery::shutdown(bufmgr, &self.txns.active(), self.txns.next_txn_id().0)?;
+        Ok(())
+    }
+}
+
+// スタンバイで開いているあいだ、プライマリから受け取ったログを当て、カタログのページが変わっていれば読み直す
+pub(crate) struct StandbyFile {
+    standby: Standby,
+    // 最後に読んだカタログ
+    saved: Vec<u8>,
+    // カタログを読んだあとにページの像を当てた
+    changed: bool,
+}
+
+impl StandbyFile {
+    pub(crate) fn applied_lsn(&self) -> Lsn {
+        self.standby.applied_lsn()
+    }
+
+    pub(crate) fn view(&self) -> &StandbyView {
+        self.standby.view()
+    }
+
+    // lsn のレコードを当てる。トランザクションの終わりが変われば true
+    pub(crate) fn apply(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        lsn: Lsn,
+        record: &LogRecord,
+    ) -> Result<bool, Error> {
+        if let LogRecord::PageImage { .. } | LogRecord::PageDelta { .. } = record {
+            self.changed = true;
+        }
+        Ok(self.standby.apply(bufmgr, lsn, record)?)
+    }
+
+    // ページの像を当てたあと、カタログが変わっていれば読み直す。
+    // カタログのページの像を当てている途中なら読めないので、続きを当ててから読み直す
+    pub(crate) fn reload(&mut self, bufmgr: &mut BufferPoolManager) -> Option<Catalog> {
+        if !self.changed {
+            return None;
+        }
+        let data = read(bufmgr).ok()?;
+        if data != self.saved {
+            let catalog = load(bufmgr, &data).ok()?;
+            self.saved = data;
+            self.changed = false;
+            return Some(catalog);
+        }
+        self.changed = false;
+        None
+    }
+}
+
+// wal のログを当ててからページのカタログを読む。新しいファイルなら空のカタログを書く。
+// 正しく閉じていなければ、コミットしていなかった版を片づけてからチェックポイントを書く。
+// 開いている間は、ログが checkpoint_wal_size だけ進むたびにチェックポイントを書く
+pub(crate) fn open(
+    bufmgr: &mut BufferPoolManager,
+    wal: Wal,
+    txns: &mut TransactionManager,
+    checkpoint_wal_size: Option<usize>,
+) -> Result<(Catalog, DatabaseFile), Error> {
+    let clean = wal.is_clean()
+        && wal
+            .checkpoint_lsn()
+            .map_err(recovery::Error::from)?
+            .is_some();
+    bufmgr.set_wal(wal);
+    start(bufmgr, txns, checkpoint_wal_size, clean)
+}
+
+// バックアップから戻したページとログを、終わっていないトランザクションを取り消さずに当ててスタンバイにする
+pub(crate) fn open_standby(
+    bufmgr: &mut BufferPoolManager,
+    wal: Wal,
+) -> Result<(Catalog, StandbyFile), Error> {
+    bufmgr.set_wal(wal);
+    let standby = Standby::new(bufmgr)?;
+    let saved = read(bufmgr)?;
+    let catalog = load(bufmgr, &saved)?;
+    let file = StandbyFile {
+        standby,
+        saved,
+        changed: false,
+    };
+    Ok((catalog, file))
+}
+
+// スタンバイを昇格させる。受け取ったログで回復し、コミットを受け取っていない版を片づけてから書けるようにする
+pub(crate) fn promote(
+    bufmgr: &mut BufferPoolManager,
+    txns: &mut TransactionManager,
+    checkpoint_wal_size: Option<usize>,
+) -> Result<(Catalog, DatabaseFile), Error> {
+    start(bufmgr, txns, checkpoint_wal_size, false)
+}
+
+// つないだログで回復し、カタログを読む。clean でなければコミットしていなかった版を片づける
+fn start(
+    bufmgr: &mut BufferPoolManager,
+    txns: &mut TransactionManager,
+    checkpoint_wal_size: Option<usize>,
+    clean: bool,
+) -> Result<(Catalog, DatabaseFile), Error> {
+    let stats = recovery::recover(bufmgr)?;
+    txns.advance_next_txn_id(TxnId(stats.next_txn));
+    txns.restore_prepared(&stats.prepared);
+    let (mut catalog, saved) = match bufmgr.page_count() {
+        0 => {
+            let catalog = Catalog::new();
+            let saved = encode(&catalog);
+            let buffer = bufmgr.create_page().map_err(executor::Error::from)?;
+            debug_assert_eq!(buffer.page_id, CATALOG_PAGE_ID);
+            set_next(&buffer, FIRST_HEADER_SIZE, PageId::INVALID_PAGE_ID);
+            drop(buffer);
+            write(bufmgr, &saved)?;
+            (catalog, saved)
+        }
+        _ => {
+            let saved = read(bufmgr)?;
+            (load(bufmgr, &saved)?, saved)
+        }
+    };
+    let mut file = DatabaseFile {
+        txns: txns.session(),
+        saved,
+        // ページは文が終わるたびに書き出してあるので、チェックポイントを書くだけでよい
+        checkpointer: checkpoint_wal_size.map(|size| Checkpointer::new(size as u64, 0)),
+    };
+    if !clean {
+        // 片づけの途中で落ちれば、次に開いたときに片づける前のページに戻してやり直せるよう、
+        // 回復したページを書き出してから、ページの像をとりながら片づける
+        bufmgr.flush().map_err(executor::Error::from)?;
+        bufmgr.log_page_images();
+        let (next_txn, committed) = recovery::committed(bufmgr)?;
+        let prepared: HashSet<u64> = stats.prepared.iter().map(|p| p.txn.txn).collect();
+        // チェックポイントの前に始まったものでも、回復で終わらせたものは残さない
+        let aborted: HashSet<u64> = stats.aborted.iter().copied().collect();
+        let kept = |txn: TxnId| {
+            txn == TxnId::FROZEN_TXN_ID
+                || (txn.0 < next_txn && !aborted.contains(&txn.0))
+                || committed.contains(&txn.0)
+                || prepared.contains(&txn.0)
+        };
+        for name in clean_up(bufmgr, &catalog, kept)? {
+            catalog.rebuild_indexes(bufmgr, &name)?;
+            let table = catalog.table(&name).ok_or(Error::BrokenDatabaseFile)?;
+            table.set_row_count(count_rows(bufmgr, table.storage.as_ref())?);
+        }
+        file.save(bufmgr, &catalog)?;
+        bufmgr.flush().map_err(executor::Error::from)?;
+        recovery::checkpoint(bufmgr, &txns.active(), txns.next_txn_id().0)?;
+    }
+    bufmgr.log_page_images();
+    Ok((catalog, file))
+}
+
+// ページの像から回復すると、像をとったときに実行中だったトランザクションの変更もページに残る。
+// kept でないトランザクションが入れた版を取り除き、消した版を戻して、変えたテーブルの名前を返す
+fn clean_up(
+    bufmgr: &mut BufferPoolManager,
+    catalog: &Catalog,
+    kept: impl Fn(TxnId) -> bool,
+) -> Result<Vec<String>, Error> {
+    let mut changed = vec![];
+    for table in catalog.tables() {
+        let mut versions = vec![];
+        let mut scan = table.storage.scan();
+        while let Some((rid, header, _)) = scan.next(bufmgr).map_err(executor::Error::from)? {
+            versions.push((rid, header));
+        }
+        let mut dirty = false;
+        for (rid, header) in versions {
+            let result = if !kept(header.xmin) {
+                table.storage.remove(bufmgr, rid)
+            } else if header.xmax.valid().is_some_and(|xmax| !kept(xmax)) {
+                table.storage.undelete(bufmgr, rid)
+            } else {
+                continue;
+            };
+            result.map_err(executor::Error::from)?;
+            #[cfg(test)]
+            crate::crash::hit(crate::crash::Point::CleanUp)?;
+            dirty = true;
+        }
+        if dirty {
+            changed.push(table.name.clone());
+        }
+    }
+    Ok(changed)
+}
+
+// 消されていない版の数
+fn count_rows(bufmgr: &mut BufferPoolManager, storage: &dyn TableAccess) -> Result<usize, Error> {
+    let mut rows = 0;
+    let mut scan = storage.scan();
+    while let Some((_, header, _)) = scan.next(bufmgr).map_err(executor::Error::from)? {
+        if !header.is_deleted() {
+            rows += 1;
+        }
+    }
+    Ok(rows)
+}
+
+// 残せるテーブル。パーティションは親を、実体化したビューは読むテーブルをすべて残せるときだけ残す
+fn persistent_tables(catalog: &Catalog) -> HashSet<&str> {
+    let mut kept = HashSet::new();
+    for table in catalog.tables() {
+        let parent = table
+            .partition
+            .as_ref()
+            .is_none_or(|p| kept.contains(p.parent.as_str()));
+        let sources = table
+            .view
+            .as_ref()
+            .is_none_or(|v| v.sources.iter().all(|(s, _)| kept.contains(s.as_str())));
+        let engine = [DEFAULT_STORAGE_ENGINE, LSM_STORAGE_ENGINE].contains(&table.engine.as_str());
+        if engine && parent && sources {
+            kept.insert(table.name.as_str());
+        }
+    }
+    kept
+}
+
+fn encode(catalog: &Catalog) -> Vec<u8> {
+    let kept = persistent_tables(catalog);
+    let mut bytes = vec![];
+    put_u32(&mut bytes, catalog.next_type_id());
+    put_u32(&mut bytes, catalog.enum_types().len() as u32);
+    for ty in catalog.enum_types() {
+        put_u32(&mut bytes, ty.id);
+        put_str(&mut bytes, &ty.name);
+        let labels = ty.label_sorts();
+        put_u32(&mut bytes, labels.len() as u32);
+        for (label, sort) in labels {
+            put_str(&mut bytes, &label);
+            put_u32(&mut bytes, sort);
+        }
+    }
+    put_str(
+        &mut bytes,
+        &dump::schema(catalog, |t| kept.contains(t.name.as_str())),
+    );
+    let tables: Vec<&Table> = catalog
+        .tables()
+        .iter()
+        .filter(|t| kept.contains(t.name.as_str()))
+        .collect();
+    put_u32(&mut bytes, tables.len() as u32);
+    for table in tables {
+        put_str(&mut bytes, &table.name);
+        put_u64(&mut bytes, table.storage.first_page_id().0);
+        put_u32(&mut bytes, table.indexes.len() as u32);
+        for index in &table.indexes {
+            put_str(&mut bytes, &index.name);
+            put_u64(&mut bytes, index.btree.meta_page_id.0);
+            let rtree = index
+                .rtree
+                .map_or(PageId::INVALID_PAGE_ID, |r| r.meta_page_id);
+            put_u64(&mut bytes, rtree.0);
+        }
+        put_u32(&mut bytes, table.dictionaries.len() as u32);
+        for dictionary in &table.dictionaries {
+            put_u32(&mut bytes, dictionary.column as u32);
+            put_u32(&mut bytes, dictionary.len() as u32);
+            for code in 0..dictionary.len() as u32 {
+                put_str(&mut bytes, &dictionary.value(code).unwrap_or_default());
+            }
+        }
+    }
+    put_u32(&mut bytes, catalog.roles().len() as u32);
+    for role in catalog.roles() {
+        put_str(&mut bytes, &role.name);
+        bytes.push(role.superuser as u8);
+        bytes.push(role.login as u8);
+        match role.password_hash() {
+            Some((salt, hash)) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&salt);
+                bytes.extend_from_slice(&hash);
+            }
+            None => bytes.push(0),
+        }
+    }
+    let grants: Vec<_> = catalog
+        .grants()
+        .iter()
+        .filter(|g| kept.contains(g.table.as_str()))
+        .collect();
+    put_u32(&mut bytes, grants.len() as u32);
+    for grant in grants {
+        put_str(&mut bytes, &grant.table);
+        put_str(&mut bytes, &grant.role);
+        let privilege = Privilege::ALL.iter().position(|&p| p == grant.privilege);
+        bytes.push(privilege.unwrap() as u8);
+    }
+    bytes
+}
+
+// CREATE の文を空のデータベースで実行して作ったカタログを、ファイルのページにつなぎ直す
+fn load(bufmgr: &mut BufferPoolManager, bytes: &[u8]) -> Result<Catalog, Error> {
+    let mut input = Input { bytes, pos: 0 };
+    let next_type_id = input.u32()?;
+    let mut types = vec![];
+    for _ in 0..input.u32()? {
+        let id = input.u32()?;
+        let name = input.str()?;
+        let labels = (0..input.u32()?)
+            .map(|_| Ok((input.str()?, input.u32()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        types.push(EnumType::restore(id, &name, labels));
+    }
+    let schema = input.str()?;
+    let db = Database::open_in_memory()?;
+    let mut conn = db.connect();
+    conn.catalog_mut().restore_enum_types(types, next_type_id);
+    if !schema.is_empty() {
+        conn.execute_batch(&schema)?;
+    }
+    let mut catalog = conn.catalog().clone();
+    drop(conn);
+    for _ in 0..input.u32()? {
+        let name = input.str()?;
+        let first_page_id = PageId(input.u64()?);
+        let mut indexes = vec![];
+        for _ in 0..input.u32()? {
+            let index = input.str()?;
+            let btree = BTree {
+                meta_page_id: PageId(input.u64()?),
+            };
+            let rtree = PageId(input.u64()?)
+                .valid()
+                .map(|meta_page_id| RTree { meta_page_id });
+            indexes.push((index, btree, rtree));
+        }
+        let table = catalog.table(&name).ok_or(Error::BrokenDatabaseFile)?;
+        let storage: Arc<dyn TableAccess> = match table.engine.as_str() {
+            LSM_STORAGE_ENGINE => {
+                Arc::new(LsmTree::open(bufmgr, first_page_id).map_err(executor::Error::from)?)
+            }
+            _ => Arc::new(HeapFile { first_page_id }),
+        };
+        catalog.attach_storage(&name, storage.clone(), &indexes)?;
+        let table = catalog.table(&name).ok_or(Error::BrokenDatabaseFile)?;
+        for _ in 0..input.u32()? {
+            let column = input.u32()? as usize;
+            let dictionary = table
+                .dictionaries
+                .iter()
+                .find(|d| d.column == column)
+                .ok_or(Error::BrokenDatabaseFile)?;
+            for _ in 0..input.u32()? {
+                dictionary.intern(&input.str()?);
+            }
+        }
+        table.set_row_count(count_rows(bufmgr, storage.as_ref())?);
+    }
+    for _ in 0..input.u32()? {
+        let mut role = Role::new(&input.str()?);
+        role.superuser = input.flag()?;
+        role.login = input.flag()?;
+        if input.flag()? {
+            let salt = input.take(16)?.try_into().unwrap();
+            let hash = input.take(32)?.try_into().unwrap();
+            role.restore_password(salt, hash);
+        }
+        catalog.create_role(role)?;
+    }
+    for _ in 0..input.u32()? {
+        let table = input.str()?;
+        let role = input.str()?;
+        let privilege = *Privilege::ALL
+            .get(input.take(1)?[0] as usize)
+            .ok_or(Error::BrokenDatabaseFile)?;
+        catalog.grant(&table, &role, privilege)?;
+    }
+    // 最後まで読めたものだけを、書いたカタログとする
+    if input.pos != bytes.len() {
+        return Err(Error::BrokenDatabaseFile);
+    }
+    Ok(catalog)
+}
+
+fn write(bufmgr: &mut BufferPoolManager, data: &[u8]) -> Result<(), Error> {
+    let mut page_id = CATALOG_PAGE_ID;
+    let mut rest = data;
+    loop {
+        let buffer = bufmgr.fetch_page(page_id).map_err(executor::Error::from)?;
+        let header = header_size(page_id);
+        let (chunk, tail) = rest.split_at(rest.len().min(PAGE_SIZE - header));
+        let mut next = next_page(&buffer, header);
+        if !tail.is_empty() && next.valid().is_none() {
+            let page = bufmgr.create_page().map_err(executor::Error::from)?;
+            set_next(&page, NEXT_HEADER_SIZE, PageId::INVALID_PAGE_ID);
+            next = page.page_id;
+        }
+        {
+            let page = &mut *buffer.page.borrow_mut();
+            if page_id == CATALOG_PAGE_ID {
+                page[..8].copy_from_slice(MAGIC);
+                page[8..16].copy_from_slice(&(data.len() as u64).to_be_bytes());
+            }
+            page[header..header + chunk.len()].copy_from_slice(chunk);
+        }
+        #[cfg(test)]
+        crate::crash::hit(crate::crash::Point::CatalogWrite)?;
+        set_next(&buffer, header, next);
+        buffer.is_dirty.set(true);
+        if tail.is_empty() {
+            return Ok(());
+        }
+        rest = tail;
+        page_id = next;
+    }
+}
+
+fn read(bufmgr: &mut BufferPoolManager) -> Result<Vec<u8>, Error> {
+    let buffer = bufmgr
+        .fetch_page(CATALOG_PAGE_ID)
+        .map_err(executor::Error::from)?;
+    let len = {
+        let page = buffer.page.borrow();
+        if &page[..8] != MAGIC {
+            return Err(Error::BrokenDatabaseFile);
+        }
+        u64::from_be_bytes(page[8..16].try_into().unwrap()) as usize
+    };
+    let mut data = Vec::with_capacity(len);
+    let mut visited = HashSet::new();
+    let mut buffer = Some(buffer);
+    let mut page_id = CATALOG_PAGE_ID;
+    while data.len() < len {
+        let page = match buffer.take() {
+            Some(page) => page,
+            None => bufmgr.fetch_page(page_id).map_err(executor::Error::from)?,
+        };
+        let header = header_size(page_id);
+        let n = (len - data.len()).min(PAGE_SIZE - header);
+        data.extend_from_slice(&page.page.borrow()[header..header + n]);
+        page_id = next_page(&page, header);
+        // 書きかけで切れたリストや、輪になったリストは読まない
+        if data.len() < len
+            && (page_id.valid().is_none()
+                || page_id.0 >= bufmgr.page_count()
+                || !visited.insert(page_id))
+        {
+            return Err(Error::BrokenDatabaseFile);
+        }
+    }
+    Ok(data)
+}
+
+fn header_size(page_id: PageId) -> usize {
+    match page_id {
+        CATALOG_PAGE_ID => FIRST_HEADER_SIZE,
+        _ => NEXT_HEADER_SIZE,
+    }
+}
+
+// 次のページの番号は、ヘッダの最後の 8 バイトにある
+fn next_page(buffer: &Rc<Buffer>, header: usize) -> PageId {
+    let page = buffer.page.borrow();
+    PageId(u64::from_be_bytes(
+        page[header - 8..header].try_into().unwrap(),
+    ))
+}
+
+fn set_next(buffer: &Rc<Buffer>, header: usize, next: PageId) {
+    buffer.page.borrow_mut()[header - 8..header].copy_from_slice(&next.0.to_be_bytes());
+}
+
+fn put_u32(bytes: &mut Vec<u8>, n: u32) {
+    bytes.extend_from_slice(&n.to_be_bytes());
+}
+
+fn put_u64(bytes: &mut Vec<u8>, n: u64) {
+    bytes.extend_from_slice(&n.to_be_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, s: &str) {
+    put_u32(bytes, s.len() as u32);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Input<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or(Error::BrokenDatabaseFile)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn flag(&mut self) -> Result<bool, Error> {
+        match self.take(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::BrokenDatabaseFile),
+        }
+    }
+
+    fn str(&mut self) -> Result<String, Error> {
+        let n = self.u32()? as usize;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|_| Error::BrokenDatabaseFile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_must_be_read() {
+        let db = Database::open_in_memory().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER);
+             CREATE USER alice PASSWORD 'secret';
+             GRANT SELECT ON t TO alice",
+        )
+        .unwrap();
+        let mut catalog = conn.catalog().clone();
+        let mut bufmgr = crate::testutil::temp_bufmgr(4);
+        let heap = HeapFile::create(&mut bufmgr).unwrap();
+        catalog.attach_storage("t", Arc::new(heap), &[]).unwrap();
+        let bytes = encode(&catalog);
+        let loaded = load(&mut bufmgr, &bytes).unwrap();
+        assert!(loaded.role("alice").unwrap().check_password("secret"));
+        assert!(loaded.has_privilege("alice", "t", Privilege::Select));
+        // ロールの部分が欠けていれば、ロールのないカタログとして開かない
+        for len in [bytes.len() - 1, bytes.len() - 40] {
+            assert!(matches!(
+                load(&mut bufmgr, &bytes[..len]),
+                Err(Error::BrokenDatabaseFile)
+            ));
+        }
+    }
+}
@@ -0,0 +1,1647 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+
+use crate::connection::{self, Connection, StatementResult};
+use crate::datetime::{Date, Interval, Time, Timestamp};
+use crate::geometry::{Point, Rect};
+use crate::sql;
+use crate::sql::ast::{Statement, TransactionStatement};
+use crate::trace;
+use crate::types::{DataType, Value};
+use crate::uuid::Uuid;
+
+// PostgreSQL のフロントエンド/バックエンドプロトコル (v3)
+//
+// LOGIN のロールがあれば、平文のパスワードを求めて起動パケットの user のロールとして認証する。
+// なければ認証はせず、起動メッセージが来ればすぐに AuthenticationOk を返す。SSL と GSSAPI の要求は断る。
+// 単純問い合わせと、Parse/Bind/Describe/Execute/Sync の拡張問い合わせを受け付ける。
+// 結果の列の型は計画の式と列の型から決め、決まらない列は Describe (文) では text と伝える。
+// Describe (ポータル) は実行してから、決まらない列の型を返した値から決める。
+// 型を伝えられていない $n は比べる列や入れる列の型とし、それも決まらなければ text として受け取る
+
+// 型の OID
+pub mod oid {
+    pub const BOOL: i32 = 16;
+    pub const BYTEA: i32 = 17;
+    pub const INT8: i32 = 20;
+    pub const INT2: i32 = 21;
+    pub const INT4: i32 = 23;
+    pub const TEXT: i32 = 25;
+    pub const JSON: i32 = 114;
+    pub const POINT: i32 = 600;
+    pub const BOX: i32 = 603;
+    pub const FLOAT4: i32 = 700;
+    pub const FLOAT8: i32 = 701;
+    pub const UNKNOWN: i32 = 705;
+    pub const BPCHAR: i32 = 1042;
+    pub const VARCHAR: i32 = 1043;
+    pub const DATE: i32 = 1082;
+    pub const TIME: i32 = 1083;
+    pub const TIMESTAMP: i32 = 1114;
+    pub const TIMESTAMPTZ: i32 = 1184;
+    pub const INTERVAL: i32 = 1186;
+    pub const NUMERIC: i32 = 1700;
+    pub const UUID: i32 = 2950;
+    pub const JSONB: i32 = 3802;
+}
+
+const PROTOCOL_V3: i32 = 196608;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+// 1 つのメッセージの長さの上限
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+// Describe (ポータル) で列の型を決めるために先に読んでおく行数
+const DESCRIBE_ROWS: usize = 100;
+// PostgreSQL の日時は 2000-01-01 からの日数とマイクロ秒で送る
+const PG_EPOCH_DAYS: i32 = 10957;
+const PG_EPOCH_MICROS: i64 = PG_EPOCH_DAYS as i64 * 86_400_000_000;
+
+// 起動パケットで受け取った接続の情報
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Startup {
+    pub params: Vec<(String, String)>,
+}
+
+impl Startup {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// 起動パケットを受け取ったあとで接続を断るときに送る FATAL のエラー
+pub fn startup_error(e: &connection::Error) -> Vec<u8> {
+    let mut out = Messages::default();
+    out.error("FATAL", e.sqlstate(), &e.to_string());
+    out.buf
+}
+
+// 起動の段階で来たもの
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartupRequest {
+    Startup(Startup),
+    // 別の接続で実行中の問い合わせを止める要求。BackendKeyData で渡した組
+    Cancel { process_id: i32, secret_key: i32 },
+}
+
+// 起動パケットを読む。SSL と GSSAPI の要求には N を返して次を待つ
+pub fn read_startup(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<StartupRequest> {
+    loop {
+        let len = read_i32(reader)?;
+        if !(8..=10_000).contains(&len) {
+            return Err(protocol_error("invalid startup packet length"));
+        }
+        let mut body = vec![0; len as usize - 4];
+        reader.read_exact(&mut body)?;
+        let mut body = Body::new(body);
+        match body.i32()? {
+            SSL_REQUEST | GSSENC_REQUEST => {
+                writer.write_all(b"N")?;
+                writer.flush()?;
+            }
+            CANCEL_REQUEST => {
+                return Ok(StartupRequest::Cancel {
+                    process_id: body.i32()?,
+                    secret_key: body.i32()?,
+                })
+            }
+            PROTOCOL_V3 => {
+                let mut startup = Startup::default();
+                loop {
+                    let name = body.cstr()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    startup.params.push((name, body.cstr()?));
+                }
+                return Ok(StartupRequest::Startup(startup));
+            }
+            version => {
+                let message = format!(
+                    "unsupported frontend protocol {}.{}",
+                    version >> 16,
+                    version & 0xffff
+                );
+                let mut out = Messages::default();
+                out.error("FATAL", "0A000", &message);
+                writer.write_all(&out.buf)?;
+                return Err(protocol_error(&message));
+            }
+        }
+    }
+}
+
+// 起動が終わった接続で、Terminate か接続が切れるまでメッセージに答える
+pub fn serve(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    conn: Connection,
+    process_id: i32,
+    secret_key: i32,
+) -> io::Result<()> {
+    let mut session = Session::new(conn, process_id, secret_key);
+    loop {
+        writer.write_all(&session.take_output())?;
+        writer.flush()?;
+        let Some((tag, body)) = read_message(reader)? else {
+            return Ok(());
+        };
+        if tag == b'X' {
+            return Ok(());
+        }
+        if let Err(e) = session.handle(tag, body) {
+            writer.write_all(&session.take_output())?;
+            return Err(e);
+        }
+    }
+}
+
+// 起動のあとのメッセージを 1 つ読む。接続が切れていれば None
+pub fn read_message(reader: &mut dyn Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut tag = [0];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    let len = read_i32(reader)?;
+    if len < 4 || len as usize > MAX_MESSAGE_LEN {
+        return Err(protocol_error("invalid message length"));
+    }
+    let mut body = vec![0; len as usize - 4];
+    reader.read_exact(&mut body)?;
+    Ok(Some((tag[0], body)))
+}
+
+// Parse で作った文
+// Bind で引数を入れた文
+struct Portal {
+    sql: String,
+    stmt: Option<Statement>,
+    params: Vec<Value>,
+    // 0 は text、1 は binary。1 つだけならすべての列に使う
+    result_formats: Vec<i16>,
+    // Describe で文かポータルについて伝えた列の型。伝えていなければすべて text として送る
+    types: Option<Vec<i32>>,
+    // 実行した結果のうち、まだ送っていないもの
+    pending: Option<Pending>,
+}
+
+enum Pending {
+    Rows {
+        columns: Vec<String>,
+        rows: VecDeque<Vec<Value>>,
+        sent: usize,
+        // 同じ名前のカーソルから、まだ行を読める
+        open: bool,
+    },
+    Done(String),
+}
+
+// ロックを待って戻った文。retry でそこから実行し直す
+enum Parked {
+    // 単純問い合わせの、next から後の文
+    Query {
+        sql: String,
+        statements: Vec<Statement>,
+        next: usize,
+    },
+    Execute {
+        name: String,
+        max_rows: i32,
+    },
+}
+
+// 1 つのクライアントとのやりとり。接続と、名前をつけた文とポータルを持つ
+pub struct Session {
+    conn: Connection,
+    out: Messages,
+    portals: HashMap<String, Portal>,
+    // Describe (文) で伝えた、文ごとの $n の型と結果の列の型。Bind はこの型で引数を読み、列を送る
+    described: HashMap<String, (Vec<i32>, Option<Vec<i32>>)>,
+    // 拡張問い合わせでエラーになったら、Sync までのメッセージを読み捨てる
+    skip_until_sync: bool,
+    // ポータルのカーソルのために始めたトランザクションの中にいる。Sync で終える
+    implicit: bool,
+    // パスワードを待っている間の、認証するロールと BackendKeyData で渡す組
+    login: Option<(String, i32, i32)>,
+    parked: Option<Parked>,
+}
+
+impl Session {
+    // 起動できたことを伝えるメッセージを出しておく
+    pub fn new(conn: Connection, process_id: i32, secret_key: i32) -> Session {
+        let mut session = Session::unstarted(conn);
+        session.start(process_id, secret_key);
+        session
+    }
+
+    // パスワードが要るなら、startup の user のロールのパスワードを求めるメッセージを出しておく。
+    // 要らなければ new と同じ
+    pub fn login(conn: Connection, startup: &Startup, process_id: i32, secret_key: i32) -> Session {
+        if !conn.requires_password() {
+            return Session::new(conn, process_id, secret_key);
+        }
+        let mut session = Session::unstarted(conn);
+        let user = startup.get("user").unwrap_or_default().to_string();
+        session.login = Some((user, process_id, secret_key));
+        // AuthenticationCleartextPassword
+        session.out.message(b'R', |m| m.i32(3));
+        session
+    }
+
+    fn unstarted(conn: Connection) -> Session {
+        Session {
+            conn,
+            out: Messages::default(),
+            portals: HashMap::new(),
+            described: HashMap::new(),
+            skip_until_sync: false,
+            implicit: false,
+            login: None,
+            parked: None,
+        }
+    }
+
+    fn start(&mut self, process_id: i32, secret_key: i32) {
+        self.out.message(b'R', |m| m.i32(0));
+        for (name, value) in [
+            ("server_version", "16.0"),
+            ("server_encoding", "UTF8"),
+            ("client_encoding", "UTF8"),
+            ("DateStyle", "ISO, MDY"),
+            ("integer_datetimes", "on"),
+            ("standard_conforming_strings", "on"),
+            ("TimeZone", "UTC"),
+        ] {
+            self.out.message(b'S', |m| {
+                m.cstr(name);
+                m.cstr(value);
+            });
+        }
+        self.out.message(b'K', |m| {
+            m.i32(process_id);
+            m.i32(secret_key);
+        });
+        self.ready();
+    }
+
+    // Terminate のほかのメッセージに答える。Err ならプロトコルの誤りか認証の失敗で、接続を閉じなければならない
+    pub fn handle(&mut self, tag: u8, body: Vec<u8>) -> io::Result<()> {
+        if let Some((user, process_id, secret_key)) = self.login.take() {
+            return self.authenticate(tag, Body::new(body), &user, process_id, secret_key);
+        }
+        let result = self.dispatch(tag, Body::new(body));
+        if let Err(e) = &result {
+            self.out.error("FATAL", "08P01", &e.to_string());
+        }
+        result
+    }
+
+    // PasswordMessage を受け取って認証する
+    fn authenticate(
+        &mut self,
+        tag: u8,
+        mut body: Body,
+        user: &str,
+        process_id: i32,
+        secret_key: i32,
+    ) -> io::Result<()> {
+        if tag != b'p' {
+            let message = "expected password response";
+            self.out.error("FATAL", "08P01", message);
+            return Err(protocol_error(message));
+        }
+        let password = body.cstr()?;
+        match self.conn.authenticate(user, &password) {
+            Ok(()) => {
+                self.start(process_id, secret_key);
+                Ok(())
+            }
+            Err(e) => {
+                self.out.error("FATAL", e.sqlstate(), &e.to_string());
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    e.to_string(),
+                ))
+            }
+        }
+    }
+
+    // 接続の set_park_lock_waits で、文がロックを待って戻った。
+    // 待っている間は応答を送らずに、ほかのロックが外れたら retry を呼ぶ
+    pub fn waiting_for_lock(&self) -> bool {
+        self.parked.is_some()
+    }
+
+    // ロックを待っていた文から実行し直す
+    pub fn retry(&mut self) {
+        match self.parked.take() {
+            Some(Parked::Query {
+                sql,
+                statements,
+                next,
+            }) => {
+                self.run_statements(sql, statements, next);
+                self.finish_query();
+            }
+            Some(Parked::Execute { name, max_rows }) => self.execute(&name, max_rows),
+            None => {}
+        }
+    }
+
+    // まだ送っていない応答
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out.buf)
+    }
+
+    fn dispatch(&mut self, tag: u8, mut body: Body) -> io::Result<()> {
+        if self.skip_until_sync && tag != b'S' {
+            return Ok(());
+        }
+        match tag {
+            b'Q' => {
+                let sql = body.cstr()?;
+                self.simple_query(&sql);
+                self.finish_query();
+            }
+            b'P' => {
+                let name = body.cstr()?;
+                let sql = body.cstr()?;
+                let n = body.i16()?;
+                let param_types = (0..n).map(|_| body.i32()).collect::<io::Result<_>>()?;
+                self.parse(name, &sql, param_types);
+            }
+            b'B' => self.bind(&mut body)?,
+            b'D' => {
+                let kind = body.u8()?;
+                let name = body.cstr()?;
+                self.describe(kind, &name);
+            }
+            b'E' => {
+                let name = body.cstr()?;
+                let max_rows = body.i32()?;
+                self.execute(&name, max_rows);
+            }
+            b'C' => {
+                let kind = body.u8()?;
+                let name = body.cstr()?;
+                if kind == b'S' {
+                    self.conn.deallocate(&name);
+                    self.described.remove(&name);
+                } else {
+                    self.remove_portal(&name);
+                }
+                self.out.message(b'3', |_| {});
+            }
+            b'S' => {
+                self.sync();
+                self.skip_until_sync = false;
+                self.ready();
+            }
+            b'H' => {}
+            tag => {
+                return Err(protocol_error(&format!(
+                    "invalid frontend message type {}",
+                    tag as char
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn ready(&mut self) {
+        let status = if self.conn.in_transaction() {
+            b'T'
+        } else {
+            b'I'
+        };
+        self.out.message(b'Z', |m| m.u8(status));
+    }
+
+    fn fail(&mut self, e: &connection::Error) {
+        self.out.error("ERROR", e.sqlstate(), &e.to_string());
+        self.skip_until_sync = true;
+    }
+
+    fn simple_query(&mut self, sql: &str) {
+        let statements = match sql::parse(sql) {
+            Ok(statements) => statements,
+            Err(e) => return self.fail(&e.into()),
+        };
+        if statements.is_empty() {
+            self.out.message(b'I', |_| {});
+        }
+        self.run_statements(sql.to_string(), statements, 0);
+    }
+
+    // 単純問い合わせでは Sync を待たない。ロックを待っていれば、終わってから答える
+    fn finish_query(&mut self) {
+        if self.parked.is_none() {
+            self.skip_until_sync = false;
+            self.ready();
+        }
+    }
+
+    fn run_statements(&mut self, sql: String, statements: Vec<Statement>, start: usize) {
+        let _span = trace::span("statement", &[("sql", &sql)]);
+        for (i, stmt) in statements.iter().enumerate().skip(start) {
+            match self.conn.execute_parsed(&sql, stmt, &[]) {
+                Ok(StatementResult::Rows(rows)) => {
+                    let tag = format!("SELECT {}", rows.len());
+                    let columns = rows.columns().to_vec();
+                    let rows: Vec<_> = rows.map(|row| row.into_values()).collect();
+                    let types = result_types(columns.len(), &rows);
+                    self.out.row_description(&columns, &types, &[]);
+                    for row in &rows {
+                        self.out.data_row(row, &types, &[]);
+                    }
+                    self.out.command_complete(&tag);
+                }
+                Ok(result) => self.out.command_complete(&result.tag()),
+                Err(e) if e.is_lock_wait() => {
+                    self.parked = Some(Parked::Query {
+                        sql,
+                        statements,
+                        next: i,
+                    });
+                    return;
+                }
+                Err(e) => return self.fail(&e),
+            }
+        }
+    }
+
+    fn parse(&mut self, name: String, sql: &str, param_types: Vec<i32>) {
+        self.described.remove(&name);
+        if let Err(e) = self.conn.prepare(&name, sql, &param_types) {
+            return self.fail(&e);
+        }
+        self.out.message(b'1', |_| {});
+    }
+
+    fn bind(&mut self, body: &mut Body) -> io::Result<()> {
+        let portal = body.cstr()?;
+        let name = body.cstr()?;
+        let n = body.i16()?;
+        let formats: Vec<i16> = (0..n).map(|_| body.i16()).collect::<io::Result<_>>()?;
+        let n = body.i16()? as usize;
+        let mut raw = Vec::with_capacity(n);
+        for _ in 0..n {
+            let len = body.i32()?;
+            raw.push(if len < 0 {
+                None
+            } else {
+                Some(body.bytes(len as usize)?)
+            });
+        }
+        let n = body.i16()?;
+        let result_formats = (0..n).map(|_| body.i16()).collect::<io::Result<_>>()?;
+        let prepared = match self.conn.prepared(&name) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                self.fail(&e);
+                return Ok(());
+            }
+        };
+        // Describe (文) を待たずに、型を書かなかった $n の型を文から決めて読む。
+        // 文の誤りは Execute で返す
+        let param_types = self
+            .param_types(&name)
+            .unwrap_or_else(|_| prepared.param_types.clone());
+        let described = self.described.get(&name);
+        let mut params = Vec::with_capacity(raw.len());
+        for (i, bytes) in raw.into_iter().enumerate() {
+            let format = match formats.len() {
+                0 => 0,
+                1 => formats[0],
+                _ => formats.get(i).copied().unwrap_or(0),
+            };
+            let ty = param_types.get(i).copied().unwrap_or(0);
+            match bytes.map(|b| decode_param(&b, ty, format)).transpose() {
+                Ok(value) => params.push(value.unwrap_or(Value::Null)),
+                Err(message) => {
+                    self.out.error("ERROR", "22P02", &message);
+                    self.skip_until_sync = true;
+                    return Ok(());
+                }
+            }
+        }
+        let sql = prepared.sql.clone();
+        let stmt = prepared.statement.clone();
+        let types = described.and_then(|(_, types)| types.clone());
+        self.remove_portal(&portal);
+        self.portals.insert(
+            portal,
+            Portal {
+                sql,
+                stmt,
+                params,
+                result_formats,
+                types,
+                pending: None,
+            },
+        );
+        self.out.message(b'2', |_| {});
+        Ok(())
+    }
+
+    // 文の $n の型の OID。型を書かなかったものは文から決め、決まらなければ TEXT
+    fn param_types(&self, name: &str) -> Result<Vec<i32>, connection::Error> {
+        let description = self.conn.describe_prepared(name)?;
+        let declared = &self.conn.prepared(name)?.param_types;
+        Ok(description
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| match declared.get(i).copied().unwrap_or(0) {
+                0 => ty.as_ref().map_or(oid::TEXT, type_oid),
+                declared => declared,
+            })
+            .collect())
+    }
+
+    fn describe(&mut self, kind: u8, name: &str) {
+        if kind == b'S' {
+            let (params, description) = match self
+                .param_types(name)
+                .and_then(|params| Ok((params, self.conn.describe_prepared(name)?)))
+            {
+                Ok(described) => described,
+                Err(e) => return self.fail(&e),
+            };
+            self.out.message(b't', |m| {
+                m.i16(params.len() as i16);
+                params.iter().for_each(|&ty| m.i32(ty));
+            });
+            let types = match description.columns {
+                Some(columns) => {
+                    let (columns, types): (Vec<_>, Vec<_>) = columns
+                        .into_iter()
+                        .map(|(name, ty)| (name, ty.as_ref().map_or(oid::TEXT, type_oid)))
+                        .unzip();
+                    self.out.row_description(&columns, &types, &[]);
+                    Some(types)
+                }
+                None => {
+                    self.out.message(b'n', |_| {});
+                    None
+                }
+            };
+            self.described.insert(name.to_string(), (params, types));
+            return;
+        }
+        // ポータルは実行して、計画から決まらない列の型を返した値から決める
+        if let Err(e) = self
+            .run_portal(name)
+            .and_then(|()| self.fill_portal(name, DESCRIBE_ROWS))
+        {
+            return self.fail(&e);
+        }
+        let Some(portal) = self.portals.get_mut(name) else {
+            return self.portal_not_found(name);
+        };
+        let planned = match &portal.stmt {
+            Some(stmt) => match self.conn.describe_statement(stmt, &portal.params) {
+                Ok(description) => description.columns,
+                Err(e) => return self.fail(&e),
+            },
+            None => None,
+        };
+        match &mut portal.pending {
+            Some(Pending::Rows { columns, rows, .. }) => {
+                let mut types = result_types(columns.len(), rows.make_contiguous());
+                for (ty, (_, planned)) in types.iter_mut().zip(planned.iter().flatten()) {
+                    if let Some(planned) = planned {
+                        *ty = type_oid(planned);
+                    }
+                }
+                self.out
+                    .row_description(columns, &types, &portal.result_formats);
+                portal.types = Some(types);
+            }
+            _ => self.out.message(b'n', |_| {}),
+        }
+    }
+
+    fn portal_not_found(&mut self, name: &str) {
+        self.out.error(
+            "ERROR",
+            "34000",
+            &format!("portal \"{}\" does not exist", name),
+        );
+        self.skip_until_sync = true;
+    }
+
+    // まだ実行していなければ実行する。問い合わせはポータルと同じ名前のカーソルを開き、
+    // 行は Execute で要るだけ読む。そのほかの文は結果をためる
+    fn run_portal(&mut self, name: &str) -> Result<(), connection::Error> {
+        let Some(portal) = self.portals.get_mut(name) else {
+            return Ok(());
+        };
+        if portal.pending.is_some() {
+            return Ok(());
+        }
+        let pending = match &portal.stmt {
+            None => None,
+            // FOR UPDATE はロックを待って実行し直せるよう、カーソルにせずに最後まで実行する
+            Some(stmt @ Statement::Query(query)) if query.locking.is_none() => {
+                // カーソルはトランザクションの中でしか開けない
+                if !self.conn.in_transaction() {
+                    self.conn.begin()?;
+                    self.implicit = true;
+                }
+                self.conn
+                    .declare_parsed_cursor(name, stmt, &portal.params)?;
+                let columns = match self.conn.fetch_cursor(name, 0) {
+                    Ok(rows) => rows.columns().to_vec(),
+                    Err(e) => {
+                        let _ = self.conn.close_cursor(name);
+                        return Err(e);
+                    }
+                };
+                Some(Pending::Rows {
+                    columns,
+                    rows: VecDeque::new(),
+                    sent: 0,
+                    open: true,
+                })
+            }
+            // 拡張問い合わせの BEGIN は、始めておいたトランザクションをそのまま使う
+            Some(Statement::Transaction(TransactionStatement::Begin)) if self.implicit => {
+                self.implicit = false;
+                Some(Pending::Done("BEGIN".to_string()))
+            }
+            Some(stmt) => Some(
+                match self
+                    .conn
+                    .execute_cached(&portal.sql, stmt, &portal.params)?
+                {
+                    StatementResult::Rows(rows) => Pending::Rows {
+                        columns: rows.columns().to_vec(),
+                        rows: rows.map(|row| row.into_values()).collect(),
+                        sent: 0,
+                        open: false,
+                    },
+                    result => Pending::Done(result.tag()),
+                },
+            ),
+        };
+        portal.pending = pending;
+        Ok(())
+    }
+
+    // ためた行が limit より少なければ、カーソルから足りない分を読む
+    fn fill_portal(&mut self, name: &str, limit: usize) -> Result<(), connection::Error> {
+        let Some(Portal {
+            pending: Some(Pending::Rows { rows, open, .. }),
+            ..
+        }) = self.portals.get_mut(name)
+        else {
+            return Ok(());
+        };
+        if !*open || rows.len() >= limit {
+            return Ok(());
+        }
+        let n = limit - rows.len();
+        let fetched: Vec<_> = self
+            .conn
+            .fetch_cursor(name, n)?
+            .map(|row| row.into_values())
+            .collect();
+        if fetched.len() < n {
+            *open = false;
+        }
+        let done = !*open;
+        rows.extend(fetched);
+        if done {
+            self.conn.close_cursor(name)?;
+        }
+        Ok(())
+    }
+
+    // ポータルを捨てる。開いているカーソルも閉じる
+    fn remove_portal(&mut self, name: &str) {
+        if let Some(Portal {
+            pending: Some(Pending::Rows { open: true, .. }),
+            ..
+        }) = self.portals.remove(name)
+        {
+            let _ = self.conn.close_cursor(name);
+        }
+    }
+
+    // ポータルのために始めたトランザクションを終える。
+    // トランザクションが終わればカーソルも閉じるので、読みかけのポータルは捨てる
+    fn sync(&mut self) {
+        // 文の中で COMMIT していれば、もう終わっている
+        if std::mem::take(&mut self.implicit) && self.conn.in_transaction() {
+            let result = if self.skip_until_sync {
+                self.conn.rollback()
+            } else {
+                self.conn.commit()
+            };
+            if let Err(e) = result {
+                self.out.error("ERROR", e.sqlstate(), &e.to_string());
+            }
+        }
+        if !self.conn.in_transaction() {
+            self.portals.retain(|_, portal| {
+                !matches!(portal.pending, Some(Pending::Rows { open: true, .. }))
+            });
+        }
+    }
+
+    fn execute(&mut self, name: &str, max_rows: i32) {
+        if !self.portals.contains_key(name) {
+            return self.portal_not_found(name);
+        }
+        let limit = if max_rows > 0 {
+            max_rows as usize
+        } else {
+            usize::MAX
+        };
+        match self
+            .run_portal(name)
+            .and_then(|()| self.fill_portal(name, limit))
+        {
+            Ok(()) => {}
+            Err(e) if e.is_lock_wait() => {
+                self.parked = Some(Parked::Execute {
+                    name: name.to_string(),
+                    max_rows,
+                });
+                return;
+            }
+            Err(e) => return self.fail(&e),
+        }
+        let portal = self.portals.get_mut(name).unwrap();
+        match &mut portal.pending {
+            None => self.out.message(b'I', |_| {}),
+            Some(Pending::Done(tag)) => self.out.command_complete(tag),
+            Some(Pending::Rows {
+                columns,
+                rows,
+                sent,
+                open,
+            }) => {
+                let width = columns.len();
+                let types = portal
+                    .types
+                    .clone()
+                    .unwrap_or_else(|| vec![oid::TEXT; width]);
+                let mut n = 0;
+                while n < limit {
+                    let Some(row) = rows.pop_front() else {
+                        break;
+                    };
+                    self.out.data_row(&row, &types, &portal.result_formats);
+                    n += 1;
+                }
+                *sent += n;
+                if rows.is_empty() && !*open {
+                    self.out.command_complete(&format!("SELECT {}", sent));
+                } else {
+                    self.out.message(b's', |_| {});
+                }
+            }
+        }
+    }
+}
+
+// 文の中の $n と ? の数
+// 列ごとに、最初の NULL でない値の型。すべて NULL なら text
+fn result_types(width: usize, rows: &[Vec<Value>]) -> Vec<i32> {
+    (0..width)
+        .map(|i| {
+            rows.iter()
+                .find_map(|row| row[i].data_type())
+                .map_or(oid::TEXT, |ty| type_oid(&ty))
+        })
+        .collect()
+}
+
+pub fn type_oid(ty: &DataType) -> i32 {
+    match ty {
+        DataType::Integer | DataType::BigInt => oid::INT8,
+        DataType::Real => oid::FLOAT8,
+        DataType::Decimal { .. } => oid::NUMERIC,
+        DataType::Text => oid::TEXT,
+        DataType::Boolean => oid::BOOL,
+        DataType::Blob => oid::BYTEA,
+        DataType::Date => oid::DATE,
+        DataType::Time => oid::TIME,
+        DataType::Timestamp => oid::TIMESTAMP,
+        DataType::Json => oid::JSON,
+        DataType::Uuid => oid::UUID,
+        DataType::Interval => oid::INTERVAL,
+        DataType::Point => oid::POINT,
+        DataType::Box => oid::BOX,
+        // 配列は {1,2} の形の、列挙型はラベルの text として返す
+        DataType::Array(_) | DataType::Enum(_) => oid::TEXT,
+    }
+}
+
+fn type_len(oid: i32) -> i16 {
+    match oid {
+        oid::BOOL => 1,
+        oid::DATE => 4,
+        oid::INT8 | oid::FLOAT8 | oid::TIME | oid::TIMESTAMP => 8,
+        oid::UUID | oid::INTERVAL | oid::POINT => 16,
+        oid::BOX => 32,
+        _ => -1,
+    }
+}
+
+// text 形式の値。真偽値は t と f
+fn text_value(value: &Value) -> String {
+    match value {
+        Value::Boolean(b) => if *b { "t" } else { "f" }.to_string(),
+        value => value.to_string(),
+    }
+}
+
+// binary 形式の値。伝えた型と値の型が合わなければ text と同じバイト列にする
+fn binary_value(value: &Value, ty: i32) -> Vec<u8> {
+    match (value, ty) {
+        (Value::Integer(n) | Value::BigInt(n), oid::INT8) => n.to_be_bytes().to_vec(),
+        (Value::Real(x), oid::FLOAT8) => x.to_be_bytes().to_vec(),
+        (Value::Boolean(b), oid::BOOL) => vec![*b as u8],
+        (Value::Blob(b), oid::BYTEA) => b.to_vec(),
+        (Value::Uuid(u), oid::UUID) => u.as_bytes().to_vec(),
+        (Value::Date(d), oid::DATE) => (d.days() - PG_EPOCH_DAYS).to_be_bytes().to_vec(),
+        (Value::Time(t), oid::TIME) => t.micros().to_be_bytes().to_vec(),
+        (Value::Timestamp(t), oid::TIMESTAMP) => {
+            (t.micros() - PG_EPOCH_MICROS).to_be_bytes().to_vec()
+        }
+        (Value::Interval(i), oid::INTERVAL) => {
+            let mut bytes = i.micros().to_be_bytes().to_vec();
+            bytes.extend_from_slice(&i.days().to_be_bytes());
+            bytes.extend_from_slice(&i.months().to_be_bytes());
+            bytes
+        }
+        (Value::Point(p), oid::POINT) => [p.x, p.y].map(f64::to_be_bytes).concat(),
+        // 右上の点を先に送る
+        (Value::Box(b), oid::BOX) => [b.high.x, b.high.y, b.low.x, b.low.y]
+            .map(f64::to_be_bytes)
+            .concat(),
+        (Value::Decimal(d), oid::NUMERIC) => encode_numeric(&d.to_string()),
+        (value, _) => text_value(value).into_bytes(),
+    }
+}
+
+// $n の値を読む。型のわからない値は文字列にする
+fn decode_param(bytes: &[u8], ty: i32, format: i16) -> Result<Value, String> {
+    let invalid = || {
+        format!(
+            "incorrect binary data format in bind parameter of type {}",
+            ty
+        )
+    };
+    if format == 0 {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| "invalid byte sequence for encoding \"UTF8\"")?;
+        // numeric は精度を決めずに読む
+        if ty == oid::NUMERIC {
+            return text
+                .trim()
+                .parse()
+                .map(Value::Decimal)
+                .map_err(|_| format!("invalid input syntax for type numeric: \"{}\"", text));
+        }
+        let Some(data_type) = param_type(ty) else {
+            return Ok(Value::Text(text.to_string()));
+        };
+        return data_type
+            .cast(Value::Text(text.to_string()))
+            .map_err(|_| format!("invalid input syntax for type {}: \"{}\"", data_type, text));
+    }
+    let int = |n: usize| -> Result<i64, String> {
+        match (n, bytes.len()) {
+            (2, 2) => Ok(i16::from_be_bytes([bytes[0], bytes[1]]) as i64),
+            (4, 4) => Ok(i32::from_be_bytes(bytes.try_into().unwrap()) as i64),
+            (8, 8) => Ok(i64::from_be_bytes(bytes.try_into().unwrap())),
+            _ => Err(invalid()),
+        }
+    };
+    Ok(match ty {
+        oid::INT2 => Value::Integer(int(2)?),
+        oid::INT4 => Value::Integer(int(4)?),
+        oid::INT8 => Value::BigInt(int(8)?),
+        oid::FLOAT4 => {
+            Value::Real(f32::from_be_bytes(bytes.try_into().map_err(|_| invalid())?) as f64)
+        }
+        oid::FLOAT8 => Value::Real(f64::from_be_bytes(bytes.try_into().map_err(|_| invalid())?)),
+        oid::BOOL => match bytes {
+            [b] => Value::Boolean(*b != 0),
+            _ => return Err(invalid()),
+        },
+        oid::BYTEA => Value::Blob(bytes.into()),
+        oid::UUID => Value::Uuid(Uuid::from_bytes(bytes.try_into().map_err(|_| invalid())?)),
+        oid::DATE => {
+            let days = int(4)? + PG_EPOCH_DAYS as i64;
+            Value::Date(Date::from_days(days).ok_or("date out of range")?)
+        }
+        oid::TIME => Value::Time(Time::from_micros(int(8)?).ok_or_else(invalid)?),
+        oid::TIMESTAMP | oid::TIMESTAMPTZ => {
+            let micros = int(8)?
+                .checked_add(PG_EPOCH_MICROS)
+                .ok_or("timestamp out of range")?;
+            Value::Timestamp(Timestamp::from_micros(micros).ok_or("timestamp out of range")?)
+        }
+        oid::INTERVAL => {
+            if bytes.len() != 16 {
+                return Err(invalid());
+            }
+            let micros = i64::from_be_bytes(bytes[..8].try_into().unwrap());
+            let days = i32::from_be_bytes(bytes[8..12].try_into().unwrap());
+            let months = i32::from_be_bytes(bytes[12..].try_into().unwrap());
+            Value::Interval(Interval::new(months, days, micros))
+        }
+        oid::POINT | oid::BOX => {
+            let n = if ty == oid::POINT { 2 } else { 4 };
+            if bytes.len() != n * 8 {
+                return Err(invalid());
+            }
+            let c: Vec<f64> = bytes
+                .chunks(8)
+                .map(|c| f64::from_be_bytes(c.try_into().unwrap()))
+                .collect();
+            let p = Point::new(c[0], c[1]);
+            if ty == oid::POINT {
+                Value::Point(p)
+            } else {
+                Value::Box(Box::new(Rect::new(p, Point::new(c[2], c[3]))))
+            }
+        }
+        oid::NUMERIC => {
+            let text = decode_numeric(bytes).ok_or_else(invalid)?;
+            match text.parse() {
+                Ok(d) => Value::Decimal(d),
+                Err(_) => return Err("numeric value out of range".to_string()),
+            }
+        }
+        // jsonb の binary 形式は先頭に版の 1 がつく
+        oid::JSONB => {
+            let text = bytes.strip_prefix(&[1]).ok_or_else(invalid)?;
+            decode_param(text, oid::JSON, 0)?
+        }
+        ty => decode_param(bytes, ty, 0)?,
+    })
+}
+
+// text 形式の引数を読むときの型。文字列の型と不明な型なら None
+fn param_type(ty: i32) -> Option<DataType> {
+    Some(match ty {
+        oid::INT2 | oid::INT4 => DataType::Integer,
+        oid::INT8 => DataType::BigInt,
+        oid::FLOAT4 | oid::FLOAT8 => DataType::Real,
+        oid::BOOL => DataType::Boolean,
+        oid::BYTEA => DataType::Blob,
+        oid::DATE => DataType::Date,
+        oid::TIME => DataType::Time,
+        oid::TIMESTAMP | oid::TIMESTAMPTZ => DataType::Timestamp,
+        oid::INTERVAL => DataType::Interval,
+        oid::JSON | oid::JSONB => DataType::Json,
+        oid::UUID => DataType::Uuid,
+        oid::POINT => DataType::Point,
+        oid::BOX => DataType::Box,
+        _ => return None,
+    })
+}
+
+// numeric の binary 形式。10000 進の桁を上から並べ、weight は先頭の桁の 10000 の指数
+fn encode_numeric(text: &str) -> Vec<u8> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (integer, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let integer = integer.trim_start_matches('0');
+    let mut digits_text = "0".repeat((4 - integer.len() % 4) % 4);
+    digits_text.push_str(integer);
+    let int_groups = digits_text.len() / 4;
+    digits_text.push_str(fraction);
+    digits_text.push_str(&"0".repeat((4 - fraction.len() % 4) % 4));
+    let mut digits: Vec<i16> = digits_text
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap().parse().unwrap())
+        .collect();
+    let mut weight = int_groups as i16 - 1;
+    while digits.first() == Some(&0) {
+        digits.remove(0);
+        weight -= 1;
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    if digits.is_empty() {
+        weight = 0;
+    }
+    let sign: u16 = if negative && !digits.is_empty() {
+        0x4000
+    } else {
+        0
+    };
+    let mut out = vec![];
+    out.extend_from_slice(&(digits.len() as i16).to_be_bytes());
+    out.extend_from_slice(&weight.to_be_bytes());
+    out.extend_from_slice(&sign.to_be_bytes());
+    out.extend_from_slice(&(fraction.len() as u16).to_be_bytes());
+    for digit in digits {
+        out.extend_from_slice(&digit.to_be_bytes());
+    }
+    out
+}
+
+// numeric の binary 形式を 10 進の文字列にする。NaN や無限大なら None
+fn decode_numeric(bytes: &[u8]) -> Option<String> {
+    let field = |i: usize| -> Option<i16> {
+        Some(i16::from_be_bytes(
+            bytes.get(i * 2..i * 2 + 2)?.try_into().ok()?,
+        ))
+    };
+    let ndigits = field(0)? as usize;
+    let weight = field(1)? as i64;
+    let sign = field(2)? as u16;
+    let dscale = field(3)? as usize;
+    if sign != 0 && sign != 0x4000 || bytes.len() != 8 + ndigits * 2 {
+        return None;
+    }
+    let digits: Vec<i16> = (0..ndigits).map(|i| field(4 + i)).collect::<Option<_>>()?;
+    // 10000 の指数が e の桁
+    let digit = |e: i64| -> i16 {
+        usize::try_from(weight - e)
+            .ok()
+            .and_then(|i| digits.get(i).copied())
+            .unwrap_or(0)
+    };
+    let mut text = String::new();
+    if sign == 0x4000 {
+        text.push('-');
+    }
+    if weight < 0 {
+        text.push('0');
+    } else {
+        text.push_str(&digit(weight).to_string());
+        for e in (0..weight).rev() {
+            text.push_str(&format!("{:04}", digit(e)));
+        }
+    }
+    if dscale > 0 {
+        let mut fraction = String::new();
+        let mut e = -1;
+        while fraction.len() < dscale {
+            fraction.push_str(&format!("{:04}", digit(e)));
+            e -= 1;
+        }
+        fraction.truncate(dscale);
+        text.push('.');
+        text.push_str(&fraction);
+    }
+    Some(text)
+}
+
+// 送るメッセージをためる
+#[derive(Default)]
+struct Messages {
+    buf: Vec<u8>,
+}
+
+// メッセージの本体を書く
+struct MessageWriter<'a>(&'a mut Vec<u8>);
+
+impl MessageWriter<'_> {
+    fn u8(&mut self, n: u8) {
+        self.0.push(n);
+    }
+
+    fn i16(&mut self, n: i16) {
+        self.0.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn i32(&mut self, n: i32) {
+        self.0.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn cstr(&mut self, s: &str) {
+        self.0.extend_from_slice(s.as_bytes());
+        self.0.push(0);
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        self.0.extend_from_slice(b);
+    }
+}
+
+impl Messages {
+    fn message(&mut self, tag: u8, body: impl FnOnce(&mut MessageWriter)) {
+        self.buf.push(tag);
+        let start = self.buf.len();
+        self.buf.extend_from_slice(&[0; 4]);
+        body(&mut MessageWriter(&mut self.buf));
+        let len = (self.buf.len() - start) as i32;
+        self.buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+
+    fn error(&mut self, severity: &str, code: &str, message: &str) {
+        self.message(b'E', |m| {
+            for (field, value) in [
+                (b'S', severity),
+                (b'V', severity),
+                (b'C', code),
+                (b'M', message),
+            ] {
+                m.u8(field);
+                m.cstr(value);
+            }
+            m.u8(0);
+        });
+    }
+
+    fn command_complete(&mut self, tag: &str) {
+        self.message(b'C', |m| m.cstr(tag));
+    }
+
+    fn row_description(&mut self, columns: &[String], types: &[i32], formats: &[i16]) {
+        self.message(b'T', |m| {
+            m.i16(columns.len() as i16);
+            for (i, (name, &ty)) in columns.iter().zip(types).enumerate() {
+                m.cstr(name);
+                m.i32(0);
+                m.i16(0);
+                m.i32(ty);
+                m.i16(type_len(ty));
+                m.i32(-1);
+                m.i16(column_format(formats, i));
+            }
+        });
+    }
+
+    fn data_row(&mut self, row: &[Value], types: &[i32], formats: &[i16]) {
+        self.message(b'D', |m| {
+            m.i16(row.len() as i16);
+            for (i, value) in row.iter().enumerate() {
+                if value.is_null() {
+                    m.i32(-1);
+                    continue;
+                }
+                let ty = types.get(i).copied().unwrap_or(oid::TEXT);
+                let bytes = match column_format(formats, i) {
+                    1 => binary_value(value, ty),
+                    _ => text_value(value).into_bytes(),
+                };
+                m.i32(bytes.len() as i32);
+                m.bytes(&bytes);
+            }
+        });
+    }
+}
+
+fn column_format(formats: &[i16], i: usize) -> i16 {
+    match formats {
+        [] => 0,
+        [format] => *format,
+        formats => formats.get(i).copied().unwrap_or(0),
+    }
+}
+
+// 受け取ったメッセージの本体を読む
+struct Body {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl Body {
+    fn new(bytes: Vec<u8>) -> Body {
+        Body { bytes, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| protocol_error("message is too short"))?;
+        let bytes = self.bytes[self.pos..end].to_vec();
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        Ok(i16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn cstr(&mut self) -> io::Result<String> {
+        let len = self.bytes[self.pos..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| protocol_error("unterminated string in message"))?;
+        let bytes = self.bytes(len)?;
+        self.pos += 1;
+        String::from_utf8(bytes).map_err(|_| protocol_error("invalid UTF-8 in message"))
+    }
+}
+
+fn read_i32(reader: &mut dyn Read) -> io::Result<i32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_be_bytes(buf))
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Database;
+
+    fn frontend(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = Messages::default();
+        out.message(tag, |m| m.bytes(body));
+        out.buf
+    }
+
+    fn cstr(s: &str) -> Vec<u8> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.push(0);
+        bytes
+    }
+
+    // 返ってきたメッセージを種類と本体に分ける
+    fn backend(mut bytes: &[u8]) -> Vec<(char, Vec<u8>)> {
+        let mut messages = vec![];
+        while !bytes.is_empty() {
+            let len = i32::from_be_bytes(bytes[1..5].try_into().unwrap()) as usize;
+            messages.push((bytes[0] as char, bytes[5..1 + len].to_vec()));
+            bytes = &bytes[1 + len..];
+        }
+        messages
+    }
+
+    #[test]
+    fn test_pgwire() {
+        // SSL を断ってから起動パケットを読む
+        let mut input = vec![];
+        input.extend_from_slice(&8i32.to_be_bytes());
+        input.extend_from_slice(&SSL_REQUEST.to_be_bytes());
+        let mut startup = PROTOCOL_V3.to_be_bytes().to_vec();
+        startup.extend(cstr("user"));
+        startup.extend(cstr("alice"));
+        startup.push(0);
+        input.extend_from_slice(&(startup.len() as i32 + 4).to_be_bytes());
+        input.extend(startup);
+        let mut output = vec![];
+        let request = read_startup(&mut &input[..], &mut output).unwrap();
+        assert_eq!(output, b"N");
+        let StartupRequest::Startup(startup) = request else {
+            panic!("{:?}", request);
+        };
+        assert_eq!(startup.get("user"), Some("alice"));
+
+        let db = Database::open_temporary().unwrap();
+        let mut input = vec![];
+        input.extend(frontend(
+            b'Q',
+            &cstr("CREATE TABLE t (a INTEGER, b TEXT); INSERT INTO t VALUES (1, 'x'), (2, NULL); SELECT a, b FROM t ORDER BY a"),
+        ));
+        input.extend(frontend(b'Q', &cstr("SELECT 1 / 0")));
+        input.extend(frontend(b'Q', &cstr("")));
+        // $1 を binary の int8 で渡し、結果も binary で受け取る
+        let mut parse = cstr("s");
+        parse.extend(cstr("SELECT a FROM t WHERE a >= $1 ORDER BY a"));
+        parse.extend(1i16.to_be_bytes());
+        parse.extend(oid::INT8.to_be_bytes());
+        input.extend(frontend(b'P', &parse));
+        let mut bind = cstr("p");
+        bind.extend(cstr("s"));
+        bind.extend([0, 1, 0, 1, 0, 1, 0, 0, 0, 8]);
+        bind.extend(1i64.to_be_bytes());
+        bind.extend([0, 1, 0, 1]);
+        input.extend(frontend(b'B', &bind));
+        input.extend(frontend(b'D', &[b"P".as_slice(), &cstr("p")].concat()));
+        input.extend(frontend(
+            b'E',
+            &[cstr("p"), 1i32.to_be_bytes().to_vec()].concat(),
+        ));
+        input.extend(frontend(
+            b'E',
+            &[cstr("p"), 0i32.to_be_bytes().to_vec()].concat(),
+        ));
+        input.extend(frontend(b'S', &[]));
+        // エラーのあとは Sync まで読み捨てる
+        let mut parse = cstr("");
+        parse.extend(cstr("SELECT nope()"));
+        parse.extend(0i16.to_be_bytes());
+        input.extend(frontend(b'P', &parse));
+        input.extend(frontend(b'B', &[cstr(""), cstr(""), vec![0; 6]].concat()));
+        input.extend(frontend(b'E', &[cstr(""), vec![0; 4]].concat()));
+        input.extend(frontend(b'E', &[cstr(""), vec![0; 4]].concat()));
+        input.extend(frontend(b'S', &[]));
+        input.extend(frontend(b'X', &[]));
+        let mut output = vec![];
+        serve(&mut &input[..], &mut output, db.connect(), 7, 42).unwrap();
+
+        let messages = backend(&output);
+        let tags: String = messages.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags, "RSSSSSSSKZCCTDDCZEZIZ12TDsDCZ12EZ");
+        assert_eq!(messages[8].1, [0, 0, 0, 7, 0, 0, 0, 42]);
+        assert_eq!(messages[10].1, cstr("CREATE TABLE"));
+        assert_eq!(messages[11].1, cstr("INSERT 0 2"));
+        // 列の型は返した値から決める
+        let desc = &messages[12].1;
+        assert_eq!(&desc[..4], [0, 2, b'a', 0]);
+        assert_eq!(&desc[10..14], oid::INT8.to_be_bytes());
+        assert_eq!(messages[13].1, [0, 2, 0, 0, 0, 1, b'1', 0, 0, 0, 1, b'x']);
+        assert_eq!(
+            messages[14].1,
+            [0, 2, 0, 0, 0, 1, b'2', 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(messages[15].1, cstr("SELECT 2"));
+        let error = String::from_utf8_lossy(&messages[17].1).to_string();
+        assert!(error.contains("C22012\0"), "{}", error);
+        assert!(error.contains("Mdivision by zero\0"), "{}", error);
+        assert_eq!(messages[18].1, b"I");
+        let row = [&[0, 1, 0, 0, 0, 8][..], &1i64.to_be_bytes()].concat();
+        assert_eq!(messages[24].1, row);
+        assert_eq!(messages[27].1, cstr("SELECT 2"));
+        let error = String::from_utf8_lossy(&messages[31].1).to_string();
+        assert!(error.contains("C42883\0"), "{}", error);
+    }
+
+    #[test]
+    fn test_password() {
+        let db = Database::open_temporary().unwrap();
+        let startup = Startup {
+            params: vec![("user".to_string(), "alice".to_string())],
+        };
+        // LOGIN のロールがなければ認証しない
+        let mut session = Session::login(db.connect(), &startup, 1, 2);
+        assert_eq!(backend(&session.take_output())[0], ('R', vec![0, 0, 0, 0]));
+        session
+            .handle(b'Q', cstr("CREATE USER alice PASSWORD 'secret'"))
+            .unwrap();
+
+        let mut session = Session::login(db.connect(), &startup, 1, 2);
+        assert_eq!(backend(&session.take_output()), [('R', vec![0, 0, 0, 3])]);
+        assert!(session.handle(b'p', cstr("wrong")).is_err());
+        let error = String::from_utf8_lossy(&backend(&session.take_output())[0].1).to_string();
+        assert!(error.contains("C28P01\0"), "{}", error);
+
+        let mut session = Session::login(db.connect(), &startup, 1, 2);
+        session.take_output();
+        session.handle(b'p', cstr("secret")).unwrap();
+        let messages = backend(&session.take_output());
+        assert_eq!(messages[0], ('R', vec![0, 0, 0, 0]));
+        assert_eq!(messages.last().unwrap(), &('Z', b"I".to_vec()));
+        // alice はスーパーユーザーではない
+        session
+            .handle(b'Q', cstr("CREATE TABLE t (a INTEGER)"))
+            .unwrap();
+        let error = String::from_utf8_lossy(&backend(&session.take_output())[0].1).to_string();
+        assert!(error.contains("C42501\0"), "{}", error);
+    }
+
+    #[test]
+    fn test_numeric() {
+        for text in ["0", "12345.678", "-0.5", "10000", "0.0001", "-99999999.99"] {
+            let bytes = encode_numeric(text);
+            assert_eq!(decode_numeric(&bytes).as_deref(), Some(text));
+        }
+        assert_eq!(
+            encode_numeric("12345.678"),
+            [0, 3, 0, 1, 0, 0, 0, 3, 0, 1, 0x09, 0x29, 0x1a, 0x7c]
+        );
+    }
+
+    #[test]
+    fn test_describe_statement() {
+        let db = Database::open_temporary().unwrap();
+        let mut session = Session::new(db.connect(), 1, 2);
+        session
+            .handle(
+                b'Q',
+                cstr("CREATE TABLE t (a INTEGER, b TEXT); INSERT INTO t VALUES (7, NULL)"),
+            )
+            .unwrap();
+        session.take_output();
+        // 型を伝えない $1 は比べる列の型になる
+        let mut parse = cstr("s");
+        parse.extend(cstr("SELECT b, a + 1 AS n FROM t WHERE a = $1"));
+        parse.extend(0i16.to_be_bytes());
+        session.handle(b'P', parse).unwrap();
+        session
+            .handle(b'D', [b"S".as_slice(), &cstr("s")].concat())
+            .unwrap();
+        let messages = backend(&session.take_output());
+        assert_eq!(
+            messages[1],
+            ('t', [&[0, 1][..], &oid::INT8.to_be_bytes()].concat())
+        );
+        // NULL しかない b も列の型で伝える
+        let desc = &messages[2].1;
+        assert_eq!(&desc[..4], [0, 2, b'b', 0]);
+        assert_eq!(&desc[10..14], oid::TEXT.to_be_bytes());
+        assert_eq!(&desc[22..24], [b'n', 0]);
+        assert_eq!(&desc[30..34], oid::INT8.to_be_bytes());
+        // 伝えた型で binary の引数を読み、結果も binary で送る
+        let mut bind = cstr("p");
+        bind.extend(cstr("s"));
+        bind.extend([0, 1, 0, 1, 0, 1, 0, 0, 0, 8]);
+        bind.extend(7i64.to_be_bytes());
+        bind.extend([0, 1, 0, 1]);
+        session.handle(b'B', bind).unwrap();
+        session
+            .handle(b'E', [cstr("p"), 0i32.to_be_bytes().to_vec()].concat())
+            .unwrap();
+        session.handle(b'S', vec![]).unwrap();
+        let messages = backend(&session.take_output());
+        let tags: String = messages.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags, "2DCZ");
+        let row = [
+            &[0, 2, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 8][..],
+            &8i64.to_be_bytes(),
+        ]
+        .concat();
+        assert_eq!(messages[1].1, row);
+    }
+
+    #[test]
+    fn test_portal_suspend() {
+        let db = Database::open_temporary().unwrap();
+        let mut session = Session::new(db.connect(), 1, 2);
+        let values: Vec<_> = (1..=250).map(|i| format!("({})", i)).collect();
+        let sql = format!(
+            "CREATE TABLE t (a INTEGER); INSERT INTO t VALUES {}",
+            values.join(", ")
+        );
+        session.handle(b'Q', cstr(&sql)).unwrap();
+        session.take_output();
+        let mut parse = cstr("s");
+        parse.extend(cstr("SELECT a FROM t ORDER BY a"));
+        parse.extend(0i16.to_be_bytes());
+        session.handle(b'P', parse).unwrap();
+        let bind = [cstr("p"), cstr("s"), vec![0; 6]].concat();
+        session.handle(b'B', bind.clone()).unwrap();
+        // 2 行だけ読んで止め、残りはカーソルから読む
+        session
+            .handle(b'E', [cstr("p"), 2i32.to_be_bytes().to_vec()].concat())
+            .unwrap();
+        let messages = backend(&session.take_output());
+        let tags: String = messages.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags, "12DDs");
+        assert!(session.conn.in_transaction());
+        session
+            .handle(b'E', [cstr("p"), 0i32.to_be_bytes().to_vec()].concat())
+            .unwrap();
+        session.handle(b'S', vec![]).unwrap();
+        let messages = backend(&session.take_output());
+        assert_eq!(messages.len(), 250);
+        assert_eq!(messages[0].1, [0, 1, 0, 0, 0, 1, b'3']);
+        assert_eq!(messages[248], ('C', cstr("SELECT 250")));
+        assert_eq!(messages[249], ('Z', b"I".to_vec()));
+
+        // Sync でトランザクションが終わると、読みかけのポータルはカーソルと一緒に閉じる
+        session.handle(b'B', bind).unwrap();
+        session
+            .handle(b'E', [cstr("p"), 1i32.to_be_bytes().to_vec()].concat())
+            .unwrap();
+        session.handle(b'S', vec![]).unwrap();
+        session
+            .handle(b'E', [cstr("p"), 0i32.to_be_bytes().to_vec()].concat())
+            .unwrap();
+        session.handle(b'S', vec![]).unwrap();
+        let messages = backend(&session.take_output());
+        let tags: String = messages.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags, "2DsZEZ");
+        let error = String::from_utf8_lossy(&messages[4].1).to_string();
+        assert!(error.contains("C34000\0"), "{}", error);
+        assert_eq!(messages[5].1, b"I");
+    }
+
+    #[test]
+    fn test_bind_untyped_params() {
+        let db = Database::open_temporary().unwrap();
+        let mut session = Session::new(db.connect(), 1, 2);
+        let sql = "CREATE TABLE t (a INTEGER, b REAL, c BOOLEAN, d TEXT)";
+        session.handle(b'Q', cstr(sql)).unwrap();
+        session.take_output();
+        // PQexecParams の順: Parse, Bind, Describe (ポータル), Execute, Sync
+        let mut parse = cstr("");
+        parse.extend(cstr("INSERT INTO t VALUES ($1, $2, $3, $4)"));
+        parse.extend(4i16.to_be_bytes());
+        parse.extend([0u8; 16]);
+        session.handle(b'P', parse).unwrap();
+        let mut bind = [cstr(""), cstr(""), 0i16.to_be_bytes().to_vec()].concat();
+        bind.extend(4i16.to_be_bytes());
+        for param in ["42", "1.5", "t", "x"] {
+            bind.extend((param.len() as i32).to_be_bytes());
+            bind.extend(param.as_bytes());
+        }
+        bind.extend(0i16.to_be_bytes());
+        session.handle(b'B', bind).unwrap();
+        session
+            .handle(b'D', [vec![b'P'], cstr("")].concat())
+            .unwrap();
+        session
+            .handle(b'E', [cstr(""), 0i32.to_be_bytes().to_vec()].concat())
+            .unwrap();
+        session.handle(b'S', vec![]).unwrap();
+        let messages = backend(&session.take_output());
+        let tags: String = messages.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags, "12nCZ", "{:?}", messages);
+        assert_eq!(messages[3].1, cstr("INSERT 0 1"));
+
+        let mut rows = session.conn.query("SELECT * FROM t", &[]).unwrap();
+        assert_eq!(
+            rows.next().unwrap().into_values(),
+            vec![
+                Value::Integer(42),
+                Value::Real(1.5),
+                Value::Boolean(true),
+                Value::Text("x".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lock_wait() {
+        let db = Database::open_temporary().unwrap();
+        let open = || {
+            let mut conn = db.connect();
+            conn.set_park_lock_waits(true);
+            let mut session = Session::new(conn, 1, 1);
+            session.take_output();
+            session
+        };
+        let query = |session: &mut Session, sql: &str| session.handle(b'Q', cstr(sql)).unwrap();
+        let tags = |session: &mut Session| -> String {
+            let output = session.take_output();
+            backend(&output).iter().map(|(tag, _)| *tag).collect()
+        };
+        let (mut a, mut b) = (open(), open());
+        query(
+            &mut a,
+            "CREATE TABLE t (a INTEGER, b INTEGER); INSERT INTO t VALUES (1, 0), (2, 0);
+             BEGIN; UPDATE t SET b = 1 WHERE a = 1",
+        );
+        assert_eq!(tags(&mut a), "CCCCZ");
+
+        // ロックを待つ間は答えず、待ち終えてから前の文の結果といっしょに答える
+        query(
+            &mut b,
+            "BEGIN; SET TRANSACTION ISOLATION LEVEL READ COMMITTED;
+             UPDATE t SET b = b + 10; SELECT b FROM t ORDER BY a",
+        );
+        assert!(b.waiting_for_lock());
+        b.retry();
+        assert!(b.waiting_for_lock());
+        query(&mut a, "COMMIT");
+        assert_eq!(tags(&mut a), "CZ");
+        b.retry();
+        assert!(!b.waiting_for_lock());
+        let output = b.take_output();
+        let messages = backend(&output);
+        let tags_b: String = messages.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags_b, "CCCTDDCZ");
+        assert_eq!(messages[2].1, cstr("UPDATE 2"));
+        assert_eq!(messages[4].1[6..], *b"11");
+
+        // 拡張問い合わせの Execute も待ち、止められたら待つのをやめて失敗する
+        query(&mut b, "COMMIT; BEGIN");
+        query(&mut a, "BEGIN; SELECT a FROM t WHERE a = 2 FOR UPDATE");
+        assert_eq!((tags(&mut a), tags(&mut b)), ("CTDCZ".into(), "CCZ".into()));
+        let mut parse = cstr("");
+        parse.extend(cstr("DELETE FROM t WHERE a = 2"));
+        parse.extend(0i16.to_be_bytes());
+        b.handle(b'P', parse).unwrap();
+        b.handle(b'B', [cstr(""), cstr(""), vec![0; 6]].concat())
+            .unwrap();
+        b.handle(b'E', [cstr(""), vec![0; 4]].concat()).unwrap();
+        assert!(b.waiting_for_lock());
+        b.conn.cancel_token().cancel();
+        b.retry();
+        assert!(!b.waiting_for_lock());
+        b.handle(b'S', vec![]).unwrap();
+        let output = b.take_output();
+        let messages = backend(&output);
+        assert_eq!(messages[2].0, 'E');
+        assert!(String::from_utf8_lossy(&messages[2].1).contains("57014"));
+        query(&mut a, "COMMIT");
+        query(&mut b, "DELETE FROM t WHERE a = 2; COMMIT");
+        assert!(!b.waiting_for_lock());
+        assert_eq!(tags(&mut b), "CCZ");
+    }
+}
@@ -0,0 +1,8216 @@
+mod cache;
+mod cost;
+mod explain;
+mod hint;
+mod simplify;
+
+use std::cell::{Cell, RefCell};
+
+use crate::catalog::{Catalog, Column, Index, Partition, PartitionBound, Table, Ttl};
+use crate::collation::Collation;
+use crate::datetime::Timestamp;
+use crate::executor::expr::{like_escape, parse_like, shape, Expr, Function, LikeToken};
+use crate::executor::{
+    self, format_plan, AggregateCall, AggregateFunction, ConflictAction, ExplainNode, IndexRange,
+    JoinType, OnConflict, PlanNode, ScanBound, SortKey, WindowCall, WindowFunction,
+};
+use crate::fulltext;
+use crate::lock::LockMode;
+use crate::rtree::Search;
+use crate::sql::ast::{
+    self, BinaryOp, ExplainFormat, FrameBound, FrameUnits, Hint, InsertSource, JoinKind, Literal,
+    Privilege, SelectItem, SetExpr, Statement, TableRef, UnaryOp, WindowFrame,
+};
+use crate::sql::ParseError;
+use crate::stats::StatsView;
+use crate::types::{DataType, Value};
+
+pub use cache::PlanCache;
+pub use cost::CostSettings;
+use cost::{distinct, selectivity, CostModel};
+use explain::explain;
+pub use explain::{generated_expr, index_columns};
+use simplify::{is_contradiction, simplify, simplify_conjuncts};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Executor(#[from] executor::Error),
+    #[error("table \"{0}\" does not exist")]
+    TableNotFound(String),
+    #[error("column \"{0}\" does not exist")]
+    ColumnNotFound(String),
+    #[error("column reference \"{0}\" is ambiguous")]
+    AmbiguousColumn(String),
+    #[error("missing FROM-clause entry for table \"{0}\"")]
+    MissingFromEntry(String),
+    #[error("table name \"{0}\" specified more than once")]
+    DuplicateTable(String),
+    #[error("function {0} does not exist")]
+    FunctionNotFound(String),
+    #[error("SELECT * with no tables specified is not valid")]
+    WildcardWithoutFrom,
+    #[error("aggregate functions are not allowed in {0}")]
+    AggregateNotAllowed(&'static str),
+    #[error("aggregate function calls cannot be nested")]
+    NestedAggregate,
+    #[error("window functions are not allowed in {0}")]
+    WindowNotAllowed(&'static str),
+    #[error("window function calls cannot be nested")]
+    NestedWindow,
+    #[error("set-returning functions are not allowed in {0}")]
+    SetReturningNotAllowed(&'static str),
+    #[error("set-returning function calls cannot be nested")]
+    NestedSetReturning,
+    #[error(
+        "column \"{0}\" must appear in the GROUP BY clause or be used in an aggregate function"
+    )]
+    NotGrouped(String),
+    #[error("arguments to GROUPING must be grouping expressions of the associated query level")]
+    GroupingArgument,
+    #[error("for SELECT DISTINCT, ORDER BY expressions must appear in select list")]
+    DistinctOrderBy,
+    #[error("{0} must not be negative")]
+    NegativeCount(&'static str),
+    #[error("argument of {0} must be type integer, not type {1}")]
+    NotInteger(&'static str, String),
+    #[error("column \"{0}\" specified more than once")]
+    DuplicateColumn(String),
+    #[error("multiple assignments to same column \"{0}\"")]
+    DuplicateAssignment(String),
+    #[error("INSERT has more expressions than target columns")]
+    TooManyExpressions,
+    #[error("INSERT has more target columns than expressions")]
+    TooManyTargetColumns,
+    #[error("VALUES lists must all be the same length")]
+    ValuesLength,
+    #[error("ORDER BY position {0} is not in select list")]
+    InvalidPosition(i64),
+    #[error("there is no parameter ${0}")]
+    ParameterNotFound(usize),
+    #[error("subquery has too many columns")]
+    SubqueryColumns,
+    #[error("each {0} query must have the same number of columns")]
+    SetOperationColumns(ast::SetOperator),
+    #[error("there is no unique or exclusion constraint matching the ON CONFLICT specification")]
+    NoConflictIndex,
+    #[error("ON CONFLICT DO UPDATE requires inference specification or constraint name")]
+    ConflictTargetRequired,
+    #[error("WITH query name \"{0}\" specified more than once")]
+    DuplicateCte(String),
+    #[error(
+        "WITH query \"{name}\" has {available} columns available but {specified} columns specified"
+    )]
+    CteColumns {
+        name: String,
+        available: usize,
+        specified: usize,
+    },
+    #[error("{0} is not allowed with {1}")]
+    LockingNotAllowed(ast::LockStrength, &'static str),
+    #[error("{0} is not supported")]
+    Unsupported(&'static str),
+    #[error("TABLESAMPLE clause can only be applied to tables")]
+    SampleNotTable,
+    #[error("{0} parameter cannot be null")]
+    SampleNull(&'static str),
+    #[error("argument of {0} must be type double precision, not type {1}")]
+    SampleNotNumber(&'static str, String),
+    #[error("sample percentage must be between 0 and 100")]
+    SamplePercent,
+    #[error("AS OF clause can only be applied to tables")]
+    AsOfNotTable,
+    #[error("percentile value {0} is not between 0 and 1")]
+    Percentile(String),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("cannot insert multiple commands into a prepared statement")]
+    MultipleStatements,
+    #[error("only queries and INSERT, UPDATE and DELETE statements can be planned")]
+    NotPlannable,
+    #[error("functions in index expression must not be volatile")]
+    VolatileIndexExpression,
+    #[error("cannot assign to generated column \"{0}\"")]
+    GeneratedColumn(String),
+    #[error("generation expression is not immutable")]
+    VolatileGenerationExpression,
+    #[error("cannot change materialized view \"{0}\"")]
+    MaterializedView(String),
+    #[error("permission denied for table {0}")]
+    PermissionDenied(String),
+    // ATTACH でつないだデータベースのテーブルを読めなかった
+    #[error("{message}")]
+    Attached {
+        message: String,
+        sqlstate: &'static str,
+    },
+}
+
+// 名前解決に使う、入力行の各列の出どころ
+//
+// 相関副問い合わせでは外側の列のあとに内側の列を並べ、名前はまず内側から探す。
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    columns: Vec<ScopeColumn>,
+    // 先頭の外側の問い合わせの列の数
+    outer_width: usize,
+    // 結合にしたスカラー副問い合わせ
+    subqueries: Vec<ScalarSubquery>,
+}
+
+// 結合にしたスカラー副問い合わせと、その値が入る列
+#[derive(Debug, Clone)]
+struct ScalarSubquery {
+    query: *const ast::Query,
+    column: usize,
+    // COUNT なら、相手のない行の NULL を 0 にする
+    count: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ScopeColumn {
+    // テーブル名か別名
+    table: String,
+    name: String,
+    // テーブルの列をそのまま参照するならその列の照合順
+    collation: Collation,
+    // わからなければ None
+    ty: Option<DataType>,
+}
+
+impl Scope {
+    fn has_table(&self, table: &str) -> bool {
+        self.columns.iter().any(|c| c.table == table)
+    }
+
+    fn resolve(&self, table: Option<&str>, name: &str) -> Result<usize, Error> {
+        if let Some(table) = table {
+            if !self.has_table(table) {
+                return Err(Error::MissingFromEntry(table.to_string()));
+            }
+        }
+        let matches = |c: &ScopeColumn| c.name == name && table.is_none_or(|t| c.table == t);
+        let start = if self.columns[self.outer_width..].iter().any(matches) {
+            self.outer_width
+        } else {
+            0
+        };
+        let mut found = self
+            .columns
+            .iter()
+            .enumerate()
+            .skip(start)
+            .filter(|(_, c)| matches(c))
+            .map(|(i, _)| i);
+        let display = || match table {
+            Some(table) => format!("{}.{}", table, name),
+            None => name.to_string(),
+        };
+        let index = found
+            .next()
+            .ok_or_else(|| Error::ColumnNotFound(display()))?;
+        if found.next().is_some() {
+            return Err(Error::AmbiguousColumn(display()));
+        }
+        Ok(index)
+    }
+}
+
+// alias.table の名前が ATTACH でつないだデータベースのテーブルなら、その列の名前と、いまコミットしている行を返す
+pub type AttachedTables<'a> =
+    dyn Fn(&str) -> Option<Result<(Vec<String>, Vec<Vec<Value>>), Error>> + 'a;
+
+// main.table は自分のデータベースのテーブル
+fn main_table(name: &str) -> &str {
+    name.strip_prefix("main.").unwrap_or(name)
+}
+
+// FROM のテーブルの (別名, テーブルの名前)。副問い合わせの中は見ない
+fn table_aliases<'a>(from: &'a TableRef, aliases: &mut Vec<(&'a str, &'a str)>) {
+    match from {
+        TableRef::Table {
+            name,
+            alias: Some(alias),
+            ..
+        } => aliases.push((alias, main_table(name))),
+        TableRef::Join { left, right, .. } => {
+            table_aliases(left, aliases);
+            table_aliases(right, aliases);
+        }
+        _ => {}
+    }
+}
+
+pub struct Planner<'a> {
+    catalog: &'a Catalog,
+    // 2 以上なら、1 つのテーブルだけを読む問い合わせで、行の復元と絞り込みをこの数のスレッドで並列に評価する
+    pub parallel_workers: usize,
+    // 計画を比べるときの費用の定数
+    pub costs: CostSettings,
+    // TTL の期限が切れてまだ取り除いていない行を読まない
+    pub ttl_filter: bool,
+    // 権限を確かめるロール。None なら埋め込みで使う持ち主として、どのテーブルも読み書きできる
+    pub user: Option<String>,
+    // $1 や ? に入れる値
+    params: &'a [Value],
+    // トリガーの本体を計画するときに NEW や OLD で参照できる行と、そのテーブル
+    trigger_rows: Vec<(&'static str, &'a Table, &'a [Value])>,
+    attached: Option<&'a AttachedTables<'a>>,
+    // 参照できる WITH の問い合わせ。内側の WITH のものほど後ろにある
+    ctes: RefCell<Vec<CteDef>>,
+    next_cte: Cell<usize>,
+    // LATERAL の副問い合わせを計画している間は、外側の行を読む CteScan の id と外側の列
+    lateral: RefCell<Option<(usize, Scope)>>,
+    // now() や random() のように呼ぶたびに値の変わる関数を計画したか
+    volatile: Cell<bool>,
+    // これまでに計画した SELECT のヒント。文のどこに書いたものも文全体に効く
+    hints: RefCell<Vec<Hint>>,
+    // 計画した式の文脈から決めた $n の型。n - 1 番目が $n のもの
+    param_types: RefCell<Vec<Option<DataType>>>,
+    // 真なら $n を値に置き換えず、Parameter のまま計画する
+    generic: Cell<bool>,
+    // generic で計画している間に $n の値を計画に使ったか
+    peeked: Cell<bool>,
+}
+
+struct CteDef {
+    name: String,
+    id: usize,
+    columns: Vec<String>,
+    // FROM で参照された回数
+    refs: usize,
+}
+
+impl<'a> Planner<'a> {
+    pub fn new(catalog: &'a Catalog) -> Self {
+        Self {
+            catalog,
+            parallel_workers: 0,
+            costs: CostSettings::default(),
+            ttl_filter: true,
+            user: None,
+            params: &[],
+            trigger_rows: vec![],
+            attached: None,
+            ctes: RefCell::new(vec![]),
+            next_cte: Cell::new(0),
+            lateral: RefCell::new(None),
+            volatile: Cell::new(false),
+            hints: RefCell::new(vec![]),
+            param_types: RefCell::new(vec![]),
+            generic: Cell::new(false),
+            peeked: Cell::new(false),
+        }
+    }
+
+    // これまでに計画した式が volatile な関数を呼ぶか。呼べば同じ文でも結果が変わりうる
+    pub fn uses_volatile(&self) -> bool {
+        self.volatile.get()
+    }
+
+    // $n に params[n - 1] を入れて計画する
+    pub fn with_params(mut self, params: &'a [Value]) -> Self {
+        self.params = params;
+        self
+    }
+
+    // $n を実行するときに値で置き換える、どの値にも使える計画。
+    // 費用は params の値で見積もり、LIMIT のように計画に $n の値が要る文なら None
+    pub fn plan_generic(&self, statement: &Statement) -> Option<PlanNode> {
+        self.generic.set(true);
+        self.peeked.set(false);
+        let plan = self.plan_statement(statement);
+        self.generic.set(false);
+        plan.ok().filter(|_| !self.peeked.get())
+    }
+
+    // これまでに計画した文の $n の型。params の数だけ返し、文脈から決まらないものは None
+    pub fn param_types(&self) -> Vec<Option<DataType>> {
+        let mut types = self.param_types.borrow().clone();
+        types.resize(self.params.len(), None);
+        types
+    }
+
+    // $n と比べたり $n を代入したりする相手の型が ty なら、$n もその型とする。先に決まった型を優先する
+    fn infer_param(&self, n: usize, ty: Option<DataType>) {
+        let mut types = self.param_types.borrow_mut();
+        if types.len() < n {
+            types.resize(n, None);
+        }
+        if types[n - 1].is_none() {
+            types[n - 1] = ty;
+        }
+    }
+
+    // ATTACH でつないだデータベースのテーブルを attached で読む
+    pub fn with_attached(mut self, attached: &'a AttachedTables<'a>) -> Self {
+        self.attached = Some(attached);
+        self
+    }
+
+    // FROM にない name.column を、rows のうち name のものの値として計画する
+    pub fn with_trigger_rows(mut self, rows: Vec<(&'static str, &'a Table, &'a [Value])>) -> Self {
+        self.trigger_rows = rows;
+        self
+    }
+
+    fn trigger_value(&self, table: &str, name: &str) -> Option<Result<Value, Error>> {
+        let (_, t, row) = self.trigger_rows.iter().find(|(n, ..)| *n == table)?;
+        Some(match t.column_index(name) {
+            Some(i) => Ok(row[i].clone()),
+            None => Err(Error::ColumnNotFound(format!("{}.{}", table, name))),
+        })
+    }
+
+    fn model(&self) -> CostModel<'_> {
+        CostModel {
+            catalog: self.catalog,
+            settings: &self.costs,
+            hints: self.hints.borrow().clone(),
+        }
+    }
+
+    pub fn plan_statement(&self, statement: &Statement) -> Result<PlanNode, Error> {
+        // 前の文のヒントは効かせない
+        self.hints.borrow_mut().clear();
+        match statement {
+            Statement::Query(query) => self.plan_query(query),
+            Statement::Insert(insert) => self.plan_insert(insert),
+            Statement::Update(update) => self.plan_update(update),
+            Statement::Delete(delete) => self.plan_delete(delete),
+            Statement::Explain {
+                analyze,
+                format,
+                statement,
+            } => self.plan_explain(statement, *analyze, *format),
+            _ => Err(Error::NotPlannable),
+        }
+    }
+
+    // plan の EXPLAIN の演算子の一覧
+    pub fn explain(&self, plan: &PlanNode) -> Result<Vec<ExplainNode>, Error> {
+        explain(&self.model(), plan)
+    }
+
+    // 選んだ計画を字下げした木として書き、1 行ずつを "QUERY PLAN" 列の行として返す計画にする。
+    // analyze なら実行してから、演算子ごとに測った値を見積もりと並べて書く
+    fn plan_explain(
+        &self,
+        statement: &Statement,
+        analyze: bool,
+        format: ExplainFormat,
+    ) -> Result<PlanNode, Error> {
+        let plan = self.plan_statement(statement)?;
+        let nodes = explain(&self.model(), &plan)?;
+        if analyze {
+            return Ok(PlanNode::ExplainAnalyze {
+                input: Box::new(plan),
+                nodes,
+                format,
+            });
+        }
+        let rows = format_plan(format, &nodes, None)
+            .into_iter()
+            .map(|line| vec![Value::Text(line)])
+            .collect();
+        Ok(PlanNode::Projection {
+            input: Box::new(PlanNode::Values { rows }),
+            exprs: vec![Expr::Column(0)],
+            columns: vec!["QUERY PLAN".into()],
+        })
+    }
+
+    pub fn plan_query(&self, query: &ast::Query) -> Result<PlanNode, Error> {
+        if query.with.is_empty() {
+            return self.plan_query_body(query);
+        }
+        let depth = self.ctes.borrow().len();
+        let plan = self.plan_with(query, depth);
+        self.ctes.borrow_mut().truncate(depth);
+        plan
+    }
+
+    // WITH の問い合わせは 1 回だけ参照されていれば参照の位置に埋め込み、
+    // 2 回以上なら With で一度だけ実行して結果を共有する。参照されなければ実行しない
+    fn plan_with(&self, query: &ast::Query, depth: usize) -> Result<PlanNode, Error> {
+        let mut plans = vec![];
+        for cte in &query.with {
+            if self.ctes.borrow()[depth..]
+                .iter()
+                .any(|c| c.name == cte.name)
+            {
+                return Err(Error::DuplicateCte(cte.name.clone()));
+            }
+            let plan = match recursive_terms(cte) {
+                Some((all, anchor, recursive)) => {
+                    self.plan_recursive_cte(cte, all, anchor, recursive)?
+                }
+                None => self.plan_query(&cte.query)?,
+            };
+            let columns = cte_columns(cte, plan.columns(self.catalog)?)?;
+            let id = self.next_cte.get();
+            self.next_cte.set(id + 1);
+            self.ctes.borrow_mut().push(CteDef {
+                name: cte.name.clone(),
+                id,
+                columns,
+                refs: 0,
+            });
+            plans.push((id, plan));
+        }
+        let mut plan = self.plan_query_body(query)?;
+        let refs = self.ctes.borrow()[depth..]
+            .iter()
+            .map(|c| c.refs)
+            .collect::<Vec<_>>();
+        // 後ろの問い合わせから埋め込めば、埋め込み先は本体か共有するものに限られる
+        let mut shared = vec![];
+        for ((id, cte), refs) in plans.into_iter().zip(refs).rev() {
+            match refs {
+                0 => {}
+                1 => {
+                    let mut cte = Some(cte);
+                    inline_cte(&mut plan, id, &mut cte);
+                    for (_, other) in &mut shared {
+                        inline_cte(other, id, &mut cte);
+                    }
+                }
+                _ => shared.push((id, cte)),
+            }
+        }
+        // 埋め込んだ問い合わせの中へも条件を押し下げる
+        let plan = push_down(&self.model(), plan, vec![])?;
+        if shared.is_empty() {
+            return Ok(plan);
+        }
+        shared.reverse();
+        Ok(PlanNode::With {
+            ctes: shared,
+            input: Box::new(plan),
+        })
+    }
+
+    // WITH RECURSIVE の問い合わせ。UNION の左を先に計画して列を決め、右では自分の名前で前の段の行を読む。
+    // 右が自分を参照しなければふつうの UNION にする
+    fn plan_recursive_cte(
+        &self,
+        cte: &ast::Cte,
+        all: bool,
+        anchor: &SetExpr,
+        recursive: &SetExpr,
+    ) -> Result<PlanNode, Error> {
+        let anchor = self.plan_set_expr(anchor)?;
+        let columns = cte_columns(cte, anchor.columns(self.catalog)?)?;
+        let id = self.next_cte.get();
+        self.next_cte.set(id + 1);
+        let depth = self.ctes.borrow().len();
+        self.ctes.borrow_mut().push(CteDef {
+            name: cte.name.clone(),
+            id,
+            columns: columns.clone(),
+            refs: 0,
+        });
+        let plan = self.plan_set_expr(recursive);
+        let refs = self.ctes.borrow()[depth].refs;
+        self.ctes.borrow_mut().truncate(depth);
+        let recursive = plan?;
+        if refs == 0 {
+            return self.plan_query(&cte.query);
+        }
+        if recursive.columns(self.catalog)?.len() != columns.len() {
+            return Err(Error::SetOperationColumns(ast::SetOperator::Union));
+        }
+        Ok(PlanNode::RecursiveUnion {
+            id,
+            anchor: Box::new(anchor),
+            recursive: Box::new(recursive),
+            all,
+        })
+    }
+
+    fn plan_query_body(&self, query: &ast::Query) -> Result<PlanNode, Error> {
+        let locking = query.locking.as_ref();
+        if let (Some(locking), SetExpr::SetOperation { .. }) = (locking, &query.body) {
+            return Err(Error::LockingNotAllowed(
+                locking.strength,
+                "UNION/INTERSECT/EXCEPT",
+            ));
+        }
+        let mut plan = match &query.body {
+            SetExpr::Select(select) => self.plan_select(select, &query.order_by, locking)?,
+            SetExpr::Query(_) if locking.is_some() => {
+                return Err(Error::Unsupported("FOR UPDATE on a parenthesized query"))
+            }
+            SetExpr::Query(inner) => {
+                let plan = self.plan_query(inner)?;
+                self.plan_order_by(plan, &query.order_by)?
+            }
+            SetExpr::SetOperation { .. } => {
+                let plan = self.plan_set_expr(&query.body)?;
+                self.plan_order_by(plan, &query.order_by)?
+            }
+        };
+        let limit = query
+            .limit
+            .as_ref()
+            .map(|limit| eval_count(self, limit, "LIMIT"))
+            .transpose()?
+            .flatten();
+        let offset = query
+            .offset
+            .as_ref()
+            .map(|offset| eval_count(self, offset, "OFFSET"))
+            .transpose()?
+            .flatten()
+            .unwrap_or(0);
+        // 並べ替えの直後の LIMIT なら、並べ替えでは先頭の行だけを残せばよい。射影は行の数を変えない
+        let sort = match &mut plan {
+            PlanNode::Projection { input, .. } => &mut **input,
+            plan => plan,
+        };
+        if let (PlanNode::Sort { top_n, .. }, Some(limit)) = (sort, limit) {
+            *top_n = Some(limit.saturating_add(offset));
+        }
+        if limit.is_some() || offset > 0 {
+            plan = PlanNode::Limit {
+                input: Box::new(plan),
+                limit,
+                offset,
+            };
+        }
+        Ok(plan)
+    }
+
+    // 集合演算の各項。ORDER BY と LIMIT は全体にかかるので、ここでは付けない
+    fn plan_set_expr(&self, body: &SetExpr) -> Result<PlanNode, Error> {
+        let (op, all, left, right) = match body {
+            SetExpr::Select(select) => return self.plan_select(select, &[], None),
+            SetExpr::Query(query) => return self.plan_query(query),
+            SetExpr::SetOperation {
+                op,
+                all,
+                left,
+                right,
+            } => (*op, *all, left, right),
+        };
+        let left = Box::new(self.plan_set_expr(left)?);
+        let right = Box::new(self.plan_set_expr(right)?);
+        let width = left.columns(self.catalog)?.len();
+        if right.columns(self.catalog)?.len() != width {
+            return Err(Error::SetOperationColumns(op));
+        }
+        if op != ast::SetOperator::Union {
+            return Ok(PlanNode::HashSetOp {
+                op,
+                all,
+                left,
+                right,
+            });
+        }
+        let plan = PlanNode::Append { left, right };
+        if all {
+            return Ok(plan);
+        }
+        Ok(PlanNode::HashAggregate {
+            input: Box::new(plan),
+            group_by: (0..width).map(Expr::column).collect(),
+            aggregates: vec![],
+        })
+    }
+
+    pub fn plan_insert(&self, insert: &ast::Insert) -> Result<PlanNode, Error> {
+        let table = self
+            .catalog
+            .table(main_table(&insert.table))
+            .ok_or_else(|| Error::TableNotFound(insert.table.clone()))?;
+        if table.view.is_some() {
+            return Err(Error::MaterializedView(insert.table.clone()));
+        }
+        self.check_privilege(&table.name, Privilege::Insert)?;
+        // 値を入れる列の位置
+        let mut targets = vec![];
+        for name in &insert.columns {
+            let i = table
+                .column_index(name)
+                .ok_or_else(|| Error::ColumnNotFound(name.clone()))?;
+            if targets.contains(&i) {
+                return Err(Error::DuplicateColumn(name.clone()));
+            }
+            if table.generated_column(i).is_some() {
+                return Err(Error::GeneratedColumn(name.clone()));
+            }
+            targets.push(i);
+        }
+        // 列を省けば、生成列のほかの列に順に入れる
+        if insert.columns.is_empty() {
+            targets = (0..table.columns.len())
+                .filter(|&i| table.generated_column(i).is_none())
+                .collect();
+        }
+        let (source, width) = match &insert.source {
+            InsertSource::Values(rows) => {
+                for row in rows {
+                    for (expr, &target) in row.iter().zip(&targets) {
+                        if let ast::Expr::Parameter(n) = expr {
+                            self.infer_param(*n, Some(table.columns[target].data_type.clone()));
+                        }
+                    }
+                }
+                let width = rows.first().map_or(0, |row| row.len());
+                (self.plan_insert_values(rows)?, width)
+            }
+            InsertSource::Query(query) => {
+                let plan = self.plan_query(query)?;
+                let width = plan.columns(self.catalog)?.len();
+                (plan, width)
+            }
+        };
+        if width > targets.len() {
+            return Err(Error::TooManyExpressions);
+        }
+        if width < targets.len() && !insert.columns.is_empty() {
+            return Err(Error::TooManyTargetColumns);
+        }
+        // テーブルの列の順に並べ替える。値のない列は NULL にする
+        let in_order =
+            width == table.columns.len() && targets.iter().enumerate().all(|(i, &t)| i == t);
+        let input = if in_order {
+            source
+        } else {
+            let exprs = (0..table.columns.len())
+                .map(|c| match targets[..width].iter().position(|&t| t == c) {
+                    Some(i) => Expr::column(i),
+                    None => Expr::Literal(Value::Null),
+                })
+                .collect();
+            PlanNode::Projection {
+                input: Box::new(source),
+                exprs,
+                columns: table.columns.iter().map(|c| c.name.clone()).collect(),
+            }
+        };
+        let on_conflict = insert
+            .on_conflict
+            .as_ref()
+            .map(|on_conflict| self.plan_on_conflict(table, on_conflict))
+            .transpose()?;
+        let plan = PlanNode::Insert {
+            table: table.name.clone(),
+            input: Box::new(input),
+            on_conflict,
+            returning: !insert.returning.is_empty(),
+        };
+        self.plan_returning(plan, &table.name, &insert.returning)
+    }
+
+    // INSERT の VALUES の行をパラメータの値で計算する。行の長さはそろっていなければならない
+    pub fn plan_values(&self, rows: &[Vec<ast::Expr>]) -> Result<Vec<Vec<Value>>, Error> {
+        let rows = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|expr| eval_constant(self, expr, "VALUES"))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            return Err(Error::ValuesLength);
+        }
+        Ok(rows)
+    }
+
+    // INSERT の VALUES。generic で $n を含むなら、1 行ごとに式を実行するときに評価する
+    fn plan_insert_values(&self, rows: &[Vec<ast::Expr>]) -> Result<PlanNode, Error> {
+        if !self.generic.get() {
+            return Ok(PlanNode::Values {
+                rows: self.plan_values(rows)?,
+            });
+        }
+        let rows = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|expr| bind_expr(self, expr, &Scope::default(), "VALUES"))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            return Err(Error::ValuesLength);
+        }
+        if !rows.iter().flatten().any(Expr::has_params) {
+            let rows = rows
+                .iter()
+                .map(|row| row.iter().map(|expr| expr.eval(&vec![])).collect())
+                .collect::<Result<_, _>>()?;
+            return Ok(PlanNode::Values { rows });
+        }
+        let columns: Vec<_> = (1..=width).map(|i| format!("column{}", i)).collect();
+        let mut plans: Vec<_> = rows
+            .into_iter()
+            .map(|exprs| PlanNode::Projection {
+                input: Box::new(PlanNode::Values { rows: vec![vec![]] }),
+                exprs,
+                columns: columns.clone(),
+            })
+            .collect();
+        // 行が多くても深くならないように、隣どうしを順に Append でつなぐ
+        while plans.len() > 1 {
+            let mut rest = plans.into_iter();
+            plans = vec![];
+            while let Some(left) = rest.next() {
+                plans.push(match rest.next() {
+                    Some(right) => PlanNode::Append {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    },
+                    None => left,
+                });
+            }
+        }
+        Ok(plans.pop().unwrap())
+    }
+
+    // ON CONFLICT の列は、列の組がちょうど一致する一意インデックスを指す
+    fn plan_on_conflict(
+        &self,
+        table: &Table,
+        on_conflict: &ast::OnConflict,
+    ) -> Result<OnConflict, Error> {
+        let index = if on_conflict.columns.is_empty() {
+            if matches!(on_conflict.action, ast::ConflictAction::Update { .. }) {
+                return Err(Error::ConflictTargetRequired);
+            }
+            None
+        } else {
+            let mut columns = on_conflict
+                .columns
+                .iter()
+                .map(|name| {
+                    table
+                        .column_index(name)
+                        .ok_or_else(|| Error::ColumnNotFound(name.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            columns.sort_unstable();
+            columns.dedup();
+            let index = table
+                .indexes
+                .iter()
+                .find(|index| {
+                    let mut indexed = index.columns.clone();
+                    indexed.sort_unstable();
+                    index.unique && indexed == columns
+                })
+                .ok_or(Error::NoConflictIndex)?;
+            Some(index.name.clone())
+        };
+        let action = match &on_conflict.action {
+            ast::ConflictAction::Nothing => ConflictAction::Nothing,
+            ast::ConflictAction::Update {
+                assignments,
+                selection,
+            } => {
+                self.check_privilege(&table.name, Privilege::Update)?;
+                // すでにある行の列のあとに、入れようとした行の列を excluded として並べる
+                let (_, mut scope) = self.table_scope(&table.name, &table.name)?;
+                let excluded = scope
+                    .columns
+                    .iter()
+                    .map(|c| ScopeColumn {
+                        table: "excluded".to_string(),
+                        name: c.name.clone(),
+                        collation: c.collation,
+                        ty: c.ty.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                scope.columns.extend(excluded);
+                ConflictAction::Update {
+                    assignments: bind_assignments(
+                        self,
+                        assignments,
+                        &scope,
+                        table,
+                        "ON CONFLICT DO UPDATE",
+                    )?,
+                    predicate: selection
+                        .as_ref()
+                        .map(|selection| bind_expr(self, selection, &scope, "WHERE"))
+                        .transpose()?,
+                }
+            }
+        };
+        Ok(OnConflict { index, action })
+    }
+
+    // 式のインデックスの式を、テーブルの行に対する式にする。問い合わせの項と同じ形になるように簡単にしておく
+    pub fn plan_index_expression(&self, table: &str, expr: &ast::Expr) -> Result<Expr, Error> {
+        let (_, scope) = self.table_scope(table, table)?;
+        let expr = simplify(bind_expr(self, expr, &scope, "index expressions")?);
+        if is_volatile(&expr) {
+            return Err(Error::VolatileIndexExpression);
+        }
+        Ok(expr)
+    }
+
+    // 生成列の式を、テーブルの行に対する式にする
+    pub fn plan_generated_expression(&self, table: &str, expr: &ast::Expr) -> Result<Expr, Error> {
+        let (_, scope) = self.table_scope(table, table)?;
+        let expr = simplify(bind_expr(self, expr, &scope, "generation expressions")?);
+        if is_volatile(&expr) {
+            return Err(Error::VolatileGenerationExpression);
+        }
+        Ok(expr)
+    }
+
+    pub fn plan_update(&self, update: &ast::Update) -> Result<PlanNode, Error> {
+        let table = main_table(&update.table);
+        let (input, scope) =
+            self.plan_target(table, Privilege::Update, update.selection.as_ref())?;
+        let assignments = bind_assignments(
+            self,
+            &update.assignments,
+            &scope,
+            self.catalog.table(table).unwrap(),
+            "UPDATE",
+        )?;
+        let plan = PlanNode::Update {
+            table: table.to_string(),
+            input: Box::new(input),
+            assignments,
+            returning: !update.returning.is_empty(),
+        };
+        self.plan_returning(plan, table, &update.returning)
+    }
+
+    pub fn plan_delete(&self, delete: &ast::Delete) -> Result<PlanNode, Error> {
+        let table = main_table(&delete.table);
+        let (input, _) = self.plan_target(table, Privilege::Delete, delete.selection.as_ref())?;
+        let plan = PlanNode::Delete {
+            table: table.to_string(),
+            input: Box::new(input),
+            returning: !delete.returning.is_empty(),
+        };
+        self.plan_returning(plan, table, &delete.returning)
+    }
+
+    // RETURNING の項目を、DML の演算子が返すテーブルの行に対して計算する
+    fn plan_returning(
+        &self,
+        plan: PlanNode,
+        table: &str,
+        items: &[SelectItem],
+    ) -> Result<PlanNode, Error> {
+        if items.is_empty() {
+            return Ok(plan);
+        }
+        // 返した行は読めてしまうので、SELECT の権限も要る
+        self.check_privilege(table, Privilege::Select)?;
+        let (_, scope) = self.table_scope(table, table)?;
+        let mut exprs = vec![];
+        let mut columns = vec![];
+        for item in items {
+            match item {
+                SelectItem::QualifiedWildcard(name) if name != table => {
+                    return Err(Error::MissingFromEntry(name.clone()));
+                }
+                SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => {
+                    exprs.extend((0..scope.columns.len()).map(Expr::column));
+                    columns.extend(scope.columns.iter().map(|c| c.name.clone()));
+                }
+                SelectItem::Expr { expr, alias } => {
+                    exprs.push(bind_expr(self, expr, &scope, "RETURNING")?);
+                    columns.push(alias.clone().unwrap_or_else(|| column_name(expr)));
+                }
+            }
+        }
+        Ok(PlanNode::Projection {
+            input: Box::new(plan),
+            exprs,
+            columns,
+        })
+    }
+
+    // UPDATE と DELETE の対象の行を返す計画。WHERE で行の値を見るなら SELECT の権限も要る
+    fn plan_target(
+        &self,
+        table: &str,
+        privilege: Privilege,
+        selection: Option<&ast::Expr>,
+    ) -> Result<(PlanNode, Scope), Error> {
+        // 実体化したビューの行は REFRESH だけが書き換える
+        if self.catalog.table(table).is_some_and(|t| t.view.is_some()) {
+            return Err(Error::MaterializedView(table.to_string()));
+        }
+        let (mut plan, scope) = self.table_scope(table, table)?;
+        self.check_privilege(table, privilege)?;
+        if let Some(selection) = selection {
+            self.check_privilege(table, Privilege::Select)?;
+            let predicate = bind_expr(self, selection, &scope, "WHERE")?;
+            plan = push_down(&self.model(), plan, predicate.split_conjunction())?;
+        }
+        Ok((plan, scope))
+    }
+
+    // WHERE の AND でつながった項のうち、EXISTS と IN (副問い合わせ) は準結合か反結合にする
+    fn plan_where(
+        &self,
+        mut plan: PlanNode,
+        scope: &Scope,
+        selection: &ast::Expr,
+    ) -> Result<PlanNode, Error> {
+        let mut conjuncts = vec![];
+        let mut selection = vec![selection];
+        let mut subqueries = vec![];
+        while let Some(expr) = selection.pop() {
+            match expr {
+                ast::Expr::Binary {
+                    op: BinaryOp::And,
+                    left,
+                    right,
+                } => selection.extend([&**right, &**left]),
+                ast::Expr::Exists { query, negated } => subqueries.push((None, query, *negated)),
+                ast::Expr::Unary {
+                    op: UnaryOp::Not,
+                    expr,
+                } if matches!(**expr, ast::Expr::Exists { .. }) => {
+                    let ast::Expr::Exists { query, negated } = &**expr else {
+                        unreachable!()
+                    };
+                    subqueries.push((None, query, !negated));
+                }
+                ast::Expr::InSubquery {
+                    expr,
+                    query,
+                    negated,
+                } => subqueries.push((Some(&**expr), query, *negated)),
+                expr => conjuncts.push(bind_expr(self, expr, scope, "WHERE")?),
+            }
+        }
+        if let Some(predicate) = Expr::conjunction(conjuncts) {
+            plan = PlanNode::Filter {
+                input: Box::new(plan),
+                predicate,
+            };
+        }
+        for (expr, query, negated) in subqueries {
+            plan = self.plan_subquery(plan, scope, expr, query, negated)?;
+        }
+        Ok(plan)
+    }
+
+    // [NOT] EXISTS (query) か expr [NOT] IN (query) を外側の計画との準結合か反結合にする
+    fn plan_subquery(
+        &self,
+        outer: PlanNode,
+        outer_scope: &Scope,
+        expr: Option<&ast::Expr>,
+        query: &ast::Query,
+        negated: bool,
+    ) -> Result<PlanNode, Error> {
+        let SetExpr::Select(select) = &query.body else {
+            return self.plan_uncorrelated_subquery(outer, outer_scope, expr, query, negated);
+        };
+        if !query.with.is_empty()
+            || query.limit.is_some()
+            || query.offset.is_some()
+            || !select.group_by.is_empty()
+            || select.grouping_sets.is_some()
+            || select.having.is_some()
+            || select.projection.iter().any(
+                |item| matches!(item, SelectItem::Expr { expr, .. } if contains_aggregate(expr)),
+            )
+        {
+            return self.plan_uncorrelated_subquery(outer, outer_scope, expr, query, negated);
+        }
+        let (mut inner, inner_scope) = match &select.from {
+            None => (PlanNode::Values { rows: vec![vec![]] }, Scope::default()),
+            Some(from) => self.plan_from(from)?,
+        };
+        let outer_width = outer_scope.columns.len();
+        let mut scope = outer_scope.clone();
+        scope.columns.extend(inner_scope.columns);
+        scope.outer_width = outer_width;
+        // 内側の列だけを参照する項は結合の前に絞り込む
+        let mut filter = vec![];
+        let mut predicate = vec![];
+        if let Some(selection) = &select.selection {
+            for conjunct in bind_expr(self, selection, &scope, "WHERE")?.split_conjunction()
+            {
+                let mut columns = vec![];
+                conjunct.collect_columns(&mut columns);
+                if columns.iter().all(|&i| i >= outer_width) {
+                    filter.push(conjunct.map_columns(&|i| i - outer_width));
+                } else {
+                    predicate.push(conjunct);
+                }
+            }
+        }
+        if let Some(predicate) = Expr::conjunction(filter) {
+            inner = PlanNode::Filter {
+                input: Box::new(inner),
+                predicate,
+            };
+        }
+        if let Some(expr) = expr {
+            let mut items = vec![];
+            for item in &select.projection {
+                match item {
+                    SelectItem::Expr { expr, .. } => {
+                        items.push(bind_expr(self, expr, &scope, "SELECT")?)
+                    }
+                    SelectItem::Wildcard => {
+                        items.extend((outer_width..scope.columns.len()).map(Expr::column))
+                    }
+                    SelectItem::QualifiedWildcard(_) => {
+                        return Err(Error::Unsupported("qualified wildcard in subquery"))
+                    }
+                }
+            }
+            let [item] = <[Expr; 1]>::try_from(items).map_err(|_| Error::SubqueryColumns)?;
+            predicate.push(in_predicate(
+                bind_expr(self, expr, outer_scope, "WHERE")?,
+                item,
+                negated,
+            ));
+        }
+        Ok(plan_join(
+            &self.model(),
+            outer,
+            inner,
+            outer_width,
+            Expr::conjunction(predicate),
+            semi_join(negated),
+        ))
+    }
+
+    // 集約や LIMIT のある副問い合わせは、外側と関係なく計画してから準結合か反結合にする。
+    // 外側の列を参照していれば、外側の行ごとに実行し直す
+    fn plan_uncorrelated_subquery(
+        &self,
+        outer: PlanNode,
+        outer_scope: &Scope,
+        expr: Option<&ast::Expr>,
+        query: &ast::Query,
+        negated: bool,
+    ) -> Result<PlanNode, Error> {
+        let outer_width = outer_scope.columns.len();
+        // 外側の列は見えないので、参照していれば列が見つからない
+        let (inner, lateral) = match self.plan_query(query) {
+            Err(Error::ColumnNotFound(_) | Error::MissingFromEntry(_)) => {
+                let (id, inner) = self.plan_lateral(outer_scope, query)?;
+                (inner, Some(id))
+            }
+            inner => (inner?, None),
+        };
+        let predicate = match expr {
+            None => None,
+            Some(expr) => {
+                if inner.columns(self.catalog)?.len() != 1 {
+                    return Err(Error::SubqueryColumns);
+                }
+                let expr = bind_expr(self, expr, outer_scope, "WHERE")?;
+                Some(in_predicate(expr, Expr::column(outer_width), negated))
+            }
+        };
+        if let Some(id) = lateral {
+            return Ok(PlanNode::LateralJoin {
+                left: Box::new(outer),
+                right: Box::new(inner),
+                id,
+                predicate,
+                join_type: semi_join(negated),
+                single: false,
+            });
+        }
+        Ok(plan_join(
+            &self.model(),
+            outer,
+            inner,
+            outer_width,
+            predicate,
+            semi_join(negated),
+        ))
+    }
+
+    // 外側の列を参照できるようにして query を計画する。実行するときは外側の行ごとに、
+    // その行を返す id の CteScan を先頭に結合して計画を始め直す
+    fn plan_lateral(
+        &self,
+        outer_scope: &Scope,
+        query: &ast::Query,
+    ) -> Result<(usize, PlanNode), Error> {
+        let id = self.next_cte.get();
+        self.next_cte.set(id + 1);
+        let outer = Scope {
+            columns: outer_scope.columns.clone(),
+            ..Scope::default()
+        };
+        let previous = self.lateral.replace(Some((id, outer)));
+        let plan = self.plan_query(query);
+        self.lateral.replace(previous);
+        Ok((id, plan?))
+    }
+
+    // LATERAL の副問い合わせを計画しているなら、FROM の前に外側の行を結合する
+    fn lateral_from(&self, plan: PlanNode, scope: Scope) -> (PlanNode, Scope) {
+        let Some((id, outer)) = self.lateral.borrow().clone() else {
+            return (plan, scope);
+        };
+        let outer_width = outer.columns.len();
+        let row = PlanNode::CteScan {
+            id,
+            columns: outer.columns.iter().map(|c| c.name.clone()).collect(),
+        };
+        let plan = plan_join(&self.model(), row, plan, outer_width, None, JoinType::Inner);
+        let mut columns = outer.columns;
+        columns.extend(scope.columns);
+        let scope = Scope {
+            columns,
+            outer_width,
+            ..Scope::default()
+        };
+        (plan, scope)
+    }
+
+    // 相関を結合に直せないスカラー副問い合わせは、外側の行ごとに実行し直して左外部結合する
+    fn plan_lateral_scalar(
+        &self,
+        outer: PlanNode,
+        outer_scope: &Scope,
+        query: &ast::Query,
+    ) -> Result<PlanNode, Error> {
+        let (id, inner) = self.plan_lateral(outer_scope, query)?;
+        if inner.columns(self.catalog)?.len() != 1 {
+            return Err(Error::SubqueryColumns);
+        }
+        Ok(PlanNode::LateralJoin {
+            left: Box::new(outer),
+            right: Box::new(inner),
+            id,
+            predicate: None,
+            join_type: JoinType::Left,
+            single: true,
+        })
+    }
+
+    // 選択項目と WHERE のスカラー副問い合わせを、外側の行ごとに実行しないように左外部結合にする。
+    // 返す scope は結合した列も含み、副問い合わせをその列に束縛する
+    fn plan_scalar_subqueries(
+        &self,
+        mut plan: PlanNode,
+        scope: &Scope,
+        select: &ast::Select,
+        grouped: bool,
+    ) -> Result<(PlanNode, Scope), Error> {
+        let mut queries = vec![];
+        if let Some(selection) = &select.selection {
+            scalar_subqueries(selection, &mut queries);
+        }
+        let where_count = queries.len();
+        for item in &select.projection {
+            if let SelectItem::Expr { expr, .. } = item {
+                scalar_subqueries(expr, &mut queries);
+            }
+        }
+        if grouped && queries.len() > where_count {
+            return Err(Error::Unsupported(
+                "scalar subquery in an aggregated select list",
+            ));
+        }
+        let mut scope = scope.clone();
+        for query in queries {
+            let joined = self.plan_scalar_subquery(plan.clone(), &scope, query);
+            let (joined, keys, count) = match joined {
+                Err(Error::Unsupported(_)) => {
+                    (self.plan_lateral_scalar(plan, &scope, query)?, 0, false)
+                }
+                joined => joined?,
+            };
+            plan = joined;
+            scope.subqueries.push(ScalarSubquery {
+                query,
+                column: scope.columns.len() + keys,
+                count,
+            });
+            // 結合のキーと値の列は名前で参照できない
+            scope.columns.extend((0..=keys).map(|_| ScopeColumn {
+                table: String::new(),
+                name: "?column?".to_string(),
+                collation: Collation::Binary,
+                ty: None,
+            }));
+        }
+        Ok((plan, scope))
+    }
+
+    // 集約関数 1 つだけを返すスカラー副問い合わせを、相関の列でグループ分けして集約し、
+    // 外側と左外部結合する。相関の条件は外側の式と内側の式の等号だけにできる。
+    // 結合の出力は外側の列、内側のキーの列、値の列の順で、キーの数と値が COUNT かを返す
+    fn plan_scalar_subquery(
+        &self,
+        outer: PlanNode,
+        outer_scope: &Scope,
+        query: &ast::Query,
+    ) -> Result<(PlanNode, usize, bool), Error> {
+        let SetExpr::Select(select) = &query.body else {
+            return Err(Error::Unsupported("set operation in subquery"));
+        };
+        let [SelectItem::Expr { expr: item, .. }] = select.projection.as_slice() else {
+            return Err(Error::SubqueryColumns);
+        };
+        if !query.with.is_empty()
+            || query.limit.is_some()
+            || query.offset.is_some()
+            || select.distinct
+            || !select.group_by.is_empty()
+            || select.grouping_sets.is_some()
+            || select.having.is_some()
+            || !contains_aggregate(item)
+        {
+            return Err(Error::Unsupported(
+                "scalar subquery other than a single aggregate",
+            ));
+        }
+        let inner_scope = match &select.from {
+            None => Scope::default(),
+            Some(from) => self.plan_from(from)?.1,
+        };
+        let outer_width = outer_scope.columns.len();
+        let mut scope = outer_scope.clone();
+        scope.columns.extend(inner_scope.columns);
+        scope.outer_width = outer_width;
+        let is_outer = |e: &Expr| {
+            let mut columns = vec![];
+            e.collect_columns(&mut columns);
+            columns.iter().all(|&i| i < outer_width)
+        };
+        let is_inner = |e: &Expr| {
+            let mut columns = vec![];
+            e.collect_columns(&mut columns);
+            columns.iter().all(|&i| i >= outer_width)
+        };
+        let mut local = vec![];
+        let mut outer_keys = vec![];
+        let mut inner_keys = vec![];
+        let mut selection = select.selection.iter().collect::<Vec<_>>();
+        while let Some(expr) = selection.pop() {
+            if let ast::Expr::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } = expr
+            {
+                selection.extend([&**right, &**left]);
+                continue;
+            }
+            let bound = bind_expr(self, expr, &scope, "WHERE")?;
+            if is_inner(&bound) {
+                local.push(expr.clone());
+                continue;
+            }
+            let (
+                ast::Expr::Binary {
+                    op: BinaryOp::Eq,
+                    left,
+                    right,
+                },
+                Expr::Binary {
+                    left: l, right: r, ..
+                },
+            ) = (expr, &bound)
+            else {
+                return Err(Error::Unsupported(
+                    "correlated subquery condition other than equality",
+                ));
+            };
+            if is_outer(l) && is_inner(r) {
+                outer_keys.push((**l).clone());
+                inner_keys.push((**right).clone());
+            } else if is_inner(l) && is_outer(r) {
+                outer_keys.push((**r).clone());
+                inner_keys.push((**left).clone());
+            } else {
+                return Err(Error::Unsupported(
+                    "correlated subquery condition other than equality",
+                ));
+            }
+        }
+        // 相関がなければ集約は必ず 1 行を返すので、値をそのまま使える
+        let count = match item {
+            _ if inner_keys.is_empty() => false,
+            ast::Expr::CountStar => true,
+            ast::Expr::Function { name, args, .. }
+                if AggregateFunction::lookup(name).is_some()
+                    && !args.iter().any(contains_aggregate) =>
+            {
+                matches!(
+                    AggregateFunction::lookup(name),
+                    Some(AggregateFunction::Count | AggregateFunction::ApproxCountDistinct)
+                )
+            }
+            // 相手のない行では集約の値を NULL として式を計算し直す必要がある
+            _ => {
+                return Err(Error::Unsupported(
+                    "correlated scalar subquery with an expression over aggregates",
+                ))
+            }
+        };
+        let mut projection = inner_keys
+            .iter()
+            .map(|key| SelectItem::Expr {
+                expr: key.clone(),
+                alias: None,
+            })
+            .collect::<Vec<_>>();
+        projection.push(SelectItem::Expr {
+            expr: item.clone(),
+            alias: None,
+        });
+        let grouped = ast::Select {
+            distinct: false,
+            projection,
+            from: select.from.clone(),
+            selection: local.into_iter().reduce(|left, right| ast::Expr::Binary {
+                op: BinaryOp::And,
+                left: Box::new(left),
+                right: Box::new(right),
+            }),
+            group_by: inner_keys,
+            grouping_sets: None,
+            having: None,
+            hints: vec![],
+        };
+        let inner = self.plan_select(&grouped, &[], None)?;
+        let keys = outer_keys.len();
+        let predicate = Expr::conjunction(
+            outer_keys
+                .into_iter()
+                .enumerate()
+                .map(|(i, key)| Expr::binary(BinaryOp::Eq, key, Expr::column(outer_width + i)))
+                .collect(),
+        );
+        Ok((
+            plan_join(
+                &self.model(),
+                outer,
+                inner,
+                outer_width,
+                predicate,
+                JoinType::Left,
+            ),
+            keys,
+            count,
+        ))
+    }
+
+    // 出力の列名か位置でしか並べ替えられない
+    fn plan_order_by(
+        &self,
+        plan: PlanNode,
+        order_by: &[ast::OrderByExpr],
+    ) -> Result<PlanNode, Error> {
+        if order_by.is_empty() {
+            return Ok(plan);
+        }
+        let columns = plan.columns(self.catalog)?;
+        let keys = order_by
+            .iter()
+            .map(|item| {
+                let i = output_column(&item.expr, &columns)?
+                    .ok_or(Error::Unsupported("ORDER BY expression on a subquery"))?;
+                Ok(SortKey {
+                    expr: Expr::column(i),
+                    asc: item.asc,
+                    nulls_first: item.nulls_first.unwrap_or(!item.asc),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(PlanNode::Sort {
+            input: Box::new(plan),
+            keys,
+            top_n: None,
+        })
+    }
+
+    fn plan_select(
+        &self,
+        select: &ast::Select,
+        order_by: &[ast::OrderByExpr],
+        locking: Option<&ast::LockingClause>,
+    ) -> Result<PlanNode, Error> {
+        if !select.hints.is_empty() {
+            let mut aliases = vec![];
+            if let Some(from) = &select.from {
+                table_aliases(from, &mut aliases);
+            }
+            let resolve = |name: &String| match aliases.iter().find(|(alias, _)| alias == name) {
+                Some((_, table)) => table.to_string(),
+                None => main_table(name).to_string(),
+            };
+            let mut hints = self.hints.borrow_mut();
+            for hint in &select.hints {
+                hints.push(Hint {
+                    relations: hint.relations.iter().map(resolve).collect(),
+                    ..hint.clone()
+                });
+            }
+        }
+        let (plan, scope) = match &select.from {
+            // FROM がなければ列のない行を 1 つだけ返す
+            None => (PlanNode::Values { rows: vec![vec![]] }, Scope::default()),
+            Some(from) => self.plan_from(from)?,
+        };
+        let (mut plan, scope) = self.lateral_from(plan, scope);
+        let grouped = !select.group_by.is_empty()
+            || select.grouping_sets.is_some()
+            || select.having.is_some()
+            || select.projection.iter().any(
+                |item| matches!(item, SelectItem::Expr { expr, .. } if contains_aggregate(expr)),
+            )
+            || order_by.iter().any(|item| contains_aggregate(&item.expr));
+        // * はスカラー副問い合わせの列を含まない scope で展開する
+        let (joined, bound) = self.plan_scalar_subqueries(plan, &scope, select, grouped)?;
+        plan = joined;
+        if let Some(selection) = &select.selection {
+            plan = self.plan_where(plan, &bound, selection)?;
+        }
+        plan = push_down(&self.model(), plan, vec![])?;
+        if let Some(locking) = locking {
+            plan = lock_rows(plan, select, grouped, locking)?;
+        } else if self.parallel_workers > 1 && !cfg!(target_arch = "wasm32") {
+            // wasm32 ではスレッドを作れない
+            plan = parallelize(plan, self.parallel_workers);
+        }
+        let mut binder = Binder::new(self, &bound, "SELECT");
+        if grouped {
+            // 大文字と小文字を区別しない列は、照合順のキーでまとめる。
+            // GROUPING SETS では集合にない列を NULL にするので、元の値をグループの最小値で返せず、そのままの値でまとめる
+            let keys = select
+                .group_by
+                .iter()
+                .map(|expr| {
+                    let key = bind_expr(self, expr, &bound, "GROUP BY")?;
+                    let collation = collation_of(expr, &bound);
+                    Ok(
+                        if collation.is_deterministic() || select.grouping_sets.is_some() {
+                            key
+                        } else {
+                            collate(key, collation)
+                        },
+                    )
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            if select.grouping_sets.is_some() && keys.len() > MAX_GROUPING_KEYS {
+                return Err(Error::Unsupported("too many grouping expressions"));
+            }
+            let sets = select.grouping_sets.as_ref().map(|sets| {
+                sets.iter()
+                    .map(|set| {
+                        set.iter()
+                            .filter_map(|expr| select.group_by.iter().position(|e| e == expr))
+                            .collect()
+                    })
+                    .collect()
+            });
+            binder.grouping = Some(Grouping {
+                keys,
+                sets,
+                aggregates: vec![],
+            });
+        }
+        let having = select
+            .having
+            .as_ref()
+            .map(|having| binder.bind(having))
+            .transpose()?;
+        binder.windows = Some(vec![]);
+        binder.sets = Some(vec![]);
+        let mut exprs = vec![];
+        let mut columns = vec![];
+        // 出力の各列の照合順。ORDER BY に使う
+        let mut collations = vec![];
+        for item in &select.projection {
+            match item {
+                SelectItem::Wildcard => {
+                    if select.from.is_none() {
+                        return Err(Error::WildcardWithoutFrom);
+                    }
+                    // LATERAL の外側の列は含めない
+                    for (i, column) in scope.columns.iter().enumerate().skip(scope.outer_width) {
+                        exprs.push(binder.bind_column(i)?);
+                        columns.push(column.name.clone());
+                        collations.push(column.collation);
+                    }
+                }
+                SelectItem::QualifiedWildcard(table) => {
+                    if !scope.has_table(table) {
+                        return Err(Error::MissingFromEntry(table.clone()));
+                    }
+                    for (i, column) in scope.columns.iter().enumerate() {
+                        if &column.table == table {
+                            exprs.push(binder.bind_column(i)?);
+                            columns.push(column.name.clone());
+                            collations.push(column.collation);
+                        }
+                    }
+                }
+                SelectItem::Expr { expr, alias } => {
+                    exprs.push(binder.bind(expr)?);
+                    columns.push(alias.clone().unwrap_or_else(|| column_name(expr)));
+                    collations.push(collation_of(expr, &bound));
+                }
+            }
+        }
+        // 選択項目にない式で並べ替えるときは、その式を隠れた列として足しておく
+        let width = exprs.len();
+        let mut keys = vec![];
+        for item in order_by {
+            let i = match output_column(&item.expr, &columns)? {
+                Some(i) => i,
+                None => {
+                    let expr = binder.bind(&item.expr)?;
+                    match exprs.iter().position(|e| *e == expr) {
+                        Some(i) => i,
+                        None => {
+                            exprs.push(expr);
+                            columns.push("?column?".to_string());
+                            collations.push(collation_of(&item.expr, &bound));
+                            exprs.len() - 1
+                        }
+                    }
+                }
+            };
+            keys.push(SortKey {
+                expr: collate(Expr::column(i), collations[i]),
+                asc: item.asc,
+                nulls_first: item.nulls_first.unwrap_or(!item.asc),
+            });
+        }
+        let windows = binder.windows.take().unwrap_or_default();
+        let mut sets = binder.sets.take().unwrap_or_default();
+        if let (Some(locking), false) = (locking, windows.is_empty()) {
+            return Err(Error::LockingNotAllowed(
+                locking.strength,
+                "window functions",
+            ));
+        }
+        if let Some(Grouping {
+            keys,
+            sets: Some(sets),
+            aggregates,
+        }) = binder.grouping
+        {
+            plan = PlanNode::GroupingSets {
+                input: Box::new(plan),
+                group_by: keys,
+                sets,
+                aggregates,
+            };
+        } else if let Some(grouping) = binder.grouping {
+            // キーの順に並んでいれば、グループを覚えずに順に集約する
+            let ordered =
+                !grouping.keys.is_empty() && is_sorted(self.catalog, &plan, &grouping.keys);
+            let input = Box::new(plan);
+            plan = if ordered {
+                PlanNode::GroupAggregate {
+                    input,
+                    group_by: grouping.keys,
+                    aggregates: grouping.aggregates,
+                }
+            } else {
+                PlanNode::HashAggregate {
+                    input,
+                    group_by: grouping.keys,
+                    aggregates: grouping.aggregates,
+                }
+            };
+        }
+        if let Some(having) = having {
+            plan = PlanNode::Filter {
+                input: Box::new(plan),
+                predicate: simplify(having),
+            };
+        }
+        if !windows.is_empty() {
+            let (windowed, positions) = self.plan_windows(plan, windows)?;
+            plan = windowed;
+            let position = |i| {
+                if i >= WINDOW_COLUMN {
+                    positions[i - WINDOW_COLUMN]
+                } else {
+                    i
+                }
+            };
+            exprs = exprs.iter().map(|expr| expr.map_columns(&position)).collect();
+            sets = sets.iter().map(|expr| expr.map_columns(&position)).collect();
+        }
+        // unnest の要素は入力の列のあとに並べる
+        if !sets.is_empty() {
+            let width = plan.columns(self.catalog)?.len();
+            let position = |i| {
+                if (SET_COLUMN..WINDOW_COLUMN).contains(&i) {
+                    width + i - SET_COLUMN
+                } else {
+                    i
+                }
+            };
+            exprs = exprs.iter().map(|expr| expr.map_columns(&position)).collect();
+            plan = PlanNode::ProjectSet {
+                input: Box::new(plan),
+                exprs: sets.into_iter().map(simplify).collect(),
+            };
+        }
+        let visible = (exprs.len() > width).then(|| columns[..width].to_vec());
+        plan = PlanNode::Projection {
+            input: Box::new(plan),
+            exprs: exprs.into_iter().map(simplify).collect(),
+            columns,
+        };
+        self.runtime_filters(&mut plan);
+        prune_columns(self.catalog, &mut plan, None);
+        parallel_aggregates(&self.model(), &mut plan);
+        if select.distinct {
+            if visible.is_some() {
+                return Err(Error::DistinctOrderBy);
+            }
+            if keys.is_empty() {
+                // 並べる必要がなければハッシュで重複を除く
+                return Ok(PlanNode::HashAggregate {
+                    input: Box::new(plan),
+                    group_by: (0..width).map(Expr::column).collect(),
+                    aggregates: vec![],
+                });
+            }
+            // ORDER BY のあとに残りの列でも並べて、同じ行を隣り合わせる
+            for i in 0..width {
+                if !keys.iter().any(|key| key.expr == Expr::column(i)) {
+                    keys.push(SortKey {
+                        expr: Expr::column(i),
+                        asc: true,
+                        nulls_first: false,
+                    });
+                }
+            }
+            return Ok(PlanNode::Unique {
+                input: Box::new(PlanNode::Sort {
+                    input: Box::new(plan),
+                    keys,
+                    top_n: None,
+                }),
+            });
+        }
+        if !keys.is_empty()
+            && !nearest_scan(self.catalog, &mut plan, &keys)
+            && !projected_sorted(self.catalog, &plan, &keys)
+        {
+            plan = PlanNode::Sort {
+                input: Box::new(plan),
+                keys,
+                top_n: None,
+            };
+        }
+        // 隠れた列を落とす
+        if let Some(columns) = visible {
+            plan = PlanNode::Projection {
+                input: Box::new(plan),
+                exprs: (0..width).map(Expr::column).collect(),
+                columns,
+            };
+        }
+        Ok(plan)
+    }
+
+    // ハッシュ結合の探索側に RuntimeFilter を置く。ハッシュ表は小さい方で作るので大きい方を探索側とみて、
+    // その行の多くがハッシュ表のどの行とも一致しないと見込めるときだけ置く
+    fn runtime_filters(&self, plan: &mut PlanNode) {
+        for child in plan.children_mut() {
+            self.runtime_filters(child);
+        }
+        let model = self.model();
+        let PlanNode::HashJoin {
+            left,
+            right,
+            left_keys,
+            right_keys,
+            join_type,
+            runtime_filter: runtime_filter @ None,
+            ..
+        } = plan
+        else {
+            return;
+        };
+        // 相手のない探索側の行を返す結合と Anti では、一致しない行も捨てられない
+        let right_probes = model.rows(left) <= model.rows(right);
+        let side = match join_type {
+            JoinType::Inner => usize::from(right_probes),
+            JoinType::Left if right_probes => 1,
+            JoinType::Right if !right_probes => 0,
+            JoinType::Semi => 0,
+            _ => return,
+        };
+        let (probe, build, probe_keys, build_keys) = if side == 0 {
+            (left, right, left_keys, right_keys)
+        } else {
+            (right, left, right_keys, left_keys)
+        };
+        if model.match_fraction(probe, build, probe_keys, build_keys) > RUNTIME_FILTER_FRACTION {
+            return;
+        }
+        let id = self.next_cte.get();
+        self.next_cte.set(id + 1);
+        push_runtime_filter(self.catalog, probe, id, probe_keys.clone());
+        *runtime_filter = Some((id, side));
+    }
+
+    // 同じ PARTITION BY と ORDER BY のウィンドウ関数をまとめて、その順に並べ替えてから計算する。
+    // 各呼び出しの結果が何列目に出るかも返す
+    fn plan_windows(
+        &self,
+        mut plan: PlanNode,
+        windows: Vec<BoundWindow>,
+    ) -> Result<(PlanNode, Vec<usize>), Error> {
+        let mut width = plan.columns(self.catalog)?.len();
+        let mut positions = vec![0; windows.len()];
+        // 同じ並べ方のウィンドウ関数の番号を、最初に現れた順にまとめる
+        let mut groups: Vec<Vec<usize>> = vec![];
+        for (k, window) in windows.iter().enumerate() {
+            let same = |group: &&mut Vec<usize>| {
+                let first = &windows[group[0]];
+                first.partition_by == window.partition_by && first.order_by == window.order_by
+            };
+            match groups.iter_mut().find(same) {
+                Some(group) => group.push(k),
+                None => groups.push(vec![k]),
+            }
+        }
+        for group in groups {
+            let BoundWindow {
+                partition_by,
+                order_by,
+                ..
+            } = &windows[group[0]];
+            let mut keys = partition_by
+                .iter()
+                .map(|expr| SortKey {
+                    expr: expr.clone(),
+                    asc: true,
+                    nulls_first: false,
+                })
+                .collect::<Vec<_>>();
+            keys.extend(order_by.iter().cloned());
+            if !keys.is_empty() {
+                plan = PlanNode::Sort {
+                    input: Box::new(plan),
+                    keys,
+                    top_n: None,
+                };
+            }
+            for (j, k) in group.iter().enumerate() {
+                positions[*k] = width + j;
+            }
+            width += group.len();
+            plan = PlanNode::Window {
+                input: Box::new(plan),
+                partition_by: partition_by.clone(),
+                order_by: order_by.clone(),
+                calls: group.iter().map(|k| windows[*k].call.clone()).collect(),
+            };
+        }
+        Ok((plan, positions))
+    }
+
+    // カタログのテーブルを読む計画と、qualifier で修飾したその列。権限は確かめない
+    fn table_scope(&self, name: &str, qualifier: &str) -> Result<(PlanNode, Scope), Error> {
+        let table = self
+            .catalog
+            .table(name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        let columns = table
+            .columns
+            .iter()
+            .map(|c| ScopeColumn {
+                table: qualifier.to_string(),
+                name: c.name.clone(),
+                collation: c.collation,
+                ty: Some(c.data_type.clone()),
+            })
+            .collect();
+        let mut plan = table_scan(self.catalog, table);
+        if let Some(ttl) = self.catalog.ttl(table).filter(|_| self.ttl_filter) {
+            plan = filter(plan, ttl_predicate(ttl).into_iter().collect());
+            // 期限は計画したときの時刻で決まるので、今の時刻を読む関数と同じに扱う
+            self.volatile.set(true);
+        }
+        Ok((
+            plan,
+            Scope {
+                columns,
+                ..Scope::default()
+            },
+        ))
+    }
+
+    // TABLESAMPLE の割合と REPEATABLE の種
+    fn sample_arguments(&self, sample: &ast::TableSample) -> Result<(f64, Option<f64>), Error> {
+        let number = |expr, clause| match eval_constant(self, expr, clause)? {
+            Value::Null => Err(Error::SampleNull(clause)),
+            value => value
+                .as_f64()
+                .ok_or(Error::SampleNotNumber(clause, value.type_name())),
+        };
+        let percent = number(&sample.percent, "TABLESAMPLE")?;
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(Error::SamplePercent);
+        }
+        let seed = sample
+            .seed
+            .as_ref()
+            .map(|seed| number(seed, "TABLESAMPLE REPEATABLE"))
+            .transpose()?;
+        Ok((percent / 100.0, seed))
+    }
+
+    fn check_privilege(&self, table: &str, privilege: Privilege) -> Result<(), Error> {
+        match &self.user {
+            Some(user) if !self.catalog.has_privilege(user, table, privilege) => {
+                Err(Error::PermissionDenied(table.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn plan_from(&self, from: &TableRef) -> Result<(PlanNode, Scope), Error> {
+        match from {
+            TableRef::Table {
+                name,
+                alias,
+                sample,
+                as_of,
+            } => {
+                let qualifier = alias.as_ref().unwrap_or(name);
+                let mut ctes = self.ctes.borrow_mut();
+                if let Some(cte) = ctes.iter_mut().rev().find(|c| c.name == *name) {
+                    if sample.is_some() {
+                        return Err(Error::SampleNotTable);
+                    }
+                    if as_of.is_some() {
+                        return Err(Error::AsOfNotTable);
+                    }
+                    cte.refs += 1;
+                    let columns = cte
+                        .columns
+                        .iter()
+                        .map(|column| ScopeColumn {
+                            table: qualifier.clone(),
+                            name: column.clone(),
+                            collation: Collation::Binary,
+                            ty: None,
+                        })
+                        .collect();
+                    let plan = PlanNode::CteScan {
+                        id: cte.id,
+                        columns: cte.columns.clone(),
+                    };
+                    return Ok((
+                        plan,
+                        Scope {
+                            columns,
+                            ..Scope::default()
+                        },
+                    ));
+                }
+                if let Some(view) = StatsView::lookup(name) {
+                    if sample.is_some() {
+                        return Err(Error::SampleNotTable);
+                    }
+                    if as_of.is_some() {
+                        return Err(Error::AsOfNotTable);
+                    }
+                    // 別名がなければ、スキーマを除いた名前で列を修飾する
+                    let qualifier = alias
+                        .clone()
+                        .unwrap_or_else(|| name.rsplit('.').next().unwrap().to_string());
+                    let columns = view
+                        .columns()
+                        .iter()
+                        .map(|column| ScopeColumn {
+                            table: qualifier.clone(),
+                            name: column.to_string(),
+                            collation: Collation::Binary,
+                            ty: None,
+                        })
+                        .collect();
+                    return Ok((
+                        PlanNode::StatsScan { view },
+                        Scope {
+                            columns,
+                            ..Scope::default()
+                        },
+                    ));
+                }
+                // 別名がなければ、スキーマを除いた名前で列を修飾する
+                let qualifier = match alias {
+                    Some(alias) => alias,
+                    None => name.rsplit('.').next().unwrap(),
+                };
+                if let Some(table) = self.attached.and_then(|attached| attached(name)) {
+                    if sample.is_some() {
+                        return Err(Error::SampleNotTable);
+                    }
+                    if as_of.is_some() {
+                        return Err(Error::AsOfNotTable);
+                    }
+                    let (columns, rows) = table?;
+                    let scope = Scope {
+                        columns: columns
+                            .iter()
+                            .map(|column| ScopeColumn {
+                                table: qualifier.to_string(),
+                                name: column.clone(),
+                                collation: Collation::Binary,
+                                ty: None,
+                            })
+                            .collect(),
+                        ..Scope::default()
+                    };
+                    let plan = PlanNode::Projection {
+                        input: Box::new(PlanNode::Values { rows }),
+                        exprs: (0..columns.len()).map(Expr::column).collect(),
+                        columns,
+                    };
+                    return Ok((plan, scope));
+                }
+                let name = main_table(name);
+                let (mut plan, scope) = self.table_scope(name, qualifier)?;
+                self.check_privilege(name, Privilege::Select)?;
+                if let Some(sample) = sample {
+                    let (fraction, seed) = self.sample_arguments(sample)?;
+                    sample_scans(&mut plan, sample.method, fraction, seed);
+                }
+                if let Some(as_of) = as_of {
+                    let (point, lsn, ty) = match &**as_of {
+                        ast::AsOf::Timestamp(point) => (point, false, DataType::Timestamp),
+                        ast::AsOf::Lsn(point) => (point, true, DataType::BigInt),
+                    };
+                    if let ast::Expr::Parameter(n) = point {
+                        self.infer_param(*n, Some(ty));
+                    }
+                    plan = PlanNode::AsOf {
+                        input: Box::new(plan),
+                        point: bind_expr(self, point, &Scope::default(), "AS OF")?,
+                        lsn,
+                    };
+                }
+                Ok((plan, scope))
+            }
+            TableRef::Subquery { query, alias, .. } => {
+                let plan = self.plan_query(query)?;
+                let scope = self.derived_scope(&plan, alias)?;
+                Ok((plan, scope))
+            }
+            TableRef::Join {
+                left,
+                right,
+                kind,
+                on,
+            } => {
+                let join_type = match kind {
+                    JoinKind::Inner | JoinKind::Cross => JoinType::Inner,
+                    JoinKind::Left => JoinType::Left,
+                    JoinKind::Right => JoinType::Right,
+                    JoinKind::Full => JoinType::Full,
+                };
+                let (left, mut scope) = self.plan_from(left)?;
+                let left_width = scope.columns.len();
+                // LATERAL の副問い合わせが左の列を参照していれば、左の行ごとに実行し直す
+                let mut lateral = None;
+                let (right, right_scope) = match &**right {
+                    TableRef::Subquery {
+                        query,
+                        alias,
+                        lateral: true,
+                    } => {
+                        let right = match self.plan_query(query) {
+                            Err(Error::ColumnNotFound(_) | Error::MissingFromEntry(_)) => {
+                                if !matches!(join_type, JoinType::Inner | JoinType::Left) {
+                                    return Err(Error::Unsupported(
+                                        "RIGHT or FULL JOIN to a LATERAL subquery",
+                                    ));
+                                }
+                                let (id, right) = self.plan_lateral(&scope, query)?;
+                                lateral = Some(id);
+                                right
+                            }
+                            right => right?,
+                        };
+                        let right_scope = self.derived_scope(&right, alias)?;
+                        (right, right_scope)
+                    }
+                    right => self.plan_from(right)?,
+                };
+                for column in &right_scope.columns {
+                    if scope.has_table(&column.table) {
+                        return Err(Error::DuplicateTable(column.table.clone()));
+                    }
+                }
+                scope.columns.extend(right_scope.columns);
+                let predicate = on
+                    .as_ref()
+                    .map(|on| bind_expr(self, on, &scope, "JOIN conditions"))
+                    .transpose()?;
+                let plan = match lateral {
+                    Some(id) => PlanNode::LateralJoin {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        id,
+                        predicate: Expr::conjunction(simplify_conjuncts(
+                            predicate.map_or(vec![], Expr::split_conjunction),
+                        )),
+                        join_type,
+                        single: false,
+                    },
+                    None => plan_join(&self.model(), left, right, left_width, predicate, join_type),
+                };
+                Ok((plan, scope))
+            }
+        }
+    }
+
+    // FROM の副問い合わせの列。alias で修飾する
+    fn derived_scope(&self, plan: &PlanNode, alias: &str) -> Result<Scope, Error> {
+        let columns = plan
+            .columns(self.catalog)?
+            .into_iter()
+            .zip(plan.column_types(self.catalog)?)
+            .map(|(name, ty)| ScopeColumn {
+                table: alias.to_string(),
+                name,
+                collation: Collation::Binary,
+                ty,
+            })
+            .collect();
+        Ok(Scope {
+            columns,
+            ..Scope::default()
+        })
+    }
+}
+
+// SET の代入を (列の位置, 新しい値) にする。代入できるのは scope の先頭から width 列まで
+fn bind_assignments(
+    planner: &Planner,
+    assignments: &[ast::Assignment],
+    scope: &Scope,
+    table: &Table,
+    clause: &'static str,
+) -> Result<Vec<(usize, Expr)>, Error> {
+    let mut bound: Vec<(usize, Expr)> = vec![];
+    for assignment in assignments {
+        let i = scope.columns[..table.columns.len()]
+            .iter()
+            .position(|c| c.name == assignment.column)
+            .ok_or_else(|| Error::ColumnNotFound(assignment.column.clone()))?;
+        if bound.iter().any(|(j, _)| *j == i) {
+            return Err(Error::DuplicateAssignment(assignment.column.clone()));
+        }
+        if table.generated_column(i).is_some() {
+            return Err(Error::GeneratedColumn(assignment.column.clone()));
+        }
+        if let ast::Expr::Parameter(n) = assignment.value {
+            planner.infer_param(n, Some(table.columns[i].data_type.clone()));
+        }
+        bound.push((i, bind_expr(planner, &assignment.value, scope, clause)?));
+    }
+    Ok(bound)
+}
+
+// 結合の方法のうち費用の見積もりが最も小さいものを選ぶ
+//
+// 入れ子ループ結合はいつでも使える。左右の列を 1 つずつ比べる等号があればハッシュ結合と、
+// 内部結合ならマージ結合も使える。右側がテーブルで、等号で列がすべて決まるインデックスがあれば
+// インデックス結合も使える。
+fn plan_join(
+    model: &CostModel,
+    left: PlanNode,
+    right: PlanNode,
+    left_width: usize,
+    predicate: Option<Expr>,
+    join_type: JoinType,
+) -> PlanNode {
+    // 式が左右どちらの列だけを参照しているか
+    let side = |expr: &Expr| {
+        let mut columns = vec![];
+        expr.collect_columns(&mut columns);
+        if columns.is_empty() {
+            None
+        } else if columns.iter().all(|&i| i < left_width) {
+            Some(true)
+        } else if columns.iter().all(|&i| i >= left_width) {
+            Some(false)
+        } else {
+            None
+        }
+    };
+    // (左の式, 右の行に対する右の式, 元の条件)
+    let mut pairs = vec![];
+    let mut residual = vec![];
+    for conjunct in predicate.map_or(vec![], Expr::split_conjunction) {
+        if let Expr::Binary {
+            op: BinaryOp::Eq,
+            left: l,
+            right: r,
+        } = &conjunct
+        {
+            let keys = match (side(l), side(r)) {
+                (Some(true), Some(false)) => Some((l, r)),
+                (Some(false), Some(true)) => Some((r, l)),
+                _ => None,
+            };
+            if let Some((l, r)) = keys {
+                let (l, r) = ((**l).clone(), r.map_columns(&|i| i - left_width));
+                pairs.push((l, r, conjunct));
+                continue;
+            }
+        }
+        residual.push(conjunct);
+    }
+    let mut candidates = vec![];
+    if !pairs.is_empty() {
+        let (left_keys, right_keys): (Vec<_>, Vec<_>) =
+            pairs.iter().map(|(l, r, _)| (l.clone(), r.clone())).unzip();
+        candidates.push(PlanNode::HashJoin {
+            left: Box::new(left.clone()),
+            right: Box::new(right.clone()),
+            left_keys: left_keys.clone(),
+            right_keys: right_keys.clone(),
+            predicate: Expr::conjunction(residual.clone()),
+            join_type,
+            runtime_filter: None,
+        });
+        if join_type == JoinType::Inner {
+            candidates.push(PlanNode::MergeJoin {
+                left: Box::new(sorted(model.catalog, left.clone(), &left_keys)),
+                right: Box::new(sorted(model.catalog, right.clone(), &right_keys)),
+                left_keys,
+                right_keys,
+                predicate: Expr::conjunction(residual.clone()),
+            });
+        }
+    }
+    let table = match &right {
+        PlanNode::SeqScan { table, .. } if join_type == JoinType::Inner => {
+            model.catalog.table(table)
+        }
+        _ => None,
+    };
+    for (t, index) in table
+        .iter()
+        .flat_map(|t| t.indexes.iter().map(move |i| (t, i)))
+        .filter(|(_, i)| i.is_ordered())
+    {
+        // 照合順のキーを比べる結合なら、外側のキーも照合順のキーになっている
+        let positions = index_keys(t, index)
+            .iter()
+            .map(|key| pairs.iter().position(|(_, r, _)| r == key))
+            .collect::<Option<Vec<_>>>();
+        let Some(positions) = positions else {
+            continue;
+        };
+        let keys = positions.iter().map(|&i| pairs[i].0.clone()).collect();
+        let mut residual = residual.clone();
+        for (i, (_, _, conjunct)) in pairs.iter().enumerate() {
+            if !positions.contains(&i) {
+                residual.push(conjunct.clone());
+            }
+        }
+        candidates.push(PlanNode::IndexJoin {
+            left: Box::new(left.clone()),
+            table: table.unwrap().name.clone(),
+            index: index.name.clone(),
+            keys,
+            predicate: Expr::conjunction(residual),
+        });
+    }
+    let mut predicate = pairs.into_iter().map(|(_, _, c)| c).collect::<Vec<_>>();
+    predicate.extend(residual);
+    let mut best = PlanNode::NestedLoopJoin {
+        left: Box::new(left),
+        right: Box::new(rescannable(right)),
+        predicate: Expr::conjunction(predicate),
+        join_type,
+    };
+    let mut best_cost = model.rank(&best);
+    for plan in candidates {
+        let cost = model.rank(&plan);
+        if cost < best_cost {
+            best = plan;
+            best_cost = cost;
+        }
+    }
+    best
+}
+
+// マージ結合の入力。keys の昇順に並んでいなければ並べ替える
+fn sorted(catalog: &Catalog, plan: PlanNode, keys: &[Expr]) -> PlanNode {
+    if is_sorted(catalog, &plan, keys) {
+        return plan;
+    }
+    PlanNode::Sort {
+        input: Box::new(plan),
+        keys: keys
+            .iter()
+            .map(|expr| SortKey {
+                expr: expr.clone(),
+                asc: true,
+                nulls_first: false,
+            })
+            .collect(),
+        top_n: None,
+    }
+}
+
+// 計画の出力がすでに keys の昇順に並んでいるか
+fn is_sorted(catalog: &Catalog, plan: &PlanNode, keys: &[Expr]) -> bool {
+    match plan {
+        PlanNode::Sort {
+            keys: sort_keys,
+            top_n: None,
+            ..
+        } => {
+            sort_keys.len() >= keys.len()
+                && sort_keys
+                    .iter()
+                    .zip(keys)
+                    .all(|(k, e)| k.asc && !k.nulls_first && k.expr == *e)
+        }
+        // 範囲の先頭の等号で決まる列のあとは、インデックスの列の順に並ぶ
+        PlanNode::IndexScan {
+            table,
+            index,
+            range,
+            ..
+        } => catalog.table(table).is_some_and(|t| {
+            t.index(index).is_some_and(|index| {
+                let index_keys = index_keys(t, index);
+                // BINARY でない照合順の列は照合順のキーの順に、式は式の値の順に並ぶ
+                let index_keys = &index_keys[range.prefix.len().min(index_keys.len())..];
+                index_keys.len() >= keys.len() && index_keys.iter().zip(keys).all(|(k, e)| k == e)
+            })
+        }),
+        PlanNode::Filter { input, .. }
+        | PlanNode::RuntimeFilter { input, .. }
+        | PlanNode::PartitionPrune { input, .. }
+        | PlanNode::AsOf { input, .. } => is_sorted(catalog, input, keys),
+        _ => false,
+    }
+}
+
+// 射影の出力が keys の順に並んでいるか。インデックスは昇順で NULL が後なので、
+// すべてのキーが昇順で NULL が後のときだけ、射影の前の式に戻してインデックスの列の順と比べる
+fn projected_sorted(catalog: &Catalog, plan: &PlanNode, keys: &[SortKey]) -> bool {
+    let PlanNode::Projection { input, exprs, .. } = plan else {
+        return false;
+    };
+    if !keys.iter().all(|key| key.asc && !key.nulls_first) {
+        return false;
+    }
+    let keys = keys
+        .iter()
+        .map(|key| key.expr.replace_columns(&|i| exprs[i].clone()))
+        .collect::<Vec<_>>();
+    is_sorted(catalog, input, &keys)
+}
+
+// WITH RECURSIVE の問い合わせが 非再帰項 UNION [ALL] 再帰項 の形なら、ALL か、非再帰項、再帰項
+fn recursive_terms(cte: &ast::Cte) -> Option<(bool, &SetExpr, &SetExpr)> {
+    let query = &cte.query;
+    if !cte.recursive
+        || !query.with.is_empty()
+        || !query.order_by.is_empty()
+        || query.limit.is_some()
+        || query.offset.is_some()
+    {
+        return None;
+    }
+    match &query.body {
+        SetExpr::SetOperation {
+            op: ast::SetOperator::Union,
+            all,
+            left,
+            right,
+        } => Some((*all, left, right)),
+        _ => None,
+    }
+}
+
+// WITH name (columns) の列名を問い合わせの列名に重ねる
+fn cte_columns(cte: &ast::Cte, mut columns: Vec<String>) -> Result<Vec<String>, Error> {
+    if cte.columns.len() > columns.len() {
+        return Err(Error::CteColumns {
+            name: cte.name.clone(),
+            available: columns.len(),
+            specified: cte.columns.len(),
+        });
+    }
+    columns[..cte.columns.len()].clone_from_slice(&cte.columns);
+    Ok(columns)
+}
+
+// id の CteScan を cte の計画で置き換える。置き換えたら cte は None になる。
+// 入れ子ループ結合の内側なら、そこで読み直せるようにする
+fn inline_cte(plan: &mut PlanNode, id: usize, cte: &mut Option<PlanNode>) {
+    if cte.is_none() {
+        return;
+    }
+    match plan {
+        PlanNode::CteScan { id: i, .. } if *i == id => *plan = cte.take().unwrap(),
+        PlanNode::NestedLoopJoin { right, .. } if matches!(**right, PlanNode::CteScan { id: i, .. } if i == id) => {
+            **right = rescannable(cte.take().unwrap())
+        }
+        plan => {
+            for child in plan.children_mut() {
+                inline_cte(child, id, cte);
+            }
+        }
+    }
+}
+
+// 入れ子ループ結合の内側。そのまま読み直すのが安くなければ、結果をためて読み直す
+fn rescannable(plan: PlanNode) -> PlanNode {
+    match plan {
+        PlanNode::SeqScan { .. }
+        | PlanNode::Values { .. }
+        | PlanNode::CteScan { .. }
+        | PlanNode::Materialize { .. } => plan,
+        plan => PlanNode::Materialize {
+            input: Box::new(plan),
+        },
+    }
+}
+
+// 結合を作り直すために分解したもの。on は左の行のあとに右の行をつなげた行に対する条件
+struct JoinParts {
+    left: PlanNode,
+    right: PlanNode,
+    left_width: usize,
+    on: Vec<Expr>,
+    join_type: JoinType,
+}
+
+impl JoinParts {
+    // 結合でなければ plan をそのまま返す
+    fn split(catalog: &Catalog, plan: PlanNode) -> Result<Result<Self, PlanNode>, Error> {
+        let conjuncts = |predicate: Option<Expr>| predicate.map_or(vec![], Expr::split_conjunction);
+        let parts = match plan {
+            PlanNode::NestedLoopJoin {
+                left,
+                right,
+                predicate,
+                join_type,
+            } => {
+                let right = match *right {
+                    PlanNode::Materialize { input } => *input,
+                    right => right,
+                };
+                Self {
+                    left_width: left.columns(catalog)?.len(),
+                    left: *left,
+                    right,
+                    on: conjuncts(predicate),
+                    join_type,
+                }
+            }
+            PlanNode::HashJoin {
+                left,
+                right,
+                left_keys,
+                right_keys,
+                predicate,
+                join_type,
+                ..
+            } => {
+                let left_width = left.columns(catalog)?.len();
+                let mut on = left_keys
+                    .into_iter()
+                    .zip(right_keys)
+                    .map(|(l, r)| Expr::binary(BinaryOp::Eq, l, r.map_columns(&|i| i + left_width)))
+                    .collect::<Vec<_>>();
+                on.extend(conjuncts(predicate));
+                Self {
+                    left: *left,
+                    right: *right,
+                    left_width,
+                    on,
+                    join_type,
+                }
+            }
+            // 結合のために並べ替えたのなら並べ替えを外す
+            PlanNode::MergeJoin {
+                left,
+                right,
+                left_keys,
+                right_keys,
+                predicate,
+            } => {
+                let unsorted = |plan: PlanNode, keys: &[Expr]| match plan {
+                    PlanNode::Sort {
+                        input,
+                        keys: sort_keys,
+                        top_n: None,
+                    } if sort_keys.iter().map(|k| &k.expr).eq(keys) => *input,
+                    plan => plan,
+                };
+                let left_width = left.columns(catalog)?.len();
+                let mut on = left_keys
+                    .iter()
+                    .zip(&right_keys)
+                    .map(|(l, r)| {
+                        Expr::binary(BinaryOp::Eq, l.clone(), r.map_columns(&|i| i + left_width))
+                    })
+                    .collect::<Vec<_>>();
+                on.extend(conjuncts(predicate));
+                Self {
+                    left: unsorted(*left, &left_keys),
+                    right: unsorted(*right, &right_keys),
+                    left_width,
+                    on,
+                    join_type: JoinType::Inner,
+                }
+            }
+            PlanNode::IndexJoin {
+                left,
+                table,
+                index,
+                keys,
+                predicate,
+            } => {
+                let left_width = left.columns(catalog)?.len();
+                let columns = catalog
+                    .table(&table)
+                    .and_then(|t| t.indexes.iter().find(|i| i.name == index))
+                    .ok_or_else(|| executor::Error::IndexNotFound(index.clone()))?
+                    .columns
+                    .clone();
+                let mut on = keys
+                    .into_iter()
+                    .zip(columns)
+                    .map(|(key, c)| Expr::binary(BinaryOp::Eq, key, Expr::column(left_width + c)))
+                    .collect::<Vec<_>>();
+                on.extend(conjuncts(predicate));
+                Self {
+                    left: *left,
+                    right: PlanNode::SeqScan {
+                        table,
+                        needed: None,
+                    },
+                    left_width,
+                    on,
+                    join_type: JoinType::Inner,
+                }
+            }
+            plan => return Ok(Err(plan)),
+        };
+        Ok(Ok(parts))
+    }
+}
+
+// 行を返さない計画
+fn empty(columns: Vec<String>) -> PlanNode {
+    PlanNode::Projection {
+        input: Box::new(PlanNode::Values { rows: vec![] }),
+        exprs: vec![Expr::Literal(Value::Null); columns.len()],
+        columns,
+    }
+}
+
+fn is_empty(plan: &PlanNode) -> bool {
+    matches!(plan, PlanNode::Projection { input, .. }
+        if matches!(&**input, PlanNode::Values { rows } if rows.is_empty()))
+}
+
+// テーブルを読む計画。パーティションに分けたテーブルは子を順につないで読む
+fn table_scan(catalog: &Catalog, table: &Table) -> PlanNode {
+    if table.partitioning.is_none() {
+        return PlanNode::SeqScan {
+            table: table.name.clone(),
+            needed: None,
+        };
+    }
+    catalog
+        .partitions(table)
+        .map(|child| table_scan(catalog, child))
+        .reduce(|left, right| PlanNode::Append {
+            left: Box::new(left),
+            right: Box::new(right),
+        })
+        .unwrap_or_else(|| empty(table.columns.iter().map(|c| c.name.clone()).collect()))
+}
+
+// テーブルを順に読む計画を TABLESAMPLE の走査にする。パーティションの子もそれぞれ同じ割合で選ぶ
+fn sample_scans(plan: &mut PlanNode, method: ast::SampleMethod, fraction: f64, seed: Option<f64>) {
+    if let PlanNode::SeqScan { table, needed } = plan {
+        *plan = PlanNode::SampleScan {
+            table: std::mem::take(table),
+            method,
+            fraction,
+            seed,
+            needed: needed.take(),
+        };
+        return;
+    }
+    for child in plan.children_mut() {
+        sample_scans(child, method, fraction, seed);
+    }
+}
+
+// 探索側の行のうちこれより多くが一致すると見込むなら、RuntimeFilter を置かない
+const RUNTIME_FILTER_FRACTION: f64 = 0.5;
+
+// id の RuntimeFilter を plan の走査のすぐ上に置く。Filter と、キーの列をすべて片側から返す内部結合は下へたどる。
+// パーティションの子をつないだ Append では子ごとに置き、範囲で分けた子の分け方の列がキーにあれば、
+// 実行中にハッシュ表のキーの範囲で子ごと読まずに済ませる
+fn push_runtime_filter(catalog: &Catalog, plan: &mut PlanNode, id: usize, keys: Vec<Expr>) {
+    match plan {
+        PlanNode::Filter { input, .. }
+        | PlanNode::RuntimeFilter { input, .. }
+        | PlanNode::PartitionPrune { input, .. } => {
+            return push_runtime_filter(catalog, input, id, keys);
+        }
+        PlanNode::Append { left, right } => {
+            push_runtime_filter(catalog, left, id, keys.clone());
+            return push_runtime_filter(catalog, right, id, keys);
+        }
+        PlanNode::NestedLoopJoin {
+            left,
+            right,
+            join_type: JoinType::Inner,
+            ..
+        }
+        | PlanNode::HashJoin {
+            left,
+            right,
+            join_type: JoinType::Inner,
+            ..
+        }
+        | PlanNode::MergeJoin { left, right, .. } => {
+            let mut columns = vec![];
+            for key in &keys {
+                key.collect_columns(&mut columns);
+            }
+            match left.columns(catalog).map(|c| c.len()) {
+                Ok(width) if !columns.is_empty() && columns.iter().all(|&c| c < width) => {
+                    return push_runtime_filter(catalog, left, id, keys);
+                }
+                Ok(width) if !columns.is_empty() && columns.iter().all(|&c| c >= width) => {
+                    let keys = keys
+                        .iter()
+                        .map(|key| key.map_columns(&|i| i - width))
+                        .collect();
+                    return push_runtime_filter(catalog, right, id, keys);
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+    let partition = match plan {
+        PlanNode::SeqScan { table, .. }
+        | PlanNode::IndexScan { table, .. }
+        | PlanNode::FulltextScan { table, .. }
+        | PlanNode::RTreeScan { table, .. }
+        | PlanNode::SampleScan { table, .. }
+        | PlanNode::Gather { table, .. } => catalog
+            .table(table)
+            .and_then(|t| t.partition.as_ref())
+            .filter(|p| matches!(p.bound, PartitionBound::Range { .. }))
+            .and_then(|p| keys.iter().position(|k| *k == Expr::Column(p.column)))
+            .map(|key| (table.clone(), key)),
+        _ => None,
+    };
+    let input = std::mem::replace(plan, PlanNode::Values { rows: vec![] });
+    *plan = PlanNode::RuntimeFilter {
+        input: Box::new(input),
+        id,
+        keys,
+        partition,
+    };
+}
+
+// 期限の切れていない行だけを通す条件。期限の時刻は計画を作るときに決める
+fn ttl_predicate(ttl: Ttl) -> Option<Expr> {
+    let cutoff = ttl.cutoff(Timestamp::now())?;
+    let column = Box::new(Expr::Column(ttl.column));
+    Some(Expr::Binary {
+        op: BinaryOp::Or,
+        left: Box::new(Expr::Binary {
+            op: BinaryOp::Gt,
+            left: column.clone(),
+            right: Box::new(Expr::Literal(Value::Timestamp(cutoff))),
+        }),
+        right: Box::new(Expr::IsNull {
+            expr: column,
+            negated: false,
+        }),
+    })
+}
+
+// 行を返さないとわかった側を除いてつなぐ。列の名前は左の側で決まるので、左は名前が同じときだけ除く
+fn append(catalog: &Catalog, left: PlanNode, right: PlanNode) -> Result<PlanNode, Error> {
+    if is_empty(&right) {
+        return Ok(left);
+    }
+    if is_empty(&left) && left.columns(catalog)? == right.columns(catalog)? {
+        return Ok(right);
+    }
+    Ok(PlanNode::Append {
+        left: Box::new(left),
+        right: Box::new(right),
+    })
+}
+
+fn filter(plan: PlanNode, conjuncts: Vec<Expr>) -> PlanNode {
+    match Expr::conjunction(conjuncts) {
+        Some(predicate) => PlanNode::Filter {
+            input: Box::new(plan),
+            predicate,
+        },
+        None => plan,
+    }
+}
+
+// 条件の各項を、評価できるいちばん下の演算子まで押し下げる
+//
+// conjuncts は plan の出力に対する条件で、押し下げられなかった項は plan の上で絞り込む。
+// 内部結合では片側だけを参照する項をその側へ、両側を参照する項を結合条件へ移し、
+// 結合を作り直して等号をハッシュ結合やインデックス結合のキーにする。
+// 外部結合では NULL で埋める側の行を WHERE で先に減らすと結果が変わるので押し下げない。
+// 項はあらかじめ簡単にしておき、どの行も満たさない項があれば何も読まない計画にする。
+fn push_down(model: &CostModel, plan: PlanNode, conjuncts: Vec<Expr>) -> Result<PlanNode, Error> {
+    let mut conjuncts = simplify_conjuncts(conjuncts);
+    if is_contradiction(&conjuncts) {
+        return Ok(empty(plan.columns(model.catalog)?));
+    }
+    let plan = match JoinParts::split(model.catalog, plan)? {
+        Ok(join) if join.join_type == JoinType::Inner => {
+            return reorder_joins(model, join, conjuncts)
+        }
+        Ok(join) => return push_down_join(model, join, conjuncts),
+        Err(plan) => plan,
+    };
+    let plan = match plan {
+        PlanNode::Filter { input, predicate } => {
+            conjuncts.extend(predicate.split_conjunction());
+            return push_down(model, *input, conjuncts);
+        }
+        PlanNode::SeqScan { table, .. } if !conjuncts.is_empty() => {
+            return Ok(plan_scan(model, table, conjuncts))
+        }
+        PlanNode::Projection {
+            input,
+            exprs,
+            columns,
+        } => {
+            let pushed = conjuncts
+                .drain(..)
+                .map(|c| c.replace_columns(&|i| exprs[i].clone()))
+                .collect();
+            PlanNode::Projection {
+                input: Box::new(push_down(model, *input, pushed)?),
+                exprs,
+                columns,
+            }
+        }
+        // グループのキーだけを参照する項はグループを作る前に絞り込める
+        PlanNode::HashAggregate {
+            input,
+            group_by,
+            aggregates,
+        } if !group_by.is_empty() => {
+            let (pushed, above) = conjuncts.into_iter().partition::<Vec<_>, _>(|c| {
+                let mut columns = vec![];
+                c.collect_columns(&mut columns);
+                columns.iter().all(|&i| i < group_by.len())
+            });
+            conjuncts = above;
+            let pushed = pushed
+                .into_iter()
+                .map(|c| c.replace_columns(&|i| group_by[i].clone()))
+                .collect();
+            PlanNode::HashAggregate {
+                input: Box::new(push_down(model, *input, pushed)?),
+                group_by,
+                aggregates,
+            }
+        }
+        PlanNode::Sort {
+            input,
+            keys,
+            top_n: None,
+        } => PlanNode::Sort {
+            input: Box::new(push_down(model, *input, std::mem::take(&mut conjuncts))?),
+            keys,
+            top_n: None,
+        },
+        PlanNode::Append { left, right } => {
+            let left = push_down(model, *left, conjuncts.clone())?;
+            let right = push_down(model, *right, std::mem::take(&mut conjuncts))?;
+            append(model.catalog, left, right)?
+        }
+        PlanNode::Materialize { input } => PlanNode::Materialize {
+            input: Box::new(push_down(model, *input, std::mem::take(&mut conjuncts))?),
+        },
+        PlanNode::With { ctes, input } => PlanNode::With {
+            ctes,
+            input: Box::new(push_down(model, *input, std::mem::take(&mut conjuncts))?),
+        },
+        // ほかの演算子は越えられないので、子の中だけを押し下げる
+        mut plan => {
+            for child in plan.children_mut() {
+                let input = std::mem::replace(child, PlanNode::Values { rows: vec![] });
+                *child = push_down(model, input, vec![])?;
+            }
+            plan
+        }
+    };
+    Ok(filter(plan, conjuncts))
+}
+
+fn push_down_join(
+    model: &CostModel,
+    join: JoinParts,
+    conjuncts: Vec<Expr>,
+) -> Result<PlanNode, Error> {
+    let JoinParts {
+        left,
+        right,
+        left_width,
+        on: join_on,
+        join_type,
+    } = join;
+    let join_on = simplify_conjuncts(join_on);
+    // (左の列を参照するか, 右の列を参照するか)
+    let sides = |expr: &Expr| {
+        let mut columns = vec![];
+        expr.collect_columns(&mut columns);
+        (
+            columns.iter().any(|&i| i < left_width),
+            columns.iter().any(|&i| i >= left_width),
+        )
+    };
+    let mut to_left = vec![];
+    let mut to_right = vec![];
+    let mut on = vec![];
+    let mut above = vec![];
+    for conjunct in conjuncts {
+        let (l, r) = sides(&conjunct);
+        match join_type {
+            JoinType::Inner if !r => to_left.push(conjunct),
+            JoinType::Inner if !l => to_right.push(conjunct),
+            JoinType::Inner => on.push(conjunct),
+            JoinType::Left | JoinType::Semi | JoinType::Anti if !r => to_left.push(conjunct),
+            JoinType::Right if !l => to_right.push(conjunct),
+            _ => above.push(conjunct),
+        }
+    }
+    // 結合条件の項は、相手のない行を NULL で埋める側にだけ押し下げられる
+    for conjunct in join_on {
+        let (l, r) = sides(&conjunct);
+        match join_type {
+            JoinType::Inner | JoinType::Right if !r => to_left.push(conjunct),
+            JoinType::Inner | JoinType::Left | JoinType::Semi | JoinType::Anti if !l => {
+                to_right.push(conjunct)
+            }
+            _ => on.push(conjunct),
+        }
+    }
+    let left = push_down(model, left, to_left)?;
+    // 右がテーブルなら、右だけの項を走査で絞り込まずにインデックス結合の絞り込みに使う方が安いこともある
+    let unpushed = (join_type == JoinType::Inner && matches!(right, PlanNode::SeqScan { .. }))
+        .then(|| {
+            let mut predicate = on.clone();
+            predicate.extend(to_right.iter().cloned());
+            plan_join(
+                model,
+                left.clone(),
+                right.clone(),
+                left_width,
+                Expr::conjunction(predicate),
+                join_type,
+            )
+        });
+    let to_right = to_right
+        .into_iter()
+        .map(|c| c.map_columns(&|i| i - left_width))
+        .collect();
+    let right = push_down(model, right, to_right)?;
+    let mut plan = plan_join(
+        model,
+        left,
+        right,
+        left_width,
+        Expr::conjunction(on),
+        join_type,
+    );
+    if let Some(unpushed) = unpushed {
+        if model.rank(&unpushed) < model.rank(&plan) {
+            plan = unpushed;
+        }
+    }
+    Ok(filter(plan, above))
+}
+
+// 結合の順序を動的計画法で決める関係の数の上限。これより多ければ貪欲法で決める
+const MAX_DP_RELATIONS: usize = 8;
+
+// 内部結合でつながった関係の 1 つ。offset は元の結合の出力でのこの関係の最初の列の位置
+struct Relation {
+    plan: PlanNode,
+    offset: usize,
+    columns: Vec<String>,
+}
+
+fn is_inner_join(plan: &PlanNode) -> bool {
+    matches!(
+        plan,
+        PlanNode::NestedLoopJoin {
+            join_type: JoinType::Inner,
+            ..
+        } | PlanNode::HashJoin {
+            join_type: JoinType::Inner,
+            ..
+        } | PlanNode::MergeJoin { .. }
+            | PlanNode::IndexJoin { .. }
+    )
+}
+
+// 内部結合とその上の絞り込みを開いて、関係と条件の項に分ける
+fn collect_relations(
+    catalog: &Catalog,
+    plan: PlanNode,
+    offset: usize,
+    relations: &mut Vec<Relation>,
+    conjuncts: &mut Vec<Expr>,
+) -> Result<(), Error> {
+    let shift = |c: Expr| c.map_columns(&|i| i + offset);
+    match plan {
+        PlanNode::Filter { input, predicate } if is_inner_join(&input) => {
+            conjuncts.extend(predicate.split_conjunction().into_iter().map(shift));
+            collect_relations(catalog, *input, offset, relations, conjuncts)
+        }
+        plan if is_inner_join(&plan) => {
+            let Ok(join) = JoinParts::split(catalog, plan)? else {
+                unreachable!()
+            };
+            conjuncts.extend(join.on.into_iter().map(shift));
+            collect_relations(catalog, join.left, offset, relations, conjuncts)?;
+            let offset = offset + join.left_width;
+            collect_relations(catalog, join.right, offset, relations, conjuncts)
+        }
+        plan => {
+            relations.push(Relation {
+                columns: plan.columns(catalog)?,
+                plan,
+                offset,
+            });
+            Ok(())
+        }
+    }
+}
+
+// 内部結合でつながった関係を、途中の結果の行数の見積もりが小さくなる順に結合し直す
+//
+// 結合の順序を変えたら、元の列の順に並べ直す射影を上に置く。
+fn reorder_joins(
+    model: &CostModel,
+    join: JoinParts,
+    mut conjuncts: Vec<Expr>,
+) -> Result<PlanNode, Error> {
+    let mut relations = vec![];
+    conjuncts.extend(join.on);
+    collect_relations(model.catalog, join.left, 0, &mut relations, &mut conjuncts)?;
+    collect_relations(
+        model.catalog,
+        join.right,
+        join.left_width,
+        &mut relations,
+        &mut conjuncts,
+    )?;
+    let conjuncts = simplify_conjuncts(conjuncts);
+    if is_contradiction(&conjuncts) {
+        return Ok(empty(
+            relations.into_iter().flat_map(|r| r.columns).collect(),
+        ));
+    }
+    let relation_of = |column: usize| {
+        relations
+            .iter()
+            .rposition(|r| r.offset <= column)
+            .expect("column belongs to a relation")
+    };
+    let stats = |column: usize| {
+        let relation = &relations[relation_of(column)];
+        model.column_stats(&relation.plan, column - relation.offset)
+    };
+    // 1 つの関係だけを参照する項はその関係の行数に、複数を参照する項は結合の行数に効く
+    let mut rows = relations
+        .iter()
+        .map(|r| model.rows(&r.plan))
+        .collect::<Vec<_>>();
+    let mut edges = vec![];
+    for conjunct in &conjuncts {
+        let mut columns = vec![];
+        conjunct.collect_columns(&mut columns);
+        let mut referenced = columns.into_iter().map(relation_of).collect::<Vec<_>>();
+        referenced.sort_unstable();
+        referenced.dedup();
+        match referenced[..] {
+            [] => {}
+            [k] => rows[k] *= selectivity(conjunct, &stats),
+            _ => edges.push((referenced, conjunct)),
+        }
+    }
+    let edges = edges
+        .into_iter()
+        .map(|(referenced, conjunct)| {
+            // 2 つの関係の列どうしの等号は、値の種類の多い方の各値と 1 つずつ結び付くとみる
+            let selectivity = match conjunct {
+                Expr::Binary {
+                    op: BinaryOp::Eq,
+                    left,
+                    right,
+                } if referenced.len() == 2 => match (&**left, &**right) {
+                    (&Expr::Column(l), &Expr::Column(r)) => {
+                        let distinct = |c: usize| distinct(stats(c), rows[relation_of(c)]);
+                        1.0 / distinct(l).max(distinct(r))
+                    }
+                    _ => selectivity(conjunct, &stats),
+                },
+                _ => selectivity(conjunct, &stats),
+            };
+            (referenced, selectivity)
+        })
+        .collect::<Vec<_>>();
+    let order = join_order(&rows, &edges);
+
+    let mut offsets = vec![0; relations.len()];
+    let mut width = 0;
+    for &k in &order {
+        offsets[k] = width;
+        width += relations[k].columns.len();
+    }
+    let position = |column: usize| {
+        let k = relation_of(column);
+        offsets[k] + column - relations[k].offset
+    };
+    let reordered = order.windows(2).any(|w| w[0] > w[1]);
+    let restore = reordered.then(|| {
+        let exprs = (0..width).map(|c| Expr::column(position(c))).collect();
+        let columns = relations.iter().flat_map(|r| r.columns.clone()).collect();
+        (exprs, columns)
+    });
+    let conjuncts = conjuncts
+        .into_iter()
+        .map(|c| c.map_columns(&position))
+        .collect();
+    let mut relations = relations.into_iter().map(Some).collect::<Vec<_>>();
+    let mut take = |k: usize| relations[k].take().unwrap();
+    let (&last, rest) = order.split_last().unwrap();
+    let mut left = take(rest[0]).plan;
+    for &k in &rest[1..] {
+        left = PlanNode::NestedLoopJoin {
+            left: Box::new(left),
+            right: Box::new(take(k).plan),
+            predicate: None,
+            join_type: JoinType::Inner,
+        };
+    }
+    let right = take(last);
+    let join = JoinParts {
+        left,
+        left_width: width - right.columns.len(),
+        right: right.plan,
+        on: vec![],
+        join_type: JoinType::Inner,
+    };
+    let plan = push_down_join(model, join, conjuncts)?;
+    Ok(match restore {
+        Some((exprs, columns)) => PlanNode::Projection {
+            input: Box::new(plan),
+            exprs,
+            columns,
+        },
+        None => plan,
+    })
+}
+
+// 関係を結合する順序。rows は各関係の行数、edges は複数の関係を参照する条件の項と、それを満たす行の割合
+//
+// 関係が少なければ、左から 1 つずつ結合していく順序のうち、途中の結果の行数の合計が最小のものを
+// 動的計画法で求める。多ければ、いちばん小さい関係から始めて、結合の結果が最も小さくなる関係を順に足す。
+// 見積もりが同じなら元の順序を選ぶ。
+fn join_order(rows: &[f64], edges: &[(Vec<usize>, f64)]) -> Vec<usize> {
+    let n = rows.len();
+    let join_rows = |set: &[bool]| {
+        let mut size = 1.0;
+        for (k, &r) in rows.iter().enumerate() {
+            if set[k] {
+                size *= r;
+            }
+        }
+        for (referenced, selectivity) in edges {
+            if referenced.iter().all(|&k| set[k]) {
+                size *= selectivity;
+            }
+        }
+        f64::max(size, 1.0)
+    };
+    if n > MAX_DP_RELATIONS {
+        let mut set = vec![false; n];
+        let first = (0..n).min_by(|&a, &b| rows[a].total_cmp(&rows[b])).unwrap();
+        set[first] = true;
+        let mut order = vec![first];
+        while order.len() < n {
+            let size = |k: usize| {
+                let mut set = set.clone();
+                set[k] = true;
+                join_rows(&set)
+            };
+            let next = (0..n)
+                .filter(|&k| !set[k])
+                .min_by(|&a, &b| size(a).total_cmp(&size(b)))
+                .unwrap();
+            set[next] = true;
+            order.push(next);
+        }
+        return order;
+    }
+    // best[s] は集合 s の関係を結合する最良の順序と、途中の結果の行数の合計
+    let mut best: Vec<Option<(Vec<usize>, f64)>> = vec![None; 1 << n];
+    for k in 0..n {
+        best[1 << k] = Some((vec![k], 0.0));
+    }
+    for s in 1..1usize << n {
+        if s.count_ones() < 2 {
+            continue;
+        }
+        let set = (0..n).map(|k| s & (1 << k) != 0).collect::<Vec<_>>();
+        let size = join_rows(&set);
+        // 最後に結合する関係を後ろから試すと、同じ見積もりなら元の順序が残る
+        for k in (0..n).rev().filter(|&k| set[k]) {
+            let (order, cost) = best[s & !(1 << k)].as_ref().unwrap();
+            let cost = cost + size;
+            if best[s].as_ref().is_none_or(|(_, best)| cost < *best) {
+                let mut order = order.clone();
+                order.push(k);
+                best[s] = Some((order, cost));
+            }
+        }
+    }
+    best.pop().flatten().unwrap().0
+}
+
+// BINARY でない照合順の列のインデックスには照合順のキーが、式のインデックスには式の値が入っているので、
+// キーの式と定数の比較だけを範囲に使う。照合順のキーと比べる定数も照合順のキーに畳んである
+fn key_bound(expr: &Expr, key: &Expr) -> Option<(BinaryOp, Value)> {
+    let Expr::Binary { op, left, right } = expr else {
+        return None;
+    };
+    let Expr::Literal(value) = &**right else {
+        return None;
+    };
+    let comparison = matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq
+    );
+    (comparison && **left == *key).then(|| (*op, value.clone()))
+}
+
+// 列 column と定数の比較なら、その演算子と定数。定数の型が列の型と違えば None。数値の型どうしと、DATE と TIMESTAMP は
+// 同じとみなす。
+// 項は簡単にしてあり、定数は右にある
+fn column_bound(expr: &Expr, column: usize, ty: &DataType) -> Option<(BinaryOp, Value)> {
+    let Expr::Binary { op, left, right } = expr else {
+        return None;
+    };
+    let (op, value) = match (&**left, &**right) {
+        (Expr::Column(c), Expr::Literal(value)) if *c == column => (*op, value),
+        _ => return None,
+    };
+    let comparison = matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq
+    );
+    // 文字列でない列と文字列の比較は、文字列を列の型で読んだ値を範囲に使う。DECIMAL は丸まるので使わない
+    if matches!(value, Value::Text(_)) && !matches!(ty, DataType::Text | DataType::Decimal { .. }) {
+        return ty
+            .cast(value.clone())
+            .ok()
+            .filter(|_| comparison)
+            .map(|value| (op, value));
+    }
+    (comparison && same_type(value.data_type().as_ref(), ty)).then(|| (op, value.clone()))
+}
+
+fn same_type(actual: Option<&DataType>, ty: &DataType) -> bool {
+    match (actual, ty) {
+        (Some(actual), ty) if actual == ty => true,
+        (Some(actual), ty) if actual.is_numeric() && ty.is_numeric() => true,
+        (Some(DataType::Date | DataType::Timestamp), DataType::Date | DataType::Timestamp) => true,
+        _ => false,
+    }
+}
+
+// 列 column と $n の比較なら、その演算子と $n。$n の型が列の型と違えば None
+fn param_bound(expr: &Expr, column: usize, ty: &DataType) -> Option<(BinaryOp, Expr)> {
+    let Expr::Binary { op, left, right } = expr else {
+        return None;
+    };
+    let (Expr::Column(c), Expr::Parameter { ty: actual, .. }) = (&**left, &**right) else {
+        return None;
+    };
+    let comparison = matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq
+    );
+    (*c == column && comparison && same_type(actual.as_ref(), ty)).then(|| (*op, (**right).clone()))
+}
+
+// 文字列の列の c LIKE 'abc%' から c >= 'abc' AND c < 'abd' の範囲を作る
+//
+// ワイルドカードより前の文字が決まる部分で、ワイルドカードがなければ等号にする。
+// 範囲の中の値がすべて一致するとは限らないので、LIKE の項はそのまま残して絞り込む
+fn like_bounds(expr: &Expr, column: usize, ty: &DataType) -> Vec<(BinaryOp, Value)> {
+    let Expr::Function {
+        func: Function::Like,
+        args,
+    } = expr
+    else {
+        return vec![];
+    };
+    let (Expr::Column(c), Expr::Literal(Value::Text(pattern))) = (&args[0], &args[1]) else {
+        return vec![];
+    };
+    let escape = match args.get(2) {
+        None => None,
+        Some(Expr::Literal(Value::Text(escape))) => Some(escape.as_str()),
+        Some(_) => return vec![],
+    };
+    if *c != column || *ty != DataType::Text {
+        return vec![];
+    }
+    let Ok(tokens) = like_escape(escape).and_then(|escape| parse_like(pattern, escape)) else {
+        return vec![];
+    };
+    let prefix = tokens
+        .iter()
+        .map_while(|t| match t {
+            LikeToken::Char(c) => Some(*c),
+            _ => None,
+        })
+        .collect::<String>();
+    if prefix.chars().count() == tokens.len() {
+        return vec![(BinaryOp::Eq, Value::Text(prefix))];
+    }
+    if prefix.is_empty() {
+        return vec![];
+    }
+    let mut bounds = vec![(BinaryOp::GtEq, Value::Text(prefix.clone()))];
+    if let Some(upper) = successor(&prefix) {
+        bounds.push((BinaryOp::Lt, Value::Text(upper)));
+    }
+    bounds
+}
+
+// prefix で始まるどの文字列よりも大きい最小の文字列。最後の文字を次の文字にする
+fn successor(prefix: &str) -> Option<String> {
+    let mut chars = prefix.chars().collect::<Vec<_>>();
+    while let Some(c) = chars.pop() {
+        if let Some(next) = (c as u32 + 1..=char::MAX as u32).find_map(char::from_u32) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+// 子のテーブルの範囲と絞り込みの項が両立しないか。両立しなければその子は読まなくてよい
+//
+// 分け方の列と定数の比較と、定数の IN だけを見る。ハッシュで分けた子には等号と IN だけを使う。
+// 照合順序のある列では等しい値が別の子に入りうるので見ない
+pub(crate) fn outside_partition(t: &Table, conjuncts: &[Expr]) -> bool {
+    let Some(partition) = &t.partition else {
+        return false;
+    };
+    let (column, bound) = (partition.column, &partition.bound);
+    let Column {
+        data_type: ty,
+        collation,
+        ..
+    } = &t.columns[column];
+    if *collation != Collation::Binary {
+        return false;
+    }
+    let outside = |value: &Value| match bound {
+        PartitionBound::Range { .. } => !bound.contains(value),
+        // ハッシュは列の型の値で決まるので、型を変えても値が変わらないときだけ
+        PartitionBound::Hash { .. } => ty
+            .cast(value.clone())
+            .ok()
+            .is_some_and(|v| v.sort_cmp(value).is_eq() && !bound.contains(&v)),
+    };
+    let outside_bound = |expr: &Expr| match (column_bound(expr, column, ty), bound) {
+        (Some((BinaryOp::Eq, value)), _) => outside(&value),
+        (Some((op, value)), PartitionBound::Range { from, to }) => match op {
+            BinaryOp::Lt => from
+                .as_ref()
+                .is_some_and(|from| value.sort_cmp(from).is_le()),
+            BinaryOp::LtEq => from
+                .as_ref()
+                .is_some_and(|from| value.sort_cmp(from).is_lt()),
+            _ => to.as_ref().is_some_and(|to| value.sort_cmp(to).is_ge()),
+        },
+        _ => false,
+    };
+    conjuncts.iter().any(|c| match c {
+        Expr::InList {
+            expr,
+            list,
+            negated: false,
+        } if **expr == Expr::Column(column) => list.iter().all(|e| {
+            outside_bound(&Expr::Binary {
+                op: BinaryOp::Eq,
+                left: expr.clone(),
+                right: Box::new(e.clone()),
+            })
+        }),
+        c => outside_bound(c),
+    })
+}
+
+// 範囲で分けた子のテーブルに、分け方の列が min 以上 max 以下の行がひとつもないか
+pub(crate) fn outside_range(t: &Table, min: &Value, max: &Value) -> bool {
+    let Some(Partition {
+        column,
+        bound: PartitionBound::Range { from, to },
+        ..
+    }) = &t.partition
+    else {
+        return false;
+    };
+    let Column {
+        data_type: ty,
+        collation,
+        ..
+    } = &t.columns[*column];
+    if *collation != Collation::Binary
+        || !same_type(min.data_type().as_ref(), ty)
+        || !same_type(max.data_type().as_ref(), ty)
+    {
+        return false;
+    }
+    from.as_ref().is_some_and(|from| max.sort_cmp(from).is_lt())
+        || to.as_ref().is_some_and(|to| min.sort_cmp(to).is_ge())
+}
+
+// 絞り込みの項でインデックスの範囲が決まり、テーブルを順に読むより安ければインデックスの走査にする
+//
+// インデックスごとに、先頭から等号で決まる列とその次の列の範囲を使う。範囲に使わなかった項は走査の中で絞り込む。
+// パーティションの子で、項が子の範囲と両立しなければ何も読まない。$n を含む項は値が決まる実行のときに見る
+fn plan_scan(model: &CostModel, table: String, conjuncts: Vec<Expr>) -> PlanNode {
+    let Some(t) = model.catalog.table(&table) else {
+        return filter(
+            PlanNode::SeqScan {
+                table,
+                needed: None,
+            },
+            conjuncts,
+        );
+    };
+    if outside_partition(t, &conjuncts) {
+        return empty(t.columns.iter().map(|c| c.name.clone()).collect());
+    }
+    let mut best = filter(
+        PlanNode::SeqScan {
+            table: table.clone(),
+            needed: None,
+        },
+        conjuncts.clone(),
+    );
+    let mut best_cost = model.rank(&best);
+    for index in t.indexes.iter().filter(|i| i.is_ordered()) {
+        let mut range = IndexRange::default();
+        let mut used = vec![];
+        let mut bounded = false;
+        for key in index_keys(t, index) {
+            // LIKE から作った範囲は項を残すので None
+            let bounds = match key {
+                Expr::Column(column) => {
+                    let ty = &t.columns[column].data_type;
+                    conjuncts
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, c)| {
+                            let bound = column_bound(c, column, ty)
+                                .map(|(op, value)| (op, Expr::Literal(value)))
+                                .or_else(|| param_bound(c, column, ty))?;
+                            Some((Some(i), bound))
+                        })
+                        .chain(conjuncts.iter().flat_map(|c| {
+                            like_bounds(c, column, ty)
+                                .into_iter()
+                                .map(|(op, value)| (None, (op, Expr::Literal(value))))
+                        }))
+                        .collect::<Vec<_>>()
+                }
+                key => conjuncts
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, c)| {
+                        let (op, value) = key_bound(c, &key)?;
+                        Some((Some(i), (op, Expr::Literal(value))))
+                    })
+                    .collect(),
+            };
+            if let Some((i, (_, value))) = bounds.iter().find(|(_, (op, _))| *op == BinaryOp::Eq) {
+                range.prefix.push(value.clone());
+                used.extend(*i);
+                bounded = true;
+                continue;
+            }
+            for (i, (op, value)) in bounds {
+                let bound = match op {
+                    BinaryOp::Gt | BinaryOp::GtEq => &mut range.lower,
+                    _ => &mut range.upper,
+                };
+                if bound.is_none() {
+                    *bound = Some(ScanBound {
+                        value,
+                        inclusive: matches!(op, BinaryOp::GtEq | BinaryOp::LtEq),
+                    });
+                    used.extend(i);
+                    bounded = true;
+                }
+            }
+            break;
+        }
+        if !bounded {
+            continue;
+        }
+        let residual = conjuncts
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !used.contains(i))
+            .map(|(_, c)| c.clone())
+            .collect();
+        let plan = PlanNode::IndexScan {
+            table: table.clone(),
+            index: index.name.clone(),
+            range,
+            predicate: Expr::conjunction(residual),
+            index_only: false,
+        };
+        let cost = model.rank(&plan);
+        if cost < best_cost {
+            best = plan;
+            best_cost = cost;
+        }
+    }
+    for index in t.indexes.iter().filter(|i| i.fulltext) {
+        for plan in (0..conjuncts.len()).filter_map(|i| fulltext_scan(t, index, &conjuncts, i)) {
+            let cost = model.rank(&plan);
+            if cost < best_cost {
+                best = plan;
+                best_cost = cost;
+            }
+        }
+    }
+    for index in t.indexes.iter().filter(|i| i.rtree.is_some()) {
+        for (i, conjunct) in conjuncts.iter().enumerate() {
+            let Some(search) = spatial_search(conjunct, index.columns[0]) else {
+                continue;
+            };
+            let residual = conjuncts
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, c)| c.clone())
+                .collect();
+            let plan = PlanNode::RTreeScan {
+                table: table.clone(),
+                index: index.name.clone(),
+                search,
+                predicate: Expr::conjunction(residual),
+            };
+            let cost = model.rank(&plan);
+            if cost < best_cost {
+                best = plan;
+                best_cost = cost;
+            }
+        }
+    }
+    let params = conjuncts
+        .iter()
+        .filter(|c| c.has_params())
+        .cloned()
+        .collect::<Vec<_>>();
+    if t.partition.is_some() && !params.is_empty() {
+        return PlanNode::PartitionPrune {
+            input: Box::new(best),
+            table,
+            conditions: params,
+        };
+    }
+    best
+}
+
+// column と定数の図形の重なりや包含の条件なら、それを R-tree で引く探し方
+fn spatial_search(conjunct: &Expr, column: usize) -> Option<Search> {
+    let Expr::Binary { op, left, right } = conjunct else {
+        return None;
+    };
+    // 定数が左にあれば包含の向きを入れ替える
+    let (op, value) = match (&**left, &**right) {
+        (Expr::Column(c), Expr::Literal(value)) if *c == column => (*op, value),
+        (Expr::Literal(value), Expr::Column(c)) if *c == column => match op {
+            BinaryOp::Contains => (BinaryOp::ContainedBy, value),
+            BinaryOp::ContainedBy => (BinaryOp::Contains, value),
+            op => (*op, value),
+        },
+        _ => return None,
+    };
+    let rect = shape(value)?;
+    match op {
+        BinaryOp::Overlaps => Some(Search::Overlaps(rect)),
+        BinaryOp::Contains => Some(Search::Contains(rect)),
+        BinaryOp::ContainedBy => Some(Search::ContainedBy(rect)),
+        _ => None,
+    }
+}
+
+// ORDER BY 列 <-> 定数 だけで並べるテーブルの走査を、R-tree で近い順に読む走査にする
+//
+// plan は Projection で、その下が SeqScan か、それを絞り込む Filter のときだけ置き換える
+fn nearest_scan(catalog: &Catalog, plan: &mut PlanNode, keys: &[SortKey]) -> bool {
+    let [SortKey {
+        expr: Expr::Column(key),
+        asc: true,
+        nulls_first: false,
+    }] = keys
+    else {
+        return false;
+    };
+    let PlanNode::Projection { input, exprs, .. } = plan else {
+        return false;
+    };
+    let Some(Expr::Binary {
+        op: BinaryOp::Distance,
+        left,
+        right,
+    }) = exprs.get(*key)
+    else {
+        return false;
+    };
+    let (column, value) = match (&**left, &**right) {
+        (Expr::Column(c), Expr::Literal(value)) | (Expr::Literal(value), Expr::Column(c)) => {
+            (*c, value)
+        }
+        _ => return false,
+    };
+    let Some(rect) = shape(value) else {
+        return false;
+    };
+    let (scan, predicate) = match &**input {
+        PlanNode::Filter { input, predicate } => (&**input, Some(predicate.clone())),
+        scan => (scan, None),
+    };
+    let PlanNode::SeqScan { table, .. } = scan else {
+        return false;
+    };
+    let Some(index) = catalog.table(table).and_then(|t| {
+        t.indexes
+            .iter()
+            .find(|i| i.rtree.is_some() && i.columns[0] == column)
+    }) else {
+        return false;
+    };
+    **input = PlanNode::RTreeScan {
+        table: table.clone(),
+        index: index.name.clone(),
+        search: Search::Nearest(rect),
+        predicate,
+    };
+    true
+}
+
+// i 番目の項が index のすべての列に対する MATCH ... AGAINST なら、その語を引く全文インデックスの走査
+fn fulltext_scan(table: &Table, index: &Index, conjuncts: &[Expr], i: usize) -> Option<PlanNode> {
+    let (Expr::Literal(Value::Text(query)), columns) = match_condition(&conjuncts[i])? else {
+        return None;
+    };
+    let mut columns = columns
+        .iter()
+        .map(|c| match c {
+            Expr::Column(c) => Some(*c),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    columns.sort_unstable();
+    columns.dedup();
+    let mut indexed = index.columns.clone();
+    indexed.sort_unstable();
+    let terms = fulltext::query_terms(query);
+    if columns != indexed || terms.is_empty() {
+        return None;
+    }
+    let residual = conjuncts
+        .iter()
+        .enumerate()
+        .filter(|&(j, _)| j != i)
+        .map(|(_, c)| c.clone())
+        .collect();
+    Some(PlanNode::FulltextScan {
+        table: table.name.clone(),
+        index: index.name.clone(),
+        terms: terms.into_iter().collect(),
+        predicate: Expr::conjunction(residual),
+    })
+}
+
+// 上の演算子が使う列を下へ伝え、テーブルの走査では使う列だけを読むようにする
+//
+// needed は plan の出力のうち上で使う列で、None ならすべての列。
+// 使う列がすべてインデックスにあるインデックスの走査は、インデックスだけを読む走査にする。
+// FOR UPDATE / FOR SHARE では、テーブルを読んで絞り込んだ行を、まとめたり並べ替えたりする前にロックする。
+// そのため ORDER BY があると、LIMIT で返す行だけでなく条件に合う行をすべてロックする
+fn lock_rows(
+    plan: PlanNode,
+    select: &ast::Select,
+    grouped: bool,
+    locking: &ast::LockingClause,
+) -> Result<PlanNode, Error> {
+    let clause = if !select.group_by.is_empty() || select.grouping_sets.is_some() {
+        Some("GROUP BY clause")
+    } else if select.having.is_some() {
+        Some("HAVING clause")
+    } else if grouped {
+        Some("aggregate functions")
+    } else if select.distinct {
+        Some("DISTINCT clause")
+    } else {
+        None
+    };
+    if let Some(clause) = clause {
+        return Err(Error::LockingNotAllowed(locking.strength, clause));
+    }
+    // 条件が成り立たなければロックする行もない
+    if let PlanNode::Projection { input, .. } = &plan {
+        if matches!(&**input, PlanNode::Values { rows } if rows.is_empty()) {
+            return Ok(plan);
+        }
+    }
+    // テーブルの行をそのまま返す計画でなければ、行の位置がわからない
+    fn scanned(plan: &PlanNode) -> Option<&str> {
+        match plan {
+            PlanNode::SeqScan { table, .. }
+            | PlanNode::IndexScan { table, .. }
+            | PlanNode::FulltextScan { table, .. }
+            | PlanNode::RTreeScan { table, .. } => Some(table),
+            PlanNode::Filter { input, .. } => scanned(input),
+            _ => None,
+        }
+    }
+    let table = scanned(&plan)
+        .ok_or(Error::Unsupported(
+            "FOR UPDATE on a query that does not read a single table",
+        ))?
+        .to_string();
+    let mode = match locking.strength {
+        ast::LockStrength::Update => LockMode::Exclusive,
+        ast::LockStrength::Share => LockMode::Shared,
+    };
+    Ok(PlanNode::LockRows {
+        table,
+        input: Box::new(plan),
+        mode,
+        wait: locking.wait,
+    })
+}
+
+fn prune_columns(catalog: &Catalog, plan: &mut PlanNode, mut needed: Option<Vec<usize>>) {
+    match plan {
+        PlanNode::SeqScan {
+            table,
+            needed: read,
+        }
+        | PlanNode::SampleScan {
+            table,
+            needed: read,
+            ..
+        } => *read = scan_columns(catalog, table, needed),
+        PlanNode::Gather {
+            table,
+            predicate,
+            needed: read,
+            ..
+        } => {
+            collect(&mut needed, &mut predicate.iter());
+            *read = scan_columns(catalog, table, needed);
+        }
+        PlanNode::IndexScan {
+            table,
+            index,
+            predicate,
+            index_only,
+            ..
+        } => {
+            collect(&mut needed, &mut predicate.iter());
+            let Some(needed) = needed else {
+                return;
+            };
+            let Some(t) = catalog.table(table) else {
+                return;
+            };
+            let Some(index) = t.index(index) else {
+                return;
+            };
+            // 照合順のキーからは元の文字列に戻せない。式のインデックスには列の値がない
+            *index_only = index.expression.is_none()
+                && needed.iter().all(|&c| {
+                    index.columns.contains(&c) && t.columns[c].collation == Collation::Binary
+                });
+        }
+        PlanNode::Projection { input, exprs, .. } => {
+            let mut needed = Some(vec![]);
+            collect(&mut needed, &mut exprs.iter());
+            prune_columns(catalog, input, needed);
+        }
+        PlanNode::HashAggregate {
+            input,
+            group_by,
+            aggregates,
+        }
+        | PlanNode::GroupAggregate {
+            input,
+            group_by,
+            aggregates,
+        }
+        | PlanNode::ParallelAggregate {
+            input,
+            group_by,
+            aggregates,
+        }
+        | PlanNode::GroupingSets {
+            input,
+            group_by,
+            aggregates,
+            ..
+        } => {
+            let mut needed = Some(vec![]);
+            collect(
+                &mut needed,
+                &mut group_by
+                    .iter()
+                    .chain(aggregates.iter().flat_map(|a| &a.arg)),
+            );
+            prune_columns(catalog, input, needed);
+        }
+        PlanNode::Filter { input, predicate } => {
+            collect(&mut needed, &mut std::iter::once(&*predicate));
+            prune_columns(catalog, input, needed);
+        }
+        PlanNode::RuntimeFilter { input, keys, .. }
+        | PlanNode::PartitionPrune {
+            input,
+            conditions: keys,
+            ..
+        } => {
+            collect(&mut needed, &mut keys.iter());
+            prune_columns(catalog, input, needed);
+        }
+        PlanNode::Sort { input, keys, .. } => {
+            collect(&mut needed, &mut keys.iter().map(|k| &k.expr));
+            prune_columns(catalog, input, needed);
+        }
+        PlanNode::Limit { input, .. }
+        | PlanNode::LockRows { input, .. }
+        | PlanNode::Materialize { input }
+        | PlanNode::AsOf { input, .. } => prune_columns(catalog, input, needed),
+        PlanNode::Window {
+            input,
+            partition_by,
+            order_by,
+            calls,
+        } => {
+            let width = input.columns(catalog).map_or(0, |c| c.len());
+            if let Some(needed) = &mut needed {
+                needed.retain(|&c| c < width);
+            }
+            collect(
+                &mut needed,
+                &mut partition_by
+                    .iter()
+                    .chain(order_by.iter().map(|k| &k.expr))
+                    .chain(calls.iter().flat_map(|c| &c.args)),
+            );
+            prune_columns(catalog, input, needed);
+        }
+        PlanNode::ProjectSet { input, exprs } => {
+            let width = input.columns(catalog).map_or(0, |c| c.len());
+            if let Some(needed) = &mut needed {
+                needed.retain(|&c| c < width);
+            }
+            collect(&mut needed, &mut exprs.iter());
+            prune_columns(catalog, input, needed);
+        }
+        PlanNode::NestedLoopJoin {
+            left,
+            right,
+            predicate,
+            ..
+        } => prune_join(catalog, left, right, needed, predicate, &[], &[]),
+        PlanNode::HashJoin {
+            left,
+            right,
+            left_keys,
+            right_keys,
+            predicate,
+            ..
+        }
+        | PlanNode::MergeJoin {
+            left,
+            right,
+            left_keys,
+            right_keys,
+            predicate,
+        } => prune_join(
+            catalog, left, right, needed, predicate, left_keys, right_keys,
+        ),
+        PlanNode::IndexJoin {
+            left,
+            keys,
+            predicate,
+            ..
+        } => {
+            collect(&mut needed, &mut predicate.iter());
+            let Ok(width) = left.columns(catalog).map(|c| c.len()) else {
+                return;
+            };
+            if let Some(needed) = &mut needed {
+                needed.retain(|&c| c < width);
+            }
+            collect(&mut needed, &mut keys.iter());
+            prune_columns(catalog, left, needed);
+        }
+        PlanNode::With { input, .. } => prune_columns(catalog, input, needed),
+        // 入力の行をそのまま比べたり書き込んだりする演算子は、すべての列を使う
+        plan => {
+            for child in plan.children_mut() {
+                prune_columns(catalog, child, None);
+            }
+        }
+    }
+}
+
+fn collect(needed: &mut Option<Vec<usize>>, exprs: &mut dyn Iterator<Item = &Expr>) {
+    if let Some(needed) = needed {
+        for expr in exprs {
+            expr.collect_columns(needed);
+        }
+    }
+}
+
+// テーブルの走査で読む列。すべての列を使うなら None
+fn scan_columns(catalog: &Catalog, table: &str, needed: Option<Vec<usize>>) -> Option<Vec<usize>> {
+    let width = catalog.table(table).map_or(0, |t| t.columns.len());
+    let mut needed = needed?;
+    needed.sort_unstable();
+    needed.dedup();
+    (needed.len() < width).then_some(needed)
+}
+
+// 結合の出力で使う列を左右の入力で使う列に分ける。出力の列は左の列、右の列の順に並ぶ
+fn prune_join(
+    catalog: &Catalog,
+    left: &mut PlanNode,
+    right: &mut PlanNode,
+    mut needed: Option<Vec<usize>>,
+    predicate: &Option<Expr>,
+    left_keys: &[Expr],
+    right_keys: &[Expr],
+) {
+    collect(&mut needed, &mut predicate.iter());
+    let (mut to_left, mut to_right) = match (needed, left.columns(catalog)) {
+        (Some(needed), Ok(columns)) => {
+            let width = columns.len();
+            let (l, r) = needed.into_iter().partition::<Vec<_>, _>(|&c| c < width);
+            (Some(l), Some(r.into_iter().map(|c| c - width).collect()))
+        }
+        _ => (None, None),
+    };
+    collect(&mut to_left, &mut left_keys.iter());
+    collect(&mut to_right, &mut right_keys.iter());
+    prune_columns(catalog, left, to_left);
+    prune_columns(catalog, right, to_right);
+}
+
+// テーブルの走査とその絞り込みを、行の復元と絞り込みをワーカーで評価する Gather に置き換える
+fn parallelize(plan: PlanNode, workers: usize) -> PlanNode {
+    match plan {
+        PlanNode::SeqScan { table, needed } => PlanNode::Gather {
+            table,
+            predicate: None,
+            workers,
+            needed,
+        },
+        PlanNode::Filter { input, predicate } => match *input {
+            PlanNode::SeqScan { table, needed } => PlanNode::Gather {
+                table,
+                predicate: Some(predicate),
+                workers,
+                needed,
+            },
+            input => PlanNode::Filter {
+                input: Box::new(input),
+                predicate,
+            },
+        },
+        plan => plan,
+    }
+}
+
+// Gather の上のハッシュ集約を、ワーカーで途中まで集約する ParallelAggregate に置き換える。
+// まとめたグループはメモリに置くので、グループが work_mem に収まる見積もりのときだけ
+fn parallel_aggregates(model: &CostModel, plan: &mut PlanNode) {
+    for child in plan.children_mut() {
+        parallel_aggregates(model, child);
+    }
+    let PlanNode::HashAggregate {
+        input, aggregates, ..
+    } = plan
+    else {
+        return;
+    };
+    if !matches!(**input, PlanNode::Gather { .. })
+        || !aggregates.iter().all(AggregateCall::mergeable)
+        || !model.fits_in_memory(plan)
+    {
+        return;
+    }
+    let PlanNode::HashAggregate {
+        input,
+        group_by,
+        aggregates,
+    } = std::mem::replace(plan, PlanNode::Values { rows: vec![] })
+    else {
+        unreachable!();
+    };
+    *plan = PlanNode::ParallelAggregate {
+        input,
+        group_by,
+        aggregates,
+    };
+}
+
+// 列を参照しない式を計画の時点で評価する
+fn eval_constant(
+    planner: &Planner,
+    expr: &ast::Expr,
+    clause: &'static str,
+) -> Result<Value, Error> {
+    let mut bound = bind_expr(planner, expr, &Scope::default(), clause)?;
+    if bound.has_params() {
+        planner.peeked.set(true);
+        bound.bind_params(planner.params)?;
+    }
+    Ok(bound.eval(&vec![])?)
+}
+
+// LIMIT や OFFSET の行数。NULL なら制限しない
+fn eval_count(
+    planner: &Planner,
+    expr: &ast::Expr,
+    clause: &'static str,
+) -> Result<Option<usize>, Error> {
+    if let ast::Expr::Parameter(n) = expr {
+        planner.infer_param(*n, Some(DataType::Integer));
+    }
+    match eval_constant(planner, expr, clause)? {
+        Value::Null => Ok(None),
+        Value::Integer(n) if n < 0 => Err(Error::NegativeCount(clause)),
+        Value::Integer(n) => Ok(Some(n as usize)),
+        value => Err(Error::NotInteger(clause, value.type_name())),
+    }
+}
+
+// ORDER BY の項目が出力の列の位置か名前なら、その列の位置
+fn output_column(expr: &ast::Expr, columns: &[String]) -> Result<Option<usize>, Error> {
+    match expr {
+        ast::Expr::Literal(Literal::Integer(n)) => {
+            if *n < 1 || *n as usize > columns.len() {
+                return Err(Error::InvalidPosition(*n));
+            }
+            Ok(Some(*n as usize - 1))
+        }
+        ast::Expr::Column { table: None, name } => {
+            let mut found = (0..columns.len()).filter(|&i| &columns[i] == name);
+            let Some(i) = found.next() else {
+                return Ok(None);
+            };
+            if found.next().is_some() {
+                return Err(Error::AmbiguousColumn(name.clone()));
+            }
+            Ok(Some(i))
+        }
+        _ => Ok(None),
+    }
+}
+
+// 別名のない選択項目の列名
+fn column_name(expr: &ast::Expr) -> String {
+    match expr {
+        ast::Expr::Column { name, .. } => name.clone(),
+        ast::Expr::Function { name, .. } => name.clone(),
+        ast::Expr::CountStar => "count".to_string(),
+        ast::Expr::Window { func, .. } => column_name(func),
+        _ => "?column?".to_string(),
+    }
+}
+
+fn bind_expr(
+    planner: &Planner,
+    expr: &ast::Expr,
+    scope: &Scope,
+    clause: &'static str,
+) -> Result<Expr, Error> {
+    let expr = Binder::new(planner, scope, clause).bind(expr)?;
+    // 条件に書いた MATCH ... AGAINST は関連度が正の行を選ぶ
+    Ok(match clause {
+        "WHERE" | "JOIN conditions" => match_predicate(expr),
+        _ => expr,
+    })
+}
+
+fn match_predicate(expr: Expr) -> Expr {
+    match expr {
+        Expr::Function {
+            func: Function::Match,
+            ..
+        } => Expr::binary(BinaryOp::Gt, expr, Expr::Literal(Value::Real(0.0))),
+        Expr::Binary {
+            op: op @ (BinaryOp::And | BinaryOp::Or),
+            left,
+            right,
+        } => Expr::Binary {
+            op,
+            left: Box::new(match_predicate(*left)),
+            right: Box::new(match_predicate(*right)),
+        },
+        Expr::Unary {
+            op: UnaryOp::Not,
+            expr,
+        } => Expr::Unary {
+            op: UnaryOp::Not,
+            expr: Box::new(match_predicate(*expr)),
+        },
+        expr => expr,
+    }
+}
+
+// MATCH ... AGAINST の条件なら、問い合わせと列
+fn match_condition(expr: &Expr) -> Option<(&Expr, &[Expr])> {
+    let Expr::Binary {
+        op: BinaryOp::Gt,
+        left,
+        right,
+    } = expr
+    else {
+        return None;
+    };
+    let Expr::Function {
+        func: Function::Match,
+        args,
+    } = &**left
+    else {
+        return None;
+    };
+    (**right == Expr::Literal(Value::Real(0.0))).then(|| (&args[0], &args[1..]))
+}
+
+// 式の照合順。テーブルの列をそのまま参照する式だけが BINARY でない照合順を持つ
+fn collation_of(expr: &ast::Expr, scope: &Scope) -> Collation {
+    match expr {
+        ast::Expr::Column { table, name } => scope
+            .resolve(table.as_deref(), name)
+            .map_or(Collation::Binary, |i| scope.columns[i].collation),
+        _ => Collation::Binary,
+    }
+}
+
+// 比べる値のどれかが BINARY でない照合順を持てば、その照合順
+fn comparison_collation<'e>(
+    exprs: impl IntoIterator<Item = &'e ast::Expr>,
+    scope: &Scope,
+) -> Collation {
+    exprs
+        .into_iter()
+        .map(|expr| collation_of(expr, scope))
+        .find(|&c| c != Collation::Binary)
+        .unwrap_or(Collation::Binary)
+}
+
+fn is_volatile(expr: &Expr) -> bool {
+    let volatile = match expr {
+        Expr::Function { func, .. } => func.is_volatile(),
+        Expr::UserFunction { func, .. } => func.is_volatile(),
+        _ => false,
+    };
+    volatile || expr.children().into_iter().any(is_volatile)
+}
+
+// インデックスのキーを、テーブルの行に対する式にしたもの
+fn index_keys(table: &Table, index: &Index) -> Vec<Expr> {
+    match &index.expression {
+        Some(expr) => vec![expr.clone()],
+        None => index
+            .columns
+            .iter()
+            .map(|&c| collate(Expr::column(c), table.columns[c].collation))
+            .collect(),
+    }
+}
+
+// expr を照合順のキーにする式
+fn collate(expr: Expr, collation: Collation) -> Expr {
+    if collation == Collation::Binary {
+        return expr;
+    }
+    Expr::Function {
+        func: Function::CollationKey,
+        args: vec![
+            expr,
+            Expr::Literal(Value::Text(collation.name().to_string())),
+        ],
+    }
+}
+
+// 集約する問い合わせで、GROUP BY の式と集約関数を集約の出力の列に置き換える
+// GROUPING SETS の式と GROUPING の引数の数の上限。集合の番号と GROUPING の結果は 1 つにつき 1 ビットを使う
+const MAX_GROUPING_KEYS: usize = 62;
+
+struct Grouping {
+    // 入力の行に対して解決した GROUP BY の式
+    keys: Vec<Expr>,
+    // GROUPING SETS なら、集合ごとの keys の添字。集約の出力ではキーのあとに集合の番号の列が入る
+    sets: Option<Vec<Vec<usize>>>,
+    aggregates: Vec<AggregateCall>,
+}
+
+impl Grouping {
+    // 集約関数の結果の列。同じ呼び出しは 1 つにまとめる
+    fn aggregate(&mut self, call: AggregateCall) -> Expr {
+        let i = match self.aggregates.iter().position(|c| *c == call) {
+            Some(i) => i,
+            None => {
+                self.aggregates.push(call);
+                self.aggregates.len() - 1
+            }
+        };
+        Expr::column(self.keys.len() + self.sets.is_some() as usize + i)
+    }
+}
+
+// 集約のあとで計算するウィンドウ関数の呼び出し
+struct BoundWindow {
+    partition_by: Vec<Expr>,
+    order_by: Vec<SortKey>,
+    call: WindowCall,
+}
+
+// ウィンドウ関数の結果は、計画を組み立てるまで位置が決まらないのでこの先の列として仮に置く
+const WINDOW_COLUMN: usize = usize::MAX / 2;
+// unnest の結果も同じように、ここから WINDOW_COLUMN の手前までの列として仮に置く
+const SET_COLUMN: usize = usize::MAX / 4;
+
+struct Binder<'s> {
+    planner: &'s Planner<'s>,
+    scope: &'s Scope,
+    grouping: Option<Grouping>,
+    // ウィンドウ関数を書ける場所なら Some
+    windows: Option<Vec<BoundWindow>>,
+    in_window: bool,
+    // unnest を書ける場所なら Some。要素を取り出す配列の式を呼び出した順に並べる
+    sets: Option<Vec<Expr>>,
+    in_set: bool,
+    // 集約関数を書けない場所の名前。エラーメッセージに使う
+    clause: &'static str,
+}
+
+impl<'s> Binder<'s> {
+    fn new(planner: &'s Planner<'s>, scope: &'s Scope, clause: &'static str) -> Self {
+        Self {
+            planner,
+            scope,
+            grouping: None,
+            windows: None,
+            in_window: false,
+            sets: None,
+            in_set: false,
+            clause,
+        }
+    }
+
+    fn bind(&mut self, expr: &ast::Expr) -> Result<Expr, Error> {
+        if let Some(grouping) = &self.grouping {
+            if !matches!(expr, ast::Expr::Column { .. }) && !contains_aggregate(expr) {
+                // 束縛できない式のエラーはこのあとで返す
+                if let Ok(bound) = bind_expr(self.planner, expr, self.scope, self.clause) {
+                    if let Some(i) = grouping.keys.iter().position(|key| *key == bound) {
+                        return Ok(Expr::column(i));
+                    }
+                }
+            }
+        }
+        let bound = match expr {
+            ast::Expr::Column {
+                table: Some(table),
+                name,
+            } if !self.scope.has_table(table) => match self.planner.trigger_value(table, name) {
+                Some(value) => Expr::Literal(value?),
+                None => self.bind_column(self.scope.resolve(Some(table), name)?)?,
+            },
+            ast::Expr::Column { table, name } => {
+                self.bind_column(self.scope.resolve(table.as_deref(), name)?)?
+            }
+            ast::Expr::Parameter(n) => match self.planner.params.get(*n - 1) {
+                Some(value) if self.planner.generic.get() => Expr::Parameter {
+                    index: *n,
+                    ty: value.data_type(),
+                },
+                Some(value) => Expr::Literal(value.clone()),
+                None => return Err(Error::ParameterNotFound(*n)),
+            },
+            ast::Expr::Literal(literal) => Expr::Literal(match literal {
+                // INTEGER に収まらない整数は BIGINT
+                Literal::Integer(n) if i32::try_from(*n).is_err() => Value::BigInt(*n),
+                Literal::Integer(n) => Value::Integer(*n),
+                Literal::String(s) => Value::Text(s.clone()),
+                Literal::Boolean(b) => Value::Boolean(*b),
+                Literal::Null => Value::Null,
+                Literal::Float(f) => Value::Real(*f),
+                Literal::Decimal(d) => Value::Decimal(*d),
+                Literal::Date(d) => Value::Date(*d),
+                Literal::Time(t) => Value::Time(*t),
+                Literal::Timestamp(t) => Value::Timestamp(*t),
+                Literal::Uuid(u) => Value::Uuid(*u),
+                Literal::Interval(i) => Value::Interval(*i),
+                Literal::Point(p) => Value::Point(*p),
+                Literal::Box(b) => Value::Box(Box::new(*b)),
+            }),
+            // BINARY でない照合順の列との比較は、両辺を照合順のキーにして比べる
+            ast::Expr::Binary { op, left, right } => {
+                match op {
+                    BinaryOp::And | BinaryOp::Or => {
+                        self.infer_params(&[left, right], Some(DataType::Boolean))
+                    }
+                    BinaryOp::Concat
+                    | BinaryOp::JsonGet
+                    | BinaryOp::JsonGetText
+                    | BinaryOp::Overlaps
+                    | BinaryOp::Contains
+                    | BinaryOp::ContainedBy
+                    | BinaryOp::Distance => {}
+                    _ => self.infer_params(&[left, right], None),
+                }
+                let collation = match op {
+                    BinaryOp::Eq
+                    | BinaryOp::NotEq
+                    | BinaryOp::Lt
+                    | BinaryOp::LtEq
+                    | BinaryOp::Gt
+                    | BinaryOp::GtEq
+                    | BinaryOp::IsDistinctFrom
+                    | BinaryOp::IsNotDistinctFrom => {
+                        comparison_collation([&**left, &**right], self.scope)
+                    }
+                    _ => Collation::Binary,
+                };
+                Expr::binary(
+                    *op,
+                    collate(self.bind(left)?, collation),
+                    collate(self.bind(right)?, collation),
+                )
+            }
+            ast::Expr::Unary { op, expr } => Expr::Unary {
+                op: *op,
+                expr: Box::new(self.bind(expr)?),
+            },
+            ast::Expr::Function {
+                name,
+                args,
+                distinct,
+            } => {
+                if name == "unnest" && !*distinct {
+                    if let [arg] = args.as_slice() {
+                        return self.bind_set(arg);
+                    }
+                }
+                if name == "grouping" && !*distinct {
+                    return self.bind_grouping(args);
+                }
+                if let Some(func) = AggregateFunction::lookup(name) {
+                    // APPROX_PERCENTILE の 2 つ目の引数は定数の分位
+                    let (arg, percentile) = match (func, args.as_slice()) {
+                        (AggregateFunction::ApproxPercentile, [arg, percentile]) => {
+                            let value = eval_constant(self.planner, percentile, self.clause)?;
+                            match value.as_f64() {
+                                Some(p) if (0.0..=1.0).contains(&p) => (arg, Some(p)),
+                                _ => return Err(Error::Percentile(value.to_string())),
+                            }
+                        }
+                        (AggregateFunction::ApproxPercentile, _) => {
+                            return Err(Error::FunctionNotFound(name.clone()))
+                        }
+                        (_, [arg]) => (arg, None),
+                        _ => return Err(Error::FunctionNotFound(name.clone())),
+                    };
+                    return self.bind_aggregate(func, Some(arg), *distinct, percentile);
+                }
+                let builtin = Function::lookup(name).filter(|f| f.accepts(args.len()));
+                let user = || {
+                    self.planner
+                        .catalog
+                        .function(name)
+                        .filter(|f| f.arg_types().len() == args.len())
+                };
+                if *distinct || (builtin.is_none() && user().is_none()) {
+                    return Err(Error::FunctionNotFound(name.clone()));
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| self.bind(arg))
+                    .collect::<Result<_, _>>()?;
+                let expr = match builtin {
+                    Some(func) => Expr::Function { func, args },
+                    None => Expr::UserFunction {
+                        func: user().unwrap().clone(),
+                        args,
+                    },
+                };
+                if is_volatile(&expr) {
+                    self.planner.volatile.set(true);
+                }
+                expr
+            }
+            ast::Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: Box::new(self.bind(expr)?),
+                negated: *negated,
+            },
+            ast::Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let mut operands = vec![&**expr];
+                operands.extend(list);
+                self.infer_params(&operands, None);
+                let collation =
+                    comparison_collation(std::iter::once(&**expr).chain(list), self.scope);
+                Expr::InList {
+                    expr: Box::new(collate(self.bind(expr)?, collation)),
+                    list: list
+                        .iter()
+                        .map(|item| Ok(collate(self.bind(item)?, collation)))
+                        .collect::<Result<_, Error>>()?,
+                    negated: *negated,
+                }
+            }
+            // x BETWEEN a AND b は a <= x AND x <= b に置き換える
+            ast::Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                self.infer_params(&[expr, low, high], None);
+                let collation = comparison_collation([&**expr, &**low, &**high], self.scope);
+                let expr = collate(self.bind(expr)?, collation);
+                let between = Expr::binary(
+                    BinaryOp::And,
+                    Expr::binary(
+                        BinaryOp::LtEq,
+                        collate(self.bind(low)?, collation),
+                        expr.clone(),
+                    ),
+                    Expr::binary(BinaryOp::LtEq, expr, collate(self.bind(high)?, collation)),
+                );
+                if *negated {
+                    Expr::Unary {
+                        op: UnaryOp::Not,
+                        expr: Box::new(between),
+                    }
+                } else {
+                    between
+                }
+            }
+            ast::Expr::CountStar => {
+                return self.bind_aggregate(AggregateFunction::Count, None, false, None)
+            }
+            ast::Expr::Window { func, spec } => return self.bind_window(func, spec),
+            ast::Expr::Subquery(query) => {
+                let Some(subquery) = self
+                    .scope
+                    .subqueries
+                    .iter()
+                    .find(|s| std::ptr::eq(s.query, &**query))
+                else {
+                    return Err(Error::Unsupported("subquery"));
+                };
+                let (column, count) = (subquery.column, subquery.count);
+                let column = self.bind_column(column)?;
+                if count {
+                    Expr::Function {
+                        func: Function::Coalesce,
+                        args: vec![column, Expr::Literal(Value::Integer(0))],
+                    }
+                } else {
+                    column
+                }
+            }
+            ast::Expr::InSubquery { .. } | ast::Expr::Exists { .. } => {
+                return Err(Error::Unsupported("subquery"))
+            }
+        };
+        Ok(bound)
+    }
+
+    // operands を比べたり計算したりするなら、その中の $n を ty か、型のわかる列の型とする
+    fn infer_params(&self, operands: &[&ast::Expr], ty: Option<DataType>) {
+        let ty = ty.or_else(|| {
+            operands.iter().find_map(|operand| match operand {
+                ast::Expr::Column { table, name } => {
+                    let i = self.scope.resolve(table.as_deref(), name).ok()?;
+                    self.scope.columns[i].ty.clone()
+                }
+                _ => None,
+            })
+        });
+        for operand in operands {
+            if let ast::Expr::Parameter(n) = operand {
+                self.planner.infer_param(*n, ty.clone());
+            }
+        }
+    }
+
+    // 入力の行の列を参照する式。集約するならその列でグループ分けしていなければならない。
+    // 照合順のキーでまとめた列は、グループの中でいちばん小さい元の値にする
+    fn bind_column(&mut self, index: usize) -> Result<Expr, Error> {
+        let Some(grouping) = &mut self.grouping else {
+            return Ok(Expr::column(index));
+        };
+        if let Some(i) = grouping
+            .keys
+            .iter()
+            .position(|key| *key == Expr::column(index))
+        {
+            return Ok(Expr::column(i));
+        }
+        let column = &self.scope.columns[index];
+        if grouping
+            .keys
+            .contains(&collate(Expr::column(index), column.collation))
+        {
+            return Ok(grouping.aggregate(AggregateCall {
+                func: AggregateFunction::Min,
+                arg: Some(Expr::column(index)),
+                distinct: false,
+                percentile: None,
+            }));
+        }
+        Err(Error::NotGrouped(format!(
+            "{}.{}",
+            column.table, column.name
+        )))
+    }
+
+    fn bind_set(&mut self, arg: &ast::Expr) -> Result<Expr, Error> {
+        if self.in_set {
+            return Err(Error::NestedSetReturning);
+        }
+        if self.sets.is_none() || self.in_window {
+            return Err(Error::SetReturningNotAllowed(self.clause));
+        }
+        self.in_set = true;
+        let bound = self.bind(arg);
+        self.in_set = false;
+        let sets = self.sets.as_mut().unwrap();
+        sets.push(bound?);
+        Ok(Expr::column(SET_COLUMN + sets.len() - 1))
+    }
+
+    fn bind_window(&mut self, func: &ast::Expr, spec: &ast::WindowSpec) -> Result<Expr, Error> {
+        if self.in_window {
+            return Err(Error::NestedWindow);
+        }
+        if self.windows.is_none() {
+            return Err(Error::WindowNotAllowed(self.clause));
+        }
+        let (name, args) = match func {
+            ast::Expr::Function { distinct: true, .. } => {
+                return Err(Error::Unsupported("DISTINCT in window function"))
+            }
+            ast::Expr::Function { name, args, .. } => (name.as_str(), args.as_slice()),
+            _ => ("count", &[][..]),
+        };
+        let func = WindowFunction::lookup(name)
+            .filter(|f| f.accepts(args.len()))
+            .ok_or_else(|| Error::FunctionNotFound(name.to_string()))?;
+        // ORDER BY があれば先頭から同じ値の行まで、なければパーティション全体
+        let frame = spec.frame.unwrap_or(WindowFrame {
+            units: FrameUnits::Range,
+            start: FrameBound::UnboundedPreceding,
+            end: FrameBound::CurrentRow,
+        });
+        if frame.units == FrameUnits::Range
+            && [frame.start, frame.end]
+                .iter()
+                .any(|b| matches!(b, FrameBound::Preceding(_) | FrameBound::Following(_)))
+        {
+            return Err(Error::Unsupported("RANGE with offset"));
+        }
+        self.in_window = true;
+        let bound = self.bind_window_spec(func, args, spec, frame);
+        self.in_window = false;
+        let windows = self.windows.as_mut().unwrap();
+        windows.push(bound?);
+        Ok(Expr::column(WINDOW_COLUMN + windows.len() - 1))
+    }
+
+    fn bind_window_spec(
+        &mut self,
+        func: WindowFunction,
+        args: &[ast::Expr],
+        spec: &ast::WindowSpec,
+        frame: WindowFrame,
+    ) -> Result<BoundWindow, Error> {
+        let args = args
+            .iter()
+            .map(|arg| self.bind(arg))
+            .collect::<Result<_, _>>()?;
+        let partition_by = spec
+            .partition_by
+            .iter()
+            .map(|expr| self.bind(expr))
+            .collect::<Result<_, _>>()?;
+        let order_by = spec
+            .order_by
+            .iter()
+            .map(|item| {
+                Ok(SortKey {
+                    expr: self.bind(&item.expr)?,
+                    asc: item.asc,
+                    nulls_first: item.nulls_first.unwrap_or(!item.asc),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(BoundWindow {
+            partition_by,
+            order_by,
+            call: WindowCall { func, args, frame },
+        })
+    }
+
+    // GROUPING(args)。引数の式ごとに、今の行の集合にその式がなければ 1 のビットを、先頭の引数を上位にして並べた数
+    fn bind_grouping(&mut self, args: &[ast::Expr]) -> Result<Expr, Error> {
+        let Some(grouping) = &self.grouping else {
+            return Err(Error::AggregateNotAllowed(self.clause));
+        };
+        if args.len() > MAX_GROUPING_KEYS {
+            return Err(Error::Unsupported("too many GROUPING arguments"));
+        }
+        let mut positions = vec![];
+        for arg in args {
+            let arg = bind_expr(self.planner, arg, self.scope, self.clause)?;
+            match grouping.keys.iter().position(|key| *key == arg) {
+                Some(i) => positions.push(i),
+                None => return Err(Error::GroupingArgument),
+            }
+        }
+        let integer = |n: i64| Expr::Literal(Value::Integer(n));
+        if grouping.sets.is_none() {
+            return Ok(integer(0));
+        }
+        let id = Expr::column(grouping.keys.len());
+        let n = grouping.keys.len();
+        // 番号を 2 で割り続けて引数の式のビットを取り出し、2 倍しながら足していく
+        let mut result = None;
+        for i in positions {
+            let bit = Expr::binary(
+                BinaryOp::Modulo,
+                match n - 1 - i {
+                    0 => id.clone(),
+                    shift => Expr::binary(BinaryOp::Divide, id.clone(), integer(1 << shift)),
+                },
+                integer(2),
+            );
+            result = Some(match result {
+                None => bit,
+                Some(high) => Expr::binary(
+                    BinaryOp::Plus,
+                    Expr::binary(BinaryOp::Multiply, high, integer(2)),
+                    bit,
+                ),
+            });
+        }
+        Ok(result.unwrap_or_else(|| integer(0)))
+    }
+
+    fn bind_aggregate(
+        &mut self,
+        func: AggregateFunction,
+        arg: Option<&ast::Expr>,
+        distinct: bool,
+        percentile: Option<f64>,
+    ) -> Result<Expr, Error> {
+        let Some(grouping) = &mut self.grouping else {
+            return Err(Error::AggregateNotAllowed(self.clause));
+        };
+        if arg.is_some_and(contains_aggregate) {
+            return Err(Error::NestedAggregate);
+        }
+        let arg = arg
+            .map(|arg| bind_expr(self.planner, arg, self.scope, self.clause))
+            .transpose()?;
+        Ok(grouping.aggregate(AggregateCall {
+            func,
+            arg,
+            distinct,
+            percentile,
+        }))
+    }
+}
+
+fn contains_aggregate(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Function { name, args, .. } => {
+            AggregateFunction::lookup(name).is_some()
+                || name == "grouping"
+                || args.iter().any(contains_aggregate)
+        }
+        ast::Expr::CountStar => true,
+        ast::Expr::Binary { left, right, .. } => {
+            contains_aggregate(left) || contains_aggregate(right)
+        }
+        ast::Expr::Unary { expr, .. }
+        | ast::Expr::IsNull { expr, .. }
+        | ast::Expr::InSubquery { expr, .. } => contains_aggregate(expr),
+        ast::Expr::InList { expr, list, .. } => {
+            contains_aggregate(expr) || list.iter().any(contains_aggregate)
+        }
+        ast::Expr::Between {
+            expr, low, high, ..
+        } => contains_aggregate(expr) || contains_aggregate(low) || contains_aggregate(high),
+        ast::Expr::Window { func, spec } => {
+            let args = match &**func {
+                ast::Expr::Function { args, .. } => args.as_slice(),
+                _ => &[],
+            };
+            args.iter()
+                .chain(&spec.partition_by)
+                .chain(spec.order_by.iter().map(|item| &item.expr))
+                .any(contains_aggregate)
+        }
+        ast::Expr::Column { .. }
+        | ast::Expr::Literal(_)
+        | ast::Expr::Parameter(_)
+        | ast::Expr::Exists { .. }
+        | ast::Expr::Subquery(_) => false,
+    }
+}
+
+// 式の中のスカラー副問い合わせ。EXISTS や IN の副問い合わせの中は見ない
+fn scalar_subqueries<'q>(expr: &'q ast::Expr, queries: &mut Vec<&'q ast::Query>) {
+    match expr {
+        ast::Expr::Subquery(query) => queries.push(query),
+        ast::Expr::Binary { left, right, .. } => {
+            scalar_subqueries(left, queries);
+            scalar_subqueries(right, queries);
+        }
+        ast::Expr::Unary { expr, .. }
+        | ast::Expr::IsNull { expr, .. }
+        | ast::Expr::InSubquery { expr, .. } => scalar_subqueries(expr, queries),
+        ast::Expr::Function { args, .. } => {
+            for arg in args {
+                scalar_subqueries(arg, queries);
+            }
+        }
+        ast::Expr::InList { expr, list, .. } => {
+            scalar_subqueries(expr, queries);
+            for item in list {
+                scalar_subqueries(item, queries);
+            }
+        }
+        ast::Expr::Between {
+            expr, low, high, ..
+        } => {
+            scalar_subqueries(expr, queries);
+            scalar_subqueries(low, queries);
+            scalar_subqueries(high, queries);
+        }
+        ast::Expr::Column { .. }
+        | ast::Expr::Literal(_)
+        | ast::Expr::Parameter(_)
+        | ast::Expr::CountStar
+        | ast::Expr::Window { .. }
+        | ast::Expr::Exists { .. } => {}
+    }
+}
+
+// expr IN (副問い合わせ) の、副問い合わせの行 item との条件。
+// NOT IN は比較が NULL になる行があっても外側の行を返さない
+fn in_predicate(expr: Expr, item: Expr, negated: bool) -> Expr {
+    let eq = Expr::binary(BinaryOp::Eq, expr, item);
+    if negated {
+        Expr::Function {
+            func: Function::Coalesce,
+            args: vec![eq, Expr::Literal(Value::Boolean(true))],
+        }
+    } else {
+        eq
+    }
+}
+
+fn semi_join(negated: bool) -> JoinType {
+    if negated {
+        JoinType::Anti
+    } else {
+        JoinType::Semi
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::buffer::BufferPoolManager;
+    use crate::catalog::Column;
+    use crate::executor::expr::ScalarFunction;
+    use crate::executor::tests::{int, setup, text};
+    use crate::executor::{compare_values, execute, ExecContext};
+    use crate::sql;
+
+    fn plan_sql(catalog: &Catalog, sql: &str) -> Result<PlanNode, Error> {
+        Planner::new(catalog).plan_statement(&sql::parse(sql).unwrap().remove(0))
+    }
+
+    // DML の文を計画して実行し、処理した行数を返す
+    fn run_dml(bufmgr: &mut BufferPoolManager, catalog: &Catalog, sql: &str) -> Result<i64, Error> {
+        let plan = plan_sql(catalog, sql)?;
+        let mut ctx = ExecContext::new(bufmgr, catalog);
+        match execute(&plan, &mut ctx)?.as_slice() {
+            [row] => match row.as_slice() {
+                [Value::Integer(n)] => Ok(*n),
+                _ => panic!("unexpected count"),
+            },
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[test]
+    fn test_plan_select() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), Value::Null],
+            vec![int(3), text("z")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let plan = plan_sql(
+            &catalog,
+            "SELECT a * 10 AS ten, upper(b), u.a, 'c' FROM t AS u WHERE a BETWEEN 2 AND 3",
+        )
+        .unwrap();
+        assert_eq!(
+            plan.columns(&catalog).unwrap(),
+            vec!["ten", "upper", "a", "?column?"]
+        );
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![int(20), Value::Null, int(2), text("c")],
+                vec![int(30), text("Z"), int(3), text("c")],
+            ]
+        );
+
+        let plan = plan_sql(&catalog, "SELECT * FROM t WHERE b IS NULL").unwrap();
+        assert_eq!(plan.columns(&catalog).unwrap(), vec!["a", "b"]);
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), vec![rows[1].clone()]);
+
+        let plan = plan_sql(&catalog, "SELECT 1 + 2").unwrap();
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), vec![vec![int(3)]]);
+
+        // b <> 'x' は b が NULL の行で UNKNOWN になり、その行を返さない
+        for (sql, expected) in [
+            ("SELECT a FROM t WHERE b <> 'x'", vec![vec![int(3)]]),
+            ("SELECT a FROM t WHERE NOT b = 'x'", vec![vec![int(3)]]),
+            (
+                "SELECT a FROM t WHERE b IS DISTINCT FROM 'x'",
+                vec![vec![int(2)], vec![int(3)]],
+            ),
+            (
+                "SELECT a FROM t WHERE b IS NOT DISTINCT FROM NULL",
+                vec![vec![int(2)]],
+            ),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(execute(&plan, &mut ctx).unwrap(), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_plan_simplify() {
+        let rows = vec![vec![int(1), text("x")], vec![int(5), text("y")]];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let filter = |plan: &PlanNode| {
+            let PlanNode::Projection { input, exprs, .. } = plan else {
+                panic!("not a projection");
+            };
+            (exprs.clone(), (**input).clone())
+        };
+
+        // 定数の部分を計算し、常に真の項を除き、定数を右に置く
+        let plan = plan_sql(
+            &catalog,
+            "SELECT a + (2 * 3) FROM t WHERE 2 + 1 < a AND 1 = 1 AND (b = 'y' OR FALSE)",
+        )
+        .unwrap();
+        let (exprs, input) = filter(&plan);
+        assert_eq!(
+            exprs,
+            vec![Expr::binary(
+                BinaryOp::Plus,
+                Expr::column(0),
+                Expr::Literal(int(6))
+            )]
+        );
+        let PlanNode::Filter { predicate, .. } = input else {
+            panic!("not a filter");
+        };
+        assert_eq!(
+            predicate,
+            Expr::binary(
+                BinaryOp::And,
+                Expr::binary(BinaryOp::Gt, Expr::column(0), Expr::Literal(int(3))),
+                Expr::binary(BinaryOp::Eq, Expr::column(1), Expr::Literal(text("y"))),
+            )
+        );
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(execute(&plan, &mut ctx).unwrap(), vec![vec![int(11)]]);
+
+        // どの行も満たさない条件ならテーブルを読まない
+        for sql in [
+            "SELECT a FROM t WHERE a > 0 AND 1 = 0",
+            "SELECT a FROM t WHERE NULL",
+            "SELECT l.a FROM t l JOIN t r ON l.a = r.a AND r.b = 'x' AND FALSE",
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            let (_, input) = filter(&plan);
+            assert!(
+                matches!(&input, PlanNode::Projection { input, .. } if matches!(**input, PlanNode::Values { .. })),
+                "{}",
+                sql
+            );
+            assert!(execute(&plan, &mut ctx).unwrap().is_empty());
+        }
+        // 外部結合の結合条件なら NULL で埋める側だけが空になる
+        let plan = plan_sql(&catalog, "SELECT l.a, r.a FROM t l LEFT JOIN t r ON 1 = 2").unwrap();
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(1), Value::Null], vec![int(5), Value::Null]]
+        );
+        // 評価でエラーになる定数は実行するときに報告する
+        let plan = plan_sql(&catalog, "SELECT a FROM t WHERE a = 1 / 0").unwrap();
+        assert!(execute(&plan, &mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_plan_join() {
+        let rows = vec![vec![int(1), text("x")], vec![int(2), text("y")]];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let plan = plan_sql(
+            &catalog,
+            "SELECT l.a, r.b FROM t l JOIN t r ON l.a + 1 = r.a",
+        )
+        .unwrap();
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(1), text("y")]]
+        );
+        let plan = plan_sql(&catalog, "SELECT * FROM t l, t r WHERE l.b = 'y'").unwrap();
+        assert_eq!(execute(&plan, &mut ctx).unwrap().len(), 2);
+        assert_eq!(plan.columns(&catalog).unwrap(), vec!["a", "b", "a", "b"]);
+
+        let plan = plan_sql(
+            &catalog,
+            "SELECT * FROM t l JOIN t r ON r.a = l.a + 1 AND l.b <> r.b",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::HashJoin {
+            right_keys,
+            predicate,
+            ..
+        } = &**input
+        else {
+            panic!("not a hash join");
+        };
+        assert_eq!(right_keys, &vec![Expr::column(0)]);
+        assert!(predicate.is_some());
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(1), text("x"), int(2), text("y")]]
+        );
+    }
+
+    #[test]
+    fn test_plan_hints() {
+        let rows = (0..30).map(|i| vec![int(i), text("x")]).collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let join = |sql: &str| {
+            let PlanNode::Projection { input, .. } = plan_sql(&catalog, sql).unwrap() else {
+                panic!("not a projection");
+            };
+            *input
+        };
+        let sql = "SELECT * FROM t l JOIN t r ON l.a = r.a";
+        let hinted = |hints: &str| sql.replacen("SELECT", &format!("SELECT /*+ {} */", hints), 1);
+        assert!(matches!(join(sql), PlanNode::HashJoin { .. }));
+        assert!(matches!(
+            join(&hinted("MergeJoin(l r)")),
+            PlanNode::MergeJoin { .. }
+        ));
+        assert!(matches!(
+            join(&hinted("NoHashJoin(l r) NoMergeJoin(r l)")),
+            PlanNode::NestedLoopJoin { .. }
+        ));
+        // 両側のテーブルが一致しない結合には効かない
+        assert!(matches!(
+            join(&hinted("MergeJoin(l s)")),
+            PlanNode::HashJoin { .. }
+        ));
+
+        let plan = plan_sql(&catalog, &hinted("NestLoop(l r)")).unwrap();
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(execute(&plan, &mut ctx).unwrap().len(), 30);
+    }
+
+    #[test]
+    fn test_plan_push_down() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), text("y")],
+            vec![int(3), text("x")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let is_filtered_scan = |plan: &PlanNode| matches!(plan, PlanNode::Filter { input, .. } if matches!(**input, PlanNode::SeqScan { .. }));
+
+        // WHERE の両側を参照する項は結合条件になり、片側だけの項はその側の走査で絞り込む。
+        // 左は 1 行ほどと見積もるので入れ子ループ結合になる
+        let plan = plan_sql(
+            &catalog,
+            "SELECT l.a, r.b FROM t l, t r WHERE l.a = r.a AND l.b = 'x' AND r.a > 1",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::NestedLoopJoin {
+            left,
+            right,
+            predicate: Some(_),
+            ..
+        } = &**input
+        else {
+            panic!("not a nested loop join");
+        };
+        let PlanNode::Materialize { input: right } = &**right else {
+            panic!("not a materialize");
+        };
+        assert!(is_filtered_scan(left) && is_filtered_scan(right));
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(3), text("x")]]
+        );
+
+        // 外部結合では NULL で埋める側を参照する WHERE の項を押し下げない
+        let plan = plan_sql(
+            &catalog,
+            "SELECT l.a, r.a FROM t l LEFT JOIN t r ON l.a = r.a AND r.b = 'y' \
+             WHERE l.a < 3 AND r.a IS NULL",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::Filter { input, .. } = &**input else {
+            panic!("not a filter");
+        };
+        let PlanNode::NestedLoopJoin {
+            left,
+            right,
+            join_type: JoinType::Left,
+            ..
+        } = &**input
+        else {
+            panic!("not a nested loop join");
+        };
+        let PlanNode::Materialize { input: right } = &**right else {
+            panic!("not a materialize");
+        };
+        assert!(is_filtered_scan(left) && is_filtered_scan(right));
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(1), Value::Null]]
+        );
+
+        // 射影と集約を越えるのはグループのキーだけを参照する項
+        let plan = plan_sql(
+            &catalog,
+            "WITH s AS (SELECT b, count(*) AS n FROM t GROUP BY b) \
+             SELECT * FROM s WHERE b = 'x' AND n > 1",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::Projection { input, .. } = &**input else {
+            panic!("not a projection");
+        };
+        let PlanNode::Filter { input, .. } = &**input else {
+            panic!("not a filter");
+        };
+        let PlanNode::HashAggregate { input, .. } = &**input else {
+            panic!("not an aggregate");
+        };
+        assert!(is_filtered_scan(input));
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![text("x"), int(2)]]
+        );
+    }
+
+    #[test]
+    fn test_plan_index_join() {
+        let rows = (0..2000)
+            .map(|i| vec![int(i), text(&format!("x{}", i % 400))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_b", "t", &["b".to_string()], false)
+            .unwrap();
+        // 左の行が少なければ、右を全部読むよりインデックスを引く方が安い
+        let plan = plan_sql(
+            &catalog,
+            "SELECT l.a, r.a FROM t l JOIN t r ON l.a = r.a AND r.b = l.b WHERE l.b = 'x5'",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::IndexJoin {
+            index,
+            keys,
+            predicate,
+            ..
+        } = &**input
+        else {
+            panic!("not an index join");
+        };
+        assert_eq!(index, "t_b");
+        assert_eq!(keys, &vec![Expr::column(1)]);
+        assert!(predicate.is_some());
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            (5..2000)
+                .step_by(400)
+                .map(|i| vec![int(i), int(i)])
+                .collect::<Vec<_>>()
+        );
+
+        // 両側を全部読むならハッシュ結合の方が安い
+        let plan = plan_sql(
+            &catalog,
+            "SELECT l.a FROM t l JOIN t r ON r.b = l.b AND l.a = r.a",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        assert!(matches!(**input, PlanNode::HashJoin { .. }));
+        assert_eq!(execute(&plan, &mut ctx).unwrap().len(), 2000);
+    }
+
+    #[test]
+    fn test_plan_join_order() {
+        let rows = (0..30)
+            .map(|i| vec![int(i), text(if i % 3 == 0 { "x" } else { "y" })])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        // r が絞り込みで小さくなるので、m と r を先に結合する
+        let plan = plan_sql(
+            &catalog,
+            "SELECT l.a, m.b, r.a FROM t l, t m, t r WHERE l.a = m.a AND m.a = r.a AND r.b = 'x'",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::Projection { input, exprs, .. } = &**input else {
+            panic!("not a projection");
+        };
+        assert_eq!(exprs[0], Expr::column(4));
+        // m と r の結合は 1 行ほどと見積もるので、l とは入れ子ループで結合する
+        let PlanNode::NestedLoopJoin { left, right, .. } = &**input else {
+            panic!("not a nested loop join");
+        };
+        assert!(matches!(**right, PlanNode::SeqScan { .. }));
+        let PlanNode::HashJoin { right, .. } = &**left else {
+            panic!("not a hash join");
+        };
+        assert!(matches!(**right, PlanNode::Filter { .. }));
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut result = execute(&plan, &mut ctx).unwrap();
+        result.sort_by(|a, b| compare_values(a, b));
+        let expected = (0..30)
+            .step_by(3)
+            .map(|i| vec![int(i), text("x"), int(i)])
+            .collect::<Vec<_>>();
+        assert_eq!(result, expected);
+
+        // 結合の条件がない関係は最後に足し、見積もりが同じなら元の順序のまま
+        assert_eq!(
+            join_order(&[100.0, 100.0, 1.0], &[(vec![1, 2], 0.01)]),
+            vec![1, 2, 0]
+        );
+        assert_eq!(join_order(&[10.0; 3], &[]), vec![0, 1, 2]);
+        // 関係が多ければいちばん小さい関係から貪欲に足す
+        let rows = (0..10).map(|k| 10.0 + k as f64).rev().collect::<Vec<_>>();
+        let edges = (1..10).map(|k| (vec![k - 1, k], 0.1)).collect::<Vec<_>>();
+        let order = join_order(&rows, &edges);
+        assert_eq!(order, (0..10).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_plan_index_scan() {
+        let rows = (0..5000)
+            .map(|i| vec![int(i), text(&format!("r{}", i % 1000))])
+            .chain([vec![Value::Null, text("r1")]])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        catalog
+            .create_index(
+                &mut bufmgr,
+                "t_b_a",
+                "t",
+                &["b".to_string(), "a".to_string()],
+                false,
+            )
+            .unwrap();
+        let scan = |plan: &PlanNode| {
+            let PlanNode::Projection { input, .. } = plan else {
+                panic!("not a projection");
+            };
+            (**input).clone()
+        };
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut query = |sql| {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            (scan(&plan), execute(&plan, &mut ctx).unwrap())
+        };
+
+        let (plan, result) = query("SELECT a FROM t WHERE a >= 5 AND a < 8");
+        let PlanNode::IndexScan {
+            index,
+            range,
+            predicate: None,
+            index_only: true,
+            ..
+        } = plan
+        else {
+            panic!("not an index only scan");
+        };
+        assert_eq!(index, "t_a");
+        assert_eq!(
+            range.upper,
+            Some(ScanBound {
+                value: Expr::Literal(int(8)),
+                inclusive: false
+            })
+        );
+        assert_eq!(result, vec![vec![int(5)], vec![int(6)], vec![int(7)]]);
+
+        // 範囲の狭いインデックスを選び、残りの項は走査の中で絞り込む
+        let (plan, result) =
+            query("SELECT a, length(b) FROM t WHERE b = 'r1' AND a > 1500 AND a <> 3001");
+        let PlanNode::IndexScan {
+            index,
+            range,
+            predicate: Some(_),
+            ..
+        } = plan
+        else {
+            panic!("not an index scan");
+        };
+        assert_eq!(index, "t_b_a");
+        assert_eq!(range.prefix, vec![Expr::Literal(text("r1"))]);
+        assert_eq!(
+            result,
+            vec![vec![int(2001), int(2)], vec![int(4001), int(2)]]
+        );
+
+        // 範囲の広い比較ならテーブルを順に読む。範囲の外にある NULL は返さない
+        let (plan, result) = query("SELECT a FROM t WHERE 3 > a");
+        assert!(matches!(plan, PlanNode::Filter { .. }));
+        assert_eq!(result, vec![vec![int(0)], vec![int(1)], vec![int(2)]]);
+        let (_, result) = query("SELECT b, a FROM t WHERE a > 4997");
+        assert_eq!(
+            result,
+            vec![vec![text("r998"), int(4998)], vec![text("r999"), int(4999)]]
+        );
+        let (plan, result) = query("SELECT a FROM t WHERE b = 'r1' AND a IS NULL");
+        assert!(matches!(plan, PlanNode::IndexScan { .. }));
+        assert_eq!(result, vec![vec![Value::Null]]);
+
+        // 型の違う定数とはインデックスで比べない
+        let plan = plan_sql(&catalog, "SELECT a FROM t WHERE a = 'x'").unwrap();
+        assert!(matches!(scan(&plan), PlanNode::Filter { .. }));
+
+        let plan = plan_sql(&catalog, "DELETE FROM t WHERE a = 3").unwrap();
+        assert!(matches!(
+            &plan,
+            PlanNode::Delete { input, .. } if matches!(**input, PlanNode::IndexScan { .. })
+        ));
+        assert_eq!(
+            run_dml(&mut bufmgr, &catalog, "DELETE FROM t WHERE a = 3").unwrap(),
+            1
+        );
+        assert_eq!(
+            run_dml(&mut bufmgr, &catalog, "DELETE FROM t WHERE a = 3").unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_plan_costs() {
+        let rows = (0..5000)
+            .map(|i| vec![int(i), text(&format!("r{}", i % 100))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        let query = match sql::parse("SELECT b FROM t WHERE a = 3").unwrap().remove(0) {
+            Statement::Query(query) => query,
+            _ => unreachable!(),
+        };
+        let mut planner = Planner::new(&catalog);
+        let plan = planner.plan_query(&query).unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        assert!(matches!(**input, PlanNode::IndexScan { .. }));
+        // 離れたページを読むのが高ければテーブルを順に読む
+        planner.costs.random_page_cost = 100.0;
+        let plan = planner.plan_query(&query).unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        assert!(matches!(**input, PlanNode::Filter { .. }));
+
+        // 両側が結合キーの順に並んでいて、ハッシュ表がメモリに収まらなければマージ結合にする
+        let query = match sql::parse(
+            "WITH x AS (SELECT a FROM t ORDER BY a), y AS (SELECT a, b FROM t ORDER BY a) \
+             SELECT x.a, y.b FROM x JOIN y ON x.a = y.a",
+        )
+        .unwrap()
+        .remove(0)
+        {
+            Statement::Query(query) => query,
+            _ => unreachable!(),
+        };
+        let mut planner = Planner::new(&catalog);
+        let plan = planner.plan_query(&query).unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        assert!(matches!(**input, PlanNode::HashJoin { .. }));
+        planner.costs.work_mem = 0;
+        let plan = planner.plan_query(&query).unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::MergeJoin { left, right, .. } = &**input else {
+            panic!("not a merge join");
+        };
+        // WITH の問い合わせの並べ替えをそのまま使う
+        let sorted_once = |plan: &PlanNode| matches!(plan, PlanNode::Sort { input, .. } if matches!(**input, PlanNode::Projection { .. }));
+        assert!(sorted_once(left) && sorted_once(right));
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let result = execute(&plan, &mut ctx).unwrap();
+        assert_eq!(result.len(), 5000);
+        assert_eq!(result[7], vec![int(7), text("r7")]);
+    }
+
+    #[test]
+    fn test_plan_statistics() {
+        let rows = (0..5000)
+            .map(|i| vec![int(i % 2), text(&format!("r{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        let scan = |catalog: &Catalog, sql: &str| {
+            let query = match sql::parse(sql).unwrap().remove(0) {
+                Statement::Query(query) => query,
+                _ => unreachable!(),
+            };
+            let planner = Planner::new(catalog);
+            let plan = planner.plan_query(&query).unwrap();
+            let PlanNode::Projection { input, .. } = plan else {
+                panic!("not a projection");
+            };
+            let rows = planner.model().rows(&input);
+            (*input, rows)
+        };
+        let (plan, rows) = scan(&catalog, "SELECT b FROM t WHERE a = 1");
+        assert!(matches!(plan, PlanNode::IndexScan { .. }));
+        assert_eq!(rows, 25.0);
+
+        // 統計があれば、値の種類が 2 つしかない列の等号は半分の行を返すとみる
+        catalog.analyze(&mut bufmgr, None).unwrap();
+        let (plan, rows) = scan(&catalog, "SELECT b FROM t WHERE a = 1");
+        assert!(matches!(plan, PlanNode::Filter { .. }));
+        assert_eq!(rows, 2500.0);
+        // 最大値を超える値とは等しくならない
+        let (plan, rows) = scan(&catalog, "SELECT b FROM t WHERE a = 7");
+        assert!(matches!(plan, PlanNode::IndexScan { .. }));
+        assert_eq!(rows, 1.0);
+        let (_, rows) = scan(&catalog, "SELECT b FROM t WHERE b < 'r2'");
+        assert!((rows - 5000.0 * 1112.0 / 5000.0).abs() < 300.0, "{}", rows);
+        let (_, rows) = scan(&catalog, "SELECT a, count(*) FROM t GROUP BY a");
+        assert_eq!(rows, 2.0);
+    }
+
+    #[test]
+    fn test_plan_prune_columns() {
+        let rows = vec![vec![int(1), text("x")], vec![int(2), text("yy")]];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = plan_sql(&catalog, "SELECT a + 1 FROM t").unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        assert_eq!(
+            **input,
+            PlanNode::SeqScan {
+                table: "t".to_string(),
+                needed: Some(vec![0]),
+            }
+        );
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(2)], vec![int(3)]]
+        );
+
+        // 結合ではキーと条件の列も読む
+        let plan = plan_sql(
+            &catalog,
+            "SELECT length(r.b) FROM t l JOIN t r ON l.a = r.a WHERE l.b <> 'x'",
+        )
+        .unwrap();
+        let mut scans = vec![];
+        let mut stack = vec![&plan];
+        while let Some(plan) = stack.pop() {
+            match plan {
+                PlanNode::SeqScan { needed, .. } => scans.push(needed.clone()),
+                PlanNode::Projection { input, .. } | PlanNode::Filter { input, .. } => {
+                    stack.push(input)
+                }
+                PlanNode::HashJoin { left, right, .. } => stack.extend([&**right, &**left]),
+                plan => panic!("unexpected {:?}", plan),
+            }
+        }
+        assert_eq!(scans, vec![None, None]);
+        let plan = plan_sql(&catalog, "SELECT r.b FROM t l JOIN t r ON l.a = r.a").unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::HashJoin { left, right, .. } = &**input else {
+            panic!("not a hash join");
+        };
+        assert!(matches!(&**left, PlanNode::SeqScan { needed: Some(c), .. } if c == &[0]));
+        assert!(matches!(&**right, PlanNode::SeqScan { needed: None, .. }));
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![text("x")], vec![text("yy")]]
+        );
+
+        // 書き込む行はすべての列を読む
+        let plan = plan_sql(&catalog, "DELETE FROM t WHERE a = 1").unwrap();
+        let PlanNode::Delete { input, .. } = &plan else {
+            panic!("not a delete");
+        };
+        assert!(
+            matches!(&**input, PlanNode::Filter { input, .. } if matches!(**input, PlanNode::SeqScan { needed: None, .. }))
+        );
+    }
+
+    #[test]
+    fn test_plan_cache() {
+        let rows = (0..5000)
+            .map(|i| vec![int(i), text("x")])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        let mut cache = PlanCache::new();
+        let sql = "SELECT b FROM t WHERE a = 3";
+        let first = cache.plan(&Planner::new(&catalog), sql).unwrap();
+        let second = cache.plan(&Planner::new(&catalog), sql).unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+        // 計画の設定が違えば作り直す
+        let mut planner = Planner::new(&catalog);
+        planner.costs.cpu_tuple_cost = 1.0;
+        assert!(!Rc::ptr_eq(&first, &cache.plan(&planner, sql).unwrap()));
+
+        // インデックスを作ったら使えるように計画し直す
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        let plan = cache.plan(&Planner::new(&catalog), sql).unwrap();
+        let PlanNode::Projection { input, .. } = &*plan else {
+            panic!("not a projection");
+        };
+        assert!(matches!(**input, PlanNode::IndexScan { .. }));
+        catalog.analyze(&mut bufmgr, Some("t")).unwrap();
+        assert!(!Rc::ptr_eq(
+            &plan,
+            &cache.plan(&Planner::new(&catalog), sql).unwrap()
+        ));
+
+        // $n の計画は値によらず使い回し、実行するものには値が入っている
+        let sql = "SELECT b FROM t WHERE a = $1";
+        for (i, a) in [3, 4].into_iter().enumerate() {
+            let params = [int(a)];
+            let planner = Planner::new(&catalog).with_params(&params);
+            let plan = cache.plan(&planner, sql).unwrap();
+            assert_eq!(cache.hits(), i as u64 + 1);
+            let PlanNode::Projection { input, .. } = &*plan else {
+                panic!("not a projection");
+            };
+            let PlanNode::IndexScan { range, .. } = &**input else {
+                panic!("not an index scan");
+            };
+            assert_eq!(range.prefix, [Expr::Literal(int(a))]);
+            let rows = execute(&plan, &mut ExecContext::new(&mut bufmgr, &catalog)).unwrap();
+            assert_eq!(rows, [vec![text("x")]]);
+        }
+        cache.plan(&Planner::new(&catalog), "SELECT 1").unwrap();
+        assert_eq!(cache.len(), 3);
+        catalog.drop_table("t").unwrap();
+        cache.evict_stale(catalog.version());
+        assert!(cache.is_empty());
+
+        let planner = Planner::new(&catalog);
+        assert!(matches!(
+            cache.plan(&planner, sql),
+            Err(Error::TableNotFound(_))
+        ));
+        assert!(matches!(
+            cache.plan(&planner, "SELECT 1; SELECT 2"),
+            Err(Error::MultipleStatements)
+        ));
+        assert!(matches!(
+            cache.plan(&planner, "SELEC 1"),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            cache.plan(&planner, "BEGIN"),
+            Err(Error::NotPlannable)
+        ));
+        assert!(cache.is_empty());
+    }
+
+    // EXPLAIN を計画して実行し、出力の各行から見積もりを除いたものと出力そのものを返す
+    fn explain_sql(
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+        sql: &str,
+    ) -> (Vec<String>, Vec<String>) {
+        let plan = plan_sql(catalog, sql).unwrap();
+        assert_eq!(plan.columns(catalog).unwrap(), vec!["QUERY PLAN"]);
+        let mut ctx = ExecContext::new(bufmgr, catalog);
+        let lines = execute(&plan, &mut ctx)
+            .unwrap()
+            .into_iter()
+            .map(|row| match row.as_slice() {
+                [Value::Text(line)] => line.clone(),
+                _ => panic!("unexpected row"),
+            })
+            .collect::<Vec<_>>();
+        let shape = lines
+            .iter()
+            .map(|line| line.split("  (cost=").next().unwrap().to_string())
+            .collect();
+        (shape, lines)
+    }
+
+    #[test]
+    fn test_plan_explain() {
+        let rows = (0..5000)
+            .map(|i| vec![int(i), text("x")])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        catalog.analyze(&mut bufmgr, Some("t")).unwrap();
+        let (shape, lines) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT b FROM t WHERE a = 3 AND b <> 'y' ORDER BY b",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Sort",
+                "  Sort Key: b",
+                "  ->  Projection",
+                "        Output: b",
+                "        ->  Index Scan using t_a on t",
+                "              Index Cond: a = 3",
+                "              Filter: (b <> 'y')",
+            ]
+        );
+        assert!(lines[0].starts_with("Sort  (cost="));
+        assert!(lines[0].ends_with(" rows=1)"));
+
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT l.b FROM t AS l JOIN t AS r ON l.a = r.a",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: b",
+                "  ->  Hash Join",
+                "        Hash Cond: a = a",
+                "        ->  Seq Scan on t",
+                "        ->  Seq Scan on t",
+                "              Columns: a",
+            ]
+        );
+
+        // ANALYZE なら実行して、見積もりのあとに実際の行数と回数を書く
+        let (shape, lines) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN ANALYZE SELECT b FROM t WHERE a < 10",
+        );
+        assert_eq!(
+            shape[..6],
+            [
+                "Projection",
+                "  Output: b",
+                "  Buffers: shared hit=21 read=3",
+                "  ->  Index Scan using t_a on t",
+                "        Index Cond: a < 10",
+                "        Buffers: shared hit=21 read=3",
+            ]
+        );
+        assert!(lines[6].starts_with("Execution Time: "));
+        assert!(lines[0].ends_with(" rows=10 loops=1)"));
+        assert!(lines[3].ends_with(" rows=10 loops=1)"));
+
+        // インデックスの順に読むなら、その順のまま集約する
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT a, count(*) FROM t WHERE a < 10 GROUP BY a",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: a, count",
+                "  ->  GroupAggregate",
+                "        Group Key: a",
+                "        Aggregates: count(*)",
+                "        ->  Index Only Scan using t_a on t",
+                "              Index Cond: a < 10",
+            ]
+        );
+        let plan = plan_sql(&catalog, "SELECT a, count(*) FROM t WHERE a < 3 GROUP BY a").unwrap();
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            (0..3).map(|i| vec![int(i), int(1)]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_plan_index_order() {
+        let rows = (0..5000)
+            .map(|i| vec![int(i), text("x")])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        catalog.analyze(&mut bufmgr, Some("t")).unwrap();
+        // インデックスの順に読むなら並べ替えない
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT b FROM t WHERE a < 10 ORDER BY a ASC NULLS LAST",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: b",
+                "  ->  Projection",
+                "        Output: b, a",
+                "        ->  Index Scan using t_a on t",
+                "              Index Cond: a < 10",
+            ]
+        );
+        for (order, key) in [("a DESC", "a DESC"), ("a NULLS FIRST", "a NULLS FIRST")] {
+            let sql = format!("EXPLAIN SELECT a FROM t WHERE a < 10 ORDER BY {}", order);
+            let (shape, _) = explain_sql(&mut bufmgr, &catalog, &sql);
+            assert_eq!(shape[..2], ["Sort".to_string(), format!("  Sort Key: {}", key)]);
+        }
+        // 選択項目にない式で並べ替えても、キーは式で書く
+        let sql = "EXPLAIN SELECT b FROM t WHERE a < 10 ORDER BY a + 1, a";
+        let (shape, _) = explain_sql(&mut bufmgr, &catalog, sql);
+        assert_eq!(shape[3], "        Sort Key: (a + 1), a");
+    }
+
+    #[test]
+    fn test_plan_explain_formats() {
+        use crate::json::{Json, PathStep};
+
+        let rows = (0..100)
+            .map(|i| vec![int(i), text("x")])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let sql = "EXPLAIN (FORMAT JSON, ANALYZE) SELECT b FROM t WHERE a < 10 ORDER BY b";
+        let (_, lines) = explain_sql(&mut bufmgr, &catalog, sql);
+        assert_eq!(lines.len(), 1);
+        let json: Json = lines[0].parse().unwrap();
+        let get = |path: &[&str]| {
+            let mut steps = vec![PathStep::Index(0)];
+            for step in path {
+                steps.push(match step.parse() {
+                    Ok(i) => PathStep::Index(i),
+                    Err(_) => PathStep::Key(step.to_string()),
+                });
+            }
+            json.get_path(&steps).cloned()
+        };
+        let string = |s: &str| Some(Json::String(s.to_string()));
+        assert_eq!(get(&["Plan", "Node Type"]), string("Sort"));
+        assert_eq!(get(&["Plan", "Sort Key"]), string("b"));
+        assert_eq!(
+            get(&["Plan", "Actual Rows"]),
+            Some(Json::Number("10".into()))
+        );
+        let scan = ["Plan", "Plans", "0", "Plans", "0", "Plans", "0"];
+        assert_eq!(
+            get(&[&scan[..], &["Node Type"]].concat()),
+            string("Seq Scan on t")
+        );
+        assert_eq!(
+            get(&[&scan[..], &["Actual Rows"]].concat()),
+            Some(Json::Number("100".into()))
+        );
+        assert!(get(&["Execution Time"]).is_some());
+
+        let (_, lines) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN (FORMAT DOT) SELECT b FROM t WHERE b = 'a\"b'",
+        );
+        assert_eq!(lines[0], "digraph plan {");
+        assert!(lines[2].starts_with("  n0 [label=\"Projection\\lcost="));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("Filter: (b = 'a\\\"b')")));
+        assert!(lines.contains(&"  n0 -> n1;".to_string()));
+        assert!(lines.contains(&"  n1 -> n2;".to_string()));
+        assert_eq!(lines.last().unwrap(), "}");
+    }
+
+    #[test]
+    fn test_plan_aggregate() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), text("x")],
+            vec![int(3), text("y")],
+            vec![Value::Null, text("y")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = plan_sql(
+            &catalog,
+            "SELECT upper(b), count(*), sum(a) + 1 AS s FROM t GROUP BY b HAVING count(a) > 1",
+        )
+        .unwrap();
+        assert_eq!(plan.columns(&catalog).unwrap(), vec!["upper", "count", "s"]);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![text("X"), int(2), int(4)]]
+        );
+
+        let plan = plan_sql(&catalog, "SELECT a + 1, count(*) FROM t GROUP BY a + 1").unwrap();
+        let mut result = execute(&plan, &mut ctx).unwrap();
+        result.sort_by(|a, b| a[0].sort_cmp(&b[0]));
+        assert_eq!(
+            result,
+            vec![
+                vec![int(2), int(1)],
+                vec![int(3), int(1)],
+                vec![int(4), int(1)],
+                vec![Value::Null, int(1)],
+            ]
+        );
+
+        let plan = plan_sql(&catalog, "SELECT count(*), max(b) FROM t WHERE a > 10").unwrap();
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(0), Value::Null]]
+        );
+
+        let plan = plan_sql(
+            &catalog,
+            "SELECT count(DISTINCT b), sum(DISTINCT a % 2), approx_count_distinct(b) FROM t",
+        )
+        .unwrap();
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(2), int(1), int(2)]]
+        );
+    }
+
+    #[test]
+    fn test_plan_order_by() {
+        let rows = vec![
+            vec![int(2), text("x")],
+            vec![int(1), text("y")],
+            vec![Value::Null, text("z")],
+            vec![int(3), text("x")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = plan_sql(&catalog, "SELECT a AS n, b FROM t ORDER BY n DESC").unwrap();
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                rows[2].clone(),
+                rows[3].clone(),
+                rows[0].clone(),
+                rows[1].clone(),
+            ]
+        );
+
+        // 選択項目にない式で並べても列は増えない
+        let plan = plan_sql(&catalog, "SELECT b FROM t WHERE a > 0 ORDER BY b, -a").unwrap();
+        assert_eq!(plan.columns(&catalog).unwrap(), vec!["b"]);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![text("x")], vec![text("x")], vec![text("y")]]
+        );
+
+        let plan = plan_sql(
+            &catalog,
+            "SELECT b, sum(a) FROM t GROUP BY b ORDER BY count(*) DESC, 1",
+        )
+        .unwrap();
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![text("x"), int(5)],
+                vec![text("y"), int(1)],
+                vec![text("z"), Value::Null],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_distinct() {
+        let rows = vec![
+            vec![int(2), text("x")],
+            vec![Value::Null, text("y")],
+            vec![int(2), text("x")],
+            vec![Value::Null, text("y")],
+            vec![int(1), text("x")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = plan_sql(&catalog, "SELECT DISTINCT b, a FROM t").unwrap();
+        assert!(matches!(
+            &plan,
+            PlanNode::HashAggregate { aggregates, .. } if aggregates.is_empty()
+        ));
+        assert_eq!(plan.columns(&catalog).unwrap(), vec!["b", "a"]);
+        let mut result = execute(&plan, &mut ctx).unwrap();
+        result.sort_by(|a, b| compare_values(a, b));
+        assert_eq!(
+            result,
+            vec![
+                vec![text("x"), int(1)],
+                vec![text("x"), int(2)],
+                vec![text("y"), Value::Null],
+            ]
+        );
+
+        // ORDER BY があれば並べ替えてから隣り合う同じ行を除く
+        let plan = plan_sql(&catalog, "SELECT DISTINCT a, b FROM t ORDER BY b DESC").unwrap();
+        assert!(matches!(&plan, PlanNode::Unique { .. }));
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![Value::Null, text("y")],
+                vec![int(1), text("x")],
+                vec![int(2), text("x")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_subquery() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), text("y")],
+            vec![int(3), Value::Null],
+            vec![Value::Null, text("z")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut query = |sql| {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            let mut rows = execute(&plan, &mut ctx).unwrap();
+            rows.sort_by(|a, b| compare_values(a, b));
+            rows
+        };
+        assert_eq!(
+            query("SELECT a FROM t l WHERE EXISTS (SELECT * FROM t WHERE a = l.a + 1)"),
+            vec![vec![int(1)], vec![int(2)]]
+        );
+        assert_eq!(
+            query("SELECT a FROM t l WHERE NOT EXISTS (SELECT * FROM t WHERE a = l.a + 1)"),
+            vec![vec![int(3)], vec![Value::Null]]
+        );
+        assert_eq!(
+            query(
+                "SELECT a FROM t WHERE b <> 'x' AND a IN (SELECT a + 1 FROM t WHERE b IS NOT NULL)"
+            ),
+            vec![vec![int(2)]]
+        );
+        // 副問い合わせの結果に NULL があれば NOT IN は真にならない
+        assert_eq!(
+            query("SELECT a FROM t WHERE a NOT IN (SELECT a + 1 FROM t WHERE b IS NOT NULL)"),
+            Vec::<Vec<Value>>::new()
+        );
+        assert_eq!(
+            query("SELECT a FROM t WHERE a NOT IN (SELECT a FROM t WHERE a > 1)"),
+            vec![vec![int(1)]]
+        );
+        // 値の並びの NULL も同じ
+        assert_eq!(
+            query("SELECT a FROM t WHERE a NOT IN (1, NULL)"),
+            Vec::<Vec<Value>>::new()
+        );
+        assert_eq!(
+            query("SELECT a FROM t WHERE a IN (1, NULL)"),
+            vec![vec![int(1)]]
+        );
+        assert_eq!(
+            query("SELECT 2 IN (1, NULL), 2 NOT IN (1, NULL)"),
+            vec![vec![Value::Null, Value::Null]]
+        );
+        assert_eq!(
+            query("SELECT a FROM t WHERE NOT EXISTS (SELECT * FROM t WHERE a > 5)").len(),
+            4
+        );
+
+        let plan = plan_sql(
+            &catalog,
+            "SELECT a FROM t l WHERE EXISTS (SELECT * FROM t r WHERE r.a = l.a AND r.b = 'x')",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::HashJoin {
+            right, join_type, ..
+        } = &**input
+        else {
+            panic!("not a hash join");
+        };
+        assert_eq!(*join_type, JoinType::Semi);
+        assert!(matches!(**right, PlanNode::Filter { .. }));
+        assert!(matches!(
+            plan_sql(&catalog, "SELECT a FROM t WHERE a IN (SELECT a, b FROM t)"),
+            Err(Error::SubqueryColumns)
+        ));
+        assert!(matches!(
+            plan_sql(&catalog, "SELECT a FROM t WHERE a = 1 OR EXISTS (SELECT 1)"),
+            Err(Error::Unsupported("subquery"))
+        ));
+    }
+
+    #[test]
+    fn test_plan_decorrelate() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), text("y")],
+            vec![int(3), Value::Null],
+            vec![Value::Null, text("z")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut query = |sql| {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            let mut rows = execute(&plan, &mut ctx).unwrap();
+            rows.sort_by(|a, b| compare_values(a, b));
+            rows
+        };
+        // 相手のない行の COUNT は 0 になる
+        assert_eq!(
+            query("SELECT a, (SELECT count(*) FROM t r WHERE r.a = l.a + 1) FROM t l"),
+            vec![
+                vec![int(1), int(1)],
+                vec![int(2), int(1)],
+                vec![int(3), int(0)],
+                vec![Value::Null, int(0)],
+            ]
+        );
+        assert_eq!(
+            query("SELECT a, (SELECT max(b) FROM t r WHERE l.a = r.a AND r.b <> 'x') FROM t l"),
+            vec![
+                vec![int(1), Value::Null],
+                vec![int(2), text("y")],
+                vec![int(3), Value::Null],
+                vec![Value::Null, Value::Null],
+            ]
+        );
+        assert_eq!(
+            query("SELECT * FROM t l WHERE b = (SELECT max(b) FROM t r WHERE r.a = l.a)"),
+            vec![vec![int(1), text("x")], vec![int(2), text("y")]]
+        );
+        assert_eq!(
+            query("SELECT b FROM t WHERE a < (SELECT max(a) FROM t)"),
+            vec![vec![text("x")], vec![text("y")]]
+        );
+        // 集約する副問い合わせは先に計画してから反結合にする
+        assert_eq!(
+            query("SELECT a FROM t WHERE a NOT IN (SELECT min(a) FROM t)"),
+            vec![vec![int(2)], vec![int(3)]]
+        );
+        assert_eq!(
+            query("SELECT a FROM t WHERE EXISTS (SELECT a FROM t LIMIT 1) AND a = 2"),
+            vec![vec![int(2)]]
+        );
+        // 結合に直せない相関は外側の行ごとに実行し直す
+        assert_eq!(
+            query("SELECT (SELECT count(*) FROM t r WHERE r.a > l.a) FROM t l"),
+            vec![vec![int(0)], vec![int(0)], vec![int(1)], vec![int(2)]]
+        );
+        assert_eq!(
+            query("SELECT (SELECT count(*) + 1 FROM t r WHERE r.a = l.a) FROM t l"),
+            vec![vec![int(1)], vec![int(2)], vec![int(2)], vec![int(2)]]
+        );
+        assert_eq!(
+            query("SELECT a FROM t l WHERE a IN (SELECT max(a) FROM t r WHERE r.b = l.b)"),
+            vec![vec![int(1)], vec![int(2)]]
+        );
+
+        let plan = plan_sql(
+            &catalog,
+            "SELECT (SELECT count(*) FROM t r WHERE r.a = l.a) FROM t l",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::HashJoin {
+            right, join_type, ..
+        } = &**input
+        else {
+            panic!("not a hash join");
+        };
+        assert_eq!(*join_type, JoinType::Left);
+        let PlanNode::Projection { input, .. } = &**right else {
+            panic!("not a projection");
+        };
+        assert!(matches!(**input, PlanNode::HashAggregate { .. }));
+
+        let plan = plan_sql(&catalog, "SELECT (SELECT a FROM t)").unwrap();
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(crate::executor::Error::SubqueryRows)
+        ));
+        assert!(matches!(
+            plan_sql(&catalog, "SELECT count(*), (SELECT max(a) FROM t) FROM t"),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_outer_join() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), text("y")],
+            vec![int(3), text("z")],
+            vec![Value::Null, text("w")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut query = |sql| {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            let mut rows = execute(&plan, &mut ctx).unwrap();
+            rows.sort_by(|a, b| compare_values(a, b));
+            rows
+        };
+        assert_eq!(
+            query("SELECT l.b, r.b FROM t l LEFT JOIN t r ON l.a = r.a + 1"),
+            vec![
+                vec![text("w"), Value::Null],
+                vec![text("x"), Value::Null],
+                vec![text("y"), text("x")],
+                vec![text("z"), text("y")],
+            ]
+        );
+        assert_eq!(
+            query("SELECT l.b, r.b FROM t l RIGHT JOIN t r ON l.a = r.a + 1"),
+            vec![
+                vec![text("y"), text("x")],
+                vec![text("z"), text("y")],
+                vec![Value::Null, text("w")],
+                vec![Value::Null, text("z")],
+            ]
+        );
+        // ON の条件は相手を選ぶのにだけ使い、左の行は残る
+        assert_eq!(
+            query("SELECT l.b, r.b FROM t l FULL JOIN t r ON l.a = r.a AND l.b = 'x'"),
+            vec![
+                vec![text("w"), Value::Null],
+                vec![text("x"), text("x")],
+                vec![text("y"), Value::Null],
+                vec![text("z"), Value::Null],
+                vec![Value::Null, text("w")],
+                vec![Value::Null, text("y")],
+                vec![Value::Null, text("z")],
+            ]
+        );
+        // WHERE は結合のあとに絞り込む
+        assert_eq!(
+            query("SELECT l.b FROM t l LEFT JOIN t r ON l.a = r.a + 1 WHERE r.a IS NULL"),
+            vec![vec![text("w")], vec![text("x")]]
+        );
+    }
+
+    #[test]
+    fn test_plan_set_operation() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), text("y")],
+            vec![int(3), text("x")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut query = |sql| {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            execute(&plan, &mut ctx).unwrap()
+        };
+        assert_eq!(
+            query("SELECT b FROM t UNION SELECT 'z' ORDER BY b"),
+            vec![vec![text("x")], vec![text("y")], vec![text("z")]]
+        );
+        assert_eq!(
+            query("SELECT b FROM t UNION ALL SELECT b FROM t WHERE a > 1 ORDER BY 1 LIMIT 4"),
+            vec![
+                vec![text("x")],
+                vec![text("x")],
+                vec![text("x")],
+                vec![text("y")]
+            ]
+        );
+        assert_eq!(
+            query("SELECT a FROM t EXCEPT SELECT a + 1 FROM t"),
+            vec![vec![int(1)]]
+        );
+        assert_eq!(
+            query("SELECT b FROM t INTERSECT ALL (SELECT b FROM t WHERE a < 3)"),
+            vec![vec![text("x")], vec![text("y")]]
+        );
+
+        assert!(matches!(
+            plan_sql(&catalog, "SELECT * FROM t UNION SELECT a FROM t"),
+            Err(Error::SetOperationColumns(ast::SetOperator::Union))
+        ));
+        let plan = plan_sql(&catalog, "SELECT a FROM t UNION SELECT b FROM t").unwrap();
+        assert!(matches!(
+            execute(&plan, &mut ExecContext::new(&mut bufmgr, &catalog)),
+            Err(executor::Error::SetOperationTypes { .. })
+        ));
+    }
+
+    #[test]
+    fn test_plan_with() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), text("y")],
+            vec![int(3), text("x")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+
+        // 1 回だけ参照される問い合わせは埋め込む
+        let plan = plan_sql(
+            &catalog,
+            "WITH s (n) AS (SELECT a FROM t WHERE b = 'x') SELECT n * 10 FROM s",
+        )
+        .unwrap();
+        assert!(!matches!(plan, PlanNode::With { .. }));
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(10)], vec![int(30)]]
+        );
+
+        let plan = plan_sql(
+            &catalog,
+            "WITH s AS (SELECT a FROM t WHERE b = 'x'), u AS (SELECT a + 1 AS a FROM s) \
+             SELECT l.a, r.a FROM u l JOIN u r ON l.a < r.a",
+        )
+        .unwrap();
+        let PlanNode::With { ctes, .. } = &plan else {
+            panic!("not a with");
+        };
+        assert_eq!(ctes.len(), 1);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(2), int(4)]]
+        );
+
+        // 入れ子ループ結合の内側は結果をためて読み直す
+        let plan = plan_sql(
+            &catalog,
+            "WITH s AS (SELECT a FROM t WHERE b = 'x') SELECT t.a, s.a FROM t JOIN s ON t.a < s.a",
+        )
+        .unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        let PlanNode::NestedLoopJoin { right, .. } = &**input else {
+            panic!("not a nested loop join");
+        };
+        assert!(matches!(**right, PlanNode::Materialize { .. }));
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(1), int(3)], vec![int(2), int(3)]]
+        );
+
+        assert!(matches!(
+            plan_sql(
+                &catalog,
+                "WITH s AS (SELECT 1), s AS (SELECT 2) SELECT * FROM s"
+            ),
+            Err(Error::DuplicateCte(_))
+        ));
+        assert!(matches!(
+            plan_sql(&catalog, "WITH s (x, y) AS (SELECT 1) SELECT * FROM s"),
+            Err(Error::CteColumns {
+                available: 1,
+                specified: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_plan_window() {
+        let rows = vec![
+            vec![int(1), text("x")],
+            vec![int(2), text("y")],
+            vec![int(3), text("x")],
+            vec![int(4), text("x")],
+        ];
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut query = |sql| {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            execute(&plan, &mut ctx).unwrap()
+        };
+        assert_eq!(
+            query(
+                "SELECT a, row_number() OVER (PARTITION BY b ORDER BY a DESC), sum(a) OVER () \
+                 FROM t ORDER BY a"
+            ),
+            vec![
+                vec![int(1), int(3), int(10)],
+                vec![int(2), int(1), int(10)],
+                vec![int(3), int(2), int(10)],
+                vec![int(4), int(1), int(10)],
+            ]
+        );
+        // 列の名前は関数の名前
+        let plan = plan_sql(
+            &catalog,
+            "SELECT row_number() OVER (ORDER BY a), sum(a) OVER (), count(*) OVER () FROM t",
+        )
+        .unwrap();
+        assert_eq!(
+            plan.columns(&catalog).unwrap(),
+            vec!["row_number", "sum", "count"]
+        );
+        assert_eq!(
+            query("SELECT b, count(*), rank() OVER (ORDER BY count(*) DESC) FROM t GROUP BY b"),
+            vec![
+                vec![text("x"), int(3), int(1)],
+                vec![text("y"), int(1), int(2)]
+            ]
+        );
+        assert_eq!(
+            query(
+                "SELECT a, sum(a) OVER (ORDER BY a ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) \
+                 FROM t WHERE b = 'x'"
+            ),
+            vec![
+                vec![int(1), int(1)],
+                vec![int(3), int(4)],
+                vec![int(4), int(7)],
+            ]
+        );
+
+        assert!(matches!(
+            plan_sql(&catalog, "SELECT a FROM t WHERE row_number() OVER () > 1"),
+            Err(Error::WindowNotAllowed("WHERE"))
+        ));
+        assert!(matches!(
+            plan_sql(&catalog, "SELECT sum(row_number() OVER ()) OVER () FROM t"),
+            Err(Error::NestedWindow)
+        ));
+        assert!(matches!(
+            plan_sql(&catalog, "SELECT lag(a, 1, 0, 2) OVER () FROM t"),
+            Err(Error::FunctionNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_parallel() {
+        let rows = (0..2000)
+            .map(|i| vec![int(i), text(&format!("row{}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let Statement::Query(query) =
+            sql::parse("SELECT a, count(*) FROM t WHERE a % 7 = 0 GROUP BY a")
+                .unwrap()
+                .remove(0)
+        else {
+            unreachable!()
+        };
+        let mut planner = Planner::new(&catalog);
+        planner.parallel_workers = 4;
+        let plan = planner.plan_query(&query).unwrap();
+        let PlanNode::Projection { input, .. } = &plan else {
+            panic!("not a projection");
+        };
+        // ワーカーが途中まで集約し、このスレッドでまとめる
+        let PlanNode::ParallelAggregate { input, .. } = &**input else {
+            panic!("not a parallel aggregate");
+        };
+        let PlanNode::Gather {
+            table,
+            predicate,
+            workers: 4,
+            needed,
+        } = &**input
+        else {
+            panic!("not a gather");
+        };
+        // ページはすべてこのスレッドで読むので、順に読むより安くは見積もらない
+        let serial = filter(
+            PlanNode::SeqScan {
+                table: table.clone(),
+                needed: needed.clone(),
+            },
+            predicate.iter().cloned().collect(),
+        );
+        let model = planner.model();
+        assert_eq!(model.cost(input), model.cost(&serial));
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut result = execute(&plan, &mut ctx).unwrap();
+        result.sort_by(|a, b| compare_values(a, b));
+        let expected = (0..2000)
+            .filter(|i| i % 7 == 0)
+            .map(|i| vec![int(i), int(1)])
+            .collect::<Vec<_>>();
+        assert_eq!(result, expected);
+
+        let plan = |sql: &str| {
+            let Statement::Query(query) = sql::parse(sql).unwrap().remove(0) else {
+                unreachable!()
+            };
+            planner.plan_query(&query).unwrap()
+        };
+        let plans = [
+            plan("SELECT count(*), sum(a), avg(a), min(b), max(a), count(b) FROM t"),
+            plan("SELECT count(*), sum(a), max(b) FROM t WHERE a < 0"),
+        ];
+        for plan in &plans {
+            let PlanNode::Projection { input, .. } = plan else {
+                panic!("not a projection");
+            };
+            assert!(matches!(**input, PlanNode::ParallelAggregate { .. }));
+        }
+        let result = execute(&plans[0], &mut ctx).unwrap();
+        assert_eq!(
+            result,
+            [vec![
+                int(2000),
+                int(1999000),
+                Value::Real(999.5),
+                text("row0"),
+                int(1999),
+                int(2000)
+            ]]
+        );
+        // 行がなくても 1 行返す
+        let result = execute(&plans[1], &mut ctx).unwrap();
+        assert_eq!(result, [vec![int(0), Value::Null, Value::Null]]);
+        // DISTINCT の状態はまとめられないので、このスレッドで集約する
+        let PlanNode::Projection { input, .. } = plan("SELECT count(DISTINCT a % 3) FROM t") else {
+            panic!("not a projection");
+        };
+        assert!(matches!(*input, PlanNode::HashAggregate { .. }));
+    }
+
+    #[test]
+    fn test_plan_limit() {
+        let rows = (1..=5).map(|i| vec![int(i), text("x")]).collect::<Vec<_>>();
+        let (mut bufmgr, catalog) = setup(&rows);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = plan_sql(&catalog, "SELECT a FROM t ORDER BY a DESC LIMIT 2 OFFSET 1").unwrap();
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![vec![int(4)], vec![int(3)]]
+        );
+        let PlanNode::Limit { input, .. } = &plan else {
+            panic!("not a limit");
+        };
+        let sort = match &**input {
+            PlanNode::Projection { input, .. } => &**input,
+            plan => plan,
+        };
+        assert!(matches!(sort, PlanNode::Sort { top_n: Some(3), .. }));
+        let plan = plan_sql(&catalog, "SELECT a FROM t LIMIT NULL OFFSET 1 + 2").unwrap();
+        assert_eq!(execute(&plan, &mut ctx).unwrap().len(), 2);
+        let plan = plan_sql(&catalog, "SELECT a FROM t ORDER BY b, a LIMIT 0").unwrap();
+        assert!(execute(&plan, &mut ctx).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_plan_numeric_types() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        let column = |name: &str, data_type| Column {
+            name: name.to_string(),
+            data_type,
+            not_null: false,
+            collation: Collation::Binary,
+        };
+        let columns = vec![
+            column("x", DataType::Real),
+            column("y", DataType::BigInt),
+            column("z", DataType::Blob),
+        ];
+        catalog.create_table(&mut bufmgr, "r", columns).unwrap();
+        catalog
+            .create_index(&mut bufmgr, "r_x", "r", &["x".to_string()], false)
+            .unwrap();
+        // 整数は REAL と BIGINT の列に揃えて入れる
+        let sql = "INSERT INTO r VALUES (1, 2, NULL), (2.5, -3, NULL), (0.25, 4 * 5, NULL)";
+        assert_eq!(run_dml(&mut bufmgr, &catalog, sql).unwrap(), 3);
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let plan = plan_sql(
+            &catalog,
+            "SELECT x, y, x + y, y * 2, x / 2 FROM r WHERE x >= 1",
+        )
+        .unwrap();
+        let rows = execute(&plan, &mut ctx).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Value::Real(1.0),
+                    Value::BigInt(2),
+                    Value::Real(3.0),
+                    Value::BigInt(4),
+                    Value::Real(0.5)
+                ],
+                vec![
+                    Value::Real(2.5),
+                    Value::BigInt(-3),
+                    Value::Real(-0.5),
+                    Value::BigInt(-6),
+                    Value::Real(1.25)
+                ],
+            ]
+        );
+        assert_eq!(
+            rows[0].iter().map(Value::type_name).collect::<Vec<_>>(),
+            vec!["real", "bigint", "real", "bigint", "real"]
+        );
+
+        // 型の違う数値どうしも値で比べる
+        for (sql, expected) in [
+            ("SELECT y FROM r WHERE x = 1", vec![vec![Value::BigInt(2)]]),
+            (
+                "SELECT x FROM r WHERE y > 2.5 ORDER BY x",
+                vec![vec![Value::Real(0.25)]],
+            ),
+            (
+                "SELECT x FROM r WHERE x < 1 ORDER BY x",
+                vec![vec![Value::Real(0.25)]],
+            ),
+            (
+                "SELECT 1 = 1.0, 7 / 2, -2.5",
+                vec![vec![
+                    Value::Boolean(true),
+                    int(3),
+                    Value::Decimal("-2.5".parse().unwrap()),
+                ]],
+            ),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(execute(&plan, &mut ctx).unwrap(), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_plan_decimal() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        let dec = |s: &str| Value::Decimal(s.parse().unwrap());
+        let columns = vec![
+            Column {
+                name: "id".to_string(),
+                data_type: DataType::Integer,
+                not_null: true,
+                collation: Collation::Binary,
+            },
+            Column {
+                name: "price".to_string(),
+                data_type: DataType::parse("NUMERIC(6, 2)").unwrap(),
+                not_null: false,
+                collation: Collation::Binary,
+            },
+        ];
+        catalog.create_table(&mut bufmgr, "m", columns).unwrap();
+        catalog
+            .create_index(&mut bufmgr, "m_price", "m", &["price".to_string()], false)
+            .unwrap();
+        // 列の scale に丸めて入れる
+        let sql = "INSERT INTO m VALUES (1, 0.1), (2, 0.2), (3, 2.675), (4, 3), (5, NULL)";
+        assert_eq!(run_dml(&mut bufmgr, &catalog, sql).unwrap(), 5);
+        assert_eq!(
+            run_dml(&mut bufmgr, &catalog, "INSERT INTO m VALUES (6, 10000)")
+                .unwrap_err()
+                .to_string(),
+            "numeric field overflow in column \"price\""
+        );
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            (
+                "SELECT price FROM m WHERE id <= 4 ORDER BY id",
+                vec![
+                    vec![dec("0.10")],
+                    vec![dec("0.20")],
+                    vec![dec("2.68")],
+                    vec![dec("3.00")],
+                ],
+            ),
+            // 0.1 + 0.2 はちょうど 0.3 になる
+            (
+                "SELECT a.price + b.price = 0.3, a.price * 3 FROM m a, m b WHERE a.id = 1 AND b.id = 2",
+                vec![vec![Value::Boolean(true), dec("0.30")]],
+            ),
+            (
+                "SELECT SUM(price), AVG(price), COUNT(price), MIN(price) FROM m",
+                vec![vec![dec("5.98"), dec("1.495000"), int(4), dec("0.10")]],
+            ),
+            ("SELECT price / 3 FROM m WHERE id = 1", vec![vec![dec("0.033333")]]),
+            // 索引から読んでも型は列の型に戻る
+            ("SELECT price FROM m WHERE price = 3", vec![vec![dec("3.00")]]),
+            ("SELECT id FROM m WHERE price > 0.1 AND price < 2.68", vec![vec![int(2)]]),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(execute(&plan, &mut ctx).unwrap(), expected, "{}", sql);
+        }
+        let plan = plan_sql(&catalog, "SELECT price FROM m WHERE price = 3").unwrap();
+        let rows = execute(&plan, &mut ctx).unwrap();
+        assert_eq!(rows[0][0].to_string(), "3.00");
+        let plan = plan_sql(&catalog, "SELECT price / 0 FROM m WHERE id = 1").unwrap();
+        assert!(matches!(
+            execute(&plan, &mut ctx),
+            Err(crate::executor::Error::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_plan_datetime() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        let date = |s: &str| Value::Date(s.parse().unwrap());
+        let timestamp = |s: &str| Value::Timestamp(s.parse().unwrap());
+        let dec = |s: &str| Value::Decimal(s.parse().unwrap());
+        let column = |name: &str, data_type| Column {
+            name: name.to_string(),
+            data_type,
+            not_null: false,
+            collation: Collation::Binary,
+        };
+        let columns = vec![
+            column("id", DataType::Integer),
+            column("d", DataType::Date),
+            column("at", DataType::Timestamp),
+        ];
+        catalog.create_table(&mut bufmgr, "e", columns).unwrap();
+        catalog
+            .create_index(&mut bufmgr, "e_d", "e", &["d".to_string()], false)
+            .unwrap();
+        // 文字列は列の型で読んで入れる
+        let sql = "INSERT INTO e VALUES (1, '2024-01-15', '2024-01-15 13:45:30'), \
+                   (2, DATE '2024-02-29', TIMESTAMP '2024-03-01 00:00'), (3, '1999-12-31', '2000-01-01')";
+        assert_eq!(run_dml(&mut bufmgr, &catalog, sql).unwrap(), 3);
+        assert_eq!(
+            run_dml(
+                &mut bufmgr,
+                &catalog,
+                "INSERT INTO e VALUES (4, '2024-02-30', NULL)"
+            )
+            .unwrap_err()
+            .to_string(),
+            "invalid input syntax for type date: \"2024-02-30\""
+        );
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            (
+                "SELECT id FROM e WHERE d >= '2024-01-01' ORDER BY d",
+                vec![vec![int(1)], vec![int(2)]],
+            ),
+            ("SELECT d FROM e WHERE d = '2024-02-29'", vec![vec![date("2024-02-29")]]),
+            // 日付はその日の 0 時と比べる
+            ("SELECT id FROM e WHERE d = at", vec![]),
+            ("SELECT id FROM e WHERE at = d + 1 ORDER BY id", vec![vec![int(2)], vec![int(3)]]),
+            (
+                "SELECT d - DATE '2024-01-01', d + 20 FROM e WHERE id = 2",
+                vec![vec![int(59), date("2024-03-20")]],
+            ),
+            (
+                "SELECT EXTRACT(YEAR FROM d), EXTRACT(dow FROM at), EXTRACT(SECOND FROM at) FROM e WHERE id = 1",
+                vec![vec![dec("2024"), dec("1"), dec("30.000000")]],
+            ),
+            (
+                "SELECT date_trunc('month', at), date_trunc('hour', at) FROM e WHERE id = 1",
+                vec![vec![
+                    timestamp("2024-01-01 00:00"),
+                    timestamp("2024-01-15 13:00"),
+                ]],
+            ),
+            ("SELECT MAX(d), MIN(at) FROM e", vec![vec![date("2024-02-29"), timestamp("2000-01-01")]]),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(execute(&plan, &mut ctx).unwrap(), expected, "{}", sql);
+        }
+        // 日付の列と文字列の比較もインデックスの範囲に使える
+        let expr = Expr::binary(
+            BinaryOp::Lt,
+            Expr::column(1),
+            Expr::Literal(text("2000-01-01")),
+        );
+        assert_eq!(
+            column_bound(&expr, 1, &DataType::Date),
+            Some((BinaryOp::Lt, date("2000-01-01")))
+        );
+
+        // NOW() は計画に畳まず、実行するたびに読む
+        let plan = plan_sql(
+            &catalog,
+            "SELECT NOW(), CURRENT_DATE, NOW() > TIMESTAMP '2024-01-01'",
+        )
+        .unwrap();
+        let rows = execute(&plan, &mut ctx).unwrap();
+        assert!(matches!(rows[0][0], Value::Timestamp(_)));
+        assert!(matches!(rows[0][1], Value::Date(_)));
+        assert_eq!(rows[0][2], Value::Boolean(true));
+        for (sql, message) in [
+            (
+                "SELECT EXTRACT(fortnight FROM d) FROM e",
+                "unit \"fortnight\" not supported for type date",
+            ),
+            (
+                "SELECT id FROM e WHERE at < 'soon'",
+                "invalid input syntax for type timestamp: \"soon\"",
+            ),
+            (
+                "SELECT d + d FROM e",
+                "operator does not exist: date + date",
+            ),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(
+                execute(&plan, &mut ctx).unwrap_err().to_string(),
+                message,
+                "{}",
+                sql
+            );
+        }
+        assert_eq!(
+            sql::parse("SELECT DATE '2024-13-01'").unwrap_err().message,
+            "invalid input syntax for type date: \"2024-13-01\""
+        );
+    }
+
+    #[test]
+    fn test_plan_cast() {
+        let rows = (0..5000)
+            .map(|i| vec![int(i), text(&i.to_string())])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        catalog.analyze(&mut bufmgr, Some("t")).unwrap();
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT CAST(a AS TEXT) FROM t WHERE CAST(b AS INTEGER) < 3",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: CAST(a AS text)",
+                "  ->  Filter",
+                "        Filter: (CAST(b AS integer) < 3)",
+                "        ->  Seq Scan on t",
+            ]
+        );
+
+        // 整数の列と文字列の定数は、定数を整数として読んでインデックスの範囲にする
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT b FROM t WHERE a = '7'",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: b",
+                "  ->  Index Scan using t_a on t",
+                "        Index Cond: a = 7",
+            ]
+        );
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            (
+                "SELECT CAST(a AS TEXT) || 'x', CAST(b AS REAL) / 2 FROM t WHERE CAST(b AS INTEGER) < 2 ORDER BY a",
+                vec![
+                    vec![text("0x"), Value::Real(0.0)],
+                    vec![text("1x"), Value::Real(0.5)],
+                ],
+            ),
+            (
+                "SELECT CAST(2.5 AS INTEGER), CAST('t' AS BOOLEAN), CAST(NULL AS INTEGER)",
+                vec![vec![int(3), Value::Boolean(true), Value::Null]],
+            ),
+            (
+                "SELECT count(*) FROM t WHERE a < '10' AND b = 5",
+                vec![vec![int(1)]],
+            ),
+        ] {
+            assert_eq!(execute(&plan_sql(&catalog, sql).unwrap(), &mut ctx).unwrap(), expected, "{}", sql);
+        }
+        for (sql, message) in [
+            (
+                "SELECT CAST(b AS DATE) FROM t",
+                "invalid input syntax for type date: \"0\"",
+            ),
+            (
+                "SELECT CAST(a * 1e30 AS BIGINT) FROM t",
+                "integer out of range",
+            ),
+            (
+                "SELECT CAST(TIME '12:00' AS DATE) FROM t",
+                "cannot cast type time to date",
+            ),
+            (
+                "SELECT a FROM t WHERE a = 'x'",
+                "invalid input syntax for type integer: \"x\"",
+            ),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(
+                execute(&plan, &mut ctx).unwrap_err().to_string(),
+                message,
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_plan_collation() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        let column = |name: &str, collation| Column {
+            name: name.to_string(),
+            data_type: DataType::Text,
+            not_null: false,
+            collation,
+        };
+        let columns = vec![
+            column("n", Collation::NoCase),
+            column("g", Collation::NoCase),
+            column("u", Collation::Unicode),
+        ];
+        catalog.create_table(&mut bufmgr, "c", columns).unwrap();
+        catalog
+            .create_index(&mut bufmgr, "c_n", "c", &["n".to_string()], false)
+            .unwrap();
+        let values = (0..2000)
+            .map(|i| {
+                let n = if i % 2 == 0 { "key" } else { "KEY" };
+                let g = ["x", "X", "y"][i % 3];
+                format!("('{}{:04}', '{}', NULL)", n, i, g)
+            })
+            .collect::<Vec<_>>();
+        let sql = format!("INSERT INTO c VALUES {}", values.join(", "));
+        assert_eq!(run_dml(&mut bufmgr, &catalog, &sql).unwrap(), 2000);
+        let sql = "INSERT INTO c VALUES (NULL, NULL, 'pêche'), (NULL, NULL, 'PECHE'), (NULL, NULL, 'peach'), (NULL, NULL, 'pèche')";
+        assert_eq!(run_dml(&mut bufmgr, &catalog, sql).unwrap(), 4);
+        catalog.analyze(&mut bufmgr, Some("c")).unwrap();
+
+        // インデックスには照合順のキーが入るので、大文字小文字の違う定数でも範囲になる。
+        // キーから元の値には戻せないので、インデックスだけのスキャンにはしない
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT n FROM c WHERE n = 'KEY0010'",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: n",
+                "  ->  Index Scan using c_n on c",
+                "        Index Cond: n = 'key0010'",
+            ]
+        );
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT g, count(*) FROM c GROUP BY g",
+        );
+        assert!(shape.contains(&"        Group Key: (g COLLATE nocase)".to_string()));
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            (
+                "SELECT n FROM c WHERE n = 'KEY0010'",
+                vec![vec![text("key0010")]],
+            ),
+            (
+                "SELECT n FROM c WHERE n BETWEEN 'key0011' AND 'KEY0013' ORDER BY n",
+                vec![
+                    vec![text("KEY0011")],
+                    vec![text("key0012")],
+                    vec![text("KEY0013")],
+                ],
+            ),
+            (
+                "SELECT n FROM c WHERE n IN ('kEy0001', 'key0002') ORDER BY n DESC",
+                vec![vec![text("key0002")], vec![text("KEY0001")]],
+            ),
+            // グループの値は、グループの中で一番小さい元の値
+            (
+                "SELECT g, count(*) FROM c GROUP BY g ORDER BY g",
+                vec![
+                    vec![text("X"), int(1334)],
+                    vec![text("y"), int(666)],
+                    vec![Value::Null, int(4)],
+                ],
+            ),
+            (
+                "SELECT count(DISTINCT g) FROM c WHERE g = 'X'",
+                vec![vec![int(2)]],
+            ),
+            (
+                "SELECT u FROM c WHERE u IS NOT NULL ORDER BY u",
+                vec![
+                    vec![text("peach")],
+                    vec![text("PECHE")],
+                    vec![text("pèche")],
+                    vec![text("pêche")],
+                ],
+            ),
+            (
+                "SELECT count(*) FROM c WHERE u = 'peche'",
+                vec![vec![int(0)]],
+            ),
+        ] {
+            assert_eq!(
+                execute(&plan_sql(&catalog, sql).unwrap(), &mut ctx).unwrap(),
+                expected,
+                "{}",
+                sql
+            );
+        }
+
+        // 一意のインデックスも照合順のキーで重複を調べる
+        catalog
+            .create_table(&mut bufmgr, "d", vec![column("n", Collation::NoCase)])
+            .unwrap();
+        catalog
+            .create_index(&mut bufmgr, "d_n", "d", &["n".to_string()], true)
+            .unwrap();
+        assert_eq!(
+            run_dml(&mut bufmgr, &catalog, "INSERT INTO d VALUES ('A')").unwrap(),
+            1
+        );
+        assert_eq!(
+            run_dml(&mut bufmgr, &catalog, "INSERT INTO d VALUES ('a')")
+                .unwrap_err()
+                .to_string(),
+            "duplicate key value violates unique constraint \"d_n\""
+        );
+    }
+
+    #[test]
+    fn test_plan_json() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        let columns = vec![
+            Column {
+                name: "id".to_string(),
+                data_type: DataType::Integer,
+                not_null: false,
+                collation: Collation::Binary,
+            },
+            Column {
+                name: "doc".to_string(),
+                data_type: DataType::Json,
+                not_null: false,
+                collation: Collation::Binary,
+            },
+        ];
+        catalog.create_table(&mut bufmgr, "j", columns).unwrap();
+        let values = (0..2000)
+            .map(|i| {
+                format!(
+                    "({}, '{{\"name\": \"k{}\", \"tags\": [\"x\", {}], \"v\": null}}')",
+                    i,
+                    i,
+                    i % 7
+                )
+            })
+            .collect::<Vec<_>>();
+        let sql = format!("INSERT INTO j VALUES {}", values.join(", "));
+        assert_eq!(run_dml(&mut bufmgr, &catalog, &sql).unwrap(), 2000);
+        // 入れるときに JSON として読めるか確かめる
+        assert_eq!(
+            run_dml(
+                &mut bufmgr,
+                &catalog,
+                "INSERT INTO j VALUES (-1, '{\"a\": }')"
+            )
+            .unwrap_err()
+            .to_string(),
+            "invalid input syntax for type json: \"{\"a\": }\""
+        );
+
+        let expr = crate::sql::parser::Parser::new("doc ->> 'name'")
+            .unwrap()
+            .parse_expr()
+            .unwrap();
+        let expr = Planner::new(&catalog)
+            .plan_index_expression("j", &expr)
+            .unwrap();
+        catalog
+            .create_expression_index(&mut bufmgr, "j_name", "j", expr, true)
+            .unwrap();
+        catalog.analyze(&mut bufmgr, Some("j")).unwrap();
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT id FROM j WHERE doc ->> 'name' = 'k42'",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: id",
+                "  ->  Index Scan using j_name on j",
+                "        Index Cond: (doc ->> 'name') = 'k42'",
+            ]
+        );
+        // 一意の式のインデックスは、式の値が同じ行を入れさせない
+        assert_eq!(
+            run_dml(
+                &mut bufmgr,
+                &catalog,
+                "INSERT INTO j VALUES (-1, '{\"name\": \"k1\"}')"
+            )
+            .unwrap_err()
+            .to_string(),
+            "duplicate key value violates unique constraint \"j_name\""
+        );
+
+        let json = |s: &str| Value::Json(s.into());
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            ("SELECT id FROM j WHERE doc ->> 'name' = 'k42'", vec![vec![int(42)]]),
+            (
+                "SELECT doc, doc -> 'tags', doc -> 'tags' ->> 0, doc -> '$.tags[-1]' FROM j WHERE id = 3",
+                vec![vec![
+                    json("{\"name\":\"k3\",\"tags\":[\"x\",3],\"v\":null}"),
+                    json("[\"x\",3]"),
+                    text("x"),
+                    json("3"),
+                ]],
+            ),
+            // JSON の null は -> では JSON のまま、->> では NULL
+            (
+                "SELECT doc -> 'v', doc ->> 'v', doc -> 'missing', json_extract(doc, '$.v') FROM j WHERE id = 0",
+                vec![vec![json("null"), Value::Null, Value::Null, Value::Null]],
+            ),
+            (
+                "SELECT json_extract(doc, '$.tags[1]') * 10, json_extract(doc, '$.tags') FROM j WHERE id = 5",
+                vec![vec![int(50), json("[\"x\",5]")]],
+            ),
+            (
+                "SELECT count(*) FROM j WHERE json_extract(doc, '$.tags[1]') = 0",
+                vec![vec![int(286)]],
+            ),
+            ("SELECT '[1, {\"a\": true}]' -> 1 ->> 'a'", vec![vec![text("true")]]),
+            (
+                "SELECT CAST(doc -> 'tags' AS TEXT) FROM j WHERE id = 1",
+                vec![vec![text("[\"x\",1]")]],
+            ),
+        ] {
+            assert_eq!(execute(&plan_sql(&catalog, sql).unwrap(), &mut ctx).unwrap(), expected, "{}", sql);
+        }
+        for (sql, message) in [
+            (
+                "SELECT json_extract(doc, 'name') FROM j",
+                "invalid JSON path \"name\"",
+            ),
+            (
+                "SELECT id -> 'a' FROM j",
+                "operator does not exist: integer -> text",
+            ),
+            (
+                "SELECT 'nope' ->> 'a'",
+                "invalid input syntax for type json: \"nope\"",
+            ),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(
+                execute(&plan, &mut ctx).unwrap_err().to_string(),
+                message,
+                "{}",
+                sql
+            );
+        }
+
+        let expr = crate::sql::parser::Parser::new("doc ->> CAST(now() AS TEXT)")
+            .unwrap()
+            .parse_expr()
+            .unwrap();
+        assert!(matches!(
+            Planner::new(&catalog).plan_index_expression("j", &expr),
+            Err(Error::VolatileIndexExpression)
+        ));
+    }
+
+    #[test]
+    fn test_plan_uuid() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        let columns = vec![
+            Column {
+                name: "id".to_string(),
+                data_type: DataType::Uuid,
+                not_null: true,
+                collation: Collation::Binary,
+            },
+            Column {
+                name: "n".to_string(),
+                data_type: DataType::Integer,
+                not_null: false,
+                collation: Collation::Binary,
+            },
+        ];
+        catalog.create_table(&mut bufmgr, "u", columns).unwrap();
+        let values = (0..1000)
+            .map(|i| {
+                format!(
+                    "('{:08x}-0000-4000-8000-{:012x}', {})",
+                    i * 7919 % 1000,
+                    i,
+                    i
+                )
+            })
+            .collect::<Vec<_>>();
+        let sql = format!("INSERT INTO u VALUES {}", values.join(", "));
+        assert_eq!(run_dml(&mut bufmgr, &catalog, &sql).unwrap(), 1000);
+        catalog
+            .create_index(&mut bufmgr, "u_id", "u", &["id".to_string()], true)
+            .unwrap();
+        catalog.analyze(&mut bufmgr, Some("u")).unwrap();
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT n FROM u WHERE id = UUID '000002fb-0000-4000-8000-00000000004d'",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: n",
+                "  ->  Index Scan using u_id on u",
+                "        Index Cond: id = UUID '000002fb-0000-4000-8000-00000000004d'",
+            ]
+        );
+        assert_eq!(
+            run_dml(
+                &mut bufmgr,
+                &catalog,
+                "INSERT INTO u VALUES ('000002FB-0000-4000-8000-00000000004D', 0)"
+            )
+            .unwrap_err()
+            .to_string(),
+            "duplicate key value violates unique constraint \"u_id\""
+        );
+        assert_eq!(
+            run_dml(
+                &mut bufmgr,
+                &catalog,
+                "INSERT INTO u VALUES (uuid_v4(), 1000), (gen_random_uuid(), 1001), (uuid_v7(), 1002)"
+            )
+            .unwrap(),
+            3
+        );
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            (
+                "SELECT n FROM u WHERE id = UUID '000002fb-0000-4000-8000-00000000004d'",
+                vec![vec![int(77)]],
+            ),
+            // 文字列と比べるときは文字列を UUID として読む
+            (
+                "SELECT n FROM u WHERE id = '{000002FB-0000-4000-8000-00000000004D}'",
+                vec![vec![int(77)]],
+            ),
+            (
+                "SELECT n, CAST(id AS TEXT) FROM u WHERE n < 1000 ORDER BY id LIMIT 2",
+                vec![
+                    vec![int(0), text("00000000-0000-4000-8000-000000000000")],
+                    vec![int(679), text("00000001-0000-4000-8000-0000000002a7")],
+                ],
+            ),
+            (
+                "SELECT count(DISTINCT id), count(*) FROM u WHERE n >= 1000",
+                vec![vec![int(3), int(3)]],
+            ),
+            (
+                "SELECT uuid_v4() = uuid_v4()",
+                vec![vec![Value::Boolean(false)]],
+            ),
+        ] {
+            assert_eq!(
+                execute(&plan_sql(&catalog, sql).unwrap(), &mut ctx).unwrap(),
+                expected,
+                "{}",
+                sql
+            );
+        }
+        let plan = plan_sql(&catalog, "SELECT n FROM u WHERE id = 'zz'").unwrap();
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap_err().to_string(),
+            "invalid input syntax for type uuid: \"zz\""
+        );
+        assert_eq!(
+            sql::parse("SELECT UUID '1234'").unwrap_err().message,
+            "invalid input syntax for type uuid: \"1234\""
+        );
+    }
+
+    #[test]
+    fn test_plan_interval() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        let timestamp = |s: &str| Value::Timestamp(s.parse().unwrap());
+        let interval = |s: &str| Value::Interval(s.parse().unwrap());
+        let column = |name: &str, data_type| Column {
+            name: name.to_string(),
+            data_type,
+            not_null: false,
+            collation: Collation::Binary,
+        };
+        let columns = vec![
+            column("id", DataType::Integer),
+            column("at", DataType::Timestamp),
+            column("took", DataType::Interval),
+        ];
+        catalog.create_table(&mut bufmgr, "e", columns).unwrap();
+        let values = (0..2000)
+            .map(|i| {
+                format!(
+                    "({}, TIMESTAMP '2024-01-01' + INTERVAL '1 hour' * {}, '{} minutes')",
+                    i,
+                    i,
+                    i % 60
+                )
+            })
+            .collect::<Vec<_>>();
+        let sql = format!("INSERT INTO e VALUES {}", values.join(", "));
+        assert_eq!(run_dml(&mut bufmgr, &catalog, &sql).unwrap(), 2000);
+        catalog
+            .create_index(&mut bufmgr, "e_at", "e", &["at".to_string()], false)
+            .unwrap();
+        catalog.analyze(&mut bufmgr, Some("e")).unwrap();
+        // 定数の式は畳んでからインデックスの範囲にする
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT id FROM e WHERE at >= TIMESTAMP '2024-01-20' - INTERVAL '2 hours' AND at < TIMESTAMP '2024-01-20'",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: id",
+                "  ->  Index Scan using e_at on e",
+                "        Index Cond: at >= TIMESTAMP '2024-01-19 22:00:00' AND at < TIMESTAMP '2024-01-20 00:00:00'",
+            ]
+        );
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            (
+                "SELECT count(*) FROM e WHERE at >= TIMESTAMP '2024-01-20' - INTERVAL '2 hours' AND at < TIMESTAMP '2024-01-20'",
+                vec![vec![int(2)]],
+            ),
+            (
+                "SELECT at + INTERVAL '1 mon', at - TIMESTAMP '2023-12-31 12:00', took * 1.5 FROM e WHERE id = 30",
+                vec![vec![
+                    timestamp("2024-02-02 06:00"),
+                    interval("1 day 18:00:00"),
+                    interval("00:45:00"),
+                ]],
+            ),
+            // 1 か月と 30 日は等しい
+            (
+                "SELECT INTERVAL '1 mon' = INTERVAL '30 days', INTERVAL '25 hours' > INTERVAL '1 day', -INTERVAL '1 day 2 hours'",
+                vec![vec![Value::Boolean(true), Value::Boolean(true), interval("-1 days -02:00:00")]],
+            ),
+            (
+                "SELECT max(took), count(*) FROM e WHERE took > '58 minutes'",
+                vec![vec![interval("59 minutes"), int(33)]],
+            ),
+            (
+                "SELECT date_add(DATE '2024-01-31', INTERVAL '1 mon'), TIME '23:00' + INTERVAL '2 hours', INTERVAL '1 day' / 4",
+                vec![vec![
+                    timestamp("2024-02-29"),
+                    Value::Time("01:00".parse().unwrap()),
+                    interval("06:00:00"),
+                ]],
+            ),
+            (
+                "SELECT date_diff('month', DATE '2024-01-31', DATE '2024-03-01'), date_diff('day', at, TIMESTAMP '2024-01-03') FROM e WHERE id = 47",
+                vec![vec![int(2), int(1)]],
+            ),
+            (
+                "SELECT CAST(at - TIMESTAMP '2024-01-01' AS TEXT) FROM e WHERE id = 25",
+                vec![vec![text("1 day 01:00:00")]],
+            ),
+        ] {
+            assert_eq!(execute(&plan_sql(&catalog, sql).unwrap(), &mut ctx).unwrap(), expected, "{}", sql);
+        }
+        for (sql, message) in [
+            (
+                "SELECT INTERVAL '1 day' + 1",
+                "operator does not exist: interval + integer",
+            ),
+            ("SELECT INTERVAL '1 day' / 0", "division by zero"),
+            (
+                "SELECT TIMESTAMP '9999-12-31' + INTERVAL '1 day'",
+                "date out of range",
+            ),
+            (
+                "SELECT date_diff('dow', DATE '2024-01-01', DATE '2024-01-02')",
+                "unit \"dow\" not supported for type date",
+            ),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(
+                execute(&plan, &mut ctx).unwrap_err().to_string(),
+                message,
+                "{}",
+                sql
+            );
+        }
+        assert_eq!(
+            sql::parse("SELECT INTERVAL '3 fortnights'")
+                .unwrap_err()
+                .message,
+            "invalid input syntax for type interval: \"3 fortnights\""
+        );
+    }
+
+    #[test]
+    fn test_plan_user_function() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        let reverse = ScalarFunction::new(
+            "Reverse",
+            vec![DataType::Text],
+            DataType::Text,
+            |args| match &args[0] {
+                Value::Text(s) => Ok(Value::Text(s.chars().rev().collect())),
+                _ => unreachable!(),
+            },
+        );
+        catalog.create_function(reverse).unwrap();
+        // 引数は宣言した型に変換して渡し、返した値は戻り値の型に変換する
+        let days = ScalarFunction::new(
+            "days_until_new_year",
+            vec![DataType::Date],
+            DataType::BigInt,
+            |args| {
+                let Value::Date(d) = &args[0] else {
+                    unreachable!()
+                };
+                let (year, ..) = d.ymd();
+                let next =
+                    crate::datetime::Date::from_ymd(year + 1, 1, 1).ok_or("year out of range")?;
+                Ok(Value::Integer((next.days() - d.days()) as i64))
+            },
+        );
+        catalog.create_function(days).unwrap();
+        let wrong = ScalarFunction::new("wrong", vec![], DataType::Integer, |_| {
+            Ok(Value::Text("x".to_string()))
+        });
+        catalog.create_function(wrong).unwrap();
+        let roll =
+            ScalarFunction::new("roll", vec![], DataType::Integer, |_| Ok(Value::Integer(4)))
+                .volatile();
+        catalog.create_function(roll).unwrap();
+        for name in ["upper", "count", "REVERSE"] {
+            let func = ScalarFunction::new(name, vec![], DataType::Integer, |_| Ok(Value::Null));
+            assert_eq!(
+                catalog.create_function(func).unwrap_err().to_string(),
+                format!("function \"{}\" already exists", name.to_lowercase())
+            );
+        }
+
+        let values = (0..2000)
+            .map(|i| format!("({}, 'name{}')", i, i))
+            .collect::<Vec<_>>();
+        let sql = format!("INSERT INTO t VALUES {}", values.join(", "));
+        assert_eq!(run_dml(&mut bufmgr, &catalog, &sql).unwrap(), 2000);
+        let expr = crate::sql::parser::Parser::new("reverse(b)")
+            .unwrap()
+            .parse_expr()
+            .unwrap();
+        let expr = Planner::new(&catalog)
+            .plan_index_expression("t", &expr)
+            .unwrap();
+        catalog
+            .create_expression_index(&mut bufmgr, "t_reverse_b", "t", expr, false)
+            .unwrap();
+        catalog.analyze(&mut bufmgr, Some("t")).unwrap();
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT a FROM t WHERE reverse(b) = reverse('name42')",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: a",
+                "  ->  Index Scan using t_reverse_b on t",
+                "        Index Cond: reverse(b) = '24eman'",
+            ]
+        );
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            (
+                "SELECT a FROM t WHERE reverse(b) = reverse('name42')",
+                vec![vec![int(42)]],
+            ),
+            (
+                "SELECT days_until_new_year('2024-12-30'), days_until_new_year(NULL), roll()",
+                vec![vec![Value::BigInt(2), Value::Null, int(4)]],
+            ),
+            (
+                "SELECT count(*) FROM t WHERE reverse(b) LIKE '0%'",
+                vec![vec![int(200)]],
+            ),
+        ] {
+            assert_eq!(
+                execute(&plan_sql(&catalog, sql).unwrap(), &mut ctx).unwrap(),
+                expected,
+                "{}",
+                sql
+            );
+        }
+        for (sql, message) in [
+            (
+                "SELECT days_until_new_year('9999-06-01')",
+                "function days_until_new_year failed: year out of range",
+            ),
+            (
+                "SELECT wrong()",
+                "function wrong failed: returned type text but integer was declared",
+            ),
+            (
+                "SELECT days_until_new_year(1)",
+                "cannot cast type integer to date",
+            ),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(
+                execute(&plan, &mut ctx).unwrap_err().to_string(),
+                message,
+                "{}",
+                sql
+            );
+        }
+        assert!(matches!(
+            plan_sql(&catalog, "SELECT reverse('a', 'b')"),
+            Err(Error::FunctionNotFound(_))
+        ));
+        let expr = crate::sql::parser::Parser::new("a + roll()")
+            .unwrap()
+            .parse_expr()
+            .unwrap();
+        assert!(matches!(
+            Planner::new(&catalog).plan_index_expression("t", &expr),
+            Err(Error::VolatileIndexExpression)
+        ));
+    }
+
+    #[test]
+    fn test_plan_like() {
+        let rows = (0..5000)
+            .map(|i| vec![int(i), text(&format!("k{:04}", i))])
+            .collect::<Vec<_>>();
+        let (mut bufmgr, mut catalog) = setup(&rows);
+        catalog
+            .create_index(&mut bufmgr, "t_b", "t", &["b".to_string()], false)
+            .unwrap();
+        catalog.analyze(&mut bufmgr, Some("t")).unwrap();
+        // 先頭が決まるパターンはインデックスの範囲にし、LIKE は絞り込みに残す
+        let (shape, _) = explain_sql(
+            &mut bufmgr,
+            &catalog,
+            "EXPLAIN SELECT a FROM t WHERE b LIKE 'k123_'",
+        );
+        assert_eq!(
+            shape,
+            vec![
+                "Projection",
+                "  Output: a",
+                "  ->  Index Scan using t_b on t",
+                "        Index Cond: b >= 'k123' AND b < 'k124'",
+                "        Filter: (b LIKE 'k123_')",
+            ]
+        );
+
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        for (sql, expected) in [
+            (
+                "SELECT a FROM t WHERE b LIKE 'k12_3' ORDER BY a",
+                (0..10).map(|i| vec![int(1203 + i * 10)]).collect(),
+            ),
+            ("SELECT a FROM t WHERE b LIKE 'k0042'", vec![vec![int(42)]]),
+            ("SELECT a FROM t WHERE b LIKE 'K499%' ORDER BY a", vec![]),
+            (
+                "SELECT a FROM t WHERE b ILIKE 'K499%' AND b NOT LIKE '%8' ORDER BY a",
+                (4990..5000)
+                    .filter(|i| *i != 4998)
+                    .map(|i| vec![int(i)])
+                    .collect(),
+            ),
+            (
+                "SELECT a FROM t WHERE b REGEXP '^k(10|49)0[05]$' ORDER BY a",
+                vec![
+                    vec![int(1000)],
+                    vec![int(1005)],
+                    vec![int(4900)],
+                    vec![int(4905)],
+                ],
+            ),
+        ] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            assert_eq!(execute(&plan, &mut ctx).unwrap(), expected, "{}", sql);
+        }
+
+        let like = |pattern: &str, escape: Option<&str>| {
+            let mut args = vec![Expr::column(1), Expr::Literal(text(pattern))];
+            args.extend(escape.map(|e| Expr::Literal(text(e))));
+            like_bounds(
+                &Expr::Function {
+                    func: Function::Like,
+                    args,
+                },
+                1,
+                &DataType::Text,
+            )
+        };
+        assert_eq!(
+            like("a\\%b%", None),
+            vec![(BinaryOp::GtEq, text("a%b")), (BinaryOp::Lt, text("a%c"))]
+        );
+        assert_eq!(like("a!_", Some("!")), vec![(BinaryOp::Eq, text("a_"))]);
+        assert_eq!(like("%a", None), vec![]);
+        assert_eq!(successor("a\u{10FFFF}"), Some("b".to_string()));
+        assert_eq!(successor("\u{D7FF}"), Some("\u{E000}".to_string()));
+    }
+
+    #[test]
+    fn test_plan_dml() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], true)
+            .unwrap();
+        for (sql, count) in [
+            ("INSERT INTO t VALUES (1, 'x'), (2, 'y')", 2),
+            ("INSERT INTO t (b) VALUES ('z')", 1),
+            (
+                "INSERT INTO t (b, a) SELECT b, a + 10 FROM t WHERE a IS NOT NULL",
+                2,
+            ),
+            ("UPDATE t SET b = upper(b) WHERE a > 5", 2),
+            ("DELETE FROM t WHERE a = 1", 1),
+            ("UPDATE t SET a = 20 WHERE a = 2", 1),
+            ("INSERT INTO t VALUES (2, 'w')", 1),
+        ] {
+            assert_eq!(
+                run_dml(&mut bufmgr, &catalog, sql).unwrap(),
+                count,
+                "{}",
+                sql
+            );
+        }
+        for (sql, message) in [
+            (
+                "INSERT INTO t VALUES (20, 'w')",
+                "duplicate key value violates unique constraint \"t_a\"",
+            ),
+            (
+                "UPDATE t SET a = 11 WHERE a = 12",
+                "duplicate key value violates unique constraint \"t_a\"",
+            ),
+            (
+                "INSERT INTO t VALUES ('a', 'b')",
+                "column \"a\" is of type integer but expression is of type text",
+            ),
+            (
+                "INSERT INTO t VALUES (1.5, 'b')",
+                "column \"a\" is of type integer but expression is of type numeric",
+            ),
+            (
+                "INSERT INTO t VALUES (1, 'x', 3)",
+                "INSERT has more expressions than target columns",
+            ),
+            (
+                "INSERT INTO t (a, b) VALUES (1)",
+                "INSERT has more target columns than expressions",
+            ),
+            (
+                "UPDATE t SET a = 1, a = 2",
+                "multiple assignments to same column \"a\"",
+            ),
+        ] {
+            let err = run_dml(&mut bufmgr, &catalog, sql).unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+
+        let plan = plan_sql(&catalog, "SELECT * FROM t ORDER BY a").unwrap();
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![int(2), text("w")],
+                vec![int(11), text("X")],
+                vec![int(12), text("Y")],
+                vec![int(20), text("y")],
+                vec![Value::Null, text("z")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_returning() {
+        let (mut bufmgr, catalog) = setup(&[]);
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        let mut query = |sql| {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            (
+                plan.columns(&catalog).unwrap(),
+                execute(&plan, &mut ctx).unwrap(),
+            )
+        };
+        assert_eq!(
+            query("INSERT INTO t (b) VALUES ('x'), ('y') RETURNING *"),
+            (
+                vec!["a".to_string(), "b".to_string()],
+                vec![vec![Value::Null, text("x")], vec![Value::Null, text("y")]]
+            )
+        );
+        assert_eq!(
+            query("UPDATE t SET a = 1 WHERE b = 'y' RETURNING a + 1 AS n, upper(t.b)"),
+            (
+                vec!["n".to_string(), "upper".to_string()],
+                vec![vec![int(2), text("Y")]]
+            )
+        );
+        assert_eq!(
+            query("DELETE FROM t WHERE a IS NULL RETURNING t.*").1,
+            vec![vec![Value::Null, text("x")]]
+        );
+        // RETURNING がなければ行数を返す
+        assert_eq!(query("DELETE FROM t").1, vec![vec![int(1)]]);
+
+        assert!(matches!(
+            plan_sql(&catalog, "DELETE FROM t RETURNING count(*)"),
+            Err(Error::AggregateNotAllowed("RETURNING"))
+        ));
+        assert!(matches!(
+            plan_sql(&catalog, "DELETE FROM t RETURNING s.*"),
+            Err(Error::MissingFromEntry(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_on_conflict() {
+        let (mut bufmgr, mut catalog) = setup(&[]);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], true)
+            .unwrap();
+        for (sql, count) in [
+            ("INSERT INTO t VALUES (1, 'x'), (2, 'y')", 2),
+            (
+                "INSERT INTO t VALUES (1, 'z'), (3, 'w') ON CONFLICT (a) DO NOTHING",
+                1,
+            ),
+            ("INSERT INTO t VALUES (3, 'v') ON CONFLICT DO NOTHING", 0),
+            (
+                "INSERT INTO t VALUES (2, 'v'), (4, 'u') \
+                 ON CONFLICT (a) DO UPDATE SET b = t.b || excluded.b",
+                2,
+            ),
+            (
+                "INSERT INTO t VALUES (4, 'q') \
+                 ON CONFLICT (a) DO UPDATE SET b = excluded.b WHERE t.b = 'x'",
+                0,
+            ),
+        ] {
+            assert_eq!(
+                run_dml(&mut bufmgr, &catalog, sql).unwrap(),
+                count,
+                "{}",
+                sql
+            );
+        }
+
+        // 同じ行を二度書き換えようとしたら、それまでの変更も取り消す
+        let err = run_dml(
+            &mut bufmgr,
+            &catalog,
+            "INSERT INTO t VALUES (5, 'a'), (1, 'b'), (5, 'c') \
+             ON CONFLICT (a) DO UPDATE SET b = excluded.b",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Executor(executor::Error::ConflictRowTwice)
+        ));
+
+        let plan = plan_sql(&catalog, "SELECT * FROM t ORDER BY a").unwrap();
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![int(1), text("x")],
+                vec![int(2), text("yv")],
+                vec![int(3), text("w")],
+                vec![int(4), text("u")],
+            ]
+        );
+
+        assert!(matches!(
+            plan_sql(
+                &catalog,
+                "INSERT INTO t VALUES (1, 'a') ON CONFLICT (b) DO NOTHING"
+            ),
+            Err(Error::NoConflictIndex)
+        ));
+        assert!(matches!(
+            plan_sql(
+                &catalog,
+                "INSERT INTO t VALUES (1, 'a') ON CONFLICT DO UPDATE SET b = 'c'"
+            ),
+            Err(Error::ConflictTargetRequired)
+        ));
+    }
+
+    #[test]
+    fn test_plan_rollback() {
+        use crate::transaction::TransactionManager;
+
+        let (mut bufmgr, mut catalog) = setup(&[vec![int(1), text("x")], vec![int(2), text("y")]]);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], true)
+            .unwrap();
+        let mut manager = TransactionManager::new();
+        manager.begin().unwrap();
+        let mut run = |bufmgr: &mut BufferPoolManager, sql: &str| {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            let mut ctx = ExecContext::new(bufmgr, &catalog);
+            ctx.txn = manager.start_statement();
+            execute(&plan, &mut ctx)
+        };
+        let rows = |bufmgr: &mut BufferPoolManager, catalog: &Catalog| {
+            let plan = plan_sql(catalog, "SELECT * FROM t ORDER BY a").unwrap();
+            execute(&plan, &mut ExecContext::new(bufmgr, catalog)).unwrap()
+        };
+        // 長くした行はページに収まらず移動する
+        let update = format!("UPDATE t SET a = a + 10, b = b || '{}'", "-".repeat(2000));
+        for sql in [
+            "INSERT INTO t VALUES (3, 'z')",
+            &update,
+            "DELETE FROM t WHERE a = 12",
+            "INSERT INTO t VALUES (2, 'again')",
+        ] {
+            run(&mut bufmgr, sql).unwrap();
+        }
+        let before = rows(&mut bufmgr, &catalog);
+        assert_eq!(before.len(), 3);
+        // 文の途中で失敗したら、その文で書き換えた行だけを戻す
+        assert!(run(&mut bufmgr, "UPDATE t SET a = 30 WHERE a > 10").is_err());
+        assert!(run(&mut bufmgr, "INSERT INTO t VALUES (4, 'p'), (2, 'q')").is_err());
+        assert_eq!(rows(&mut bufmgr, &catalog), before);
+
+        manager.rollback(&mut bufmgr, &catalog).unwrap();
+        assert_eq!(
+            rows(&mut bufmgr, &catalog),
+            vec![vec![int(1), text("x")], vec![int(2), text("y")]]
+        );
+        // インデックスも戻っている
+        let table = catalog.table("t").unwrap();
+        let index = &table.indexes[0];
+        let rid = index
+            .find(&mut bufmgr, &*table.storage, &[int(2), text("y")])
+            .unwrap()
+            .unwrap();
+        let (_, bytes) = table.storage.get(&mut bufmgr, rid).unwrap().unwrap();
+        assert_eq!(
+            crate::tuple::decode(&bytes, &[]).unwrap(),
+            vec![int(2), text("y")]
+        );
+        for a in [3, 12] {
+            assert_eq!(
+                index
+                    .find(&mut bufmgr, &*table.storage, &[int(a), Value::Null])
+                    .unwrap(),
+                None
+            );
+        }
+        assert_eq!(
+            run_dml(&mut bufmgr, &catalog, "INSERT INTO t VALUES (3, 'z')").unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_plan_savepoint() {
+        use crate::sql::ast::Statement;
+        use crate::transaction::TransactionManager;
+
+        let (mut bufmgr, mut catalog) = setup(&[vec![int(1), text("x")]]);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], true)
+            .unwrap();
+        let mut manager = TransactionManager::new();
+        let mut run = |bufmgr: &mut BufferPoolManager, sql: &str| {
+            for stmt in sql::parse(sql).unwrap() {
+                if let Statement::Transaction(stmt) = &stmt {
+                    manager.execute(stmt, bufmgr, &catalog).unwrap();
+                    continue;
+                }
+                let plan = Planner::new(&catalog).plan_statement(&stmt)?;
+                let mut ctx = ExecContext::new(bufmgr, &catalog);
+                ctx.txn = manager.start_statement();
+                execute(&plan, &mut ctx)?;
+            }
+            Ok::<_, Error>(())
+        };
+        run(
+            &mut bufmgr,
+            "BEGIN; INSERT INTO t VALUES (2, 'y'); SAVEPOINT s; \
+             UPDATE t SET b = 'z'; DELETE FROM t WHERE a = 1",
+        )
+        .unwrap();
+        // 失敗した操作をセーブポイントまで戻してやり直す
+        assert!(run(&mut bufmgr, "INSERT INTO t VALUES (3, 'w'), (2, 'v')").is_err());
+        run(
+            &mut bufmgr,
+            "ROLLBACK TO s; INSERT INTO t VALUES (3, 'w'); COMMIT",
+        )
+        .unwrap();
+        let plan = plan_sql(&catalog, "SELECT * FROM t ORDER BY a").unwrap();
+        let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+        assert_eq!(
+            execute(&plan, &mut ctx).unwrap(),
+            vec![
+                vec![int(1), text("x")],
+                vec![int(2), text("y")],
+                vec![int(3), text("w")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_snapshot_isolation() {
+        use crate::sql::ast::Statement;
+        use crate::transaction::TransactionManager;
+
+        let (mut bufmgr, catalog) = setup(&[vec![int(1), text("x")], vec![int(2), text("y")]]);
+        let mut run = |manager: &mut TransactionManager, sql: &str| {
+            let mut rows = vec![];
+            for stmt in sql::parse(sql).unwrap() {
+                if let Statement::Transaction(stmt) = &stmt {
+                    manager.execute(stmt, &mut bufmgr, &catalog).unwrap();
+                    continue;
+                }
+                let plan = Planner::new(&catalog).plan_statement(&stmt)?;
+                let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+                ctx.txn = manager.start_statement();
+                rows = execute(&plan, &mut ctx)?;
+            }
+            Ok::<_, Error>(rows)
+        };
+        let mut s1 = TransactionManager::new();
+        let mut s2 = s1.session();
+        let mut s3 = s1.session();
+        run(&mut s1, "BEGIN").unwrap();
+        run(
+            &mut s2,
+            "BEGIN; UPDATE t SET b = 'z' WHERE a = 1; INSERT INTO t VALUES (3, 'w')",
+        )
+        .unwrap();
+        let select = "SELECT * FROM t ORDER BY a";
+        // コミットしていない変更は見えない
+        assert_eq!(
+            run(&mut s1, select).unwrap(),
+            vec![vec![int(1), text("x")], vec![int(2), text("y")]]
+        );
+        run(&mut s2, "COMMIT").unwrap();
+        // 始める前にコミットしたものだけが見える
+        run(&mut s3, "BEGIN").unwrap();
+        assert_eq!(
+            run(&mut s1, select).unwrap(),
+            vec![vec![int(1), text("x")], vec![int(2), text("y")]]
+        );
+        assert_eq!(
+            run(&mut s3, select).unwrap(),
+            vec![
+                vec![int(1), text("z")],
+                vec![int(2), text("y")],
+                vec![int(3), text("w")],
+            ]
+        );
+
+        // 先に書き換えた方が勝ち、後から書いた方は失敗する
+        assert!(matches!(
+            run(&mut s1, "DELETE FROM t WHERE a = 1"),
+            Err(Error::Executor(executor::Error::SerializationFailure))
+        ));
+        run(&mut s1, "UPDATE t SET b = 'v' WHERE a = 2; COMMIT").unwrap();
+        assert!(matches!(
+            run(&mut s3, "UPDATE t SET b = 'u'"),
+            Err(Error::Executor(executor::Error::SerializationFailure))
+        ));
+        // 失敗した文の変更は戻っている
+        assert_eq!(
+            run(&mut s3, "SELECT b FROM t ORDER BY a").unwrap(),
+            vec![vec![text("z")], vec![text("y")], vec![text("w")]]
+        );
+        run(&mut s3, "ROLLBACK").unwrap();
+        run(&mut s3, "BEGIN").unwrap();
+        assert_eq!(
+            run(&mut s3, "SELECT b FROM t ORDER BY a").unwrap(),
+            vec![vec![text("z")], vec![text("v")], vec![text("w")]]
+        );
+    }
+
+    #[test]
+    fn test_plan_locks() {
+        use std::thread;
+
+        use crate::lock::{LockMode, LockTarget};
+        use crate::transaction::{TransactionManager, TxnId};
+
+        let (mut bufmgr, catalog) = setup(&[vec![int(1), text("x")], vec![int(2), text("y")]]);
+        let mut manager = TransactionManager::new();
+        let locks = manager.lock_manager();
+        let id = manager.begin().unwrap();
+        for sql in ["SELECT * FROM t", "UPDATE t SET b = 'z' WHERE a = 2"] {
+            let plan = plan_sql(&catalog, sql).unwrap();
+            let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+            ctx.txn = manager.start_statement();
+            execute(&plan, &mut ctx).unwrap();
+        }
+        let held = locks.locks(id);
+        assert_eq!(held.len(), 2);
+        assert_eq!(
+            held[0],
+            (
+                LockTarget::Table("t".to_string()),
+                LockMode::IntentionExclusive
+            )
+        );
+        assert!(matches!(
+            &held[1],
+            (LockTarget::Row(table, _), LockMode::Exclusive) if table == "t"
+        ));
+
+        // テーブルを作り変えるものは、トランザクションが終わるまで待つ
+        let ddl = {
+            let locks = locks.clone();
+            thread::spawn(move || {
+                let id = TxnId(100);
+                locks
+                    .lock(id, LockTarget::Table("t".to_string()), LockMode::Exclusive)
+                    .unwrap();
+                locks.release_all(id);
+            })
+        };
+        while locks.waiting() == 0 {
+            thread::yield_now();
+        }
+        manager.commit(&mut bufmgr, &catalog).unwrap();
+        ddl.join().unwrap();
+        assert!(locks.locks(id).is_empty());
+    }
+
+    #[test]
+    fn test_plan_vacuum() {
+        use crate::sql::ast::Statement;
+        use crate::transaction::TransactionManager;
+
+        let (mut bufmgr, mut catalog) = setup(&[vec![int(1), text("x")], vec![int(2), text("y")]]);
+        catalog
+            .create_index(&mut bufmgr, "t_a", "t", &["a".to_string()], false)
+            .unwrap();
+        let versions = |bufmgr: &mut BufferPoolManager, catalog: &Catalog| {
+            let table = catalog.table("t").unwrap();
+            let mut heap = 0;
+            let mut scan = table.storage.scan();
+            while scan.next(bufmgr).unwrap().is_some() {
+                heap += 1;
+            }
+            let mut index = 0;
+            let mut scan = table.indexes[0].btree.scan(bufmgr, None).unwrap();
+            while scan.next(bufmgr).unwrap().is_some() {
+                index += 1;
+            }
+            (heap, index)
+        };
+        let run = |bufmgr: &mut BufferPoolManager, manager: &mut TransactionManager, sql: &str| {
+            let mut rows = vec![];
+            for stmt in sql::parse(sql).unwrap() {
+                match &stmt {
+                    Statement::Transaction(stmt) => {
+                        manager.execute(stmt, bufmgr, &catalog).unwrap();
+                    }
+                    Statement::Vacuum { table } => {
+                        let removed = catalog
+                            .vacuum(bufmgr, table.as_deref(), manager.horizon(), |_| false)
+                            .unwrap();
+                        rows = vec![vec![int(removed as i64)]];
+                    }
+                    _ => {
+                        let plan = Planner::new(&catalog).plan_statement(&stmt).unwrap();
+                        let mut ctx = ExecContext::new(bufmgr, &catalog);
+                        ctx.txn = manager.start_statement();
+                        rows = execute(&plan, &mut ctx).unwrap();
+                    }
+                }
+            }
+            rows
+        };
+        let mut s1 = TransactionManager::new();
+        let mut s2 = s1.session();
+        run(&mut bufmgr, &mut s1, "BEGIN");
+        run(
+            &mut bufmgr,
+            &mut s2,
+            "BEGIN; UPDATE t SET b = 'z' WHERE a = 1; DELETE FROM t WHERE a = 2; COMMIT",
+        );
+        assert_eq!(versions(&mut bufmgr, &catalog), (3, 3));
+        // 古いスナップショットから見える版は残す
+        assert_eq!(run(&mut bufmgr, &mut s2, "VACUUM"), vec![vec![int(0)]]);
+        let select = "SELECT b FROM t WHERE a = 1";
+        assert_eq!(run(&mut bufmgr, &mut s1, select), vec![vec![text("x")]]);
+        run(&mut bufmgr, &mut s1, "COMMIT");
+        assert_eq!(run(&mut bufmgr, &mut s2, "VACUUM t"), vec![vec![int(2)]]);
+        assert_eq!(versions(&mut bufmgr, &catalog), (1, 1));
+        assert_eq!(run(&mut bufmgr, &mut s1, select), vec![vec![text("z")]]);
+
+        // トランザクションの外で消した版はすぐに取り除ける
+        run(&mut bufmgr, &mut s1, "DELETE FROM t");
+        assert_eq!(run(&mut bufmgr, &mut s1, "VACUUM"), vec![vec![int(1)]]);
+        assert_eq!(versions(&mut bufmgr, &catalog), (0, 0));
+    }
+
+    #[test]
+    fn test_plan_row_locking() {
+        use std::time::Duration;
+
+        use crate::lock::{self, LockTarget};
+        use crate::sql::ast::{Statement, WaitPolicy};
+        use crate::transaction::TransactionManager;
+
+        let (mut bufmgr, catalog) = setup(&[
+            vec![int(1), text("x")],
+            vec![int(2), text("y")],
+            vec![int(3), text("z")],
+        ]);
+        let mut run = |manager: &mut TransactionManager, sql: &str| {
+            let stmt = sql::parse(sql).unwrap().remove(0);
+            if let Statement::Transaction(stmt) = &stmt {
+                manager.execute(stmt, &mut bufmgr, &catalog).unwrap();
+                return Ok(vec![]);
+            }
+            let plan = Planner::new(&catalog).plan_statement(&stmt).unwrap();
+            let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+            ctx.txn = manager.start_statement();
+            execute(&plan, &mut ctx)
+        };
+        let mut s1 = TransactionManager::new();
+        let mut s2 = s1.session();
+        let locks = s1.lock_manager();
+        let id = s1.begin().unwrap();
+        assert_eq!(
+            run(&mut s1, "SELECT a FROM t WHERE a <= 2 FOR UPDATE").unwrap(),
+            vec![vec![int(1)], vec![int(2)]]
+        );
+        let rows = locks
+            .locks(id)
+            .into_iter()
+            .filter(|(target, mode)| {
+                matches!(target, LockTarget::Row(..)) && *mode == LockMode::Exclusive
+            })
+            .count();
+        assert_eq!(rows, 2);
+
+        // ロックされている行を飛ばして、空いている行を取る
+        s2.begin().unwrap();
+        let queue = "SELECT a FROM t LIMIT 1 FOR UPDATE SKIP LOCKED";
+        assert_eq!(run(&mut s2, queue).unwrap(), vec![vec![int(3)]]);
+        assert_eq!(run(&mut s2, queue).unwrap(), vec![vec![int(3)]]);
+        // NOWAIT と lock_timeout は待たずに、または待ってから失敗する
+        let err = run(&mut s2, "SELECT a FROM t WHERE a = 1 FOR SHARE NOWAIT").unwrap_err();
+        assert!(matches!(
+            err,
+            executor::Error::Lock(lock::Error::LockNotAvailable)
+        ));
+        s2.set_lock_timeout(Some(Duration::from_millis(10)));
+        let err = run(&mut s2, "SELECT a FROM t WHERE a = 2 FOR UPDATE").unwrap_err();
+        assert!(matches!(
+            err,
+            executor::Error::Lock(lock::Error::LockTimeout)
+        ));
+        assert_eq!(locks.waiting(), 0);
+        run(&mut s2, "ROLLBACK").unwrap();
+
+        // ロックを取るまでにほかのトランザクションが書き換えてコミットしていれば失敗する
+        s2.begin().unwrap();
+        run(&mut s1, "UPDATE t SET b = 'w' WHERE a = 1").unwrap();
+        run(&mut s1, "COMMIT").unwrap();
+        let err = run(&mut s2, "SELECT a FROM t WHERE a = 1 FOR UPDATE NOWAIT").unwrap_err();
+        assert!(matches!(err, executor::Error::SerializationFailure));
+        run(&mut s2, "ROLLBACK").unwrap();
+        // トランザクションの外ではロックをかけない
+        assert_eq!(
+            run(&mut s2, "SELECT b FROM t WHERE a = 1 FOR UPDATE").unwrap(),
+            vec![vec![text("w")]]
+        );
+
+        let plan = plan_sql(&catalog, queue).unwrap();
+        let PlanNode::Limit { input, .. } = &plan else {
+            panic!("expected limit, got {:?}", plan);
+        };
+        let PlanNode::Projection { input, .. } = &**input else {
+            panic!("expected projection, got {:?}", input);
+        };
+        assert!(matches!(
+            &**input,
+            PlanNode::LockRows {
+                mode: LockMode::Exclusive,
+                wait: WaitPolicy::SkipLocked,
+                ..
+            }
+        ));
+        for (sql, message) in [
+            (
+                "SELECT count(*) FROM t FOR UPDATE",
+                "FOR UPDATE is not allowed with aggregate functions",
+            ),
+            (
+                "SELECT DISTINCT a FROM t FOR SHARE",
+                "FOR SHARE is not allowed with DISTINCT clause",
+            ),
+            (
+                "SELECT a FROM t UNION SELECT a FROM t FOR UPDATE",
+                "FOR UPDATE is not allowed with UNION/INTERSECT/EXCEPT",
+            ),
+            (
+                "SELECT * FROM t, t AS u FOR UPDATE",
+                "FOR UPDATE on a query that does not read a single table is not supported",
+            ),
+        ] {
+            assert_eq!(plan_sql(&catalog, sql).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_plan_isolation_level() {
+        use crate::sql::ast::{IsolationLevel, Statement};
+        use crate::transaction::{self, TransactionManager};
+
+        let (mut bufmgr, catalog) = setup(&[vec![int(1), text("x")]]);
+        let mut run = |manager: &mut TransactionManager, sql: &str| {
+            let mut rows = vec![];
+            for stmt in sql::parse(sql).unwrap() {
+                if let Statement::Transaction(stmt) = &stmt {
+                    manager.execute(stmt, &mut bufmgr, &catalog)?;
+                    continue;
+                }
+                let plan = Planner::new(&catalog).plan_statement(&stmt).unwrap();
+                let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+                ctx.txn = manager.start_statement();
+                rows = execute(&plan, &mut ctx).unwrap();
+            }
+            Ok::<_, transaction::Error>(rows)
+        };
+        let mut read_committed = TransactionManager::new();
+        let mut repeatable_read = read_committed.session();
+        let mut writer = read_committed.session();
+        run(
+            &mut read_committed,
+            "BEGIN; SET TRANSACTION ISOLATION LEVEL READ COMMITTED",
+        )
+        .unwrap();
+        assert_eq!(
+            read_committed.current().unwrap().isolation_level(),
+            IsolationLevel::ReadCommitted
+        );
+        repeatable_read.begin().unwrap();
+        let select = "SELECT b FROM t ORDER BY a";
+        assert_eq!(
+            run(&mut read_committed, select).unwrap(),
+            vec![vec![text("x")]]
+        );
+        assert_eq!(
+            run(&mut repeatable_read, select).unwrap(),
+            vec![vec![text("x")]]
+        );
+        run(&mut writer, "BEGIN; INSERT INTO t VALUES (2, 'y'); COMMIT").unwrap();
+        // READ COMMITTED は文ごとに、それまでにコミットした変更が見える
+        assert_eq!(
+            run(&mut read_committed, select).unwrap(),
+            vec![vec![text("x")], vec![text("y")]]
+        );
+        assert_eq!(
+            run(&mut repeatable_read, select).unwrap(),
+            vec![vec![text("x")]]
+        );
+        assert!(matches!(
+            run(
+                &mut repeatable_read,
+                "SET TRANSACTION ISOLATION LEVEL READ COMMITTED"
+            ),
+            Err(transaction::Error::IsolationLevelAfterQuery)
+        ));
+        run(&mut read_committed, "COMMIT").unwrap();
+        run(&mut repeatable_read, "COMMIT").unwrap();
+
+        // 埋め込む側からはセッションの既定の分離レベルを変えられる
+        writer.set_default_isolation_level(IsolationLevel::ReadCommitted);
+        writer.begin().unwrap();
+        assert_eq!(
+            writer.current().unwrap().isolation_level(),
+            IsolationLevel::ReadCommitted
+        );
+        run(
+            &mut read_committed,
+            "BEGIN; SET TRANSACTION ISOLATION LEVEL REPEATABLE READ; \
+             INSERT INTO t VALUES (3, 'z'); COMMIT",
+        )
+        .unwrap();
+        assert_eq!(run(&mut writer, select).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_plan_serializable() {
+        use crate::sql::ast::Statement;
+        use crate::transaction::{self, TransactionManager};
+
+        // 2 人の当番のうち、ほかに当番がいれば自分を外す
+        let (mut bufmgr, catalog) = setup(&[vec![int(1), text("on")], vec![int(2), text("on")]]);
+        let mut run = |manager: &mut TransactionManager, sql: &str| {
+            let mut rows = vec![];
+            for stmt in sql::parse(sql).unwrap() {
+                if let Statement::Transaction(stmt) = &stmt {
+                    manager.execute(stmt, &mut bufmgr, &catalog)?;
+                    continue;
+                }
+                let plan = Planner::new(&catalog).plan_statement(&stmt).unwrap();
+                let mut ctx = ExecContext::new(&mut bufmgr, &catalog);
+                ctx.txn = manager.start_statement();
+                rows = execute(&plan, &mut ctx).unwrap();
+            }
+            Ok::<_, transaction::Error>(rows)
+        };
+        let count = "SELECT count(*) FROM t WHERE b = 'on'";
+        let mut s1 = TransactionManager::new();
+        let mut s2 = s1.session();
+        let mut s3 = s1.session();
+        let skew = |level: &str| {
+            [
+                format!("BEGIN; SET TRANSACTION ISOLATION LEVEL {level}; {count}"),
+                "UPDATE t SET b = 'off' WHERE a = 1".to_string(),
+                "UPDATE t SET b = 'off' WHERE a = 2".to_string(),
+            ]
+        };
+
+        // REPEATABLE READ では両方コミットでき、当番がいなくなる
+        let [begin, off1, off2] = skew("REPEATABLE READ");
+        assert_eq!(run(&mut s1, &begin).unwrap(), vec![vec![int(2)]]);
+        assert_eq!(run(&mut s2, &begin).unwrap(), vec![vec![int(2)]]);
+        run(&mut s1, &off1).unwrap();
+        run(&mut s2, &off2).unwrap();
+        run(&mut s1, "COMMIT").unwrap();
+        run(&mut s2, "COMMIT").unwrap();
+        assert_eq!(run(&mut s3, count).unwrap(), vec![vec![int(0)]]);
+        run(&mut s3, "UPDATE t SET b = 'on'").unwrap();
+
+        // SERIALIZABLE では片方のコミットが失敗し、取り消される
+        let [begin, off1, off2] = skew("SERIALIZABLE");
+        run(&mut s1, &begin).unwrap();
+        run(&mut s2, &begin).unwrap();
+        // 読むだけのトランザクションは巻き込まない
+        run(
+            &mut s3,
+            &format!("BEGIN; SET TRANSACTION ISOLATION LEVEL SERIALIZABLE; {count}"),
+        )
+        .unwrap();
+        run(&mut s1, &off1).unwrap();
+        run(&mut s2, &off2).unwrap();
+        assert!(matches!(
+            run(&mut s1, "COMMIT"),
+            Err(transaction::Error::SerializationFailure)
+        ));
+        assert!(!s1.in_transaction());
+        run(&mut s2, "COMMIT").unwrap();
+        assert_eq!(
+            run(&mut s3, "SELECT count(*) FROM t WHERE b = 'on'; COMMIT").unwrap(),
+            vec![vec![int(2)]]
+        );
+        // やり直せば成功する
+        assert_eq!(run(&mut s1, &begin).unwrap(), vec![vec![int(1)]]);
+        run(&mut s1, "COMMIT").unwrap();
+        assert_eq!(run(&mut s3, count).unwrap(), vec![vec![int(1)]]);
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        let (_bufmgr, catalog) = setup(&[]);
+        for (sql, message) in [
+            ("SELECT c FROM t", "column \"c\" does not exist"),
+            (
+                "SELECT u.a FROM t",
+                "missing FROM-clause entry for table \"u\"",
+            ),
+            ("SELECT t.c FROM t", "column \"t.c\" does not exist"),
+            ("SELECT * FROM missing", "table \"missing\" does not exist"),
+            ("SELECT upper(a, b) FROM t", "function upper does not exist"),
+            ("SELECT *", "SELECT * with no tables specified is not valid"),
+            (
+                "SELECT a FROM t l, t r",
+                "column reference \"a\" is ambiguous",
+            ),
+            (
+                "SELECT 1 FROM t, t",
+                "table name \"t\" specified more than once",
+            ),
+            (
+                "SELECT a FROM t GROUP BY b",
+                "column \"t.a\" must appear in the GROUP BY clause or be used in an aggregate function",
+            ),
+            (
+                "SELECT a FROM t WHERE count(*) > 1",
+                "aggregate functions are not allowed in WHERE",
+            ),
+            (
+                "SELECT sum(count(*)) FROM t",
+                "aggregate function calls cannot be nested",
+            ),
+            (
+                "SELECT a FROM t ORDER BY 2",
+                "ORDER BY position 2 is not in select list",
+            ),
+            (
+                "SELECT DISTINCT a FROM t ORDER BY b",
+                "for SELECT DISTINCT, ORDER BY expressions must appear in select list",
+            ),
+            ("SELECT a FROM t LIMIT -1", "LIMIT must not be negative"),
+            (
+                "SELECT a FROM t OFFSET 'x'",
+                "argument of OFFSET must be type integer, not type text",
+            ),
+        ] {
+            assert_eq!(plan_sql(&catalog, sql).unwrap_err().to_string(), message);
+        }
+    }
+}
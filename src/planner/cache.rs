@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::executor::PlanNode;
+use crate::sql::{self, ast::Statement};
+use crate::types::{DataType, Value};
+
+use super::{CostSettings, Error, Planner};
+
+// 文の文字列ごとに、作った計画をためておく
+//
+// 計画はカタログの版と計画の設定、ロールが同じ間だけ使い回す。
+// 版は DDL と ANALYZE で進むので、テーブルやインデックス、統計、権限が変われば作り直す。
+// $n は実行するときに値を入れるので、値の型が同じならどの値にも使う。
+// LIMIT $1 のように計画に $n の値が要るものだけは、同じ値で計画したものを使う。
+// volatile な関数を呼ぶものや TTL で行を選ぶものは、計画したときの値で結果が変わりうるのでためない
+#[derive(Debug, Default)]
+pub struct PlanCache {
+    entries: HashMap<String, CachedPlan>,
+    hits: u64,
+}
+
+#[derive(Debug)]
+struct CachedPlan {
+    version: u64,
+    parallel_workers: usize,
+    costs: CostSettings,
+    ttl_filter: bool,
+    user: Option<String>,
+    param_types: Vec<Option<DataType>>,
+    // 計画に $n の値を使ったときの値
+    params: Option<Vec<Value>>,
+    plan: Rc<PlanNode>,
+}
+
+fn param_types(params: &[Value]) -> Vec<Option<DataType>> {
+    params.iter().map(Value::data_type).collect()
+}
+
+impl PlanCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // sql の計画。使える計画がためてあれば、解析も計画もせずにそれを返す
+    pub fn plan(&mut self, planner: &Planner, sql: &str) -> Result<Rc<PlanNode>, Error> {
+        if let Some(plan) = self.get(planner, sql) {
+            return Ok(plan);
+        }
+        let mut statements = sql::parse(sql)?;
+        if statements.len() != 1 {
+            return Err(Error::MultipleStatements);
+        }
+        self.insert(planner, sql, &statements.remove(0))
+    }
+
+    // 解析した文が stmt の sql の計画
+    pub fn plan_statement(
+        &mut self,
+        planner: &Planner,
+        sql: &str,
+        stmt: &Statement,
+    ) -> Result<Rc<PlanNode>, Error> {
+        match self.get(planner, sql) {
+            Some(plan) => Ok(plan),
+            None => self.insert(planner, sql, stmt),
+        }
+    }
+
+    fn get(&mut self, planner: &Planner, sql: &str) -> Option<Rc<PlanNode>> {
+        let entry = self.entries.get(sql)?;
+        let usable = entry.version == planner.catalog.version()
+            && entry.parallel_workers == planner.parallel_workers
+            && entry.costs == planner.costs
+            && entry.ttl_filter == planner.ttl_filter
+            && entry.user == planner.user
+            && entry.param_types == param_types(planner.params)
+            && entry.params.as_deref().is_none_or(|p| p == planner.params);
+        if !usable {
+            return None;
+        }
+        let plan = match entry.params {
+            None if !planner.params.is_empty() => {
+                let mut plan = (*entry.plan).clone();
+                plan.bind_params(planner.params).ok()?;
+                Rc::new(plan)
+            }
+            _ => entry.plan.clone(),
+        };
+        self.hits += 1;
+        Some(plan)
+    }
+
+    fn insert(
+        &mut self,
+        planner: &Planner,
+        sql: &str,
+        stmt: &Statement,
+    ) -> Result<Rc<PlanNode>, Error> {
+        let generic = match planner.params {
+            [] => None,
+            _ => planner.plan_generic(stmt),
+        };
+        // ためるものと、今の値で実行するもの
+        let (cached, plan, params) = match generic {
+            Some(generic) => {
+                let mut plan = generic.clone();
+                plan.bind_params(planner.params)?;
+                (Rc::new(generic), Rc::new(plan), None)
+            }
+            None => {
+                let plan = Rc::new(planner.plan_statement(stmt)?);
+                let params = (!planner.params.is_empty()).then(|| planner.params.to_vec());
+                (plan.clone(), plan, params)
+            }
+        };
+        if planner.uses_volatile() {
+            self.entries.remove(sql);
+            return Ok(plan);
+        }
+        self.entries.insert(
+            sql.to_string(),
+            CachedPlan {
+                version: planner.catalog.version(),
+                parallel_workers: planner.parallel_workers,
+                costs: planner.costs.clone(),
+                ttl_filter: planner.ttl_filter,
+                user: planner.user.clone(),
+                param_types: param_types(planner.params),
+                params,
+                plan: cached,
+            },
+        );
+        Ok(plan)
+    }
+
+    pub fn remove(&mut self, sql: &str) {
+        self.entries.remove(sql);
+    }
+
+    // 今のカタログの版で作ったもの以外を捨てる
+    pub fn evict_stale(&mut self, version: u64) {
+        self.entries.retain(|_, entry| entry.version == version);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // 作らずにためたものを返した回数
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
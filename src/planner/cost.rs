@@ -0,0 +1,935 @@
+use crate::catalog::{Catalog, ColumnStats};
+use crate::disk::PAGE_SIZE;
+use crate::executor::expr::Expr;
+use crate::executor::{IndexRange, JoinType, PlanNode, ScanBound, DEFAULT_WORK_MEM};
+use crate::fulltext;
+use crate::rtree::Search;
+use crate::sql::ast::{BinaryOp, Hint, SampleMethod, SetOperator, UnaryOp};
+use crate::types::Value;
+
+use super::match_condition;
+
+// 行数がわからない入力の行数
+pub(super) const DEFAULT_ROWS: f64 = 1000.0;
+// 等号、大小比較、同じ列の上下からの大小比較、それ以外の条件を満たす行の割合
+const EQ_SELECTIVITY: f64 = 0.005;
+const RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+const BETWEEN_SELECTIVITY: f64 = 0.005;
+const DEFAULT_SELECTIVITY: f64 = 0.5;
+// 全文検索の語 1 つを含む行の割合。引く語はたいてい少ない行にしか現れない
+const MATCH_SELECTIVITY: f64 = 0.001;
+// 図形の重なりや包含の条件を満たす行の割合
+const SPATIAL_SELECTIVITY: f64 = 0.001;
+// unnest で 1 行から作る行の数
+const ARRAY_LENGTH: f64 = 10.0;
+// WITH RECURSIVE の再帰項を実行する回数
+const RECURSION_STEPS: f64 = 10.0;
+// 行の大きさの見積もり。列 1 つあたりと、行ごとの見出しやスロットの分
+const COLUMN_WIDTH: f64 = 16.0;
+const TUPLE_OVERHEAD: f64 = 8.0;
+
+// 計画どうしを比べるための費用の定数。ページを 1 つ順に読む費用を 1 とする
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostSettings {
+    pub seq_page_cost: f64,
+    // 離れたページを 1 つ読む費用
+    pub random_page_cost: f64,
+    // 1 行を処理する費用
+    pub cpu_tuple_cost: f64,
+    // 演算子や関数を 1 回評価する費用
+    pub cpu_operator_cost: f64,
+    // ハッシュ表や並べ替えに使えるメモリ。これを超える入力は一時ファイルに書き出すとみる
+    pub work_mem: usize,
+}
+
+impl Default for CostSettings {
+    fn default() -> Self {
+        Self {
+            seq_page_cost: 1.0,
+            random_page_cost: 4.0,
+            cpu_tuple_cost: 0.01,
+            cpu_operator_cost: 0.0025,
+            work_mem: DEFAULT_WORK_MEM,
+        }
+    }
+}
+
+// 計画の行数と費用を見積もる
+pub(super) struct CostModel<'a> {
+    pub catalog: &'a Catalog,
+    pub settings: &'a CostSettings,
+    // 別名をテーブルの名前に直したヒント
+    pub hints: Vec<Hint>,
+}
+
+impl<'a> CostModel<'a> {
+    // 計画が返す行の数
+    pub fn rows(&self, plan: &PlanNode) -> f64 {
+        let rows = |plan: &PlanNode| self.rows(plan);
+        let predicate = |predicate: &Option<Expr>| {
+            predicate
+                .as_ref()
+                .map_or(1.0, |p| self.selectivity(plan, p))
+        };
+        let estimate = match plan {
+            PlanNode::SeqScan { table, .. } => self.table_rows(table),
+            PlanNode::SampleScan {
+                table, fraction, ..
+            } => self.table_rows(table) * fraction,
+            PlanNode::Gather {
+                table,
+                predicate: p,
+                ..
+            } => self.table_rows(table) * predicate(p),
+            PlanNode::IndexScan {
+                table,
+                index,
+                range,
+                predicate: p,
+                ..
+            } => {
+                self.table_rows(table) * self.index_selectivity(table, index, range) * predicate(p)
+            }
+            PlanNode::FulltextScan {
+                table,
+                terms,
+                predicate: p,
+                ..
+            } => self.table_rows(table) * match_selectivity(terms.len()) * predicate(p),
+            PlanNode::RTreeScan {
+                table,
+                search,
+                predicate: p,
+                ..
+            } => self.table_rows(table) * search_selectivity(search) * predicate(p),
+            PlanNode::Values { rows } => rows.len() as f64,
+            PlanNode::CteScan { .. } | PlanNode::StatsScan { .. } => DEFAULT_ROWS,
+            PlanNode::Filter { input, predicate } => {
+                rows(input) * self.selectivity(input, predicate)
+            }
+            // 捨てるのは結合で一致しない行だけなので、結合の見積もりは変えない
+            PlanNode::RuntimeFilter { input, .. } => rows(input),
+            // 実行するまでどの子を読むかわからないので、読むとみる
+            PlanNode::PartitionPrune { input, .. } => rows(input),
+            // 昔の版の数はわからないので、いまの行数で見積もる
+            PlanNode::AsOf { input, .. } => rows(input),
+            PlanNode::NestedLoopJoin {
+                left,
+                right,
+                predicate: p,
+                join_type,
+            } => join_rows(rows(left), rows(right), *join_type, predicate(p)),
+            // 右は左の 1 行に対して返す行の数
+            PlanNode::LateralJoin {
+                left,
+                right,
+                predicate: p,
+                join_type,
+                ..
+            } => join_rows(rows(left), rows(right), *join_type, predicate(p)),
+            PlanNode::HashJoin {
+                left,
+                right,
+                left_keys,
+                right_keys,
+                predicate: p,
+                join_type,
+                ..
+            } => join_rows(
+                rows(left),
+                rows(right),
+                *join_type,
+                self.key_selectivity(left, right, left_keys, right_keys) * predicate(p),
+            ),
+            PlanNode::MergeJoin {
+                left,
+                right,
+                left_keys,
+                right_keys,
+                predicate: p,
+            } => join_rows(
+                rows(left),
+                rows(right),
+                JoinType::Inner,
+                self.key_selectivity(left, right, left_keys, right_keys) * predicate(p),
+            ),
+            PlanNode::IndexJoin {
+                left,
+                table,
+                index,
+                keys,
+                predicate: p,
+            } => {
+                let (l, r) = (rows(left), self.table_rows(table));
+                let index = self.catalog.table(table).and_then(|t| t.index(index));
+                let keys = keys
+                    .iter()
+                    .enumerate()
+                    .map(|(i, key)| {
+                        let right =
+                            index.and_then(|index| self.table_stats(table, *index.columns.get(i)?));
+                        1.0 / distinct(self.key_stats(left, key), l).max(distinct(right, r))
+                    })
+                    .product::<f64>();
+                join_rows(l, r, JoinType::Inner, keys * predicate(p))
+            }
+            PlanNode::Sort { input, top_n, .. } => {
+                let n = rows(input);
+                top_n.map_or(n, |top_n| n.min(top_n as f64))
+            }
+            PlanNode::Limit {
+                input,
+                limit,
+                offset,
+            } => {
+                let n = (rows(input) - *offset as f64).max(0.0);
+                limit.map_or(n, |limit| n.min(limit as f64))
+            }
+            // グループの数は、統計があればキーの値の種類の数の積とみる
+            PlanNode::HashAggregate {
+                input, group_by, ..
+            }
+            | PlanNode::GroupAggregate {
+                input, group_by, ..
+            }
+            | PlanNode::ParallelAggregate {
+                input, group_by, ..
+            } => self.groups(input, &group_by.iter().collect::<Vec<_>>()),
+            // 集合ごとのグループの数の和
+            PlanNode::GroupingSets {
+                input,
+                group_by,
+                sets,
+                ..
+            } => sets
+                .iter()
+                .map(|set| {
+                    let keys = set.iter().map(|&i| &group_by[i]).collect::<Vec<_>>();
+                    self.groups(input, &keys)
+                })
+                .sum(),
+            PlanNode::Unique { input }
+            | PlanNode::Window { input, .. }
+            | PlanNode::LockRows { input, .. }
+            | PlanNode::Materialize { input }
+            | PlanNode::With { input, .. }
+            | PlanNode::Projection { input, .. } => rows(input),
+            PlanNode::ProjectSet { input, .. } => rows(input) * ARRAY_LENGTH,
+            PlanNode::Append { left, right } => rows(left) + rows(right),
+            PlanNode::RecursiveUnion { anchor, .. } => rows(anchor) * RECURSION_STEPS,
+            PlanNode::HashSetOp {
+                op, left, right, ..
+            } => match op {
+                SetOperator::Intersect => rows(left).min(rows(right)),
+                _ => rows(left),
+            },
+            PlanNode::Insert { .. } | PlanNode::Update { .. } | PlanNode::Delete { .. } => 1.0,
+            PlanNode::ExplainAnalyze { nodes, .. } => nodes.len() as f64,
+        };
+        estimate.max(1.0)
+    }
+
+    // 計画を最後まで実行する費用
+    pub fn cost(&self, plan: &PlanNode) -> f64 {
+        let s = self.settings;
+        let rows = |plan: &PlanNode| self.rows(plan);
+        let cost = |plan: &PlanNode| self.cost(plan);
+        let operators = |predicate: &Option<Expr>| predicate.as_ref().map_or(0.0, operators);
+        let output = rows(plan) * s.cpu_tuple_cost;
+        let children = match plan {
+            PlanNode::SeqScan { table, .. } => return self.scan_cost(table),
+            // SYSTEM は選んだページだけを読み、BERNOULLI はすべての行を見る
+            PlanNode::SampleScan {
+                table,
+                method,
+                fraction,
+                ..
+            } => {
+                return match method {
+                    SampleMethod::System => self.scan_cost(table) * fraction,
+                    SampleMethod::Bernoulli => self.scan_cost(table),
+                }
+            }
+            PlanNode::Gather {
+                table, predicate, ..
+            } => {
+                // ページはすべてこのスレッドで読み、ワーカーから行を受け取り直すので、順に読んで絞り込むより安くは見積もらない
+                self.scan_cost(table)
+                    + self.table_rows(table) * operators(predicate) * s.cpu_operator_cost
+            }
+            PlanNode::IndexScan {
+                table,
+                index,
+                range,
+                predicate,
+                index_only,
+            } => {
+                let matched =
+                    (self.table_rows(table) * self.index_selectivity(table, index, range)).max(1.0);
+                let width = (range.prefix.len() + 1) as f64 * COLUMN_WIDTH + TUPLE_OVERHEAD;
+                // 根から葉までたどり、範囲の葉を順に読み、行ごとにヒープのページを読む
+                let mut cost = s.random_page_cost
+                    + pages(matched * width) * s.seq_page_cost
+                    + matched * (s.cpu_tuple_cost + operators(predicate) * s.cpu_operator_cost);
+                if !index_only {
+                    cost += matched * s.random_page_cost;
+                }
+                return cost;
+            }
+            // 語ごとに根から葉までたどってキーを読み、重なりを除いた行ごとにヒープのページを読む
+            PlanNode::FulltextScan {
+                table,
+                terms,
+                predicate,
+                ..
+            } => {
+                let rows = self.table_rows(table);
+                let postings = rows * MATCH_SELECTIVITY * terms.len() as f64;
+                let matched = (rows * match_selectivity(terms.len())).max(1.0);
+                let width = COLUMN_WIDTH + TUPLE_OVERHEAD;
+                return terms.len() as f64 * s.random_page_cost
+                    + pages(postings * width) * s.seq_page_cost
+                    + matched
+                        * (s.random_page_cost
+                            + s.cpu_tuple_cost
+                            + operators(predicate) * s.cpu_operator_cost);
+            }
+            // 根から重なる枝をたどり、見つけた行ごとにヒープのページを読む
+            PlanNode::RTreeScan {
+                table,
+                search,
+                predicate,
+                ..
+            } => {
+                let matched = (self.table_rows(table) * search_selectivity(search)).max(1.0);
+                let width = 4.0 * COLUMN_WIDTH + TUPLE_OVERHEAD;
+                return s.random_page_cost
+                    + pages(matched * width) * s.random_page_cost
+                    + matched
+                        * (s.random_page_cost
+                            + s.cpu_tuple_cost
+                            + operators(predicate) * s.cpu_operator_cost);
+            }
+            PlanNode::Values { .. } | PlanNode::CteScan { .. } | PlanNode::StatsScan { .. } => 0.0,
+            PlanNode::Filter { input, predicate } => {
+                cost(input) + rows(input) * self::operators(predicate) * s.cpu_operator_cost
+            }
+            PlanNode::RuntimeFilter { input, keys, .. } => {
+                cost(input) + rows(input) * keys.len() as f64 * s.cpu_operator_cost
+            }
+            PlanNode::AsOf { input, .. } | PlanNode::PartitionPrune { input, .. } => cost(input),
+            PlanNode::Projection { input, exprs, .. } | PlanNode::ProjectSet { input, exprs } => {
+                let ops = exprs.iter().map(self::operators).sum::<f64>();
+                cost(input) + rows(input) * ops * s.cpu_operator_cost
+            }
+            // 左の行ごとに右を読み直す
+            PlanNode::NestedLoopJoin {
+                left,
+                right,
+                predicate,
+                ..
+            } => {
+                let (l, r) = (rows(left), rows(right));
+                cost(left)
+                    + cost(right)
+                    + (l - 1.0).max(0.0) * self.rescan_cost(right)
+                    + l * r * (s.cpu_tuple_cost + operators(predicate) * s.cpu_operator_cost)
+            }
+            // 左の行ごとに右を始めから実行し直す
+            PlanNode::LateralJoin {
+                left,
+                right,
+                predicate,
+                ..
+            } => {
+                let (l, r) = (rows(left), rows(right));
+                cost(left)
+                    + l * cost(right)
+                    + l * r * (s.cpu_tuple_cost + operators(predicate) * s.cpu_operator_cost)
+            }
+            // 小さい方がメモリに収まらなければ、両側を一度書き出して読み直す
+            PlanNode::HashJoin {
+                left,
+                right,
+                left_keys,
+                predicate,
+                ..
+            } => {
+                let (l, r) = (rows(left), rows(right));
+                let (lb, rb) = (l * self.width(left), r * self.width(right));
+                let spill = if lb.min(rb) > s.work_mem as f64 {
+                    2.0 * (pages(lb) + pages(rb)) * s.seq_page_cost
+                } else {
+                    0.0
+                };
+                cost(left)
+                    + cost(right)
+                    + (l + r) * (s.cpu_tuple_cost + left_keys.len() as f64 * s.cpu_operator_cost)
+                    + rows(plan) * operators(predicate) * s.cpu_operator_cost
+                    + spill
+            }
+            PlanNode::MergeJoin {
+                left,
+                right,
+                left_keys,
+                predicate,
+                ..
+            } => {
+                let (l, r) = (rows(left), rows(right));
+                cost(left)
+                    + cost(right)
+                    + (l + r) * (s.cpu_tuple_cost + left_keys.len() as f64 * s.cpu_operator_cost)
+                    + rows(plan) * operators(predicate) * s.cpu_operator_cost
+            }
+            // 左の行ごとにインデックスをたどり、一致した行のヒープのページを読む
+            PlanNode::IndexJoin {
+                left,
+                keys,
+                predicate,
+                ..
+            } => {
+                let l = rows(left);
+                let matched = rows(plan) / l;
+                cost(left)
+                    + l * (s.random_page_cost
+                        + keys.len() as f64 * s.cpu_operator_cost
+                        + matched * (s.random_page_cost + s.cpu_tuple_cost))
+                    + rows(plan) * operators(predicate) * s.cpu_operator_cost
+            }
+            PlanNode::Sort {
+                input, keys, top_n, ..
+            } => {
+                let n = rows(input);
+                let kept = top_n.map_or(n, |top_n| n.min(top_n as f64));
+                let compare = n * kept.max(2.0).log2() * keys.len() as f64 * s.cpu_operator_cost;
+                let spill = match top_n {
+                    Some(_) => 0.0,
+                    None => self.spill_cost(n * self.width(input)),
+                };
+                cost(input) + compare + spill
+            }
+            PlanNode::HashAggregate {
+                input,
+                group_by,
+                aggregates,
+            } => {
+                let ops = (group_by.len() + aggregates.len()) as f64;
+                cost(input)
+                    + rows(input) * ops * s.cpu_operator_cost
+                    + self.spill_cost(rows(plan) * self.width(plan))
+            }
+            // 行ごとの集約はワーカーに分かれ、このスレッドはワーカーごとのグループをまとめる
+            PlanNode::ParallelAggregate {
+                input,
+                group_by,
+                aggregates,
+            } => {
+                let workers = match &**input {
+                    PlanNode::Gather { workers, .. } => (*workers).max(1) as f64,
+                    _ => 1.0,
+                };
+                let ops = (group_by.len() + aggregates.len()) as f64;
+                cost(input)
+                    + rows(input) * ops * s.cpu_operator_cost / workers
+                    + workers * rows(plan) * aggregates.len() as f64 * s.cpu_operator_cost
+            }
+            // 入力の行ごとに集合の数だけグループを更新する
+            PlanNode::GroupingSets {
+                input,
+                group_by,
+                sets,
+                aggregates,
+            } => {
+                let ops = (group_by.len() + aggregates.len() * sets.len()) as f64;
+                cost(input)
+                    + rows(input) * ops * s.cpu_operator_cost
+                    + self.spill_cost(rows(plan) * self.width(plan))
+            }
+            // 今のグループだけを持つので書き出さない
+            PlanNode::GroupAggregate {
+                input,
+                group_by,
+                aggregates,
+            } => {
+                let ops = (group_by.len() + aggregates.len()) as f64;
+                cost(input) + rows(input) * ops * s.cpu_operator_cost
+            }
+            PlanNode::Materialize { input } => {
+                cost(input) + self.spill_cost(rows(input) * self.width(input))
+            }
+            // WITH の問い合わせはそれぞれ一度実行して結果をためる
+            PlanNode::With { ctes, input } => {
+                let ctes = ctes
+                    .iter()
+                    .map(|(_, plan)| {
+                        cost(plan)
+                            + rows(plan) * s.cpu_tuple_cost
+                            + self.spill_cost(rows(plan) * self.width(plan))
+                    })
+                    .sum::<f64>();
+                ctes + cost(input)
+            }
+            PlanNode::Limit { input, .. }
+            | PlanNode::Unique { input }
+            | PlanNode::Window { input, .. }
+            | PlanNode::Insert { input, .. }
+            | PlanNode::Update { input, .. }
+            | PlanNode::Delete { input, .. }
+            | PlanNode::LockRows { input, .. }
+            | PlanNode::ExplainAnalyze { input, .. } => cost(input),
+            PlanNode::Append { left, right } | PlanNode::HashSetOp { left, right, .. } => {
+                cost(left) + cost(right)
+            }
+            PlanNode::RecursiveUnion {
+                anchor, recursive, ..
+            } => cost(anchor) + cost(recursive) * RECURSION_STEPS,
+        };
+        children + output
+    }
+
+    // 入れ子ループ結合の内側を読み直す費用。ためてある行は読み直すだけで済む
+    fn rescan_cost(&self, plan: &PlanNode) -> f64 {
+        match plan {
+            PlanNode::Materialize { .. } | PlanNode::Values { .. } | PlanNode::CteScan { .. } => {
+                self.rows(plan) * self.settings.cpu_tuple_cost
+            }
+            plan => self.cost(plan),
+        }
+    }
+
+    fn scan_cost(&self, table: &str) -> f64 {
+        let rows = self.table_rows(table);
+        let width = self
+            .catalog
+            .table(table)
+            .map_or(COLUMN_WIDTH, |t| t.columns.len() as f64 * COLUMN_WIDTH)
+            + TUPLE_OVERHEAD;
+        pages(rows * width) * self.settings.seq_page_cost + rows * self.settings.cpu_tuple_cost
+    }
+
+    // work_mem を超える大きさなら、一時ファイルに書いて読み直す費用
+    // plan の出力が work_mem に収まる見積もりか
+    pub fn fits_in_memory(&self, plan: &PlanNode) -> bool {
+        self.rows(plan) * self.width(plan) <= self.settings.work_mem as f64
+    }
+
+    fn spill_cost(&self, bytes: f64) -> f64 {
+        if bytes > self.settings.work_mem as f64 {
+            2.0 * pages(bytes) * self.settings.seq_page_cost
+        } else {
+            0.0
+        }
+    }
+
+    // predicate を満たす input の行の割合
+    pub fn selectivity(&self, input: &PlanNode, predicate: &Expr) -> f64 {
+        selectivity(predicate, &|column| self.column_stats(input, column))
+    }
+
+    // 計画の出力の column 番目の列がテーブルの列をそのまま返すものなら、その列の統計
+    pub fn column_stats(&self, plan: &PlanNode, column: usize) -> Option<&'a ColumnStats> {
+        match plan {
+            PlanNode::SeqScan { table, .. }
+            | PlanNode::SampleScan { table, .. }
+            | PlanNode::IndexScan { table, .. }
+            | PlanNode::FulltextScan { table, .. }
+            | PlanNode::RTreeScan { table, .. }
+            | PlanNode::Gather { table, .. } => self.table_stats(table, column),
+            PlanNode::Filter { input, .. }
+            | PlanNode::RuntimeFilter { input, .. }
+            | PlanNode::PartitionPrune { input, .. }
+            | PlanNode::AsOf { input, .. }
+            | PlanNode::Sort { input, .. }
+            | PlanNode::Limit { input, .. }
+            | PlanNode::Unique { input }
+            | PlanNode::Window { input, .. }
+            | PlanNode::Materialize { input }
+            | PlanNode::With { input, .. } => self.column_stats(input, column),
+            PlanNode::Projection { input, exprs, .. } => self.key_stats(input, exprs.get(column)?),
+            // 配列の要素の列の統計はない
+            PlanNode::ProjectSet { input, .. } => {
+                let width = input.columns(self.catalog).ok()?.len();
+                (column < width).then(|| self.column_stats(input, column))?
+            }
+            PlanNode::NestedLoopJoin { left, right, .. }
+            | PlanNode::HashJoin { left, right, .. }
+            | PlanNode::MergeJoin { left, right, .. } => {
+                let width = left.columns(self.catalog).ok()?.len();
+                match column.checked_sub(width) {
+                    None => self.column_stats(left, column),
+                    Some(column) => self.column_stats(right, column),
+                }
+            }
+            PlanNode::IndexJoin { left, table, .. } => {
+                let width = left.columns(self.catalog).ok()?.len();
+                match column.checked_sub(width) {
+                    None => self.column_stats(left, column),
+                    Some(column) => self.table_stats(table, column),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // 式が入力の列そのものなら、その列の統計
+    // keys でグループ分けしたグループの数
+    fn groups(&self, input: &PlanNode, keys: &[&Expr]) -> f64 {
+        let n = self.rows(input);
+        let distinct = keys
+            .iter()
+            .map(|key| self.key_stats(input, key).map(|s| s.distinct))
+            .product::<Option<f64>>();
+        match distinct {
+            _ if keys.is_empty() => 1.0,
+            Some(distinct) => distinct.min(n),
+            None => n * DEFAULT_SELECTIVITY,
+        }
+    }
+
+    fn key_stats(&self, input: &PlanNode, key: &Expr) -> Option<&'a ColumnStats> {
+        match key {
+            Expr::Column(c) => self.column_stats(input, *c),
+            _ => None,
+        }
+    }
+
+    fn table_stats(&self, table: &str, column: usize) -> Option<&'a ColumnStats> {
+        self.catalog
+            .table(table)?
+            .stats
+            .as_ref()?
+            .columns
+            .get(column)
+    }
+
+    // インデックスの範囲に入る行の割合
+    fn index_selectivity(&self, table: &str, index: &str, range: &IndexRange) -> f64 {
+        let index = self.catalog.table(table).and_then(|t| t.index(index));
+        range_selectivity(range, &|i| {
+            index.and_then(|index| self.table_stats(table, *index.columns.get(i)?))
+        })
+    }
+
+    // 等結合のキーを満たす組の割合。キーごとに、値の種類の多い方の各値と 1 つずつ結び付くとみる。
+    // 値の種類の数がわからなければ行数とする
+    pub fn key_selectivity(
+        &self,
+        left: &PlanNode,
+        right: &PlanNode,
+        left_keys: &[Expr],
+        right_keys: &[Expr],
+    ) -> f64 {
+        let distinct =
+            |plan: &PlanNode, key: &Expr| distinct(self.key_stats(plan, key), self.rows(plan));
+        left_keys
+            .iter()
+            .zip(right_keys)
+            .map(|(l, r)| 1.0 / distinct(left, l).max(distinct(right, r)))
+            .product()
+    }
+
+    // probe の行のうち、キーが build のどれかの行と一致すると見込む割合
+    pub fn match_fraction(
+        &self,
+        probe: &PlanNode,
+        build: &PlanNode,
+        probe_keys: &[Expr],
+        build_keys: &[Expr],
+    ) -> f64 {
+        let selectivity = self.key_selectivity(probe, build, probe_keys, build_keys);
+        (self.rows(build) * selectivity).min(1.0)
+    }
+
+    fn table_rows(&self, table: &str) -> f64 {
+        self.catalog
+            .table(table)
+            .map_or(DEFAULT_ROWS, |t| t.row_count() as f64)
+    }
+
+    // 1 行の大きさ
+    fn width(&self, plan: &PlanNode) -> f64 {
+        let columns = plan.columns(self.catalog).map_or(1, |c| c.len());
+        columns as f64 * COLUMN_WIDTH + TUPLE_OVERHEAD
+    }
+}
+
+// MATCH ... AGAINST の terms 個の語のどれかを含む行の割合
+// 近い順に読むときはすべての行を返す
+fn search_selectivity(search: &Search) -> f64 {
+    match search {
+        Search::Nearest(_) => 1.0,
+        _ => SPATIAL_SELECTIVITY,
+    }
+}
+
+pub(super) fn match_selectivity(terms: usize) -> f64 {
+    1.0 - (1.0 - MATCH_SELECTIVITY).powi(terms as i32)
+}
+
+fn pages(bytes: f64) -> f64 {
+    (bytes / PAGE_SIZE as f64).ceil().max(1.0)
+}
+
+// 結合の行数。selectivity は直積のうち条件を満たす組の割合
+fn join_rows(left: f64, right: f64, join_type: JoinType, selectivity: f64) -> f64 {
+    let inner = left * right * selectivity;
+    match join_type {
+        JoinType::Inner => inner,
+        JoinType::Left => inner.max(left),
+        JoinType::Right => inner.max(right),
+        JoinType::Full => inner.max(left).max(right),
+        JoinType::Semi => inner.min(left),
+        JoinType::Anti => left - inner.min(left),
+    }
+}
+
+// 値の種類の数。統計がなければ行数とみる
+pub(super) fn distinct(stats: Option<&ColumnStats>, rows: f64) -> f64 {
+    stats.map_or(rows, |s| s.distinct.min(rows)).max(1.0)
+}
+
+// インデックスの範囲に入る行の割合。stats はインデックスの i 番目の列の統計
+fn range_selectivity<'s>(
+    range: &IndexRange,
+    stats: &dyn Fn(usize) -> Option<&'s ColumnStats>,
+) -> f64 {
+    let prefix = range
+        .prefix
+        .iter()
+        .enumerate()
+        .map(|(i, value)| match (stats(i), value) {
+            (Some(s), Expr::Literal(value)) => s.eq_fraction(value),
+            // $n はどの値かわからないので、値ごとに同じ行数があるとみる
+            (Some(s), _) => (1.0 - s.null_fraction) / s.distinct.max(1.0),
+            (None, _) => EQ_SELECTIVITY,
+        })
+        .product::<f64>();
+    // 端が $n なら値がわからないので、決まった割合を使う
+    fn literal(b: &Option<ScanBound>) -> Result<Option<(&Value, bool)>, ()> {
+        match b {
+            Some(ScanBound {
+                value: Expr::Literal(value),
+                inclusive,
+            }) => Ok(Some((value, *inclusive))),
+            Some(_) => Err(()),
+            None => Ok(None),
+        }
+    }
+    let bounds = match (literal(&range.lower), literal(&range.upper)) {
+        (Ok(lower), Ok(upper)) => bounds_selectivity(stats(range.prefix.len()), lower, upper),
+        _ if range.lower.is_some() && range.upper.is_some() => BETWEEN_SELECTIVITY,
+        _ => RANGE_SELECTIVITY,
+    };
+    prefix * bounds
+}
+
+// 列が lower と upper の間にある行の割合。端は (値, 端を含むか)
+fn bounds_selectivity(
+    stats: Option<&ColumnStats>,
+    lower: Option<(&Value, bool)>,
+    upper: Option<(&Value, bool)>,
+) -> f64 {
+    if lower.is_none() && upper.is_none() {
+        return 1.0;
+    }
+    if let Some(stats) = stats {
+        // 値より小さい行の割合。with_eq なら値と等しい行も含める
+        let below = |(value, with_eq): (&Value, bool)| {
+            let eq = if with_eq {
+                stats.eq_fraction(value)
+            } else {
+                0.0
+            };
+            Some(stats.below_fraction(value)? + eq)
+        };
+        let lo = lower.map_or(Some(0.0), |(value, inclusive)| below((value, !inclusive)));
+        let hi = upper.map_or(Some(1.0 - stats.null_fraction), below);
+        if let (Some(lo), Some(hi)) = (lo, hi) {
+            return (hi - lo).max(0.0);
+        }
+    }
+    match (lower, upper) {
+        (Some(_), Some(_)) => BETWEEN_SELECTIVITY,
+        _ => RANGE_SELECTIVITY,
+    }
+}
+
+// 式を評価するときの演算子と関数の数
+fn operators(expr: &Expr) -> f64 {
+    let own = match expr {
+        Expr::Column(_) | Expr::Literal(_) => 0.0,
+        _ => 1.0,
+    };
+    own + expr.children().into_iter().map(operators).sum::<f64>()
+}
+
+// 条件を満たす行の割合。stats は入力の列の統計で、なければ決まった割合を使う。
+// 同じ列を下と上から挟む大小比較の組は 1 つの範囲とみる
+pub(super) fn selectivity<'s>(
+    expr: &Expr,
+    stats: &dyn Fn(usize) -> Option<&'s ColumnStats>,
+) -> f64 {
+    let mut conjuncts = vec![];
+    collect_conjuncts(expr, &mut conjuncts);
+    let comparisons = conjuncts.iter().map(|c| comparison(c)).collect::<Vec<_>>();
+    let lower = |i: usize| match comparisons[i] {
+        Some((column, BinaryOp::Gt | BinaryOp::GtEq, _)) => Some((column, true)),
+        Some((column, BinaryOp::Lt | BinaryOp::LtEq, _)) => Some((column, false)),
+        _ => None,
+    };
+    let mut paired = vec![false; conjuncts.len()];
+    let mut result = 1.0;
+    for (i, conjunct) in conjuncts.iter().enumerate() {
+        if paired[i] {
+            continue;
+        }
+        if let Some((column, is_lower)) = lower(i) {
+            let pair = (i + 1..conjuncts.len())
+                .find(|&j| !paired[j] && lower(j) == Some((column, !is_lower)));
+            if let Some(j) = pair {
+                paired[j] = true;
+                let bound = |k: usize| {
+                    let (_, op, value) = comparisons[k].unwrap();
+                    (value, matches!(op, BinaryOp::GtEq | BinaryOp::LtEq))
+                };
+                let (lo, hi) = if is_lower { (i, j) } else { (j, i) };
+                result *= bounds_selectivity(stats(column), Some(bound(lo)), Some(bound(hi)));
+                continue;
+            }
+        }
+        result *= term_selectivity(conjunct, stats);
+    }
+    result
+}
+
+fn collect_conjuncts<'e>(expr: &'e Expr, conjuncts: &mut Vec<&'e Expr>) {
+    match expr {
+        Expr::Binary {
+            op: BinaryOp::And,
+            left,
+            right,
+        } => {
+            collect_conjuncts(left, conjuncts);
+            collect_conjuncts(right, conjuncts);
+        }
+        expr => conjuncts.push(expr),
+    }
+}
+
+// 列と定数の比較なら、列が左に来るように向きをそろえた (列の位置, 演算子, 定数)
+fn comparison(expr: &Expr) -> Option<(usize, BinaryOp, &Value)> {
+    let Expr::Binary { op, left, right } = expr else {
+        return None;
+    };
+    if !matches!(
+        op,
+        BinaryOp::Eq
+            | BinaryOp::NotEq
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq
+    ) {
+        return None;
+    }
+    match (&**left, &**right) {
+        (Expr::Column(c), Expr::Literal(value)) => Some((*c, *op, value)),
+        (Expr::Literal(value), Expr::Column(c)) => {
+            let op = match op {
+                BinaryOp::Lt => BinaryOp::Gt,
+                BinaryOp::LtEq => BinaryOp::GtEq,
+                BinaryOp::Gt => BinaryOp::Lt,
+                BinaryOp::GtEq => BinaryOp::LtEq,
+                op => *op,
+            };
+            Some((*c, op, value))
+        }
+        _ => None,
+    }
+}
+
+fn term_selectivity<'s>(expr: &Expr, stats: &dyn Fn(usize) -> Option<&'s ColumnStats>) -> f64 {
+    if let Some((column, op, value)) = comparison(expr) {
+        if let Some(s) = stats(column) {
+            return match op {
+                BinaryOp::Eq => s.eq_fraction(value),
+                BinaryOp::NotEq => (1.0 - s.null_fraction - s.eq_fraction(value)).max(0.0),
+                BinaryOp::Lt | BinaryOp::LtEq => {
+                    bounds_selectivity(Some(s), None, Some((value, op == BinaryOp::LtEq)))
+                }
+                _ => bounds_selectivity(Some(s), Some((value, op == BinaryOp::GtEq)), None),
+            };
+        }
+    }
+    if let Some((query, _)) = match_condition(expr) {
+        return match query {
+            Expr::Literal(Value::Text(query)) => {
+                match_selectivity(fulltext::query_terms(query).len())
+            }
+            _ => MATCH_SELECTIVITY,
+        };
+    }
+    let column_stats = |expr: &Expr| match expr {
+        Expr::Column(c) => stats(*c),
+        _ => None,
+    };
+    match expr {
+        Expr::Binary {
+            op: BinaryOp::Or,
+            left,
+            right,
+        } => {
+            let (l, r) = (selectivity(left, stats), selectivity(right, stats));
+            l + r - l * r
+        }
+        Expr::Binary {
+            op: BinaryOp::Eq, ..
+        } => EQ_SELECTIVITY,
+        Expr::Binary {
+            op: BinaryOp::NotEq,
+            ..
+        } => 1.0 - EQ_SELECTIVITY,
+        Expr::Binary {
+            op: BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq,
+            ..
+        } => RANGE_SELECTIVITY,
+        Expr::Binary {
+            op: BinaryOp::Overlaps | BinaryOp::Contains | BinaryOp::ContainedBy,
+            ..
+        } => SPATIAL_SELECTIVITY,
+        Expr::Unary {
+            op: UnaryOp::Not,
+            expr,
+        } => 1.0 - selectivity(expr, stats),
+        Expr::IsNull { expr, negated } => {
+            let s = column_stats(expr).map_or(EQ_SELECTIVITY, |s| s.null_fraction);
+            if *negated {
+                1.0 - s
+            } else {
+                s
+            }
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let column = column_stats(expr);
+            let s = list
+                .iter()
+                .map(|item| match (column, item) {
+                    (Some(c), Expr::Literal(value)) => c.eq_fraction(value),
+                    _ => EQ_SELECTIVITY,
+                })
+                .sum::<f64>()
+                .min(1.0);
+            let not_null = column.map_or(1.0, |c| 1.0 - c.null_fraction);
+            if *negated {
+                (not_null - s).max(0.0)
+            } else {
+                s
+            }
+        }
+        _ => DEFAULT_SELECTIVITY,
+    }
+}
@@ -0,0 +1,685 @@
+use crate::catalog::{Catalog, Table};
+use crate::executor::expr::{op_symbol, Expr, Function};
+use crate::executor::{ConflictAction, ExplainNode, JoinType, PlanNode, SortKey};
+use crate::lock::LockMode;
+use crate::rtree::Search;
+use crate::sql::ast::{SampleMethod, UnaryOp, WaitPolicy};
+use crate::types::Value;
+
+use super::cost::CostModel;
+use super::Error;
+
+// EXPLAIN で書く演算子の一覧
+//
+// 演算子ごとに名前と見積もった費用と行数を書き、条件やキーなどを details に入れる。
+pub(super) fn explain(model: &CostModel, plan: &PlanNode) -> Result<Vec<ExplainNode>, Error> {
+    let mut nodes = vec![];
+    node(model, plan, 0, &mut nodes)?;
+    Ok(nodes)
+}
+
+fn node(
+    model: &CostModel,
+    plan: &PlanNode,
+    depth: usize,
+    nodes: &mut Vec<ExplainNode>,
+) -> Result<(), Error> {
+    let catalog = model.catalog;
+    let mut details = vec![];
+    let mut push = |name: &str, text: String| details.push(format!("{}: {}", name, text));
+    match plan {
+        PlanNode::SeqScan {
+            needed: Some(needed),
+            ..
+        } => {
+            let columns = plan.columns(catalog)?;
+            let names = needed.iter().map(|&i| columns[i].as_str());
+            push("Columns", names.collect::<Vec<_>>().join(", "));
+        }
+        PlanNode::SampleScan {
+            method,
+            fraction,
+            seed,
+            needed,
+            ..
+        } => {
+            let method = match method {
+                SampleMethod::System => "system",
+                SampleMethod::Bernoulli => "bernoulli",
+            };
+            let mut sampling = format!("{} ({})", method, fraction * 100.0);
+            if let Some(seed) = seed {
+                sampling += &format!(" REPEATABLE ({})", seed);
+            }
+            push("Sampling", sampling);
+            if let Some(needed) = needed {
+                let columns = plan.columns(catalog)?;
+                let names = needed.iter().map(|&i| columns[i].as_str());
+                push("Columns", names.collect::<Vec<_>>().join(", "));
+            }
+        }
+        PlanNode::Gather {
+            predicate,
+            workers,
+            needed,
+            ..
+        } => {
+            let columns = plan.columns(catalog)?;
+            push("Workers", workers.to_string());
+            if let Some(needed) = needed {
+                let names = needed.iter().map(|&i| columns[i].as_str());
+                push("Columns", names.collect::<Vec<_>>().join(", "));
+            }
+            if let Some(predicate) = predicate {
+                push("Filter", expr(predicate, &columns));
+            }
+        }
+        PlanNode::IndexScan {
+            table,
+            index,
+            range,
+            predicate,
+            ..
+        } => {
+            let columns = plan.columns(catalog)?;
+            let keys = index_columns(catalog, table, index);
+            let mut conds = vec![];
+            for (key, value) in keys.iter().zip(&range.prefix) {
+                conds.push(format!("{} = {}", key, expr(value, &columns)));
+            }
+            if let Some(key) = keys.get(range.prefix.len()) {
+                if let Some(lower) = &range.lower {
+                    let op = if lower.inclusive { ">=" } else { ">" };
+                    conds.push(format!("{} {} {}", key, op, expr(&lower.value, &columns)));
+                }
+                if let Some(upper) = &range.upper {
+                    let op = if upper.inclusive { "<=" } else { "<" };
+                    conds.push(format!("{} {} {}", key, op, expr(&upper.value, &columns)));
+                }
+            }
+            if !conds.is_empty() {
+                push("Index Cond", conds.join(" AND "));
+            }
+            if let Some(predicate) = predicate {
+                push("Filter", expr(predicate, &columns));
+            }
+        }
+        PlanNode::FulltextScan {
+            terms, predicate, ..
+        } => {
+            push("Terms", terms.join(", "));
+            if let Some(predicate) = predicate {
+                push("Filter", expr(predicate, &plan.columns(catalog)?));
+            }
+        }
+        PlanNode::RTreeScan {
+            table,
+            index,
+            search,
+            predicate,
+        } => {
+            let key = index_columns(catalog, table, index).join(", ");
+            let (name, op, rect) = match search {
+                Search::Overlaps(rect) => ("Index Cond", "&&", rect),
+                Search::Contains(rect) => ("Index Cond", "@>", rect),
+                Search::ContainedBy(rect) => ("Index Cond", "<@", rect),
+                Search::Nearest(rect) => ("Order By", "<->", rect),
+            };
+            // 点で引いたときは幅のない矩形になっている
+            let value = if rect.low == rect.high {
+                Value::Point(rect.low)
+            } else {
+                Value::Box(Box::new(*rect))
+            };
+            push(name, format!("{} {} {}", key, op, literal(&value)));
+            if let Some(predicate) = predicate {
+                push("Filter", expr(predicate, &plan.columns(catalog)?));
+            }
+        }
+        PlanNode::Filter { input, predicate } => {
+            push("Filter", expr(predicate, &input.columns(catalog)?));
+        }
+        PlanNode::RuntimeFilter { input, keys, .. } => {
+            let columns = input.columns(catalog)?;
+            let keys = keys.iter().map(|key| expr(key, &columns));
+            push("Keys", keys.collect::<Vec<_>>().join(", "));
+        }
+        PlanNode::PartitionPrune {
+            input, conditions, ..
+        } => {
+            push("Prune", exprs(conditions, &input.columns(catalog)?));
+        }
+        PlanNode::AsOf { point, lsn, .. } => {
+            let kind = if *lsn { "LSN" } else { "TIMESTAMP" };
+            push(kind, expr(point, &[]));
+        }
+        PlanNode::NestedLoopJoin {
+            left,
+            right,
+            predicate,
+            ..
+        } => {
+            if let Some(predicate) = predicate {
+                push(
+                    "Join Filter",
+                    expr(predicate, &joined(catalog, left, right)?),
+                );
+            }
+        }
+        PlanNode::HashJoin {
+            left,
+            right,
+            left_keys,
+            right_keys,
+            predicate,
+            ..
+        }
+        | PlanNode::MergeJoin {
+            left,
+            right,
+            left_keys,
+            right_keys,
+            predicate,
+        } => {
+            let name = if matches!(plan, PlanNode::HashJoin { .. }) {
+                "Hash Cond"
+            } else {
+                "Merge Cond"
+            };
+            let (left_columns, right_columns) = (left.columns(catalog)?, right.columns(catalog)?);
+            let conds = left_keys
+                .iter()
+                .zip(right_keys)
+                .map(|(l, r)| format!("{} = {}", expr(l, &left_columns), expr(r, &right_columns)));
+            push(name, conds.collect::<Vec<_>>().join(" AND "));
+            if let Some(predicate) = predicate {
+                push(
+                    "Join Filter",
+                    expr(predicate, &joined(catalog, left, right)?),
+                );
+            }
+            if let PlanNode::HashJoin {
+                runtime_filter: Some((id, _)),
+                ..
+            } = plan
+            {
+                push("Runtime Filter", format!("bf{}", id));
+            }
+        }
+        // 右は左の行を Outer Row の CTE Scan で読む
+        PlanNode::LateralJoin {
+            left,
+            right,
+            id,
+            predicate,
+            single,
+            ..
+        } => {
+            push("Outer Row", format!("cte{}", id));
+            if let Some(predicate) = predicate {
+                push(
+                    "Join Filter",
+                    expr(predicate, &joined(catalog, left, right)?),
+                );
+            }
+            if *single {
+                push("Single Row", "true".to_string());
+            }
+        }
+        PlanNode::IndexJoin {
+            left,
+            table,
+            index,
+            keys,
+            predicate,
+        } => {
+            let left_columns = left.columns(catalog)?;
+            let conds = index_columns(catalog, table, index)
+                .into_iter()
+                .zip(keys)
+                .map(|(column, key)| format!("{} = {}", column, expr(key, &left_columns)));
+            push("Index Cond", conds.collect::<Vec<_>>().join(" AND "));
+            if let Some(predicate) = predicate {
+                let mut columns = left_columns;
+                columns.extend(table_columns(catalog, table));
+                push("Filter", expr(predicate, &columns));
+            }
+        }
+        PlanNode::Sort { input, keys, top_n } => {
+            // 射影の上なら、隠れた列の ?column? ではなく射影の式で書く
+            let keys = match &**input {
+                PlanNode::Projection {
+                    input, exprs: list, ..
+                } => {
+                    let keys = keys
+                        .iter()
+                        .map(|key| SortKey {
+                            expr: key.expr.replace_columns(&|i| list[i].clone()),
+                            ..key.clone()
+                        })
+                        .collect::<Vec<_>>();
+                    sort_keys(&keys, &input.columns(catalog)?)
+                }
+                input => sort_keys(keys, &input.columns(catalog)?),
+            };
+            push("Sort Key", keys);
+            if let Some(n) = top_n {
+                push("Top-N", n.to_string());
+            }
+        }
+        PlanNode::Limit { limit, offset, .. } => {
+            if let Some(limit) = limit {
+                push("Count", limit.to_string());
+            }
+            if *offset > 0 {
+                push("Offset", offset.to_string());
+            }
+        }
+        PlanNode::HashAggregate {
+            input,
+            group_by,
+            aggregates,
+        }
+        | PlanNode::GroupAggregate {
+            input,
+            group_by,
+            aggregates,
+        }
+        | PlanNode::ParallelAggregate {
+            input,
+            group_by,
+            aggregates,
+        }
+        | PlanNode::GroupingSets {
+            input,
+            group_by,
+            aggregates,
+            ..
+        } => {
+            let columns = input.columns(catalog)?;
+            if let PlanNode::GroupingSets { sets, .. } = plan {
+                let sets = sets.iter().map(|set| {
+                    let keys = set.iter().map(|&i| group_by[i].clone()).collect::<Vec<_>>();
+                    format!("({})", exprs(&keys, &columns))
+                });
+                push("Grouping Sets", sets.collect::<Vec<_>>().join(", "));
+            } else if !group_by.is_empty() {
+                push("Group Key", exprs(group_by, &columns));
+            }
+            let calls = aggregates.iter().map(|call| {
+                let mut arg = call
+                    .arg
+                    .as_ref()
+                    .map_or("*".into(), |arg| expr(arg, &columns));
+                if let Some(percentile) = call.percentile {
+                    arg += &format!(", {}", percentile);
+                }
+                let distinct = if call.distinct { "DISTINCT " } else { "" };
+                format!("{}({}{})", call.func.name(), distinct, arg)
+            });
+            push("Aggregates", calls.collect::<Vec<_>>().join(", "));
+        }
+        PlanNode::Window {
+            input,
+            partition_by,
+            order_by,
+            calls,
+        } => {
+            let columns = input.columns(catalog)?;
+            if !partition_by.is_empty() {
+                push("Partition By", exprs(partition_by, &columns));
+            }
+            if !order_by.is_empty() {
+                push("Order By", sort_keys(order_by, &columns));
+            }
+            let calls = calls
+                .iter()
+                .map(|call| format!("{}({})", call.func.name(), exprs(&call.args, &columns)));
+            push("Functions", calls.collect::<Vec<_>>().join(", "));
+        }
+        PlanNode::ProjectSet { input, exprs } => {
+            let columns = input.columns(catalog)?;
+            let calls = exprs
+                .iter()
+                .map(|e| format!("unnest({})", expr(e, &columns)));
+            push("Functions", calls.collect::<Vec<_>>().join(", "));
+        }
+        PlanNode::Insert { on_conflict, .. } => {
+            if let Some(on_conflict) = on_conflict {
+                let action = match on_conflict.action {
+                    ConflictAction::Nothing => "Nothing",
+                    ConflictAction::Update { .. } => "Update",
+                };
+                push("Conflict Resolution", action.to_string());
+                if let Some(index) = &on_conflict.index {
+                    push("Conflict Arbiter Index", index.clone());
+                }
+            }
+        }
+        PlanNode::Update {
+            table, assignments, ..
+        } => {
+            let columns = table_columns(catalog, table);
+            let sets = assignments
+                .iter()
+                .map(|(i, value)| format!("{} = {}", columns[*i], expr(value, &columns)));
+            push("Set", sets.collect::<Vec<_>>().join(", "));
+        }
+        PlanNode::LockRows { mode, wait, .. } => {
+            let mut text = match mode {
+                LockMode::Exclusive => "FOR UPDATE",
+                _ => "FOR SHARE",
+            }
+            .to_string();
+            match wait {
+                WaitPolicy::Block => {}
+                WaitPolicy::NoWait => text.push_str(" NOWAIT"),
+                WaitPolicy::SkipLocked => text.push_str(" SKIP LOCKED"),
+            }
+            push("Lock Mode", text);
+        }
+        PlanNode::Projection {
+            input, exprs: list, ..
+        } => {
+            push("Output", exprs(list, &input.columns(catalog)?));
+        }
+        PlanNode::SeqScan { .. }
+        | PlanNode::Values { .. }
+        | PlanNode::Unique { .. }
+        | PlanNode::Append { .. }
+        | PlanNode::HashSetOp { .. }
+        | PlanNode::Delete { .. }
+        | PlanNode::Materialize { .. }
+        | PlanNode::CteScan { .. }
+        | PlanNode::StatsScan { .. }
+        | PlanNode::ExplainAnalyze { .. } => {}
+        // 再帰項は前の段の行を Work Table の CTE Scan で読む
+        PlanNode::RecursiveUnion { id, all, .. } => {
+            push("Work Table", format!("cte{}", id));
+            if !all {
+                push("Distinct", "true".to_string());
+            }
+        }
+        // 子は CTE の計画を ctes の順に並べたあとに本体が来る
+        PlanNode::With { ctes, .. } => {
+            let ids = ctes.iter().map(|(id, _)| format!("cte{}", id));
+            push("CTEs", ids.collect::<Vec<_>>().join(", "));
+        }
+    }
+    nodes.push(ExplainNode {
+        depth,
+        name: label(plan),
+        cost: model.cost(plan),
+        rows: model.rows(plan).max(1.0),
+        details,
+    });
+    for child in plan.children() {
+        node(model, child, depth + 1, nodes)?;
+    }
+    Ok(())
+}
+
+fn label(plan: &PlanNode) -> String {
+    match plan {
+        PlanNode::SeqScan { table, .. } => format!("Seq Scan on {}", table),
+        PlanNode::SampleScan { table, .. } => format!("Sample Scan on {}", table),
+        PlanNode::Gather { table, .. } => format!("Parallel Filter on {}", table),
+        PlanNode::IndexScan {
+            table,
+            index,
+            index_only,
+            ..
+        } => {
+            let kind = if *index_only {
+                "Index Only Scan"
+            } else {
+                "Index Scan"
+            };
+            format!("{} using {} on {}", kind, index, table)
+        }
+        PlanNode::FulltextScan { table, index, .. } => {
+            format!("Fulltext Scan using {} on {}", index, table)
+        }
+        PlanNode::RTreeScan { table, index, .. } => {
+            format!("R-tree Scan using {} on {}", index, table)
+        }
+        PlanNode::Values { .. } => "Values Scan".into(),
+        PlanNode::Filter { .. } => "Filter".into(),
+        PlanNode::RuntimeFilter { id, .. } => format!("Bloom Filter bf{}", id),
+        PlanNode::PartitionPrune { table, .. } => format!("Partition Prune on {}", table),
+        PlanNode::AsOf { .. } => "As Of".into(),
+        PlanNode::NestedLoopJoin { join_type, .. } => join("Nested Loop", *join_type),
+        PlanNode::HashJoin { join_type, .. } => join("Hash", *join_type),
+        PlanNode::LateralJoin { join_type, .. } => join("Lateral", *join_type),
+        PlanNode::IndexJoin { table, index, .. } => {
+            format!("Index Nested Loop using {} on {}", index, table)
+        }
+        PlanNode::MergeJoin { .. } => "Merge Join".into(),
+        PlanNode::Sort { .. } => "Sort".into(),
+        PlanNode::Limit { .. } => "Limit".into(),
+        PlanNode::Unique { .. } => "Unique".into(),
+        PlanNode::HashAggregate { .. } | PlanNode::GroupingSets { .. } => "HashAggregate".into(),
+        PlanNode::GroupAggregate { .. } => "GroupAggregate".into(),
+        PlanNode::ParallelAggregate { .. } => "Finalize HashAggregate".into(),
+        PlanNode::Window { .. } => "WindowAgg".into(),
+        PlanNode::ProjectSet { .. } => "ProjectSet".into(),
+        PlanNode::Append { .. } => "Append".into(),
+        PlanNode::RecursiveUnion { .. } => "Recursive Union".into(),
+        PlanNode::HashSetOp { op, all, .. } => {
+            let all = if *all { " All" } else { "" };
+            let op = op.to_string();
+            format!("HashSetOp {}{}{}", &op[..1], op[1..].to_lowercase(), all)
+        }
+        PlanNode::Insert { table, .. } => format!("Insert on {}", table),
+        PlanNode::Update { table, .. } => format!("Update on {}", table),
+        PlanNode::Delete { table, .. } => format!("Delete on {}", table),
+        PlanNode::LockRows { table, .. } => format!("LockRows on {}", table),
+        PlanNode::Materialize { .. } => "Materialize".into(),
+        PlanNode::With { .. } => "With".into(),
+        PlanNode::CteScan { id, .. } => format!("CTE Scan on cte{}", id),
+        PlanNode::StatsScan { view } => format!("Stats Scan on {}", view.name()),
+        PlanNode::Projection { .. } => "Projection".into(),
+        PlanNode::ExplainAnalyze { .. } => "Explain Analyze".into(),
+    }
+}
+
+fn join(method: &str, join_type: JoinType) -> String {
+    let kind = match join_type {
+        JoinType::Inner => "",
+        JoinType::Left => " Left",
+        JoinType::Right => " Right",
+        JoinType::Full => " Full",
+        JoinType::Semi => " Semi",
+        JoinType::Anti => " Anti",
+    };
+    // 内部結合の入れ子ループは PostgreSQL と同じく Join をつけない
+    if method == "Nested Loop" && kind.is_empty() {
+        method.to_string()
+    } else {
+        format!("{}{} Join", method, kind)
+    }
+}
+
+fn table_columns(catalog: &Catalog, table: &str) -> Vec<String> {
+    catalog.table(table).map_or(vec![], |t| {
+        t.columns.iter().map(|c| c.name.clone()).collect()
+    })
+}
+
+// インデックスのキー。式のインデックスなら式を SQL の形で書いたもの
+pub fn index_columns(catalog: &Catalog, table: &str, index: &str) -> Vec<String> {
+    let Some(table) = catalog.table(table) else {
+        return vec![];
+    };
+    table
+        .indexes
+        .iter()
+        .find(|i| i.name == index)
+        .map_or(vec![], |index| match &index.expression {
+            Some(e) => {
+                let columns = table
+                    .columns
+                    .iter()
+                    .map(|c| c.name.clone())
+                    .collect::<Vec<_>>();
+                vec![expr(e, &columns)]
+            }
+            None => index
+                .columns
+                .iter()
+                .map(|&i| table.columns[i].name.clone())
+                .collect(),
+        })
+}
+
+// 生成列の式を、テーブルの列の名前で書いたもの
+pub fn generated_expr(table: &Table, column: usize) -> Option<String> {
+    let generated = table.generated_column(column)?;
+    let columns = table
+        .columns
+        .iter()
+        .map(|c| c.name.clone())
+        .collect::<Vec<_>>();
+    Some(expr(&generated.expr, &columns))
+}
+
+// 結合の条件を評価する、左の行と右の行をつなげた行の列名
+fn joined(catalog: &Catalog, left: &PlanNode, right: &PlanNode) -> Result<Vec<String>, Error> {
+    let mut columns = left.columns(catalog)?;
+    columns.extend(right.columns(catalog)?);
+    Ok(columns)
+}
+
+fn sort_keys(keys: &[SortKey], columns: &[String]) -> String {
+    keys.iter()
+        .map(|key| {
+            let order = if key.asc { "" } else { " DESC" };
+            // NULL の位置は既定と違うときだけ書く
+            let nulls = match (key.asc, key.nulls_first) {
+                (true, true) => " NULLS FIRST",
+                (false, false) => " NULLS LAST",
+                _ => "",
+            };
+            format!("{}{}{}", expr(&key.expr, columns), order, nulls)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn exprs(list: &[Expr], columns: &[String]) -> String {
+    list.iter()
+        .map(|e| expr(e, columns))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// 式を SQL の形で書く。列は columns の名前で、二項演算はかっこで囲む
+fn expr(e: &Expr, columns: &[String]) -> String {
+    match e {
+        Expr::Column(i) => columns
+            .get(*i)
+            .cloned()
+            .unwrap_or_else(|| format!("${}", i)),
+        Expr::Literal(value) => literal(value),
+        Expr::Parameter { index, .. } => format!("${}", index),
+        Expr::Binary { op, left, right } => format!(
+            "({} {} {})",
+            expr(left, columns),
+            op_symbol(*op),
+            expr(right, columns)
+        ),
+        Expr::Unary { op, expr: inner } => match op {
+            UnaryOp::Not => format!("NOT {}", expr(inner, columns)),
+            UnaryOp::Minus => format!("-{}", expr(inner, columns)),
+            UnaryOp::Plus => format!("+{}", expr(inner, columns)),
+        },
+        Expr::IsNull {
+            expr: inner,
+            negated,
+        } => {
+            let not = if *negated { " NOT" } else { "" };
+            format!("({} IS{} NULL)", expr(inner, columns), not)
+        }
+        Expr::InList {
+            expr: inner,
+            list,
+            negated,
+        } => {
+            let not = if *negated { " NOT" } else { "" };
+            format!(
+                "({}{} IN ({}))",
+                expr(inner, columns),
+                not,
+                exprs(list, columns)
+            )
+        }
+        Expr::Function {
+            func: func @ (Function::Like | Function::ILike | Function::Regexp),
+            args,
+        } => {
+            let op = match func {
+                Function::Like => "LIKE",
+                Function::ILike => "ILIKE",
+                _ => "REGEXP",
+            };
+            let mut s = format!(
+                "({} {} {}",
+                expr(&args[0], columns),
+                op,
+                expr(&args[1], columns)
+            );
+            if let Some(escape) = args.get(2) {
+                s += &format!(" ESCAPE {}", expr(escape, columns));
+            }
+            s + ")"
+        }
+        Expr::Function {
+            func: Function::Match,
+            args,
+        } => format!(
+            "MATCH({}) AGAINST({})",
+            exprs(&args[1..], columns),
+            expr(&args[0], columns)
+        ),
+        Expr::Function {
+            func: Function::Cast,
+            args,
+        } => match &args[1] {
+            Expr::Literal(Value::Text(ty)) => {
+                format!("CAST({} AS {})", expr(&args[0], columns), ty)
+            }
+            _ => format!("cast({})", exprs(args, columns)),
+        },
+        Expr::Function {
+            func: Function::CollationKey,
+            args,
+        } => match &args[1] {
+            Expr::Literal(Value::Text(collation)) => {
+                format!("({} COLLATE {})", expr(&args[0], columns), collation)
+            }
+            _ => format!("collation_key({})", exprs(args, columns)),
+        },
+        Expr::Function { func, args } => format!("{}({})", func.name(), exprs(args, columns)),
+        Expr::UserFunction { func, args } => {
+            format!("{}({})", func.name(), exprs(args, columns))
+        }
+    }
+}
+
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        Value::Blob(_) | Value::Array(_) | Value::Enum(_) => format!("'{}'", value),
+        Value::Json(s) => format!("JSON '{}'", s.replace('\'', "''")),
+        Value::Date(_)
+        | Value::Time(_)
+        | Value::Timestamp(_)
+        | Value::Uuid(_)
+        | Value::Interval(_)
+        | Value::Point(_)
+        | Value::Box(_) => {
+            format!("{} '{}'", value.type_name().to_uppercase(), value)
+        }
+        value => value.to_string(),
+    }
+}
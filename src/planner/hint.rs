@@ -0,0 +1,148 @@
+use crate::executor::PlanNode;
+use crate::sql::ast::HintMethod;
+
+use super::cost::CostModel;
+
+// SELECT /*+ ... */ のヒントで計画の選び方を縛る
+//
+// ヒントに反する方法を候補から外すのではなく、反する演算子の少ない計画を費用より先に比べて選ぶ。
+// 反さない候補がなければ、ヒントがないときと同じ計画になる。EXPLAIN の費用はヒントで変わらない。
+// テーブルはパーティションの子なら親の名前でも指せる。結合のヒントは、両側のテーブルの集まりがちょうど一致する結合に効く
+impl CostModel<'_> {
+    // 計画を比べるときの順。小さいほうを選ぶ
+    pub fn rank(&self, plan: &PlanNode) -> (usize, f64) {
+        let violations = if self.hints.is_empty() {
+            0
+        } else {
+            self.violations(plan)
+        };
+        (violations, self.cost(plan))
+    }
+
+    // plan とその子のうち、ヒントに反する演算子の数
+    fn violations(&self, plan: &PlanNode) -> usize {
+        let own = match plan {
+            PlanNode::SeqScan { table, .. } | PlanNode::Gather { table, .. } => {
+                self.scan_violations(table, None)
+            }
+            PlanNode::IndexScan { table, index, .. }
+            | PlanNode::FulltextScan { table, index, .. }
+            | PlanNode::RTreeScan { table, index, .. } => self.scan_violations(table, Some(index)),
+            PlanNode::NestedLoopJoin { left, right, .. } => {
+                self.join_violations(HintMethod::NestLoop, left, Some(right), None)
+            }
+            PlanNode::HashJoin { left, right, .. } => {
+                self.join_violations(HintMethod::HashJoin, left, Some(right), None)
+            }
+            PlanNode::MergeJoin { left, right, .. } => {
+                self.join_violations(HintMethod::MergeJoin, left, Some(right), None)
+            }
+            // 外側の行ごとに内側のテーブルをインデックスで引く入れ子ループ結合
+            PlanNode::IndexJoin {
+                left, table, index, ..
+            } => {
+                self.scan_violations(table, Some(index))
+                    + self.join_violations(HintMethod::NestLoop, left, None, Some(table))
+            }
+            _ => 0,
+        };
+        own + plan
+            .children()
+            .into_iter()
+            .map(|child| self.violations(child))
+            .sum::<usize>()
+    }
+
+    fn scan_violations(&self, table: &str, index: Option<&str>) -> usize {
+        self.hints
+            .iter()
+            .filter(|hint| !hint.method.is_join() && self.names(table, &hint.relations[0]))
+            .filter(|hint| {
+                let uses = match hint.method {
+                    HintMethod::SeqScan => index.is_none(),
+                    _ => index.is_some_and(|index| {
+                        hint.negated
+                            || hint.indexes.is_empty()
+                            || hint.indexes.iter().any(|i| i == index)
+                    }),
+                };
+                uses == hint.negated
+            })
+            .count()
+    }
+
+    fn join_violations(
+        &self,
+        method: HintMethod,
+        left: &PlanNode,
+        right: Option<&PlanNode>,
+        table: Option<&str>,
+    ) -> usize {
+        let mut tables = vec![];
+        collect_tables(left, &mut tables);
+        if let Some(right) = right {
+            collect_tables(right, &mut tables);
+        }
+        tables.extend(table);
+        let mut tables = tables
+            .into_iter()
+            .map(|table| self.root(table))
+            .collect::<Vec<_>>();
+        tables.sort_unstable();
+        tables.dedup();
+        self.hints
+            .iter()
+            .filter(|hint| hint.method.is_join())
+            .filter(|hint| {
+                let mut relations = hint
+                    .relations
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+                relations.sort_unstable();
+                relations.dedup();
+                relations == tables
+            })
+            .filter(|hint| (hint.method == method) == hint.negated)
+            .count()
+    }
+
+    // name が table か、table を子に持つパーティションに分けたテーブルか
+    fn names(&self, table: &str, name: &str) -> bool {
+        let mut table = table;
+        loop {
+            if table == name {
+                return true;
+            }
+            match self.catalog.table(table).and_then(|t| t.partition.as_ref()) {
+                Some(partition) => table = &partition.parent,
+                None => return false,
+            }
+        }
+    }
+
+    // パーティションの子なら、いちばん上の親
+    fn root<'b>(&'b self, table: &'b str) -> &'b str {
+        match self.catalog.table(table).and_then(|t| t.partition.as_ref()) {
+            Some(partition) => self.root(&partition.parent),
+            None => table,
+        }
+    }
+}
+
+// plan が読むテーブルの名前
+fn collect_tables<'a>(plan: &'a PlanNode, tables: &mut Vec<&'a str>) {
+    match plan {
+        PlanNode::SeqScan { table, .. }
+        | PlanNode::SampleScan { table, .. }
+        | PlanNode::Gather { table, .. }
+        | PlanNode::IndexScan { table, .. }
+        | PlanNode::FulltextScan { table, .. }
+        | PlanNode::RTreeScan { table, .. }
+        | PlanNode::IndexJoin { table, .. } => tables.push(table),
+        _ => {}
+    }
+    for child in plan.children() {
+        collect_tables(child, tables);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::executor::expr::Expr;
+use crate::sql::ast::BinaryOp;
+use crate::types::Value;
+
+// 計画を作るときに式を簡単にする
+//
+// 定数だけの部分式は評価した値に置き換える。評価でエラーになるものと、NOW() のように実行するたびに値が変わるものは
+// 実行するときに任せる。
+// AND と OR は片側で値が決まれば畳み、定数と列などの比較は定数を右に置く。
+pub(super) fn simplify(expr: Expr) -> Expr {
+    let expr = match expr {
+        Expr::Column(_) | Expr::Literal(_) | Expr::Parameter { .. } => return expr,
+        Expr::Binary { op, left, right } => {
+            let (left, right) = (simplify(*left), simplify(*right));
+            match (op, &left, &right) {
+                (BinaryOp::And, Expr::Literal(Value::Boolean(false)), _)
+                | (BinaryOp::And, _, Expr::Literal(Value::Boolean(false)))
+                | (BinaryOp::Or, Expr::Literal(Value::Boolean(true)), _)
+                | (BinaryOp::Or, _, Expr::Literal(Value::Boolean(true))) => {
+                    return Expr::Literal(Value::Boolean(op == BinaryOp::Or))
+                }
+                (BinaryOp::And, Expr::Literal(Value::Boolean(true)), _)
+                | (BinaryOp::Or, Expr::Literal(Value::Boolean(false)), _) => return right,
+                (BinaryOp::And, _, Expr::Literal(Value::Boolean(true)))
+                | (BinaryOp::Or, _, Expr::Literal(Value::Boolean(false))) => return left,
+                _ => normalize(op, left, right),
+            }
+        }
+        Expr::Unary { op, expr } => Expr::Unary {
+            op,
+            expr: Box::new(simplify(*expr)),
+        },
+        Expr::IsNull { expr, negated } => Expr::IsNull {
+            expr: Box::new(simplify(*expr)),
+            negated,
+        },
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => Expr::InList {
+            expr: Box::new(simplify(*expr)),
+            list: list.into_iter().map(simplify).collect(),
+            negated,
+        },
+        Expr::Function { func, args } => Expr::Function {
+            func,
+            args: args.into_iter().map(simplify).collect(),
+        },
+        Expr::UserFunction { func, args } => Expr::UserFunction {
+            func,
+            args: args.into_iter().map(simplify).collect(),
+        },
+    };
+    let volatile = match &expr {
+        Expr::Function { func, .. } => func.is_volatile(),
+        Expr::UserFunction { func, .. } => func.is_volatile(),
+        _ => false,
+    };
+    if !volatile
+        && expr
+            .children()
+            .iter()
+            .all(|child| matches!(child, Expr::Literal(_)))
+    {
+        if let Ok(value) = expr.eval(&vec![]) {
+            return Expr::Literal(value);
+        }
+    }
+    expr
+}
+
+// 定数と定数でないものの比較は、定数が右に来るように向きを変える
+fn normalize(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    let flipped = match op {
+        BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::IsDistinctFrom | BinaryOp::IsNotDistinctFrom => {
+            op
+        }
+        BinaryOp::Lt => BinaryOp::Gt,
+        BinaryOp::LtEq => BinaryOp::GtEq,
+        BinaryOp::Gt => BinaryOp::Lt,
+        BinaryOp::GtEq => BinaryOp::LtEq,
+        _ => return Expr::binary(op, left, right),
+    };
+    let constant = |e: &Expr| matches!(e, Expr::Literal(_) | Expr::Parameter { .. });
+    if constant(&left) && !constant(&right) {
+        Expr::binary(flipped, right, left)
+    } else {
+        Expr::binary(op, left, right)
+    }
+}
+
+// 絞り込みの条件の各項を簡単にし、常に真の項を除く。
+// 偽か NULL になる項があれば、どの行も満たさないので偽の項 1 つにする
+pub(super) fn simplify_conjuncts(conjuncts: Vec<Expr>) -> Vec<Expr> {
+    let mut result = vec![];
+    for conjunct in conjuncts {
+        for conjunct in simplify(conjunct).split_conjunction() {
+            match conjunct {
+                Expr::Literal(Value::Boolean(true)) => {}
+                Expr::Literal(Value::Boolean(false) | Value::Null) => {
+                    return vec![Expr::Literal(Value::Boolean(false))]
+                }
+                conjunct => result.push(conjunct),
+            }
+        }
+    }
+    result
+}
+
+// simplify_conjuncts の結果がどの行も満たさないものか
+pub(super) fn is_contradiction(conjuncts: &[Expr]) -> bool {
+    matches!(conjuncts, [Expr::Literal(Value::Boolean(false))])
+}
@@ -0,0 +1,1073 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::buffer::{self, Buffer, BufferPool, BufferPoolManager};
+use crate::disk::{DiskManager, PageId};
+use crate::wal::{self, ActiveTxn, LogRecord, Lsn, Wal};
+
+// 起動時の回復
+//
+// ページの変更はログに書かない。行は版として書き換え、コミットするときに汚れたページの像をログに書く (buffer の log_images)。
+// チェックポイントのあとに初めて書くページは全体の像を、そのあとは前に書いた像からの差分を書く。
+// 像を書いてからページを書き出すまでに追い出したページは、ディスクにある元の像を先にログに書く。
+// 1. 分析: 最後のチェックポイントからログを読み、落ちた時点で実行中だったトランザクションと、像を書いたページを求める。
+// 2. やり直し: 全体の像から差分をログの順に当てる。ページは最後にコミットしたときの像か、追い出す前の像に戻る。
+// 3. 実行中だったトランザクションには終わりのレコードを書く。ページを戻すレコードはないので、回復ではページを書き換えない。
+//    ページに残ったそのトランザクションの版は、開くときに persist の clean_up が committed を見て片づける。
+//
+// ベースバックアップとアーカイブしたログからは、途中の時点まで戻せる (ポイントインタイムリカバリ)。
+// バックアップのチェックポイントから始めて目標の位置で当てるのを止め、
+// その時点でコミットしていなかったトランザクションを終わらせる。
+//
+// オンラインバックアップはページを書き出さずに heap を写し、回復に要るログも一緒に写す。
+// アーカイブがなくても、バックアップのディレクトリだけで写し終えた時点まで戻せる。
+// 増分バックアップは前のバックアップのあとのログのセグメントだけを写し、戻すときに前のバックアップにつなげる。
+
+// バックアップのディレクトリに置くファイル
+const BACKUP_HEAP: &str = "heap.db";
+const BACKUP_LABEL: &str = "backup_label";
+const BACKUP_WAL: &str = "wal";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Buffer(#[from] buffer::Error),
+    #[error(transparent)]
+    Wal(#[from] wal::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("no write-ahead log is attached to the buffer pool")]
+    NoWal,
+    #[error("backup label is broken")]
+    BadBackupLabel,
+    #[error("recovery target {0:?} is before the base backup")]
+    TargetBeforeBackup(Lsn),
+    #[error("backup needs write-ahead log up to {0:?}")]
+    IncompleteBackup(Lsn),
+    #[error("incremental backup starts at {found:?} but the previous backup ends at {expected:?}")]
+    BrokenBackupChain { expected: Lsn, found: Lsn },
+}
+
+// 回復をどこで止めるか
+#[derive(Debug, Copy, Clone)]
+pub enum RecoveryTarget {
+    // このレコードまで当てる
+    Lsn(Lsn),
+    // この時刻までにコミットしたものまで当てる
+    Time(SystemTime),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RecoveryStats {
+    // 当て直したページの像の数
+    pub redone: usize,
+    // 終わらせたトランザクション
+    pub aborted: Vec<u64>,
+    // クラッシュ前に使っていない最小の番号。トランザクションの管理はここから番号を振る
+    pub next_txn: u64,
+    // 準備したまま終わっていなかったトランザクション。取り消さずに残す
+    pub prepared: Vec<PreparedTxn>,
+}
+
+// 2 相コミットの準備をしたトランザクションと、その最初と最後のレコード
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedTxn {
+    pub gid: String,
+    pub txn: ActiveTxn,
+}
+
+fn wal(bufmgr: &mut BufferPoolManager) -> Result<&mut Wal, Error> {
+    bufmgr.wal_mut().ok_or(Error::NoWal)
+}
+
+// ページを書き出さずに、いま汚れているページと実行中のトランザクションをチェックポイントに書き、
+// 回復の始まりをそこに進める。回復に要らなくなったログのセグメントは消す
+// next_txn は次に振るトランザクションの番号で、ログを切り詰めても回復のあとに同じ番号を使わないよう残しておく
+pub fn checkpoint(
+    bufmgr: &mut BufferPoolManager,
+    active: &[ActiveTxn],
+    next_txn: u64,
+) -> Result<Lsn, Error> {
+    // チェックポイントに載らないページは、これまでに書き出した分がディスクに届いていなければならない
+    bufmgr.sync()?;
+    let dirty = bufmgr.dirty_pages();
+    bufmgr.mark_checkpoint();
+    // やり直しは汚れたページの最も古い像から、実行中のトランザクションは最初のレコードから読む
+    let keep = dirty
+        .iter()
+        .map(|&(_, lsn)| lsn)
+        .chain(active.iter().filter_map(|txn| txn.first.valid()))
+        .min();
+    let wal = wal(bufmgr)?;
+    let lsn = wal.append(&LogRecord::Checkpoint {
+        active: active.to_vec(),
+        dirty,
+        next_txn,
+    })?;
+    wal.flush(lsn)?;
+    wal.set_checkpoint_lsn(lsn)?;
+    wal.truncate(keep.map_or(lsn, |keep| keep.min(lsn)))?;
+    Ok(lsn)
+}
+
+// ページをすべて書き出してから heap のファイルを backup に写し、写したときのチェックポイントを返す。
+// バックアップから戻すには、このチェックポイントからあとのログがアーカイブに残っていなければならない
+pub fn base_backup(
+    bufmgr: &mut BufferPoolManager,
+    heap: &Path,
+    backup: &Path,
+    active: &[ActiveTxn],
+    next_txn: u64,
+) -> Result<Lsn, Error> {
+    bufmgr.flush()?;
+    let lsn = checkpoint(bufmgr, active, next_txn)?;
+    fs::create_dir_all(backup)?;
+    fs::copy(heap, backup.join(BACKUP_HEAP))?;
+    fs::write(backup.join(BACKUP_LABEL), lsn.0.to_be_bytes())?;
+    // チェックポイントを含むセグメントをアーカイブに届ける
+    wal(bufmgr)?.switch_segment()?;
+    Ok(lsn)
+}
+
+// バックアップの heap とアーカイブのログで heap と wal_dir を作り直す。
+// このあと開いたバッファプールで recover_until を呼ぶと、バックアップのチェックポイントから回復する。
+// 止めた位置より後ろのログもアーカイブには残っているので、戻したあとは別のアーカイブ先を使う
+pub fn restore_backup(
+    backup: &Path,
+    archive: &Path,
+    heap: &Path,
+    wal_dir: &Path,
+) -> Result<(), Error> {
+    let label = read_label(backup)?.start;
+    fs::copy(backup.join(BACKUP_HEAP), heap)?;
+    if wal_dir.exists() {
+        fs::remove_dir_all(wal_dir)?;
+    }
+    fs::create_dir_all(wal_dir)?;
+    for entry in fs::read_dir(archive)? {
+        let path = entry?.path();
+        // 写している途中の一時ファイルは使わない
+        if path.extension().is_none() {
+            fs::copy(&path, wal_dir.join(path.file_name().unwrap()))?;
+        }
+    }
+    Wal::open(wal_dir)?.set_checkpoint_lsn(label)?;
+    Ok(())
+}
+
+fn write_label(backup: &Path, label: BackupLabel) -> Result<(), Error> {
+    let mut bytes = label.start.0.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&label.end.0.to_be_bytes());
+    fs::write(backup.join(BACKUP_LABEL), bytes)?;
+    Ok(())
+}
+
+// バックアップで写したログの範囲。オンラインバックアップは start のチェックポイントから end までを当てれば、写した heap が揃う。
+// 増分バックアップの start は前のバックアップの end
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackupLabel {
+    pub start: Lsn,
+    pub end: Lsn,
+}
+
+// ベースバックアップのラベルは | チェックポイント (8) |、ほかは | start (8) | end (8) |
+fn read_label(backup: &Path) -> Result<BackupLabel, Error> {
+    let label = fs::read(backup.join(BACKUP_LABEL))?;
+    let lsn = |bytes: &[u8]| Lsn(u64::from_be_bytes(bytes.try_into().unwrap()));
+    match label.len() {
+        8 => Ok(BackupLabel {
+            start: lsn(&label),
+            end: lsn(&label),
+        }),
+        16 => Ok(BackupLabel {
+            start: lsn(&label[..8]),
+            end: lsn(&label[8..]),
+        }),
+        _ => Err(Error::BadBackupLabel),
+    }
+}
+
+// 動いているデータベースを backup に写す。チェックポイントを書いてから heap を写し、
+// チェックポイントからの回復に要るログと写し終えた時点までのログを backup の wal に写す。
+// 汚れたページは書き出さないので写した heap には古いページや実行中のトランザクションの変更が混じるが、
+// 写したログで回復すれば写し終えた時点のコミットした状態に揃う。open_backup で開く。
+// ログをまだ書いていない実行中のトランザクションも active に入れておけば、回復でそれを終わらせたことにする
+pub fn online_backup(
+    bufmgr: &mut BufferPoolManager,
+    heap: &Path,
+    backup: &Path,
+    active: &[ActiveTxn],
+    next_txn: u64,
+) -> Result<BackupLabel, Error> {
+    let start = checkpoint(bufmgr, active, next_txn)?;
+    fs::create_dir_all(backup)?;
+    fs::copy(heap, backup.join(BACKUP_HEAP))?;
+    // heap を写している間に書き出したページの変更も、ログのほうが先にディスクに届いている
+    let log = wal(bufmgr)?;
+    let first = log.first_lsn();
+    let end = log.copy_to(first, backup.join(BACKUP_WAL))?;
+    Wal::open(backup.join(BACKUP_WAL))?.set_checkpoint_lsn(start)?;
+    let label = BackupLabel { start, end };
+    write_label(backup, label)?;
+    Ok(label)
+}
+
+// 前のバックアップ previous のあとのログだけを backup に写す増分バックアップ。
+// previous はオンラインバックアップかベースバックアップか、ほかの増分バックアップ。
+// ラベルの start は previous の end で、restore_incremental でつなげて戻す。
+// previous のあとのログをチェックポイントで消していれば作れない
+pub fn incremental_backup(
+    bufmgr: &mut BufferPoolManager,
+    previous: &Path,
+    backup: &Path,
+) -> Result<BackupLabel, Error> {
+    let start = read_label(previous)?.end;
+    let end = wal(bufmgr)?.copy_to(start, backup.join(BACKUP_WAL))?;
+    let label = BackupLabel { start, end };
+    write_label(backup, label)?;
+    Ok(label)
+}
+
+// base に increments を古い順につなげて、open_backup で開けるディレクトリを dest に作る。
+// 増分はそれぞれ、ひとつ前のバックアップが終わったところから始まっていなければならない
+pub fn restore_incremental(
+    base: &Path,
+    increments: &[&Path],
+    dest: &Path,
+) -> Result<BackupLabel, Error> {
+    let mut label = read_label(base)?;
+    fs::create_dir_all(dest.join(BACKUP_WAL))?;
+    fs::copy(base.join(BACKUP_HEAP), dest.join(BACKUP_HEAP))?;
+    let mut sources = vec![base.join(BACKUP_WAL)];
+    for increment in increments {
+        let next = read_label(increment)?;
+        if next.start != label.end {
+            return Err(Error::BrokenBackupChain {
+                expected: label.end,
+                found: next.start,
+            });
+        }
+        label.end = next.end;
+        sources.push(increment.join(BACKUP_WAL));
+    }
+    // 同じセグメントは後のバックアップのほうが長く写している
+    for source in sources.iter().filter(|source| source.exists()) {
+        for entry in fs::read_dir(source)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap();
+            // チェックポイントの位置を書いたファイルは写さない
+            if name.len() == 16 {
+                fs::copy(&path, dest.join(BACKUP_WAL).join(name))?;
+            }
+        }
+    }
+    Wal::open(dest.join(BACKUP_WAL))?.set_checkpoint_lsn(label.start)?;
+    write_label(dest, label)?;
+    Ok(label)
+}
+
+// online_backup や restore_incremental で作ったディレクトリに写し終えた時点までのログがあることを確かめ、
+// heap のファイルとログのディレクトリを返す
+pub fn backup_files(backup: &Path) -> Result<(PathBuf, PathBuf), Error> {
+    let label = read_label(backup)?;
+    let wal_dir = backup.join(BACKUP_WAL);
+    if Wal::open(&wal_dir)?.next_lsn() < label.end {
+        return Err(Error::IncompleteBackup(label.end));
+    }
+    Ok((backup.join(BACKUP_HEAP), wal_dir))
+}
+
+// online_backup で作ったディレクトリのファイルをそのまま使って開き、回復する
+pub fn open_backup(
+    backup: &Path,
+    pool: BufferPool,
+) -> Result<(BufferPoolManager, RecoveryStats), Error> {
+    let (heap, wal_dir) = backup_files(backup)?;
+    let mut bufmgr = BufferPoolManager::new(DiskManager::open(heap)?, pool);
+    bufmgr.set_wal(Wal::open(wal_dir)?);
+    let stats = recover(&mut bufmgr)?;
+    Ok((bufmgr, stats))
+}
+
+// 汚れたページを少しずつ書き出し、ログが進んだらチェックポイントを書く。呼び出し側が定期的に step を呼ぶ
+pub struct Checkpointer {
+    // 前のチェックポイントからログがこれだけ進んだら次のチェックポイントを書く
+    interval: u64,
+    // 1 回の step で書き出すページの数
+    pages_per_step: usize,
+    last: Lsn,
+}
+
+impl Checkpointer {
+    pub fn new(interval: u64, pages_per_step: usize) -> Self {
+        Self {
+            interval,
+            pages_per_step,
+            last: Lsn::INVALID_LSN,
+        }
+    }
+
+    // 汚れたページを古い変更のものから書き出す。チェックポイントを書いたらその LSN を返す
+    pub fn step(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        active: &[ActiveTxn],
+        next_txn: u64,
+    ) -> Result<Option<Lsn>, Error> {
+        let mut dirty = bufmgr.dirty_pages();
+        dirty.sort_unstable_by_key(|&(_, lsn)| lsn);
+        for &(page_id, _) in dirty.iter().take(self.pages_per_step) {
+            bufmgr.flush_page(page_id)?;
+        }
+        if self.last.valid().is_none() {
+            self.last = wal(bufmgr)?.checkpoint_lsn()?.unwrap_or(Lsn::INVALID_LSN);
+        }
+        if wal(bufmgr)?.next_lsn().0 < self.last.0 + self.interval {
+            return Ok(None);
+        }
+        self.last = checkpoint(bufmgr, active, next_txn)?;
+        Ok(Some(self.last))
+    }
+}
+
+fn write(buffer: &Buffer, record: &LogRecord, lsn: Lsn) {
+    record.redo(buffer.page.borrow_mut().as_mut());
+    buffer.lsn.set(lsn);
+    if buffer.rec_lsn.get().valid().is_none() {
+        buffer.rec_lsn.set(lsn);
+    }
+    buffer.is_dirty.set(true);
+}
+
+pub fn recover(bufmgr: &mut BufferPoolManager) -> Result<RecoveryStats, Error> {
+    // 正しく閉じていれば、ページはすべて書き出してあり取り消すトランザクションもないので、
+    // チェックポイントから次のトランザクションの番号だけを読む
+    let log = wal(bufmgr)?;
+    if log.is_clean() {
+        if let Some(lsn) = log.checkpoint_lsn()? {
+            if let LogRecord::Checkpoint { next_txn, .. } = log.record(lsn)? {
+                return Ok(RecoveryStats {
+                    next_txn,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    recover_until(bufmgr, None)
+}
+
+// 最後のチェックポイントで次に振る番号だったものと、チェックポイントのあとにコミットを記したトランザクション。
+// ページの像から回復したときに、ページに残った版のどれがコミットしたものかを決めるのに使う
+pub fn committed(bufmgr: &mut BufferPoolManager) -> Result<(u64, HashSet<u64>), Error> {
+    let log = wal(bufmgr)?;
+    let checkpoint = log.checkpoint_lsn()?;
+    let mut next_txn = 0;
+    if let Some(lsn) = checkpoint {
+        if let LogRecord::Checkpoint { next_txn: next, .. } = log.record(lsn)? {
+            next_txn = next;
+        }
+    }
+    let mut committed = HashSet::new();
+    let mut reader = log.reader(checkpoint.unwrap_or(log.first_lsn()))?;
+    while let Some((_, record)) = reader.next_record()? {
+        if let LogRecord::Commit { txn, .. } = record {
+            committed.insert(txn);
+        }
+    }
+    Ok((next_txn, committed))
+}
+
+// 汚れたページをすべて書き出してチェックポイントを書き、正しく閉じたと記す。
+// 次に開いたときの recover はログを読まずに終わる。実行中のトランザクションがあれば、回復で取り消すので記さない
+pub fn shutdown(
+    bufmgr: &mut BufferPoolManager,
+    active: &[ActiveTxn],
+    next_txn: u64,
+) -> Result<Lsn, Error> {
+    bufmgr.flush()?;
+    let lsn = checkpoint(bufmgr, active, next_txn)?;
+    if active.is_empty() {
+        wal(bufmgr)?.mark_clean()?;
+    }
+    Ok(lsn)
+}
+
+// target までのログを当て、その時点でコミットしていなかったトランザクションを終わらせる。
+// target より後ろのログは捨てるので、restore_backup で戻したファイルに使う
+pub fn recover_until(
+    bufmgr: &mut BufferPoolManager,
+    target: Option<RecoveryTarget>,
+) -> Result<RecoveryStats, Error> {
+    let mut stats = RecoveryStats::default();
+    let log = wal(bufmgr)?;
+    let start = log.checkpoint_lsn()?.unwrap_or(log.first_lsn());
+    let stop = match target {
+        Some(target) => stop_lsn(log, start, target)?,
+        None => None,
+    };
+    let (txns, dirty, next_txn) = analyze(log, start, stop)?;
+    stats.next_txn = next_txn;
+    // 準備したトランザクションは終わらせず、COMMIT PREPARED か ROLLBACK PREPARED を待つ
+    let mut active = HashMap::new();
+    // ログを書く前にチェックポイントに載ったトランザクションは、レコードがなければ終わらせる
+    for (id, txn) in txns {
+        let last = match txn.last.valid() {
+            Some(last) => Some(log.record(last)?),
+            None => None,
+        };
+        match last {
+            Some(LogRecord::Prepare { gid, .. }) => stats.prepared.push(PreparedTxn { gid, txn }),
+            _ => {
+                active.insert(id, txn.last);
+            }
+        }
+    }
+    stats.prepared.sort_by(|a, b| a.gid.cmp(&b.gid));
+    if let Some(&start) = dirty.values().min() {
+        stats.redone = redo(bufmgr, start, &dirty, stop)?;
+    }
+    if let Some(stop) = stop {
+        wal(bufmgr)?.discard_from(stop)?;
+    }
+    stats.aborted = active.keys().copied().collect();
+    stats.aborted.sort_unstable();
+    let wal = wal(bufmgr)?;
+    for &txn in &stats.aborted {
+        wal.append(&LogRecord::End {
+            txn,
+            prev: active[&txn],
+        })?;
+    }
+    wal.flush(wal.next_lsn())?;
+    Ok(stats)
+}
+
+// 実行中だったトランザクションとその最初と最後のレコード、汚れていたかもしれないページとその最初の像、
+// 次に振ってよいトランザクションの番号を返す
+#[allow(clippy::type_complexity)]
+fn analyze(
+    wal: &mut Wal,
+    start: Lsn,
+    stop: Option<Lsn>,
+) -> Result<(HashMap<u64, ActiveTxn>, HashMap<PageId, Lsn>, u64), Error> {
+    let mut active = HashMap::new();
+    let mut dirty = HashMap::new();
+    let mut next_txn = 0;
+    let mut reader = wal.reader(start)?;
+    while let Some((lsn, record)) = reader.next_record()? {
+        if stop.is_some_and(|stop| lsn >= stop) {
+            break;
+        }
+        if let Some(txn) = record.txn() {
+            next_txn = next_txn.max(txn + 1);
+        }
+        match record {
+            LogRecord::Checkpoint {
+                active: txns,
+                dirty: pages,
+                next_txn: next,
+            } if lsn == start => {
+                active.extend(txns.iter().map(|txn| (txn.txn, *txn)));
+                dirty.extend(pages);
+                next_txn = next_txn.max(next);
+            }
+            LogRecord::Checkpoint { .. } => {}
+            LogRecord::Abort { txn, .. }
+            | LogRecord::Prepare { txn, .. }
+            | LogRecord::Changes { txn: Some(txn), .. } => {
+                seen(&mut active, txn, lsn);
+            }
+            LogRecord::PageImage { page_id, .. } | LogRecord::PageDelta { page_id, .. } => {
+                dirty.entry(page_id).or_insert(lsn);
+            }
+            LogRecord::Changes { txn: None, .. } => {}
+            LogRecord::Commit { txn, .. } | LogRecord::End { txn, .. } => {
+                active.remove(&txn);
+            }
+        }
+    }
+    Ok((active, dirty, next_txn))
+}
+
+// target で止めるとき、当てない最初のレコード。ログの末尾まで当てるなら None
+fn stop_lsn(wal: &mut Wal, start: Lsn, target: RecoveryTarget) -> Result<Option<Lsn>, Error> {
+    if let RecoveryTarget::Lsn(lsn) = target {
+        if lsn < start {
+            return Err(Error::TargetBeforeBackup(lsn));
+        }
+    }
+    let mut reader = wal.reader(start)?;
+    while let Some((lsn, record)) = reader.next_record()? {
+        let stop = match (target, record) {
+            (RecoveryTarget::Lsn(target), _) => lsn > target,
+            (RecoveryTarget::Time(target), LogRecord::Commit { time, .. }) => {
+                time > wal::micros(target)
+            }
+            _ => false,
+        };
+        if stop {
+            return Ok(Some(lsn));
+        }
+    }
+    Ok(None)
+}
+
+// トランザクションのレコードを lsn に見つけた
+fn seen(active: &mut HashMap<u64, ActiveTxn>, txn: u64, lsn: Lsn) {
+    let entry = active.entry(txn).or_insert(ActiveTxn {
+        txn,
+        first: lsn,
+        last: lsn,
+    });
+    if entry.first.valid().is_none() {
+        entry.first = lsn;
+    }
+    entry.last = lsn;
+}
+
+// 実行中のトランザクションを終わらせずにログを当てる。スタンバイは終わっていないトランザクションの続きを受け取る
+pub fn replay(bufmgr: &mut BufferPoolManager) -> Result<usize, Error> {
+    let log = wal(bufmgr)?;
+    let start = log.checkpoint_lsn()?.unwrap_or(log.first_lsn());
+    let (_, dirty, _) = analyze(log, start, None)?;
+    match dirty.values().min() {
+        Some(&start) => redo(bufmgr, start, &dirty, None),
+        None => Ok(0),
+    }
+}
+
+// ほかのログから受け取った lsn のレコードをページに当てる。ページ LSN より新しい像と差分だけ当て、当てたら true
+pub fn apply(bufmgr: &mut BufferPoolManager, lsn: Lsn, record: &LogRecord) -> Result<bool, Error> {
+    let Some(page_id) = record.page_id() else {
+        return Ok(false);
+    };
+    let buffer = bufmgr.fetch_page(page_id)?;
+    if buffer.lsn.get() >= lsn {
+        return Ok(false);
+    }
+    write(&buffer, record, lsn);
+    Ok(true)
+}
+
+fn redo(
+    bufmgr: &mut BufferPoolManager,
+    start: Lsn,
+    dirty: &HashMap<PageId, Lsn>,
+    stop: Option<Lsn>,
+) -> Result<usize, Error> {
+    let mut redone = 0;
+    let mut reader = wal(bufmgr)?.reader(start)?;
+    while let Some((lsn, record)) = reader.next_record()? {
+        if stop.is_some_and(|stop| lsn >= stop) {
+            break;
+        }
+        let Some(page_id) = record.page_id() else {
+            continue;
+        };
+        // 書き出してあったページや、汚れるより前の像は当て直さなくてよい
+        if dirty.get(&page_id).is_none_or(|&rec_lsn| lsn < rec_lsn) {
+            continue;
+        }
+        if apply(bufmgr, lsn, &record)? {
+            redone += 1;
+        }
+    }
+    Ok(redone)
+}
+
+// トランザクションを取り消したと記して終わらせ、そのレコードがディスクに届いてから返る。
+// last はトランザクションが最後に書いたレコード。書き換えた行の版は、呼び出し側が演算子の残した変更から戻す
+pub fn rollback(bufmgr: &mut BufferPoolManager, txn: u64, last: Lsn) -> Result<(), Error> {
+    let wal = wal(bufmgr)?;
+    let abort = wal.append(&LogRecord::Abort { txn, prev: last })?;
+    let end = wal.append(&LogRecord::End { txn, prev: abort })?;
+    wal.flush(end)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_path;
+    use std::thread;
+    use std::time::Duration;
+
+    // データベースと同じように、コミットするときにページの像を書くバッファプール
+    fn open(heap: &Path, wal: &Path) -> BufferPoolManager {
+        let disk = DiskManager::open(heap).unwrap();
+        let mut bufmgr = BufferPoolManager::new(disk, BufferPool::new(4));
+        bufmgr.set_wal(Wal::open(wal).unwrap());
+        bufmgr.log_page_images();
+        bufmgr
+    }
+
+    fn read(bufmgr: &mut BufferPoolManager, page_id: PageId, offset: usize) -> Vec<u8> {
+        let buffer = bufmgr.fetch_page(page_id).unwrap();
+        let page = buffer.page.borrow();
+        page[offset..offset + 3].to_vec()
+    }
+
+    fn records(bufmgr: &mut BufferPoolManager) -> Vec<LogRecord> {
+        let wal = bufmgr.wal_mut().unwrap();
+        let mut reader = wal.reader(wal.first_lsn()).unwrap();
+        let mut records = vec![];
+        while let Some((_, record)) = reader.next_record().unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    // ログを書かずにページの offset から data を書く
+    fn write_page(page: &Buffer, offset: usize, data: &[u8]) {
+        page.page.borrow_mut()[offset..offset + data.len()].copy_from_slice(data);
+        page.is_dirty.set(true);
+    }
+
+    // ページを書き換えてコミットする。ページの像とコミットをログに書き、ページは書き出さない
+    fn commit(
+        bufmgr: &mut BufferPoolManager,
+        page: &Buffer,
+        txn: u64,
+        offset: usize,
+        data: &[u8],
+    ) -> Lsn {
+        write_page(page, offset, data);
+        bufmgr.log_images().unwrap();
+        bufmgr
+            .wal_mut()
+            .unwrap()
+            .commit(txn, Lsn::INVALID_LSN)
+            .unwrap()
+    }
+
+    // 変更を書いたがコミットを書く前のトランザクション
+    fn changes(bufmgr: &mut BufferPoolManager, txn: u64) -> ActiveTxn {
+        let wal = bufmgr.wal_mut().unwrap();
+        let lsn = wal
+            .append(&LogRecord::Changes {
+                txn: Some(txn),
+                prev: Lsn::INVALID_LSN,
+                changes: vec![],
+            })
+            .unwrap();
+        wal.flush(lsn).unwrap();
+        ActiveTxn {
+            txn,
+            first: lsn,
+            last: lsn,
+        }
+    }
+
+    #[test]
+    fn test_recover() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let mut bufmgr = open(&heap, &dir);
+        let p1 = bufmgr.create_page().unwrap();
+        let p2 = bufmgr.create_page().unwrap();
+        bufmgr.flush().unwrap();
+
+        // 1 はコミットしたが書き出していない。2 はコミットせずに書き出し、3 はコミットを書く前に落ちる
+        commit(&mut bufmgr, &p1, 1, 100, b"aaa");
+        write_page(&p2, 200, b"bbb");
+        bufmgr.flush_page(p2.page_id).unwrap();
+        changes(&mut bufmgr, 3);
+        let (p1, p2) = (p1.page_id, p2.page_id);
+        drop(bufmgr);
+
+        let mut bufmgr = open(&heap, &dir);
+        assert_eq!(read(&mut bufmgr, p1, 100), vec![0; 3]);
+        assert_eq!(read(&mut bufmgr, p2, 200), b"bbb");
+        let stats = recover(&mut bufmgr).unwrap();
+        // 作ったページを書き出したときの元の像も当てる
+        assert_eq!(
+            stats,
+            RecoveryStats {
+                redone: 4,
+                aborted: vec![3],
+                next_txn: 4,
+                prepared: vec![],
+            }
+        );
+        // 書き出す前に元の像を書いたので、コミットしていないページも戻る
+        assert_eq!(read(&mut bufmgr, p1, 100), b"aaa");
+        assert_eq!(read(&mut bufmgr, p2, 200), vec![0; 3]);
+        let records = records(&mut bufmgr);
+        assert!(matches!(
+            records.last(),
+            Some(LogRecord::End { txn: 3, .. })
+        ));
+        drop(bufmgr);
+
+        // もう一度落ちても、終わらせたトランザクションは数えない
+        let mut bufmgr = open(&heap, &dir);
+        let stats = recover(&mut bufmgr).unwrap();
+        assert_eq!(stats.aborted, Vec::<u64>::new());
+        assert_eq!(read(&mut bufmgr, p1, 100), b"aaa");
+        assert_eq!(read(&mut bufmgr, p2, 200), vec![0; 3]);
+        std::fs::remove_file(&heap).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_page_delta() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let mut bufmgr = open(&heap, &dir);
+        let page = bufmgr.create_page().unwrap();
+        bufmgr.flush().unwrap();
+        let start = bufmgr.wal_mut().unwrap().next_lsn();
+
+        // 初めて書くページは全体の像を、そのあとは変わったところだけを書く。近い変更は 1 つにまとめる
+        commit(&mut bufmgr, &page, 1, 0, b"aaa");
+        commit(&mut bufmgr, &page, 2, 3, b"bbb");
+        write_page(&page, 100, b"x");
+        commit(&mut bufmgr, &page, 3, 104, b"y");
+        // チェックポイントのあとは、また全体の像から始める
+        checkpoint(&mut bufmgr, &[], 4).unwrap();
+        commit(&mut bufmgr, &page, 4, 6, b"ccc");
+        commit(&mut bufmgr, &page, 5, 9, b"ddd");
+        let page_id = page.page_id;
+        drop(page);
+
+        let wal = bufmgr.wal_mut().unwrap();
+        let mut reader = wal.reader(start).unwrap();
+        let mut changes = vec![];
+        while let Some((_, record)) = reader.next_record().unwrap() {
+            match record {
+                LogRecord::PageImage { .. } => changes.push(None),
+                LogRecord::PageDelta { ranges, .. } => changes.push(Some(ranges)),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            changes,
+            [
+                None,
+                Some(vec![(3, b"bbb".to_vec())]),
+                Some(vec![(100, b"x\0\0\0y".to_vec())]),
+                None,
+                Some(vec![(9, b"ddd".to_vec())]),
+            ]
+        );
+        drop(bufmgr);
+
+        // 書き出さずに落ちても戻る。チェックポイントに載ったページは、その前の全体の像から当て直す
+        let mut bufmgr = open(&heap, &dir);
+        let stats = recover(&mut bufmgr).unwrap();
+        assert_eq!(stats.redone, 5);
+        let buffer = bufmgr.fetch_page(page_id).unwrap();
+        assert_eq!(&buffer.page.borrow()[..12], b"aaabbbcccddd");
+        assert_eq!(&buffer.page.borrow()[100..105], b"x\0\0\0y");
+        drop(buffer);
+        std::fs::remove_file(&heap).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_redo_idempotent() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let mut bufmgr = open(&heap, &dir);
+        // バッファより多いページを書き換えてコミットし、前半のページだけを書き出して落ちる
+        let pages: Vec<PageId> = (0..6)
+            .map(|_| bufmgr.create_page().unwrap().page_id)
+            .collect();
+        bufmgr.flush().unwrap();
+        for (i, &page_id) in pages.iter().enumerate() {
+            let page = bufmgr.fetch_page(page_id).unwrap();
+            commit(&mut bufmgr, &page, i as u64 + 1, 0, &[b'a' + i as u8; 3]);
+        }
+        drop(bufmgr);
+
+        // スタンバイのように像を書かないバッファプールは、当てた像のページ LSN を書き出す
+        let open_replica = || {
+            let disk = DiskManager::open(&heap).unwrap();
+            let mut bufmgr = BufferPoolManager::new(disk, BufferPool::new(4));
+            bufmgr.set_wal(Wal::open(&dir).unwrap());
+            bufmgr
+        };
+        let mut bufmgr = open_replica();
+        let redone = replay(&mut bufmgr).unwrap();
+        assert!(redone >= 6, "{}", redone);
+        for &page_id in &pages[..3] {
+            bufmgr.fetch_page(page_id).unwrap();
+            bufmgr.flush_page(page_id).unwrap();
+        }
+        let written = pages
+            .iter()
+            .filter(|&&page_id| !bufmgr.dirty_pages().iter().any(|&(p, _)| p == page_id))
+            .count();
+        assert!(written >= 3);
+        drop(bufmgr);
+
+        // ページ LSN が追いついているページには当て直さない
+        let mut bufmgr = open_replica();
+        assert!(replay(&mut bufmgr).unwrap() < redone);
+        bufmgr.flush().unwrap();
+        drop(bufmgr);
+
+        // 当て直しを書き出したあとに落ちても、もう一度当て直すものはない
+        let mut bufmgr = open_replica();
+        assert_eq!(replay(&mut bufmgr).unwrap(), 0);
+        let stats = recover(&mut bufmgr).unwrap();
+        assert_eq!(stats.redone, 0);
+        for (i, &page_id) in pages.iter().enumerate() {
+            assert_eq!(read(&mut bufmgr, page_id, 0), [b'a' + i as u8; 3]);
+        }
+        std::fs::remove_file(&heap).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_clean_shutdown() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let mut bufmgr = open(&heap, &dir);
+        let page = bufmgr.create_page().unwrap();
+        commit(&mut bufmgr, &page, 1, 100, b"aaa");
+        let p1 = page.page_id;
+        drop(page);
+        shutdown(&mut bufmgr, &[], 2).unwrap();
+        drop(bufmgr);
+
+        // 正しく閉じたのでログを当てずに番号だけを返す
+        let mut bufmgr = open(&heap, &dir);
+        assert!(bufmgr.wal_mut().unwrap().is_clean());
+        let stats = recover(&mut bufmgr).unwrap();
+        assert_eq!(
+            stats,
+            RecoveryStats {
+                next_txn: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(read(&mut bufmgr, p1, 100), b"aaa");
+        drop(bufmgr);
+
+        // 開いたときに DIRTY に戻すので、閉じないまま落ちれば回復する
+        let mut bufmgr = open(&heap, &dir);
+        assert!(!bufmgr.wal_mut().unwrap().is_clean());
+        let page = bufmgr.fetch_page(p1).unwrap();
+        let lsn = commit(&mut bufmgr, &page, 2, 100, b"bbb");
+        drop(page);
+        drop(bufmgr);
+        let mut bufmgr = open(&heap, &dir);
+        let stats = recover(&mut bufmgr).unwrap();
+        assert_eq!((stats.redone, stats.next_txn), (1, 3));
+        assert_eq!(read(&mut bufmgr, p1, 100), b"bbb");
+
+        // 実行中のトランザクションがあれば正しく閉じたとは記さない
+        let active = ActiveTxn {
+            txn: 3,
+            first: lsn,
+            last: lsn,
+        };
+        shutdown(&mut bufmgr, &[active], 3).unwrap();
+        drop(bufmgr);
+        assert!(!open(&heap, &dir).wal_mut().unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_recover_from_checkpoint() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let mut bufmgr = open(&heap, &dir);
+        let a = bufmgr.create_page().unwrap();
+        let b = bufmgr.create_page().unwrap();
+        bufmgr.flush().unwrap();
+        let first = commit(&mut bufmgr, &a, 1, 0, b"abc");
+        commit(&mut bufmgr, &b, 2, 0, b"zzz");
+        let active = changes(&mut bufmgr, 3);
+
+        // 像の古いほうのページだけを書き出す。どちらのページも最後の像からやり直すようチェックポイントに載る
+        let mut checkpointer = Checkpointer::new(0, 1);
+        let checkpoint = checkpointer
+            .step(&mut bufmgr, &[active], 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!([&a, &b].iter().filter(|p| p.is_dirty.get()).count(), 1);
+        assert!(checkpoint > first);
+        assert_eq!(bufmgr.dirty_pages().len(), 2);
+        assert_eq!(
+            bufmgr.wal_mut().unwrap().checkpoint_lsn().unwrap(),
+            Some(checkpoint)
+        );
+        // チェックポイントのあとに a を書き換えたが、コミットしないまま書き出した
+        write_page(&a, 3, b"def");
+        bufmgr.flush_page(a.page_id).unwrap();
+        let (a, b) = (a.page_id, b.page_id);
+        drop(bufmgr);
+
+        let mut bufmgr = open(&heap, &dir);
+        let stats = recover(&mut bufmgr).unwrap();
+        assert_eq!(
+            stats,
+            RecoveryStats {
+                // 変わっていないページはコミットで書き直さないので、像は 2 つ
+                redone: 2,
+                aborted: vec![3],
+                // 番号はログを切り詰めてもチェックポイントから引き継ぐ
+                next_txn: 10,
+                prepared: vec![],
+            }
+        );
+        assert_eq!(read(&mut bufmgr, a, 0), b"abc");
+        assert_eq!(read(&mut bufmgr, a, 3), vec![0; 3]);
+        assert_eq!(read(&mut bufmgr, b, 0), b"zzz");
+        std::fs::remove_file(&heap).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_point_in_time_recovery() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let (archive, backup) = (temp_path("archive"), temp_path("backup"));
+        let mut bufmgr = open(&heap, &dir);
+        bufmgr.wal_mut().unwrap().set_archive_dir(&archive).unwrap();
+        let page = bufmgr.create_page().unwrap();
+        commit(&mut bufmgr, &page, 2, 0, b"aaa");
+        let label = base_backup(&mut bufmgr, &heap, &backup, &[], 3).unwrap();
+
+        // バックアップのあとに 3 と 4 がコミットし、5 は実行中のまま
+        let third = commit(&mut bufmgr, &page, 3, 3, b"bbb");
+        thread::sleep(Duration::from_millis(2));
+        let time = SystemTime::now();
+        thread::sleep(Duration::from_millis(2));
+        commit(&mut bufmgr, &page, 4, 6, b"ccc");
+        changes(&mut bufmgr, 5);
+        bufmgr.wal_mut().unwrap().switch_segment().unwrap();
+        let page_id = page.page_id;
+        drop(page);
+        drop(bufmgr);
+
+        // 3 のコミットで止めると 4 の像は当てず、時刻で止めると 4 のコミットの手前まで像を当てる。
+        // どちらでも 4 はコミットしていないので、4 の版は開くときに片づける
+        for (target, ccc) in [
+            (RecoveryTarget::Lsn(third), &[0; 3]),
+            (RecoveryTarget::Time(time), b"ccc"),
+        ] {
+            restore_backup(&backup, &archive, &heap, &dir).unwrap();
+            let mut bufmgr = open(&heap, &dir);
+            let stats = recover_until(&mut bufmgr, Some(target)).unwrap();
+            assert!(stats.aborted.is_empty());
+            assert_eq!(stats.next_txn, 4);
+            assert_eq!(read(&mut bufmgr, page_id, 0), b"aaa");
+            assert_eq!(read(&mut bufmgr, page_id, 3), b"bbb");
+            assert_eq!(read(&mut bufmgr, page_id, 6), ccc);
+            let (_, committed) = committed(&mut bufmgr).unwrap();
+            assert_eq!(committed, HashSet::from([3]));
+            // 止めた位置より後ろのログは捨ててある
+            let records = records(&mut bufmgr);
+            assert!(records.iter().all(|record| record.txn() != Some(5)));
+        }
+
+        restore_backup(&backup, &archive, &heap, &dir).unwrap();
+        let mut bufmgr = open(&heap, &dir);
+        assert!(matches!(
+            recover_until(&mut bufmgr, Some(RecoveryTarget::Lsn(Lsn(label.0 - 1)))),
+            Err(Error::TargetBeforeBackup(_))
+        ));
+        drop(bufmgr);
+        std::fs::remove_file(&heap).unwrap();
+        for dir in [dir, archive, backup] {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_online_backup() {
+        let (heap, dir, backup) = (temp_path("heap.db"), temp_path("wal"), temp_path("backup"));
+        let mut bufmgr = open(&heap, &dir);
+        let a = bufmgr.create_page().unwrap();
+        let b = bufmgr.create_page().unwrap();
+        bufmgr.flush().unwrap();
+
+        // 2 はコミットしたが書き出していない。1 はコミットせずに書き出し、変更を書いたところで止まっている
+        commit(&mut bufmgr, &b, 2, 0, b"bbb");
+        write_page(&a, 0, b"aaa");
+        bufmgr.flush_page(a.page_id).unwrap();
+        let active = changes(&mut bufmgr, 1);
+        let label = online_backup(&mut bufmgr, &heap, &backup, &[active], 3).unwrap();
+        assert!(label.start < label.end);
+        assert!(b.is_dirty.get());
+
+        // バックアップのあとの変更は入らない
+        commit(&mut bufmgr, &b, 3, 3, b"ccc");
+        bufmgr.write_logged().unwrap();
+        let (a, b) = (a.page_id, b.page_id);
+
+        let (mut restored, stats) = open_backup(&backup, BufferPool::new(4)).unwrap();
+        assert_eq!(stats.aborted, vec![1]);
+        assert_eq!(read(&mut restored, a, 0), vec![0; 3]);
+        assert_eq!(read(&mut restored, b, 0), b"bbb");
+        assert_eq!(read(&mut restored, b, 3), vec![0; 3]);
+        drop(restored);
+        // 元のデータベースはそのまま動いている
+        assert_eq!(read(&mut bufmgr, b, 3), b"ccc");
+        drop(bufmgr);
+
+        // ログが欠けたバックアップは開かない
+        let label = read_label(&backup).unwrap();
+        let segment = format!("{:016X}", label.end.0 / wal::SEGMENT_SIZE);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(backup.join(BACKUP_WAL).join(segment))
+            .unwrap()
+            .set_len(label.start.0 % wal::SEGMENT_SIZE)
+            .unwrap();
+        assert!(matches!(
+            open_backup(&backup, BufferPool::new(4)),
+            Err(Error::IncompleteBackup(_))
+        ));
+        std::fs::remove_file(&heap).unwrap();
+        for dir in [dir, backup] {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_incremental_backup() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let [base, first, second, restored, broken] =
+            ["base", "inc1", "inc2", "restored", "broken"].map(temp_path);
+        let mut bufmgr = open(&heap, &dir);
+        let page = bufmgr.create_page().unwrap();
+        commit(&mut bufmgr, &page, 1, 0, b"aaa");
+        let label = online_backup(&mut bufmgr, &heap, &base, &[], 2).unwrap();
+        commit(&mut bufmgr, &page, 2, 3, b"bbb");
+        let inc1 = incremental_backup(&mut bufmgr, &base, &first).unwrap();
+        assert_eq!(inc1.start, label.end);
+        commit(&mut bufmgr, &page, 3, 6, b"ccc");
+        let inc2 = incremental_backup(&mut bufmgr, &first, &second).unwrap();
+        assert_eq!(inc2.start, inc1.end);
+        // 増分には heap を写さない
+        assert!(!second.join(BACKUP_HEAP).exists());
+        let page_id = page.page_id;
+        drop(page);
+        drop(bufmgr);
+
+        let chained = restore_incremental(&base, &[&first, &second], &restored).unwrap();
+        assert_eq!(
+            chained,
+            BackupLabel {
+                start: label.start,
+                end: inc2.end,
+            }
+        );
+        let (mut bufmgr, _) = open_backup(&restored, BufferPool::new(4)).unwrap();
+        assert_eq!(read(&mut bufmgr, page_id, 0), b"aaa");
+        assert_eq!(read(&mut bufmgr, page_id, 3), b"bbb");
+        assert_eq!(read(&mut bufmgr, page_id, 6), b"ccc");
+        drop(bufmgr);
+
+        // 間の増分が欠けていればつながらない
+        assert!(matches!(
+            restore_incremental(&base, &[&second], &broken),
+            Err(Error::BrokenBackupChain { expected, found })
+                if expected == label.end && found == inc1.end
+        ));
+        std::fs::remove_file(&heap).unwrap();
+        for dir in [dir, base, first, second, restored, broken] {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use std::fmt;
+
+// REGEXP に使う正規表現
+//
+// 文字、.、[...] の文字クラス、\d \w \s とその否定、^ と $、( ) のまとまり、| の選択、
+// * + ? {n} {n,} {n,m} の繰り返しを扱う。パターンは命令の列にして、
+// 入力のどの位置から始めた照合も同時に 1 回なぞる (Pike VM)。そのため入力の長さに比例した時間で終わる。
+// 一致したかどうかだけを返し、どこに一致したかは覚えない
+
+// 繰り返しの回数の上限。命令の列が大きくなりすぎないようにする
+const MAX_REPEAT: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError(String);
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != self.negated
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Class),
+    Bol,
+    Eol,
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Bol,
+    Eol,
+    // 両方の行き先へ分かれる
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    insts: Vec<Inst>,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+fn error(message: impl Into<String>) -> RegexError {
+    RegexError(message.into())
+}
+
+impl Parser<'_> {
+    fn parse_alt(&mut self) -> Result<Node, RegexError> {
+        let mut alternatives = vec![self.parse_concat()?];
+        while self.chars.next_if_eq(&'|').is_some() {
+            alternatives.push(self.parse_concat()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Node::Alt(alternatives)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = vec![];
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_repeat(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, RegexError> {
+        let node = match self.chars.next().unwrap() {
+            '(' => {
+                // (?: ...) も同じまとまりとして読む
+                if self.chars.next_if_eq(&'?').is_some() && self.chars.next_if_eq(&':').is_none() {
+                    return Err(error("unsupported group"));
+                }
+                let node = self.parse_alt()?;
+                if self.chars.next() != Some(')') {
+                    return Err(error("parentheses () not balanced"));
+                }
+                node
+            }
+            '[' => Node::Class(self.parse_class()?),
+            '.' => Node::Any,
+            '^' => Node::Bol,
+            '$' => Node::Eol,
+            '\\' => self.parse_escape()?,
+            c @ ('*' | '+' | '?' | '{') => {
+                return Err(error(format!("quantifier operand invalid: {}", c)))
+            }
+            c => Node::Char(c),
+        };
+        Ok(node)
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, RegexError> {
+        let c = self
+            .chars
+            .next()
+            .ok_or_else(|| error("invalid escape \\ sequence"))?;
+        Ok(match shorthand(c) {
+            Some(class) => Node::Class(class),
+            None => Node::Char(escaped(c)),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Class, RegexError> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = self
+                .chars
+                .next()
+                .ok_or_else(|| error("brackets [] not balanced"))?;
+            // 先頭の ] は文字として扱う
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = match c {
+                '\\' => {
+                    let c = self
+                        .chars
+                        .next()
+                        .ok_or_else(|| error("brackets [] not balanced"))?;
+                    if let Some(class) = shorthand(c) {
+                        if class.negated {
+                            return Err(error("negated shorthand in brackets"));
+                        }
+                        ranges.extend(class.ranges);
+                        continue;
+                    }
+                    escaped(c)
+                }
+                c => c,
+            };
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') && !matches!(lookahead.peek(), Some(']') | None) {
+                self.chars.next();
+                let hi = match self.chars.next().unwrap() {
+                    '\\' => escaped(
+                        self.chars
+                            .next()
+                            .ok_or_else(|| error("brackets [] not balanced"))?,
+                    ),
+                    c => c,
+                };
+                if hi < lo {
+                    return Err(error("invalid character range"));
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Class { ranges, negated })
+    }
+
+    fn parse_repeat(&mut self, mut node: Node) -> Result<Node, RegexError> {
+        loop {
+            let (min, max) = match self.chars.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    self.chars.next();
+                    let (min, max) = self.parse_bounds()?;
+                    node = repeat(node, min, max)?;
+                    self.chars.next_if_eq(&'?');
+                    continue;
+                }
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            // 最短一致の ? は一致するかどうかに関わらない
+            self.chars.next_if_eq(&'?');
+            node = repeat(node, min, max)?;
+        }
+    }
+
+    // {n}、{n,}、{n,m} の { より後ろ
+    fn parse_bounds(&mut self) -> Result<(u32, Option<u32>), RegexError> {
+        let mut number = || {
+            let mut digits = String::new();
+            while let Some(c) = self.chars.next_if(char::is_ascii_digit) {
+                digits.push(c);
+            }
+            digits.parse::<u32>().ok()
+        };
+        let min = number().ok_or_else(|| error("invalid repetition count(s)"))?;
+        let max = if self.chars.next_if_eq(&',').is_some() {
+            let mut digits = String::new();
+            while let Some(c) = self.chars.next_if(char::is_ascii_digit) {
+                digits.push(c);
+            }
+            if digits.is_empty() {
+                None
+            } else {
+                Some(
+                    digits
+                        .parse::<u32>()
+                        .map_err(|_| error("invalid repetition count(s)"))?,
+                )
+            }
+        } else {
+            Some(min)
+        };
+        if self.chars.next() != Some('}') || max.is_some_and(|max| max < min) {
+            return Err(error("invalid repetition count(s)"));
+        }
+        Ok((min, max))
+    }
+}
+
+fn repeat(node: Node, min: u32, max: Option<u32>) -> Result<Node, RegexError> {
+    if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+        return Err(error("invalid repetition count(s)"));
+    }
+    Ok(Node::Repeat {
+        node: Box::new(node),
+        min,
+        max,
+    })
+}
+
+// \d \w \s と、その大文字の否定
+fn shorthand(c: char) -> Option<Class> {
+    let ranges = match c.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('0', '9'), ('A', 'Z'), ('a', 'z'), ('_', '_')],
+        's' => vec![(' ', ' '), ('\t', '\r')],
+        _ => return None,
+    };
+    Some(Class {
+        ranges,
+        negated: c.is_ascii_uppercase(),
+    })
+}
+
+fn escaped(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Char(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            Node::Any => {
+                self.emit(Inst::Any);
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()));
+            }
+            Node::Bol => {
+                self.emit(Inst::Bol);
+            }
+            Node::Eol => {
+                self.emit(Inst::Eol);
+            }
+            Node::Concat(nodes) => nodes.iter().for_each(|node| self.compile(node)),
+            // Split で最後の選択肢以外へ分かれ、それぞれ終わったら最後へ飛ぶ
+            Node::Alt(nodes) => {
+                let mut jumps = vec![];
+                for (i, node) in nodes.iter().enumerate() {
+                    if i + 1 == nodes.len() {
+                        self.compile(node);
+                        break;
+                    }
+                    let split = self.emit(Inst::Split(0, 0));
+                    self.compile(node);
+                    jumps.push(self.emit(Inst::Jmp(0)));
+                    let next = self.insts.len();
+                    self.insts[split] = Inst::Split(split + 1, next);
+                }
+                let end = self.insts.len();
+                for jump in jumps {
+                    self.insts[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+                match max {
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(node);
+                        self.emit(Inst::Jmp(split));
+                        let end = self.insts.len();
+                        self.insts[split] = Inst::Split(split + 1, end);
+                    }
+                    Some(max) => {
+                        let mut splits = vec![];
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        let end = self.insts.len();
+                        for split in splits {
+                            self.insts[split] = Inst::Split(split + 1, end);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let mut parser = Parser {
+            chars: pattern.chars().peekable(),
+        };
+        let node = parser.parse_alt()?;
+        if parser.chars.next().is_some() {
+            return Err(error("parentheses () not balanced"));
+        }
+        let mut compiler = Compiler { insts: vec![] };
+        compiler.compile(&node);
+        compiler.emit(Inst::Match);
+        Ok(Regex {
+            insts: compiler.insts,
+        })
+    }
+
+    // s のどこかに一致するか
+    pub fn is_match(&self, s: &str) -> bool {
+        let chars = s.chars().collect::<Vec<_>>();
+        let mut current = Threads::new(self.insts.len());
+        let mut next = Threads::new(self.insts.len());
+        for pos in 0..=chars.len() {
+            // どの位置からでも照合を始める
+            if self.add(&mut current, 0, pos, &chars) {
+                return true;
+            }
+            let Some(&c) = chars.get(pos) else {
+                break;
+            };
+            for i in 0..current.pcs.len() {
+                let pc = current.pcs[i];
+                let matched = match &self.insts[pc] {
+                    Inst::Char(expected) => *expected == c,
+                    Inst::Any => true,
+                    Inst::Class(class) => class.matches(c),
+                    _ => false,
+                };
+                if matched && self.add(&mut next, pc + 1, pos + 1, &chars) {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+
+    // pc から文字を読まずに進める命令をたどり、文字を読む命令を threads に加える。Match に着いたら true
+    fn add(&self, threads: &mut Threads, pc: usize, pos: usize, chars: &[char]) -> bool {
+        if !threads.insert(pc) {
+            return false;
+        }
+        match &self.insts[pc] {
+            Inst::Match => true,
+            Inst::Jmp(to) => self.add(threads, *to, pos, chars),
+            Inst::Split(a, b) => {
+                self.add(threads, *a, pos, chars) || self.add(threads, *b, pos, chars)
+            }
+            Inst::Bol => pos == 0 && self.add(threads, pc + 1, pos, chars),
+            Inst::Eol => pos == chars.len() && self.add(threads, pc + 1, pos, chars),
+            Inst::Char(_) | Inst::Any | Inst::Class(_) => {
+                threads.pcs.push(pc);
+                false
+            }
+        }
+    }
+}
+
+// 同じ位置で動いている命令の集合
+struct Threads {
+    pcs: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Self {
+            pcs: vec![],
+            seen: vec![false; len],
+        }
+    }
+
+    fn insert(&mut self, pc: usize) -> bool {
+        !std::mem::replace(&mut self.seen[pc], true)
+    }
+
+    fn clear(&mut self) {
+        self.pcs.clear();
+        self.seen.fill(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex() {
+        for (pattern, s, expected) in [
+            ("abc", "xabcx", true),
+            ("^abc$", "xabc", false),
+            ("^a.c$", "aあc", true),
+            ("^(ab|cd)+$", "abcdab", true),
+            ("^(ab|cd)+$", "abc", false),
+            ("colou?r", "color", true),
+            ("^\\d{3}-\\d{4}$", "123-4567", true),
+            ("^\\d{3}-\\d{4}$", "12-34567", false),
+            ("^[a-cx]+$", "abcx", true),
+            ("^[^a-c]+$", "xyz", true),
+            ("^[^a-c]+$", "xaz", false),
+            ("^[]a]$", "]", true),
+            ("^\\w+@\\w+\\.com$", "me@example.com", true),
+            ("a{2,}", "baab", true),
+            ("^a{2,3}$", "aaaa", false),
+            ("x*", "", true),
+            ("(a|)b", "b", true),
+            ("^\\S+$", "a b", false),
+        ] {
+            let regex = Regex::new(pattern).unwrap();
+            assert_eq!(regex.is_match(s), expected, "{} {}", pattern, s);
+        }
+        // 指数的に遅くなるパターンもすぐに終わる
+        let regex = Regex::new("^(a*)*b$").unwrap();
+        assert!(!regex.is_match(&"a".repeat(10000)));
+
+        for pattern in ["(ab", "ab)", "[ab", "*a", "a{2,1}", "a{1001}", "\\"] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
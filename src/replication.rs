@@ -0,0 +1,482 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::buffer::{self, BufferPoolManager};
+use crate::recovery::{self, RecoveryStats};
+use crate::transaction::StandbyView;
+use crate::wal::{self, LogRecord, Lsn, WalReader};
+
+// ストリーミングレプリケーション
+//
+// プライマリはログを TCP で送り、スタンバイは受け取ったレコードを自分のログの同じ LSN に書いてからページに当てる。
+//
+// 1. スタンバイはつないだら | REPLICATION_MAGIC | 開始 LSN (8) | を送る。開始 LSN はスタンバイのログの末尾。
+// 2. プライマリは | 状態 (1) | LSN (8) | を返す。STATUS_OK なら LSN はプライマリの fsync 済みの位置で、
+//    開始 LSN から送り始める。STATUS_REMOVED なら開始 LSN のセグメントはもう消してあり、
+//    スタンバイはベースバックアップから作り直す。
+// 3. プライマリは fsync 済みのレコードを | LSN (8) | 長さ (4) | 本体 | で順に送る。追いつくまでの分も同じ形で送る。
+//    送るものがなければ KEEPALIVE_INTERVAL ごとに LSN 0、長さ 0 のフレームを送り、切れたスタンバイに気づく。
+// 4. スタンバイはチェックポイントのレコードを受け取るとページをすべて書き出し、次の起動時はそこから当て直す。
+// 5. promote でプライマリとの接続を切り、終わっていないトランザクションを取り消して書けるようにする。
+//
+// Database::open_standby で開いたデータベースは、受け取ったレコードを当てながら読むだけの問い合わせに答える。
+// ページには終わっていないトランザクションの版も載るので、スナップショットはコミットを受け取ったものだけを見る (StandbyView)。
+// 送るのはプライマリのデータベース 1 つのログで、カタログはそのページが変わったら読み直す
+
+const REPLICATION_MAGIC: &[u8; 8] = b"RDBREP01";
+const STATUS_OK: u8 = 0;
+const STATUS_REMOVED: u8 = 1;
+const FRAME_HEADER_SIZE: usize = 12;
+// 送るものがないときに新しいレコードを探す間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(100);
+// 一度に送るフレームの大きさの目安
+const SEND_BATCH: usize = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Buffer(#[from] buffer::Error),
+    #[error(transparent)]
+    Wal(#[from] wal::Error),
+    #[error(transparent)]
+    Recovery(#[from] recovery::Error),
+    #[error("replication protocol violation: {0}")]
+    Protocol(&'static str),
+    #[error("WAL at {0:?} has already been removed on the primary")]
+    WalRemoved(Lsn),
+    #[error("no write-ahead log is attached to the buffer pool")]
+    NoWal,
+}
+
+// プライマリの側。listener につないできたスタンバイそれぞれに、別のスレッドで dir のログを送る。
+// flushed は Wal::flushed_watch で、ここまでのレコードだけを送る
+pub fn serve(
+    listener: TcpListener,
+    dir: PathBuf,
+    flushed: Arc<AtomicU64>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let (dir, flushed) = (dir.clone(), flushed.clone());
+            // スタンバイが切れたらそのスレッドは終わる
+            thread::spawn(move || send_wal(stream, &dir, &flushed));
+        }
+    })
+}
+
+// 1 つのスタンバイにログを送り続ける。スタンバイが切れるとエラーで返る
+pub fn send_wal(mut stream: TcpStream, dir: &Path, flushed: &AtomicU64) -> Result<(), Error> {
+    let mut hello = [0; REPLICATION_MAGIC.len() + 8];
+    stream.read_exact(&mut hello)?;
+    if &hello[..REPLICATION_MAGIC.len()] != REPLICATION_MAGIC {
+        return Err(Error::Protocol("bad magic"));
+    }
+    let mut next = Lsn(u64::from_be_bytes(
+        hello[REPLICATION_MAGIC.len()..].try_into().unwrap(),
+    ));
+    if !wal::retains(dir, next)? {
+        write_status(&mut stream, STATUS_REMOVED, next)?;
+        return Err(Error::WalRemoved(next));
+    }
+    write_status(&mut stream, STATUS_OK, Lsn(flushed.load(Ordering::Acquire)))?;
+
+    let mut sent = Instant::now();
+    loop {
+        let limit = Lsn(flushed.load(Ordering::Acquire));
+        let mut frames = vec![];
+        if next < limit {
+            let mut reader = WalReader::new(dir, next);
+            while frames.len() < SEND_BATCH {
+                let Some((lsn, record)) = reader.next_record()? else {
+                    break;
+                };
+                if lsn >= limit {
+                    break;
+                }
+                put_frame(&mut frames, lsn, &record);
+                next = reader.lsn();
+            }
+            // 読めるはずのレコードが読めないのは、送る前にチェックポイントがセグメントを消したとき
+            if frames.is_empty() && !wal::retains(dir, next)? {
+                return Err(Error::WalRemoved(next));
+            }
+        }
+        if !frames.is_empty() {
+            stream.write_all(&frames)?;
+            sent = Instant::now();
+        } else if sent.elapsed() >= KEEPALIVE_INTERVAL {
+            stream.write_all(&[0; FRAME_HEADER_SIZE])?;
+            sent = Instant::now();
+        } else {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn write_status(stream: &mut TcpStream, status: u8, lsn: Lsn) -> io::Result<()> {
+    let mut bytes = vec![status];
+    bytes.extend_from_slice(&lsn.0.to_be_bytes());
+    stream.write_all(&bytes)
+}
+
+fn put_frame(frames: &mut Vec<u8>, lsn: Lsn, record: &LogRecord) {
+    let mut body = vec![];
+    record.encode(&mut body);
+    frames.extend_from_slice(&lsn.0.to_be_bytes());
+    frames.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frames.extend_from_slice(&body);
+}
+
+// スタンバイの側で、プライマリから送られてくるレコードを受け取る
+pub struct Receiver {
+    stream: TcpStream,
+}
+
+impl Receiver {
+    // プライマリにつないで start から送るよう頼み、プライマリの fsync 済みの位置を返す
+    pub fn connect(addr: impl ToSocketAddrs, start: Lsn) -> Result<(Receiver, Lsn), Error> {
+        let mut stream = TcpStream::connect(addr)?;
+        let mut hello = REPLICATION_MAGIC.to_vec();
+        hello.extend_from_slice(&start.0.to_be_bytes());
+        stream.write_all(&hello)?;
+        let mut status = [0; 9];
+        stream.read_exact(&mut status)?;
+        let lsn = Lsn(u64::from_be_bytes(status[1..].try_into().unwrap()));
+        match status[0] {
+            STATUS_OK => Ok((Receiver { stream }, lsn)),
+            STATUS_REMOVED => Err(Error::WalRemoved(lsn)),
+            _ => Err(Error::Protocol("unknown status")),
+        }
+    }
+
+    // 次のフレームを待って受け取る。送るものがないプライマリからの KEEPALIVE なら None
+    pub fn receive(&mut self) -> Result<Option<(Lsn, LogRecord)>, Error> {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        self.stream.read_exact(&mut header)?;
+        let lsn = Lsn(u64::from_be_bytes(header[..8].try_into().unwrap()));
+        let len = u32::from_be_bytes(header[8..].try_into().unwrap()) as usize;
+        if lsn.valid().is_none() {
+            return Ok(None);
+        }
+        let mut body = vec![0; len];
+        self.stream.read_exact(&mut body)?;
+        let record = LogRecord::decode(&body).ok_or(Error::Protocol("broken log record"))?;
+        Ok(Some((lsn, record)))
+    }
+}
+
+// スタンバイの側。受け取ったレコードを自分のログに書き、ページに当てる
+pub struct Standby {
+    // ここより前のレコードは当ててある
+    applied: Lsn,
+    // プライマリのトランザクションのうち、どれのコミットを受け取ったか
+    view: StandbyView,
+}
+
+impl Standby {
+    // ベースバックアップから戻したバッファプールを、取り消しをせずに当て直してスタンバイにする
+    pub fn new(bufmgr: &mut BufferPoolManager) -> Result<Self, Error> {
+        recovery::replay(bufmgr)?;
+        let wal = bufmgr.wal_mut().ok_or(Error::NoWal)?;
+        let mut standby = Self {
+            applied: wal.next_lsn(),
+            view: StandbyView::new(0, &[]),
+        };
+        // 当て直したところのトランザクションを、チェックポイントから読む
+        let start = wal.checkpoint_lsn()?.unwrap_or(wal.first_lsn());
+        let mut reader = wal.reader(start)?;
+        while let Some((_, record)) = reader.next_record()? {
+            standby.observe(&record);
+        }
+        Ok(standby)
+    }
+
+    pub fn applied_lsn(&self) -> Lsn {
+        self.applied
+    }
+
+    pub fn view(&self) -> &StandbyView {
+        &self.view
+    }
+
+    // lsn のレコードを自分のログに書いて当てる。トランザクションの終わりが変われば true
+    pub fn apply(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        lsn: Lsn,
+        record: &LogRecord,
+    ) -> Result<bool, Error> {
+        let wal = bufmgr.wal_mut().ok_or(Error::NoWal)?;
+        wal.append_at(lsn, record)?;
+        self.applied = wal.next_lsn();
+        if let LogRecord::Checkpoint { .. } = record {
+            // 再開点。ページをすべて書き出したので、次に落ちたときはここから当て直せばよい
+            wal.flush(lsn)?;
+            bufmgr.flush()?;
+            bufmgr
+                .wal_mut()
+                .ok_or(Error::NoWal)?
+                .set_checkpoint_lsn(lsn)?;
+        } else {
+            recovery::apply(bufmgr, lsn, record)?;
+        }
+        Ok(self.observe(record))
+    }
+
+    fn observe(&mut self, record: &LogRecord) -> bool {
+        match record {
+            LogRecord::Checkpoint {
+                active, next_txn, ..
+            } => {
+                let running = active.iter().map(|txn| txn.txn).collect::<Vec<_>>();
+                self.view = StandbyView::new(*next_txn, &running);
+            }
+            LogRecord::Commit { txn, .. } => self.view.commit(*txn),
+            _ => return false,
+        }
+        true
+    }
+
+    // lsn のレコードを当て終わるまで受け取る
+    pub fn catch_up(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        receiver: &mut Receiver,
+        lsn: Lsn,
+    ) -> Result<(), Error> {
+        while self.applied <= lsn {
+            if let Some((lsn, record)) = receiver.receive()? {
+                self.apply(bufmgr, lsn, &record)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 終わっていないトランザクションを取り消して、プライマリとして使えるようにする
+    pub fn promote(self, bufmgr: &mut BufferPoolManager) -> Result<RecoveryStats, Error> {
+        Ok(recovery::recover(bufmgr)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::buffer::BufferPool;
+    use crate::connection::{Connection, Database};
+    use crate::disk::{DiskManager, PageId};
+    use crate::recovery::{base_backup, restore_backup};
+    use crate::settings::Settings;
+    use crate::testutil::temp_path;
+    use crate::types::Value;
+    use crate::wal::Wal;
+
+    fn open(heap: &Path, wal: &Path) -> BufferPoolManager {
+        let disk = DiskManager::open(heap).unwrap();
+        let mut bufmgr = BufferPoolManager::new(disk, BufferPool::new(4));
+        bufmgr.set_wal(Wal::open(wal).unwrap());
+        bufmgr
+    }
+
+    // ページを書き換え、像とコミットをログに書く
+    fn commit(bufmgr: &mut BufferPoolManager, page: &Buffer, txn: u64, offset: usize) -> Lsn {
+        page.page.borrow_mut()[offset..offset + 3].fill(b'a' + txn as u8 - 1);
+        page.is_dirty.set(true);
+        bufmgr.log_images().unwrap();
+        bufmgr
+            .wal_mut()
+            .unwrap()
+            .commit(txn, Lsn::INVALID_LSN)
+            .unwrap()
+    }
+
+    fn read(bufmgr: &mut BufferPoolManager, page_id: PageId, offset: usize) -> Vec<u8> {
+        let buffer = bufmgr.fetch_page(page_id).unwrap();
+        let page = buffer.page.borrow();
+        page[offset..offset + 3].to_vec()
+    }
+
+    #[test]
+    fn test_streaming_replication() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let (backup, standby_heap, standby_dir) = (
+            temp_path("backup"),
+            temp_path("standby.db"),
+            temp_path("standby-wal"),
+        );
+        let mut primary = open(&heap, &dir);
+        primary.log_page_images();
+        let page = primary.create_page().unwrap();
+        commit(&mut primary, &page, 1, 0);
+        base_backup(&mut primary, &heap, &backup, &[], 2).unwrap();
+        // アーカイブの代わりにプライマリのログのディレクトリから戻す
+        restore_backup(&backup, &dir, &standby_heap, &standby_dir).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let flushed = primary.wal_mut().unwrap().flushed_watch();
+        serve(listener, dir.clone(), flushed);
+        let mut bufmgr = open(&standby_heap, &standby_dir);
+        let mut standby = Standby::new(&mut bufmgr).unwrap();
+        let (mut receiver, _) = Receiver::connect(addr, standby.applied_lsn()).unwrap();
+        assert_eq!(read(&mut bufmgr, page.page_id, 0), b"aaa");
+
+        // 2 はコミットし、3 は変更を書いたがコミットしていない
+        let lsn = commit(&mut primary, &page, 2, 3);
+        standby.catch_up(&mut bufmgr, &mut receiver, lsn).unwrap();
+        assert_eq!(read(&mut bufmgr, page.page_id, 3), b"bbb");
+        assert_eq!(standby.view(), &{
+            let mut view = StandbyView::new(2, &[]);
+            view.commit(2);
+            view
+        });
+
+        let wal = primary.wal_mut().unwrap();
+        let lsn = wal
+            .append(&LogRecord::Changes {
+                txn: Some(3),
+                prev: Lsn::INVALID_LSN,
+                changes: vec![],
+            })
+            .unwrap();
+        wal.flush(lsn).unwrap();
+        standby.catch_up(&mut bufmgr, &mut receiver, lsn).unwrap();
+
+        // 昇格すると 3 は終わらせる
+        let page_id = page.page_id;
+        drop(page);
+        drop(receiver);
+        let stats = standby.promote(&mut bufmgr).unwrap();
+        assert_eq!(stats.aborted, vec![3]);
+        assert_eq!(stats.next_txn, 4);
+        assert_eq!(read(&mut bufmgr, page_id, 0), b"aaa");
+        assert_eq!(read(&mut bufmgr, page_id, 3), b"bbb");
+    }
+
+    #[test]
+    fn test_hot_standby() {
+        let (path, backup) = (temp_path("primary.db"), temp_path("standby"));
+        let settings = Settings {
+            buffer_pool_size: 8,
+            ..Settings::default()
+        };
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        let insert = |conn: &mut Connection, i: i64| {
+            conn.execute(
+                "INSERT INTO t VALUES ($1, $2)",
+                &[Value::Integer(i), Value::Text("x".repeat(500))],
+            )
+            .unwrap();
+        };
+        let primary = Database::open_with(&path, settings.clone()).unwrap();
+        let mut conn = primary.connect();
+        conn.execute("CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT)", &[])
+            .unwrap();
+        (0..10).for_each(|i| insert(&mut conn, i));
+        let mut long = primary.connect();
+        long.begin().unwrap();
+        insert(&mut long, 100);
+        conn.backup(&backup).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        primary.serve_replication(listener).unwrap();
+        let standby = Database::open_standby(&backup, settings).unwrap();
+        let start = standby.standby_lsn().unwrap();
+        let (mut receiver, _) = Receiver::connect(addr, start).unwrap();
+        let mut reader = standby.connect();
+        assert_eq!(count(&mut reader, "SELECT count(*) FROM t"), 10);
+
+        // 終わっていないトランザクションの版が載ったページを受け取っても、コミットしたものだけが見える
+        (10..20).for_each(|i| insert(&mut conn, i));
+        conn.execute_batch("CREATE TABLE u (a INTEGER); INSERT INTO u VALUES (1)")
+            .unwrap();
+        standby
+            .catch_up(&mut receiver, primary.flushed_lsn().unwrap())
+            .unwrap();
+        assert_eq!(count(&mut reader, "SELECT count(*) FROM t"), 20);
+        assert_eq!(
+            count(&mut reader, "SELECT count(*) FROM t WHERE a = 100"),
+            0
+        );
+        assert_eq!(count(&mut reader, "SELECT a FROM u"), 1);
+        long.commit().unwrap();
+        standby
+            .catch_up(&mut receiver, primary.flushed_lsn().unwrap())
+            .unwrap();
+        assert_eq!(
+            count(&mut reader, "SELECT count(*) FROM t WHERE a = 100"),
+            1
+        );
+
+        // 読むだけの文しか実行できない
+        for sql in ["INSERT INTO u VALUES (2)", "CREATE TABLE v (a INTEGER)"] {
+            let err = reader.execute(sql, &[]).unwrap_err();
+            assert_eq!(err.sqlstate(), "25006", "{}", sql);
+        }
+
+        // 昇格すると、コミットを受け取っていない変更は取り消して書けるようになる
+        let mut open = primary.connect();
+        open.begin().unwrap();
+        open.execute("DELETE FROM t WHERE a < 5", &[]).unwrap();
+        insert(&mut conn, 20);
+        standby
+            .catch_up(&mut receiver, primary.flushed_lsn().unwrap())
+            .unwrap();
+        assert_eq!(count(&mut reader, "SELECT count(*) FROM t"), 22);
+        drop(receiver);
+        standby.promote().unwrap();
+        assert!(standby.standby_lsn().is_none());
+        assert_eq!(count(&mut reader, "SELECT count(*) FROM t WHERE a < 5"), 5);
+        reader.execute("INSERT INTO u VALUES (2)", &[]).unwrap();
+        assert_eq!(count(&mut reader, "SELECT count(*) FROM u"), 2);
+    }
+
+    #[test]
+    fn test_standby_behind_removed_wal() {
+        let dir = temp_path("wal");
+        let mut wal = Wal::open(&dir).unwrap();
+        let commit = |txn| LogRecord::Commit {
+            txn,
+            prev: Lsn::INVALID_LSN,
+            time: 0,
+        };
+        let old = wal.append(&commit(1)).unwrap();
+        wal.switch_segment().unwrap();
+        let lsn = wal.append(&commit(2)).unwrap();
+        wal.flush(lsn).unwrap();
+        wal.truncate(lsn).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener, dir.clone(), wal.flushed_watch());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut hello = REPLICATION_MAGIC.to_vec();
+        hello.extend_from_slice(&wal.first_lsn().0.to_be_bytes());
+        stream.write_all(&hello).unwrap();
+        let mut status = [0; 9];
+        stream.read_exact(&mut status).unwrap();
+        assert_eq!(status[0], STATUS_OK);
+
+        // 消したセグメントからは送れない
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut hello = REPLICATION_MAGIC.to_vec();
+        hello.extend_from_slice(&old.0.to_be_bytes());
+        stream.write_all(&hello).unwrap();
+        stream.read_exact(&mut status).unwrap();
+        assert_eq!(status[0], STATUS_REMOVED);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use crate::executor::Row;
+
+// ためておく結果の数の上限。超えたら最も長く使っていないものを捨てる
+pub const MAX_ENTRIES: usize = 256;
+// これより多くの行を返した問い合わせの結果はためない
+pub const MAX_ROWS: usize = 10_000;
+
+// 読むだけの問い合わせの結果をためておき、同じ問い合わせを実行せずに返す
+//
+// 鍵は解析した文と引数とロールで、空白や大文字と小文字の違う同じ文は同じ鍵になる。
+// 結果には読んだテーブルの版を添え、どれかが変わっていれば使わない。版はテーブルに行を入れたり消したりした回数と、
+// テーブルを書き換えてコミットしたトランザクションの数で、後者が変われば新しいスナップショットから見える行も変わる。
+// テーブルを作り直す DDL は invalidate でそのテーブルを読んだ結果を捨てる
+#[derive(Debug, Default)]
+pub struct ResultCache {
+    entries: HashMap<String, Entry>,
+    // 使った順を決める時計
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableVersion {
+    pub table: String,
+    pub changes: u64,
+    pub commits: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    versions: Vec<TableVersion>,
+    columns: Vec<String>,
+    rows: Vec<Row>,
+    used: u64,
+}
+
+impl ResultCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // key の結果。読んだテーブルの版が versions と違えば捨てて None
+    pub fn get(&mut self, key: &str, versions: &[TableVersion]) -> Option<(Vec<String>, Vec<Row>)> {
+        self.clock += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.versions == versions => {
+                entry.used = self.clock;
+                self.hits += 1;
+                Some((entry.columns.clone(), entry.rows.clone()))
+            }
+            Some(_) => {
+                self.entries.remove(key);
+                self.misses += 1;
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        key: String,
+        versions: Vec<TableVersion>,
+        columns: &[String],
+        rows: &[Row],
+    ) {
+        if rows.len() > MAX_ROWS {
+            return;
+        }
+        if self.entries.len() >= MAX_ENTRIES && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            Entry {
+                versions,
+                columns: columns.to_vec(),
+                rows: rows.to_vec(),
+                used: self.clock,
+            },
+        );
+    }
+
+    // table を読んだ結果を捨てる
+    pub fn invalidate(&mut self, table: &str) {
+        self.entries
+            .retain(|_, entry| entry.versions.iter().all(|v| v.table != table));
+    }
+
+    // ためた結果をすべて捨てる
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // ためた結果を返した回数と、返せなかった回数
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Value;
+
+    #[test]
+    fn test_result_cache() {
+        let mut cache = ResultCache::new();
+        let version = |table: &str, changes| TableVersion {
+            table: table.to_string(),
+            changes,
+            commits: 0,
+        };
+        let columns = vec!["n".to_string()];
+        for i in 0..MAX_ENTRIES {
+            let rows = vec![vec![Value::Integer(i as i64)]];
+            cache.insert(i.to_string(), vec![version("t", 0)], &columns, &rows);
+        }
+        assert!(cache.get("0", &[version("t", 0)]).is_some());
+        // 一杯なら最も長く使っていない "1" を捨てる
+        cache.insert("u".to_string(), vec![version("u", 0)], &columns, &[]);
+        assert_eq!(cache.len(), MAX_ENTRIES);
+        assert!(cache.get("1", &[version("t", 0)]).is_none());
+        assert!(cache.get("2", &[version("t", 1)]).is_none());
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+        cache.invalidate("t");
+        assert_eq!(cache.len(), 1);
+        let too_many = vec![vec![Value::Null]; MAX_ROWS + 1];
+        cache.insert("big".to_string(), vec![], &columns, &too_many);
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,492 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::buffer::{self, Buffer, BufferPoolManager};
+use crate::disk::{PageId, PAGE_SIZE};
+use crate::geometry::{Point, Rect};
+use crate::heap::RecordId;
+
+// 矩形で行を引く R-tree
+//
+// メタページには根のページ ID だけを置く。ノードのページは
+// | level (2) | count (2) | エントリ |
+// で、エントリは矩形の 4 つの f64 と、葉なら行の RecordId、枝なら子のページ ID と 0 の 42 バイト。level は葉が 0。
+// 入れるときは矩形の面積の増えがいちばん小さい子へ下り、あふれたノードは quadratic split で 2 つに分ける。
+// 消すときは葉のエントリを外すだけで、親の矩形は縮めずノードの併合もしない。矩形が大きめに残るだけで、引ける行は変わらない。
+// 走査はノードと葉のエントリを優先度つきの待ち行列に入れて進める。近い順の走査では距離の近いものから、
+// そうでなければ後に入れたものから取り出すので、深さ優先にたどる
+const ENTRY_SIZE: usize = 42;
+const HEADER_SIZE: usize = 4;
+const MAX_ENTRIES: usize = (PAGE_SIZE - HEADER_SIZE) / ENTRY_SIZE;
+// 分割したノードに少なくとも残すエントリの数
+const MIN_ENTRIES: usize = MAX_ENTRIES * 2 / 5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Buffer(#[from] buffer::Error),
+}
+
+// 引き方。矩形はインデックスの値と比べる相手で、点は幅のない矩形にしておく
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Search {
+    // 値 && 矩形
+    Overlaps(Rect),
+    // 値 @> 矩形
+    Contains(Rect),
+    // 値 <@ 矩形
+    ContainedBy(Rect),
+    // すべての値を 値 <-> 矩形 の近い順に
+    Nearest(Rect),
+}
+
+impl Search {
+    // 子の矩形の中に合う値がありうるか
+    fn descends(&self, rect: &Rect) -> bool {
+        match self {
+            Search::Overlaps(q) | Search::ContainedBy(q) => rect.overlaps(q),
+            Search::Contains(q) => rect.contains(q),
+            Search::Nearest(_) => true,
+        }
+    }
+
+    fn matches(&self, rect: &Rect) -> bool {
+        match self {
+            Search::Overlaps(q) => rect.overlaps(q),
+            Search::Contains(q) => rect.contains(q),
+            Search::ContainedBy(q) => q.contains(rect),
+            Search::Nearest(_) => true,
+        }
+    }
+
+    fn distance(&self, rect: &Rect) -> f64 {
+        match self {
+            Search::Nearest(q) => rect.distance(q),
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Entry {
+    rect: Rect,
+    // 葉なら行のページ、枝なら子のページ
+    page_id: PageId,
+    slot: u16,
+}
+
+impl Entry {
+    fn record_id(&self) -> RecordId {
+        RecordId {
+            page_id: self.page_id,
+            slot: self.slot,
+        }
+    }
+}
+
+struct Node {
+    level: u16,
+    entries: Vec<Entry>,
+}
+
+fn read_f64(page: &[u8], offset: usize) -> f64 {
+    f64::from_be_bytes(page[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_node(buffer: &Buffer) -> Node {
+    let page = buffer.page.borrow();
+    let level = u16::from_be_bytes([page[0], page[1]]);
+    let count = u16::from_be_bytes([page[2], page[3]]) as usize;
+    let entries = (0..count)
+        .map(|i| {
+            let offset = HEADER_SIZE + i * ENTRY_SIZE;
+            let c = |k: usize| read_f64(&page[..], offset + k * 8);
+            Entry {
+                rect: Rect {
+                    low: Point::new(c(0), c(1)),
+                    high: Point::new(c(2), c(3)),
+                },
+                page_id: PageId(u64::from_be_bytes(
+                    page[offset + 32..offset + 40].try_into().unwrap(),
+                )),
+                slot: u16::from_be_bytes([page[offset + 40], page[offset + 41]]),
+            }
+        })
+        .collect();
+    Node { level, entries }
+}
+
+fn write_node(buffer: &Buffer, node: &Node) {
+    let mut page = buffer.page.borrow_mut();
+    page[..2].copy_from_slice(&node.level.to_be_bytes());
+    page[2..4].copy_from_slice(&(node.entries.len() as u16).to_be_bytes());
+    for (i, entry) in node.entries.iter().enumerate() {
+        let offset = HEADER_SIZE + i * ENTRY_SIZE;
+        let r = &entry.rect;
+        for (k, c) in [r.low.x, r.low.y, r.high.x, r.high.y].iter().enumerate() {
+            page[offset + k * 8..offset + k * 8 + 8].copy_from_slice(&c.to_be_bytes());
+        }
+        page[offset + 32..offset + 40].copy_from_slice(&entry.page_id.to_u64().to_be_bytes());
+        page[offset + 40..offset + 42].copy_from_slice(&entry.slot.to_be_bytes());
+    }
+    buffer.is_dirty.set(true);
+}
+
+fn bounds(entries: &[Entry]) -> Rect {
+    entries
+        .iter()
+        .skip(1)
+        .fold(entries[0].rect, |r, e| r.union(&e.rect))
+}
+
+// r に rect を足したときの面積の増え
+fn enlargement(r: &Rect, rect: &Rect) -> f64 {
+    r.union(rect).area() - r.area()
+}
+
+// 合わせたときに無駄な面積がいちばん大きい 2 つを種にし、残りは増えの差が大きいものから増えの小さいほうへ入れる
+fn split(mut entries: Vec<Entry>) -> (Vec<Entry>, Vec<Entry>) {
+    let mut seeds = (0, 1);
+    let mut worst = f64::NEG_INFINITY;
+    for i in 0..entries.len() {
+        for j in i + 1..entries.len() {
+            let (a, b) = (&entries[i].rect, &entries[j].rect);
+            let waste = a.union(b).area() - a.area() - b.area();
+            if waste > worst {
+                worst = waste;
+                seeds = (i, j);
+            }
+        }
+    }
+    let second = entries.swap_remove(seeds.1);
+    let first = entries.swap_remove(seeds.0);
+    let (mut left, mut right) = (vec![first], vec![second]);
+    let (mut left_rect, mut right_rect) = (first.rect, second.rect);
+    while !entries.is_empty() {
+        // 片方が少なすぎるなら残りをすべて入れる
+        if left.len() + entries.len() <= MIN_ENTRIES {
+            left.append(&mut entries);
+            break;
+        }
+        if right.len() + entries.len() <= MIN_ENTRIES {
+            right.append(&mut entries);
+            break;
+        }
+        let (i, _) = entries
+            .iter()
+            .map(|e| (enlargement(&left_rect, &e.rect) - enlargement(&right_rect, &e.rect)).abs())
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        let entry = entries.swap_remove(i);
+        let to_left = enlargement(&left_rect, &entry.rect)
+            .total_cmp(&enlargement(&right_rect, &entry.rect))
+            .then_with(|| left_rect.area().total_cmp(&right_rect.area()))
+            .then_with(|| left.len().cmp(&right.len()))
+            .is_le();
+        if to_left {
+            left_rect = left_rect.union(&entry.rect);
+            left.push(entry);
+        } else {
+            right_rect = right_rect.union(&entry.rect);
+            right.push(entry);
+        }
+    }
+    (left, right)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RTree {
+    pub meta_page_id: PageId,
+}
+
+impl RTree {
+    pub fn create(bufmgr: &mut BufferPoolManager) -> Result<Self, Error> {
+        let meta = bufmgr.create_page()?;
+        let root = bufmgr.create_page()?;
+        write_node(
+            &root,
+            &Node {
+                level: 0,
+                entries: vec![],
+            },
+        );
+        Self::write_root(&meta, root.page_id);
+        Ok(Self {
+            meta_page_id: meta.page_id,
+        })
+    }
+
+    fn write_root(meta: &Buffer, root: PageId) {
+        meta.page.borrow_mut()[..8].copy_from_slice(&root.to_u64().to_be_bytes());
+        meta.is_dirty.set(true);
+    }
+
+    fn root(&self, bufmgr: &mut BufferPoolManager) -> Result<PageId, Error> {
+        let meta = bufmgr.fetch_page(self.meta_page_id)?;
+        let root = u64::from_be_bytes(meta.page.borrow()[..8].try_into().unwrap());
+        Ok(PageId(root))
+    }
+
+    pub fn insert(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rect: Rect,
+        rid: RecordId,
+    ) -> Result<(), Error> {
+        let root = self.root(bufmgr)?;
+        let entry = Entry {
+            rect,
+            page_id: rid.page_id,
+            slot: rid.slot,
+        };
+        let (rect, Some(right)) = insert_into(bufmgr, root, entry)? else {
+            return Ok(());
+        };
+        // 根が分割されたので 1 段高くする
+        let level = read_node(&*bufmgr.fetch_page(root)?).level + 1;
+        let new_root = bufmgr.create_page()?;
+        let left = Entry {
+            rect,
+            page_id: root,
+            slot: 0,
+        };
+        write_node(
+            &new_root,
+            &Node {
+                level,
+                entries: vec![left, right],
+            },
+        );
+        let meta = bufmgr.fetch_page(self.meta_page_id)?;
+        Self::write_root(&meta, new_root.page_id);
+        Ok(())
+    }
+
+    // rect の値を持つ rid のエントリを外す。なければ false
+    pub fn delete(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rect: Rect,
+        rid: RecordId,
+    ) -> Result<bool, Error> {
+        let mut stack = vec![self.root(bufmgr)?];
+        while let Some(page_id) = stack.pop() {
+            let buffer = bufmgr.fetch_page(page_id)?;
+            let mut node = read_node(&buffer);
+            if node.level > 0 {
+                stack.extend(
+                    node.entries
+                        .iter()
+                        .filter(|e| e.rect.contains(&rect))
+                        .map(|e| e.page_id),
+                );
+                continue;
+            }
+            if let Some(i) = node
+                .entries
+                .iter()
+                .position(|e| e.record_id() == rid && e.rect == rect)
+            {
+                node.entries.remove(i);
+                write_node(&buffer, &node);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn scan(&self, bufmgr: &mut BufferPoolManager, search: Search) -> Result<Scan, Error> {
+        let mut scan = Scan {
+            search,
+            queue: BinaryHeap::new(),
+            seq: 0,
+        };
+        scan.push(0.0, Item::Node(self.root(bufmgr)?));
+        Ok(scan)
+    }
+
+    // すべての葉のエントリ
+    pub fn entries(&self, bufmgr: &mut BufferPoolManager) -> Result<Vec<(Rect, RecordId)>, Error> {
+        let plane = Rect {
+            low: Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY),
+            high: Point::new(f64::INFINITY, f64::INFINITY),
+        };
+        let mut scan = self.scan(bufmgr, Search::Overlaps(plane))?;
+        let mut entries = vec![];
+        while let Some((_, rect, rid)) = scan.next(bufmgr)? {
+            entries.push((rect, rid));
+        }
+        Ok(entries)
+    }
+}
+
+// entry を page_id の下の葉に入れ、入れたあとのノードの矩形と、分割したなら右側のノードのエントリを返す
+fn insert_into(
+    bufmgr: &mut BufferPoolManager,
+    page_id: PageId,
+    entry: Entry,
+) -> Result<(Rect, Option<Entry>), Error> {
+    let buffer = bufmgr.fetch_page(page_id)?;
+    let mut node = read_node(&buffer);
+    if node.level == 0 {
+        node.entries.push(entry);
+    } else {
+        // 増えが同じなら面積の小さいほう
+        let cost = |e: &Entry| (enlargement(&e.rect, &entry.rect), e.rect.area());
+        let i = (0..node.entries.len())
+            .min_by(|&a, &b| {
+                let (a, b) = (cost(&node.entries[a]), cost(&node.entries[b]));
+                a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+            })
+            .unwrap();
+        let (rect, split) = insert_into(bufmgr, node.entries[i].page_id, entry)?;
+        node.entries[i].rect = rect;
+        node.entries.extend(split);
+    }
+    if node.entries.len() <= MAX_ENTRIES {
+        write_node(&buffer, &node);
+        return Ok((bounds(&node.entries), None));
+    }
+    let (left, right) = split(std::mem::take(&mut node.entries));
+    let sibling = bufmgr.create_page()?;
+    let right = Node {
+        level: node.level,
+        entries: right,
+    };
+    write_node(&sibling, &right);
+    node.entries = left;
+    write_node(&buffer, &node);
+    Ok((
+        bounds(&node.entries),
+        Some(Entry {
+            rect: bounds(&right.entries),
+            page_id: sibling.page_id,
+            slot: 0,
+        }),
+    ))
+}
+
+enum Item {
+    Node(PageId),
+    Leaf(Rect, RecordId),
+}
+
+// 距離の近い順、同じなら後に入れた順に取り出す
+struct Queued {
+    distance: f64,
+    seq: u64,
+    item: Item,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Queued) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Queued) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Queued) -> Ordering {
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then(self.seq.cmp(&other.seq))
+    }
+}
+
+pub struct Scan {
+    search: Search,
+    queue: BinaryHeap<Queued>,
+    seq: u64,
+}
+
+impl Scan {
+    fn push(&mut self, distance: f64, item: Item) {
+        self.seq += 1;
+        self.queue.push(Queued {
+            distance,
+            seq: self.seq,
+            item,
+        });
+    }
+
+    // 合う値の (距離, 矩形, RecordId)。距離は近い順の走査のほかは 0
+    pub fn next(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+    ) -> Result<Option<(f64, Rect, RecordId)>, Error> {
+        while let Some(Queued { distance, item, .. }) = self.queue.pop() {
+            let page_id = match item {
+                Item::Leaf(rect, rid) => return Ok(Some((distance, rect, rid))),
+                Item::Node(page_id) => page_id,
+            };
+            let node = read_node(&*bufmgr.fetch_page(page_id)?);
+            for entry in node.entries {
+                let distance = self.search.distance(&entry.rect);
+                if node.level == 0 {
+                    if self.search.matches(&entry.rect) {
+                        self.push(distance, Item::Leaf(entry.rect, entry.record_id()));
+                    }
+                } else if self.search.descends(&entry.rect) {
+                    self.push(distance, Item::Node(entry.page_id));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_bufmgr;
+
+    #[test]
+    fn test_rtree() {
+        let mut bufmgr = temp_bufmgr(16);
+        let rtree = RTree::create(&mut bufmgr).unwrap();
+        let rid = |i: u64| RecordId {
+            page_id: PageId(i),
+            slot: 0,
+        };
+        let point = |i: u64| Rect::point(Point::new((i % 50) as f64, (i / 50) as f64));
+        // 50 x 50 の格子の点。ノードが何度も分割される
+        for i in 0..2500 {
+            rtree.insert(&mut bufmgr, point(i), rid(i)).unwrap();
+        }
+        assert!(rtree.delete(&mut bufmgr, point(7), rid(7)).unwrap());
+        assert!(!rtree.delete(&mut bufmgr, point(7), rid(8)).unwrap());
+        assert_eq!(rtree.entries(&mut bufmgr).unwrap().len(), 2499);
+
+        let mut found = |search| {
+            let mut scan = rtree.scan(&mut bufmgr, search).unwrap();
+            let mut found = vec![];
+            while let Some((_, _, rid)) = scan.next(&mut bufmgr).unwrap() {
+                found.push(rid.page_id.to_u64());
+            }
+            found
+        };
+        let window = Rect::new(Point::new(5.0, 0.0), Point::new(8.0, 1.0));
+        let mut inside = found(Search::ContainedBy(window));
+        inside.sort_unstable();
+        assert_eq!(inside, [5, 6, 8, 55, 56, 57, 58]);
+        assert_eq!(found(Search::Contains(point(60))), [60]);
+
+        let mut scan = rtree.scan(&mut bufmgr, Search::Nearest(point(7))).unwrap();
+        let mut distances = vec![];
+        for _ in 0..5 {
+            let (distance, rect, _) = scan.next(&mut bufmgr).unwrap().unwrap();
+            assert_eq!(distance, rect.distance(&point(7)));
+            distances.push(distance);
+        }
+        assert_eq!(distances, [1.0, 1.0, 1.0, 2.0_f64.sqrt(), 2.0_f64.sqrt()]);
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::buffer::BufferPoolManager;
+use crate::catalog::Catalog;
+use crate::clock::Instant;
+use crate::executor::{CancelToken, Cursors, ExecContext, Profile};
+use crate::planner::{PlanCache, Planner};
+use crate::settings::{self, Scope, Settings};
+use crate::sql::ast::Statement;
+use crate::sql::lexer::{Lexer, TokenKind};
+use crate::transaction::TransactionManager;
+
+// 接続ごとの状態
+//
+// 実行中のトランザクション、名前を付けて準備した文、SET で変える設定を持つ。
+// シェルもサーバーも埋め込みの API も Connection を通して文を実行し、Connection はひとつの Session を持つので、
+// どの入り口から実行しても同じ状態の下で同じように動く。一時テーブルはまだないので、その置き場所もない
+pub struct Session {
+    pub(crate) txns: TransactionManager,
+    pub(crate) cancel: CancelToken,
+    statements: HashMap<String, PreparedStatement>,
+    settings: Settings,
+    // RESET で戻す値。データベースを開いたときの設定
+    defaults: Settings,
+    // SET LOCAL で変えた設定と、トランザクションの前の値
+    local: Vec<(String, Settings)>,
+    // SET で変えさせない設定
+    fixed: Vec<&'static str>,
+    // 立っていれば計画を実行するたびに演算子ごとの値を測り、profile に残す
+    pub(crate) profiling: bool,
+    pub(crate) profile: Option<Profile>,
+    // 文を実行するロール。None なら埋め込みで使う持ち主で、権限を確かめない
+    pub(crate) user: Option<String>,
+    // 立っている間、トランザクションの外で書き換えた行をログに書かない
+    pub(crate) unlogged: bool,
+    // 今のトランザクションで DDL を実行していれば、そのカタログ
+    pub(crate) pending: Option<PendingCatalog>,
+    // SAVEPOINT で作ったセーブポイントと、そのときの写しと changed。写していなければ None。
+    // ROLLBACK TO でセーブポイントより後の DDL を取り消すのに使う
+    pub(crate) savepoint_catalogs: Vec<(String, Option<(Catalog, bool)>)>,
+    // 準備した文の計画。文の文字列ごとにためる
+    pub(crate) plans: PlanCache,
+    // 実行している準備した文の文字列。計画するときに plans を使う
+    pub(crate) plan_key: Option<String>,
+    // 開いているカーソル。トランザクションが終わると閉じる
+    pub(crate) cursors: Cursors,
+    // ロックの列に残して戻った文が、待ち始めた時刻
+    pub(crate) lock_wait_since: Option<Instant>,
+}
+
+// トランザクションの中で DDL を実行してから、トランザクションが終わるまでのカタログ
+//
+// 最初の DDL でコミットしたカタログを写し、この接続が文を実行する間だけ Engine のものと入れ替えて変える。
+// ほかの接続は写す前のカタログを使い続けるので、作ったテーブルやインデックスはコミットするまで見えない。
+// コミットで写しをコミットしたカタログにし、取り消せば捨てる。
+// REPEATABLE READ と SERIALIZABLE では最初の文で写し、ほかの接続がコミットした DDL も見えないようにする。
+// DDL を実行していなければ changed は立たず、終わるときに写しを捨てる
+pub(crate) struct PendingCatalog {
+    // active なら Engine から外したコミットしたカタログ、そうでなければこの接続が変えた写し
+    pub catalog: Catalog,
+    pub active: bool,
+    // 写したときのコミットしたカタログの schema_version
+    pub base: u64,
+    pub changed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedStatement {
+    pub sql: String,
+    // 空の文なら None
+    pub statement: Option<Statement>,
+    // 引数の型の PostgreSQL の OID。決めていないものは 0。$n の n の最大の数だけある
+    pub param_types: Vec<i32>,
+}
+
+impl Session {
+    pub(crate) fn new(mut txns: TransactionManager, defaults: Settings) -> Session {
+        txns.set_lock_timeout(defaults.lock_timeout);
+        Session {
+            txns,
+            cancel: CancelToken::new(),
+            statements: HashMap::new(),
+            settings: defaults.clone(),
+            defaults,
+            local: vec![],
+            fixed: vec![],
+            profiling: false,
+            profile: None,
+            user: None,
+            unlogged: false,
+            pending: None,
+            savepoint_catalogs: vec![],
+            plans: PlanCache::new(),
+            plan_key: None,
+            cursors: Cursors::new(),
+            lock_wait_since: None,
+        }
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.txns.in_transaction()
+    }
+
+    // ロックを待つ時間の上限。None ならいつまでも待つ
+    pub fn lock_timeout(&self) -> Option<Duration> {
+        self.txns.lock_timeout()
+    }
+
+    pub fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        self.settings.lock_timeout = timeout;
+        self.txns.set_lock_timeout(timeout);
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    // SET name = value。value が None なら RESET と同じ。local ならトランザクションの終わりまで
+    pub fn set(
+        &mut self,
+        name: &str,
+        value: Option<&str>,
+        local: bool,
+    ) -> Result<(), settings::Error> {
+        let mut settings = self.settings.clone();
+        match value {
+            Some(value) => settings.set(name, value)?,
+            None if Settings::scope(name)? == Scope::Startup => {
+                return Err(settings::Error::Startup(name.to_string()))
+            }
+            None => settings.copy(name, &self.defaults),
+        }
+        if let Some(&name) = self.fixed.iter().find(|&&fixed| fixed == name) {
+            return Err(settings::Error::Fixed(name.to_string()));
+        }
+        // SET LOCAL をトランザクションの外で使っても何も変えない
+        if local && !self.in_transaction() {
+            return Ok(());
+        }
+        if local && !self.local.iter().any(|(n, _)| n == name) {
+            self.local.push((name.to_string(), self.settings.clone()));
+        }
+        self.settings = settings;
+        self.txns.set_lock_timeout(self.settings.lock_timeout);
+        Ok(())
+    }
+
+    // RESET ALL。変えさせない設定は残す
+    pub fn reset_all(&mut self) {
+        for (name, _, _) in settings::PARAMETERS {
+            if !self.fixed.contains(name) {
+                self.settings.copy(name, &self.defaults);
+            }
+        }
+        self.txns.set_lock_timeout(self.settings.lock_timeout);
+    }
+
+    // サーバーがこの接続で name を変えさせないようにする
+    pub fn fix_setting(&mut self, name: &'static str) {
+        self.fixed.push(name);
+    }
+
+    // 設定を当てた計画器
+    pub(crate) fn planner<'a>(&self, catalog: &'a Catalog) -> Planner<'a> {
+        let mut planner = Planner::new(catalog);
+        planner.parallel_workers = self.settings.parallel_workers;
+        planner.costs.work_mem = self.settings.work_mem;
+        planner.ttl_filter = self.settings.ttl_filter;
+        planner.user = self.user.clone();
+        planner
+    }
+
+    // 文を実行する状態。文の時間の上限はここから数える
+    pub(crate) fn exec_context<'a>(
+        &'a mut self,
+        bufmgr: &'a mut BufferPoolManager,
+        catalog: &'a Catalog,
+    ) -> ExecContext<'a> {
+        self.txns.start_statement();
+        self.cursor_context(bufmgr, catalog)
+    }
+
+    // 開いたカーソルから読む状態。文を始めないので、READ COMMITTED でもスナップショットを取り直さない
+    pub(crate) fn cursor_context<'a>(
+        &'a mut self,
+        bufmgr: &'a mut BufferPoolManager,
+        catalog: &'a Catalog,
+    ) -> ExecContext<'a> {
+        let mut ctx = ExecContext::new(bufmgr, catalog);
+        ctx.txn = self.txns.current_mut();
+        ctx.cancel = self.cancel.clone();
+        ctx.work_mem = self.settings.work_mem;
+        ctx.max_rows = self.settings.max_result_rows;
+        ctx.temp_file_limit = self.settings.temp_file_limit;
+        ctx.max_memory = self.settings.max_query_memory;
+        ctx.max_recursion_depth = self.settings.max_recursion_depth;
+        ctx.unlogged = self.unlogged;
+        if let Some(timeout) = self.settings.statement_timeout {
+            ctx.set_statement_timeout(timeout);
+        }
+        ctx
+    }
+
+    // トランザクションが終わったら、SET LOCAL で変えた設定を戻す
+    pub(crate) fn end_transaction(&mut self) {
+        for (name, before) in self.local.drain(..).rev() {
+            self.settings.copy(&name, &before);
+        }
+        self.txns.set_lock_timeout(self.settings.lock_timeout);
+    }
+
+    pub fn prepared(&self, name: &str) -> Option<&PreparedStatement> {
+        self.statements.get(name)
+    }
+
+    // 同じ名前の文があれば置き換える。引数の数は sql の $n から決める
+    pub(crate) fn prepare(
+        &mut self,
+        name: &str,
+        sql: &str,
+        statement: Option<Statement>,
+        param_types: &[i32],
+    ) {
+        let mut param_types = param_types.to_vec();
+        let params = parameter_count(sql);
+        if param_types.len() < params {
+            param_types.resize(params, 0);
+        }
+        let old = self.statements.insert(
+            name.to_string(),
+            PreparedStatement {
+                sql: sql.to_string(),
+                statement,
+                param_types,
+            },
+        );
+        if let Some(old) = old {
+            self.forget_plan(&old.sql);
+        }
+    }
+
+    // 準備した文を捨てる。なければ false
+    pub fn deallocate(&mut self, name: &str) -> bool {
+        match self.statements.remove(name) {
+            Some(old) => {
+                self.forget_plan(&old.sql);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 準備した文のどれも sql でなければ、その計画を捨てる
+    fn forget_plan(&mut self, sql: &str) {
+        if self.statements.values().all(|s| s.sql != sql) {
+            self.plans.remove(sql);
+        }
+    }
+}
+
+fn parameter_count(sql: &str) -> usize {
+    Lexer::new(sql).tokenize().map_or(0, |tokens| {
+        tokens
+            .iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::Parameter(n) => Some(n),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::{Database, Error, StatementResult};
+    use crate::types::Value;
+
+    #[test]
+    fn test_prepared_statements() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE t (id INTEGER, name TEXT)", &[])
+            .unwrap();
+        conn.prepare("ins", "INSERT INTO t VALUES ($1, $2)", &[20])
+            .unwrap();
+        assert_eq!(conn.prepared("ins").unwrap().param_types, vec![20, 0]);
+        for (id, name) in [(1, "a"), (2, "b")] {
+            let result = conn
+                .execute_prepared("ins", &[Value::from(id), Value::from(name)])
+                .unwrap();
+            assert!(matches!(
+                result,
+                Some(StatementResult::Modified { rows: 1, .. })
+            ));
+        }
+        conn.prepare("empty", "", &[]).unwrap();
+        assert!(conn.execute_prepared("empty", &[]).unwrap().is_none());
+        assert!(matches!(
+            conn.prepare("two", "SELECT 1; SELECT 2", &[]),
+            Err(Error::MultipleStatements)
+        ));
+
+        // 準備した文もトランザクションも接続ごと
+        let mut other = db.connect();
+        assert_eq!(
+            other.execute_prepared("ins", &[]).unwrap_err().sqlstate(),
+            "26000"
+        );
+        other.execute("BEGIN", &[]).unwrap();
+        assert!(other.session().in_transaction() && !conn.session().in_transaction());
+
+        assert!(conn.deallocate("ins"));
+        assert!(!conn.deallocate("ins"));
+        assert!(conn.prepared("ins").is_err());
+    }
+
+    #[test]
+    fn test_prepared_plans() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER, b TEXT); INSERT INTO t VALUES (1, 'x'), (2, 'y')",
+        )
+        .unwrap();
+        conn.prepare("q", "SELECT b FROM t WHERE a = $1", &[])
+            .unwrap();
+        let run = |conn: &mut crate::connection::Connection, a: i64| match conn
+            .execute_prepared("q", &[Value::from(a)])
+            .unwrap()
+        {
+            Some(StatementResult::Rows(rows)) => {
+                rows.map(|row| row.into_values()).collect::<Vec<_>>()
+            }
+            _ => panic!("not rows"),
+        };
+        // $n の値が違っても計画を使い回す
+        assert_eq!(run(&mut conn, 1), [vec![Value::from("x")]]);
+        assert_eq!(run(&mut conn, 1), [vec![Value::from("x")]]);
+        assert_eq!(conn.plan_cache().hits(), 1);
+        assert_eq!(run(&mut conn, 2), [vec![Value::from("y")]]);
+        assert!(run(&mut conn, 3).is_empty());
+        assert_eq!(conn.plan_cache().hits(), 3);
+
+        // DDL でカタログが変われば計画し直す
+        conn.execute("CREATE INDEX t_a ON t (a)", &[]).unwrap();
+        assert_eq!(run(&mut conn, 2), [vec![Value::from("y")]]);
+        assert_eq!(conn.plan_cache().hits(), 3);
+        conn.execute_batch(
+            "DROP TABLE t; CREATE TABLE t (a INTEGER, b TEXT); INSERT INTO t VALUES (2, 'z')",
+        )
+        .unwrap();
+        assert_eq!(run(&mut conn, 2), [vec![Value::from("z")]]);
+        assert!(run(&mut conn, 1).is_empty());
+        assert_eq!(conn.plan_cache().hits(), 4);
+
+        // VALUES の $n も実行するときに入れる
+        conn.prepare("i", "INSERT INTO t VALUES ($1, 'w'), (3, $2)", &[])
+            .unwrap();
+        for i in [10, 20] {
+            let params = [Value::from(i), Value::from("v")];
+            let result = conn.execute_prepared("i", &params).unwrap();
+            assert!(matches!(
+                result,
+                Some(StatementResult::Modified { rows: 2, .. })
+            ));
+        }
+        assert_eq!(conn.plan_cache().hits(), 5);
+        let rows = conn.query("SELECT a, b FROM t ORDER BY a, b", &[]).unwrap();
+        let rows: Vec<_> = rows.map(|row| row.into_values()).collect();
+        assert_eq!(
+            rows,
+            [
+                vec![Value::from(2), Value::from("z")],
+                vec![Value::from(3), Value::from("v")],
+                vec![Value::from(3), Value::from("v")],
+                vec![Value::from(10), Value::from("w")],
+                vec![Value::from(20), Value::from("w")],
+            ]
+        );
+
+        // LIMIT の行数のように計画に値が要るものは、同じ値のときだけ使い回す
+        conn.prepare("l", "SELECT a FROM t ORDER BY a LIMIT $1", &[])
+            .unwrap();
+        for n in [1, 1, 2] {
+            conn.execute_prepared("l", &[Value::from(n)]).unwrap();
+        }
+        assert_eq!(conn.plan_cache().hits(), 6);
+
+        // 計画したときの値で結果が変わるものはためない
+        conn.prepare("r", "SELECT gen_random_uuid()", &[]).unwrap();
+        conn.execute_prepared("r", &[]).unwrap();
+        conn.execute_prepared("r", &[]).unwrap();
+        assert_eq!(conn.plan_cache().hits(), 6);
+        assert_eq!(conn.plan_cache().len(), 3);
+        assert!(conn.deallocate("q"));
+        assert_eq!(conn.plan_cache().len(), 2);
+    }
+}
@@ -0,0 +1,641 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::executor::DEFAULT_WORK_MEM;
+
+// 実行時の設定
+//
+// 設定ごとに変えられる範囲が決まっている。Startup の設定はデータベースを開くときの設定ファイルでだけ決まり、
+// Session の設定は設定ファイルで既定の値を決めたうえで、SET で接続ごとに変えられる。
+// SET LOCAL で変えた値はトランザクションが終わると戻る。SET で変えた値はトランザクションを取り消しても戻らない。
+// 設定ファイルは TOML のうち、name = value の行と # のコメントだけを読む
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("unrecognized configuration parameter \"{0}\"")]
+    Unknown(String),
+    #[error("invalid value for parameter \"{name}\": \"{value}\"")]
+    InvalidValue { name: String, value: String },
+    #[error("parameter \"{0}\" cannot be changed without restarting the server")]
+    Startup(String),
+    // サーバーが接続ごとに決めて、変えさせない設定
+    #[error("parameter \"{0}\" cannot be changed on this connection")]
+    Fixed(String),
+    #[error("config line {line}: {message}")]
+    Config { line: usize, message: String },
+}
+
+impl Error {
+    pub fn sqlstate(&self) -> &'static str {
+        match self {
+            Error::Unknown(_) => "42704",
+            Error::InvalidValue { .. } => "22023",
+            Error::Startup(_) | Error::Fixed(_) => "55P02",
+            Error::Io(_) => "58030",
+            Error::Config { .. } => "F0000",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Startup,
+    Session,
+}
+
+// 設定の名前、範囲、説明
+pub const PARAMETERS: &[(&str, Scope, &str)] = &[
+    (
+        "buffer_pool_size",
+        Scope::Startup,
+        "Number of pages in the buffer pool.",
+    ),
+    (
+        "work_mem",
+        Scope::Session,
+        "Memory one operator may use before spilling to temporary files.",
+    ),
+    (
+        "synchronous_commit",
+        Scope::Session,
+        "Write dirty pages to disk and sync at every commit.",
+    ),
+    (
+        "statement_timeout",
+        Scope::Session,
+        "Maximum time a statement may run. 0 disables it.",
+    ),
+    (
+        "lock_timeout",
+        Scope::Session,
+        "Maximum time to wait for a lock. 0 waits forever.",
+    ),
+    (
+        "parallel_workers",
+        Scope::Session,
+        "Threads used to scan a table in parallel. Below 2 disables it.",
+    ),
+    (
+        "max_result_rows",
+        Scope::Session,
+        "Maximum rows a query may return. 0 disables it.",
+    ),
+    (
+        "temp_file_limit",
+        Scope::Session,
+        "Maximum temporary file bytes a query may write. 0 disables it.",
+    ),
+    (
+        "max_query_memory",
+        Scope::Session,
+        "Maximum memory a query may use. 0 disables it.",
+    ),
+    (
+        "max_recursion_depth",
+        Scope::Session,
+        "Maximum iterations of a WITH RECURSIVE query. 0 disables it.",
+    ),
+    (
+        "ttl_filter",
+        Scope::Session,
+        "Hide rows past their table's TTL that VACUUM has not removed yet.",
+    ),
+    (
+        "result_cache",
+        Scope::Session,
+        "Serve repeated read-only queries from the shared result cache.",
+    ),
+    (
+        "autovacuum",
+        Scope::Startup,
+        "Run VACUUM and ANALYZE automatically in server mode.",
+    ),
+    (
+        "autovacuum_naptime",
+        Scope::Startup,
+        "Time to sleep when no table needs automatic VACUUM or ANALYZE.",
+    ),
+    (
+        "autovacuum_delay",
+        Scope::Startup,
+        "Time to sleep after each table that automatic VACUUM or ANALYZE processes.",
+    ),
+    (
+        "autovacuum_vacuum_threshold",
+        Scope::Startup,
+        "Dead row versions that trigger an automatic VACUUM, added to the scale factor.",
+    ),
+    (
+        "autovacuum_vacuum_scale_factor",
+        Scope::Startup,
+        "Fraction of the table's rows added to the VACUUM threshold.",
+    ),
+    (
+        "autovacuum_analyze_threshold",
+        Scope::Startup,
+        "Inserted or deleted rows that trigger an automatic ANALYZE, added to the scale factor.",
+    ),
+    (
+        "autovacuum_analyze_scale_factor",
+        Scope::Startup,
+        "Fraction of the table's rows added to the ANALYZE threshold.",
+    ),
+    (
+        "version_retention",
+        Scope::Startup,
+        "How long to keep old row versions readable by AS OF queries. 0 disables AS OF.",
+    ),
+    (
+        "checkpoint_wal_size",
+        Scope::Startup,
+        "WAL bytes written between automatic checkpoints. 0 disables them.",
+    ),
+    (
+        "commit_delay",
+        Scope::Startup,
+        "Time the server waits to gather more commits into one WAL flush.",
+    ),
+];
+
+const MIN_BUFFER_POOL_SIZE: usize = 16;
+const MAX_BUFFER_POOL_SIZE: usize = 1 << 20;
+const MIN_WORK_MEM: usize = 64 * 1024;
+// usize が 32 bit の wasm32 では usize::MAX
+const MAX_WORK_MEM: usize = if (usize::MAX as u64) < 1 << 40 {
+    usize::MAX
+} else {
+    (1u64 << 40) as usize
+};
+const MAX_PARALLEL_WORKERS: usize = 64;
+const DEFAULT_MAX_RECURSION_DEPTH: u64 = 100_000;
+const MAX_SCALE_FACTOR: f64 = 100.0;
+const DEFAULT_CHECKPOINT_WAL_SIZE: usize = 16 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub buffer_pool_size: usize,
+    // バイト数
+    pub work_mem: usize,
+    pub synchronous_commit: bool,
+    pub statement_timeout: Option<Duration>,
+    // None ならいつまでも待つ。0 なら待たない
+    pub lock_timeout: Option<Duration>,
+    pub parallel_workers: usize,
+    // 以下の上限は None なら決めない。超えた問い合わせはエラーで止める
+    pub max_result_rows: Option<u64>,
+    // バイト数
+    pub temp_file_limit: Option<usize>,
+    pub max_query_memory: Option<usize>,
+    pub max_recursion_depth: Option<u64>,
+    pub ttl_filter: bool,
+    pub result_cache: bool,
+    // テーブルの消えた版が vacuum_threshold + vacuum_scale_factor * 行数を超えたら VACUUM し、
+    // 最後の ANALYZE から入れたり消したりした行が analyze の同じ式を超えたら ANALYZE する
+    pub autovacuum: bool,
+    pub autovacuum_naptime: Duration,
+    pub autovacuum_delay: Duration,
+    pub autovacuum_vacuum_threshold: u64,
+    pub autovacuum_vacuum_scale_factor: f64,
+    pub autovacuum_analyze_threshold: u64,
+    pub autovacuum_analyze_scale_factor: f64,
+    // None なら AS OF で昔の版を読めない
+    pub version_retention: Option<Duration>,
+    // ファイルのデータベースで、前のチェックポイントからログがこのバイト数だけ進んだら次のチェックポイントを書く。
+    // None なら開くときと閉じるときにしか書かない
+    pub checkpoint_wal_size: Option<usize>,
+    // サーバーはコミットを記してからこれだけほかのコミットを待ち、まとめて 1 回の fsync でディスクに届ける。
+    // 0 なら待たずに、それまでに届いていた依頼の分だけをまとめる
+    pub commit_delay: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            buffer_pool_size: 1024,
+            work_mem: DEFAULT_WORK_MEM,
+            synchronous_commit: false,
+            statement_timeout: None,
+            lock_timeout: None,
+            parallel_workers: 0,
+            max_result_rows: None,
+            temp_file_limit: None,
+            max_query_memory: None,
+            max_recursion_depth: Some(DEFAULT_MAX_RECURSION_DEPTH),
+            ttl_filter: true,
+            result_cache: false,
+            autovacuum: true,
+            autovacuum_naptime: Duration::from_secs(60),
+            autovacuum_delay: Duration::from_millis(20),
+            autovacuum_vacuum_threshold: 50,
+            autovacuum_vacuum_scale_factor: 0.2,
+            autovacuum_analyze_threshold: 50,
+            autovacuum_analyze_scale_factor: 0.1,
+            version_retention: None,
+            checkpoint_wal_size: Some(DEFAULT_CHECKPOINT_WAL_SIZE),
+            commit_delay: Duration::ZERO,
+        }
+    }
+}
+
+impl Settings {
+    // 設定ファイルを読む。書いていない設定は既定の値
+    pub fn load(path: impl AsRef<Path>) -> Result<Settings, Error> {
+        Settings::parse_config(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse_config(text: &str) -> Result<Settings, Error> {
+        let mut settings = Settings::default();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| Error::Config {
+                line: i + 1,
+                message,
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(error("tables are not supported".to_string()));
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(error("expected name = value".to_string()));
+            };
+            let value = value.trim();
+            let value = match value.strip_prefix('"') {
+                Some(rest) => rest
+                    .strip_suffix('"')
+                    .filter(|s| !s.contains('"'))
+                    .ok_or_else(|| error(format!("unterminated string {}", value)))?,
+                None => value,
+            };
+            settings
+                .assign(name.trim(), value)
+                .map_err(|e| error(e.to_string()))?;
+        }
+        Ok(settings)
+    }
+
+    pub fn scope(name: &str) -> Result<Scope, Error> {
+        PARAMETERS
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, scope, _)| *scope)
+            .ok_or_else(|| Error::Unknown(name.to_string()))
+    }
+
+    // SHOW で見せる形
+    pub fn get(&self, name: &str) -> Result<String, Error> {
+        Ok(match name {
+            "buffer_pool_size" => self.buffer_pool_size.to_string(),
+            "work_mem" => format_memory(self.work_mem),
+            "synchronous_commit" => if self.synchronous_commit { "on" } else { "off" }.to_string(),
+            "statement_timeout" => format_timeout(self.statement_timeout),
+            "lock_timeout" => format_timeout(self.lock_timeout),
+            "parallel_workers" => self.parallel_workers.to_string(),
+            "max_result_rows" => self.max_result_rows.unwrap_or(0).to_string(),
+            "temp_file_limit" => format_limit(self.temp_file_limit),
+            "max_query_memory" => format_limit(self.max_query_memory),
+            "max_recursion_depth" => self.max_recursion_depth.unwrap_or(0).to_string(),
+            "ttl_filter" => if self.ttl_filter { "on" } else { "off" }.to_string(),
+            "result_cache" => if self.result_cache { "on" } else { "off" }.to_string(),
+            "autovacuum" => if self.autovacuum { "on" } else { "off" }.to_string(),
+            "autovacuum_naptime" => format_timeout(Some(self.autovacuum_naptime)),
+            "autovacuum_delay" => format_delay(self.autovacuum_delay),
+            "autovacuum_vacuum_threshold" => self.autovacuum_vacuum_threshold.to_string(),
+            "autovacuum_vacuum_scale_factor" => self.autovacuum_vacuum_scale_factor.to_string(),
+            "autovacuum_analyze_threshold" => self.autovacuum_analyze_threshold.to_string(),
+            "autovacuum_analyze_scale_factor" => self.autovacuum_analyze_scale_factor.to_string(),
+            "version_retention" => format_timeout(self.version_retention),
+            "checkpoint_wal_size" => format_limit(self.checkpoint_wal_size),
+            "commit_delay" => format_delay(self.commit_delay),
+            _ => return Err(Error::Unknown(name.to_string())),
+        })
+    }
+
+    // SET で変える。Startup の設定は変えられない
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        if Settings::scope(name)? == Scope::Startup {
+            return Err(Error::Startup(name.to_string()));
+        }
+        self.assign(name, value)
+    }
+
+    // name の値を from の値にする
+    pub(crate) fn copy(&mut self, name: &str, from: &Settings) {
+        match name {
+            "buffer_pool_size" => self.buffer_pool_size = from.buffer_pool_size,
+            "work_mem" => self.work_mem = from.work_mem,
+            "synchronous_commit" => self.synchronous_commit = from.synchronous_commit,
+            "statement_timeout" => self.statement_timeout = from.statement_timeout,
+            "lock_timeout" => self.lock_timeout = from.lock_timeout,
+            "parallel_workers" => self.parallel_workers = from.parallel_workers,
+            "max_result_rows" => self.max_result_rows = from.max_result_rows,
+            "temp_file_limit" => self.temp_file_limit = from.temp_file_limit,
+            "max_query_memory" => self.max_query_memory = from.max_query_memory,
+            "max_recursion_depth" => self.max_recursion_depth = from.max_recursion_depth,
+            "ttl_filter" => self.ttl_filter = from.ttl_filter,
+            "result_cache" => self.result_cache = from.result_cache,
+            "autovacuum" => self.autovacuum = from.autovacuum,
+            "autovacuum_naptime" => self.autovacuum_naptime = from.autovacuum_naptime,
+            "autovacuum_delay" => self.autovacuum_delay = from.autovacuum_delay,
+            "autovacuum_vacuum_threshold" => {
+                self.autovacuum_vacuum_threshold = from.autovacuum_vacuum_threshold
+            }
+            "autovacuum_vacuum_scale_factor" => {
+                self.autovacuum_vacuum_scale_factor = from.autovacuum_vacuum_scale_factor
+            }
+            "autovacuum_analyze_threshold" => {
+                self.autovacuum_analyze_threshold = from.autovacuum_analyze_threshold
+            }
+            "autovacuum_analyze_scale_factor" => {
+                self.autovacuum_analyze_scale_factor = from.autovacuum_analyze_scale_factor
+            }
+            "version_retention" => self.version_retention = from.version_retention,
+            "checkpoint_wal_size" => self.checkpoint_wal_size = from.checkpoint_wal_size,
+            "commit_delay" => self.commit_delay = from.commit_delay,
+            _ => {}
+        }
+    }
+
+    fn assign(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let invalid = || Error::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        match name {
+            "buffer_pool_size" => {
+                self.buffer_pool_size = value
+                    .parse()
+                    .ok()
+                    .filter(|n| (MIN_BUFFER_POOL_SIZE..=MAX_BUFFER_POOL_SIZE).contains(n))
+                    .ok_or_else(invalid)?
+            }
+            "work_mem" => {
+                self.work_mem = parse_memory(value)
+                    .filter(|n| (MIN_WORK_MEM..=MAX_WORK_MEM).contains(n))
+                    .ok_or_else(invalid)?
+            }
+            "synchronous_commit" => {
+                self.synchronous_commit = parse_bool(value).ok_or_else(invalid)?
+            }
+            "statement_timeout" => {
+                self.statement_timeout = parse_timeout(value).ok_or_else(invalid)?
+            }
+            "lock_timeout" => self.lock_timeout = parse_timeout(value).ok_or_else(invalid)?,
+            "parallel_workers" => {
+                self.parallel_workers = value
+                    .parse()
+                    .ok()
+                    .filter(|n| *n <= MAX_PARALLEL_WORKERS)
+                    .ok_or_else(invalid)?
+            }
+            "max_result_rows" => {
+                let n: u64 = value.parse().map_err(|_| invalid())?;
+                self.max_result_rows = (n != 0).then_some(n)
+            }
+            "temp_file_limit" => {
+                let n = parse_memory(value).ok_or_else(invalid)?;
+                self.temp_file_limit = (n != 0).then_some(n)
+            }
+            "max_query_memory" => {
+                let n = parse_memory(value).ok_or_else(invalid)?;
+                self.max_query_memory = (n != 0).then_some(n)
+            }
+            "max_recursion_depth" => {
+                let n: u64 = value.parse().map_err(|_| invalid())?;
+                self.max_recursion_depth = (n != 0).then_some(n)
+            }
+            "ttl_filter" => self.ttl_filter = parse_bool(value).ok_or_else(invalid)?,
+            "result_cache" => self.result_cache = parse_bool(value).ok_or_else(invalid)?,
+            "autovacuum" => self.autovacuum = parse_bool(value).ok_or_else(invalid)?,
+            // 0 では休まずに調べ続けてしまう
+            "autovacuum_naptime" => {
+                self.autovacuum_naptime = parse_timeout(value)
+                    .flatten()
+                    .filter(|d| !d.is_zero())
+                    .ok_or_else(invalid)?
+            }
+            "autovacuum_delay" => {
+                self.autovacuum_delay = parse_timeout(value)
+                    .ok_or_else(invalid)?
+                    .unwrap_or_default()
+            }
+            "autovacuum_vacuum_threshold" => {
+                self.autovacuum_vacuum_threshold = value.parse().map_err(|_| invalid())?
+            }
+            "autovacuum_vacuum_scale_factor" => {
+                self.autovacuum_vacuum_scale_factor =
+                    parse_scale_factor(value).ok_or_else(invalid)?
+            }
+            "autovacuum_analyze_threshold" => {
+                self.autovacuum_analyze_threshold = value.parse().map_err(|_| invalid())?
+            }
+            "autovacuum_analyze_scale_factor" => {
+                self.autovacuum_analyze_scale_factor =
+                    parse_scale_factor(value).ok_or_else(invalid)?
+            }
+            "version_retention" => {
+                self.version_retention = parse_timeout(value)
+                    .filter(|t| !t.is_some_and(|d| d.is_zero()))
+                    .ok_or_else(invalid)?
+            }
+            "checkpoint_wal_size" => {
+                let n = parse_memory(value).ok_or_else(invalid)?;
+                self.checkpoint_wal_size = (n != 0).then_some(n)
+            }
+            "commit_delay" => {
+                self.commit_delay = parse_timeout(value)
+                    .ok_or_else(invalid)?
+                    .unwrap_or_default()
+            }
+            _ => return Err(Error::Unknown(name.to_string())),
+        }
+        Ok(())
+    }
+}
+
+fn parse_scale_factor(value: &str) -> Option<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|n| (0.0..=MAX_SCALE_FACTOR).contains(n))
+}
+
+// 文字列の中の # はコメントにしない
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Some(true),
+        "off" | "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// 数と単位に分ける。単位の大文字と小文字は区別しない
+fn split_unit(value: &str) -> Option<(u64, String)> {
+    let value = value.trim();
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let n = value[..end].parse().ok()?;
+    Some((n, value[end..].trim().to_ascii_lowercase()))
+}
+
+// 単位がなければ kB
+fn parse_memory(value: &str) -> Option<usize> {
+    let (n, unit) = split_unit(value)?;
+    let scale: u64 = match unit.as_str() {
+        "b" => 1,
+        "" | "kb" => 1 << 10,
+        "mb" => 1 << 20,
+        "gb" => 1 << 30,
+        "tb" => 1 << 40,
+        _ => return None,
+    };
+    n.checked_mul(scale)?.try_into().ok()
+}
+
+fn format_memory(bytes: usize) -> String {
+    for (unit, scale) in [
+        ("TB", 1 << 40),
+        ("GB", 1 << 30),
+        ("MB", 1 << 20),
+        ("kB", 1 << 10),
+    ] {
+        if bytes >= scale && bytes.is_multiple_of(scale) {
+            return format!("{}{}", bytes / scale, unit);
+        }
+    }
+    format!("{}B", bytes)
+}
+
+// 上限を決めていなければ 0
+fn format_limit(bytes: Option<usize>) -> String {
+    bytes.map_or_else(|| "0".to_string(), format_memory)
+}
+
+// 単位がなければミリ秒。0 は時間を決めない。nowait は待たない
+fn parse_timeout(value: &str) -> Option<Option<Duration>> {
+    if value.eq_ignore_ascii_case("nowait") {
+        return Some(Some(Duration::ZERO));
+    }
+    let (n, unit) = split_unit(value)?;
+    let ms: u64 = match unit.as_str() {
+        "" | "ms" => 1,
+        "s" => 1000,
+        "min" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    let ms = n.checked_mul(ms)?;
+    Some((ms != 0).then(|| Duration::from_millis(ms)))
+}
+
+fn format_timeout(timeout: Option<Duration>) -> String {
+    let Some(timeout) = timeout else {
+        return "0".to_string();
+    };
+    let ms = timeout.as_millis();
+    if ms == 0 {
+        return "nowait".to_string();
+    }
+    for (unit, scale) in [
+        ("d", 86_400_000),
+        ("h", 3_600_000),
+        ("min", 60_000),
+        ("s", 1000),
+    ] {
+        if ms % scale == 0 {
+            return format!("{}{}", ms / scale, unit);
+        }
+    }
+    format!("{}ms", ms)
+}
+
+// 待たないなら 0
+fn format_delay(delay: Duration) -> String {
+    if delay.is_zero() {
+        return "0".to_string();
+    }
+    format_timeout(Some(delay))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings() {
+        let mut settings = Settings::parse_config(
+            "# comment\nbuffer_pool_size = 64\nwork_mem = \"16MB\" # inline\n\nsynchronous_commit = true\nlock_timeout = \"1s\"\n",
+        )
+        .unwrap();
+        assert_eq!(settings.buffer_pool_size, 64);
+        assert_eq!(settings.get("work_mem").unwrap(), "16MB");
+        assert_eq!(settings.get("synchronous_commit").unwrap(), "on");
+        assert_eq!(settings.lock_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(settings.get("statement_timeout").unwrap(), "0");
+
+        settings.set("work_mem", "1536").unwrap();
+        assert_eq!(settings.get("work_mem").unwrap(), "1536kB");
+        assert_eq!(settings.work_mem, 1536 * 1024);
+        settings.set("statement_timeout", "90 s").unwrap();
+        assert_eq!(settings.get("statement_timeout").unwrap(), "90s");
+        settings.set("statement_timeout", "1500").unwrap();
+        assert_eq!(settings.get("statement_timeout").unwrap(), "1500ms");
+        settings.set("lock_timeout", "0").unwrap();
+        assert_eq!(settings.lock_timeout, None);
+        settings.set("temp_file_limit", "2MB").unwrap();
+        assert_eq!(settings.temp_file_limit, Some(2 << 20));
+        settings.set("max_query_memory", "0").unwrap();
+        assert_eq!(settings.get("max_query_memory").unwrap(), "0");
+        assert_eq!(settings.get("checkpoint_wal_size").unwrap(), "16MB");
+        let config =
+            Settings::parse_config("checkpoint_wal_size = 0\ncommit_delay = \"2ms\"").unwrap();
+        assert_eq!(config.checkpoint_wal_size, None);
+        assert_eq!(config.get("commit_delay").unwrap(), "2ms");
+
+        assert!(matches!(
+            settings.set("buffer_pool_size", "128"),
+            Err(Error::Startup(_))
+        ));
+        assert!(matches!(
+            settings.set("checkpoint_wal_size", "1MB"),
+            Err(Error::Startup(_))
+        ));
+        assert!(matches!(
+            settings.set("work_mem", "1kB"),
+            Err(Error::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            settings.set("parallel_workers", "-1"),
+            Err(Error::InvalidValue { .. })
+        ));
+        assert!(matches!(settings.get("nope"), Err(Error::Unknown(_))));
+        let err = Settings::parse_config("work_mem = 4MB\n[server]\n").unwrap_err();
+        assert_eq!(err.to_string(), "config line 2: tables are not supported");
+        let err = Settings::parse_config("\nnope = 1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "config line 2: unrecognized configuration parameter \"nope\""
+        );
+    }
+}
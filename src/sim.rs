@@ -0,0 +1,369 @@
+use std::path::Path;
+use std::time::Duration;
+
+use crate::clock;
+use crate::connection::{Connection, Database, Error};
+use crate::crash::{self, Point, POINTS};
+use crate::disk::{DiskManager, Fault, MemoryDisk};
+use crate::settings::Settings;
+
+// 種から再現できるテストの道具
+//
+// ディスクはメモリに置き、時計はこのスレッドだけの仮想の時計にする。複数の接続を同じスレッドで動かし、
+// 次にどの接続が 1 歩進むか、時計をどれだけ進めるか、ディスクに障害を起こすかを種から作る乱数で決める。
+// ロックを待つ間も時計を進めるだけなので、待たせた相手が外さなければ決まった時刻に時間切れになる。
+// 落ちる場所も crash の場所と回数を種から選ぶ。同じ種なら同じ順に同じことが起こり、trace も同じになる。
+// WAL はメモリに置けないので一時ディレクトリに書き、落ちたあとはファイルのデータベースと同じように回復する
+
+// 1 歩ごとに進める時計の上限
+const MAX_TICK: Duration = Duration::from_micros(500);
+
+// splitmix64
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    // p の確率で true
+    pub fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64) < p * (1u64 << 53) as f64
+    }
+}
+
+pub struct Simulation {
+    pub rng: Rng,
+    pub disk: MemoryDisk,
+    // 1 歩ごとに障害を起こす確率
+    fault_rate: f64,
+    trace: Vec<String>,
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Simulation {
+        clock::set_virtual(true);
+        Simulation {
+            rng: Rng::new(seed),
+            disk: MemoryDisk::new(),
+            fault_rate: 0.0,
+            trace: vec![],
+        }
+    }
+
+    pub fn with_faults(mut self, rate: f64) -> Simulation {
+        self.fault_rate = rate;
+        self
+    }
+
+    // メモリのディスクの上のデータベース。ページが追い出されるように、バッファプールは小さくしておく
+    pub fn database(&self, pool_size: usize) -> Database {
+        let settings = Settings {
+            buffer_pool_size: pool_size,
+            lock_timeout: Some(Duration::from_millis(5)),
+            ..Settings::default()
+        };
+        Database::from_disk(DiskManager::memory(self.disk.clone()), settings).unwrap()
+    }
+
+    // すべての接続が終わるか steps 歩に達するまで、乱数で選んだ接続に step を呼ぶ。
+    // step は接続の番号と接続を受け取り、したことの説明を返す。None ならその接続は終わり
+    pub fn run(
+        &mut self,
+        conns: &mut [Connection],
+        steps: usize,
+        mut step: impl FnMut(usize, &mut Connection, &mut Rng) -> Option<String>,
+    ) {
+        let mut running: Vec<usize> = (0..conns.len()).collect();
+        for _ in 0..steps {
+            if running.is_empty() {
+                break;
+            }
+            clock::advance(Duration::from_nanos(
+                self.rng.below(MAX_TICK.as_nanos() as u64),
+            ));
+            if self.rng.chance(self.fault_rate) {
+                let fault = [Fault::ReadError, Fault::WriteError, Fault::TornWrite]
+                    [self.rng.below(3) as usize];
+                self.disk.inject(fault);
+                self.record(format!("inject {:?}", fault));
+            }
+            let i = self.rng.below(running.len() as u64) as usize;
+            let id = running[i];
+            match step(id, &mut conns[id], &mut self.rng) {
+                Some(action) => self.record(format!("c{} {}", id, action)),
+                None => {
+                    running.remove(i);
+                }
+            }
+        }
+        // 起こらなかった障害を残さない
+        self.disk.clear_fault();
+    }
+
+    // 落ちる場所と、そこを何回通ったあとで落ちるか
+    pub fn crash_point(&mut self, max: usize) -> (Point, usize) {
+        let point = POINTS[self.rng.below(POINTS.len() as u64) as usize];
+        (point, self.rng.below(max as u64) as usize)
+    }
+
+    // このシミュレーションのディスクと wal のログで、ファイルのデータベースと同じように開く。開くときは回復する
+    pub fn open_file(&self, wal: &Path, pool_size: usize) -> Result<Database, Error> {
+        let settings = Settings {
+            buffer_pool_size: pool_size,
+            ..Settings::default()
+        };
+        Database::open_disk(DiskManager::memory(self.disk.clone()), wal, settings)
+    }
+
+    // open_file で開いたデータベースを閉じずに落とし、fsync したと分かっていないログを失う
+    pub fn crash(&self, db: Database, wal: &Path) {
+        if let Some(flushed) = db.crash() {
+            crate::wal::lose_unflushed(wal, flushed).unwrap();
+        }
+    }
+
+    pub fn trace(&self) -> &[String] {
+        &self.trace
+    }
+
+    fn record(&mut self, event: String) {
+        let micros = clock::elapsed().unwrap_or_default().as_micros();
+        self.trace.push(format!("{:>8}us {}", micros, event));
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        crash::disarm();
+        clock::set_virtual(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_path;
+    use crate::types::Value;
+
+    const ACCOUNTS: i64 = 40;
+    const TOTAL: i64 = ACCOUNTS * 100;
+
+    // 口座の間で送金するトランザクションを、1 歩に 1 文ずつ進める
+    fn bank(seed: u64) -> Vec<String> {
+        let mut sim = Simulation::new(seed).with_faults(0.05);
+        let db = sim.database(8);
+        let mut setup = db.connect();
+        setup
+            .execute(
+                "CREATE TABLE account (id INTEGER PRIMARY KEY, balance INTEGER, note TEXT)",
+                &[],
+            )
+            .unwrap();
+        let rows: Vec<(i64, String)> = (0..ACCOUNTS).map(|i| (i, "x".repeat(300))).collect();
+        setup
+            .execute_many("INSERT INTO account VALUES ($1, 100, $2)", rows)
+            .unwrap();
+        let mut conns: Vec<Connection> = (0..4).map(|_| db.connect()).collect();
+        // 接続ごとの、進めている送金と次の文の番号
+        let mut transfers = vec![None; conns.len()];
+        let mut remaining = vec![10; conns.len()];
+        sim.run(&mut conns, 2000, |id, conn, rng| {
+            let (from, to, amount, stage) = match transfers[id] {
+                Some(transfer) => transfer,
+                None if remaining[id] == 0 => return None,
+                None => {
+                    remaining[id] -= 1;
+                    let from = rng.below(ACCOUNTS as u64) as i64;
+                    let to = (from + 1 + rng.below(ACCOUNTS as u64 - 1) as i64) % ACCOUNTS;
+                    (from, to, rng.below(50) as i64, 0)
+                }
+            };
+            let (sql, params) = match stage {
+                0 => ("BEGIN", vec![]),
+                1 => (
+                    "UPDATE account SET balance = balance - $2 WHERE id = $1",
+                    vec![from, amount],
+                ),
+                2 => (
+                    "UPDATE account SET balance = balance + $2 WHERE id = $1",
+                    vec![to, amount],
+                ),
+                _ => ("COMMIT", vec![]),
+            };
+            let params: Vec<Value> = params.into_iter().map(Value::from).collect();
+            match conn.execute(sql, &params) {
+                Ok(_) if stage < 3 => {
+                    transfers[id] = Some((from, to, amount, stage + 1));
+                    Some(sql.to_string())
+                }
+                Ok(_) => {
+                    transfers[id] = None;
+                    Some(sql.to_string())
+                }
+                Err(e) => {
+                    let _ = conn.rollback();
+                    transfers[id] = None;
+                    Some(format!("{} failed: {}", sql, e.sqlstate()))
+                }
+            }
+        });
+        drop(conns);
+
+        // 障害で失敗した送金も含めて、取り消したものは残らない
+        let sum = setup
+            .query_row("SELECT sum(balance), count(*) FROM account", &[])
+            .unwrap();
+        assert_eq!(
+            sum.values(),
+            [Value::BigInt(TOTAL), Value::BigInt(ACCOUNTS)],
+            "seed {}",
+            seed
+        );
+        sim.trace().to_vec()
+    }
+
+    #[test]
+    fn test_simulation() {
+        for seed in 0..8 {
+            let trace = bank(seed);
+            assert!(
+                trace.iter().any(|event| event.ends_with("COMMIT")),
+                "seed {}",
+                seed
+            );
+            // 同じ種なら同じ順に同じことが起こる
+            assert_eq!(trace, bank(seed), "seed {}", seed);
+        }
+        let traces: Vec<_> = (0..2).map(bank).collect();
+        assert_ne!(traces[0], traces[1]);
+    }
+
+    // kv の k ごとの v と、作ったテーブルの番号
+    fn state(conn: &mut Connection) -> (Vec<i64>, Vec<i64>) {
+        let values = conn
+            .query("SELECT v FROM kv ORDER BY k", &[])
+            .unwrap()
+            .map(|row| row.get::<i64>(0).unwrap())
+            .collect();
+        let tables = (0..TXNS)
+            .filter(|i| conn.catalog().table(&format!("t{}", i)).is_some())
+            .collect();
+        (values, tables)
+    }
+
+    // txns のうち committed に入るものをこの順にコミットしたときの状態
+    fn expected(txns: &[(i64, u64, bool)], committed: &[i64]) -> (Vec<i64>, Vec<i64>) {
+        let mut values = vec![-1; KEYS as usize];
+        for &(i, k, _) in txns.iter().filter(|(i, ..)| committed.contains(i)) {
+            values[k as usize] = i;
+        }
+        let tables = txns
+            .iter()
+            .filter(|&&(i, _, create)| create && committed.contains(&i))
+            .map(|&(i, ..)| i)
+            .collect();
+        (values, tables)
+    }
+
+    const KEYS: i64 = 8;
+    const TXNS: i64 = 10;
+
+    // 種で選んだ行を書き換えてテーブルも作るトランザクションを、種で選んだ場所で落として開き直す。
+    // SQL から見て、コミットが返ったものだけが残り、実行中だったものは残るか残らないかのどちらかになる
+    #[test]
+    fn test_simulated_crash() {
+        for seed in 0..24 {
+            let mut sim = Simulation::new(seed);
+            let dir = temp_path("sim-wal");
+            let (point, n) = sim.crash_point(12);
+            let txns: Vec<(i64, u64, bool)> = (0..TXNS)
+                .map(|i| (i, sim.rng.below(KEYS as u64), sim.rng.chance(0.3)))
+                .collect();
+            let aborts: Vec<bool> = txns.iter().map(|_| sim.rng.chance(0.25)).collect();
+            let what = format!("seed {}: crashed at {:?} #{}", seed, point, n);
+
+            let db = sim.open_file(&dir, 4).unwrap();
+            let mut conn = db.connect();
+            conn.execute("CREATE TABLE kv (k INTEGER PRIMARY KEY, v INTEGER)", &[])
+                .unwrap();
+            let rows: Vec<(i64, i64)> = (0..KEYS).map(|k| (k, -1)).collect();
+            conn.execute_many("INSERT INTO kv VALUES ($1, $2)", rows)
+                .unwrap();
+            // 終わらないトランザクションが足した行は、開き直すときに片づける
+            let mut long = db.connect();
+            long.begin().unwrap();
+            long.execute("INSERT INTO kv VALUES ($1, 0)", &[Value::from(KEYS)])
+                .unwrap();
+            let (mut acked, mut pending) = (vec![], None);
+            crash::arm(point, n);
+            let result = (|| -> Result<(), Error> {
+                for (&(i, k, create), &abort) in txns.iter().zip(&aborts) {
+                    conn.begin()?;
+                    conn.execute(
+                        "UPDATE kv SET v = $1 WHERE k = $2",
+                        &[Value::from(i), Value::from(k as i64)],
+                    )?;
+                    if create {
+                        conn.execute(&format!("CREATE TABLE t{} (a INTEGER)", i), &[])?;
+                    }
+                    if abort {
+                        conn.rollback()?;
+                    } else {
+                        pending = Some(i);
+                        conn.commit()?;
+                        acked.push(i);
+                        pending = None;
+                    }
+                }
+                Ok(())
+            })();
+            // 落ちたのでトランザクションは取り消さない
+            std::mem::forget(conn);
+            std::mem::forget(long);
+            sim.crash(db, &dir);
+            let reopened = sim.open_file(&dir, 4);
+            let crashed = crash::disarm();
+            if let Err(e) = result {
+                assert!(crashed, "{}: {}", what, e);
+            }
+            let db = match reopened {
+                Ok(db) => db,
+                Err(e) => {
+                    assert!(crashed, "{}: {}", what, e);
+                    sim.open_file(&dir, 4).unwrap()
+                }
+            };
+
+            let actual = state(&mut db.connect());
+            let mut candidates = vec![expected(&txns, &acked)];
+            if let Some(i) = pending {
+                candidates.push(expected(&txns, &[&acked[..], &[i]].concat()));
+            }
+            assert!(candidates.contains(&actual), "{}: {:?}", what, actual);
+            // 回復したあとに書いたものも残る
+            db.connect()
+                .execute("UPDATE kv SET v = 100 WHERE k = 0", &[])
+                .unwrap();
+            sim.crash(db, &dir);
+            let db = sim.open_file(&dir, 4).unwrap();
+            let (values, _) = state(&mut db.connect());
+            assert_eq!(values[0], 100, "{}", what);
+            assert_eq!(values[1..], actual.0[1..], "{}", what);
+            drop(db);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
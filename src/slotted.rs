@@ -0,0 +1,292 @@
+// スロット付きページ
+//
+// | num_slots (2) | free_space_end (2) | slot 0 (4) | slot 1 (4) | ... 空き ... | データ |
+//
+// スロットは (offset, len) の組で、データはページ末尾から前に向かって詰める。
+// offset が 0 のスロットは削除済み。
+
+use std::ops::Range;
+
+const HEADER_SIZE: usize = 4;
+const SLOT_SIZE: usize = 4;
+
+pub struct Slotted<B> {
+    bytes: B,
+}
+
+impl<B: AsRef<[u8]>> Slotted<B> {
+    pub fn new(bytes: B) -> Self {
+        Self { bytes }
+    }
+
+    fn read_u16(&self, offset: usize) -> usize {
+        let b = self.bytes.as_ref();
+        u16::from_be_bytes([b[offset], b[offset + 1]]) as usize
+    }
+
+    pub fn num_slots(&self) -> usize {
+        self.read_u16(0)
+    }
+
+    fn free_space_end(&self) -> usize {
+        self.read_u16(2)
+    }
+
+    fn slot(&self, slot_id: usize) -> (usize, usize) {
+        let base = HEADER_SIZE + slot_id * SLOT_SIZE;
+        (self.read_u16(base), self.read_u16(base + 2))
+    }
+
+    fn slots_end(&self) -> usize {
+        HEADER_SIZE + self.num_slots() * SLOT_SIZE
+    }
+
+    // 新しいスロットを 1 つ追加したうえで使える連続領域の大きさ
+    pub fn free_space(&self) -> usize {
+        self.free_space_end().saturating_sub(self.slots_end())
+    }
+
+    // 削除済みデータを詰めた場合の空き容量
+    fn reclaimable_space(&self) -> usize {
+        let used: usize = (0..self.num_slots()).map(|i| self.slot(i).1).sum();
+        self.bytes.as_ref().len() - self.slots_end() - used
+    }
+
+    pub fn get(&self, slot_id: usize) -> Option<&[u8]> {
+        self.data_range(slot_id)
+            .map(|range| &self.bytes.as_ref()[range])
+    }
+
+    // データがページ内のどこにあるか
+    pub fn data_range(&self, slot_id: usize) -> Option<Range<usize>> {
+        if slot_id >= self.num_slots() {
+            return None;
+        }
+        let (offset, len) = self.slot(slot_id);
+        if offset == 0 {
+            return None;
+        }
+        Some(offset..offset + len)
+    }
+
+    // スロットの配列とデータの位置が壊れていないか確かめる。壊れていればその説明を返す
+    pub fn verify(&self) -> Result<(), String> {
+        let len = self.bytes.as_ref().len();
+        if self.slots_end() > len {
+            return Err(format!("{} slots do not fit in the page", self.num_slots()));
+        }
+        let free_space_end = self.free_space_end();
+        if free_space_end < self.slots_end() || free_space_end > len {
+            return Err(format!(
+                "free space ends at {}, outside {}..{}",
+                free_space_end,
+                self.slots_end(),
+                len
+            ));
+        }
+        let mut ranges = vec![];
+        for slot_id in 0..self.num_slots() {
+            let Some(range) = self.data_range(slot_id) else {
+                continue;
+            };
+            if range.start < free_space_end || range.end > len {
+                return Err(format!(
+                    "slot {} at {}..{} is outside the data area {}..{}",
+                    slot_id, range.start, range.end, free_space_end, len
+                ));
+            }
+            ranges.push((range, slot_id));
+        }
+        ranges.sort_by_key(|(range, _)| (range.start, range.end));
+        for pair in ranges.windows(2) {
+            let ((a, a_id), (b, b_id)) = (&pair[0], &pair[1]);
+            if b.start < a.end {
+                return Err(format!("slot {} overlaps slot {}", b_id, a_id));
+            }
+        }
+        Ok(())
+    }
+
+    // 1 ページに収まる最大のデータ長
+    pub fn capacity(page_len: usize) -> usize {
+        page_len - HEADER_SIZE - SLOT_SIZE
+    }
+}
+
+impl<B: AsMut<[u8]> + AsRef<[u8]>> Slotted<B> {
+    pub fn initialize(&mut self) {
+        let len = self.bytes.as_ref().len();
+        self.write_u16(0, 0);
+        self.write_u16(2, len);
+    }
+
+    fn write_u16(&mut self, offset: usize, value: usize) {
+        self.bytes.as_mut()[offset..offset + 2].copy_from_slice(&(value as u16).to_be_bytes());
+    }
+
+    fn set_slot(&mut self, slot_id: usize, offset: usize, len: usize) {
+        let base = HEADER_SIZE + slot_id * SLOT_SIZE;
+        self.write_u16(base, offset);
+        self.write_u16(base + 2, len);
+    }
+
+    // 領域を確保してデータを書き込み、その offset を返す
+    fn allocate(&mut self, data: &[u8], extra: usize) -> Option<usize> {
+        if self.free_space() < data.len() + extra {
+            if self.reclaimable_space() < data.len() + extra {
+                return None;
+            }
+            self.compact();
+        }
+        let offset = self.free_space_end() - data.len();
+        self.bytes.as_mut()[offset..offset + data.len()].copy_from_slice(data);
+        self.write_u16(2, offset);
+        Some(offset)
+    }
+
+    pub fn insert(&mut self, data: &[u8]) -> Option<usize> {
+        let offset = self.allocate(data, SLOT_SIZE)?;
+        let slot_id = self.num_slots();
+        self.write_u16(0, slot_id + 1);
+        // offset 0 は削除済みを表すので、長さ 0 のデータでも 0 以外にしておく
+        self.set_slot(slot_id, offset.max(1), data.len());
+        Some(slot_id)
+    }
+
+    // slot_id の位置にスロットを差し込み、以降のスロットを 1 つずつ後ろにずらす
+    pub fn insert_at(&mut self, slot_id: usize, data: &[u8]) -> bool {
+        let Some(offset) = self.allocate(data, SLOT_SIZE) else {
+            return false;
+        };
+        let num_slots = self.num_slots();
+        let start = HEADER_SIZE + slot_id * SLOT_SIZE;
+        let end = HEADER_SIZE + num_slots * SLOT_SIZE;
+        self.bytes
+            .as_mut()
+            .copy_within(start..end, start + SLOT_SIZE);
+        self.write_u16(0, num_slots + 1);
+        self.set_slot(slot_id, offset.max(1), data.len());
+        true
+    }
+
+    // スロットそのものを取り除き、以降のスロットを前に詰める
+    pub fn remove(&mut self, slot_id: usize) {
+        let num_slots = self.num_slots();
+        let start = HEADER_SIZE + (slot_id + 1) * SLOT_SIZE;
+        let end = HEADER_SIZE + num_slots * SLOT_SIZE;
+        self.bytes
+            .as_mut()
+            .copy_within(start..end, start - SLOT_SIZE);
+        self.write_u16(0, num_slots - 1);
+    }
+
+    pub fn delete(&mut self, slot_id: usize) -> bool {
+        if self.get(slot_id).is_none() {
+            return false;
+        }
+        self.set_slot(slot_id, 0, 0);
+        true
+    }
+
+    // 収まらない場合は false を返し、元のデータは変更しない
+    pub fn update(&mut self, slot_id: usize, data: &[u8]) -> bool {
+        let Some(old) = self.get(slot_id) else {
+            return false;
+        };
+        let (offset, len) = self.slot(slot_id);
+        if data.len() <= old.len() {
+            self.bytes.as_mut()[offset..offset + data.len()].copy_from_slice(data);
+            self.set_slot(slot_id, offset, data.len());
+            return true;
+        }
+        // 古い領域も空きとして数えられるよう一旦外してから確保する
+        self.set_slot(slot_id, 0, 0);
+        match self.allocate(data, 0) {
+            Some(offset) => {
+                self.set_slot(slot_id, offset.max(1), data.len());
+                true
+            }
+            None => {
+                // compact は allocate が成功する場合にしか走らないので元の位置は有効
+                self.set_slot(slot_id, offset, len);
+                false
+            }
+        }
+    }
+
+    fn compact(&mut self) {
+        let mut live: Vec<(usize, Vec<u8>)> = (0..self.num_slots())
+            .filter_map(|i| self.get(i).map(|data| (i, data.to_vec())))
+            .collect();
+        live.sort_by_key(|(i, _)| *i);
+        let mut end = self.bytes.as_ref().len();
+        for (slot_id, data) in live {
+            end -= data.len();
+            self.bytes.as_mut()[end..end + data.len()].copy_from_slice(&data);
+            self.set_slot(slot_id, end.max(1), data.len());
+        }
+        self.write_u16(2, end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_get_delete() {
+        let mut page = [0u8; 64];
+        let mut slotted = Slotted::new(&mut page[..]);
+        slotted.initialize();
+        assert_eq!(slotted.insert(b"hello"), Some(0));
+        assert_eq!(slotted.insert(b""), Some(1));
+        assert_eq!(slotted.insert(b"world"), Some(2));
+        assert_eq!(slotted.get(0), Some(&b"hello"[..]));
+        assert_eq!(slotted.get(1), Some(&b""[..]));
+        assert!(slotted.delete(0));
+        assert_eq!(slotted.get(0), None);
+        assert!(!slotted.delete(0));
+        assert_eq!(slotted.get(2), Some(&b"world"[..]));
+    }
+
+    #[test]
+    fn test_update_and_compact() {
+        let mut page = [0u8; 48];
+        let mut slotted = Slotted::new(&mut page[..]);
+        slotted.initialize();
+        slotted.insert(&[1; 16]).unwrap();
+        slotted.insert(&[2; 16]).unwrap();
+        assert_eq!(slotted.insert(&[3; 16]), None);
+        assert!(slotted.update(1, &[4; 8]));
+        slotted.delete(0);
+        // 削除した領域を詰めれば入る
+        assert!(slotted.update(1, &[5; 24]));
+        assert_eq!(slotted.get(1), Some(&[5; 24][..]));
+        assert!(!slotted.update(1, &[6; 64]));
+        assert_eq!(slotted.get(1), Some(&[5; 24][..]));
+    }
+
+    #[test]
+    fn test_insert_at_remove() {
+        let mut page = [0u8; 64];
+        let mut slotted = Slotted::new(&mut page[..]);
+        slotted.initialize();
+        assert!(slotted.insert_at(0, b"b"));
+        assert!(slotted.insert_at(0, b"a"));
+        assert!(slotted.insert_at(2, b"c"));
+        let all = |s: &Slotted<&mut [u8]>| {
+            (0..s.num_slots())
+                .map(|i| s.get(i).unwrap().to_vec())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            all(&slotted),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+        slotted.remove(1);
+        assert_eq!(all(&slotted), vec![b"a".to_vec(), b"c".to_vec()]);
+        // 取り除いた領域は詰めれば再利用できる
+        assert!(slotted.insert_at(1, &[7; 40]));
+        assert!(!slotted.insert_at(1, &[8; 10]));
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::trace;
+use crate::types::Value;
+
+// 遅い文のログ
+//
+// min_duration より長くかかった文を、文の全体、パラメータ、かかった時間、返したか書き換えた行の数、
+// バッファプールで見つかったページとディスクから読んだページの数とともに書く。
+// 書く先はファイル (など任意の Write) か、trace の "slow_query" のスパン。
+// 終わった文だけを書く。失敗した文と、execute_many がまとめて入れた INSERT は書かない
+
+pub struct SlowQueryLog {
+    pub(crate) min_duration: Duration,
+    redact_params: bool,
+    target: Target,
+}
+
+enum Target {
+    Writer(Box<dyn Write>),
+    Trace,
+}
+
+// ログに書く 1 つの文
+pub(crate) struct Entry<'a> {
+    pub sql: &'a str,
+    pub params: &'a [Value],
+    pub duration: Duration,
+    pub rows: usize,
+    pub hits: u64,
+    pub reads: u64,
+}
+
+impl SlowQueryLog {
+    // path のファイルの末尾に 1 つの文を 1 行として書き足す
+    pub fn to_file(path: impl AsRef<Path>, min_duration: Duration) -> io::Result<SlowQueryLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(SlowQueryLog::to_writer(file, min_duration))
+    }
+
+    pub fn to_writer(writer: impl Write + 'static, min_duration: Duration) -> SlowQueryLog {
+        SlowQueryLog {
+            min_duration,
+            redact_params: false,
+            target: Target::Writer(Box::new(writer)),
+        }
+    }
+
+    pub fn to_trace(min_duration: Duration) -> SlowQueryLog {
+        SlowQueryLog {
+            min_duration,
+            redact_params: false,
+            target: Target::Trace,
+        }
+    }
+
+    // パラメータの値を書かず、数だけを残す
+    pub fn redact_params(mut self, redact: bool) -> SlowQueryLog {
+        self.redact_params = redact;
+        self
+    }
+
+    // 書けなくても文の実行は失敗させない
+    pub(crate) fn log(&mut self, entry: &Entry) {
+        let duration = format!("{:.3}", entry.duration.as_secs_f64() * 1000.0);
+        let params = self.params(entry.params);
+        match &mut self.target {
+            Target::Writer(writer) => {
+                let mut line = format!(
+                    "duration: {} ms  rows: {}  buffers: hit={} read={}  statement: {}",
+                    duration, entry.rows, entry.hits, entry.reads, entry.sql
+                );
+                if !params.is_empty() {
+                    write!(line, "  parameters: {}", params).unwrap();
+                }
+                let _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+            }
+            Target::Trace => {
+                let _span = trace::span(
+                    "slow_query",
+                    &[
+                        ("sql", &entry.sql),
+                        ("params", &params),
+                        ("duration_ms", &duration),
+                        ("rows", &entry.rows),
+                        ("buffer_hits", &entry.hits),
+                        ("buffer_reads", &entry.reads),
+                    ],
+                );
+            }
+        }
+    }
+
+    // PostgreSQL のログと同じく $1 = '...', $2 = NULL の形
+    fn params(&self, params: &[Value]) -> String {
+        params
+            .iter()
+            .enumerate()
+            .map(|(i, value)| match value {
+                _ if self.redact_params => format!("${} = ?", i + 1),
+                Value::Null => format!("${} = NULL", i + 1),
+                value => format!("${} = '{}'", i + 1, value.to_string().replace('\'', "''")),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Database;
+
+    #[test]
+    fn test_slow_query_log() {
+        let path = crate::testutil::temp_path("slow.log");
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE t (a INTEGER, b TEXT)", &[])
+            .unwrap();
+        db.set_slow_query_log(Some(SlowQueryLog::to_file(&path, Duration::ZERO).unwrap()));
+        conn.execute(
+            "INSERT INTO t VALUES ($1, $2), ($1 + 1, NULL)",
+            &[Value::Integer(1), Value::Text("it's".to_string())],
+        )
+        .unwrap();
+        conn.query("SELECT * FROM t", &[]).unwrap();
+        assert!(conn.execute("SELECT * FROM nope", &[]).is_err());
+
+        db.set_slow_query_log(Some(
+            SlowQueryLog::to_file(&path, Duration::ZERO)
+                .unwrap()
+                .redact_params(true),
+        ));
+        conn.execute("DELETE FROM t WHERE a = $1", &[Value::Integer(2)])
+            .unwrap();
+        // 閾値より速い文は書かない
+        db.set_slow_query_log(Some(
+            SlowQueryLog::to_file(&path, Duration::from_secs(3600)).unwrap(),
+        ));
+        conn.execute("DELETE FROM t", &[]).unwrap();
+        db.set_slow_query_log(None);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", log);
+        assert!(lines[0].starts_with("duration: "), "{}", log);
+        assert!(lines[0].contains(" ms  rows: 2  buffers: hit="), "{}", log);
+        assert!(
+            lines[0].ends_with(
+                "statement: INSERT INTO t VALUES ($1, $2), ($1 + 1, NULL)  parameters: $1 = '1', $2 = 'it''s'"
+            ),
+            "{}",
+            log
+        );
+        assert!(lines[1].ends_with("statement: SELECT * FROM t"), "{}", log);
+        assert!(lines[1].contains("  rows: 2  "), "{}", log);
+        assert!(
+            lines[2].ends_with("statement: DELETE FROM t WHERE a = $1  parameters: $1 = ?"),
+            "{}",
+            log
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,896 @@
+use std::fmt;
+
+use crate::datetime::{Date, Interval, Time, Timestamp};
+use crate::decimal::Decimal;
+use crate::geometry::{Point, Rect};
+use crate::uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Query(Box<Query>),
+    Insert(Insert),
+    Update(Update),
+    Delete(Delete),
+    CreateTable(Box<CreateTable>),
+    CreateIndex(CreateIndex),
+    CreateMaterializedView(Box<CreateMaterializedView>),
+    // REFRESH MATERIALIZED VIEW name
+    RefreshMaterializedView(String),
+    // DROP TABLE と DROP MATERIALIZED VIEW
+    DropTable(DropTable),
+    CreateTrigger(Box<CreateTrigger>),
+    // DROP TRIGGER [IF EXISTS] name ON table
+    DropTrigger {
+        name: String,
+        table: String,
+        if_exists: bool,
+    },
+    // CREATE DATABASE name
+    CreateDatabase(String),
+    // ATTACH [DATABASE] 'path' AS alias [(READ_ONLY)]
+    Attach {
+        path: String,
+        alias: String,
+        read_only: bool,
+    },
+    // DETACH [DATABASE] alias
+    Detach(String),
+    // CREATE ROLE name [WITH] option ... / CREATE USER は LOGIN を省いた CREATE ROLE
+    CreateRole {
+        name: String,
+        options: RoleOptions,
+    },
+    // ALTER ROLE name [WITH] option ...
+    AlterRole {
+        name: String,
+        options: RoleOptions,
+    },
+    // DROP ROLE [IF EXISTS] name
+    DropRole {
+        name: String,
+        if_exists: bool,
+    },
+    // CREATE TYPE name AS ENUM ('label', ...)
+    CreateType {
+        name: String,
+        labels: Vec<String>,
+    },
+    // ALTER TYPE name ADD VALUE [IF NOT EXISTS] 'label' [{BEFORE | AFTER} 'label']。
+    // position は BEFORE なら true と隣のラベル
+    AlterType {
+        name: String,
+        label: String,
+        position: Option<(bool, String)>,
+        if_not_exists: bool,
+    },
+    // ALTER TABLE name action
+    AlterTable {
+        name: String,
+        action: AlterTableAction,
+    },
+    // DROP TYPE [IF EXISTS] name
+    DropType {
+        name: String,
+        if_exists: bool,
+    },
+    Grant(Grant),
+    Revoke(Grant),
+    // EXPLAIN [ANALYZE] statement か EXPLAIN (ANALYZE [bool], FORMAT {TEXT | JSON | DOT}) statement
+    Explain {
+        analyze: bool,
+        format: ExplainFormat,
+        statement: Box<Statement>,
+    },
+    Transaction(TransactionStatement),
+    // ANALYZE [table]。テーブルを省くとすべてのテーブル
+    Analyze {
+        table: Option<String>,
+    },
+    // VACUUM [table]。誰からも見えなくなった版を取り除く
+    Vacuum {
+        table: Option<String>,
+    },
+    Copy(Copy),
+    // SET [LOCAL] name { = | TO } value。value が None なら DEFAULT
+    Set {
+        name: String,
+        value: Option<String>,
+        local: bool,
+    },
+    // SHOW name / SHOW ALL。ALL なら None
+    Show(Option<String>),
+    // RESET name / RESET ALL。ALL なら None
+    Reset(Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionStatement {
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint(String),
+    RollbackTo(String),
+    Release(String),
+    // SET TRANSACTION ISOLATION LEVEL level
+    SetIsolationLevel(IsolationLevel),
+    // PREPARE TRANSACTION 'gid' / COMMIT PREPARED 'gid' / ROLLBACK PREPARED 'gid'
+    Prepare(String),
+    CommitPrepared(String),
+    RollbackPrepared(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationLevel {
+    // 文ごとにスナップショットを取る
+    ReadCommitted,
+    // トランザクションを始めたときのスナップショットを使い続ける
+    RepeatableRead,
+    // REPEATABLE READ に加えて、同時に実行したトランザクションとの読み書きの依存が循環しうるならコミットを失敗させる
+    Serializable,
+}
+
+impl fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IsolationLevel::ReadCommitted => "READ COMMITTED",
+            IsolationLevel::RepeatableRead => "REPEATABLE READ",
+            IsolationLevel::Serializable => "SERIALIZABLE",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub with: Vec<Cte>,
+    pub body: SetExpr,
+    pub order_by: Vec<OrderByExpr>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+    pub locking: Option<LockingClause>,
+}
+
+// FOR UPDATE | FOR SHARE [NOWAIT | SKIP LOCKED]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockingClause {
+    pub strength: LockStrength,
+    pub wait: WaitPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockStrength {
+    Update,
+    Share,
+}
+
+impl fmt::Display for LockStrength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LockStrength::Update => "FOR UPDATE",
+            LockStrength::Share => "FOR SHARE",
+        })
+    }
+}
+
+// ほかのトランザクションがロックしている行に当たったときの動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitPolicy {
+    Block,
+    NoWait,
+    SkipLocked,
+}
+
+// WITH [RECURSIVE] name [(columns)] AS (query)。RECURSIVE はその WITH のすべての問い合わせにつく
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cte {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub query: Box<Query>,
+    pub recursive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetExpr {
+    Select(Box<Select>),
+    // 括弧で囲まれた問い合わせ
+    Query(Box<Query>),
+    SetOperation {
+        op: SetOperator,
+        // UNION ALL など。false は DISTINCT
+        all: bool,
+        left: Box<SetExpr>,
+        right: Box<SetExpr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
+}
+
+impl fmt::Display for SetOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SetOperator::Union => "UNION",
+            SetOperator::Intersect => "INTERSECT",
+            SetOperator::Except => "EXCEPT",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub distinct: bool,
+    pub projection: Vec<SelectItem>,
+    pub from: Option<TableRef>,
+    pub selection: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    // GROUPING SETS、ROLLUP、CUBE を展開した集合。group_by はどれかの集合に現れる式を一度ずつ並べたもの
+    pub grouping_sets: Option<Vec<Vec<Expr>>>,
+    pub having: Option<Expr>,
+    // SELECT のすぐあとの /*+ ... */ に書いた計画のヒント
+    pub hints: Vec<Hint>,
+}
+
+// 計画のヒント。SeqScan(t)、IndexScan(t [index ...])、HashJoin(t s ...) のように書く
+//
+// 走査のヒントの relations は 1 つ、結合のヒントは結合の両側にあるテーブルをすべて並べる。
+// テーブルは名前か FROM の別名で指す
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hint {
+    pub method: HintMethod,
+    // NoSeqScan(t) のように No で始まれば、その方法を使わせない
+    pub negated: bool,
+    pub relations: Vec<String>,
+    // IndexScan で使ってよいインデックス。空ならどれでもよい
+    pub indexes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HintMethod {
+    SeqScan,
+    IndexScan,
+    HashJoin,
+    MergeJoin,
+    NestLoop,
+}
+
+impl HintMethod {
+    pub fn is_join(self) -> bool {
+        matches!(
+            self,
+            HintMethod::HashJoin | HintMethod::MergeJoin | HintMethod::NestLoop
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectItem {
+    Wildcard,
+    QualifiedWildcard(String),
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableRef {
+    Table {
+        name: String,
+        alias: Option<String>,
+        sample: Option<TableSample>,
+        as_of: Option<Box<AsOf>>,
+    },
+    // LATERAL なら、左にあるテーブルの列を参照できる
+    Subquery {
+        query: Box<Query>,
+        alias: String,
+        lateral: bool,
+    },
+    Join {
+        left: Box<TableRef>,
+        right: Box<TableRef>,
+        kind: JoinKind,
+        on: Option<Expr>,
+    },
+}
+
+// TABLESAMPLE method (percent) REPEATABLE (seed)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSample {
+    pub method: SampleMethod,
+    pub percent: Expr,
+    pub seed: Option<Expr>,
+}
+
+// AS OF TIMESTAMP expr か AS OF LSN expr。その時点の版を読む
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsOf {
+    Timestamp(Expr),
+    Lsn(Expr),
+}
+
+// SYSTEM はページごと、BERNOULLI は行ごとに選ぶ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleMethod {
+    System,
+    Bernoulli,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+    Cross,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderByExpr {
+    pub expr: Expr,
+    pub asc: bool,
+    // NULLS FIRST なら Some(true)、NULLS LAST なら Some(false)。なければ昇順で後、降順で先
+    pub nulls_first: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Integer(i64),
+    Float(f64),
+    // 指数のない小数
+    Decimal(Decimal),
+    // DATE '2024-01-15' のように型名を前に付けた文字列
+    Date(Date),
+    Time(Time),
+    Timestamp(Timestamp),
+    Uuid(Uuid),
+    Interval(Interval),
+    Point(Point),
+    Box(Rect),
+    String(String),
+    Boolean(bool),
+    Null,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+    // JSON の要素を取り出す -> と、文字列にして取り出す ->>
+    JsonGet,
+    JsonGetText,
+    // NULL どうしを等しいとみなす比較。結果は NULL にならない
+    IsDistinctFrom,
+    IsNotDistinctFrom,
+    // 図形が重なるか &&、含むか @>、含まれるか <@ と、図形の距離 <->
+    Overlaps,
+    Contains,
+    ContainedBy,
+    Distance,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Minus,
+    Plus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Literal(Literal),
+    // $n か ?。n は 1 から数える
+    Parameter(usize),
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        distinct: bool,
+    },
+    // COUNT(*)
+    CountStar,
+    // func(...) OVER (...)。func は Function か CountStar
+    Window {
+        func: Box<Expr>,
+        spec: WindowSpec,
+    },
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    InSubquery {
+        expr: Box<Expr>,
+        query: Box<Query>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    Exists {
+        query: Box<Query>,
+        negated: bool,
+    },
+    Subquery(Box<Query>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowSpec {
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderByExpr>,
+    pub frame: Option<WindowFrame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameUnits {
+    Rows,
+    Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowFrame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(u64),
+    CurrentRow,
+    Following(u64),
+    UnboundedFollowing,
+}
+
+impl FrameBound {
+    // 現在行を 0 としたときの相対位置 (比較用)
+    pub fn position(self) -> i128 {
+        match self {
+            FrameBound::UnboundedPreceding => i128::MIN,
+            FrameBound::Preceding(n) => -(n as i128),
+            FrameBound::CurrentRow => 0,
+            FrameBound::Following(n) => n as i128,
+            FrameBound::UnboundedFollowing => i128::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertSource {
+    Values(Vec<Vec<Expr>>),
+    Query(Box<Query>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub source: InsertSource,
+    pub on_conflict: Option<OnConflict>,
+    // RETURNING の項目。なければ空
+    pub returning: Vec<SelectItem>,
+}
+
+// ON CONFLICT [(columns)] DO ...。columns が空なら、どの一意インデックスでの重複も対象にする
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnConflict {
+    pub columns: Vec<String>,
+    pub action: ConflictAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConflictAction {
+    Nothing,
+    // 入れようとした行は excluded という名前で参照できる
+    Update {
+        assignments: Vec<Assignment>,
+        selection: Option<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub column: String,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub table: String,
+    pub assignments: Vec<Assignment>,
+    pub selection: Option<Expr>,
+    pub returning: Vec<SelectItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub table: String,
+    pub selection: Option<Expr>,
+    pub returning: Vec<SelectItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    // 型名は大文字に正規化したもの (例: "INTEGER", "VARCHAR(10)")
+    pub data_type: String,
+    pub not_null: bool,
+    pub primary_key: bool,
+    pub unique: bool,
+    // COLLATE で選んだ照合順の名前。省けば BINARY
+    pub collation: Option<String>,
+    // GENERATED ALWAYS AS (expr) [STORED | VIRTUAL]
+    pub generated: Option<Generated>,
+    // COMPRESSION 名前。dictionary なら値を辞書の番号にして持つ
+    pub compression: Option<String>,
+}
+
+// 生成列の式。stored なら書くときに計算して持ち、そうでなければ読むときに計算する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generated {
+    pub expr: Expr,
+    pub stored: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub if_not_exists: bool,
+    pub columns: Vec<ColumnDef>,
+    pub primary_key: Vec<String>,
+    // PARTITION BY RANGE (列) か PARTITION BY HASH (列)
+    pub partition_by: Option<PartitionBy>,
+    // PARTITION OF 親 FOR VALUES ...。列は親と同じにするので columns は空
+    pub partition_of: Option<PartitionOf>,
+    // TTL 列 + 間隔。列の時刻から間隔が過ぎた行は期限切れになる
+    pub ttl: Option<TableTtl>,
+    // USING 名前。行の版を持たせる StorageEngine で、None なら heap
+    pub using: Option<String>,
+    // WITH (名前 [= 値], ...) のテーブルの設定
+    pub options: Vec<(String, Option<String>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlterTableAction {
+    // SET (名前 [= 値], ...)
+    SetOptions(Vec<(String, Option<String>)>),
+    // ADD [COLUMN] 列の定義
+    AddColumn(ColumnDef),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableTtl {
+    pub column: String,
+    pub interval: Expr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionStrategy {
+    Range,
+    Hash,
+}
+
+impl fmt::Display for PartitionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PartitionStrategy::Range => "range",
+            PartitionStrategy::Hash => "hash",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionBy {
+    pub strategy: PartitionStrategy,
+    pub column: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionOf {
+    pub parent: String,
+    pub bound: PartitionBoundSpec,
+}
+
+// FOR VALUES FROM (下限) TO (上限) か FOR VALUES WITH (MODULUS m, REMAINDER r)。
+// 範囲は下限を含み上限を含まない。MINVALUE と MAXVALUE は None
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartitionBoundSpec {
+    Range {
+        from: Option<Expr>,
+        to: Option<Expr>,
+    },
+    Hash {
+        modulus: u64,
+        remainder: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    // ON t ((expr)) の式。あれば columns は空
+    pub expression: Option<Expr>,
+    pub unique: bool,
+    // CREATE FULLTEXT INDEX。列の語を引くインデックスで、式は使えない
+    pub fulltext: bool,
+    // USING rtree か USING gist の R-tree のインデックス
+    pub rtree: bool,
+}
+
+// COPY table [(columns)] FROM 'file' [WITH] (options)
+// COPY {table [(columns)] | (query)} TO 'file' [WITH] (options)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Copy {
+    pub relation: CopyRelation,
+    pub direction: CopyDirection,
+    pub file: String,
+    pub options: CopyOptions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CopyRelation {
+    Table {
+        name: String,
+        // 空ならテーブルのすべての列
+        columns: Vec<String>,
+    },
+    // TO でだけ使える
+    Query(Box<Query>),
+}
+
+impl CopyRelation {
+    // 書き出す行を返す問い合わせ。テーブルなら SELECT columns FROM table
+    pub fn query(&self) -> Query {
+        let (name, columns) = match self {
+            CopyRelation::Query(query) => return (**query).clone(),
+            CopyRelation::Table { name, columns } => (name, columns),
+        };
+        let projection = if columns.is_empty() {
+            vec![SelectItem::Wildcard]
+        } else {
+            columns
+                .iter()
+                .map(|column| SelectItem::Expr {
+                    expr: Expr::Column {
+                        table: None,
+                        name: column.clone(),
+                    },
+                    alias: None,
+                })
+                .collect()
+        };
+        Query {
+            with: vec![],
+            body: SetExpr::Select(Box::new(Select {
+                distinct: false,
+                projection,
+                from: Some(TableRef::Table {
+                    name: name.clone(),
+                    alias: None,
+                    sample: None,
+                    as_of: None,
+                }),
+                selection: None,
+                group_by: vec![],
+                grouping_sets: None,
+                having: None,
+                hints: vec![],
+            })),
+            order_by: vec![],
+            limit: None,
+            offset: None,
+            locking: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyDirection {
+    From,
+    To,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExplainFormat {
+    // 字下げした木
+    #[default]
+    Text,
+    // PostgreSQL の FORMAT JSON と同じ形
+    Json,
+    // Graphviz の DOT
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyFormat {
+    // RFC 4180 の CSV
+    #[default]
+    Csv,
+    // PostgreSQL の text 形式。\ で逃がし、NULL は \N
+    Text,
+    // 1 行に 1 つのオブジェクトを書く JSON Lines。TO でだけ使える
+    Json,
+    // Apache Parquet。TO でだけ使え、区切りや NULL の字面は使わない
+    Parquet,
+}
+
+// 省いた区切りと NULL の字面は形式ごとの既定を使う
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CopyOptions {
+    pub format: CopyFormat,
+    // 最初の行を列名として読み飛ばす
+    pub header: bool,
+    pub delimiter: Option<char>,
+    pub null: Option<String>,
+    pub quote: Option<char>,
+    // BULK。FROM で、行ごとのログを書かずに空のテーブルへまとめて入れる
+    pub bulk: bool,
+}
+
+impl CopyOptions {
+    pub fn delimiter(&self) -> char {
+        self.delimiter.unwrap_or(match self.format {
+            CopyFormat::Csv | CopyFormat::Json | CopyFormat::Parquet => ',',
+            CopyFormat::Text => '\t',
+        })
+    }
+
+    pub fn null(&self) -> &str {
+        self.null.as_deref().unwrap_or(match self.format {
+            CopyFormat::Csv | CopyFormat::Json | CopyFormat::Parquet => "",
+            CopyFormat::Text => "\\N",
+        })
+    }
+
+    pub fn quote(&self) -> char {
+        self.quote.unwrap_or('"')
+    }
+}
+
+// CREATE MATERIALIZED VIEW [IF NOT EXISTS] name AS query。
+// sql は query の部分の文字列で、REFRESH のたびに解析し直す
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateMaterializedView {
+    pub name: String,
+    pub if_not_exists: bool,
+    pub query: Query,
+    pub sql: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DropTable {
+    pub name: String,
+    pub if_exists: bool,
+}
+
+// SUPERUSER | NOSUPERUSER、LOGIN | NOLOGIN、PASSWORD 'password' | PASSWORD NULL。
+// 書かなかったものは None
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RoleOptions {
+    pub superuser: Option<bool>,
+    pub login: Option<bool>,
+    pub password: Option<Option<String>>,
+}
+
+// GRANT privileges ON [TABLE] tables TO roles / REVOKE privileges ON [TABLE] tables FROM roles。
+// ALL [PRIVILEGES] は 4 つの権限すべて
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    pub privileges: Vec<Privilege>,
+    pub tables: Vec<String>,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    Select,
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Privilege {
+    pub const ALL: [Privilege; 4] = [
+        Privilege::Select,
+        Privilege::Insert,
+        Privilege::Update,
+        Privilege::Delete,
+    ];
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Privilege::Select => "SELECT",
+            Privilege::Insert => "INSERT",
+            Privilege::Update => "UPDATE",
+            Privilege::Delete => "DELETE",
+        })
+    }
+}
+
+// CREATE TRIGGER name {BEFORE | AFTER} {INSERT | UPDATE | DELETE} ON table FOR EACH ROW body
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTrigger {
+    pub name: String,
+    pub timing: TriggerTiming,
+    pub event: TriggerEvent,
+    pub table: String,
+    pub body: TriggerBodySpec,
+}
+
+// 本体は SQL の文か EXECUTE FUNCTION name()。sql は文の部分の文字列
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerBodySpec {
+    Statement {
+        statement: Box<Statement>,
+        sql: String,
+    },
+    Function(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerTiming {
+    Before,
+    After,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerEvent {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl fmt::Display for TriggerTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TriggerTiming::Before => "BEFORE",
+            TriggerTiming::After => "AFTER",
+        })
+    }
+}
+
+impl fmt::Display for TriggerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TriggerEvent::Insert => "INSERT",
+            TriggerEvent::Update => "UPDATE",
+            TriggerEvent::Delete => "DELETE",
+        })
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+
+use super::lexer::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+    // 問題のあったトークン (字句エラーの場合は None)
+    pub found: Option<String>,
+    pub expected: Vec<String>,
+}
+
+impl ParseError {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self::lexical(span, message)
+    }
+
+    pub fn lexical(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+            found: None,
+            expected: Vec::new(),
+        }
+    }
+
+    pub fn unexpected(span: Span, found: impl Into<String>, expected: Vec<String>) -> Self {
+        let found = found.into();
+        Self {
+            span,
+            message: format!("unexpected {}", found),
+            found: Some(found),
+            expected,
+        }
+    }
+
+    // エラー位置の行を取り出して ^^^ で下線を引いたものを返す
+    pub fn underline(&self, src: &str) -> String {
+        let line_start = src[..self.span.start.min(src.len())]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line_end = src[line_start..]
+            .find('\n')
+            .map_or(src.len(), |i| line_start + i);
+        let line = &src[line_start..line_end];
+        let end = self.span.end.clamp(self.span.start, line_end);
+        let width = src[self.span.start.min(line_end)..end]
+            .chars()
+            .count()
+            .max(1);
+        let padding = self.span.column - 1;
+        format!("{}\n{}{}", line, " ".repeat(padding), "^".repeat(width))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "syntax error at line {}, column {}: {}",
+            self.span.line, self.span.column, self.message
+        )?;
+        match self.expected.as_slice() {
+            [] => Ok(()),
+            [one] => write!(f, ", expected {}", one),
+            many => write!(f, ", expected one of {}", many.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -0,0 +1,480 @@
+use std::fmt;
+
+use super::error::ParseError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    // バイトオフセット [start, end)
+    pub start: usize,
+    pub end: usize,
+    // 1 始まりの行・列
+    pub line: usize,
+    pub column: usize,
+}
+
+macro_rules! keywords {
+    ($($kw:ident),* $(,)?) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Keyword {
+            $($kw,)*
+        }
+
+        impl Keyword {
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(Keyword::$kw => stringify!($kw),)*
+                }
+            }
+
+            pub fn lookup(word: &str) -> Option<Keyword> {
+                $(
+                    if word.eq_ignore_ascii_case(stringify!($kw)) {
+                        return Some(Keyword::$kw);
+                    }
+                )*
+                None
+            }
+        }
+    };
+}
+
+keywords! {
+    ALL, ANALYZE, AND, AS, ASC, BEGIN, BETWEEN, BY, COMMIT, COMMITTED, CONFLICT,
+    COPY, CREATE, CROSS, CURRENT, DELETE, DESC, DISTINCT, DO, DROP, EXCEPT, EXISTS,
+    EXPLAIN, FALSE, FOLLOWING, FOR, FROM, FULL, GROUP, HAVING, IF, ILIKE, IN, INDEX,
+    INNER, INSERT, INTERSECT, INTO, IS, ISOLATION, JOIN, KEY, LEFT, LEVEL, LIKE, LIMIT,
+    LOCKED, NOT, NOTHING, NOWAIT, NULL, OFFSET, ON, OR, ORDER, OUTER, OVER,
+    PARTITION, PRECEDING, PREPARE, PREPARED, PRIMARY, RANGE, READ, REGEXP, RELEASE, REPEATABLE, RETURNING,
+    RIGHT, ROLLBACK, ROW, ROWS, SAVEPOINT, SELECT, SERIALIZABLE, SET, SHARE, SKIP,
+    START, TABLE, TO, TRANSACTION, TRUE, UNBOUNDED, UNION, UNIQUE, UPDATE, VACUUM,
+    VALUES, WHERE, WITH, WORK,
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Ident(String),
+    Number(String),
+    String(String),
+    Comma,
+    Dot,
+    Semicolon,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    // -> と ->>
+    Arrow,
+    LongArrow,
+    // 図形の演算子 &&、@>、<@、<->
+    Overlap,
+    Contains,
+    ContainedBy,
+    Distance,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    // $n か ?。? には左から順に番号を振る
+    Parameter(usize),
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Keyword(kw) => write!(f, "{}", kw),
+            TokenKind::Ident(name) => write!(f, "identifier \"{}\"", name),
+            TokenKind::Number(n) => write!(f, "number {}", n),
+            TokenKind::String(s) => write!(f, "string '{}'", s),
+            TokenKind::Comma => f.write_str("\",\""),
+            TokenKind::Dot => f.write_str("\".\""),
+            TokenKind::Semicolon => f.write_str("\";\""),
+            TokenKind::LParen => f.write_str("\"(\""),
+            TokenKind::RParen => f.write_str("\")\""),
+            TokenKind::LBracket => f.write_str("\"[\""),
+            TokenKind::RBracket => f.write_str("\"]\""),
+            TokenKind::Star => f.write_str("\"*\""),
+            TokenKind::Plus => f.write_str("\"+\""),
+            TokenKind::Minus => f.write_str("\"-\""),
+            TokenKind::Slash => f.write_str("\"/\""),
+            TokenKind::Percent => f.write_str("\"%\""),
+            TokenKind::Concat => f.write_str("\"||\""),
+            TokenKind::Arrow => f.write_str("\"->\""),
+            TokenKind::LongArrow => f.write_str("\"->>\""),
+            TokenKind::Overlap => f.write_str("\"&&\""),
+            TokenKind::Contains => f.write_str("\"@>\""),
+            TokenKind::ContainedBy => f.write_str("\"<@\""),
+            TokenKind::Distance => f.write_str("\"<->\""),
+            TokenKind::Eq => f.write_str("\"=\""),
+            TokenKind::NotEq => f.write_str("\"<>\""),
+            TokenKind::Lt => f.write_str("\"<\""),
+            TokenKind::LtEq => f.write_str("\"<=\""),
+            TokenKind::Gt => f.write_str("\">\""),
+            TokenKind::GtEq => f.write_str("\">=\""),
+            TokenKind::Parameter(n) => write!(f, "parameter ${}", n),
+            TokenKind::Eof => f.write_str("end of input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+pub struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    line: usize,
+    column: usize,
+    // これまでに読んだ ? の数
+    params: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            pos: 0,
+            line: 1,
+            column: 1,
+            params: 0,
+        }
+    }
+
+    pub fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            let is_eof = token.kind == TokenKind::Eof;
+            tokens.push(token);
+            if is_eof {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.src[self.pos..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn start_span(&self) -> Span {
+        Span {
+            start: self.pos,
+            end: self.pos,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn finish(&self, mut span: Span, kind: TokenKind) -> Token {
+        span.end = self.pos;
+        Token { kind, span }
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(), self.peek_nth(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('-'), Some('-')) => {
+                    while let Some(c) = self.bump() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let mut span = self.start_span();
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match self.bump() {
+                            Some('*') if self.peek() == Some('/') => {
+                                self.bump();
+                                break;
+                            }
+                            Some(_) => {}
+                            None => {
+                                span.end = self.pos;
+                                return Err(ParseError::lexical(
+                                    span,
+                                    "unterminated block comment",
+                                ));
+                            }
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_trivia()?;
+        let span = self.start_span();
+        let c = match self.bump() {
+            Some(c) => c,
+            None => return Ok(self.finish(span, TokenKind::Eof)),
+        };
+        let kind = match c {
+            ',' => TokenKind::Comma,
+            '.' if !matches!(self.peek(), Some('0'..='9')) => TokenKind::Dot,
+            ';' => TokenKind::Semicolon,
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            '*' => TokenKind::Star,
+            '+' => TokenKind::Plus,
+            '-' if self.peek() == Some('>') => {
+                self.bump();
+                if self.peek() == Some('>') {
+                    self.bump();
+                    TokenKind::LongArrow
+                } else {
+                    TokenKind::Arrow
+                }
+            }
+            '-' => TokenKind::Minus,
+            '/' => TokenKind::Slash,
+            '%' => TokenKind::Percent,
+            '=' => TokenKind::Eq,
+            '|' if self.peek() == Some('|') => {
+                self.bump();
+                TokenKind::Concat
+            }
+            '&' if self.peek() == Some('&') => {
+                self.bump();
+                TokenKind::Overlap
+            }
+            '@' if self.peek() == Some('>') => {
+                self.bump();
+                TokenKind::Contains
+            }
+            '!' if self.peek() == Some('=') => {
+                self.bump();
+                TokenKind::NotEq
+            }
+            '<' => match self.peek() {
+                Some('=') => {
+                    self.bump();
+                    TokenKind::LtEq
+                }
+                Some('>') => {
+                    self.bump();
+                    TokenKind::NotEq
+                }
+                Some('@') => {
+                    self.bump();
+                    TokenKind::ContainedBy
+                }
+                Some('-') if self.peek_nth(1) == Some('>') => {
+                    self.bump();
+                    self.bump();
+                    TokenKind::Distance
+                }
+                _ => TokenKind::Lt,
+            },
+            '>' => match self.peek() {
+                Some('=') => {
+                    self.bump();
+                    TokenKind::GtEq
+                }
+                _ => TokenKind::Gt,
+            },
+            '?' => {
+                self.params += 1;
+                TokenKind::Parameter(self.params)
+            }
+            '$' if matches!(self.peek(), Some('1'..='9')) => {
+                let mut n = 0usize;
+                while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+                    self.bump();
+                    n = n.saturating_mul(10).saturating_add(d as usize);
+                }
+                TokenKind::Parameter(n)
+            }
+            '\'' => return self.string(span),
+            '"' => return self.quoted_ident(span),
+            c if c.is_ascii_digit() || c == '.' => return Ok(self.number(span)),
+            c if c.is_alphabetic() || c == '_' => return Ok(self.word(span)),
+            c => {
+                let mut span = span;
+                span.end = self.pos;
+                return Err(ParseError::lexical(
+                    span,
+                    format!("unexpected character '{}'", c),
+                ));
+            }
+        };
+        Ok(self.finish(span, kind))
+    }
+
+    fn string(&mut self, span: Span) -> Result<Token, ParseError> {
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                // '' はエスケープされた '
+                Some('\'') if self.peek() == Some('\'') => {
+                    self.bump();
+                    value.push('\'');
+                }
+                Some('\'') => return Ok(self.finish(span, TokenKind::String(value))),
+                Some(c) => value.push(c),
+                None => {
+                    let mut span = span;
+                    span.end = self.pos;
+                    return Err(ParseError::lexical(span, "unterminated string literal"));
+                }
+            }
+        }
+    }
+
+    fn quoted_ident(&mut self, span: Span) -> Result<Token, ParseError> {
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('"') if self.peek() == Some('"') => {
+                    self.bump();
+                    value.push('"');
+                }
+                Some('"') => return Ok(self.finish(span, TokenKind::Ident(value))),
+                Some(c) => value.push(c),
+                None => {
+                    let mut span = span;
+                    span.end = self.pos;
+                    return Err(ParseError::lexical(span, "unterminated quoted identifier"));
+                }
+            }
+        }
+    }
+
+    fn number(&mut self, span: Span) -> Token {
+        let mut seen_dot = self.src[span.start..self.pos].ends_with('.');
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() {
+                self.bump();
+            } else if c == '.' && !seen_dot {
+                seen_dot = true;
+                self.bump();
+            } else if (c == 'e' || c == 'E')
+                && matches!(self.peek_nth(1), Some('0'..='9' | '+' | '-'))
+            {
+                self.bump();
+                if matches!(self.peek(), Some('+' | '-')) {
+                    self.bump();
+                }
+                seen_dot = true;
+            } else {
+                break;
+            }
+        }
+        let text = self.src[span.start..self.pos].to_string();
+        self.finish(span, TokenKind::Number(text))
+    }
+
+    fn word(&mut self, span: Span) -> Token {
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        let word = &self.src[span.start..self.pos];
+        let kind = match Keyword::lookup(word) {
+            Some(kw) => TokenKind::Keyword(kw),
+            None => TokenKind::Ident(word.to_lowercase()),
+        };
+        self.finish(span, kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        Lexer::new(src)
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            kinds("select a, 'it''s' from \"T\" -- comment\n where x <> 1.5"),
+            vec![
+                TokenKind::Keyword(Keyword::SELECT),
+                TokenKind::Ident("a".to_string()),
+                TokenKind::Comma,
+                TokenKind::String("it's".to_string()),
+                TokenKind::Keyword(Keyword::FROM),
+                TokenKind::Ident("T".to_string()),
+                TokenKind::Keyword(Keyword::WHERE),
+                TokenKind::Ident("x".to_string()),
+                TokenKind::NotEq,
+                TokenKind::Number("1.5".to_string()),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_span() {
+        let tokens = Lexer::new("select\n  foo").tokenize().unwrap();
+        assert_eq!(
+            tokens[1].span,
+            Span {
+                start: 9,
+                end: 12,
+                line: 2,
+                column: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let err = Lexer::new("select 'abc").tokenize().unwrap_err();
+        assert_eq!(err.span.line, 1);
+        assert_eq!(err.span.column, 8);
+    }
+}
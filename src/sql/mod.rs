@@ -0,0 +1,17 @@
+pub mod ast;
+pub mod error;
+pub mod lexer;
+pub mod parser;
+
+pub use error::ParseError;
+pub use lexer::Span;
+
+use ast::Statement;
+use parser::Parser;
+
+use crate::trace;
+
+pub fn parse(sql: &str) -> Result<Vec<Statement>, ParseError> {
+    let _span = trace::span("parse", &[("sql", &sql)]);
+    Parser::new(sql)?.parse_statements()
+}
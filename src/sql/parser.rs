@@ -0,0 +1,3462 @@
+use crate::collation::Collation;
+use crate::datetime::{Date, Interval, Time, Timestamp};
+use crate::decimal::Decimal;
+use crate::geometry::{Point, Rect};
+use crate::types::DataType;
+use crate::uuid::Uuid;
+
+use super::ast::*;
+use super::error::ParseError;
+use super::lexer::{Keyword, Lexer, Span, Token, TokenKind};
+
+// CUBE は要素が 1 つ増えるたびに集合の数が倍になるので、要素の数を抑える
+const MAX_CUBE_ELEMENTS: usize = 12;
+
+// GROUP BY の集合の並び
+type GroupingSets = Vec<Vec<Expr>>;
+
+type ParseStatement = fn(&mut Parser) -> Result<Statement, ParseError>;
+
+// 文の始まりの語と、その文を読む関数。予約語でない語は識別子として読んだものと比べる。
+// どれにも合わなければ、ここにある語をすべて期待したものとして挙げる
+const STATEMENTS: &[(&str, ParseStatement)] = &[
+    ("SELECT", Parser::parse_query_statement),
+    ("WITH", Parser::parse_query_statement),
+    ("INSERT", Parser::parse_insert),
+    ("UPDATE", Parser::parse_update),
+    ("DELETE", Parser::parse_delete),
+    ("CREATE", Parser::parse_create),
+    ("DROP", Parser::parse_drop),
+    ("EXPLAIN", Parser::parse_explain),
+    ("ANALYZE", Parser::parse_analyze),
+    ("VACUUM", Parser::parse_vacuum),
+    ("COPY", Parser::parse_copy),
+    ("BEGIN", Parser::parse_transaction),
+    ("START", Parser::parse_transaction),
+    ("COMMIT", Parser::parse_transaction),
+    ("ROLLBACK", Parser::parse_transaction),
+    ("SAVEPOINT", Parser::parse_transaction),
+    ("RELEASE", Parser::parse_transaction),
+    ("SET", Parser::parse_transaction),
+    ("PREPARE", Parser::parse_transaction),
+    ("SHOW", Parser::parse_show),
+    ("RESET", Parser::parse_reset),
+    ("ALTER", Parser::parse_alter),
+    ("ATTACH", Parser::parse_attach),
+    ("DETACH", Parser::parse_detach),
+    ("GRANT", Parser::parse_grant_statement),
+    ("REVOKE", Parser::parse_revoke),
+    ("REFRESH", Parser::parse_refresh),
+];
+
+pub struct Parser {
+    // 解析している文字列。元の文字列のまま覚える部分を切り出すのに使う
+    sql: String,
+    tokens: Vec<Token>,
+    pos: usize,
+    // 現在位置で試して失敗したトークンの一覧。エラーメッセージの expected に使う
+    expected: Vec<String>,
+}
+
+impl Parser {
+    pub fn new(sql: &str) -> Result<Self, ParseError> {
+        let tokens = Lexer::new(sql).tokenize()?;
+        Ok(Self {
+            sql: sql.to_string(),
+            tokens,
+            pos: 0,
+            expected: Vec::new(),
+        })
+    }
+
+    pub fn parse_statements(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let mut statements = Vec::new();
+        loop {
+            while self.eat(&TokenKind::Semicolon) {}
+            if self.at_eof() {
+                return Ok(statements);
+            }
+            statements.push(self.parse_statement()?);
+            if !self.at_eof() {
+                self.expect(&TokenKind::Semicolon)?;
+            }
+        }
+    }
+
+    pub fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        if *self.peek_kind() == TokenKind::LParen {
+            return Ok(Statement::Query(Box::new(self.parse_query()?)));
+        }
+        let parse = STATEMENTS.iter().find(|(word, _)| match self.peek_kind() {
+            TokenKind::Keyword(kw) => kw.as_str() == *word,
+            TokenKind::Ident(name) => *name == word.to_ascii_lowercase(),
+            _ => false,
+        });
+        match parse {
+            Some((_, parse)) => parse(self),
+            None => {
+                for (word, _) in STATEMENTS {
+                    self.expected.push(word.to_string());
+                }
+                Err(self.error())
+            }
+        }
+    }
+
+    fn parse_query_statement(&mut self) -> Result<Statement, ParseError> {
+        Ok(Statement::Query(Box::new(self.parse_query()?)))
+    }
+
+    fn parse_show(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("show")?;
+        Ok(Statement::Show(self.parse_parameter_name()?))
+    }
+
+    fn parse_reset(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("reset")?;
+        Ok(Statement::Reset(self.parse_parameter_name()?))
+    }
+
+    fn parse_alter(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("alter")?;
+        if self.eat_keyword(Keyword::TABLE) {
+            return self.parse_alter_table();
+        }
+        if self.eat_word("type") {
+            return self.parse_alter_type();
+        }
+        self.expected.push("TABLE".to_string());
+        self.expected.push("TYPE".to_string());
+        self.expect_word("role")?;
+        let name = self.expect_ident()?;
+        let options = self.parse_role_options(RoleOptions::default())?;
+        Ok(Statement::AlterRole { name, options })
+    }
+
+    fn parse_attach(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("attach")?;
+        self.eat_word("database");
+        let path = self.expect_string()?;
+        self.expect_keyword(Keyword::AS)?;
+        let alias = self.expect_ident()?;
+        let read_only = *self.peek_kind() == TokenKind::LParen;
+        if read_only {
+            self.parenthesized(|p| p.expect_word("read_only"))?;
+        }
+        Ok(Statement::Attach {
+            path,
+            alias,
+            read_only,
+        })
+    }
+
+    fn parse_detach(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("detach")?;
+        self.eat_word("database");
+        Ok(Statement::Detach(self.expect_ident()?))
+    }
+
+    fn parse_grant_statement(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("grant")?;
+        Ok(Statement::Grant(self.parse_grant(Keyword::TO)?))
+    }
+
+    fn parse_revoke(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("revoke")?;
+        Ok(Statement::Revoke(self.parse_grant(Keyword::FROM)?))
+    }
+
+    fn parse_refresh(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("refresh")?;
+        self.expect_word("materialized")?;
+        self.expect_word("view")?;
+        Ok(Statement::RefreshMaterializedView(self.expect_ident()?))
+    }
+
+    // SET の後。SET TRANSACTION は parse_transaction で読む
+    fn parse_set(&mut self) -> Result<Statement, ParseError> {
+        let local = self.eat_word("local");
+        if !local {
+            self.eat_word("session");
+        }
+        let name = self.expect_ident()?;
+        if !self.eat_keyword(Keyword::TO) {
+            self.expect(&TokenKind::Eq)?;
+        }
+        let negative = self.eat(&TokenKind::Minus);
+        let value = match self.peek_kind().clone() {
+            TokenKind::Number(n) if negative => Some(format!("-{}", n)),
+            TokenKind::Ident(word) if !negative && word == "default" => None,
+            TokenKind::Number(n) | TokenKind::String(n) | TokenKind::Ident(n) if !negative => {
+                Some(n)
+            }
+            TokenKind::Keyword(kw) if !negative => Some(kw.as_str().to_lowercase()),
+            _ => {
+                self.expected.push("value".to_string());
+                return Err(self.error());
+            }
+        };
+        self.advance();
+        Ok(Statement::Set { name, value, local })
+    }
+
+    // 名前か ALL
+    fn parse_parameter_name(&mut self) -> Result<Option<String>, ParseError> {
+        if self.eat_keyword(Keyword::ALL) {
+            return Ok(None);
+        }
+        Ok(Some(self.expect_ident()?))
+    }
+
+    fn parse_explain(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::EXPLAIN)?;
+        let mut analyze = false;
+        let mut format = ExplainFormat::Text;
+        // 括弧で始まる問い合わせと区別するため、括弧の次が選択肢の名前のときだけ選択肢として読む
+        let options = *self.peek_kind() == TokenKind::LParen
+            && (matches!(self.peek_nth_kind(1), TokenKind::Keyword(Keyword::ANALYZE))
+                || matches!(self.peek_nth_kind(1), TokenKind::Ident(name) if name == "format"));
+        if options {
+            self.advance();
+            loop {
+                if self.eat_keyword(Keyword::ANALYZE) {
+                    analyze = !self.eat_keyword(Keyword::FALSE);
+                    if analyze {
+                        self.eat_keyword(Keyword::TRUE);
+                    }
+                } else {
+                    let span = self.current().span;
+                    if !self.eat_word("format") {
+                        return Err(ParseError::new(span, "EXPLAIN option not recognized"));
+                    }
+                    let span = self.current().span;
+                    format = match self.expect_ident()?.as_str() {
+                        "text" => ExplainFormat::Text,
+                        "json" => ExplainFormat::Json,
+                        "dot" => ExplainFormat::Dot,
+                        format => {
+                            return Err(ParseError::new(
+                                span,
+                                format!("EXPLAIN format \"{}\" not recognized", format),
+                            ))
+                        }
+                    };
+                }
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::RParen)?;
+        } else {
+            analyze = self.eat_keyword(Keyword::ANALYZE);
+        }
+        let span = self.current().span;
+        let statement = self.parse_statement()?;
+        if matches!(statement, Statement::Explain { .. }) {
+            return Err(ParseError::new(span, "EXPLAIN cannot be nested"));
+        }
+        Ok(Statement::Explain {
+            analyze,
+            format,
+            statement: Box::new(statement),
+        })
+    }
+
+    fn parse_analyze(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::ANALYZE)?;
+        let table = match self.peek_kind() {
+            TokenKind::Ident(_) => Some(self.expect_ident()?),
+            _ => None,
+        };
+        Ok(Statement::Analyze { table })
+    }
+
+    fn parse_vacuum(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::VACUUM)?;
+        let table = match self.peek_kind() {
+            TokenKind::Ident(_) => Some(self.expect_ident()?),
+            _ => None,
+        };
+        Ok(Statement::Vacuum { table })
+    }
+
+    fn parse_copy(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::COPY)?;
+        let span = self.current().span;
+        let relation = if *self.peek_kind() == TokenKind::LParen {
+            CopyRelation::Query(Box::new(self.parenthesized(Self::parse_query)?))
+        } else {
+            let name = self.expect_ident()?;
+            let columns = if *self.peek_kind() == TokenKind::LParen {
+                self.parenthesized(|p| p.comma_separated(Self::expect_ident))?
+            } else {
+                Vec::new()
+            };
+            CopyRelation::Table { name, columns }
+        };
+        let direction = if self.eat_keyword(Keyword::TO) {
+            CopyDirection::To
+        } else {
+            self.expected.push(Keyword::TO.to_string());
+            self.expect_keyword(Keyword::FROM)?;
+            CopyDirection::From
+        };
+        let file = self.expect_string()?;
+        let mut options = CopyOptions::default();
+        self.eat_keyword(Keyword::WITH);
+        if *self.peek_kind() == TokenKind::LParen {
+            self.parenthesized(|p| p.comma_separated(|p| p.parse_copy_option(&mut options)))?;
+        }
+        if direction == CopyDirection::From {
+            if matches!(relation, CopyRelation::Query(_)) {
+                return Err(ParseError::new(span, "COPY FROM cannot read into a query"));
+            }
+            let format = match options.format {
+                CopyFormat::Json => Some("json"),
+                CopyFormat::Parquet => Some("parquet"),
+                _ => None,
+            };
+            if let Some(format) = format {
+                return Err(ParseError::new(
+                    span,
+                    format!("COPY FROM does not support the {} format", format),
+                ));
+            }
+        }
+        Ok(Statement::Copy(Copy {
+            relation,
+            direction,
+            file,
+            options,
+        }))
+    }
+
+    // FORMAT csv|text|json|parquet、HEADER [bool]、DELIMITER 'c'、NULL 'str'、QUOTE 'c'
+    fn parse_copy_option(&mut self, options: &mut CopyOptions) -> Result<(), ParseError> {
+        let span = self.current().span;
+        if self.eat_keyword(Keyword::NULL) {
+            options.null = Some(self.expect_string()?);
+            return Ok(());
+        }
+        let name = self.expect_ident()?;
+        let char_value = |p: &mut Self| -> Result<char, ParseError> {
+            let span = p.current().span;
+            let value = p.expect_string()?;
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c != '\n' && c != '\r' => Ok(c),
+                _ => Err(ParseError::new(
+                    span,
+                    format!("COPY {} must be a single character", name),
+                )),
+            }
+        };
+        match name.as_str() {
+            "format" => {
+                let span = self.current().span;
+                options.format = match self.expect_ident()?.as_str() {
+                    "csv" => CopyFormat::Csv,
+                    "text" => CopyFormat::Text,
+                    "json" => CopyFormat::Json,
+                    "parquet" => CopyFormat::Parquet,
+                    format => {
+                        return Err(ParseError::new(
+                            span,
+                            format!("COPY format \"{}\" not recognized", format),
+                        ))
+                    }
+                };
+            }
+            "header" => {
+                options.header = if self.eat_keyword(Keyword::FALSE) {
+                    false
+                } else {
+                    self.eat_keyword(Keyword::TRUE);
+                    true
+                };
+            }
+            "bulk" => {
+                options.bulk = if self.eat_keyword(Keyword::FALSE) {
+                    false
+                } else {
+                    self.eat_keyword(Keyword::TRUE);
+                    true
+                };
+            }
+            "delimiter" => options.delimiter = Some(char_value(self)?),
+            "quote" => options.quote = Some(char_value(self)?),
+            _ => {
+                return Err(ParseError::new(
+                    span,
+                    format!("option \"{}\" not recognized", name),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_transaction(&mut self) -> Result<Statement, ParseError> {
+        let stmt = if self.eat_keyword(Keyword::BEGIN) {
+            if !self.eat_keyword(Keyword::TRANSACTION) {
+                self.eat_keyword(Keyword::WORK);
+            }
+            TransactionStatement::Begin
+        } else if self.eat_keyword(Keyword::START) {
+            self.expect_keyword(Keyword::TRANSACTION)?;
+            TransactionStatement::Begin
+        } else if self.eat_keyword(Keyword::COMMIT) {
+            if self.eat_keyword(Keyword::PREPARED) {
+                TransactionStatement::CommitPrepared(self.expect_string()?)
+            } else {
+                if !self.eat_keyword(Keyword::TRANSACTION) {
+                    self.eat_keyword(Keyword::WORK);
+                }
+                TransactionStatement::Commit
+            }
+        } else if self.eat_keyword(Keyword::ROLLBACK) {
+            if self.eat_keyword(Keyword::PREPARED) {
+                TransactionStatement::RollbackPrepared(self.expect_string()?)
+            } else {
+                if !self.eat_keyword(Keyword::TRANSACTION) {
+                    self.eat_keyword(Keyword::WORK);
+                }
+                if self.eat_keyword(Keyword::TO) {
+                    self.eat_keyword(Keyword::SAVEPOINT);
+                    TransactionStatement::RollbackTo(self.expect_ident()?)
+                } else {
+                    TransactionStatement::Rollback
+                }
+            }
+        } else if self.eat_keyword(Keyword::SAVEPOINT) {
+            TransactionStatement::Savepoint(self.expect_ident()?)
+        } else if self.eat_keyword(Keyword::PREPARE) {
+            self.expect_keyword(Keyword::TRANSACTION)?;
+            TransactionStatement::Prepare(self.expect_string()?)
+        } else if self.eat_keyword(Keyword::SET) {
+            if !self.eat_keyword(Keyword::TRANSACTION) {
+                return self.parse_set();
+            }
+            self.expect_keyword(Keyword::ISOLATION)?;
+            self.expect_keyword(Keyword::LEVEL)?;
+            let level = if self.eat_keyword(Keyword::READ) {
+                self.expect_keyword(Keyword::COMMITTED)?;
+                IsolationLevel::ReadCommitted
+            } else if self.eat_keyword(Keyword::SERIALIZABLE) {
+                IsolationLevel::Serializable
+            } else {
+                self.expect_keyword(Keyword::REPEATABLE)?;
+                self.expect_keyword(Keyword::READ)?;
+                IsolationLevel::RepeatableRead
+            };
+            TransactionStatement::SetIsolationLevel(level)
+        } else {
+            self.expect_keyword(Keyword::RELEASE)?;
+            self.eat_keyword(Keyword::SAVEPOINT);
+            TransactionStatement::Release(self.expect_ident()?)
+        };
+        Ok(Statement::Transaction(stmt))
+    }
+
+    // ---- トークン操作 ----
+
+    fn current(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_kind(&self) -> &TokenKind {
+        &self.current().kind
+    }
+
+    fn peek_nth_kind(&self, n: usize) -> &TokenKind {
+        let idx = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[idx].kind
+    }
+
+    fn peek_keyword(&self) -> Option<Keyword> {
+        match self.peek_kind() {
+            TokenKind::Keyword(kw) => Some(*kw),
+            _ => None,
+        }
+    }
+
+    fn at_query_start(&self) -> bool {
+        matches!(self.peek_keyword(), Some(Keyword::SELECT | Keyword::WITH))
+    }
+
+    fn at_eof(&self) -> bool {
+        *self.peek_kind() == TokenKind::Eof
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.current().clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        self.expected.clear();
+        token
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek_kind() == kind {
+            self.advance();
+            true
+        } else {
+            self.expected.push(kind.to_string());
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, kw: Keyword) -> bool {
+        self.eat(&TokenKind::Keyword(kw))
+    }
+
+    // 予約語ではない語。識別子として小文字で読んだものと比べる。
+    // 識別子が来てもよいところで使うので、なかったときに期待したものとして挙げない
+    fn eat_word(&mut self, word: &str) -> bool {
+        if matches!(self.peek_kind(), TokenKind::Ident(name) if name == word) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        if self.eat_word(word) {
+            Ok(())
+        } else {
+            self.expected.push(word.to_uppercase());
+            Err(self.error())
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<Span, ParseError> {
+        let span = self.current().span;
+        if self.eat(kind) {
+            Ok(span)
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn expect_keyword(&mut self, kw: Keyword) -> Result<Span, ParseError> {
+        self.expect(&TokenKind::Keyword(kw))
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        if let TokenKind::Ident(name) = self.peek_kind() {
+            let name = name.clone();
+            self.advance();
+            Ok(name)
+        } else {
+            self.expected.push("identifier".to_string());
+            Err(self.error())
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, ParseError> {
+        if let TokenKind::String(value) = self.peek_kind() {
+            let value = value.clone();
+            self.advance();
+            Ok(value)
+        } else {
+            self.expected.push("string".to_string());
+            Err(self.error())
+        }
+    }
+
+    fn error(&mut self) -> ParseError {
+        let span = self.current().span;
+        let found = self.current().kind.to_string();
+        let mut expected = Vec::new();
+        for e in self.expected.drain(..) {
+            if !expected.contains(&e) {
+                expected.push(e);
+            }
+        }
+        ParseError::unexpected(span, found, expected)
+    }
+
+    fn comma_separated<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut items = vec![f(self)?];
+        while self.eat(&TokenKind::Comma) {
+            items.push(f(self)?);
+        }
+        Ok(items)
+    }
+
+    fn parenthesized<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        self.expect(&TokenKind::LParen)?;
+        let value = f(self)?;
+        self.expect(&TokenKind::RParen)?;
+        Ok(value)
+    }
+
+    // ---- SELECT ----
+
+    pub fn parse_query(&mut self) -> Result<Query, ParseError> {
+        let with = if self.eat_keyword(Keyword::WITH) {
+            let recursive = self.eat_word("recursive");
+            self.comma_separated(|p| p.parse_cte(recursive))?
+        } else {
+            Vec::new()
+        };
+        let body = self.parse_set_expr()?;
+        let mut order_by = Vec::new();
+        if self.eat_keyword(Keyword::ORDER) {
+            self.expect_keyword(Keyword::BY)?;
+            order_by = self.comma_separated(Self::parse_order_by_expr)?;
+        }
+        let mut limit = None;
+        let mut offset = None;
+        if self.eat_keyword(Keyword::LIMIT) {
+            limit = Some(self.parse_expr()?);
+        }
+        if self.eat_keyword(Keyword::OFFSET) {
+            offset = Some(self.parse_expr()?);
+        }
+        let locking = if self.eat_keyword(Keyword::FOR) {
+            Some(self.parse_locking_clause()?)
+        } else {
+            None
+        };
+        Ok(Query {
+            with,
+            body,
+            order_by,
+            limit,
+            offset,
+            locking,
+        })
+    }
+
+    // FOR の後ろ
+    fn parse_locking_clause(&mut self) -> Result<LockingClause, ParseError> {
+        let strength = if self.eat_keyword(Keyword::UPDATE) {
+            LockStrength::Update
+        } else {
+            self.expect_keyword(Keyword::SHARE)?;
+            LockStrength::Share
+        };
+        let wait = if self.eat_keyword(Keyword::NOWAIT) {
+            WaitPolicy::NoWait
+        } else if self.eat_keyword(Keyword::SKIP) {
+            self.expect_keyword(Keyword::LOCKED)?;
+            WaitPolicy::SkipLocked
+        } else {
+            WaitPolicy::Block
+        };
+        Ok(LockingClause { strength, wait })
+    }
+
+    fn parse_cte(&mut self, recursive: bool) -> Result<Cte, ParseError> {
+        let name = self.expect_ident()?;
+        let columns = if *self.peek_kind() == TokenKind::LParen {
+            self.parenthesized(|p| p.comma_separated(Self::expect_ident))?
+        } else {
+            Vec::new()
+        };
+        self.expect_keyword(Keyword::AS)?;
+        let query = self.parenthesized(Self::parse_query)?;
+        Ok(Cte {
+            name,
+            columns,
+            query: Box::new(query),
+            recursive,
+        })
+    }
+
+    fn parse_order_by_expr(&mut self) -> Result<OrderByExpr, ParseError> {
+        let expr = self.parse_expr()?;
+        let asc = if self.eat_keyword(Keyword::DESC) {
+            false
+        } else {
+            self.eat_keyword(Keyword::ASC);
+            true
+        };
+        let nulls_first = if !self.eat_word("nulls") {
+            None
+        } else if self.eat_word("first") {
+            Some(true)
+        } else {
+            self.expect_word("last")?;
+            Some(false)
+        };
+        Ok(OrderByExpr {
+            expr,
+            asc,
+            nulls_first,
+        })
+    }
+
+    // UNION / EXCEPT は INTERSECT より結合が弱い
+    fn parse_set_expr(&mut self) -> Result<SetExpr, ParseError> {
+        let mut left = self.parse_set_term()?;
+        loop {
+            let span = self.current().span;
+            let op = if self.eat_keyword(Keyword::UNION) {
+                SetOperator::Union
+            } else if self.eat_keyword(Keyword::EXCEPT) {
+                SetOperator::Except
+            } else {
+                return Ok(left);
+            };
+            let all = self.parse_set_quantifier();
+            let right = self.parse_set_term()?;
+            left = set_operation(op, all, left, right, span)?;
+        }
+    }
+
+    fn parse_set_term(&mut self) -> Result<SetExpr, ParseError> {
+        let mut left = self.parse_set_primary()?;
+        loop {
+            let span = self.current().span;
+            if !self.eat_keyword(Keyword::INTERSECT) {
+                return Ok(left);
+            }
+            let all = self.parse_set_quantifier();
+            let right = self.parse_set_primary()?;
+            left = set_operation(SetOperator::Intersect, all, left, right, span)?;
+        }
+    }
+
+    fn parse_set_quantifier(&mut self) -> bool {
+        if self.eat_keyword(Keyword::ALL) {
+            true
+        } else {
+            self.eat_keyword(Keyword::DISTINCT);
+            false
+        }
+    }
+
+    fn parse_set_primary(&mut self) -> Result<SetExpr, ParseError> {
+        if self.eat(&TokenKind::LParen) {
+            let query = self.parse_query()?;
+            self.expect(&TokenKind::RParen)?;
+            return Ok(SetExpr::Query(Box::new(query)));
+        }
+        Ok(SetExpr::Select(Box::new(self.parse_select()?)))
+    }
+
+    fn parse_select(&mut self) -> Result<Select, ParseError> {
+        self.expect_keyword(Keyword::SELECT)?;
+        let hints = self.parse_hints()?;
+        let distinct = if self.eat_keyword(Keyword::DISTINCT) {
+            true
+        } else {
+            self.eat_keyword(Keyword::ALL);
+            false
+        };
+        let projection = self.comma_separated(Self::parse_select_item)?;
+        let from = if self.eat_keyword(Keyword::FROM) {
+            Some(self.parse_from()?)
+        } else {
+            None
+        };
+        let selection = if self.eat_keyword(Keyword::WHERE) {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        let mut group_by = Vec::new();
+        let mut grouping_sets = None;
+        if self.eat_keyword(Keyword::GROUP) {
+            self.expect_keyword(Keyword::BY)?;
+            (group_by, grouping_sets) = self.parse_group_by()?;
+        }
+        let having = if self.eat_keyword(Keyword::HAVING) {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        Ok(Select {
+            distinct,
+            projection,
+            from,
+            selection,
+            group_by,
+            grouping_sets,
+            having,
+            hints,
+        })
+    }
+
+    // 前のトークンと今のトークンの間にある /*+ ... */ のヒント。コメントはトークンにならないので、元の文字列から探す
+    fn parse_hints(&self) -> Result<Vec<Hint>, ParseError> {
+        let span = self.current().span;
+        let mut gap = &self.sql[self.tokens[self.pos - 1].span.end..span.start];
+        let mut hints = vec![];
+        while !gap.is_empty() {
+            if let Some(rest) = gap.strip_prefix("--") {
+                gap = rest.split_once('\n').map_or("", |(_, rest)| rest);
+            } else if let Some(rest) = gap.strip_prefix("/*") {
+                let (comment, rest) = rest.split_once("*/").unwrap_or((rest, ""));
+                if let Some(body) = comment.strip_prefix('+') {
+                    hints.extend(
+                        parse_hint_body(body).map_err(|message| ParseError::new(span, message))?,
+                    );
+                }
+                gap = rest;
+            } else {
+                gap = &gap[gap.chars().next().unwrap().len_utf8()..];
+            }
+        }
+        Ok(hints)
+    }
+
+    // GROUP BY の項目。GROUPING SETS、ROLLUP、CUBE があれば、項目ごとの集合の直積を集合の並びにして返す
+    fn parse_group_by(&mut self) -> Result<(Vec<Expr>, Option<GroupingSets>), ParseError> {
+        let items = self.comma_separated(Self::parse_grouping_item)?;
+        if items.iter().all(|item| item.is_err()) {
+            let exprs = items.into_iter().map(|item| item.unwrap_err()).collect();
+            return Ok((exprs, None));
+        }
+        let mut sets = vec![vec![]];
+        for item in items {
+            sets = match item {
+                Err(expr) => sets
+                    .into_iter()
+                    .map(|mut set| {
+                        set.push(expr.clone());
+                        set
+                    })
+                    .collect(),
+                Ok(item) => sets
+                    .iter()
+                    .flat_map(|set| item.iter().map(move |other| [&set[..], other].concat()))
+                    .collect(),
+            };
+        }
+        let mut exprs = vec![];
+        for expr in sets.iter().flatten() {
+            if !exprs.contains(expr) {
+                exprs.push(expr.clone());
+            }
+        }
+        Ok((exprs, Some(sets)))
+    }
+
+    // ふつうの式なら Err、集合を作る項目なら Ok でその集合の並び
+    fn parse_grouping_item(&mut self) -> Result<Result<GroupingSets, Expr>, ParseError> {
+        if self.peek_kind() == &TokenKind::LParen && self.peek_nth_kind(1) == &TokenKind::RParen {
+            self.advance();
+            self.advance();
+            return Ok(Ok(vec![vec![]]));
+        }
+        let word = match self.peek_kind() {
+            TokenKind::Ident(word) => word.clone(),
+            _ => return Ok(Err(self.parse_expr()?)),
+        };
+        match word.as_str() {
+            "grouping" if matches!(self.peek_nth_kind(1), TokenKind::Ident(w) if w == "sets") => {
+                self.advance();
+                self.advance();
+                let items = self.parenthesized(|p| p.comma_separated(Self::parse_grouping_set))?;
+                Ok(Ok(items.into_iter().flatten().collect()))
+            }
+            "rollup" | "cube" if self.peek_nth_kind(1) == &TokenKind::LParen => {
+                let span = self.advance().span;
+                let elements =
+                    self.parenthesized(|p| p.comma_separated(Self::parse_grouping_element))?;
+                if word == "rollup" {
+                    // (a, b, c) なら (a, b, c)、(a, b)、(a)、()
+                    return Ok(Ok((0..=elements.len())
+                        .rev()
+                        .map(|n| elements[..n].concat())
+                        .collect()));
+                }
+                if elements.len() > MAX_CUBE_ELEMENTS {
+                    return Err(ParseError::new(
+                        span,
+                        format!("CUBE is limited to {} elements", MAX_CUBE_ELEMENTS),
+                    ));
+                }
+                // 要素のすべての部分集合を、大きいものから
+                let n = elements.len();
+                Ok(Ok((0..1usize << n)
+                    .rev()
+                    .map(|mask| {
+                        (0..n)
+                            .filter(|i| mask & (1 << (n - 1 - i)) != 0)
+                            .flat_map(|i| elements[i].clone())
+                            .collect()
+                    })
+                    .collect()))
+            }
+            _ => Ok(Err(self.parse_expr()?)),
+        }
+    }
+
+    // GROUPING SETS の中の 1 つ。( ... ) か式か、入れ子の ROLLUP などの集合の並び
+    fn parse_grouping_set(&mut self) -> Result<GroupingSets, ParseError> {
+        if self.peek_kind() == &TokenKind::LParen {
+            return Ok(vec![self.parse_grouping_element()?]);
+        }
+        Ok(match self.parse_grouping_item()? {
+            Ok(sets) => sets,
+            Err(expr) => vec![vec![expr]],
+        })
+    }
+
+    // ROLLUP や CUBE の要素。(a, b) はまとめて 1 つの要素にする
+    fn parse_grouping_element(&mut self) -> Result<Vec<Expr>, ParseError> {
+        if self.eat(&TokenKind::LParen) {
+            if self.eat(&TokenKind::RParen) {
+                return Ok(vec![]);
+            }
+            let exprs = self.comma_separated(Self::parse_expr)?;
+            self.expect(&TokenKind::RParen)?;
+            return Ok(exprs);
+        }
+        Ok(vec![self.parse_expr()?])
+    }
+
+    fn parse_select_item(&mut self) -> Result<SelectItem, ParseError> {
+        if self.eat(&TokenKind::Star) {
+            return Ok(SelectItem::Wildcard);
+        }
+        if let (TokenKind::Ident(table), TokenKind::Dot, TokenKind::Star) = (
+            self.peek_kind(),
+            self.peek_nth_kind(1),
+            self.peek_nth_kind(2),
+        ) {
+            let table = table.clone();
+            self.advance();
+            self.advance();
+            self.advance();
+            return Ok(SelectItem::QualifiedWildcard(table));
+        }
+        let expr = self.parse_expr()?;
+        let alias = self.parse_alias()?;
+        Ok(SelectItem::Expr { expr, alias })
+    }
+
+    fn parse_alias(&mut self) -> Result<Option<String>, ParseError> {
+        if self.eat_keyword(Keyword::AS) {
+            return Ok(Some(self.expect_ident()?));
+        }
+        if let TokenKind::Ident(name) = self.peek_kind() {
+            let name = name.clone();
+            self.advance();
+            return Ok(Some(name));
+        }
+        Ok(None)
+    }
+
+    fn parse_from(&mut self) -> Result<TableRef, ParseError> {
+        let mut left = self.parse_table_factor()?;
+        loop {
+            if self.eat(&TokenKind::Comma) {
+                let right = self.parse_table_factor()?;
+                left = TableRef::Join {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    kind: JoinKind::Cross,
+                    on: None,
+                };
+                continue;
+            }
+            let kind = if self.eat_keyword(Keyword::JOIN) {
+                JoinKind::Inner
+            } else if self.eat_keyword(Keyword::INNER) {
+                self.expect_keyword(Keyword::JOIN)?;
+                JoinKind::Inner
+            } else if self.eat_keyword(Keyword::CROSS) {
+                self.expect_keyword(Keyword::JOIN)?;
+                JoinKind::Cross
+            } else if let Some(kind) = self.parse_outer_join_kind() {
+                self.eat_keyword(Keyword::OUTER);
+                self.expect_keyword(Keyword::JOIN)?;
+                kind
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_table_factor()?;
+            let on = if kind != JoinKind::Cross {
+                self.expect_keyword(Keyword::ON)?;
+                Some(self.parse_expr()?)
+            } else {
+                None
+            };
+            left = TableRef::Join {
+                left: Box::new(left),
+                right: Box::new(right),
+                kind,
+                on,
+            };
+        }
+    }
+
+    fn parse_outer_join_kind(&mut self) -> Option<JoinKind> {
+        if self.eat_keyword(Keyword::LEFT) {
+            Some(JoinKind::Left)
+        } else if self.eat_keyword(Keyword::RIGHT) {
+            Some(JoinKind::Right)
+        } else if self.eat_keyword(Keyword::FULL) {
+            Some(JoinKind::Full)
+        } else {
+            None
+        }
+    }
+
+    fn parse_table_factor(&mut self) -> Result<TableRef, ParseError> {
+        // lateral という名前のテーブルもあり得るので、括弧が続くときだけ LATERAL とみる
+        let lateral = matches!(self.peek_kind(), TokenKind::Ident(name) if name == "lateral")
+            && self.peek_nth_kind(1) == &TokenKind::LParen;
+        if lateral {
+            self.advance();
+        }
+        if self.eat(&TokenKind::LParen) {
+            let query = self.parse_query()?;
+            self.expect(&TokenKind::RParen)?;
+            self.eat_keyword(Keyword::AS);
+            let alias = self.expect_ident()?;
+            return Ok(TableRef::Subquery {
+                query: Box::new(query),
+                alias,
+                lateral,
+            });
+        }
+        let name = self.parse_table_name()?;
+        let as_of = self.parse_as_of()?;
+        let alias = if self.at_tablesample() {
+            None
+        } else {
+            self.parse_alias()?
+        };
+        let sample = if self.at_tablesample() {
+            self.advance();
+            Some(self.parse_table_sample()?)
+        } else {
+            None
+        };
+        Ok(TableRef::Table {
+            name,
+            alias,
+            sample,
+            as_of,
+        })
+    }
+
+    // 別名の AS と見分けるため、AS OF TIMESTAMP か AS OF LSN が続くときだけ読む。
+    // TIMESTAMP '...' は timestamp の定数として読む
+    fn parse_as_of(&mut self) -> Result<Option<Box<AsOf>>, ParseError> {
+        let at_of = self.peek_kind() == &TokenKind::Keyword(Keyword::AS)
+            && matches!(self.peek_nth_kind(1), TokenKind::Ident(word) if word == "of");
+        let lsn = match self.peek_nth_kind(2) {
+            TokenKind::Ident(word) if at_of && word == "timestamp" => false,
+            TokenKind::Ident(word) if at_of && word == "lsn" => true,
+            _ => return Ok(None),
+        };
+        self.advance();
+        self.advance();
+        if lsn {
+            self.advance();
+            return Ok(Some(Box::new(AsOf::Lsn(self.parse_expr()?))));
+        }
+        if !matches!(self.peek_nth_kind(1), TokenKind::String(_)) {
+            self.advance();
+        }
+        Ok(Some(Box::new(AsOf::Timestamp(self.parse_expr()?))))
+    }
+
+    // tablesample という別名もあり得るので、手法の名前が続くときだけ TABLESAMPLE とみる
+    fn at_tablesample(&self) -> bool {
+        matches!(self.peek_kind(), TokenKind::Ident(word) if word == "tablesample")
+            && matches!(self.peek_nth_kind(1), TokenKind::Ident(_))
+    }
+
+    fn parse_table_sample(&mut self) -> Result<TableSample, ParseError> {
+        let span = self.current().span;
+        let method = match self.expect_ident()?.as_str() {
+            "system" => SampleMethod::System,
+            "bernoulli" => SampleMethod::Bernoulli,
+            method => {
+                return Err(ParseError::new(
+                    span,
+                    format!("tablesample method \"{}\" does not exist", method),
+                ))
+            }
+        };
+        let percent = self.parenthesized(Self::parse_expr)?;
+        let seed = if self.eat_keyword(Keyword::REPEATABLE) {
+            Some(self.parenthesized(Self::parse_expr)?)
+        } else {
+            None
+        };
+        Ok(TableSample {
+            method,
+            percent,
+            seed,
+        })
+    }
+
+    // schema.name の形のテーブルの名前。スキーマは stats の表と、ATTACH でつないだデータベースと main
+    fn parse_table_name(&mut self) -> Result<String, ParseError> {
+        let name = self.expect_ident()?;
+        if self.eat(&TokenKind::Dot) {
+            return Ok(format!("{}.{}", name, self.expect_ident()?));
+        }
+        Ok(name)
+    }
+
+    // ---- 式 ----
+
+    pub fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword(Keyword::OR) {
+            let right = self.parse_and()?;
+            left = binary(BinaryOp::Or, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword(Keyword::AND) {
+            let right = self.parse_not()?;
+            left = binary(BinaryOp::And, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_keyword(Keyword::NOT) {
+            let expr = self.parse_not()?;
+            return Ok(Expr::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(expr),
+            });
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let left = self.parse_concat()?;
+        let op = match self.peek_kind() {
+            TokenKind::Eq => Some(BinaryOp::Eq),
+            TokenKind::NotEq => Some(BinaryOp::NotEq),
+            TokenKind::Lt => Some(BinaryOp::Lt),
+            TokenKind::LtEq => Some(BinaryOp::LtEq),
+            TokenKind::Gt => Some(BinaryOp::Gt),
+            TokenKind::GtEq => Some(BinaryOp::GtEq),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.advance();
+            if let Some(quantifier) = self.parse_quantifier() {
+                return self.parse_quantified(op, quantifier, left);
+            }
+            let right = self.parse_concat()?;
+            return Ok(binary(op, left, right));
+        }
+        if self.eat_keyword(Keyword::IS) {
+            let negated = self.eat_keyword(Keyword::NOT);
+            if self.eat_keyword(Keyword::DISTINCT) {
+                self.expect_keyword(Keyword::FROM)?;
+                let right = self.parse_concat()?;
+                let op = if negated {
+                    BinaryOp::IsNotDistinctFrom
+                } else {
+                    BinaryOp::IsDistinctFrom
+                };
+                return Ok(binary(op, left, right));
+            }
+            self.expect_keyword(Keyword::NULL)?;
+            return Ok(Expr::IsNull {
+                expr: Box::new(left),
+                negated,
+            });
+        }
+        let negated = self.peek_keyword() == Some(Keyword::NOT)
+            && matches!(
+                self.peek_nth_kind(1),
+                TokenKind::Keyword(
+                    Keyword::IN
+                        | Keyword::BETWEEN
+                        | Keyword::LIKE
+                        | Keyword::ILIKE
+                        | Keyword::REGEXP
+                )
+            );
+        if negated {
+            self.advance();
+        }
+        let like = match self.peek_keyword() {
+            Some(Keyword::LIKE) => Some("like"),
+            Some(Keyword::ILIKE) => Some("ilike"),
+            Some(Keyword::REGEXP) => Some("regexp_like"),
+            _ => None,
+        };
+        if let Some(name) = like {
+            self.advance();
+            let expr = self.parse_like(name, left)?;
+            return Ok(if negated {
+                Expr::Unary {
+                    op: UnaryOp::Not,
+                    expr: Box::new(expr),
+                }
+            } else {
+                expr
+            });
+        }
+        if self.eat_keyword(Keyword::IN) {
+            return self.parse_in(left, negated);
+        }
+        if self.eat_keyword(Keyword::BETWEEN) {
+            let low = self.parse_concat()?;
+            self.expect_keyword(Keyword::AND)?;
+            let high = self.parse_concat()?;
+            return Ok(Expr::Between {
+                expr: Box::new(left),
+                low: Box::new(low),
+                high: Box::new(high),
+                negated,
+            });
+        }
+        Ok(left)
+    }
+
+    // 比較の右辺の ANY (...)、SOME (...)、ALL (...)
+    fn parse_quantifier(&mut self) -> Option<&'static str> {
+        if *self.peek_nth_kind(1) != TokenKind::LParen {
+            return None;
+        }
+        let quantifier = match self.peek_kind() {
+            TokenKind::Ident(name) if name == "any" || name == "some" => "any",
+            TokenKind::Keyword(Keyword::ALL) => "all",
+            _ => return None,
+        };
+        self.advance();
+        Some(quantifier)
+    }
+
+    // a op ANY (arr) と a op ALL (arr) は演算子を文字列にして 3 引数の関数にする。
+    // a = ANY (問い合わせ) は a IN (問い合わせ) と同じ
+    fn parse_quantified(
+        &mut self,
+        op: BinaryOp,
+        quantifier: &str,
+        left: Expr,
+    ) -> Result<Expr, ParseError> {
+        self.expect(&TokenKind::LParen)?;
+        if self.at_query_start() {
+            if (op, quantifier) != (BinaryOp::Eq, "any") {
+                return Err(ParseError::new(
+                    self.current().span,
+                    "only = ANY is supported with a subquery".to_string(),
+                ));
+            }
+            let query = self.parse_query()?;
+            self.expect(&TokenKind::RParen)?;
+            return Ok(Expr::InSubquery {
+                expr: Box::new(left),
+                query: Box::new(query),
+                negated: false,
+            });
+        }
+        let array = self.parse_expr()?;
+        self.expect(&TokenKind::RParen)?;
+        let op = match op {
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "<>",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            _ => ">=",
+        };
+        Ok(Expr::Function {
+            name: quantifier.to_string(),
+            args: vec![Expr::Literal(Literal::String(op.to_string())), left, array],
+            distinct: false,
+        })
+    }
+
+    // a LIKE p [ESCAPE e]、a ILIKE p、a REGEXP p は関数の呼び出しにする
+    fn parse_like(&mut self, name: &str, left: Expr) -> Result<Expr, ParseError> {
+        let mut args = vec![left, self.parse_concat()?];
+        if name != "regexp_like" && matches!(self.peek_kind(), TokenKind::Ident(s) if s == "escape")
+        {
+            self.advance();
+            args.push(self.parse_concat()?);
+        }
+        Ok(Expr::Function {
+            name: name.to_string(),
+            args,
+            distinct: false,
+        })
+    }
+
+    fn parse_in(&mut self, left: Expr, negated: bool) -> Result<Expr, ParseError> {
+        self.expect(&TokenKind::LParen)?;
+        let expr = if self.at_query_start() {
+            Expr::InSubquery {
+                expr: Box::new(left),
+                query: Box::new(self.parse_query()?),
+                negated,
+            }
+        } else {
+            Expr::InList {
+                expr: Box::new(left),
+                list: self.comma_separated(Self::parse_expr)?,
+                negated,
+            }
+        };
+        self.expect(&TokenKind::RParen)?;
+        Ok(expr)
+    }
+
+    // || と JSON の -> と ->> は同じ強さで左から結び付く
+    fn parse_concat(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let op = match self.peek_kind() {
+                TokenKind::Concat => BinaryOp::Concat,
+                TokenKind::Arrow => BinaryOp::JsonGet,
+                TokenKind::LongArrow => BinaryOp::JsonGetText,
+                TokenKind::Overlap => BinaryOp::Overlaps,
+                TokenKind::Contains => BinaryOp::Contains,
+                TokenKind::ContainedBy => BinaryOp::ContainedBy,
+                TokenKind::Distance => BinaryOp::Distance,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_additive()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek_kind() {
+                TokenKind::Plus => BinaryOp::Plus,
+                TokenKind::Minus => BinaryOp::Minus,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_multiplicative()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.peek_kind() {
+                TokenKind::Star => BinaryOp::Multiply,
+                TokenKind::Slash => BinaryOp::Divide,
+                TokenKind::Percent => BinaryOp::Modulo,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_unary()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let op = match self.peek_kind() {
+            TokenKind::Minus => UnaryOp::Minus,
+            TokenKind::Plus => UnaryOp::Plus,
+            _ => return self.parse_subscript(),
+        };
+        self.advance();
+        // -9223372036854775808 は符号を付けてはじめて整数に収まる
+        if let (UnaryOp::Minus, TokenKind::Number(text)) = (op, self.peek_kind()) {
+            if let Ok(n) = format!("-{}", text).parse::<i64>() {
+                if n == i64::MIN && *self.peek_nth_kind(1) != TokenKind::LBracket {
+                    self.advance();
+                    return Ok(Expr::Literal(Literal::Integer(n)));
+                }
+            }
+        }
+        let expr = self.parse_unary()?;
+        // 負の数値リテラルはその場で畳む
+        if let (UnaryOp::Minus, Expr::Literal(Literal::Integer(n))) = (op, &expr) {
+            return Ok(Expr::Literal(Literal::Integer(-n)));
+        }
+        if let (UnaryOp::Minus, Expr::Literal(Literal::Float(n))) = (op, &expr) {
+            return Ok(Expr::Literal(Literal::Float(-n)));
+        }
+        if let (UnaryOp::Minus, Expr::Literal(Literal::Decimal(d))) = (op, &expr) {
+            if let Some(d) = d.checked_neg() {
+                return Ok(Expr::Literal(Literal::Decimal(d)));
+            }
+        }
+        Ok(Expr::Unary {
+            op,
+            expr: Box::new(expr),
+        })
+    }
+
+    // a[i] は 2 引数の関数 subscript にする
+    fn parse_subscript(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_primary()?;
+        while self.eat(&TokenKind::LBracket) {
+            let index = self.parse_expr()?;
+            self.expect(&TokenKind::RBracket)?;
+            expr = Expr::Function {
+                name: "subscript".to_string(),
+                args: vec![expr, index],
+                distinct: false,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let token = self.current().clone();
+        match token.kind {
+            TokenKind::Number(text) => {
+                self.advance();
+                parse_number(&text, token.span)
+            }
+            TokenKind::String(s) => {
+                self.advance();
+                Ok(Expr::Literal(Literal::String(s)))
+            }
+            TokenKind::Parameter(n) => {
+                self.advance();
+                Ok(Expr::Parameter(n))
+            }
+            TokenKind::Keyword(Keyword::TRUE) => {
+                self.advance();
+                Ok(Expr::Literal(Literal::Boolean(true)))
+            }
+            TokenKind::Keyword(Keyword::FALSE) => {
+                self.advance();
+                Ok(Expr::Literal(Literal::Boolean(false)))
+            }
+            TokenKind::Keyword(Keyword::NULL) => {
+                self.advance();
+                Ok(Expr::Literal(Literal::Null))
+            }
+            TokenKind::Keyword(Keyword::EXISTS) => {
+                self.advance();
+                let query = self.parenthesized(Self::parse_query)?;
+                Ok(Expr::Exists {
+                    query: Box::new(query),
+                    negated: false,
+                })
+            }
+            TokenKind::LParen => {
+                self.advance();
+                let expr = if self.at_query_start() {
+                    Expr::Subquery(Box::new(self.parse_query()?))
+                } else {
+                    self.parse_expr()?
+                };
+                self.expect(&TokenKind::RParen)?;
+                Ok(expr)
+            }
+            TokenKind::Ident(name) => {
+                self.advance();
+                if let TokenKind::String(s) = self.peek_kind() {
+                    if matches!(
+                        name.as_str(),
+                        "date" | "time" | "timestamp" | "uuid" | "interval" | "point" | "box"
+                    ) {
+                        let literal = parse_typed_literal(&name, s, self.current().span)?;
+                        self.advance();
+                        return Ok(Expr::Literal(literal));
+                    }
+                }
+                if *self.peek_kind() == TokenKind::LParen {
+                    return self.parse_function(name);
+                }
+                // ARRAY[a, b, ...] は関数 array の呼び出しにする
+                if name == "array" && self.eat(&TokenKind::LBracket) {
+                    let args = if *self.peek_kind() == TokenKind::RBracket {
+                        Vec::new()
+                    } else {
+                        self.comma_separated(Self::parse_expr)?
+                    };
+                    self.expect(&TokenKind::RBracket)?;
+                    return Ok(Expr::Function {
+                        name,
+                        args,
+                        distinct: false,
+                    });
+                }
+                if self.eat(&TokenKind::Dot) {
+                    let column = self.expect_ident()?;
+                    return Ok(Expr::Column {
+                        table: Some(name),
+                        name: column,
+                    });
+                }
+                // 括弧のない関数
+                if matches!(name.as_str(), "current_date" | "current_timestamp") {
+                    return Ok(Expr::Function {
+                        name,
+                        args: vec![],
+                        distinct: false,
+                    });
+                }
+                Ok(Expr::Column { table: None, name })
+            }
+            _ => {
+                self.expected.push("expression".to_string());
+                Err(self.error())
+            }
+        }
+    }
+
+    // TRIM([LEADING | TRAILING | BOTH] [chars] FROM s) を ltrim / rtrim / trim の呼び出しにする。
+    // ふつうの関数の形なら読まずに None を返す
+    fn parse_trim(&mut self) -> Result<Option<Expr>, ParseError> {
+        let start = self.pos;
+        let mut name = None;
+        if let TokenKind::Ident(side) = self.peek_kind() {
+            let side = match side.as_str() {
+                "leading" => Some("ltrim"),
+                "trailing" => Some("rtrim"),
+                "both" => Some("trim"),
+                _ => None,
+            };
+            // 列の名前かもしれないので、後ろが区切りなら向きとみなさない
+            if side.is_some()
+                && !matches!(self.peek_nth_kind(1), TokenKind::Comma | TokenKind::RParen)
+            {
+                name = side;
+                self.advance();
+            }
+        }
+        let (s, chars) = if self.eat_keyword(Keyword::FROM) {
+            (self.parse_expr()?, None)
+        } else {
+            let first = self.parse_expr()?;
+            if self.eat_keyword(Keyword::FROM) {
+                (self.parse_expr()?, Some(first))
+            } else if name.is_some() {
+                (first, None)
+            } else {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+        self.expect(&TokenKind::RParen)?;
+        let mut args = vec![s];
+        args.extend(chars);
+        Ok(Some(Expr::Function {
+            name: name.unwrap_or("trim").to_string(),
+            args,
+            distinct: false,
+        }))
+    }
+
+    fn parse_function(&mut self, name: String) -> Result<Expr, ParseError> {
+        self.expect(&TokenKind::LParen)?;
+        // EXTRACT(field FROM expr) は単位を文字列にして 2 引数の関数にする
+        if name == "extract" {
+            let field = self.expect_ident()?;
+            self.expect_keyword(Keyword::FROM)?;
+            let expr = self.parse_expr()?;
+            self.expect(&TokenKind::RParen)?;
+            return Ok(Expr::Function {
+                name,
+                args: vec![Expr::Literal(Literal::String(field)), expr],
+                distinct: false,
+            });
+        }
+        // CAST(expr AS type) は型の名前を文字列にして 2 引数の関数にする
+        if name == "cast" {
+            let expr = self.parse_expr()?;
+            self.expect_keyword(Keyword::AS)?;
+            let span = self.current().span;
+            let ty = self.parse_type_name()?;
+            let Some(ty) = DataType::parse(&ty) else {
+                return Err(ParseError::new(
+                    span,
+                    format!("type \"{}\" does not exist", ty.to_lowercase()),
+                ));
+            };
+            self.expect(&TokenKind::RParen)?;
+            return Ok(Expr::Function {
+                name,
+                args: vec![expr, Expr::Literal(Literal::String(ty.to_string()))],
+                distinct: false,
+            });
+        }
+        // MATCH(col, ...) AGAINST(query) は問い合わせを 1 つめの引数にした関数にする
+        if name == "match" {
+            let mut args = self.comma_separated(Self::parse_expr)?;
+            self.expect(&TokenKind::RParen)?;
+            if !self.eat_word("against") {
+                self.expected.push("AGAINST".to_string());
+                return Err(self.error());
+            }
+            args.insert(0, self.parenthesized(Self::parse_expr)?);
+            return Ok(Expr::Function {
+                name,
+                args,
+                distinct: false,
+            });
+        }
+        if name == "trim" {
+            if let Some(func) = self.parse_trim()? {
+                return Ok(func);
+            }
+        }
+        // SUBSTRING(s FROM start [FOR len])。FROM がなければふつうの関数として読み直す
+        if name == "substring" && *self.peek_kind() != TokenKind::RParen {
+            let start = self.pos;
+            let s = self.parse_expr()?;
+            if self.eat_keyword(Keyword::FROM) {
+                let mut args = vec![s, self.parse_expr()?];
+                if self.eat_keyword(Keyword::FOR) {
+                    args.push(self.parse_expr()?);
+                }
+                self.expect(&TokenKind::RParen)?;
+                return Ok(Expr::Function {
+                    name,
+                    args,
+                    distinct: false,
+                });
+            }
+            self.pos = start;
+        }
+        let func = if name == "count" && self.eat(&TokenKind::Star) {
+            self.expect(&TokenKind::RParen)?;
+            Expr::CountStar
+        } else {
+            let distinct = self.eat_keyword(Keyword::DISTINCT);
+            let args = if *self.peek_kind() == TokenKind::RParen {
+                Vec::new()
+            } else {
+                self.comma_separated(Self::parse_expr)?
+            };
+            self.expect(&TokenKind::RParen)?;
+            Expr::Function {
+                name,
+                args,
+                distinct,
+            }
+        };
+        if !self.eat_keyword(Keyword::OVER) {
+            return Ok(func);
+        }
+        let spec = self.parenthesized(Self::parse_window_spec)?;
+        Ok(Expr::Window {
+            func: Box::new(func),
+            spec,
+        })
+    }
+
+    fn parse_window_spec(&mut self) -> Result<WindowSpec, ParseError> {
+        let mut partition_by = Vec::new();
+        if self.eat_keyword(Keyword::PARTITION) {
+            self.expect_keyword(Keyword::BY)?;
+            partition_by = self.comma_separated(Self::parse_expr)?;
+        }
+        let mut order_by = Vec::new();
+        if self.eat_keyword(Keyword::ORDER) {
+            self.expect_keyword(Keyword::BY)?;
+            order_by = self.comma_separated(Self::parse_order_by_expr)?;
+        }
+        let units = if self.eat_keyword(Keyword::ROWS) {
+            Some(FrameUnits::Rows)
+        } else if self.eat_keyword(Keyword::RANGE) {
+            Some(FrameUnits::Range)
+        } else {
+            None
+        };
+        let frame = match units {
+            Some(units) => Some(self.parse_window_frame(units)?),
+            None => None,
+        };
+        Ok(WindowSpec {
+            partition_by,
+            order_by,
+            frame,
+        })
+    }
+
+    fn parse_window_frame(&mut self, units: FrameUnits) -> Result<WindowFrame, ParseError> {
+        let between = self.eat_keyword(Keyword::BETWEEN);
+        let start_span = self.current().span;
+        let start = self.parse_frame_bound()?;
+        // 終端を省略した場合は CURRENT ROW まで
+        let mut end = FrameBound::CurrentRow;
+        if between {
+            self.expect_keyword(Keyword::AND)?;
+            let end_span = self.current().span;
+            end = self.parse_frame_bound()?;
+            if end == FrameBound::UnboundedPreceding {
+                return Err(ParseError::new(
+                    end_span,
+                    "frame end cannot be UNBOUNDED PRECEDING",
+                ));
+            }
+        }
+        if start == FrameBound::UnboundedFollowing {
+            return Err(ParseError::new(
+                start_span,
+                "frame start cannot be UNBOUNDED FOLLOWING",
+            ));
+        }
+        if start.position() > end.position() {
+            return Err(ParseError::new(
+                start_span,
+                "frame starting offset must not be after the ending offset",
+            ));
+        }
+        Ok(WindowFrame { units, start, end })
+    }
+
+    fn parse_frame_bound(&mut self) -> Result<FrameBound, ParseError> {
+        if self.eat_keyword(Keyword::CURRENT) {
+            self.expect_keyword(Keyword::ROW)?;
+            return Ok(FrameBound::CurrentRow);
+        }
+        if self.eat_keyword(Keyword::UNBOUNDED) {
+            if self.eat_keyword(Keyword::PRECEDING) {
+                return Ok(FrameBound::UnboundedPreceding);
+            }
+            self.expect_keyword(Keyword::FOLLOWING)?;
+            return Ok(FrameBound::UnboundedFollowing);
+        }
+        let offset = match self.peek_kind() {
+            TokenKind::Number(n) => n.parse::<u64>().ok(),
+            _ => None,
+        };
+        let Some(offset) = offset else {
+            self.expected.push("frame offset".to_string());
+            return Err(self.error());
+        };
+        self.advance();
+        if self.eat_keyword(Keyword::PRECEDING) {
+            return Ok(FrameBound::Preceding(offset));
+        }
+        self.expect_keyword(Keyword::FOLLOWING)?;
+        Ok(FrameBound::Following(offset))
+    }
+
+    // ---- DML ----
+
+    fn parse_insert(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::INSERT)?;
+        self.expect_keyword(Keyword::INTO)?;
+        let table = self.parse_table_name()?;
+        let columns = if *self.peek_kind() == TokenKind::LParen {
+            self.parenthesized(|p| p.comma_separated(Self::expect_ident))?
+        } else {
+            Vec::new()
+        };
+        let source = if self.eat_keyword(Keyword::VALUES) {
+            InsertSource::Values(
+                self.comma_separated(|p| p.parenthesized(|p| p.comma_separated(Self::parse_expr)))?,
+            )
+        } else if self.at_query_start() {
+            InsertSource::Query(Box::new(self.parse_query()?))
+        } else {
+            self.expected.push(Keyword::VALUES.to_string());
+            self.expected.push(Keyword::SELECT.to_string());
+            return Err(self.error());
+        };
+        let on_conflict = if self.eat_keyword(Keyword::ON) {
+            Some(self.parse_on_conflict()?)
+        } else {
+            None
+        };
+        let returning = self.parse_returning()?;
+        Ok(Statement::Insert(Insert {
+            table,
+            columns,
+            source,
+            on_conflict,
+            returning,
+        }))
+    }
+
+    fn parse_on_conflict(&mut self) -> Result<OnConflict, ParseError> {
+        self.expect_keyword(Keyword::CONFLICT)?;
+        let columns = if *self.peek_kind() == TokenKind::LParen {
+            self.parenthesized(|p| p.comma_separated(Self::expect_ident))?
+        } else {
+            Vec::new()
+        };
+        self.expect_keyword(Keyword::DO)?;
+        if self.eat_keyword(Keyword::NOTHING) {
+            return Ok(OnConflict {
+                columns,
+                action: ConflictAction::Nothing,
+            });
+        }
+        self.expect_keyword(Keyword::UPDATE)?;
+        self.expect_keyword(Keyword::SET)?;
+        let assignments = self.parse_assignments()?;
+        let selection = if self.eat_keyword(Keyword::WHERE) {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        Ok(OnConflict {
+            columns,
+            action: ConflictAction::Update {
+                assignments,
+                selection,
+            },
+        })
+    }
+
+    fn parse_update(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::UPDATE)?;
+        let table = self.parse_table_name()?;
+        self.expect_keyword(Keyword::SET)?;
+        let assignments = self.parse_assignments()?;
+        let selection = if self.eat_keyword(Keyword::WHERE) {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        let returning = self.parse_returning()?;
+        Ok(Statement::Update(Update {
+            table,
+            assignments,
+            selection,
+            returning,
+        }))
+    }
+
+    fn parse_delete(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::DELETE)?;
+        self.expect_keyword(Keyword::FROM)?;
+        let table = self.parse_table_name()?;
+        let selection = if self.eat_keyword(Keyword::WHERE) {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        let returning = self.parse_returning()?;
+        Ok(Statement::Delete(Delete {
+            table,
+            selection,
+            returning,
+        }))
+    }
+
+    fn parse_assignments(&mut self) -> Result<Vec<Assignment>, ParseError> {
+        self.comma_separated(|p| {
+            let column = p.expect_ident()?;
+            p.expect(&TokenKind::Eq)?;
+            let value = p.parse_expr()?;
+            Ok(Assignment { column, value })
+        })
+    }
+
+    fn parse_returning(&mut self) -> Result<Vec<SelectItem>, ParseError> {
+        if self.eat_keyword(Keyword::RETURNING) {
+            self.comma_separated(Self::parse_select_item)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    // ---- DDL ----
+
+    fn parse_create(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::CREATE)?;
+        if self.eat_keyword(Keyword::TABLE) {
+            return self.parse_create_table();
+        }
+        if self.eat_word("materialized") {
+            return self.parse_create_materialized_view();
+        }
+        if self.eat_word("trigger") {
+            return self.parse_create_trigger();
+        }
+        if self.eat_word("database") {
+            return Ok(Statement::CreateDatabase(self.expect_ident()?));
+        }
+        if self.eat_word("type") {
+            let name = self.expect_ident()?;
+            self.expect_keyword(Keyword::AS)?;
+            self.expect_word("enum")?;
+            let labels = self.parenthesized(|p| p.comma_separated(Self::expect_string))?;
+            return Ok(Statement::CreateType { name, labels });
+        }
+        // CREATE USER は LOGIN のついた CREATE ROLE
+        let user = self.eat_word("user");
+        if user || self.eat_word("role") {
+            let name = self.expect_ident()?;
+            let options = self.parse_role_options(RoleOptions {
+                login: user.then_some(true),
+                ..RoleOptions::default()
+            })?;
+            return Ok(Statement::CreateRole { name, options });
+        }
+        let unique = self.eat_keyword(Keyword::UNIQUE);
+        let fulltext = !unique && self.eat_word("fulltext");
+        if self.eat_keyword(Keyword::INDEX) {
+            return self.parse_create_index(unique, fulltext);
+        }
+        if fulltext {
+            return Err(self.error());
+        }
+        if !unique {
+            self.expected.push(Keyword::TABLE.to_string());
+        }
+        Err(self.error())
+    }
+
+    fn parse_if_not_exists(&mut self) -> Result<bool, ParseError> {
+        if self.eat_keyword(Keyword::IF) {
+            self.expect_keyword(Keyword::NOT)?;
+            self.expect_keyword(Keyword::EXISTS)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn parse_create_table(&mut self) -> Result<Statement, ParseError> {
+        let if_not_exists = self.parse_if_not_exists()?;
+        let name = self.parse_table_name()?;
+        if self.eat_keyword(Keyword::PARTITION) {
+            self.expect_word("of")?;
+            let parent = self.expect_ident()?;
+            self.expect_keyword(Keyword::FOR)?;
+            self.expect_keyword(Keyword::VALUES)?;
+            let bound = self.parse_partition_bound()?;
+            return Ok(Statement::CreateTable(Box::new(CreateTable {
+                name,
+                if_not_exists,
+                columns: vec![],
+                primary_key: vec![],
+                partition_by: self.parse_partition_by()?,
+                partition_of: Some(PartitionOf { parent, bound }),
+                ttl: None,
+                using: None,
+                options: vec![],
+            })));
+        }
+        let mut columns = Vec::new();
+        let mut primary_key = Vec::new();
+        self.expect(&TokenKind::LParen)?;
+        loop {
+            if self.eat_keyword(Keyword::PRIMARY) {
+                self.expect_keyword(Keyword::KEY)?;
+                primary_key = self.parenthesized(|p| p.comma_separated(Self::expect_ident))?;
+            } else {
+                columns.push(self.parse_column_def()?);
+            }
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RParen)?;
+        Ok(Statement::CreateTable(Box::new(CreateTable {
+            name,
+            if_not_exists,
+            columns,
+            primary_key,
+            partition_by: self.parse_partition_by()?,
+            partition_of: None,
+            using: if self.eat_word("using") {
+                Some(self.expect_ident()?)
+            } else {
+                None
+            },
+            options: if self.eat_keyword(Keyword::WITH) {
+                self.parse_table_options()?
+            } else {
+                vec![]
+            },
+            ttl: self.parse_ttl()?,
+        })))
+    }
+
+    // (名前 [= 値], ...)
+    fn parse_table_options(&mut self) -> Result<Vec<(String, Option<String>)>, ParseError> {
+        self.parenthesized(|p| {
+            p.comma_separated(|p| {
+                let name = p.expect_ident()?;
+                if !p.eat(&TokenKind::Eq) {
+                    return Ok((name, None));
+                }
+                let value = match p.peek_kind().clone() {
+                    TokenKind::Number(v) | TokenKind::String(v) | TokenKind::Ident(v) => v,
+                    TokenKind::Keyword(kw) => kw.as_str().to_lowercase(),
+                    _ => {
+                        p.expected.push("value".to_string());
+                        return Err(p.error());
+                    }
+                };
+                p.advance();
+                Ok((name, Some(value)))
+            })
+        })
+    }
+
+    fn parse_alter_table(&mut self) -> Result<Statement, ParseError> {
+        let name = self.parse_table_name()?;
+        if self.eat_word("add") {
+            self.eat_word("column");
+            let action = AlterTableAction::AddColumn(self.parse_column_def()?);
+            return Ok(Statement::AlterTable { name, action });
+        }
+        self.expect_keyword(Keyword::SET)?;
+        let action = AlterTableAction::SetOptions(self.parse_table_options()?);
+        Ok(Statement::AlterTable { name, action })
+    }
+
+    fn parse_ttl(&mut self) -> Result<Option<TableTtl>, ParseError> {
+        if !self.eat_word("ttl") {
+            return Ok(None);
+        }
+        let column = self.expect_ident()?;
+        self.expect(&TokenKind::Plus)?;
+        let interval = self.parse_expr()?;
+        Ok(Some(TableTtl { column, interval }))
+    }
+
+    fn parse_partition_by(&mut self) -> Result<Option<PartitionBy>, ParseError> {
+        if !self.eat_keyword(Keyword::PARTITION) {
+            return Ok(None);
+        }
+        self.expect_keyword(Keyword::BY)?;
+        let strategy = if self.eat_keyword(Keyword::RANGE) {
+            PartitionStrategy::Range
+        } else if self.eat_word("hash") {
+            PartitionStrategy::Hash
+        } else {
+            self.expected.push("HASH".to_string());
+            return Err(self.error());
+        };
+        let column = self.parenthesized(Self::expect_ident)?;
+        Ok(Some(PartitionBy { strategy, column }))
+    }
+
+    fn parse_partition_bound(&mut self) -> Result<PartitionBoundSpec, ParseError> {
+        if self.eat_keyword(Keyword::FROM) {
+            let from = self.parenthesized(|p| p.parse_range_bound("minvalue"))?;
+            self.expect_keyword(Keyword::TO)?;
+            let to = self.parenthesized(|p| p.parse_range_bound("maxvalue"))?;
+            return Ok(PartitionBoundSpec::Range { from, to });
+        }
+        if !self.eat_keyword(Keyword::WITH) {
+            return Err(self.error());
+        }
+        self.expect(&TokenKind::LParen)?;
+        self.expect_word("modulus")?;
+        let modulus = self.expect_count("modulus")?;
+        self.expect(&TokenKind::Comma)?;
+        self.expect_word("remainder")?;
+        let remainder = self.expect_count("remainder")?;
+        self.expect(&TokenKind::RParen)?;
+        Ok(PartitionBoundSpec::Hash { modulus, remainder })
+    }
+
+    // 範囲の端の値。unbounded の語なら端がない
+    fn parse_range_bound(&mut self, unbounded: &str) -> Result<Option<Expr>, ParseError> {
+        if self.eat_word(unbounded) {
+            return Ok(None);
+        }
+        self.parse_expr().map(Some)
+    }
+
+    fn expect_count(&mut self, what: &str) -> Result<u64, ParseError> {
+        let count = match self.peek_kind() {
+            TokenKind::Number(n) => n.parse::<u64>().ok(),
+            _ => None,
+        };
+        let Some(count) = count else {
+            self.expected.push(what.to_string());
+            return Err(self.error());
+        };
+        self.advance();
+        Ok(count)
+    }
+
+    fn parse_column_def(&mut self) -> Result<ColumnDef, ParseError> {
+        let name = self.expect_ident()?;
+        let data_type = self.parse_type_name()?;
+        let mut column = ColumnDef {
+            name,
+            data_type,
+            not_null: false,
+            primary_key: false,
+            unique: false,
+            collation: None,
+            generated: None,
+            compression: None,
+        };
+        loop {
+            if self.eat_keyword(Keyword::NOT) {
+                self.expect_keyword(Keyword::NULL)?;
+                column.not_null = true;
+            } else if self.eat_keyword(Keyword::NULL) {
+                column.not_null = false;
+            } else if self.eat_keyword(Keyword::PRIMARY) {
+                self.expect_keyword(Keyword::KEY)?;
+                column.primary_key = true;
+                column.not_null = true;
+            } else if self.eat_keyword(Keyword::UNIQUE) {
+                column.unique = true;
+            } else if matches!(self.peek_kind(), TokenKind::Ident(s) if s == "collate") {
+                // COLLATE はキーワードにせず、列の定義の中でだけ読む
+                self.advance();
+                let span = self.current().span;
+                let name = self.expect_ident()?;
+                let collation = Collation::parse(&name).ok_or_else(|| {
+                    ParseError::new(span, format!("collation \"{}\" does not exist", name))
+                })?;
+                column.collation = Some(collation.name().to_string());
+            } else if self.eat_word("generated") {
+                // GENERATED、ALWAYS、STORED、VIRTUAL もキーワードにしない
+                self.expect_word("always")?;
+                self.expect_keyword(Keyword::AS)?;
+                self.expect(&TokenKind::LParen)?;
+                let expr = self.parse_expr()?;
+                self.expect(&TokenKind::RParen)?;
+                let stored = self.eat_word("stored");
+                if !stored {
+                    self.eat_word("virtual");
+                }
+                column.generated = Some(Generated { expr, stored });
+            } else if self.eat_word("compression") {
+                column.compression = Some(self.expect_ident()?);
+            } else {
+                return Ok(column);
+            }
+        }
+    }
+
+    fn parse_type_name(&mut self) -> Result<String, ParseError> {
+        let mut name = match self.peek_kind() {
+            TokenKind::Ident(name) => name.to_uppercase(),
+            _ => {
+                self.expected.push("type name".to_string());
+                return Err(self.error());
+            }
+        };
+        self.advance();
+        if self.eat(&TokenKind::LParen) {
+            let mut args = Vec::new();
+            loop {
+                match self.peek_kind() {
+                    TokenKind::Number(n) => {
+                        args.push(n.clone());
+                        self.advance();
+                    }
+                    _ => {
+                        self.expected.push("number".to_string());
+                        return Err(self.error());
+                    }
+                }
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::RParen)?;
+            name = format!("{}({})", name, args.join(","));
+        }
+        // 配列の型は要素の型の名前に [] を付ける
+        if self.eat(&TokenKind::LBracket) {
+            self.expect(&TokenKind::RBracket)?;
+            name.push_str("[]");
+        }
+        Ok(name)
+    }
+
+    fn parse_create_index(
+        &mut self,
+        unique: bool,
+        fulltext: bool,
+    ) -> Result<Statement, ParseError> {
+        let name = self.expect_ident()?;
+        self.expect_keyword(Keyword::ON)?;
+        let table = self.expect_ident()?;
+        let mut rtree = false;
+        if !fulltext && self.eat_word("using") {
+            rtree = match self.peek_kind() {
+                TokenKind::Ident(method) if method == "btree" => false,
+                TokenKind::Ident(method) if method == "rtree" || method == "gist" => true,
+                _ => {
+                    self.expected
+                        .extend(["btree", "rtree", "gist"].map(String::from));
+                    return Err(self.error());
+                }
+            };
+            self.advance();
+        }
+        self.expect(&TokenKind::LParen)?;
+        let (columns, expression) = if !fulltext && !rtree && self.peek_kind() == &TokenKind::LParen
+        {
+            (vec![], Some(self.parenthesized(Self::parse_expr)?))
+        } else {
+            (self.comma_separated(Self::expect_ident)?, None)
+        };
+        self.expect(&TokenKind::RParen)?;
+        Ok(Statement::CreateIndex(CreateIndex {
+            name,
+            table,
+            columns,
+            expression,
+            unique,
+            fulltext,
+            rtree,
+        }))
+    }
+
+    fn parse_create_materialized_view(&mut self) -> Result<Statement, ParseError> {
+        self.expect_word("view")?;
+        let if_not_exists = self.parse_if_not_exists()?;
+        let name = self.expect_ident()?;
+        self.expect_keyword(Keyword::AS)?;
+        let start = self.current().span.start;
+        let query = self.parse_query()?;
+        let end = self.tokens[self.pos - 1].span.end;
+        Ok(Statement::CreateMaterializedView(Box::new(
+            CreateMaterializedView {
+                name,
+                if_not_exists,
+                query,
+                sql: self.sql[start..end].to_string(),
+            },
+        )))
+    }
+
+    fn parse_create_trigger(&mut self) -> Result<Statement, ParseError> {
+        let name = self.expect_ident()?;
+        let timing = if self.eat_word("before") {
+            TriggerTiming::Before
+        } else {
+            self.expected.push("BEFORE".to_string());
+            self.expect_word("after")?;
+            TriggerTiming::After
+        };
+        let event = if self.eat_keyword(Keyword::INSERT) {
+            TriggerEvent::Insert
+        } else if self.eat_keyword(Keyword::UPDATE) {
+            TriggerEvent::Update
+        } else {
+            self.expected.push(Keyword::INSERT.to_string());
+            self.expected.push(Keyword::UPDATE.to_string());
+            self.expect_keyword(Keyword::DELETE)?;
+            TriggerEvent::Delete
+        };
+        self.expect_keyword(Keyword::ON)?;
+        let table = self.expect_ident()?;
+        self.expect_keyword(Keyword::FOR)?;
+        self.expect_word("each")?;
+        self.expect_keyword(Keyword::ROW)?;
+        let body = if self.eat_word("execute") {
+            self.expect_word("function")?;
+            let function = self.expect_ident()?;
+            self.expect(&TokenKind::LParen)?;
+            self.expect(&TokenKind::RParen)?;
+            TriggerBodySpec::Function(function)
+        } else {
+            let span = self.current().span;
+            let statement = self.parse_statement()?;
+            if !matches!(
+                statement,
+                Statement::Query(_)
+                    | Statement::Insert(_)
+                    | Statement::Update(_)
+                    | Statement::Delete(_)
+            ) {
+                return Err(ParseError::new(
+                    span,
+                    "trigger body must be SELECT, INSERT, UPDATE or DELETE",
+                ));
+            }
+            let end = self.tokens[self.pos - 1].span.end;
+            TriggerBodySpec::Statement {
+                statement: Box::new(statement),
+                sql: self.sql[span.start..end].to_string(),
+            }
+        };
+        Ok(Statement::CreateTrigger(Box::new(CreateTrigger {
+            name,
+            timing,
+            event,
+            table,
+            body,
+        })))
+    }
+
+    // ALTER TYPE name ADD VALUE [IF NOT EXISTS] 'label' [{BEFORE | AFTER} 'label']
+    fn parse_alter_type(&mut self) -> Result<Statement, ParseError> {
+        let name = self.expect_ident()?;
+        self.expect_word("add")?;
+        self.expect_word("value")?;
+        let if_not_exists = self.parse_if_not_exists()?;
+        let label = self.expect_string()?;
+        let position = if self.eat_word("before") {
+            Some((true, self.expect_string()?))
+        } else if self.eat_word("after") {
+            Some((false, self.expect_string()?))
+        } else {
+            None
+        };
+        Ok(Statement::AlterType {
+            name,
+            label,
+            position,
+            if_not_exists,
+        })
+    }
+
+    fn parse_role_options(&mut self, mut options: RoleOptions) -> Result<RoleOptions, ParseError> {
+        self.eat_keyword(Keyword::WITH);
+        loop {
+            if self.eat_word("superuser") {
+                options.superuser = Some(true);
+            } else if self.eat_word("nosuperuser") {
+                options.superuser = Some(false);
+            } else if self.eat_word("login") {
+                options.login = Some(true);
+            } else if self.eat_word("nologin") {
+                options.login = Some(false);
+            } else if self.eat_word("password") {
+                options.password = Some(if self.eat_keyword(Keyword::NULL) {
+                    None
+                } else {
+                    Some(self.expect_string()?)
+                });
+            } else {
+                return Ok(options);
+            }
+        }
+    }
+
+    // GRANT と REVOKE の後。to は GRANT なら TO、REVOKE なら FROM
+    fn parse_grant(&mut self, to: Keyword) -> Result<Grant, ParseError> {
+        let privileges = if self.eat_keyword(Keyword::ALL) {
+            self.eat_word("privileges");
+            Privilege::ALL.to_vec()
+        } else {
+            self.comma_separated(|p| {
+                if p.eat_keyword(Keyword::SELECT) {
+                    Ok(Privilege::Select)
+                } else if p.eat_keyword(Keyword::INSERT) {
+                    Ok(Privilege::Insert)
+                } else if p.eat_keyword(Keyword::UPDATE) {
+                    Ok(Privilege::Update)
+                } else {
+                    p.expected.push(Keyword::ALL.to_string());
+                    p.expected.push(Keyword::SELECT.to_string());
+                    p.expected.push(Keyword::INSERT.to_string());
+                    p.expected.push(Keyword::UPDATE.to_string());
+                    p.expect_keyword(Keyword::DELETE)?;
+                    Ok(Privilege::Delete)
+                }
+            })?
+        };
+        self.expect_keyword(Keyword::ON)?;
+        self.eat_keyword(Keyword::TABLE);
+        let tables = self.comma_separated(Self::expect_ident)?;
+        self.expect_keyword(to)?;
+        let roles = self.comma_separated(Self::expect_ident)?;
+        Ok(Grant {
+            privileges,
+            tables,
+            roles,
+        })
+    }
+
+    fn parse_drop(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::DROP)?;
+        if self.eat_word("role") {
+            let if_exists = self.parse_if_exists()?;
+            let name = self.expect_ident()?;
+            return Ok(Statement::DropRole { name, if_exists });
+        }
+        if self.eat_word("type") {
+            let if_exists = self.parse_if_exists()?;
+            let name = self.expect_ident()?;
+            return Ok(Statement::DropType { name, if_exists });
+        }
+        if self.eat_word("trigger") {
+            let if_exists = self.parse_if_exists()?;
+            let name = self.expect_ident()?;
+            self.expect_keyword(Keyword::ON)?;
+            let table = self.expect_ident()?;
+            return Ok(Statement::DropTrigger {
+                name,
+                table,
+                if_exists,
+            });
+        }
+        if self.eat_word("materialized") {
+            self.expect_word("view")?;
+        } else {
+            self.expect_keyword(Keyword::TABLE)?;
+        }
+        let if_exists = self.parse_if_exists()?;
+        let name = self.parse_table_name()?;
+        Ok(Statement::DropTable(DropTable { name, if_exists }))
+    }
+
+    fn parse_if_exists(&mut self) -> Result<bool, ParseError> {
+        if self.eat_keyword(Keyword::IF) {
+            self.expect_keyword(Keyword::EXISTS)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+// 集合演算の左右で出力列の数と (リテラルから分かる範囲の) 型が揃っているか確認する
+fn set_operation(
+    op: SetOperator,
+    all: bool,
+    left: SetExpr,
+    right: SetExpr,
+    span: Span,
+) -> Result<SetExpr, ParseError> {
+    if let (Some(l), Some(r)) = (output_kinds(&left), output_kinds(&right)) {
+        if l.len() != r.len() {
+            return Err(ParseError::new(
+                span,
+                format!("each {} query must have the same number of columns", op),
+            ));
+        }
+        for (i, (a, b)) in l.iter().zip(&r).enumerate() {
+            if let (Some(a), Some(b)) = (a, b) {
+                if a != b {
+                    return Err(ParseError::new(
+                        span,
+                        format!(
+                            "{} types {} and {} cannot be matched in column {}",
+                            op,
+                            a,
+                            b,
+                            i + 1
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    Ok(SetExpr::SetOperation {
+        op,
+        all,
+        left: Box::new(left),
+        right: Box::new(right),
+    })
+}
+
+// ワイルドカードを含む場合は列数が分からないので None
+fn output_kinds(set: &SetExpr) -> Option<Vec<Option<&'static str>>> {
+    match set {
+        SetExpr::Select(select) => select
+            .projection
+            .iter()
+            .map(|item| match item {
+                SelectItem::Expr { expr, .. } => Some(literal_kind(expr)),
+                _ => None,
+            })
+            .collect(),
+        SetExpr::Query(query) => output_kinds(&query.body),
+        SetExpr::SetOperation { left, right, .. } => {
+            let l = output_kinds(left)?;
+            let r = output_kinds(right)?;
+            Some(l.into_iter().zip(r).map(|(a, b)| a.or(b)).collect())
+        }
+    }
+}
+
+fn literal_kind(expr: &Expr) -> Option<&'static str> {
+    match expr {
+        Expr::Literal(Literal::Integer(_) | Literal::Float(_) | Literal::Decimal(_)) => {
+            Some("numeric")
+        }
+        Expr::Literal(Literal::String(_)) => Some("text"),
+        Expr::Literal(Literal::Boolean(_)) => Some("boolean"),
+        Expr::Literal(Literal::Date(_)) => Some("date"),
+        Expr::Literal(Literal::Time(_)) => Some("time"),
+        Expr::Literal(Literal::Timestamp(_)) => Some("timestamp"),
+        Expr::Literal(Literal::Uuid(_)) => Some("uuid"),
+        Expr::Literal(Literal::Interval(_)) => Some("interval"),
+        Expr::Literal(Literal::Point(_)) => Some("point"),
+        Expr::Literal(Literal::Box(_)) => Some("box"),
+        _ => None,
+    }
+}
+
+fn parse_typed_literal(ty: &str, text: &str, span: Span) -> Result<Literal, ParseError> {
+    let literal = match ty {
+        "date" => text.parse::<Date>().map(Literal::Date).ok(),
+        "time" => text.parse::<Time>().map(Literal::Time).ok(),
+        "uuid" => text.parse::<Uuid>().map(Literal::Uuid).ok(),
+        "interval" => text.parse::<Interval>().map(Literal::Interval).ok(),
+        "point" => text.parse::<Point>().map(Literal::Point).ok(),
+        "box" => text.parse::<Rect>().map(Literal::Box).ok(),
+        _ => text.parse::<Timestamp>().map(Literal::Timestamp).ok(),
+    };
+    literal.ok_or_else(|| {
+        ParseError::new(
+            span,
+            format!("invalid input syntax for type {}: \"{}\"", ty, text),
+        )
+    })
+}
+
+fn parse_number(text: &str, span: Span) -> Result<Expr, ParseError> {
+    if let Ok(n) = text.parse::<i64>() {
+        return Ok(Expr::Literal(Literal::Integer(n)));
+    }
+    // 指数のない小数は精度に収まれば DECIMAL にする
+    if !text.contains(['e', 'E']) {
+        if let Ok(d) = text.parse::<Decimal>() {
+            return Ok(Expr::Literal(Literal::Decimal(d)));
+        }
+    }
+    text.parse::<f64>()
+        .map(|n| Expr::Literal(Literal::Float(n)))
+        .map_err(|_| ParseError::lexical(span, format!("invalid number {}", text)))
+}
+
+// ヒントのコメントの中身。名前 (テーブル ...) を並べたもの
+fn parse_hint_body(body: &str) -> Result<Vec<Hint>, String> {
+    let invalid = || format!("invalid hint: {}", body.trim());
+    let tokens = Lexer::new(body).tokenize().map_err(|_| invalid())?;
+    let mut tokens = tokens.into_iter().map(|t| t.kind);
+    let mut hints = vec![];
+    loop {
+        let name = match tokens.next() {
+            Some(TokenKind::Ident(name)) => name.to_ascii_lowercase(),
+            Some(TokenKind::Eof) | None => return Ok(hints),
+            _ => return Err(invalid()),
+        };
+        let (negated, method) = match name.strip_prefix("no") {
+            Some(method) => (true, method),
+            None => (false, name.as_str()),
+        };
+        let method = match method {
+            "seqscan" => HintMethod::SeqScan,
+            "indexscan" => HintMethod::IndexScan,
+            "hashjoin" => HintMethod::HashJoin,
+            "mergejoin" => HintMethod::MergeJoin,
+            "nestloop" => HintMethod::NestLoop,
+            _ => return Err(format!("unrecognized hint \"{}\"", name)),
+        };
+        if tokens.next() != Some(TokenKind::LParen) {
+            return Err(invalid());
+        }
+        let mut names = vec![];
+        loop {
+            match tokens.next() {
+                Some(TokenKind::Ident(name)) => names.push(name),
+                Some(TokenKind::Comma) if !names.is_empty() => {}
+                Some(TokenKind::RParen) => break,
+                _ => return Err(invalid()),
+            }
+        }
+        // IndexScan だけは、テーブルのあとに使ってよいインデックスを並べられる
+        let indexes = if method.is_join() {
+            if names.len() < 2 {
+                return Err(format!("hint \"{}\" requires two or more tables", name));
+            }
+            vec![]
+        } else {
+            if names.is_empty() || names.len() > 1 && (method != HintMethod::IndexScan || negated) {
+                return Err(format!("hint \"{}\" requires one table", name));
+            }
+            names.split_off(1)
+        };
+        hints.push(Hint {
+            method,
+            negated,
+            relations: names,
+            indexes,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parse;
+
+    fn select(query: &Query) -> &Select {
+        match &query.body {
+            SetExpr::Select(select) => select,
+            body => panic!("expected select, got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn test_parse_select() {
+        let stmts =
+            parse("SELECT a, b + 1 AS c FROM t WHERE a = 'x' ORDER BY b DESC LIMIT 10").unwrap();
+        let Statement::Query(query) = &stmts[0] else {
+            panic!("expected query");
+        };
+        assert_eq!(select(query).projection.len(), 2);
+        assert_eq!(
+            select(query).from,
+            Some(TableRef::Table {
+                name: "t".to_string(),
+                alias: None,
+                sample: None,
+                as_of: None
+            })
+        );
+        assert!(!query.order_by[0].asc);
+        assert_eq!(query.limit, Some(Expr::Literal(Literal::Integer(10))));
+    }
+
+    #[test]
+    fn test_parse_tablesample() {
+        let from = |sql: &str| {
+            let stmts = parse(sql).unwrap();
+            let Statement::Query(query) = &stmts[0] else {
+                panic!("expected query");
+            };
+            select(query).from.clone().unwrap()
+        };
+        let TableRef::Table { alias, sample, .. } =
+            from("SELECT * FROM t TABLESAMPLE SYSTEM (10) REPEATABLE (1)")
+        else {
+            panic!("expected table");
+        };
+        assert_eq!(alias, None);
+        let sample = sample.unwrap();
+        assert_eq!(sample.method, SampleMethod::System);
+        assert_eq!(sample.seed, Some(Expr::Literal(Literal::Integer(1))));
+        // 手法の名前が続かなければ tablesample は別名
+        let TableRef::Table { alias, sample, .. } = from("SELECT * FROM t tablesample") else {
+            panic!("expected table");
+        };
+        assert_eq!((alias.as_deref(), sample), (Some("tablesample"), None));
+        let err = parse("SELECT * FROM t TABLESAMPLE foo (1)").unwrap_err();
+        assert_eq!(err.message, "tablesample method \"foo\" does not exist");
+    }
+
+    #[test]
+    fn test_parse_as_of() {
+        let from = |sql: &str| {
+            let stmts = parse(sql).unwrap();
+            let Statement::Query(query) = &stmts[0] else {
+                panic!("expected query");
+            };
+            select(query).from.clone().unwrap()
+        };
+        let TableRef::Table { alias, as_of, .. } =
+            from("SELECT * FROM t AS OF TIMESTAMP '2024-01-02 03:04:05' x")
+        else {
+            panic!("expected table");
+        };
+        assert_eq!(alias.as_deref(), Some("x"));
+        assert!(matches!(
+            as_of.as_deref(),
+            Some(AsOf::Timestamp(Expr::Literal(Literal::Timestamp(_))))
+        ));
+        let TableRef::Table { alias, as_of, .. } = from("SELECT * FROM t AS OF LSN 42 AS x") else {
+            panic!("expected table");
+        };
+        assert_eq!(alias.as_deref(), Some("x"));
+        assert_eq!(
+            as_of.as_deref(),
+            Some(&AsOf::Lsn(Expr::Literal(Literal::Integer(42))))
+        );
+        let TableRef::Table { alias, as_of, .. } = from("SELECT * FROM t AS of") else {
+            panic!("expected table");
+        };
+        assert_eq!((alias.as_deref(), as_of), (Some("of"), None));
+    }
+
+    #[test]
+    fn test_parse_hints() {
+        let hints = |sql: &str| {
+            let stmts = parse(sql).unwrap();
+            let Statement::Query(query) = &stmts[0] else {
+                panic!("expected query");
+            };
+            select(query).hints.clone()
+        };
+        assert_eq!(
+            hints("SELECT /* c */ /*+ IndexScan(t t_a t_b) NoHashJoin(t, s) */ * FROM t, s"),
+            [
+                Hint {
+                    method: HintMethod::IndexScan,
+                    negated: false,
+                    relations: vec!["t".to_string()],
+                    indexes: vec!["t_a".to_string(), "t_b".to_string()],
+                },
+                Hint {
+                    method: HintMethod::HashJoin,
+                    negated: true,
+                    relations: vec!["t".to_string(), "s".to_string()],
+                    indexes: vec![],
+                },
+            ]
+        );
+        // SELECT のすぐあとでなければただのコメント
+        assert!(hints("/*+ SeqScan(t) */ SELECT * FROM t").is_empty());
+        assert!(hints("SELECT * /*+ SeqScan(t) */ FROM t").is_empty());
+        let err = parse("SELECT /*+ SeqScan(t s) */ * FROM t").unwrap_err();
+        assert_eq!(err.message, "hint \"seqscan\" requires one table");
+        let err = parse("SELECT /*+ SeqScan(t */ * FROM t").unwrap_err();
+        assert_eq!(err.message, "invalid hint: SeqScan(t");
+    }
+
+    #[test]
+    fn test_parse_with() {
+        let stmts =
+            parse("WITH a AS (SELECT x FROM t), b (y) AS (SELECT x FROM a) SELECT y FROM b")
+                .unwrap();
+        let Statement::Query(query) = &stmts[0] else {
+            panic!("expected query");
+        };
+        assert_eq!(query.with.len(), 2);
+        assert_eq!(query.with[0].name, "a");
+        assert_eq!(query.with[1].columns, vec!["y".to_string()]);
+        assert!(!query.with[0].recursive);
+        assert_eq!(
+            select(query).from,
+            Some(TableRef::Table {
+                name: "b".to_string(),
+                alias: None,
+                sample: None,
+                as_of: None
+            })
+        );
+
+        let stmts = parse("SELECT * FROM t, LATERAL (SELECT t.x) s, lateral").unwrap();
+        let Statement::Query(query) = &stmts[0] else {
+            panic!("expected query");
+        };
+        let Some(TableRef::Join { left, right, .. }) = &select(query).from else {
+            panic!("expected join");
+        };
+        assert!(matches!(**right, TableRef::Table { .. }));
+        assert!(matches!(
+            **left,
+            TableRef::Join { ref right, .. }
+                if matches!(**right, TableRef::Subquery { lateral: true, .. })
+        ));
+
+        let err = parse("WITH a (SELECT 1) SELECT 1").unwrap_err();
+        assert_eq!(err.found.as_deref(), Some("SELECT"));
+        assert_eq!(err.expected, vec!["identifier".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_locking() {
+        let locking = |sql| {
+            let stmts = parse(sql).unwrap();
+            let Statement::Query(query) = &stmts[0] else {
+                panic!("expected query");
+            };
+            query.locking
+        };
+        assert_eq!(locking("SELECT a FROM t"), None);
+        assert_eq!(
+            locking("SELECT a FROM t WHERE a = 1 LIMIT 1 FOR UPDATE"),
+            Some(LockingClause {
+                strength: LockStrength::Update,
+                wait: WaitPolicy::Block
+            })
+        );
+        assert_eq!(
+            locking("SELECT a FROM t FOR SHARE NOWAIT"),
+            Some(LockingClause {
+                strength: LockStrength::Share,
+                wait: WaitPolicy::NoWait
+            })
+        );
+        assert_eq!(
+            locking("SELECT a FROM t FOR UPDATE SKIP LOCKED"),
+            Some(LockingClause {
+                strength: LockStrength::Update,
+                wait: WaitPolicy::SkipLocked
+            })
+        );
+
+        let err = parse("SELECT a FROM t FOR DELETE").unwrap_err();
+        assert_eq!(err.found.as_deref(), Some("DELETE"));
+        assert_eq!(
+            err.expected,
+            vec!["UPDATE".to_string(), "SHARE".to_string()]
+        );
+        assert!(parse("SELECT a FROM t FOR UPDATE SKIP").is_err());
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let stmts = parse("SELECT 1 + 2 * 3 FROM t WHERE a OR b AND NOT c").unwrap();
+        let Statement::Query(query) = &stmts[0] else {
+            panic!("expected query");
+        };
+        let SelectItem::Expr { expr, .. } = &select(query).projection[0] else {
+            panic!("expected expr");
+        };
+        assert_eq!(
+            *expr,
+            binary(
+                BinaryOp::Plus,
+                Expr::Literal(Literal::Integer(1)),
+                binary(
+                    BinaryOp::Multiply,
+                    Expr::Literal(Literal::Integer(2)),
+                    Expr::Literal(Literal::Integer(3))
+                )
+            )
+        );
+        let Some(Expr::Binary { op, .. }) = &select(query).selection else {
+            panic!("expected binary");
+        };
+        assert_eq!(*op, BinaryOp::Or);
+
+        let stmts = parse("SELECT * FROM t WHERE a IS NOT DISTINCT FROM b || 'x' AND c").unwrap();
+        let Statement::Query(query) = &stmts[0] else {
+            panic!("expected query");
+        };
+        let column = |name: &str| Expr::Column {
+            table: None,
+            name: name.to_string(),
+        };
+        assert_eq!(
+            select(query).selection,
+            Some(binary(
+                BinaryOp::And,
+                binary(
+                    BinaryOp::IsNotDistinctFrom,
+                    column("a"),
+                    binary(
+                        BinaryOp::Concat,
+                        column("b"),
+                        Expr::Literal(Literal::String("x".to_string()))
+                    )
+                ),
+                column("c")
+            ))
+        );
+
+        // -> と ->> は || と同じ強さで左から結び付き、- > とは別のトークンになる
+        let mut parser = Parser::new("j -> 'a' ->> 0 = x - 1").unwrap();
+        let string = |s: &str| Expr::Literal(Literal::String(s.to_string()));
+        assert_eq!(
+            parser.parse_expr().unwrap(),
+            binary(
+                BinaryOp::Eq,
+                binary(
+                    BinaryOp::JsonGetText,
+                    binary(BinaryOp::JsonGet, column("j"), string("a")),
+                    Expr::Literal(Literal::Integer(0))
+                ),
+                binary(
+                    BinaryOp::Minus,
+                    column("x"),
+                    Expr::Literal(Literal::Integer(1))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_special_functions() {
+        let parse_expr = |sql: &str| {
+            let mut parser = Parser::new(sql).unwrap();
+            parser.parse_expr().unwrap()
+        };
+        let call = |name: &str, args: Vec<Expr>| Expr::Function {
+            name: name.to_string(),
+            args,
+            distinct: false,
+        };
+        let column = |name: &str| Expr::Column {
+            table: None,
+            name: name.to_string(),
+        };
+        let string = |s: &str| Expr::Literal(Literal::String(s.to_string()));
+        for (sql, expected) in [
+            ("TRIM(s)", call("trim", vec![column("s")])),
+            ("trim(s, 'x')", call("trim", vec![column("s"), string("x")])),
+            (
+                "TRIM('x' FROM s)",
+                call("trim", vec![column("s"), string("x")]),
+            ),
+            ("TRIM(LEADING FROM s)", call("ltrim", vec![column("s")])),
+            (
+                "TRIM(TRAILING 'x' FROM s)",
+                call("rtrim", vec![column("s"), string("x")]),
+            ),
+            ("TRIM(both)", call("trim", vec![column("both")])),
+            (
+                "s LIKE 'a%' || 'b'",
+                call(
+                    "like",
+                    vec![
+                        column("s"),
+                        binary(BinaryOp::Concat, string("a%"), string("b")),
+                    ],
+                ),
+            ),
+            (
+                "s NOT ILIKE 'a!%' ESCAPE '!'",
+                Expr::Unary {
+                    op: UnaryOp::Not,
+                    expr: Box::new(call("ilike", vec![column("s"), string("a!%"), string("!")])),
+                },
+            ),
+            (
+                "s REGEXP '^a' AND escape",
+                binary(
+                    BinaryOp::And,
+                    call("regexp_like", vec![column("s"), string("^a")]),
+                    column("escape"),
+                ),
+            ),
+            (
+                "SUBSTRING(s FROM 2 FOR 3)",
+                call(
+                    "substring",
+                    vec![
+                        column("s"),
+                        Expr::Literal(Literal::Integer(2)),
+                        Expr::Literal(Literal::Integer(3)),
+                    ],
+                ),
+            ),
+            (
+                "substring(s, 2)",
+                call(
+                    "substring",
+                    vec![column("s"), Expr::Literal(Literal::Integer(2))],
+                ),
+            ),
+            (
+                "EXTRACT(year FROM d)",
+                call("extract", vec![string("year"), column("d")]),
+            ),
+            (
+                "MATCH(s, t) AGAINST('a b')",
+                call("match", vec![string("a b"), column("s"), column("t")]),
+            ),
+            (
+                "CAST(s AS varchar(10))",
+                call("cast", vec![column("s"), string("text")]),
+            ),
+        ] {
+            assert_eq!(parse_expr(sql), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_window() {
+        let stmts = parse(
+            "SELECT row_number() OVER (PARTITION BY a ORDER BY b DESC), \
+             sum(c) OVER (ORDER BY b ROWS BETWEEN 2 PRECEDING AND CURRENT ROW), \
+             count(*) OVER () FROM t",
+        )
+        .unwrap();
+        let Statement::Query(query) = &stmts[0] else {
+            panic!("expected query");
+        };
+        let specs: Vec<_> = select(query)
+            .projection
+            .iter()
+            .map(|item| match item {
+                SelectItem::Expr {
+                    expr: Expr::Window { spec, .. },
+                    ..
+                } => spec.clone(),
+                item => panic!("expected window, got {:?}", item),
+            })
+            .collect();
+        assert_eq!(specs[0].partition_by.len(), 1);
+        assert!(!specs[0].order_by[0].asc);
+        assert_eq!(specs[0].frame, None);
+        assert_eq!(
+            specs[1].frame,
+            Some(WindowFrame {
+                units: FrameUnits::Rows,
+                start: FrameBound::Preceding(2),
+                end: FrameBound::CurrentRow,
+            })
+        );
+        assert_eq!(
+            specs[2],
+            WindowSpec {
+                partition_by: vec![],
+                order_by: vec![],
+                frame: None,
+            }
+        );
+
+        let err = parse("SELECT sum(a) OVER (ROWS UNBOUNDED FOLLOWING) FROM t").unwrap_err();
+        assert_eq!(err.message, "frame start cannot be UNBOUNDED FOLLOWING");
+        let err = parse("SELECT sum(a) OVER (ROWS BETWEEN 1 FOLLOWING AND 1 PRECEDING) FROM t")
+            .unwrap_err();
+        assert_eq!(err.span.column, 34);
+    }
+
+    #[test]
+    fn test_parse_grouping_sets() {
+        let column = |name: &str| Expr::Column {
+            table: None,
+            name: name.to_string(),
+        };
+        let sets = |sql: &str| {
+            let stmts = parse(sql).unwrap();
+            let Statement::Query(query) = &stmts[0] else {
+                panic!("expected query");
+            };
+            let select = select(query);
+            (select.group_by.clone(), select.grouping_sets.clone())
+        };
+        let (a, b, c) = (column("a"), column("b"), column("c"));
+        assert_eq!(
+            sets("SELECT 1 FROM t GROUP BY ROLLUP (a, b)"),
+            (
+                vec![a.clone(), b.clone()],
+                Some(vec![vec![a.clone(), b.clone()], vec![a.clone()], vec![]])
+            )
+        );
+        // 項目どうしは掛け合わせ、かっこの中の式は 1 つの要素として扱う
+        assert_eq!(
+            sets("SELECT 1 FROM t GROUP BY c, CUBE ((a, b))").1,
+            Some(vec![vec![c.clone(), a.clone(), b.clone()], vec![c.clone()]])
+        );
+        assert_eq!(
+            sets("SELECT 1 FROM t GROUP BY GROUPING SETS (a, (b, c), ())").1,
+            Some(vec![vec![a.clone()], vec![b, c], vec![]])
+        );
+        assert_eq!(sets("SELECT 1 FROM t GROUP BY a").1, None);
+        let cube = (0..13).map(|i| format!("c{}", i)).collect::<Vec<_>>();
+        let err = parse(&format!(
+            "SELECT 1 FROM t GROUP BY CUBE ({})",
+            cube.join(", ")
+        ))
+        .unwrap_err();
+        assert_eq!(err.message, "CUBE is limited to 12 elements");
+    }
+
+    #[test]
+    fn test_parse_set_operations() {
+        let stmts =
+            parse("SELECT a FROM t UNION ALL SELECT b FROM s INTERSECT SELECT c FROM u ORDER BY 1")
+                .unwrap();
+        let Statement::Query(query) = &stmts[0] else {
+            panic!("expected query");
+        };
+        let SetExpr::SetOperation { op, all, right, .. } = &query.body else {
+            panic!("expected set operation");
+        };
+        assert_eq!((*op, *all), (SetOperator::Union, true));
+        assert!(matches!(
+            **right,
+            SetExpr::SetOperation {
+                op: SetOperator::Intersect,
+                all: false,
+                ..
+            }
+        ));
+        assert_eq!(query.order_by.len(), 1);
+
+        assert!(parse("(SELECT a FROM t) EXCEPT DISTINCT (SELECT b FROM s)").is_ok());
+    }
+
+    #[test]
+    fn test_set_operation_compatibility() {
+        let err = parse("SELECT 1, 2 UNION SELECT 3").unwrap_err();
+        assert_eq!(
+            err.message,
+            "each UNION query must have the same number of columns"
+        );
+        assert_eq!(err.span.column, 13);
+
+        let err = parse("SELECT 1, 'a' EXCEPT SELECT 2, 3").unwrap_err();
+        assert_eq!(
+            err.message,
+            "EXCEPT types text and numeric cannot be matched in column 2"
+        );
+
+        assert!(parse("SELECT * FROM t UNION SELECT 1, 2").is_ok());
+        assert!(parse("SELECT NULL, 'a' UNION SELECT 1, x FROM t").is_ok());
+    }
+
+    #[test]
+    fn test_parse_ddl_and_dml() {
+        let stmts = parse(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(20) NOT NULL);
+             INSERT INTO t (id, name) VALUES (1, 'a'), (2, 'b');
+             UPDATE t SET name = 'c' WHERE id = 1;
+             DELETE FROM t WHERE id = 2;",
+        )
+        .unwrap();
+        assert_eq!(stmts.len(), 4);
+        let Statement::CreateTable(create) = &stmts[0] else {
+            panic!("expected create table");
+        };
+        assert_eq!(create.columns[1].data_type, "VARCHAR(20)");
+        assert!(create.columns[0].primary_key);
+        let stmts = parse("CREATE TABLE t (name TEXT COLLATE NOCASE NOT NULL, s TEXT)").unwrap();
+        let Statement::CreateTable(create) = &stmts[0] else {
+            panic!("expected create table");
+        };
+        assert_eq!(create.columns[0].collation.as_deref(), Some("nocase"));
+        assert!(create.columns[0].not_null);
+        assert_eq!(create.columns[1].collation, None);
+        let stmts = parse("CREATE UNIQUE INDEX t_j ON t ((j ->> 'id'))").unwrap();
+        let Statement::CreateIndex(create) = &stmts[0] else {
+            panic!("expected create index");
+        };
+        assert!(create.columns.is_empty() && create.unique && !create.fulltext);
+        assert!(matches!(
+            &create.expression,
+            Some(Expr::Binary {
+                op: BinaryOp::JsonGetText,
+                ..
+            })
+        ));
+        let stmts = parse("CREATE FULLTEXT INDEX t_body ON t (title, body)").unwrap();
+        let Statement::CreateIndex(create) = &stmts[0] else {
+            panic!("expected create index");
+        };
+        assert!(create.fulltext && !create.unique);
+        assert_eq!(create.columns, ["title", "body"]);
+        let stmts = parse("CREATE INDEX t_loc ON t USING gist (loc)").unwrap();
+        let Statement::CreateIndex(create) = &stmts[0] else {
+            panic!("expected create index");
+        };
+        assert!(create.rtree && create.columns == ["loc"]);
+        assert!(parse("CREATE INDEX t_loc ON t USING hash (loc)").is_err());
+        let stmts = parse(
+            "CREATE TABLE m (k INTEGER, v TEXT) PARTITION BY RANGE (k);
+             CREATE TABLE m1 PARTITION OF m FOR VALUES FROM (MINVALUE) TO (-10);
+             CREATE TABLE h1 PARTITION OF h FOR VALUES WITH (MODULUS 4, REMAINDER 3)",
+        )
+        .unwrap();
+        let Statement::CreateTable(create) = &stmts[0] else {
+            panic!("expected create table");
+        };
+        let partition_by = create.partition_by.as_ref().unwrap();
+        assert_eq!(partition_by.strategy, PartitionStrategy::Range);
+        assert_eq!(partition_by.column, "k");
+        let Statement::CreateTable(create) = &stmts[1] else {
+            panic!("expected create table");
+        };
+        let partition_of = create.partition_of.as_ref().unwrap();
+        assert_eq!(partition_of.parent, "m");
+        assert!(matches!(
+            &partition_of.bound,
+            PartitionBoundSpec::Range {
+                from: None,
+                to: Some(_)
+            }
+        ));
+        let Statement::CreateTable(create) = &stmts[2] else {
+            panic!("expected create table");
+        };
+        assert_eq!(
+            create.partition_of.as_ref().unwrap().bound,
+            PartitionBoundSpec::Hash {
+                modulus: 4,
+                remainder: 3
+            }
+        );
+        assert!(parse("CREATE TABLE m (k INTEGER) PARTITION BY LIST (k)").is_err());
+        let stmts = parse("CREATE TABLE e (at TIMESTAMP) TTL at + INTERVAL '30 days'").unwrap();
+        let Statement::CreateTable(create) = &stmts[0] else {
+            panic!("expected create table");
+        };
+        assert_eq!(create.ttl.as_ref().unwrap().column, "at");
+        assert!(parse("CREATE TABLE e (at TIMESTAMP) TTL at").is_err());
+        let stmts = parse(
+            "CREATE MATERIALIZED VIEW totals AS SELECT k, sum(v) FROM t GROUP BY k ;
+             REFRESH MATERIALIZED VIEW totals",
+        )
+        .unwrap();
+        let Statement::CreateMaterializedView(create) = &stmts[0] else {
+            panic!("expected create materialized view");
+        };
+        assert_eq!(create.sql, "SELECT k, sum(v) FROM t GROUP BY k");
+        assert_eq!(
+            stmts[1],
+            Statement::RefreshMaterializedView("totals".to_string())
+        );
+        let stmts = parse(
+            "CREATE TRIGGER t_log AFTER UPDATE ON t FOR EACH ROW INSERT INTO log VALUES (old.id) ;
+             CREATE TRIGGER t_check BEFORE INSERT ON t FOR EACH ROW EXECUTE FUNCTION check_row();
+             DROP TRIGGER IF EXISTS t_log ON t",
+        )
+        .unwrap();
+        let Statement::CreateTrigger(create) = &stmts[0] else {
+            panic!("expected create trigger");
+        };
+        assert_eq!(
+            (create.timing, create.event, create.table.as_str()),
+            (TriggerTiming::After, TriggerEvent::Update, "t")
+        );
+        assert!(matches!(
+            &create.body,
+            TriggerBodySpec::Statement { sql, .. } if sql == "INSERT INTO log VALUES (old.id)"
+        ));
+        let Statement::CreateTrigger(create) = &stmts[1] else {
+            panic!("expected create trigger");
+        };
+        assert_eq!(
+            create.body,
+            TriggerBodySpec::Function("check_row".to_string())
+        );
+        assert!(matches!(
+            &stmts[2],
+            Statement::DropTrigger {
+                if_exists: true,
+                ..
+            }
+        ));
+        assert!(parse("CREATE TRIGGER x BEFORE INSERT ON t FOR EACH ROW VACUUM").is_err());
+        let stmts = parse(
+            "CREATE USER alice WITH PASSWORD 'secret' NOSUPERUSER ;
+             ALTER ROLE alice NOLOGIN PASSWORD NULL ;
+             GRANT SELECT, UPDATE ON TABLE t, u TO alice, bob ;
+             REVOKE ALL PRIVILEGES ON t FROM bob",
+        )
+        .unwrap();
+        assert_eq!(
+            stmts[0],
+            Statement::CreateRole {
+                name: "alice".to_string(),
+                options: RoleOptions {
+                    superuser: Some(false),
+                    login: Some(true),
+                    password: Some(Some("secret".to_string())),
+                },
+            }
+        );
+        assert_eq!(
+            stmts[1],
+            Statement::AlterRole {
+                name: "alice".to_string(),
+                options: RoleOptions {
+                    superuser: None,
+                    login: Some(false),
+                    password: Some(None),
+                },
+            }
+        );
+        assert_eq!(
+            stmts[2],
+            Statement::Grant(Grant {
+                privileges: vec![Privilege::Select, Privilege::Update],
+                tables: vec!["t".to_string(), "u".to_string()],
+                roles: vec!["alice".to_string(), "bob".to_string()],
+            })
+        );
+        let Statement::Revoke(revoke) = &stmts[3] else {
+            panic!("expected revoke");
+        };
+        assert_eq!(revoke.privileges, Privilege::ALL);
+        assert!(parse("GRANT TRUNCATE ON t TO alice").is_err());
+        assert_eq!(
+            parse("ATTACH DATABASE 'other.db' AS other (READ_ONLY); DETACH other").unwrap(),
+            [
+                Statement::Attach {
+                    path: "other.db".to_string(),
+                    alias: "other".to_string(),
+                    read_only: true,
+                },
+                Statement::Detach("other".to_string()),
+            ]
+        );
+        let stmts = parse("INSERT INTO other.t SELECT * FROM main.s").unwrap();
+        let Statement::Insert(insert) = &stmts[0] else {
+            panic!("expected insert");
+        };
+        assert_eq!(insert.table, "other.t");
+        assert_eq!(
+            parse("CREATE DATABASE sales").unwrap(),
+            [Statement::CreateDatabase("sales".to_string())]
+        );
+        let err = parse("CREATE TABLE t (name TEXT COLLATE klingon)").unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("collation \"klingon\" does not exist"));
+        let stmts = parse("DELETE FROM t WHERE id = 2 RETURNING id, name AS n").unwrap();
+        let Statement::Delete(delete) = &stmts[0] else {
+            panic!("expected delete");
+        };
+        assert!(delete.selection.is_some());
+        assert_eq!(delete.returning.len(), 2);
+        assert!(matches!(
+            &delete.returning[1],
+            SelectItem::Expr { alias: Some(alias), .. } if alias == "n"
+        ));
+
+        let stmts = parse(
+            "INSERT INTO t VALUES (1, 'a') ON CONFLICT (id) DO UPDATE SET name = excluded.name \
+             WHERE t.name <> 'b' RETURNING *",
+        )
+        .unwrap();
+        let Statement::Insert(insert) = &stmts[0] else {
+            panic!("expected insert");
+        };
+        let Some(OnConflict {
+            columns,
+            action: ConflictAction::Update { selection, .. },
+        }) = &insert.on_conflict
+        else {
+            panic!("expected ON CONFLICT DO UPDATE");
+        };
+        assert_eq!(columns, &["id"]);
+        assert!(selection.is_some());
+        assert_eq!(insert.returning, [SelectItem::Wildcard]);
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        let stmts = parse("EXPLAIN SELECT a FROM t; EXPLAIN ANALYZE DELETE FROM t").unwrap();
+        assert!(matches!(
+            &stmts[0],
+            Statement::Explain { analyze: false, statement, .. } if matches!(**statement, Statement::Query(_))
+        ));
+        assert!(matches!(
+            &stmts[1],
+            Statement::Explain { analyze: true, statement, .. } if matches!(**statement, Statement::Delete(_))
+        ));
+
+        let stmts =
+            parse("EXPLAIN (ANALYZE, FORMAT JSON) SELECT 1; EXPLAIN (FORMAT dot, ANALYZE false) (SELECT 1)")
+                .unwrap();
+        assert!(matches!(
+            &stmts[0],
+            Statement::Explain {
+                analyze: true,
+                format: ExplainFormat::Json,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &stmts[1],
+            Statement::Explain {
+                analyze: false,
+                format: ExplainFormat::Dot,
+                ..
+            }
+        ));
+        let stmts = parse("EXPLAIN (SELECT 1)").unwrap();
+        assert!(matches!(
+            &stmts[0],
+            Statement::Explain {
+                format: ExplainFormat::Text,
+                ..
+            }
+        ));
+        let err = parse("EXPLAIN (FORMAT yaml) SELECT 1").unwrap_err();
+        assert_eq!(err.message, "EXPLAIN format \"yaml\" not recognized");
+
+        let err = parse("EXPLAIN EXPLAIN SELECT 1").unwrap_err();
+        assert_eq!(err.message, "EXPLAIN cannot be nested");
+        assert_eq!(err.span.column, 9);
+    }
+
+    #[test]
+    fn test_parse_analyze() {
+        let stmts = parse("ANALYZE; ANALYZE t; EXPLAIN ANALYZE SELECT 1").unwrap();
+        assert_eq!(stmts[0], Statement::Analyze { table: None });
+        assert_eq!(
+            stmts[1],
+            Statement::Analyze {
+                table: Some("t".to_string())
+            }
+        );
+        assert!(matches!(stmts[2], Statement::Explain { analyze: true, .. }));
+
+        let stmts = parse("VACUUM; VACUUM t").unwrap();
+        assert_eq!(stmts[0], Statement::Vacuum { table: None });
+        assert_eq!(
+            stmts[1],
+            Statement::Vacuum {
+                table: Some("t".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_error_position() {
+        let err = parse("SELECT a\nFROM WHERE").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (2, 6));
+        assert_eq!(err.found.as_deref(), Some("WHERE"));
+        assert_eq!(
+            err.expected,
+            vec!["\"(\"".to_string(), "identifier".to_string()]
+        );
+        assert_eq!(
+            err.to_string(),
+            "syntax error at line 2, column 6: unexpected WHERE, expected one of \"(\", identifier"
+        );
+        assert_eq!(
+            err.underline("SELECT a\nFROM WHERE"),
+            "FROM WHERE\n     ^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_error_expected_list() {
+        let err = parse("SELECT a FROM t WHERE").unwrap_err();
+        assert_eq!(err.found.as_deref(), Some("end of input"));
+        assert_eq!(
+            err.expected,
+            vec!["NOT".to_string(), "expression".to_string()]
+        );
+
+        let err = parse("FOO").unwrap_err();
+        assert!(err.expected.contains(&"SELECT".to_string()));
+        assert!(err.expected.contains(&"CREATE".to_string()));
+        // 後から足した文も挙げる
+        for word in [
+            "COPY",
+            "SET",
+            "SHOW",
+            "ALTER",
+            "GRANT",
+            "SAVEPOINT",
+            "START",
+        ] {
+            assert!(err.expected.contains(&word.to_string()), "{}", word);
+        }
+
+        let err = parse("SELECT CAST(1 AS xml)").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (1, 18));
+        assert!(err.to_string().ends_with("type \"xml\" does not exist"));
+    }
+
+    #[test]
+    fn test_parse_add_column() {
+        for sql in [
+            "ALTER TABLE t ADD COLUMN b TEXT COLLATE nocase",
+            "ALTER TABLE t ADD b TEXT COLLATE nocase",
+        ] {
+            let stmts = parse(sql).unwrap();
+            let Statement::AlterTable {
+                name,
+                action: AlterTableAction::AddColumn(column),
+            } = &stmts[0]
+            else {
+                panic!("expected ADD COLUMN");
+            };
+            assert_eq!(name, "t");
+            assert_eq!(column.name, "b");
+            assert_eq!(column.data_type, "TEXT");
+            assert_eq!(column.collation.as_deref(), Some("nocase"));
+        }
+        assert!(parse("ALTER TABLE t ADD").is_err());
+    }
+}
@@ -0,0 +1,890 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::catalog;
+use crate::collation::Collation;
+use crate::connection::{self, input_value, Connection};
+use crate::sql::ast::{ColumnDef, CreateIndex, CreateTable, Statement};
+use crate::types::{DataType, Value};
+
+// SQLite のデータベースファイルを読んで、テーブルとインデックスと行をこちらに作り直す
+//
+// ファイルは読むだけで、ページを直接たどる。UTF-8 のファイルだけ扱い、ロールバックジャーナルや WAL に
+// 残った変更は見ない。CREATE TABLE の SQL から列の名前と型と制約を読み、型は SQLite の型の親和性で
+// こちらの型に決める。DEFAULT や CHECK や外部キーは写さない。
+// INTEGER PRIMARY KEY の列は rowid の別名なので、行の rowid を値にする。
+// WITHOUT ROWID のテーブルとビューとトリガー、列だけでないインデックスは作らず ImportStats に残す
+
+// 取り込みで一度に入れる行の数
+const IMPORT_BATCH: usize = 1000;
+// たどる B 木の深さの上限。壊れたファイルで回り続けないようにする
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("file is not a SQLite database")]
+    NotSqlite,
+    #[error("unsupported SQLite text encoding {0}")]
+    UnsupportedEncoding(u32),
+    #[error("malformed SQLite database: {0}")]
+    Corrupt(String),
+}
+
+// sqlite_schema の 1 行
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaEntry {
+    // table、index、view、trigger のどれか
+    pub kind: String,
+    pub name: String,
+    pub table: String,
+    pub root: u32,
+    pub sql: Option<String>,
+}
+
+pub struct SqliteFile {
+    file: File,
+    page_size: usize,
+    // ページの末尾の予約を除いた大きさ
+    usable: usize,
+    pages: u32,
+}
+
+impl SqliteFile {
+    pub fn open(path: impl AsRef<Path>) -> Result<SqliteFile, Error> {
+        let mut file = File::open(path)?;
+        let mut header = [0; 100];
+        file.read_exact(&mut header).map_err(|_| Error::NotSqlite)?;
+        if &header[..16] != b"SQLite format 3\0" {
+            return Err(Error::NotSqlite);
+        }
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            n if n >= 512 && n.is_power_of_two() => n as usize,
+            n => return Err(Error::Corrupt(format!("invalid page size {}", n))),
+        };
+        let encoding = u32::from_be_bytes(header[56..60].try_into().unwrap());
+        // 0 はまだ何も書いていないファイル
+        if encoding > 1 {
+            return Err(Error::UnsupportedEncoding(encoding));
+        }
+        let usable = page_size - header[20] as usize;
+        let pages = (file.metadata()?.len() / page_size as u64) as u32;
+        Ok(SqliteFile {
+            file,
+            page_size,
+            usable,
+            pages,
+        })
+    }
+
+    fn page(&mut self, n: u32) -> Result<Vec<u8>, Error> {
+        if n == 0 || n > self.pages {
+            return Err(Error::Corrupt(format!("page {} out of range", n)));
+        }
+        let mut page = vec![0; self.page_size];
+        self.file
+            .seek(SeekFrom::Start((n as u64 - 1) * self.page_size as u64))?;
+        self.file.read_exact(&mut page)?;
+        Ok(page)
+    }
+
+    pub fn schema(&mut self) -> Result<Vec<SchemaEntry>, Error> {
+        let mut entries = vec![];
+        self.scan(1, &mut |_, values| -> Result<(), Error> {
+            let text = |value: Option<&Value>| match value {
+                Some(Value::Text(s)) => Some(s.clone()),
+                _ => None,
+            };
+            let root = match values.get(3) {
+                Some(Value::BigInt(n)) => *n as u32,
+                _ => 0,
+            };
+            entries.push(SchemaEntry {
+                kind: text(values.first()).unwrap_or_default(),
+                name: text(values.get(1)).unwrap_or_default(),
+                table: text(values.get(2)).unwrap_or_default(),
+                root,
+                sql: text(values.get(4)),
+            });
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    // root から始まるテーブルの B 木の行を rowid の順に f に渡す
+    pub fn scan<E: From<Error>>(
+        &mut self,
+        root: u32,
+        f: &mut dyn FnMut(i64, Vec<Value>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.scan_page(root, 0, f)
+    }
+
+    fn scan_page<E: From<Error>>(
+        &mut self,
+        n: u32,
+        depth: usize,
+        f: &mut dyn FnMut(i64, Vec<Value>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let corrupt = |message: &str| Error::Corrupt(format!("{} on page {}", message, n));
+        if depth > MAX_DEPTH {
+            return Err(corrupt("b-tree too deep").into());
+        }
+        let page = self.page(n)?;
+        let base = if n == 1 { 100 } else { 0 };
+        let cells = u16_at(&page, base + 3) as usize;
+        match page[base] {
+            // 中間のページ。セルは左の子と rowid、右端の子は見出しにある
+            0x05 => {
+                for i in 0..cells {
+                    let offset = u16_at(&page, base + 12 + i * 2) as usize;
+                    let child = page
+                        .get(offset..offset + 4)
+                        .ok_or_else(|| corrupt("cell out of range"))?;
+                    let child = u32::from_be_bytes(child.try_into().unwrap());
+                    self.scan_page(child, depth + 1, f)?;
+                }
+                let right = u32::from_be_bytes(page[base + 8..base + 12].try_into().unwrap());
+                self.scan_page(right, depth + 1, f)
+            }
+            0x0d => {
+                for i in 0..cells {
+                    let mut pos = u16_at(&page, base + 8 + i * 2) as usize;
+                    let size = varint(&page, &mut pos).ok_or_else(|| corrupt("bad cell"))?;
+                    let rowid = varint(&page, &mut pos).ok_or_else(|| corrupt("bad cell"))?;
+                    let payload = self.payload(&page, pos, size as usize)?;
+                    f(rowid as i64, record(&payload)?)?;
+                }
+                Ok(())
+            }
+            kind => Err(corrupt(&format!("unexpected page type {}", kind)).into()),
+        }
+    }
+
+    // 葉のセルの中身。入りきらない分はオーバーフローページの連なりから読む
+    fn payload(&mut self, page: &[u8], pos: usize, size: usize) -> Result<Vec<u8>, Error> {
+        let corrupt = || Error::Corrupt("cell payload out of range".to_string());
+        let max_local = self.usable - 35;
+        let min_local = (self.usable - 12) * 32 / 255 - 23;
+        if size <= max_local {
+            return Ok(page.get(pos..pos + size).ok_or_else(corrupt)?.to_vec());
+        }
+        let local = match min_local + (size - min_local) % (self.usable - 4) {
+            k if k <= max_local => k,
+            _ => min_local,
+        };
+        let mut data = page.get(pos..pos + local).ok_or_else(corrupt)?.to_vec();
+        let next = page.get(pos + local..pos + local + 4).ok_or_else(corrupt)?;
+        let mut next = u32::from_be_bytes(next.try_into().unwrap());
+        let mut visited = 0;
+        while data.len() < size {
+            visited += 1;
+            if next == 0 || visited > self.pages {
+                return Err(Error::Corrupt("broken overflow chain".to_string()));
+            }
+            let overflow = self.page(next)?;
+            next = u32::from_be_bytes(overflow[..4].try_into().unwrap());
+            let take = (size - data.len()).min(self.usable - 4);
+            data.extend_from_slice(&overflow[4..4 + take]);
+        }
+        Ok(data)
+    }
+}
+
+fn u16_at(page: &[u8], pos: usize) -> u16 {
+    match page.get(pos..pos + 2) {
+        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+        None => 0,
+    }
+}
+
+// 上位から 7 ビットずつの可変長整数。9 バイト目だけは 8 ビットすべてを使う
+fn varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let b = *bytes.get(*pos)?;
+        *pos += 1;
+        if i == 8 {
+            return Some((value << 8) | b as u64);
+        }
+        value = (value << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    Some(value)
+}
+
+// レコードの見出しの型の並びに従って値を読む
+fn record(payload: &[u8]) -> Result<Vec<Value>, Error> {
+    let corrupt = || Error::Corrupt("malformed record".to_string());
+    let mut pos = 0;
+    let header = varint(payload, &mut pos).ok_or_else(corrupt)? as usize;
+    let mut types = vec![];
+    while pos < header {
+        types.push(varint(payload, &mut pos).ok_or_else(corrupt)?);
+    }
+    let mut pos = header;
+    let mut values = Vec::with_capacity(types.len());
+    for ty in types {
+        let len = match ty {
+            0 | 8 | 9 => 0,
+            1..=4 => ty as usize,
+            5 => 6,
+            6 | 7 => 8,
+            10 | 11 => return Err(corrupt()),
+            n => (n as usize - 12) / 2,
+        };
+        let bytes = payload.get(pos..pos + len).ok_or_else(corrupt)?;
+        pos += len;
+        values.push(match ty {
+            0 => Value::Null,
+            8 => Value::BigInt(0),
+            9 => Value::BigInt(1),
+            7 => Value::Real(f64::from_be_bytes(bytes.try_into().unwrap())),
+            1..=6 => {
+                let first = (bytes[0] as i8) as i64;
+                Value::BigInt(bytes[1..].iter().fold(first, |n, &b| (n << 8) | b as i64))
+            }
+            n if n % 2 == 0 => Value::Blob(bytes.into()),
+            _ => Value::Text(String::from_utf8(bytes.to_vec()).map_err(|_| corrupt())?),
+        });
+    }
+    Ok(values)
+}
+
+// 取り込んだものの数と、作らなかったもの
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportStats {
+    pub tables: usize,
+    pub rows: usize,
+    pub indexes: usize,
+    pub skipped: Vec<String>,
+}
+
+// path の SQLite データベースを conn のデータベースに取り込む。
+// テーブルを作ってから行を IMPORT_BATCH 行ずつ入れ、最後にインデックスを作る。
+// 行はひとつのトランザクションで入れるので、どこかの行で失敗すれば 1 行も入らない (テーブルは残る)
+pub fn import(
+    conn: &mut Connection,
+    path: impl AsRef<Path>,
+) -> Result<ImportStats, connection::Error> {
+    let mut file = SqliteFile::open(path)?;
+    let schema = file.schema()?;
+    let mut stats = ImportStats::default();
+    let mut tables = vec![];
+    for entry in &schema {
+        if entry.name.starts_with("sqlite_") {
+            continue;
+        }
+        let sql = entry.sql.as_deref().unwrap_or_default();
+        match entry.kind.as_str() {
+            "table" => {
+                let Some(table) = parse_table(&entry.name, sql, &mut stats.skipped) else {
+                    stats
+                        .skipped
+                        .push(format!("WITHOUT ROWID table \"{}\"", entry.name));
+                    continue;
+                };
+                conn.execute_statement(
+                    &Statement::CreateTable(Box::new(table.create.clone())),
+                    &[],
+                )?;
+                stats.tables += 1;
+                tables.push((entry.root, table));
+            }
+            "index" => {}
+            kind => stats.skipped.push(format!("{} \"{}\"", kind, entry.name)),
+        }
+    }
+    stats.rows = if conn.in_transaction() {
+        load(conn, &mut file, &tables)?
+    } else {
+        conn.transaction(|conn| load(conn, &mut file, &tables))?
+    };
+    for table in &tables {
+        for create in &table.1.unique {
+            conn.execute_statement(&Statement::CreateIndex(create.clone()), &[])?;
+        }
+    }
+    for entry in schema.iter().filter(|entry| entry.kind == "index") {
+        // sql のないものは制約が作ったインデックスで、CREATE TABLE で作り直した
+        let Some(sql) = &entry.sql else {
+            continue;
+        };
+        if !tables.iter().any(|(_, table)| table.source == entry.table) {
+            continue;
+        }
+        match parse_index(sql) {
+            Some(create) => {
+                conn.execute_statement(&Statement::CreateIndex(create), &[])?;
+                stats.indexes += 1;
+            }
+            None => stats.skipped.push(format!("index \"{}\"", entry.name)),
+        }
+    }
+    Ok(stats)
+}
+
+fn load(
+    conn: &mut Connection,
+    file: &mut SqliteFile,
+    tables: &[(u32, SqliteTable)],
+) -> Result<usize, connection::Error> {
+    let mut count = 0;
+    for (root, table) in tables {
+        let name = &table.create.name;
+        let columns = conn
+            .catalog()
+            .table(name)
+            .ok_or_else(|| catalog::Error::TableNotFound(name.clone()))?
+            .columns
+            .clone();
+        let context = |rowid, source: connection::Error| connection::Error::Import {
+            table: name.clone(),
+            rowid,
+            source: Box::new(source),
+        };
+        let mut batch = vec![];
+        let mut rowids = vec![];
+        let mut flush = |conn: &mut Connection,
+                         batch: &mut Vec<(usize, Vec<Value>)>,
+                         rowids: &mut Vec<i64>|
+         -> Result<(), connection::Error> {
+            count += conn
+                .insert_batch(name, std::mem::take(batch))
+                .map_err(|(i, e)| context(rowids[i], e))?;
+            rowids.clear();
+            Ok(())
+        };
+        file.scan(*root, &mut |rowid,
+                               mut values|
+         -> Result<(), connection::Error> {
+            // ALTER TABLE で足した列は古い行にない
+            values.resize(columns.len(), Value::Null);
+            if let Some(i) = table.rowid {
+                values[i] = Value::BigInt(rowid);
+            }
+            let mut row = Vec::with_capacity(columns.len());
+            for (column, value) in columns.iter().zip(values) {
+                row.push(match value {
+                    Value::Null => Value::Null,
+                    value => input_value(column, value).map_err(|e| context(rowid, e.into()))?,
+                });
+            }
+            batch.push((rowids.len(), row));
+            rowids.push(rowid);
+            if batch.len() == IMPORT_BATCH {
+                flush(conn, &mut batch, &mut rowids)?;
+            }
+            Ok(())
+        })?;
+        flush(conn, &mut batch, &mut rowids)?;
+    }
+    Ok(count)
+}
+
+struct SqliteTable {
+    // SQLite での名前。インデックスの tbl_name と比べる
+    source: String,
+    create: CreateTable,
+    // rowid の別名の列
+    rowid: Option<usize>,
+    // 複数の列の UNIQUE 制約。行を入れてから作る
+    unique: Vec<CreateIndex>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // そのままの名前や予約語。比べるときは大文字にする
+    Word(String),
+    // "..."、[...]、`...` で囲んだ名前
+    Quoted(String),
+    Str(String),
+    Punct(char),
+}
+
+impl Token {
+    fn is(&self, word: &str) -> bool {
+        matches!(self, Token::Word(w) if w.eq_ignore_ascii_case(word))
+    }
+
+    fn name(&self) -> Option<String> {
+        match self {
+            Token::Word(s) | Token::Quoted(s) | Token::Str(s) => Some(s.to_lowercase()),
+            Token::Punct(_) => None,
+        }
+    }
+}
+
+fn tokenize(sql: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        let close = match c {
+            '"' => '"',
+            '`' => '`',
+            '[' => ']',
+            '\'' => '\'',
+            c if c.is_whitespace() => continue,
+            '-' if chars.peek() == Some(&'-') => {
+                chars.find(|&c| c == '\n');
+                continue;
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '$' => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '$' || c == '.') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+                continue;
+            }
+            c => {
+                tokens.push(Token::Punct(c));
+                continue;
+            }
+        };
+        let mut text = String::new();
+        while let Some(c) = chars.next() {
+            if c == close {
+                // 閉じる文字を 2 つ重ねれば 1 文字
+                if close != ']' && chars.peek() == Some(&close) {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            text.push(c);
+        }
+        tokens.push(match c {
+            '\'' => Token::Str(text),
+            _ => Token::Quoted(text),
+        });
+    }
+    tokens
+}
+
+// 一番外のかっこの中身を , で分けたものと、かっこの後ろ
+fn split_list(tokens: &[Token]) -> Option<(Vec<&[Token]>, &[Token])> {
+    let open = tokens.iter().position(|t| *t == Token::Punct('('))?;
+    let mut items = vec![];
+    let mut depth = 0;
+    let mut start = open + 1;
+    for (i, token) in tokens.iter().enumerate().skip(open + 1) {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') if depth == 0 => {
+                items.push(&tokens[start..i]);
+                return Some((items, &tokens[i + 1..]));
+            }
+            Token::Punct(')') => depth -= 1,
+            Token::Punct(',') if depth == 0 => {
+                items.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// 列の制約を始める語
+const CONSTRAINT_WORDS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+// WITHOUT ROWID のテーブルなら None
+fn parse_table(name: &str, sql: &str, skipped: &mut Vec<String>) -> Option<SqliteTable> {
+    let tokens = tokenize(sql);
+    let (items, rest) = split_list(&tokens)?;
+    if rest.iter().any(|t| t.is("WITHOUT")) {
+        return None;
+    }
+    let table = name.to_lowercase();
+    let mut columns = vec![];
+    let mut primary_key = vec![];
+    let mut unique = vec![];
+    for item in items {
+        let item = match item {
+            [constraint, _, rest @ ..] if constraint.is("CONSTRAINT") => rest,
+            item => item,
+        };
+        let Some(first) = item.first() else {
+            continue;
+        };
+        if first.is("PRIMARY") || first.is("UNIQUE") {
+            let names: Vec<String> = split_list(item)
+                .map(|(keys, _)| keys.iter().filter_map(|k| k.first()?.name()).collect())
+                .unwrap_or_default();
+            if first.is("PRIMARY") {
+                primary_key = names;
+            } else {
+                unique.push(names);
+            }
+            continue;
+        }
+        if first.is("CHECK") || first.is("FOREIGN") {
+            continue;
+        }
+        let Some(column) = first.name() else {
+            continue;
+        };
+        let mut type_name = vec![];
+        let mut i = 1;
+        while let Some(token) = item.get(i) {
+            match token {
+                Token::Word(word) if !CONSTRAINT_WORDS.iter().any(|w| token.is(w)) => {
+                    type_name.push(word.clone());
+                }
+                Token::Punct('(') => {
+                    let end = item[i..].iter().position(|t| *t == Token::Punct(')'))?;
+                    let args: Vec<String> = item[i + 1..i + end]
+                        .iter()
+                        .filter_map(|t| match t {
+                            Token::Word(w) => Some(w.clone()),
+                            Token::Punct(c) => Some(c.to_string()),
+                            _ => None,
+                        })
+                        .collect();
+                    let last = type_name.pop().unwrap_or_default();
+                    type_name.push(format!("{}({})", last, args.concat()));
+                    i += end;
+                }
+                _ => break,
+            }
+            i += 1;
+        }
+        let declared = type_name.join(" ");
+        let mut def = ColumnDef {
+            name: column.clone(),
+            data_type: column_type(&declared).to_string().to_uppercase(),
+            not_null: false,
+            primary_key: false,
+            unique: false,
+            collation: None,
+            generated: None,
+            compression: None,
+        };
+        let mut depth = 0;
+        for (j, token) in item.iter().enumerate().skip(i) {
+            match token {
+                Token::Punct('(') => depth += 1,
+                Token::Punct(')') => depth -= 1,
+                _ if depth > 0 => {}
+                _ if token.is("PRIMARY") => def.primary_key = true,
+                _ if token.is("UNIQUE") => def.unique = true,
+                _ if token.is("NULL") && item[j - 1].is("NOT") => def.not_null = true,
+                _ if token.is("COLLATE") => {
+                    let collation = item.get(j + 1).and_then(Token::name)?;
+                    if Collation::parse(&collation).is_some() {
+                        def.collation = Some(collation);
+                    } else {
+                        skipped.push(format!(
+                            "collation {} of \"{}.{}\"",
+                            collation, table, column
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        columns.push((declared, def));
+    }
+    if primary_key.is_empty() {
+        primary_key = columns
+            .iter()
+            .filter(|(_, def)| def.primary_key)
+            .map(|(_, def)| def.name.clone())
+            .collect();
+    }
+    // 主キーが INTEGER の 1 列だけなら rowid の別名
+    let rowid = match &primary_key[..] {
+        [key] => columns.iter().position(|(declared, def)| {
+            def.name == *key && declared.eq_ignore_ascii_case("INTEGER")
+        }),
+        _ => None,
+    };
+    let mut columns: Vec<ColumnDef> = columns.into_iter().map(|(_, def)| def).collect();
+    let mut indexes = vec![];
+    for names in unique {
+        match &names[..] {
+            [name] => columns
+                .iter_mut()
+                .filter(|def| def.name == *name)
+                .for_each(|def| def.unique = true),
+            _ => indexes.push(CreateIndex {
+                name: format!("{}_{}_key", table, names.join("_")),
+                table: table.clone(),
+                columns: names,
+                expression: None,
+                unique: true,
+                fulltext: false,
+                rtree: false,
+            }),
+        }
+    }
+    for def in &mut columns {
+        def.primary_key = false;
+    }
+    Some(SqliteTable {
+        source: name.to_string(),
+        create: CreateTable {
+            name: table,
+            if_not_exists: false,
+            columns,
+            primary_key,
+            partition_by: None,
+            partition_of: None,
+            ttl: None,
+            using: None,
+            options: vec![],
+        },
+        rowid,
+        unique: indexes,
+    })
+}
+
+// 宣言した型の名前からこちらの型を決める。知らない名前は SQLite の型の親和性で決め、
+// 決められなければどんな値も入る TEXT にする。SQLite の整数は 64 bit なので INTEGER も BIGINT にする
+fn column_type(declared: &str) -> DataType {
+    match DataType::parse(declared) {
+        Some(DataType::Integer) => return DataType::BigInt,
+        Some(ty) => return ty,
+        None => {}
+    }
+    let upper = declared.to_uppercase();
+    if upper.contains("INT") {
+        DataType::BigInt
+    } else if ["CHAR", "CLOB", "TEXT"].iter().any(|s| upper.contains(s)) {
+        DataType::Text
+    } else if upper.contains("BLOB") {
+        DataType::Blob
+    } else if ["REAL", "FLOA", "DOUB"].iter().any(|s| upper.contains(s)) {
+        DataType::Real
+    } else {
+        DataType::Text
+    }
+}
+
+// CREATE [UNIQUE] INDEX [IF NOT EXISTS] name ON table (column [ASC | DESC], ...)。
+// 式や COLLATE を含むものと、WHERE のある部分インデックスは None
+fn parse_index(sql: &str) -> Option<CreateIndex> {
+    let tokens = tokenize(sql);
+    let unique = tokens.get(1)?.is("UNIQUE");
+    let on = tokens.iter().position(|t| t.is("ON"))?;
+    let name = tokens.get(on.checked_sub(1)?)?.name()?;
+    let table = tokens.get(on + 1)?.name()?;
+    let (keys, rest) = split_list(&tokens[on..])?;
+    if !rest.is_empty() {
+        return None;
+    }
+    let columns = keys
+        .iter()
+        .map(|key| {
+            let (column, order) = match key {
+                [column] => (column, None),
+                [column, order] => (column, Some(order)),
+                _ => return None,
+            };
+            if order.is_some_and(|t| !t.is("ASC") && !t.is("DESC"))
+                || !matches!(column, Token::Word(_) | Token::Quoted(_))
+            {
+                return None;
+            }
+            column.name()
+        })
+        .collect::<Option<Vec<String>>>()?;
+    Some(CreateIndex {
+        name,
+        table,
+        columns,
+        expression: None,
+        unique,
+        fulltext: false,
+        rtree: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Database;
+
+    #[test]
+    fn test_varint_and_record() {
+        let mut pos = 0;
+        assert_eq!(varint(&[0x81, 0x00], &mut pos), Some(128));
+        assert_eq!(pos, 2);
+        let mut pos = 0;
+        assert_eq!(varint(&[0xff; 9], &mut pos), Some(u64::MAX));
+        assert_eq!(varint(&[0x80], &mut 0), None);
+
+        // NULL、1 バイトの -2、1、3 バイトの文字列 "abc"、2 バイトのブロブ
+        let payload = [6, 0, 1, 9, 19, 16, 0xfe, b'a', b'b', b'c', 1, 2];
+        assert_eq!(
+            record(&payload).unwrap(),
+            vec![
+                Value::Null,
+                Value::BigInt(-2),
+                Value::BigInt(1),
+                Value::Text("abc".to_string()),
+                Value::Blob(vec![1, 2].into()),
+            ]
+        );
+        assert!(record(&[2, 21, b'x']).is_err());
+    }
+
+    #[test]
+    fn test_parse_schema() {
+        let mut skipped = vec![];
+        let table = parse_table(
+            "T",
+            "CREATE TABLE \"T\" (id INTEGER PRIMARY KEY, [Name] varchar(20) NOT NULL COLLATE NOCASE,
+             n UNSIGNED BIG INT DEFAULT (1), x, y CHECK (y NOT NULL), z TEXT COLLATE RTRIM,
+             CONSTRAINT u UNIQUE (n, x))",
+            &mut skipped,
+        )
+        .unwrap();
+        assert_eq!(table.rowid, Some(0));
+        assert_eq!(table.create.primary_key, vec!["id"]);
+        let types: Vec<&str> = table
+            .create
+            .columns
+            .iter()
+            .map(|c| c.data_type.as_str())
+            .collect();
+        assert_eq!(types, ["BIGINT", "TEXT", "BIGINT", "TEXT", "TEXT", "TEXT"]);
+        assert!(table.create.columns[1].not_null && !table.create.columns[4].not_null);
+        assert_eq!(table.create.columns[1].collation.as_deref(), Some("nocase"));
+        assert_eq!(table.unique[0].columns, vec!["n", "x"]);
+        assert_eq!(skipped, ["collation rtrim of \"t.z\""]);
+        assert!(parse_table(
+            "k",
+            "CREATE TABLE k (a PRIMARY KEY) WITHOUT ROWID",
+            &mut skipped
+        )
+        .is_none());
+
+        let index =
+            parse_index("CREATE UNIQUE INDEX IF NOT EXISTS \"I\" ON t (a DESC, [b])").unwrap();
+        assert_eq!((index.name.as_str(), index.unique), ("i", true));
+        assert_eq!(index.columns, vec!["a", "b"]);
+        assert!(parse_index("CREATE INDEX i ON t (lower(a))").is_none());
+        assert!(parse_index("CREATE INDEX i ON t (a) WHERE a > 0").is_none());
+        assert!(parse_index("CREATE INDEX i ON t (a COLLATE nocase)").is_none());
+    }
+
+    #[test]
+    fn test_import() {
+        // Python の sqlite3 でページの大きさを 512 にして作ったファイル。
+        // users は中間ページとオーバーフローページを持ち、ALTER TABLE で列を足してある
+        let path = std::env::temp_dir().join(format!("rdbms_import_{}.sqlite", std::process::id()));
+        std::fs::write(&path, include_bytes!("../testdata/import.sqlite")).unwrap();
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let stats = import(&mut conn, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((stats.tables, stats.rows, stats.indexes), (2, 63, 1));
+        assert_eq!(
+            stats.skipped,
+            ["WITHOUT ROWID table \"kv\"", "index \"users_active\""]
+        );
+        let row = conn
+            .query_row(
+                "SELECT count(*), sum(id), max(length(note)), count(email), count(avatar) FROM users",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(
+            row.into_values(),
+            vec![
+                Value::BigInt(60),
+                Value::BigInt(1830),
+                Value::BigInt(2000),
+                Value::BigInt(54),
+                Value::BigInt(20),
+            ]
+        );
+        let row = conn
+            .query_row(
+                "SELECT name, balance, active, joined, avatar, note, extra FROM users WHERE id = 60",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(
+            row.into_values()
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "user60",
+                "60.25",
+                "false",
+                "2024-01-05",
+                "\\x3c00ff",
+                "60",
+                "42"
+            ]
+        );
+        let row = conn
+            .query_row("SELECT qty FROM \"order items\" WHERE user_id = 2", &[])
+            .unwrap();
+        assert_eq!(row.into_values(), vec![Value::BigInt(i64::MIN)]);
+
+        // 制約とインデックスも作り直している
+        let err = conn
+            .execute("INSERT INTO users (id, name) VALUES (1, 'x')", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "23505");
+        let err = conn
+            .execute(
+                "INSERT INTO users (id, name, email) VALUES (61, 'x', 'U1@example.com')",
+                &[],
+            )
+            .err();
+        assert!(err.is_none());
+        let err = conn
+            .execute("INSERT INTO users (id, name) VALUES (62, NULL)", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "23502");
+        let count = conn
+            .query_row("SELECT count(*) FROM users WHERE name = 'USER7'", &[])
+            .unwrap();
+        assert_eq!(count.into_values(), vec![Value::BigInt(1)]);
+        assert!(conn
+            .catalog()
+            .table("users")
+            .unwrap()
+            .indexes
+            .iter()
+            .any(|i| i.name == "users_score"));
+
+        // 同じ名前のテーブルがあれば失敗する
+        std::fs::write(&path, include_bytes!("../testdata/import.sqlite")).unwrap();
+        assert!(import(&mut conn, &path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            import(&mut conn, "Cargo.toml"),
+            Err(connection::Error::Sqlite(Error::NotSqlite))
+        ));
+    }
+}
@@ -0,0 +1,471 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::datetime::Timestamp;
+use crate::disk::PageId;
+use crate::executor::{Error, ExecContext, Row};
+use crate::types::Value;
+
+// エンジンの内部を問い合わせで読むための stats スキーマの表
+//
+// stats.buffer_pool はフレームごとの状態とプール全体のヒット率、stats.tables はテーブルごとのページと版の数と、
+// 最後に ANALYZE してから書き換えた行の数と自動の VACUUM と ANALYZE の時刻、
+// stats.wal は WAL の位置と残しているセグメントの数、stats.activity は接続ごとの実行中か最後の文を返す。
+// stats.io はテーブルとインデックスごとの、走査の回数と読んだページと汚したページの数を返す。
+// stats.materialized_views は実体化したビューごとの、最後に作り直した時刻と、読んだテーブルがそれから変わったかを返す。
+// stats.roles はロールを、stats.table_privileges は GRANT で与えた権限を 1 つずつ返す。パスワードのハッシュは見せない。
+// 値は読み始めたときに集める。stats.tables はヒープをすべて読むので、大きなテーブルがあると遅い
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsView {
+    BufferPool,
+    Tables,
+    Wal,
+    Activity,
+    Io,
+    MaterializedViews,
+    Roles,
+    TablePrivileges,
+}
+
+impl StatsView {
+    pub fn lookup(name: &str) -> Option<StatsView> {
+        Some(match name {
+            "stats.buffer_pool" => StatsView::BufferPool,
+            "stats.tables" => StatsView::Tables,
+            "stats.wal" => StatsView::Wal,
+            "stats.activity" => StatsView::Activity,
+            "stats.io" => StatsView::Io,
+            "stats.materialized_views" => StatsView::MaterializedViews,
+            "stats.roles" => StatsView::Roles,
+            "stats.table_privileges" => StatsView::TablePrivileges,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StatsView::BufferPool => "stats.buffer_pool",
+            StatsView::Tables => "stats.tables",
+            StatsView::Wal => "stats.wal",
+            StatsView::Activity => "stats.activity",
+            StatsView::Io => "stats.io",
+            StatsView::MaterializedViews => "stats.materialized_views",
+            StatsView::Roles => "stats.roles",
+            StatsView::TablePrivileges => "stats.table_privileges",
+        }
+    }
+
+    pub fn columns(self) -> &'static [&'static str] {
+        match self {
+            StatsView::BufferPool => &[
+                "buffer_id",
+                "page_id",
+                "dirty",
+                "pins",
+                "usage_count",
+                "hit_ratio",
+            ],
+            StatsView::Tables => &[
+                "name",
+                "pages",
+                "tuples",
+                "dead_tuples",
+                "modifications",
+                "last_autovacuum",
+                "last_autoanalyze",
+            ],
+            StatsView::Wal => &[
+                "current_lsn",
+                "flushed_lsn",
+                "first_lsn",
+                "segments_retained",
+            ],
+            StatsView::Activity => &["session_id", "state", "query", "query_start"],
+            StatsView::Io => &[
+                "name",
+                "kind",
+                "seq_scans",
+                "index_scans",
+                "buffer_hits",
+                "buffer_reads",
+                "pages_dirtied",
+            ],
+            StatsView::MaterializedViews => &["name", "query", "refreshed_at", "stale"],
+            StatsView::Roles => &["name", "superuser", "login"],
+            StatsView::TablePrivileges => &["table_name", "grantee", "privilege"],
+        }
+    }
+
+    pub fn rows(self, ctx: &mut ExecContext) -> Result<Vec<Row>, Error> {
+        match self {
+            StatsView::BufferPool => {
+                let (hits, reads) = (ctx.bufmgr.hits(), ctx.bufmgr.reads());
+                let ratio = if hits + reads == 0 {
+                    0.0
+                } else {
+                    hits as f64 / (hits + reads) as f64
+                };
+                Ok(ctx
+                    .bufmgr
+                    .frames()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (page_id, dirty, pins, usage))| {
+                        let page_id = match page_id {
+                            PageId::INVALID_PAGE_ID => Value::Null,
+                            page_id => Value::BigInt(page_id.to_u64() as i64),
+                        };
+                        vec![
+                            Value::Integer(i as i64),
+                            page_id,
+                            Value::Boolean(dirty),
+                            Value::Integer(pins as i64),
+                            Value::BigInt(usage as i64),
+                            Value::Real(ratio),
+                        ]
+                    })
+                    .collect())
+            }
+            // 消えた版は、消したトランザクションがコミットしたかにかかわらず数える
+            StatsView::Tables => {
+                let mut rows = vec![];
+                for table in ctx.catalog.tables() {
+                    let pages = table.storage.page_ids(ctx.bufmgr)?.len();
+                    let (mut live, mut dead) = (0, 0);
+                    let mut scan = table.storage.scan();
+                    while let Some((_, header, _)) = scan.next(ctx.bufmgr)? {
+                        if header.is_deleted() {
+                            dead += 1;
+                        } else {
+                            live += 1;
+                        }
+                    }
+                    rows.push(vec![
+                        Value::Text(table.name.clone()),
+                        Value::BigInt(pages as i64),
+                        Value::BigInt(live),
+                        Value::BigInt(dead),
+                        Value::BigInt(table.changes_since_analyze() as i64),
+                        table
+                            .last_autovacuum
+                            .get()
+                            .map_or(Value::Null, Value::Timestamp),
+                        table
+                            .last_autoanalyze
+                            .get()
+                            .map_or(Value::Null, Value::Timestamp),
+                    ]);
+                }
+                Ok(rows)
+            }
+            // WAL を使っていなければ行を返さない
+            StatsView::Wal => Ok(ctx
+                .bufmgr
+                .wal()
+                .map(|wal| {
+                    let lsn = |lsn: crate::wal::Lsn| Value::BigInt(lsn.to_u64() as i64);
+                    vec![
+                        lsn(wal.next_lsn()),
+                        lsn(wal.flushed_lsn()),
+                        lsn(wal.first_lsn()),
+                        Value::BigInt(wal.retained_segments() as i64),
+                    ]
+                })
+                .into_iter()
+                .collect()),
+            StatsView::Activity => Ok(ctx.activity.map_or_else(Vec::new, Activity::rows)),
+            // 今あるテーブルとインデックスを、一度も読んでいないものも含めて返す
+            StatsView::Io => {
+                let stats = ctx.io.map(|io| io.relations.borrow());
+                let mut rows = vec![];
+                for table in ctx.catalog.tables() {
+                    let names = std::iter::once((&table.name, "table"))
+                        .chain(table.indexes.iter().map(|index| (&index.name, "index")));
+                    for (name, kind) in names {
+                        let io = stats
+                            .as_ref()
+                            .and_then(|stats| stats.get(name))
+                            .copied()
+                            .unwrap_or_default();
+                        rows.push(vec![
+                            Value::Text(name.clone()),
+                            Value::Text(kind.to_string()),
+                            Value::BigInt(io.seq_scans as i64),
+                            Value::BigInt(io.index_scans as i64),
+                            Value::BigInt(io.hits as i64),
+                            Value::BigInt(io.reads as i64),
+                            Value::BigInt(io.dirtied as i64),
+                        ]);
+                    }
+                }
+                Ok(rows)
+            }
+            StatsView::MaterializedViews => Ok(ctx
+                .catalog
+                .tables()
+                .iter()
+                .filter_map(|table| {
+                    let view = table.view.as_ref()?;
+                    Some(vec![
+                        Value::Text(table.name.clone()),
+                        Value::Text(view.query.clone()),
+                        Value::Timestamp(view.refreshed_at),
+                        Value::Boolean(ctx.catalog.is_stale(view)),
+                    ])
+                })
+                .collect()),
+            StatsView::Roles => Ok(ctx
+                .catalog
+                .roles()
+                .iter()
+                .map(|role| {
+                    vec![
+                        Value::Text(role.name.clone()),
+                        Value::Boolean(role.superuser),
+                        Value::Boolean(role.login),
+                    ]
+                })
+                .collect()),
+            StatsView::TablePrivileges => Ok(ctx
+                .catalog
+                .grants()
+                .iter()
+                .map(|grant| {
+                    vec![
+                        Value::Text(grant.table.clone()),
+                        Value::Text(grant.role.clone()),
+                        Value::Text(grant.privilege.to_string()),
+                    ]
+                })
+                .collect()),
+        }
+    }
+}
+
+// テーブルとインデックスごとの読み書き
+//
+// 読んだページはその演算子がヒープを読んだのかインデックスを読んだのかで分ける。
+// 書き換える演算子が汚したページは、インデックスのページも含めてテーブルに数える。
+// テーブルを消すと、そのテーブルとインデックスの数も消す
+#[derive(Debug, Default)]
+pub struct IoStats {
+    relations: RefCell<BTreeMap<String, RelationIo>>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RelationIo {
+    pub seq_scans: u64,
+    // テーブルではそのテーブルを、インデックスではそのインデックスを使って探した回数
+    pub index_scans: u64,
+    pub hits: u64,
+    pub reads: u64,
+    pub dirtied: u64,
+}
+
+impl IoStats {
+    pub fn get(&self, name: &str) -> RelationIo {
+        self.relations
+            .borrow()
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn update(&self, name: &str, f: impl FnOnce(&mut RelationIo)) {
+        let mut relations = self.relations.borrow_mut();
+        match relations.get_mut(name) {
+            Some(io) => f(io),
+            None => f(relations.entry(name.to_string()).or_default()),
+        }
+    }
+
+    pub(crate) fn forget(&self, name: &str) {
+        self.relations.borrow_mut().remove(name);
+    }
+}
+
+// 接続ごとの、実行中か最後に実行した文
+#[derive(Debug, Default)]
+pub struct Activity {
+    sessions: BTreeMap<u64, SessionActivity>,
+}
+
+#[derive(Debug, Default)]
+struct SessionActivity {
+    query: Option<String>,
+    query_start: Option<Timestamp>,
+    running: bool,
+    in_transaction: bool,
+}
+
+impl Activity {
+    pub(crate) fn connect(&mut self, id: u64) {
+        self.sessions.insert(id, SessionActivity::default());
+    }
+
+    pub(crate) fn disconnect(&mut self, id: u64) {
+        self.sessions.remove(&id);
+    }
+
+    pub(crate) fn start(&mut self, id: u64, sql: &str) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.query = Some(sql.to_string());
+            session.query_start = Some(Timestamp::now());
+            session.running = true;
+        }
+    }
+
+    pub(crate) fn finish(&mut self, id: u64, in_transaction: bool) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.running = false;
+            session.in_transaction = in_transaction;
+        }
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        self.sessions
+            .iter()
+            .map(|(id, session)| {
+                let state = match session {
+                    SessionActivity { running: true, .. } => "active",
+                    SessionActivity {
+                        in_transaction: true,
+                        ..
+                    } => "idle in transaction",
+                    _ => "idle",
+                };
+                vec![
+                    Value::BigInt(*id as i64),
+                    Value::Text(state.to_string()),
+                    session.query.clone().map_or(Value::Null, Value::Text),
+                    session.query_start.map_or(Value::Null, Value::Timestamp),
+                ]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::Database;
+    use crate::types::Value;
+
+    #[test]
+    fn test_stats_views() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let mut other = db.connect();
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER); INSERT INTO t VALUES (1), (2), (3); DELETE FROM t WHERE a = 2",
+        )
+        .unwrap();
+        let row = conn
+            .query_row(
+                "SELECT pages, tuples, dead_tuples FROM stats.tables WHERE name = 't'",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(
+            row.values(),
+            [Value::BigInt(1), Value::BigInt(2), Value::BigInt(1)]
+        );
+
+        let row = conn
+            .query_row(
+                "SELECT count(*), count(page_id), max(hit_ratio) > 0 FROM stats.buffer_pool b WHERE b.pins = 0",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(row.values()[0], Value::BigInt(1024));
+        assert_eq!(row.values()[2], Value::Boolean(true));
+
+        other.execute("BEGIN", &[]).unwrap();
+        let sql = "SELECT session_id, state, query FROM stats.activity";
+        let states: Vec<_> = conn
+            .query(sql, &[])
+            .unwrap()
+            .map(|row| (row.values()[1].clone(), row.values()[2].clone()))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (Value::Text("active".into()), Value::Text(sql.to_string())),
+                (
+                    Value::Text("idle in transaction".into()),
+                    Value::Text("BEGIN".into())
+                ),
+            ]
+        );
+        drop(other);
+        assert_eq!(conn.query(sql, &[]).unwrap().len(), 1);
+
+        // WAL を使わないデータベースでは行がない
+        assert_eq!(conn.query("SELECT * FROM stats.wal", &[]).unwrap().len(), 0);
+        assert!(conn.query("SELECT * FROM stats.nope", &[]).is_err());
+    }
+
+    #[test]
+    fn test_io_stats() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT)", &[])
+            .unwrap();
+        let rows: Vec<(i64, String)> = (0..500).map(|i| (i, "x".repeat(100))).collect();
+        conn.execute_many("INSERT INTO t VALUES ($1, $2)", rows)
+            .unwrap();
+        conn.execute("ANALYZE t", &[]).unwrap();
+        conn.query("SELECT count(*) FROM t WHERE b = 'y'", &[])
+            .unwrap();
+        conn.query("SELECT b FROM t WHERE a = 7", &[]).unwrap();
+        let io = |conn: &mut crate::connection::Connection| -> Vec<Vec<Value>> {
+            conn.query(
+                "SELECT name, kind, seq_scans, index_scans, buffer_hits > 0, pages_dirtied > 0 FROM stats.io",
+                &[],
+            )
+            .unwrap()
+            .map(|row| row.values().to_vec())
+            .collect()
+        };
+        let (int, text, boolean) = (
+            Value::BigInt,
+            |s: &str| Value::Text(s.into()),
+            Value::Boolean,
+        );
+        assert_eq!(
+            io(&mut conn),
+            [
+                vec![
+                    text("t"),
+                    text("table"),
+                    int(1),
+                    int(1),
+                    boolean(true),
+                    boolean(true)
+                ],
+                vec![
+                    text("t_pkey"),
+                    text("index"),
+                    int(0),
+                    int(1),
+                    boolean(true),
+                    boolean(false)
+                ],
+            ]
+        );
+
+        conn.execute("DROP TABLE t", &[]).unwrap();
+        conn.execute("CREATE TABLE t (a INTEGER)", &[]).unwrap();
+        assert_eq!(
+            io(&mut conn),
+            [vec![
+                text("t"),
+                text("table"),
+                int(0),
+                int(0),
+                boolean(false),
+                boolean(false)
+            ]]
+        );
+    }
+}
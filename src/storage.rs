@@ -0,0 +1,379 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::buffer::BufferPoolManager;
+use crate::disk::PageId;
+use crate::heap::{Error, HeapFile, HeapScan, RecordId, TupleHeader};
+use crate::lsm::LsmTree;
+use crate::transaction::TxnId;
+
+// テーブルを作るときに選ばなければ使う仕組み
+pub const DEFAULT_STORAGE_ENGINE: &str = "heap";
+pub const LSM_STORAGE_ENGINE: &str = "lsm";
+
+// テーブルの版を持つ仕組み
+//
+// 行はバイト列のまま受け取り、版は RecordId で指す。版が見えるかはヘッダの xmin と xmax で呼ぶ側が決めるので、
+// 消したしるしをつけた版も remove するまでは get と scan で返す。
+// ページはバッファプールから借りるので、ページへの変更は WAL に書かれ、トランザクションの取り消しもこの操作で行う
+pub trait TableAccess: fmt::Debug + Send + Sync {
+    // xmin のトランザクションが入れた版として行を入れる
+    fn insert(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        xmin: TxnId,
+        data: &[u8],
+    ) -> Result<RecordId, Error>;
+
+    // 版のヘッダと行
+    fn get(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+    ) -> Result<Option<(TupleHeader, Vec<u8>)>, Error>;
+
+    // 版に xmax をつけて消したことにする。版がないか、すでに消されていれば false
+    fn delete(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+        xmax: TxnId,
+    ) -> Result<bool, Error>;
+
+    // 消した版を元に戻す
+    fn undelete(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error>;
+
+    // 版を取り除く。取り消した挿入や、誰からも見えなくなった版に使う
+    fn remove(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error>;
+
+    // すべての版を、消されたものも含めて順に読む
+    fn scan(&self) -> Box<dyn TableScan>;
+
+    // 並列に読むときに分ける単位。単位ごとの版は page_tuples でまとめて読む
+    fn page_ids(&self, bufmgr: &mut BufferPoolManager) -> Result<Vec<PageId>, Error>;
+
+    fn page_tuples(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        page_id: PageId,
+    ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error>;
+
+    // 作りを確かめる。見つけた誤りを problems に足し、読めた版を返す
+    fn verify(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        problems: &mut Vec<(PageId, String)>,
+    ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error>;
+
+    // どのテーブルのものかを見分ける、最初に作ったページ
+    fn first_page_id(&self) -> PageId;
+}
+
+pub trait TableScan {
+    fn next(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+    ) -> Result<Option<(RecordId, TupleHeader, Vec<u8>)>, Error>;
+}
+
+// CREATE TABLE ... USING name で選ぶ、テーブルの版を持つ仕組みの作り方
+//
+// heap と lsm はいつも使える。ほかのものは Connection::register_storage_engine で登録する
+pub trait StorageEngine: fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    // 空のテーブルを作る
+    fn create(&self, bufmgr: &mut BufferPoolManager) -> Result<Arc<dyn TableAccess>, Error>;
+}
+
+// スロット付きページのリストに版を入れる HeapFile
+#[derive(Debug)]
+pub struct Heap;
+
+impl StorageEngine for Heap {
+    fn name(&self) -> &str {
+        DEFAULT_STORAGE_ENGINE
+    }
+
+    fn create(&self, bufmgr: &mut BufferPoolManager) -> Result<Arc<dyn TableAccess>, Error> {
+        Ok(Arc::new(HeapFile::create(bufmgr)?))
+    }
+}
+
+// 書き込みの多いテーブルのための LsmTree
+#[derive(Debug)]
+pub struct Lsm;
+
+impl StorageEngine for Lsm {
+    fn name(&self) -> &str {
+        LSM_STORAGE_ENGINE
+    }
+
+    fn create(&self, bufmgr: &mut BufferPoolManager) -> Result<Arc<dyn TableAccess>, Error> {
+        Ok(Arc::new(LsmTree::create(bufmgr)?))
+    }
+}
+
+impl TableAccess for HeapFile {
+    fn insert(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        xmin: TxnId,
+        data: &[u8],
+    ) -> Result<RecordId, Error> {
+        HeapFile::insert(self, bufmgr, xmin, data)
+    }
+
+    fn get(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+    ) -> Result<Option<(TupleHeader, Vec<u8>)>, Error> {
+        HeapFile::get(self, bufmgr, rid)
+    }
+
+    fn delete(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        rid: RecordId,
+        xmax: TxnId,
+    ) -> Result<bool, Error> {
+        HeapFile::delete(self, bufmgr, rid, xmax)
+    }
+
+    fn undelete(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error> {
+        HeapFile::undelete(self, bufmgr, rid)
+    }
+
+    fn remove(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error> {
+        HeapFile::remove(self, bufmgr, rid)
+    }
+
+    fn scan(&self) -> Box<dyn TableScan> {
+        Box::new(HeapFile::scan(self))
+    }
+
+    fn page_ids(&self, bufmgr: &mut BufferPoolManager) -> Result<Vec<PageId>, Error> {
+        HeapFile::page_ids(self, bufmgr)
+    }
+
+    fn page_tuples(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        page_id: PageId,
+    ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        HeapFile::page_tuples(self, bufmgr, page_id)
+    }
+
+    fn verify(
+        &self,
+        bufmgr: &mut BufferPoolManager,
+        problems: &mut Vec<(PageId, String)>,
+    ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        HeapFile::verify(self, bufmgr, problems)
+    }
+
+    fn first_page_id(&self) -> PageId {
+        self.first_page_id
+    }
+}
+
+impl TableScan for HeapScan {
+    fn next(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+    ) -> Result<Option<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+        HeapScan::next(self, bufmgr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::connection::{Connection, Database};
+    use crate::types::Value;
+
+    // 入れた行の数を数えるだけで、版は HeapFile に任せる
+    #[derive(Debug)]
+    struct Counting {
+        heap: HeapFile,
+        inserts: Arc<AtomicUsize>,
+    }
+
+    impl TableAccess for Counting {
+        fn insert(
+            &self,
+            bufmgr: &mut BufferPoolManager,
+            xmin: TxnId,
+            data: &[u8],
+        ) -> Result<RecordId, Error> {
+            self.inserts.fetch_add(1, Ordering::Relaxed);
+            self.heap.insert(bufmgr, xmin, data)
+        }
+
+        fn get(
+            &self,
+            bufmgr: &mut BufferPoolManager,
+            rid: RecordId,
+        ) -> Result<Option<(TupleHeader, Vec<u8>)>, Error> {
+            self.heap.get(bufmgr, rid)
+        }
+
+        fn delete(
+            &self,
+            bufmgr: &mut BufferPoolManager,
+            rid: RecordId,
+            xmax: TxnId,
+        ) -> Result<bool, Error> {
+            self.heap.delete(bufmgr, rid, xmax)
+        }
+
+        fn undelete(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error> {
+            self.heap.undelete(bufmgr, rid)
+        }
+
+        fn remove(&self, bufmgr: &mut BufferPoolManager, rid: RecordId) -> Result<bool, Error> {
+            self.heap.remove(bufmgr, rid)
+        }
+
+        fn scan(&self) -> Box<dyn TableScan> {
+            Box::new(self.heap.scan())
+        }
+
+        fn page_ids(&self, bufmgr: &mut BufferPoolManager) -> Result<Vec<PageId>, Error> {
+            self.heap.page_ids(bufmgr)
+        }
+
+        fn page_tuples(
+            &self,
+            bufmgr: &mut BufferPoolManager,
+            page_id: PageId,
+        ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+            self.heap.page_tuples(bufmgr, page_id)
+        }
+
+        fn verify(
+            &self,
+            bufmgr: &mut BufferPoolManager,
+            problems: &mut Vec<(PageId, String)>,
+        ) -> Result<Vec<(RecordId, TupleHeader, Vec<u8>)>, Error> {
+            self.heap.verify(bufmgr, problems)
+        }
+
+        fn first_page_id(&self) -> PageId {
+            self.heap.first_page_id
+        }
+    }
+
+    #[derive(Debug)]
+    struct CountingEngine(Arc<AtomicUsize>);
+
+    impl StorageEngine for CountingEngine {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn create(&self, bufmgr: &mut BufferPoolManager) -> Result<Arc<dyn TableAccess>, Error> {
+            Ok(Arc::new(Counting {
+                heap: HeapFile::create(bufmgr)?,
+                inserts: self.0.clone(),
+            }))
+        }
+    }
+
+    #[test]
+    fn test_storage_engine() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        let inserts = Arc::new(AtomicUsize::new(0));
+        conn.register_storage_engine(Arc::new(CountingEngine(inserts.clone())))
+            .unwrap();
+        let err = conn
+            .register_storage_engine(Arc::new(CountingEngine(inserts.clone())))
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42710");
+
+        conn.execute_batch(
+            "CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT) USING counting;
+             CREATE TABLE h (a INTEGER) USING heap;
+             INSERT INTO t VALUES (1, 'x'), (2, 'y'), (3, 'z');
+             INSERT INTO h VALUES (1);
+             UPDATE t SET b = 'w' WHERE a = 2;
+             DELETE FROM t WHERE a = 3",
+        )
+        .unwrap();
+        assert_eq!(inserts.load(Ordering::Relaxed), 4);
+        let rows = conn
+            .query(
+                "SELECT b FROM t WHERE a = 2 UNION ALL SELECT b FROM t ORDER BY 1",
+                &[],
+            )
+            .unwrap()
+            .map(|row| row.into_values())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [["w"], ["w"], ["x"]].map(|[b]| vec![Value::Text(b.to_string())])
+        );
+        // 取り消した挿入は選んだ仕組みの上で取り除く
+        conn.execute_batch("BEGIN; INSERT INTO t VALUES (4, 'v'); ROLLBACK")
+            .unwrap();
+        assert_eq!(
+            conn.query_row("SELECT count(*) FROM t", &[])
+                .unwrap()
+                .get::<i64>(0)
+                .unwrap(),
+            2
+        );
+
+        let err = conn
+            .execute("CREATE TABLE u (a INTEGER) USING nope", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "42704");
+    }
+
+    #[test]
+    fn test_lsm_table() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute(
+            "CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT) USING lsm",
+            &[],
+        )
+        .unwrap();
+        // メモテーブルを何度か run に書き出す量を入れる
+        let values: Vec<String> = (0..3000)
+            .map(|i| format!("({}, 'value {}')", i, i))
+            .collect();
+        conn.execute(&format!("INSERT INTO t VALUES {}", values.join(", ")), &[])
+            .unwrap();
+        conn.execute_batch(
+            "UPDATE t SET b = 'updated' WHERE a % 10 = 0;
+             DELETE FROM t WHERE a >= 2000;
+             BEGIN; DELETE FROM t; ROLLBACK;
+             VACUUM t",
+        )
+        .unwrap();
+        let count = |conn: &mut Connection, sql: &str| -> i64 {
+            conn.query_row(sql, &[]).unwrap().get(0).unwrap()
+        };
+        assert_eq!(count(&mut conn, "SELECT count(*) FROM t"), 2000);
+        assert_eq!(
+            count(&mut conn, "SELECT count(*) FROM t WHERE b = 'updated'"),
+            200
+        );
+        let b: String = conn
+            .query_row("SELECT b FROM t WHERE a = 1234", &[])
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(b, "value 1234");
+        let err = conn
+            .execute("INSERT INTO t VALUES (5, 'x')", &[])
+            .unwrap_err();
+        assert_eq!(err.sqlstate(), "23505");
+        assert!(conn.check().unwrap().is_empty());
+    }
+}
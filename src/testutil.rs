@@ -0,0 +1,32 @@
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::buffer::{BufferPool, BufferPoolManager};
+use crate::disk::DiskManager;
+
+// テストが並列に走っても衝突しない一時ファイルのパス
+pub fn temp_path(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!(
+        "rdbms-training-{}-{}-{}",
+        std::process::id(),
+        n,
+        name
+    ))
+}
+
+// 開いたあとすぐ削除した一時ファイルを使うバッファプールマネージャ
+pub fn temp_bufmgr(pool_size: usize) -> BufferPoolManager {
+    let path = temp_path("heap.db");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let disk = DiskManager::new(file).unwrap();
+    BufferPoolManager::new(disk, BufferPool::new(pool_size))
+}
@@ -0,0 +1,174 @@
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::clock::Instant;
+
+// 処理にかかった時間を調べるための計装
+//
+// 文の解析・計画・実行、バッファプールのページの取得と追い出し、ディスクの読み書き、WAL の flush で
+// スパン (処理の区間) を作り、登録した Subscriber に入ったときと出たときを知らせる。
+// Subscriber はプロセスでひとつ。登録していなければ、スパンを作る費用はフラグを 1 つ読むだけ
+
+// スパンの名前と項目
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanData {
+    pub id: u64,
+    // 同じスレッドでこのスパンを囲んでいるスパン
+    pub parent: Option<u64>,
+    pub name: &'static str,
+    pub fields: Vec<(&'static str, String)>,
+}
+
+impl SpanData {
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// どのスレッドから呼ばれてもよい
+pub trait Subscriber: Send + Sync {
+    fn enter(&self, span: &SpanData);
+    // exit の fields には、スパンの中で record した項目も入る
+    fn exit(&self, span: &SpanData, elapsed: Duration);
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SUBSCRIBER: RwLock<Option<Arc<dyn Subscriber>>> = RwLock::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CURRENT: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+// None なら計装を止める。登録し直す前に作ったスパンは、前の Subscriber に出たことを知らせる
+pub fn set_subscriber(subscriber: Option<Arc<dyn Subscriber>>) {
+    let mut current = SUBSCRIBER.write().unwrap();
+    ENABLED.store(subscriber.is_some(), Ordering::Relaxed);
+    *current = subscriber;
+}
+
+// 捨てたときに出る。項目の値は Subscriber があるときだけ文字列にする
+pub fn span(name: &'static str, fields: &[(&'static str, &dyn fmt::Display)]) -> Span {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Span { entered: None };
+    }
+    let Some(subscriber) = SUBSCRIBER.read().unwrap().clone() else {
+        return Span { entered: None };
+    };
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let data = SpanData {
+        id,
+        parent: CURRENT.with(|current| current.replace(Some(id))),
+        name,
+        fields: fields
+            .iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect(),
+    };
+    subscriber.enter(&data);
+    Span {
+        entered: Some(Entered {
+            data,
+            subscriber,
+            start: Instant::now(),
+        }),
+    }
+}
+
+#[must_use]
+pub struct Span {
+    entered: Option<Entered>,
+}
+
+struct Entered {
+    data: SpanData,
+    subscriber: Arc<dyn Subscriber>,
+    start: Instant,
+}
+
+impl Span {
+    // 処理のあとでわかる値 (返した行の数など) を項目に加える
+    pub fn record(&mut self, name: &'static str, value: impl fmt::Display) {
+        if let Some(entered) = &mut self.entered {
+            entered.data.fields.push((name, value.to_string()));
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(entered) = self.entered.take() {
+            CURRENT.with(|current| current.set(entered.data.parent));
+            let elapsed = entered.start.elapsed();
+            entered.subscriber.exit(&entered.data, elapsed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Database;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        spans: Mutex<Vec<SpanData>>,
+    }
+
+    impl Subscriber for Recorder {
+        fn enter(&self, _: &SpanData) {}
+
+        fn exit(&self, span: &SpanData, _: Duration) {
+            self.spans.lock().unwrap().push(span.clone());
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let db = Database::open_temporary().unwrap();
+        let mut conn = db.connect();
+        conn.execute("CREATE TABLE trace_t (a INTEGER)", &[])
+            .unwrap();
+        let recorder = Arc::new(Recorder::default());
+        set_subscriber(Some(recorder.clone()));
+        let sql = "INSERT INTO trace_t VALUES (1), (2)";
+        conn.execute(sql, &[]).unwrap();
+        set_subscriber(None);
+        conn.execute(sql, &[]).unwrap();
+
+        // ほかのテストのスレッドのスパンも届くので、この文のスパンの下だけを見る
+        let spans = recorder.spans.lock().unwrap();
+        let statement = spans
+            .iter()
+            .find(|span| span.name == "statement" && span.field("sql") == Some(sql))
+            .unwrap();
+        assert_eq!(statement.field("rows"), Some("2"));
+        let children: Vec<&str> = spans
+            .iter()
+            .filter(|span| span.parent == Some(statement.id))
+            .map(|span| span.name)
+            .collect();
+        assert_eq!(children, ["parse", "plan", "execute"]);
+        let execute = spans
+            .iter()
+            .find(|span| span.name == "execute" && span.parent == Some(statement.id))
+            .unwrap();
+        assert!(spans.iter().any(|span| span.name == "fetch_page"
+            && span.parent == Some(execute.id)
+            && span.field("page").is_some()));
+        assert_eq!(
+            spans
+                .iter()
+                .filter(|span| span.name == "statement" && span.field("sql") == Some(sql))
+                .count(),
+            1
+        );
+    }
+}
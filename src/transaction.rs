@@ -0,0 +1,1525 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::buffer::BufferPoolManager;
+use crate::catalog::{self, Catalog};
+use crate::datetime::Timestamp;
+use crate::heap::{RecordId, TupleHeader};
+use crate::lock::{self, LockManager, LockMode, LockTarget, LockWait};
+use crate::metrics::METRICS;
+use crate::recovery::{self, PreparedTxn};
+use crate::sql::ast::{IsolationLevel, TransactionStatement};
+use crate::types::Value;
+use crate::wal::{self, ActiveTxn, LogRecord, Lsn, RowChange};
+
+// 1 つの Changes のレコードに入れる行の変更の数
+const CHANGES_PER_RECORD: usize = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("there is already a transaction in progress")]
+    AlreadyInTransaction,
+    #[error("there is no transaction in progress")]
+    NoTransaction,
+    #[error("savepoint \"{0}\" does not exist")]
+    NoSuchSavepoint(String),
+    #[error("SET TRANSACTION ISOLATION LEVEL must be called before any query")]
+    IsolationLevelAfterQuery,
+    // トランザクションは取り消してあるので、初めからやり直せば成功しうる
+    #[error("could not serialize access due to read/write dependencies among transactions")]
+    SerializationFailure,
+    #[error("transaction identifier \"{0}\" is already in use")]
+    DuplicateGid(String),
+    #[error("prepared transaction with identifier \"{0}\" does not exist")]
+    NoSuchPrepared(String),
+    #[error("{0} cannot run inside a transaction block")]
+    InTransactionBlock(&'static str),
+    #[error("transaction ID space is exhausted")]
+    TxnIdExhausted,
+    #[error("AS OF requires version_retention to be set")]
+    NoVersionRetention,
+    #[error("AS OF LSN requires the write-ahead log")]
+    NoWal,
+    #[error("snapshot too old: row versions before {0} are not retained")]
+    SnapshotTooOld(String),
+    #[error("AS OF {0} is in the future")]
+    FutureSnapshot(String),
+    #[error(transparent)]
+    Wal(#[from] wal::Error),
+    #[error(transparent)]
+    Recovery(#[from] recovery::Error),
+    #[error(transparent)]
+    Catalog(#[from] catalog::Error),
+    #[error(transparent)]
+    Lock(#[from] lock::Error),
+}
+
+// 番号は 64 bit で、振るたびに 1 ずつ増え一周することはない。
+// 毎秒 100 万件のトランザクションでも使い切るまで 50 万年かかるので、
+// 行のヘッダにもそのまま 8 byte で書き、見えるかどうかはただの大小で比べる
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct TxnId(pub u64);
+
+impl TxnId {
+    // 行を消していないことを表す
+    pub const INVALID_TXN_ID: Self = Self(0);
+    // トランザクションの外で書いた行。誰からも見える
+    pub const FROZEN_TXN_ID: Self = Self(1);
+
+    pub fn to_u64(self) -> u64 {
+        self.0
+    }
+
+    pub fn valid(self) -> Option<Self> {
+        if self == Self::INVALID_TXN_ID {
+            None
+        } else {
+            Some(self)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TxnState {
+    Active,
+    Committed,
+    Aborted,
+}
+
+// トランザクションを始めたときに実行中だったトランザクション。
+// xmax 以降に始まったものと active にあるものの変更は見えない。
+// 取り消したトランザクションの版は取り消すときに元に戻しているので、それ以外の終わったものはコミットしている
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    // これより前のトランザクションはすべて終わっている
+    xmin: TxnId,
+    xmax: TxnId,
+    active: Vec<TxnId>,
+    // スタンバイで、xmax からあとでもコミットを受け取ったもの
+    later: Vec<TxnId>,
+    // 自分の変更は見える
+    own: TxnId,
+}
+
+impl Snapshot {
+    // xid の変更がこのスナップショットから見えるか
+    fn sees(&self, xid: TxnId) -> bool {
+        if xid == self.own || xid < self.xmin {
+            return true;
+        }
+        if xid >= self.xmax {
+            return self.later.binary_search(&xid).is_ok();
+        }
+        self.active.binary_search(&xid).is_err()
+    }
+
+    pub fn visible(&self, header: &TupleHeader) -> bool {
+        self.sees(header.xmin) && !header.xmax.valid().is_some_and(|xmax| self.sees(xmax))
+    }
+}
+
+// スタンバイが、プライマリのログから知ったトランザクションの終わり
+//
+// プライマリは終わっていないトランザクションの版が載ったページの像も送るので、コミットを受け取ったものだけが見えるようにする。
+// チェックポイントの next_txn より前で running にないものは、コミットを記したか、取り消して版を戻し終えている。
+// 読むだけのトランザクションや取り消したものはコミットを記さないが、版を残さないので見えなくてよい
+#[derive(Debug, Clone, PartialEq)]
+pub struct StandbyView {
+    next_txn: TxnId,
+    // next_txn より前で、まだコミットを受け取っていないもの
+    running: Vec<TxnId>,
+    // next_txn からあとで、コミットを受け取ったもの
+    committed: Vec<TxnId>,
+}
+
+impl StandbyView {
+    // チェックポイントに書いてあった、次に振る番号と実行中のトランザクション
+    pub fn new(next_txn: u64, running: &[u64]) -> Self {
+        let mut running = running
+            .iter()
+            .filter(|&&txn| txn < next_txn)
+            .map(|&txn| TxnId(txn))
+            .collect::<Vec<_>>();
+        running.sort();
+        running.dedup();
+        Self {
+            next_txn: TxnId(next_txn),
+            running,
+            committed: vec![],
+        }
+    }
+
+    pub fn commit(&mut self, txn: u64) {
+        let txn = TxnId(txn);
+        if txn < self.next_txn {
+            self.running.retain(|&id| id != txn);
+        } else if let Err(i) = self.committed.binary_search(&txn) {
+            self.committed.insert(i, txn);
+        }
+    }
+
+    // スタンバイの接続は書かないので、自分の変更はない
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            xmin: self.running.first().copied().unwrap_or(self.next_txn),
+            xmax: self.next_txn,
+            active: self.running.clone(),
+            later: self.committed.clone(),
+            own: TxnId::INVALID_TXN_ID,
+        }
+    }
+}
+
+// AS OF で読む時点
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AsOfPoint {
+    Timestamp(Timestamp),
+    // この LSN までのログにコミットを書いたトランザクションが見える
+    Lsn(Lsn),
+}
+
+// 行を書き換えた演算子が残す、取り消すための変更。インデックスはテーブルの行と一緒に戻す
+#[derive(Debug, Clone, PartialEq)]
+pub enum Undo {
+    Insert {
+        table: String,
+        rid: RecordId,
+        row: Vec<Value>,
+    },
+    // before は古い版、after は新しい版
+    Update {
+        table: String,
+        before: RecordId,
+        after: RecordId,
+        old: Vec<Value>,
+        new: Vec<Value>,
+    },
+    Delete {
+        table: String,
+        rid: RecordId,
+        row: Vec<Value>,
+    },
+}
+
+impl Undo {
+    pub fn table(&self) -> &str {
+        match self {
+            Undo::Insert { table, .. } | Undo::Update { table, .. } | Undo::Delete { table, .. } => {
+                table
+            }
+        }
+    }
+
+    // 変更したあとの行の場所
+    pub fn rid(&self) -> RecordId {
+        match self {
+            Undo::Insert { rid, .. } | Undo::Delete { rid, .. } => *rid,
+            Undo::Update { after, .. } => *after,
+        }
+    }
+
+    // 論理デコードに渡す行の変更
+    pub fn row_change(&self) -> RowChange {
+        match self.clone() {
+            Undo::Insert { table, row, .. } => RowChange::Insert { table, new: row },
+            Undo::Update {
+                table, old, new, ..
+            } => RowChange::Update { table, old, new },
+            Undo::Delete { table, row, .. } => RowChange::Delete { table, old: row },
+        }
+    }
+}
+
+fn row_changes(changes: &[Undo]) -> Vec<RowChange> {
+    changes.iter().map(Undo::row_change).collect()
+}
+
+// トランザクションの外で書き換えた行を、論理デコード用のレコードに書く
+pub fn log_autocommit(bufmgr: &mut BufferPoolManager, changes: &[Undo]) -> Result<(), wal::Error> {
+    let Some(wal) = bufmgr.wal_mut() else {
+        return Ok(());
+    };
+    for chunk in changes.chunks(CHANGES_PER_RECORD) {
+        wal.append(&LogRecord::Changes {
+            txn: None,
+            prev: Lsn::INVALID_LSN,
+            changes: row_changes(chunk),
+        })?;
+    }
+    Ok(())
+}
+
+// 変更を新しいものから順に元に戻す。入れた版は取り除き、消したしるしをつけた版は戻す
+pub fn undo(
+    bufmgr: &mut BufferPoolManager,
+    catalog: &Catalog,
+    changes: impl DoubleEndedIterator<Item = Undo>,
+) -> Result<(), catalog::Error> {
+    for change in changes.rev() {
+        let Some(table) = catalog.table(match &change {
+            Undo::Insert { table, .. }
+            | Undo::Update { table, .. }
+            | Undo::Delete { table, .. } => table,
+        }) else {
+            // 消したテーブルの行は戻さない
+            continue;
+        };
+        match change {
+            Undo::Insert { rid, row, .. } => table.remove(bufmgr, rid, &row)?,
+            Undo::Update {
+                before, after, new, ..
+            } => {
+                table.remove(bufmgr, after, &new)?;
+                table.restore(bufmgr, before)?;
+            }
+            Undo::Delete { rid, .. } => table.restore(bufmgr, rid)?,
+        }
+    }
+    Ok(())
+}
+
+// SERIALIZABLE のトランザクションが読んだテーブルと書いたテーブル。
+// 範囲や述語ではなくテーブル単位で覚えるので、実際には依存しない組み合わせでも失敗することがある
+#[derive(Debug)]
+struct SerialTxn {
+    id: TxnId,
+    snapshot: Snapshot,
+    reads: HashSet<String>,
+    writes: HashSet<String>,
+    // 同時に実行したトランザクションとの rw 依存 (片方が読んだものをもう片方が書いた) が入ってくるか、出ていくか
+    conflict_in: bool,
+    conflict_out: bool,
+    committed: bool,
+}
+
+// セーブポイントを作ったときの変更の数。ROLLBACK TO はここまで戻す
+#[derive(Debug)]
+struct Savepoint {
+    name: String,
+    undo: usize,
+}
+
+#[derive(Debug)]
+pub struct Transaction {
+    id: TxnId,
+    // 作成順に並んだセーブポイント
+    savepoints: Vec<Savepoint>,
+    // 演算子が残した変更。古い順
+    undo: Vec<Undo>,
+    // このトランザクションが書いた最初と最後のログのレコード
+    first_lsn: Lsn,
+    last_lsn: Lsn,
+    isolation: IsolationLevel,
+    snapshot: Snapshot,
+    // 文を 1 つでも実行したか
+    started: bool,
+    serial: Option<Rc<RefCell<SerialTxn>>>,
+    locks: Arc<LockManager>,
+    // ロックをすぐに与えられないときの動作
+    lock_wait: LockWait,
+    // AS OF のスナップショットを作るときに読む。準備したトランザクションは Shared から持たれるので弱い参照にする
+    shared: Weak<RefCell<Shared>>,
+}
+
+impl Transaction {
+    fn new(
+        id: TxnId,
+        isolation: IsolationLevel,
+        snapshot: Snapshot,
+        locks: Arc<LockManager>,
+        lock_wait: LockWait,
+        shared: Weak<RefCell<Shared>>,
+    ) -> Self {
+        Self {
+            id,
+            locks,
+            lock_wait,
+            shared,
+            isolation,
+            snapshot,
+            started: false,
+            serial: None,
+            savepoints: vec![],
+            undo: vec![],
+            first_lsn: Lsn::INVALID_LSN,
+            last_lsn: Lsn::INVALID_LSN,
+        }
+    }
+
+    pub fn id(&self) -> TxnId {
+        self.id
+    }
+
+    pub fn isolation_level(&self) -> IsolationLevel {
+        self.isolation
+    }
+
+    pub fn started(&self) -> bool {
+        self.started
+    }
+
+    // 読む演算子はこのスナップショットから見える版だけを返す
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    // point の時点でコミットしていたトランザクションだけが見えるスナップショット。
+    // 自分の変更も、その時点より後のものなら見えない
+    pub fn snapshot_as_of(&self, point: AsOfPoint) -> Result<Snapshot, Error> {
+        let shared = self.shared.upgrade().ok_or(Error::NoVersionRetention)?;
+        let mut shared = shared.borrow_mut();
+        shared.snapshot_as_of(point)
+    }
+
+    pub fn savepoints(&self) -> Vec<&str> {
+        self.savepoints.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    // ロックはトランザクションが終わるまで持つ。
+    // DeadlockDetected が返ったら、このトランザクションは取り消さなければならない
+    pub fn lock_table(&self, table: &str, mode: LockMode) -> Result<(), lock::Error> {
+        let target = LockTarget::Table(table.to_string());
+        self.locks
+            .lock_with(self.id, target, mode, self.lock_wait())
+    }
+
+    // table にかけているロック
+    pub fn table_lock(&self, table: &str) -> Option<LockMode> {
+        let target = LockTarget::Table(table.to_string());
+        self.locks
+            .holders(&target)
+            .into_iter()
+            .find(|&(holder, _)| holder == self.id)
+            .map(|(_, mode)| mode)
+    }
+
+    // table のロックを、table_lock で調べておいたモードに戻す
+    pub fn restore_table_lock(&self, table: &str, mode: Option<LockMode>) {
+        let target = LockTarget::Table(table.to_string());
+        self.locks.restore(self.id, &target, mode);
+    }
+
+    pub fn lock_row(&self, table: &str, rid: RecordId, mode: LockMode) -> Result<(), lock::Error> {
+        self.lock_row_with(table, rid, mode, self.lock_wait())
+    }
+
+    pub fn lock_row_with(
+        &self,
+        table: &str,
+        rid: RecordId,
+        mode: LockMode,
+        wait: LockWait,
+    ) -> Result<(), lock::Error> {
+        let target = LockTarget::Row(table.to_string(), rid);
+        self.locks.lock_with(self.id, target, mode, wait)
+    }
+
+    // セッションの lock_timeout と park_lock_waits で決まる待ち方
+    pub fn lock_wait(&self) -> LockWait {
+        self.lock_wait
+    }
+
+    // 読む演算子がテーブルを開くときに呼ぶ
+    pub fn record_read(&mut self, table: &str) {
+        if let Some(serial) = &self.serial {
+            serial.borrow_mut().reads.insert(table.to_string());
+        }
+    }
+
+    // 文が終わったときに、その文の変更を足す
+    pub fn push_undo(&mut self, changes: impl IntoIterator<Item = Undo>) {
+        let start = self.undo.len();
+        self.undo.extend(changes);
+        if let Some(serial) = &self.serial {
+            let mut serial = serial.borrow_mut();
+            for change in &self.undo[start..] {
+                let (Undo::Insert { table, .. }
+                | Undo::Update { table, .. }
+                | Undo::Delete { table, .. }) = change;
+                serial.writes.insert(table.clone());
+            }
+        }
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    // start より後に足した変更を取り出す。トリガーが実行した文の変更を、トリガーを呼んだ文の変更にするのに使う
+    pub fn take_undo(&mut self, start: usize) -> Vec<Undo> {
+        self.undo.split_off(start)
+    }
+
+    // コミットか準備をする前に、取り消されずに残った変更を論理デコード用のレコードに書く
+    fn log_changes(&mut self, bufmgr: &mut BufferPoolManager) -> Result<(), wal::Error> {
+        let Some(wal) = bufmgr.wal_mut() else {
+            return Ok(());
+        };
+        for chunk in self.undo.chunks(CHANGES_PER_RECORD) {
+            let lsn = wal.append(&LogRecord::Changes {
+                txn: Some(self.id.0),
+                prev: self.last_lsn,
+                changes: row_changes(chunk),
+            })?;
+            if self.first_lsn.valid().is_none() {
+                self.first_lsn = lsn;
+            }
+            self.last_lsn = lsn;
+        }
+        Ok(())
+    }
+}
+
+// トランザクションに番号を振り、状態を覚えておく。
+// バッファプールにログがつないであれば、コミットはログがディスクに届いてから返る。
+// 1 つの管理が 1 つのセッションにあたり、session で作った管理どうしは番号と状態を分け合う
+#[derive(Debug)]
+pub struct TransactionManager {
+    shared: Rc<RefCell<Shared>>,
+    current: Option<Transaction>,
+    // このセッションで始めるトランザクションの分離レベル
+    default_isolation: IsolationLevel,
+    lock_timeout: Option<Duration>,
+    // ロックを待たずに列に残して戻る
+    park_lock_waits: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    next_id: u64,
+    states: HashMap<TxnId, TxnState>,
+    // 実行中のトランザクションの、いまのスナップショットの xmin
+    xmins: HashMap<TxnId, TxnId>,
+    // 実行中の SERIALIZABLE のトランザクションと、それと同時に実行してコミットしたもの
+    serializable: Vec<Rc<RefCell<SerialTxn>>>,
+    // PREPARE TRANSACTION でセッションから切り離したもの。gid ごと
+    prepared: HashMap<String, Transaction>,
+    locks: Arc<LockManager>,
+    // テーブルごとの、それを書き換えてコミットしたトランザクションの数
+    commits: HashMap<String, u64>,
+    history: History,
+    // スタンバイなら、スナップショットはこれで取る
+    standby: Option<StandbyView>,
+}
+
+// AS OF のために覚えておく、行を書き換えたトランザクションのコミット。
+// ある時点のスナップショットは、その時点までのコミットの直後に取ったものとして作る。
+// retention より古いコミットは忘れ、忘れた最後のコミットを since にする。since より前の時点は読めない
+#[derive(Debug)]
+struct History {
+    // None なら覚えない
+    retention: Option<Duration>,
+    since: Commit,
+    // コミットした順
+    commits: VecDeque<Commit>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Commit {
+    txn: TxnId,
+    time: Timestamp,
+    // コミットのレコード。ログがなければ INVALID_LSN
+    lsn: Lsn,
+    // コミットしたときに次に振る番号だったもの
+    next: TxnId,
+}
+
+impl History {
+    // いまより後のコミットを覚える。next は次に振る番号
+    fn new(retention: Option<Duration>, next: TxnId) -> Self {
+        Self {
+            retention,
+            since: Commit {
+                txn: TxnId::INVALID_TXN_ID,
+                time: Timestamp::now(),
+                lsn: Lsn::INVALID_LSN,
+                next,
+            },
+            commits: VecDeque::new(),
+        }
+    }
+
+    // retention より古いコミットを忘れる
+    fn prune(&mut self, now: Timestamp) {
+        let Some(retention) = self.retention else {
+            return;
+        };
+        let cutoff = now.micros().saturating_sub(retention.as_micros() as i64);
+        while self
+            .commits
+            .front()
+            .is_some_and(|commit| commit.time.micros() < cutoff)
+        {
+            self.since = self.commits.pop_front().unwrap();
+        }
+    }
+}
+
+impl Shared {
+    // 次の番号を振る。使い切ったら一周させずに失敗する
+    fn take_id(&mut self) -> Result<TxnId, Error> {
+        let id = TxnId(self.next_id);
+        self.next_id = self.next_id.checked_add(1).ok_or(Error::TxnIdExhausted)?;
+        Ok(id)
+    }
+
+    // いま実行中のトランザクションを元に own のスナップショットを取る
+    fn snapshot(&self, own: TxnId) -> Snapshot {
+        if let Some(view) = &self.standby {
+            return view.snapshot();
+        }
+        let mut active = self
+            .states
+            .iter()
+            .filter(|(id, state)| **state == TxnState::Active && **id != own)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        active.sort();
+        let xmax = TxnId(self.next_id);
+        Snapshot {
+            xmin: active.first().copied().unwrap_or(xmax),
+            xmax,
+            active,
+            later: vec![],
+            own,
+        }
+    }
+
+    fn snapshot_as_of(&mut self, point: AsOfPoint) -> Result<Snapshot, Error> {
+        if self.history.retention.is_none() {
+            return Err(Error::NoVersionRetention);
+        }
+        let now = Timestamp::now();
+        self.history.prune(now);
+        let history = &self.history;
+        let pos = match point {
+            AsOfPoint::Timestamp(time) => {
+                if time > now {
+                    return Err(Error::FutureSnapshot(time.to_string()));
+                }
+                if time < history.since.time {
+                    return Err(Error::SnapshotTooOld(history.since.time.to_string()));
+                }
+                history
+                    .commits
+                    .partition_point(|commit| commit.time <= time)
+            }
+            AsOfPoint::Lsn(lsn) => {
+                if lsn < history.since.lsn {
+                    let since = format!("LSN {}", history.since.lsn.0);
+                    return Err(Error::SnapshotTooOld(since));
+                }
+                history.commits.partition_point(|commit| commit.lsn <= lsn)
+            }
+        };
+        let xmax = match pos {
+            0 => history.since.next,
+            _ => history.commits[pos - 1].next,
+        };
+        // その時点より後にコミットしたものと、まだ実行中のものは見えない。取り消したものの版は残っていない
+        let mut active = history
+            .commits
+            .iter()
+            .skip(pos)
+            .map(|commit| commit.txn)
+            .chain(
+                self.states
+                    .iter()
+                    .filter(|(_, state)| **state == TxnState::Active)
+                    .map(|(id, _)| *id),
+            )
+            .filter(|id| *id < xmax)
+            .collect::<Vec<_>>();
+        active.sort();
+        active.dedup();
+        Ok(Snapshot {
+            xmin: active.first().copied().unwrap_or(xmax),
+            xmax,
+            active,
+            later: vec![],
+            own: TxnId::INVALID_TXN_ID,
+        })
+    }
+
+    // txn をコミットしてよいか確かめ、同時に実行したトランザクションとの rw 依存を記録する。
+    // txn か、コミットしたトランザクションが両方向の依存を持つことになるなら、循環しうるので失敗させる
+    fn commit_serializable(&mut self, txn: &Rc<RefCell<SerialTxn>>) -> Result<(), Error> {
+        let mut edges = vec![];
+        {
+            let me = txn.borrow();
+            let (mut conflict_in, mut conflict_out) = (me.conflict_in, me.conflict_out);
+            for other in &self.serializable {
+                if Rc::ptr_eq(other, txn) {
+                    continue;
+                }
+                let other_ref = other.borrow();
+                // スナップショットから見えるものは先にコミットしていて、同時には実行していない
+                if other_ref.committed && me.snapshot.sees(other_ref.id) {
+                    continue;
+                }
+                let out = !me.reads.is_disjoint(&other_ref.writes);
+                let into = !other_ref.reads.is_disjoint(&me.writes);
+                if other_ref.committed
+                    && ((out && other_ref.conflict_out) || (into && other_ref.conflict_in))
+                {
+                    return Err(Error::SerializationFailure);
+                }
+                conflict_out |= out;
+                conflict_in |= into;
+                edges.push((other.clone(), out, into));
+            }
+            if conflict_in && conflict_out {
+                return Err(Error::SerializationFailure);
+            }
+        }
+        let mut me = txn.borrow_mut();
+        for (other, out, into) in edges {
+            let mut other = other.borrow_mut();
+            other.conflict_in |= out;
+            other.conflict_out |= into;
+            me.conflict_out |= out;
+            me.conflict_in |= into;
+        }
+        me.committed = true;
+        Ok(())
+    }
+
+    // 取り消したものと、実行中のどのトランザクションとも同時に実行していないものを忘れる
+    fn prune_serializable(&mut self) {
+        let states = &self.states;
+        let active = self
+            .serializable
+            .iter()
+            .filter(|s| states.get(&s.borrow().id) == Some(&TxnState::Active))
+            .map(|s| s.borrow().snapshot.clone())
+            .collect::<Vec<_>>();
+        self.serializable.retain(|s| {
+            let s = s.borrow();
+            match states.get(&s.id) {
+                Some(TxnState::Active) => true,
+                Some(TxnState::Committed) => active.iter().any(|snapshot| !snapshot.sees(s.id)),
+                _ => false,
+            }
+        });
+    }
+}
+
+impl Default for TransactionManager {
+    fn default() -> Self {
+        let shared = Shared {
+            next_id: TxnId::FROZEN_TXN_ID.0 + 1,
+            states: HashMap::new(),
+            xmins: HashMap::new(),
+            serializable: vec![],
+            prepared: HashMap::new(),
+            locks: Arc::new(LockManager::new()),
+            commits: HashMap::new(),
+            history: History::new(None, TxnId(TxnId::FROZEN_TXN_ID.0 + 1)),
+            standby: None,
+        };
+        Self {
+            shared: Rc::new(RefCell::new(shared)),
+            current: None,
+            default_isolation: IsolationLevel::RepeatableRead,
+            lock_timeout: None,
+            park_lock_waits: false,
+        }
+    }
+}
+
+impl TransactionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 同じ番号と状態を使う別のセッション
+    pub fn session(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            current: None,
+            default_isolation: self.default_isolation,
+            lock_timeout: self.lock_timeout,
+            park_lock_waits: self.park_lock_waits,
+        }
+    }
+
+    // 次に振る番号
+    pub fn next_txn_id(&self) -> TxnId {
+        TxnId(self.shared.borrow().next_id)
+    }
+
+    // 回復のあとに呼び、クラッシュ前に使った番号をもう一度振らないようにする。
+    // 番号を戻すことはしない
+    pub fn advance_next_txn_id(&mut self, next: TxnId) {
+        let mut shared = self.shared.borrow_mut();
+        shared.next_id = shared.next_id.max(next.0);
+    }
+
+    // スタンバイのあいだ、スナップショットをプライマリのトランザクションから取る。昇格したら None にする
+    pub fn set_standby(&self, view: Option<StandbyView>) {
+        self.shared.borrow_mut().standby = view;
+    }
+
+    // コミットを retention のあいだ覚え、AS OF で読めるようにする。None なら覚えるのをやめる。
+    // 覚えていたコミットは忘れ、いまより前の時点は読めなくなる
+    pub fn set_version_retention(&mut self, retention: Option<Duration>) {
+        let mut shared = self.shared.borrow_mut();
+        shared.history = History::new(retention, TxnId(shared.next_id));
+    }
+
+    // ほかのスレッドで待つものと分け合うロックの管理
+    pub fn lock_manager(&self) -> Arc<LockManager> {
+        self.shared.borrow().locks.clone()
+    }
+
+    // テーブルを作り変える前に呼ぶ。トランザクションの中ならロックを終わるまで持ち、
+    // 外なら持っているトランザクションが終わるのを待つだけにする
+    pub fn lock_table(&mut self, table: &str, mode: LockMode) -> Result<(), Error> {
+        if let Some(txn) = &self.current {
+            return Ok(txn.lock_table(table, mode)?);
+        }
+        let locks = self.lock_manager();
+        let id = self.shared.borrow_mut().take_id()?;
+        let locked = locks.lock_with(
+            id,
+            LockTarget::Table(table.to_string()),
+            mode,
+            self.lock_wait(),
+        );
+        locks.release_all(id);
+        Ok(locked?)
+    }
+
+    // ほかのトランザクションが、行を書き換えさせないロックを table にかけているか。
+    // トランザクションの中で作ったインデックスはコミットするまでほかの接続のカタログにないので、その間は VACUUM で版を消さない
+    pub fn table_locked(&self, table: &str) -> bool {
+        let own = self.current.as_ref().map(|txn| txn.id);
+        self.lock_manager()
+            .holders(&LockTarget::Table(table.to_string()))
+            .into_iter()
+            .any(|(holder, mode)| {
+                Some(holder) != own && !mode.compatible(LockMode::IntentionExclusive)
+            })
+    }
+
+    pub fn lock_timeout(&self) -> Option<Duration> {
+        self.lock_timeout
+    }
+
+    // ロックを待つ時間の上限を変える。実行中のトランザクションにも次に待つときから効く
+    pub fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        self.lock_timeout = timeout;
+        let wait = self.lock_wait();
+        if let Some(txn) = &mut self.current {
+            txn.lock_wait = wait;
+        }
+    }
+
+    // on なら、ロックをすぐに与えられなければ待たずに列に残して WouldBlock を返す。lock_timeout は呼ぶ側で守る
+    pub fn set_park_lock_waits(&mut self, on: bool) {
+        self.park_lock_waits = on;
+        let wait = self.lock_wait();
+        if let Some(txn) = &mut self.current {
+            txn.lock_wait = wait;
+        }
+    }
+
+    fn lock_wait(&self) -> LockWait {
+        match (self.park_lock_waits, self.lock_timeout) {
+            (true, _) => LockWait::Park,
+            (false, Some(timeout)) => LockWait::Timeout(timeout),
+            (false, None) => LockWait::Block,
+        }
+    }
+
+    pub fn default_isolation_level(&self) -> IsolationLevel {
+        self.default_isolation
+    }
+
+    // これから始めるトランザクションの分離レベルを変える
+    pub fn set_default_isolation_level(&mut self, level: IsolationLevel) {
+        self.default_isolation = level;
+    }
+
+    pub fn execute(
+        &mut self,
+        stmt: &TransactionStatement,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        match stmt {
+            TransactionStatement::Begin => self.begin().map(|_| ()),
+            TransactionStatement::Commit => self.commit(bufmgr, catalog),
+            TransactionStatement::Rollback => self.rollback(bufmgr, catalog),
+            TransactionStatement::Savepoint(name) => self.savepoint(name),
+            TransactionStatement::RollbackTo(name) => self.rollback_to(name, bufmgr, catalog),
+            TransactionStatement::Release(name) => self.release(name),
+            TransactionStatement::SetIsolationLevel(level) => self.set_isolation_level(*level),
+            TransactionStatement::Prepare(gid) => self.prepare(gid, bufmgr, catalog),
+            TransactionStatement::CommitPrepared(gid) => self.commit_prepared(gid, bufmgr),
+            TransactionStatement::RollbackPrepared(gid) => {
+                self.rollback_prepared(gid, bufmgr, catalog)
+            }
+        }
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn current(&self) -> Option<&Transaction> {
+        self.current.as_ref()
+    }
+
+    pub fn current_mut(&mut self) -> Option<&mut Transaction> {
+        self.current.as_mut()
+    }
+
+    // 文を実行する前に呼び、実行器に渡すトランザクションを返す。
+    // READ COMMITTED なら文ごとに、それまでにコミットしたものが見えるスナップショットを取り直す
+    pub fn start_statement(&mut self) -> Option<&mut Transaction> {
+        let txn = self.current.as_mut()?;
+        match txn.isolation {
+            IsolationLevel::ReadCommitted => {
+                let mut shared = self.shared.borrow_mut();
+                txn.snapshot = shared.snapshot(txn.id);
+                shared.xmins.insert(txn.id, txn.snapshot.xmin);
+            }
+            IsolationLevel::RepeatableRead => {}
+            IsolationLevel::Serializable if txn.serial.is_none() => {
+                let serial = Rc::new(RefCell::new(SerialTxn {
+                    id: txn.id,
+                    snapshot: txn.snapshot.clone(),
+                    reads: HashSet::new(),
+                    writes: HashSet::new(),
+                    conflict_in: false,
+                    conflict_out: false,
+                    committed: false,
+                }));
+                self.shared.borrow_mut().serializable.push(serial.clone());
+                txn.serial = Some(serial);
+            }
+            IsolationLevel::Serializable => {}
+        }
+        txn.started = true;
+        Some(txn)
+    }
+
+    // 実行中のトランザクションの分離レベルを変える。文を実行する前でなければならない
+    pub fn set_isolation_level(&mut self, level: IsolationLevel) -> Result<(), Error> {
+        let txn = self.current.as_mut().ok_or(Error::NoTransaction)?;
+        if txn.started {
+            return Err(Error::IsolationLevelAfterQuery);
+        }
+        txn.isolation = level;
+        Ok(())
+    }
+
+    // table を書き換えてコミットしたトランザクションの数。変わらなければ、新しいスナップショットから見える行も変わらない
+    pub fn table_commits(&self, table: &str) -> u64 {
+        self.shared.borrow().commits.get(table).copied().unwrap_or(0)
+    }
+
+    // これより前のトランザクションが消した版は、実行中のどのトランザクションからも見えない。
+    // 実行中のものが消した版を残すよう、実行中のトランザクションの番号も含めて最も古いもの。
+    // AS OF のために覚えているコミットが消した版も残す
+    pub fn horizon(&self) -> TxnId {
+        let mut shared = self.shared.borrow_mut();
+        shared.history.prune(Timestamp::now());
+        let active = shared
+            .states
+            .iter()
+            .filter(|(_, state)| **state == TxnState::Active)
+            .map(|(id, _)| *id);
+        let retained = shared.history.commits.iter().map(|commit| commit.txn);
+        active
+            .chain(shared.xmins.values().copied())
+            .chain(retained)
+            .min()
+            .unwrap_or(TxnId(shared.next_id))
+    }
+
+    // 準備したものを除いて、どこかのセッションで実行中のトランザクション
+    pub fn running(&self) -> Vec<TxnId> {
+        let shared = self.shared.borrow();
+        let mut running = shared
+            .states
+            .iter()
+            .filter(|(id, state)| {
+                **state == TxnState::Active && !shared.prepared.values().any(|txn| txn.id == **id)
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        running.sort();
+        running
+    }
+
+    pub fn state(&self, id: TxnId) -> Option<TxnState> {
+        self.shared.borrow().states.get(&id).copied()
+    }
+
+    // チェックポイントに書く実行中のトランザクション。準備したものも含め、まだログを書いていないものは含めない
+    pub fn active(&self) -> Vec<ActiveTxn> {
+        let shared = self.shared.borrow();
+        self.current
+            .iter()
+            .chain(shared.prepared.values())
+            .filter(|txn| txn.last_lsn.valid().is_some())
+            .map(|txn| ActiveTxn {
+                txn: txn.id.0,
+                first: txn.first_lsn,
+                last: txn.last_lsn,
+            })
+            .collect()
+    }
+
+    pub fn begin(&mut self) -> Result<TxnId, Error> {
+        if self.current.is_some() {
+            return Err(Error::AlreadyInTransaction);
+        }
+        let mut shared = self.shared.borrow_mut();
+        let id = TxnId(shared.next_id);
+        let snapshot = shared.snapshot(id);
+        shared.take_id()?;
+        shared.states.insert(id, TxnState::Active);
+        shared.xmins.insert(id, snapshot.xmin);
+        self.current = Some(Transaction::new(
+            id,
+            self.default_isolation,
+            snapshot,
+            shared.locks.clone(),
+            self.lock_wait(),
+            Rc::downgrade(&self.shared),
+        ));
+        METRICS.active_transactions.inc();
+        Ok(id)
+    }
+
+    // ログを書いたトランザクションなら、コミットのレコードがディスクに届いてから返る。
+    // SERIALIZABLE で直列化できなければ、トランザクションを取り消して SerializationFailure を返す
+    pub fn commit(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        let txn = self.current.as_ref().ok_or(Error::NoTransaction)?;
+        let checked = txn.serial.as_ref().map_or(Ok(()), |serial| {
+            self.shared.borrow_mut().commit_serializable(serial)
+        });
+        if let Err(err) = checked {
+            self.rollback(bufmgr, catalog)?;
+            return Err(err);
+        }
+        let txn = self.current.as_mut().unwrap();
+        txn.log_changes(bufmgr)?;
+        // ページの像を書いたなら、行を書き換えていなくてもコミットを記す。回復ではこれを見て残す版を決める
+        let images = bufmgr.log_images().map_err(recovery::Error::from)?;
+        let mut lsn = Lsn::INVALID_LSN;
+        if let Some(wal) = bufmgr.wal_mut() {
+            if txn.last_lsn.valid().is_some() || images.is_some() {
+                lsn = wal.commit(txn.id.0, txn.last_lsn)?;
+            }
+        }
+        let txn = self.current.take().unwrap();
+        self.finish_commit(txn, lsn);
+        Ok(())
+    }
+
+    // lsn はコミットのレコード
+    fn finish_commit(&self, txn: Transaction, lsn: Lsn) {
+        let mut shared = self.shared.borrow_mut();
+        shared.states.insert(txn.id, TxnState::Committed);
+        shared.xmins.remove(&txn.id);
+        // 読むだけのトランザクションはどの時点から見えても変わらない
+        if shared.history.retention.is_some()
+            && (!txn.undo.is_empty() || txn.last_lsn.valid().is_some())
+        {
+            let commit = Commit {
+                txn: txn.id,
+                time: Timestamp::now(),
+                lsn,
+                next: TxnId(shared.next_id),
+            };
+            shared.history.commits.push_back(commit);
+            shared.history.prune(commit.time);
+        }
+        let tables: HashSet<&String> = txn
+            .undo
+            .iter()
+            .map(|change| {
+                let (Undo::Insert { table, .. }
+                | Undo::Update { table, .. }
+                | Undo::Delete { table, .. }) = change;
+                table
+            })
+            .collect();
+        for table in tables {
+            *shared.commits.entry(table.clone()).or_default() += 1;
+        }
+        METRICS.active_transactions.dec();
+        shared.prune_serializable();
+        txn.locks.release_all(txn.id);
+    }
+
+    // 演算子が残した変更を取り消す。準備したトランザクションなら、取り消したこともログに記す
+    pub fn rollback(
+        &mut self,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        let txn = self.current.take().ok_or(Error::NoTransaction)?;
+        self.finish_rollback(txn, bufmgr, catalog)
+    }
+
+    fn finish_rollback(
+        &self,
+        txn: Transaction,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        let mut shared = self.shared.borrow_mut();
+        shared.states.insert(txn.id, TxnState::Aborted);
+        shared.xmins.remove(&txn.id);
+        METRICS.active_transactions.dec();
+        shared.prune_serializable();
+        drop(shared);
+        undo(bufmgr, catalog, txn.undo.into_iter())?;
+        if let Some(last) = txn.last_lsn.valid() {
+            recovery::rollback(bufmgr, txn.id.0, last)?;
+        }
+        txn.locks.release_all(txn.id);
+        Ok(())
+    }
+
+    // PREPARE TRANSACTION。トランザクションをセッションから切り離し、COMMIT PREPARED か ROLLBACK PREPARED を待つ。
+    // ロックは持ったままにする。ログがあれば準備のレコードがディスクに届いてから返るので、落ちても gid で終わらせられる。
+    // 失敗したらトランザクションは取り消す
+    pub fn prepare(
+        &mut self,
+        gid: &str,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        let txn = self.current.as_ref().ok_or(Error::NoTransaction)?;
+        let checked = if self.shared.borrow().prepared.contains_key(gid) {
+            Err(Error::DuplicateGid(gid.to_string()))
+        } else {
+            // 直列化できるかは準備するときに確かめる
+            txn.serial.as_ref().map_or(Ok(()), |serial| {
+                self.shared.borrow_mut().commit_serializable(serial)
+            })
+        };
+        if let Err(err) = checked {
+            self.rollback(bufmgr, catalog)?;
+            return Err(err);
+        }
+        let txn = self.current.as_mut().unwrap();
+        txn.log_changes(bufmgr)?;
+        bufmgr.log_images().map_err(recovery::Error::from)?;
+        if let Some(wal) = bufmgr.wal_mut() {
+            let lsn = wal.prepare(txn.id.0, txn.last_lsn, gid)?;
+            if txn.first_lsn.valid().is_none() {
+                txn.first_lsn = lsn;
+            }
+            txn.last_lsn = lsn;
+        }
+        let txn = self.current.take().unwrap();
+        let mut shared = self.shared.borrow_mut();
+        // 準備したトランザクションはもう読まない
+        shared.xmins.remove(&txn.id);
+        shared.prepared.insert(gid.to_string(), txn);
+        Ok(())
+    }
+
+    // COMMIT PREPARED。どのセッションからでも、トランザクションの外で呼べる
+    pub fn commit_prepared(
+        &mut self,
+        gid: &str,
+        bufmgr: &mut BufferPoolManager,
+    ) -> Result<(), Error> {
+        let txn = self.take_prepared(gid, "COMMIT PREPARED")?;
+        if let Err(err) = bufmgr.log_images() {
+            self.shared
+                .borrow_mut()
+                .prepared
+                .insert(gid.to_string(), txn);
+            return Err(recovery::Error::from(err).into());
+        }
+        let mut lsn = Lsn::INVALID_LSN;
+        if let (Some(wal), Some(prev)) = (bufmgr.wal_mut(), txn.last_lsn.valid()) {
+            match wal.commit(txn.id.0, prev) {
+                Ok(commit) => lsn = commit,
+                Err(err) => {
+                    self.shared
+                        .borrow_mut()
+                        .prepared
+                        .insert(gid.to_string(), txn);
+                    return Err(err.into());
+                }
+            }
+        }
+        self.finish_commit(txn, lsn);
+        Ok(())
+    }
+
+    // ROLLBACK PREPARED
+    pub fn rollback_prepared(
+        &mut self,
+        gid: &str,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        let txn = self.take_prepared(gid, "ROLLBACK PREPARED")?;
+        self.finish_rollback(txn, bufmgr, catalog)
+    }
+
+    fn take_prepared(&mut self, gid: &str, command: &'static str) -> Result<Transaction, Error> {
+        if self.current.is_some() {
+            return Err(Error::InTransactionBlock(command));
+        }
+        self.shared
+            .borrow_mut()
+            .prepared
+            .remove(gid)
+            .ok_or_else(|| Error::NoSuchPrepared(gid.to_string()))
+    }
+
+    // 準備したトランザクションの gid
+    pub fn prepared(&self) -> Vec<String> {
+        let mut gids = self
+            .shared
+            .borrow()
+            .prepared
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        gids.sort();
+        gids
+    }
+
+    // 回復で見つかった準備したままのトランザクションを、COMMIT PREPARED や ROLLBACK PREPARED で終わらせられるよう戻す。
+    // 演算子が残した変更とロックは戻らない。ROLLBACK PREPARED で取り消した版は見えなくなり、次に開くときに片づく
+    pub fn restore_prepared(&mut self, prepared: &[PreparedTxn]) {
+        let mut shared = self.shared.borrow_mut();
+        for restored in prepared {
+            let id = TxnId(restored.txn.txn);
+            shared.next_id = shared.next_id.max(id.0 + 1);
+            let snapshot = shared.snapshot(id);
+            shared.states.insert(id, TxnState::Active);
+            let mut txn = Transaction::new(
+                id,
+                self.default_isolation,
+                snapshot,
+                shared.locks.clone(),
+                LockWait::Block,
+                Rc::downgrade(&self.shared),
+            );
+            txn.first_lsn = restored.txn.first;
+            txn.last_lsn = restored.txn.last;
+            shared.prepared.insert(restored.gid.clone(), txn);
+            METRICS.active_transactions.inc();
+        }
+    }
+
+    pub fn savepoint(&mut self, name: &str) -> Result<(), Error> {
+        let txn = self.current.as_mut().ok_or(Error::NoTransaction)?;
+        txn.savepoints.push(Savepoint {
+            name: name.to_string(),
+            undo: txn.undo.len(),
+        });
+        Ok(())
+    }
+
+    // セーブポイントより後の変更を取り消し、それ以降に作られたセーブポイントを破棄する (指定したものは残る)。
+    // トランザクションは続き、失敗した操作をやり直せる
+    pub fn rollback_to(
+        &mut self,
+        name: &str,
+        bufmgr: &mut BufferPoolManager,
+        catalog: &Catalog,
+    ) -> Result<(), Error> {
+        let txn = self.current.as_mut().ok_or(Error::NoTransaction)?;
+        let pos = find_savepoint(txn, name)?;
+        txn.savepoints.truncate(pos + 1);
+        let savepoint = &txn.savepoints[pos];
+        undo(bufmgr, catalog, txn.undo.drain(savepoint.undo..))?;
+        Ok(())
+    }
+
+    // 指定したセーブポイントとそれ以降のものを破棄する
+    pub fn release(&mut self, name: &str) -> Result<(), Error> {
+        let txn = self.current.as_mut().ok_or(Error::NoTransaction)?;
+        let pos = find_savepoint(txn, name)?;
+        txn.savepoints.truncate(pos);
+        Ok(())
+    }
+}
+
+// 同名のセーブポイントがある場合は新しい方を使う
+fn find_savepoint(txn: &Transaction, name: &str) -> Result<usize, Error> {
+    txn.savepoints
+        .iter()
+        .rposition(|s| s.name == name)
+        .ok_or_else(|| Error::NoSuchSavepoint(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferPool;
+    use crate::disk::DiskManager;
+    use crate::sql::ast::Statement;
+    use crate::sql::parse;
+    use crate::testutil::{temp_bufmgr, temp_path};
+    use crate::wal::{LogRecord, Wal};
+
+    fn run(manager: &mut TransactionManager, sql: &str) -> Result<(), Error> {
+        let mut bufmgr = temp_bufmgr(1);
+        let catalog = Catalog::new();
+        for stmt in parse(sql).unwrap() {
+            let Statement::Transaction(stmt) = stmt else {
+                panic!("expected transaction statement");
+            };
+            manager.execute(&stmt, &mut bufmgr, &catalog)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_begin_commit() {
+        let mut manager = TransactionManager::new();
+        run(&mut manager, "BEGIN").unwrap();
+        assert!(manager.in_transaction());
+        assert!(matches!(
+            run(&mut manager, "BEGIN"),
+            Err(Error::AlreadyInTransaction)
+        ));
+        run(&mut manager, "COMMIT").unwrap();
+        assert!(!manager.in_transaction());
+        assert!(matches!(
+            run(&mut manager, "ROLLBACK"),
+            Err(Error::NoTransaction)
+        ));
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let mut manager = TransactionManager::new();
+        run(
+            &mut manager,
+            "START TRANSACTION; SAVEPOINT a; SAVEPOINT b; SAVEPOINT c; ROLLBACK TO SAVEPOINT b",
+        )
+        .unwrap();
+        assert_eq!(manager.current().unwrap().savepoints(), ["a", "b"]);
+        run(&mut manager, "RELEASE a").unwrap();
+        assert!(manager.current().unwrap().savepoints().is_empty());
+        assert!(matches!(
+            run(&mut manager, "ROLLBACK TO b"),
+            Err(Error::NoSuchSavepoint(name)) if name == "b"
+        ));
+        run(&mut manager, "ROLLBACK WORK").unwrap();
+        assert!(matches!(
+            run(&mut manager, "SAVEPOINT x"),
+            Err(Error::NoTransaction)
+        ));
+    }
+
+    #[test]
+    fn test_transaction_states() {
+        let dir = temp_path("wal");
+        let mut bufmgr = temp_bufmgr(2);
+        bufmgr.set_wal(Wal::open(&dir).unwrap());
+        let page = bufmgr.create_page().unwrap();
+        bufmgr.flush().unwrap();
+        bufmgr.log_page_images();
+        let mut manager = TransactionManager::new();
+
+        // 何も書かなかったトランザクションはログを書かない
+        let first = manager.begin().unwrap();
+        manager.commit(&mut bufmgr, &Catalog::new()).unwrap();
+        assert_eq!(manager.state(first), Some(TxnState::Committed));
+        assert_eq!(
+            bufmgr.wal_mut().unwrap().next_lsn(),
+            bufmgr.wal_mut().unwrap().first_lsn()
+        );
+
+        // ページを書き換えたトランザクションは、コミットするときにページの像とコミットを書く
+        let second = manager.begin().unwrap();
+        assert!(second > first);
+        assert_eq!(manager.state(second), Some(TxnState::Active));
+        page.page.borrow_mut()[10..13].copy_from_slice(b"abc");
+        page.is_dirty.set(true);
+        assert!(manager.active().is_empty());
+        manager.commit(&mut bufmgr, &Catalog::new()).unwrap();
+        assert_eq!(manager.state(second), Some(TxnState::Committed));
+        let wal = bufmgr.wal_mut().unwrap();
+        assert_eq!(wal.flushed_lsn(), wal.next_lsn());
+        let mut reader = wal.reader(wal.first_lsn()).unwrap();
+        let (image, _) = reader.next_record().unwrap().unwrap();
+        assert!(matches!(
+            reader.next_record().unwrap().unwrap().1,
+            LogRecord::Commit { txn, prev, .. } if txn == second.0 && prev == Lsn::INVALID_LSN
+        ));
+        assert!(reader.next_record().unwrap().is_none());
+        assert_eq!(
+            wal.record(image).unwrap(),
+            LogRecord::PageImage {
+                page_id: page.page_id,
+                image: page.page.borrow().to_vec(),
+            }
+        );
+
+        // 取り消すだけならログは書かない
+        let end = wal.next_lsn();
+        let third = manager.begin().unwrap();
+        manager.rollback(&mut bufmgr, &Catalog::new()).unwrap();
+        assert_eq!(manager.state(third), Some(TxnState::Aborted));
+        assert_eq!(bufmgr.wal_mut().unwrap().next_lsn(), end);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_horizon() {
+        let mut s1 = TransactionManager::new();
+        let mut s2 = s1.session();
+        assert_eq!(s1.horizon(), TxnId(2));
+        // 実行中のトランザクションが消した版は残す
+        let first = s1.begin().unwrap();
+        assert_eq!(s1.horizon(), first);
+        let second = s2.begin().unwrap();
+        run(&mut s1, "COMMIT").unwrap();
+        // 2 つめのスナップショットからは 1 つめの変更が見えない
+        assert_eq!(s2.horizon(), first);
+
+        // READ COMMITTED は文ごとにスナップショットを取り直すので進む
+        s2.current_mut().unwrap().isolation = IsolationLevel::ReadCommitted;
+        s2.start_statement();
+        assert_eq!(s2.horizon(), second);
+        run(&mut s2, "COMMIT").unwrap();
+        assert_eq!(s1.horizon(), TxnId(second.0 + 1));
+    }
+
+    #[test]
+    fn test_txn_id_space() {
+        let mut manager = TransactionManager::new();
+        // 回復で知った番号より前には戻らない
+        manager.advance_next_txn_id(TxnId(100));
+        manager.advance_next_txn_id(TxnId(50));
+        assert_eq!(manager.begin().unwrap(), TxnId(100));
+        run(&mut manager, "COMMIT").unwrap();
+        assert_eq!(manager.next_txn_id(), TxnId(101));
+
+        // 使い切っても一周して INVALID や FROZEN を振り直すことはない
+        manager.advance_next_txn_id(TxnId(u64::MAX - 1));
+        assert_eq!(manager.begin().unwrap(), TxnId(u64::MAX - 1));
+        run(&mut manager, "COMMIT").unwrap();
+        assert!(matches!(manager.begin(), Err(Error::TxnIdExhausted)));
+        assert!(manager.current().is_none());
+    }
+
+    #[test]
+    fn test_two_phase_commit() {
+        let mut s1 = TransactionManager::new();
+        let mut s2 = s1.session();
+        let first = s1.begin().unwrap();
+        run(&mut s1, "PREPARE TRANSACTION 'a'").unwrap();
+        // 準備したトランザクションはセッションから切り離され、まだコミットしていない
+        assert!(!s1.in_transaction());
+        assert_eq!(s1.prepared(), vec!["a"]);
+        assert_eq!(s1.state(first), Some(TxnState::Active));
+        // 同じ gid では準備できず、そのトランザクションは取り消す
+        let second = s1.begin().unwrap();
+        assert!(matches!(
+            run(&mut s1, "PREPARE TRANSACTION 'a'"),
+            Err(Error::DuplicateGid(_))
+        ));
+        assert_eq!(s1.state(second), Some(TxnState::Aborted));
+
+        // 別のセッションから終わらせる。トランザクションの中からは終わらせられない
+        run(&mut s2, "BEGIN").unwrap();
+        assert!(matches!(
+            run(&mut s2, "COMMIT PREPARED 'a'"),
+            Err(Error::InTransactionBlock("COMMIT PREPARED"))
+        ));
+        run(&mut s2, "ROLLBACK; COMMIT PREPARED 'a'").unwrap();
+        assert_eq!(s1.state(first), Some(TxnState::Committed));
+        assert!(s1.prepared().is_empty());
+        assert!(matches!(
+            run(&mut s2, "ROLLBACK PREPARED 'a'"),
+            Err(Error::NoSuchPrepared(_))
+        ));
+
+        let third = s1.begin().unwrap();
+        run(&mut s1, "PREPARE TRANSACTION 'b'; ROLLBACK PREPARED 'b'").unwrap();
+        assert_eq!(s1.state(third), Some(TxnState::Aborted));
+        assert!(matches!(
+            run(&mut s1, "PREPARE TRANSACTION 'c'"),
+            Err(Error::NoTransaction)
+        ));
+    }
+
+    #[test]
+    fn test_prepared_survives_restart() {
+        let (heap, dir) = (temp_path("heap.db"), temp_path("wal"));
+        let open = || {
+            let disk = DiskManager::open(&heap).unwrap();
+            let mut bufmgr = BufferPoolManager::new(disk, BufferPool::new(2));
+            bufmgr.set_wal(Wal::open(&dir).unwrap());
+            bufmgr.log_page_images();
+            bufmgr
+        };
+        let read = |bufmgr: &mut BufferPoolManager, page_id| {
+            bufmgr.fetch_page(page_id).unwrap().page.borrow()[..2].to_vec()
+        };
+        let catalog = Catalog::new();
+        let mut bufmgr = open();
+        let page = bufmgr.create_page().unwrap();
+        bufmgr.flush().unwrap();
+        let mut manager = TransactionManager::new();
+        let mut ids = vec![];
+        for (gid, offset, data) in [("a", 0, b'a'), ("b", 1, b'b')] {
+            ids.push(manager.begin().unwrap().0);
+            page.page.borrow_mut()[offset] = data;
+            page.is_dirty.set(true);
+            manager.prepare(gid, &mut bufmgr, &catalog).unwrap();
+        }
+        // 準備したものはチェックポイントに載る
+        let active = manager.active();
+        assert_eq!(active.len(), 2);
+        recovery::checkpoint(&mut bufmgr, &active, manager.next_txn_id().0).unwrap();
+        let page_id = page.page_id;
+        drop(page);
+        drop(bufmgr);
+
+        // 落ちても取り消さず、gid で終わらせられる
+        let mut bufmgr = open();
+        let stats = recovery::recover(&mut bufmgr).unwrap();
+        assert!(stats.aborted.is_empty());
+        assert_eq!(read(&mut bufmgr, page_id), b"ab");
+        let mut manager = TransactionManager::new();
+        manager.advance_next_txn_id(TxnId(stats.next_txn));
+        manager.restore_prepared(&stats.prepared);
+        assert_eq!(manager.prepared(), vec!["a", "b"]);
+        manager.commit_prepared("a", &mut bufmgr).unwrap();
+        manager
+            .rollback_prepared("b", &mut bufmgr, &catalog)
+            .unwrap();
+        drop(bufmgr);
+
+        // ページはそのままで、どちらの版を残すかはコミットを記したかで決まる
+        let mut bufmgr = open();
+        let stats = recovery::recover(&mut bufmgr).unwrap();
+        assert!(stats.prepared.is_empty());
+        assert!(stats.aborted.is_empty());
+        assert_eq!(read(&mut bufmgr, page_id), b"ab");
+        let (_, committed) = recovery::committed(&mut bufmgr).unwrap();
+        assert!(committed.contains(&ids[0]));
+        assert!(!committed.contains(&ids[1]));
+        drop(bufmgr);
+        std::fs::remove_file(&heap).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
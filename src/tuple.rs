@@ -0,0 +1,436 @@
+use std::sync::Arc;
+
+use crate::datetime::{Date, Interval, Time, Timestamp};
+use crate::decimal::Decimal;
+use crate::enum_type::{self, EnumType};
+use crate::geometry::{Point, Rect};
+use crate::types::Value;
+use crate::uuid::Uuid;
+
+// 値ごとに 1 バイトのタグを付けて並べる
+const TAG_NULL: u8 = 0;
+const TAG_INTEGER: u8 = 1;
+const TAG_TEXT: u8 = 2;
+const TAG_BOOLEAN: u8 = 3;
+const TAG_BIGINT: u8 = 4;
+const TAG_REAL: u8 = 5;
+const TAG_BLOB: u8 = 6;
+const TAG_DECIMAL: u8 = 7;
+const TAG_DATE: u8 = 8;
+const TAG_TIME: u8 = 9;
+const TAG_TIMESTAMP: u8 = 10;
+const TAG_JSON: u8 = 11;
+const TAG_UUID: u8 = 12;
+const TAG_INTERVAL: u8 = 13;
+const TAG_POINT: u8 = 14;
+const TAG_BOX: u8 = 15;
+// 辞書の番号 (4) で入れた TEXT の値
+const TAG_CODE: u8 = 16;
+// 配列は要素を並べたバイト数 (4) のあとに、要素を同じ形で並べる
+const TAG_ARRAY: u8 = 17;
+// 列挙型は型の番号 (4) とラベルの番号 (4)
+const TAG_ENUM: u8 = 18;
+
+pub fn encode(values: &[Value], bytes: &mut Vec<u8>) {
+    encode_with(values, bytes, |_, _| None)
+}
+
+// code(i, s) が番号を返した i 番目の TEXT の値は、文字列の代わりにその番号を入れる
+pub fn encode_with(
+    values: &[Value],
+    bytes: &mut Vec<u8>,
+    code: impl Fn(usize, &str) -> Option<u32>,
+) {
+    for (i, value) in values.iter().enumerate() {
+        let code = match value {
+            Value::Text(s) => code(i, s),
+            _ => None,
+        };
+        if let Some(code) = code {
+            bytes.push(TAG_CODE);
+            bytes.extend_from_slice(&code.to_be_bytes());
+            continue;
+        }
+        match value {
+            Value::Null => bytes.push(TAG_NULL),
+            Value::Integer(n) => {
+                bytes.push(TAG_INTEGER);
+                bytes.extend_from_slice(&n.to_be_bytes());
+            }
+            Value::BigInt(n) => {
+                bytes.push(TAG_BIGINT);
+                bytes.extend_from_slice(&n.to_be_bytes());
+            }
+            Value::Real(f) => {
+                bytes.push(TAG_REAL);
+                bytes.extend_from_slice(&f.to_be_bytes());
+            }
+            Value::Decimal(d) => {
+                bytes.push(TAG_DECIMAL);
+                bytes.extend_from_slice(&d.mantissa().to_be_bytes());
+                bytes.push(d.scale());
+            }
+            Value::Text(s) => {
+                bytes.push(TAG_TEXT);
+                bytes.extend_from_slice(&(s.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(s.as_bytes());
+            }
+            Value::Json(s) => {
+                bytes.push(TAG_JSON);
+                bytes.extend_from_slice(&(s.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(s.as_bytes());
+            }
+            Value::Uuid(u) => {
+                bytes.push(TAG_UUID);
+                bytes.extend_from_slice(u.as_bytes());
+            }
+            Value::Interval(i) => {
+                bytes.push(TAG_INTERVAL);
+                bytes.extend_from_slice(&i.months().to_be_bytes());
+                bytes.extend_from_slice(&i.days().to_be_bytes());
+                bytes.extend_from_slice(&i.micros().to_be_bytes());
+            }
+            Value::Point(p) => {
+                bytes.push(TAG_POINT);
+                encode_point(p, bytes);
+            }
+            Value::Box(b) => {
+                bytes.push(TAG_BOX);
+                encode_point(&b.low, bytes);
+                encode_point(&b.high, bytes);
+            }
+            Value::Boolean(b) => {
+                bytes.push(TAG_BOOLEAN);
+                bytes.push(*b as u8);
+            }
+            Value::Blob(b) => {
+                bytes.push(TAG_BLOB);
+                bytes.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(b);
+            }
+            Value::Date(d) => {
+                bytes.push(TAG_DATE);
+                bytes.extend_from_slice(&d.days().to_be_bytes());
+            }
+            Value::Time(t) => {
+                bytes.push(TAG_TIME);
+                bytes.extend_from_slice(&t.micros().to_be_bytes());
+            }
+            Value::Timestamp(t) => {
+                bytes.push(TAG_TIMESTAMP);
+                bytes.extend_from_slice(&t.micros().to_be_bytes());
+            }
+            Value::Array(items) => {
+                bytes.push(TAG_ARRAY);
+                let start = bytes.len();
+                bytes.extend_from_slice(&[0; 4]);
+                encode(items, bytes);
+                let len = (bytes.len() - start - 4) as u32;
+                bytes[start..start + 4].copy_from_slice(&len.to_be_bytes());
+            }
+            Value::Enum(e) => {
+                bytes.push(TAG_ENUM);
+                bytes.extend_from_slice(&e.ty().id.to_be_bytes());
+                bytes.extend_from_slice(&e.code().to_be_bytes());
+            }
+        }
+    }
+}
+
+// 壊れたデータの場合は None。列挙型の値は enums の中の型で読む
+pub fn decode(bytes: &[u8], enums: &[Arc<EnumType>]) -> Option<Vec<Value>> {
+    decode_with(bytes, |_| true, enums, |_, _| None)
+}
+
+// needed[i] が false の列と needed より後ろの列は、値を作らずに NULL にする。列挙型の値は読めない
+pub fn decode_columns(bytes: &[u8], needed: &[bool]) -> Option<Vec<Value>> {
+    decode_with(
+        bytes,
+        |i| needed.get(i).copied().unwrap_or(false),
+        &[],
+        |_, _| None,
+    )
+}
+
+// 番号で入れた i 番目の TEXT の値を text(i, code) で文字列に戻す。needed は decode_columns と同じ
+pub fn decode_dictionary(
+    bytes: &[u8],
+    needed: Option<&[bool]>,
+    enums: &[Arc<EnumType>],
+    text: impl Fn(usize, u32) -> Option<String>,
+) -> Option<Vec<Value>> {
+    let keep = |i: usize| needed.is_none_or(|needed| needed.get(i).copied().unwrap_or(false));
+    decode_with(bytes, keep, enums, text)
+}
+
+// column 番目の値が辞書の番号で入っていれば、値を作らずにその番号を返す
+pub fn column_code(bytes: &[u8], column: usize) -> Option<u32> {
+    let mut offset = 0;
+    for _ in 0..column {
+        let tag = *bytes.get(offset)?;
+        offset += 1 + value_size(tag, bytes.get(offset + 1..)?)?;
+    }
+    match bytes.get(offset..offset + 5)? {
+        [TAG_CODE, code @ ..] => Some(u32::from_be_bytes(code.try_into().unwrap())),
+        _ => None,
+    }
+}
+
+// タグに続く値のバイト数
+fn value_size(tag: u8, bytes: &[u8]) -> Option<usize> {
+    Some(match tag {
+        TAG_NULL => 0,
+        TAG_BOOLEAN => 1,
+        TAG_DATE | TAG_CODE => 4,
+        TAG_INTEGER | TAG_BIGINT | TAG_REAL | TAG_TIME | TAG_TIMESTAMP | TAG_ENUM => 8,
+        TAG_DECIMAL => 9,
+        TAG_UUID | TAG_INTERVAL | TAG_POINT => 16,
+        TAG_BOX => 32,
+        TAG_TEXT | TAG_BLOB | TAG_JSON | TAG_ARRAY => {
+            4 + u32::from_be_bytes(*bytes.first_chunk::<4>()?) as usize
+        }
+        _ => return None,
+    })
+}
+
+fn decode_with(
+    mut bytes: &[u8],
+    needed: impl Fn(usize) -> bool,
+    enums: &[Arc<EnumType>],
+    text: impl Fn(usize, u32) -> Option<String>,
+) -> Option<Vec<Value>> {
+    let mut values = vec![];
+    while let Some((&tag, rest)) = bytes.split_first() {
+        bytes = rest;
+        let keep = needed(values.len());
+        let value = match tag {
+            TAG_NULL => Value::Null,
+            TAG_INTEGER | TAG_BIGINT => {
+                let (n, rest) = bytes.split_first_chunk::<8>()?;
+                bytes = rest;
+                let n = i64::from_be_bytes(*n);
+                if tag == TAG_INTEGER {
+                    Value::Integer(n)
+                } else {
+                    Value::BigInt(n)
+                }
+            }
+            TAG_REAL => {
+                let (f, rest) = bytes.split_first_chunk::<8>()?;
+                bytes = rest;
+                Value::Real(f64::from_be_bytes(*f))
+            }
+            TAG_DECIMAL => {
+                let (m, rest) = bytes.split_first_chunk::<8>()?;
+                let (&scale, rest) = rest.split_first()?;
+                bytes = rest;
+                Value::Decimal(Decimal::new(i64::from_be_bytes(*m) as i128, scale)?)
+            }
+            TAG_DATE => {
+                let (n, rest) = bytes.split_first_chunk::<4>()?;
+                bytes = rest;
+                Value::Date(Date::from_days(i32::from_be_bytes(*n) as i64)?)
+            }
+            TAG_TIME | TAG_TIMESTAMP => {
+                let (n, rest) = bytes.split_first_chunk::<8>()?;
+                bytes = rest;
+                let n = i64::from_be_bytes(*n);
+                if tag == TAG_TIME {
+                    Value::Time(Time::from_micros(n)?)
+                } else {
+                    Value::Timestamp(Timestamp::from_micros(n)?)
+                }
+            }
+            TAG_TEXT | TAG_BLOB | TAG_JSON => {
+                let (len, rest) = bytes.split_first_chunk::<4>()?;
+                let len = u32::from_be_bytes(*len) as usize;
+                if rest.len() < len {
+                    return None;
+                }
+                let (s, rest) = rest.split_at(len);
+                bytes = rest;
+                if !keep {
+                    Value::Null
+                } else if tag == TAG_TEXT {
+                    Value::Text(String::from_utf8(s.to_vec()).ok()?)
+                } else if tag == TAG_JSON {
+                    Value::Json(String::from_utf8(s.to_vec()).ok()?.into())
+                } else {
+                    Value::Blob(s.into())
+                }
+            }
+            TAG_UUID => {
+                let (u, rest) = bytes.split_first_chunk::<16>()?;
+                bytes = rest;
+                Value::Uuid(Uuid::from_bytes(*u))
+            }
+            TAG_INTERVAL => {
+                let (months, rest) = bytes.split_first_chunk::<4>()?;
+                let (days, rest) = rest.split_first_chunk::<4>()?;
+                let (micros, rest) = rest.split_first_chunk::<8>()?;
+                bytes = rest;
+                Value::Interval(Interval::new(
+                    i32::from_be_bytes(*months),
+                    i32::from_be_bytes(*days),
+                    i64::from_be_bytes(*micros),
+                ))
+            }
+            TAG_POINT => Value::Point(decode_point(&mut bytes)?),
+            TAG_BOX => {
+                let low = decode_point(&mut bytes)?;
+                let high = decode_point(&mut bytes)?;
+                Value::Box(Box::new(Rect { low, high }))
+            }
+            TAG_ARRAY => {
+                let (len, rest) = bytes.split_first_chunk::<4>()?;
+                let len = u32::from_be_bytes(*len) as usize;
+                if rest.len() < len {
+                    return None;
+                }
+                let (items, rest) = rest.split_at(len);
+                bytes = rest;
+                if keep {
+                    Value::Array(decode(items, enums)?.into())
+                } else {
+                    Value::Null
+                }
+            }
+            TAG_BOOLEAN => {
+                let (&b, rest) = bytes.split_first()?;
+                bytes = rest;
+                Value::Boolean(b != 0)
+            }
+            TAG_ENUM => {
+                let (id, rest) = bytes.split_first_chunk::<4>()?;
+                let (code, rest) = rest.split_first_chunk::<4>()?;
+                bytes = rest;
+                let ty = enum_type::find(enums, u32::from_be_bytes(*id))?;
+                Value::Enum(ty.from_code(u32::from_be_bytes(*code))?)
+            }
+            TAG_CODE => {
+                let (code, rest) = bytes.split_first_chunk::<4>()?;
+                bytes = rest;
+                if keep {
+                    Value::Text(text(values.len(), u32::from_be_bytes(*code))?)
+                } else {
+                    Value::Null
+                }
+            }
+            _ => return None,
+        };
+        values.push(if keep { value } else { Value::Null });
+    }
+    Some(values)
+}
+
+fn encode_point(p: &Point, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&p.x.to_be_bytes());
+    bytes.extend_from_slice(&p.y.to_be_bytes());
+}
+
+fn decode_point(bytes: &mut &[u8]) -> Option<Point> {
+    let (x, rest) = bytes.split_first_chunk::<8>()?;
+    let (y, rest) = rest.split_first_chunk::<8>()?;
+    *bytes = rest;
+    Some(Point::new(f64::from_be_bytes(*x), f64::from_be_bytes(*y)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let values = vec![
+            Value::Integer(-3),
+            Value::Text("こんにちは".to_string()),
+            Value::Null,
+            Value::Boolean(true),
+        ];
+        let mut bytes = vec![];
+        encode(&values, &mut bytes);
+        assert_eq!(decode(&bytes, &[]), Some(values));
+        assert_eq!(decode(&bytes[..bytes.len() - 1], &[]), None);
+        assert_eq!(
+            decode_columns(&bytes, &[false, false, true, true]),
+            Some(vec![
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Boolean(true)
+            ])
+        );
+        assert_eq!(
+            decode_columns(&bytes, &[true]),
+            Some(vec![
+                Value::Integer(-3),
+                Value::Null,
+                Value::Null,
+                Value::Null
+            ])
+        );
+
+        let values = vec![
+            Value::BigInt(i64::MIN),
+            Value::Real(-1.5),
+            Value::Decimal("-12.50".parse().unwrap()),
+            Value::Blob(vec![0, 1, 2].into()),
+            Value::Date("2024-01-15".parse().unwrap()),
+            Value::Time("13:45:00.5".parse().unwrap()),
+            Value::Timestamp("1900-03-01 08:00".parse().unwrap()),
+            Value::Json("{\"a\":1}".into()),
+            Value::Uuid(Uuid::new_v4()),
+            Value::Interval("1 mon -2 days 03:00".parse().unwrap()),
+            Value::Point(Point::new(1.5, -2.0)),
+            Value::Box(Box::new("(0,0),(3,4)".parse().unwrap())),
+            Value::Array(vec![Value::Integer(1), Value::Null, Value::Text("a".to_string())].into()),
+        ];
+        let mut bytes = vec![];
+        encode(&values, &mut bytes);
+        let decoded = decode(&bytes, &[]).unwrap();
+        assert_eq!(decoded, values);
+        assert_eq!(
+            decoded.iter().map(Value::type_name).collect::<Vec<_>>(),
+            vec![
+                "bigint",
+                "real",
+                "numeric",
+                "blob",
+                "date",
+                "time",
+                "timestamp",
+                "json",
+                "uuid",
+                "interval",
+                "point",
+                "box",
+                "integer[]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dictionary_codes() {
+        let values = vec![
+            Value::Point(Point::new(1.0, 2.0)),
+            Value::Text("red".to_string()),
+            Value::Text("blue".to_string()),
+            Value::Text("red".to_string()),
+        ];
+        let code = |i: usize, s: &str| (i >= 2).then_some(s.len() as u32);
+        let mut bytes = vec![];
+        encode_with(&values, &mut bytes, code);
+        assert_eq!(
+            [0, 1, 2, 3].map(|i| column_code(&bytes, i)),
+            [None, None, Some(4), Some(3)]
+        );
+        assert_eq!(decode(&bytes, &[]), None);
+        let text =
+            |_: usize, code: u32| Some(["", "", "", "red", "blue"][code as usize].to_string());
+        assert_eq!(decode_dictionary(&bytes, None, &[], text), Some(values));
+        assert_eq!(
+            decode_dictionary(&bytes, Some(&[false, false, true]), &[], text).unwrap()[2..],
+            [Value::Text("blue".to_string()), Value::Null]
+        );
+    }
+}
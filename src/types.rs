@@ -0,0 +1,1152 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::num::IntErrorKind;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::datetime::{Date, Interval, Time, Timestamp};
+use crate::decimal::{Decimal, MAX_PRECISION};
+use crate::enum_type::{EnumType, EnumValue};
+use crate::geometry::{Point, Rect};
+use crate::json::Json;
+use crate::uuid::Uuid;
+
+// 層のあいだで受け渡す SQL の値
+//
+// INTEGER も BIGINT も 64 bit で持ち、違いは列の型だけ。数値どうしは型が違っても値で比べ、
+// 等しい数値は同じハッシュになる。REAL の NaN はどの数値よりも大きく、NaN どうしは等しい。
+// DATE と TIMESTAMP も比べるときはひとつにまとめ、日付はその日の 0 時として比べる。
+// Blob は Box<[u8]> に、JSON は Box<str> にして、Value の大きさを String と同じ 24 バイトに収めている。
+// JSON は空白を詰めて書き直した文字列で持ち、同じ文字列になる値どうしが等しい。UUID は 16 バイトのまま持つ。
+// INTERVAL は 1 か月を 30 日として比べる。POINT は x、y の順に、BOX は左下、右上の点の順に比べ、
+// BOX は Box<Rect> にして大きさを抑えている。
+// 配列は要素を前から比べ、前の要素がすべて等しければ短いほうが小さい。要素は NULL でもよく、配列の配列は持たない。
+// 列挙型の値は型の中ではラベルを宣言した順に並び、違う型の値は型を作った順に並ぶ
+#[derive(Debug, Clone)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    BigInt(i64),
+    Real(f64),
+    Decimal(Decimal),
+    Text(String),
+    Boolean(bool),
+    Blob(Box<[u8]>),
+    Date(Date),
+    Time(Time),
+    Timestamp(Timestamp),
+    Json(Box<str>),
+    Uuid(Uuid),
+    Interval(Interval),
+    Point(Point),
+    Box(Box<Rect>),
+    Array(Box<[Value]>),
+    Enum(EnumValue),
+}
+
+// 列の型
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DataType {
+    Integer,
+    BigInt,
+    Real,
+    // 全体の桁数と小数点以下の桁数
+    Decimal { precision: u8, scale: u8 },
+    Text,
+    Boolean,
+    Blob,
+    Date,
+    Time,
+    Timestamp,
+    Json,
+    Uuid,
+    Interval,
+    Point,
+    Box,
+    // 要素の型。DataType::array で作る
+    Array(&'static DataType),
+    Enum(Arc<EnumType>),
+}
+
+// 型の変換を許す場面。広い場面では狭い場面の変換もできる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Coercion {
+    // 演算や比較で黙って変換してよい。値は変わらない
+    Implicit,
+    // 列に入れるとき。丸めたり、文字列を日時として読んだりする
+    Assignment,
+    // CAST で明示したときだけ。情報が落ちたり、読めない文字列で失敗したりする
+    Explicit,
+}
+
+// 型の変換の失敗
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoerceError {
+    // その場面では変換できない型
+    Mismatch,
+    // DECIMAL の整数部の桁が足りないか、整数に収まらない
+    Overflow,
+    // 変換先の型として読めない文字列
+    InvalidSyntax(String),
+}
+
+// 整数か実数か 10 進数の値
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64),
+    Real(f64),
+    Decimal(Decimal),
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    // 並べ替え用の全順序。NULL は最後で、型が違う値は型ごとにまとめる。数値の型はひとつにまとめる
+    pub fn sort_cmp(&self, other: &Value) -> Ordering {
+        let rank = |value: &Value| match value {
+            Value::Boolean(_) => 0,
+            Value::Integer(_) | Value::BigInt(_) | Value::Real(_) | Value::Decimal(_) => 1,
+            Value::Date(_) | Value::Timestamp(_) => 2,
+            Value::Time(_) => 3,
+            Value::Text(_) => 4,
+            Value::Blob(_) => 5,
+            Value::Json(_) => 6,
+            Value::Uuid(_) => 7,
+            Value::Interval(_) => 8,
+            Value::Point(_) => 9,
+            Value::Box(_) => 10,
+            Value::Array(_) => 11,
+            Value::Enum(_) => 12,
+            Value::Null => 13,
+        };
+        match (self, other) {
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            (Value::Json(a), Value::Json(b)) => a.cmp(b),
+            (Value::Uuid(a), Value::Uuid(b)) => a.cmp(b),
+            (Value::Interval(a), Value::Interval(b)) => a.total_micros().cmp(&b.total_micros()),
+            (Value::Point(a), Value::Point(b)) => cmp_point(a, b),
+            (Value::Box(a), Value::Box(b)) => {
+                cmp_point(&a.low, &b.low).then_with(|| cmp_point(&a.high, &b.high))
+            }
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            (Value::Time(a), Value::Time(b)) => a.cmp(b),
+            (Value::Array(a), Value::Array(b)) => a
+                .iter()
+                .zip(b.iter())
+                .map(|(a, b)| a.sort_cmp(b))
+                .find(|ord| ord.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Value::Enum(a), Value::Enum(b)) if a.ty() == b.ty() => a.sort().cmp(&b.sort()),
+            (Value::Enum(a), Value::Enum(b)) => a.ty().id.cmp(&b.ty().id),
+            _ if self.timestamp().is_some() && other.timestamp().is_some() => {
+                self.timestamp().cmp(&other.timestamp())
+            }
+            _ => match (self.number(), other.number()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                _ => rank(self).cmp(&rank(other)),
+            },
+        }
+    }
+
+    // エラーメッセージに使う型名
+    pub fn type_name(&self) -> String {
+        match self.data_type() {
+            Some(ty) => ty.name().to_string(),
+            None => "unknown".to_string(),
+        }
+    }
+
+    // 値の型。NULL は型を持たない
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            Value::Null => None,
+            Value::Integer(_) => Some(DataType::Integer),
+            Value::BigInt(_) => Some(DataType::BigInt),
+            Value::Real(_) => Some(DataType::Real),
+            Value::Decimal(d) => Some(DataType::Decimal {
+                precision: MAX_PRECISION,
+                scale: d.scale(),
+            }),
+            Value::Text(_) => Some(DataType::Text),
+            Value::Boolean(_) => Some(DataType::Boolean),
+            Value::Blob(_) => Some(DataType::Blob),
+            Value::Date(_) => Some(DataType::Date),
+            Value::Time(_) => Some(DataType::Time),
+            Value::Timestamp(_) => Some(DataType::Timestamp),
+            Value::Json(_) => Some(DataType::Json),
+            Value::Uuid(_) => Some(DataType::Uuid),
+            Value::Interval(_) => Some(DataType::Interval),
+            Value::Point(_) => Some(DataType::Point),
+            Value::Box(_) => Some(DataType::Box),
+            // 要素の型は NULL でない最初の要素の型。なければ TEXT の配列とする
+            Value::Array(items) => {
+                let element = items.iter().find_map(Value::data_type);
+                DataType::array(element.unwrap_or(DataType::Text))
+            }
+            Value::Enum(e) => Some(DataType::Enum(e.ty().clone())),
+        }
+    }
+
+    // POINT か BOX なら囲む矩形。点は幅のない矩形にする
+    pub fn rect(&self) -> Option<Rect> {
+        match self {
+            Value::Point(p) => Some(Rect::point(*p)),
+            Value::Box(b) => Some(**b),
+            _ => None,
+        }
+    }
+
+    pub fn is_temporal(&self) -> bool {
+        matches!(self, Value::Date(_) | Value::Time(_) | Value::Timestamp(_))
+    }
+
+    // DATE か TIMESTAMP なら TIMESTAMP にしたもの
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        match self {
+            Value::Date(d) => Some(Timestamp::from(*d)),
+            Value::Timestamp(t) => Some(*t),
+            _ => None,
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.number().is_some()
+    }
+
+    // 数値なら f64 にしたもの
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.number()? {
+            Number::Int(n) => Some(n as f64),
+            Number::Real(f) => Some(f),
+            Number::Decimal(d) => Some(d.to_f64()),
+        }
+    }
+
+    fn number(&self) -> Option<Number> {
+        match self {
+            Value::Integer(n) | Value::BigInt(n) => Some(Number::Int(*n)),
+            Value::Real(f) => Some(Number::Real(*f)),
+            Value::Decimal(d) => Some(Number::Decimal(*d)),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.sort_cmp(other).is_eq()
+    }
+}
+
+impl Eq for Value {}
+
+// 型ごとの番号のあとに中身を書く。derive していたときと同じハッシュ値になるようにしてある
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Null => state.write_isize(0),
+            Value::Text(s) => {
+                state.write_isize(2);
+                s.hash(state);
+            }
+            Value::Boolean(b) => {
+                state.write_isize(3);
+                b.hash(state);
+            }
+            Value::Blob(b) => {
+                state.write_isize(4);
+                b.hash(state);
+            }
+            Value::Date(_) | Value::Timestamp(_) => {
+                state.write_isize(5);
+                self.timestamp().unwrap().hash(state);
+            }
+            Value::Time(t) => {
+                state.write_isize(6);
+                t.hash(state);
+            }
+            Value::Json(s) => {
+                state.write_isize(7);
+                s.hash(state);
+            }
+            Value::Uuid(u) => {
+                state.write_isize(8);
+                u.hash(state);
+            }
+            Value::Interval(i) => {
+                state.write_isize(9);
+                i.total_micros().hash(state);
+            }
+            Value::Point(p) => {
+                state.write_isize(10);
+                hash_point(p, state);
+            }
+            Value::Box(b) => {
+                state.write_isize(11);
+                hash_point(&b.low, state);
+                hash_point(&b.high, state);
+            }
+            Value::Array(items) => {
+                state.write_isize(12);
+                items.len().hash(state);
+                items.iter().for_each(|item| item.hash(state));
+            }
+            Value::Enum(e) => {
+                state.write_isize(13);
+                e.ty().id.hash(state);
+                e.code().hash(state);
+            }
+            // 整数で表せる数は整数、f64 で表せる数は f64 と同じにする
+            _ => {
+                state.write_isize(1);
+                match self.number() {
+                    Some(Number::Int(n)) => n.hash(state),
+                    Some(Number::Real(f)) => hash_real(f, state),
+                    Some(Number::Decimal(d)) => {
+                        let d = d.normalize();
+                        let f = d.to_f64();
+                        if d.scale() == 0 {
+                            d.mantissa().hash(state)
+                        } else if d.cmp_f64(f).is_eq() {
+                            hash_real(f, state)
+                        } else {
+                            (d.mantissa(), d.scale()).hash(state)
+                        }
+                    }
+                    None => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+fn hash_real<H: Hasher>(f: f64, state: &mut H) {
+    if f.fract() == 0.0 && in_i64(f) {
+        (f as i64).hash(state)
+    } else if f.is_nan() {
+        f64::NAN.to_bits().hash(state)
+    } else {
+        f.to_bits().hash(state)
+    }
+}
+
+// -0.0 と 0.0 を同じにする
+fn hash_point<H: Hasher>(p: &Point, state: &mut H) {
+    (p.x + 0.0).to_bits().hash(state);
+    (p.y + 0.0).to_bits().hash(state);
+}
+
+impl Number {
+    fn cmp(self, other: Number) -> Ordering {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => a.cmp(&b),
+            (Number::Real(a), Number::Real(b)) => cmp_real(a, b),
+            (Number::Decimal(a), Number::Decimal(b)) => a.cmp(&b),
+            (Number::Int(a), Number::Real(b)) => cmp_int_real(a, b),
+            (Number::Real(a), Number::Int(b)) => cmp_int_real(b, a).reverse(),
+            (Number::Decimal(a), Number::Int(b)) => a.cmp_i64(b),
+            (Number::Int(a), Number::Decimal(b)) => b.cmp_i64(a).reverse(),
+            (Number::Decimal(a), Number::Real(b)) => a.cmp_f64(b),
+            (Number::Real(a), Number::Decimal(b)) => b.cmp_f64(a).reverse(),
+        }
+    }
+}
+
+// NaN を最大、-0.0 と 0.0 を等しいとする
+fn cmp_real(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        _ => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+fn cmp_point(a: &Point, b: &Point) -> Ordering {
+    cmp_real(a.x, b.x).then_with(|| cmp_real(a.y, b.y))
+}
+
+// f64 にすると丸まる整数もあるので、実数の整数部と小数部に分けて比べる
+fn cmp_int_real(a: i64, b: f64) -> Ordering {
+    if !in_i64(b) {
+        return if b > 0.0 || b.is_nan() {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+    }
+    a.cmp(&(b.trunc() as i64))
+        .then_with(|| cmp_real(0.0, b.fract()))
+}
+
+// 小数部を切り捨てると i64 に収まる
+fn in_i64(f: f64) -> bool {
+    (-9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0).contains(&f)
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("NULL"),
+            Value::Integer(n) | Value::BigInt(n) => write!(f, "{}", n),
+            Value::Real(x) if x.is_infinite() => {
+                f.write_str(if *x > 0.0 { "Infinity" } else { "-Infinity" })
+            }
+            Value::Real(x) => write!(f, "{}", x),
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::Text(s) => f.write_str(s),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Blob(b) => {
+                f.write_str("\\x")?;
+                b.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+            Value::Date(d) => write!(f, "{}", d),
+            Value::Time(t) => write!(f, "{}", t),
+            Value::Timestamp(t) => write!(f, "{}", t),
+            Value::Json(s) => f.write_str(s),
+            Value::Uuid(u) => write!(f, "{}", u),
+            Value::Interval(i) => write!(f, "{}", i),
+            Value::Point(p) => write!(f, "{}", p),
+            Value::Box(b) => write!(f, "{}", b),
+            Value::Array(items) => {
+                f.write_str("{")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    match item {
+                        Value::Null => f.write_str("NULL")?,
+                        item => write_array_item(f, &item.to_string())?,
+                    }
+                }
+                f.write_str("}")
+            }
+            Value::Enum(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// 配列の文字列表現では、空の文字列や、区切りや空白を含む要素や、NULL と読める要素を " で囲み、" と \ の前に \ を置く
+fn write_array_item(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    let quote = s.is_empty()
+        || s.eq_ignore_ascii_case("NULL")
+        || s.chars()
+            .any(|c| matches!(c, '{' | '}' | ',' | '"' | '\\') || c.is_whitespace());
+    if !quote {
+        return f.write_str(s);
+    }
+    f.write_str("\"")?;
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            f.write_str("\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    f.write_str("\"")
+}
+
+// {1,2,"a b",NULL} を要素の文字列に分ける。" で囲んでいない NULL は None
+fn parse_array(s: &str) -> Option<Vec<Option<String>>> {
+    let body = s.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let mut items = vec![];
+    if body.trim().is_empty() {
+        return Some(items);
+    }
+    let mut chars = body.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let item = if chars.next_if_eq(&'"').is_some() {
+            let mut item = String::new();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => item.push(chars.next()?),
+                    c => item.push(c),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            Some(item)
+        } else {
+            let mut item = String::new();
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                if matches!(c, '{' | '}' | '"') {
+                    return None;
+                }
+                item.push(c);
+            }
+            let item = item.trim();
+            if item.is_empty() {
+                return None;
+            }
+            (!item.eq_ignore_ascii_case("NULL")).then(|| item.to_string())
+        };
+        items.push(item);
+        match chars.next() {
+            None => return Some(items),
+            Some(',') => {}
+            Some(_) => return None,
+        }
+    }
+}
+
+// 文の引数に Rust の値をそのまま渡せるようにする
+macro_rules! impl_into_value {
+    ($($ty:ty => $f:expr),* $(,)?) => {
+        $(impl From<$ty> for Value {
+            fn from(v: $ty) -> Value {
+                $f(v)
+            }
+        })*
+    };
+}
+
+impl_into_value!(
+    i64 => Value::BigInt,
+    i32 => |n| Value::Integer(i64::from(n)),
+    f64 => Value::Real,
+    bool => Value::Boolean,
+    String => Value::Text,
+    &str => |s: &str| Value::Text(s.to_string()),
+    Vec<u8> => |b: Vec<u8>| Value::Blob(b.into()),
+    Decimal => Value::Decimal,
+    Date => Value::Date,
+    Time => Value::Time,
+    Timestamp => Value::Timestamp,
+    Uuid => Value::Uuid,
+    Interval => Value::Interval,
+    Point => Value::Point,
+    Rect => |r| Value::Box(Box::new(r)),
+);
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+impl DataType {
+    // CREATE TABLE に書く型名から。VARCHAR(20) のような引数は無視する。わからない型名なら None。
+    // DECIMAL(p, s) の s を省くと 0、p も省くと MAX_PRECISION
+    pub fn parse(name: &str) -> Option<DataType> {
+        if let Some(element) = name.trim().strip_suffix("[]") {
+            return DataType::array(DataType::parse(element)?);
+        }
+        let (base, args) = name.split_once('(').unwrap_or((name, ""));
+        let base = base.trim().to_uppercase();
+        if matches!(base.as_str(), "DECIMAL" | "NUMERIC" | "DEC") {
+            let args = args
+                .trim_end_matches(')')
+                .split(',')
+                .filter(|arg| !arg.trim().is_empty())
+                .map(|arg| arg.trim().parse::<u8>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            let (precision, scale) = match args[..] {
+                [] => (MAX_PRECISION, 0),
+                [precision] => (precision, 0),
+                [precision, scale] => (precision, scale),
+                _ => return None,
+            };
+            return (1..=MAX_PRECISION)
+                .contains(&precision)
+                .then_some(())
+                .filter(|_| scale <= precision)
+                .map(|_| DataType::Decimal { precision, scale });
+        }
+        match base.as_str() {
+            "INTEGER" | "INT" | "INT2" | "INT4" | "SMALLINT" => Some(DataType::Integer),
+            "BIGINT" | "INT8" => Some(DataType::BigInt),
+            "REAL" | "FLOAT" | "FLOAT4" | "FLOAT8" | "DOUBLE" | "DOUBLE PRECISION" => {
+                Some(DataType::Real)
+            }
+            "TEXT" | "VARCHAR" | "CHAR" | "CHARACTER" | "CHARACTER VARYING" => Some(DataType::Text),
+            "BOOLEAN" | "BOOL" => Some(DataType::Boolean),
+            "BLOB" | "BYTEA" => Some(DataType::Blob),
+            "DATE" => Some(DataType::Date),
+            "TIME" | "TIME WITHOUT TIME ZONE" => Some(DataType::Time),
+            "TIMESTAMP" | "DATETIME" | "TIMESTAMP WITHOUT TIME ZONE" => Some(DataType::Timestamp),
+            "JSON" => Some(DataType::Json),
+            "UUID" => Some(DataType::Uuid),
+            "INTERVAL" => Some(DataType::Interval),
+            "POINT" => Some(DataType::Point),
+            "BOX" => Some(DataType::Box),
+            _ => None,
+        }
+    }
+
+    // 要素の型が element の配列の型。配列の配列と列挙型の配列は作らない
+    pub fn array(element: DataType) -> Option<DataType> {
+        let element: &'static DataType = match element {
+            DataType::Integer => &DataType::Integer,
+            DataType::BigInt => &DataType::BigInt,
+            DataType::Real => &DataType::Real,
+            DataType::Decimal { precision, scale } => decimal_element(precision, scale),
+            DataType::Text => &DataType::Text,
+            DataType::Boolean => &DataType::Boolean,
+            DataType::Blob => &DataType::Blob,
+            DataType::Date => &DataType::Date,
+            DataType::Time => &DataType::Time,
+            DataType::Timestamp => &DataType::Timestamp,
+            DataType::Json => &DataType::Json,
+            DataType::Uuid => &DataType::Uuid,
+            DataType::Interval => &DataType::Interval,
+            DataType::Point => &DataType::Point,
+            DataType::Box => &DataType::Box,
+            DataType::Array(_) | DataType::Enum(_) => return None,
+        };
+        Some(DataType::Array(element))
+    }
+
+    // 配列の型なら要素の型
+    pub fn element(self) -> Option<DataType> {
+        match self {
+            DataType::Array(element) => Some(element.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            DataType::Array(element) => match **element {
+                DataType::Integer => "integer[]",
+                DataType::BigInt => "bigint[]",
+                DataType::Real => "real[]",
+                DataType::Decimal { .. } => "numeric[]",
+                DataType::Text => "text[]",
+                DataType::Boolean => "boolean[]",
+                DataType::Blob => "blob[]",
+                DataType::Date => "date[]",
+                DataType::Time => "time[]",
+                DataType::Timestamp => "timestamp[]",
+                DataType::Json => "json[]",
+                DataType::Uuid => "uuid[]",
+                DataType::Interval => "interval[]",
+                DataType::Point => "point[]",
+                DataType::Box => "box[]",
+                DataType::Array(_) | DataType::Enum(_) => "array",
+            },
+            DataType::Enum(ty) => &ty.name,
+            DataType::Integer => "integer",
+            DataType::BigInt => "bigint",
+            DataType::Real => "real",
+            DataType::Decimal { .. } => "numeric",
+            DataType::Text => "text",
+            DataType::Boolean => "boolean",
+            DataType::Blob => "blob",
+            DataType::Date => "date",
+            DataType::Time => "time",
+            DataType::Timestamp => "timestamp",
+            DataType::Json => "json",
+            DataType::Uuid => "uuid",
+            DataType::Interval => "interval",
+            DataType::Point => "point",
+            DataType::Box => "box",
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, DataType::Integer | DataType::BigInt)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            DataType::Integer | DataType::BigInt | DataType::Real | DataType::Decimal { .. }
+        )
+    }
+
+    // value がこの型の列にそのまま入る値か。DECIMAL は scale が同じで整数部が収まるもの
+    pub fn holds(&self, value: &Value) -> bool {
+        match (self, value) {
+            (&DataType::Decimal { precision, scale }, Value::Decimal(d)) => {
+                d.scale() == scale && d.integer_digits() <= precision - scale
+            }
+            (DataType::Array(element), Value::Array(items)) => items
+                .iter()
+                .all(|item| item.is_null() || element.holds(item)),
+            (ty, value) => value.data_type().as_ref() == Some(ty),
+        }
+    }
+
+    // from の値をこの型に変換できる一番狭い場面。変換できなければ None。
+    // 整数どうしと、整数から REAL や DECIMAL、DECIMAL から REAL、DATE から TIMESTAMP へは値が変わらない。
+    // 整数でない数を整数にするのと、文字列を数や真偽値にするのと、どの型も文字列にするのは CAST だけ。
+    // 文字列は JSON や UUID や INTERVAL や POINT や BOX として読めればその列に入る
+    pub fn coercion_from(&self, from: &DataType) -> Option<Coercion> {
+        use DataType::*;
+        let coercion = match (from, self) {
+            (from, to) if from == to => Coercion::Implicit,
+            (Array(from), Array(to)) => return to.coercion_from(from),
+            (Decimal { .. }, Decimal { .. })
+            | (Integer | BigInt, Integer | BigInt | Real | Decimal { .. })
+            | (Decimal { .. }, Real)
+            | (Date, Timestamp) => Coercion::Implicit,
+            (Real, Decimal { .. })
+            | (Timestamp, Date | Time)
+            | (
+                Text,
+                Date | Time | Timestamp | Json | Uuid | Interval | Point | Box | Array(_) | Enum(_),
+            ) => Coercion::Assignment,
+            (Real | Decimal { .. }, Integer | BigInt)
+            | (Boolean, Integer | BigInt)
+            | (Integer | BigInt, Boolean)
+            | (Text, Integer | BigInt | Real | Decimal { .. } | Boolean | Blob)
+            | (_, Text) => Coercion::Explicit,
+            _ => return None,
+        };
+        Some(coercion)
+    }
+
+    // 列に入れるときの型の変換。DECIMAL の列には小数点以下を丸めて入れる
+    pub fn coerce(&self, value: Value) -> Result<Value, CoerceError> {
+        self.convert(value, Coercion::Assignment)
+    }
+
+    // CAST での型の変換。整数にするときは近いほうに丸め、ちょうど半分なら REAL は偶数に、DECIMAL は 0 から遠いほうに丸める
+    pub fn cast(&self, value: Value) -> Result<Value, CoerceError> {
+        self.convert(value, Coercion::Explicit)
+    }
+
+    // context の場面で value をこの型にする。NULL はそのまま。配列は要素ごとに変換する
+    pub fn convert(&self, value: Value, context: Coercion) -> Result<Value, CoerceError> {
+        if let (DataType::Array(element), Value::Array(items)) = (self, &value) {
+            if items
+                .iter()
+                .all(|item| item.is_null() || element.holds(item))
+            {
+                return Ok(value);
+            }
+            let Value::Array(items) = value else {
+                unreachable!()
+            };
+            return items
+                .into_vec()
+                .into_iter()
+                .map(|item| element.convert(item, context))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|items| Value::Array(items.into()));
+        }
+        let Some(from) = value.data_type() else {
+            return Ok(Value::Null);
+        };
+        if self.coercion_from(&from).is_none_or(|c| c > context) {
+            return Err(CoerceError::Mismatch);
+        }
+        // INTEGER は 32 bit に収まらなければあふれる
+        let int = |n: i64| match self {
+            DataType::Integer if i32::try_from(n).is_err() => Err(CoerceError::Overflow),
+            DataType::Integer => Ok(Value::Integer(n)),
+            _ => Ok(Value::BigInt(n)),
+        };
+        let value = match (self, value) {
+            (DataType::Integer | DataType::BigInt, Value::Integer(n) | Value::BigInt(n)) => int(n)?,
+            (DataType::Integer | DataType::BigInt, Value::Real(f)) => {
+                let f = f.round_ties_even();
+                if !in_i64(f) {
+                    return Err(CoerceError::Overflow);
+                }
+                int(f as i64)?
+            }
+            (DataType::Integer | DataType::BigInt, Value::Decimal(d)) => int(d.round())?,
+            (DataType::Integer | DataType::BigInt, Value::Boolean(b)) => int(b as i64)?,
+            (DataType::Integer | DataType::BigInt, Value::Text(s)) => {
+                match s.trim().parse::<i64>() {
+                    Ok(n) => int(n)?,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow
+                        ) =>
+                    {
+                        return Err(CoerceError::Overflow)
+                    }
+                    Err(_) => return Err(CoerceError::InvalidSyntax(s)),
+                }
+            }
+            (DataType::Real, Value::Text(s)) => match s.trim().parse() {
+                Ok(f) => Value::Real(f),
+                Err(_) => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            (DataType::Real, value) if value.is_numeric() => Value::Real(value.as_f64().unwrap()),
+            (DataType::Decimal { .. }, Value::Text(s)) => match s.trim().parse() {
+                Ok(d) => return self.convert(Value::Decimal(d), context),
+                Err(_) => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            (&DataType::Decimal { precision, scale }, value) if value.is_numeric() => {
+                let d = match value.number().unwrap() {
+                    Number::Int(n) => Decimal::from_i64(n).and_then(|d| d.rescale(scale)),
+                    Number::Real(f) => Decimal::from_f64(f, scale),
+                    Number::Decimal(d) => d.rescale(scale),
+                };
+                match d {
+                    Some(d) if d.integer_digits() <= precision - scale => Value::Decimal(d),
+                    _ => return Err(CoerceError::Overflow),
+                }
+            }
+            (DataType::Date | DataType::Time | DataType::Timestamp, Value::Text(s)) => {
+                let value = match self {
+                    DataType::Date => s.parse().map(Value::Date),
+                    DataType::Time => s.parse().map(Value::Time),
+                    _ => s.parse().map(Value::Timestamp),
+                };
+                value.map_err(|_| CoerceError::InvalidSyntax(s))?
+            }
+            (DataType::Json, Value::Text(s)) => match s.parse::<Json>() {
+                Ok(json) => Value::Json(json.to_string().into()),
+                Err(_) => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            (DataType::Uuid, Value::Text(s)) => match s.parse() {
+                Ok(uuid) => Value::Uuid(uuid),
+                Err(_) => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            (DataType::Interval, Value::Text(s)) => match s.parse() {
+                Ok(interval) => Value::Interval(interval),
+                Err(_) => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            (DataType::Point, Value::Text(s)) => match s.parse() {
+                Ok(p) => Value::Point(p),
+                Err(_) => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            (DataType::Box, Value::Text(s)) => match s.parse() {
+                Ok(b) => Value::Box(Box::new(b)),
+                Err(_) => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            // 要素の文字列は CAST と同じに読む
+            (DataType::Array(element), Value::Text(s)) => {
+                let Some(items) = parse_array(&s) else {
+                    return Err(CoerceError::InvalidSyntax(s));
+                };
+                let items = items
+                    .into_iter()
+                    .map(|item| {
+                        item.map_or(Ok(Value::Null), |item| element.cast(Value::Text(item)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Value::Array(items.into())
+            }
+            (DataType::Enum(ty), Value::Text(s)) => match ty.value(&s) {
+                Some(e) => Value::Enum(e),
+                None => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            (DataType::Date, Value::Timestamp(t)) => Value::Date(t.date()),
+            (DataType::Time, Value::Timestamp(t)) => Value::Time(t.time()),
+            (DataType::Timestamp, Value::Date(d)) => Value::Timestamp(d.into()),
+            (DataType::Boolean, Value::Integer(n) | Value::BigInt(n)) => Value::Boolean(n != 0),
+            (DataType::Boolean, Value::Text(s)) => match parse_bool(&s) {
+                Some(b) => Value::Boolean(b),
+                None => return Err(CoerceError::InvalidSyntax(s)),
+            },
+            // \x で始まれば 16 進数、そうでなければ UTF-8 のバイト列
+            (DataType::Blob, Value::Text(s)) => match s.strip_prefix("\\x") {
+                Some(hex) => match parse_hex(hex) {
+                    Some(bytes) => Value::Blob(bytes.into()),
+                    None => return Err(CoerceError::InvalidSyntax(s)),
+                },
+                None => Value::Blob(s.into_bytes().into()),
+            },
+            (DataType::Text, value @ Value::Text(_)) => value,
+            (DataType::Text, value) => Value::Text(value.to_string()),
+            (DataType::Boolean, value @ Value::Boolean(_))
+            | (DataType::Blob, value @ Value::Blob(_))
+            | (DataType::Date, value @ Value::Date(_))
+            | (DataType::Time, value @ Value::Time(_))
+            | (DataType::Timestamp, value @ Value::Timestamp(_))
+            | (DataType::Json, value @ Value::Json(_))
+            | (DataType::Uuid, value @ Value::Uuid(_))
+            | (DataType::Interval, value @ Value::Interval(_))
+            | (DataType::Point, value @ Value::Point(_))
+            | (DataType::Box, value @ Value::Box(_))
+            | (DataType::Enum(_), value @ Value::Enum(_)) => value,
+            _ => return Err(CoerceError::Mismatch),
+        };
+        Ok(value)
+    }
+}
+
+// DECIMAL の要素の型は、精度ごとに一度だけ作って使い回す
+fn decimal_element(precision: u8, scale: u8) -> &'static DataType {
+    static ELEMENTS: OnceLock<Mutex<HashMap<(u8, u8), &'static DataType>>> = OnceLock::new();
+    let mut elements = ELEMENTS.get_or_init(Mutex::default).lock().unwrap();
+    elements
+        .entry((precision, scale))
+        .or_insert_with(|| Box::leak(Box::new(DataType::Decimal { precision, scale })))
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim().to_lowercase().as_str() {
+        "t" | "true" | "y" | "yes" | "on" | "1" => Some(true),
+        "f" | "false" | "n" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::Decimal { precision, scale } => {
+                write!(f, "numeric({},{})", precision, scale)
+            }
+            DataType::Array(element @ DataType::Decimal { .. }) => write!(f, "{}[]", element),
+            ty => f.write_str(ty.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_numeric_equality() {
+        let equal = [
+            (Value::Integer(3), Value::BigInt(3)),
+            (Value::Integer(3), Value::Real(3.0)),
+            (Value::Real(-0.0), Value::Integer(0)),
+            (Value::Real(f64::NAN), Value::Real(f64::NAN)),
+        ];
+        for (a, b) in equal {
+            assert_eq!(a, b);
+            assert_eq!(hash(&a), hash(&b), "{:?} {:?}", a, b);
+        }
+        assert_ne!(Value::Integer(3), Value::Real(3.5));
+        assert_ne!(Value::Integer(3), Value::Text("3".to_string()));
+
+        // 日付はその日の 0 時と等しい
+        let date = Value::Date("2024-01-15".parse().unwrap());
+        let midnight = Value::Timestamp("2024-01-15 00:00".parse().unwrap());
+        assert_eq!(date, midnight);
+        assert_eq!(hash(&date), hash(&midnight));
+        assert!(date
+            .sort_cmp(&Value::Timestamp("2024-01-15 00:00:01".parse().unwrap()))
+            .is_lt());
+        assert_ne!(
+            Value::Time("00:00".parse().unwrap()),
+            Value::Date("1970-01-01".parse().unwrap())
+        );
+
+        // f64 にすると丸まる整数も正しく比べる
+        let big = i64::MAX - 1;
+        assert_eq!(
+            Value::BigInt(big).sort_cmp(&Value::Real(big as f64)),
+            Ordering::Less
+        );
+        assert_eq!(
+            Value::Real(2.5).sort_cmp(&Value::Integer(2)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            Value::Real(f64::NAN).sort_cmp(&Value::BigInt(i64::MAX)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            Value::Real(f64::INFINITY).sort_cmp(&Value::Null),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn test_data_type() {
+        assert_eq!(DataType::parse("VARCHAR(20)"), Some(DataType::Text));
+        assert_eq!(DataType::parse("int8"), Some(DataType::BigInt));
+        assert_eq!(DataType::parse("DOUBLE PRECISION"), Some(DataType::Real));
+        assert_eq!(DataType::parse("json"), Some(DataType::Json));
+        assert_eq!(DataType::parse("XML"), None);
+
+        assert_eq!(
+            DataType::Real.coerce(Value::Integer(2)),
+            Ok(Value::Real(2.0))
+        );
+        assert!(matches!(
+            DataType::BigInt.coerce(Value::Integer(2)),
+            Ok(Value::BigInt(2))
+        ));
+        assert_eq!(
+            DataType::Integer.coerce(Value::Real(2.0)),
+            Err(CoerceError::Mismatch)
+        );
+        // INTEGER は 32 bit
+        assert_eq!(
+            DataType::Integer.coerce(Value::BigInt(i32::MAX as i64 + 1)),
+            Err(CoerceError::Overflow)
+        );
+        assert_eq!(
+            DataType::Integer.coerce(Value::BigInt(i32::MIN as i64)),
+            Ok(Value::Integer(i32::MIN as i64))
+        );
+        assert_eq!(DataType::Blob.coerce(Value::Null), Ok(Value::Null));
+        assert_eq!(
+            DataType::Date.coerce(Value::Text("2024-01-15".to_string())),
+            Ok(Value::Date("2024-01-15".parse().unwrap()))
+        );
+        assert_eq!(
+            DataType::Timestamp.coerce(Value::Text("yesterday".to_string())),
+            Err(CoerceError::InvalidSyntax("yesterday".to_string()))
+        );
+        assert_eq!(Value::Blob(vec![0, 0xab].into()).to_string(), "\\x00ab");
+        assert_eq!(Value::Real(1.5).to_string(), "1.5");
+    }
+
+    #[test]
+    fn test_cast() {
+        let text = |s: &str| Value::Text(s.to_string());
+        let decimal = |s: &str| Value::Decimal(s.parse().unwrap());
+        for (ty, value, expected) in [
+            (DataType::Integer, Value::Real(2.5), Value::Integer(2)),
+            (DataType::Integer, Value::Real(-3.5), Value::Integer(-4)),
+            (DataType::BigInt, decimal("2.5"), Value::BigInt(3)),
+            (DataType::Integer, text(" 42 "), Value::Integer(42)),
+            (DataType::Integer, Value::Boolean(true), Value::Integer(1)),
+            (DataType::Real, text("1.5e3"), Value::Real(1500.0)),
+            (
+                DataType::parse("NUMERIC(5,1)").unwrap(),
+                text("3.14"),
+                decimal("3.1"),
+            ),
+            (DataType::Boolean, text("Off"), Value::Boolean(false)),
+            (DataType::Boolean, Value::Integer(2), Value::Boolean(true)),
+            (DataType::Text, Value::Real(1.5), text("1.5")),
+            (DataType::Text, decimal("1.50"), text("1.50")),
+            (DataType::Text, Value::Boolean(true), text("true")),
+            (
+                DataType::Blob,
+                text("\\x00AB"),
+                Value::Blob(vec![0, 0xab].into()),
+            ),
+            (
+                DataType::Blob,
+                text("ab"),
+                Value::Blob(b"ab".to_vec().into()),
+            ),
+            (
+                DataType::Date,
+                text("2024-01-15"),
+                Value::Date("2024-01-15".parse().unwrap()),
+            ),
+            (
+                DataType::Json,
+                text(" { \"a\" : [1, 2] } "),
+                Value::Json("{\"a\":[1,2]}".into()),
+            ),
+            (DataType::Text, Value::Json("[true]".into()), text("[true]")),
+            (
+                DataType::Interval,
+                text("36 hours"),
+                Value::Interval("1 day 12:00:00".parse().unwrap()),
+            ),
+            (
+                DataType::Uuid,
+                text("{A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11}"),
+                Value::Uuid("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11".parse().unwrap()),
+            ),
+            (
+                DataType::Box,
+                text("(0,4),(3,0)"),
+                Value::Box(Box::new("(3,4),(0,0)".parse().unwrap())),
+            ),
+            (
+                DataType::Text,
+                Value::Point(Point::new(1.0, -2.5)),
+                text("(1,-2.5)"),
+            ),
+        ] {
+            let actual = ty.cast(value.clone()).unwrap();
+            assert_eq!(actual, expected, "{:?} {:?}", ty, value);
+            assert_eq!(actual.data_type(), expected.data_type());
+        }
+        for (ty, value, err) in [
+            (DataType::Integer, Value::Real(1e19), CoerceError::Overflow),
+            (
+                DataType::Integer,
+                Value::Real(f64::NAN),
+                CoerceError::Overflow,
+            ),
+            (
+                DataType::BigInt,
+                text("99999999999999999999"),
+                CoerceError::Overflow,
+            ),
+            (
+                DataType::parse("NUMERIC(3,1)").unwrap(),
+                text("123"),
+                CoerceError::Overflow,
+            ),
+            (
+                DataType::Integer,
+                text("1.5"),
+                CoerceError::InvalidSyntax("1.5".to_string()),
+            ),
+            (
+                DataType::Boolean,
+                text("maybe"),
+                CoerceError::InvalidSyntax("maybe".to_string()),
+            ),
+            (
+                DataType::Blob,
+                text("\\xabc"),
+                CoerceError::InvalidSyntax("\\xabc".to_string()),
+            ),
+            (
+                DataType::Date,
+                Value::Time("12:00".parse().unwrap()),
+                CoerceError::Mismatch,
+            ),
+            (DataType::Boolean, Value::Real(1.0), CoerceError::Mismatch),
+            (
+                DataType::Json,
+                text("{a: 1}"),
+                CoerceError::InvalidSyntax("{a: 1}".to_string()),
+            ),
+            (
+                DataType::Uuid,
+                text("a0eebc99"),
+                CoerceError::InvalidSyntax("a0eebc99".to_string()),
+            ),
+            (DataType::Uuid, Value::Integer(1), CoerceError::Mismatch),
+        ] {
+            assert_eq!(ty.cast(value), Err(err));
+        }
+
+        // 列に入れるときは、情報が落ちる変換や文字列を数にする変換はしない
+        assert_eq!(
+            DataType::Integer.coerce(text("1")),
+            Err(CoerceError::Mismatch)
+        );
+        assert_eq!(
+            DataType::Text.coerce(Value::Integer(1)),
+            Err(CoerceError::Mismatch)
+        );
+        assert_eq!(
+            DataType::Real.coercion_from(&DataType::Integer),
+            Some(Coercion::Implicit)
+        );
+        assert_eq!(
+            DataType::Decimal {
+                precision: 5,
+                scale: 2
+            }
+            .coercion_from(&DataType::Real),
+            Some(Coercion::Assignment)
+        );
+        assert_eq!(DataType::Time.coercion_from(&DataType::Date), None);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+use crate::clock;
+
+// 16 バイトの UUID。バイト列の順に比べる
+//
+// 文字列は 'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11' の形で書き、読むときは大文字と、ハイフンのない 32 桁と、
+// 全体を囲む {} も受け付ける
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uuid([u8; 16]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseUuidError;
+
+impl fmt::Display for ParseUuidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid uuid")
+    }
+}
+
+impl Uuid {
+    pub fn from_bytes(bytes: [u8; 16]) -> Uuid {
+        Uuid(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    // 乱数で作る version 4
+    pub fn new_v4() -> Uuid {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&random().to_be_bytes());
+        bytes[8..].copy_from_slice(&random().to_be_bytes());
+        Uuid(bytes).with_version(4)
+    }
+
+    // 先頭の 48 bit を 1970-01-01 からのミリ秒にした version 7。作った順におおよそ並ぶので、
+    // インデックスの右端に入ってページが散らばらない
+    pub fn new_v7() -> Uuid {
+        let millis = clock::system_now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock is before 1970")
+            .as_millis() as u64;
+        let mut bytes = [0; 16];
+        bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+        bytes[6..8].copy_from_slice(&random().to_be_bytes()[..2]);
+        bytes[8..].copy_from_slice(&random().to_be_bytes());
+        Uuid(bytes).with_version(7)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.0[6] >> 4
+    }
+
+    // version と RFC 4122 の variant のビットを立てる
+    fn with_version(mut self, version: u8) -> Uuid {
+        self.0[6] = (self.0[6] & 0x0f) | (version << 4);
+        self.0[8] = (self.0[8] & 0x3f) | 0x80;
+        self
+    }
+}
+
+// 暗号には使えない乱数。プロセスごとに種の違う SipHash に通し番号を通す
+fn random() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(
+        clock::system_now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos()),
+    );
+    hasher.finish()
+}
+
+impl FromStr for Uuid {
+    type Err = ParseUuidError;
+
+    fn from_str(s: &str) -> Result<Uuid, ParseUuidError> {
+        let s = s.trim();
+        let s = s
+            .strip_prefix('{')
+            .and_then(|s| s.strip_suffix('}'))
+            .unwrap_or(s);
+        let hex = match s.len() {
+            32 => s.to_string(),
+            36 if [8, 13, 18, 23].iter().all(|&i| s.as_bytes()[i] == b'-') => s.replace('-', ""),
+            _ => return Err(ParseUuidError),
+        };
+        if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseUuidError);
+        }
+        let mut bytes = [0; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        Ok(Uuid(bytes))
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid() {
+        let uuid: Uuid = "A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11".parse().unwrap();
+        assert_eq!(uuid.to_string(), "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11");
+        assert_eq!(uuid.version(), 4);
+        for s in [
+            "{a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11}",
+            "a0eebc999c0b4ef8bb6d6bb9bd380a11",
+        ] {
+            assert_eq!(s.parse::<Uuid>(), Ok(uuid));
+        }
+        for s in [
+            "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a1",
+            "a0eebc99-9c0b4-ef8-bb6d-6bb9bd380a11",
+            "g0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11",
+            "",
+        ] {
+            assert_eq!(s.parse::<Uuid>(), Err(ParseUuidError), "{}", s);
+        }
+
+        let a = Uuid::new_v4();
+        assert_ne!(a, Uuid::new_v4());
+        assert_eq!(a.version(), 4);
+        assert_eq!(a.as_bytes()[8] & 0xc0, 0x80);
+        // version 7 は時刻の順に並ぶ
+        let first = Uuid::new_v7();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = Uuid::new_v7();
+        assert_eq!(first.version(), 7);
+        assert!(first < second);
+    }
+}
@@ -0,0 +1,1339 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::disk::PageId;
+use crate::metrics::METRICS;
+use crate::types::Value;
+use crate::{clock, logical, lz4, trace, tuple};
+
+// 先行書き込みログ (WAL)
+//
+// ログはディレクトリの中のセグメントファイルに追記していく。セグメントは SEGMENT_SIZE バイトずつで、
+// ファイル名はセグメントの番号を 16 桁の 16 進数で書いたもの。
+// セグメントは | SEGMENT_MAGIC | セグメントの番号 (8) | で始まり、そのあとにレコードが続く。
+// レコードは | 長さ (4) | チェックサム (4) | 本体 | で、セグメントをまたがない。
+// チェックサムは LSN と本体から求めるので、使い回したセグメントに残る古いレコードは読まない。
+// COMPRESS_THRESHOLD バイト以上の本体は LZ4 で縮め、縮んだときだけ | 元の長さ (4) | 圧縮した本体 | を書いて
+// 長さの最上位 bit を立てる。長さとチェックサムは書いた本体のもの。
+// LSN はレコードの先頭の、ログ全体を 1 つのバイト列とみたときの位置。
+// セグメントの先頭にレコードはないので、LSN 0 は「LSN なし」に使える。
+//
+// アーカイブ先を決めておくと、書き終わったセグメントを切り替えるたびにそこへ同じ名前で写す。
+// 写し終わるまで次のセグメントに進まないので、チェックポイントで消したセグメントもアーカイブには残っている。
+//
+// チェックポイントで要らなくなったセグメントは、RECYCLED_SEGMENTS 個までこれから使う番号に名前を変えて取っておき、
+// ファイルを作り直さずにヘッダだけ書き換えて使う。
+pub const SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+// 追記したレコードをファイルに書かずにためておくバイト数
+pub const WAL_BUFFER_SIZE: usize = 64 * 1024;
+const RECORD_HEADER_SIZE: usize = 8;
+pub const COMPRESS_THRESHOLD: usize = 256;
+const COMPRESSED: u32 = 1 << 31;
+const SEGMENT_MAGIC: &[u8; 8] = b"RDBWAL02";
+const SEGMENT_HEADER_SIZE: u64 = SEGMENT_MAGIC.len() as u64 + 8;
+pub const RECYCLED_SEGMENTS: usize = 2;
+// 最後のチェックポイントの LSN を書いておくファイル。
+// 中身は | チェックポイントの LSN (8) | 状態 (1) | で、状態が CLEAN ならそのチェックポイントを書いて正しく閉じた。
+// 状態のない 8 バイトのファイルは DIRTY として読む
+const CONTROL_FILE: &str = "checkpoint";
+const DIRTY: u8 = 0;
+const CLEAN: u8 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("log record of {0} bytes does not fit in a segment")]
+    RecordTooLarge(usize),
+    #[error("no log record at {0:?}")]
+    NoRecord(Lsn),
+    #[error("log record at {found:?} does not follow the end of the log at {expected:?}")]
+    UnexpectedLsn { expected: Lsn, found: Lsn },
+    #[error("log at {0:?} is no longer retained")]
+    NotRetained(Lsn),
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct Lsn(pub u64);
+
+impl Lsn {
+    pub const INVALID_LSN: Lsn = Lsn(0);
+
+    pub fn valid(self) -> Option<Lsn> {
+        if self == Self::INVALID_LSN {
+            None
+        } else {
+            Some(self)
+        }
+    }
+
+    pub fn to_u64(self) -> u64 {
+        self.0
+    }
+
+    // セグメントの最初のレコードの位置
+    fn segment_start(segment: u64) -> Lsn {
+        Lsn(segment * SEGMENT_SIZE + SEGMENT_HEADER_SIZE)
+    }
+
+    fn segment(self) -> u64 {
+        self.0 / SEGMENT_SIZE
+    }
+
+    fn offset(self) -> u64 {
+        self.0 % SEGMENT_SIZE
+    }
+}
+
+// チェックポイントの時点で実行中のトランザクションと、その最初と最後のレコード。
+// まだレコードを書いていなければ first と last は INVALID_LSN
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveTxn {
+    pub txn: u64,
+    pub first: Lsn,
+    pub last: Lsn,
+}
+
+// prev はそのトランザクションが直前に書いたレコードで、なければ INVALID_LSN
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogRecord {
+    // time はコミットした時刻 (UNIX 時間のマイクロ秒) で、時刻を指定した回復で止める位置を決める
+    Commit {
+        txn: u64,
+        prev: Lsn,
+        time: u64,
+    },
+    Abort {
+        txn: u64,
+        prev: Lsn,
+    },
+    // 2 相コミットの準備ができた。COMMIT PREPARED か ROLLBACK PREPARED が来るまで、回復しても取り消さない
+    Prepare {
+        txn: u64,
+        prev: Lsn,
+        gid: String,
+    },
+    // 取り消しが終わった
+    End {
+        txn: u64,
+        prev: Lsn,
+    },
+    // コミットするときに書く、トランザクションの行の変更。論理デコードで読む。
+    // txn が None のものはトランザクションの外の変更で、書いた時点でコミットしている
+    Changes {
+        txn: Option<u64>,
+        prev: Lsn,
+        changes: Vec<RowChange>,
+    },
+    // 書いた時点で実行中のトランザクションと、
+    // 汚れているページとそのページを最初に汚したレコード、次に振るトランザクションの番号
+    Checkpoint {
+        active: Vec<ActiveTxn>,
+        dirty: Vec<(PageId, Lsn)>,
+        next_txn: u64,
+    },
+    // ページ全体の像。BufferPoolManager::log_page_images で書く
+    PageImage {
+        page_id: PageId,
+        image: Vec<u8>,
+    },
+    // 前に書いた像から変わったところ。offset からを data に書き換える
+    PageDelta {
+        page_id: PageId,
+        ranges: Vec<(u16, Vec<u8>)>,
+    },
+}
+
+// テーブルの行の変更
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowChange {
+    Insert {
+        table: String,
+        new: Vec<Value>,
+    },
+    Update {
+        table: String,
+        old: Vec<Value>,
+        new: Vec<Value>,
+    },
+    Delete {
+        table: String,
+        old: Vec<Value>,
+    },
+    // COPY (BULK) で行ごとのレコードを書かずに入れ直した。読む側はテーブルの行を読み直さなければならない
+    Rebuilt {
+        table: String,
+    },
+}
+
+impl RowChange {
+    pub fn table(&self) -> &str {
+        match self {
+            RowChange::Insert { table, .. }
+            | RowChange::Update { table, .. }
+            | RowChange::Delete { table, .. }
+            | RowChange::Rebuilt { table } => table,
+        }
+    }
+
+    // 列挙型の値は読む側に型がないので、ラベルの文字列にして書く
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let row = |values: &[Value]| {
+            let values = values
+                .iter()
+                .map(|v| match v {
+                    Value::Enum(e) => Value::Text(e.label()),
+                    v => v.clone(),
+                })
+                .collect::<Vec<_>>();
+            let mut data = vec![];
+            tuple::encode(&values, &mut data);
+            data
+        };
+        let (tag, rows) = match self {
+            RowChange::Insert { new, .. } => (0, vec![row(new)]),
+            RowChange::Update { old, new, .. } => (1, vec![row(old), row(new)]),
+            RowChange::Delete { old, .. } => (2, vec![row(old)]),
+            RowChange::Rebuilt { .. } => (3, vec![]),
+        };
+        bytes.push(tag);
+        put_data(bytes, self.table().as_bytes());
+        for row in rows {
+            put_data(bytes, &row);
+        }
+    }
+
+    fn decode(input: &mut Input) -> Option<Self> {
+        let tag = input.u8()?;
+        let table = String::from_utf8(input.data()?).ok()?;
+        let mut row = || tuple::decode(&input.data()?, &[]);
+        Some(match tag {
+            0 => RowChange::Insert { table, new: row()? },
+            1 => RowChange::Update {
+                table,
+                old: row()?,
+                new: row()?,
+            },
+            2 => RowChange::Delete { table, old: row()? },
+            3 => RowChange::Rebuilt { table },
+            _ => return None,
+        })
+    }
+}
+
+impl LogRecord {
+    // レコードを書いたトランザクション
+    pub fn txn(&self) -> Option<u64> {
+        match self {
+            LogRecord::Commit { txn, .. }
+            | LogRecord::Abort { txn, .. }
+            | LogRecord::Prepare { txn, .. }
+            | LogRecord::End { txn, .. } => Some(*txn),
+            LogRecord::Changes { txn, .. } => *txn,
+            LogRecord::Checkpoint { .. }
+            | LogRecord::PageImage { .. }
+            | LogRecord::PageDelta { .. } => None,
+        }
+    }
+
+    // レコードが書き換えるページ
+    pub fn page_id(&self) -> Option<PageId> {
+        match self {
+            LogRecord::PageImage { page_id, .. } | LogRecord::PageDelta { page_id, .. } => {
+                Some(*page_id)
+            }
+            _ => None,
+        }
+    }
+
+    // page_id のページに像を当てる
+    pub fn redo(&self, page: &mut [u8]) {
+        match self {
+            LogRecord::PageImage { image, .. } => page.copy_from_slice(image),
+            LogRecord::PageDelta { ranges, .. } => {
+                for (offset, data) in ranges {
+                    let offset = *offset as usize;
+                    page[offset..offset + data.len()].copy_from_slice(data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            LogRecord::Commit { txn, prev, time } => {
+                bytes.push(1);
+                put_u64s(bytes, [*txn, prev.0, *time]);
+            }
+            LogRecord::Abort { txn, prev } => {
+                bytes.push(2);
+                put_u64s(bytes, [*txn, prev.0]);
+            }
+            LogRecord::Checkpoint {
+                active,
+                dirty,
+                next_txn,
+            } => {
+                bytes.push(3);
+                bytes.extend_from_slice(&(active.len() as u32).to_be_bytes());
+                for txn in active {
+                    put_u64s(bytes, [txn.txn, txn.first.0, txn.last.0]);
+                }
+                bytes.extend_from_slice(&(dirty.len() as u32).to_be_bytes());
+                for (page_id, lsn) in dirty {
+                    put_u64s(bytes, [page_id.to_u64(), lsn.0]);
+                }
+                put_u64s(bytes, [*next_txn]);
+            }
+            LogRecord::End { txn, prev } => {
+                bytes.push(5);
+                put_u64s(bytes, [*txn, prev.0]);
+            }
+            LogRecord::Prepare { txn, prev, gid } => {
+                bytes.push(6);
+                put_u64s(bytes, [*txn, prev.0]);
+                put_data(bytes, gid.as_bytes());
+            }
+            LogRecord::Changes { txn, prev, changes } => {
+                bytes.push(7);
+                // トランザクションの番号に 0 は使わない
+                put_u64s(bytes, [txn.unwrap_or(0), prev.0]);
+                bytes.extend_from_slice(&(changes.len() as u32).to_be_bytes());
+                for change in changes {
+                    change.encode(bytes);
+                }
+            }
+            LogRecord::PageImage { page_id, image } => {
+                bytes.push(8);
+                put_u64s(bytes, [page_id.to_u64()]);
+                put_data(bytes, image);
+            }
+            LogRecord::PageDelta { page_id, ranges } => {
+                bytes.push(9);
+                put_u64s(bytes, [page_id.to_u64()]);
+                bytes.extend_from_slice(&(ranges.len() as u32).to_be_bytes());
+                for (offset, data) in ranges {
+                    bytes.extend_from_slice(&offset.to_be_bytes());
+                    put_data(bytes, data);
+                }
+            }
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut input = Input(bytes);
+        let record = match input.u8()? {
+            1 => LogRecord::Commit {
+                txn: input.u64()?,
+                prev: Lsn(input.u64()?),
+                time: input.u64()?,
+            },
+            2 => LogRecord::Abort {
+                txn: input.u64()?,
+                prev: Lsn(input.u64()?),
+            },
+            3 => {
+                let mut active = vec![];
+                for _ in 0..input.u32()? {
+                    active.push(ActiveTxn {
+                        txn: input.u64()?,
+                        first: Lsn(input.u64()?),
+                        last: Lsn(input.u64()?),
+                    });
+                }
+                let mut dirty = vec![];
+                for _ in 0..input.u32()? {
+                    dirty.push((PageId(input.u64()?), Lsn(input.u64()?)));
+                }
+                LogRecord::Checkpoint {
+                    active,
+                    dirty,
+                    next_txn: input.u64()?,
+                }
+            }
+            5 => LogRecord::End {
+                txn: input.u64()?,
+                prev: Lsn(input.u64()?),
+            },
+            6 => LogRecord::Prepare {
+                txn: input.u64()?,
+                prev: Lsn(input.u64()?),
+                gid: String::from_utf8(input.data()?).ok()?,
+            },
+            7 => {
+                let txn = Some(input.u64()?).filter(|&txn| txn != 0);
+                let prev = Lsn(input.u64()?);
+                let mut changes = vec![];
+                for _ in 0..input.u32()? {
+                    changes.push(RowChange::decode(&mut input)?);
+                }
+                LogRecord::Changes { txn, prev, changes }
+            }
+            8 => LogRecord::PageImage {
+                page_id: PageId(input.u64()?),
+                image: input.data()?,
+            },
+            9 => {
+                let page_id = PageId(input.u64()?);
+                let mut ranges = vec![];
+                for _ in 0..input.u32()? {
+                    ranges.push((input.u16()?, input.data()?));
+                }
+                LogRecord::PageDelta { page_id, ranges }
+            }
+            _ => return None,
+        };
+        input.0.is_empty().then_some(record)
+    }
+}
+
+fn put_u64s<const N: usize>(bytes: &mut Vec<u8>, values: [u64; N]) {
+    for value in values {
+        bytes.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn put_data(bytes: &mut Vec<u8>, data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(data);
+}
+
+struct Input<'a>(&'a [u8]);
+
+impl Input<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn data(&mut self) -> Option<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Some(self.take(len)?.to_vec())
+    }
+}
+
+pub struct Wal {
+    dir: PathBuf,
+    // 残っている最初のレコードの位置
+    first: Lsn,
+    // 書き込み中のセグメントとその番号
+    file: File,
+    segment: u64,
+    // まだファイルに書いていないレコード。先頭は written の位置に当たる
+    buffer: Vec<u8>,
+    written: Lsn,
+    // fsync 済みの位置と、fsync した回数
+    flushed: Lsn,
+    syncs: u64,
+    // 書き終わったセグメントを写すディレクトリ
+    archive: Option<PathBuf>,
+    // ほかのスレッドに知らせる fsync 済みの位置
+    flushed_watch: Option<Arc<AtomicU64>>,
+    // 正しく閉じたあと、まだ何も追記していない
+    clean: bool,
+    // 制御ファイルに CLEAN と書いてある
+    marked_clean: bool,
+    // コミットを記しても flush しない。呼び出し側が同じころのコミットをまとめて flush する (グループコミット)
+    group_commit: bool,
+}
+
+impl Wal {
+    // ログを開き、最後の正しいレコードの後ろから追記する。
+    // 途中で書き込みが止まった末尾のレコードがあれば切り捨てる
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let segments = segments(&dir)?;
+        let first = Lsn::segment_start(segments.first().copied().unwrap_or(0));
+        // 正しく閉じていれば、最後のレコードはチェックポイントなのでそこから末尾を探す。
+        // 開いたら DIRTY に戻し、閉じる前に落ちたときは回復させる
+        let control = read_control(&dir)?;
+        let clean = matches!(control, Some((_, CLEAN)));
+        let start = match control {
+            Some((lsn, CLEAN)) => lsn,
+            _ => first,
+        };
+        if let Some((lsn, CLEAN)) = control {
+            write_control(&dir, lsn, DIRTY)?;
+        }
+        let mut reader = WalReader::new(&dir, start);
+        while reader.next_record()?.is_some() {}
+        let end = reader.lsn;
+        // 末尾より後ろにあるのは取っておいたセグメントのはずで、そうでないものは消す
+        for &segment in &segments {
+            if segment > end.segment() && is_segment(&dir, segment)? {
+                fs::remove_file(segment_path(&dir, segment))?;
+            }
+        }
+        let file = open_segment(&dir, end.segment())?;
+        file.set_len(end.offset())?;
+        Ok(Self {
+            dir,
+            first,
+            file,
+            segment: end.segment(),
+            buffer: vec![],
+            written: end,
+            flushed: end,
+            syncs: 0,
+            archive: None,
+            flushed_watch: None,
+            clean,
+            marked_clean: false,
+            group_commit: false,
+        })
+    }
+
+    // fsync 済みの位置を、ほかのスレッドから読めるようにする。WAL を送るスレッドはここまでを送る
+    pub fn flushed_watch(&mut self) -> Arc<AtomicU64> {
+        let flushed = self.flushed.0;
+        self.flushed_watch
+            .get_or_insert_with(|| Arc::new(AtomicU64::new(flushed)))
+            .clone()
+    }
+
+    fn set_flushed(&mut self, lsn: Lsn) {
+        self.flushed = lsn;
+        if let Some(watch) = &self.flushed_watch {
+            watch.store(lsn.0, Ordering::Release);
+        }
+    }
+
+    // 書き終わったセグメントを dir に写すようにする。まだ写していない書き終わったセグメントもここで写す
+    pub fn set_archive_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        for segment in segments(&self.dir)? {
+            if segment < self.segment && !segment_path(&dir, segment).exists() {
+                archive_segment(&self.dir, &dir, segment)?;
+            }
+        }
+        self.archive = Some(dir);
+        Ok(())
+    }
+
+    // 書き込み中のセグメントを終わらせ、次のレコードから次のセグメントに書く。
+    // ベースバックアップのあとに呼ぶと、そこまでのログがアーカイブに届く
+    pub fn switch_segment(&mut self) -> Result<(), Error> {
+        if self.next_lsn() == Lsn::segment_start(self.segment) {
+            return Ok(());
+        }
+        self.start_segment(self.segment + 1)
+    }
+
+    fn start_segment(&mut self, segment: u64) -> Result<(), Error> {
+        // 前のセグメントは以後書かないので、ここでディスクに届ける
+        self.write_buffer()?;
+        self.sync()?;
+        if let Some(archive) = &self.archive {
+            archive_segment(&self.dir, archive, self.segment)?;
+        }
+        self.file = open_segment(&self.dir, segment)?;
+        self.segment = segment;
+        self.written = Lsn::segment_start(segment);
+        Ok(())
+    }
+
+    // lsn から後ろのレコードを捨て、lsn から追記する。回復を途中の時点で止めるときに使う
+    pub fn discard_from(&mut self, lsn: Lsn) -> Result<(), Error> {
+        self.write_buffer()?;
+        for segment in segments(&self.dir)? {
+            if segment > lsn.segment() {
+                fs::remove_file(segment_path(&self.dir, segment))?;
+            }
+        }
+        self.file = open_segment(&self.dir, lsn.segment())?;
+        self.file.set_len(lsn.offset())?;
+        self.file.sync_all()?;
+        self.segment = lsn.segment();
+        self.written = lsn;
+        self.set_flushed(self.flushed.min(lsn));
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // 末尾までを fsync し、from を含むセグメントから書き込み中のセグメントまでを dest に写す。
+    // dest を開いたログは写したときの末尾で終わる。写した末尾を返す
+    pub fn copy_to(&mut self, from: Lsn, dest: impl AsRef<Path>) -> Result<Lsn, Error> {
+        if from < self.first {
+            return Err(Error::NotRetained(from));
+        }
+        let dest = dest.as_ref();
+        let end = self.next_lsn();
+        self.flush(end)?;
+        fs::create_dir_all(dest)?;
+        for segment in from.segment()..=self.segment {
+            fs::copy(
+                segment_path(&self.dir, segment),
+                segment_path(dest, segment),
+            )?;
+        }
+        Ok(end)
+    }
+
+    pub fn first_lsn(&self) -> Lsn {
+        self.first
+    }
+
+    // 最後に書いたチェックポイント。回復はここから始める
+    pub fn checkpoint_lsn(&self) -> Result<Option<Lsn>, Error> {
+        Ok(read_control(&self.dir)?.map(|(lsn, _)| lsn))
+    }
+
+    // チェックポイントを回復の始まりにする。チェックポイントのレコードは flush してあること
+    pub fn set_checkpoint_lsn(&mut self, lsn: Lsn) -> Result<(), Error> {
+        write_control(&self.dir, lsn, DIRTY)?;
+        self.marked_clean = false;
+        Ok(())
+    }
+
+    // 最後のチェックポイントを書いて正しく閉じたと記す。ページはすべて書き出し、
+    // 実行中のトランザクションはないこと。このあと追記すれば DIRTY に戻す
+    pub fn mark_clean(&mut self) -> Result<(), Error> {
+        let lsn = self
+            .checkpoint_lsn()?
+            .ok_or(Error::NoRecord(Lsn::INVALID_LSN))?;
+        self.flush(self.next_lsn())?;
+        write_control(&self.dir, lsn, CLEAN)?;
+        self.clean = true;
+        self.marked_clean = true;
+        Ok(())
+    }
+
+    // 開いたときに正しく閉じてあり、そのあと何も追記していない。回復は要らない
+    pub fn is_clean(&self) -> bool {
+        self.clean
+    }
+
+    // lsn より前のレコードしかないセグメントを手放す。書き込み中のセグメントは残す。
+    // 手放したセグメントは RECYCLED_SEGMENTS 個まで、これから使う番号に名前を変えて取っておく
+    pub fn truncate(&mut self, lsn: Lsn) -> Result<(), Error> {
+        // 論理デコードのスロットがまだ読んでいないログも残す
+        let lsn = logical::restart_lsn(&self.dir)?.map_or(lsn, |restart| restart.min(lsn));
+        let keep = lsn.segment().min(self.segment);
+        let segments = segments(&self.dir)?;
+        let mut spare = self.recycled(&segments);
+        let mut last = segments
+            .last()
+            .map_or(self.segment, |&last| last.max(self.segment));
+        for &segment in segments.iter().take_while(|&&segment| segment < keep) {
+            let path = segment_path(&self.dir, segment);
+            if spare < RECYCLED_SEGMENTS {
+                last += 1;
+                fs::rename(&path, segment_path(&self.dir, last))?;
+                spare += 1;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+        self.first = self.first.max(Lsn::segment_start(keep));
+        Ok(())
+    }
+
+    fn recycled(&self, segments: &[u64]) -> usize {
+        segments
+            .iter()
+            .filter(|&&segment| segment > self.segment)
+            .count()
+    }
+
+    // 回復に残しているログのバイト数
+    pub fn retained_bytes(&self) -> u64 {
+        self.next_lsn().0 - self.first.0
+    }
+
+    // 使い回すために取っておいたセグメントの数
+    pub fn recycled_segments(&self) -> Result<usize, Error> {
+        Ok(self.recycled(&segments(&self.dir)?))
+    }
+
+    // 次に追記するレコードの LSN
+    pub fn next_lsn(&self) -> Lsn {
+        Lsn(self.written.0 + self.buffer.len() as u64)
+    }
+
+    pub fn flushed_lsn(&self) -> Lsn {
+        self.flushed
+    }
+
+    // 最初のレコードから書き込み中のものまで、残しているセグメントの数
+    pub fn retained_segments(&self) -> u64 {
+        self.segment - self.first.segment() + 1
+    }
+
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    // ほかのログから受け取ったレコードを、同じ LSN に追記する。
+    // lsn はログの末尾か、セグメントを切り替えたときの次のセグメントの先頭でなければならない
+    pub fn append_at(&mut self, lsn: Lsn, record: &LogRecord) -> Result<(), Error> {
+        if lsn.segment() > self.segment {
+            self.start_segment(lsn.segment())?;
+        }
+        if lsn != self.next_lsn() {
+            return Err(Error::UnexpectedLsn {
+                expected: self.next_lsn(),
+                found: lsn,
+            });
+        }
+        self.append(record)?;
+        Ok(())
+    }
+
+    // レコードをバッファに追記して LSN を返す。ディスクに届くのは flush したとき
+    pub fn append(&mut self, record: &LogRecord) -> Result<Lsn, Error> {
+        if self.marked_clean {
+            let lsn = self.checkpoint_lsn()?.unwrap_or(Lsn::INVALID_LSN);
+            write_control(&self.dir, lsn, DIRTY)?;
+            self.marked_clean = false;
+        }
+        self.clean = false;
+        let mut body = vec![];
+        record.encode(&mut body);
+        let (body, flag) = compress(body);
+        let size = (RECORD_HEADER_SIZE + body.len()) as u64;
+        if size > SEGMENT_SIZE - SEGMENT_HEADER_SIZE {
+            return Err(Error::RecordTooLarge(body.len()));
+        }
+        METRICS.wal_bytes.add(size);
+        let mut lsn = self.next_lsn();
+        // セグメントの残りに収まらなければ次のセグメントから書く
+        if lsn.offset() + size > SEGMENT_SIZE {
+            lsn = Lsn::segment_start(lsn.segment() + 1);
+        }
+        if lsn.segment() != self.segment {
+            self.start_segment(lsn.segment())?;
+        }
+        self.buffer
+            .extend_from_slice(&(body.len() as u32 | flag).to_be_bytes());
+        self.buffer
+            .extend_from_slice(&crc32(&[&lsn.0.to_be_bytes(), &body]).to_be_bytes());
+        self.buffer.extend_from_slice(&body);
+        if self.buffer.len() >= WAL_BUFFER_SIZE {
+            self.write_buffer()?;
+        }
+        Ok(lsn)
+    }
+
+    // lsn のレコードまでをディスクに書き、fsync する
+    pub fn flush(&mut self, lsn: Lsn) -> Result<(), Error> {
+        if lsn < self.flushed {
+            return Ok(());
+        }
+        let _span = trace::span("wal_flush", &[("lsn", &lsn.0)]);
+        self.write_buffer()?;
+        self.sync()?;
+        self.set_flushed(self.written);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        #[cfg(test)]
+        crate::crash::hit(crate::crash::Point::WalSync)?;
+        self.syncs += 1;
+        self.file.sync_data()
+    }
+
+    // トランザクションのコミットを記録し、ディスクに届いてから返る。グループコミットなら flush せずに返る
+    pub fn commit(&mut self, txn: u64, prev: Lsn) -> Result<Lsn, Error> {
+        let lsn = self.append(&LogRecord::Commit {
+            txn,
+            prev,
+            time: micros(clock::system_now()),
+        })?;
+        if !self.group_commit {
+            self.flush(lsn)?;
+        }
+        Ok(lsn)
+    }
+
+    pub fn set_group_commit(&mut self, on: bool) {
+        self.group_commit = on;
+    }
+
+    // 2 相コミットの準備を記録し、ディスクに届いてから返る
+    pub fn prepare(&mut self, txn: u64, prev: Lsn, gid: &str) -> Result<Lsn, Error> {
+        let lsn = self.append(&LogRecord::Prepare {
+            txn,
+            prev,
+            gid: gid.to_string(),
+        })?;
+        self.flush(lsn)?;
+        Ok(lsn)
+    }
+
+    // lsn のレコードを読む
+    pub fn record(&mut self, lsn: Lsn) -> Result<LogRecord, Error> {
+        match self.reader(lsn)?.next_record()? {
+            Some((found, record)) if found == lsn => Ok(record),
+            _ => Err(Error::NoRecord(lsn)),
+        }
+    }
+
+    // lsn から順にレコードを読む。バッファの分はファイルに書いてから読む
+    pub fn reader(&mut self, lsn: Lsn) -> Result<WalReader, Error> {
+        self.write_buffer()?;
+        Ok(WalReader::new(&self.dir, lsn))
+    }
+
+    fn write_buffer(&mut self) -> Result<(), Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.file.seek(SeekFrom::Start(self.written.offset()))?;
+        self.file.write_all(&self.buffer)?;
+        self.written = Lsn(self.written.0 + self.buffer.len() as u64);
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+// ログのレコードを LSN の順に読む。壊れたレコードか書きかけのレコードに当たったら終わる
+pub struct WalReader {
+    dir: PathBuf,
+    reader: Option<BufReader<File>>,
+    lsn: Lsn,
+}
+
+impl WalReader {
+    // dir のログを lsn から読む。書き込み中のログなら、ファイルに書かれた分だけ読める
+    pub fn new(dir: &Path, lsn: Lsn) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            reader: None,
+            lsn,
+        }
+    }
+
+    // 次に読むレコードの LSN。最後まで読んだあとならログの末尾
+    pub fn lsn(&self) -> Lsn {
+        self.lsn
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<(Lsn, LogRecord)>, Error> {
+        loop {
+            if self.reader.is_none() {
+                let path = segment_path(&self.dir, self.lsn.segment());
+                let mut file = match File::open(path) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                if !read_header(&mut file, self.lsn.segment())? {
+                    return Ok(None);
+                }
+                self.lsn = self.lsn.max(Lsn::segment_start(self.lsn.segment()));
+                file.seek(SeekFrom::Start(self.lsn.offset()))?;
+                self.reader = Some(BufReader::new(file));
+            }
+            let reader = self.reader.as_mut().unwrap();
+            let mut header = [0; RECORD_HEADER_SIZE];
+            if !read_full(reader, &mut header)? {
+                // セグメントの末尾まで書いてあれば次のセグメントに続く
+                if !self.next_segment_exists()? {
+                    return Ok(None);
+                }
+                self.lsn = Lsn::segment_start(self.lsn.segment() + 1);
+                self.reader = None;
+                continue;
+            }
+            let len = u32::from_be_bytes(header[..4].try_into().unwrap());
+            let (len, compressed) = ((len & !COMPRESSED) as usize, len & COMPRESSED != 0);
+            let checksum = u32::from_be_bytes(header[4..].try_into().unwrap());
+            if self.lsn.offset() + (RECORD_HEADER_SIZE + len) as u64 > SEGMENT_SIZE {
+                return Ok(None);
+            }
+            let mut body = vec![0; len];
+            if !read_full(reader, &mut body)?
+                || crc32(&[&self.lsn.0.to_be_bytes(), &body]) != checksum
+            {
+                return Ok(None);
+            }
+            if compressed {
+                let Some(decompressed) = decompress(&body) else {
+                    return Ok(None);
+                };
+                body = decompressed;
+            }
+            let Some(record) = LogRecord::decode(&body) else {
+                return Ok(None);
+            };
+            let lsn = self.lsn;
+            self.lsn = Lsn(lsn.0 + (RECORD_HEADER_SIZE + len) as u64);
+            return Ok(Some((lsn, record)));
+        }
+    }
+
+    fn next_segment_exists(&self) -> io::Result<bool> {
+        is_segment(&self.dir, self.lsn.segment() + 1)
+    }
+}
+
+// lsn を含むセグメントが dir に残っているか
+pub fn retains(dir: &Path, lsn: Lsn) -> Result<bool, Error> {
+    Ok(is_segment(dir, lsn.segment())?)
+}
+
+// 落ちたときに、fsync したと分かっている flushed より後ろのログを失ったことにする
+#[cfg(test)]
+pub(crate) fn lose_unflushed(dir: &Path, flushed: Lsn) -> io::Result<()> {
+    for segment in segments(dir)? {
+        let path = segment_path(dir, segment);
+        if segment > flushed.segment() {
+            fs::remove_file(path)?;
+        } else if segment == flushed.segment() {
+            OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_len(flushed.offset())?;
+        }
+    }
+    Ok(())
+}
+
+// buf を埋めるまで読む。途中でファイルが終われば false
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => return Ok(false),
+            n => read += n,
+        }
+    }
+    Ok(true)
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:016X}", segment))
+}
+
+// セグメントを開く。なければ作り、取っておいたファイルならヘッダを書き直す
+fn open_segment(dir: &Path, segment: u64) -> io::Result<File> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(segment_path(dir, segment))?;
+    if !read_header(&mut file, segment)? {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(SEGMENT_MAGIC)?;
+        file.write_all(&segment.to_be_bytes())?;
+    }
+    Ok(file)
+}
+
+// ヘッダを読み、segment 番目のセグメントなら true
+fn read_header(file: &mut File, segment: u64) -> io::Result<bool> {
+    let mut header = [0; SEGMENT_HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    Ok(read_full(file, &mut header)?
+        && header[..SEGMENT_MAGIC.len()] == SEGMENT_MAGIC[..]
+        && header[SEGMENT_MAGIC.len()..] == segment.to_be_bytes())
+}
+
+// segment 番目のセグメントが書き始めてあるか。取っておいただけのファイルは数えない
+fn is_segment(dir: &Path, segment: u64) -> io::Result<bool> {
+    match File::open(segment_path(dir, segment)) {
+        Ok(mut file) => read_header(&mut file, segment),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// 大きな本体を縮める。縮んだら圧縮したことを表す長さの bit も返す
+fn compress(body: Vec<u8>) -> (Vec<u8>, u32) {
+    if body.len() < COMPRESS_THRESHOLD {
+        return (body, 0);
+    }
+    let mut compressed = (body.len() as u32).to_be_bytes().to_vec();
+    compressed.extend(lz4::compress(&body));
+    if compressed.len() < body.len() {
+        (compressed, COMPRESSED)
+    } else {
+        (body, 0)
+    }
+}
+
+fn decompress(body: &[u8]) -> Option<Vec<u8>> {
+    let size = u32::from_be_bytes(body.get(..4)?.try_into().unwrap());
+    lz4::decompress(&body[4..], size as usize)
+}
+
+// セグメントをアーカイブに写す。書きかけのファイルを残さないよう、一時ファイルに書いてから名前を変える
+fn archive_segment(dir: &Path, archive: &Path, segment: u64) -> io::Result<()> {
+    let path = segment_path(archive, segment);
+    let tmp = path.with_extension("tmp");
+    fs::copy(segment_path(dir, segment), &tmp)?;
+    File::open(&tmp)?.sync_all()?;
+    fs::rename(&tmp, &path)?;
+    File::open(archive)?.sync_all()
+}
+
+// 時刻を UNIX 時間のマイクロ秒にする
+pub fn micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_micros() as u64)
+}
+
+// ディレクトリにあるセグメントの番号を昇順に返す
+// 制御ファイルのチェックポイントの LSN と状態
+fn read_control(dir: &Path) -> Result<Option<(Lsn, u8)>, Error> {
+    let bytes = match fs::read(dir.join(CONTROL_FILE)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Some(lsn) = bytes.get(..8) else {
+        return Ok(None);
+    };
+    let lsn = Lsn(u64::from_be_bytes(lsn.try_into().unwrap()));
+    Ok(Some((lsn, bytes.get(8).copied().unwrap_or(DIRTY))))
+}
+
+fn write_control(dir: &Path, lsn: Lsn, state: u8) -> Result<(), Error> {
+    let path = dir.join(CONTROL_FILE);
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&lsn.0.to_be_bytes())?;
+    file.write_all(&[state])?;
+    file.sync_all()?;
+    fs::rename(&tmp, &path)?;
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name.len() == 16 {
+            if let Ok(segment) = u64::from_str_radix(name, 16) {
+                segments.push(segment);
+            }
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+// parts をつなげたものの CRC-32 (IEEE)
+pub(crate) fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in parts.iter().copied().flatten() {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_path;
+
+    fn image(page: u64, image: &[u8]) -> LogRecord {
+        LogRecord::PageImage {
+            page_id: PageId(page),
+            image: image.to_vec(),
+        }
+    }
+
+    // 縮まないバイト列
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn read_all(wal: &mut Wal, lsn: Lsn) -> Vec<(Lsn, LogRecord)> {
+        let mut reader = wal.reader(lsn).unwrap();
+        let mut records = vec![];
+        while let Some(record) = reader.next_record().unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    #[test]
+    fn test_wal() {
+        let dir = temp_path("wal");
+        let mut wal = Wal::open(&dir).unwrap();
+        let first = wal.append(&image(3, b"abc")).unwrap();
+        let second = wal.append(&image(4, b"de")).unwrap();
+        assert!(first < second);
+        assert_eq!(wal.flushed_lsn(), first);
+        let commit = wal.commit(1, second).unwrap();
+        assert!(wal.flushed_lsn() > commit);
+        let LogRecord::Commit { time, .. } = wal.record(commit).unwrap() else {
+            panic!("not a commit record");
+        };
+        assert!(time <= micros(SystemTime::now()));
+        let abort = LogRecord::Abort {
+            txn: 2,
+            prev: Lsn::INVALID_LSN,
+        };
+        wal.append(&abort).unwrap();
+        assert_eq!(
+            read_all(&mut wal, second),
+            vec![
+                (second, image(4, b"de")),
+                (
+                    commit,
+                    LogRecord::Commit {
+                        txn: 1,
+                        prev: second,
+                        time,
+                    }
+                ),
+                (wal.flushed_lsn(), abort),
+            ]
+        );
+        assert_eq!(wal.record(second).unwrap(), image(4, b"de"));
+        assert!(matches!(
+            wal.record(Lsn(second.0 + 1)),
+            Err(Error::NoRecord(_))
+        ));
+
+        // 開き直すと続きから追記する
+        let end = wal.next_lsn();
+        drop(wal);
+        let mut wal = Wal::open(&dir).unwrap();
+        assert_eq!(wal.next_lsn(), end);
+        let checkpoint = LogRecord::Checkpoint {
+            active: vec![ActiveTxn {
+                txn: 2,
+                first: Lsn(100),
+                last: Lsn(200),
+            }],
+            dirty: vec![(PageId(3), first)],
+            next_txn: 5,
+        };
+        assert_eq!(wal.append(&checkpoint).unwrap(), end);
+        let records = read_all(&mut wal, Lsn(0));
+        assert_eq!(records[0].0, first);
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[4].1, checkpoint);
+        let prepare = wal.prepare(3, Lsn::INVALID_LSN, "gid").unwrap();
+        assert!(wal.flushed_lsn() > prepare);
+        assert_eq!(
+            wal.record(prepare).unwrap(),
+            LogRecord::Prepare {
+                txn: 3,
+                prev: Lsn::INVALID_LSN,
+                gid: "gid".to_string(),
+            }
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_torn_tail() {
+        let dir = temp_path("wal");
+        let mut wal = Wal::open(&dir).unwrap();
+        let first = wal.append(&image(3, b"abc")).unwrap();
+        let lsn = wal.commit(1, first).unwrap();
+        let end = wal.next_lsn();
+        drop(wal);
+        // 最後のレコードの途中までしか書けなかったことにする
+        let file = OpenOptions::new()
+            .write(true)
+            .open(segment_path(&dir, 0))
+            .unwrap();
+        file.set_len(end.0 - 1).unwrap();
+        let mut wal = Wal::open(&dir).unwrap();
+        assert_eq!(wal.next_lsn(), lsn);
+        assert_eq!(read_all(&mut wal, Lsn(0)).len(), 1);
+        assert_eq!(wal.commit(2, Lsn::INVALID_LSN).unwrap(), lsn);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_segment_switch() {
+        let dir = temp_path("wal");
+        let mut wal = Wal::open(&dir).unwrap();
+        let data = noise(100_000);
+        let mut lsns = vec![];
+        // 1 つのセグメントに収まらないだけ書く
+        while wal.next_lsn().segment() == 0 {
+            lsns.push(wal.append(&image(0, &data)).unwrap());
+        }
+        let last = *lsns.last().unwrap();
+        assert_eq!(last, Lsn::segment_start(1));
+        wal.commit(1, last).unwrap();
+        let records = read_all(&mut wal, Lsn(0));
+        assert_eq!(records.len(), lsns.len() + 1);
+        assert_eq!(records[lsns.len() - 1].0, last);
+        drop(wal);
+        let mut wal = Wal::open(&dir).unwrap();
+        assert_eq!(wal.next_lsn().segment(), 1);
+
+        // 2 つ目のセグメントより前を消すと、開き直してもそこから始まる
+        wal.truncate(last).unwrap();
+        assert_eq!(wal.first_lsn(), last);
+        assert_eq!(wal.checkpoint_lsn().unwrap(), None);
+        wal.set_checkpoint_lsn(last).unwrap();
+        drop(wal);
+        let mut wal = Wal::open(&dir).unwrap();
+        assert_eq!(wal.first_lsn(), last);
+        assert_eq!(wal.checkpoint_lsn().unwrap(), Some(last));
+        assert_eq!(read_all(&mut wal, Lsn(0)).len(), 0);
+        assert_eq!(read_all(&mut wal, last).len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_archive() {
+        let (dir, archive) = (temp_path("wal"), temp_path("archive"));
+        let mut wal = Wal::open(&dir).unwrap();
+        let first = wal.append(&image(0, b"abc")).unwrap();
+        wal.switch_segment().unwrap();
+        // 書き終わったセグメントは、アーカイブ先を決めたときに写す
+        wal.set_archive_dir(&archive).unwrap();
+        assert_eq!(segments(&archive).unwrap(), vec![0]);
+        let second = wal.append(&image(1, b"de")).unwrap();
+        assert_eq!(second, Lsn::segment_start(1));
+        wal.switch_segment().unwrap();
+        wal.switch_segment().unwrap();
+        assert_eq!(segments(&archive).unwrap(), vec![0, 1]);
+        assert_eq!(
+            fs::read(segment_path(&archive, 1)).unwrap(),
+            fs::read(segment_path(&dir, 1)).unwrap()
+        );
+
+        // 捨てた位置から追記し、開き直しても捨てたレコードは読めない
+        wal.discard_from(second).unwrap();
+        assert_eq!(wal.next_lsn(), second);
+        let third = wal.append(&image(2, b"f")).unwrap();
+        assert_eq!(third, second);
+        wal.flush(third).unwrap();
+        drop(wal);
+        let mut wal = Wal::open(&dir).unwrap();
+        let records = read_all(&mut wal, Lsn(0));
+        assert_eq!(
+            records,
+            vec![(first, image(0, b"abc")), (third, image(2, b"f"))]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&archive).unwrap();
+    }
+
+    #[test]
+    fn test_wal_compression() {
+        let dir = temp_path("wal");
+        let mut wal = Wal::open(&dir).unwrap();
+        // ページ全体のような繰り返しの多い本体は縮めて書く
+        let page = b"0123456789abcdef".repeat(512);
+        let first = wal.append(&image(0, &page)).unwrap();
+        let size = wal.next_lsn().0 - first.0;
+        assert!(size < page.len() as u64 / 4, "{size} bytes");
+        // 縮まない本体はそのまま書く
+        let noise = noise(1000);
+        let second = wal.append(&image(1, &noise)).unwrap();
+        let third = wal.append(&image(2, b"small")).unwrap();
+        wal.flush(third).unwrap();
+        drop(wal);
+
+        let mut wal = Wal::open(&dir).unwrap();
+        assert_eq!(
+            read_all(&mut wal, Lsn(0)),
+            vec![
+                (first, image(0, &page)),
+                (second, image(1, &noise)),
+                (third, image(2, b"small")),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_recycle() {
+        let dir = temp_path("wal");
+        let mut wal = Wal::open(&dir).unwrap();
+        for page in 0..5 {
+            wal.append(&image(page, b"abc")).unwrap();
+            wal.switch_segment().unwrap();
+        }
+        let start = Lsn::segment_start(3);
+        wal.truncate(start).unwrap();
+        // 0 と 1 は 6 と 7 として取っておき、2 は消す
+        assert_eq!(segments(&dir).unwrap(), vec![3, 4, 5, 6, 7]);
+        assert_eq!(wal.recycled_segments().unwrap(), 2);
+        assert_eq!(wal.retained_bytes(), wal.next_lsn().0 - start.0);
+
+        // 取っておいたファイルに残る古いレコードは読まない
+        wal.append(&image(5, b"abc")).unwrap();
+        wal.switch_segment().unwrap();
+        assert_eq!(wal.recycled_segments().unwrap(), 1);
+        let records = read_all(&mut wal, start);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2], (Lsn::segment_start(5), image(5, b"abc")));
+        let lsn = wal.append(&image(6, b"de")).unwrap();
+        assert_eq!(lsn, Lsn::segment_start(6));
+        wal.flush(lsn).unwrap();
+        drop(wal);
+
+        let mut wal = Wal::open(&dir).unwrap();
+        assert_eq!(wal.recycled_segments().unwrap(), 1);
+        let records = read_all(&mut wal, start);
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3], (lsn, image(6, b"de")));
+        assert!(wal.next_lsn() > lsn);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}